@@ -1,3 +1,5 @@
 fn main() {
+    // The core build has no Tauri to configure
+    #[cfg(feature = "app")]
     tauri_build::build()
 }
@@ -0,0 +1,99 @@
+//! Downloading a torrent with the engine alone, no Tauri
+//!
+//! Serves a generated torrent from the loopback tracker and seeder of
+//! `seedcore_lib::testing`, adds its .torrent file to a `TorrentEngine` and
+//! exits once the download is verified. The engine's events arrive on a
+//! channel, the sink an embedder passes where the app passes its frontend.
+//!
+//! ```text
+//! cargo run --example headless_download --no-default-features --features core
+//! ```
+//!
+//! `cargo test --features core` runs it as an end-to-end smoke test.
+
+use seedcore_lib::engine::{EngineCommand, TorrentEngine};
+use seedcore_lib::events::{Event, EventSink};
+use seedcore_lib::state::TorrentState;
+use seedcore_lib::testing::{MiniTracker, Seeder, TestTorrent};
+use seedcore_lib::torrent::Metainfo;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+/// Longest the download may take
+const TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Download a generated torrent into `dir` and check what landed on disk
+async fn download(dir: &Path) -> Result<(), String> {
+    let tracker = MiniTracker::start(vec![]).await;
+    let torrent = TestTorrent::generate(tracker.url(), 32768, 40, 1000, 7);
+    let seeder = Seeder::start(&torrent, 0..torrent.num_pieces()).await;
+    tracker.set_peers(vec![seeder.addr()]);
+
+    // Added the way a user would: from a .torrent file
+    let torrent_file = dir.join("fixture.torrent");
+    std::fs::write(&torrent_file, &torrent.torrent).map_err(|e| e.to_string())?;
+    let bytes = std::fs::read(&torrent_file).map_err(|e| e.to_string())?;
+    let metainfo = Metainfo::from_bytes(&bytes).map_err(|e| e.to_string())?;
+    println!(
+        "Added {} ({} bytes in {} pieces)",
+        metainfo.info.name, metainfo.info.total_size, metainfo.info.piece_count
+    );
+
+    let download_dir = dir.join("downloads");
+    let (sink, mut events) = mpsc::unbounded_channel::<Event>();
+    let mut engine = TorrentEngine::new(metainfo, download_dir.clone(), Some(Arc::new(sink) as EventSink));
+    let handle = engine.command_handle();
+    let task = tokio::spawn(async move { engine.run().await });
+    handle.send(EngineCommand::Start).await.map_err(|e| e.to_string())?;
+
+    let outcome = tokio::time::timeout(TIMEOUT, async {
+        while let Some(event) = events.recv().await {
+            match event {
+                Event::TorrentUpdate(info) => {
+                    println!("{:?}: {} of {} bytes, {} peers", info.state, info.downloaded, info.size, info.peers);
+                    match info.state {
+                        TorrentState::Seeding => return Ok(()),
+                        TorrentState::Error => return Err(info.error.unwrap_or_else(|| "engine error".to_string())),
+                        _ => {}
+                    }
+                }
+                Event::FileCompleted(file) => println!("Completed {}", file.path),
+                _ => {}
+            }
+        }
+        Err("the engine stopped before finishing".to_string())
+    })
+    .await
+    .unwrap_or_else(|_| Err(format!("not finished after {:?}", TIMEOUT)));
+
+    let _ = handle.stop();
+    let _ = tokio::time::timeout(Duration::from_secs(10), task).await;
+    outcome?;
+
+    let content = std::fs::read(download_dir.join("fixture.bin")).map_err(|e| e.to_string())?;
+    if content != *torrent.content {
+        return Err("the downloaded file differs from what was seeded".to_string());
+    }
+    println!("Downloaded and verified {} bytes", content.len());
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() {
+    let dir = tempfile::TempDir::new().expect("temporary directory");
+    if let Err(e) = download(dir.path()).await {
+        eprintln!("Download failed: {}", e);
+        std::process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[tokio::test]
+    async fn test_headless_download() {
+        let dir = tempfile::TempDir::new().unwrap();
+        super::download(dir.path()).await.unwrap();
+    }
+}
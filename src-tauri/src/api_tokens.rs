@@ -0,0 +1,381 @@
+//! Scoped API tokens for helper tools and the future remote control
+//!
+//! A token looks like `sct_<id>_<secret>`. Only the id and an Argon2 hash of
+//! the secret are stored, so a token is shown once at creation and can't be
+//! read back. Every request is checked against the database, which makes a
+//! revocation effective for the next request.
+//!
+//! Token operations and rejected requests are logged under the `audit`
+//! tracing target.
+
+use crate::database::{ApiTokenRecord, Database};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Start of every token, so leaked ones are easy to grep for
+pub const TOKEN_PREFIX: &str = "sct_";
+
+/// Bytes of randomness in a token's id and secret
+const ID_BYTES: usize = 8;
+const SECRET_BYTES: usize = 32;
+
+/// What a token may do. Every scope also allows read-only access.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TokenScope {
+    ReadOnly,
+    TorrentControl,
+    Settings,
+    Debrid,
+}
+
+impl TokenScope {
+    fn grants(self, required: TokenScope) -> bool {
+        self == required || required == TokenScope::ReadOnly
+    }
+}
+
+impl fmt::Display for TokenScope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ReadOnly => write!(f, "read-only"),
+            Self::TorrentControl => write!(f, "torrent-control"),
+            Self::Settings => write!(f, "settings"),
+            Self::Debrid => write!(f, "debrid"),
+        }
+    }
+}
+
+/// Scope each endpoint (named after its command) requires. Endpoints not
+/// listed, like the master password and token commands, are never reachable
+/// with a token.
+const ENDPOINT_SCOPES: &[(&str, TokenScope)] = &[
+    ("get_version", TokenScope::ReadOnly),
+    ("get_client_info", TokenScope::ReadOnly),
+    ("check_clock", TokenScope::ReadOnly),
+    ("get_diagnostics", TokenScope::ReadOnly),
+    ("get_background_tasks", TokenScope::ReadOnly),
+    ("run_background_task_now", TokenScope::Settings),
+    ("get_benchmark_history", TokenScope::ReadOnly),
+    ("run_benchmark", TokenScope::Settings),
+    ("cancel_benchmark", TokenScope::Settings),
+    ("get_torrents", TokenScope::ReadOnly),
+    ("query_torrents", TokenScope::ReadOnly),
+    ("get_torrent_details", TokenScope::ReadOnly),
+    ("get_magnet_link", TokenScope::ReadOnly),
+    ("get_torrent_provenance", TokenScope::ReadOnly),
+    ("parse_torrent_file", TokenScope::ReadOnly),
+    ("parse_magnet_link", TokenScope::ReadOnly),
+    ("get_peer_list", TokenScope::ReadOnly),
+    ("get_peer_choke_history", TokenScope::ReadOnly),
+    ("get_tracker_list", TokenScope::ReadOnly),
+    ("get_pieces_info", TokenScope::ReadOnly),
+    ("get_piece_details", TokenScope::ReadOnly),
+    ("get_availability_history", TokenScope::ReadOnly),
+    ("subscribe_torrent_details", TokenScope::ReadOnly),
+    ("unsubscribe_torrent_details", TokenScope::ReadOnly),
+    ("get_torrent_debug_log", TokenScope::ReadOnly),
+    ("get_peer_disconnect_history", TokenScope::ReadOnly),
+    ("get_engine_metrics", TokenScope::ReadOnly),
+    ("audit_storage", TokenScope::ReadOnly),
+    ("detect_duplicate_content", TokenScope::ReadOnly),
+    ("get_file_list", TokenScope::ReadOnly),
+    ("get_file_preview", TokenScope::ReadOnly),
+    ("get_available_disk_space", TokenScope::ReadOnly),
+    ("get_traffic_stats", TokenScope::ReadOnly),
+    ("get_event_catalog", TokenScope::ReadOnly),
+    ("get_dashboard_summary", TokenScope::ReadOnly),
+    ("add_torrent_file", TokenScope::TorrentControl),
+    ("add_magnet_link", TokenScope::TorrentControl),
+    ("add_torrents_batch", TokenScope::TorrentControl),
+    ("remove_torrent", TokenScope::TorrentControl),
+    ("start_torrent", TokenScope::TorrentControl),
+    ("pause_torrent", TokenScope::TorrentControl),
+    ("set_torrent_auto_stop", TokenScope::TorrentControl),
+    ("pause_seeding", TokenScope::TorrentControl),
+    ("recover_torrent", TokenScope::TorrentControl),
+    ("relocate_torrent", TokenScope::TorrentControl),
+    ("extract_torrent_data", TokenScope::TorrentControl),
+    ("add_peer_manually", TokenScope::TorrentControl),
+    ("rebuild_search_index", TokenScope::TorrentControl),
+    ("set_queue_position", TokenScope::TorrentControl),
+    ("queue_move_up", TokenScope::TorrentControl),
+    ("queue_move_down", TokenScope::TorrentControl),
+    ("queue_move_top", TokenScope::TorrentControl),
+    ("queue_move_bottom", TokenScope::TorrentControl),
+    ("set_file_priority", TokenScope::TorrentControl),
+    ("set_tracker_enabled", TokenScope::TorrentControl),
+    ("download_file_prefix", TokenScope::TorrentControl),
+    ("relink_duplicates", TokenScope::TorrentControl),
+    ("set_torrent_debug_logging", TokenScope::TorrentControl),
+    ("get_settings", TokenScope::Settings),
+    ("update_settings", TokenScope::Settings),
+    ("backup_data", TokenScope::Settings),
+    ("restore_data", TokenScope::Settings),
+    ("export_backup", TokenScope::Settings),
+    ("cancel_backup_export", TokenScope::Settings),
+    ("export_torrent_file", TokenScope::Settings),
+    ("import_backup", TokenScope::Settings),
+    ("restore_from_backup_file", TokenScope::Settings),
+    ("get_database_recovery", TokenScope::ReadOnly),
+    ("salvage_damaged_database", TokenScope::Settings),
+    ("get_log_usage", TokenScope::ReadOnly),
+    ("add_cloud_torrent", TokenScope::Debrid),
+    ("add_cloud_torrent_file", TokenScope::Debrid),
+    ("check_torrent_cache", TokenScope::Debrid),
+    ("get_preferred_cached_provider", TokenScope::Debrid),
+    ("get_debrid_health", TokenScope::Debrid),
+    ("add_magnet_to_debrid", TokenScope::Debrid),
+    ("add_torrent_file_to_debrid", TokenScope::Debrid),
+    ("select_debrid_files", TokenScope::Debrid),
+    ("get_debrid_torrent_files", TokenScope::Debrid),
+    ("get_debrid_download_links", TokenScope::Debrid),
+    ("list_debrid_torrents", TokenScope::Debrid),
+    ("delete_debrid_torrent", TokenScope::Debrid),
+    ("get_cloud_file_progress", TokenScope::Debrid),
+    ("get_debrid_settings", TokenScope::Debrid),
+    ("update_debrid_settings", TokenScope::Debrid),
+];
+
+/// Scope needed to call `endpoint` with a token, None if tokens can't
+pub fn required_scope(endpoint: &str) -> Option<TokenScope> {
+    ENDPOINT_SCOPES.iter().find(|(name, _)| *name == endpoint).map(|(_, scope)| *scope)
+}
+
+/// A token as listed to the user (never includes the secret or its hash)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ApiTokenInfo {
+    pub id: String,
+    pub label: String,
+    pub scopes: Vec<TokenScope>,
+    pub created_at: i64,
+    pub expires_at: Option<i64>,
+}
+
+impl From<&ApiTokenRecord> for ApiTokenInfo {
+    fn from(record: &ApiTokenRecord) -> Self {
+        Self {
+            id: record.id.clone(),
+            label: record.label.clone(),
+            scopes: record.scopes.clone(),
+            created_at: record.created_at,
+            expires_at: record.expires_at,
+        }
+    }
+}
+
+/// Result of `create_api_token`: the only time `token` is available
+#[derive(Debug, Clone, Serialize)]
+pub struct CreatedApiToken {
+    pub token: String,
+    pub info: ApiTokenInfo,
+}
+
+/// Why a request's token was refused
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TokenError {
+    /// Not shaped like a token
+    Malformed,
+    /// Unknown, revoked, or wrong secret (deliberately not told apart)
+    Invalid,
+    Expired,
+    /// Valid token without the scope the endpoint needs
+    Forbidden { required: TokenScope },
+    /// Endpoint that can't be called with a token
+    NotAllowed(String),
+    /// The token store couldn't be read
+    Storage(String),
+}
+
+impl fmt::Display for TokenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Malformed => write!(f, "Malformed API token"),
+            Self::Invalid => write!(f, "Invalid or revoked API token"),
+            Self::Expired => write!(f, "API token expired"),
+            Self::Forbidden { required } => write!(f, "API token lacks the {} scope", required),
+            Self::NotAllowed(endpoint) => write!(f, "{} is not available to API tokens", endpoint),
+            Self::Storage(e) => write!(f, "Failed to read API tokens: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for TokenError {}
+
+/// Create a token and store its hash. `now` is a Unix timestamp.
+pub fn create_token(
+    db: &Database,
+    label: &str,
+    scopes: Vec<TokenScope>,
+    expires_days: Option<u32>,
+    now: i64,
+) -> crate::Result<CreatedApiToken> {
+    if scopes.is_empty() {
+        return Err(crate::Error::ValidationError("An API token needs at least one scope".to_string()));
+    }
+    let mut scopes = scopes;
+    scopes.sort_by_key(|scope| *scope as u8);
+    scopes.dedup();
+
+    let id = random_hex(ID_BYTES);
+    let secret = random_hex(SECRET_BYTES);
+    let secret_hash = crate::crypto::hash_master_password(&secret, &crate::crypto::generate_salt())
+        .map_err(|e| crate::Error::CryptoError(e.to_string()))?;
+
+    let record = ApiTokenRecord {
+        id: id.clone(),
+        label: label.trim().to_string(),
+        scopes,
+        secret_hash,
+        created_at: now,
+        expires_at: expires_days.map(|days| now + i64::from(days) * 86_400),
+    };
+    db.save_api_token(&record)?;
+
+    let info = ApiTokenInfo::from(&record);
+    tracing::info!(target: "audit", "API token {} ({:?}) created with scopes {:?}", info.id, info.label, info.scopes);
+    Ok(CreatedApiToken { token: format!("{}{}_{}", TOKEN_PREFIX, id, secret), info })
+}
+
+/// Tokens, oldest first
+pub fn list_tokens(db: &Database) -> crate::Result<Vec<ApiTokenInfo>> {
+    let mut tokens: Vec<ApiTokenInfo> = db.load_api_tokens()?.iter().map(ApiTokenInfo::from).collect();
+    tokens.sort_by_key(|token| token.created_at);
+    Ok(tokens)
+}
+
+/// Revoke a token; returns whether it existed
+pub fn revoke_token(db: &Database, id: &str) -> crate::Result<bool> {
+    let existed = db.delete_api_token(id)?;
+    if existed {
+        tracing::info!(target: "audit", "API token {} revoked", id);
+    } else {
+        tracing::warn!(target: "audit", "Revoking unknown API token {}", id);
+    }
+    Ok(existed)
+}
+
+/// Authenticate `token` for a request to `endpoint` at `now` (Unix timestamp).
+/// What the WebUI layer calls before dispatching a request.
+pub fn authorize(db: &Database, token: &str, endpoint: &str, now: i64) -> Result<ApiTokenInfo, TokenError> {
+    let result = check(db, token, endpoint, now);
+    match &result {
+        Ok(info) => tracing::debug!(target: "audit", "API token {} used for {}", info.id, endpoint),
+        Err(e) => {
+            let id = parse_token(token).map(|(id, _)| id).unwrap_or("?");
+            tracing::warn!(target: "audit", "API token {} refused for {}: {}", id, endpoint, e);
+        }
+    }
+    result
+}
+
+fn check(db: &Database, token: &str, endpoint: &str, now: i64) -> Result<ApiTokenInfo, TokenError> {
+    let required = required_scope(endpoint).ok_or_else(|| TokenError::NotAllowed(endpoint.to_string()))?;
+    let (id, secret) = parse_token(token).ok_or(TokenError::Malformed)?;
+    let record = db
+        .load_api_token(id)
+        .map_err(|e| TokenError::Storage(e.to_string()))?
+        .ok_or(TokenError::Invalid)?;
+
+    // Secret first, so expiry and scopes are only revealed to the holder
+    let valid = crate::crypto::verify_master_password(secret, &record.secret_hash).unwrap_or(false);
+    if !valid {
+        return Err(TokenError::Invalid);
+    }
+    if record.expires_at.is_some_and(|expires_at| now >= expires_at) {
+        return Err(TokenError::Expired);
+    }
+    if !record.scopes.iter().any(|scope| scope.grants(required)) {
+        return Err(TokenError::Forbidden { required });
+    }
+    Ok(ApiTokenInfo::from(&record))
+}
+
+/// Split a token into id and secret
+fn parse_token(token: &str) -> Option<(&str, &str)> {
+    let (id, secret) = token.trim().strip_prefix(TOKEN_PREFIX)?.split_once('_')?;
+    let well_formed = |part: &str, bytes: usize| part.len() == bytes * 2 && part.bytes().all(|b| b.is_ascii_hexdigit());
+    (well_formed(id, ID_BYTES) && well_formed(secret, SECRET_BYTES)).then_some((id, secret))
+}
+
+fn random_hex(bytes: usize) -> String {
+    let mut buf = vec![0u8; bytes];
+    rand::thread_rng().fill_bytes(&mut buf);
+    hex::encode(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn open_db() -> (tempfile::TempDir, Database) {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let db = Database::open(temp_dir.path().join("db")).unwrap();
+        (temp_dir, db)
+    }
+
+    #[test]
+    fn test_scope_enforcement() {
+        let (_dir, db) = open_db();
+        let reader = create_token(&db, "dashboard", vec![TokenScope::ReadOnly], None, 1000).unwrap();
+        let control = create_token(&db, "rss", vec![TokenScope::TorrentControl], None, 1000).unwrap();
+
+        assert!(authorize(&db, &reader.token, "get_torrents", 1000).is_ok());
+        assert_eq!(
+            authorize(&db, &reader.token, "pause_torrent", 1000),
+            Err(TokenError::Forbidden { required: TokenScope::TorrentControl })
+        );
+        assert!(authorize(&db, &control.token, "pause_torrent", 1000).is_ok());
+        // Every scope can read
+        assert!(authorize(&db, &control.token, "get_peer_list", 1000).is_ok());
+        assert_eq!(
+            authorize(&db, &control.token, "update_settings", 1000),
+            Err(TokenError::Forbidden { required: TokenScope::Settings })
+        );
+
+        // Secrets and tokens themselves are out of reach of any token
+        for endpoint in ["unlock_with_master_password", "save_debrid_credentials", "create_api_token", "made_up"] {
+            assert!(matches!(authorize(&db, &control.token, endpoint, 1000), Err(TokenError::NotAllowed(_))));
+        }
+    }
+
+    #[test]
+    fn test_hashing_round_trip_and_revocation() {
+        let (_dir, db) = open_db();
+        let created = create_token(&db, " helper ", vec![TokenScope::Debrid, TokenScope::Debrid], Some(30), 1000).unwrap();
+        assert!(created.token.starts_with(TOKEN_PREFIX));
+        assert_eq!(created.info.label, "helper");
+        assert_eq!(created.info.scopes, vec![TokenScope::Debrid]);
+
+        // Only the hash is stored; the listing never carries the secret
+        let record = db.load_api_token(&created.info.id).unwrap().unwrap();
+        let (_, secret) = parse_token(&created.token).unwrap();
+        assert!(!String::from_utf8_lossy(&record.secret_hash).contains(secret));
+        assert_eq!(list_tokens(&db).unwrap(), vec![created.info.clone()]);
+
+        assert!(authorize(&db, &created.token, "list_debrid_torrents", 1000).is_ok());
+
+        // Same id, different secret
+        let forged = format!("{}{}_{}", TOKEN_PREFIX, created.info.id, "0".repeat(SECRET_BYTES * 2));
+        assert_eq!(authorize(&db, &forged, "list_debrid_torrents", 1000), Err(TokenError::Invalid));
+        assert_eq!(authorize(&db, "sct_nope", "list_debrid_torrents", 1000), Err(TokenError::Malformed));
+
+        assert!(revoke_token(&db, &created.info.id).unwrap());
+        assert_eq!(authorize(&db, &created.token, "list_debrid_torrents", 1000), Err(TokenError::Invalid));
+        assert!(!revoke_token(&db, &created.info.id).unwrap());
+        assert!(list_tokens(&db).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_expiry() {
+        let (_dir, db) = open_db();
+        let created = create_token(&db, "temp", vec![TokenScope::ReadOnly], Some(1), 1000).unwrap();
+        assert_eq!(created.info.expires_at, Some(1000 + 86_400));
+
+        assert!(authorize(&db, &created.token, "get_torrents", 1000 + 86_399).is_ok());
+        assert_eq!(authorize(&db, &created.token, "get_torrents", 1000 + 86_400), Err(TokenError::Expired));
+
+        assert!(create_token(&db, "none", Vec::new(), None, 1000).is_err());
+    }
+}
@@ -0,0 +1,400 @@
+//! Storage audit: what each torrent occupies on disk, and what nothing claims
+//!
+//! Read-only. For every saved session the expected files (honouring a renamed
+//! root and the current, possibly relocated, download dir) are compared with
+//! what is on disk; then the download directories are walked for files no
+//! session accounts for. The report carries paths so the UI can offer cleanup,
+//! but nothing here deletes anything.
+//!
+//! Cloud downloads are not saved sessions, so their files show up as orphan
+//! candidates.
+
+use crate::database::TorrentSession;
+use crate::disk::DiskManager;
+use serde::Serialize;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+/// Deepest directory level walked below a download dir
+pub const MAX_SCAN_DEPTH: usize = 16;
+
+/// Scanned entries between two progress reports
+const PROGRESS_EVERY: u64 = 500;
+
+/// An expected file whose size on disk differs
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SizeMismatch {
+    pub path: String,
+    pub expected: u64,
+    pub actual: u64,
+}
+
+/// One torrent's files compared with the disk
+#[derive(Debug, Clone, Serialize)]
+pub struct TorrentStorage {
+    pub torrent_id: String,
+    pub name: String,
+    /// Root file/folder on disk
+    pub root: String,
+    pub expected_bytes: u64,
+    /// Bytes of the torrent's files that exist (sparse files count in full)
+    pub on_disk_bytes: u64,
+    pub missing: Vec<String>,
+    pub size_mismatched: Vec<SizeMismatch>,
+    /// Magnet still fetching metadata: there is no file list to check
+    pub metadata_pending: bool,
+}
+
+/// A file under a download dir that no torrent accounts for
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct OrphanFile {
+    pub path: String,
+    pub size: u64,
+}
+
+/// An entry the scan could not read (permissions, vanished meanwhile, ...)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct UnreadableEntry {
+    pub path: String,
+    pub error: String,
+}
+
+/// Result of `audit_storage`
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct StorageAudit {
+    pub torrents: Vec<TorrentStorage>,
+    pub orphans: Vec<OrphanFile>,
+    pub unreadable: Vec<UnreadableEntry>,
+    /// Directories not descended into because of `MAX_SCAN_DEPTH`
+    pub depth_limited: Vec<String>,
+    /// Download directories that were walked
+    pub scanned_dirs: Vec<String>,
+    pub expected_bytes: u64,
+    pub on_disk_bytes: u64,
+    pub orphan_bytes: u64,
+}
+
+/// Payload of the `storage-audit-progress` event
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "phase", rename_all = "snake_case")]
+pub enum AuditProgress {
+    Torrents { done: usize, total: usize },
+    Scanning { entries: u64, dir: String },
+    Finished,
+}
+
+/// Compare one session's expected files with the disk
+pub fn audit_session(session: &TorrentSession) -> TorrentStorage {
+    let download_dir = PathBuf::from(&session.download_dir);
+    let mut storage = TorrentStorage {
+        torrent_id: session.id.clone(),
+        name: session.metainfo.info.name.clone(),
+        root: download_dir.join(session.root_name()).to_string_lossy().to_string(),
+        expected_bytes: session.metainfo.info.total_size,
+        on_disk_bytes: 0,
+        missing: Vec::new(),
+        size_mismatched: Vec::new(),
+        metadata_pending: !session.metainfo.has_metadata(),
+    };
+    if storage.metadata_pending {
+        return storage;
+    }
+
+    for file in expected_files(session) {
+        let path = file.path.to_string_lossy().to_string();
+        match std::fs::metadata(&file.path) {
+            Ok(metadata) if metadata.is_file() => {
+                storage.on_disk_bytes += metadata.len().min(file.length);
+                if metadata.len() != file.length {
+                    storage.size_mismatched.push(SizeMismatch { path, expected: file.length, actual: metadata.len() });
+                }
+            }
+            _ => storage.missing.push(path),
+        }
+    }
+    storage
+}
+
+fn expected_files(session: &TorrentSession) -> Vec<crate::disk::FileInfo> {
+    if session.metainfo.info.piece_count == 0 {
+        return Vec::new();
+    }
+    let mut disk = DiskManager::with_root_name(&session.metainfo, PathBuf::from(&session.download_dir), session.root_name());
+    disk.set_storage_mode(session.storage_mode);
+    disk.data_files().to_vec()
+}
+
+/// Download dirs worth walking: the configured one plus every session's,
+/// minus any nested inside another (it would be walked twice)
+pub fn scan_roots(default_dir: &str, sessions: &[TorrentSession]) -> Vec<PathBuf> {
+    let mut dirs: Vec<PathBuf> = std::iter::once(default_dir)
+        .chain(sessions.iter().map(|s| s.download_dir.as_str()))
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .collect();
+    dirs.sort();
+    dirs.dedup();
+    let nested: Vec<bool> = dirs
+        .iter()
+        .map(|dir| dirs.iter().any(|other| other != dir && dir.starts_with(other)))
+        .collect();
+    dirs.into_iter().zip(nested).filter(|(_, nested)| !nested).map(|(dir, _)| dir).collect()
+}
+
+/// Run the whole audit. Blocking: call from `spawn_blocking`.
+pub fn run_audit(
+    sessions: &[TorrentSession],
+    roots: &[PathBuf],
+    max_depth: usize,
+    mut progress: impl FnMut(AuditProgress),
+) -> StorageAudit {
+    let mut audit = StorageAudit::default();
+    let mut attributed = HashSet::new();
+
+    for (done, session) in sessions.iter().enumerate() {
+        progress(AuditProgress::Torrents { done, total: sessions.len() });
+        let storage = audit_session(session);
+        audit.expected_bytes += storage.expected_bytes;
+        audit.on_disk_bytes += storage.on_disk_bytes;
+        audit.torrents.push(storage);
+        attributed.extend(expected_files(session).into_iter().map(|f| f.path));
+    }
+
+    let mut entries = 0;
+    for root in roots {
+        audit.scanned_dirs.push(root.to_string_lossy().to_string());
+        scan_dir(root, &attributed, max_depth, &mut audit, &mut entries, &mut progress);
+    }
+    audit.orphan_bytes = audit.orphans.iter().map(|o| o.size).sum();
+
+    progress(AuditProgress::Finished);
+    audit
+}
+
+/// Walk `root` for regular files not in `attributed`. Symlinks are never
+/// followed, and one unreadable entry doesn't end the walk.
+fn scan_dir(
+    root: &Path,
+    attributed: &HashSet<PathBuf>,
+    max_depth: usize,
+    audit: &mut StorageAudit,
+    entries: &mut u64,
+    progress: &mut impl FnMut(AuditProgress),
+) {
+    let mut stack = vec![(root.to_path_buf(), 0usize)];
+    while let Some((dir, depth)) = stack.pop() {
+        let listing = match std::fs::read_dir(&dir) {
+            Ok(listing) => listing,
+            Err(e) => {
+                audit.unreadable.push(UnreadableEntry { path: dir.to_string_lossy().to_string(), error: e.to_string() });
+                continue;
+            }
+        };
+
+        for entry in listing {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) => {
+                    audit.unreadable.push(UnreadableEntry { path: dir.to_string_lossy().to_string(), error: e.to_string() });
+                    continue;
+                }
+            };
+            let path = entry.path();
+            *entries += 1;
+            if *entries % PROGRESS_EVERY == 0 {
+                progress(AuditProgress::Scanning { entries: *entries, dir: root.to_string_lossy().to_string() });
+            }
+
+            // DirEntry::metadata doesn't traverse symlinks
+            let metadata = match entry.metadata() {
+                Ok(metadata) => metadata,
+                Err(e) => {
+                    audit.unreadable.push(UnreadableEntry { path: path.to_string_lossy().to_string(), error: e.to_string() });
+                    continue;
+                }
+            };
+            if metadata.is_symlink() {
+                continue;
+            }
+            if metadata.is_dir() {
+                if depth < max_depth {
+                    stack.push((path, depth + 1));
+                } else {
+                    audit.depth_limited.push(path.to_string_lossy().to_string());
+                }
+            } else if metadata.is_file() && !attributed.contains(&path) {
+                audit.orphans.push(OrphanFile { path: path.to_string_lossy().to_string(), size: metadata.len() });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::torrent::{FileInfo, Metainfo, TorrentInfo};
+
+    fn session(id: &str, download_dir: &Path, name: &str, files: &[(&[&str], u64)]) -> TorrentSession {
+        let total_size = files.iter().map(|(_, len)| len).sum();
+        let metainfo = Metainfo {
+            announce: String::new(),
+            announce_list: vec![],
+            info: TorrentInfo {
+                piece_length: 16384,
+                pieces: vec![0u8; 20],
+                piece_count: 1,
+                files: files
+                    .iter()
+                    .map(|(path, length)| FileInfo { path: path.iter().map(|p| p.to_string()).collect(), length: *length })
+                    .collect(),
+                name: name.to_string(),
+                total_size,
+                is_single_file: false,
+                private: false,
+                legacy_names: None,
+            },
+            info_hash: [0u8; 20],
+            creation_date: None,
+            comment: None,
+            created_by: None,
+        };
+        TorrentSession {
+            id: id.to_string(),
+            metainfo,
+            bitfield: Vec::new(),
+            num_pieces: 1,
+            downloaded: 0,
+            uploaded: 0,
+            state: "seeding".to_string(),
+            download_dir: download_dir.to_string_lossy().to_string(),
+            added_at: 0,
+            last_activity: 0,
+            source: crate::debrid::types::DownloadSource::P2P,
+            completed_at: None,
+            volume_id: None,
+            category: None,
+            traffic: Default::default(),
+            swarm: None,
+            root_name: None,
+            data_sync: Default::default(),
+            queue_position: None,
+            saved_peers: Vec::new(),
+            added_from: None,
+            storage_mode: Default::default(),
+            auto_stop: Default::default(),
+            low_disk_paused_at: None,
+            linked_files: Vec::new(),
+            disabled_trackers: Vec::new(),
+            file_completed_at: Default::default(),
+        }
+    }
+
+    fn write(path: &Path, len: usize) {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, vec![1u8; len]).unwrap();
+    }
+
+    fn names(paths: impl IntoIterator<Item = String>, root: &Path) -> Vec<String> {
+        let mut names: Vec<String> = paths
+            .into_iter()
+            .map(|p| Path::new(&p).strip_prefix(root).unwrap().to_string_lossy().replace('\\', "/"))
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn test_audit_attributes_files_and_finds_orphans() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let root = temp_dir.path();
+
+        // Complete torrent, stored under a renamed root
+        let mut complete = session("a", root, "Album", &[(&["01.flac"], 100), (&["cover", "front.jpg"], 20)]);
+        complete.root_name = Some("Album (2)".to_string());
+        write(&root.join("Album (2)/01.flac"), 100);
+        write(&root.join("Album (2)/cover/front.jpg"), 20);
+        write(&root.join("Album (2)/notes.txt"), 5);
+
+        // Partially deleted by hand, and relocated to a subdirectory
+        let moved = root.join("moved");
+        let partial = session("b", &moved, "Show", &[(&["e1.mkv"], 300), (&["e2.mkv"], 300), (&["e3.mkv"], 300)]);
+        write(&moved.join("Show/e1.mkv"), 300);
+        write(&moved.join("Show/e2.mkv"), 120);
+
+        // Left behind by a removed torrent, plus the old location of "b"
+        write(&root.join("Old.Movie/movie.mkv"), 700);
+        write(&root.join("Show/e1.mkv"), 300);
+
+        let sessions = vec![complete, partial];
+        let roots = scan_roots(&root.to_string_lossy(), &sessions);
+        assert_eq!(roots, vec![root.to_path_buf()], "the relocated dir is inside the default one");
+
+        let mut events = Vec::new();
+        let audit = run_audit(&sessions, &roots, MAX_SCAN_DEPTH, |p| events.push(p));
+
+        let a = &audit.torrents[0];
+        assert!(a.missing.is_empty() && a.size_mismatched.is_empty());
+        assert_eq!((a.expected_bytes, a.on_disk_bytes), (120, 120));
+
+        let b = &audit.torrents[1];
+        assert_eq!(names(b.missing.clone(), root), vec!["moved/Show/e3.mkv"]);
+        assert_eq!(b.size_mismatched.len(), 1);
+        assert_eq!((b.size_mismatched[0].expected, b.size_mismatched[0].actual), (300, 120));
+        assert_eq!((b.expected_bytes, b.on_disk_bytes), (900, 420));
+
+        assert_eq!(
+            names(audit.orphans.iter().map(|o| o.path.clone()), root),
+            vec!["Album (2)/notes.txt", "Old.Movie/movie.mkv", "Show/e1.mkv"]
+        );
+        assert_eq!(audit.orphan_bytes, 1005);
+        assert_eq!((audit.expected_bytes, audit.on_disk_bytes), (1020, 540));
+        assert!(audit.unreadable.is_empty());
+        assert!(matches!(events.last(), Some(AuditProgress::Finished)));
+
+        // Nothing was touched
+        assert!(root.join("Old.Movie/movie.mkv").exists());
+    }
+
+    #[test]
+    fn test_scan_depth_cap() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let root = temp_dir.path();
+        write(&root.join("a/b/c/deep.bin"), 1);
+        write(&root.join("a/shallow.bin"), 1);
+
+        let audit = run_audit(&[], &[root.to_path_buf()], 1, |_| {});
+        assert_eq!(names(audit.orphans.iter().map(|o| o.path.clone()), root), vec!["a/shallow.bin"]);
+        assert_eq!(names(audit.depth_limited, root), vec!["a/b"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlinks_and_unreadable_entries() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let root = temp_dir.path().join("downloads");
+        let elsewhere = temp_dir.path().join("elsewhere");
+        write(&elsewhere.join("big.iso"), 50);
+        write(&root.join("kept.bin"), 3);
+        std::os::unix::fs::symlink(&elsewhere, root.join("link-to-dir")).unwrap();
+        std::os::unix::fs::symlink(elsewhere.join("big.iso"), root.join("link.iso")).unwrap();
+
+        let locked = root.join("locked");
+        write(&locked.join("secret.bin"), 1);
+        std::fs::set_permissions(&locked, std::fs::Permissions::from_mode(0o000)).unwrap();
+        // Root ignores permissions; the entry is then simply readable
+        let enforced = std::fs::read_dir(&locked).is_err();
+
+        let audit = run_audit(&[], std::slice::from_ref(&root), MAX_SCAN_DEPTH, |_| {});
+        std::fs::set_permissions(&locked, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let orphans = names(audit.orphans.iter().map(|o| o.path.clone()), &root);
+        if enforced {
+            assert_eq!(orphans, vec!["kept.bin"]);
+            assert_eq!(names(audit.unreadable.iter().map(|u| u.path.clone()), &root), vec!["locked"]);
+        } else {
+            assert_eq!(orphans, vec!["kept.bin", "locked/secret.bin"]);
+        }
+    }
+}
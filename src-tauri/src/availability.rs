@@ -0,0 +1,136 @@
+//! Swarm availability history per torrent
+//!
+//! Running engines append a sample of their swarm health every hour (and when
+//! the download completes) to a series kept in the database, capped at
+//! `MAX_SAMPLES`. Samples are built from stats the engine already has: the
+//! tracker-reported swarm, connected peers and the piece selector's
+//! availability counts.
+
+use serde::{Deserialize, Serialize};
+
+/// Time between samples of a running torrent
+pub const SAMPLE_INTERVAL_SECS: i64 = 60 * 60;
+
+/// Samples kept per torrent (30 days of hourly points); oldest dropped first
+pub const MAX_SAMPLES: usize = 30 * 24;
+
+/// Swarm health at one point in time
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct AvailabilitySample {
+    /// Unix seconds
+    pub at: i64,
+    /// As last reported by the trackers
+    pub swarm_seeds: Option<u32>,
+    pub swarm_leechers: Option<u32>,
+    /// Complete copies among connected peers, see `distributed_copies`
+    pub distributed_copies: f64,
+    pub connected_peers: u32,
+    /// Pieces we had; None in samples from before this was recorded
+    #[serde(default)]
+    pub pieces_have: Option<u32>,
+}
+
+/// Complete copies of the torrent the connected peers have between them: the
+/// availability of the rarest piece, plus the fraction of pieces that are
+/// more common than that. Our own pieces don't count.
+pub fn distributed_copies(availability: &[usize]) -> f64 {
+    let Some(&rarest) = availability.iter().min() else {
+        return 0.0;
+    };
+    let above = availability.iter().filter(|&&a| a > rarest).count();
+    rarest as f64 + above as f64 / availability.len() as f64
+}
+
+/// Add a sample, dropping the oldest ones beyond `MAX_SAMPLES`
+pub fn append(series: &mut Vec<AvailabilitySample>, sample: AvailabilitySample) {
+    series.push(sample);
+    if series.len() > MAX_SAMPLES {
+        series.drain(..series.len() - MAX_SAMPLES);
+    }
+}
+
+/// Samples taken within `from..=to` (either end open when None), oldest first
+pub fn in_range(series: &[AvailabilitySample], from: Option<i64>, to: Option<i64>) -> Vec<AvailabilitySample> {
+    series
+        .iter()
+        .filter(|s| from.map_or(true, |from| s.at >= from) && to.map_or(true, |to| s.at <= to))
+        .copied()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::Database;
+
+    fn sample(at: i64, peers: u32) -> AvailabilitySample {
+        AvailabilitySample {
+            at,
+            swarm_seeds: Some(peers / 2),
+            swarm_leechers: Some(peers - peers / 2),
+            distributed_copies: peers as f64 / 10.0,
+            connected_peers: peers,
+            pieces_have: Some(0),
+        }
+    }
+
+    #[test]
+    fn test_distributed_copies() {
+        assert_eq!(distributed_copies(&[]), 0.0);
+        assert_eq!(distributed_copies(&[0, 0, 0, 0]), 0.0);
+        assert_eq!(distributed_copies(&[2, 2, 2, 2]), 2.0);
+        assert_eq!(distributed_copies(&[1, 3, 2, 1]), 1.5);
+        assert_eq!(distributed_copies(&[0, 5, 5, 5]), 0.75);
+    }
+
+    #[test]
+    fn test_series_is_capped() {
+        let mut series = Vec::new();
+        for hour in 0..MAX_SAMPLES as i64 + 10 {
+            append(&mut series, sample(hour * SAMPLE_INTERVAL_SECS, 1));
+        }
+        assert_eq!(series.len(), MAX_SAMPLES);
+        assert_eq!(series[0].at, 10 * SAMPLE_INTERVAL_SECS);
+        assert_eq!(series.last().unwrap().at, (MAX_SAMPLES as i64 + 9) * SAMPLE_INTERVAL_SECS);
+    }
+
+    #[test]
+    fn test_history_ranges_restarts_and_removal() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let db_path = temp_dir.path().join("db");
+        let id = "0123456789abcdef0123456789abcdef01234567";
+        {
+            let db = Database::open(&db_path).unwrap();
+            // A day of hourly samples with peers growing by one each hour
+            for hour in 0..24 {
+                db.append_availability_sample(id, sample(hour * SAMPLE_INTERVAL_SECS, hour as u32)).unwrap();
+            }
+        }
+
+        let db = Database::open(&db_path).unwrap();
+        let series = db.load_availability_history(id).unwrap();
+        assert_eq!(series.len(), 24);
+
+        let hours = |from: Option<i64>, to: Option<i64>| -> Vec<u32> {
+            in_range(&series, from, to).iter().map(|s| s.connected_peers).collect()
+        };
+        assert_eq!(hours(Some(3 * SAMPLE_INTERVAL_SECS), Some(5 * SAMPLE_INTERVAL_SECS)), vec![3, 4, 5]);
+        assert_eq!(hours(Some(3 * SAMPLE_INTERVAL_SECS + 1), Some(5 * SAMPLE_INTERVAL_SECS - 1)), vec![4]);
+        assert_eq!(hours(Some(22 * SAMPLE_INTERVAL_SECS), None), vec![22, 23]);
+        assert_eq!(hours(None, Some(1)), vec![0]);
+        assert!(hours(Some(30 * SAMPLE_INTERVAL_SECS), None).is_empty());
+        assert_eq!(series[5], sample(5 * SAMPLE_INTERVAL_SECS, 5));
+
+        // Carried by backups, older ones without it still restore
+        let backup = db.dump_all().unwrap();
+        let restored = Database::open(temp_dir.path().join("restored")).unwrap();
+        restored.restore(&backup).unwrap();
+        assert_eq!(restored.load_availability_history(id).unwrap(), series);
+        let mut old_backup: serde_json::Value = serde_json::from_str(&backup).unwrap();
+        old_backup.as_object_mut().unwrap().remove("availability");
+        restored.restore(&old_backup.to_string()).unwrap();
+
+        db.delete_torrent(id).unwrap();
+        assert!(db.load_availability_history(id).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,300 @@
+//! Scheduled database backups
+//!
+//! When enabled in the settings, a background task writes the JSON backup
+//! (`Database::dump_all`) to `seedcore-backup-<UTC time>.json` in the backup
+//! directory once per interval and keeps only the newest few. The file name
+//! carries the time, so the schedule survives restarts. A cycle is skipped
+//! while another backup or a restore is running, or while a benchmark is
+//! loading the disk.
+
+use crate::database::Database;
+use crate::error::{Error, Result};
+#[cfg(feature = "app")]
+use crate::events::{emit_event, BackupCreatedEvent, BackupFailedEvent, Event};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+#[cfg(feature = "app")]
+use tauri::Manager;
+use tokio::time::{self, Duration};
+use tokio_util::sync::CancellationToken;
+
+/// Hours between automatic backups unless configured
+pub const DEFAULT_INTERVAL_HOURS: u64 = 24;
+
+/// Backup files kept unless configured
+pub const DEFAULT_KEEP: usize = 7;
+
+/// How often the task checks whether a backup is due
+const CHECK_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+const FILE_PREFIX: &str = "seedcore-backup-";
+const FILE_SUFFIX: &str = ".json";
+pub(crate) const NAME_TIME_FORMAT: &str = "%Y%m%d-%H%M%S";
+
+pub fn default_interval_hours() -> u64 {
+    DEFAULT_INTERVAL_HOURS
+}
+
+pub fn default_keep() -> usize {
+    DEFAULT_KEEP
+}
+
+/// Set while a backup or restore runs; cancels it
+static RUNNING: Mutex<Option<CancellationToken>> = Mutex::new(None);
+
+/// Claim the backup slot (shared by backups and restores); None if taken
+pub fn begin() -> Option<BackupGuard> {
+    let mut running = RUNNING.lock().unwrap();
+    if running.is_some() {
+        return None;
+    }
+    let token = CancellationToken::new();
+    *running = Some(token.clone());
+    Some(BackupGuard { token })
+}
+
+/// Ask the backup or restore holding the slot to stop. Only exports check;
+/// returns whether anything was running.
+pub fn cancel() -> bool {
+    match RUNNING.lock().unwrap().as_ref() {
+        Some(token) => {
+            token.cancel();
+            true
+        }
+        None => false,
+    }
+}
+
+/// Frees the backup slot when dropped
+pub struct BackupGuard {
+    pub token: CancellationToken,
+}
+
+impl Drop for BackupGuard {
+    fn drop(&mut self) {
+        *RUNNING.lock().unwrap() = None;
+    }
+}
+
+/// Default backup directory, next to the database
+pub const DIR_NAME: &str = "backups";
+
+/// `backups` next to the database
+pub fn default_dir(database: &Database) -> PathBuf {
+    let path = database.path();
+    path.parent().unwrap_or(&path).join(DIR_NAME)
+}
+
+/// Backup directory from the settings, else `default_dir`
+pub fn backup_dir(database: &Database, configured: Option<&str>) -> PathBuf {
+    match configured.map(str::trim) {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => default_dir(database),
+    }
+}
+
+fn file_name(at: i64) -> String {
+    let time = chrono::DateTime::from_timestamp(at, 0).unwrap_or_default();
+    format!("{}{}{}", FILE_PREFIX, time.format(NAME_TIME_FORMAT), FILE_SUFFIX)
+}
+
+/// When a backup file was written, from its name; None for other files
+fn file_time(name: &str) -> Option<i64> {
+    let time = name.strip_prefix(FILE_PREFIX)?.strip_suffix(FILE_SUFFIX)?;
+    chrono::NaiveDateTime::parse_from_str(time, NAME_TIME_FORMAT)
+        .ok()
+        .map(|time| time.and_utc().timestamp())
+}
+
+/// Backup files in `dir` with their times, oldest first
+pub fn list_backups(dir: &Path) -> Vec<(i64, PathBuf)> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut backups: Vec<(i64, PathBuf)> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let at = file_time(&entry.file_name().to_string_lossy())?;
+            Some((at, entry.path()))
+        })
+        .collect();
+    backups.sort();
+    backups
+}
+
+/// Delete all but the newest `keep` backups in `dir` (at least one is kept);
+/// returns the deleted files. Other files in `dir` are left alone.
+pub fn prune(dir: &Path, keep: usize) -> Vec<PathBuf> {
+    let backups = list_backups(dir);
+    let excess = backups.len().saturating_sub(keep.max(1));
+    let mut removed = Vec::new();
+    for (_, path) in backups.into_iter().take(excess) {
+        match std::fs::remove_file(&path) {
+            Ok(()) => removed.push(path),
+            Err(e) => tracing::warn!("Failed to delete old backup {:?}: {}", path, e),
+        }
+    }
+    removed
+}
+
+/// Whether a backup is due at `now` given the newest one's time
+pub fn is_due(last: Option<i64>, now: i64, interval_hours: u64) -> bool {
+    let interval = interval_hours.max(1) as i64 * 3600;
+    last.map_or(true, |last| now - last >= interval)
+}
+
+/// Write a backup of `database` into `dir` (created if needed), then prune
+/// to `keep` files. Returns the file and its size.
+pub fn write_backup(database: &Database, dir: &Path, keep: usize, now: i64) -> Result<(PathBuf, u64)> {
+    std::fs::create_dir_all(dir)
+        .map_err(|e| Error::IoError(format!("Failed to create backup directory {:?}: {}", dir, e)))?;
+    // Deferred writes reach the disk no later than the backup does
+    database.flush_now()?;
+    let json = database.dump_all()?;
+
+    // Written aside and renamed, so a crash never leaves a truncated backup
+    // under a backup's name
+    let path = dir.join(file_name(now));
+    let partial = path.with_extension("json.partial");
+    std::fs::write(&partial, &json)
+        .and_then(|()| std::fs::rename(&partial, &path))
+        .map_err(|e| {
+            let _ = std::fs::remove_file(&partial);
+            Error::IoError(format!("Failed to write backup {:?}: {}", path, e))
+        })?;
+
+    prune(dir, keep);
+    Ok((path, json.len() as u64))
+}
+
+/// Run a backup if one is due; None if not due or skipped
+#[cfg(feature = "app")]
+async fn run_due_backup(app: &tauri::AppHandle, database: &Arc<Database>) -> Option<Result<(PathBuf, u64)>> {
+    let settings = match database.load_settings() {
+        Ok(settings) => settings,
+        Err(e) => {
+            tracing::error!("Backup task failed to load settings: {}", e);
+            return None;
+        }
+    };
+    if !settings.auto_backup_enabled {
+        return None;
+    }
+
+    let dir = backup_dir(database, settings.auto_backup_dir.as_deref());
+    let now = chrono::Utc::now().timestamp();
+    let last = list_backups(&dir).last().map(|(at, _)| *at);
+    if !is_due(last, now, settings.auto_backup_interval_hours) {
+        return None;
+    }
+    if crate::benchmark::is_running() {
+        tracing::debug!("Skipping scheduled backup: a benchmark is running");
+        return None;
+    }
+    let Some(guard) = begin() else {
+        tracing::debug!("Skipping scheduled backup: another backup or restore is running");
+        return None;
+    };
+
+    let database = database.clone();
+    let keep = settings.auto_backup_keep;
+    let result = tokio::task::spawn_blocking(move || {
+        let _guard = guard;
+        write_backup(&database, &dir, keep, now).map_err(|e| (dir, e))
+    })
+    .await;
+
+    match result {
+        Ok(Ok((path, size))) => {
+            tracing::info!("Scheduled backup written to {:?} ({} bytes)", path, size);
+            let event = BackupCreatedEvent { path: path.display().to_string(), size };
+            emit_event(app, Event::BackupCreated(event));
+            Some(Ok((path, size)))
+        }
+        Ok(Err((dir, e))) => {
+            tracing::error!("Scheduled backup failed: {}", e);
+            let event = BackupFailedEvent { path: dir.display().to_string(), error: e.to_string() };
+            emit_event(app, Event::BackupFailed(event));
+            Some(Err(e))
+        }
+        Err(e) => {
+            tracing::error!("Scheduled backup task failed: {}", e);
+            None
+        }
+    }
+}
+
+#[cfg(feature = "app")]
+pub async fn start_backup_task(app_handle: tauri::AppHandle) {
+    let database = app_handle.state::<crate::state::AppState>().database.clone();
+    let mut interval = time::interval(CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        run_due_backup(&app_handle, &database).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    const DAY: i64 = 24 * 3600;
+
+    #[test]
+    fn test_file_names_round_trip() {
+        let at = 1_700_000_000;
+        assert_eq!(file_name(at), "seedcore-backup-20231114-221320.json");
+        assert_eq!(file_time(&file_name(at)), Some(at));
+        assert_eq!(file_time("seedcore-backup-20231114-221320.json.partial"), None);
+        assert_eq!(file_time("notes.json"), None);
+    }
+
+    #[test]
+    fn test_backups_rotate() {
+        let temp_dir = TempDir::new().unwrap();
+        let database = Database::open(temp_dir.path().join("data.db")).unwrap();
+        let dir = temp_dir.path().join("backups");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("notes.txt"), "not a backup").unwrap();
+
+        let start = 1_700_000_000;
+        for day in 0..10 {
+            let (path, size) = write_backup(&database, &dir, 3, start + day * DAY).unwrap();
+            assert_eq!(std::fs::metadata(&path).unwrap().len(), size);
+        }
+
+        let kept: Vec<i64> = list_backups(&dir).into_iter().map(|(at, _)| at).collect();
+        assert_eq!(kept, vec![start + 7 * DAY, start + 8 * DAY, start + 9 * DAY]);
+        assert!(dir.join("notes.txt").exists());
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 4);
+
+        // Keeping fewer later trims the rest; zero still keeps the newest
+        assert_eq!(prune(&dir, 0).len(), 2);
+        assert_eq!(list_backups(&dir)[0].0, start + 9 * DAY);
+
+        // A backup is a restorable dump
+        let json = std::fs::read_to_string(&list_backups(&dir)[0].1).unwrap();
+        database.restore(&json).unwrap();
+    }
+
+    #[test]
+    fn test_schedule() {
+        let now = 1_700_000_000;
+        assert!(is_due(None, now, 24));
+        assert!(!is_due(Some(now - DAY + 60), now, 24));
+        assert!(is_due(Some(now - DAY), now, 24));
+        // An interval of 0 means hourly, not constantly
+        assert!(!is_due(Some(now - 60), now, 0));
+        assert!(is_due(Some(now - 3600), now, 0));
+    }
+
+    #[test]
+    fn test_one_backup_at_a_time() {
+        let guard = begin().unwrap();
+        assert!(begin().is_none());
+        drop(guard);
+        assert!(begin().is_some());
+    }
+}
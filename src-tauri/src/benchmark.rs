@@ -0,0 +1,472 @@
+//! Disk and network throughput test
+//!
+//! Slow downloads get blamed on the client when the disk or the connection is
+//! the limit. The disk part writes and reads back a scratch "torrent" in the
+//! download directory through `DiskManager`, exactly as pieces are written,
+//! then syncs it the way a progress save does. The network part downloads a
+//! test file with the HTTP settings used for cloud downloads. Nothing here
+//! touches the session database except the result history.
+
+use crate::disk::DiskManager;
+use crate::error::{Error, Result};
+use crate::torrent::Metainfo;
+use async_trait::async_trait;
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio_util::sync::CancellationToken;
+
+/// Downloaded by the network test unless another URL is given
+pub const DEFAULT_TEST_URL: &str = "https://speed.cloudflare.com/__down?bytes=104857600";
+
+/// Piece size of the scratch torrent, a common size for large torrents
+pub const PIECE_LENGTH: usize = 1024 * 1024;
+
+/// Largest scratch file the disk test will write
+pub const MAX_DISK_SIZE_MB: u64 = 4096;
+
+/// Combined transfer speed of running torrents above which the test refuses
+/// to run (it would measure a shared disk and link)
+pub const BUSY_THRESHOLD: u64 = 1024 * 1024;
+
+/// Results kept in the history
+pub const HISTORY_LEN: usize = 20;
+
+/// Same timeout as cloud file downloads
+const NETWORK_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Name of the scratch file in the download directory
+const SCRATCH_NAME: &str = ".seedcore-benchmark";
+
+/// What to run
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct BenchmarkOptions {
+    /// Run the disk test
+    pub disk: bool,
+    /// Size of the scratch file (MiB)
+    pub disk_size_mb: u64,
+    /// Run the network test
+    pub network: bool,
+    /// File to download (defaults to `DEFAULT_TEST_URL`)
+    pub network_url: Option<String>,
+    /// Stop the download after this many MiB
+    pub network_max_mb: u64,
+    /// Run even while torrents are transferring
+    pub force: bool,
+}
+
+impl Default for BenchmarkOptions {
+    fn default() -> Self {
+        Self {
+            disk: true,
+            disk_size_mb: 256,
+            network: true,
+            network_url: None,
+            network_max_mb: 100,
+            force: false,
+        }
+    }
+}
+
+/// Disk test results
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DiskBenchmark {
+    pub path: String,
+    pub bytes: u64,
+    /// Piece writes, data handed to the OS (MB/s)
+    pub write_mbps: f64,
+    /// One sync of everything written, as at a progress save (ms)
+    pub sync_ms: f64,
+    /// Piece reads right after writing, so partly from the OS cache (MB/s)
+    pub read_mbps: f64,
+}
+
+/// Network test results
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NetworkBenchmark {
+    pub url: String,
+    pub bytes: u64,
+    pub secs: f64,
+    pub mbps: f64,
+}
+
+/// One benchmark run; also what the history holds
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BenchmarkReport {
+    /// When it started (Unix seconds)
+    pub started_at: i64,
+    pub disk: Option<DiskBenchmark>,
+    pub network: Option<NetworkBenchmark>,
+    /// Why a part didn't run or should be taken with a grain of salt
+    pub warnings: Vec<String>,
+}
+
+/// Fetches the network test file; mocked in tests
+#[async_trait]
+pub trait Fetcher: Send + Sync {
+    /// Download `url`, stopping after `max_bytes`. Returns the bytes received.
+    async fn fetch(&self, url: &str, max_bytes: u64, cancel: &CancellationToken) -> Result<u64>;
+}
+
+/// Fetcher over HTTP, configured like the cloud downloader
+pub struct HttpFetcher {
+    client: reqwest::Client,
+}
+
+impl HttpFetcher {
+    pub fn new() -> Result<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(NETWORK_TIMEOUT)
+            .build()
+            .map_err(|e| Error::NetworkError(format!("Failed to build HTTP client: {}", e)))?;
+        Ok(Self { client })
+    }
+}
+
+#[async_trait]
+impl Fetcher for HttpFetcher {
+    async fn fetch(&self, url: &str, max_bytes: u64, cancel: &CancellationToken) -> Result<u64> {
+        let response = self.client.get(url).send().await?;
+        if !response.status().is_success() {
+            return Err(Error::NetworkError(format!("{} answered {}", url, response.status())));
+        }
+
+        let mut stream = response.bytes_stream();
+        let mut received = 0u64;
+        while received < max_bytes {
+            let chunk = tokio::select! {
+                _ = cancel.cancelled() => return Err(cancelled()),
+                chunk = stream.next() => chunk,
+            };
+            match chunk {
+                Some(chunk) => received += chunk?.len() as u64,
+                None => break,
+            }
+        }
+        Ok(received)
+    }
+}
+
+fn cancelled() -> Error {
+    Error::Other("Benchmark cancelled".to_string())
+}
+
+/// Cancels the run in progress, if any
+static RUNNING: Mutex<Option<CancellationToken>> = Mutex::new(None);
+
+/// Claim the one benchmark slot; None if a run is already going
+pub fn begin() -> Option<RunGuard> {
+    let mut running = RUNNING.lock().unwrap();
+    if running.is_some() {
+        return None;
+    }
+    let token = CancellationToken::new();
+    *running = Some(token.clone());
+    Some(RunGuard { token })
+}
+
+/// Whether a run is in progress
+pub fn is_running() -> bool {
+    RUNNING.lock().unwrap().is_some()
+}
+
+/// Cancel the run in progress. Returns whether there was one.
+pub fn cancel() -> bool {
+    match RUNNING.lock().unwrap().as_ref() {
+        Some(token) => {
+            token.cancel();
+            true
+        }
+        None => false,
+    }
+}
+
+/// Held for the duration of a run; frees the slot when dropped
+pub struct RunGuard {
+    pub token: CancellationToken,
+}
+
+impl Drop for RunGuard {
+    fn drop(&mut self) {
+        *RUNNING.lock().unwrap() = None;
+    }
+}
+
+/// Why the test shouldn't run now: torrents moving more than
+/// `BUSY_THRESHOLD` between them, or cloud downloads going
+pub fn busy_reason(transfer_speed: u64, cloud_downloads: usize) -> Option<String> {
+    if cloud_downloads > 0 {
+        return Some(format!("{} cloud download(s) running", cloud_downloads));
+    }
+    if transfer_speed > BUSY_THRESHOLD {
+        return Some(format!("torrents are transferring {} KiB/s", transfer_speed / 1024));
+    }
+    None
+}
+
+/// Run the parts selected in `options`
+pub async fn run(
+    options: &BenchmarkOptions,
+    download_dir: &Path,
+    fetcher: &dyn Fetcher,
+    cancel: &CancellationToken,
+) -> Result<BenchmarkReport> {
+    let mut report = BenchmarkReport {
+        started_at: chrono::Utc::now().timestamp(),
+        disk: None,
+        network: None,
+        warnings: Vec::new(),
+    };
+
+    if options.disk {
+        let size = options.disk_size_mb.clamp(1, MAX_DISK_SIZE_MB) * 1024 * 1024;
+        report.disk = Some(disk_benchmark(download_dir, size, cancel).await?);
+    }
+
+    if options.network {
+        let url = options.network_url.as_deref().unwrap_or(DEFAULT_TEST_URL);
+        let max_bytes = options.network_max_mb.max(1) * 1024 * 1024;
+        let network = network_benchmark(fetcher, url, max_bytes, cancel).await?;
+        if network.bytes < max_bytes && network.secs < 1.0 {
+            report.warnings.push(format!(
+                "The test file was only {} bytes; use a larger one for a meaningful result",
+                network.bytes
+            ));
+        }
+        report.network = Some(network);
+    }
+
+    Ok(report)
+}
+
+/// Time writing, syncing and reading `size` bytes of pieces in `dir`
+pub async fn disk_benchmark(dir: &Path, size: u64, cancel: &CancellationToken) -> Result<DiskBenchmark> {
+    let name = format!("{}-{}", SCRATCH_NAME, std::process::id());
+    let metainfo = scratch_metainfo(&name, size)?;
+    let _scratch = Scratch(dir.join(&name));
+
+    let mut disk = DiskManager::new(&metainfo, dir.to_path_buf());
+    disk.allocate_files().await?;
+    let pieces = disk.num_pieces();
+
+    let started = Instant::now();
+    for piece in 0..pieces {
+        if cancel.is_cancelled() {
+            return Err(cancelled());
+        }
+        let length = piece_size(size, piece);
+        disk.write_piece(piece, scratch_data(piece, length)).await?;
+    }
+    let write_secs = started.elapsed().as_secs_f64();
+
+    let started = Instant::now();
+    disk.sync().await?;
+    let sync_ms = started.elapsed().as_secs_f64() * 1000.0;
+
+    let started = Instant::now();
+    for piece in 0..pieces {
+        if cancel.is_cancelled() {
+            return Err(cancelled());
+        }
+        let data = disk.read_piece(piece).await?;
+        if data != scratch_data(piece, piece_size(size, piece)) {
+            return Err(Error::IoError(format!("Piece {} of the benchmark file read back wrong", piece)));
+        }
+    }
+    let read_secs = started.elapsed().as_secs_f64();
+
+    Ok(DiskBenchmark {
+        path: dir.to_string_lossy().to_string(),
+        bytes: size,
+        write_mbps: mbps(size, write_secs),
+        sync_ms,
+        read_mbps: mbps(size, read_secs),
+    })
+}
+
+async fn network_benchmark(
+    fetcher: &dyn Fetcher,
+    url: &str,
+    max_bytes: u64,
+    cancel: &CancellationToken,
+) -> Result<NetworkBenchmark> {
+    let started = Instant::now();
+    let bytes = tokio::select! {
+        _ = cancel.cancelled() => return Err(cancelled()),
+        bytes = fetcher.fetch(url, max_bytes, cancel) => bytes?,
+    };
+    let secs = started.elapsed().as_secs_f64();
+    Ok(NetworkBenchmark { url: url.to_string(), bytes, secs, mbps: mbps(bytes, secs) })
+}
+
+/// Single-file metainfo of `size` bytes (the piece hashes are never checked)
+fn scratch_metainfo(name: &str, size: u64) -> Result<Metainfo> {
+    let pieces = size.div_ceil(PIECE_LENGTH as u64) as usize;
+    let mut data = format!(
+        "d8:announce0:4:infod6:lengthi{}e4:name{}:{}12:piece lengthi{}e6:pieces{}:",
+        size,
+        name.len(),
+        name,
+        PIECE_LENGTH,
+        pieces * 20
+    )
+    .into_bytes();
+    data.resize(data.len() + pieces * 20, 0);
+    data.extend_from_slice(b"ee");
+    Metainfo::from_bytes(&data)
+}
+
+fn piece_size(total: u64, piece: usize) -> usize {
+    let start = piece as u64 * PIECE_LENGTH as u64;
+    (total - start).min(PIECE_LENGTH as u64) as usize
+}
+
+/// Non-repeating contents, so nothing along the way can compress them away
+fn scratch_data(piece: usize, length: usize) -> Vec<u8> {
+    let mut state = (piece as u64 + 1).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+    (0..length)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as u8
+        })
+        .collect()
+}
+
+fn mbps(bytes: u64, secs: f64) -> f64 {
+    if secs <= 0.0 {
+        return 0.0;
+    }
+    bytes as f64 / 1_000_000.0 / secs
+}
+
+/// Removes the scratch file however the run ends
+struct Scratch(PathBuf);
+
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(&self.0) {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                tracing::warn!("Failed to remove benchmark file {:?}: {}", self.0, e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    /// Hands back `bytes` after `delay`, or waits for cancellation forever
+    struct MockFetcher {
+        bytes: u64,
+        delay: Option<Duration>,
+    }
+
+    #[async_trait]
+    impl Fetcher for MockFetcher {
+        async fn fetch(&self, _url: &str, max_bytes: u64, cancel: &CancellationToken) -> Result<u64> {
+            match self.delay {
+                Some(delay) => {
+                    tokio::time::sleep(delay).await;
+                    Ok(self.bytes.min(max_bytes))
+                }
+                None => {
+                    cancel.cancelled().await;
+                    Err(cancelled())
+                }
+            }
+        }
+    }
+
+    fn disk_only(size_mb: u64) -> BenchmarkOptions {
+        BenchmarkOptions { disk_size_mb: size_mb, network: false, ..BenchmarkOptions::default() }
+    }
+
+    #[tokio::test]
+    async fn test_disk_benchmark_cleans_up() {
+        let dir = TempDir::new().unwrap();
+        let fetcher = MockFetcher { bytes: 0, delay: None };
+
+        // Not a whole number of pieces, so the short last piece is covered
+        let size = 3 * PIECE_LENGTH as u64 + 1000;
+        let disk = disk_benchmark(dir.path(), size, &CancellationToken::new()).await.unwrap();
+        assert_eq!(disk.bytes, size);
+        assert!(disk.write_mbps > 0.0 && disk.read_mbps > 0.0);
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+
+        let report = run(&disk_only(2), dir.path(), &fetcher, &CancellationToken::new()).await.unwrap();
+        assert_eq!(report.disk.unwrap().bytes, 2 * 1024 * 1024);
+        assert!(report.network.is_none());
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_run_removes_scratch_file() {
+        let dir = TempDir::new().unwrap();
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+
+        let fetcher = MockFetcher { bytes: 0, delay: None };
+        assert!(run(&disk_only(4), dir.path(), &fetcher, &cancel).await.is_err());
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_network_benchmark_with_mock() {
+        let dir = TempDir::new().unwrap();
+        let options = BenchmarkOptions {
+            disk: false,
+            network_url: Some("http://test.invalid/file".to_string()),
+            network_max_mb: 1,
+            ..BenchmarkOptions::default()
+        };
+
+        let fetcher = MockFetcher { bytes: 10 * 1024 * 1024, delay: Some(Duration::from_millis(20)) };
+        let report = run(&options, dir.path(), &fetcher, &CancellationToken::new()).await.unwrap();
+        let network = report.network.unwrap();
+        assert_eq!(network.url, "http://test.invalid/file");
+        assert_eq!(network.bytes, 1024 * 1024);
+        assert!(network.mbps > 0.0);
+        assert!(report.warnings.is_empty());
+
+        // A tiny file is measured but flagged
+        let fetcher = MockFetcher { bytes: 1000, delay: Some(Duration::from_millis(1)) };
+        let report = run(&options, dir.path(), &fetcher, &CancellationToken::new()).await.unwrap();
+        assert_eq!(report.warnings.len(), 1);
+
+        // A stalled download ends when cancelled
+        let cancel = CancellationToken::new();
+        let fetcher = MockFetcher { bytes: 0, delay: None };
+        let canceller = cancel.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            canceller.cancel();
+        });
+        assert!(run(&options, dir.path(), &fetcher, &cancel).await.is_err());
+    }
+
+    #[test]
+    fn test_one_run_at_a_time() {
+        let guard = begin().unwrap();
+        assert!(begin().is_none());
+        assert!(cancel());
+        assert!(guard.token.is_cancelled());
+        drop(guard);
+        assert!(!cancel());
+        assert!(begin().is_some());
+    }
+
+    #[test]
+    fn test_busy_reason() {
+        assert_eq!(busy_reason(0, 0), None);
+        assert_eq!(busy_reason(BUSY_THRESHOLD, 0), None);
+        assert!(busy_reason(BUSY_THRESHOLD + 1, 0).is_some());
+        assert!(busy_reason(0, 1).is_some());
+    }
+}
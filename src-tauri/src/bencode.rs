@@ -6,9 +6,12 @@
 //! - Strings: <length>:<string> (e.g., 4:spam)
 //! - Lists: l<contents>e (e.g., l4:spam4:eggse)
 //! - Dictionaries: d<key><value>...e (e.g., d3:cow3:moo4:spam4:eggse)
+//!
+//! The spec requires dictionary keys to be unique and sorted. `parse` tolerates
+//! violations (the last duplicate wins), while `parse_strict` rejects them; use
+//! strict parsing for anything that gets hashed, such as info dictionaries.
 
 use crate::error::{Error, Result};
-use std::collections::HashMap;
 
 /// Bencode value types
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -22,17 +25,77 @@ pub enum BencodeValue {
     /// List of bencode values
     List(Vec<BencodeValue>),
 
-    /// Dictionary (ordered map)
-    Dictionary(HashMap<Vec<u8>, BencodeValue>),
+    /// Dictionary (entries in source order)
+    Dictionary(Dictionary),
+}
+
+/// Dictionary entries in the order they appeared in the source
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Dictionary {
+    entries: Vec<(Vec<u8>, BencodeValue)>,
+}
+
+impl Dictionary {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Look up a key
+    pub fn get(&self, key: &[u8]) -> Option<&BencodeValue> {
+        self.entries
+            .iter()
+            .find(|(k, _)| k.as_slice() == key)
+            .map(|(_, v)| v)
+    }
+
+    pub fn contains_key(&self, key: &[u8]) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// Insert or replace; a replaced entry keeps its original position
+    pub fn insert(&mut self, key: Vec<u8>, value: BencodeValue) -> Option<BencodeValue> {
+        match self.entries.iter_mut().find(|(k, _)| *k == key) {
+            Some((_, existing)) => Some(std::mem::replace(existing, value)),
+            None => {
+                self.entries.push((key, value));
+                None
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Entries in source order
+    pub fn iter(&self) -> impl Iterator<Item = (&[u8], &BencodeValue)> {
+        self.entries.iter().map(|(k, v)| (k.as_slice(), v))
+    }
+
+    /// Keys in source order
+    pub fn keys(&self) -> impl Iterator<Item = &[u8]> {
+        self.entries.iter().map(|(k, _)| k.as_slice())
+    }
 }
 
 impl BencodeValue {
-    /// Parse bencode data from bytes
+    /// Parse bencode data from bytes, tolerating duplicate and unsorted keys
     pub fn parse(data: &[u8]) -> Result<Self> {
         let mut parser = Parser::new(data);
         parser.parse_value()
     }
 
+    /// Parse bencode data, rejecting duplicate or unsorted dictionary keys
+    pub fn parse_strict(data: &[u8]) -> Result<Self> {
+        let mut parser = Parser::new(data);
+        parser.strict = true;
+        parser.parse_value()
+    }
+
     /// Get as integer
     pub fn as_integer(&self) -> Option<i64> {
         match self {
@@ -64,7 +127,7 @@ impl BencodeValue {
     }
 
     /// Get as dictionary
-    pub fn as_dict(&self) -> Option<&HashMap<Vec<u8>, BencodeValue>> {
+    pub fn as_dict(&self) -> Option<&Dictionary> {
         match self {
             Self::Dictionary(dict) => Some(dict),
             _ => None,
@@ -85,17 +148,93 @@ impl BencodeValue {
     pub fn dict_get_int(&self, key: &[u8]) -> Option<i64> {
         self.dict_get(key).and_then(|v| v.as_integer())
     }
+
+    /// Encode to bytes, dictionary entries in their stored order
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.encode_into(&mut out);
+        out
+    }
+
+    fn encode_into(&self, out: &mut Vec<u8>) {
+        match self {
+            Self::Integer(n) => out.extend_from_slice(format!("i{}e", n).as_bytes()),
+            Self::ByteString(bytes) => {
+                out.extend_from_slice(format!("{}:", bytes.len()).as_bytes());
+                out.extend_from_slice(bytes);
+            }
+            Self::List(items) => {
+                out.push(b'l');
+                for item in items {
+                    item.encode_into(out);
+                }
+                out.push(b'e');
+            }
+            Self::Dictionary(dict) => {
+                out.push(b'd');
+                for (key, value) in dict.iter() {
+                    Self::ByteString(key.to_vec()).encode_into(out);
+                    value.encode_into(out);
+                }
+                out.push(b'e');
+            }
+        }
+    }
+}
+
+/// Find the exact encoded bytes of a top-level dictionary value.
+///
+/// Needed wherever the original encoding matters (e.g. the info hash is the
+/// SHA-1 of the `info` value exactly as it appears in the .torrent file).
+/// A key that appears twice is an error, so the caller can't be handed a
+/// different copy than some other parser would pick.
+pub fn raw_dict_value<'a>(data: &'a [u8], key: &[u8]) -> Result<Option<&'a [u8]>> {
+    let mut parser = Parser::new(data);
+    parser.expect(b'd')?;
+    let mut found = None;
+
+    while parser.pos < data.len() && data[parser.pos] != b'e' {
+        let entry_key = match parser.parse_value()? {
+            BencodeValue::ByteString(bytes) => bytes,
+            _ => {
+                return Err(Error::BencodeError(
+                    "dictionary key must be a string".to_string(),
+                ))
+            }
+        };
+
+        let start = parser.pos;
+        parser.parse_value()?;
+        if entry_key == key {
+            if found.is_some() {
+                return Err(duplicate_key(&entry_key));
+            }
+            found = Some(&data[start..parser.pos]);
+        }
+    }
+
+    parser.expect(b'e')?;
+    Ok(found)
+}
+
+fn duplicate_key(key: &[u8]) -> Error {
+    Error::BencodeError(format!(
+        "duplicate dictionary key: {}",
+        String::from_utf8_lossy(key)
+    ))
 }
 
 /// Bencode parser
 struct Parser<'a> {
     data: &'a [u8],
     pos: usize,
+    /// Reject duplicate and out-of-order dictionary keys
+    strict: bool,
 }
 
 impl<'a> Parser<'a> {
     fn new(data: &'a [u8]) -> Self {
-        Self { data, pos: 0 }
+        Self { data, pos: 0, strict: false }
     }
 
     fn parse_value(&mut self) -> Result<BencodeValue> {
@@ -205,7 +344,8 @@ impl<'a> Parser<'a> {
         // Format: d<key><value>...e
         self.expect(b'd')?;
 
-        let mut dict = HashMap::new();
+        let mut dict = Dictionary::new();
+        let mut previous_key: Option<Vec<u8>> = None;
 
         while self.pos < self.data.len() && self.data[self.pos] != b'e' {
             // Keys must be byte strings
@@ -218,7 +358,24 @@ impl<'a> Parser<'a> {
                 }
             };
 
+            if self.strict {
+                if let Some(previous) = &previous_key {
+                    if key == *previous {
+                        return Err(duplicate_key(&key));
+                    }
+                    if key < *previous {
+                        return Err(Error::BencodeError(format!(
+                            "dictionary key out of order: {} after {}",
+                            String::from_utf8_lossy(&key),
+                            String::from_utf8_lossy(previous)
+                        )));
+                    }
+                }
+            }
+
             let value = self.parse_value()?;
+            previous_key = Some(key.clone());
+            // Lenient mode: last duplicate wins, as before
             dict.insert(key, value);
         }
 
@@ -294,4 +451,61 @@ mod tests {
 
         assert_eq!(value.dict_get_int(b"number"), Some(42));
     }
+
+    #[test]
+    fn test_raw_dict_value() {
+        let data = b"d4:infod4:name1:ae6:numberi42ee";
+        assert_eq!(raw_dict_value(data, b"info").unwrap(), Some(&b"d4:name1:ae"[..]));
+        assert_eq!(raw_dict_value(data, b"number").unwrap(), Some(&b"i42e"[..]));
+        assert_eq!(raw_dict_value(data, b"missing").unwrap(), None);
+        assert!(raw_dict_value(b"l4:spame", b"info").is_err());
+    }
+
+    #[test]
+    fn test_dict_preserves_source_order() {
+        let value = BencodeValue::parse(b"d4:spam4:eggs3:cow3:mooe").unwrap();
+        let keys: Vec<&[u8]> = value.as_dict().unwrap().keys().collect();
+        assert_eq!(keys, vec![&b"spam"[..], &b"cow"[..]]);
+    }
+
+    #[test]
+    fn test_encode_round_trip() {
+        let data = b"d4:spaml1:a1:be3:cowi-3e4:infod6:lengthi0eee";
+        let value = BencodeValue::parse(data).unwrap();
+        assert_eq!(value.encode(), data.to_vec());
+        assert_eq!(BencodeValue::ByteString(Vec::new()).encode(), b"0:".to_vec());
+    }
+
+    #[test]
+    fn test_duplicate_keys() {
+        let data = b"d3:cow3:moo3:cow4:oinke";
+
+        // Lenient: last one wins
+        let value = BencodeValue::parse(data).unwrap();
+        assert_eq!(value.dict_get_str(b"cow"), Some("oink"));
+        assert_eq!(value.as_dict().unwrap().len(), 1);
+
+        let err = BencodeValue::parse_strict(data).unwrap_err().to_string();
+        assert!(err.contains("duplicate dictionary key: cow"), "{}", err);
+
+        let err = raw_dict_value(b"d4:infod1:ai1ee4:infod1:ai2eee", b"info").unwrap_err();
+        assert!(err.to_string().contains("duplicate dictionary key: info"));
+    }
+
+    #[test]
+    fn test_unsorted_keys() {
+        let data = b"d4:spam4:eggs3:cow3:mooe";
+        assert!(BencodeValue::parse(data).is_ok());
+
+        let err = BencodeValue::parse_strict(data).unwrap_err().to_string();
+        assert!(err.contains("out of order: cow after spam"), "{}", err);
+
+        // Nested dictionaries are checked too
+        let nested = b"d5:filesld4:pathl1:ae6:lengthi1eeee";
+        assert!(BencodeValue::parse(nested).is_ok());
+        let err = BencodeValue::parse_strict(nested).unwrap_err().to_string();
+        assert!(err.contains("length after path"), "{}", err);
+
+        assert!(BencodeValue::parse_strict(b"d3:cow3:moo4:spam4:eggse").is_ok());
+    }
 }
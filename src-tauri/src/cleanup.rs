@@ -1,101 +1,151 @@
+//! Auto-cleanup of torrents that seeded enough
+//!
+//! Runs under the background task supervisor; the policy comes from the
+//! settings through `AppState::cleanup_policy`.
+
+use crate::database::AppSettings;
+#[cfg(feature = "app")]
 use crate::state::AppState;
-use tauri::{Manager, Emitter};
-use tokio::time::{self, Duration};
+#[cfg(feature = "app")]
+use tauri::Manager;
+use tokio::time::Duration;
+#[cfg(feature = "app")]
 use crate::engine::EngineState;
 
-pub async fn start_cleanup_task(app_handle: tauri::AppHandle) {
-    let mut interval = time::interval(Duration::from_secs(60)); // Check every minute
+/// How often seeding torrents are checked against the policy
+pub const CLEANUP_INTERVAL: Duration = Duration::from_secs(60);
 
-    loop {
-        interval.tick().await;
+/// Cleanup ratio before the user sets one
+pub const DEFAULT_RATIO: f32 = 2.0;
 
-        let state_guard = app_handle.state::<AppState>();
-        
-        // Load settings from database
-        let settings = match state_guard.database.load_settings() {
-            Ok(s) => s,
-            Err(e) => {
-                tracing::error!("Cleanup task failed to load settings: {}", e);
-                continue;
+pub fn default_ratio() -> f32 {
+    DEFAULT_RATIO
+}
+
+pub fn default_mode() -> String {
+    "Pause".to_string()
+}
+
+/// The cleanup settings
+#[derive(Debug, Clone, PartialEq)]
+pub struct CleanupPolicy {
+    pub enabled: bool,
+    /// Seeding ratio limit (0.0 = unlimited)
+    pub ratio: f32,
+    /// Seeding time limit in seconds (0 = unlimited)
+    pub time: u64,
+    /// "Pause", "Remove" or "Delete"
+    pub mode: String,
+}
+
+impl From<&AppSettings> for CleanupPolicy {
+    fn from(settings: &AppSettings) -> Self {
+        Self {
+            enabled: settings.cleanup_enabled,
+            ratio: settings.cleanup_ratio,
+            time: settings.cleanup_time,
+            mode: settings.cleanup_mode.clone(),
+        }
+    }
+}
+
+/// Register the cleanup task with the supervisor
+#[cfg(feature = "app")]
+pub fn register_cleanup_task(app_handle: tauri::AppHandle) -> Result<(), String> {
+    let state = app_handle.state::<AppState>();
+    let policy = state.cleanup_policy.subscribe();
+    let app = app_handle.clone();
+    state.background_tasks.register("cleanup", CLEANUP_INTERVAL, policy, move |policy: CleanupPolicy| {
+        let app = app.clone();
+        crate::tasks::job(move || {
+            let (app, policy) = (app.clone(), policy.clone());
+            async move {
+                run_cleanup(&app, &policy).await;
+                Ok(())
             }
-        };
+        })
+    })
+}
+
+/// Apply the policy to every seeding torrent
+#[cfg(feature = "app")]
+async fn run_cleanup(app_handle: &tauri::AppHandle, settings: &CleanupPolicy) {
+    let state_guard = app_handle.state::<AppState>();
 
-        if !settings.cleanup_enabled {
+    if !settings.enabled {
+        return;
+    }
+
+    // Get snapshot of engines (cloning the map to avoid holding lock while iterating and locking engines)
+    let engines_map = state_guard.engines.read().await.clone();
+    
+    for (id, engine_arc) in engines_map {
+        // Read stats
+        let engine = engine_arc.read().await;
+        let stats = engine.get_stats().await;
+        
+        // Only consider Seeding torrents
+        if stats.state != EngineState::Seeding {
             continue;
         }
 
-        // Get snapshot of engines (cloning the map to avoid holding lock while iterating and locking engines)
-        let engines_map = state_guard.engines.read().await.clone();
-        
-        for (id, engine_arc) in engines_map {
-            // Read stats
-            let engine = engine_arc.read().await;
-            let stats = engine.get_stats().await;
-            
-            // Only consider Seeding torrents
-            if stats.state != EngineState::Seeding {
-                continue;
-            }
+        let metainfo = engine.metainfo();
+        let total_size = metainfo.info.total_size;
+        let torrent_name = metainfo.info.name.clone();
+        let completed_at = stats.completed_at;
+        let uploaded = stats.uploaded_bytes;
+        drop(engine); // Release read lock
 
-            let metainfo = engine.metainfo();
-            let total_size = metainfo.info.total_size;
-            let torrent_name = metainfo.info.name.clone();
-            let completed_at = stats.completed_at;
-            let uploaded = stats.uploaded_bytes;
-            drop(engine); // Release read lock
+        let mut should_cleanup = false;
+        let mut reason = String::new();
 
-            let mut should_cleanup = false;
-            let mut reason = String::new();
+        // Check Ratio
+        if settings.ratio > 0.0 && total_size > 0 {
+            let ratio = uploaded as f64 / total_size as f64;
+            if ratio >= settings.ratio as f64 {
+                should_cleanup = true;
+                reason = format!("Ratio reached {:.2} (limit {:.2})", ratio, settings.ratio);
+            }
+        }
 
-            // Check Ratio
-            if settings.cleanup_ratio > 0.0 && total_size > 0 {
-                let ratio = uploaded as f64 / total_size as f64;
-                if ratio >= settings.cleanup_ratio as f64 {
+        // Check Time
+        if !should_cleanup && settings.time > 0 {
+            if let Some(ts) = completed_at {
+                let now = chrono::Utc::now().timestamp();
+                let seeded_seconds = now - ts;
+                if seeded_seconds >= settings.time as i64 {
                     should_cleanup = true;
-                    reason = format!("Ratio reached {:.2} (limit {:.2})", ratio, settings.cleanup_ratio);
+                    reason = format!("Seeding time reached {}s (limit {}s)", seeded_seconds, settings.time);
                 }
             }
+        }
 
-            // Check Time
-            if !should_cleanup && settings.cleanup_time > 0 {
-                if let Some(ts) = completed_at {
-                    let now = chrono::Utc::now().timestamp();
-                    let seeded_seconds = now - ts;
-                    if seeded_seconds >= settings.cleanup_time as i64 {
-                        should_cleanup = true;
-                        reason = format!("Seeding time reached {}s (limit {}s)", seeded_seconds, settings.cleanup_time);
-                    }
-                }
-            }
+        if should_cleanup {
+            tracing::info!("Auto-cleanup triggered for {} ({}): {}", torrent_name, id, reason);
+            
+            match settings.mode.as_str() {
+                "Pause" => {
+                     let engine = engine_arc.read().await; 
+                     let _ = engine.command_handle().send(crate::engine::EngineCommand::Pause).await;
+                     drop(engine);
 
-            if should_cleanup {
-                tracing::info!("Auto-cleanup triggered for {} ({}): {}", torrent_name, id, reason);
-                
-                match settings.cleanup_mode.as_str() {
-                    "Pause" => {
-                         let engine = engine_arc.read().await; 
-                         let _ = engine.command_sender().send(crate::engine::EngineCommand::Pause);
-                         drop(engine);
-
-                         // Update UI state
-                         let mut torrents = state_guard.torrents.write().await;
-                         if let Some(torrent) = torrents.get_mut(&id) {
-                             torrent.state = crate::state::TorrentState::Paused;
-                         }
-                    }
-                    "Remove" => {
-                        let _ = crate::commands::remove_torrent_internal(&state_guard, id.clone(), false).await;
-                    }
-                    "Delete" => {
-                        let _ = crate::commands::remove_torrent_internal(&state_guard, id.clone(), true).await;
-                    }
-                    _ => {}
+                     // Update UI state
+                     let mut torrents = state_guard.torrents.write().await;
+                     if let Some(torrent) = torrents.get_mut(&id) {
+                         torrent.state = crate::state::TorrentState::Paused;
+                     }
                 }
-                
-               if let Err(e) = app_handle.emit("cleanup-triggered", format!("Cleaned up {}: {}", torrent_name, reason)) {
-                   tracing::error!("Failed to emit cleanup-triggered event: {}", e);
-               }
+                "Remove" => {
+                    let _ = crate::commands::remove_torrent_internal(&state_guard, id.clone(), false, false, false).await;
+                }
+                "Delete" => {
+                    let _ = crate::commands::remove_torrent_internal(&state_guard, id.clone(), true, false, false).await;
+                }
+                _ => {}
             }
+            
+            let message = format!("Cleaned up {}: {}", torrent_name, reason);
+            crate::events::emit_event(app_handle, crate::events::Event::CleanupTriggered(message));
         }
     }
 }
@@ -0,0 +1,254 @@
+//! System clock sanity check
+//!
+//! A badly wrong clock makes every HTTPS tracker and debrid call fail
+//! certificate validation with opaque errors. When such an error shows up we
+//! compare our clock with the `Date` header of a plain-HTTP response; once the
+//! skew is known, network errors are reported as `Error::ClockSkew` so the UI
+//! can point at the clock instead of the network.
+
+use crate::error::Error;
+#[cfg(feature = "app")]
+use crate::events::{emit_event, ClockSkewEvent, Event};
+use serde::Serialize;
+use std::sync::atomic::{AtomicBool, AtomicI64, Ordering};
+use std::time::Duration;
+use tokio::sync::Notify;
+
+/// Skew (either way) beyond which the clock is considered wrong
+pub const MAX_CLOCK_SKEW_SECS: i64 = 60 * 60;
+
+/// Probed by `check_clock` when no URL is given (plain HTTP on purpose)
+pub const DEFAULT_PROBE_URL: &str = "http://detectportal.firefox.com/success.txt";
+
+const PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Latest clock measurement
+struct ClockCheck {
+    /// Local clock minus server clock, in seconds
+    skew_secs: AtomicI64,
+    /// Whether `skew_secs` is over the threshold
+    skewed: AtomicBool,
+    /// Set while a probe is out, so a burst of failures probes once
+    probing: AtomicBool,
+    /// Signalled when `skewed` flips
+    changed: Notify,
+}
+
+static CLOCK: ClockCheck = ClockCheck {
+    skew_secs: AtomicI64::new(0),
+    skewed: AtomicBool::new(false),
+    probing: AtomicBool::new(false),
+    changed: Notify::const_new(),
+};
+
+/// Offset of `local_now` from the time in an HTTP `Date` header (seconds;
+/// positive when the local clock is ahead). None if the header doesn't parse.
+pub fn measure_skew(local_now: i64, date_header: &str) -> Option<i64> {
+    let server = chrono::DateTime::parse_from_rfc2822(date_header.trim()).ok()?;
+    Some(local_now - server.timestamp())
+}
+
+/// Whether an error (full source chain, see `error_text`) is a certificate
+/// rejected for being expired or not yet valid, as a wrong clock causes
+pub fn is_certificate_validity_error(text: &str) -> bool {
+    let text = text.to_ascii_lowercase();
+    [
+        // rustls
+        "certificate expired",
+        "expired",
+        "notvalidyet",
+        "not valid yet",
+        // OpenSSL
+        "certificate has expired",
+        "certificate is not yet valid",
+        // Windows SChannel (CERT_E_EXPIRED)
+        "not within its validity period",
+    ]
+    .iter()
+    .any(|needle| text.contains(needle))
+        && ["certificate", "tls", "ssl"].iter().any(|ctx| text.contains(ctx))
+}
+
+/// An error and all its sources, joined (reqwest's Display hides the TLS cause)
+pub fn error_text(err: &(dyn std::error::Error + 'static)) -> String {
+    let mut text = err.to_string();
+    let mut source = err.source();
+    while let Some(cause) = source {
+        text.push_str(": ");
+        text.push_str(&cause.to_string());
+        source = cause.source();
+    }
+    text
+}
+
+/// "3d 4h", "2h 5m", "12m" (at least a minute)
+pub fn describe_duration(secs: u64) -> String {
+    let (days, hours, minutes) = (secs / 86_400, secs % 86_400 / 3600, secs % 3600 / 60);
+    match (days, hours) {
+        (0, 0) => format!("{}m", minutes.max(1)),
+        (0, _) => format!("{}h {}m", hours, minutes),
+        _ => format!("{}d {}h", days, hours),
+    }
+}
+
+/// "3d 4h ahead", "12m behind", ...
+pub fn describe_skew(skew_secs: i64) -> String {
+    let direction = if skew_secs > 0 { "ahead" } else { "behind" };
+    format!("{} {}", describe_duration(skew_secs.unsigned_abs()), direction)
+}
+
+/// Record a measurement; a change of verdict wakes the event task
+pub fn record_skew(skew_secs: i64) {
+    let skewed = skew_secs.abs() > MAX_CLOCK_SKEW_SECS;
+    CLOCK.skew_secs.store(skew_secs, Ordering::Relaxed);
+    if CLOCK.skewed.swap(skewed, Ordering::AcqRel) != skewed {
+        if skewed {
+            tracing::warn!("Clock skew detected: system clock is {}", describe_skew(skew_secs));
+        } else {
+            tracing::info!("System clock is back in sync");
+        }
+        CLOCK.changed.notify_one();
+    }
+}
+
+/// Measure against a response we already have (e.g. a tracker's)
+pub fn observe_date_header(headers: &reqwest::header::HeaderMap) {
+    let date = headers.get(reqwest::header::DATE).and_then(|v| v.to_str().ok());
+    if let Some(skew) = date.and_then(|d| measure_skew(chrono::Utc::now().timestamp(), d)) {
+        record_skew(skew);
+    }
+}
+
+/// Skew of the local clock if it is known to be wrong
+pub fn detected_skew() -> Option<i64> {
+    CLOCK.skewed.load(Ordering::Acquire).then(|| CLOCK.skew_secs.load(Ordering::Relaxed))
+}
+
+/// Measure the skew with a plain-HTTP request to `url`'s host
+pub async fn probe(url: &str) -> Result<i64, Error> {
+    let mut probe_url = reqwest::Url::parse(url)
+        .map_err(|e| Error::ValidationError(format!("Invalid probe URL: {}", e)))?;
+    if probe_url.scheme() == "https" {
+        // No TLS, so the wrong clock can't get in the way
+        probe_url.set_path("/");
+        probe_url.set_query(None);
+        let _ = probe_url.set_scheme("http");
+        let _ = probe_url.set_port(None);
+    }
+
+    let client = reqwest::Client::builder()
+        .timeout(PROBE_TIMEOUT)
+        .build()
+        .map_err(|e| Error::NetworkError(format!("Failed to build HTTP client: {}", e)))?;
+    let response = client
+        .head(probe_url.clone())
+        .send()
+        .await
+        .map_err(|e| Error::NetworkError(format!("Clock probe to {} failed: {}", probe_url, e)))?;
+
+    let date = response
+        .headers()
+        .get(reqwest::header::DATE)
+        .and_then(|v| v.to_str().ok())
+        .ok_or_else(|| Error::InvalidData(format!("{} sent no Date header", probe_url)))?;
+    let skew = measure_skew(chrono::Utc::now().timestamp(), date)
+        .ok_or_else(|| Error::InvalidData(format!("Unparseable Date header: {}", date)))?;
+
+    record_skew(skew);
+    Ok(skew)
+}
+
+/// Called with failed requests: a certificate validity error starts a
+/// background probe of the same host (at most one at a time)
+pub fn on_request_error(text: &str, url: Option<&reqwest::Url>) {
+    let Some(url) = url else { return };
+    if !is_certificate_validity_error(text) || CLOCK.probing.swap(true, Ordering::AcqRel) {
+        return;
+    }
+    let url = url.to_string();
+    tokio::spawn(async move {
+        if let Err(e) = probe(&url).await {
+            tracing::debug!("Clock probe after certificate error failed: {}", e);
+        }
+        CLOCK.probing.store(false, Ordering::Release);
+    });
+}
+
+/// A network error, reported as clock skew while the clock is known to be wrong
+pub fn network_error(message: String) -> Error {
+    match detected_skew() {
+        Some(skew) => Error::ClockSkew(format!("{} (system clock is {})", message, describe_skew(skew))),
+        None => Error::NetworkError(message),
+    }
+}
+
+/// Emit `clock-skew-detected` whenever the clock is found to be wrong
+#[cfg(feature = "app")]
+pub async fn start_clock_task(app_handle: tauri::AppHandle) {
+    loop {
+        CLOCK.changed.notified().await;
+        let Some(skew_secs) = detected_skew() else { continue };
+        let event = ClockSkewEvent {
+            skew_secs,
+            message: format!("Clock skew detected (off by {})", describe_skew(skew_secs)),
+        };
+        emit_event(&app_handle, Event::ClockSkew(event));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_measure_skew() {
+        // Sun, 06 Nov 1994 08:49:37 GMT
+        let server = 784_111_777;
+        let date = "Sun, 06 Nov 1994 08:49:37 GMT";
+
+        assert_eq!(measure_skew(server, date), Some(0));
+        assert_eq!(measure_skew(server + 3 * 86_400, date), Some(3 * 86_400));
+        assert_eq!(measure_skew(server - 90, &format!(" {} ", date)), Some(-90));
+        assert_eq!(measure_skew(server, "Sun, 06 Nov 1994 09:49:37 +0100"), Some(0));
+        assert_eq!(measure_skew(server, "yesterday"), None);
+        assert_eq!(measure_skew(server, ""), None);
+    }
+
+    #[test]
+    fn test_certificate_validity_errors() {
+        let validity = [
+            // rustls (reqwest default features)
+            "error sending request for url (https://tracker.example/announce): client error (Connect): invalid peer certificate: Expired",
+            "error sending request for url (https://api.torbox.app/v1/api/user/me): client error (Connect): invalid peer certificate: NotValidYet",
+            "client error (Connect): invalid peer certificate: certificate expired: verification time 1900000000 (UNIX), but certificate is not valid after 1800000000 (99999999 seconds ago)",
+            "client error (Connect): invalid peer certificate: certificate not valid yet: verification time 1000000000 (UNIX), but certificate is not valid before 1700000000",
+            // native-tls / OpenSSL
+            "error trying to connect: error:0A000086:SSL routines:tls_post_process_server_certificate:certificate verify failed:../ssl/statem/statem_clnt.c:1889: (certificate has expired)",
+            "error trying to connect: error:1416F086:SSL routines:tls_process_server_certificate:certificate verify failed (certificate is not yet valid)",
+            // SChannel
+            "error trying to connect: A required certificate is not within its validity period when verifying against the current system clock or the timestamp in the signed file. (os error -2146762495)",
+        ];
+        for text in validity {
+            assert!(is_certificate_validity_error(text), "{}", text);
+        }
+
+        let other = [
+            "client error (Connect): invalid peer certificate: UnknownIssuer",
+            "error trying to connect: certificate verify failed:../ssl/statem/statem_clnt.c:1889: (self-signed certificate)",
+            "error trying to connect: (unable to get local issuer certificate)",
+            "error sending request for url (http://tracker.example/announce): operation timed out",
+            "Tracker error: torrent expired",
+            "error trying to connect: tcp connect error: Connection refused (os error 111)",
+        ];
+        for text in other {
+            assert!(!is_certificate_validity_error(text), "{}", text);
+        }
+    }
+
+    #[test]
+    fn test_describe_skew() {
+        assert_eq!(describe_skew(3 * 86_400 + 4 * 3600 + 59), "3d 4h ahead");
+        assert_eq!(describe_skew(-(2 * 3600 + 5 * 60)), "2h 5m behind");
+        assert_eq!(describe_skew(-20), "1m behind");
+    }
+}
@@ -2,505 +2,2376 @@
 //! 
 //! Handles downloading torrents through Real-Debrid, Torbox, etc.
 
-use crate::debrid::types::{DebridProviderType, DebridFile};
+use crate::database::FileCollisionPolicy;
+use crate::debrid::types::{DebridProviderType, DebridFile, RemoteFileInfo};
 use crate::debrid::DebridManager;
 use crate::error::Result;
+use crate::events::{Event, EventSink};
+use crate::ids::DebridTorrentId;
 use crate::state::TorrentState;
-use std::path::PathBuf;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use tokio::sync::RwLock;
-use tokio::fs::File;
+use tokio::sync::{oneshot, RwLock};
+use tokio::fs::{File, OpenOptions};
 use tokio::io::AsyncWriteExt;
 use tokio::time::{sleep, Duration};
 use tokio_util::sync::CancellationToken;
 
+mod progress;
+
+pub use progress::{CloudProgress, FINISHED_GRACE, MAX_LIVE_TORRENTS};
+
 /// Polling interval for checking debrid download status (in seconds)
 const POLL_INTERVAL: u64 = 10;
 
-/// Cloud download manager
+/// Failed polls in a row (each already retried by the provider client)
+/// after which a provider counts as down
+const MAX_POLL_ERRORS: u32 = 3;
+
+/// Attempts made to delete a finished torrent from the provider
+const DELETE_RETRY_ATTEMPTS: u32 = 3;
+
+/// Delay before the first delete retry (doubled on each further attempt)
+const DELETE_RETRY_BASE_DELAY: Duration = Duration::from_secs(2);
+
+/// How long a cancelled download task gets to stop before it is aborted
+const TASK_STOP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long a download waits for the user to pick files before taking all of them
+const FILE_SELECTION_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Times a file's link is refreshed after expiring before the file errors
+const MAX_LINK_REFRESHES: u32 = 3;
+
+/// Error bodies that mark a dead link even without a 403/410
+const EXPIRED_LINK_BODIES: &[&str] = &["expired", "invalid link", "link not found", "unavailable_file"];
+
+/// Download tasks waiting for the user to pick files (by debrid torrent id)
+pub type FileSelectionWaiters = Arc<RwLock<HashMap<String, oneshot::Sender<()>>>>;
+
+/// Payload of the `debrid-file-selection` event
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FileSelectionRequest {
+    /// Info hash of the cloud torrent in the torrent list
+    pub torrent_id: String,
+    pub provider: DebridProviderType,
+    /// Id to pass back to `select_debrid_files`
+    pub debrid_torrent_id: String,
+    pub files: Vec<RemoteFileInfo>,
+    /// Seconds until everything is selected automatically
+    pub timeout_secs: u64,
+}
+
+/// Wake the download task waiting on `debrid_torrent_id`, if there is one
+pub async fn finish_file_selection(waiters: &FileSelectionWaiters, debrid_torrent_id: &str) -> bool {
+    match waiters.write().await.remove(debrid_torrent_id) {
+        Some(waiter) => waiter.send(()).is_ok(),
+        None => false,
+    }
+}
+
+/// Suffix of a cloud file that is still being downloaded
+pub const PART_SUFFIX: &str = ".scpart";
+
+/// Where `destination` lives until it is complete
+pub fn part_path(destination: &Path) -> PathBuf {
+    let mut name = destination.file_name().unwrap_or_default().to_os_string();
+    name.push(PART_SUFFIX);
+    destination.with_file_name(name)
+}
+
+/// A running cloud download's task
+pub struct CloudTask {
+    pub handle: tokio::task::JoinHandle<()>,
+    pub cancel: CancellationToken,
+}
+
+impl CloudTask {
+    /// Cancel the task and wait for it to stop (it checks between polls and
+    /// between chunks), aborting it if it takes longer than `TASK_STOP_TIMEOUT`
+    pub async fn stop(self) {
+        self.cancel.cancel();
+        let abort = self.handle.abort_handle();
+        if tokio::time::timeout(TASK_STOP_TIMEOUT, self.handle).await.is_err() {
+            tracing::warn!("Cloud download task did not stop in time, aborting it");
+            abort.abort();
+        }
+    }
+}
+
+/// Delete the part files left in `save_path` by downloads of `file_names`;
+/// returns how many were deleted
+pub async fn remove_part_files(save_path: &Path, file_names: impl IntoIterator<Item = String>) -> usize {
+    let mut removed = 0;
+    for name in file_names {
+        let part = part_path(&save_path.join(&name));
+        match tokio::fs::remove_file(&part).await {
+            Ok(()) => {
+                tracing::info!("Deleted partial cloud file: {:?}", part);
+                removed += 1;
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => tracing::error!("Failed to delete partial cloud file {:?}: {}", part, e),
+        }
+    }
+    removed
+}
+
+/// How a cloud download is started; kept to start it again on resume
+#[derive(Clone)]
+pub struct CloudDownloadParams {
+    /// Info hash of the torrent in the torrent list
+    pub info_hash: String,
+    pub debrid_torrent_id: DebridTorrentId,
+    pub provider: DebridProviderType,
+    /// Directory the files are written to
+    pub save_path: PathBuf,
+    /// Delete the torrent from the provider once every file is local
+    pub delete_after_download: bool,
+    pub file_collision: FileCollisionPolicy,
+    /// Offer a torrent waiting for file selection to the UI instead of
+    /// selecting every file right away
+    pub ask_file_selection: bool,
+    pub failover: FailoverPlan,
+    pub events: Option<EventSink>,
+}
+
+/// A download in the registry; `task` is None while it is paused
+struct Registered {
+    params: CloudDownloadParams,
+    task: Option<CloudTask>,
+}
+
+/// Cloud downloads: starts them, and keeps every one by info hash until it
+/// is cancelled (finished ones stay, like a seeding torrent). One lives in
+/// `AppState::cloud_downloads`. Requests to the providers are paced by the
+/// debrid manager's own request queues.
 pub struct CloudDownloadManager {
-    /// Debrid manager for API calls
     debrid_manager: Arc<RwLock<DebridManager>>,
-    /// HTTP client for downloading files
+    /// Shared by every file download
     client: reqwest::Client,
+    torrents: Arc<RwLock<HashMap<String, crate::state::TorrentInfo>>>,
+    file_progress: CloudProgress,
+    file_selections: FileSelectionWaiters,
+    downloads: RwLock<HashMap<String, Registered>>,
 }
 
 impl CloudDownloadManager {
-    /// Create a new cloud download manager
-    pub fn new(debrid_manager: Arc<RwLock<DebridManager>>) -> Self {
+    pub fn new(
+        debrid_manager: Arc<RwLock<DebridManager>>,
+        torrents: Arc<RwLock<HashMap<String, crate::state::TorrentInfo>>>,
+        file_progress: CloudProgress,
+        file_selections: FileSelectionWaiters,
+    ) -> Self {
         Self {
             debrid_manager,
             client: reqwest::Client::builder()
-                .timeout(std::time::Duration::from_secs(30))
+                .timeout(std::time::Duration::from_secs(300))  // 5 min for large file downloads
                 .build()
                 .expect("Failed to create HTTP client"),
+            torrents,
+            file_progress,
+            file_selections,
+            downloads: RwLock::new(HashMap::new()),
         }
     }
 
-    /// Add torrent to debrid service and start downloading
-    /// Returns the debrid torrent ID
-    pub async fn add_torrent(
-        &self,
-        magnet_or_hash: &str,
-        provider: DebridProviderType,
-    ) -> Result<String> {
-        tracing::info!("Adding torrent to {:?}: {}", provider, magnet_or_hash);
-        
-        let debrid_manager = self.debrid_manager.read().await;
-        let request = crate::debrid::AddTorrentRequest::Magnet(magnet_or_hash.to_string());
-        let torrent_id = debrid_manager.add_to_cloud(provider, request).await?;
-        
-        tracing::info!("Added torrent to debrid service: {}", torrent_id.id);
-        Ok(torrent_id.id)
+    /// Start downloading `params.info_hash` in the background. Adding the
+    /// same torrent again replaces its earlier download.
+    ///
+    /// The task:
+    /// 1. Polls the debrid service every POLL_INTERVAL seconds
+    /// 2. Gets download links when the torrent is ready
+    /// 3. Downloads all files to the save path
+    /// 4. Updates the torrent's state in the torrent list
+    /// 5. Optionally deletes the torrent from the provider once every file is local
+    ///
+    /// Files are written as `<name>.scpart` and only renamed to their final
+    /// name once complete; a leftover part file is resumed with a Range request.
+    ///
+    /// When the provider fails for good, the torrent is added to the next
+    /// provider of `params.failover` and the download carries on from there
+    /// (see `FailoverPlan`).
+    pub async fn start_download(&self, params: CloudDownloadParams) -> tokio::task::AbortHandle {
+        let task = self.spawn(params.clone());
+        let running = task.handle.abort_handle();
+        let info_hash = params.info_hash.clone();
+        let previous = self.downloads.write().await.insert(info_hash, Registered { params, task: Some(task) });
+        if let Some(CloudTask { cancel, .. }) = previous.and_then(|previous| previous.task) {
+            cancel.cancel();
+        }
+        running
     }
 
-    /// Get download links for a torrent from debrid service
-    pub async fn get_download_links(
-        &self,
-        torrent_id: &str,
-        provider: DebridProviderType,
-    ) -> Result<Vec<DebridFile>> {
-        tracing::info!("Getting download links for torrent: {}", torrent_id);
-        
-        let debrid_manager = self.debrid_manager.read().await;
-        let files = debrid_manager.get_download_links(provider, torrent_id).await?;
-        
-        Ok(files)
+    /// Stop a running download, keeping it to resume; false if it wasn't running
+    pub async fn pause(&self, info_hash: &str) -> bool {
+        let task = match self.downloads.write().await.get_mut(info_hash) {
+            Some(registered) => registered.task.take(),
+            None => return false,
+        };
+        let Some(task) = task else { return false };
+        task.stop().await;
+        self.set_state(info_hash, TorrentState::Paused).await;
+        true
     }
 
-    /// Download a file from a direct URL to the specified path
-    pub async fn download_file(
-        &self,
-        url: &str,
-        destination: PathBuf,
-    ) -> Result<()> {
-        tracing::info!("Downloading file from {} to {:?}", url, destination);
-        
-        // Create parent directories if they don't exist
-        if let Some(parent) = destination.parent() {
-            tokio::fs::create_dir_all(parent).await?;
+    /// Start a paused download again, from the provider it was last on; its
+    /// part files are resumed. None if it isn't paused.
+    pub async fn resume(&self, info_hash: &str) -> Option<tokio::task::AbortHandle> {
+        let source = self.torrents.read().await.get(info_hash).map(|torrent| torrent.source.clone());
+        let mut downloads = self.downloads.write().await;
+        let registered = downloads.get_mut(info_hash).filter(|registered| registered.task.is_none())?;
+        // A failover moved it: carry on where it went
+        if let Some((provider, debrid_torrent_id)) =
+            source.as_ref().and_then(|source| source.get_provider().zip(source.get_debrid_torrent_id()))
+        {
+            registered.params.provider = provider;
+            registered.params.debrid_torrent_id = debrid_torrent_id.clone();
         }
+        // Before the task runs, so a quick finish isn't overwritten
+        self.set_state(info_hash, TorrentState::Downloading).await;
+        let task = self.spawn(registered.params.clone());
+        let running = task.handle.abort_handle();
+        registered.task = Some(task);
+        Some(running)
+    }
 
-        // Download the file
-        let response = self.client.get(url).send().await?;
-        
-        if !response.status().is_success() {
-            return Err(crate::error::Error::NetworkError(format!(
-                "Failed to download file: HTTP {}",
-                response.status()
-            )));
+    /// Stop a download and forget it; returns its save path if there was one
+    pub async fn cancel(&self, info_hash: &str) -> Option<PathBuf> {
+        let registered = self.downloads.write().await.remove(info_hash)?;
+        if let Some(task) = registered.task {
+            task.stop().await;
         }
-
-        let bytes = response.bytes().await?;
-        
-        // Write to file
-        let mut file = File::create(&destination).await?;
-        file.write_all(&bytes).await?;
-        file.flush().await?;
-        
-        tracing::info!("Downloaded file to {:?} ({} bytes)", destination, bytes.len());
-        Ok(())
+        Some(registered.params.save_path)
     }
 
-    /// Download a file with progress reporting
-    pub async fn download_file_with_progress<F>(
-        &self,
-        url: &str,
-        destination: PathBuf,
-        mut progress_callback: F,
-    ) -> Result<()>
-    where
-        F: FnMut(u64, u64) + Send,
-    {
-        tracing::info!("Downloading file from {} to {:?}", url, destination);
-        
-        // Create parent directories if they don't exist
-        if let Some(parent) = destination.parent() {
-            tokio::fs::create_dir_all(parent).await?;
+    /// Cancel every download, without waiting for them (app exit)
+    pub async fn abort_all(&self) {
+        for (info_hash, registered) in self.downloads.write().await.drain() {
+            if let Some(task) = registered.task {
+                tracing::info!("Aborting cloud download: {}", info_hash);
+                task.cancel.cancel();
+                task.handle.abort();
+            }
         }
+    }
 
-        // Download the file with streaming
-        let response = self.client.get(url).send().await?;
-        
-        if !response.status().is_success() {
-            return Err(crate::error::Error::NetworkError(format!(
-                "Failed to download file: HTTP {}",
-                response.status()
-            )));
-        }
+    /// Info hashes of every download, running or not
+    pub async fn info_hashes(&self) -> Vec<String> {
+        self.downloads.read().await.keys().cloned().collect()
+    }
+
+    pub async fn contains(&self, info_hash: &str) -> bool {
+        self.downloads.read().await.contains_key(info_hash)
+    }
 
-        let total_size = response.content_length().unwrap_or(0);
-        let mut file = File::create(&destination).await?;
-        let mut downloaded: u64 = 0;
-        let mut stream = response.bytes_stream();
+    /// Whether the download's task is still going
+    pub async fn is_running(&self, info_hash: &str) -> bool {
+        self.downloads
+            .read()
+            .await
+            .get(info_hash)
+            .and_then(|registered| registered.task.as_ref())
+            .is_some_and(|task| !task.handle.is_finished())
+    }
 
-        use futures::StreamExt;
-        
-        while let Some(chunk) = stream.next().await {
-            let chunk = chunk?;
-            file.write_all(&chunk).await?;
-            downloaded += chunk.len() as u64;
-            progress_callback(downloaded, total_size);
+    /// Downloads whose task is still going
+    pub async fn running(&self) -> usize {
+        self.downloads
+            .read()
+            .await
+            .values()
+            .filter_map(|registered| registered.task.as_ref())
+            .filter(|task| !task.handle.is_finished())
+            .count()
+    }
+
+    /// Register a stand-in task, for tests of what drives the manager
+    #[cfg(test)]
+    pub(crate) async fn insert_task(&self, params: CloudDownloadParams, task: CloudTask) {
+        self.downloads.write().await.insert(params.info_hash.clone(), Registered { params, task: Some(task) });
+    }
+
+    async fn set_state(&self, info_hash: &str, state: TorrentState) {
+        if let Some(torrent) = self.torrents.write().await.get_mut(info_hash) {
+            torrent.state = state;
         }
-        
-        file.flush().await?;
-        
-        tracing::info!("Downloaded file to {:?} ({} bytes)", destination, downloaded);
-        Ok(())
-    }
-
-    /// Start a background task to poll debrid service and download files
-    /// 
-    /// This task will:
-    /// 1. Poll the debrid service every POLL_INTERVAL seconds
-    /// 2. Get download links when the torrent is ready
-    /// 3. Download all files to the specified directory
-    /// 4. Update torrent state in AppState
-    pub async fn start_download_task(
-        info_hash: String,
-        debrid_torrent_id: String,
-        provider: DebridProviderType,
-        save_path: PathBuf,
-        torrents: Arc<RwLock<std::collections::HashMap<String, crate::state::TorrentInfo>>>,
-        debrid_manager: Arc<RwLock<DebridManager>>,
-        file_progress: Arc<RwLock<std::collections::HashMap<String, std::collections::HashMap<String, crate::state::CloudFileProgress>>>>,
-        cancel_token: CancellationToken,
-    ) {
-        let info_hash_clone = info_hash.clone();
-        let debrid_torrent_id_clone = debrid_torrent_id.clone();
-        
-        tokio::spawn(async move {
+    }
+
+    /// Run the download of `params` (see `start_download`)
+    fn spawn(&self, params: CloudDownloadParams) -> CloudTask {
+        let CloudDownloadParams {
+            info_hash,
+            debrid_torrent_id,
+            provider,
+            save_path,
+            delete_after_download,
+            file_collision,
+            ask_file_selection,
+            failover,
+            events,
+        } = params;
+        let cancel_token = CancellationToken::new();
+        let task = CloudDownload {
+            info_hash,
+            save_path,
+            torrents: self.torrents.clone(),
+            debrid_manager: self.debrid_manager.clone(),
+            client: self.client.clone(),
+            file_progress: self.file_progress.clone(),
+            cancel_token: cancel_token.clone(),
+            file_collision,
+            file_selections: ask_file_selection.then(|| self.file_selections.clone()),
+            events,
+        };
+
+        let handle = tokio::spawn(async move {
             tracing::info!(
                 "Starting cloud download task for {} (debrid_id: {})",
-                info_hash_clone,
-                debrid_torrent_id_clone
+                task.info_hash,
+                debrid_torrent_id
             );
 
-            // Poll until torrent is ready to download
-            let files = loop {
-                // Check cancellation before each poll
-                if cancel_token.is_cancelled() {
-                    tracing::info!("Cloud download task cancelled for {}", info_hash_clone);
-                    return;
-                }
+            let mut provider = provider;
+            let mut debrid_torrent_id = debrid_torrent_id;
+            let mut tried = vec![provider];
+            // Files partly written to disk, which a new provider has to serve too
+            let mut written: Option<Vec<DebridFile>> = None;
+            // Switch waiting for the new provider to prove it serves `written`
+            let mut pending: Option<ProviderSwitch> = None;
+            let mut completed = std::collections::HashSet::new();
 
-                tracing::debug!("Polling debrid service for torrent {}", debrid_torrent_id_clone);
-                
-                let manager = debrid_manager.read().await;
-                
-                // First, check torrent status/progress
-                match manager.get_progress(provider, &debrid_torrent_id_clone).await {
-                    Ok(progress) => {
-                        tracing::debug!(
-                            "Torrent {} status: {:?}, progress: {:.1}%",
-                            debrid_torrent_id_clone,
-                            progress.status,
-                            progress.progress
-                        );
-                        
-                        // Update torrent progress in UI
-                        {
-                            let mut torrent_map = torrents.write().await;
-                            if let Some(torrent) = torrent_map.get_mut(&info_hash_clone) {
-                                torrent.size = progress.total_size;
-                            }
+            let files = loop {
+                let failure = match task.poll_until_ready(provider, debrid_torrent_id.as_str()).await {
+                    Polled::Cancelled => return,
+                    Polled::Failed(reason) => reason,
+                    Polled::Ready(files) if written.as_ref().is_some_and(|old| !same_files(old, &files)) => {
+                        task.abandon(provider, debrid_torrent_id.as_str()).await;
+                        format!("{} serves different files", provider.display_name())
+                    }
+                    Polled::Ready(files) => {
+                        if let Some(switch) = pending.take() {
+                            task.switch_source(switch).await;
                         }
-                        
-                        // Check if we need to select files
-                        use crate::debrid::types::DebridStatus;
-                        if matches!(progress.status, DebridStatus::WaitingFilesSelection) {
-                            tracing::info!("Torrent waiting for file selection, selecting all files");
-                            
-                            if let Err(e) = manager.select_files(provider, &debrid_torrent_id_clone, &[]).await {
-                                tracing::error!("Failed to select files: {}", e);
-                                
-                                // Update torrent state to error
-                                let mut torrent_map = torrents.write().await;
-                                if let Some(torrent) = torrent_map.get_mut(&info_hash_clone) {
-                                    torrent.state = TorrentState::Error;
-                                }
-                                return;
-                            }
-                            
-                            tracing::info!("Successfully selected all files, waiting for download to complete");
+                        let first_attempt = written.is_none();
+                        if !task.prepare(&files, first_attempt).await {
+                            return;
                         }
-                        
-                        // If downloaded (or downloading with high progress), try to get download links
-                        if matches!(progress.status, DebridStatus::Downloaded) 
-                            || (matches!(progress.status, DebridStatus::Downloading) && progress.progress > 95.0) {
-                            
-                            tracing::info!("Torrent is ready, getting download links");
-                            
-                            match manager.get_download_links(provider, &debrid_torrent_id_clone).await {
-                                Ok(files) if !files.is_empty() => {
-                                    tracing::info!("Got {} download links for torrent {}", files.len(), debrid_torrent_id_clone);
-                                    break files;
-                                }
-                                Ok(_) => {
-                                    tracing::debug!("No download links yet, waiting...");
-                                }
-                                Err(e) => {
-                                    tracing::error!("Error getting download links: {}", e);
+                        match task.download_files(provider, debrid_torrent_id.as_str(), &files, &mut completed).await {
+                            Downloaded::Cancelled => return,
+                            Downloaded::Complete => break files,
+                            Downloaded::Incomplete => {
+                                match task.provider_failure(provider, debrid_torrent_id.as_str()).await {
+                                    Some(reason) if failover.next(&tried).is_some() => {
+                                        written = Some(files);
+                                        reason
+                                    }
+                                    // Files that failed on their own stay failed
+                                    _ => break files,
                                 }
                             }
-                        } else {
-                            tracing::debug!(
-                                "Torrent not ready yet (status: {:?}, progress: {:.1}%), waiting...",
-                                progress.status,
-                                progress.progress
-                            );
                         }
                     }
-                    Err(e) => {
-                        tracing::error!("Error getting torrent progress: {}", e);
-                        
-                        // Update torrent state to error
-                        let mut torrent_map = torrents.write().await;
-                        if let Some(torrent) = torrent_map.get_mut(&info_hash_clone) {
-                            torrent.state = TorrentState::Error;
-                        }
-                        return;
-                    }
-                }
-                
-                // Wait before polling again, but check for cancellation
-                tokio::select! {
-                    _ = sleep(Duration::from_secs(POLL_INTERVAL)) => {}
-                    _ = cancel_token.cancelled() => {
-                        tracing::info!("Cloud download task cancelled during polling for {}", info_hash_clone);
-                        return;
-                    }
+                };
+
+                tracing::warn!("{} failed for {}: {}", provider.display_name(), task.info_hash, failure);
+                let Some((next, next_id)) = task.add_to_next(&failover, &mut tried).await else {
+                    tracing::error!("No debrid provider left to download {} from", task.info_hash);
+                    task.set_state(TorrentState::Error).await;
+                    return;
+                };
+                let switch = ProviderSwitch {
+                    torrent_id: task.info_hash.clone(),
+                    from: pending.take().map_or(provider, |earlier| earlier.from),
+                    to: next,
+                    debrid_torrent_id: next_id.clone(),
+                    reason: failure,
+                    resumed: written.is_some(),
+                };
+                // Moving a download that already wrote bytes waits for the file check
+                if written.is_some() {
+                    pending = Some(switch);
+                } else {
+                    task.switch_source(switch).await;
                 }
+                provider = next;
+                debrid_torrent_id = next_id;
             };
 
-            // Calculate total size
+            let info_hash = &task.info_hash;
             let total_size: u64 = files.iter().map(|f| f.size).sum();
-            
-            // Initialize file progress for all files
+
+            // Mark torrent as complete
             {
-                let mut progress_map = file_progress.write().await;
-                let mut file_map = std::collections::HashMap::new();
-                for file in &files {
-                    file_map.insert(file.name.clone(), crate::state::CloudFileProgress {
-                        name: file.name.clone(),
-                        size: file.size,
-                        downloaded: 0,
-                        speed: 0,
-                        state: crate::state::CloudFileState::Queued,
-                    });
+                let mut torrent_map = task.torrents.write().await;
+                if let Some(torrent) = cloud_entry(&mut torrent_map, info_hash) {
+                    torrent.state = TorrentState::Seeding;
+                    torrent.downloaded = total_size;
                 }
-                progress_map.insert(info_hash_clone.clone(), file_map);
             }
-            
-            // Update torrent info with total size
+
+            task.file_progress.finish(info_hash).await;
+            tracing::info!("Cloud download task completed for {}", info_hash);
+
+            if !delete_after_download {
+                return;
+            }
+
+            let file_states = task.file_progress.states(info_hash).await;
+
+            match remove_from_provider_after_download(
+                &task.debrid_manager,
+                provider,
+                debrid_torrent_id.as_str(),
+                &file_states,
+                task.cancel_token.is_cancelled(),
+                DELETE_RETRY_BASE_DELAY,
+            )
+            .await
             {
-                let mut torrent_map = torrents.write().await;
-                if let Some(torrent) = torrent_map.get_mut(&info_hash_clone) {
-                    torrent.size = total_size;
-                    if let Some(first_file) = files.first() {
-                        torrent.name = first_file.name.clone();
+                Ok(true) => {
+                    let mut torrent_map = task.torrents.write().await;
+                    if let Some(torrent) = torrent_map.get_mut(info_hash) {
+                        torrent.remote_deleted = true;
+                    }
+                }
+                Ok(false) => {
+                    tracing::info!(
+                        "Keeping {} on {:?}: not every file downloaded successfully",
+                        debrid_torrent_id,
+                        provider
+                    );
+                }
+                Err(e) => {
+                    // Non-fatal: the local copy is complete, only the remote cleanup failed
+                    tracing::warn!("Failed to delete {} from {:?}: {}", debrid_torrent_id, provider, e);
+                    if let Some(events) = &task.events {
+                        let message = format!(
+                            "Downloaded {} but could not remove it from {}: {}",
+                            info_hash,
+                            provider.display_name(),
+                            e
+                        );
+                        events.send(Event::DebridWarning(message));
                     }
                 }
             }
+        });
+        CloudTask { handle, cancel: cancel_token }
+    }
+}
 
-            // Download each file
-            let client = reqwest::Client::builder()
-                .timeout(std::time::Duration::from_secs(300))  // 5 min for large file downloads
-                .build()
-                .expect("Failed to create HTTP client");
-            let mut total_downloaded: u64 = 0;
-
-            for file in files {
-                // Mark file as downloading
-                {
-                    let mut progress_map = file_progress.write().await;
-                    if let Some(file_map) = progress_map.get_mut(&info_hash_clone) {
-                        if let Some(progress) = file_map.get_mut(&file.name) {
-                            progress.state = crate::state::CloudFileState::Downloading;
-                        }
+/// Providers a cloud download may move to, recorded when it is added.
+///
+/// A provider that fails for good (it refuses requests about the torrent,
+/// reports it dead, or keeps erroring for `MAX_POLL_ERRORS` polls) is given
+/// up for the next candidate not tried yet. Before any bytes were written
+/// the download moves right away; afterwards only once the new provider
+/// serves the same files (name and size), whose part files are then resumed.
+#[derive(Debug, Clone)]
+pub struct FailoverPlan {
+    /// In preference order, starting with the provider added to first
+    pub candidates: Vec<DebridProviderType>,
+    /// What is added to the next candidate
+    pub magnet: String,
+    /// The torrent's "no failover" override
+    pub disabled: bool,
+}
+
+impl FailoverPlan {
+    /// A plan that keeps the download on the provider it was added to
+    pub fn none() -> Self {
+        Self { candidates: Vec::new(), magnet: String::new(), disabled: true }
+    }
+
+    /// First candidate not in `tried`
+    fn next(&self, tried: &[DebridProviderType]) -> Option<DebridProviderType> {
+        if self.disabled {
+            return None;
+        }
+        self.candidates.iter().copied().find(|candidate| !tried.contains(candidate))
+    }
+}
+
+/// Payload of the `cloud-provider-switched` event
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProviderSwitch {
+    /// Info hash of the cloud torrent in the torrent list
+    pub torrent_id: String,
+    pub from: DebridProviderType,
+    pub to: DebridProviderType,
+    /// Id of the torrent on `to`
+    pub debrid_torrent_id: DebridTorrentId,
+    /// Why `from` was given up
+    pub reason: String,
+    /// Whether partly downloaded files continue from where they stopped
+    pub resumed: bool,
+}
+
+/// Whether two providers serve the same files: same names with same sizes
+fn same_files(old: &[DebridFile], new: &[DebridFile]) -> bool {
+    let listing = |files: &[DebridFile]| {
+        let mut listing: Vec<(String, u64)> = files.iter().map(|file| (file.name.clone(), file.size)).collect();
+        listing.sort();
+        listing
+    };
+    listing(old) == listing(new)
+}
+
+/// What polling a provider came to
+enum Polled {
+    /// Links for every file
+    Ready(Vec<DebridFile>),
+    /// The provider failed for good; why
+    Failed(String),
+    Cancelled,
+}
+
+/// How a round of file downloads ended
+enum Downloaded {
+    Complete,
+    /// At least one file failed
+    Incomplete,
+    Cancelled,
+}
+
+/// What the steps of one cloud download task share
+struct CloudDownload {
+    info_hash: String,
+    save_path: PathBuf,
+    torrents: Arc<RwLock<std::collections::HashMap<String, crate::state::TorrentInfo>>>,
+    debrid_manager: Arc<RwLock<DebridManager>>,
+    client: reqwest::Client,
+    file_progress: CloudProgress,
+    cancel_token: CancellationToken,
+    file_collision: FileCollisionPolicy,
+    file_selections: Option<FileSelectionWaiters>,
+    events: Option<EventSink>,
+}
+
+impl CloudDownload {
+    async fn set_state(&self, state: TorrentState) {
+        let mut torrent_map = self.torrents.write().await;
+        if let Some(torrent) = cloud_entry(&mut torrent_map, &self.info_hash) {
+            torrent.state = state;
+        }
+    }
+
+    /// Poll `provider` until the torrent is ready to download
+    async fn poll_until_ready(&self, provider: DebridProviderType, debrid_torrent_id: &str) -> Polled {
+        let info_hash = &self.info_hash;
+        let mut link_errors = crate::utils::RepeatedError::default();
+        let mut unknown_statuses = crate::utils::RepeatedError::default();
+        let mut poll_errors = 0;
+        loop {
+            // Check cancellation before each poll
+            if self.cancel_token.is_cancelled() {
+                tracing::info!("Cloud download task cancelled for {}", info_hash);
+                return Polled::Cancelled;
+            }
+
+            tracing::debug!("Polling debrid service for torrent {}", debrid_torrent_id);
+
+            let mut manager = self.debrid_manager.read().await;
+
+            // First, check torrent status/progress
+            match manager.get_progress(provider, debrid_torrent_id).await {
+                Ok(progress) => {
+                    poll_errors = 0;
+                    tracing::debug!(
+                        "Torrent {} status: {:?}, progress: {:.1}%",
+                        debrid_torrent_id,
+                        progress.status,
+                        progress.progress
+                    );
+
+                    if progress.status.is_error() {
+                        return Polled::Failed(format!(
+                            "{} reports the torrent as {:?}",
+                            provider.display_name(),
+                            progress.status
+                        ));
                     }
-                }
 
-                // Use the file name directly for the destination path
-                let file_path = save_path.join(&file.name);
-                tracing::info!("Downloading file: {} -> {:?}", file.name, file_path);
-
-                // Get download URL (prefer download_link, fallback to stream_link)
-                let download_url = match file.download_link.as_ref().or(file.stream_link.as_ref()) {
-                    Some(url) => url,
-                    None => {
-                        tracing::error!("No download URL for file: {}", file.name);
-                        
-                        // Mark file as error
-                        let mut progress_map = file_progress.write().await;
-                        if let Some(file_map) = progress_map.get_mut(&info_hash_clone) {
-                            if let Some(progress) = file_map.get_mut(&file.name) {
-                                progress.state = crate::state::CloudFileState::Error;
-                            }
+                    // Update torrent progress in UI
+                    {
+                        let mut torrent_map = self.torrents.write().await;
+                        if let Some(torrent) = cloud_entry(&mut torrent_map, info_hash) {
+                            torrent.size = progress.total_size;
                         }
-                        continue;
                     }
-                };
 
-                // Create parent directories
-                if let Some(parent) = file_path.parent() {
-                    if let Err(e) = tokio::fs::create_dir_all(parent).await {
-                        tracing::error!("Failed to create directory {:?}: {}", parent, e);
-                        
-                        // Mark file as error
-                        let mut progress_map = file_progress.write().await;
-                        if let Some(file_map) = progress_map.get_mut(&info_hash_clone) {
-                            if let Some(progress) = file_map.get_mut(&file.name) {
-                                progress.state = crate::state::CloudFileState::Error;
-                            }
+                    // Check if we need to select files
+                    use crate::debrid::types::DebridStatus;
+                    if let DebridStatus::Unknown(status) = &progress.status {
+                        // Not known to be an error or ready; keep polling
+                        if unknown_statuses.is_new(status) {
+                            tracing::warn!(
+                                "{} reports unknown status {:?} for {}, still waiting",
+                                provider.display_name(),
+                                status,
+                                debrid_torrent_id
+                            );
                         }
-                        continue;
                     }
-                }
+                    if matches!(progress.status, DebridStatus::WaitingFilesSelection) {
+                        let selected = match &self.file_selections {
+                            Some(waiters) => {
+                                // Waiting on the user can take minutes; don't hold the manager meanwhile
+                                drop(manager);
+                                let selected = await_file_selection(
+                                    &self.debrid_manager,
+                                    provider,
+                                    debrid_torrent_id,
+                                    info_hash,
+                                    waiters,
+                                    FILE_SELECTION_TIMEOUT,
+                                    &self.cancel_token,
+                                    self.events.as_ref(),
+                                )
+                                .await;
+                                manager = self.debrid_manager.read().await;
+                                selected
+                            }
+                            None => {
+                                tracing::info!("Torrent waiting for file selection, selecting all files");
+                                manager
+                                    .select_files(provider, debrid_torrent_id, &[])
+                                    .await
+                                    .map(|_| true)
+                                    .map_err(Into::into)
+                            }
+                        };
 
-                // Download file with progress updates
-                match download_file_with_state_update(
-                    &client,
-                    download_url,
-                    &file_path,
-                    &info_hash_clone,
-                    &file.name,
-                    file.size,
-                    &torrents,
-                    &file_progress,
-                    &mut total_downloaded,
-                ).await {
-                    Ok(_) => {
-                        tracing::info!("Successfully downloaded: {}", file.name);
-                        
-                        // Mark file as complete
-                        let mut progress_map = file_progress.write().await;
-                        if let Some(file_map) = progress_map.get_mut(&info_hash_clone) {
-                            if let Some(progress) = file_map.get_mut(&file.name) {
-                                progress.state = crate::state::CloudFileState::Complete;
-                                progress.downloaded = file.size;
+                        match selected {
+                            Ok(true) => {
+                                tracing::info!("Files selected, waiting for download to complete");
+                            }
+                            Ok(false) => {
+                                tracing::info!("Cloud download task cancelled during file selection for {}", info_hash);
+                                return Polled::Cancelled;
+                            }
+                            Err(e) => {
+                                tracing::error!("Failed to select files: {}", e);
+                                return Polled::Failed(format!("Failed to select files: {}", e));
                             }
                         }
                     }
-                    Err(e) => {
-                        tracing::error!("Failed to download {}: {}", file.name, e);
-                        
-                        // Mark file as error
-                        let mut progress_map = file_progress.write().await;
-                        if let Some(file_map) = progress_map.get_mut(&info_hash_clone) {
-                            if let Some(progress) = file_map.get_mut(&file.name) {
-                                progress.state = crate::state::CloudFileState::Error;
+
+                    // If downloaded (or downloading with high progress), try to get download links
+                    if matches!(progress.status, DebridStatus::Downloaded)
+                        || (matches!(progress.status, DebridStatus::Downloading) && progress.progress > 95.0) {
+
+                        tracing::info!("Torrent is ready, getting download links");
+
+                        match manager.get_download_links(provider, debrid_torrent_id).await {
+                            Ok(files) if !files.is_empty() => {
+                                tracing::info!("Got {} download links for torrent {}", files.len(), debrid_torrent_id);
+                                return Polled::Ready(files);
+                            }
+                            Ok(_) => {
+                                link_errors.clear();
+                                tracing::debug!("No download links yet, waiting...");
+                            }
+                            Err(e) if crate::debrid::error::is_permanent(&e) => {
+                                return Polled::Failed(e.to_string());
+                            }
+                            Err(e) => {
+                                // Polled every few seconds; only say it again when it changes
+                                let error = e.to_string();
+                                if link_errors.is_new(&error) {
+                                    tracing::error!("Error getting download links: {}", error);
+                                } else {
+                                    tracing::debug!("Error getting download links: {}", error);
+                                }
                             }
                         }
+                    } else {
+                        tracing::debug!(
+                            "Torrent not ready yet (status: {:?}, progress: {:.1}%), waiting...",
+                            progress.status,
+                            progress.progress
+                        );
+                    }
+                }
+                Err(e) if crate::debrid::error::is_permanent(&e) => {
+                    tracing::error!("Error getting torrent progress: {}", e);
+                    return Polled::Failed(e.to_string());
+                }
+                Err(e) => {
+                    // The client already retried; give it a few more polls
+                    poll_errors += 1;
+                    tracing::error!("Error getting torrent progress ({}/{}): {}", poll_errors, MAX_POLL_ERRORS, e);
+                    if poll_errors >= MAX_POLL_ERRORS {
+                        return Polled::Failed(format!("{} (failed {} polls in a row)", e, poll_errors));
                     }
                 }
             }
+            drop(manager);
 
-            // Mark torrent as complete
-            {
-                let mut torrent_map = torrents.write().await;
-                if let Some(torrent) = torrent_map.get_mut(&info_hash_clone) {
-                    torrent.state = TorrentState::Seeding;
-                    torrent.downloaded = total_size;
+            // Wait before polling again, but check for cancellation
+            tokio::select! {
+                _ = sleep(Duration::from_secs(POLL_INTERVAL)) => {}
+                _ = self.cancel_token.cancelled() => {
+                    tracing::info!("Cloud download task cancelled during polling for {}", info_hash);
+                    return Polled::Cancelled;
                 }
             }
-
-            tracing::info!("Cloud download task completed for {}", info_hash_clone);
-        });
+        }
     }
-}
 
-/// Helper function to download a file with state updates
-async fn download_file_with_state_update(
-    client: &reqwest::Client,
-    url: &str,
-    destination: &PathBuf,
-    info_hash: &str,
-    file_name: &str,
-    _file_size: u64,
-    torrents: &Arc<RwLock<std::collections::HashMap<String, crate::state::TorrentInfo>>>,
-    file_progress: &Arc<RwLock<std::collections::HashMap<String, std::collections::HashMap<String, crate::state::CloudFileProgress>>>>,
-    total_downloaded: &mut u64,
-) -> Result<()> {
-    let response = client.get(url).send().await?;
-    
-    if !response.status().is_success() {
-        return Err(crate::error::Error::NetworkError(format!(
-            "Failed to download file: HTTP {}",
-            response.status()
-        )));
+    /// Check the download folder and set up the torrent for `files`; the
+    /// first attempt also starts their progress. False if the folder is gone.
+    async fn prepare(&self, files: &[DebridFile], first_attempt: bool) -> bool {
+        let info_hash = &self.info_hash;
+
+        // The drive may have been unmounted while the provider was downloading
+        if let Err(reason) = crate::disk::verify_download_dir(&self.save_path, None) {
+            tracing::warn!("Not downloading {}: {}", info_hash, reason);
+            self.set_state(TorrentState::MissingFiles).await;
+            if let Some(events) = &self.events {
+                let event = crate::events::MissingFilesEvent {
+                    torrent_id: info_hash.clone(),
+                    download_dir: self.save_path.to_string_lossy().to_string(),
+                    reason,
+                };
+                events.send(Event::MissingFiles(event));
+            }
+            return false;
+        }
+
+        if !first_attempt {
+            return true;
+        }
+
+        // Initialize file progress for all files
+        self.file_progress
+            .start(info_hash, files.iter().map(|file| (file.name.clone(), file.size)))
+            .await;
+
+        // Update torrent info with total size
+        let mut torrent_map = self.torrents.write().await;
+        if let Some(torrent) = cloud_entry(&mut torrent_map, info_hash) {
+            torrent.size = files.iter().map(|f| f.size).sum();
+            if let Some(first_file) = files.first() {
+                torrent.name = first_file.name.clone();
+            }
+        }
+        true
     }
 
-    let mut file = File::create(destination).await?;
-    let mut downloaded: u64 = 0;
-    let mut stream = response.bytes_stream();
-    let mut last_update = std::time::Instant::now();
-    let mut last_downloaded = 0u64;
+    /// Download the files not in `completed` yet, adding the ones that finish
+    async fn download_files(
+        &self,
+        provider: DebridProviderType,
+        debrid_torrent_id: &str,
+        files: &[DebridFile],
+        completed: &mut std::collections::HashSet<String>,
+    ) -> Downloaded {
+        let info_hash = &self.info_hash;
+        let file_progress = &self.file_progress;
+        let refresher = LinkRefresher::new(self.debrid_manager.clone(), provider, debrid_torrent_id.to_string());
 
-    use futures::StreamExt;
-    
-    while let Some(chunk) = stream.next().await {
-        let chunk = chunk?;
-        file.write_all(&chunk).await?;
-        downloaded += chunk.len() as u64;
-        *total_downloaded += chunk.len() as u64;
-        
-        // Update state every 100KB or 1 second
-        let now = std::time::Instant::now();
-        let elapsed = now.duration_since(last_update).as_secs_f64();
-        
-        if downloaded % (100 * 1024) == 0 || elapsed >= 1.0 {
-            // Calculate speed
-            let speed = if elapsed > 0.0 {
-                ((downloaded - last_downloaded) as f64 / elapsed) as u64
-            } else {
-                0
+        let mut total_downloaded: u64 = files
+            .iter()
+            .filter(|file| completed.contains(&file.name))
+            .map(|file| file.size)
+            .sum();
+        let mut outcome = Downloaded::Complete;
+
+        for file in files {
+            if completed.contains(&file.name) {
+                continue;
+            }
+            if self.cancel_token.is_cancelled() {
+                tracing::info!("Cloud download task cancelled for {}", info_hash);
+                return Downloaded::Cancelled;
+            }
+
+            // Mark file as downloading
+            file_progress
+                .set_state(info_hash, &file.name, crate::state::CloudFileState::Downloading)
+                .await;
+
+            // Use the file name directly for the destination path
+            let file_path = self.save_path.join(&file.name);
+            tracing::info!("Downloading file: {} -> {:?}", file.name, file_path);
+
+            // Get download URL (prefer download_link, fallback to stream_link)
+            let download_url = match file.download_link.as_ref().or(file.stream_link.as_ref()) {
+                Some(url) => url,
+                None => {
+                    tracing::error!("No download URL for file: {}", file.name);
+
+                    // Mark file as error
+                    file_progress
+                        .set_state(info_hash, &file.name, crate::state::CloudFileState::Error)
+                        .await;
+                    outcome = Downloaded::Incomplete;
+                    continue;
+                }
             };
-            
-            // Update file progress
-            {
-                let mut progress_map = file_progress.write().await;
-                if let Some(file_map) = progress_map.get_mut(info_hash) {
-                    if let Some(progress) = file_map.get_mut(file_name) {
-                        progress.downloaded = downloaded;
-                        progress.speed = speed;
-                    }
+
+            // Create parent directories
+            if let Some(parent) = file_path.parent() {
+                if let Err(e) = tokio::fs::create_dir_all(parent).await {
+                    tracing::error!("Failed to create directory {:?}: {}", parent, e);
+
+                    // Mark file as error
+                    file_progress
+                        .set_state(info_hash, &file.name, crate::state::CloudFileState::Error)
+                        .await;
+                    outcome = Downloaded::Incomplete;
+                    continue;
                 }
             }
-            
-            // Update torrent progress
-            {
-                let mut torrent_map = torrents.write().await;
-                if let Some(torrent) = torrent_map.get_mut(info_hash) {
-                    torrent.downloaded = *total_downloaded;
-                    torrent.download_speed = speed;
+
+            // Download file with progress updates
+            match download_with_link_refresh(
+                &self.client,
+                &refresher,
+                file,
+                download_url,
+                &file_path,
+                info_hash,
+                self.file_collision,
+                &self.cancel_token,
+                &self.torrents,
+                file_progress,
+                &mut total_downloaded,
+            ).await {
+                Ok(final_path) => {
+                    tracing::info!("Successfully downloaded: {} -> {:?}", file.name, final_path);
+
+                    // Mark file as complete
+                    file_progress
+                        .update(info_hash, &file.name, |progress| {
+                            progress.state = crate::state::CloudFileState::Complete;
+                            progress.downloaded = file.size;
+                        })
+                        .await;
+                    completed.insert(file.name.clone());
+                }
+                Err(_) if self.cancel_token.is_cancelled() => {
+                    tracing::info!("Cloud download task cancelled for {}", info_hash);
+                    return Downloaded::Cancelled;
+                }
+                Err(e) => {
+                    tracing::error!("Failed to download {}: {}", file.name, e);
+
+                    // Mark file as error
+                    file_progress
+                        .set_state(info_hash, &file.name, crate::state::CloudFileState::Error)
+                        .await;
+                    outcome = Downloaded::Incomplete;
                 }
             }
-            
-            last_update = now;
-            last_downloaded = downloaded;
         }
+        outcome
     }
+
+    /// Why `provider` can't serve the torrent any more, if it can't: it
+    /// refuses requests about it or reports it failed
+    async fn provider_failure(&self, provider: DebridProviderType, debrid_torrent_id: &str) -> Option<String> {
+        match self.debrid_manager.read().await.get_progress(provider, debrid_torrent_id).await {
+            Err(e) if crate::debrid::error::is_permanent(&e) => Some(e.to_string()),
+            Ok(progress) if progress.status.is_error() => Some(format!(
+                "{} reports the torrent as {:?}",
+                provider.display_name(),
+                progress.status
+            )),
+            _ => None,
+        }
+    }
+
+    /// Add the torrent to the first candidate of `plan` that takes it
+    async fn add_to_next(
+        &self,
+        plan: &FailoverPlan,
+        tried: &mut Vec<DebridProviderType>,
+    ) -> Option<(DebridProviderType, DebridTorrentId)> {
+        while let Some(next) = plan.next(tried) {
+            tried.push(next);
+            let request = crate::debrid::AddTorrentRequest::Magnet(plan.magnet.clone());
+            let added = self.debrid_manager.read().await.add_to_cloud(next, request).await;
+            match added.map_err(crate::error::Error::from).and_then(|id| DebridTorrentId::parse(&id.id)) {
+                Ok(id) => {
+                    tracing::info!("Added {} to {} as {}", self.info_hash, next.display_name(), id);
+                    return Some((next, id));
+                }
+                Err(e) => tracing::warn!("Could not add {} to {}: {}", self.info_hash, next.display_name(), e),
+            }
+        }
+        None
+    }
+
+    /// Remove a torrent added to a provider that turned out not to serve the
+    /// same files; a failure only leaves it behind on the provider
+    async fn abandon(&self, provider: DebridProviderType, debrid_torrent_id: &str) {
+        if let Err(e) = self.debrid_manager.read().await.delete_torrent(provider, debrid_torrent_id).await {
+            tracing::warn!("Failed to remove {} from {}: {}", debrid_torrent_id, provider.display_name(), e);
+        }
+    }
+
+    /// Point the torrent at the provider it moved to and tell the UI
+    async fn switch_source(&self, switch: ProviderSwitch) {
+        tracing::info!(
+            "Moving cloud download {} from {} to {}: {}",
+            self.info_hash,
+            switch.from.display_name(),
+            switch.to.display_name(),
+            switch.reason
+        );
+        {
+            let mut torrent_map = self.torrents.write().await;
+            if let Some(torrent) = torrent_map.get_mut(&self.info_hash) {
+                use crate::debrid::types::DownloadSource;
+                match &mut torrent.source {
+                    DownloadSource::Hybrid { debrid_provider, debrid_torrent_id, .. } => {
+                        *debrid_provider = switch.to;
+                        *debrid_torrent_id = switch.debrid_torrent_id.clone();
+                    }
+                    source => {
+                        *source = DownloadSource::Debrid {
+                            provider: switch.to,
+                            torrent_id: switch.debrid_torrent_id.clone(),
+                        };
+                    }
+                }
+            }
+        }
+        if let Some(events) = &self.events {
+            events.send(Event::CloudProviderSwitched(switch));
+        }
+    }
+}
+
+/// Offer a torrent's files to the UI and wait for `select_debrid_files`.
+///
+/// Everything is selected instead when the listing fails or nobody answers
+/// within `timeout`. Returns `Ok(false)` if the task was cancelled meanwhile.
+#[allow(clippy::too_many_arguments)]
+async fn await_file_selection(
+    debrid_manager: &Arc<RwLock<DebridManager>>,
+    provider: DebridProviderType,
+    debrid_torrent_id: &str,
+    info_hash: &str,
+    waiters: &FileSelectionWaiters,
+    timeout: Duration,
+    cancel_token: &CancellationToken,
+    events: Option<&EventSink>,
+) -> Result<bool> {
+    let listing = debrid_manager
+        .read()
+        .await
+        .get_torrent_files(provider, debrid_torrent_id)
+        .await;
+
+    match listing {
+        Ok(files) if !files.is_empty() => {
+            let (waiter, answer) = oneshot::channel();
+            waiters.write().await.insert(debrid_torrent_id.to_string(), waiter);
+
+            if let Some(events) = events {
+                let request = FileSelectionRequest {
+                    torrent_id: info_hash.to_string(),
+                    provider,
+                    debrid_torrent_id: debrid_torrent_id.to_string(),
+                    files,
+                    timeout_secs: timeout.as_secs(),
+                };
+                events.send(Event::DebridFileSelection(request));
+            }
+
+            tracing::info!("Waiting up to {:?} for file selection on {}", timeout, debrid_torrent_id);
+            tokio::select! {
+                answered = answer => {
+                    if answered.is_ok() {
+                        return Ok(true);
+                    }
+                }
+                _ = sleep(timeout) => {
+                    tracing::info!("No file selection for {}, selecting all files", debrid_torrent_id);
+                }
+                _ = cancel_token.cancelled() => {
+                    waiters.write().await.remove(debrid_torrent_id);
+                    return Ok(false);
+                }
+            }
+            waiters.write().await.remove(debrid_torrent_id);
+        }
+        Ok(_) => {}
+        Err(e) => {
+            tracing::warn!("Could not list files of {}, selecting all: {}", debrid_torrent_id, e);
+        }
+    }
+
+    debrid_manager
+        .read()
+        .await
+        .select_files(provider, debrid_torrent_id, &[])
+        .await?;
+    Ok(true)
+}
+
+/// Delete a finished cloud torrent from the provider, retrying with backoff.
+///
+/// Only deletes when the task was not cancelled and every file reached
+/// `Complete`. Returns `Ok(true)` if the torrent was deleted, `Ok(false)` if
+/// deletion was skipped, and the last error once all attempts failed.
+async fn remove_from_provider_after_download(
+    debrid_manager: &Arc<RwLock<DebridManager>>,
+    provider: DebridProviderType,
+    debrid_torrent_id: &str,
+    file_states: &[crate::state::CloudFileState],
+    cancelled: bool,
+    retry_base_delay: Duration,
+) -> Result<bool> {
+    let all_complete = !file_states.is_empty()
+        && file_states
+            .iter()
+            .all(|s| *s == crate::state::CloudFileState::Complete);
+    if cancelled || !all_complete {
+        return Ok(false);
+    }
+
+    let mut delay = retry_base_delay;
+    let mut attempt = 1;
+    loop {
+        let result = debrid_manager
+            .read()
+            .await
+            .delete_torrent(provider, debrid_torrent_id)
+            .await;
+
+        match result {
+            Ok(()) => {
+                tracing::info!("Deleted {} from {:?} after local download", debrid_torrent_id, provider);
+                return Ok(true);
+            }
+            Err(e) if attempt < DELETE_RETRY_ATTEMPTS => {
+                tracing::warn!(
+                    "Delete attempt {}/{} for {} failed: {}",
+                    attempt,
+                    DELETE_RETRY_ATTEMPTS,
+                    debrid_torrent_id,
+                    e
+                );
+                sleep(delay).await;
+                delay *= 2;
+                attempt += 1;
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+/// Helper function to download a file with state updates
+///
+/// Writes to the `.scpart` next to `destination` (continuing it if an earlier
+/// attempt left one), fsyncs it, then renames it into place. Errors and
+/// cancellation leave the part file for the next attempt. Returns the final
+/// path, which differs from `destination` when a collision was renamed.
+#[allow(clippy::too_many_arguments)]
+async fn download_file_with_state_update(
+    client: &reqwest::Client,
+    url: &str,
+    destination: &Path,
+    info_hash: &str,
+    file_name: &str,
+    file_size: u64,
+    file_collision: FileCollisionPolicy,
+    cancel_token: &CancellationToken,
+    torrents: &Arc<RwLock<std::collections::HashMap<String, crate::state::TorrentInfo>>>,
+    file_progress: &CloudProgress,
+    total_downloaded: &mut u64,
+) -> Result<PathBuf> {
+    let part = part_path(destination);
+    let existing = match tokio::fs::metadata(&part).await {
+        Ok(metadata) => metadata.len(),
+        Err(_) => 0,
+    };
+
+    let mut request = client.get(url);
+    if existing > 0 {
+        tracing::info!("Resuming {} from byte {}", file_name, existing);
+        request = request.header(reqwest::header::RANGE, format!("bytes={}-", existing));
+    }
+    let response = tokio::select! {
+        response = request.send() => response?,
+        _ = cancel_token.cancelled() => {
+            return Err(crate::error::Error::Other(format!("Download of {} cancelled", file_name)));
+        }
+    };
+    let status = response.status();
+
+    // The part file already holds everything; only the rename is missing
+    if existing > 0 && existing == file_size && status == reqwest::StatusCode::RANGE_NOT_SATISFIABLE {
+        *total_downloaded += existing;
+        return finalize_part(&part, destination, file_collision).await;
+    }
+
+    if !status.is_success() {
+        let body = response.text().await.unwrap_or_default();
+        if is_link_expiry(status, &body) {
+            return Err(crate::error::Error::LinkExpired(format!("HTTP {} for {}", status, file_name)));
+        }
+        return Err(crate::error::Error::NetworkError(format!(
+            "Failed to download file: HTTP {}",
+            status
+        )));
+    }
+
+    // A server that ignores Range sends the whole file again
+    let resumed = existing > 0 && status == reqwest::StatusCode::PARTIAL_CONTENT;
+    let mut file = if resumed {
+        OpenOptions::new().append(true).open(&part).await?
+    } else {
+        File::create(&part).await?
+    };
+    let mut downloaded: u64 = if resumed { existing } else { 0 };
+    *total_downloaded += downloaded;
+    let mut stream = response.bytes_stream();
+    let mut last_update = std::time::Instant::now();
+    let mut last_downloaded = downloaded;
+
+    use futures::StreamExt;
     
-    file.flush().await?;
-    
-    // Final state update
-    {
-        let mut progress_map = file_progress.write().await;
-        if let Some(file_map) = progress_map.get_mut(info_hash) {
-            if let Some(progress) = file_map.get_mut(file_name) {
-                progress.downloaded = downloaded;
-                progress.speed = 0;
+    loop {
+        let chunk = tokio::select! {
+            chunk = stream.next() => chunk,
+            _ = cancel_token.cancelled() => {
+                file.flush().await?;
+                return Err(crate::error::Error::Other(format!("Download of {} cancelled", file_name)));
             }
+        };
+        let Some(chunk) = chunk else { break };
+        let chunk = chunk?;
+        file.write_all(&chunk).await?;
+        downloaded += chunk.len() as u64;
+        *total_downloaded += chunk.len() as u64;
+        
+        // Update state every 100KB or 1 second
+        let now = std::time::Instant::now();
+        let elapsed = now.duration_since(last_update).as_secs_f64();
+        
+        if downloaded % (100 * 1024) == 0 || elapsed >= 1.0 {
+            // Calculate speed
+            let speed = if elapsed > 0.0 {
+                ((downloaded - last_downloaded) as f64 / elapsed) as u64
+            } else {
+                0
+            };
+            
+            // Update file progress
+            file_progress
+                .update(info_hash, file_name, |progress| {
+                    progress.downloaded = downloaded;
+                    progress.speed = speed;
+                })
+                .await;
+            
+            // Update torrent progress
+            {
+                let mut torrent_map = torrents.write().await;
+                if let Some(torrent) = cloud_entry(&mut torrent_map, info_hash) {
+                    torrent.downloaded = *total_downloaded;
+                    torrent.download_speed = speed;
+                }
+            }
+            
+            last_update = now;
+            last_downloaded = downloaded;
         }
     }
     
+    file.flush().await?;
+    file.sync_all().await?;
+    drop(file);
+    
+    // Final state update
+    file_progress
+        .update(info_hash, file_name, |progress| {
+            progress.downloaded = downloaded;
+            progress.speed = 0;
+        })
+        .await;
+    
     {
         let mut torrent_map = torrents.write().await;
-        if let Some(torrent) = torrent_map.get_mut(info_hash) {
+        if let Some(torrent) = cloud_entry(&mut torrent_map, info_hash) {
             torrent.downloaded = *total_downloaded;
         }
     }
     
-    Ok(())
+    finalize_part(&part, destination, file_collision).await
+}
+
+/// The entry of `info_hash` for the cloud download to update. A hybrid's
+/// entry belongs to its P2P side; the cloud side shows in its file progress.
+fn cloud_entry<'a>(
+    torrent_map: &'a mut HashMap<String, crate::state::TorrentInfo>,
+    info_hash: &str,
+) -> Option<&'a mut crate::state::TorrentInfo> {
+    torrent_map.get_mut(info_hash).filter(|torrent| !torrent.source.is_hybrid())
+}
+
+/// Whether a failed download response means the signed link itself expired
+/// (rather than the file or the provider being unavailable)
+fn is_link_expiry(status: reqwest::StatusCode, body: &str) -> bool {
+    if status == reqwest::StatusCode::FORBIDDEN || status == reqwest::StatusCode::GONE {
+        return true;
+    }
+    let body = body.to_lowercase();
+    status.is_client_error() && EXPIRED_LINK_BODIES.iter().any(|fragment| body.contains(fragment))
+}
+
+/// Re-fetches a cloud torrent's download links once they expire. Files that
+/// fail around the same time share one refresh instead of each calling the
+/// provider (whose client already paces its requests through a `RequestQueue`).
+struct LinkRefresher {
+    debrid_manager: Arc<RwLock<DebridManager>>,
+    provider: DebridProviderType,
+    debrid_torrent_id: String,
+    /// Last refresh and when it was fetched
+    latest: tokio::sync::Mutex<Option<(std::time::Instant, Vec<DebridFile>)>>,
+}
+
+impl LinkRefresher {
+    fn new(debrid_manager: Arc<RwLock<DebridManager>>, provider: DebridProviderType, debrid_torrent_id: String) -> Self {
+        Self { debrid_manager, provider, debrid_torrent_id, latest: tokio::sync::Mutex::new(None) }
+    }
+
+    /// A new URL for `file`, whose old link was found dead by an attempt
+    /// started at `attempt_started`
+    async fn fresh_url(&self, file: &DebridFile, attempt_started: std::time::Instant) -> Result<String> {
+        let mut latest = self.latest.lock().await;
+        let reusable = matches!(&*latest, Some((fetched_at, _)) if *fetched_at >= attempt_started);
+        if !reusable {
+            tracing::info!("Refreshing download links for {}", self.debrid_torrent_id);
+            let files = self
+                .debrid_manager
+                .read()
+                .await
+                .get_download_links(self.provider, &self.debrid_torrent_id)
+                .await?;
+            *latest = Some((std::time::Instant::now(), files));
+        }
+
+        let files = latest.as_ref().map(|(_, files)| files.as_slice()).unwrap_or_default();
+        matching_file(files, file)
+            .and_then(|fresh| fresh.download_link.clone().or_else(|| fresh.stream_link.clone()))
+            .ok_or_else(|| crate::error::Error::DebridError(format!("No fresh download link for {}", file.name)))
+    }
+}
+
+/// `file` among refreshed links: same id and name or size, else same name and size
+fn matching_file<'a>(files: &'a [DebridFile], file: &DebridFile) -> Option<&'a DebridFile> {
+    files
+        .iter()
+        .find(|f| f.id == file.id && (f.name == file.name || f.size == file.size))
+        .or_else(|| files.iter().find(|f| f.name == file.name && f.size == file.size))
+}
+
+/// `download_file_with_state_update`, refreshing the link when it expires and
+/// continuing the part file from where the dead link left it
+#[allow(clippy::too_many_arguments)]
+async fn download_with_link_refresh(
+    client: &reqwest::Client,
+    refresher: &LinkRefresher,
+    file: &DebridFile,
+    url: &str,
+    destination: &Path,
+    info_hash: &str,
+    file_collision: FileCollisionPolicy,
+    cancel_token: &CancellationToken,
+    torrents: &Arc<RwLock<std::collections::HashMap<String, crate::state::TorrentInfo>>>,
+    file_progress: &CloudProgress,
+    total_downloaded: &mut u64,
+) -> Result<PathBuf> {
+    let mut url = url.to_string();
+    let mut refreshes = 0;
+    loop {
+        let started = std::time::Instant::now();
+        // A resumed attempt counts the part file again
+        let before = *total_downloaded;
+        match download_file_with_state_update(
+            client, &url, destination, info_hash, &file.name, file.size, file_collision,
+            cancel_token, torrents, file_progress, total_downloaded,
+        )
+        .await
+        {
+            Err(crate::error::Error::LinkExpired(reason)) if refreshes < MAX_LINK_REFRESHES && !cancel_token.is_cancelled() => {
+                refreshes += 1;
+                tracing::info!("{}; refreshing the link ({}/{})", reason, refreshes, MAX_LINK_REFRESHES);
+                *total_downloaded = before;
+                url = refresher.fresh_url(file, started).await?;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Rename a finished part file to its final name
+async fn finalize_part(part: &Path, destination: &Path, file_collision: FileCollisionPolicy) -> Result<PathBuf> {
+    let target = if tokio::fs::try_exists(destination).await? {
+        match file_collision {
+            FileCollisionPolicy::Rename => unique_path(destination).await?,
+            FileCollisionPolicy::Error => {
+                return Err(crate::error::Error::IoError(format!(
+                    "{} already exists; the download was kept as {}",
+                    destination.display(),
+                    part.display()
+                )));
+            }
+        }
+    } else {
+        destination.to_path_buf()
+    };
+
+    tokio::fs::rename(part, &target).await?;
+    Ok(target)
+}
+
+/// First free "name (n).ext" next to `path`
+async fn unique_path(path: &Path) -> Result<PathBuf> {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy().to_string();
+    let extension = path.extension().map(|e| e.to_string_lossy().to_string());
+
+    for n in 1.. {
+        let name = match &extension {
+            Some(ext) => format!("{} ({}).{}", stem, n, ext),
+            None => format!("{} ({})", stem, n),
+        };
+        let candidate = path.with_file_name(name);
+        if !tokio::fs::try_exists(&candidate).await? {
+            return Ok(candidate);
+        }
+    }
+    unreachable!("ran out of candidate names for {}", path.display())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::debrid::types::*;
+    use crate::debrid::DebridProvider;
+    use crate::state::CloudFileState;
+    use anyhow::anyhow;
+    use async_trait::async_trait;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    /// Provider that supports file listing/selection, download links and
+    /// delete, failing the first `failures` delete calls
+    struct FakeProvider {
+        delete_calls: AtomicU32,
+        failures: u32,
+        files: Vec<RemoteFileInfo>,
+        selections: std::sync::Mutex<Vec<Vec<usize>>>,
+        links: std::sync::Mutex<Vec<DebridFile>>,
+        link_calls: AtomicU32,
+    }
+
+    #[async_trait]
+    impl DebridProvider for FakeProvider {
+        fn provider_type(&self) -> DebridProviderType {
+            DebridProviderType::RealDebrid
+        }
+        async fn validate_credentials(&self) -> anyhow::Result<bool> {
+            Ok(true)
+        }
+        async fn get_user_info(&self) -> anyhow::Result<UserInfo> {
+            Err(anyhow!("unsupported"))
+        }
+        async fn check_instant_availability(&self, _info_hash: &str) -> anyhow::Result<CacheStatus> {
+            Err(anyhow!("unsupported"))
+        }
+        async fn add_magnet(&self, _magnet_uri: &str) -> anyhow::Result<TorrentId> {
+            Err(anyhow!("unsupported"))
+        }
+        async fn add_torrent_file(&self, _torrent_data: &[u8]) -> anyhow::Result<TorrentId> {
+            Err(anyhow!("unsupported"))
+        }
+        async fn select_files(&self, _torrent_id: &str, file_ids: Vec<usize>) -> anyhow::Result<()> {
+            self.selections.lock().unwrap().push(file_ids);
+            Ok(())
+        }
+        async fn get_torrent_files(&self, _torrent_id: &str) -> anyhow::Result<Vec<RemoteFileInfo>> {
+            Ok(self.files.clone())
+        }
+        async fn get_torrent_info(&self, torrent_id: &str) -> anyhow::Result<DebridProgress> {
+            Ok(DebridProgress {
+                torrent_id: torrent_id.to_string(),
+                status: DebridStatus::Downloaded,
+                progress: 100.0,
+                speed: 0,
+                downloaded: 0,
+                total_size: self.files.iter().map(|f| f.size).sum(),
+                seeders: None,
+                eta: None,
+                info_hash: None,
+            })
+        }
+        async fn get_download_links(&self, _torrent_id: &str) -> anyhow::Result<Vec<DebridFile>> {
+            self.link_calls.fetch_add(1, Ordering::SeqCst);
+            Ok(self.links.lock().unwrap().clone())
+        }
+        async fn unrestrict_link(&self, _link: &str) -> anyhow::Result<String> {
+            Err(anyhow!("unsupported"))
+        }
+        async fn delete_torrent(&self, _torrent_id: &str) -> anyhow::Result<()> {
+            let call = self.delete_calls.fetch_add(1, Ordering::SeqCst);
+            if call < self.failures {
+                Err(anyhow!("HTTP 503"))
+            } else {
+                Ok(())
+            }
+        }
+        async fn list_torrents(&self) -> anyhow::Result<Vec<DebridProgress>> {
+            Ok(Vec::new())
+        }
+    }
+
+    fn setup(failures: u32) -> (Arc<FakeProvider>, Arc<RwLock<DebridManager>>) {
+        let provider = Arc::new(FakeProvider {
+            delete_calls: AtomicU32::new(0),
+            failures,
+            files: vec![
+                RemoteFileInfo { index: 0, path: "movie.mkv".to_string(), size: 1000, selected: false },
+                RemoteFileInfo { index: 1, path: "sample.mkv".to_string(), size: 10, selected: false },
+            ],
+            selections: std::sync::Mutex::new(Vec::new()),
+            links: std::sync::Mutex::new(Vec::new()),
+            link_calls: AtomicU32::new(0),
+        });
+        let mut manager = DebridManager::new();
+        manager.set_real_debrid(provider.clone());
+        (provider, Arc::new(RwLock::new(manager)))
+    }
+
+    #[tokio::test]
+    async fn test_deletes_when_all_files_complete() {
+        let (provider, manager) = setup(0);
+        let states = [CloudFileState::Complete, CloudFileState::Complete];
+
+        let deleted = remove_from_provider_after_download(
+            &manager,
+            DebridProviderType::RealDebrid,
+            "abc",
+            &states,
+            false,
+            Duration::ZERO,
+        )
+        .await
+        .unwrap();
+
+        assert!(deleted);
+        assert_eq!(provider.delete_calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_keeps_torrent_when_a_file_failed() {
+        let (provider, manager) = setup(0);
+        let states = [CloudFileState::Complete, CloudFileState::Error];
+
+        let deleted = remove_from_provider_after_download(
+            &manager,
+            DebridProviderType::RealDebrid,
+            "abc",
+            &states,
+            false,
+            Duration::ZERO,
+        )
+        .await
+        .unwrap();
+
+        assert!(!deleted);
+        assert_eq!(provider.delete_calls.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn test_keeps_torrent_when_cancelled() {
+        let (provider, manager) = setup(0);
+        let states = [CloudFileState::Complete];
+
+        let deleted = remove_from_provider_after_download(
+            &manager,
+            DebridProviderType::RealDebrid,
+            "abc",
+            &states,
+            true,
+            Duration::ZERO,
+        )
+        .await
+        .unwrap();
+
+        assert!(!deleted);
+        assert_eq!(provider.delete_calls.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn test_retries_failed_delete() {
+        let (provider, manager) = setup(2);
+        let states = [CloudFileState::Complete];
+
+        let deleted = remove_from_provider_after_download(
+            &manager,
+            DebridProviderType::RealDebrid,
+            "abc",
+            &states,
+            false,
+            Duration::ZERO,
+        )
+        .await
+        .unwrap();
+
+        assert!(deleted);
+        assert_eq!(provider.delete_calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_gives_up_after_max_attempts() {
+        let (provider, manager) = setup(u32::MAX);
+        let states = [CloudFileState::Complete];
+
+        let result = remove_from_provider_after_download(
+            &manager,
+            DebridProviderType::RealDebrid,
+            "abc",
+            &states,
+            false,
+            Duration::ZERO,
+        )
+        .await;
+
+        assert!(result.is_err());
+        assert_eq!(provider.delete_calls.load(Ordering::SeqCst), DELETE_RETRY_ATTEMPTS);
+    }
+
+    /// Read a request's head; returns its path and the `Range: bytes=N-` start
+    async fn read_request(socket: &mut tokio::net::TcpStream) -> (String, usize) {
+        use tokio::io::AsyncReadExt;
+
+        let mut request = Vec::new();
+        let mut buf = [0u8; 1024];
+        while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+            let n = socket.read(&mut buf).await.unwrap();
+            if n == 0 {
+                break;
+            }
+            request.extend_from_slice(&buf[..n]);
+        }
+
+        let request = String::from_utf8_lossy(&request).to_lowercase();
+        let path = request.split_whitespace().nth(1).unwrap_or("/").to_string();
+        let start = request
+            .lines()
+            .find_map(|line| line.trim().strip_prefix("range: bytes="))
+            .and_then(|range| range.trim_end_matches('-').parse::<usize>().ok())
+            .unwrap_or(0);
+        (path, start)
+    }
+
+    /// Response head for `body[start..]`
+    fn range_head(len: usize, start: usize) -> String {
+        if start > 0 {
+            format!(
+                "HTTP/1.1 206 Partial Content\r\nContent-Length: {}\r\nContent-Range: bytes {}-{}/{}\r\nConnection: close\r\n\r\n",
+                len - start,
+                start,
+                len - 1,
+                len
+            )
+        } else {
+            format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", len)
+        }
+    }
+
+    /// Serves `body` over plain HTTP. The first response is cut off after
+    /// `cut` bytes; later ones honour `Range: bytes=N-`.
+    async fn flaky_file_server(body: Vec<u8>, cut: usize) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/file.bin", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let mut first = true;
+            loop {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let (_, start) = read_request(&mut socket).await;
+                let end = if first { cut } else { body.len() };
+                first = false;
+
+                socket.write_all(range_head(body.len(), start).as_bytes()).await.unwrap();
+                socket.write_all(&body[start..end]).await.unwrap();
+            }
+        });
+        url
+    }
+
+    /// Serves `body` at an "old" signed URL that cuts off after `cut` bytes
+    /// and then answers 403, and at a "new" URL that works and honours Range
+    async fn expiring_link_server(body: Vec<u8>, cut: usize) -> (String, String) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let mut old_served = false;
+            loop {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let (path, start) = read_request(&mut socket).await;
+                if path.starts_with("/old") && old_served {
+                    let error = r#"{"error":"link expired"}"#;
+                    let head = format!("HTTP/1.1 403 Forbidden\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", error.len());
+                    socket.write_all(head.as_bytes()).await.unwrap();
+                    socket.write_all(error.as_bytes()).await.unwrap();
+                    continue;
+                }
+                let end = if path.starts_with("/old") { cut } else { body.len() };
+                old_served |= path.starts_with("/old");
+
+                socket.write_all(range_head(body.len(), start).as_bytes()).await.unwrap();
+                socket.write_all(&body[start..end]).await.unwrap();
+            }
+        });
+        (format!("{}/old/file.bin?sig=1", base), format!("{}/new/file.bin?sig=2", base))
+    }
+
+    /// Serves the first `cut` bytes of a `len` byte file, then stalls with
+    /// the connection open
+    async fn stalling_file_server(len: usize, cut: usize) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/file.bin", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let mut open = Vec::new();
+            loop {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                read_request(&mut socket).await;
+                socket.write_all(range_head(len, 0).as_bytes()).await.unwrap();
+                socket.write_all(&vec![7u8; cut]).await.unwrap();
+                open.push(socket);
+            }
+        });
+        url
+    }
+
+    /// Serves `body`, stalling with the connection open after `cut` bytes
+    /// of the first response; later ones honour `Range: bytes=N-`
+    async fn stall_once_file_server(body: Vec<u8>, cut: usize) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/file.bin", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let mut stalled = Vec::new();
+            loop {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let (_, start) = read_request(&mut socket).await;
+                socket.write_all(range_head(body.len(), start).as_bytes()).await.unwrap();
+                if stalled.is_empty() {
+                    socket.write_all(&body[..cut]).await.unwrap();
+                    stalled.push(socket);
+                } else {
+                    socket.write_all(&body[start..]).await.unwrap();
+                }
+            }
+        });
+        url
+    }
+
+    fn debrid_file(id: &str, name: &str, size: u64, link: &str) -> DebridFile {
+        DebridFile {
+            id: id.to_string(),
+            name: name.to_string(),
+            size,
+            download_link: Some(link.to_string()),
+            stream_link: None,
+            mime_type: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_interrupted_download_resumes_from_part_file() {
+        let body: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+        let url = flaky_file_server(body.clone(), 70_000).await;
+        let dir = tempfile::TempDir::new().unwrap();
+        let destination = dir.path().join("file.bin");
+        let part = part_path(&destination);
+
+        let client = reqwest::Client::new();
+        let torrents = Arc::new(RwLock::new(std::collections::HashMap::new()));
+        let progress = CloudProgress::new(Arc::new(crate::database::Database::open(dir.path().join("db")).unwrap()));
+        let cancel = CancellationToken::new();
+        let mut total = 0;
+
+        let first = download_file_with_state_update(
+            &client, &url, &destination, "hash", "file.bin", body.len() as u64,
+            FileCollisionPolicy::Rename, &cancel, &torrents, &progress, &mut total,
+        )
+        .await;
+        assert!(first.is_err());
+        assert!(!destination.exists(), "an interrupted download must not produce the final name");
+        let partial = std::fs::metadata(&part).unwrap().len();
+        assert!(partial > 0 && partial <= 70_000, "part file holds {} bytes", partial);
+
+        let mut total = 0;
+        let final_path = download_file_with_state_update(
+            &client, &url, &destination, "hash", "file.bin", body.len() as u64,
+            FileCollisionPolicy::Rename, &cancel, &torrents, &progress, &mut total,
+        )
+        .await
+        .unwrap();
+        assert_eq!(final_path, destination);
+        assert!(!part.exists());
+        assert_eq!(std::fs::read(&destination).unwrap(), body);
+        assert_eq!(total, body.len() as u64);
+    }
+
+    #[tokio::test]
+    async fn test_expired_link_is_refreshed_and_resumed() {
+        let body: Vec<u8> = (0..200_000u32).map(|i| (i % 241) as u8).collect();
+        let (old_url, new_url) = expiring_link_server(body.clone(), 100_000).await;
+        let (provider, manager) = setup(0);
+        let file = debrid_file("0", "file.bin", body.len() as u64, &old_url);
+        *provider.links.lock().unwrap() = vec![
+            debrid_file("1", "sample.bin", 10, "http://unused.invalid/sample"),
+            debrid_file("0", "file.bin", body.len() as u64, &new_url),
+        ];
+        let refresher = LinkRefresher::new(manager, DebridProviderType::RealDebrid, "T1".to_string());
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let destination = dir.path().join("file.bin");
+        let client = reqwest::Client::new();
+        let torrents = Arc::new(RwLock::new(std::collections::HashMap::new()));
+        let progress = CloudProgress::new(Arc::new(crate::database::Database::open(dir.path().join("db")).unwrap()));
+        let cancel = CancellationToken::new();
+
+        // Half the file arrives before the connection drops
+        let mut total = 0;
+        let first = download_with_link_refresh(
+            &client, &refresher, &file, &old_url, &destination, "hash",
+            FileCollisionPolicy::Rename, &cancel, &torrents, &progress, &mut total,
+        )
+        .await;
+        assert!(first.is_err());
+        assert_eq!(provider.link_calls.load(Ordering::SeqCst), 0);
+        let partial = std::fs::metadata(part_path(&destination)).unwrap().len();
+
+        // The old link now answers 403: refresh, then continue from the part file
+        let mut total = 0;
+        let final_path = download_with_link_refresh(
+            &client, &refresher, &file, &old_url, &destination, "hash",
+            FileCollisionPolicy::Rename, &cancel, &torrents, &progress, &mut total,
+        )
+        .await
+        .unwrap();
+        assert_eq!(final_path, destination);
+        assert_eq!(std::fs::read(&destination).unwrap(), body);
+        assert_eq!(total, body.len() as u64);
+        assert_eq!(provider.link_calls.load(Ordering::SeqCst), 1);
+        assert!(partial > 0 && partial < body.len() as u64);
+    }
+
+    #[tokio::test]
+    async fn test_refresh_gives_up_after_max_attempts() {
+        let body = vec![7u8; 1000];
+        let (old_url, _) = expiring_link_server(body.clone(), 10).await;
+        let (provider, manager) = setup(0);
+        let file = debrid_file("0", "file.bin", 1000, &old_url);
+        // The provider keeps handing out the same dead link
+        *provider.links.lock().unwrap() = vec![file.clone()];
+        let refresher = LinkRefresher::new(manager, DebridProviderType::RealDebrid, "T1".to_string());
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let destination = dir.path().join("file.bin");
+        let client = reqwest::Client::new();
+        let torrents = Arc::new(RwLock::new(std::collections::HashMap::new()));
+        let progress = CloudProgress::new(Arc::new(crate::database::Database::open(dir.path().join("db")).unwrap()));
+        let cancel = CancellationToken::new();
+        let mut total = 0;
+
+        let _ = download_with_link_refresh(
+            &client, &refresher, &file, &old_url, &destination, "hash",
+            FileCollisionPolicy::Rename, &cancel, &torrents, &progress, &mut total,
+        )
+        .await;
+        let result = download_with_link_refresh(
+            &client, &refresher, &file, &old_url, &destination, "hash",
+            FileCollisionPolicy::Rename, &cancel, &torrents, &progress, &mut total,
+        )
+        .await;
+        assert!(matches!(result, Err(crate::error::Error::LinkExpired(_))));
+        assert_eq!(provider.link_calls.load(Ordering::SeqCst), MAX_LINK_REFRESHES);
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_failures_share_one_refresh() {
+        let (provider, manager) = setup(0);
+        let movie = debrid_file("0", "movie.mkv", 1000, "http://old/0");
+        let sample = debrid_file("1", "sample.mkv", 10, "http://old/1");
+        *provider.links.lock().unwrap() = vec![
+            debrid_file("0", "movie.mkv", 1000, "http://new/0"),
+            // Renumbered by the provider: still found by name and size
+            debrid_file("5", "sample.mkv", 10, "http://new/1"),
+        ];
+        let refresher = LinkRefresher::new(manager, DebridProviderType::RealDebrid, "T1".to_string());
+
+        let failed_at = std::time::Instant::now();
+        let (a, b) = tokio::join!(refresher.fresh_url(&movie, failed_at), refresher.fresh_url(&sample, failed_at));
+        assert_eq!(a.unwrap(), "http://new/0");
+        assert_eq!(b.unwrap(), "http://new/1");
+        assert_eq!(provider.link_calls.load(Ordering::SeqCst), 1);
+
+        // A failure after that refresh fetches again
+        let later = std::time::Instant::now();
+        refresher.fresh_url(&movie, later).await.unwrap();
+        assert_eq!(provider.link_calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_link_expiry_classification() {
+        use reqwest::StatusCode;
+        assert!(is_link_expiry(StatusCode::FORBIDDEN, ""));
+        assert!(is_link_expiry(StatusCode::GONE, ""));
+        assert!(is_link_expiry(StatusCode::BAD_REQUEST, r#"{"error":"Link Expired"}"#));
+        assert!(!is_link_expiry(StatusCode::NOT_FOUND, ""));
+        assert!(!is_link_expiry(StatusCode::SERVICE_UNAVAILABLE, r#"{"error":"expired"}"#));
+    }
+
+    #[tokio::test]
+    async fn test_finalize_part_collisions() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let destination = dir.path().join("movie.mkv");
+        let part = part_path(&destination);
+        std::fs::write(&destination, b"old").unwrap();
+
+        std::fs::write(&part, b"new").unwrap();
+        assert!(finalize_part(&part, &destination, FileCollisionPolicy::Error).await.is_err());
+        assert!(part.exists());
+        assert_eq!(std::fs::read(&destination).unwrap(), b"old");
+
+        let renamed = finalize_part(&part, &destination, FileCollisionPolicy::Rename).await.unwrap();
+        assert_eq!(renamed, dir.path().join("movie (1).mkv"));
+        assert_eq!(std::fs::read(&renamed).unwrap(), b"new");
+        assert_eq!(std::fs::read(&destination).unwrap(), b"old");
+    }
+
+    #[tokio::test]
+    async fn test_file_selection_waits_for_user() {
+        let (provider, manager) = setup(0);
+        let waiters: FileSelectionWaiters = Arc::new(RwLock::new(HashMap::new()));
+        let cancel = CancellationToken::new();
+
+        let task = {
+            let (manager, waiters, cancel) = (manager.clone(), waiters.clone(), cancel.clone());
+            tokio::spawn(async move {
+                await_file_selection(
+                    &manager,
+                    DebridProviderType::RealDebrid,
+                    "abc",
+                    "hash",
+                    &waiters,
+                    Duration::from_secs(60),
+                    &cancel,
+                    None,
+                )
+                .await
+            })
+        };
+
+        while !waiters.read().await.contains_key("abc") {
+            tokio::task::yield_now().await;
+        }
+
+        // What select_debrid_files does when the user confirms the dialog
+        manager.read().await.select_files(DebridProviderType::RealDebrid, "abc", &[0]).await.unwrap();
+        assert!(finish_file_selection(&waiters, "abc").await);
+
+        assert!(task.await.unwrap().unwrap());
+        assert_eq!(*provider.selections.lock().unwrap(), vec![vec![0]]);
+        assert!(waiters.read().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_file_selection_times_out_to_all_files() {
+        let (provider, manager) = setup(0);
+        let waiters: FileSelectionWaiters = Arc::new(RwLock::new(HashMap::new()));
+
+        let selected = await_file_selection(
+            &manager,
+            DebridProviderType::RealDebrid,
+            "abc",
+            "hash",
+            &waiters,
+            Duration::from_millis(10),
+            &CancellationToken::new(),
+            None,
+        )
+        .await
+        .unwrap();
+
+        assert!(selected);
+        assert_eq!(*provider.selections.lock().unwrap(), vec![Vec::<usize>::new()]);
+        assert!(waiters.read().await.is_empty());
+        assert!(!finish_file_selection(&waiters, "abc").await);
+    }
+
+    /// A cloud torrent in the torrent list, downloading from `provider`
+    fn cloud_torrent(info_hash: &str, provider: DebridProviderType, debrid_id: &DebridTorrentId) -> crate::state::TorrentInfo {
+        crate::state::TorrentInfo {
+            id: info_hash.to_string(),
+            name: "movie.mkv".to_string(),
+            size: 100_000,
+            downloaded: 0,
+            uploaded: 0,
+            state: TorrentState::Downloading,
+            download_speed: 0,
+            upload_speed: 0,
+            peers: 0,
+            seeds: 0,
+            source: DownloadSource::Debrid { provider, torrent_id: debrid_id.clone() },
+            remote_deleted: false,
+            swarm_seeds: None,
+            swarm_leechers: None,
+            swarm_updated_at: None,
+            queue_position: None,
+            metadata_pending: false,
+            name_encoding: None,
+            tags: Vec::new(),
+            error: None,
+        }
+    }
+
+    /// How the tests start a download of `info_hash` on Real-Debrid
+    fn download_params(
+        info_hash: &str,
+        debrid_torrent_id: DebridTorrentId,
+        save_path: &Path,
+        failover: FailoverPlan,
+    ) -> CloudDownloadParams {
+        CloudDownloadParams {
+            info_hash: info_hash.to_string(),
+            debrid_torrent_id,
+            provider: DebridProviderType::RealDebrid,
+            save_path: save_path.to_path_buf(),
+            delete_after_download: false,
+            file_collision: FileCollisionPolicy::Rename,
+            ask_file_selection: false,
+            failover,
+            events: None,
+        }
+    }
+
+    /// Remove a cloud torrent whose download is stuck mid-file; returns
+    /// whether its part file is left and how often the provider was asked
+    /// to delete it
+    #[cfg(feature = "app")]
+    async fn remove_stalled_download(delete_files: bool, delete_from_provider: bool) -> (bool, u32) {
+        let (provider, manager) = setup(0);
+        let url = stalling_file_server(100_000, 30_000).await;
+        *provider.links.lock().unwrap() = vec![debrid_file("0", "movie.mkv", 100_000, &url)];
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let mut state = crate::state::AppState::with_database(crate::database::Database::open(dir.path().join("db")).unwrap());
+        state.debrid_manager = manager;
+        let save_path = dir.path().join("downloads");
+        std::fs::create_dir_all(&save_path).unwrap();
+        let info_hash = "ab".repeat(20);
+        let debrid_id = DebridTorrentId::parse("RD123").unwrap();
+        state.torrents.write().await.insert(info_hash.clone(), cloud_torrent(&info_hash, DebridProviderType::RealDebrid, &debrid_id));
+
+        state.cloud_downloads = Arc::new(CloudDownloadManager::new(
+            state.debrid_manager.clone(),
+            state.torrents.clone(),
+            state.cloud_file_progress.clone(),
+            state.cloud_file_selections.clone(),
+        ));
+        let stopped = state
+            .cloud_downloads
+            .start_download(download_params(&info_hash, debrid_id, &save_path, FailoverPlan::none()))
+            .await;
+
+        let part = part_path(&save_path.join("movie.mkv"));
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while std::fs::metadata(&part).map_or(0, |m| m.len()) < 30_000 {
+                sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("download never started");
+
+        let started = std::time::Instant::now();
+        crate::commands::remove_torrent_internal(&state, info_hash, delete_files, delete_from_provider, false)
+            .await
+            .unwrap();
+        // Stopped by its token, not aborted after the timeout
+        assert!(started.elapsed() < TASK_STOP_TIMEOUT);
+        assert!(stopped.is_finished());
+        assert!(state.cloud_downloads.info_hashes().await.is_empty());
+        assert!(state.torrents.read().await.is_empty());
+
+        (part.exists(), provider.delete_calls.load(Ordering::SeqCst))
+    }
+
+    #[cfg(feature = "app")]
+    #[tokio::test]
+    async fn test_remove_stops_cloud_download() {
+        assert_eq!(remove_stalled_download(false, false).await, (true, 0));
+        assert_eq!(remove_stalled_download(true, false).await, (false, 0));
+        assert_eq!(remove_stalled_download(true, true).await, (false, 1));
+    }
+
+    #[tokio::test]
+    async fn test_pause_resume_and_cancel() {
+        let body: Vec<u8> = (0..100_000u32).map(|i| (i % 233) as u8).collect();
+        let url = stall_once_file_server(body.clone(), 30_000).await;
+        let (provider, manager) = setup(0);
+        *provider.links.lock().unwrap() = vec![debrid_file("0", "movie.mkv", body.len() as u64, &url)];
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let info_hash = "cd".repeat(20);
+        let debrid_id = DebridTorrentId::parse("RD7").unwrap();
+        let torrents = Arc::new(RwLock::new(HashMap::from([(
+            info_hash.clone(),
+            cloud_torrent(&info_hash, DebridProviderType::RealDebrid, &debrid_id),
+        )])));
+        let progress = CloudProgress::new(Arc::new(crate::database::Database::open(dir.path().join("db")).unwrap()));
+        let downloads = CloudDownloadManager::new(manager, torrents.clone(), progress, Arc::new(RwLock::new(HashMap::new())));
+        let state = || async { torrents.read().await.get(&info_hash).map(|torrent| torrent.state.clone()) };
+
+        let first = downloads.start_download(download_params(&info_hash, debrid_id, dir.path(), FailoverPlan::none())).await;
+        let part = part_path(&dir.path().join("movie.mkv"));
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while std::fs::metadata(&part).map_or(0, |m| m.len()) < 30_000 {
+                sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("download never started");
+
+        // Paused: the task is gone, the download and its part file stay
+        assert!(downloads.pause(&info_hash).await);
+        assert!(first.is_finished());
+        assert!(!downloads.pause(&info_hash).await);
+        assert!(downloads.contains(&info_hash).await && !downloads.is_running(&info_hash).await);
+        assert_eq!(state().await, Some(TorrentState::Paused));
+        assert!(part.exists());
+
+        // Resumed: carries on from the part file to the end
+        assert!(downloads.resume(&info_hash).await.is_some());
+        assert!(downloads.resume(&info_hash).await.is_none());
+        tokio::time::timeout(Duration::from_secs(10), async {
+            while downloads.is_running(&info_hash).await {
+                sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("resumed download hung");
+        assert_eq!(std::fs::read(dir.path().join("movie.mkv")).unwrap(), body);
+        assert_eq!(state().await, Some(TorrentState::Seeding));
+        assert_eq!(downloads.running().await, 0);
+
+        // Cancelled: forgotten
+        assert_eq!(downloads.cancel(&info_hash).await, Some(dir.path().to_path_buf()));
+        assert!(!downloads.contains(&info_hash).await);
+        assert!(downloads.cancel(&info_hash).await.is_none());
+        assert!(downloads.resume("unknown").await.is_none());
+    }
+
+    /// Where a `ScriptedProvider` stops working
+    #[derive(Clone, Copy, PartialEq)]
+    enum FailAt {
+        Never,
+        /// Every status poll is refused
+        Poll,
+        /// The torrent is reported dead
+        Status,
+        /// Status polls are refused once the links were handed out
+        AfterLinks,
+    }
+
+    /// Provider that takes magnets and serves fixed links, failing as scripted
+    struct ScriptedProvider {
+        kind: DebridProviderType,
+        fail_at: FailAt,
+        links: Vec<DebridFile>,
+        added: std::sync::Mutex<Vec<String>>,
+        link_calls: AtomicU32,
+        delete_calls: AtomicU32,
+    }
+
+    impl ScriptedProvider {
+        fn new(kind: DebridProviderType, fail_at: FailAt, links: Vec<DebridFile>) -> Arc<Self> {
+            Arc::new(Self {
+                kind,
+                fail_at,
+                links,
+                added: std::sync::Mutex::new(Vec::new()),
+                link_calls: AtomicU32::new(0),
+                delete_calls: AtomicU32::new(0),
+            })
+        }
+    }
+
+    #[async_trait]
+    impl DebridProvider for ScriptedProvider {
+        fn provider_type(&self) -> DebridProviderType {
+            self.kind
+        }
+        async fn validate_credentials(&self) -> anyhow::Result<bool> {
+            Ok(true)
+        }
+        async fn get_user_info(&self) -> anyhow::Result<UserInfo> {
+            Err(anyhow!("unsupported"))
+        }
+        async fn check_instant_availability(&self, _info_hash: &str) -> anyhow::Result<CacheStatus> {
+            Err(anyhow!("unsupported"))
+        }
+        async fn add_magnet(&self, magnet_uri: &str) -> anyhow::Result<TorrentId> {
+            self.added.lock().unwrap().push(magnet_uri.to_string());
+            Ok(TorrentId { id: "TB1".to_string(), uri: None })
+        }
+        async fn add_torrent_file(&self, _torrent_data: &[u8]) -> anyhow::Result<TorrentId> {
+            Err(anyhow!("unsupported"))
+        }
+        async fn select_files(&self, _torrent_id: &str, _file_ids: Vec<usize>) -> anyhow::Result<()> {
+            Ok(())
+        }
+        async fn get_torrent_files(&self, _torrent_id: &str) -> anyhow::Result<Vec<RemoteFileInfo>> {
+            Ok(Vec::new())
+        }
+        async fn get_torrent_info(&self, torrent_id: &str) -> anyhow::Result<DebridProgress> {
+            use crate::debrid::ProviderError;
+            let refused = match self.fail_at {
+                FailAt::Poll => Some((reqwest::StatusCode::FORBIDDEN, "too_many_active_downloads")),
+                FailAt::AfterLinks if self.link_calls.load(Ordering::SeqCst) > 0 => {
+                    Some((reqwest::StatusCode::NOT_FOUND, "unknown_ressource"))
+                }
+                _ => None,
+            };
+            if let Some((status, body)) = refused {
+                return Err(ProviderError::from_status(self.kind, status, body.to_string()).into());
+            }
+            let status = if self.fail_at == FailAt::Status { DebridStatus::Dead } else { DebridStatus::Downloaded };
+            Ok(DebridProgress {
+                torrent_id: torrent_id.to_string(),
+                status,
+                progress: 100.0,
+                speed: 0,
+                downloaded: 0,
+                total_size: self.links.iter().map(|f| f.size).sum(),
+                seeders: None,
+                eta: None,
+                info_hash: None,
+            })
+        }
+        async fn get_download_links(&self, _torrent_id: &str) -> anyhow::Result<Vec<DebridFile>> {
+            self.link_calls.fetch_add(1, Ordering::SeqCst);
+            Ok(self.links.clone())
+        }
+        async fn unrestrict_link(&self, _link: &str) -> anyhow::Result<String> {
+            Err(anyhow!("unsupported"))
+        }
+        async fn delete_torrent(&self, _torrent_id: &str) -> anyhow::Result<()> {
+            self.delete_calls.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+        async fn list_torrents(&self) -> anyhow::Result<Vec<DebridProgress>> {
+            Ok(Vec::new())
+        }
+    }
+
+    /// Serves each of `files` (path, body) in full, honouring Range; records
+    /// the path and range start of every request
+    async fn recording_file_server(files: Vec<(&'static str, Vec<u8>)>) -> (String, Arc<std::sync::Mutex<Vec<(String, usize)>>>) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let seen = requests.clone();
+        tokio::spawn(async move {
+            loop {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let (path, start) = read_request(&mut socket).await;
+                seen.lock().unwrap().push((path.clone(), start));
+                let body = &files.iter().find(|(name, _)| *name == path).unwrap().1;
+                socket.write_all(range_head(body.len(), start).as_bytes()).await.unwrap();
+                socket.write_all(&body[start..]).await.unwrap();
+            }
+        });
+        (base, requests)
+    }
+
+    /// Run a cloud download added to `first` (as Real-Debrid) that may fail
+    /// over to `second` (as Torbox) until the task ends; returns the torrent
+    /// as the task left it
+    async fn run_failover(
+        first: Arc<ScriptedProvider>,
+        second: Arc<ScriptedProvider>,
+        disabled: bool,
+        save_path: &Path,
+    ) -> crate::state::TorrentInfo {
+        let mut manager = DebridManager::new();
+        manager.set_real_debrid(first);
+        manager.set_torbox(second);
+        let failover = FailoverPlan {
+            candidates: manager.failover_candidates(DebridProviderType::RealDebrid),
+            magnet: format!("magnet:?xt=urn:btih:{}", "ab".repeat(20)),
+            disabled,
+        };
+        assert_eq!(failover.candidates, [DebridProviderType::RealDebrid, DebridProviderType::Torbox]);
+
+        let info_hash = "ab".repeat(20);
+        let debrid_id = DebridTorrentId::parse("RD1").unwrap();
+        let torrents = Arc::new(RwLock::new(HashMap::from([(
+            info_hash.clone(),
+            cloud_torrent(&info_hash, DebridProviderType::RealDebrid, &debrid_id),
+        )])));
+        let progress = CloudProgress::new(Arc::new(
+            crate::database::Database::open(save_path.join("db")).unwrap(),
+        ));
+
+        let downloads = CloudDownloadManager::new(
+            Arc::new(RwLock::new(manager)),
+            torrents.clone(),
+            progress,
+            Arc::new(RwLock::new(HashMap::new())),
+        );
+        downloads.start_download(download_params(&info_hash, debrid_id, save_path, failover)).await;
+        tokio::time::timeout(Duration::from_secs(10), async {
+            while downloads.is_running(&info_hash).await {
+                sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("download task hung");
+
+        let torrent = torrents.read().await.get(&info_hash).cloned();
+        torrent.unwrap()
+    }
+
+    /// Whether `source` is the copy added to Torbox on failover
+    fn on_torbox(source: &DownloadSource) -> bool {
+        matches!(source, DownloadSource::Debrid { provider: DebridProviderType::Torbox, torrent_id } if torrent_id.as_str() == "TB1")
+    }
+
+    #[test]
+    fn test_failover_plan() {
+        let plan = FailoverPlan {
+            candidates: vec![DebridProviderType::RealDebrid, DebridProviderType::Torbox],
+            magnet: String::new(),
+            disabled: false,
+        };
+        assert_eq!(plan.next(&[DebridProviderType::RealDebrid]), Some(DebridProviderType::Torbox));
+        assert_eq!(plan.next(&[DebridProviderType::RealDebrid, DebridProviderType::Torbox]), None);
+        assert_eq!(FailoverPlan { disabled: true, ..plan.clone() }.next(&[DebridProviderType::RealDebrid]), None);
+        assert_eq!(FailoverPlan::none().next(&[]), None);
+
+        let old = [debrid_file("1", "a.bin", 10, "http://a/1"), debrid_file("2", "b.bin", 20, "http://a/2")];
+        // Other ids, links and order don't matter
+        let new = [debrid_file("9", "b.bin", 20, "http://b/9"), debrid_file("8", "a.bin", 10, "http://b/8")];
+        assert!(same_files(&old, &new));
+        assert!(!same_files(&old, &new[..1]));
+        assert!(!same_files(&old, &[debrid_file("8", "a.bin", 10, "x"), debrid_file("9", "b.bin", 21, "y")]));
+    }
+
+    #[tokio::test]
+    async fn test_fails_over_before_any_bytes() {
+        for fail_at in [FailAt::Poll, FailAt::Status] {
+            let body: Vec<u8> = (0..50_000u32).map(|i| (i % 239) as u8).collect();
+            let (base, requests) = recording_file_server(vec![("/movie.mkv", body.clone())]).await;
+            let first = ScriptedProvider::new(DebridProviderType::RealDebrid, fail_at, Vec::new());
+            let second = ScriptedProvider::new(
+                DebridProviderType::Torbox,
+                FailAt::Never,
+                vec![debrid_file("0", "movie.mkv", body.len() as u64, &format!("{}/movie.mkv", base))],
+            );
+            let dir = tempfile::TempDir::new().unwrap();
+
+            let torrent = run_failover(first.clone(), second.clone(), false, dir.path()).await;
+            assert_eq!(torrent.state, TorrentState::Seeding);
+            assert!(on_torbox(&torrent.source));
+            assert_eq!(*second.added.lock().unwrap(), [format!("magnet:?xt=urn:btih:{}", "ab".repeat(20))]);
+            assert_eq!(first.link_calls.load(Ordering::SeqCst), 0);
+            assert_eq!(*requests.lock().unwrap(), [("/movie.mkv".to_string(), 0)]);
+            assert_eq!(std::fs::read(dir.path().join("movie.mkv")).unwrap(), body);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_no_failover_override_is_respected() {
+        let first = ScriptedProvider::new(DebridProviderType::RealDebrid, FailAt::Poll, Vec::new());
+        let second = ScriptedProvider::new(DebridProviderType::Torbox, FailAt::Never, Vec::new());
+        let dir = tempfile::TempDir::new().unwrap();
+
+        let torrent = run_failover(first, second.clone(), true, dir.path()).await;
+        assert_eq!(torrent.state, TorrentState::Error);
+        assert!(second.added.lock().unwrap().is_empty());
+        assert!(matches!(torrent.source, DownloadSource::Debrid { provider: DebridProviderType::RealDebrid, .. }));
+    }
+
+    /// A download from Real-Debrid whose second file breaks off after
+    /// 70 000 bytes, after which Real-Debrid forgets the torrent; Torbox
+    /// serves `torbox_files` (path, body) from a recording server
+    async fn fail_mid_download(
+        done: &[u8],
+        cut: &[u8],
+        torbox_files: Vec<(&'static str, Vec<u8>)>,
+    ) -> (crate::state::TorrentInfo, Arc<ScriptedProvider>, Vec<(String, usize)>, tempfile::TempDir) {
+        let done_url = flaky_file_server(done.to_vec(), done.len()).await;
+        let cut_url = flaky_file_server(cut.to_vec(), 70_000).await;
+        let first = ScriptedProvider::new(
+            DebridProviderType::RealDebrid,
+            FailAt::AfterLinks,
+            vec![
+                debrid_file("0", "a.bin", done.len() as u64, &done_url),
+                debrid_file("1", "b.bin", cut.len() as u64, &cut_url),
+            ],
+        );
+        let (base, requests) = recording_file_server(torbox_files.clone()).await;
+        let links = torbox_files
+            .iter()
+            .map(|(path, body)| debrid_file("7", &path[1..], body.len() as u64, &format!("{}{}", base, path)))
+            .collect();
+        let second = ScriptedProvider::new(DebridProviderType::Torbox, FailAt::Never, links);
+        let dir = tempfile::TempDir::new().unwrap();
+
+        let torrent = run_failover(first, second.clone(), false, dir.path()).await;
+        let requests = requests.lock().unwrap().clone();
+        (torrent, second, requests, dir)
+    }
+
+    #[tokio::test]
+    async fn test_fails_over_mid_download_and_resumes() {
+        let done = vec![1u8; 20_000];
+        let cut: Vec<u8> = (0..200_000u32).map(|i| (i % 233) as u8).collect();
+        let (torrent, second, requests, dir) =
+            fail_mid_download(&done, &cut, vec![("/a.bin", done.clone()), ("/b.bin", cut.clone())]).await;
+
+        assert_eq!(torrent.state, TorrentState::Seeding);
+        assert!(on_torbox(&torrent.source));
+        assert_eq!(second.delete_calls.load(Ordering::SeqCst), 0);
+        // The finished file isn't fetched again; the cut one continues where it broke off
+        assert_eq!(requests.len(), 1);
+        let (path, start) = &requests[0];
+        assert_eq!(path, "/b.bin");
+        assert!(*start > 0 && *start <= 70_000, "resumed from byte {}", start);
+        assert_eq!(std::fs::read(dir.path().join("a.bin")).unwrap(), done);
+        assert_eq!(std::fs::read(dir.path().join("b.bin")).unwrap(), cut);
+        assert!(!part_path(&dir.path().join("b.bin")).exists());
+    }
+
+    #[tokio::test]
+    async fn test_no_failover_to_different_files_once_bytes_written() {
+        let done = vec![1u8; 20_000];
+        let cut = vec![2u8; 200_000];
+        // Torbox's copy of b.bin has another size
+        let (torrent, second, requests, dir) =
+            fail_mid_download(&done, &cut, vec![("/a.bin", done.clone()), ("/b.bin", vec![2u8; 200_001])]).await;
+
+        assert_eq!(torrent.state, TorrentState::Error);
+        assert!(matches!(torrent.source, DownloadSource::Debrid { provider: DebridProviderType::RealDebrid, .. }));
+        assert_eq!(second.added.lock().unwrap().len(), 1);
+        assert_eq!(second.delete_calls.load(Ordering::SeqCst), 1, "the useless copy is removed again");
+        assert!(requests.is_empty());
+        assert!(part_path(&dir.path().join("b.bin")).exists());
+    }
 }
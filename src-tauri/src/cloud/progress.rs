@@ -0,0 +1,219 @@
+//! Per-file progress of cloud downloads
+//!
+//! A running download keeps a live entry per file. When it finishes, the
+//! final state and size of each file go to the database right away, and the
+//! live entry is dropped after `FINISHED_GRACE` or sooner when more than
+//! `MAX_LIVE_TORRENTS` finished ones pile up (oldest first). Lookups fall
+//! back to the stored summary, so the files tab survives both and a restart.
+
+use crate::database::Database;
+use crate::state::{CloudFileProgress, CloudFileState, CloudFileSummary};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::RwLock;
+
+/// How long a finished torrent keeps its live progress
+pub const FINISHED_GRACE: Duration = Duration::from_secs(10 * 60);
+
+/// Torrents with live progress kept at most (running ones are never dropped)
+pub const MAX_LIVE_TORRENTS: usize = 32;
+
+struct TorrentProgress {
+    /// By file name
+    files: HashMap<String, CloudFileProgress>,
+    /// Set once the download task is done with the torrent
+    finished_at: Option<Instant>,
+}
+
+/// Cloud file progress by info hash
+#[derive(Clone)]
+pub struct CloudProgress {
+    live: Arc<RwLock<HashMap<String, TorrentProgress>>>,
+    database: Arc<Database>,
+}
+
+impl CloudProgress {
+    pub fn new(database: Arc<Database>) -> Self {
+        Self { live: Default::default(), database }
+    }
+
+    /// Begin tracking a torrent's files (name and size), all queued
+    pub async fn start(&self, info_hash: &str, files: impl IntoIterator<Item = (String, u64)>) {
+        let files = files
+            .into_iter()
+            .map(|(name, size)| {
+                let progress = CloudFileProgress {
+                    name: name.clone(),
+                    size,
+                    downloaded: 0,
+                    speed: 0,
+                    state: CloudFileState::Queued,
+                };
+                (name, progress)
+            })
+            .collect();
+
+        let mut live = self.live.write().await;
+        live.insert(info_hash.to_string(), TorrentProgress { files, finished_at: None });
+        collapse(&mut live, Instant::now());
+    }
+
+    /// Change one file's progress; unknown torrents and files are ignored
+    pub async fn update(&self, info_hash: &str, file_name: &str, update: impl FnOnce(&mut CloudFileProgress)) {
+        if let Some(progress) = self
+            .live
+            .write()
+            .await
+            .get_mut(info_hash)
+            .and_then(|torrent| torrent.files.get_mut(file_name))
+        {
+            update(progress);
+        }
+    }
+
+    pub async fn set_state(&self, info_hash: &str, file_name: &str, state: CloudFileState) {
+        self.update(info_hash, file_name, |progress| progress.state = state).await;
+    }
+
+    /// Current state of each of a torrent's live files
+    pub async fn states(&self, info_hash: &str) -> Vec<CloudFileState> {
+        self.live
+            .read()
+            .await
+            .get(info_hash)
+            .map(|torrent| torrent.files.values().map(|p| p.state).collect())
+            .unwrap_or_default()
+    }
+
+    /// The download task is done with a torrent: store its file summary and
+    /// start the grace period
+    pub async fn finish(&self, info_hash: &str) {
+        let mut live = self.live.write().await;
+        let Some(torrent) = live.get_mut(info_hash) else {
+            return;
+        };
+        let mut summary: Vec<CloudFileSummary> = torrent.files.values().map(Into::into).collect();
+        summary.sort_by(|a, b| a.name.cmp(&b.name));
+        if let Err(e) = self.database.save_cloud_files(info_hash, &summary) {
+            tracing::warn!("Failed to save cloud file states of {}: {}", info_hash, e);
+        }
+        torrent.finished_at = Some(Instant::now());
+        collapse(&mut live, Instant::now());
+    }
+
+    /// A torrent's files, sorted by name: live while it is tracked, the stored
+    /// summary after that
+    pub async fn files(&self, info_hash: &str) -> Vec<CloudFileProgress> {
+        let mut files: Vec<CloudFileProgress> = {
+            let mut live = self.live.write().await;
+            collapse(&mut live, Instant::now());
+            match live.get(info_hash) {
+                Some(torrent) => torrent.files.values().cloned().collect(),
+                None => match self.database.load_cloud_files(info_hash) {
+                    Ok(summary) => summary.unwrap_or_default().into_iter().map(Into::into).collect(),
+                    Err(e) => {
+                        tracing::warn!("Failed to load cloud file states of {}: {}", info_hash, e);
+                        Vec::new()
+                    }
+                },
+            }
+        };
+        files.sort_by(|a, b| a.name.cmp(&b.name));
+        files
+    }
+
+    /// Forget a removed torrent's live progress (the stored summary goes with
+    /// `Database::delete_torrent`)
+    pub async fn remove(&self, info_hash: &str) {
+        self.live.write().await.remove(info_hash);
+    }
+}
+
+/// Drop finished torrents past their grace period, then the oldest finished
+/// ones while over `MAX_LIVE_TORRENTS`
+fn collapse(live: &mut HashMap<String, TorrentProgress>, now: Instant) {
+    live.retain(|_, torrent| torrent.finished_at.map_or(true, |at| now.duration_since(at) < FINISHED_GRACE));
+    if live.len() <= MAX_LIVE_TORRENTS {
+        return;
+    }
+
+    let mut finished: Vec<(Instant, String)> = live
+        .iter()
+        .filter_map(|(hash, torrent)| torrent.finished_at.map(|at| (at, hash.clone())))
+        .collect();
+    finished.sort();
+    let excess = live.len() - MAX_LIVE_TORRENTS;
+    for (_, hash) in finished.into_iter().take(excess) {
+        live.remove(&hash);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hash(n: usize) -> String {
+        format!("{:040x}", n)
+    }
+
+    fn open(path: &std::path::Path) -> CloudProgress {
+        CloudProgress::new(Arc::new(Database::open(path).unwrap()))
+    }
+
+    #[tokio::test]
+    async fn test_finished_states_survive_restart() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let db_path = temp_dir.path().join("db");
+        let id = hash(1);
+        {
+            let progress = open(&db_path);
+            progress.start(&id, [("b.mkv".to_string(), 200), ("a.nfo".to_string(), 10)]).await;
+            progress.update(&id, "b.mkv", |p| {
+                p.downloaded = 200;
+                p.state = CloudFileState::Complete;
+            }).await;
+            progress.set_state(&id, "a.nfo", CloudFileState::Error).await;
+            progress.finish(&id).await;
+            assert_eq!(progress.files(&id).await[1].downloaded, 200);
+        }
+
+        let progress = open(&db_path);
+        let files = progress.files(&id).await;
+        let states: Vec<_> = files.iter().map(|f| (f.name.as_str(), f.size, f.state)).collect();
+        assert_eq!(
+            states,
+            vec![("a.nfo", 10, CloudFileState::Error), ("b.mkv", 200, CloudFileState::Complete)]
+        );
+        assert_eq!((files[0].downloaded, files[1].downloaded), (0, 200));
+        assert!(progress.files(&hash(2)).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_collapse_keeps_running_torrents() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let progress = open(&temp_dir.path().join("db"));
+
+        let running = hash(0);
+        progress.start(&running, [("file".to_string(), 1)]).await;
+        for n in 1..=MAX_LIVE_TORRENTS + 5 {
+            progress.start(&hash(n), [("file".to_string(), 1)]).await;
+            progress.finish(&hash(n)).await;
+        }
+        {
+            let live = progress.live.read().await;
+            assert_eq!(live.len(), MAX_LIVE_TORRENTS);
+            assert!(live.contains_key(&running));
+            // The oldest finished went first
+            assert!(!live.contains_key(&hash(1)));
+            assert!(live.contains_key(&hash(MAX_LIVE_TORRENTS + 5)));
+        }
+        // Still answered from the database
+        assert_eq!(progress.files(&hash(1)).await.len(), 1);
+
+        // Past the grace period only the running one is left
+        let mut live = progress.live.write().await;
+        collapse(&mut live, Instant::now() + FINISHED_GRACE);
+        assert_eq!(live.keys().collect::<Vec<_>>(), vec![&running]);
+    }
+}
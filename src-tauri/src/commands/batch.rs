@@ -0,0 +1,391 @@
+//! Batch add: many .torrent files / magnets / URLs with one set of options
+
+use super::torrent::{
+    new_p2p_torrent, parse_magnet, parse_torrent_bytes, read_torrent_file, register_torrent,
+    stash_torrent_file, start_torrent_internal, NewTorrent,
+};
+use crate::piece::Bitfield;
+use crate::provenance::AddedFrom;
+use crate::state::AppState;
+use crate::torrent::Metainfo;
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::time::Duration;
+use tauri::State;
+
+/// Maximum items parsed or added at the same time
+const BATCH_PARALLELISM: usize = 8;
+
+/// Largest .torrent accepted from a URL
+const MAX_TORRENT_URL_SIZE: u64 = 10 * 1024 * 1024;
+
+/// Timeout for fetching a .torrent from a URL
+const TORRENT_URL_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// One thing to add
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "kind", content = "value", rename_all = "snake_case")]
+pub enum AddItem {
+    /// Path to a .torrent file
+    File(String),
+    /// Raw .torrent contents
+    Bytes(Vec<u8>),
+    /// magnet: URI
+    Magnet(String),
+    /// http(s) URL of a .torrent (magnet: URIs are accepted too)
+    Url(String),
+}
+
+/// Options chosen once in the add dialog and applied to every item
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct BatchAddOptions {
+    /// Download directory (defaults to the configured one)
+    pub download_dir: Option<String>,
+    /// Category stored with each torrent
+    pub category: Option<String>,
+    /// Leave the torrents paused after adding
+    pub paused: bool,
+    /// Trust existing data as complete instead of downloading it again
+    pub skip_hash_check: bool,
+    /// Storage mode for every torrent (by default each gets the suggested one)
+    pub storage_mode: Option<crate::disk::StorageMode>,
+}
+
+impl Default for BatchAddOptions {
+    fn default() -> Self {
+        Self {
+            download_dir: None,
+            category: None,
+            paused: true,
+            skip_hash_check: false,
+            storage_mode: None,
+        }
+    }
+}
+
+/// Outcome for one item, in the same order as the request
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum AddItemResult {
+    Added { id: String },
+    /// Already present (in the session list or earlier in this batch)
+    Duplicate { of: String },
+    Error { message: String },
+}
+
+/// Add many torrents at once with shared options
+#[tauri::command]
+pub async fn add_torrents_batch(
+    app: tauri::AppHandle,
+    state: State<'_, AppState>,
+    items: Vec<AddItem>,
+    shared_options: BatchAddOptions,
+) -> Result<Vec<AddItemResult>, String> {
+    add_torrents_batch_internal(Some(app), &state, items, shared_options).await
+}
+
+pub async fn add_torrents_batch_internal(
+    app: Option<tauri::AppHandle>,
+    state: &AppState,
+    items: Vec<AddItem>,
+    options: BatchAddOptions,
+) -> Result<Vec<AddItemResult>, String> {
+    tracing::info!("Adding batch of {} torrents", items.len());
+
+    let download_dir = match &options.download_dir {
+        Some(dir) => PathBuf::from(dir),
+        None => {
+            let db_settings = state.database
+                .load_settings()
+                .map_err(|e| format!("Failed to load settings: {}", e))?;
+            PathBuf::from(db_settings.download_dir)
+        }
+    };
+
+    // 1. Parse everything first; a bad item only fails itself
+    let anonymous = *state.anonymous_mode.borrow();
+    let mut parsed: Vec<(usize, Result<ParsedItem, String>)> =
+        futures::stream::iter(items.into_iter().enumerate())
+            .map(|(index, item)| async move { (index, parse_item(item, anonymous).await) })
+            .buffer_unordered(BATCH_PARALLELISM)
+            .collect()
+            .await;
+    parsed.sort_by_key(|(index, _)| *index);
+
+    // 2. Deduplicate against existing torrents and within the batch
+    let existing: HashSet<String> = state.torrents.read().await.keys().cloned().collect();
+    let mut seen = HashSet::new();
+    let mut results = Vec::with_capacity(parsed.len());
+    let mut to_add: Vec<(usize, NewTorrent)> = Vec::new();
+
+    for (index, item) in parsed {
+        let ParsedItem { metainfo, name, added_from, torrent_file } = match item {
+            Ok(parsed) => parsed,
+            Err(message) => {
+                results.push(AddItemResult::Error { message });
+                continue;
+            }
+        };
+
+        let torrent_id = metainfo.info_hash_hex();
+        if existing.contains(&torrent_id) || !seen.insert(torrent_id.clone()) {
+            results.push(AddItemResult::Duplicate { of: torrent_id });
+            continue;
+        }
+
+        let mut torrent = new_p2p_torrent(metainfo, name, &download_dir).with_source(added_from, torrent_file);
+        apply_options(&mut torrent, &options);
+        results.push(AddItemResult::Added { id: torrent_id });
+        to_add.push((index, torrent));
+    }
+
+    if to_add.is_empty() {
+        return Ok(results);
+    }
+
+    // 3. Persist all new sessions in one batch
+    let sessions: Vec<_> = to_add.iter().map(|(_, t)| t.session.clone()).collect();
+    if let Err(e) = state.database.save_torrents(&sessions) {
+        let message = format!("Failed to save torrent to database: {}", e);
+        for (index, _) in &to_add {
+            results[*index] = AddItemResult::Error { message: message.clone() };
+        }
+        return Ok(results);
+    }
+    for (_, torrent) in &to_add {
+        stash_torrent_file(state, torrent);
+    }
+
+    // 4. Create engines (and start them unless paused) with bounded parallelism
+    let paused = options.paused;
+    futures::stream::iter(to_add)
+        .for_each_concurrent(BATCH_PARALLELISM, |(_, torrent)| {
+            let app = app.clone();
+            async move {
+                let torrent_id = register_torrent(app, state, torrent).await;
+                if !paused {
+                    if let Err(e) = start_torrent_internal(state, torrent_id.clone(), false).await {
+                        tracing::warn!("Added {} but failed to start it: {}", torrent_id, e);
+                    }
+                }
+            }
+        })
+        .await;
+
+    let added = results.iter().filter(|r| matches!(r, AddItemResult::Added { .. })).count();
+    tracing::info!("Batch add finished: {} of {} added", added, results.len());
+
+    Ok(results)
+}
+
+/// A parsed item and where it came from
+struct ParsedItem {
+    metainfo: Metainfo,
+    name: String,
+    added_from: AddedFrom,
+    torrent_file: Option<Vec<u8>>,
+}
+
+/// Parse one item through the same helpers as the single-item commands
+async fn parse_item(item: AddItem, anonymous: bool) -> Result<ParsedItem, String> {
+    match item {
+        AddItem::File(path) => {
+            let (metainfo, data) = read_torrent_file(&path)?;
+            Ok(from_file(metainfo, AddedFrom::TorrentFile { original_path: Some(path) }, data))
+        }
+        AddItem::Bytes(data) => {
+            let metainfo = parse_torrent_bytes(&data)?;
+            Ok(from_file(metainfo, AddedFrom::TorrentFile { original_path: None }, data))
+        }
+        AddItem::Magnet(uri) => from_magnet(&uri, anonymous),
+        AddItem::Url(url) if url.starts_with("magnet:") => from_magnet(&url, anonymous),
+        AddItem::Url(url) => {
+            let data = fetch_torrent(&url).await?;
+            let metainfo = parse_torrent_bytes(&data)?;
+            Ok(from_file(metainfo, AddedFrom::Url { url }, data))
+        }
+    }
+}
+
+fn from_file(metainfo: Metainfo, added_from: AddedFrom, data: Vec<u8>) -> ParsedItem {
+    let name = metainfo.info.name.clone();
+    ParsedItem { metainfo, name, added_from, torrent_file: Some(data) }
+}
+
+fn from_magnet(uri: &str, anonymous: bool) -> Result<ParsedItem, String> {
+    let (metainfo, name) = parse_magnet(uri)?;
+    Ok(ParsedItem { metainfo, name, added_from: AddedFrom::magnet(uri, anonymous), torrent_file: None })
+}
+
+/// Download a .torrent file over http(s)
+async fn fetch_torrent(url: &str) -> Result<Vec<u8>, String> {
+    if !(url.starts_with("http://") || url.starts_with("https://")) {
+        return Err(format!("Unsupported URL: {}", url));
+    }
+
+    let client = reqwest::Client::builder()
+        .timeout(TORRENT_URL_TIMEOUT)
+        .build()
+        .map_err(|e| format!("Failed to create HTTP client: {}", e))?;
+
+    let response = client
+        .get(url)
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .map_err(|e| format!("Failed to download torrent: {}", e))?;
+
+    if response.content_length().unwrap_or(0) > MAX_TORRENT_URL_SIZE {
+        return Err("Torrent file too large".to_string());
+    }
+
+    let data = response
+        .bytes()
+        .await
+        .map_err(|e| format!("Failed to download torrent: {}", e))?;
+    if data.len() as u64 > MAX_TORRENT_URL_SIZE {
+        return Err("Torrent file too large".to_string());
+    }
+
+    Ok(data.to_vec())
+}
+
+/// Apply the shared dialog options to a freshly built torrent
+fn apply_options(torrent: &mut NewTorrent, options: &BatchAddOptions) {
+    torrent.session.category = options.category.clone();
+    if let Some(storage_mode) = options.storage_mode {
+        torrent.session.storage_mode = storage_mode;
+    }
+
+    // Magnets have no piece hashes yet, so there is nothing to mark complete
+    let num_pieces = torrent.session.num_pieces;
+    if options.skip_hash_check && num_pieces > 0 {
+        let total_size = torrent.session.metainfo.info.total_size;
+        torrent.session.bitfield = Bitfield::complete(num_pieces).as_bytes().to_vec();
+        torrent.session.downloaded = total_size;
+        torrent.session.completed_at = Some(chrono::Utc::now().timestamp());
+        torrent.info.downloaded = total_size;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::Database;
+
+    const MAGNET: &str = "magnet:?xt=urn:btih:0123456789abcdef0123456789abcdef01234567&dn=Magnet%20Item";
+
+    fn torrent_bytes(name: &str) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(b"d8:announce14:http://tracker4:infod6:lengthi1234e");
+        data.extend_from_slice(format!("4:name{}:{}", name.len(), name).as_bytes());
+        data.extend_from_slice(b"12:piece_lengthi16384e6:pieces20:12345678901234567890ee");
+        data
+    }
+
+    #[tokio::test]
+    async fn test_batch_add_outcomes() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let state = AppState::with_database(Database::open(temp_dir.path().join("db")).unwrap());
+
+        let corrupt = temp_dir.path().join("corrupt.torrent");
+        std::fs::write(&corrupt, b"not bencode").unwrap();
+
+        let valid = torrent_bytes("valid.iso");
+        let valid_id = Metainfo::from_bytes(&valid).unwrap().info_hash_hex();
+
+        let items = vec![
+            AddItem::Bytes(valid.clone()),
+            AddItem::Bytes(valid),
+            AddItem::File(corrupt.to_string_lossy().to_string()),
+            AddItem::Magnet(MAGNET.to_string()),
+        ];
+        let options = BatchAddOptions {
+            download_dir: Some(temp_dir.path().to_string_lossy().to_string()),
+            category: Some("linux".to_string()),
+            ..BatchAddOptions::default()
+        };
+
+        let results = add_torrents_batch_internal(None, &state, items, options).await.unwrap();
+
+        assert_eq!(results.len(), 4);
+        assert_eq!(results[0], AddItemResult::Added { id: valid_id.clone() });
+        assert_eq!(results[1], AddItemResult::Duplicate { of: valid_id });
+        assert!(matches!(&results[2], AddItemResult::Error { message } if message.starts_with("Failed to parse torrent")));
+        assert_eq!(
+            results[3],
+            AddItemResult::Added { id: "0123456789abcdef0123456789abcdef01234567".to_string() }
+        );
+
+        assert_eq!(state.engines.read().await.len(), 2);
+        assert_eq!(state.torrents.read().await.len(), 2);
+        let sessions = state.database.load_all_torrents().unwrap();
+        assert_eq!(sessions.len(), 2);
+        assert!(sessions.iter().all(|s| s.category.as_deref() == Some("linux")));
+        assert!(state.engine_tasks.read().await.is_empty(), "paused batch must not start engines");
+    }
+
+    #[tokio::test]
+    async fn test_batch_add_records_provenance() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let state = AppState::with_database(Database::open(temp_dir.path().join("db")).unwrap());
+        state.anonymous_mode.send_replace(true);
+
+        let data = torrent_bytes("from-file.iso");
+        let file_id = Metainfo::from_bytes(&data).unwrap().info_hash_hex();
+        let path = temp_dir.path().join("from-file.torrent").to_string_lossy().to_string();
+        std::fs::write(&path, &data).unwrap();
+
+        let items = vec![AddItem::File(path.clone()), AddItem::Magnet(MAGNET.to_string())];
+        let options = BatchAddOptions {
+            download_dir: Some(temp_dir.path().to_string_lossy().to_string()),
+            ..BatchAddOptions::default()
+        };
+        add_torrents_batch_internal(None, &state, items, options).await.unwrap();
+
+        let file = state.database.load_torrent(&file_id).unwrap().unwrap();
+        assert_eq!(file.added_from, Some(AddedFrom::TorrentFile { original_path: Some(path) }));
+        assert_eq!(state.database.load_torrent_file(&file_id).unwrap(), Some(data));
+
+        // Anonymous mode keeps the display name out of the record
+        let magnet_id = "0123456789abcdef0123456789abcdef01234567";
+        let magnet = state.database.load_torrent(magnet_id).unwrap().unwrap();
+        assert_eq!(
+            magnet.added_from,
+            Some(AddedFrom::Magnet { uri: format!("magnet:?xt=urn:btih:{}", magnet_id) })
+        );
+        assert_eq!(state.database.load_torrent_file(magnet_id).unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_batch_add_skips_existing_torrents() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let state = AppState::with_database(Database::open(temp_dir.path().join("db")).unwrap());
+        let options = BatchAddOptions {
+            download_dir: Some(temp_dir.path().to_string_lossy().to_string()),
+            skip_hash_check: true,
+            ..BatchAddOptions::default()
+        };
+
+        let first = add_torrents_batch_internal(None, &state, vec![AddItem::Bytes(torrent_bytes("a"))], options.clone())
+            .await
+            .unwrap();
+        let AddItemResult::Added { id } = &first[0] else {
+            panic!("expected first add to succeed: {:?}", first);
+        };
+
+        // Skip hash check trusts the existing data as complete
+        let session = state.database.load_torrent(id).unwrap().unwrap();
+        assert_eq!(session.downloaded, 1234);
+        assert!(session.completed_at.is_some());
+
+        let second = add_torrents_batch_internal(None, &state, vec![AddItem::Bytes(torrent_bytes("a"))], options)
+            .await
+            .unwrap();
+        assert_eq!(second, vec![AddItemResult::Duplicate { of: id.clone() }]);
+        assert_eq!(state.engines.read().await.len(), 1);
+    }
+}
@@ -1,9 +1,8 @@
-//! Credential commands: master password and debrid API key management
+//! Credential commands: master password, debrid API keys and API tokens
 
 use crate::state::AppState;
 use crate::debrid::types::DebridProviderType;
 use crate::crypto::{self, CryptoManager};
-use std::collections::HashMap;
 use tauri::State;
 
 /// Check if master password is set
@@ -52,12 +51,18 @@ pub async fn set_master_password(
     Ok(())
 }
 
-/// Unlock debrid services with master password
+/// Unlock debrid services with master password. Once unlocked, every
+/// stored credential is test-decrypted and its health reported, so the UI
+/// can ask for just the keys that need entering again.
 #[tauri::command]
 pub async fn unlock_with_master_password(
     password: String,
     state: State<'_, AppState>,
-) -> Result<bool, String> {
+) -> Result<super::UnlockResult, String> {
+    unlock_internal(&state, password).await
+}
+
+pub async fn unlock_internal(state: &AppState, password: String) -> Result<super::UnlockResult, String> {
     tracing::info!("Attempting to unlock with master password");
 
     // Load password data
@@ -70,16 +75,77 @@ pub async fn unlock_with_master_password(
     let is_valid = crypto::verify_master_password(&password, &password_data.password_hash)
         .map_err(|e| format!("Failed to verify password: {}", e))?;
 
-    if is_valid {
-        // Cache password in memory
-        let mut cached_password = state.master_password.write().await;
-        *cached_password = Some(password);
-
-        tracing::info!("Master password verified and cached");
-        Ok(true)
-    } else {
+    if !is_valid {
         tracing::warn!("Invalid master password attempt");
-        Ok(false)
+        return Ok(super::UnlockResult { unlocked: false, credentials: Vec::new() });
+    }
+
+    let credentials = check_credentials(state, &password, &password_data)?;
+
+    // Cache password in memory
+    let mut cached_password = state.master_password.write().await;
+    *cached_password = Some(password);
+    drop(cached_password);
+
+    tracing::info!("Master password verified and cached");
+    load_debrid_providers(state).await;
+    Ok(super::UnlockResult { unlocked: true, credentials })
+}
+
+/// Self-test the cipher derived from `password`, then try to decrypt every
+/// stored credential with it
+fn check_credentials(
+    state: &AppState,
+    password: &str,
+    password_data: &crate::database::MasterPasswordData,
+) -> Result<Vec<super::CredentialCheck>, String> {
+    let crypto_manager = CryptoManager::from_password(password, &password_data.salt)
+        .map_err(|e| format!("Failed to create crypto manager: {}", e))?;
+    crypto_manager
+        .self_test()
+        .map_err(|e| format!("Encryption self-test failed: {}", e))?;
+    let fingerprint = crypto::salt_fingerprint(&password_data.salt);
+
+    let credentials = state.database
+        .load_all_debrid_credentials()
+        .map_err(|e| format!("Failed to load credentials: {}", e))?;
+    Ok(credentials
+        .iter()
+        .map(|cred| {
+            let health = match decrypt_api_key(&crypto_manager, &fingerprint, cred) {
+                Ok(_) => super::CredentialHealth::Ok,
+                Err(health) => {
+                    tracing::warn!("{}", broken_key_message(cred.provider, health));
+                    health
+                }
+            };
+            super::CredentialCheck { provider: cred.provider.as_str().to_string(), health }
+        })
+        .collect())
+}
+
+/// Decrypt a stored API key. A key recorded as encrypted under another salt
+/// isn't even tried.
+fn decrypt_api_key(
+    crypto_manager: &CryptoManager,
+    salt_fingerprint: &str,
+    cred: &crate::database::DebridCredentials,
+) -> Result<String, super::CredentialHealth> {
+    if cred.salt_fingerprint.as_deref().is_some_and(|stored| stored != salt_fingerprint) {
+        return Err(super::CredentialHealth::SaltMismatch);
+    }
+    crypto_manager
+        .decrypt(&cred.api_key_encrypted, &cred.nonce)
+        .map_err(|_| super::CredentialHealth::DecryptFailed)
+}
+
+fn broken_key_message(provider: DebridProviderType, health: super::CredentialHealth) -> String {
+    match health {
+        super::CredentialHealth::SaltMismatch => format!(
+            "The saved {} API key was encrypted under a different master password; please enter it again",
+            provider.as_str()
+        ),
+        _ => format!("The saved {} API key can't be decrypted; please enter it again", provider.as_str()),
     }
 }
 
@@ -89,6 +155,16 @@ pub async fn change_master_password(
     old_password: String,
     new_password: String,
     state: State<'_, AppState>,
+) -> Result<(), String> {
+    change_master_password_internal(&state, old_password, new_password).await
+}
+
+/// Re-encrypt every credential under the new password, then save them and
+/// the new password data in one transaction
+pub async fn change_master_password_internal(
+    state: &AppState,
+    old_password: String,
+    new_password: String,
 ) -> Result<(), String> {
     tracing::info!("Attempting to change master password");
 
@@ -111,14 +187,17 @@ pub async fn change_master_password(
         .load_all_debrid_credentials()
         .map_err(|e| format!("Failed to load credentials: {}", e))?;
 
-    // Decrypt all API keys with old password
-    let mut decrypted_keys: HashMap<DebridProviderType, String> = HashMap::new();
+    // Decrypt all API keys with old password; a broken one stops the
+    // change before anything is written
+    let old_crypto = CryptoManager::from_password(&old_password, &password_data.salt)
+        .map_err(|e| format!("Failed to create crypto manager: {}", e))?;
+    let old_fingerprint = crypto::salt_fingerprint(&password_data.salt);
+    let mut decrypted_keys = Vec::with_capacity(old_credentials.len());
     for cred in old_credentials {
-        let old_crypto = CryptoManager::from_password(&old_password, &password_data.salt)
-            .map_err(|e| format!("Failed to create crypto manager: {}", e))?;
-        let api_key = old_crypto.decrypt(&cred.api_key_encrypted, &cred.nonce)
-            .map_err(|e| format!("Failed to decrypt credentials for {}: {}", cred.provider.as_str(), e))?;
-        decrypted_keys.insert(cred.provider, api_key);
+        let api_key = decrypt_api_key(&old_crypto, &old_fingerprint, &cred).map_err(|health| {
+            format!("{} (or delete it) before changing the master password", broken_key_message(cred.provider, health))
+        })?;
+        decrypted_keys.push((cred, api_key));
     }
 
     // Create new password hash
@@ -129,23 +208,22 @@ pub async fn change_master_password(
     // Re-encrypt all API keys with new password
     let new_crypto = CryptoManager::from_password(&new_password, &new_salt)
         .map_err(|e| format!("Failed to create crypto manager: {}", e))?;
+    new_crypto
+        .self_test()
+        .map_err(|e| format!("Encryption self-test failed: {}", e))?;
+    let new_fingerprint = crypto::salt_fingerprint(&new_salt);
 
-    for (provider, api_key) in decrypted_keys {
+    let mut new_credentials = Vec::with_capacity(decrypted_keys.len());
+    for (cred, api_key) in decrypted_keys {
         let (encrypted_api_key, nonce) = new_crypto.encrypt(&api_key)
-            .map_err(|e| format!("Failed to encrypt credentials for {}: {}", provider.as_str(), e))?;
+            .map_err(|e| format!("Failed to encrypt credentials for {}: {}", cred.provider.as_str(), e))?;
 
-        let new_cred = crate::database::DebridCredentials {
-            provider,
+        new_credentials.push(crate::database::DebridCredentials {
             api_key_encrypted: encrypted_api_key,
             nonce,
-            created_at: chrono::Utc::now().timestamp(),
-            last_validated: 0,
-            is_valid: false,
-        };
-
-        state.database
-            .save_debrid_credentials(&new_cred)
-            .map_err(|e| format!("Failed to save re-encrypted credentials: {}", e))?;
+            salt_fingerprint: Some(new_fingerprint.clone()),
+            ..cred
+        });
     }
 
     // Save new password
@@ -155,7 +233,7 @@ pub async fn change_master_password(
     };
 
     state.database
-        .save_master_password(&new_password_data)
+        .replace_master_password(&new_password_data, &new_credentials)
         .map_err(|e| format!("Failed to save new password: {}", e))?;
 
     // Update cached password
@@ -177,6 +255,64 @@ pub async fn lock_debrid_services(state: State<'_, AppState>) -> Result<(), Stri
     Ok(())
 }
 
+/// Set up the providers whose credentials are saved, once the master
+/// password is unlocked. Skipped entirely while debrid is disabled, so
+/// nothing is decrypted or sent to a provider. Returns how many were loaded.
+pub(crate) async fn load_debrid_providers(state: &AppState) -> usize {
+    if !state.debrid_manager.read().await.is_enabled() {
+        tracing::debug!("Debrid is disabled, not loading provider credentials");
+        return 0;
+    }
+    let Some(master_password) = state.master_password.read().await.clone() else {
+        return 0;
+    };
+    let credentials = match state.database.load_all_debrid_credentials() {
+        Ok(credentials) => credentials,
+        Err(e) => {
+            tracing::error!("Failed to load debrid credentials: {}", e);
+            return 0;
+        }
+    };
+    if credentials.is_empty() {
+        return 0;
+    }
+    let password_data = match state.database.load_master_password() {
+        Ok(Some(password_data)) => password_data,
+        Ok(None) => return 0,
+        Err(e) => {
+            tracing::error!("Failed to load password data: {}", e);
+            return 0;
+        }
+    };
+    let crypto_manager = match CryptoManager::from_password(&master_password, &password_data.salt) {
+        Ok(crypto_manager) => crypto_manager,
+        Err(e) => {
+            tracing::error!("Failed to create crypto manager: {}", e);
+            return 0;
+        }
+    };
+
+    let fingerprint = crypto::salt_fingerprint(&password_data.salt);
+
+    let mut debrid_manager = state.debrid_manager.write().await;
+    let mut loaded = 0;
+    for cred in credentials {
+        let api_key = match decrypt_api_key(&crypto_manager, &fingerprint, &cred) {
+            Ok(api_key) => api_key,
+            Err(health) => {
+                tracing::error!("{}", broken_key_message(cred.provider, health));
+                continue;
+            }
+        };
+        match debrid_manager.initialize_provider(cred.provider, api_key).await {
+            Ok(()) => loaded += 1,
+            Err(e) => tracing::error!("Failed to initialize {}: {}", cred.provider.as_str(), e),
+        }
+    }
+    tracing::info!("Loaded {} debrid provider(s)", loaded);
+    loaded
+}
+
 /// Save debrid provider credentials
 #[tauri::command]
 pub async fn save_debrid_credentials(
@@ -184,6 +320,7 @@ pub async fn save_debrid_credentials(
     api_key: String,
     state: State<'_, AppState>,
 ) -> Result<(), String> {
+    super::require_debrid(&state).await?;
     tracing::info!("Saving credentials for provider: {}", provider);
 
     let provider_type = super::parse_provider(&provider)?;
@@ -215,6 +352,7 @@ pub async fn save_debrid_credentials(
         created_at: chrono::Utc::now().timestamp(),
         last_validated: 0,
         is_valid: false,
+        salt_fingerprint: Some(crypto::salt_fingerprint(&password_data.salt)),
     };
 
     // Save to database
@@ -287,6 +425,7 @@ pub async fn validate_debrid_provider(
     provider: String,
     state: State<'_, AppState>,
 ) -> Result<bool, String> {
+    super::require_debrid(&state).await?;
     tracing::info!("Validating credentials for provider: {}", provider);
 
     let provider_type = super::parse_provider(&provider)?;
@@ -317,8 +456,8 @@ pub async fn validate_debrid_provider(
     // Decrypt API key
     let crypto_manager = CryptoManager::from_password(master_password, &password_data.salt)
         .map_err(|e| format!("Failed to create crypto manager: {}", e))?;
-    let api_key = crypto_manager.decrypt(&credentials.api_key_encrypted, &credentials.nonce)
-        .map_err(|e| format!("Failed to decrypt API key: {}", e))?;
+    let api_key = decrypt_api_key(&crypto_manager, &crypto::salt_fingerprint(&password_data.salt), &credentials)
+        .map_err(|health| broken_key_message(provider_type, health))?;
 
     tracing::debug!("Validating {} with API (first 10 chars: {}...)", provider, &api_key.chars().take(10).collect::<String>());
 
@@ -347,3 +486,150 @@ pub async fn validate_debrid_provider(
 
     Ok(is_valid)
 }
+
+/// Create a scoped API token for a helper tool. The token is only ever
+/// returned here; afterwards just its label and scopes can be listed.
+#[tauri::command]
+pub async fn create_api_token(
+    label: String,
+    scopes: Vec<crate::api_tokens::TokenScope>,
+    expires_days: Option<u32>,
+    state: State<'_, AppState>,
+) -> Result<crate::api_tokens::CreatedApiToken, String> {
+    let now = chrono::Utc::now().timestamp();
+    crate::api_tokens::create_token(&state.database, &label, scopes, expires_days, now)
+        .map_err(|e| format!("Failed to create API token: {}", e))
+}
+
+/// List API tokens (without their secrets)
+#[tauri::command]
+pub async fn list_api_tokens(
+    state: State<'_, AppState>,
+) -> Result<Vec<crate::api_tokens::ApiTokenInfo>, String> {
+    crate::api_tokens::list_tokens(&state.database)
+        .map_err(|e| format!("Failed to list API tokens: {}", e))
+}
+
+/// Revoke an API token; requests using it fail from now on
+#[tauri::command]
+pub async fn revoke_api_token(
+    id: String,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    match crate::api_tokens::revoke_token(&state.database, &id) {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(format!("API token not found: {}", id)),
+        Err(e) => Err(format!("Failed to revoke API token: {}", e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::CredentialHealth;
+    use crate::database::{Database, DebridCredentials, MasterPasswordData};
+
+    async fn state(temp_dir: &tempfile::TempDir) -> AppState {
+        let state = AppState::with_database(Database::open(temp_dir.path().join("db")).unwrap());
+        // No provider setup (and no network) on unlock
+        state.debrid_manager.write().await.set_enabled(false);
+        state
+    }
+
+    fn set_password(state: &AppState, password: &str) -> Vec<u8> {
+        let salt = crypto::generate_salt();
+        let password_hash = crypto::hash_master_password(password, &salt).unwrap();
+        state.database.save_master_password(&MasterPasswordData { password_hash, salt: salt.clone() }).unwrap();
+        salt
+    }
+
+    fn save_key(state: &AppState, provider: DebridProviderType, api_key: &str, password: &str, salt: &[u8], marked: bool) {
+        let (api_key_encrypted, nonce) = CryptoManager::from_password(password, salt).unwrap().encrypt(api_key).unwrap();
+        let credentials = DebridCredentials {
+            provider,
+            api_key_encrypted,
+            nonce,
+            created_at: 1,
+            last_validated: 2,
+            is_valid: true,
+            salt_fingerprint: marked.then(|| crypto::salt_fingerprint(salt)),
+        };
+        state.database.save_debrid_credentials(&credentials).unwrap();
+    }
+
+    fn health(result: &crate::commands::UnlockResult) -> Vec<(String, CredentialHealth)> {
+        let mut health: Vec<_> = result.credentials.iter().map(|check| (check.provider.clone(), check.health)).collect();
+        health.sort_by(|a, b| a.0.cmp(&b.0));
+        health
+    }
+
+    #[tokio::test]
+    async fn test_unlock_reports_credentials_from_another_salt() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let state = state(&temp_dir).await;
+        let salt = set_password(&state, "current");
+        save_key(&state, DebridProviderType::Torbox, "torbox-key", "current", &salt, true);
+        // Left behind by a password change that died halfway
+        save_key(&state, DebridProviderType::RealDebrid, "rd-key", "older", &crypto::generate_salt(), true);
+
+        let result = unlock_internal(&state, "wrong".to_string()).await.unwrap();
+        assert!(!result.unlocked && result.credentials.is_empty());
+        assert!(state.master_password.read().await.is_none());
+
+        let result = unlock_internal(&state, "current".to_string()).await.unwrap();
+        assert!(result.unlocked);
+        assert_eq!(
+            health(&result),
+            [
+                ("real-debrid".to_string(), CredentialHealth::SaltMismatch),
+                ("torbox".to_string(), CredentialHealth::Ok),
+            ]
+        );
+
+        // Without a recorded salt the mismatch still shows, as a failed decryption
+        save_key(&state, DebridProviderType::RealDebrid, "rd-key", "older", &crypto::generate_salt(), false);
+        let result = unlock_internal(&state, "current".to_string()).await.unwrap();
+        assert_eq!(health(&result)[0], ("real-debrid".to_string(), CredentialHealth::DecryptFailed));
+
+        // Changing the password refuses to carry the broken key over, and
+        // leaves everything as it was
+        let err = change_master_password_internal(&state, "current".to_string(), "new".to_string()).await.unwrap_err();
+        assert!(err.contains("real-debrid"), "{}", err);
+        let password_data = state.database.load_master_password().unwrap().unwrap();
+        assert_eq!(password_data.salt, salt);
+        assert!(crypto::verify_master_password("current", &password_data.password_hash).unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_change_master_password_rewrites_everything() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let state = state(&temp_dir).await;
+        let salt = set_password(&state, "old");
+        save_key(&state, DebridProviderType::Torbox, "torbox-key", "old", &salt, true);
+        save_key(&state, DebridProviderType::RealDebrid, "rd-key", "old", &salt, false);
+
+        let err = change_master_password_internal(&state, "bad".to_string(), "new".to_string()).await.unwrap_err();
+        assert_eq!(err, "Invalid old password");
+        change_master_password_internal(&state, "old".to_string(), "new".to_string()).await.unwrap();
+
+        let password_data = state.database.load_master_password().unwrap().unwrap();
+        assert_ne!(password_data.salt, salt);
+        let fingerprint = crypto::salt_fingerprint(&password_data.salt);
+        let new_crypto = CryptoManager::from_password("new", &password_data.salt).unwrap();
+        for cred in state.database.load_all_debrid_credentials().unwrap() {
+            assert_eq!(cred.salt_fingerprint.as_deref(), Some(fingerprint.as_str()));
+            assert_eq!((cred.created_at, cred.last_validated, cred.is_valid), (1, 2, true));
+            let expected = match cred.provider {
+                DebridProviderType::Torbox => "torbox-key",
+                DebridProviderType::RealDebrid => "rd-key",
+            };
+            assert_eq!(decrypt_api_key(&new_crypto, &fingerprint, &cred).unwrap(), expected);
+        }
+
+        *state.master_password.write().await = None;
+        let result = unlock_internal(&state, "new".to_string()).await.unwrap();
+        assert!(result.unlocked);
+        assert!(result.credentials.iter().all(|check| check.health == CredentialHealth::Ok));
+        assert!(!unlock_internal(&state, "old".to_string()).await.unwrap().unlocked);
+    }
+}
@@ -1,46 +1,261 @@
 //! Debrid commands: cloud torrents, cache checking, debrid torrent management
 
 use crate::state::AppState;
-use crate::debrid::types::{CacheStatus, DebridFile, DebridProgress};
+use crate::events::{emit_event, Event, EventSink};
+use crate::debrid::types::{CacheStatus, DebridFile, DebridProgress, RemoteFileInfo};
 use std::path::PathBuf;
 use std::sync::Arc;
 use std::collections::HashMap;
 use tauri::State;
+use std::time::Duration;
+
+/// How often a finish-policy shutdown checks for cloud downloads still running
+const DISABLE_GRACE_POLL: Duration = Duration::from_secs(2);
+
+/// A torrent on its way to a debrid provider, with what we know about it locally
+pub struct CloudTorrent {
+    /// Canonical info hash (the state map key)
+    pub info_hash: String,
+    /// Name and size from a .torrent file; magnets get a placeholder name
+    pub name: Option<String>,
+    pub size: u64,
+    pub request: crate::debrid::AddTorrentRequest,
+}
+
+impl CloudTorrent {
+    /// From a magnet link or a bare info hash
+    pub fn from_magnet(magnet_or_hash: &str) -> Result<Self, String> {
+        // Resolve the canonical info hash up front (used as the state map key)
+        let info_hash = if magnet_or_hash.starts_with("magnet:") {
+            let magnet = crate::magnet::MagnetLink::parse(magnet_or_hash)
+                .map_err(|e| format!("Failed to parse magnet: {}", e))?;
+            magnet.info_hash_hex()
+        } else {
+            super::normalize_torrent_id(magnet_or_hash)?
+        };
+
+        // Convert to magnet URI if just hash
+        let magnet_uri = if magnet_or_hash.starts_with("magnet:") {
+            magnet_or_hash.to_string()
+        } else {
+            format!("magnet:?xt=urn:btih:{}", info_hash)
+        };
+
+        Ok(Self {
+            info_hash,
+            name: None,
+            size: 0,
+            request: crate::debrid::AddTorrentRequest::Magnet(magnet_uri),
+        })
+    }
+
+    /// From a .torrent file, which is uploaded to the provider as is
+    pub fn from_file(file_path: &str) -> Result<Self, String> {
+        let data = std::fs::read(file_path)
+            .map_err(|e| format!("Failed to read torrent file: {}", e))?;
+        let metainfo = crate::torrent::Metainfo::from_bytes(&data)
+            .map_err(|e| format!("Failed to parse torrent: {}", e))?;
+
+        Ok(Self {
+            info_hash: metainfo.info_hash_hex(),
+            name: Some(metainfo.info.name.clone()),
+            size: metainfo.info.total_size,
+            request: crate::debrid::AddTorrentRequest::File(PathBuf::from(file_path)),
+        })
+    }
+}
+
+/// Warning for when the provider reports a different info hash than the one
+/// we derived (the torrent would then be tracked under the wrong id)
+fn hash_mismatch(local: &str, reported: Option<&str>) -> Option<String> {
+    let reported = reported.filter(|hash| !hash.is_empty())?;
+    (!reported.eq_ignore_ascii_case(local)).then(|| {
+        format!(
+            "The provider reports info hash {} for the torrent added as {}",
+            reported.to_lowercase(),
+            local
+        )
+    })
+}
+
+/// Turn debrid features on or off for the running session. Switching them
+/// on loads the saved providers if the master password is unlocked.
+/// Switching them off tears the providers down, once the running cloud
+/// downloads have finished or after stopping them, depending on `policy`;
+/// the returned task waits for the downloads in the former case.
+pub(crate) async fn apply_debrid_enabled(
+    state: &AppState,
+    enabled: bool,
+    policy: crate::database::DebridDisablePolicy,
+) -> Option<tokio::task::JoinHandle<()>> {
+    let was_enabled = {
+        let mut debrid_manager = state.debrid_manager.write().await;
+        let was_enabled = debrid_manager.is_enabled();
+        debrid_manager.set_enabled(enabled);
+        was_enabled
+    };
+    if enabled == was_enabled {
+        return None;
+    }
+    if enabled {
+        tracing::info!("Debrid enabled");
+        super::load_debrid_providers(state).await;
+        return None;
+    }
+
+    match policy {
+        crate::database::DebridDisablePolicy::Abort => {
+            for info_hash in state.cloud_downloads.info_hashes().await {
+                tracing::info!("Debrid disabled, stopping cloud download {}", info_hash);
+                state.cloud_downloads.cancel(&info_hash).await;
+                if let Some(torrent) = state.torrents.write().await.get_mut(&info_hash) {
+                    torrent.state = crate::state::TorrentState::Paused;
+                }
+            }
+            state.debrid_manager.write().await.clear_providers();
+            tracing::info!("Debrid disabled, providers removed");
+            None
+        }
+        crate::database::DebridDisablePolicy::Finish => {
+            let cloud_downloads = Arc::clone(&state.cloud_downloads);
+            let debrid_manager = Arc::clone(&state.debrid_manager);
+            Some(tokio::spawn(async move {
+                while cloud_downloads.running().await > 0 {
+                    tokio::time::sleep(DISABLE_GRACE_POLL).await;
+                }
+                // Turned back on meanwhile: keep the providers
+                let mut debrid_manager = debrid_manager.write().await;
+                if !debrid_manager.is_enabled() {
+                    debrid_manager.clear_providers();
+                    tracing::info!("Debrid disabled and cloud downloads finished, providers removed");
+                }
+            }))
+        }
+    }
+}
 
 /// Add and download a torrent using cloud debrid service
 #[tauri::command]
 pub async fn add_cloud_torrent(
+    app: tauri::AppHandle,
     state: State<'_, AppState>,
     magnet_or_hash: String,
     provider: String,
     save_path: String,
+    delete_after_download: Option<bool>,
+    no_failover: Option<bool>,
+    hybrid: Option<bool>,
 ) -> Result<String, String> {
     tracing::info!("Adding cloud torrent via {}: {}", provider, magnet_or_hash);
 
-    let provider_type = super::parse_provider(&provider)?;
+    let torrent = CloudTorrent::from_magnet(&magnet_or_hash)?;
+    add_cloud_torrent_internal(
+        Some(app),
+        &state,
+        torrent,
+        &provider,
+        &save_path,
+        delete_after_download,
+        no_failover.unwrap_or(false),
+        hybrid.unwrap_or(false),
+    )
+    .await
+}
 
-    // Convert to magnet URI if just hash
-    let magnet_uri = if magnet_or_hash.starts_with("magnet:") {
-        magnet_or_hash.clone()
-    } else {
-        format!("magnet:?xt=urn:btih:{}", magnet_or_hash)
+/// Add a local .torrent file to a debrid service and download it from there
+#[tauri::command]
+pub async fn add_cloud_torrent_file(
+    app: tauri::AppHandle,
+    state: State<'_, AppState>,
+    file_path: String,
+    provider: String,
+    save_path: String,
+    delete_after_download: Option<bool>,
+    no_failover: Option<bool>,
+    hybrid: Option<bool>,
+) -> Result<String, String> {
+    tracing::info!("Adding cloud torrent file via {}: {}", provider, file_path);
+
+    let torrent = CloudTorrent::from_file(&file_path)?;
+    add_cloud_torrent_internal(
+        Some(app),
+        &state,
+        torrent,
+        &provider,
+        &save_path,
+        delete_after_download,
+        no_failover.unwrap_or(false),
+        hybrid.unwrap_or(false),
+    )
+    .await
+}
+
+/// Add `torrent` to `provider` and start downloading it from there. Unless
+/// `no_failover` is set, the download may move to the other configured
+/// providers, in preference order, if `provider` fails (see `FailoverPlan`).
+///
+/// A torrent already downloading over P2P is refused before anything reaches
+/// the provider, unless `hybrid` is set: then the cloud download joins it.
+#[allow(clippy::too_many_arguments)]
+pub async fn add_cloud_torrent_internal(
+    app: Option<tauri::AppHandle>,
+    state: &AppState,
+    torrent: CloudTorrent,
+    provider: &str,
+    save_path: &str,
+    delete_after_download: Option<bool>,
+    no_failover: bool,
+    hybrid: bool,
+) -> Result<String, String> {
+    super::require_debrid(state).await?;
+    let provider_type = super::parse_provider(provider)?;
+
+    // Don't queue a download onto a missing or unmounted drive
+    crate::disk::verify_download_dir(std::path::Path::new(save_path), None)?;
+
+    let CloudTorrent { info_hash, name, size, request } = torrent;
+    let (admission, _) =
+        super::admit_download(state, &info_hash, crate::download::Adding::Cloud, hybrid).await?;
+
+    let db_settings = state.database.load_settings().unwrap_or_default();
+    let ask_file_selection = db_settings.ask_before_selecting_cloud_files;
+
+    // Other providers are sent a magnet, also for torrents added from a file
+    let magnet = match &request {
+        crate::debrid::AddTorrentRequest::Magnet(magnet) => magnet.clone(),
+        crate::debrid::AddTorrentRequest::File(_) => format!("magnet:?xt=urn:btih:{}", info_hash),
     };
 
     // Add to debrid service
     let debrid_manager = state.debrid_manager.read().await;
-    let request = crate::debrid::AddTorrentRequest::Magnet(magnet_uri.clone());
+    let failover = crate::cloud::FailoverPlan {
+        candidates: debrid_manager.failover_candidates(provider_type),
+        magnet,
+        disabled: no_failover,
+    };
     let torrent_id_result = debrid_manager.add_to_cloud(provider_type, request)
         .await
         .map_err(|e| format!("Failed to add to debrid: {}", e))?;
 
     tracing::info!("Added to debrid service: {}", torrent_id_result.id);
+    let debrid_torrent_id = super::parse_debrid_id(&torrent_id_result.id)?;
 
     // For Real-Debrid, we need to check if file selection is required
     match debrid_manager.get_progress(provider_type, &torrent_id_result.id).await {
         Ok(progress) => {
             tracing::info!("Torrent status: {:?}", progress.status);
 
-            if matches!(progress.status, crate::debrid::types::DebridStatus::WaitingFilesSelection) {
+            if let Some(warning) = hash_mismatch(&info_hash, progress.info_hash.as_deref()) {
+                tracing::warn!("{}", warning);
+                if let Some(app) = &app {
+                    emit_event(app, Event::DebridWarning(warning));
+                }
+            }
+
+            // With "ask before selecting" on, the download task prompts the user instead
+            if !ask_file_selection
+                && matches!(progress.status, crate::debrid::types::DebridStatus::WaitingFilesSelection)
+            {
                 tracing::info!("Torrent waiting for file selection, selecting all files");
 
                 if let Err(e) = debrid_manager.select_files(provider_type, &torrent_id_result.id, &[]).await {
@@ -56,20 +271,11 @@ pub async fn add_cloud_torrent(
         }
     }
 
-    // Parse the magnet to get info hash
-    let info_hash = if magnet_or_hash.starts_with("magnet:") {
-        let magnet = crate::magnet::MagnetLink::parse(&magnet_or_hash)
-            .map_err(|e| format!("Failed to parse magnet: {}", e))?;
-        hex::encode(magnet.info_hash)
-    } else {
-        magnet_or_hash.clone()
-    };
-
     // Create a TorrentInfo entry for UI tracking
     let torrent_info = crate::state::TorrentInfo {
         id: info_hash.clone(),
-        name: format!("Cloud Download ({})", torrent_id_result.id),
-        size: 0,
+        name: name.unwrap_or_else(|| format!("Cloud Download ({})", torrent_id_result.id)),
+        size,
         downloaded: 0,
         uploaded: 0,
         state: crate::state::TorrentState::Downloading,
@@ -79,39 +285,81 @@ pub async fn add_cloud_torrent(
         seeds: 0,
         source: crate::debrid::types::DownloadSource::Debrid {
             provider: provider_type,
-            torrent_id: torrent_id_result.id.clone(),
+            torrent_id: debrid_torrent_id.clone(),
         },
+        remote_deleted: false,
+        swarm_seeds: None,
+        swarm_leechers: None,
+        swarm_updated_at: None,
+        queue_position: None,
+        metadata_pending: false,
+        name_encoding: None,
+        tags: Vec::new(),
+        error: None,
     };
 
-    // Store in torrents map
-    state.torrents.write().await.insert(info_hash.clone(), torrent_info);
+    if admission == crate::download::Admission::Join {
+        join_p2p_download(state, &info_hash, provider_type, debrid_torrent_id.clone()).await?;
+    } else {
+        state.torrents.write().await.insert(info_hash.clone(), torrent_info);
+    }
 
     // Drop the debrid_manager read lock before spawning the task
     drop(debrid_manager);
 
-    // Start background download task with cancellation support
-    let cancel_token = tokio_util::sync::CancellationToken::new();
-    crate::cloud::CloudDownloadManager::start_download_task(
-        info_hash.clone(),
-        torrent_id_result.id.clone(),
-        provider_type,
-        PathBuf::from(&save_path),
-        Arc::clone(&state.torrents),
-        Arc::clone(&state.debrid_manager),
-        Arc::clone(&state.cloud_file_progress),
-        cancel_token,
-    ).await;
+    // Per-add override falls back to the global setting
+    let delete_after_download =
+        delete_after_download.unwrap_or(db_settings.delete_from_provider_after_download);
+
+    let params = crate::cloud::CloudDownloadParams {
+        info_hash: info_hash.clone(),
+        debrid_torrent_id,
+        provider: provider_type,
+        save_path: PathBuf::from(save_path),
+        delete_after_download,
+        file_collision: db_settings.file_collision,
+        ask_file_selection,
+        failover,
+        events: app.map(|app| Arc::new(app) as EventSink),
+    };
+    state.cloud_downloads.start_download(params).await;
 
     tracing::info!("Cloud download task started for: {}", info_hash);
     Ok(info_hash)
 }
 
+/// Make the P2P torrent `info_hash` a hybrid with its copy on `provider`; the
+/// P2P side keeps its entry and session, only the source changes
+async fn join_p2p_download(
+    state: &AppState,
+    info_hash: &str,
+    provider: crate::debrid::types::DebridProviderType,
+    debrid_torrent_id: crate::ids::DebridTorrentId,
+) -> Result<(), String> {
+    tracing::info!("Joining the P2P download of {} from {}", info_hash, provider.display_name());
+    let source = crate::download::DownloadOrchestrator::hybrid_source(provider, debrid_torrent_id);
+
+    if let Some(mut session) = state.database.load_torrent(info_hash)
+        .map_err(|e| format!("Failed to load torrent: {}", e))?
+    {
+        session.source = source.clone();
+        state.database.save_torrent(&session)
+            .map_err(|e| format!("Failed to save torrent: {}", e))?;
+    }
+    if let Some(torrent) = state.torrents.write().await.get_mut(info_hash) {
+        torrent.source = source;
+    }
+    Ok(())
+}
+
 /// Check torrent cache status across all providers
 #[tauri::command]
 pub async fn check_torrent_cache(
     info_hash: String,
     state: State<'_, AppState>,
 ) -> Result<HashMap<String, CacheStatus>, String> {
+    super::require_debrid(&state).await?;
+    let info_hash = super::normalize_torrent_id(&info_hash)?;
     tracing::info!("Checking cache for info_hash: {}", info_hash);
 
     let debrid_manager = state.debrid_manager.read().await;
@@ -133,6 +381,8 @@ pub async fn get_preferred_cached_provider(
     info_hash: String,
     state: State<'_, AppState>,
 ) -> Result<Option<String>, String> {
+    super::require_debrid(&state).await?;
+    let info_hash = super::normalize_torrent_id(&info_hash)?;
     tracing::info!("Getting preferred cached provider for: {}", info_hash);
 
     let debrid_manager = state.debrid_manager.read().await;
@@ -143,6 +393,13 @@ pub async fn get_preferred_cached_provider(
     Ok(preferred.map(|p| p.as_str().to_string()))
 }
 
+/// Health of the debrid providers used so far (see `debrid::health`); the
+/// `debrid-provider-health` event reports changes
+#[tauri::command]
+pub async fn get_debrid_health(state: State<'_, AppState>) -> Result<Vec<crate::debrid::ProviderHealth>, String> {
+    Ok(state.debrid_manager.read().await.health().snapshot())
+}
+
 /// Add magnet link to debrid provider
 #[tauri::command]
 pub async fn add_magnet_to_debrid(
@@ -150,6 +407,7 @@ pub async fn add_magnet_to_debrid(
     provider: String,
     state: State<'_, AppState>,
 ) -> Result<String, String> {
+    super::require_debrid(&state).await?;
     tracing::info!("Adding magnet to {}", provider);
 
     let provider_type = super::parse_provider(&provider)?;
@@ -169,6 +427,7 @@ pub async fn add_torrent_file_to_debrid(
     provider: String,
     state: State<'_, AppState>,
 ) -> Result<String, String> {
+    super::require_debrid(&state).await?;
     tracing::info!("Adding torrent file to {}: {}", provider, file_path);
 
     let provider_type = super::parse_provider(&provider)?;
@@ -189,18 +448,40 @@ pub async fn select_debrid_files(
     file_indices: Vec<usize>,
     state: State<'_, AppState>,
 ) -> Result<(), String> {
+    super::require_debrid(&state).await?;
+    let torrent_id = super::parse_debrid_id(&torrent_id)?;
     tracing::info!("Selecting {} files in torrent {} on {}", file_indices.len(), torrent_id, provider);
 
     let provider_type = super::parse_provider(&provider)?;
 
     let debrid_manager = state.debrid_manager.read().await;
-    debrid_manager.select_files(provider_type, &torrent_id, &file_indices)
+    debrid_manager.select_files(provider_type, torrent_id.as_str(), &file_indices)
         .await
         .map_err(|e| format!("Failed to select files: {}", e))?;
 
+    // Let a download task that is waiting on this choice carry on
+    crate::cloud::finish_file_selection(&state.cloud_file_selections, torrent_id.as_str()).await;
+
     Ok(())
 }
 
+/// List the files of a torrent on a debrid provider for the selection dialog
+#[tauri::command]
+pub async fn get_debrid_torrent_files(
+    provider: String,
+    torrent_id: String,
+    state: State<'_, AppState>,
+) -> Result<Vec<RemoteFileInfo>, String> {
+    super::require_debrid(&state).await?;
+    let torrent_id = super::parse_debrid_id(&torrent_id)?;
+    let provider_type = super::parse_provider(&provider)?;
+
+    let debrid_manager = state.debrid_manager.read().await;
+    debrid_manager.get_torrent_files(provider_type, torrent_id.as_str())
+        .await
+        .map_err(|e| format!("Failed to list torrent files: {}", e))
+}
+
 /// Get download links for debrid torrent
 #[tauri::command]
 pub async fn get_debrid_download_links(
@@ -208,12 +489,14 @@ pub async fn get_debrid_download_links(
     provider: String,
     state: State<'_, AppState>,
 ) -> Result<Vec<DebridFile>, String> {
+    super::require_debrid(&state).await?;
+    let torrent_id = super::parse_debrid_id(&torrent_id)?;
     tracing::info!("Getting download links for torrent {} on {}", torrent_id, provider);
 
     let provider_type = super::parse_provider(&provider)?;
 
     let debrid_manager = state.debrid_manager.read().await;
-    let files = debrid_manager.get_download_links(provider_type, &torrent_id)
+    let files = debrid_manager.get_download_links(provider_type, torrent_id.as_str())
         .await
         .map_err(|e| format!("Failed to get download links: {}", e))?;
 
@@ -226,6 +509,7 @@ pub async fn list_debrid_torrents(
     provider: String,
     state: State<'_, AppState>,
 ) -> Result<Vec<DebridProgress>, String> {
+    super::require_debrid(&state).await?;
     tracing::info!("Listing torrents on {}", provider);
 
     let provider_type = super::parse_provider(&provider)?;
@@ -245,12 +529,14 @@ pub async fn delete_debrid_torrent(
     provider: String,
     state: State<'_, AppState>,
 ) -> Result<(), String> {
+    super::require_debrid(&state).await?;
+    let torrent_id = super::parse_debrid_id(&torrent_id)?;
     tracing::info!("Deleting torrent {} from {}", torrent_id, provider);
 
     let provider_type = super::parse_provider(&provider)?;
 
     let debrid_manager = state.debrid_manager.read().await;
-    debrid_manager.delete_torrent(provider_type, &torrent_id)
+    debrid_manager.delete_torrent(provider_type, torrent_id.as_str())
         .await
         .map_err(|e| format!("Failed to delete torrent: {}", e))?;
 
@@ -263,15 +549,325 @@ pub async fn get_cloud_file_progress(
     torrent_id: String,
     state: State<'_, AppState>,
 ) -> Result<Vec<crate::state::CloudFileProgress>, String> {
+    let torrent_id = super::normalize_torrent_id(&torrent_id)?;
     tracing::debug!("Getting cloud file progress for torrent: {}", torrent_id);
 
-    let progress_map = state.cloud_file_progress.read().await;
-    let files = progress_map.get(&torrent_id)
-        .map(|file_map| {
-            let mut files: Vec<_> = file_map.values().cloned().collect();
-            files.sort_by(|a, b| a.name.cmp(&b.name));
-            files
-        })
-        .unwrap_or_default();
-    Ok(files)
+    Ok(state.cloud_file_progress.files(&torrent_id).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::Database;
+    use crate::debrid::provider::DebridProvider;
+    use crate::debrid::types::*;
+    use anyhow::anyhow;
+
+    /// Real-Debrid stand-in that records adds and deletes and reports a fixed hash
+    #[derive(Default)]
+    struct MockProvider {
+        uploaded: std::sync::Mutex<Vec<Vec<u8>>>,
+        magnets: std::sync::Mutex<Vec<String>>,
+        deleted: std::sync::Mutex<Vec<String>>,
+        reported_hash: Option<String>,
+    }
+
+    #[async_trait::async_trait]
+    impl DebridProvider for MockProvider {
+        fn provider_type(&self) -> DebridProviderType {
+            DebridProviderType::RealDebrid
+        }
+        async fn validate_credentials(&self) -> anyhow::Result<bool> {
+            Ok(true)
+        }
+        async fn get_user_info(&self) -> anyhow::Result<UserInfo> {
+            Err(anyhow!("not mocked"))
+        }
+        async fn check_instant_availability(&self, _info_hash: &str) -> anyhow::Result<CacheStatus> {
+            Ok(CacheStatus::not_cached())
+        }
+        async fn add_magnet(&self, magnet_uri: &str) -> anyhow::Result<TorrentId> {
+            self.magnets.lock().unwrap().push(magnet_uri.to_string());
+            Ok(TorrentId { id: "RD123".to_string(), uri: None })
+        }
+        async fn add_torrent_file(&self, torrent_data: &[u8]) -> anyhow::Result<TorrentId> {
+            self.uploaded.lock().unwrap().push(torrent_data.to_vec());
+            Ok(TorrentId { id: "RD123".to_string(), uri: None })
+        }
+        async fn select_files(&self, _torrent_id: &str, _file_ids: Vec<usize>) -> anyhow::Result<()> {
+            Ok(())
+        }
+        async fn get_torrent_files(&self, _torrent_id: &str) -> anyhow::Result<Vec<RemoteFileInfo>> {
+            Ok(Vec::new())
+        }
+        async fn get_torrent_info(&self, torrent_id: &str) -> anyhow::Result<DebridProgress> {
+            Ok(DebridProgress {
+                torrent_id: torrent_id.to_string(),
+                status: DebridStatus::Downloading,
+                progress: 0.0,
+                speed: 0,
+                downloaded: 0,
+                total_size: 0,
+                seeders: None,
+                eta: None,
+                info_hash: self.reported_hash.clone(),
+            })
+        }
+        async fn get_download_links(&self, _torrent_id: &str) -> anyhow::Result<Vec<DebridFile>> {
+            Ok(Vec::new())
+        }
+        async fn unrestrict_link(&self, link: &str) -> anyhow::Result<String> {
+            Ok(link.to_string())
+        }
+        async fn delete_torrent(&self, torrent_id: &str) -> anyhow::Result<()> {
+            self.deleted.lock().unwrap().push(torrent_id.to_string());
+            Ok(())
+        }
+        async fn list_torrents(&self) -> anyhow::Result<Vec<DebridProgress>> {
+            Ok(Vec::new())
+        }
+    }
+
+    fn torrent_bytes() -> Vec<u8> {
+        b"d8:announce14:http://tracker4:infod6:lengthi1234e4:name9:cloud.iso12:piece lengthi16384e6:pieces20:12345678901234567890ee".to_vec()
+    }
+
+    #[tokio::test]
+    async fn test_add_cloud_torrent_file() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let state = AppState::with_database(Database::open(temp_dir.path().join("db")).unwrap());
+        let file = temp_dir.path().join("cloud.torrent");
+        std::fs::write(&file, torrent_bytes()).unwrap();
+        let expected_hash = crate::torrent::Metainfo::from_bytes(&torrent_bytes()).unwrap().info_hash_hex();
+
+        let provider = Arc::new(MockProvider {
+            reported_hash: Some(expected_hash.to_uppercase()),
+            ..Default::default()
+        });
+        state.debrid_manager.write().await.set_real_debrid(provider.clone());
+        let save_path = temp_dir.path().to_string_lossy().to_string();
+
+        // Debrid is off by default
+        let torrent = CloudTorrent::from_file(file.to_str().unwrap()).unwrap();
+        let err = add_cloud_torrent_internal(None, &state, torrent, "real-debrid", &save_path, Some(false), false, false)
+            .await
+            .unwrap_err();
+        assert_eq!(err, "Feature disabled: debrid");
+        assert!(provider.uploaded.lock().unwrap().is_empty());
+        state.debrid_manager.write().await.set_enabled(true);
+
+        let torrent = CloudTorrent::from_file(file.to_str().unwrap()).unwrap();
+        let id = add_cloud_torrent_internal(None, &state, torrent, "real-debrid", &save_path, Some(false), false, false)
+            .await
+            .unwrap();
+
+        assert_eq!(id, expected_hash);
+        assert_eq!(*provider.uploaded.lock().unwrap(), vec![torrent_bytes()]);
+
+        let torrents = state.torrents.read().await;
+        let info = &torrents[&id];
+        assert_eq!(info.name, "cloud.iso");
+        assert_eq!(info.size, 1234);
+        assert!(matches!(
+            &info.source,
+            DownloadSource::Debrid { provider: DebridProviderType::RealDebrid, torrent_id } if torrent_id.as_str() == "RD123"
+        ));
+    }
+
+    #[test]
+    fn test_hash_cross_check() {
+        let local = "0123456789abcdef0123456789abcdef01234567";
+        assert_eq!(hash_mismatch(local, None), None);
+        assert_eq!(hash_mismatch(local, Some("")), None);
+        assert_eq!(hash_mismatch(local, Some("0123456789ABCDEF0123456789ABCDEF01234567")), None);
+        let warning = hash_mismatch(local, Some("ffffffffffffffffffffffffffffffffffffffff")).unwrap();
+        assert!(warning.contains("ffffffffffffffffffffffffffffffffffffffff") && warning.contains(local));
+    }
+
+    #[test]
+    fn test_cloud_torrent_from_magnet_or_hash() {
+        let hash = "0123456789abcdef0123456789abcdef01234567";
+        let from_hash = CloudTorrent::from_magnet(&hash.to_uppercase()).unwrap();
+        assert_eq!(from_hash.info_hash, hash);
+        assert!(from_hash.name.is_none());
+        assert!(matches!(
+            from_hash.request,
+            crate::debrid::AddTorrentRequest::Magnet(ref uri) if uri == &format!("magnet:?xt=urn:btih:{}", hash)
+        ));
+        assert!(CloudTorrent::from_file("/nonexistent/file.torrent").is_err());
+    }
+
+    /// Debrid on with a provider set up, and a stand-in cloud download that
+    /// runs until `finish` fires or it is cancelled
+    async fn state_with_cloud_download(
+        temp_dir: &tempfile::TempDir,
+    ) -> (AppState, tokio::sync::oneshot::Sender<()>, tokio::task::AbortHandle) {
+        let state = AppState::with_database(Database::open(temp_dir.path().join("db")).unwrap());
+        state.debrid_manager.write().await.set_enabled(true);
+        state.debrid_manager.write().await.set_real_debrid(Arc::new(MockProvider::default()));
+
+        let (finish, finished) = tokio::sync::oneshot::channel::<()>();
+        let cancel = tokio_util::sync::CancellationToken::new();
+        let stopped = cancel.clone();
+        let handle = tokio::spawn(async move {
+            tokio::select! {
+                _ = finished => {}
+                _ = stopped.cancelled() => {}
+            }
+        });
+        let running = handle.abort_handle();
+        let params = crate::cloud::CloudDownloadParams {
+            info_hash: "cloud".to_string(),
+            debrid_torrent_id: crate::ids::DebridTorrentId::parse("RD1").unwrap(),
+            provider: DebridProviderType::RealDebrid,
+            save_path: temp_dir.path().to_path_buf(),
+            delete_after_download: false,
+            file_collision: crate::database::FileCollisionPolicy::Rename,
+            ask_file_selection: false,
+            failover: crate::cloud::FailoverPlan::none(),
+            events: None,
+        };
+        state.cloud_downloads.insert_task(params, crate::cloud::CloudTask { handle, cancel }).await;
+        (state, finish, running)
+    }
+
+    #[tokio::test]
+    async fn test_disable_debrid_aborting_downloads() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let (state, _finish, running) = state_with_cloud_download(&temp_dir).await;
+
+        let watcher = apply_debrid_enabled(&state, false, crate::database::DebridDisablePolicy::Abort).await;
+        assert!(watcher.is_none());
+        assert!(running.is_finished());
+        assert!(state.cloud_downloads.info_hashes().await.is_empty());
+        assert!(state.debrid_manager.read().await.configured_providers().is_empty());
+        assert_eq!(super::super::require_debrid(&state).await.unwrap_err(), "Feature disabled: debrid");
+    }
+
+    #[tokio::test]
+    async fn test_disable_debrid_letting_downloads_finish() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let (state, finish, running) = state_with_cloud_download(&temp_dir).await;
+
+        let watcher = apply_debrid_enabled(&state, false, crate::database::DebridDisablePolicy::Finish)
+            .await
+            .unwrap();
+        // New work is refused right away, but the download keeps its provider
+        assert!(super::super::require_debrid(&state).await.is_err());
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!running.is_finished());
+        assert!(state.debrid_manager.read().await.is_configured(DebridProviderType::RealDebrid));
+
+        finish.send(()).unwrap();
+        tokio::time::timeout(DISABLE_GRACE_POLL * 3, watcher).await.unwrap().unwrap();
+        assert!(state.debrid_manager.read().await.configured_providers().is_empty());
+        // Finished, not stopped
+        assert!(state.cloud_downloads.contains("cloud").await);
+    }
+
+    const HYBRID_HASH: &str = "0123456789abcdef0123456789abcdef01234567";
+
+    /// Debrid on with `provider` set up, downloading into `temp_dir`
+    async fn state_with_provider(temp_dir: &tempfile::TempDir, provider: Arc<MockProvider>) -> AppState {
+        let database = Database::open(temp_dir.path().join("db")).unwrap();
+        let mut settings = database.load_settings().unwrap();
+        settings.download_dir = temp_dir.path().to_string_lossy().to_string();
+        database.save_settings(&settings).unwrap();
+        let state = AppState::with_database(database);
+        state.debrid_manager.write().await.set_enabled(true);
+        state.debrid_manager.write().await.set_real_debrid(provider);
+        state
+    }
+
+    async fn add_p2p(state: &AppState, hybrid: bool) -> Result<String, String> {
+        let magnet = format!("magnet:?xt=urn:btih:{}", HYBRID_HASH);
+        crate::commands::add_magnet_link_internal(None, state, magnet, hybrid).await
+    }
+
+    async fn add_cloud(state: &AppState, temp_dir: &tempfile::TempDir, hybrid: bool) -> Result<String, String> {
+        let torrent = CloudTorrent::from_magnet(HYBRID_HASH).unwrap();
+        let save_path = temp_dir.path().to_string_lossy().to_string();
+        add_cloud_torrent_internal(None, state, torrent, "real-debrid", &save_path, Some(false), true, hybrid).await
+    }
+
+    async fn source_of(state: &AppState) -> DownloadSource {
+        state.torrents.read().await[HYBRID_HASH].source.clone()
+    }
+
+    fn is_hybrid_on_rd123(source: &DownloadSource) -> bool {
+        matches!(
+            source,
+            DownloadSource::Hybrid { debrid_provider: DebridProviderType::RealDebrid, debrid_torrent_id, .. }
+                if debrid_torrent_id.as_str() == "RD123"
+        )
+    }
+
+    #[tokio::test]
+    async fn test_cloud_add_of_p2p_torrent() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let provider = Arc::new(MockProvider::default());
+        let state = state_with_provider(&temp_dir, provider.clone()).await;
+        add_p2p(&state, false).await.unwrap();
+
+        // Refused before the provider hears of it
+        let err = add_cloud(&state, &temp_dir, false).await.unwrap_err();
+        assert_eq!(err, format!("Already added from another source: {} is a P2P download", HYBRID_HASH));
+        assert!(provider.magnets.lock().unwrap().is_empty());
+        assert!(matches!(source_of(&state).await, DownloadSource::P2P));
+        assert!(state.cloud_downloads.info_hashes().await.is_empty());
+
+        // Joined on request: the P2P entry stays, with both sources
+        add_cloud(&state, &temp_dir, true).await.unwrap();
+        assert_eq!(provider.magnets.lock().unwrap().len(), 1);
+        assert!(is_hybrid_on_rd123(&source_of(&state).await));
+        assert_eq!(state.torrents.read().await[HYBRID_HASH].state, crate::state::TorrentState::Paused);
+        assert!(is_hybrid_on_rd123(&state.database.load_torrent(HYBRID_HASH).unwrap().unwrap().source));
+        assert!(state.engines.read().await.contains_key(HYBRID_HASH));
+        assert!(state.cloud_downloads.contains(HYBRID_HASH).await);
+
+        // Nothing more to join
+        assert!(add_cloud(&state, &temp_dir, true).await.unwrap_err().contains("is a hybrid P2P and Real-Debrid cloud download"));
+        assert!(add_p2p(&state, true).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_p2p_add_of_cloud_torrent() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let provider = Arc::new(MockProvider::default());
+        let state = state_with_provider(&temp_dir, provider.clone()).await;
+        add_cloud(&state, &temp_dir, false).await.unwrap();
+
+        let err = add_p2p(&state, false).await.unwrap_err();
+        assert_eq!(err, format!("Already added from another source: {} is a Real-Debrid cloud download", HYBRID_HASH));
+        assert!(matches!(source_of(&state).await, DownloadSource::Debrid { .. }));
+        assert!(state.engines.read().await.is_empty());
+        assert!(state.database.load_torrent(HYBRID_HASH).unwrap().is_none());
+
+        // The P2P side takes over the entry; the cloud download keeps running
+        add_p2p(&state, true).await.unwrap();
+        assert!(is_hybrid_on_rd123(&source_of(&state).await));
+        assert!(is_hybrid_on_rd123(&state.database.load_torrent(HYBRID_HASH).unwrap().unwrap().source));
+        assert!(state.engines.read().await.contains_key(HYBRID_HASH));
+        assert!(state.cloud_downloads.is_running(HYBRID_HASH).await);
+    }
+
+    #[tokio::test]
+    async fn test_remove_hybrid_cleans_up_both_sides() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let provider = Arc::new(MockProvider::default());
+        let state = state_with_provider(&temp_dir, provider.clone()).await;
+        add_p2p(&state, false).await.unwrap();
+        add_cloud(&state, &temp_dir, true).await.unwrap();
+
+        crate::commands::remove_torrent_internal(&state, HYBRID_HASH.to_string(), true, true, false).await.unwrap();
+
+        assert!(state.torrents.read().await.is_empty());
+        assert!(state.engines.read().await.is_empty());
+        assert!(state.engine_controls.read().await.is_empty());
+        assert!(state.engine_tasks.read().await.is_empty());
+        assert!(state.cloud_downloads.info_hashes().await.is_empty());
+        assert!(state.cloud_file_progress.files(HYBRID_HASH).await.is_empty());
+        assert!(state.database.load_torrent(HYBRID_HASH).unwrap().is_none());
+        assert_eq!(*provider.deleted.lock().unwrap(), vec!["RD123".to_string()]);
+    }
 }
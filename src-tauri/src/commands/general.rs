@@ -1,6 +1,9 @@
 //! General commands: app info, settings, greeting
 
+use crate::error::Error;
 use crate::state::AppState;
+use crate::tracker::http::TrackerHttpConfig;
+use std::collections::HashMap;
 use tauri::State;
 
 /// Simple greeting command (for testing)
@@ -15,6 +18,128 @@ pub fn get_version() -> String {
     env!("CARGO_PKG_VERSION").to_string()
 }
 
+/// Client identity as seen by trackers
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct ClientInfo {
+    pub version: String,
+    /// User-Agent sent to trackers (None in anonymous mode)
+    pub tracker_user_agent: Option<String>,
+    /// Our public addresses as trackers agree on them (None until one says)
+    pub external_ipv4: Option<std::net::Ipv4Addr>,
+    pub external_ipv6: Option<std::net::Ipv6Addr>,
+}
+
+/// Get client name/version and the effective tracker user agent
+#[tauri::command]
+pub fn get_client_info(state: State<'_, AppState>) -> ClientInfo {
+    let anonymous = *state.anonymous_mode.borrow();
+    ClientInfo {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        tracker_user_agent: (!anonymous).then(|| state.tracker_http.borrow().user_agent().to_string()),
+        external_ipv4: state.external_ip.ipv4(),
+        external_ipv6: state.external_ip.ipv6(),
+    }
+}
+
+/// Result of `check_clock`
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct ClockStatus {
+    /// Local clock minus server clock (seconds; positive = ahead)
+    pub skew_secs: i64,
+    /// Whether the skew is large enough to break HTTPS
+    pub skewed: bool,
+    /// Human-readable description
+    pub message: String,
+}
+
+/// Diagnostics: compare the system clock with a web server's `Date` header
+#[tauri::command]
+pub async fn check_clock(probe_url: Option<String>) -> Result<ClockStatus, String> {
+    let url = probe_url.unwrap_or_else(|| crate::clock::DEFAULT_PROBE_URL.to_string());
+    let skew_secs = crate::clock::probe(&url).await.map_err(|e| e.to_string())?;
+    let skewed = skew_secs.abs() > crate::clock::MAX_CLOCK_SKEW_SECS;
+    let message = if skewed {
+        format!("Clock skew detected (off by {})", crate::clock::describe_skew(skew_secs))
+    } else {
+        "System clock is correct".to_string()
+    };
+    Ok(ClockStatus { skew_secs, skewed, message })
+}
+
+/// Internal counters for troubleshooting
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct Diagnostics {
+    /// Peer connections waiting for the dial pacer, all torrents
+    pub queued_dials: usize,
+    /// Peer connections currently connecting or handshaking
+    pub half_open_dials: usize,
+    /// Peer connections past the handshake
+    pub established_connections: usize,
+    /// Cap on all peer connections in force now (0 = unlimited)
+    pub connection_cap: usize,
+    /// Whether the cap is lowered after running out of file descriptors
+    pub connections_throttled: bool,
+    /// Open files limit (None if unknown or unlimited)
+    pub fd_limit: Option<u64>,
+    /// Descriptors open now (None where the platform can't tell)
+    pub open_fds: Option<usize>,
+    /// Size the open file handle cache may use
+    pub file_handle_budget: usize,
+    /// "Too many open files" errors since startup
+    pub fd_exhaustion_errors: u64,
+    /// Engine metrics by torrent id (empty if the engine list is busy)
+    pub engines: HashMap<String, crate::engine::EngineMetricsSnapshot>,
+    /// Whether system sleep is being prevented, and why not if it failed
+    pub sleep_inhibitor: crate::power::SleepStatus,
+    /// Each tracker's latest report of our public address
+    pub external_ip_reports: Vec<crate::tracker::external_ip::ExternalIpReport>,
+    /// Saved torrents whose key, id and metadata disagree on the info hash
+    pub hash_mismatches: Vec<crate::database::SessionHashMismatch>,
+}
+
+/// Diagnostics: internal queue depths, file descriptor usage and saved
+/// torrents that disagree on their info hash
+#[tauri::command]
+pub fn get_diagnostics(state: State<'_, AppState>) -> Diagnostics {
+    Diagnostics {
+        queued_dials: state.dial_pacer.queue_depth(),
+        half_open_dials: state.dial_pacer.half_open(),
+        established_connections: state.dial_pacer.established(),
+        connection_cap: state.dial_pacer.connection_cap(),
+        connections_throttled: state.dial_pacer.is_throttled(),
+        fd_limit: state.resources.fd_limit,
+        open_fds: crate::resources::open_fds(),
+        file_handle_budget: state.resources.file_handles,
+        fd_exhaustion_errors: crate::resources::fd_exhaustion_count(),
+        engines: state.engine_controls.try_read()
+            .map(|controls| {
+                controls.iter()
+                    .map(|(id, control)| (id.clone(), control.handle.metrics_snapshot()))
+                    .collect()
+            })
+            .unwrap_or_default(),
+        sleep_inhibitor: crate::power::status(),
+        external_ip_reports: state.external_ip.reports(),
+        hash_mismatches: state.database.find_hash_mismatches().unwrap_or_else(|e| {
+            tracing::warn!("Could not check saved torrents' info hashes: {}", e);
+            Vec::new()
+        }),
+    }
+}
+
+/// Periodic background tasks with their last and next runs
+#[tauri::command]
+pub fn get_background_tasks(state: State<'_, AppState>) -> Vec<crate::tasks::TaskStatus> {
+    state.background_tasks.statuses()
+}
+
+/// Run a background task now instead of at its next scheduled run
+#[tauri::command]
+pub fn run_background_task_now(state: State<'_, AppState>, name: String) -> Result<(), String> {
+    tracing::info!("Running background task {} on request", name);
+    state.background_tasks.run_now(&name)
+}
+
 /// Get application settings
 #[tauri::command]
 pub async fn get_settings(state: State<'_, AppState>) -> Result<crate::state::Settings, String> {
@@ -27,6 +152,22 @@ pub async fn update_settings(
     state: State<'_, AppState>,
     settings: crate::state::Settings,
 ) -> Result<(), String> {
+    update_settings_internal(&state, settings).await
+}
+
+pub async fn update_settings_internal(state: &AppState, settings: crate::state::Settings) -> Result<(), String> {
+    // A port we can't use is rejected up front so the old one stays in effect
+    let port_changed = *state.listen_port.borrow() != settings.listen_port;
+    if port_changed {
+        check_listen_port(settings.listen_port)?;
+    }
+    let tracker_http = TrackerHttpConfig::new(
+        settings.tracker_user_agent.as_deref(),
+        &settings.tracker_extra_headers,
+    )
+    .and_then(|config| config.with_tls(settings.tracker_ca_file.as_deref(), &settings.tracker_insecure_hosts))
+    .map_err(|e| e.to_string())?;
+
     // Update memory state
     *state.settings.write().await = settings.clone();
 
@@ -36,31 +177,142 @@ pub async fn update_settings(
     
     db_settings.max_download_speed = settings.download_limit;
     db_settings.max_upload_speed = settings.upload_limit;
+    db_settings.count_overhead_in_rate_limits = settings.count_overhead_in_rate_limits;
     db_settings.max_concurrent_downloads = settings.max_active_downloads as usize;
     db_settings.listen_port = settings.listen_port;
     db_settings.enable_dht = settings.enable_dht;
     db_settings.enable_pex = settings.enable_pex;
+    db_settings.anonymous_mode = settings.anonymous_mode;
+    db_settings.tracker_user_agent = settings.tracker_user_agent;
+    db_settings.tracker_extra_headers = settings.tracker_extra_headers;
+    db_settings.tracker_ca_file = settings.tracker_ca_file.filter(|path| !path.trim().is_empty());
+    db_settings.tracker_insecure_hosts = settings.tracker_insecure_hosts;
+    db_settings.cleanup_enabled = settings.cleanup_enabled;
+    db_settings.cleanup_ratio = settings.cleanup_ratio;
+    db_settings.cleanup_time = settings.cleanup_time;
+    db_settings.cleanup_mode = settings.cleanup_mode;
     db_settings.bandwidth_scheduler_enabled = settings.bandwidth_scheduler_enabled;
     db_settings.bandwidth_schedule = settings.bandwidth_schedule;
+    db_settings.ffmpeg_path = settings.ffmpeg_path.filter(|path| !path.trim().is_empty());
+    db_settings.saved_peer_max_age_secs = settings.saved_peer_max_age_secs;
+    db_settings.max_dials_per_sec = settings.max_dials_per_sec;
+    db_settings.max_half_open_connections = settings.max_half_open_connections;
+    db_settings.max_connections = settings.max_connections;
+    db_settings.mmap_piece_reads = settings.mmap_piece_reads;
+    db_settings.file_mtime_from_creation_date = settings.file_mtime_from_creation_date;
+    db_settings.auto_backup_enabled = settings.auto_backup_enabled;
+    db_settings.auto_backup_interval_hours = settings.auto_backup_interval_hours.max(1);
+    db_settings.auto_backup_keep = settings.auto_backup_keep.max(1);
+    db_settings.auto_backup_dir = settings.auto_backup_dir.filter(|dir| !dir.trim().is_empty());
+    db_settings.log_keep_files = settings.log_keep_files.max(1);
+    db_settings.log_max_total_mb = settings.log_max_total_mb;
+    db_settings.log_file_level = settings.log_file_level;
+    db_settings.auto_stop_dead_enabled = settings.auto_stop_dead_enabled;
+    db_settings.auto_stop_dead_days = settings.auto_stop_dead_days.clamp(1, crate::stall::MAX_DEAD_AFTER_DAYS);
+    db_settings.auto_resume_revived = settings.auto_resume_revived;
+    db_settings.reactivate_paused_seeding = settings.reactivate_paused_seeding;
+    db_settings.notify_on_tracker_warning = settings.notify_on_tracker_warning;
+    db_settings.low_disk_reserve_mb = settings.low_disk_reserve_mb;
+    db_settings.low_disk_auto_resume = settings.low_disk_auto_resume;
+    db_settings.prevent_sleep = settings.prevent_sleep;
+    db_settings.prevent_sleep_while_seeding = settings.prevent_sleep_while_seeding;
+    db_settings.db_flush_interval_secs = settings.db_flush_interval_secs.max(1);
 
     state.database.save_settings(&db_settings)
         .map_err(|e| format!("Failed to save settings: {}", e))?;
 
+    // Running engines re-announce with the new port
+    if port_changed {
+        tracing::info!("Listen port changed to {}", settings.listen_port);
+        state.listen_port.send_replace(settings.listen_port);
+    }
+    // Picked up by the next announce and handshake of every running engine
+    state.anonymous_mode.send_replace(settings.anonymous_mode);
+    state.mmap_reads.send_replace(settings.mmap_piece_reads);
+    state.completion_mtimes.send_replace(settings.file_mtime_from_creation_date);
+    state.reactivate_paused_seeding.send_replace(settings.reactivate_paused_seeding);
+    state.notify_tracker_warnings.send_replace(settings.notify_on_tracker_warning);
+    // Background tasks are rebuilt only if their part of the settings changed
+    let cleanup_policy = crate::cleanup::CleanupPolicy::from(&db_settings);
+    state.cleanup_policy.send_if_modified(|current| {
+        let changed = *current != cleanup_policy;
+        *current = cleanup_policy;
+        changed
+    });
+    let bandwidth_schedule = crate::scheduler::BandwidthSchedule::from(&db_settings);
+    state.bandwidth_schedule.send_if_modified(|current| {
+        let changed = *current != bandwidth_schedule;
+        *current = bandwidth_schedule;
+        changed
+    });
+    state.flush_interval.send_if_modified(|current| {
+        let changed = *current != db_settings.db_flush_interval_secs;
+        *current = db_settings.db_flush_interval_secs;
+        changed
+    });
+    state.tracker_http.send_if_modified(|current| {
+        let changed = *current != tracker_http;
+        *current = tracker_http;
+        changed
+    });
+    state.dial_pacer.configure(settings.max_dials_per_sec, settings.max_half_open_connections);
+    state.dial_pacer.set_max_connections(state.resources.connection_cap(settings.max_connections));
+    crate::logs::set_file_level(settings.log_file_level);
+    // Tighter retention applies right away rather than at the next daily pass
+    tokio::task::spawn_blocking(move || crate::logs::prune_with_settings(&db_settings));
+
     Ok(())
 }
 
+/// Check that we could accept connections on `port`.
+///
+/// There is no incoming peer listener yet, so a probe bind stands in for the
+/// rebind (and NAT mapping refresh) that will happen once there is one.
+pub(crate) fn check_listen_port(port: u16) -> Result<(), String> {
+    if port == 0 {
+        return Err(Error::ValidationError("Listen port must be between 1 and 65535".to_string()).to_string());
+    }
+
+    std::net::TcpListener::bind(("0.0.0.0", port))
+        .map(drop)
+        .map_err(|e| Error::ValidationError(format!("Listen port {} is unavailable: {}", port, e)).to_string())
+}
+
 /// Get list of all torrents
 #[tauri::command]
 pub async fn get_torrents(state: State<'_, AppState>) -> Result<Vec<crate::state::TorrentInfo>, String> {
     Ok(state.torrents.read().await.values().cloned().collect())
 }
 
+/// Search the torrent list: filters, sort and one page of results with the
+/// total match count
+#[tauri::command]
+pub async fn query_torrents(
+    state: State<'_, AppState>,
+    query: crate::search::TorrentQuery,
+) -> Result<crate::search::TorrentPage, String> {
+    let torrents = state.torrents.read().await;
+    Ok(crate::search::run_query(&state.search_index, &state.database, &torrents, &query))
+}
+
+/// Re-derive the metadata search summaries of every saved torrent, e.g. for
+/// torrents added before metadata search existed. Returns how many.
+#[tauri::command]
+pub async fn rebuild_search_index(state: State<'_, AppState>) -> Result<usize, String> {
+    let database = state.database.clone();
+    tokio::task::spawn_blocking(move || crate::search::rebuild(&database))
+        .await
+        .map_err(|e| format!("Failed to rebuild search index: {}", e))?
+        .map_err(|e| format!("Failed to rebuild search index: {}", e))
+}
+
 /// Get debrid settings
 #[tauri::command]
 pub async fn get_debrid_settings(state: State<'_, AppState>) -> Result<super::DebridSettings, String> {
     let app_settings = state.database
         .load_settings()
         .map_err(|e| format!("Failed to load settings: {}", e))?;
+    let debrid_manager = state.debrid_manager.read().await;
 
     Ok(super::DebridSettings {
         enable_debrid: app_settings.enable_debrid,
@@ -69,6 +321,23 @@ pub async fn get_debrid_settings(state: State<'_, AppState>) -> Result<super::De
             .map(|p| p.as_str().to_string())
             .collect(),
         smart_mode_enabled: app_settings.smart_mode_enabled,
+        delete_from_provider_after_download: app_settings.delete_from_provider_after_download,
+        file_collision: app_settings.file_collision,
+        ask_before_selecting_cloud_files: app_settings.ask_before_selecting_cloud_files,
+        debrid_base_urls: app_settings.debrid_base_urls
+            .iter()
+            .map(|(p, url)| (p.as_str().to_string(), url.clone()))
+            .collect(),
+        debrid_disable_policy: app_settings.debrid_disable_policy,
+        status: super::DebridStatus {
+            enabled: debrid_manager.is_enabled(),
+            unlocked: state.master_password.read().await.is_some(),
+            providers_configured: debrid_manager
+                .configured_providers()
+                .iter()
+                .map(|p| p.as_str().to_string())
+                .collect(),
+        },
     })
 }
 
@@ -80,6 +349,17 @@ pub async fn update_debrid_settings(
 ) -> Result<(), String> {
     tracing::info!("Updating debrid settings");
 
+    // Reject bad overrides before anything is saved
+    let mut base_urls = HashMap::new();
+    for (provider, url) in &settings.debrid_base_urls {
+        if url.trim().is_empty() {
+            continue;
+        }
+        let provider_type = super::parse_provider(provider)?;
+        let url = crate::debrid::validate_base_url(url).map_err(|e| e.to_string())?;
+        base_urls.insert(provider_type, url);
+    }
+
     // Load current settings
     let mut app_settings = state.database
         .load_settings()
@@ -88,6 +368,11 @@ pub async fn update_debrid_settings(
     // Update debrid-related fields
     app_settings.enable_debrid = settings.enable_debrid;
     app_settings.smart_mode_enabled = settings.smart_mode_enabled;
+    app_settings.delete_from_provider_after_download = settings.delete_from_provider_after_download;
+    app_settings.file_collision = settings.file_collision;
+    app_settings.ask_before_selecting_cloud_files = settings.ask_before_selecting_cloud_files;
+    app_settings.debrid_base_urls = base_urls;
+    app_settings.debrid_disable_policy = settings.debrid_disable_policy;
 
     // Parse provider preference using shared helper
     let mut preference = Vec::new();
@@ -104,8 +389,12 @@ pub async fn update_debrid_settings(
         .map_err(|e| format!("Failed to save settings: {}", e))?;
 
     // Update debrid manager preference
-    let mut debrid_manager = state.debrid_manager.write().await;
-    debrid_manager.set_preference(app_settings.debrid_preference.clone());
+    {
+        let mut debrid_manager = state.debrid_manager.write().await;
+        debrid_manager.set_preference(app_settings.debrid_preference.clone());
+        debrid_manager.set_base_urls(app_settings.debrid_base_urls.clone());
+    }
+    super::apply_debrid_enabled(&state, app_settings.enable_debrid, app_settings.debrid_disable_policy).await;
 
     tracing::info!("Debrid settings updated successfully");
     Ok(())
@@ -119,18 +408,50 @@ pub async fn backup_data(state: State<'_, AppState>) -> Result<String, String> {
         .map_err(|e| format!("Failed to create backup: {}", e))
 }
 
-/// Export backup to a file
+/// Export a backup to a file in the stream format (`database::stream`),
+/// one session at a time, reporting `backup-export-progress` events. Written
+/// aside and renamed when complete; `cancel_backup_export` stops it.
 #[tauri::command]
-pub async fn export_backup(state: State<'_, AppState>, path: String) -> Result<(), String> {
-    let json = state.database
-        .dump_all()
-        .map_err(|e| format!("Failed to create backup: {}", e))?;
-    
-    std::fs::write(&path, json)
-        .map_err(|e| format!("Failed to write backup file: {}", e))?;
-    
-    tracing::info!("Backup exported successfully to: {}", path);
-    Ok(())
+pub async fn export_backup(
+    app: tauri::AppHandle,
+    state: State<'_, AppState>,
+    path: String,
+) -> Result<crate::database::BackupSummary, String> {
+    let guard = crate::backup::begin().ok_or("A backup or restore is already running")?;
+    let database = state.database.clone();
+    let target = std::path::PathBuf::from(&path);
+    let summary = tokio::task::spawn_blocking(move || {
+        let partial = target.with_extension("partial");
+        let written = std::fs::File::create(&partial)
+            .map_err(|e| crate::error::Error::IoError(format!("Failed to create {:?}: {}", partial, e)))
+            .and_then(|file| {
+                let progress = |progress: crate::database::StreamProgress| {
+                    crate::events::emit_event(&app, crate::events::Event::BackupExportProgress(progress));
+                };
+                database.export_stream(std::io::BufWriter::new(file), &guard.token, progress)
+            })
+            .and_then(|summary| {
+                std::fs::rename(&partial, &target)
+                    .map_err(|e| crate::error::Error::IoError(format!("Failed to move backup into place: {}", e)))?;
+                Ok(summary)
+            });
+        if written.is_err() {
+            let _ = std::fs::remove_file(&partial);
+        }
+        written
+    })
+    .await
+    .map_err(|e| format!("Failed to create backup: {}", e))?
+    .map_err(|e| format!("Failed to create backup: {}", e))?;
+
+    tracing::info!("Backup of {} torrents exported to: {}", summary.torrents, path);
+    Ok(summary)
+}
+
+/// Stop the backup export in progress. Returns whether one was running.
+#[tauri::command]
+pub async fn cancel_backup_export() -> Result<bool, String> {
+    Ok(crate::backup::cancel())
 }
 
 /// Restore database data from a JSON string
@@ -147,20 +468,265 @@ pub async fn restore_data(state: State<'_, AppState>, json: String) -> Result<()
     Ok(())
 }
 
-/// Import backup from a file
+/// Import a backup file (upsert, like `restore_data`). Stream backups from
+/// `export_backup` are read record by record, skipping records that don't
+/// decode; older JSON backups are read whole.
 #[tauri::command]
-pub async fn import_backup(state: State<'_, AppState>, path: String) -> Result<(), String> {
-    let json = std::fs::read_to_string(&path)
-        .map_err(|e| format!("Failed to read backup file: {}", e))?;
-    
-    state.database
-        .restore(&json)
-        .map_err(|e| format!("Failed to restore backup: {}", e))?;
-    
+pub async fn import_backup(
+    state: State<'_, AppState>,
+    path: String,
+) -> Result<crate::database::BackupSummary, String> {
+    let _guard = crate::backup::begin().ok_or("A backup or restore is already running")?;
+    let database = state.database.clone();
+    let file_path = path.clone();
+    let summary = tokio::task::spawn_blocking(move || {
+        let mut file = std::io::BufReader::new(
+            std::fs::File::open(&file_path)
+                .map_err(|e| crate::error::Error::IoError(format!("Failed to read backup file: {}", e)))?,
+        );
+        if crate::database::stream::is_stream(&mut file)? {
+            database.import_stream(file)
+        } else {
+            let mut json = String::new();
+            std::io::Read::read_to_string(&mut file, &mut json)
+                .map_err(|e| crate::error::Error::IoError(format!("Failed to read backup file: {}", e)))?;
+            database.restore(&json)
+        }
+    })
+    .await
+    .map_err(|e| format!("Failed to restore backup: {}", e))?
+    .map_err(|e| format!("Failed to restore backup: {}", e))?;
+
     if let Ok(settings) = state.database.load_settings() {
         *state.settings.write().await = settings.into();
     }
-    
+
+    if summary.skipped > 0 {
+        tracing::warn!("Backup import from {} skipped {} unreadable records", path, summary.skipped);
+    }
     tracing::info!("Backup imported successfully from: {}", path);
-    Ok(())
+    Ok(summary)
+}
+
+/// Replace the whole database with a backup file (as written by the
+/// scheduled backups or `export_backup`). The backup is restored into a
+/// scratch database and checked before the live one is touched, so a bad
+/// file changes nothing. Afterwards the torrent list is reloaded from the
+/// restored database and torrents it left running are started again.
+#[tauri::command]
+pub async fn restore_from_backup_file(
+    app: tauri::AppHandle,
+    state: State<'_, AppState>,
+    path: String,
+) -> Result<crate::database::RestoreSummary, String> {
+    let _guard = crate::backup::begin().ok_or("A backup or restore is already running")?;
+    let mut file = std::io::BufReader::new(
+        std::fs::File::open(&path).map_err(|e| format!("Failed to read backup file: {}", e))?,
+    );
+    let stream = crate::database::stream::is_stream(&mut file)
+        .map_err(|e| format!("Failed to read backup file: {}", e))?;
+    let json = if stream {
+        None
+    } else {
+        let mut json = String::new();
+        std::io::Read::read_to_string(&mut file, &mut json)
+            .map_err(|e| format!("Failed to read backup file: {}", e))?;
+        Some(json)
+    };
+
+    let running: Vec<String> = state.engine_tasks.read().await.keys().cloned().collect();
+    for torrent_id in &running {
+        super::pause_torrent_internal(&state, torrent_id, crate::state::TorrentState::Paused).await?;
+    }
+
+    let database = state.database.clone();
+    let result = tokio::task::spawn_blocking(move || match json {
+        Some(json) => database.restore_replacing(&json),
+        None => database.restore_replacing_stream(file),
+    })
+        .await
+        .map_err(|e| format!("Failed to restore backup: {}", e))
+        .and_then(|restored| restored.map_err(|e| format!("Failed to restore backup: {}", e)));
+
+    let summary = match result {
+        Ok(summary) => summary,
+        Err(e) => {
+            for torrent_id in running {
+                if let Err(e) = super::start_torrent_internal(&state, torrent_id.clone(), false).await {
+                    tracing::warn!("Failed to resume {} after a failed restore: {}", torrent_id, e);
+                }
+            }
+            state.queue.request_reconcile();
+            return Err(e);
+        }
+    };
+
+    if let Ok(settings) = state.database.load_settings() {
+        *state.settings.write().await = settings.into();
+    }
+    unload_torrents(&state).await;
+    super::load_saved_torrents_internal(Some(app), &state).await?;
+    state.queue.request_reconcile();
+
+    tracing::info!("Restored {} torrents from backup {}", summary.torrents, path);
+    Ok(summary)
+}
+
+/// What startup did about a damaged database, None if it opened normally.
+/// Also sent as the `database-recovered` event, which fires before the UI
+/// may be listening.
+#[tauri::command]
+pub async fn get_database_recovery(
+    state: State<'_, AppState>,
+) -> Result<Option<crate::database::DatabaseRecovery>, String> {
+    Ok(state.database_recovery.clone())
+}
+
+/// Export what can still be read from the damaged database startup moved
+/// aside into the backup directory. The file restores like a backup
+/// (`restore_from_backup_file`).
+#[tauri::command]
+pub async fn salvage_damaged_database(
+    state: State<'_, AppState>,
+) -> Result<crate::database::SalvageReport, String> {
+    let recovery = state.database_recovery.as_ref().ok_or("No damaged database to salvage")?;
+    let damaged = std::path::PathBuf::from(&recovery.moved_to);
+    let out_dir = crate::backup::default_dir(&state.database);
+    let now = chrono::Utc::now().timestamp();
+    tokio::task::spawn_blocking(move || crate::database::salvage(&damaged, &out_dir, now))
+        .await
+        .map_err(|e| format!("Failed to salvage database: {}", e))?
+        .map_err(|e| format!("Failed to salvage database: {}", e))
+}
+
+/// Log files on disk, oldest first, for the log retention settings
+#[tauri::command]
+pub async fn get_log_usage() -> Result<crate::logs::LogUsage, String> {
+    tokio::task::spawn_blocking(|| crate::logs::usage(&crate::logs::log_dir()))
+        .await
+        .map_err(|e| format!("Failed to list log files: {}", e))
+}
+
+/// Drop every torrent and its engine from memory, leaving the database and
+/// files alone
+async fn unload_torrents(state: &AppState) {
+    for (_, control) in state.engine_controls.write().await.drain() {
+        control.cancel.cancel();
+        let _ = control.handle.stop();
+    }
+    for (_, task_handle) in state.engine_tasks.write().await.drain() {
+        task_handle.abort();
+    }
+    state.engines.write().await.clear();
+
+    let ids: Vec<String> = state.torrents.write().await.drain().map(|(id, _)| id).collect();
+    for torrent_id in ids {
+        state.search_index.remove(&torrent_id);
+        state.detail_subscriptions.unsubscribe(&torrent_id);
+    }
+}
+
+/// Move the database and logs to `new_path` (an empty or new directory) and
+/// use it from now on; returns the new location. Running torrents are paused
+/// while the database is copied and resumed afterwards. The old copy is left
+/// in place, and log lines keep going there until the app is restarted.
+#[tauri::command]
+pub async fn migrate_data_dir(state: State<'_, AppState>, new_path: String) -> Result<String, String> {
+    let target = std::path::PathBuf::from(new_path.trim());
+    let current = crate::data_dir::data_dir();
+
+    let running: Vec<String> = state.engine_tasks.read().await.keys().cloned().collect();
+    for torrent_id in &running {
+        super::pause_torrent_internal(&state, torrent_id, crate::state::TorrentState::Paused).await?;
+    }
+
+    let database = state.database.clone();
+    let destination = target.clone();
+    let result = tokio::task::spawn_blocking(move || {
+        crate::data_dir::migrate(&database, &current, &destination, &crate::state::config_dir())
+    })
+    .await
+    .map_err(|e| format!("Failed to move data directory: {}", e))
+    .and_then(|moved| moved.map_err(|e| format!("Failed to move data directory: {}", e)));
+
+    for torrent_id in running {
+        if let Err(e) = super::start_torrent_internal(&state, torrent_id.clone(), false).await {
+            tracing::warn!("Failed to resume {} after moving the data directory: {}", torrent_id, e);
+        }
+    }
+    state.queue.request_reconcile();
+
+    result.map(|()| target.display().to_string())
+}
+
+/// Measure disk and network throughput (see `benchmark`). Refuses to run
+/// while torrents or cloud downloads are busy unless `force` is set, and one
+/// run at a time. Results are added to the benchmark history.
+#[tauri::command]
+pub async fn run_benchmark(
+    state: State<'_, AppState>,
+    options: crate::benchmark::BenchmarkOptions,
+) -> Result<crate::benchmark::BenchmarkReport, String> {
+    let transfer_speed: u64 = state.torrents.read().await
+        .values()
+        .map(|t| t.download_speed + t.upload_speed)
+        .sum();
+    let cloud_downloads = state.cloud_downloads.running().await;
+    let busy = crate::benchmark::busy_reason(transfer_speed, cloud_downloads);
+    if let (Some(reason), false) = (&busy, options.force) {
+        return Err(format!("Not running the benchmark while {}", reason));
+    }
+
+    let guard = crate::benchmark::begin().ok_or("A benchmark is already running")?;
+    let download_dir = state.database
+        .load_settings()
+        .map_err(|e| format!("Failed to load settings: {}", e))?
+        .download_dir;
+    let fetcher = crate::benchmark::HttpFetcher::new().map_err(|e| e.to_string())?;
+
+    tracing::info!("Running benchmark in {}: {:?}", download_dir, options);
+    let mut report = crate::benchmark::run(&options, std::path::Path::new(&download_dir), &fetcher, &guard.token)
+        .await
+        .map_err(|e| format!("Benchmark failed: {}", e))?;
+    if let Some(reason) = busy {
+        report.warnings.push(format!("Ran while {}, so results are lower than they could be", reason));
+    }
+
+    if let Err(e) = state.database.save_benchmark(&report) {
+        tracing::warn!("Failed to record benchmark result: {}", e);
+    }
+    Ok(report)
+}
+
+/// Stop a running benchmark; returns whether one was running
+#[tauri::command]
+pub fn cancel_benchmark() -> bool {
+    crate::benchmark::cancel()
+}
+
+/// Earlier benchmark results, oldest first
+#[tauri::command]
+pub fn get_benchmark_history(state: State<'_, AppState>) -> Result<Vec<crate::benchmark::BenchmarkReport>, String> {
+    state.database
+        .load_benchmarks()
+        .map_err(|e| format!("Failed to load benchmark history: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_listen_port() {
+        assert!(check_listen_port(0).is_err());
+
+        // A port someone else holds is reported as a validation error
+        let taken = std::net::TcpListener::bind(("0.0.0.0", 0)).unwrap();
+        let port = taken.local_addr().unwrap().port();
+        let err = check_listen_port(port).unwrap_err();
+        assert!(err.starts_with("Validation error"), "{}", err);
+
+        drop(taken);
+        assert!(check_listen_port(port).is_ok());
+    }
 }
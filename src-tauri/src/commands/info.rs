@@ -1,10 +1,13 @@
-//! Info commands: peers, trackers, pieces, files, disk space
+//! Info commands: peers, choke history, trackers, pieces, availability history, files, previews, disk space, storage audit, duplicate content, engine metrics, event catalog
 
 use crate::state::AppState;
 use crate::peer::PeerInfo;
+use crate::peer::choking::ChokeDecision;
 use crate::tracker::TrackerInfo;
-use crate::piece::PiecesInfo;
-use std::path::PathBuf;
+use crate::piece::buckets::DEFAULT_MAX_BUCKETS;
+use crate::piece::{Bitfield, PieceWindow, PiecesInfo};
+use crate::torrent::MetadataResult;
+use std::path::{Path, PathBuf};
 use tauri::State;
 
 /// Get peer list for a torrent
@@ -13,6 +16,7 @@ pub async fn get_peer_list(
     state: State<'_, AppState>,
     torrent_id: String,
 ) -> Result<Vec<PeerInfo>, String> {
+    let torrent_id = super::normalize_torrent_id(&torrent_id)?;
     tracing::debug!("Getting peer list for torrent: {}", torrent_id);
 
     let engines = state.engines.read().await;
@@ -25,12 +29,37 @@ pub async fn get_peer_list(
     Ok(peers)
 }
 
+/// Recent choking decisions about a connected peer (`peer_addr` is "ip:port"),
+/// oldest first
+#[tauri::command]
+pub async fn get_peer_choke_history(
+    state: State<'_, AppState>,
+    torrent_id: String,
+    peer_addr: String,
+) -> Result<Vec<ChokeDecision>, String> {
+    let torrent_id = super::normalize_torrent_id(&torrent_id)?;
+    let addr: std::net::SocketAddr = peer_addr
+        .parse()
+        .map_err(|e| format!("Invalid peer address {}: {}", peer_addr, e))?;
+
+    let engines = state.engines.read().await;
+    let engine = engines.get(&torrent_id)
+        .ok_or_else(|| format!("Torrent not found: {}", torrent_id))?;
+
+    let engine_lock = engine.read().await;
+    engine_lock
+        .get_peer_choke_history(addr)
+        .await
+        .ok_or_else(|| format!("Peer not connected: {}", addr))
+}
+
 /// Get tracker list for a torrent
 #[tauri::command]
 pub async fn get_tracker_list(
     state: State<'_, AppState>,
     torrent_id: String,
 ) -> Result<Vec<TrackerInfo>, String> {
+    let torrent_id = super::normalize_torrent_id(&torrent_id)?;
     tracing::debug!("Getting tracker list for torrent: {}", torrent_id);
 
     let engines = state.engines.read().await;
@@ -43,12 +72,16 @@ pub async fn get_tracker_list(
     Ok(trackers)
 }
 
-/// Get pieces info for a torrent
+/// Get pieces info for a torrent. Torrents with more pieces than
+/// `max_buckets` (default `DEFAULT_MAX_BUCKETS`) get their piece map in
+/// buckets; `get_piece_details` has the pieces of one stretch.
 #[tauri::command]
 pub async fn get_pieces_info(
     state: State<'_, AppState>,
     torrent_id: String,
-) -> Result<PiecesInfo, String> {
+    max_buckets: Option<usize>,
+) -> Result<MetadataResult<PiecesInfo>, String> {
+    let torrent_id = super::normalize_torrent_id(&torrent_id)?;
     tracing::debug!("Getting pieces info for torrent: {}", torrent_id);
 
     let engines = state.engines.read().await;
@@ -56,10 +89,77 @@ pub async fn get_pieces_info(
         .ok_or_else(|| format!("Torrent not found: {}", torrent_id))?;
 
     let engine_lock = engine.read().await;
-    let piece_manager = engine_lock.piece_manager();
-    let pm = piece_manager.read().await;
+    Ok(engine_lock.pieces_info(max_buckets.unwrap_or(DEFAULT_MAX_BUCKETS)).await)
+}
+
+/// State and availability of pieces `start..start + count`, one by one
+/// (at most `piece::buckets::MAX_PIECE_WINDOW` of them)
+#[tauri::command]
+pub async fn get_piece_details(
+    state: State<'_, AppState>,
+    torrent_id: String,
+    start: usize,
+    count: usize,
+) -> Result<MetadataResult<PieceWindow>, String> {
+    let torrent_id = super::normalize_torrent_id(&torrent_id)?;
+
+    let engines = state.engines.read().await;
+    let engine = engines.get(&torrent_id)
+        .ok_or_else(|| format!("Torrent not found: {}", torrent_id))?;
+
+    let engine_lock = engine.read().await;
+    Ok(engine_lock.piece_window(start, count).await)
+}
+
+/// Availability samples of a torrent taken between `from` and `to` (unix
+/// seconds, inclusive; either may be omitted), oldest first
+#[tauri::command]
+pub async fn get_availability_history(
+    state: State<'_, AppState>,
+    torrent_id: String,
+    from: Option<i64>,
+    to: Option<i64>,
+) -> Result<Vec<crate::availability::AvailabilitySample>, String> {
+    let torrent_id = super::normalize_torrent_id(&torrent_id)?;
+    if !state.torrents.read().await.contains_key(&torrent_id) {
+        return Err(format!("Torrent not found: {}", torrent_id));
+    }
+
+    let series = state.database
+        .load_availability_history(&torrent_id)
+        .map_err(|e| format!("Failed to load availability history: {}", e))?;
+    Ok(crate::availability::in_range(&series, from, to))
+}
+
+/// Receive `torrent-details-update` events for a torrent every second until
+/// unsubscribed. Returns the torrent whose (oldest) subscription was dropped
+/// to make room, if any.
+#[tauri::command]
+pub async fn subscribe_torrent_details(
+    state: State<'_, AppState>,
+    torrent_id: String,
+) -> Result<Option<String>, String> {
+    let torrent_id = super::normalize_torrent_id(&torrent_id)?;
+    if !state.torrents.read().await.contains_key(&torrent_id) {
+        return Err(format!("Torrent not found: {}", torrent_id));
+    }
 
-    Ok(pm.get_pieces_info())
+    let evicted = state.detail_subscriptions.subscribe(&torrent_id);
+    if let Some(ref evicted) = evicted {
+        tracing::debug!("Details subscription for {} evicted by {}", evicted, torrent_id);
+    }
+    Ok(evicted)
+}
+
+/// Stop `torrent-details-update` events for a torrent
+#[tauri::command]
+pub async fn unsubscribe_torrent_details(
+    state: State<'_, AppState>,
+    torrent_id: String,
+) -> Result<(), String> {
+    let torrent_id = super::normalize_torrent_id(&torrent_id)?;
+    state.detail_subscriptions.unsubscribe(&torrent_id);
+    Ok(())
 }
 
 /// Get file list for a torrent
@@ -67,7 +167,8 @@ pub async fn get_pieces_info(
 pub async fn get_file_list(
     state: State<'_, AppState>,
     torrent_id: String,
-) -> Result<Vec<crate::torrent::FileInfoUI>, String> {
+) -> Result<MetadataResult<Vec<crate::torrent::FileInfoUI>>, String> {
+    let torrent_id = super::normalize_torrent_id(&torrent_id)?;
     tracing::debug!("Getting file list for torrent: {}", torrent_id);
 
     let engines = state.engines.read().await;
@@ -75,28 +176,61 @@ pub async fn get_file_list(
         .ok_or_else(|| format!("Torrent not found: {}", torrent_id))?;
 
     let engine_lock = engine.read().await;
-    let metainfo = engine_lock.metainfo();
-    let piece_manager = engine_lock.piece_manager();
-    let pm = piece_manager.read().await;
-    
-    let progress = pm.calculate_file_progress(&metainfo.info.files);
+    Ok(engine_lock.file_list().await)
+}
+
+/// Thumbnail of a video file in a torrent, or why there is none yet
+///
+/// Works from the saved session, so progress is as of the last autosave.
+#[tauri::command]
+pub async fn get_file_preview(
+    state: State<'_, AppState>,
+    torrent_id: String,
+    file_index: usize,
+) -> Result<crate::preview::FilePreview, String> {
+    use crate::preview::{Ffmpeg, PreviewFile, ThumbnailExtractor};
+
+    let torrent_id = super::normalize_torrent_id(&torrent_id)?;
+    tracing::debug!("Getting preview of file {} in torrent {}", file_index, torrent_id);
+
+    let session = state.database
+        .load_torrent(&torrent_id)
+        .map_err(|e| format!("Failed to load torrent: {}", e))?
+        .ok_or_else(|| format!("Torrent not found: {}", torrent_id))?;
+    if !session.metainfo.has_metadata() {
+        return Ok(crate::preview::PreviewUnavailable::MetadataPending.into());
+    }
+    let file = PreviewFile::from_session(&session, file_index)
+        .ok_or_else(|| format!("File index {} out of range", file_index))?;
+
+    let have = if session.bitfield.len() >= session.num_pieces.div_ceil(8) {
+        Bitfield::from_bytes(session.bitfield, session.num_pieces)
+    } else {
+        Bitfield::new(session.num_pieces)
+    };
+
+    let ffmpeg = state.settings.read().await.ffmpeg_path.clone().map(|path| Ffmpeg::new(PathBuf::from(path)));
+    let extractor = ffmpeg.as_ref().map(|f| f as &dyn ThumbnailExtractor);
 
-    Ok(crate::torrent::get_file_list(&metainfo, Some(&progress)))
+    Ok(crate::preview::file_preview(extractor, &crate::preview::cache_dir(), &file, |piece| have.has_piece(piece)).await)
 }
 
 /// Get available disk space for a given path
 #[tauri::command]
 pub fn get_available_disk_space(path: String) -> Result<u64, String> {
-    use fs2::statvfs;
-
     tracing::debug!("Getting disk space for path: {}", path);
+    available_disk_space(Path::new(&path))
+}
 
-    let path_buf = PathBuf::from(&path);
+/// Free space on the filesystem holding `path`, or that would hold it when
+/// it doesn't exist yet (its parent, else the current directory)
+pub fn available_disk_space(path: &Path) -> Result<u64, String> {
+    use fs2::statvfs;
 
     // Get the actual path to check
-    let check_path = if path_buf.exists() {
-        path_buf
-    } else if let Some(parent) = path_buf.parent() {
+    let check_path = if path.exists() {
+        path.to_path_buf()
+    } else if let Some(parent) = path.parent() {
         if parent.exists() {
             parent.to_path_buf()
         } else {
@@ -117,3 +251,147 @@ pub fn get_available_disk_space(path: String) -> Result<u64, String> {
 
     Ok(available_bytes)
 }
+
+/// Get global wire traffic (payload vs protocol overhead) for this session
+#[tauri::command]
+pub fn get_traffic_stats() -> crate::peer::TrafficStats {
+    crate::peer::traffic::global_traffic().snapshot()
+}
+
+/// Group torrents that appear to hold the same content (see `duplicates`).
+/// Read-only; reads samples of complete files.
+#[tauri::command]
+pub async fn detect_duplicate_content(
+    state: State<'_, AppState>,
+) -> Result<Vec<crate::duplicates::DuplicateCluster>, String> {
+    let sessions = state.database.load_all_torrents()
+        .map_err(|e| format!("Failed to load torrents: {}", e))?;
+
+    tokio::task::spawn_blocking(move || {
+        let contents: Vec<_> = sessions
+            .iter()
+            .filter(|session| session.metainfo.has_metadata())
+            .map(crate::duplicates::TorrentContent::from_session)
+            .collect();
+        crate::duplicates::find_clusters(&contents)
+    })
+    .await
+    .map_err(|e| format!("Failed to detect duplicates: {}", e))
+}
+
+/// Every event the backend emits, with an example payload
+#[tauri::command]
+pub fn get_event_catalog() -> Vec<crate::events::EventSchema> {
+    crate::events::catalog()
+}
+
+/// Counts, speeds and sizes by state and category, and the fastest torrents
+#[tauri::command]
+pub async fn get_dashboard_summary(state: State<'_, AppState>) -> Result<crate::dashboard::DashboardSummary, String> {
+    let categories = state.search_index.categories();
+    let torrents = state.torrents.read().await;
+    Ok(crate::dashboard::summarize(&torrents, &categories))
+}
+
+/// Turn a torrent's debug transfer log on or off. With `write_file`, records
+/// are also appended to a file in the logs dir, whose path is returned.
+#[tauri::command]
+pub async fn set_torrent_debug_logging(
+    state: State<'_, AppState>,
+    torrent_id: String,
+    enabled: bool,
+    write_file: Option<bool>,
+) -> Result<Option<String>, String> {
+    let torrent_id = super::normalize_torrent_id(&torrent_id)?;
+    if !state.torrents.read().await.contains_key(&torrent_id) {
+        return Err(format!("Torrent not found: {}", torrent_id));
+    }
+
+    let log = state.transfer_logs.get(&torrent_id);
+    if !enabled {
+        log.disable();
+        tracing::info!("Transfer log disabled for {}", torrent_id);
+        return Ok(None);
+    }
+
+    let file = write_file
+        .unwrap_or(false)
+        .then(|| crate::transfer_log::log_file_path(&torrent_id));
+    log.enable(file.clone())
+        .map_err(|e| format!("Failed to open transfer log file: {}", e))?;
+    tracing::info!("Transfer log enabled for {}", torrent_id);
+    Ok(file.map(|path| path.to_string_lossy().into_owned()))
+}
+
+/// Page through a torrent's debug transfer log: records after `since_seq`
+/// (0 = from the oldest kept), at most `limit`
+#[tauri::command]
+pub async fn get_torrent_debug_log(
+    state: State<'_, AppState>,
+    torrent_id: String,
+    since_seq: u64,
+    limit: Option<usize>,
+) -> Result<crate::transfer_log::TransferLogPage, String> {
+    let torrent_id = super::normalize_torrent_id(&torrent_id)?;
+    if !state.torrents.read().await.contains_key(&torrent_id) {
+        return Err(format!("Torrent not found: {}", torrent_id));
+    }
+    let limit = limit.unwrap_or(crate::transfer_log::MAX_PAGE_SIZE);
+    Ok(state.transfer_logs.get(&torrent_id).page(since_seq, limit))
+}
+
+/// A torrent's recently ended peer connections and why each ended, newest first
+#[tauri::command]
+pub async fn get_peer_disconnect_history(
+    state: State<'_, AppState>,
+    torrent_id: String,
+) -> Result<Vec<crate::peer::disconnect::Disconnection>, String> {
+    let torrent_id = super::normalize_torrent_id(&torrent_id)?;
+    if !state.torrents.read().await.contains_key(&torrent_id) {
+        return Err(format!("Torrent not found: {}", torrent_id));
+    }
+    Ok(state.disconnect_histories.get(&torrent_id).recent())
+}
+
+/// Live concurrency metrics of a torrent's engine; readable while it runs
+#[tauri::command]
+pub async fn get_engine_metrics(
+    state: State<'_, AppState>,
+    torrent_id: String,
+) -> Result<crate::engine::EngineMetricsSnapshot, String> {
+    let torrent_id = super::normalize_torrent_id(&torrent_id)?;
+    state.engine_controls.read().await
+        .get(&torrent_id)
+        .map(|control| control.handle.metrics_snapshot())
+        .ok_or_else(|| format!("Torrent not found: {}", torrent_id))
+}
+
+/// Compare every torrent's files with the disk and list files in the
+/// download dirs that no torrent accounts for. Read-only; progress is
+/// reported through `storage-audit-progress` events.
+#[tauri::command]
+pub async fn audit_storage(
+    app: tauri::AppHandle,
+    state: State<'_, AppState>,
+) -> Result<crate::audit::StorageAudit, String> {
+    let sessions = state.database.load_all_torrents()
+        .map_err(|e| format!("Failed to load torrents: {}", e))?;
+    let settings = state.database.load_settings().unwrap_or_default();
+    let roots = crate::audit::scan_roots(&settings.download_dir, &sessions);
+
+    let audit = tokio::task::spawn_blocking(move || {
+        crate::audit::run_audit(&sessions, &roots, crate::audit::MAX_SCAN_DEPTH, |progress| {
+            crate::events::emit_event(&app, crate::events::Event::StorageAuditProgress(progress));
+        })
+    })
+    .await
+    .map_err(|e| format!("Failed to audit storage: {}", e))?;
+
+    tracing::info!(
+        "Storage audit: {} torrents, {} orphan candidates ({} bytes)",
+        audit.torrents.len(),
+        audit.orphans.len(),
+        audit.orphan_bytes
+    );
+    Ok(audit)
+}
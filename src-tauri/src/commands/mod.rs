@@ -3,22 +3,32 @@
 //! Split into focused submodules for maintainability:
 //! - `general`: App info, settings, greeting
 //! - `torrent`: P2P torrent operations (add, remove, start, pause, load)
+//! - `batch`: Adding many torrents at once with shared options
 //! - `debrid`: Cloud debrid operations (add cloud torrent, cache, debrid torrent management)
 //! - `credentials`: Master password and credential management
-//! - `info`: Monitoring data (peers, trackers, pieces, files, disk space)
+//! - `info`: Monitoring data (peers, trackers, pieces, files, previews, disk space)
+//! - `queue`: Download queue ordering
+//!
+//! Commands that do more than read state are thin wrappers around a
+//! `*_internal` function taking `&AppState` (and an optional app handle),
+//! which the queue, the API and `tests/commands.rs` call directly.
 
 mod general;
 mod torrent;
+mod batch;
 mod debrid;
 mod credentials;
 mod info;
+mod queue;
 
 // Re-export all commands so lib.rs can reference them as commands::command_name
 pub use general::*;
 pub use torrent::*;
+pub use batch::*;
 pub use debrid::*;
 pub use credentials::*;
 pub use info::*;
+pub use queue::*;
 
 // Shared types used across submodules
 use serde::{Serialize, Deserialize};
@@ -34,6 +44,10 @@ pub struct TorrentMetadata {
     pub creation_date: Option<i64>,
     pub comment: Option<String>,
     pub created_by: Option<String>,
+    /// Consolidated storage for torrents with very many files
+    pub suggested_storage_mode: crate::disk::StorageMode,
+    /// Seeds and leechers from a quick scrape; unknown if no tracker answered
+    pub health: crate::tracker::health::SwarmHealth,
 }
 
 /// Credential status for frontend
@@ -45,12 +59,65 @@ pub struct CredentialStatus {
     pub last_validated: Option<i64>,
 }
 
+/// Whether a stored credential decrypts with the unlocked master password
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CredentialHealth {
+    Ok,
+    /// Encrypted under another salt than the current master password's
+    SaltMismatch,
+    /// Doesn't decrypt (corrupt, or saved before salts were recorded)
+    DecryptFailed,
+}
+
+/// Integrity of one provider's stored credential
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CredentialCheck {
+    pub provider: String,
+    pub health: CredentialHealth,
+}
+
+/// Result of `unlock_with_master_password`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UnlockResult {
+    pub unlocked: bool,
+    /// Every stored credential once unlocked (empty otherwise); keys that
+    /// aren't `ok` need to be entered again
+    pub credentials: Vec<CredentialCheck>,
+}
+
 /// Debrid settings for frontend
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DebridSettings {
     pub enable_debrid: bool,
     pub debrid_preference: Vec<String>,
     pub smart_mode_enabled: bool,
+    #[serde(default)]
+    pub delete_from_provider_after_download: bool,
+    /// What a finished cloud file does when its final name already exists
+    #[serde(default)]
+    pub file_collision: crate::database::FileCollisionPolicy,
+    #[serde(default)]
+    pub ask_before_selecting_cloud_files: bool,
+    /// API base URL overrides by provider ("torbox", "real-debrid")
+    #[serde(default)]
+    pub debrid_base_urls: std::collections::HashMap<String, String>,
+    /// What running cloud downloads do when debrid is switched off
+    #[serde(default)]
+    pub debrid_disable_policy: crate::database::DebridDisablePolicy,
+    /// Effective state right now; ignored on update
+    #[serde(default)]
+    pub status: DebridStatus,
+}
+
+/// What debrid can actually do at the moment, for deciding which UI to show
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DebridStatus {
+    pub enabled: bool,
+    /// The master password has been entered this session
+    pub unlocked: bool,
+    /// Providers with an API key loaded ("torbox", "real-debrid")
+    pub providers_configured: Vec<String>,
 }
 
 /// Parse a provider string from the frontend into a DebridProviderType.
@@ -62,3 +129,71 @@ pub(crate) fn parse_provider(provider: &str) -> Result<crate::debrid::types::Deb
         _ => Err(format!("Unknown provider: {}", provider)),
     }
 }
+
+/// Fail with `Error::FeatureDisabled` while debrid features are turned off.
+/// Every debrid and cloud command checks this first, except the settings
+/// commands needed to turn them back on.
+pub(crate) async fn require_debrid(state: &crate::state::AppState) -> Result<(), String> {
+    if state.debrid_manager.read().await.is_enabled() {
+        Ok(())
+    } else {
+        Err(crate::error::Error::FeatureDisabled("debrid".to_string()).to_string())
+    }
+}
+
+/// How an add of `torrent_id` from `adding` goes with whatever already
+/// downloads under that info hash (see `DownloadOrchestrator::admit`)
+pub(crate) async fn admit_download(
+    state: &crate::state::AppState,
+    torrent_id: &str,
+    adding: crate::download::Adding,
+    hybrid: bool,
+) -> Result<(crate::download::Admission, Option<crate::debrid::types::DownloadSource>), String> {
+    let existing = state.torrents.read().await.get(torrent_id).map(|torrent| torrent.source.clone());
+    let admission = crate::download::DownloadOrchestrator::admit(torrent_id, existing.as_ref(), adding, hybrid)
+        .map_err(|e| e.to_string())?;
+    Ok((admission, existing))
+}
+
+/// Parse a torrent id from the frontend (hex in any case, or base32) into the
+/// canonical lowercase hex key used by the state maps and the database.
+pub(crate) fn normalize_torrent_id(torrent_id: &str) -> Result<String, String> {
+    crate::ids::InfoHash::parse(torrent_id)
+        .map(|hash| hash.to_hex())
+        .map_err(|e| e.to_string())
+}
+
+/// Parse a debrid provider's torrent id from the frontend
+pub(crate) fn parse_debrid_id(torrent_id: &str) -> Result<crate::ids::DebridTorrentId, String> {
+    crate::ids::DebridTorrentId::parse(torrent_id).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_uppercase_id_finds_lowercase_torrent() {
+        // Torrents are stored under hex::encode output (lowercase)
+        let stored = "0123456789abcdef0123456789abcdef01234567".to_string();
+        let mut torrents = HashMap::new();
+        torrents.insert(stored.clone(), "ubuntu.iso");
+
+        let from_frontend = normalize_torrent_id("0123456789ABCDEF0123456789ABCDEF01234567").unwrap();
+        assert_eq!(from_frontend, stored);
+        assert_eq!(torrents.get(&from_frontend), Some(&"ubuntu.iso"));
+
+        // Base32 ids resolve to the same key
+        let from_base32 = normalize_torrent_id("AERUKZ4JVPG66AJDIVTYTK6N54ASGRLH").unwrap();
+        assert_eq!(torrents.get(&from_base32), Some(&"ubuntu.iso"));
+    }
+
+    #[test]
+    fn test_malformed_ids_are_rejected() {
+        let err = normalize_torrent_id("not-a-hash").unwrap_err();
+        assert!(err.starts_with("Validation error"));
+        assert!(parse_debrid_id("").is_err());
+        assert_eq!(parse_debrid_id("12345").unwrap().as_str(), "12345");
+    }
+}
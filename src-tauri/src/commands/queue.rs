@@ -0,0 +1,71 @@
+//! Download queue ordering
+
+use crate::queue::{self, QueueMove};
+use crate::state::AppState;
+use tauri::State;
+
+/// Load the queue, apply `reorder` and persist the renumbered positions
+async fn reorder_queue(
+    state: &AppState,
+    torrent_id: &str,
+    reorder: impl FnOnce(&mut Vec<String>) -> bool,
+) -> Result<(), String> {
+    let sessions = state.database
+        .load_all_torrents()
+        .map_err(|e| format!("Failed to load torrents: {}", e))?;
+
+    let mut order = queue::queue_order(&sessions);
+    if !reorder(&mut order) {
+        return Err(format!("Torrent is not queued: {}", torrent_id));
+    }
+
+    queue::save_order(state, sessions, &order)?;
+    queue::publish_positions(state, &order).await;
+
+    // Slots are handed out again once the user stops dragging
+    state.queue.request_reconcile();
+    Ok(())
+}
+
+/// Move a torrent to an absolute queue position (0 = first; clamped to the end)
+#[tauri::command]
+pub async fn set_queue_position(
+    state: State<'_, AppState>,
+    torrent_id: String,
+    position: u32,
+) -> Result<(), String> {
+    let torrent_id = super::normalize_torrent_id(&torrent_id)?;
+    reorder_queue(&state, &torrent_id, |order| {
+        queue::move_to(order, &torrent_id, position as usize)
+    })
+    .await
+}
+
+/// Move a torrent one place up
+#[tauri::command]
+pub async fn queue_move_up(state: State<'_, AppState>, torrent_id: String) -> Result<(), String> {
+    queue_move(&state, torrent_id, QueueMove::Up).await
+}
+
+/// Move a torrent one place down
+#[tauri::command]
+pub async fn queue_move_down(state: State<'_, AppState>, torrent_id: String) -> Result<(), String> {
+    queue_move(&state, torrent_id, QueueMove::Down).await
+}
+
+/// Move a torrent to the top of the queue
+#[tauri::command]
+pub async fn queue_move_top(state: State<'_, AppState>, torrent_id: String) -> Result<(), String> {
+    queue_move(&state, torrent_id, QueueMove::Top).await
+}
+
+/// Move a torrent to the bottom of the queue
+#[tauri::command]
+pub async fn queue_move_bottom(state: State<'_, AppState>, torrent_id: String) -> Result<(), String> {
+    queue_move(&state, torrent_id, QueueMove::Bottom).await
+}
+
+async fn queue_move(state: &AppState, torrent_id: String, movement: QueueMove) -> Result<(), String> {
+    let torrent_id = super::normalize_torrent_id(&torrent_id)?;
+    reorder_queue(state, &torrent_id, |order| queue::shift(order, &torrent_id, movement)).await
+}
@@ -1,16 +1,23 @@
 //! Torrent commands: add, remove, start, pause, load saved torrents
 
+use crate::events::{emit_event, Event, EventSink};
+use crate::provenance::AddedFrom;
 use crate::state::{AppState, TorrentInfo, TorrentState};
 use crate::torrent::Metainfo;
+use crate::tracker::health::{self, HealthSource, SwarmHealth};
+use crate::tracker::redact::redact_tracker_url;
 use crate::engine::TorrentEngine;
-use std::path::PathBuf;
+use futures::StreamExt;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use tauri::State;
+use tauri::{Manager, State};
 use tokio::sync::RwLock as TokioRwLock;
 
-/// Parse torrent metadata from .torrent file without adding it
+/// Parse torrent metadata from .torrent file without adding it, along with
+/// the swarm's health from a quick scrape of its trackers
 #[tauri::command]
-pub fn parse_torrent_file(file_path: String) -> Result<super::TorrentMetadata, String> {
+pub async fn parse_torrent_file(state: State<'_, AppState>, file_path: String) -> Result<super::TorrentMetadata, String> {
     tracing::info!("Parsing torrent file: {}", file_path);
 
     // Read .torrent file
@@ -31,29 +38,37 @@ pub fn parse_torrent_file(file_path: String) -> Result<super::TorrentMetadata, S
             downloaded: 0,
             priority: crate::torrent::FilePriority::Normal,
             is_folder: false,
+            on_disk_size: None,
+            modified: None,
+            prefix_only: None,
+            completed_at: None,
         })
         .collect();
+    let health = swarm_health(&state, &metainfo.info_hash, &crate::tracker::tracker_urls(&metainfo), false).await;
 
     Ok(super::TorrentMetadata {
         name: metainfo.info.name.clone(),
         info_hash: metainfo.info_hash_hex(),
         total_size: metainfo.info.total_size,
         files,
-        announce: metainfo.announce.clone(),
+        announce: redact_tracker_url(&metainfo.announce),
         creation_date: metainfo.creation_date,
         comment: metainfo.comment.clone(),
         created_by: metainfo.created_by.clone(),
+        suggested_storage_mode: crate::disk::StorageMode::suggested(&metainfo.info),
+        health,
     })
 }
 
-/// Parse torrent metadata from magnet link without adding it
+/// Parse torrent metadata from magnet link without adding it. The swarm's
+/// health comes from its trackers, or a debrid cache check without them.
 #[tauri::command]
-pub fn parse_magnet_link(magnet_uri: String) -> Result<super::TorrentMetadata, String> {
-    tracing::info!("Parsing magnet link: {}", magnet_uri);
-
+pub async fn parse_magnet_link(state: State<'_, AppState>, magnet_uri: String) -> Result<super::TorrentMetadata, String> {
     // Parse the magnet link
     let magnet = crate::magnet::MagnetLink::parse(&magnet_uri)
         .map_err(|e| format!("Failed to parse magnet link: {}", e))?;
+    tracing::info!("Parsing magnet link: {}", magnet.without_passkeys().to_uri());
+    let health = swarm_health(&state, &magnet.info_hash, &magnet.trackers, true).await;
 
     // For magnet links, we don't have full metadata yet
     Ok(super::TorrentMetadata {
@@ -61,38 +76,105 @@ pub fn parse_magnet_link(magnet_uri: String) -> Result<super::TorrentMetadata, S
         info_hash: magnet.info_hash_hex(),
         total_size: 0, // Unknown until we get metadata
         files: vec![], // Unknown until we get metadata
-        announce: magnet.trackers.first().cloned().unwrap_or_default(),
+        announce: magnet.trackers.first().map(|url| redact_tracker_url(url)).unwrap_or_default(),
         creation_date: None,
         comment: None,
         created_by: None,
+        suggested_storage_mode: crate::disk::StorageMode::Files,
+        health,
     })
 }
 
-/// Add a torrent from a .torrent file
-#[tauri::command]
-pub async fn add_torrent_file(
-    app: tauri::AppHandle,
-    state: State<'_, AppState>,
-    file_path: String,
-) -> Result<String, String> {
-    tracing::info!("Adding torrent from file: {}", file_path);
+/// Seeds and leechers for the add dialog, within `PROBE_BUDGET` however the
+/// trackers behave. Reused for a while, so confirming the dialog or parsing
+/// the torrent again doesn't scrape again.
+async fn swarm_health(state: &AppState, info_hash: &[u8; 20], trackers: &[String], ask_debrid: bool) -> SwarmHealth {
+    if let Some(health) = state.swarm_health.get(info_hash) {
+        return health;
+    }
+    let started = std::time::Instant::now();
+    let tracker = crate::tracker::http::HttpTracker::with_config(&state.tracker_http.borrow());
+    let anonymous = *state.anonymous_mode.borrow();
+    let mut health = health::probe(&tracker, trackers, info_hash, anonymous, health::PROBE_BUDGET).await;
+
+    // No tracker knows: a provider with it cached still says it is alive
+    let debrid_manager = state.debrid_manager.read().await;
+    if ask_debrid && health.source == HealthSource::Unknown && debrid_manager.is_enabled() {
+        let remaining = health::PROBE_BUDGET.saturating_sub(started.elapsed());
+        match tokio::time::timeout(remaining, debrid_manager.check_cache_all(&hex::encode(info_hash))).await {
+            Ok(Ok(results)) if !results.is_empty() => {
+                health.source = HealthSource::Debrid;
+                health.debrid_cached = Some(results.values().any(|status| status.is_cached));
+            }
+            Ok(Ok(_)) => {}
+            Ok(Err(e)) => tracing::debug!("Cache check for the add dialog failed: {}", e),
+            Err(_) => tracing::debug!("Cache check for the add dialog ran out of time"),
+        }
+    }
 
-    // Read .torrent file
-    let path = PathBuf::from(&file_path);
-    let data = std::fs::read(&path)
+    state.swarm_health.insert(*info_hash, health.clone());
+    health
+}
+
+/// Parse raw .torrent bytes (shared by every add path)
+pub(super) fn parse_torrent_bytes(data: &[u8]) -> Result<Metainfo, String> {
+    Metainfo::from_bytes(data).map_err(|e| format!("Failed to parse torrent: {}", e))
+}
+
+/// Read and parse a .torrent file from disk, returning its contents as well
+pub(super) fn read_torrent_file(file_path: &str) -> Result<(Metainfo, Vec<u8>), String> {
+    let data = std::fs::read(file_path)
         .map_err(|e| format!("Failed to read torrent file: {}", e))?;
+    Ok((parse_torrent_bytes(&data)?, data))
+}
 
-    // Parse metainfo
-    let metainfo = Metainfo::from_bytes(&data)
-        .map_err(|e| format!("Failed to parse torrent: {}", e))?;
+/// Parse a magnet link into a stub Metainfo plus the name to show until
+/// the real metadata arrives
+pub(super) fn parse_magnet(magnet_uri: &str) -> Result<(Metainfo, String), String> {
+    let magnet = crate::magnet::MagnetLink::parse(magnet_uri)
+        .map_err(|e| format!("Failed to parse magnet link: {}", e))?;
+
+    let torrent_id = magnet.info_hash_hex();
+    tracing::info!(
+        "Parsed magnet link - ID: {}, Name: {:?}, Trackers: {}",
+        torrent_id,
+        magnet.display_name,
+        magnet.trackers.len()
+    );
+
+    let name = magnet
+        .display_name
+        .clone()
+        .unwrap_or_else(|| format!("Magnet {}", &torrent_id[..8]));
+    let metainfo = Metainfo::from_magnet(magnet.info_hash, magnet.display_name, magnet.trackers);
+    Ok((metainfo, name))
+}
 
-    // Generate torrent ID from info hash
+/// UI and database records for a torrent that is about to be added
+pub(super) struct NewTorrent {
+    pub info: TorrentInfo,
+    pub session: crate::database::TorrentSession,
+    /// .torrent contents to stash with the session
+    pub torrent_file: Option<Vec<u8>>,
+}
+
+impl NewTorrent {
+    /// Record where the torrent came from, with the .torrent file if there was one
+    pub(super) fn with_source(mut self, added_from: AddedFrom, torrent_file: Option<Vec<u8>>) -> Self {
+        self.session.added_from = Some(added_from);
+        self.torrent_file = torrent_file.and_then(crate::provenance::stashable);
+        self
+    }
+}
+
+/// Build the paused UI entry and database session for a new P2P torrent
+pub(super) fn new_p2p_torrent(metainfo: Metainfo, name: String, download_dir: &Path) -> NewTorrent {
     let torrent_id = metainfo.info_hash_hex();
+    let now = chrono::Utc::now().timestamp();
 
-    // Create torrent info
-    let torrent_info = TorrentInfo {
+    let info = TorrentInfo {
         id: torrent_id.clone(),
-        name: metainfo.info.name.clone(),
+        name,
         size: metainfo.info.total_size,
         downloaded: 0,
         uploaded: 0,
@@ -102,77 +184,279 @@ pub async fn add_torrent_file(
         peers: 0,
         seeds: 0,
         source: crate::debrid::types::DownloadSource::P2P,
+        remote_deleted: false,
+        swarm_seeds: None,
+        swarm_leechers: None,
+        swarm_updated_at: None,
+        queue_position: None,
+        metadata_pending: !metainfo.has_metadata(),
+        name_encoding: metainfo.info.name_encoding(),
+        tags: Vec::new(),
+        error: None,
     };
 
-    // Add to state
-    state.torrents.write().await.insert(torrent_id.clone(), torrent_info);
-
-    // Save to database
-    let db_session = crate::database::TorrentSession {
-        id: torrent_id.clone(),
-        metainfo: metainfo.clone(),
-        bitfield: vec![],
+    let storage_mode = crate::disk::StorageMode::suggested(&metainfo.info);
+    let session = crate::database::TorrentSession {
+        id: torrent_id,
         num_pieces: metainfo.info.piece_count,
+        metainfo,
+        bitfield: Vec::new(),
         downloaded: 0,
         uploaded: 0,
         state: "paused".to_string(),
-        download_dir: dirs::download_dir()
-            .or_else(|| std::env::current_dir().ok())
-            .unwrap_or_else(|| PathBuf::from("."))
-            .to_string_lossy()
-            .to_string(),
-        added_at: chrono::Utc::now().timestamp(),
-        last_activity: chrono::Utc::now().timestamp(),
+        download_dir: download_dir.to_string_lossy().to_string(),
+        added_at: now,
+        last_activity: now,
         source: crate::debrid::types::DownloadSource::P2P,
         completed_at: None,
+        volume_id: crate::disk::volume_id(download_dir),
+        category: None,
+        traffic: Default::default(),
+        swarm: None,
+        root_name: None,
+        data_sync: Default::default(),
+        queue_position: None,
+        saved_peers: Vec::new(),
+        added_from: None,
+        storage_mode,
+        auto_stop: Default::default(),
+        low_disk_paused_at: None,
+        linked_files: Vec::new(),
+        disabled_trackers: Vec::new(),
+        file_completed_at: Default::default(),
+    };
+
+    NewTorrent { info, session, torrent_file: None }
+}
+
+/// Refuse a new P2P torrent that is already downloading from the cloud, or
+/// with `hybrid` make it the P2P side of that download
+async fn admit_p2p(state: &AppState, torrent: &mut NewTorrent, hybrid: bool) -> Result<(), String> {
+    use crate::download::{Adding, Admission, DownloadOrchestrator};
+
+    let (admission, existing) = super::admit_download(state, &torrent.session.id, Adding::P2P, hybrid).await?;
+    let remote = existing
+        .as_ref()
+        .and_then(|source| source.get_provider().zip(source.get_debrid_torrent_id().cloned()));
+    if let (Admission::Join, Some((provider, debrid_torrent_id))) = (admission, remote) {
+        tracing::info!("Joining the {} cloud download of {}", provider.display_name(), torrent.session.id);
+        let source = DownloadOrchestrator::hybrid_source(provider, debrid_torrent_id);
+        torrent.info.source = source.clone();
+        torrent.session.source = source;
+    }
+    Ok(())
+}
+
+/// Keep a new torrent's .torrent file. Only provenance depends on it, so a
+/// failure is logged rather than failing the add.
+pub(super) fn stash_torrent_file(state: &AppState, torrent: &NewTorrent) {
+    if let Some(data) = &torrent.torrent_file {
+        if let Err(e) = state.database.save_torrent_file(&torrent.session.id, data) {
+            tracing::warn!("Failed to keep the .torrent file of {}: {}", torrent.session.id, e);
+        }
+    }
+}
+
+/// Build the (paused) engine for a saved session, wired to the app-wide
+/// settings and restored to the session's progress. Every path that creates
+/// an engine goes through here.
+async fn build_engine(app: Option<tauri::AppHandle>, state: &AppState, session: &crate::database::TorrentSession) -> TorrentEngine {
+    let download_dir = PathBuf::from(&session.download_dir);
+    let mut engine = TorrentEngine::new(session.metainfo.clone(), download_dir, app.map(|app| Arc::new(app) as EventSink));
+    engine.set_tracked_id(&session.id);
+    if let Some(root_name) = &session.root_name {
+        engine.set_root_name(root_name);
+    }
+    engine.set_storage_mode(session.storage_mode).await;
+    if !session.linked_files.is_empty() {
+        let linked: Vec<usize> = session.linked_files.iter().map(|link| link.file_index).collect();
+        engine.disk_manager().write().await.set_linked_files(&linked);
+    }
+    engine.set_database(state.database.clone());
+    engine.set_listen_port(state.listen_port.subscribe());
+    engine.set_anonymous_mode(state.anonymous_mode.subscribe());
+    engine.set_tracker_http(state.tracker_http.subscribe());
+    engine.set_dial_pacer(state.dial_pacer.clone());
+    engine.set_external_ip(state.external_ip.clone());
+    engine.set_transfer_log(state.transfer_logs.get(&session.id));
+    engine.set_disconnect_history(state.disconnect_histories.get(&session.id));
+    engine.set_mmap_reads(state.mmap_reads.subscribe());
+    engine.set_completion_mtimes(state.completion_mtimes.subscribe());
+    engine.set_reactivate_paused_seeding(state.reactivate_paused_seeding.subscribe());
+    engine.set_notify_tracker_warnings(state.notify_tracker_warnings.subscribe());
+    engine.set_start_paused_seeding(session.state == "pausedseeding");
+    engine.set_completed_at(session.completed_at);
+    engine.set_file_completed_at(session.file_completed_at.clone());
+    engine.set_traffic_base(session.traffic);
+    engine.set_swarm_stats(session.swarm);
+    engine.set_disabled_trackers(&session.disabled_trackers).await;
+
+    // Restore bitfield from saved session
+    // (re-verifying pieces saved before their data was synced)
+    if !session.bitfield.is_empty() {
+        engine.restore_progress(&session.bitfield, &session.data_sync).await;
+    }
+    engine.restore_peers(&session.saved_peers, saved_peer_max_age(state).await).await;
+    engine
+}
+
+/// Put an engine in `state.engines` (replacing any old one) along with its controls
+async fn publish_engine(state: &AppState, torrent_id: &str, engine: TorrentEngine) {
+    state.engine_controls.write().await.insert(torrent_id.to_string(), engine.control());
+    state.engines.write().await.insert(torrent_id.to_string(), Arc::new(TokioRwLock::new(engine)));
+}
+
+/// Controls of a torrent's engine
+async fn engine_control(state: &AppState, torrent_id: &str) -> Result<crate::engine::EngineControl, String> {
+    state.engine_controls.read().await
+        .get(torrent_id)
+        .cloned()
+        .ok_or_else(|| format!("Torrent not found: {}", torrent_id))
+}
+
+/// Create the (paused) engine for an already-persisted torrent and publish it to state
+pub(super) async fn register_torrent(
+    app: Option<tauri::AppHandle>,
+    state: &AppState,
+    torrent: NewTorrent,
+) -> String {
+    let NewTorrent { info, session, .. } = torrent;
+
+    let engine = build_engine(app, state, &session).await;
+    publish_engine(state, &session.id, engine).await;
+    state.search_index.insert(&session);
+    crate::search::index_metainfo(&state.database, &session.id, &session.metainfo);
+    state.torrents.write().await.insert(session.id.clone(), info);
+
+    // New torrents join the bottom of the queue
+    if let Ok(sessions) = state.database.load_all_torrents() {
+        crate::queue::publish_positions(state, &crate::queue::queue_order(&sessions)).await;
+    }
+    state.queue.request_reconcile();
+
+    session.id
+}
+
+/// How old a saved peer may be and still get reused
+async fn saved_peer_max_age(state: &AppState) -> std::time::Duration {
+    std::time::Duration::from_secs(state.settings.read().await.saved_peer_max_age_secs)
+}
+
+/// Preflight a torrent's on-disk layout against what's already there
+///
+/// A collision is returned as an error, or with `rename` resolved by moving
+/// the torrent to a free " (n)" root name recorded in the session. Returns
+/// whether the session was renamed. Magnets without metadata are skipped.
+pub(super) fn resolve_path_collision(
+    session: &mut crate::database::TorrentSession,
+    rename: bool,
+) -> Result<bool, String> {
+    if session.metainfo.info.piece_count == 0 {
+        return Ok(false);
+    }
+
+    let download_dir = PathBuf::from(&session.download_dir);
+    let disk = crate::disk::DiskManager::with_root_name(
+        &session.metainfo,
+        download_dir.clone(),
+        session.root_name(),
+    );
+    let collision = match disk.check_layout() {
+        Ok(()) => return Ok(false),
+        Err(collision) => collision,
     };
+    if !rename {
+        return Err(collision.to_string());
+    }
+
+    let root_name = crate::disk::available_root_name(
+        &download_dir,
+        session.root_name(),
+        session.metainfo.info.is_single_file,
+    );
+    tracing::info!("{}; saving {} as {:?} instead", collision, session.id, root_name);
+    session.root_name = Some(root_name);
+    Ok(true)
+}
 
+/// Add a torrent from a .torrent file
+///
+/// With `rename_on_collision`, an existing file/folder in the way of the
+/// torrent's layout is sidestepped by saving it as "<name> (2)".
+///
+/// A torrent already downloading from the cloud is refused, unless `hybrid`
+/// is set: then the P2P download joins it as one hybrid torrent.
+#[tauri::command]
+pub async fn add_torrent_file(
+    app: tauri::AppHandle,
+    state: State<'_, AppState>,
+    file_path: String,
+    rename_on_collision: Option<bool>,
+    hybrid: Option<bool>,
+) -> Result<String, String> {
+    add_torrent_file_internal(
+        Some(app),
+        &state,
+        file_path,
+        rename_on_collision.unwrap_or(false),
+        hybrid.unwrap_or(false),
+    )
+    .await
+}
+
+pub async fn add_torrent_file_internal(
+    app: Option<tauri::AppHandle>,
+    state: &AppState,
+    file_path: String,
+    rename_on_collision: bool,
+    hybrid: bool,
+) -> Result<String, String> {
+    tracing::info!("Adding torrent from file: {}", file_path);
+
+    let (metainfo, data) = read_torrent_file(&file_path)?;
+    let name = metainfo.info.name.clone();
+
+    let download_dir = dirs::download_dir()
+        .or_else(|| std::env::current_dir().ok())
+        .unwrap_or_else(|| PathBuf::from("."));
+    let added_from = AddedFrom::TorrentFile { original_path: Some(file_path.clone()) };
+    let mut torrent = new_p2p_torrent(metainfo, name.clone(), &download_dir).with_source(added_from, Some(data));
+    admit_p2p(state, &mut torrent, hybrid).await?;
+    resolve_path_collision(&mut torrent.session, rename_on_collision)?;
+
+    // Save to database
     state.database
-        .save_torrent(&db_session)
+        .save_torrent(&torrent.session)
         .map_err(|e| format!("Failed to save torrent to database: {}", e))?;
+    stash_torrent_file(state, &torrent);
 
     // Create TorrentEngine instance (in paused state)
-    let download_dir = PathBuf::from(&db_session.download_dir);
-    let mut engine = TorrentEngine::new(metainfo.clone(), download_dir, Some(app));
-    engine.set_database(state.database.clone());
+    let torrent_id = register_torrent(app, state, torrent).await;
 
-    // Store engine in state
-    let engine_arc = Arc::new(TokioRwLock::new(engine));
-    state.engines.write().await.insert(torrent_id.clone(), engine_arc);
-
-    tracing::info!("Added torrent: {} ({})", metainfo.info.name, torrent_id);
+    tracing::info!("Added torrent: {} ({})", name, torrent_id);
 
     Ok(torrent_id)
 }
 
-/// Add a torrent from a magnet link
+/// Add a torrent from a magnet link; `hybrid` works as for `add_torrent_file`
 #[tauri::command]
 pub async fn add_magnet_link(
     app: tauri::AppHandle,
     state: State<'_, AppState>,
     magnet_uri: String,
+    hybrid: Option<bool>,
 ) -> Result<String, String> {
-    tracing::info!("Adding magnet link: {}", magnet_uri);
-
-    // Parse the magnet link
-    let magnet = crate::magnet::MagnetLink::parse(&magnet_uri)
-        .map_err(|e| format!("Failed to parse magnet link: {}", e))?;
-
-    let torrent_id = magnet.info_hash_hex();
-
-    tracing::info!(
-        "Parsed magnet link - ID: {}, Name: {:?}, Trackers: {}",
-        torrent_id,
-        magnet.display_name,
-        magnet.trackers.len()
-    );
+    add_magnet_link_internal(Some(app), &state, magnet_uri, hybrid.unwrap_or(false)).await
+}
 
-    // Create minimal Metainfo from magnet link
-    let metainfo = crate::torrent::Metainfo::from_magnet(
-        magnet.info_hash,
-        magnet.display_name.clone(),
-        magnet.trackers.clone(),
-    );
+pub async fn add_magnet_link_internal(
+    app: Option<tauri::AppHandle>,
+    state: &AppState,
+    magnet_uri: String,
+    hybrid: bool,
+) -> Result<String, String> {
+    let (metainfo, name) = parse_magnet(&magnet_uri)?;
+    tracing::info!("Adding magnet link: {}", metainfo.info_hash_hex());
 
     tracing::debug!("Loading settings from database");
     let download_dir = {
@@ -182,102 +466,123 @@ pub async fn add_magnet_link(
         PathBuf::from(db_settings.download_dir)
     };
 
-    tracing::debug!("Creating TorrentEngine for magnet");
-    let mut engine = TorrentEngine::new(metainfo.clone(), download_dir.clone(), Some(app));
-    engine.set_database(state.database.clone());
-
-    tracing::debug!("Storing engine in state");
-    let engine_arc = Arc::new(TokioRwLock::new(engine));
-    state.engines.write().await.insert(torrent_id.clone(), engine_arc);
-
-    tracing::debug!("Creating TorrentInfo for UI");
-    let torrent_info = TorrentInfo {
-        id: torrent_id.clone(),
-        name: magnet.display_name.unwrap_or_else(|| format!("Magnet {}", &torrent_id[..8])),
-        size: 0,
-        downloaded: 0,
-        uploaded: 0,
-        state: TorrentState::Paused,
-        download_speed: 0,
-        upload_speed: 0,
-        peers: 0,
-        seeds: 0,
-        source: crate::debrid::types::DownloadSource::P2P,
-    };
-
-    tracing::debug!("Adding to in-memory state");
-    state.torrents.write().await.insert(torrent_id.clone(), torrent_info);
+    let added_from = AddedFrom::magnet(&magnet_uri, *state.anonymous_mode.borrow());
+    let mut torrent = new_p2p_torrent(metainfo, name, &download_dir).with_source(added_from, None);
+    admit_p2p(state, &mut torrent, hybrid).await?;
 
     tracing::debug!("Saving to database");
-    let db_session = crate::database::TorrentSession {
-        id: torrent_id.clone(),
-        metainfo: metainfo.clone(),
-        download_dir: download_dir.to_string_lossy().to_string(),
-        state: "paused".to_string(),
-        downloaded: 0,
-        uploaded: 0,
-        last_activity: chrono::Utc::now().timestamp(),
-        bitfield: Vec::new(),
-        num_pieces: 0,
-        added_at: chrono::Utc::now().timestamp(),
-        source: crate::debrid::types::DownloadSource::P2P,
-        completed_at: None,
-    };
-
     state.database
-        .save_torrent(&db_session)
+        .save_torrent(&torrent.session)
         .map_err(|e| format!("Failed to save torrent to database: {}", e))?;
 
-    tracing::info!("Successfully added magnet link: {} ({})", metainfo.info.name, torrent_id);
+    tracing::debug!("Creating TorrentEngine for magnet");
+    let torrent_id = register_torrent(app, state, torrent).await;
 
+    tracing::info!("Successfully added magnet link: {}", torrent_id);
     Ok(torrent_id)
 }
 
 /// Remove a torrent
+///
+/// `delete_files` also deletes its data (for cloud torrents, the partial
+/// files of an unfinished download). `delete_from_provider` also deletes a
+/// cloud torrent from its debrid provider. `rollback_allocation` deletes
+/// only what a failed file allocation created, leaving anything that was
+/// there before.
 #[tauri::command]
 pub async fn remove_torrent(
     state: State<'_, AppState>,
     torrent_id: String,
     delete_files: bool,
+    delete_from_provider: Option<bool>,
+    rollback_allocation: Option<bool>,
 ) -> Result<(), String> {
-    remove_torrent_internal(&state, torrent_id, delete_files).await
+    let torrent_id = super::normalize_torrent_id(&torrent_id)?;
+    let rollback = rollback_allocation.unwrap_or(false);
+    remove_torrent_internal(&state, torrent_id, delete_files, delete_from_provider.unwrap_or(false), rollback).await
 }
 
 pub async fn remove_torrent_internal(
     state: &AppState,
     torrent_id: String,
     delete_files: bool,
+    delete_from_provider: bool,
+    rollback_allocation: bool,
 ) -> Result<(), String> {
-    tracing::info!("Removing torrent: {} (delete_files: {})", torrent_id, delete_files);
+    tracing::info!(
+        "Removing torrent: {} (delete_files: {}, delete_from_provider: {})",
+        torrent_id,
+        delete_files,
+        delete_from_provider
+    );
 
-    // Stop the engine if running — cancel token + stop command
-    {
-        let engines = state.engines.read().await;
-        if let Some(engine_arc) = engines.get(&torrent_id) {
-            let engine = engine_arc.read().await;
-            engine.cancel_token().cancel();
-            let _ = engine.command_sender().send(crate::engine::EngineCommand::Stop);
+    let removed = state.torrents.read().await.get(&torrent_id).cloned();
+    let source = match &removed {
+        Some(torrent) => Some(torrent.source.clone()),
+        None => state.database.load_torrent(&torrent_id).ok().flatten().map(|session| session.source),
+    };
+
+    // Cloud download: stop it before its files go; it would keep polling
+    // the provider and writing into its save path otherwise
+    if let Some(save_path) = state.cloud_downloads.cancel(&torrent_id).await {
+        if delete_files {
+            let names = state.cloud_file_progress.files(&torrent_id).await.into_iter().map(|file| file.name);
+            crate::cloud::remove_part_files(&save_path, names).await;
         }
     }
 
+    if delete_from_provider && !removed.as_ref().is_some_and(|torrent| torrent.remote_deleted) {
+        let remote = source.as_ref().and_then(|source| source.get_provider().zip(source.get_debrid_torrent_id()));
+        if let Some((provider, debrid_id)) = remote {
+            // The local side is gone either way; a failure here only leaves the remote copy
+            if let Err(e) = state.debrid_manager.read().await.delete_torrent(provider, debrid_id.as_str()).await {
+                tracing::warn!("Failed to delete {} from {}: {}", debrid_id.as_str(), provider.display_name(), e);
+            }
+        }
+    }
+
+    // Stop the engine if running — cancel token + stop command
+    if let Some(control) = state.engine_controls.write().await.remove(&torrent_id) {
+        control.cancel.cancel();
+        let _ = control.handle.stop();
+    }
+
     // Wait for task to complete and remove from task tracker
     if let Some(task_handle) = state.engine_tasks.write().await.remove(&torrent_id) {
         task_handle.abort();
     }
 
-    // Remove from engines HashMap
-    state.engines.write().await.remove(&torrent_id);
+    // Remove from engines HashMap; peer tasks may outlive the engine for a
+    // moment, so drop its file mappings before any file is deleted
+    let mut allocated = crate::disk::CreatedPaths::default();
+    if let Some(engine_arc) = state.engines.write().await.remove(&torrent_id) {
+        let mut engine = engine_arc.write().await;
+        engine.disk_manager().read().await.unmap_all();
+        allocated = engine.take_allocation_rollback();
+    }
 
     // Remove from torrents HashMap
     state.torrents.write().await.remove(&torrent_id);
+    state.search_index.remove(&torrent_id);
+    state.cloud_file_progress.remove(&torrent_id).await;
+    state.detail_subscriptions.unsubscribe(&torrent_id);
+    state.transfer_logs.remove(&torrent_id);
+    state.disconnect_histories.remove(&torrent_id);
 
     // Delete downloaded files if requested
     if delete_files {
         // Get download directory from database before deleting the entry
         if let Ok(Some(session)) = state.database.load_torrent(&torrent_id) {
             let download_dir = PathBuf::from(&session.download_dir);
-            let torrent_name = &session.metainfo.info.name;
-            let torrent_path = download_dir.join(torrent_name);
+            let torrent_path = download_dir.join(session.root_name());
+
+            let parts_path = crate::disk::parts_path(&download_dir, session.root_name());
+            if session.storage_mode == crate::disk::StorageMode::Consolidated && parts_path.is_file() {
+                match std::fs::remove_file(&parts_path) {
+                    Ok(()) => tracing::info!("Deleted consolidated data file: {:?}", parts_path),
+                    Err(e) => tracing::error!("Failed to delete consolidated data file {:?}: {}", parts_path, e),
+                }
+            }
 
             if torrent_path.exists() {
                 if torrent_path.is_dir() {
@@ -296,21 +601,67 @@ pub async fn remove_torrent_internal(
             } else {
                 tracing::warn!("Torrent path not found for deletion: {:?}", torrent_path);
             }
+
+            // Torrents whose files were linked to these still have their own
+            // names for the data; they just aren't shared anymore
+            let mut partners: Vec<&str> = session.linked_files.iter().map(|link| link.torrent_id.as_str()).collect();
+            partners.sort_unstable();
+            partners.dedup();
+            for partner_id in partners {
+                if let Ok(Some(mut partner)) = state.database.load_torrent(partner_id) {
+                    if crate::duplicates::forget_links_to(&mut partner, &torrent_id) {
+                        if let Err(e) = state.database.save_torrent(&partner) {
+                            tracing::warn!("Failed to update the file links of {}: {}", partner_id, e);
+                        }
+                    }
+                }
+            }
         }
     }
 
+    // Or only what a failed allocation left behind
+    if rollback_allocation && !delete_files && !allocated.is_empty() {
+        let removed = allocated.roll_back().await;
+        tracing::info!("Rolled back the failed allocation of {}: removed {} entries", torrent_id, removed);
+    }
+
     // Delete from database
     state.database
         .delete_torrent(&torrent_id)
         .map_err(|e| format!("Failed to delete torrent from database: {}", e))?;
 
+    state.queue.request_reconcile();
+
     tracing::info!("Removed torrent: {}", torrent_id);
     Ok(())
 }
 
 /// Start/resume a torrent
+///
+/// `rename_on_collision` works as for `add_torrent_file`.
 #[tauri::command]
-pub async fn start_torrent(state: State<'_, AppState>, torrent_id: String) -> Result<(), String> {
+pub async fn start_torrent(
+    state: State<'_, AppState>,
+    torrent_id: String,
+    rename_on_collision: Option<bool>,
+) -> Result<(), String> {
+    let torrent_id = super::normalize_torrent_id(&torrent_id)?;
+    start_torrent_internal(&state, torrent_id.clone(), rename_on_collision.unwrap_or(false)).await?;
+    if let Err(e) = crate::stall::clear_stop(&state, &torrent_id).await {
+        tracing::warn!("Failed to clear the {} tag of {}: {}", crate::stall::STALLED_DEAD_TAG, torrent_id, e);
+    }
+    if let Err(e) = crate::low_disk::clear_pause(&state, &torrent_id).await {
+        tracing::warn!("Failed to clear the {} tag of {}: {}", crate::low_disk::LOW_DISK_TAG, torrent_id, e);
+    }
+    state.queue.request_reconcile();
+    Ok(())
+}
+
+pub async fn start_torrent_internal(
+    state: &AppState,
+    torrent_id: String,
+    rename_on_collision: bool,
+) -> Result<(), String> {
     tracing::info!("Starting torrent: {}", torrent_id);
 
     // Check if engine exists
@@ -319,23 +670,48 @@ pub async fn start_torrent(state: State<'_, AppState>, torrent_id: String) -> Re
         .ok_or_else(|| format!("Torrent not found: {}", torrent_id))?
         .clone();
     drop(engines);
+    let control = engine_control(state, &torrent_id).await?;
 
-    // Check if already running
+    // Already running: resume it if it was paused or queued
     let engine_tasks = state.engine_tasks.read().await;
     if engine_tasks.contains_key(&torrent_id) {
-        tracing::warn!("Torrent {} is already running", torrent_id);
+        drop(engine_tasks);
+        let downloading = state.torrents.read().await
+            .get(&torrent_id)
+            .is_some_and(|t| t.state == TorrentState::Downloading);
+        if downloading {
+            tracing::warn!("Torrent {} is already running", torrent_id);
+            return Ok(());
+        }
+        control.handle
+            .send(crate::engine::EngineCommand::Start)
+            .await
+            .map_err(|e| format!("Failed to send start command: {}", e))?;
+        set_ui_state(state, &torrent_id, TorrentState::Downloading).await;
+        tracing::info!("Resumed torrent: {}", torrent_id);
         return Ok(());
     }
     drop(engine_tasks);
 
-    // Send Start command to engine
-    {
-        let engine = engine_arc.read().await;
-        let cmd_tx = engine.command_sender();
-        cmd_tx.send(crate::engine::EngineCommand::Start)
-            .map_err(|e| format!("Failed to send start command: {}", e))?;
+    // Never start onto a missing or swapped drive
+    check_download_dir(state, &torrent_id).await?;
+
+    // Nor into a layout that can't be created
+    if let Ok(Some(mut session)) = state.database.load_torrent(&torrent_id) {
+        if resolve_path_collision(&mut session, rename_on_collision)? {
+            state.database
+                .save_torrent(&session)
+                .map_err(|e| format!("Failed to save torrent to database: {}", e))?;
+            engine_arc.write().await.set_root_name(session.root_name());
+        }
     }
 
+    // Send Start command to engine
+    control.handle
+        .send(crate::engine::EngineCommand::Start)
+        .await
+        .map_err(|e| format!("Failed to send start command: {}", e))?;
+
     // Spawn the engine's event loop
     let task_handle = tokio::spawn(async move {
         let mut engine = engine_arc.write().await;
@@ -345,199 +721,634 @@ pub async fn start_torrent(state: State<'_, AppState>, torrent_id: String) -> Re
     // Store task handle
     state.engine_tasks.write().await.insert(torrent_id.clone(), task_handle);
 
-    // Update torrent state in UI
-    {
-        let mut torrents = state.torrents.write().await;
-        if let Some(torrent) = torrents.get_mut(&torrent_id) {
-            torrent.state = TorrentState::Downloading;
-        }
-    }
+    // A session saved in paused seeding starts back in it (see `build_engine`)
+    let paused_seeding = state.torrents.read().await
+        .get(&torrent_id)
+        .is_some_and(|t| t.state == TorrentState::PausedSeeding);
+    let new_state = if paused_seeding { TorrentState::PausedSeeding } else { TorrentState::Downloading };
+    set_ui_state(state, &torrent_id, new_state).await;
 
     tracing::info!("Started torrent: {}", torrent_id);
     Ok(())
 }
 
+async fn set_ui_state(state: &AppState, torrent_id: &str, new_state: TorrentState) {
+    if let Some(torrent) = state.torrents.write().await.get_mut(torrent_id) {
+        torrent.state = new_state;
+    }
+}
+
 /// Pause a torrent
 #[tauri::command]
 pub async fn pause_torrent(state: State<'_, AppState>, torrent_id: String) -> Result<(), String> {
-    tracing::info!("Pausing torrent: {}", torrent_id);
+    let torrent_id = super::normalize_torrent_id(&torrent_id)?;
+    pause_torrent_internal(&state, &torrent_id, TorrentState::Paused).await?;
+    state.queue.request_reconcile();
+    Ok(())
+}
 
-    // Get engine
-    let engines = state.engines.read().await;
-    let engine_arc = engines.get(&torrent_id)
-        .ok_or_else(|| format!("Torrent not found: {}", torrent_id))?
-        .clone();
-    drop(engines);
+/// Pause a torrent's engine; `new_state` is `Paused` for the user, `Queued`
+/// when the queue takes its slot away
+pub async fn pause_torrent_internal(
+    state: &AppState,
+    torrent_id: &str,
+    new_state: TorrentState,
+) -> Result<(), String> {
+    tracing::info!("Pausing torrent: {}", torrent_id);
 
     // Send Pause command to engine
-    {
-        let engine = engine_arc.read().await;
-        let cmd_tx = engine.command_sender();
-        cmd_tx.send(crate::engine::EngineCommand::Pause)
-            .map_err(|e| format!("Failed to send pause command: {}", e))?;
-    }
+    let command = if new_state == TorrentState::Queued {
+        crate::engine::EngineCommand::Queue
+    } else {
+        crate::engine::EngineCommand::Pause
+    };
+    engine_control(state, torrent_id).await?
+        .handle
+        .send(command)
+        .await
+        .map_err(|e| format!("Failed to send pause command: {}", e))?;
 
-    // Update torrent state in UI
-    {
-        let mut torrents = state.torrents.write().await;
-        if let Some(torrent) = torrents.get_mut(&torrent_id) {
-            torrent.state = TorrentState::Paused;
-        }
-    }
+    set_ui_state(state, torrent_id, new_state).await;
 
     tracing::info!("Paused torrent: {}", torrent_id);
     Ok(())
 }
 
+/// Keep a complete torrent announced to its trackers but drop its peers and
+/// stop uploading. `start_torrent` brings it back to full seeding, as does
+/// an announce reporting leechers when `reactivate_paused_seeding` is on.
+#[tauri::command]
+pub async fn pause_seeding(state: State<'_, AppState>, torrent_id: String) -> Result<(), String> {
+    let torrent_id = super::normalize_torrent_id(&torrent_id)?;
+    let current = state.torrents.read().await
+        .get(&torrent_id)
+        .map(|t| t.state)
+        .ok_or_else(|| format!("Torrent not found: {}", torrent_id))?;
+    if current == TorrentState::PausedSeeding {
+        return Ok(());
+    }
+    let complete = state.database
+        .load_torrent(&torrent_id)
+        .map_err(|e| format!("Failed to load torrent: {}", e))?
+        .is_some_and(|session| session.completed_at.is_some());
+    if !complete || !matches!(current, TorrentState::Seeding | TorrentState::Paused) {
+        return Err(format!("Only complete torrents can pause seeding ({:?})", current));
+    }
+    if !state.engine_tasks.read().await.contains_key(&torrent_id) {
+        return Err("Start the torrent before pausing seeding".to_string());
+    }
+
+    engine_control(&state, &torrent_id).await?
+        .handle
+        .send(crate::engine::EngineCommand::PauseSeeding)
+        .await
+        .map_err(|e| format!("Failed to send pause seeding command: {}", e))?;
+    set_ui_state(&state, &torrent_id, TorrentState::PausedSeeding).await;
+
+    tracing::info!("Paused seeding of torrent: {}", torrent_id);
+    Ok(())
+}
+
+/// Let the dead download policy stop this torrent (the default) or not
+#[tauri::command]
+pub async fn set_torrent_auto_stop(
+    state: State<'_, AppState>,
+    torrent_id: String,
+    enabled: bool,
+) -> Result<(), String> {
+    let torrent_id = super::normalize_torrent_id(&torrent_id)?;
+    crate::stall::update(&state, &torrent_id, |auto_stop| auto_stop.exempt = !enabled).await?;
+    tracing::info!("Dead download auto-stop {} for {}", if enabled { "enabled" } else { "disabled" }, torrent_id);
+    Ok(())
+}
+
 /// Get detailed info about a specific torrent
 #[tauri::command]
 pub async fn get_torrent_details(
     state: State<'_, AppState>,
     torrent_id: String,
 ) -> Result<TorrentInfo, String> {
+    let torrent_id = super::normalize_torrent_id(&torrent_id)?;
     state.torrents.read().await
         .get(&torrent_id)
         .cloned()
         .ok_or_else(|| format!("Torrent not found: {}", torrent_id))
 }
 
+/// Build a shareable magnet link for a saved torrent. Tracker passkeys are
+/// masked unless `include_passkeys` is set.
+#[tauri::command]
+pub async fn get_magnet_link(
+    state: State<'_, AppState>,
+    torrent_id: String,
+    include_passkeys: Option<bool>,
+) -> Result<String, String> {
+    let torrent_id = super::normalize_torrent_id(&torrent_id)?;
+
+    let session = state.database
+        .load_torrent(&torrent_id)
+        .map_err(|e| format!("Failed to load torrent: {}", e))?
+        .ok_or_else(|| format!("Torrent not found: {}", torrent_id))?;
+
+    let magnet = crate::magnet::MagnetLink::from_metainfo(&session.metainfo);
+    if include_passkeys.unwrap_or(false) {
+        Ok(magnet.to_uri())
+    } else {
+        Ok(magnet.without_passkeys().to_uri())
+    }
+}
+
+/// Where a torrent was added from
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct TorrentProvenance {
+    /// None for torrents added before this was recorded
+    pub added_from: Option<AddedFrom>,
+    /// Size of the stashed .torrent file, if one was kept
+    pub torrent_file_size: Option<usize>,
+}
+
+/// Get where a torrent was added from
+#[tauri::command]
+pub async fn get_torrent_provenance(
+    state: State<'_, AppState>,
+    torrent_id: String,
+) -> Result<TorrentProvenance, String> {
+    let torrent_id = super::normalize_torrent_id(&torrent_id)?;
+
+    let session = state.database
+        .load_torrent(&torrent_id)
+        .map_err(|e| format!("Failed to load torrent: {}", e))?
+        .ok_or_else(|| format!("Torrent not found: {}", torrent_id))?;
+    let torrent_file = state.database
+        .load_torrent_file(&torrent_id)
+        .map_err(|e| format!("Failed to load torrent file: {}", e))?;
+
+    Ok(TorrentProvenance {
+        added_from: session.added_from,
+        torrent_file_size: torrent_file.map(|data| data.len()),
+    })
+}
+
+/// Write the .torrent file a torrent was added from to `path`. The file is
+/// written as added unless `include_passkeys` is false, which masks the
+/// passkeys in its tracker URLs (the info hash stays the same).
+#[tauri::command]
+pub async fn export_torrent_file(
+    state: State<'_, AppState>,
+    torrent_id: String,
+    path: String,
+    include_passkeys: Option<bool>,
+) -> Result<(), String> {
+    let torrent_id = super::normalize_torrent_id(&torrent_id)?;
+
+    let data = state.database
+        .load_torrent_file(&torrent_id)
+        .map_err(|e| format!("Failed to load torrent file: {}", e))?
+        .ok_or_else(|| format!("No .torrent file was kept for {}", torrent_id))?;
+    let data = if include_passkeys.unwrap_or(true) {
+        data
+    } else {
+        crate::tracker::redact::redact_torrent_file(&data).map_err(|e| format!("Failed to strip passkeys: {}", e))?
+    };
+    std::fs::write(&path, data).map_err(|e| format!("Failed to write torrent file: {}", e))?;
+
+    tracing::info!("Exported the .torrent file of {} to {}", torrent_id, path);
+    Ok(())
+}
+
+/// Decide the in-memory state of a saved session at startup.
+///
+/// Sessions whose download directory is missing or on another volume come back
+/// as `MissingFiles` (with the reason) so they are not auto-started.
+fn startup_state(session: &crate::database::TorrentSession) -> (TorrentState, Option<String>) {
+    let saved_state = match session.state.as_str() {
+        "downloading" => TorrentState::Downloading,
+        "seeding" => TorrentState::Seeding,
+        "paused" => TorrentState::Paused,
+        "queued" => TorrentState::Queued,
+        "pausedseeding" => TorrentState::PausedSeeding,
+        "stopped" => TorrentState::Paused,
+        _ => TorrentState::Paused,
+    };
+
+    match crate::disk::verify_download_dir(Path::new(&session.download_dir), session.volume_id) {
+        Ok(()) => (saved_state, None),
+        Err(reason) => (TorrentState::MissingFiles, Some(reason)),
+    }
+}
+
+/// Notify the UI that a torrent's download directory is unavailable
+fn emit_missing_files(app: &tauri::AppHandle, torrent_id: &str, download_dir: &str, reason: String) {
+
+    let event = crate::events::MissingFilesEvent {
+        torrent_id: torrent_id.to_string(),
+        download_dir: download_dir.to_string(),
+        reason,
+    };
+    emit_event(app, Event::MissingFiles(event));
+}
+
+/// Refuse to start a torrent whose download directory is missing or moved,
+/// flagging it as `MissingFiles` for the UI
+async fn check_download_dir(state: &AppState, torrent_id: &str) -> Result<(), String> {
+    let session = match state.database.load_torrent(torrent_id) {
+        Ok(Some(session)) => session,
+        _ => return Ok(()),
+    };
+
+    if let Err(reason) =
+        crate::disk::verify_download_dir(Path::new(&session.download_dir), session.volume_id)
+    {
+        if let Some(torrent) = state.torrents.write().await.get_mut(torrent_id) {
+            torrent.state = TorrentState::MissingFiles;
+        }
+        return Err(reason);
+    }
+
+    Ok(())
+}
+
+/// Re-check a `MissingFiles` torrent, e.g. after the drive was mounted again
+#[tauri::command]
+pub async fn recover_torrent(
+    state: State<'_, AppState>,
+    torrent_id: String,
+) -> Result<TorrentInfo, String> {
+    let torrent_id = super::normalize_torrent_id(&torrent_id)?;
+    tracing::info!("Recovering torrent: {}", torrent_id);
+
+    let mut session = state.database
+        .load_torrent(&torrent_id)
+        .map_err(|e| format!("Failed to load torrent: {}", e))?
+        .ok_or_else(|| format!("Torrent not found: {}", torrent_id))?;
+
+    crate::disk::verify_download_dir(Path::new(&session.download_dir), session.volume_id)?;
+
+    // Sessions added before volume tracking get their volume pinned now
+    if session.volume_id.is_none() {
+        session.volume_id = crate::disk::volume_id(Path::new(&session.download_dir));
+        state.database
+            .save_torrent(&session)
+            .map_err(|e| format!("Failed to save torrent to database: {}", e))?;
+    }
+
+    let mut torrents = state.torrents.write().await;
+    let torrent = torrents.get_mut(&torrent_id)
+        .ok_or_else(|| format!("Torrent not found: {}", torrent_id))?;
+    if torrent.state == TorrentState::MissingFiles {
+        torrent.state = TorrentState::Paused;
+    }
+
+    tracing::info!("Recovered torrent: {}", torrent_id);
+    Ok(torrent.clone())
+}
+
+/// Deliberately point a torrent at a new download directory
+#[tauri::command]
+pub async fn relocate_torrent(
+    app: tauri::AppHandle,
+    state: State<'_, AppState>,
+    torrent_id: String,
+    new_path: String,
+) -> Result<TorrentInfo, String> {
+    let torrent_id = super::normalize_torrent_id(&torrent_id)?;
+    tracing::info!("Relocating torrent {} to {}", torrent_id, new_path);
+
+    if state.engine_tasks.read().await.contains_key(&torrent_id) {
+        return Err("Pause the torrent before relocating it".to_string());
+    }
+
+    let new_dir = PathBuf::from(&new_path);
+    crate::disk::verify_download_dir(&new_dir, None)?;
+
+    let mut session = state.database
+        .load_torrent(&torrent_id)
+        .map_err(|e| format!("Failed to load torrent: {}", e))?
+        .ok_or_else(|| format!("Torrent not found: {}", torrent_id))?;
+
+    session.download_dir = new_dir.to_string_lossy().to_string();
+    session.volume_id = crate::disk::volume_id(&new_dir);
+    state.database
+        .save_torrent(&session)
+        .map_err(|e| format!("Failed to save torrent to database: {}", e))?;
+
+    // Rebuild the engine so its disk manager uses the new location
+    let engine = build_engine(Some(app), &state, &session).await;
+    publish_engine(&state, &torrent_id, engine).await;
+
+    let mut torrents = state.torrents.write().await;
+    let torrent = torrents.get_mut(&torrent_id)
+        .ok_or_else(|| format!("Torrent not found: {}", torrent_id))?;
+    torrent.state = TorrentState::Paused;
+
+    tracing::info!("Relocated torrent {} to {}", torrent_id, session.download_dir);
+    Ok(torrent.clone())
+}
+
+/// Split a complete torrent stored consolidated into its real files (this
+/// normally happens on completion by itself)
+#[tauri::command]
+pub async fn extract_torrent_data(state: State<'_, AppState>, torrent_id: String) -> Result<(), String> {
+    let torrent_id = super::normalize_torrent_id(&torrent_id)?;
+    tracing::info!("Extracting consolidated data of {}", torrent_id);
+
+    // A running engine holds its own lock, so it is asked to do it
+    if state.engine_tasks.read().await.contains_key(&torrent_id) {
+        let control = engine_control(&state, &torrent_id).await?;
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        control.handle
+            .send(crate::engine::EngineCommand::ExtractParts(tx))
+            .await
+            .map_err(|e| format!("Failed to send extract command: {}", e))?;
+        return rx.await.map_err(|_| "Engine stopped before extracting".to_string())?;
+    }
+
+    let engine = state.engines.read().await
+        .get(&torrent_id)
+        .cloned()
+        .ok_or_else(|| format!("Torrent not found: {}", torrent_id))?;
+    let mut engine = engine.write().await;
+    engine.extract_parts().await
+}
+
+/// Replace a torrent's copies of the files it shares with `canonical_id` by
+/// hard links to the canonical copies (see `duplicates`). Both torrents must
+/// be paused; files on another filesystem or with different bytes are skipped.
+#[tauri::command]
+pub async fn relink_duplicates(
+    state: State<'_, AppState>,
+    torrent_id: String,
+    canonical_id: String,
+) -> Result<crate::duplicates::RelinkReport, String> {
+    let torrent_id = super::normalize_torrent_id(&torrent_id)?;
+    let canonical_id = super::normalize_torrent_id(&canonical_id)?;
+    if torrent_id == canonical_id {
+        return Err("A torrent can't be linked to itself".to_string());
+    }
+    tracing::info!("Relinking the files {} shares with {}", torrent_id, canonical_id);
+
+    {
+        let tasks = state.engine_tasks.read().await;
+        if tasks.contains_key(&torrent_id) || tasks.contains_key(&canonical_id) {
+            return Err("Pause both torrents before relinking their files".to_string());
+        }
+    }
+
+    let load = |id: &str| {
+        state.database
+            .load_torrent(id)
+            .map_err(|e| format!("Failed to load torrent: {}", e))?
+            .ok_or_else(|| format!("Torrent not found: {}", id))
+    };
+    let member = load(&torrent_id)?;
+    let canonical = load(&canonical_id)?;
+
+    let (report, member, canonical) = tokio::task::spawn_blocking(move || {
+        use crate::duplicates::{overlap, record_links, relink, TorrentContent};
+        let (mut member, mut canonical) = (member, canonical);
+        let ours = TorrentContent::from_session(&member);
+        let theirs = TorrentContent::from_session(&canonical);
+        let matches = overlap(&ours, &theirs).map(|overlap| overlap.files).unwrap_or_default();
+        if matches.is_empty() {
+            return Err(format!("{} has no complete files matching {}", member.id, canonical.id));
+        }
+        let report = relink(&ours, &theirs, &matches);
+        record_links(&mut member, &mut canonical, &report.linked);
+        Ok((report, member, canonical))
+    })
+    .await
+    .map_err(|e| format!("Failed to relink files: {}", e))??;
+
+    for session in [&member, &canonical] {
+        state.database
+            .save_torrent(session)
+            .map_err(|e| format!("Failed to save torrent to database: {}", e))?;
+
+        // The engines may have the replaced files mapped, and must break the
+        // links before writing
+        if let Some(engine) = state.engines.read().await.get(&session.id).cloned() {
+            let disk_manager = engine.read().await.disk_manager();
+            let mut disk_manager = disk_manager.write().await;
+            disk_manager.unmap_all();
+            let linked: Vec<usize> = session.linked_files.iter().map(|link| link.file_index).collect();
+            disk_manager.set_linked_files(&linked);
+        }
+    }
+
+    tracing::info!(
+        "Linked {} files of {} ({} bytes freed), skipped {}",
+        report.linked.len(),
+        torrent_id,
+        report.bytes_saved,
+        report.skipped.len()
+    );
+    Ok(report)
+}
+
+/// Add a peer by "ip:port" ("[ip]:port" for IPv6) from the peers tab.
+/// Returns whether it is being dialed now or waits for the torrent to start.
+#[tauri::command]
+pub async fn add_peer_manually(
+    state: State<'_, AppState>,
+    torrent_id: String,
+    addr: String,
+) -> Result<crate::peer::manual::ManualDial, String> {
+    add_peer_manually_internal(&state, torrent_id, addr).await
+}
+
+pub async fn add_peer_manually_internal(
+    state: &AppState,
+    torrent_id: String,
+    addr: String,
+) -> Result<crate::peer::manual::ManualDial, String> {
+    let torrent_id = super::normalize_torrent_id(&torrent_id)?;
+    let addr = crate::peer::manual::parse_peer_addr(&addr)?;
+    let listen_port = *state.listen_port.borrow();
+    crate::peer::manual::check_peer_addr(addr, listen_port)?;
+    if state.external_ip.is_self(addr, listen_port) {
+        return Err(format!("{} is this client's own public address", addr));
+    }
+    tracing::info!("Adding peer {} to {} by hand", addr, torrent_id);
+
+    // A running engine holds its own lock, so it is asked to do it
+    if state.engine_tasks.read().await.contains_key(&torrent_id) {
+        let control = engine_control(state, &torrent_id).await?;
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        control.handle
+            .send(crate::engine::EngineCommand::AddManualPeer(addr, tx))
+            .await
+            .map_err(|e| format!("Failed to send add peer command: {}", e))?;
+        return rx.await.map_err(|_| "Engine stopped before adding the peer".to_string());
+    }
+
+    let engine = state.engines.read().await
+        .get(&torrent_id)
+        .cloned()
+        .ok_or_else(|| format!("Torrent not found: {}", torrent_id))?;
+    let engine = engine.read().await;
+    Ok(engine.add_manual_peer(addr).await)
+}
+
+/// Engines built at the same time while loading saved torrents
+const LOAD_PARALLELISM: usize = 8;
+
+/// Saved torrents whose engines are still to be built
+pub struct PendingEngines {
+    sessions: Vec<crate::database::TorrentSession>,
+    /// Torrents that were running and aren't left to the download queue
+    auto_start: Vec<String>,
+}
+
 /// Load all saved torrents from database
+///
+/// Returns as soon as the torrents are listed in state; their engines are
+/// built in the background, each announced by a `torrent-ready` event.
 #[tauri::command]
 pub async fn load_saved_torrents(
     app: tauri::AppHandle,
     state: State<'_, AppState>
 ) -> Result<Vec<TorrentInfo>, String> {
+    let (torrents, pending) = list_saved_torrents(Some(&app), &state).await?;
+    tokio::spawn(async move {
+        let state = app.state::<AppState>();
+        build_saved_engines(Some(app.clone()), &state, pending).await;
+    });
+    Ok(torrents)
+}
+
+/// Load all saved torrents and wait until their engines are built
+pub async fn load_saved_torrents_internal(
+    app: Option<tauri::AppHandle>,
+    state: &AppState,
+) -> Result<Vec<TorrentInfo>, String> {
+    let (torrents, pending) = list_saved_torrents(app.as_ref(), state).await?;
+    build_saved_engines(app, state, pending).await;
+    Ok(torrents)
+}
+
+/// Put every saved torrent in `state.torrents` and the search index (one
+/// write lock), leaving out the engines
+pub async fn list_saved_torrents(
+    app: Option<&tauri::AppHandle>,
+    state: &AppState,
+) -> Result<(Vec<TorrentInfo>, PendingEngines), String> {
     tracing::info!("Loading saved torrents from database");
 
     let sessions = state.database
         .load_all_torrents()
         .map_err(|e| format!("Failed to load torrents from database: {}", e))?;
 
-    let mut torrents = Vec::new();
-    let mut new_engines = Vec::new();
-    let mut new_tasks = Vec::new();
+    let queue_order = crate::queue::queue_order(&sessions);
+    let existing_engines: HashSet<String> = state.engines.read().await.keys().cloned().collect();
 
-    // Check which engines already exist (single read lock)
-    let existing_engines = {
-        let engines = state.engines.read().await;
-        sessions.iter()
-            .map(|s| (s.id.clone(), engines.contains_key(&s.id)))
-            .collect::<std::collections::HashMap<String, bool>>()
-    };
-
-    // Process all sessions and create engines/tasks
+    let mut torrents = Vec::with_capacity(sessions.len());
+    let mut pending = PendingEngines { sessions: Vec::new(), auto_start: Vec::new() };
     for session in sessions {
-        // Wrap in a catch to prevent one bad torrent from breaking all loading
-        let process_result = async {
-            // Convert database session to TorrentInfo
-            let torrent_state = match session.state.as_str() {
-                "downloading" => TorrentState::Downloading,
-                "seeding" => TorrentState::Seeding,
-                "paused" => TorrentState::Paused,
-                "stopped" => TorrentState::Paused,
-                _ => TorrentState::Paused,
-            };
-
-            let torrent_info = TorrentInfo {
-                id: session.id.clone(),
-                name: session.metainfo.info.name.clone(),
-                size: session.metainfo.info.total_size,
-                downloaded: session.downloaded,
-                uploaded: session.uploaded,
-                state: torrent_state,
-                download_speed: 0,
-                upload_speed: 0,
-                peers: 0,
-                seeds: 0,
-                source: session.source.clone(),
-            };
-
-            // Create engine for this torrent (if not already exists)
-            if !existing_engines.get(&session.id).unwrap_or(&false) {
-                let download_dir = PathBuf::from(&session.download_dir);
-                let mut engine = TorrentEngine::new(session.metainfo.clone(), download_dir, Some(app.clone()));
-                engine.set_database(state.database.clone());
-                engine.set_completed_at(session.completed_at);
-
-                // Restore bitfield from saved session
-                if !session.bitfield.is_empty() {
-                    let pm = engine.piece_manager();
-                    let mut pm_guard = pm.write().await;
-                    pm_guard.restore_bitfield(&session.bitfield);
-                }
-
-                let engine_arc = Arc::new(TokioRwLock::new(engine));
-                
-                // Prepare for batch insertion
-                new_engines.push((session.id.clone(), engine_arc.clone()));
-
-                // Auto-start if it was downloading/seeding before
-                if torrent_state == TorrentState::Downloading || torrent_state == TorrentState::Seeding {
-                    tracing::info!("Auto-starting torrent: {}", session.id);
-
-                    // Clone Arc for the spawned task
-                    let engine_arc_clone = engine_arc.clone();
-                    
-                    // Send Start command
-                    {
-                        let engine = engine_arc.read().await;
-                        let cmd_tx = engine.command_sender();
-                        let _ = cmd_tx.send(crate::engine::EngineCommand::Start);
-                    }
-
-                    // Spawn the engine's event loop
-                    let task_handle = tokio::spawn(async move {
-                        let mut engine = engine_arc_clone.write().await;
-                        engine.run().await;
-                    });
+        let (mut torrent_state, missing_reason) = startup_state(&session);
+        let queue_position = queue_order.iter()
+            .position(|id| *id == session.id)
+            .map(|p| p as u32);
+
+        // Unfinished downloads wait for the queue to start them in order
+        if torrent_state == TorrentState::Downloading && queue_position.is_some() {
+            torrent_state = TorrentState::Queued;
+        }
 
-                    // Prepare for batch insertion
-                    new_tasks.push((session.id.clone(), task_handle));
-                }
+        if let Some(reason) = missing_reason {
+            tracing::warn!("Not resuming {}: {}", session.id, reason);
+            if let Some(app) = app {
+                emit_missing_files(app, &session.id, &session.download_dir, reason);
             }
+        }
 
-            Ok::<_, String>((session.id.clone(), torrent_info))
-        }.await;
-
-        match process_result {
-            Ok((id, info)) => {
-                torrents.push((id, info));
-            }
-            Err(e) => {
-                tracing::error!("Failed to load torrent {}: {}", session.id, e);
+        torrents.push(TorrentInfo {
+            id: session.id.clone(),
+            name: session.metainfo.info.name.clone(),
+            size: session.metainfo.info.total_size,
+            downloaded: session.downloaded,
+            uploaded: session.uploaded,
+            state: torrent_state,
+            download_speed: 0,
+            upload_speed: 0,
+            peers: 0,
+            seeds: 0,
+            source: session.source.clone(),
+            remote_deleted: false,
+            swarm_seeds: session.swarm.map(|s| s.seeds),
+            swarm_leechers: session.swarm.map(|s| s.leechers),
+            swarm_updated_at: session.swarm.map(|s| s.updated_at),
+            queue_position,
+            metadata_pending: !session.metainfo.has_metadata(),
+            name_encoding: session.metainfo.info.name_encoding(),
+            tags: session.tags(),
+            error: None,
+        });
+        state.search_index.insert(&session);
+
+        if !existing_engines.contains(&session.id) {
+            // Seeding (or otherwise unqueued) torrents that were running resume too
+            if matches!(torrent_state, TorrentState::Downloading | TorrentState::Seeding | TorrentState::PausedSeeding) {
+                pending.auto_start.push(session.id.clone());
             }
+            pending.sessions.push(session);
         }
     }
 
-    // Batch insert all new engines (single write lock)
-    if !new_engines.is_empty() {
-        let mut engines = state.engines.write().await;
-        for (id, engine) in new_engines {
-            engines.insert(id, engine);
+    {
+        let mut torrents_map = state.torrents.write().await;
+        for info in &torrents {
+            torrents_map.insert(info.id.clone(), info.clone());
         }
     }
 
-    // Batch insert all new tasks (single write lock)
-    if !new_tasks.is_empty() {
-        let mut tasks = state.engine_tasks.write().await;
-        for (id, task) in new_tasks {
-            tasks.insert(id, task);
+    tracing::info!("Loaded {} torrents from database", torrents.len());
+    Ok((torrents, pending))
+}
+
+/// Build and publish the engines of `list_saved_torrents`, `LOAD_PARALLELISM`
+/// at a time, then leave the starting to the download queue
+pub async fn build_saved_engines(app: Option<tauri::AppHandle>, state: &AppState, pending: PendingEngines) {
+    build_saved_engines_with(app.clone(), state, pending, |session| {
+        let app = app.clone();
+        async move { build_engine(app, state, &session).await }
+    })
+    .await;
+}
+
+async fn build_saved_engines_with<F, Fut>(
+    app: Option<tauri::AppHandle>,
+    state: &AppState,
+    pending: PendingEngines,
+    build: F,
+) where
+    F: Fn(crate::database::TorrentSession) -> Fut,
+    Fut: std::future::Future<Output = TorrentEngine>,
+{
+    let PendingEngines { sessions, auto_start } = pending;
+    let count = sessions.len();
+
+    let mut built = futures::stream::iter(sessions)
+        .map(|session| {
+            let id = session.id.clone();
+            let engine = build(session);
+            async move { (id, engine.await) }
+        })
+        .buffer_unordered(LOAD_PARALLELISM);
+    while let Some((id, engine)) = built.next().await {
+        // Loaded twice at once, or added again meanwhile: the first engine stays
+        if state.engines.read().await.contains_key(&id) {
+            continue;
         }
-    }
+        publish_engine(state, &id, engine).await;
 
-    // Batch insert all torrent info (single write lock)
-    {
-        let mut torrents_map = state.torrents.write().await;
-        for (id, info) in &torrents {
-            torrents_map.insert(id.clone(), info.clone());
+        if let Some(app) = &app {
+            let event = crate::events::TorrentReadyEvent { torrent_id: id };
+            emit_event(app, Event::TorrentReady(event));
         }
     }
 
-    let result: Vec<TorrentInfo> = torrents.into_iter().map(|(_, info)| info).collect();
-    
-    tracing::info!("Loaded {} torrents from database", result.len());
-
-    Ok(result)
+    tracing::info!("Built {} engines for saved torrents", count);
+    state.queue.defer_starts(auto_start);
 }
 
 /// Set priority for a file in a torrent
@@ -548,6 +1359,7 @@ pub async fn set_file_priority(
     file_index: usize,
     priority: u8,
 ) -> Result<(), String> {
+    let torrent_id = super::normalize_torrent_id(&torrent_id)?;
     tracing::info!("Setting file priority - Torrent: {}, File: {}, Priority: {}", torrent_id, file_index, priority);
 
     // Convert u8 to PiecePriority
@@ -560,17 +1372,287 @@ pub async fn set_file_priority(
         _ => return Err(format!("Invalid priority value: {}", priority)),
     };
 
-    // Get engine
-    let engines = state.engines.read().await;
-    let engine_arc = engines.get(&torrent_id)
-        .ok_or_else(|| format!("Torrent not found: {}", torrent_id))?
-        .clone();
-    drop(engines);
+    // A running engine holds its own lock, so it is asked to do it
+    if state.engine_tasks.read().await.contains_key(&torrent_id) {
+        let control = engine_control(&state, &torrent_id).await?;
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        control.handle
+            .send(crate::engine::EngineCommand::SetFilePriority(file_index, priority_enum, tx))
+            .await
+            .map_err(|e| format!("Failed to send file priority command: {}", e))?;
+        rx.await.map_err(|_| "Engine stopped before setting the priority".to_string())??;
+    } else {
+        // Get engine
+        let engines = state.engines.read().await;
+        let engine_arc = engines.get(&torrent_id)
+            .ok_or_else(|| format!("Torrent not found: {}", torrent_id))?
+            .clone();
+        drop(engines);
 
-    // Set priority
-    let mut engine = engine_arc.write().await;
-    engine.set_file_priority(file_index, priority_enum).await?;
+        // Set priority
+        let mut engine = engine_arc.write().await;
+        engine.set_file_priority(file_index, priority_enum).await?;
+    }
 
     tracing::info!("Set priority for file {} to {:?}", file_index, priority_enum);
     Ok(())
 }
+
+/// Stop or resume announcing to one tracker of a torrent; `url` may be the
+/// displayed (passkey-masked) URL. The choice is kept on the session.
+#[tauri::command]
+pub async fn set_tracker_enabled(
+    state: State<'_, AppState>,
+    torrent_id: String,
+    url: String,
+    enabled: bool,
+) -> Result<crate::tracker::TrackerToggle, String> {
+    let torrent_id = super::normalize_torrent_id(&torrent_id)?;
+
+    // A running engine holds its own lock, so it is asked to do it
+    let toggle = if state.engine_tasks.read().await.contains_key(&torrent_id) {
+        let control = engine_control(&state, &torrent_id).await?;
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        control.handle
+            .send(crate::engine::EngineCommand::SetTrackerEnabled(url, enabled, tx))
+            .await
+            .map_err(|e| format!("Failed to send tracker command: {}", e))?;
+        rx.await.map_err(|_| "Engine stopped before changing the tracker".to_string())??
+    } else {
+        let engine_arc = state.engines.read().await
+            .get(&torrent_id)
+            .cloned()
+            .ok_or_else(|| format!("Torrent not found: {}", torrent_id))?;
+        let mut engine = engine_arc.write().await;
+        engine.set_tracker_enabled(&url, enabled).await?
+    };
+
+    let mut session = state.database
+        .load_torrent(&torrent_id)
+        .map_err(|e| format!("Failed to load torrent: {}", e))?
+        .ok_or_else(|| format!("Torrent not found: {}", torrent_id))?;
+    session.disabled_trackers = toggle.disabled.clone();
+    state.database
+        .save_torrent(&session)
+        .map_err(|e| format!("Failed to save torrent: {}", e))?;
+
+    if let Some(warning) = &toggle.warning {
+        tracing::warn!("{}: {}", torrent_id, warning);
+    }
+    Ok(toggle)
+}
+
+/// Download only the first `bytes` of a file, e.g. to check its quality
+///
+/// The rest of the file is skipped until its priority is set again. Returns
+/// the bytes selected; `prefix-ready` follows once they are on disk.
+#[tauri::command]
+pub async fn download_file_prefix(
+    state: State<'_, AppState>,
+    torrent_id: String,
+    file_index: usize,
+    bytes: u64,
+) -> Result<u64, String> {
+    let torrent_id = super::normalize_torrent_id(&torrent_id)?;
+    tracing::info!("Downloading the first {} bytes of file {} in {}", bytes, file_index, torrent_id);
+
+    if state.engine_tasks.read().await.contains_key(&torrent_id) {
+        let control = engine_control(&state, &torrent_id).await?;
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        control.handle
+            .send(crate::engine::EngineCommand::DownloadFilePrefix(file_index, bytes, tx))
+            .await
+            .map_err(|e| format!("Failed to send file prefix command: {}", e))?;
+        return rx.await.map_err(|_| "Engine stopped before selecting the prefix".to_string())?;
+    }
+
+    let engine = state.engines.read().await
+        .get(&torrent_id)
+        .cloned()
+        .ok_or_else(|| format!("Torrent not found: {}", torrent_id))?;
+    let mut engine = engine.write().await;
+    engine.download_file_prefix(file_index, bytes).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::TorrentSession;
+    use crate::debrid::types::DownloadSource;
+
+    fn session_in(download_dir: &Path, state: &str) -> TorrentSession {
+        TorrentSession {
+            id: "0123456789abcdef0123456789abcdef01234567".to_string(),
+            metainfo: Metainfo::from_magnet([0u8; 20], Some("test".to_string()), vec![]),
+            bitfield: vec![],
+            num_pieces: 0,
+            downloaded: 0,
+            uploaded: 0,
+            state: state.to_string(),
+            download_dir: download_dir.to_string_lossy().to_string(),
+            added_at: 0,
+            last_activity: 0,
+            source: DownloadSource::P2P,
+            completed_at: None,
+            volume_id: crate::disk::volume_id(download_dir),
+            category: None,
+            traffic: Default::default(),
+            swarm: None,
+            root_name: None,
+            data_sync: Default::default(),
+            queue_position: None,
+            saved_peers: Vec::new(),
+            added_from: None,
+            storage_mode: Default::default(),
+            auto_stop: Default::default(),
+            low_disk_paused_at: None,
+            linked_files: Vec::new(),
+            disabled_trackers: Vec::new(),
+            file_completed_at: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_missing_download_dir_is_not_resumed() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let mount_point = temp_dir.path().join("external");
+        let session = session_in(&mount_point, "downloading");
+
+        let (torrent_state, reason) = startup_state(&session);
+
+        assert_eq!(torrent_state, TorrentState::MissingFiles);
+        assert!(reason.is_some());
+        assert!(!mount_point.exists(), "no directory may be created on the parent filesystem");
+        assert_eq!(std::fs::read_dir(temp_dir.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_present_download_dir_keeps_saved_state() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let session = session_in(temp_dir.path(), "downloading");
+
+        assert_eq!(startup_state(&session), (TorrentState::Downloading, None));
+    }
+
+    fn single_file_session_in(download_dir: &Path) -> TorrentSession {
+        let metainfo = Metainfo::from_bytes(
+            b"d8:announce14:http://tracker4:infod6:lengthi32768e4:name9:Movie.mkv12:piece lengthi16384e6:pieces40:1234567890123456789012345678901234567890ee",
+        )
+        .unwrap();
+        TorrentSession {
+            num_pieces: metainfo.info.piece_count,
+            metainfo,
+            ..session_in(download_dir, "paused")
+        }
+    }
+
+    #[test]
+    fn test_path_collision_is_reported_or_renamed() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let mut session = single_file_session_in(temp_dir.path());
+        assert_eq!(resolve_path_collision(&mut session, false), Ok(false));
+
+        // A leftover folder named like the file
+        std::fs::create_dir(temp_dir.path().join("Movie.mkv")).unwrap();
+        let err = resolve_path_collision(&mut session, false).unwrap_err();
+        assert!(err.contains("already exists as a directory"), "{}", err);
+        assert_eq!(session.root_name, None);
+
+        assert_eq!(resolve_path_collision(&mut session, true), Ok(true));
+        assert_eq!(session.root_name(), "Movie (2).mkv");
+
+        // The renamed layout is clear from then on
+        assert_eq!(resolve_path_collision(&mut session, false), Ok(false));
+    }
+
+    #[test]
+    fn test_download_dir_on_other_volume_is_missing() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let mut session = session_in(temp_dir.path(), "seeding");
+        let Some(volume) = session.volume_id else {
+            return; // Platform without volume ids
+        };
+        session.volume_id = Some(volume.wrapping_add(1));
+
+        assert_eq!(startup_state(&session).0, TorrentState::MissingFiles);
+    }
+
+    #[test]
+    fn test_magnet_torrent_is_listed_as_metadata_pending() {
+        let download_dir = Path::new("/tmp/test_magnet_listing");
+        let magnet = Metainfo::from_magnet([2u8; 20], Some("clip.mkv".to_string()), vec![]);
+        assert!(new_p2p_torrent(magnet, "clip.mkv".to_string(), download_dir).info.metadata_pending);
+
+        let session = session_in(download_dir, "paused");
+        let mut metainfo = session.metainfo.clone();
+        metainfo.info.piece_count = 1;
+        assert!(!new_p2p_torrent(metainfo, "test".to_string(), download_dir).info.metadata_pending);
+    }
+
+    #[tokio::test]
+    async fn test_saved_torrents_listed_before_engines_are_built() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let state = AppState::with_database(crate::database::Database::open(temp_dir.path().join("db")).unwrap());
+        let mut seeding = Vec::new();
+        for i in 0..36u32 {
+            let mut session = session_in(temp_dir.path(), if i % 3 == 0 { "seeding" } else { "downloading" });
+            session.id = format!("{:040x}", i);
+            if i % 3 == 0 {
+                session.completed_at = Some(1);
+                seeding.push(session.id.clone());
+            }
+            state.database.save_torrent(&session).unwrap();
+        }
+
+        // Every torrent is listed right away, without engines
+        let (listed, pending) = list_saved_torrents(None, &state).await.unwrap();
+        assert_eq!(listed.len(), 36);
+        assert_eq!(state.torrents.read().await.len(), 36);
+        assert!(state.engines.read().await.is_empty());
+        // Downloads are left to the queue; only the seeds are resumed directly
+        for info in &listed {
+            let expected = if seeding.contains(&info.id) { TorrentState::Seeding } else { TorrentState::Queued };
+            assert_eq!(info.state, expected);
+        }
+        assert_eq!(pending.auto_start, seeding);
+
+        let in_flight = AtomicUsize::new(0);
+        let most_in_flight = AtomicUsize::new(0);
+        build_saved_engines_with(None, &state, pending, |session| {
+            let (in_flight, most_in_flight, state) = (&in_flight, &most_in_flight, &state);
+            async move {
+                let now = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                most_in_flight.fetch_max(now, Ordering::SeqCst);
+                tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+                let engine = build_engine(None, state, &session).await;
+                in_flight.fetch_sub(1, Ordering::SeqCst);
+                engine
+            }
+        })
+        .await;
+        let most = most_in_flight.load(Ordering::SeqCst);
+        assert!(most > 1 && most <= LOAD_PARALLELISM, "{} engines built at once", most);
+        assert_eq!(state.engines.read().await.len(), 36);
+        assert_eq!(state.engine_controls.read().await.len(), 36);
+
+        // Nothing started inline; the queue resumes the seeds a few per pass
+        assert!(state.engine_tasks.read().await.is_empty());
+        let mut resumed = Vec::new();
+        loop {
+            let batch = state.queue.next_auto_starts();
+            if batch.is_empty() {
+                break;
+            }
+            assert!(batch.len() <= crate::queue::AUTO_STARTS_PER_PASS);
+            resumed.extend(batch);
+        }
+        assert_eq!(resumed, seeding);
+
+        // Loading again lists everything but builds nothing new
+        let (listed, pending) = list_saved_torrents(None, &state).await.unwrap();
+        assert_eq!(listed.len(), 36);
+        assert!(pending.sessions.is_empty() && pending.auto_start.is_empty());
+    }
+}
@@ -101,6 +101,27 @@ impl CryptoManager {
     }
 }
 
+impl CryptoManager {
+    /// Encrypt and decrypt a probe value, to catch a broken cipher before
+    /// blaming the stored credentials for failing to decrypt
+    pub fn self_test(&self) -> Result<()> {
+        const PROBE: &str = "seedcore-crypto-self-test";
+        let (ciphertext, nonce) = self.encrypt(PROBE)?;
+        if ciphertext.as_slice() == PROBE.as_bytes() || self.decrypt(&ciphertext, &nonce)? != PROBE {
+            return Err(anyhow!("Encryption round trip failed"));
+        }
+        Ok(())
+    }
+}
+
+/// Short fingerprint of a key derivation salt. Stored next to everything
+/// encrypted under the salt, so data left over from an older salt is
+/// recognised as such instead of just failing to decrypt.
+pub fn salt_fingerprint(salt: &[u8]) -> String {
+    use sha1::{Digest, Sha1};
+    hex::encode(&Sha1::digest(salt)[..8])
+}
+
 /// Hash a master password for storage verification
 pub fn hash_master_password(password: &str, salt: &[u8]) -> Result<Vec<u8>> {
     let argon2 = Argon2::default();
@@ -173,6 +194,16 @@ mod tests {
         assert!(result.is_err());
     }
 
+    #[test]
+    fn test_self_test_and_fingerprint() {
+        let salt = generate_salt();
+        CryptoManager::from_password("password", &salt).unwrap().self_test().unwrap();
+
+        assert_eq!(salt_fingerprint(&salt), salt_fingerprint(&salt.clone()));
+        assert_eq!(salt_fingerprint(&salt).len(), 16);
+        assert_ne!(salt_fingerprint(&salt), salt_fingerprint(&generate_salt()));
+    }
+
     #[test]
     fn test_password_hashing() {
         let password = "my_master_password";
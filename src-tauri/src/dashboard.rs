@@ -0,0 +1,263 @@
+//! Dashboard summary
+//!
+//! Counts, speeds and sizes rolled up by state and by category, so the
+//! dashboard doesn't have to fetch every torrent. Computed in one pass over
+//! the in-memory torrent list and the categories from the search index;
+//! engines and the database aren't touched.
+
+use crate::state::{TorrentInfo, TorrentState};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+
+/// Fastest torrents listed in the summary
+pub const TOP_TORRENTS: usize = 5;
+
+/// Torrents in one state
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct StateRollup {
+    pub state: TorrentState,
+    pub count: usize,
+    /// Bytes/sec, summed
+    pub download_speed: u64,
+    pub upload_speed: u64,
+}
+
+/// Torrents in one category
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct CategoryRollup {
+    /// None for torrents without a category
+    pub category: Option<String>,
+    pub count: usize,
+    /// Total size in bytes
+    pub size: u64,
+    pub downloaded: u64,
+    pub uploaded: u64,
+    /// Downloading or seeding
+    pub active: usize,
+}
+
+/// One of the fastest torrents right now
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TopTorrent {
+    pub id: String,
+    pub name: String,
+    pub download_speed: u64,
+    pub upload_speed: u64,
+}
+
+/// Result of `get_dashboard_summary`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DashboardSummary {
+    pub torrents: usize,
+    /// States with at least one torrent, in `TorrentState` order
+    pub by_state: Vec<StateRollup>,
+    /// Categories by name, torrents without one last
+    pub by_category: Vec<CategoryRollup>,
+    pub size: u64,
+    pub downloaded: u64,
+    pub uploaded: u64,
+    /// Bytes/sec over all torrents
+    pub download_speed: u64,
+    pub upload_speed: u64,
+    /// Uploaded over downloaded for the torrents in the list (0 before
+    /// anything was downloaded)
+    pub ratio: f64,
+    /// Up to `TOP_TORRENTS` torrents moving data, fastest first
+    pub top: Vec<TopTorrent>,
+}
+
+/// Position of a state in `DashboardSummary::by_state`
+fn state_slot(state: TorrentState) -> usize {
+    match state {
+        TorrentState::Downloading => 0,
+        TorrentState::Seeding => 1,
+        TorrentState::Paused => 2,
+        TorrentState::Checking => 3,
+        TorrentState::Error => 4,
+        TorrentState::Queued => 5,
+        TorrentState::MissingFiles => 6,
+        TorrentState::Unregistered => 7,
+        TorrentState::PausedSeeding => 8,
+    }
+}
+
+const STATE_SLOTS: usize = 9;
+
+/// Summarize `torrents`; `categories` maps torrent ids to their category
+pub fn summarize(torrents: &HashMap<String, TorrentInfo>, categories: &HashMap<String, String>) -> DashboardSummary {
+    let mut states: [Option<StateRollup>; STATE_SLOTS] = [None; STATE_SLOTS];
+    let mut named: BTreeMap<&str, CategoryRollup> = BTreeMap::new();
+    let mut uncategorized = CategoryRollup::default();
+    let mut top: Vec<&TorrentInfo> = Vec::with_capacity(TOP_TORRENTS + 1);
+    let (mut size, mut downloaded, mut uploaded) = (0u64, 0u64, 0u64);
+    let (mut download_speed, mut upload_speed) = (0u64, 0u64);
+
+    for info in torrents.values() {
+        let rollup = states[state_slot(info.state)].get_or_insert(StateRollup {
+            state: info.state,
+            count: 0,
+            download_speed: 0,
+            upload_speed: 0,
+        });
+        rollup.count += 1;
+        rollup.download_speed += info.download_speed;
+        rollup.upload_speed += info.upload_speed;
+
+        let category = match categories.get(&info.id) {
+            Some(name) => named.entry(name).or_default(),
+            None => &mut uncategorized,
+        };
+        category.count += 1;
+        category.size += info.size;
+        category.downloaded += info.downloaded;
+        category.uploaded += info.uploaded;
+        if matches!(info.state, TorrentState::Downloading | TorrentState::Seeding) {
+            category.active += 1;
+        }
+
+        size += info.size;
+        downloaded += info.downloaded;
+        uploaded += info.uploaded;
+        download_speed += info.download_speed;
+        upload_speed += info.upload_speed;
+
+        // Kept sorted and short while scanning instead of sorting everything
+        let speed = |info: &TorrentInfo| info.download_speed + info.upload_speed;
+        if speed(info) > 0 {
+            let at = top.partition_point(|kept| (speed(kept), &kept.id) >= (speed(info), &info.id));
+            if at < TOP_TORRENTS {
+                top.insert(at, info);
+                top.truncate(TOP_TORRENTS);
+            }
+        }
+    }
+
+    let mut by_category: Vec<CategoryRollup> = named
+        .into_iter()
+        .map(|(name, rollup)| CategoryRollup { category: Some(name.to_string()), ..rollup })
+        .collect();
+    if uncategorized.count > 0 {
+        by_category.push(uncategorized);
+    }
+
+    DashboardSummary {
+        torrents: torrents.len(),
+        by_state: states.into_iter().flatten().collect(),
+        by_category,
+        size,
+        downloaded,
+        uploaded,
+        download_speed,
+        upload_speed,
+        ratio: if downloaded == 0 { 0.0 } else { uploaded as f64 / downloaded as f64 },
+        top: top
+            .into_iter()
+            .map(|info| TopTorrent {
+                id: info.id.clone(),
+                name: info.name.clone(),
+                download_speed: info.download_speed,
+                upload_speed: info.upload_speed,
+            })
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn torrent(n: usize, state: TorrentState, download_speed: u64, upload_speed: u64) -> TorrentInfo {
+        TorrentInfo {
+            id: format!("{:040x}", n),
+            name: format!("torrent {}", n),
+            size: 1000,
+            downloaded: 500,
+            uploaded: 250,
+            state,
+            download_speed,
+            upload_speed,
+            peers: 0,
+            seeds: 0,
+            swarm_seeds: None,
+            swarm_leechers: None,
+            swarm_updated_at: None,
+            source: crate::debrid::types::DownloadSource::P2P,
+            remote_deleted: false,
+            queue_position: None,
+            metadata_pending: false,
+            name_encoding: None,
+            tags: Vec::new(),
+            error: None,
+        }
+    }
+
+    #[test]
+    fn test_summary_of_2000_torrents() {
+        // Every 4th downloads, every 4th + 1 seeds, the rest are paused;
+        // every 5th is "linux", every 5th + 1 "movies"
+        let mut torrents = HashMap::new();
+        let mut categories = HashMap::new();
+        for n in 0..2000 {
+            let info = match n % 4 {
+                0 => torrent(n, TorrentState::Downloading, n as u64, 0),
+                1 => torrent(n, TorrentState::Seeding, 0, 10),
+                _ => torrent(n, TorrentState::Paused, 0, 0),
+            };
+            match n % 5 {
+                0 => categories.insert(info.id.clone(), "linux".to_string()),
+                1 => categories.insert(info.id.clone(), "movies".to_string()),
+                _ => None,
+            };
+            torrents.insert(info.id.clone(), info);
+        }
+
+        let started = std::time::Instant::now();
+        let summary = summarize(&torrents, &categories);
+        let elapsed = started.elapsed();
+        assert!(elapsed < std::time::Duration::from_millis(50), "took {:?}", elapsed);
+
+        assert_eq!(summary.torrents, 2000);
+        let downloading_speed: u64 = (0..2000u64).step_by(4).sum();
+        assert_eq!(
+            summary.by_state,
+            vec![
+                StateRollup { state: TorrentState::Downloading, count: 500, download_speed: downloading_speed, upload_speed: 0 },
+                StateRollup { state: TorrentState::Seeding, count: 500, download_speed: 0, upload_speed: 5000 },
+                StateRollup { state: TorrentState::Paused, count: 1000, download_speed: 0, upload_speed: 0 },
+            ]
+        );
+        assert_eq!((summary.size, summary.downloaded, summary.uploaded), (2_000_000, 1_000_000, 500_000));
+        assert_eq!((summary.download_speed, summary.upload_speed), (downloading_speed, 5000));
+        assert_eq!(summary.ratio, 0.5);
+
+        // Of every 20, linux has 0 (downloading), 5 (seeding), 10 and 15 (paused)
+        let names: Vec<Option<&str>> = summary.by_category.iter().map(|c| c.category.as_deref()).collect();
+        assert_eq!(names, vec![Some("linux"), Some("movies"), None]);
+        let linux = &summary.by_category[0];
+        assert_eq!((linux.count, linux.size, linux.downloaded, linux.uploaded), (400, 400_000, 200_000, 100_000));
+        assert_eq!(linux.active, 200);
+        assert_eq!(summary.by_category[2].count, 1200);
+        assert_eq!(summary.by_category.iter().map(|c| c.count).sum::<usize>(), 2000);
+
+        // The fastest downloads: 1996, 1992, ...
+        let top: Vec<u64> = summary.top.iter().map(|t| t.download_speed).collect();
+        assert_eq!(top, vec![1996, 1992, 1988, 1984, 1980]);
+    }
+
+    #[test]
+    fn test_empty_and_idle() {
+        let summary = summarize(&HashMap::new(), &HashMap::new());
+        assert_eq!(summary.torrents, 0);
+        assert!(summary.by_state.is_empty() && summary.by_category.is_empty() && summary.top.is_empty());
+        assert_eq!(summary.ratio, 0.0);
+
+        // Torrents not moving data aren't listed; equal speeds go by id, highest first
+        let torrents: HashMap<String, TorrentInfo> = (0..3)
+            .map(|n| torrent(n, TorrentState::Seeding, 0, if n == 0 { 0 } else { 7 }))
+            .map(|info| (info.id.clone(), info))
+            .collect();
+        let summary = summarize(&torrents, &HashMap::new());
+        let top: Vec<&str> = summary.top.iter().map(|t| t.name.as_str()).collect();
+        assert_eq!(top, vec!["torrent 2", "torrent 1"]);
+    }
+}
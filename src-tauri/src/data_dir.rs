@@ -0,0 +1,265 @@
+//! Where the database and logs live
+//!
+//! By default that is the config dir (`state::config_dir`). `migrate` moves
+//! them elsewhere and leaves a pointer file in the config dir naming the new
+//! location, which `data_dir` follows at startup. The preview cache and the
+//! pointer itself stay in the config dir.
+
+use crate::database::Database;
+use crate::error::{Error, Result};
+use std::path::{Path, PathBuf};
+
+/// File in the config dir holding the data dir's path
+pub const POINTER_FILE: &str = "data-dir";
+
+/// Database directory inside the data dir
+pub const DATABASE_DIR: &str = "data.db";
+
+/// Logs directory inside the data dir
+pub const LOGS_DIR: &str = "logs";
+
+/// Written and removed to check that a target is writable
+const WRITE_PROBE: &str = ".seedcore-write-test";
+
+/// The data dir in use: where the pointer file says, else the config dir
+pub fn data_dir() -> PathBuf {
+    resolve(&crate::state::config_dir())
+}
+
+/// `data_dir` for a given config dir. A pointer to a directory that has gone
+/// missing (e.g. an unplugged drive) is still returned, so that startup
+/// fails instead of quietly creating an empty database in its place.
+pub fn resolve(config_dir: &Path) -> PathBuf {
+    match std::fs::read_to_string(config_dir.join(POINTER_FILE)) {
+        Ok(path) if !path.trim().is_empty() => PathBuf::from(path.trim()),
+        _ => config_dir.to_path_buf(),
+    }
+}
+
+/// Record `data_dir` in the pointer file (write + rename, so a crash leaves
+/// either the old or the new pointer)
+fn write_pointer(config_dir: &Path, data_dir: &Path) -> Result<()> {
+    let pointer = config_dir.join(POINTER_FILE);
+    let temp = config_dir.join(format!("{}.tmp", POINTER_FILE));
+    std::fs::write(&temp, data_dir.to_string_lossy().as_bytes())
+        .and_then(|()| std::fs::rename(&temp, &pointer))
+        .map_err(|e| {
+            let _ = std::fs::remove_file(&temp);
+            Error::IoError(format!("Failed to update {}: {}", pointer.display(), e))
+        })
+}
+
+/// Check that `target` can become the data dir, creating it if needed: an
+/// absolute path outside the current data dir, empty or new, writable, with
+/// `needed` bytes free
+pub fn check_target(current: &Path, target: &Path, needed: u64) -> Result<()> {
+    if !target.is_absolute() {
+        return Err(Error::ValidationError(format!("{} is not an absolute path", target.display())));
+    }
+    if target.starts_with(current) || current.starts_with(target) {
+        return Err(Error::ValidationError(format!(
+            "{} overlaps the current data directory {}",
+            target.display(),
+            current.display()
+        )));
+    }
+    if target.exists() {
+        let mut entries = std::fs::read_dir(target)
+            .map_err(|e| Error::ValidationError(format!("{} is not a usable directory: {}", target.display(), e)))?;
+        if entries.next().is_some() {
+            return Err(Error::ValidationError(format!("{} is not empty", target.display())));
+        }
+    } else {
+        std::fs::create_dir_all(target)
+            .map_err(|e| Error::ValidationError(format!("Cannot create {}: {}", target.display(), e)))?;
+    }
+
+    let probe = target.join(WRITE_PROBE);
+    std::fs::write(&probe, b"")
+        .and_then(|()| std::fs::remove_file(&probe))
+        .map_err(|e| Error::ValidationError(format!("{} is not writable: {}", target.display(), e)))?;
+
+    let available = fs2::available_space(target)
+        .map_err(|e| Error::IoError(format!("Failed to get free space of {}: {}", target.display(), e)))?;
+    if available < needed {
+        return Err(Error::ValidationError(format!(
+            "{} has {} bytes free, {} needed",
+            target.display(),
+            available,
+            needed
+        )));
+    }
+    Ok(())
+}
+
+/// Move the database and logs from `current` to `target` and point
+/// `config_dir`'s pointer file at it. On failure `target` is emptied again
+/// and `database` keeps working from `current`. The old files are left in
+/// place; log lines keep going to the old logs dir until the next start.
+pub fn migrate(database: &Database, current: &Path, target: &Path, config_dir: &Path) -> Result<()> {
+    let logs = current.join(LOGS_DIR);
+    let needed = database.stats().size_on_disk + dir_size(&logs);
+    check_target(current, target, needed)?;
+
+    let result = copy_dir(&logs, &target.join(LOGS_DIR))
+        .and_then(|()| database.relocate(&target.join(DATABASE_DIR), || write_pointer(config_dir, target)));
+    if let Err(e) = result {
+        tracing::error!("Moving the data directory to {} failed: {}", target.display(), e);
+        empty_dir(target);
+        return Err(e);
+    }
+
+    tracing::info!("Data directory moved from {} to {}", current.display(), target.display());
+    Ok(())
+}
+
+/// Total size of the files under `dir` (0 if it doesn't exist)
+fn dir_size(dir: &Path) -> u64 {
+    let Ok(entries) = std::fs::read_dir(dir) else { return 0 };
+    entries
+        .flatten()
+        .map(|entry| match entry.file_type() {
+            Ok(kind) if kind.is_dir() => dir_size(&entry.path()),
+            Ok(kind) if kind.is_file() => entry.metadata().map(|m| m.len()).unwrap_or(0),
+            _ => 0,
+        })
+        .sum()
+}
+
+/// Copy the files under `from` to `to` (nothing to do if `from` doesn't exist)
+fn copy_dir(from: &Path, to: &Path) -> Result<()> {
+    if !from.is_dir() {
+        return Ok(());
+    }
+    let copy_error = |path: &Path, e: std::io::Error| Error::IoError(format!("Failed to copy {}: {}", path.display(), e));
+    std::fs::create_dir_all(to).map_err(|e| copy_error(to, e))?;
+    for entry in std::fs::read_dir(from).map_err(|e| copy_error(from, e))? {
+        let entry = entry.map_err(|e| copy_error(from, e))?;
+        let kind = entry.file_type().map_err(|e| copy_error(&entry.path(), e))?;
+        let destination = to.join(entry.file_name());
+        if kind.is_dir() {
+            copy_dir(&entry.path(), &destination)?;
+        } else if kind.is_file() {
+            std::fs::copy(entry.path(), &destination).map_err(|e| copy_error(&entry.path(), e))?;
+        }
+    }
+    Ok(())
+}
+
+/// Remove everything inside `dir`, keeping `dir` itself
+fn empty_dir(dir: &Path) {
+    let Ok(entries) = std::fs::read_dir(dir) else { return };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let removed = if path.is_dir() { std::fs::remove_dir_all(&path) } else { std::fs::remove_file(&path) };
+        if let Err(e) = removed {
+            tracing::warn!("Failed to clean up {}: {}", path.display(), e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::TorrentSession;
+    use crate::debrid::types::DownloadSource;
+    use crate::torrent::Metainfo;
+    use tempfile::TempDir;
+
+    const TORRENT_ID: &str = "0123456789abcdef0123456789abcdef01234567";
+
+    /// A config dir that is also the data dir, with one saved torrent and a log file
+    fn setup() -> (TempDir, Database) {
+        let config = TempDir::new().unwrap();
+        std::fs::create_dir_all(config.path().join(LOGS_DIR)).unwrap();
+        std::fs::write(config.path().join(LOGS_DIR).join("seedcore.log"), "started\n").unwrap();
+
+        let database = Database::open(config.path().join(DATABASE_DIR)).unwrap();
+        let metainfo = Metainfo::from_magnet([0x01; 20], Some("test".to_string()), vec![]);
+        database
+            .save_torrent(&TorrentSession {
+                id: TORRENT_ID.to_string(),
+                metainfo,
+                bitfield: vec![],
+                num_pieces: 0,
+                downloaded: 42,
+                uploaded: 0,
+                state: "paused".to_string(),
+                download_dir: "/tmp".to_string(),
+                added_at: 0,
+                last_activity: 0,
+                source: DownloadSource::P2P,
+                completed_at: None,
+                volume_id: None,
+                category: None,
+                traffic: Default::default(),
+                swarm: None,
+                root_name: None,
+                data_sync: Default::default(),
+                queue_position: None,
+                saved_peers: Vec::new(),
+                added_from: None,
+                storage_mode: Default::default(),
+                auto_stop: Default::default(),
+                low_disk_paused_at: None,
+                linked_files: Vec::new(),
+                disabled_trackers: Vec::new(),
+                file_completed_at: Default::default(),
+            })
+            .unwrap();
+        (config, database)
+    }
+
+    #[test]
+    fn test_migration_moves_sessions_and_logs() {
+        let (config, database) = setup();
+        let target_root = TempDir::new().unwrap();
+        let target = target_root.path().join("seedcore-data");
+
+        migrate(&database, config.path(), &target, config.path()).unwrap();
+
+        assert_eq!(resolve(config.path()), target);
+        assert_eq!(database.path(), target.join(DATABASE_DIR));
+        assert_eq!(std::fs::read_to_string(target.join(LOGS_DIR).join("seedcore.log")).unwrap(), "started\n");
+
+        // Writes after the move land in the new copy
+        assert!(database.update_state(TORRENT_ID, "seeding".to_string()).unwrap());
+        drop(database);
+
+        // What the next start opens
+        let reopened = Database::open(resolve(config.path()).join(DATABASE_DIR)).unwrap();
+        let session = reopened.load_torrent(TORRENT_ID).unwrap().unwrap();
+        assert_eq!(session.state, "seeding");
+        assert_eq!(session.downloaded, 42);
+    }
+
+    #[test]
+    fn test_failed_migration_keeps_old_location() {
+        let (config, database) = setup();
+        let target = TempDir::new().unwrap();
+
+        // The pointer can't be written: the config "dir" is a file
+        let bad_config = target.path().join("not-a-dir");
+        let bad_target = TempDir::new().unwrap();
+        std::fs::write(&bad_config, "").unwrap();
+        assert!(migrate(&database, config.path(), bad_target.path(), &bad_config).is_err());
+        assert_eq!(std::fs::read_dir(bad_target.path()).unwrap().count(), 0);
+
+        assert_eq!(database.path(), config.path().join(DATABASE_DIR));
+        assert_eq!(resolve(config.path()), config.path());
+        assert_eq!(database.load_torrent(TORRENT_ID).unwrap().unwrap().downloaded, 42);
+    }
+
+    #[test]
+    fn test_target_checks() {
+        let (config, _database) = setup();
+        let target = TempDir::new().unwrap();
+
+        std::fs::write(target.path().join("something"), "").unwrap();
+        assert!(check_target(config.path(), target.path(), 0).is_err());
+        assert!(check_target(config.path(), Path::new("relative/dir"), 0).is_err());
+        assert!(check_target(config.path(), &config.path().join("inner"), 0).is_err());
+        assert!(check_target(config.path(), &target.path().join("new"), u64::MAX).is_err());
+        assert!(check_target(config.path(), &target.path().join("new"), 0).is_ok());
+    }
+}
@@ -1,11 +1,24 @@
 /// Database module for persistent storage using Sled
 /// Stores torrent metadata, download progress, and settings
+use crate::availability::AvailabilitySample;
 use crate::debrid::types::{DebridProviderType, DownloadSource};
 use crate::error::{Error, Result};
+use crate::ids::InfoHash;
+use crate::search::MetadataSummary;
+use crate::state::CloudFileSummary;
 use crate::torrent::Metainfo;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
 use serde::{Deserialize, Serialize};
 use sled::Db;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+mod recovery;
+pub use recovery::{lock_instance, open_or_recover, repair, salvage, DatabaseRecovery, SalvageReport};
+pub mod stream;
+pub use stream::{BackupSummary, StreamProgress};
 
 /// Database keys
 const KEY_TORRENTS: &[u8] = b"torrents";
@@ -13,6 +26,12 @@ const KEY_PROGRESS: &[u8] = b"progress";
 const KEY_SETTINGS: &[u8] = b"settings";
 const KEY_DEBRID_CREDENTIALS: &[u8] = b"debrid_credentials";
 const KEY_MASTER_PASSWORD: &[u8] = b"master_password";
+const KEY_API_TOKENS: &[u8] = b"api_tokens";
+const KEY_TORRENT_FILES: &[u8] = b"torrent_files";
+const KEY_BENCHMARKS: &[u8] = b"benchmarks";
+const KEY_CLOUD_FILES: &[u8] = b"cloud_files";
+const KEY_AVAILABILITY: &[u8] = b"availability";
+const KEY_SEARCH_METADATA: &[u8] = b"search_metadata";
 
 /// Download session data stored in database (renamed from TorrentSession)
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -41,6 +60,125 @@ pub struct TorrentSession {
     pub source: DownloadSource,
     /// Time completed (Unix timestamp), None if not completed
     pub completed_at: Option<i64>,
+    /// Volume (device) id of download_dir captured at add time, if known
+    #[serde(default)]
+    pub volume_id: Option<u64>,
+    /// User-assigned category, if any
+    #[serde(default)]
+    pub category: Option<String>,
+    /// Last swarm size reported by the trackers
+    #[serde(default)]
+    pub swarm: Option<crate::tracker::SwarmStats>,
+    /// Wire traffic totals (payload vs protocol overhead)
+    #[serde(default)]
+    pub traffic: crate::peer::TrafficStats,
+    /// On-disk name of the torrent's root file/folder, when it was renamed
+    /// to get around a path collision
+    #[serde(default)]
+    pub root_name: Option<String>,
+    /// Which pieces of `bitfield` still need re-verifying after a crash
+    #[serde(default)]
+    pub data_sync: crate::disk::SyncPoint,
+    /// Position in the download queue (0 = first); None until the queue is
+    /// reordered, which places it after all positioned torrents
+    #[serde(default)]
+    pub queue_position: Option<u32>,
+    /// Best peers known when progress was last saved, reused on the next start
+    #[serde(default)]
+    pub saved_peers: Vec<crate::peer::SavedPeer>,
+    /// Where the torrent was added from (None for sessions saved before
+    /// this was recorded)
+    #[serde(default)]
+    pub added_from: Option<crate::provenance::AddedFrom>,
+    /// Consolidated storage until the download is extracted (see `disk::parts`)
+    #[serde(default)]
+    pub storage_mode: crate::disk::StorageMode,
+    /// Dead download policy state (see `stall`)
+    #[serde(default)]
+    pub auto_stop: crate::stall::AutoStop,
+    /// When the free space monitor paused it (see `low_disk`); cleared once
+    /// it is started again
+    #[serde(default)]
+    pub low_disk_paused_at: Option<i64>,
+    /// Files hard-linked to another torrent's copy (see `duplicates`)
+    #[serde(default)]
+    pub linked_files: Vec<crate::duplicates::LinkedFile>,
+    /// Trackers the user disabled for this torrent (see
+    /// `TorrentEngine::set_tracker_enabled`)
+    #[serde(default)]
+    pub disabled_trackers: Vec<String>,
+    /// When each complete file finished, by file index; kept through
+    /// rechecks that find the file intact
+    #[serde(default)]
+    pub file_completed_at: crate::piece::FileCompletions,
+}
+
+impl TorrentSession {
+    /// Labels the app puts on the torrent's UI entry
+    pub fn tags(&self) -> Vec<String> {
+        let mut tags = crate::stall::tags(&self.auto_stop);
+        if self.low_disk_paused_at.is_some() {
+            tags.push(crate::low_disk::LOW_DISK_TAG.to_string());
+        }
+        tags
+    }
+
+    /// Name of the torrent's root file/folder inside `download_dir`
+    pub fn root_name(&self) -> &str {
+        self.root_name.as_deref().unwrap_or(&self.metainfo.info.name)
+    }
+}
+
+/// The parts of a session that change while it runs
+///
+/// Kept in their own tree so progress saves rewrite a few hundred bytes instead
+/// of the whole session (metainfo included). Field names match
+/// `TorrentSession`, so a record can also be read straight out of a session.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SessionProgress {
+    /// Download progress (bitfield as bytes)
+    pub bitfield: Vec<u8>,
+    /// Downloaded bytes
+    pub downloaded: u64,
+    /// Uploaded bytes
+    pub uploaded: u64,
+    /// Torrent state
+    pub state: String,
+    /// Last activity timestamp
+    pub last_activity: i64,
+    /// Time completed (Unix timestamp), None if not completed
+    pub completed_at: Option<i64>,
+    /// Last swarm size reported by the trackers
+    #[serde(default)]
+    pub swarm: Option<crate::tracker::SwarmStats>,
+    /// Wire traffic totals
+    #[serde(default)]
+    pub traffic: crate::peer::TrafficStats,
+    /// Which pieces of `bitfield` still need re-verifying after a crash
+    #[serde(default)]
+    pub data_sync: crate::disk::SyncPoint,
+    /// Best peers known at save time
+    #[serde(default)]
+    pub saved_peers: Vec<crate::peer::SavedPeer>,
+    /// When each complete file finished
+    #[serde(default)]
+    pub file_completed_at: crate::piece::FileCompletions,
+}
+
+impl SessionProgress {
+    fn apply_to(self, session: &mut TorrentSession) {
+        session.bitfield = self.bitfield;
+        session.downloaded = self.downloaded;
+        session.uploaded = self.uploaded;
+        session.state = self.state;
+        session.last_activity = self.last_activity;
+        session.completed_at = self.completed_at;
+        session.swarm = self.swarm;
+        session.traffic = self.traffic;
+        session.data_sync = self.data_sync;
+        session.saved_peers = self.saved_peers;
+        session.file_completed_at = self.file_completed_at;
+    }
 }
 
 /// Debrid provider credentials stored encrypted in database
@@ -58,6 +196,10 @@ pub struct DebridCredentials {
     pub last_validated: i64,
     /// Whether credentials are valid
     pub is_valid: bool,
+    /// `crypto::salt_fingerprint` of the salt the key was encrypted under
+    /// (None for credentials saved before it was recorded)
+    #[serde(default)]
+    pub salt_fingerprint: Option<String>,
 }
 
 /// Master password hash stored in database
@@ -69,6 +211,21 @@ pub struct MasterPasswordData {
     pub salt: Vec<u8>,
 }
 
+/// An API token as stored: only the hash of its secret is kept
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiTokenRecord {
+    /// Public part of the token, used to look it up
+    pub id: String,
+    pub label: String,
+    pub scopes: Vec<crate::api_tokens::TokenScope>,
+    /// Argon2 hash of the secret part
+    pub secret_hash: Vec<u8>,
+    /// Unix timestamp
+    pub created_at: i64,
+    /// Unix timestamp; None = never expires
+    pub expires_at: Option<i64>,
+}
+
 /// Application settings stored in database
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AppSettings {
@@ -78,6 +235,9 @@ pub struct AppSettings {
     pub max_download_speed: u64,
     /// Maximum upload speed (bytes/sec, 0 = unlimited)
     pub max_upload_speed: u64,
+    /// Charge protocol overhead (not just payload) against the speed limits
+    #[serde(default)]
+    pub count_overhead_in_rate_limits: bool,
     /// Maximum concurrent downloads
     pub max_concurrent_downloads: usize,
     /// Port for incoming connections
@@ -86,12 +246,49 @@ pub struct AppSettings {
     pub enable_dht: bool,
     /// Enable PEX
     pub enable_pex: bool,
+    /// Anonymous mode: strip client-identifying fields from what we send.
+    ///
+    /// Announces and handshakes use a random per-torrent peer ID with no
+    /// `-SC0100-` prefix, and tracker requests carry no User-Agent. We never
+    /// send `ip=` or `key=` in either mode, and there is no extension
+    /// handshake (so no `v` field) to strip. It does NOT hide the user's IP
+    /// address, route anything through a proxy, or encrypt peer traffic.
+    #[serde(default)]
+    pub anonymous_mode: bool,
+    /// User-Agent for tracker requests (None = "SeedCore/<version>")
+    #[serde(default)]
+    pub tracker_user_agent: Option<String>,
+    /// Extra headers sent with tracker requests (never to debrid providers)
+    #[serde(default)]
+    pub tracker_extra_headers: Vec<crate::tracker::http::HttpHeader>,
+    /// PEM file of extra root certificates trusted for trackers (never for debrid)
+    #[serde(default)]
+    pub tracker_ca_file: Option<String>,
+    /// DANGEROUS: trackers on these exact hosts are used without checking
+    /// their certificates, so anyone on the network path can impersonate them
+    #[serde(default)]
+    pub tracker_insecure_hosts: Vec<String>,
     /// Enable debrid services
     pub enable_debrid: bool,
     /// Debrid provider preference order (first = most preferred)
     pub debrid_preference: Vec<DebridProviderType>,
     /// Smart mode: auto-select best source (cloud vs P2P)
     pub smart_mode_enabled: bool,
+    /// Delete cloud torrents from the debrid account once all files are downloaded locally
+    #[serde(default)]
+    pub delete_from_provider_after_download: bool,
+    /// What to do when a finished download's final name is already taken
+    #[serde(default)]
+    pub file_collision: FileCollisionPolicy,
+    /// Let the user pick which files of a cloud torrent to download
+    #[serde(default)]
+    pub ask_before_selecting_cloud_files: bool,
+    /// Per-provider API base URL overrides
+    #[serde(default)]
+    pub debrid_base_urls: std::collections::HashMap<DebridProviderType, String>,
+    /// What running cloud downloads do when debrid is switched off
+    #[serde(default)]
+    pub debrid_disable_policy: DebridDisablePolicy,
     /// Auto-cleanup enabled
     pub cleanup_enabled: bool,
     /// Seeding ratio limit (0.0 = unlimited)
@@ -104,10 +301,121 @@ pub struct AppSettings {
     pub bandwidth_scheduler_enabled: bool,
     /// Bandwidth schedule rules
     pub bandwidth_schedule: Vec<BandwidthRule>,
+    /// User-supplied ffmpeg binary for video thumbnails (never bundled)
+    #[serde(default)]
+    pub ffmpeg_path: Option<String>,
+    /// Peers saved with a torrent older than this are not reused (seconds)
+    #[serde(default = "default_saved_peer_max_age")]
+    pub saved_peer_max_age_secs: u64,
+    /// New outbound peer connections per second across all torrents (0 = unlimited)
+    #[serde(default = "default_max_dials_per_sec")]
+    pub max_dials_per_sec: u32,
+    /// Peer connections that may be connecting/handshaking at once (0 = unlimited)
+    #[serde(default = "default_max_half_open")]
+    pub max_half_open_connections: u32,
+    /// Peer connections across all torrents (0 = derived from the open files limit)
+    #[serde(default)]
+    pub max_connections: u32,
+    /// Serve uploads of complete files through memory maps (off by default:
+    /// a file truncated by another program while mapped crashes the app)
+    #[serde(default)]
+    pub mmap_piece_reads: bool,
+    /// Give each downloaded file the torrent's creation date as its mtime
+    /// once complete (see `DiskManager::piece_completed`)
+    #[serde(default)]
+    pub file_mtime_from_creation_date: bool,
+    /// Write a backup file every `auto_backup_interval_hours` (see `backup`)
+    #[serde(default)]
+    pub auto_backup_enabled: bool,
+    #[serde(default = "crate::backup::default_interval_hours")]
+    pub auto_backup_interval_hours: u64,
+    /// Backup files kept; older ones are deleted
+    #[serde(default = "crate::backup::default_keep")]
+    pub auto_backup_keep: usize,
+    /// Where backups go (None = `backups` next to the database)
+    #[serde(default)]
+    pub auto_backup_dir: Option<String>,
+    /// Daily log files kept; older ones are deleted (see `logs`)
+    #[serde(default = "crate::logs::default_keep_files")]
+    pub log_keep_files: usize,
+    /// Cap on the logs directory in MiB (0 = none), oldest files go first
+    #[serde(default)]
+    pub log_max_total_mb: u64,
+    /// Most verbose level written to the log files
+    #[serde(default)]
+    pub log_file_level: crate::logs::LogLevel,
+    /// Pause downloads that can't finish (see `stall`)
+    #[serde(default)]
+    pub auto_stop_dead_enabled: bool,
+    /// Days with less than one copy in the swarm and no progress before that
+    #[serde(default = "crate::stall::default_dead_after_days")]
+    pub auto_stop_dead_days: u32,
+    /// Start such downloads again once a tracker reports a seed
+    #[serde(default)]
+    pub auto_resume_revived: bool,
+    /// Torrents in paused seeding go back to seeding when an announce
+    /// reports leechers
+    #[serde(default)]
+    pub reactivate_paused_seeding: bool,
+    /// Send a `tracker-warning` event when a tracker's announce carries a
+    /// new warning message
+    #[serde(default = "crate::tracker::default_notify_on_tracker_warning")]
+    pub notify_on_tracker_warning: bool,
+    /// Free space kept on every download filesystem in MiB; downloads are
+    /// paused below it (see `low_disk`, 0 = never pause)
+    #[serde(default = "crate::low_disk::default_reserve_mb")]
+    pub low_disk_reserve_mb: u64,
+    /// Start downloads paused for low disk space again once they fit
+    #[serde(default)]
+    pub low_disk_auto_resume: bool,
+    /// Keep the machine from sleeping while torrents download (see `power`)
+    #[serde(default)]
+    pub prevent_sleep: bool,
+    /// Seeding torrents keep it awake too
+    #[serde(default)]
+    pub prevent_sleep_while_seeding: bool,
+    /// Longest a saved change waits before it is flushed to disk, in
+    /// seconds (see `Database::flush_if_due`)
+    #[serde(default = "default_flush_interval_secs")]
+    pub db_flush_interval_secs: u64,
+}
+
+fn default_saved_peer_max_age() -> u64 {
+    2 * 60 * 60
+}
+
+fn default_max_dials_per_sec() -> u32 {
+    crate::peer::pacer::DEFAULT_DIALS_PER_SEC
+}
+
+fn default_max_half_open() -> u32 {
+    crate::peer::pacer::DEFAULT_MAX_HALF_OPEN
+}
+
+/// Handling of an existing file at a finished download's final path
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FileCollisionPolicy {
+    /// Keep both: the new file gets a " (n)" suffix
+    #[default]
+    Rename,
+    /// Fail the download and leave the existing file alone
+    Error,
+}
+
+/// Running cloud downloads when debrid features are switched off
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DebridDisablePolicy {
+    /// Let them finish; providers are torn down once the last one ends
+    #[default]
+    Finish,
+    /// Stop them right away
+    Abort,
 }
 
 /// Bandwidth schedule rule
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct BandwidthRule {
     /// Start time (HH:MM)
     pub start_time: String,
@@ -133,72 +441,300 @@ impl Default for AppSettings {
                 .to_string(),
             max_download_speed: 0, // Unlimited
             max_upload_speed: 0,   // Unlimited
+            count_overhead_in_rate_limits: false,
             max_concurrent_downloads: 3,
             listen_port: 6881,
+            anonymous_mode: false,
+            tracker_user_agent: None,
+            tracker_extra_headers: Vec::new(),
+            tracker_ca_file: None,
+            tracker_insecure_hosts: Vec::new(),
             enable_dht: true,
             enable_pex: true,
             enable_debrid: false,
             debrid_preference: vec![DebridProviderType::Torbox, DebridProviderType::RealDebrid],
             smart_mode_enabled: true,
+            delete_from_provider_after_download: false,
+            file_collision: FileCollisionPolicy::Rename,
+            ask_before_selecting_cloud_files: false,
+            debrid_base_urls: std::collections::HashMap::new(),
+            debrid_disable_policy: DebridDisablePolicy::Finish,
             cleanup_enabled: false,
             cleanup_ratio: 2.0, // 200%
             cleanup_time: 0,    // Unlimited
             cleanup_mode: "Pause".to_string(),
             bandwidth_scheduler_enabled: false,
             bandwidth_schedule: Vec::new(),
+            ffmpeg_path: None,
+            saved_peer_max_age_secs: default_saved_peer_max_age(),
+            max_dials_per_sec: default_max_dials_per_sec(),
+            max_half_open_connections: default_max_half_open(),
+            max_connections: 0,
+            mmap_piece_reads: false,
+            file_mtime_from_creation_date: false,
+            auto_backup_enabled: false,
+            auto_backup_interval_hours: crate::backup::DEFAULT_INTERVAL_HOURS,
+            auto_backup_keep: crate::backup::DEFAULT_KEEP,
+            auto_backup_dir: None,
+            log_keep_files: crate::logs::DEFAULT_KEEP_FILES,
+            log_max_total_mb: 0,
+            log_file_level: crate::logs::LogLevel::default(),
+            auto_stop_dead_enabled: false,
+            auto_stop_dead_days: crate::stall::DEFAULT_DEAD_AFTER_DAYS,
+            auto_resume_revived: false,
+            reactivate_paused_seeding: false,
+            notify_on_tracker_warning: true,
+            low_disk_reserve_mb: crate::low_disk::DEFAULT_RESERVE_MB,
+            low_disk_auto_resume: false,
+            prevent_sleep: false,
+            prevent_sleep_while_seeding: false,
+            db_flush_interval_secs: DEFAULT_FLUSH_INTERVAL_SECS,
         }
     }
 }
 
+/// Canonical key for a torrent session: the lowercase hex info hash
+fn torrent_key(id: &str) -> Result<String> {
+    Ok(InfoHash::parse(id)?.to_hex())
+}
+
+#[cfg(test)]
+thread_local! {
+    /// Full session decodes on this thread, so tests can assert progress
+    /// updates never deserialize the metainfo
+    static SESSION_DECODES: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+}
+
+fn decode_session(data: &[u8]) -> Result<TorrentSession> {
+    #[cfg(test)]
+    SESSION_DECODES.with(|n| n.set(n.get() + 1));
+
+    serde_json::from_slice(data)
+        .map_err(|e| Error::IoError(format!("Failed to deserialize torrent: {}", e)))
+}
+
+fn decode_progress(data: &[u8]) -> Result<SessionProgress> {
+    serde_json::from_slice(data)
+        .map_err(|e| Error::IoError(format!("Failed to deserialize progress: {}", e)))
+}
+
+/// Overlay the separately stored progress record, if any, onto a session
+fn merge_progress(progress: &sled::Tree, session: &mut TorrentSession) -> Result<()> {
+    let record = progress
+        .get(torrent_key(&session.id)?.as_bytes())
+        .map_err(|e| Error::IoError(format!("Failed to load progress: {}", e)))?;
+    if let Some(data) = record {
+        decode_progress(&data)?.apply_to(session);
+    }
+    Ok(())
+}
+
+/// Default for `AppSettings::db_flush_interval_secs`
+pub const DEFAULT_FLUSH_INTERVAL_SECS: u64 = 5;
+
+pub fn default_flush_interval_secs() -> u64 {
+    DEFAULT_FLUSH_INTERVAL_SECS
+}
+
+#[cfg(test)]
+thread_local! {
+    /// Explicit flushes on this thread, so tests can count the fsyncs a
+    /// burst of writes costs
+    static FLUSHES: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+}
+
+/// How often the flusher checks for due writes
+const FLUSH_TICK: Duration = Duration::from_secs(1);
+
+/// Run the flusher under `supervisor`: deferred writes are flushed at most
+/// once per `interval` seconds
+pub fn register_flush_task(
+    supervisor: &crate::tasks::Supervisor,
+    database: std::sync::Arc<Database>,
+    interval: tokio::sync::watch::Receiver<u64>,
+) -> std::result::Result<(), String> {
+    supervisor.register("database_flush", FLUSH_TICK, interval, move |secs: u64| {
+        let database = database.clone();
+        crate::tasks::job(move || {
+            let database = database.clone();
+            async move {
+                tokio::task::spawn_blocking(move || database.flush_if_due(Duration::from_secs(secs.max(1))))
+                    .await
+                    .map_err(|e| e.to_string())?
+                    .map(drop)
+                    .map_err(|e| e.to_string())
+            }
+        })
+    })
+}
+
+/// When a write has to be on disk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Durability {
+    /// Within the flush interval, together with whatever else was written
+    Deferred,
+    /// Before the write returns: passwords, tokens and deletions of secrets,
+    /// which must not come back after a crash
+    Immediate,
+}
+
+/// Writes waiting for the flusher
+struct FlushState {
+    dirty: AtomicBool,
+    last: Mutex<Instant>,
+}
+
 /// Database manager
+///
+/// Writes go into sled's page cache and most are left for the flusher task
+/// (`flush_if_due`), which fsyncs at most once per flush interval; a flush
+/// of the whole tree per progress save competed with piece writes for the
+/// disk. Critical writes still flush before returning.
 pub struct Database {
+    /// Swapped by `relocate`, so every holder of the Arc follows a move
+    storage: RwLock<Storage>,
+    flush: FlushState,
+}
+
+struct Storage {
     db: Db,
+    path: PathBuf,
+}
+
+/// Deferred writes aren't lost with the last handle
+impl Drop for Database {
+    fn drop(&mut self) {
+        if self.flush.dirty.load(Ordering::SeqCst) {
+            if let Err(e) = self.flush_now() {
+                tracing::error!("Failed to flush database on close: {}", e);
+            }
+        }
+    }
 }
 
 impl Database {
     /// Open or create a database at the given path
     pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
-        let db = sled::open(path)
+        let db = sled::open(path.as_ref())
             .map_err(|e| Error::IoError(format!("Failed to open database: {}", e)))?;
 
-        Ok(Self { db })
+        Ok(Self::with_db(db, path.as_ref()))
+    }
+
+    fn with_db(db: Db, path: &Path) -> Self {
+        Self {
+            storage: RwLock::new(Storage { db, path: path.to_path_buf() }),
+            flush: FlushState { dirty: AtomicBool::new(false), last: Mutex::new(Instant::now()) },
+        }
+    }
+
+    /// Handle to the current storage (waits while `relocate` runs)
+    fn db(&self) -> Db {
+        self.storage.read().unwrap().db.clone()
+    }
+
+    /// Directory the database lives in
+    pub fn path(&self) -> PathBuf {
+        self.storage.read().unwrap().path.clone()
+    }
+
+    /// Move the database to `path`, which must not hold a database yet: copy
+    /// every tree there, run `commit` (to record the new location), then
+    /// switch over. Operations wait until it is done and then use the new
+    /// copy; the old one is closed once the last of them finishes and is left
+    /// on disk. On any error the copy is deleted and nothing changes.
+    pub fn relocate(&self, path: &Path, commit: impl FnOnce() -> Result<()>) -> Result<()> {
+        let mut storage = self.storage.write().unwrap();
+        storage.db
+            .flush()
+            .map_err(|e| Error::IoError(format!("Failed to flush database: {}", e)))?;
+
+        match copy_database(&storage.db, path).and_then(|copy| commit().map(|()| copy)) {
+            Ok(copy) => {
+                let old = std::mem::replace(&mut storage.db, copy);
+                storage.path = path.to_path_buf();
+                drop(storage);
+                drop(old);
+                Ok(())
+            }
+            Err(e) => {
+                if let Err(cleanup) = std::fs::remove_dir_all(path) {
+                    tracing::warn!("Failed to remove partial database copy at {:?}: {}", path, cleanup);
+                }
+                Err(e)
+            }
+        }
     }
 
     /// Save a torrent session
     pub fn save_torrent(&self, session: &TorrentSession) -> Result<()> {
         let tree = self
-            .db
+            .db()
             .open_tree(KEY_TORRENTS)
             .map_err(|e| Error::IoError(format!("Failed to open torrents tree: {}", e)))?;
 
         let data = serde_json::to_vec(session)
             .map_err(|e| Error::IoError(format!("Failed to serialize torrent: {}", e)))?;
 
-        tree.insert(session.id.as_bytes(), data)
+        let key = torrent_key(&session.id)?;
+        tree.insert(key.as_bytes(), data)
             .map_err(|e| Error::IoError(format!("Failed to save torrent: {}", e)))?;
 
-        self.db
-            .flush()
-            .map_err(|e| Error::IoError(format!("Failed to flush database: {}", e)))?;
+        // The full session now carries the latest progress
+        self.progress_tree()?
+            .remove(key.as_bytes())
+            .map_err(|e| Error::IoError(format!("Failed to save torrent: {}", e)))?;
+
+        self.finish_write(Durability::Deferred)?;
 
         tracing::debug!("Saved torrent session: {}", session.id);
         Ok(())
     }
 
+    /// Save several torrent sessions atomically with a single flush
+    pub fn save_torrents(&self, sessions: &[TorrentSession]) -> Result<()> {
+        let tree = self
+            .db()
+            .open_tree(KEY_TORRENTS)
+            .map_err(|e| Error::IoError(format!("Failed to open torrents tree: {}", e)))?;
+
+        let mut batch = sled::Batch::default();
+        let mut stale_progress = sled::Batch::default();
+        for session in sessions {
+            let data = serde_json::to_vec(session)
+                .map_err(|e| Error::IoError(format!("Failed to serialize torrent: {}", e)))?;
+            let key = torrent_key(&session.id)?;
+            batch.insert(key.as_bytes(), data);
+            stale_progress.remove(key.as_bytes());
+        }
+
+        tree.apply_batch(batch)
+            .map_err(|e| Error::IoError(format!("Failed to save torrents: {}", e)))?;
+        self.progress_tree()?
+            .apply_batch(stale_progress)
+            .map_err(|e| Error::IoError(format!("Failed to save torrents: {}", e)))?;
+
+        self.finish_write(Durability::Deferred)?;
+
+        tracing::debug!("Saved {} torrent sessions", sessions.len());
+        Ok(())
+    }
+
     /// Load a torrent session by ID
     pub fn load_torrent(&self, id: &str) -> Result<Option<TorrentSession>> {
         let tree = self
-            .db
+            .db()
             .open_tree(KEY_TORRENTS)
             .map_err(|e| Error::IoError(format!("Failed to open torrents tree: {}", e)))?;
 
+        let key = torrent_key(id)?;
         match tree
-            .get(id.as_bytes())
+            .get(key.as_bytes())
             .map_err(|e| Error::IoError(format!("Failed to load torrent: {}", e)))?
         {
             Some(data) => {
-                let session = serde_json::from_slice(&data)
-                    .map_err(|e| Error::IoError(format!("Failed to deserialize torrent: {}", e)))?;
+                let mut session = decode_session(&data)?;
+                merge_progress(&self.progress_tree()?, &mut session)?;
                 Ok(Some(session))
             }
             None => Ok(None),
@@ -208,18 +744,19 @@ impl Database {
     /// Load all torrent sessions
     pub fn load_all_torrents(&self) -> Result<Vec<TorrentSession>> {
         let tree = self
-            .db
+            .db()
             .open_tree(KEY_TORRENTS)
             .map_err(|e| Error::IoError(format!("Failed to open torrents tree: {}", e)))?;
 
+        let progress = self.progress_tree()?;
         let mut sessions = Vec::new();
 
         for item in tree.iter() {
             let (_, data) =
                 item.map_err(|e| Error::IoError(format!("Failed to iterate torrents: {}", e)))?;
 
-            let session = serde_json::from_slice(&data)
-                .map_err(|e| Error::IoError(format!("Failed to deserialize torrent: {}", e)))?;
+            let mut session = decode_session(&data)?;
+            merge_progress(&progress, &mut session)?;
 
             sessions.push(session);
         }
@@ -228,56 +765,287 @@ impl Database {
         Ok(sessions)
     }
 
+    /// Call `f` with each torrent session in turn, decoding one at a time
+    /// rather than loading them all. Returns how many there were.
+    pub fn for_each_torrent(&self, mut f: impl FnMut(TorrentSession)) -> Result<usize> {
+        let tree = self
+            .db()
+            .open_tree(KEY_TORRENTS)
+            .map_err(|e| Error::IoError(format!("Failed to open torrents tree: {}", e)))?;
+        let progress = self.progress_tree()?;
+
+        let mut count = 0;
+        for item in tree.iter() {
+            let (_, data) =
+                item.map_err(|e| Error::IoError(format!("Failed to iterate torrents: {}", e)))?;
+            let mut session = decode_session(&data)?;
+            merge_progress(&progress, &mut session)?;
+            f(session);
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Sessions whose storage key, id and metadata don't all name the same
+    /// info hash (see `ids::check_torrent_id`); such a torrent would start
+    /// under one hash while the app tracks another
+    pub fn find_hash_mismatches(&self) -> Result<Vec<SessionHashMismatch>> {
+        let tree = self
+            .db()
+            .open_tree(KEY_TORRENTS)
+            .map_err(|e| Error::IoError(format!("Failed to open torrents tree: {}", e)))?;
+
+        let mut mismatches = Vec::new();
+        for item in tree.iter() {
+            let (key, data) =
+                item.map_err(|e| Error::IoError(format!("Failed to iterate torrents: {}", e)))?;
+            let session = decode_session(&data)?;
+            let key = String::from_utf8_lossy(&key).into_owned();
+            let info_hash = session.metainfo.info_hash_hex();
+            if key != info_hash || session.id != info_hash {
+                mismatches.push(SessionHashMismatch { key, id: session.id, info_hash });
+            }
+        }
+        Ok(mismatches)
+    }
+
     /// Delete a torrent session
     pub fn delete_torrent(&self, id: &str) -> Result<()> {
         let tree = self
-            .db
+            .db()
             .open_tree(KEY_TORRENTS)
             .map_err(|e| Error::IoError(format!("Failed to open torrents tree: {}", e)))?;
 
-        tree.remove(id.as_bytes())
+        let key = torrent_key(id)?;
+        tree.remove(key.as_bytes())
+            .map_err(|e| Error::IoError(format!("Failed to delete torrent: {}", e)))?;
+        self.progress_tree()?
+            .remove(key.as_bytes())
+            .map_err(|e| Error::IoError(format!("Failed to delete torrent: {}", e)))?;
+        self.torrent_files_tree()?
+            .remove(key.as_bytes())
+            .map_err(|e| Error::IoError(format!("Failed to delete torrent: {}", e)))?;
+        self.cloud_files_tree()?
+            .remove(key.as_bytes())
+            .map_err(|e| Error::IoError(format!("Failed to delete torrent: {}", e)))?;
+        self.availability_tree()?
+            .remove(key.as_bytes())
+            .map_err(|e| Error::IoError(format!("Failed to delete torrent: {}", e)))?;
+        self.search_metadata_tree()?
+            .remove(key.as_bytes())
             .map_err(|e| Error::IoError(format!("Failed to delete torrent: {}", e)))?;
 
-        self.db
-            .flush()
-            .map_err(|e| Error::IoError(format!("Failed to flush database: {}", e)))?;
+        self.finish_write(Durability::Deferred)?;
 
         tracing::debug!("Deleted torrent session: {}", id);
         Ok(())
     }
 
-    /// Update torrent progress
+    fn progress_tree(&self) -> Result<sled::Tree> {
+        self.db()
+            .open_tree(KEY_PROGRESS)
+            .map_err(|e| Error::IoError(format!("Failed to open progress tree: {}", e)))
+    }
+
+    fn torrent_files_tree(&self) -> Result<sled::Tree> {
+        self.db()
+            .open_tree(KEY_TORRENT_FILES)
+            .map_err(|e| Error::IoError(format!("Failed to open torrent files tree: {}", e)))
+    }
+
+    /// Keep the .torrent file a torrent was added from. Stored apart from the
+    /// session, which is rewritten far more often.
+    pub fn save_torrent_file(&self, id: &str, data: &[u8]) -> Result<()> {
+        self.torrent_files_tree()?
+            .insert(torrent_key(id)?.as_bytes(), data)
+            .map_err(|e| Error::IoError(format!("Failed to save torrent file: {}", e)))?;
+        self.finish_write(Durability::Deferred)?;
+        Ok(())
+    }
+
+    /// The stashed .torrent file of a torrent, if any
+    pub fn load_torrent_file(&self, id: &str) -> Result<Option<Vec<u8>>> {
+        let data = self
+            .torrent_files_tree()?
+            .get(torrent_key(id)?.as_bytes())
+            .map_err(|e| Error::IoError(format!("Failed to load torrent file: {}", e)))?;
+        Ok(data.map(|data| data.to_vec()))
+    }
+
+    fn cloud_files_tree(&self) -> Result<sled::Tree> {
+        self.db()
+            .open_tree(KEY_CLOUD_FILES)
+            .map_err(|e| Error::IoError(format!("Failed to open cloud files tree: {}", e)))
+    }
+
+    /// Record the final file states of a finished cloud download. Cloud
+    /// torrents have no session, so this is keyed by info hash on its own.
+    pub fn save_cloud_files(&self, info_hash: &str, files: &[CloudFileSummary]) -> Result<()> {
+        let data = serde_json::to_vec(files)
+            .map_err(|e| Error::IoError(format!("Failed to serialize cloud files: {}", e)))?;
+        self.cloud_files_tree()?
+            .insert(torrent_key(info_hash)?.as_bytes(), data)
+            .map_err(|e| Error::IoError(format!("Failed to save cloud files: {}", e)))?;
+        self.finish_write(Durability::Deferred)?;
+        Ok(())
+    }
+
+    /// Final file states of a finished cloud download, if recorded
+    pub fn load_cloud_files(&self, info_hash: &str) -> Result<Option<Vec<CloudFileSummary>>> {
+        let Some(data) = self
+            .cloud_files_tree()?
+            .get(torrent_key(info_hash)?.as_bytes())
+            .map_err(|e| Error::IoError(format!("Failed to load cloud files: {}", e)))?
+        else {
+            return Ok(None);
+        };
+        let files = serde_json::from_slice(&data)
+            .map_err(|e| Error::IoError(format!("Failed to deserialize cloud files: {}", e)))?;
+        Ok(Some(files))
+    }
+
+    fn availability_tree(&self) -> Result<sled::Tree> {
+        self.db()
+            .open_tree(KEY_AVAILABILITY)
+            .map_err(|e| Error::IoError(format!("Failed to open availability tree: {}", e)))
+    }
+
+    /// Add a sample to a torrent's availability history (capped, see
+    /// `availability::append`)
+    pub fn append_availability_sample(&self, id: &str, sample: AvailabilitySample) -> Result<()> {
+        let mut series = self.load_availability_history(id)?;
+        crate::availability::append(&mut series, sample);
+        let data = serde_json::to_vec(&series)
+            .map_err(|e| Error::IoError(format!("Failed to serialize availability history: {}", e)))?;
+        self.availability_tree()?
+            .insert(torrent_key(id)?.as_bytes(), data)
+            .map_err(|e| Error::IoError(format!("Failed to save availability history: {}", e)))?;
+        self.finish_write(Durability::Deferred)?;
+        Ok(())
+    }
+
+    /// A torrent's availability samples, oldest first (empty if none)
+    pub fn load_availability_history(&self, id: &str) -> Result<Vec<AvailabilitySample>> {
+        let Some(data) = self
+            .availability_tree()?
+            .get(torrent_key(id)?.as_bytes())
+            .map_err(|e| Error::IoError(format!("Failed to load availability history: {}", e)))?
+        else {
+            return Ok(Vec::new());
+        };
+        serde_json::from_slice(&data)
+            .map_err(|e| Error::IoError(format!("Failed to deserialize availability history: {}", e)))
+    }
+
+    fn search_metadata_tree(&self) -> Result<sled::Tree> {
+        self.db()
+            .open_tree(KEY_SEARCH_METADATA)
+            .map_err(|e| Error::IoError(format!("Failed to open search metadata tree: {}", e)))
+    }
+
+    /// Store a torrent's metadata search summary. Not flushed: it can
+    /// always be rebuilt from the sessions.
+    pub fn save_search_metadata(&self, id: &str, summary: &MetadataSummary) -> Result<()> {
+        let data = serde_json::to_vec(summary)
+            .map_err(|e| Error::IoError(format!("Failed to serialize search metadata: {}", e)))?;
+        self.search_metadata_tree()?
+            .insert(torrent_key(id)?.as_bytes(), data)
+            .map_err(|e| Error::IoError(format!("Failed to save search metadata: {}", e)))?;
+        Ok(())
+    }
+
+    /// Call `f` with each stored metadata summary and its torrent id, one at
+    /// a time. Undecodable entries are skipped.
+    pub fn for_each_search_metadata(&self, mut f: impl FnMut(&str, MetadataSummary)) -> Result<()> {
+        for item in self.search_metadata_tree()?.iter() {
+            let (key, data) = item
+                .map_err(|e| Error::IoError(format!("Failed to iterate search metadata: {}", e)))?;
+            let Ok(id) = std::str::from_utf8(&key) else { continue };
+            match serde_json::from_slice(&data) {
+                Ok(summary) => f(id, summary),
+                Err(e) => tracing::warn!("Skipping unreadable search metadata of {}: {}", id, e),
+            }
+        }
+        Ok(())
+    }
+
+    /// Drop every metadata summary (before a rebuild)
+    pub fn clear_search_metadata(&self) -> Result<()> {
+        self.search_metadata_tree()?
+            .clear()
+            .map_err(|e| Error::IoError(format!("Failed to clear search metadata: {}", e)))
+    }
+
+    /// Read-modify-write the progress record of a torrent
+    ///
+    /// Only the small progress record is decoded and written; the session is
+    /// just checked for existence. There's no explicit flush: sled writes
+    /// dirty pages back on its own interval, so a burst of updates to the same
+    /// torrent lands on disk as one write. Returns false for unknown torrents.
+    fn modify_progress(&self, id: &str, modify: impl FnOnce(&mut SessionProgress)) -> Result<bool> {
+        let key = torrent_key(id)?;
+        let torrents = self
+            .db()
+            .open_tree(KEY_TORRENTS)
+            .map_err(|e| Error::IoError(format!("Failed to open torrents tree: {}", e)))?;
+        let Some(session_data) = torrents
+            .get(key.as_bytes())
+            .map_err(|e| Error::IoError(format!("Failed to load torrent: {}", e)))?
+        else {
+            return Ok(false);
+        };
+
+        let progress = self.progress_tree()?;
+        let mut record = match progress
+            .get(key.as_bytes())
+            .map_err(|e| Error::IoError(format!("Failed to load progress: {}", e)))?
+        {
+            Some(data) => decode_progress(&data)?,
+            // First update since the last full save: pick the fields out of
+            // the session without decoding its metainfo
+            None => decode_progress(&session_data)?,
+        };
+
+        modify(&mut record);
+        record.last_activity = chrono::Utc::now().timestamp();
+
+        let data = serde_json::to_vec(&record)
+            .map_err(|e| Error::IoError(format!("Failed to serialize progress: {}", e)))?;
+        progress
+            .insert(key.as_bytes(), data)
+            .map_err(|e| Error::IoError(format!("Failed to save progress: {}", e)))?;
+        Ok(true)
+    }
+
+    /// Update torrent progress; returns false if the torrent is unknown
     pub fn update_progress(
         &self,
         id: &str,
         bitfield: Vec<u8>,
         downloaded: u64,
         uploaded: u64,
-    ) -> Result<()> {
-        if let Some(mut session) = self.load_torrent(id)? {
-            session.bitfield = bitfield;
-            session.downloaded = downloaded;
-            session.uploaded = uploaded;
-            session.last_activity = chrono::Utc::now().timestamp();
-            self.save_torrent(&session)?;
-        }
-        Ok(())
+    ) -> Result<bool> {
+        self.modify_progress(id, |record| {
+            record.bitfield = bitfield;
+            record.downloaded = downloaded;
+            record.uploaded = uploaded;
+        })
     }
 
-    /// Update torrent state
-    pub fn update_state(&self, id: &str, state: String) -> Result<()> {
-        if let Some(mut session) = self.load_torrent(id)? {
-            session.state = state;
-            session.last_activity = chrono::Utc::now().timestamp();
-            self.save_torrent(&session)?;
-        }
-        Ok(())
+    /// Update torrent state; returns false if the torrent is unknown
+    pub fn update_state(&self, id: &str, state: String) -> Result<bool> {
+        self.modify_progress(id, |record| record.state = state)
+    }
+
+    /// Replace the whole progress record; returns false if the torrent is unknown
+    pub fn update_session_progress(&self, id: &str, progress: SessionProgress) -> Result<bool> {
+        self.modify_progress(id, |record| *record = progress)
     }
 
     /// Save application settings
     pub fn save_settings(&self, settings: &AppSettings) -> Result<()> {
         let tree = self
-            .db
+            .db()
             .open_tree(KEY_SETTINGS)
             .map_err(|e| Error::IoError(format!("Failed to open settings tree: {}", e)))?;
 
@@ -287,9 +1055,7 @@ impl Database {
         tree.insert(b"app", data)
             .map_err(|e| Error::IoError(format!("Failed to save settings: {}", e)))?;
 
-        self.db
-            .flush()
-            .map_err(|e| Error::IoError(format!("Failed to flush database: {}", e)))?;
+        self.finish_write(Durability::Deferred)?;
 
         tracing::debug!("Saved application settings");
         Ok(())
@@ -298,7 +1064,7 @@ impl Database {
     /// Load application settings
     pub fn load_settings(&self) -> Result<AppSettings> {
         let tree = self
-            .db
+            .db()
             .open_tree(KEY_SETTINGS)
             .map_err(|e| Error::IoError(format!("Failed to open settings tree: {}", e)))?;
 
@@ -346,26 +1112,24 @@ impl Database {
 
     /// Clear all data (for testing)
     pub fn clear_all(&self) -> Result<()> {
-        self.db
+        self.db()
             .clear()
             .map_err(|e| Error::IoError(format!("Failed to clear database: {}", e)))?;
-        self.db
-            .flush()
-            .map_err(|e| Error::IoError(format!("Failed to flush database: {}", e)))?;
+        self.finish_write(Durability::Immediate)?;
         Ok(())
     }
 
     /// Get database statistics
     pub fn stats(&self) -> DatabaseStats {
         DatabaseStats {
-            size_on_disk: self.db.size_on_disk().unwrap_or(0),
+            size_on_disk: self.db().size_on_disk().unwrap_or(0),
         }
     }
 
     /// Save debrid credentials for a provider
     pub fn save_debrid_credentials(&self, credentials: &DebridCredentials) -> Result<()> {
         let tree = self
-            .db
+            .db()
             .open_tree(KEY_DEBRID_CREDENTIALS)
             .map_err(|e| Error::IoError(format!("Failed to open credentials tree: {}", e)))?;
 
@@ -376,9 +1140,7 @@ impl Database {
         tree.insert(key, data)
             .map_err(|e| Error::IoError(format!("Failed to save credentials: {}", e)))?;
 
-        self.db
-            .flush()
-            .map_err(|e| Error::IoError(format!("Failed to flush database: {}", e)))?;
+        self.finish_write(Durability::Deferred)?;
 
         tracing::debug!("Saved credentials for provider: {:?}", credentials.provider);
         Ok(())
@@ -390,7 +1152,7 @@ impl Database {
         provider: DebridProviderType,
     ) -> Result<Option<DebridCredentials>> {
         let tree = self
-            .db
+            .db()
             .open_tree(KEY_DEBRID_CREDENTIALS)
             .map_err(|e| Error::IoError(format!("Failed to open credentials tree: {}", e)))?;
 
@@ -412,7 +1174,7 @@ impl Database {
     /// Load all debrid credentials
     pub fn load_all_debrid_credentials(&self) -> Result<Vec<DebridCredentials>> {
         let tree = self
-            .db
+            .db()
             .open_tree(KEY_DEBRID_CREDENTIALS)
             .map_err(|e| Error::IoError(format!("Failed to open credentials tree: {}", e)))?;
 
@@ -435,7 +1197,7 @@ impl Database {
     /// Delete debrid credentials for a provider
     pub fn delete_debrid_credentials(&self, provider: DebridProviderType) -> Result<()> {
         let tree = self
-            .db
+            .db()
             .open_tree(KEY_DEBRID_CREDENTIALS)
             .map_err(|e| Error::IoError(format!("Failed to open credentials tree: {}", e)))?;
 
@@ -443,9 +1205,7 @@ impl Database {
         tree.remove(key)
             .map_err(|e| Error::IoError(format!("Failed to delete credentials: {}", e)))?;
 
-        self.db
-            .flush()
-            .map_err(|e| Error::IoError(format!("Failed to flush database: {}", e)))?;
+        self.finish_write(Durability::Immediate)?;
 
         tracing::debug!("Deleted credentials for provider: {:?}", provider);
         Ok(())
@@ -454,7 +1214,7 @@ impl Database {
     /// Save master password data (hash and salt)
     pub fn save_master_password(&self, password_data: &MasterPasswordData) -> Result<()> {
         let tree = self
-            .db
+            .db()
             .open_tree(KEY_MASTER_PASSWORD)
             .map_err(|e| Error::IoError(format!("Failed to open master password tree: {}", e)))?;
 
@@ -464,9 +1224,7 @@ impl Database {
         tree.insert(b"data", data)
             .map_err(|e| Error::IoError(format!("Failed to save master password: {}", e)))?;
 
-        self.db
-            .flush()
-            .map_err(|e| Error::IoError(format!("Failed to flush database: {}", e)))?;
+        self.finish_write(Durability::Immediate)?;
 
         tracing::debug!("Saved master password data");
         Ok(())
@@ -475,7 +1233,7 @@ impl Database {
     /// Load master password data
     pub fn load_master_password(&self) -> Result<Option<MasterPasswordData>> {
         let tree = self
-            .db
+            .db()
             .open_tree(KEY_MASTER_PASSWORD)
             .map_err(|e| Error::IoError(format!("Failed to open master password tree: {}", e)))?;
 
@@ -493,6 +1251,51 @@ impl Database {
         }
     }
 
+    /// Save new master password data together with the credentials
+    /// re-encrypted under it, in one transaction: a crash leaves either the
+    /// old password and credentials or the new ones, never a mix
+    pub fn replace_master_password(
+        &self,
+        password_data: &MasterPasswordData,
+        credentials: &[DebridCredentials],
+    ) -> Result<()> {
+        use sled::transaction::TransactionError;
+        use sled::Transactional;
+
+        let password_tree = self
+            .db()
+            .open_tree(KEY_MASTER_PASSWORD)
+            .map_err(|e| Error::IoError(format!("Failed to open master password tree: {}", e)))?;
+        let credentials_tree = self
+            .db()
+            .open_tree(KEY_DEBRID_CREDENTIALS)
+            .map_err(|e| Error::IoError(format!("Failed to open credentials tree: {}", e)))?;
+
+        let password_value = serde_json::to_vec(password_data)
+            .map_err(|e| Error::IoError(format!("Failed to serialize master password: {}", e)))?;
+        let mut credential_values = Vec::with_capacity(credentials.len());
+        for credential in credentials {
+            let value = serde_json::to_vec(credential)
+                .map_err(|e| Error::IoError(format!("Failed to serialize credentials: {}", e)))?;
+            credential_values.push((credential.provider.as_str().as_bytes(), value));
+        }
+
+        (&password_tree, &credentials_tree)
+            .transaction(|(password_tree, credentials_tree)| {
+                password_tree.insert(&b"data"[..], password_value.as_slice())?;
+                for (key, value) in &credential_values {
+                    credentials_tree.insert(*key, value.as_slice())?;
+                }
+                Ok(())
+            })
+            .map_err(|e: TransactionError| Error::IoError(format!("Failed to save master password: {}", e)))?;
+
+        self.finish_write(Durability::Immediate)?;
+
+        tracing::debug!("Saved master password data and {} re-encrypted credentials", credentials.len());
+        Ok(())
+    }
+
     /// Check if master password is set
     pub fn has_master_password(&self) -> Result<bool> {
         Ok(self.load_master_password()?.is_some())
@@ -501,7 +1304,7 @@ impl Database {
     /// Delete master password (and all debrid credentials for security)
     pub fn delete_master_password(&self) -> Result<()> {
         let tree = self
-            .db
+            .db()
             .open_tree(KEY_MASTER_PASSWORD)
             .map_err(|e| Error::IoError(format!("Failed to open master password tree: {}", e)))?;
 
@@ -510,7 +1313,7 @@ impl Database {
 
         // Also clear all debrid credentials since they can't be decrypted without the password
         let creds_tree = self
-            .db
+            .db()
             .open_tree(KEY_DEBRID_CREDENTIALS)
             .map_err(|e| Error::IoError(format!("Failed to open credentials tree: {}", e)))?;
 
@@ -518,32 +1321,195 @@ impl Database {
             .clear()
             .map_err(|e| Error::IoError(format!("Failed to clear credentials: {}", e)))?;
 
-        self.db
-            .flush()
-            .map_err(|e| Error::IoError(format!("Failed to flush database: {}", e)))?;
+        self.finish_write(Durability::Immediate)?;
 
         tracing::warn!("Deleted master password and all debrid credentials");
         Ok(())
     }
 
-    /// Flush all pending writes to disk
-    pub fn flush(&self) -> Result<()> {
-        self.db
-            .flush()
-            .map_err(|e| Error::IoError(format!("Failed to flush database: {}", e)))?;
+    /// Save an API token record
+    pub fn save_api_token(&self, record: &ApiTokenRecord) -> Result<()> {
+        let tree = self
+            .db()
+            .open_tree(KEY_API_TOKENS)
+            .map_err(|e| Error::IoError(format!("Failed to open API token tree: {}", e)))?;
+
+        let data = serde_json::to_vec(record)
+            .map_err(|e| Error::IoError(format!("Failed to serialize API token: {}", e)))?;
+
+        tree.insert(record.id.as_bytes(), data)
+            .map_err(|e| Error::IoError(format!("Failed to save API token: {}", e)))?;
+
+        self.finish_write(Durability::Immediate)?;
+        Ok(())
+    }
+
+    /// Load one API token record by id
+    pub fn load_api_token(&self, id: &str) -> Result<Option<ApiTokenRecord>> {
+        let tree = self
+            .db()
+            .open_tree(KEY_API_TOKENS)
+            .map_err(|e| Error::IoError(format!("Failed to open API token tree: {}", e)))?;
+
+        match tree
+            .get(id.as_bytes())
+            .map_err(|e| Error::IoError(format!("Failed to load API token: {}", e)))?
+        {
+            Some(data) => {
+                let record = serde_json::from_slice(&data)
+                    .map_err(|e| Error::IoError(format!("Failed to deserialize API token: {}", e)))?;
+                Ok(Some(record))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Load every API token record
+    pub fn load_api_tokens(&self) -> Result<Vec<ApiTokenRecord>> {
+        let tree = self
+            .db()
+            .open_tree(KEY_API_TOKENS)
+            .map_err(|e| Error::IoError(format!("Failed to open API token tree: {}", e)))?;
+
+        let mut records = Vec::new();
+        for item in tree.iter() {
+            let (_, data) = item.map_err(|e| Error::IoError(format!("Failed to iterate API tokens: {}", e)))?;
+            match serde_json::from_slice(&data) {
+                Ok(record) => records.push(record),
+                Err(e) => tracing::error!("Failed to deserialize API token: {}", e),
+            }
+        }
+        Ok(records)
+    }
+
+    /// Delete an API token record; returns whether it existed
+    pub fn delete_api_token(&self, id: &str) -> Result<bool> {
+        let tree = self
+            .db()
+            .open_tree(KEY_API_TOKENS)
+            .map_err(|e| Error::IoError(format!("Failed to open API token tree: {}", e)))?;
+
+        let existed = tree
+            .remove(id.as_bytes())
+            .map_err(|e| Error::IoError(format!("Failed to delete API token: {}", e)))?
+            .is_some();
+
+        self.finish_write(Durability::Immediate)?;
+        Ok(existed)
+    }
+
+    /// Flush all pending writes to disk now: on shutdown, before a backup
+    /// and after critical writes
+    pub fn flush_now(&self) -> Result<()> {
+        let db = self.db();
+        self.flush.dirty.store(false, Ordering::SeqCst);
+        #[cfg(test)]
+        FLUSHES.with(|n| n.set(n.get() + 1));
+        let flushed = db.flush();
+        *self.flush.last.lock().unwrap() = Instant::now();
+        if let Err(e) = flushed {
+            // Still pending; the flusher retries
+            self.flush.dirty.store(true, Ordering::SeqCst);
+            return Err(Error::IoError(format!("Failed to flush database: {}", e)));
+        }
         Ok(())
     }
 
+    /// Flush if there are deferred writes and the last flush was at least
+    /// `interval` ago; true if it did. Called by the flusher task.
+    pub fn flush_if_due(&self, interval: Duration) -> Result<bool> {
+        if !self.flush.dirty.load(Ordering::SeqCst) || self.flush.last.lock().unwrap().elapsed() < interval {
+            return Ok(false);
+        }
+        self.flush_now()?;
+        Ok(true)
+    }
+
+    /// Finish a write: flush now, or leave it to the flusher
+    fn finish_write(&self, durability: Durability) -> Result<()> {
+        match durability {
+            Durability::Immediate => self.flush_now(),
+            Durability::Deferred => {
+                self.flush.dirty.store(true, Ordering::SeqCst);
+                Ok(())
+            }
+        }
+    }
+
+    /// Add a benchmark result to the history, dropping the oldest beyond
+    /// `benchmark::HISTORY_LEN`
+    pub fn save_benchmark(&self, report: &crate::benchmark::BenchmarkReport) -> Result<()> {
+        let db = self.db();
+        let tree = db
+            .open_tree(KEY_BENCHMARKS)
+            .map_err(|e| Error::IoError(format!("Failed to open benchmark tree: {}", e)))?;
+
+        let data = serde_json::to_vec(report)
+            .map_err(|e| Error::IoError(format!("Failed to serialize benchmark: {}", e)))?;
+        let id = db
+            .generate_id()
+            .map_err(|e| Error::IoError(format!("Failed to save benchmark: {}", e)))?;
+        tree.insert(id.to_be_bytes(), data)
+            .map_err(|e| Error::IoError(format!("Failed to save benchmark: {}", e)))?;
+
+        while tree.len() > crate::benchmark::HISTORY_LEN {
+            tree.pop_min()
+                .map_err(|e| Error::IoError(format!("Failed to trim benchmark history: {}", e)))?;
+        }
+
+        self.finish_write(Durability::Deferred)?;
+        Ok(())
+    }
+
+    /// Benchmark history, oldest first
+    pub fn load_benchmarks(&self) -> Result<Vec<crate::benchmark::BenchmarkReport>> {
+        let tree = self
+            .db()
+            .open_tree(KEY_BENCHMARKS)
+            .map_err(|e| Error::IoError(format!("Failed to open benchmark tree: {}", e)))?;
+
+        let mut reports = Vec::new();
+        for item in tree.iter() {
+            let (_, data) = item.map_err(|e| Error::IoError(format!("Failed to iterate benchmarks: {}", e)))?;
+            match serde_json::from_slice(&data) {
+                Ok(report) => reports.push(report),
+                Err(e) => tracing::error!("Failed to deserialize benchmark: {}", e),
+            }
+        }
+        Ok(reports)
+    }
+
     /// Dump all data to JSON string for backup
     pub fn dump_all(&self) -> Result<String> {
         let settings = self.load_settings()?;
         let torrents = self.load_all_torrents()?;
 
+        let mut torrent_files = std::collections::HashMap::new();
+        for item in self.torrent_files_tree()?.iter() {
+            let (key, data) =
+                item.map_err(|e| Error::IoError(format!("Failed to iterate torrent files: {}", e)))?;
+            torrent_files.insert(String::from_utf8_lossy(&key).into_owned(), BASE64.encode(data));
+        }
+
+        let mut availability = std::collections::HashMap::new();
+        for item in self.availability_tree()?.iter() {
+            let (key, data) =
+                item.map_err(|e| Error::IoError(format!("Failed to iterate availability history: {}", e)))?;
+            match serde_json::from_slice::<Vec<AvailabilitySample>>(&data) {
+                Ok(series) => {
+                    availability.insert(String::from_utf8_lossy(&key).into_owned(), series);
+                }
+                Err(e) => tracing::error!("Failed to deserialize availability history: {}", e),
+            }
+        }
+
         let backup = BackupData {
-            version: 1,
+            version: BACKUP_VERSION,
             timestamp: chrono::Utc::now().timestamp(),
             settings,
             torrents,
+            torrent_files,
+            availability,
         };
 
         serde_json::to_string(&backup).map_err(|e| Error::DatabaseError(e.to_string()))
@@ -551,9 +1517,121 @@ impl Database {
 
     /// Restore data from JSON string
     /// Warning: This overwrites existing settings and torrents (upsert)
-    pub fn restore(&self, json: &str) -> Result<()> {
+    pub fn restore(&self, json: &str) -> Result<BackupSummary> {
         let backup: BackupData =
             serde_json::from_str(json).map_err(|e| Error::DatabaseError(e.to_string()))?;
+        self.restore_backup(backup)
+    }
+
+    /// Replace the whole database with a backup, checked first
+    ///
+    /// Unlike `restore` nothing of the current data survives. The backup is
+    /// restored into a scratch database next to this one and read back; only
+    /// if that matches the backup are the live contents swapped for it. A bad
+    /// backup leaves the database untouched, and a failure during the swap
+    /// puts the previous contents back.
+    pub fn restore_replacing(&self, json: &str) -> Result<RestoreSummary> {
+        let backup: BackupData = serde_json::from_str(json)
+            .map_err(|e| Error::ValidationError(format!("Not a valid backup: {}", e)))?;
+        if backup.version > BACKUP_VERSION {
+            return Err(Error::ValidationError(format!(
+                "Backup format {} is newer than this version understands ({})",
+                backup.version, BACKUP_VERSION
+            )));
+        }
+
+        let mut torrent_ids = std::collections::HashSet::new();
+        for torrent in &backup.torrents {
+            torrent_ids.insert(torrent_key(&torrent.id)?);
+        }
+        let expected = RestoreSummary {
+            torrents: torrent_ids.len(),
+            torrent_files: backup.torrent_files.values().filter(|data| BASE64.decode(data).is_ok()).count(),
+        };
+
+        self.replace_with(|staged| {
+            staged.restore_backup(backup)?;
+            Ok(expected)
+        })
+    }
+
+    /// `restore_replacing` for a stream backup (see `stream`). Records that
+    /// don't decode are left out, as on import; a damaged file is refused.
+    pub fn restore_replacing_stream<R: std::io::Read + std::io::Seek>(&self, input: R) -> Result<RestoreSummary> {
+        self.replace_with(|staged| {
+            let imported = staged.import_stream(input)?;
+            Ok(RestoreSummary { torrents: imported.torrents, torrent_files: imported.torrent_files })
+        })
+    }
+
+    /// Fill a scratch database with `stage`, which returns what it should
+    /// now hold, check that and swap it in
+    fn replace_with(&self, stage: impl FnOnce(&Database) -> Result<RestoreSummary>) -> Result<RestoreSummary> {
+        let scratch = self.sibling_path("restore");
+        let previous = self.sibling_path("previous");
+        let _ = std::fs::remove_dir_all(&scratch);
+        let staged = Database::open(&scratch).and_then(|staged| {
+            let expected = stage(&staged)?;
+            let restored = RestoreSummary {
+                torrents: staged.load_all_torrents()?.len(),
+                torrent_files: staged.torrent_files_tree()?.len(),
+            };
+            staged.load_settings()?;
+            if restored != expected {
+                return Err(Error::DatabaseError(format!(
+                    "Restored backup holds {:?} instead of {:?}",
+                    restored, expected
+                )));
+            }
+            Ok((staged, expected))
+        });
+        let result = staged.and_then(|(staged, expected)| {
+            self.replace_contents(&staged.db(), &previous)?;
+            Ok(expected)
+        });
+        if let Err(e) = std::fs::remove_dir_all(&scratch) {
+            tracing::warn!("Failed to remove scratch database {:?}: {}", scratch, e);
+        }
+        result
+    }
+
+    /// `<database dir>.<suffix>`, next to the database
+    fn sibling_path(&self, suffix: &str) -> PathBuf {
+        let path = self.path();
+        let name = path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+        path.with_file_name(format!("{}.{}", name, suffix))
+    }
+
+    /// Make the live database an exact copy of `source`, keeping a copy of
+    /// the old contents at `previous` until that succeeded
+    fn replace_contents(&self, source: &Db, previous: &Path) -> Result<()> {
+        let _ = std::fs::remove_dir_all(previous);
+        let storage = self.storage.write().unwrap();
+        storage.db
+            .flush()
+            .map_err(|e| Error::IoError(format!("Failed to flush database: {}", e)))?;
+        let old = copy_database(&storage.db, previous)?;
+
+        if let Err(e) = copy_trees(source, &storage.db) {
+            tracing::error!("Replacing the database failed, putting the previous contents back: {}", e);
+            if let Err(undo) = copy_trees(&old, &storage.db) {
+                tracing::error!("Failed to put the previous database back (a copy is at {:?}): {}", previous, undo);
+                return Err(e);
+            }
+            drop(old);
+            let _ = std::fs::remove_dir_all(previous);
+            return Err(e);
+        }
+
+        drop(old);
+        if let Err(e) = std::fs::remove_dir_all(previous) {
+            tracing::warn!("Failed to remove the previous database copy {:?}: {}", previous, e);
+        }
+        Ok(())
+    }
+
+    fn restore_backup(&self, backup: BackupData) -> Result<BackupSummary> {
+        let mut summary = BackupSummary::default();
 
         // Restore settings
         self.save_settings(&backup.settings)?;
@@ -561,10 +1639,84 @@ impl Database {
         // Restore torrents (upsert)
         for torrent in backup.torrents {
             self.save_torrent(&torrent)?;
+            summary.torrents += 1;
         }
 
-        Ok(())
+        for (id, data) in backup.torrent_files {
+            match BASE64.decode(&data) {
+                Ok(data) => {
+                    self.save_torrent_file(&id, &data)?;
+                    summary.torrent_files += 1;
+                }
+                Err(e) => {
+                    tracing::warn!("Skipping unreadable torrent file for {} in backup: {}", id, e);
+                    summary.skipped += 1;
+                }
+            }
+        }
+
+        for (id, series) in backup.availability {
+            let data = serde_json::to_vec(&series)
+                .map_err(|e| Error::IoError(format!("Failed to serialize availability history: {}", e)))?;
+            self.availability_tree()?
+                .insert(torrent_key(&id)?.as_bytes(), data)
+                .map_err(|e| Error::IoError(format!("Failed to save availability history: {}", e)))?;
+            summary.availability += 1;
+        }
+
+        Ok(summary)
+    }
+}
+
+/// Copy every tree of `source` into a new database at `path` (flushed)
+fn copy_database(source: &Db, path: &Path) -> Result<Db> {
+    let target = sled::open(path)
+        .map_err(|e| Error::IoError(format!("Failed to open the copy while copying the database: {}", e)))?;
+    copy_trees(source, &target)?;
+    Ok(target)
+}
+
+/// Make `target` hold exactly the trees of `source` (flushed)
+fn copy_trees(source: &Db, target: &Db) -> Result<()> {
+    let io_error = |what: &str, e: sled::Error| Error::IoError(format!("Failed to {} while copying the database: {}", what, e));
+    let names = source.tree_names();
+    for name in target.tree_names() {
+        if names.contains(&name) {
+            continue;
+        }
+        // The default tree can't be dropped, but it is in every source too
+        target.drop_tree(&name).map_err(|e| io_error("drop a tree", e))?;
     }
+
+    for name in names {
+        let from = source.open_tree(&name).map_err(|e| io_error("open a tree", e))?;
+        let to = target.open_tree(&name).map_err(|e| io_error("create a tree", e))?;
+        to.clear().map_err(|e| io_error("clear a tree", e))?;
+        for entry in from.iter() {
+            let (key, value) = entry.map_err(|e| io_error("read", e))?;
+            to.insert(key, value).map_err(|e| io_error("write", e))?;
+        }
+        if to.len() != from.len() {
+            return Err(Error::DatabaseError(format!(
+                "copy of tree {} has {} entries instead of {}",
+                String::from_utf8_lossy(&name),
+                to.len(),
+                from.len()
+            )));
+        }
+    }
+    target.flush().map_err(|e| io_error("flush the copy", e))?;
+    Ok(())
+}
+
+/// Newest `BackupData::version` written and understood
+const BACKUP_VERSION: u32 = 1;
+
+/// What `restore_replacing` brought in
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RestoreSummary {
+    pub torrents: usize,
+    pub torrent_files: usize,
 }
 
 /// Backup data structure
@@ -574,6 +1726,23 @@ pub struct BackupData {
     pub timestamp: i64,
     pub settings: AppSettings,
     pub torrents: Vec<TorrentSession>,
+    /// Stashed .torrent files by torrent id (base64)
+    #[serde(default)]
+    pub torrent_files: std::collections::HashMap<String, String>,
+    /// Availability history by torrent id
+    #[serde(default)]
+    pub availability: std::collections::HashMap<String, Vec<AvailabilitySample>>,
+}
+
+/// A stored session that disagrees with itself on its info hash
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SessionHashMismatch {
+    /// Key the session is stored under
+    pub key: String,
+    /// The session's `id`
+    pub id: String,
+    /// What its metadata hashes to
+    pub info_hash: String,
 }
 
 #[derive(Debug, Clone)]
@@ -602,6 +1771,8 @@ mod tests {
                 name: "test.txt".to_string(),
                 total_size: 20000,
                 is_single_file: true,
+                private: false,
+                legacy_names: None,
             },
             info_hash: [0u8; 20],
             creation_date: None,
@@ -618,13 +1789,67 @@ mod tests {
         assert!(db_path.exists());
     }
 
+    #[test]
+    fn test_find_hash_mismatches() {
+        let temp_dir = TempDir::new().unwrap();
+        let db = Database::open(temp_dir.path().join("test.db")).unwrap();
+        let zero = "0".repeat(40);
+
+        // The metadata hashes to all zeros
+        let session = |id: &str| TorrentSession {
+            id: id.to_string(),
+            metainfo: create_test_metainfo(),
+            bitfield: Vec::new(),
+            num_pieces: 2,
+            downloaded: 0,
+            uploaded: 0,
+            state: "paused".to_string(),
+            download_dir: "/tmp/downloads".to_string(),
+            added_at: 1234567890,
+            last_activity: 1234567890,
+            source: DownloadSource::P2P,
+            completed_at: None,
+            volume_id: None,
+            category: None,
+            traffic: Default::default(),
+            swarm: None,
+            root_name: None,
+            data_sync: Default::default(),
+            queue_position: None,
+            saved_peers: Vec::new(),
+            added_from: None,
+            storage_mode: Default::default(),
+            auto_stop: Default::default(),
+            low_disk_paused_at: None,
+            linked_files: Vec::new(),
+            disabled_trackers: Vec::new(),
+            file_completed_at: Default::default(),
+        };
+        db.save_torrent(&session(&zero)).unwrap();
+        assert!(db.find_hash_mismatches().unwrap().is_empty());
+
+        // Tracked under another id, and stored under another key
+        db.save_torrent(&session(&"1".repeat(40))).unwrap();
+        let data = serde_json::to_vec(&session(&zero)).unwrap();
+        db.db().open_tree(KEY_TORRENTS).unwrap().insert("2".repeat(40).as_bytes(), data).unwrap();
+
+        let mismatches = db.find_hash_mismatches().unwrap();
+        assert_eq!(
+            mismatches,
+            vec![
+                SessionHashMismatch { key: "1".repeat(40), id: "1".repeat(40), info_hash: zero.clone() },
+                SessionHashMismatch { key: "2".repeat(40), id: zero.clone(), info_hash: zero.clone() },
+            ]
+        );
+    }
+
     #[test]
     fn test_save_and_load_torrent() {
         let temp_dir = TempDir::new().unwrap();
         let db = Database::open(temp_dir.path().join("test.db")).unwrap();
 
         let session = TorrentSession {
-            id: "test123".to_string(),
+            id: "1111111111111111111111111111111111111111".to_string(),
             metainfo: create_test_metainfo(),
             bitfield: vec![0b11000000],
             num_pieces: 2,
@@ -636,17 +1861,34 @@ mod tests {
             last_activity: 1234567890,
             source: DownloadSource::P2P,
             completed_at: None,
+            volume_id: None,
+            category: None,
+            traffic: Default::default(),
+            swarm: None,
+            root_name: None,
+            data_sync: Default::default(),
+            queue_position: None,
+            saved_peers: Vec::new(),
+            added_from: None,
+            storage_mode: Default::default(),
+            auto_stop: Default::default(),
+            low_disk_paused_at: None,
+            linked_files: Vec::new(),
+            disabled_trackers: vec!["http://tracker.example.com/announce".to_string()],
+            file_completed_at: crate::piece::FileCompletions::from([(2, 1_700_000_000)]),
         };
 
         db.save_torrent(&session).unwrap();
 
         let loaded = db
-            .load_torrent("test123")
+            .load_torrent("1111111111111111111111111111111111111111")
             .unwrap()
             .expect("Torrent should exist");
         assert_eq!(loaded.id, session.id);
         assert_eq!(loaded.downloaded, session.downloaded);
         assert_eq!(loaded.state, session.state);
+        assert_eq!(loaded.disabled_trackers, session.disabled_trackers);
+        assert_eq!(loaded.file_completed_at, session.file_completed_at);
     }
 
     #[test]
@@ -655,7 +1897,7 @@ mod tests {
         let db = Database::open(temp_dir.path().join("test.db")).unwrap();
 
         let session1 = TorrentSession {
-            id: "torrent1".to_string(),
+            id: "2222222222222222222222222222222222222222".to_string(),
             metainfo: create_test_metainfo(),
             bitfield: vec![],
             num_pieces: 2,
@@ -667,10 +1909,25 @@ mod tests {
             last_activity: 1234567890,
             source: DownloadSource::P2P,
             completed_at: Some(1234567990),
+            volume_id: None,
+            category: None,
+            traffic: Default::default(),
+            swarm: None,
+            root_name: None,
+            data_sync: Default::default(),
+            queue_position: None,
+            saved_peers: Vec::new(),
+            added_from: None,
+            storage_mode: Default::default(),
+            auto_stop: Default::default(),
+            low_disk_paused_at: None,
+            linked_files: Vec::new(),
+            disabled_trackers: Vec::new(),
+            file_completed_at: Default::default(),
         };
 
         let session2 = TorrentSession {
-            id: "torrent2".to_string(),
+            id: "3333333333333333333333333333333333333333".to_string(),
             metainfo: create_test_metainfo(),
             bitfield: vec![],
             num_pieces: 2,
@@ -681,6 +1938,22 @@ mod tests {
             added_at: 1234567890,
             last_activity: 1234567890,
             source: DownloadSource::P2P,
+            completed_at: None,
+            volume_id: None,
+            category: None,
+            traffic: Default::default(),
+            swarm: None,
+            root_name: None,
+            data_sync: Default::default(),
+            queue_position: None,
+            saved_peers: Vec::new(),
+            added_from: None,
+            storage_mode: Default::default(),
+            auto_stop: Default::default(),
+            low_disk_paused_at: None,
+            linked_files: Vec::new(),
+            disabled_trackers: Vec::new(),
+            file_completed_at: Default::default(),
         };
 
         db.save_torrent(&session1).unwrap();
@@ -690,13 +1963,102 @@ mod tests {
         assert_eq!(all.len(), 2);
     }
 
+    #[test]
+    fn test_save_torrents_batch() {
+        let temp_dir = TempDir::new().unwrap();
+        let db = Database::open(temp_dir.path().join("test.db")).unwrap();
+
+        let sessions: Vec<TorrentSession> = ["4444444444444444444444444444444444444444", "5555555555555555555555555555555555555555"]
+            .iter()
+            .map(|id| TorrentSession {
+                id: id.to_string(),
+                metainfo: create_test_metainfo(),
+                bitfield: vec![],
+                num_pieces: 2,
+                downloaded: 0,
+                uploaded: 0,
+                state: "paused".to_string(),
+                download_dir: "/tmp".to_string(),
+                added_at: 1234567890,
+                last_activity: 1234567890,
+                source: DownloadSource::P2P,
+                completed_at: None,
+                volume_id: None,
+                category: Some("linux".to_string()),
+                traffic: Default::default(),
+                swarm: None,
+                root_name: None,
+                data_sync: Default::default(),
+                queue_position: None,
+                saved_peers: Vec::new(),
+                added_from: None,
+                storage_mode: Default::default(),
+                auto_stop: Default::default(),
+                low_disk_paused_at: None,
+                linked_files: Vec::new(),
+                disabled_trackers: Vec::new(),
+                file_completed_at: Default::default(),
+            })
+            .collect();
+
+        db.save_torrents(&sessions).unwrap();
+
+        let all = db.load_all_torrents().unwrap();
+        assert_eq!(all.len(), 2);
+        assert!(all.iter().all(|s| s.category.as_deref() == Some("linux")));
+    }
+
+    #[test]
+    fn test_load_torrent_ignores_hex_case() {
+        let temp_dir = TempDir::new().unwrap();
+        let db = Database::open(temp_dir.path().join("test.db")).unwrap();
+
+        let session = TorrentSession {
+            id: "abcdefabcdefabcdefabcdefabcdefabcdefabcd".to_string(),
+            metainfo: create_test_metainfo(),
+            bitfield: vec![],
+            num_pieces: 2,
+            downloaded: 0,
+            uploaded: 0,
+            state: "paused".to_string(),
+            download_dir: "/tmp".to_string(),
+            added_at: 1234567890,
+            last_activity: 1234567890,
+            source: DownloadSource::P2P,
+            completed_at: None,
+            volume_id: None,
+            category: None,
+            traffic: Default::default(),
+            swarm: None,
+            root_name: None,
+            data_sync: Default::default(),
+            queue_position: None,
+            saved_peers: Vec::new(),
+            added_from: None,
+            storage_mode: Default::default(),
+            auto_stop: Default::default(),
+            low_disk_paused_at: None,
+            linked_files: Vec::new(),
+            disabled_trackers: Vec::new(),
+            file_completed_at: Default::default(),
+        };
+        db.save_torrent(&session).unwrap();
+
+        let loaded = db
+            .load_torrent("ABCDEFABCDEFABCDEFABCDEFABCDEFABCDEFABCD")
+            .unwrap()
+            .unwrap();
+        assert_eq!(loaded.id, session.id);
+        assert!(db.load_torrent("not-a-hash").is_err());
+    }
+
     #[test]
     fn test_delete_torrent() {
         let temp_dir = TempDir::new().unwrap();
         let db = Database::open(temp_dir.path().join("test.db")).unwrap();
 
         let session = TorrentSession {
-            id: "delete_me".to_string(),
+            id: "4444444444444444444444444444444444444444".to_string(),
             metainfo: create_test_metainfo(),
             bitfield: vec![],
             num_pieces: 2,
@@ -708,13 +2070,28 @@ mod tests {
             last_activity: 1234567890,
             source: DownloadSource::P2P,
             completed_at: None,
+            volume_id: None,
+            category: None,
+            traffic: Default::default(),
+            swarm: None,
+            root_name: None,
+            data_sync: Default::default(),
+            queue_position: None,
+            saved_peers: Vec::new(),
+            added_from: None,
+            storage_mode: Default::default(),
+            auto_stop: Default::default(),
+            low_disk_paused_at: None,
+            linked_files: Vec::new(),
+            disabled_trackers: Vec::new(),
+            file_completed_at: Default::default(),
         };
 
         db.save_torrent(&session).unwrap();
-        assert!(db.load_torrent("delete_me").unwrap().is_some());
+        assert!(db.load_torrent("4444444444444444444444444444444444444444").unwrap().is_some());
 
-        db.delete_torrent("delete_me").unwrap();
-        assert!(db.load_torrent("delete_me").unwrap().is_none());
+        db.delete_torrent("4444444444444444444444444444444444444444").unwrap();
+        assert!(db.load_torrent("4444444444444444444444444444444444444444").unwrap().is_none());
     }
 
     #[test]
@@ -723,7 +2100,7 @@ mod tests {
         let db = Database::open(temp_dir.path().join("test.db")).unwrap();
 
         let session = TorrentSession {
-            id: "progress_test".to_string(),
+            id: "5555555555555555555555555555555555555555".to_string(),
             metainfo: create_test_metainfo(),
             bitfield: vec![0b00000000],
             num_pieces: 2,
@@ -735,17 +2112,314 @@ mod tests {
             last_activity: 1234567890,
             source: DownloadSource::P2P,
             completed_at: None,
+            volume_id: None,
+            category: None,
+            traffic: Default::default(),
+            swarm: None,
+            root_name: None,
+            data_sync: Default::default(),
+            queue_position: None,
+            saved_peers: Vec::new(),
+            added_from: None,
+            storage_mode: Default::default(),
+            auto_stop: Default::default(),
+            low_disk_paused_at: None,
+            linked_files: Vec::new(),
+            disabled_trackers: Vec::new(),
+            file_completed_at: Default::default(),
         };
 
         db.save_torrent(&session).unwrap();
 
-        db.update_progress("progress_test", vec![0b11000000], 16384, 1024)
-            .unwrap();
+        assert!(db
+            .update_progress("5555555555555555555555555555555555555555", vec![0b11000000], 16384, 1024)
+            .unwrap());
+        assert!(db.update_state("5555555555555555555555555555555555555555", "seeding".to_string()).unwrap());
 
-        let updated = db.load_torrent("progress_test").unwrap().unwrap();
+        let updated = db.load_torrent("5555555555555555555555555555555555555555").unwrap().unwrap();
         assert_eq!(updated.bitfield, vec![0b11000000]);
         assert_eq!(updated.downloaded, 16384);
         assert_eq!(updated.uploaded, 1024);
+        assert_eq!(updated.state, "seeding");
+        assert_eq!(db.load_all_torrents().unwrap()[0].downloaded, 16384);
+
+        // Unknown torrents are reported, not silently accepted
+        assert!(!db.update_progress("6666666666666666666666666666666666666666", vec![], 0, 0).unwrap());
+        assert!(!db.update_state("6666666666666666666666666666666666666666", "paused".to_string()).unwrap());
+
+        // A full save supersedes the progress record
+        db.save_torrent(&session).unwrap();
+        let reset = db.load_torrent("5555555555555555555555555555555555555555").unwrap().unwrap();
+        assert_eq!(reset.downloaded, 0);
+        assert_eq!(reset.state, "downloading");
+    }
+
+    #[test]
+    fn test_update_progress_leaves_metainfo_alone() {
+        let temp_dir = TempDir::new().unwrap();
+        let db = Database::open(temp_dir.path().join("test.db")).unwrap();
+        let id = "7777777777777777777777777777777777777777";
+
+        let mut metainfo = create_test_metainfo();
+        metainfo.info.pieces = vec![0xAB; 20 * 10_000];
+        metainfo.info.piece_count = 10_000;
+        let session = TorrentSession {
+            id: id.to_string(),
+            metainfo,
+            bitfield: vec![0; 1250],
+            num_pieces: 10_000,
+            downloaded: 0,
+            uploaded: 0,
+            state: "downloading".to_string(),
+            download_dir: "/tmp".to_string(),
+            added_at: 1234567890,
+            last_activity: 1234567890,
+            source: DownloadSource::P2P,
+            completed_at: None,
+            volume_id: None,
+            category: None,
+            traffic: Default::default(),
+            swarm: None,
+            root_name: None,
+            data_sync: Default::default(),
+            queue_position: None,
+            saved_peers: Vec::new(),
+            added_from: None,
+            storage_mode: Default::default(),
+            auto_stop: Default::default(),
+            low_disk_paused_at: None,
+            linked_files: Vec::new(),
+            disabled_trackers: Vec::new(),
+            file_completed_at: Default::default(),
+        };
+        db.save_torrent(&session).unwrap();
+
+        let torrents = db.db().open_tree(KEY_TORRENTS).unwrap();
+        let stored = torrents.get(id).unwrap().unwrap();
+        SESSION_DECODES.with(|n| n.set(0));
+
+        for i in 1..=100u64 {
+            assert!(db.update_progress(id, vec![0xFF; 1250], i * 16384, i).unwrap());
+        }
+
+        assert_eq!(SESSION_DECODES.with(|n| n.get()), 0);
+        assert_eq!(torrents.get(id).unwrap().unwrap(), stored);
+
+        let loaded = db.load_torrent(id).unwrap().unwrap();
+        assert_eq!(loaded.downloaded, 100 * 16384);
+        assert_eq!(loaded.uploaded, 100);
+        assert_eq!(loaded.metainfo.info.pieces, session.metainfo.info.pieces);
+    }
+
+    #[test]
+    fn test_progress_saves_share_flushes() {
+        let temp_dir = TempDir::new().unwrap();
+        let db = Database::open(temp_dir.path().join("test.db")).unwrap();
+        let id = "7878787878787878787878787878787878787878";
+        let mut session = TorrentSession {
+            id: id.to_string(),
+            metainfo: create_test_metainfo(),
+            bitfield: vec![0],
+            num_pieces: 2,
+            downloaded: 0,
+            uploaded: 0,
+            state: "downloading".to_string(),
+            download_dir: "/tmp".to_string(),
+            added_at: 1234567890,
+            last_activity: 1234567890,
+            source: DownloadSource::P2P,
+            completed_at: None,
+            volume_id: None,
+            category: None,
+            traffic: Default::default(),
+            swarm: None,
+            root_name: None,
+            data_sync: Default::default(),
+            queue_position: None,
+            saved_peers: Vec::new(),
+            added_from: None,
+            storage_mode: Default::default(),
+            auto_stop: Default::default(),
+            low_disk_paused_at: None,
+            linked_files: Vec::new(),
+            disabled_trackers: Vec::new(),
+            file_completed_at: Default::default(),
+        };
+        let interval = Duration::from_secs(DEFAULT_FLUSH_INTERVAL_SECS);
+        FLUSHES.with(|n| n.set(0));
+
+        // The flusher keeps running throughout
+        for i in 1..=100u64 {
+            session.downloaded = i * 16384;
+            db.save_torrent(&session).unwrap();
+            db.flush_if_due(interval).unwrap();
+        }
+        assert!(FLUSHES.with(|n| n.get()) <= 1);
+        assert_eq!(db.load_torrent(id).unwrap().unwrap().downloaded, 100 * 16384);
+
+        // Due once the interval has passed, then clean
+        assert!(db.flush_if_due(Duration::ZERO).unwrap());
+        assert!(!db.flush_if_due(Duration::ZERO).unwrap());
+
+        let flushes = FLUSHES.with(|n| n.get());
+        db.save_master_password(&MasterPasswordData { password_hash: vec![1; 32], salt: vec![2; 16] })
+            .unwrap();
+        assert_eq!(FLUSHES.with(|n| n.get()), flushes + 1);
+        // Nothing left over for the flusher
+        assert!(!db.flush_if_due(Duration::ZERO).unwrap());
+    }
+
+    #[test]
+    fn test_provenance_backup_and_old_sessions() {
+        let temp_dir = TempDir::new().unwrap();
+        let db = Database::open(temp_dir.path().join("test.db")).unwrap();
+        let id = "8888888888888888888888888888888888888888";
+
+        let session = TorrentSession {
+            id: id.to_string(),
+            metainfo: create_test_metainfo(),
+            bitfield: vec![],
+            num_pieces: 2,
+            downloaded: 0,
+            uploaded: 0,
+            state: "paused".to_string(),
+            download_dir: "/tmp".to_string(),
+            added_at: 1234567890,
+            last_activity: 1234567890,
+            source: DownloadSource::P2P,
+            completed_at: None,
+            volume_id: None,
+            category: None,
+            traffic: Default::default(),
+            swarm: None,
+            root_name: None,
+            data_sync: Default::default(),
+            queue_position: None,
+            saved_peers: Vec::new(),
+            added_from: Some(crate::provenance::AddedFrom::Url { url: "https://example.com/a.torrent".to_string() }),
+            storage_mode: Default::default(),
+            auto_stop: Default::default(),
+            low_disk_paused_at: None,
+            linked_files: Vec::new(),
+            disabled_trackers: Vec::new(),
+            file_completed_at: Default::default(),
+        };
+        db.save_torrent(&session).unwrap();
+        db.save_torrent_file(id, b"d4:infod4:name1:aee").unwrap();
+
+        // Both come back from a backup
+        let backup = db.dump_all().unwrap();
+        let restored = Database::open(temp_dir.path().join("restored.db")).unwrap();
+        restored.restore(&backup).unwrap();
+        assert_eq!(restored.load_torrent(id).unwrap().unwrap().added_from, session.added_from);
+        assert_eq!(restored.load_torrent_file(id).unwrap().unwrap(), b"d4:infod4:name1:aee");
+
+        // Sessions and backups from before provenance still load
+        let mut old = serde_json::to_value(&session).unwrap();
+        old.as_object_mut().unwrap().remove("added_from");
+        assert_eq!(decode_session(&serde_json::to_vec(&old).unwrap()).unwrap().added_from, None);
+        let mut old_backup: serde_json::Value = serde_json::from_str(&backup).unwrap();
+        old_backup.as_object_mut().unwrap().remove("torrent_files");
+        restored.restore(&old_backup.to_string()).unwrap();
+
+        // Removing the torrent drops its file too
+        db.delete_torrent(id).unwrap();
+        assert_eq!(db.load_torrent_file(id).unwrap(), None);
+    }
+
+    #[test]
+    fn test_restore_replacing_checks_before_swapping() {
+        let temp_dir = TempDir::new().unwrap();
+        let session = |id: &str, downloaded: u64| TorrentSession {
+            id: id.to_string(),
+            metainfo: create_test_metainfo(),
+            bitfield: vec![],
+            num_pieces: 2,
+            downloaded,
+            uploaded: 0,
+            state: "paused".to_string(),
+            download_dir: "/tmp".to_string(),
+            added_at: 1234567890,
+            last_activity: 1234567890,
+            source: DownloadSource::P2P,
+            completed_at: None,
+            volume_id: None,
+            category: None,
+            traffic: Default::default(),
+            swarm: None,
+            root_name: None,
+            data_sync: Default::default(),
+            queue_position: None,
+            saved_peers: Vec::new(),
+            added_from: None,
+            storage_mode: Default::default(),
+            auto_stop: Default::default(),
+            low_disk_paused_at: None,
+            linked_files: Vec::new(),
+            disabled_trackers: Vec::new(),
+            file_completed_at: Default::default(),
+        };
+        let only_live = "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa";
+        let shared = "bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb";
+        let only_backup = "cccccccccccccccccccccccccccccccccccccccc";
+
+        let live = Database::open(temp_dir.path().join("live.db")).unwrap();
+        live.save_torrent(&session(only_live, 1)).unwrap();
+        live.save_torrent(&session(shared, 1)).unwrap();
+        live.save_torrent_file(only_live, b"live").unwrap();
+
+        let source = Database::open(temp_dir.path().join("source.db")).unwrap();
+        source.save_torrent(&session(shared, 2)).unwrap();
+        source.save_torrent(&session(only_backup, 2)).unwrap();
+        source.save_torrent_file(only_backup, b"backup").unwrap();
+        source.save_settings(&AppSettings { listen_port: 7000, ..AppSettings::default() }).unwrap();
+        let backup = source.dump_all().unwrap();
+
+        // Backups that don't check out leave the live database as it was
+        let mut bad_id: serde_json::Value = serde_json::from_str(&backup).unwrap();
+        bad_id["torrents"][1]["id"] = "not-a-hash".into();
+        let mut newer: serde_json::Value = serde_json::from_str(&backup).unwrap();
+        newer["version"] = (BACKUP_VERSION + 1).into();
+        for bad in [bad_id.to_string(), newer.to_string(), backup[..backup.len() / 2].to_string()] {
+            assert!(live.restore_replacing(&bad).is_err());
+            let mut ids: Vec<String> = live.load_all_torrents().unwrap().into_iter().map(|s| s.id).collect();
+            ids.sort();
+            assert_eq!(ids, vec![only_live, shared]);
+            assert_eq!(live.load_torrent_file(only_live).unwrap().unwrap(), b"live");
+        }
+
+        // A good one replaces everything, nothing of the old contents stays
+        let summary = live.restore_replacing(&backup).unwrap();
+        assert_eq!(summary, RestoreSummary { torrents: 2, torrent_files: 1 });
+        assert!(live.load_torrent(only_live).unwrap().is_none());
+        assert_eq!(live.load_torrent_file(only_live).unwrap(), None);
+        assert_eq!(live.load_torrent(shared).unwrap().unwrap().downloaded, 2);
+        assert_eq!(live.load_torrent_file(only_backup).unwrap().unwrap(), b"backup");
+        assert_eq!(live.load_settings().unwrap().listen_port, 7000);
+
+        // No scratch copies are left behind
+        let mut entries: Vec<String> = std::fs::read_dir(temp_dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        entries.sort();
+        assert_eq!(entries, vec!["live.db", "source.db"]);
+    }
+
+    #[test]
+    fn test_benchmark_history_is_bounded() {
+        let temp_dir = TempDir::new().unwrap();
+        let db = Database::open(temp_dir.path().join("test.db")).unwrap();
+
+        for started_at in 0..crate::benchmark::HISTORY_LEN as i64 + 5 {
+            let report = crate::benchmark::BenchmarkReport { started_at, disk: None, network: None, warnings: vec![] };
+            db.save_benchmark(&report).unwrap();
+        }
+
+        let history = db.load_benchmarks().unwrap();
+        assert_eq!(history.len(), crate::benchmark::HISTORY_LEN);
+        assert_eq!(history.first().unwrap().started_at, 5);
+        assert_eq!(history.last().unwrap().started_at, crate::benchmark::HISTORY_LEN as i64 + 4);
     }
 
     #[test]
@@ -764,6 +2438,7 @@ mod tests {
             enable_debrid: true,
             debrid_preference: vec![DebridProviderType::RealDebrid],
             smart_mode_enabled: false,
+            ..AppSettings::default()
         };
 
         db.save_settings(&settings).unwrap();
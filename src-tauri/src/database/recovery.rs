@@ -0,0 +1,399 @@
+//! Opening the database after a crash, a downgrade or a second launch
+//!
+//! A failure to open is sorted by cause. If another instance holds the
+//! database, startup stops with a message saying so. A damaged or
+//! incompatible database no longer keeps the app from starting:
+//! 1. sled runs its own recovery on open, rolling back a torn last write
+//! 2. a torn `conf` (sled's small settings file) is rebuilt, since ours are
+//!    sled's defaults
+//! 3. failing that, the database directory is moved aside to
+//!    `<db>.damaged-<UTC time>` and a fresh one takes its place. Startup
+//!    reports that (`database-recovered`) so the UI can offer the newest
+//!    automatic backup, and `salvage` exports what can still be read from the
+//!    damaged copy in the backup format.
+//!
+//! Other failures (permissions, a full disk) fail startup as before, since
+//! moving the database aside wouldn't help.
+
+use super::Database;
+use crate::backup;
+use crate::error::{Error, Result};
+use serde::Serialize;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+/// File in the config dir locked by the running instance
+pub const INSTANCE_LOCK_FILE: &str = "seedcore.lock";
+
+/// sled's settings file inside the database directory
+const SLED_CONFIG: &str = "conf";
+
+const SALVAGE_PREFIX: &str = "seedcore-salvage-";
+
+/// Why the database couldn't be opened
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpenFailure {
+    /// Another process has it open
+    Locked,
+    /// Its files can't be read back: corrupt, or from another sled version
+    Damaged,
+    /// Anything else, e.g. no permission or no space
+    Unavailable,
+}
+
+/// Sort a sled open error by cause
+pub fn classify(error: &sled::Error) -> OpenFailure {
+    use std::io::ErrorKind;
+    match error {
+        // sled reports its lock as a plain I/O error
+        sled::Error::Io(e) if e.to_string().contains("could not acquire lock") => OpenFailure::Locked,
+        sled::Error::Io(e) if matches!(e.kind(), ErrorKind::InvalidData | ErrorKind::UnexpectedEof) => {
+            OpenFailure::Damaged
+        }
+        sled::Error::Corruption { .. } | sled::Error::Unsupported(_) | sled::Error::ReportableBug(_) => {
+            OpenFailure::Damaged
+        }
+        _ => OpenFailure::Unavailable,
+    }
+}
+
+/// Held by the running instance for its whole life
+static INSTANCE_LOCK: OnceLock<File> = OnceLock::new();
+
+/// Take the single-instance lock in `config_dir`, failing if another instance
+/// holds it. The OS drops the lock with the process, so a crash can't leave
+/// it stale. If the lock file can't be used at all, startup goes on without
+/// it; sled's own lock still keeps two instances off the same database.
+pub fn lock_instance(config_dir: &Path) -> Result<()> {
+    match try_lock(&config_dir.join(INSTANCE_LOCK_FILE)) {
+        Ok(Some(file)) => {
+            let _ = INSTANCE_LOCK.set(file);
+            Ok(())
+        }
+        Ok(None) => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Lock `path`; Ok(None) if locking isn't possible there
+fn try_lock(path: &Path) -> Result<Option<File>> {
+    use fs2::FileExt;
+    let file = match std::fs::OpenOptions::new().create(true).truncate(false).write(true).open(path) {
+        Ok(file) => file,
+        Err(e) => {
+            tracing::warn!("Cannot create instance lock {:?}, skipping the single-instance check: {}", path, e);
+            return Ok(None);
+        }
+    };
+    match file.try_lock_exclusive() {
+        Ok(()) => Ok(Some(file)),
+        Err(e) if e.kind() == fs2::lock_contended_error().kind() => Err(Error::DatabaseError(
+            "Another SeedCore instance is already running; close it first".to_string(),
+        )),
+        Err(e) => {
+            tracing::warn!("Cannot lock {:?}, skipping the single-instance check: {}", path, e);
+            Ok(None)
+        }
+    }
+}
+
+/// What startup did about a damaged database, for the user
+#[derive(Debug, Clone, Serialize)]
+pub struct DatabaseRecovery {
+    /// Why the database couldn't be opened
+    pub error: String,
+    /// Where the damaged database was moved
+    pub moved_to: String,
+    /// Newest automatic backup in the default backup directory, to offer
+    /// restoring (`restore_from_backup_file`)
+    pub latest_backup: Option<String>,
+}
+
+/// Open the database at `path`, repairing or replacing it if it is damaged
+/// (see the module docs). The recovery is Some when a fresh database replaced
+/// a damaged one.
+pub fn open_or_recover(path: &Path, now: i64) -> Result<(Database, Option<DatabaseRecovery>)> {
+    let error = match sled::open(path) {
+        Ok(db) => return Ok((Database::with_db(db, path), None)),
+        Err(e) => e,
+    };
+    match classify(&error) {
+        OpenFailure::Locked => {
+            return Err(Error::DatabaseError(format!(
+                "The database at {} is in use by another SeedCore instance",
+                path.display()
+            )));
+        }
+        OpenFailure::Unavailable => {
+            return Err(Error::IoError(format!("Failed to open database: {}", error)));
+        }
+        OpenFailure::Damaged => {}
+    }
+    tracing::error!("Database at {:?} is damaged: {}", path, error);
+
+    if matches!(error, sled::Error::Corruption { .. }) {
+        if let Some(db) = rebuild_config(path) {
+            tracing::warn!("Database at {:?} opened after rebuilding its settings file", path);
+            return Ok((Database::with_db(db, path), None));
+        }
+    }
+
+    let moved_to = move_aside(path, now)?;
+    tracing::error!("Moved the damaged database to {:?}, starting with an empty one", moved_to);
+    let database = Database::open(path)?;
+    let latest_backup = backup::list_backups(&backup::default_dir(&database))
+        .pop()
+        .map(|(_, backup)| backup.display().to_string());
+    let recovery = DatabaseRecovery {
+        error: error.to_string(),
+        moved_to: moved_to.display().to_string(),
+        latest_backup,
+    };
+    Ok((database, Some(recovery)))
+}
+
+/// Open `path` with a fresh sled settings file; the torn one is put back if
+/// that doesn't help
+fn rebuild_config(path: &Path) -> Option<sled::Db> {
+    let config = path.join(SLED_CONFIG);
+    let torn = path.join(format!("{}.torn", SLED_CONFIG));
+    std::fs::rename(&config, &torn).ok()?;
+    match sled::open(path) {
+        Ok(db) => Some(db),
+        Err(e) => {
+            tracing::debug!("Rebuilding the settings file of {:?} didn't help: {}", path, e);
+            let _ = std::fs::rename(&torn, &config);
+            None
+        }
+    }
+}
+
+/// Move a database directory to `<name>.damaged-<UTC time>` next to it
+/// (with a counter if that's taken); returns the new location
+pub fn move_aside(path: &Path, now: i64) -> Result<PathBuf> {
+    let name = path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+    let time = chrono::DateTime::from_timestamp(now, 0).unwrap_or_default();
+    let base = format!("{}.damaged-{}", name, time.format(backup::NAME_TIME_FORMAT));
+    let mut target = path.with_file_name(&base);
+    let mut n = 2;
+    while target.exists() {
+        target = path.with_file_name(format!("{}-{}", base, n));
+        n += 1;
+    }
+    std::fs::rename(path, &target)
+        .map_err(|e| Error::IoError(format!("Failed to move the damaged database {:?} aside: {}", path, e)))?;
+    Ok(target)
+}
+
+/// What `salvage` got out of a damaged database
+#[derive(Debug, Clone, Serialize)]
+pub struct SalvageReport {
+    /// The exported file, in the backup format
+    pub path: String,
+    pub torrents: usize,
+    /// Entries copied over all trees
+    pub entries: usize,
+    /// Trees that stopped reading part way (or couldn't be opened)
+    pub damaged_trees: Vec<String>,
+}
+
+/// Export whatever can still be read from the database at `damaged` to
+/// `seedcore-salvage-<UTC time>.json` in `out_dir`. The file can be restored
+/// like a backup. Opening it runs sled's recovery, which may modify it, so
+/// this is meant for a copy that was moved aside.
+pub fn salvage(damaged: &Path, out_dir: &Path, now: i64) -> Result<SalvageReport> {
+    let source = match sled::open(damaged) {
+        Ok(db) => db,
+        Err(e) if matches!(e, sled::Error::Corruption { .. }) => rebuild_config(damaged)
+            .ok_or_else(|| Error::DatabaseError(format!("Nothing can be read from {}: {}", damaged.display(), e)))?,
+        Err(e) => {
+            return Err(Error::DatabaseError(format!("Nothing can be read from {}: {}", damaged.display(), e)));
+        }
+    };
+
+    std::fs::create_dir_all(out_dir)
+        .map_err(|e| Error::IoError(format!("Failed to create {:?}: {}", out_dir, e)))?;
+    let scratch_path = out_dir.join(".salvage.db");
+    let _ = std::fs::remove_dir_all(&scratch_path);
+    let result = (|| {
+        let scratch = Database::open(&scratch_path)?;
+        let (entries, damaged_trees) = copy_readable(&source, &scratch.db());
+        let torrents = scratch.load_all_torrents()?.len();
+        let json = scratch.dump_all()?;
+
+        let time = chrono::DateTime::from_timestamp(now, 0).unwrap_or_default();
+        let path = out_dir.join(format!("{}{}.json", SALVAGE_PREFIX, time.format(backup::NAME_TIME_FORMAT)));
+        std::fs::write(&path, json).map_err(|e| Error::IoError(format!("Failed to write {:?}: {}", path, e)))?;
+        Ok(SalvageReport { path: path.display().to_string(), torrents, entries, damaged_trees })
+    })();
+    if let Err(e) = std::fs::remove_dir_all(&scratch_path) {
+        tracing::warn!("Failed to remove scratch database {:?}: {}", scratch_path, e);
+    }
+    result
+}
+
+/// `--repair`: export what can be read from the database in use to the
+/// default backup directory, without starting the app
+pub fn repair(config_dir: &Path) -> Result<SalvageReport> {
+    lock_instance(config_dir)?;
+    let data_dir = crate::data_dir::resolve(config_dir);
+    let now = chrono::Utc::now().timestamp();
+    salvage(&data_dir.join(crate::data_dir::DATABASE_DIR), &data_dir.join(backup::DIR_NAME), now)
+}
+
+/// Copy every entry of `source` that reads back into `target`; returns the
+/// number copied and the trees that failed part way
+fn copy_readable(source: &sled::Db, target: &sled::Db) -> (usize, Vec<String>) {
+    let mut copied = 0;
+    let mut damaged_trees = Vec::new();
+    for name in source.tree_names() {
+        let label = String::from_utf8_lossy(&name).into_owned();
+        let (Ok(from), Ok(to)) = (source.open_tree(&name), target.open_tree(&name)) else {
+            damaged_trees.push(label);
+            continue;
+        };
+        for entry in from.iter() {
+            match entry.and_then(|(key, value)| to.insert(key, value)) {
+                Ok(_) => copied += 1,
+                Err(e) => {
+                    tracing::warn!("Stopped reading tree {} of the damaged database: {}", label, e);
+                    damaged_trees.push(label);
+                    break;
+                }
+            }
+        }
+    }
+    (copied, damaged_trees)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    const NOW: i64 = 1_700_000_000;
+
+    fn database_with_torrent(path: &Path) {
+        let db = Database::open(path).unwrap();
+        let mut settings = db.load_settings().unwrap();
+        settings.max_connections = 123;
+        db.save_settings(&settings).unwrap();
+        db.save_torrent_file("0123456789abcdef0123456789abcdef01234567", b"d4:infodee").unwrap();
+        db.flush_now().unwrap();
+    }
+
+    /// A database whose settings file says it was made with other settings,
+    /// as after a downgrade
+    fn incompatible_database(path: &Path) {
+        let db = sled::Config::new().path(path).segment_size(1 << 16).open().unwrap();
+        db.insert("key", "value").unwrap();
+        db.flush().unwrap();
+    }
+
+    #[test]
+    fn test_classifies_open_failures() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("data.db");
+
+        // Held file lock
+        let held = sled::open(&path).unwrap();
+        assert_eq!(classify(&sled::open(&path).unwrap_err()), OpenFailure::Locked);
+        let err = open_or_recover(&path, NOW).err().unwrap().to_string();
+        assert!(err.contains("another SeedCore instance"), "{}", err);
+        drop(held);
+
+        // Truncated settings file
+        let config = std::fs::read(path.join(SLED_CONFIG)).unwrap();
+        std::fs::write(path.join(SLED_CONFIG), &config[..config.len() / 2]).unwrap();
+        assert_eq!(classify(&sled::open(&path).unwrap_err()), OpenFailure::Damaged);
+
+        let other = temp_dir.path().join("other.db");
+        incompatible_database(&other);
+        assert_eq!(classify(&sled::open(&other).unwrap_err()), OpenFailure::Damaged);
+
+        let denied = sled::Error::Io(std::io::Error::new(std::io::ErrorKind::PermissionDenied, "denied"));
+        assert_eq!(classify(&denied), OpenFailure::Unavailable);
+    }
+
+    #[test]
+    fn test_one_instance_at_a_time() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join(INSTANCE_LOCK_FILE);
+        let first = try_lock(&path).unwrap().unwrap();
+        let err = try_lock(&path).unwrap_err().to_string();
+        assert!(err.contains("already running"), "{}", err);
+        drop(first);
+        assert!(try_lock(&path).unwrap().is_some());
+
+        // A place the lock can't be made doesn't stop startup
+        assert!(try_lock(&temp_dir.path().join("missing").join(INSTANCE_LOCK_FILE)).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_torn_settings_file_is_rebuilt() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("data.db");
+        database_with_torrent(&path);
+        let config = std::fs::read(path.join(SLED_CONFIG)).unwrap();
+        std::fs::write(path.join(SLED_CONFIG), &config[..config.len() / 2]).unwrap();
+
+        let (database, recovery) = open_or_recover(&path, NOW).unwrap();
+        assert!(recovery.is_none());
+        assert_eq!(database.load_settings().unwrap().max_connections, 123);
+    }
+
+    #[test]
+    fn test_damaged_database_is_moved_aside() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("data.db");
+        incompatible_database(&path);
+        let backups = temp_dir.path().join("backups");
+        std::fs::create_dir_all(&backups).unwrap();
+        std::fs::write(backups.join("seedcore-backup-20231101-000000.json"), "{}").unwrap();
+
+        let (database, recovery) = open_or_recover(&path, NOW).unwrap();
+        let recovery = recovery.unwrap();
+        let moved_to = temp_dir.path().join("data.db.damaged-20231114-221320");
+        assert_eq!(recovery.moved_to, moved_to.display().to_string());
+        assert!(moved_to.join(SLED_CONFIG).is_file());
+        assert!(recovery.latest_backup.unwrap().ends_with("seedcore-backup-20231101-000000.json"));
+
+        // The fresh database works and is found again on the next start
+        database.save_settings(&database.load_settings().unwrap()).unwrap();
+        drop(database);
+        assert!(open_or_recover(&path, NOW).unwrap().1.is_none());
+
+        // A second failure in the same second doesn't overwrite the first
+        std::fs::create_dir_all(&path).unwrap();
+        assert_eq!(move_aside(&path, NOW).unwrap(), temp_dir.path().join("data.db.damaged-20231114-221320-2"));
+    }
+
+    #[test]
+    fn test_salvage_exports_a_restorable_backup() {
+        let temp_dir = TempDir::new().unwrap();
+        let damaged = temp_dir.path().join("data.db.damaged");
+        database_with_torrent(&damaged);
+        let config = std::fs::read(damaged.join(SLED_CONFIG)).unwrap();
+        std::fs::write(damaged.join(SLED_CONFIG), &config[..config.len() / 2]).unwrap();
+
+        let out_dir = temp_dir.path().join("backups");
+        let report = salvage(&damaged, &out_dir, NOW).unwrap();
+        assert!(report.path.ends_with("seedcore-salvage-20231114-221320.json"));
+        assert!(report.entries >= 2);
+        assert!(report.damaged_trees.is_empty());
+        assert!(!out_dir.join(".salvage.db").exists());
+
+        let restored = Database::open(temp_dir.path().join("restored.db")).unwrap();
+        let summary = restored.restore_replacing(&std::fs::read_to_string(&report.path).unwrap()).unwrap();
+        assert_eq!(summary.torrent_files, 1);
+        assert_eq!(restored.load_settings().unwrap().max_connections, 123);
+
+        // Salvaged files aren't taken for backups by the rotation
+        assert!(backup::list_backups(&out_dir).is_empty());
+
+        let incompatible = temp_dir.path().join("incompatible.db");
+        incompatible_database(&incompatible);
+        assert!(salvage(&incompatible, &out_dir, NOW).is_err());
+    }
+}
@@ -0,0 +1,612 @@
+//! Streaming backup format, written by `export_backup`
+//!
+//! The JSON backup (`dump_all`) holds the whole collection in memory at once,
+//! which for tens of thousands of sessions (each with its metainfo) is far
+//! too much. This format is a sequence of length-prefixed records instead:
+//! a session is read, serialized, written and dropped before the next one,
+//! so memory use stays flat however large the collection.
+//!
+//! Layout, integers little-endian:
+//!
+//! ```text
+//! "SCBKSTRM"                       magic
+//! kind: u8, length: u32, payload   record, repeated
+//! END, 20, SHA-1                   checksum of every byte before it
+//! ```
+//!
+//! The first record is the header (version, time and counts), then come the
+//! settings, the sessions, the stashed .torrent files and the availability
+//! histories. Payloads are JSON. Importing reads the file twice: once to
+//! check the checksum, so a damaged file is refused before anything is
+//! written, and once to import. A record that doesn't decode is skipped with
+//! a warning rather than failing the whole import.
+
+use super::{decode_session, merge_progress, torrent_key, AppSettings, Database, TorrentSession, BASE64, KEY_TORRENTS};
+use crate::availability::AvailabilitySample;
+use crate::error::{Error, Result};
+use base64::Engine as _;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+use std::io::{Read, Seek, SeekFrom, Write};
+use tokio_util::sync::CancellationToken;
+
+pub const MAGIC: &[u8; 8] = b"SCBKSTRM";
+
+/// Newest stream format written and understood
+pub const STREAM_VERSION: u32 = 1;
+
+/// Largest record accepted; guards against allocating a garbage length
+const MAX_RECORD_LEN: u32 = 256 << 20;
+
+/// The output is flushed and progress reported every this many sessions
+const FLUSH_EVERY: usize = 32;
+
+/// Sessions imported per database batch
+const IMPORT_BATCH: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Header = 1,
+    Settings = 2,
+    Torrent = 3,
+    TorrentFile = 4,
+    Availability = 5,
+    End = 0xff,
+}
+
+impl Kind {
+    fn from_u8(kind: u8) -> Option<Self> {
+        Some(match kind {
+            1 => Self::Header,
+            2 => Self::Settings,
+            3 => Self::Torrent,
+            4 => Self::TorrentFile,
+            5 => Self::Availability,
+            0xff => Self::End,
+            _ => return None,
+        })
+    }
+}
+
+/// First record of a stream
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StreamHeader {
+    pub version: u32,
+    pub timestamp: i64,
+    pub torrents: usize,
+    pub torrent_files: usize,
+    pub availability: usize,
+}
+
+/// Payload of the `backup-export-progress` event: sessions written so far
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct StreamProgress {
+    pub done: usize,
+    pub total: usize,
+}
+
+/// What an export wrote or an import brought in
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct BackupSummary {
+    pub torrents: usize,
+    pub torrent_files: usize,
+    pub availability: usize,
+    /// Records that didn't decode and were left out (import only)
+    pub skipped: usize,
+}
+
+#[derive(Serialize, Deserialize)]
+struct TorrentFileRecord {
+    id: String,
+    /// The .torrent file, base64
+    data: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct AvailabilityRecord {
+    id: String,
+    series: Vec<AvailabilitySample>,
+}
+
+fn cancelled() -> Error {
+    Error::Other("Backup export cancelled".to_string())
+}
+
+fn write_error(e: std::io::Error) -> Error {
+    Error::IoError(format!("Failed to write backup: {}", e))
+}
+
+fn read_error(e: std::io::Error) -> Error {
+    if e.kind() == std::io::ErrorKind::UnexpectedEof {
+        Error::ValidationError("Backup is truncated".to_string())
+    } else {
+        Error::IoError(format!("Failed to read backup: {}", e))
+    }
+}
+
+/// Whether `input` starts like a stream backup; leaves it at the start
+pub fn is_stream<R: Read + Seek>(input: &mut R) -> Result<bool> {
+    let mut magic = [0u8; MAGIC.len()];
+    let read = input.read(&mut magic).map_err(read_error)?;
+    input.seek(SeekFrom::Start(0)).map_err(read_error)?;
+    Ok(read == magic.len() && &magic == MAGIC)
+}
+
+/// Writes records, hashing everything on the way out
+struct RecordWriter<W: Write> {
+    out: W,
+    hasher: Sha1,
+}
+
+impl<W: Write> RecordWriter<W> {
+    fn new(mut out: W) -> Result<Self> {
+        out.write_all(MAGIC).map_err(write_error)?;
+        let mut hasher = Sha1::new();
+        hasher.update(MAGIC);
+        Ok(Self { out, hasher })
+    }
+
+    fn record(&mut self, kind: Kind, payload: &[u8]) -> Result<()> {
+        let len = u32::try_from(payload.len())
+            .ok()
+            .filter(|&len| len <= MAX_RECORD_LEN)
+            .ok_or_else(|| Error::DatabaseError(format!("Backup record of {} bytes is too large", payload.len())))?;
+        let mut frame = [0u8; 5];
+        frame[0] = kind as u8;
+        frame[1..].copy_from_slice(&len.to_le_bytes());
+        self.hasher.update(frame);
+        self.hasher.update(payload);
+        self.out.write_all(&frame).map_err(write_error)?;
+        self.out.write_all(payload).map_err(write_error)
+    }
+
+    fn json(&mut self, kind: Kind, value: &impl Serialize) -> Result<()> {
+        let payload = serde_json::to_vec(value)
+            .map_err(|e| Error::DatabaseError(format!("Failed to serialize backup record: {}", e)))?;
+        self.record(kind, &payload)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.out.flush().map_err(write_error)
+    }
+
+    /// Write the checksum record and flush
+    fn finish(mut self) -> Result<()> {
+        let checksum = self.hasher.clone().finalize();
+        self.record(Kind::End, &checksum)?;
+        self.flush()
+    }
+}
+
+/// Reads records back, checking the checksum at the end
+struct RecordReader<R: Read> {
+    input: R,
+    hasher: Sha1,
+}
+
+impl<R: Read> RecordReader<R> {
+    fn new(mut input: R) -> Result<Self> {
+        let mut magic = [0u8; MAGIC.len()];
+        input.read_exact(&mut magic).map_err(read_error)?;
+        if &magic != MAGIC {
+            return Err(Error::ValidationError("Not a SeedCore backup stream".to_string()));
+        }
+        let mut hasher = Sha1::new();
+        hasher.update(magic);
+        Ok(Self { input, hasher })
+    }
+
+    /// The next record as (kind, payload); None after a valid checksum
+    fn next(&mut self) -> Result<Option<(u8, Vec<u8>)>> {
+        let mut frame = [0u8; 5];
+        self.input.read_exact(&mut frame).map_err(read_error)?;
+        let len = u32::from_le_bytes([frame[1], frame[2], frame[3], frame[4]]);
+        if len > MAX_RECORD_LEN {
+            return Err(Error::ValidationError(format!("Backup record of {} bytes is too large", len)));
+        }
+        // Read through `take` so a garbage length on a short file doesn't
+        // allocate it up front
+        let mut payload = Vec::new();
+        (&mut self.input).take(u64::from(len)).read_to_end(&mut payload).map_err(read_error)?;
+        if payload.len() != len as usize {
+            return Err(read_error(std::io::ErrorKind::UnexpectedEof.into()));
+        }
+
+        if Kind::from_u8(frame[0]) == Some(Kind::End) {
+            if payload[..] != self.hasher.clone().finalize()[..] {
+                return Err(Error::ValidationError("Backup checksum mismatch, the file is damaged".to_string()));
+            }
+            return Ok(None);
+        }
+        self.hasher.update(frame);
+        self.hasher.update(&payload);
+        Ok(Some((frame[0], payload)))
+    }
+}
+
+/// Read the whole stream and check its checksum; returns the header
+pub fn verify<R: Read>(input: R) -> Result<StreamHeader> {
+    let mut reader = RecordReader::new(input)?;
+    let header = match reader.next()? {
+        Some((kind, payload)) if kind == Kind::Header as u8 => serde_json::from_slice::<StreamHeader>(&payload)
+            .map_err(|e| Error::ValidationError(format!("Unreadable backup header: {}", e)))?,
+        _ => return Err(Error::ValidationError("Backup has no header".to_string())),
+    };
+    if header.version > STREAM_VERSION {
+        return Err(Error::ValidationError(format!(
+            "Backup format {} is newer than this version understands ({})",
+            header.version, STREAM_VERSION
+        )));
+    }
+    while reader.next()?.is_some() {}
+    Ok(header)
+}
+
+/// Decode one record's payload; a failure is logged and counted
+fn decode<T: DeserializeOwned>(payload: &[u8], what: &str, summary: &mut BackupSummary) -> Option<T> {
+    match serde_json::from_slice(payload) {
+        Ok(value) => Some(value),
+        Err(e) => {
+            tracing::warn!("Skipping unreadable {} in backup: {}", what, e);
+            summary.skipped += 1;
+            None
+        }
+    }
+}
+
+impl Database {
+    /// Write the database to `out` in the stream format. `progress` hears
+    /// about the sessions written every few records. When `cancel` fires the
+    /// export stops with an error, leaving `out` incomplete.
+    pub fn export_stream<W: Write>(
+        &self,
+        out: W,
+        cancel: &CancellationToken,
+        mut progress: impl FnMut(StreamProgress),
+    ) -> Result<BackupSummary> {
+        let torrents = self
+            .db()
+            .open_tree(KEY_TORRENTS)
+            .map_err(|e| Error::IoError(format!("Failed to open torrents tree: {}", e)))?;
+        let progress_tree = self.progress_tree()?;
+        let torrent_files = self.torrent_files_tree()?;
+        let availability = self.availability_tree()?;
+
+        let header = StreamHeader {
+            version: STREAM_VERSION,
+            timestamp: chrono::Utc::now().timestamp(),
+            torrents: torrents.len(),
+            torrent_files: torrent_files.len(),
+            availability: availability.len(),
+        };
+        let mut writer = RecordWriter::new(out)?;
+        writer.json(Kind::Header, &header)?;
+        writer.json(Kind::Settings, &self.load_settings()?)?;
+
+        let mut summary = BackupSummary::default();
+        // Sessions added during the export can push past the header's count
+        let total = |done: usize| StreamProgress { done, total: header.torrents.max(done) };
+        progress(total(0));
+        for item in torrents.iter() {
+            if cancel.is_cancelled() {
+                return Err(cancelled());
+            }
+            let (_, data) = item.map_err(|e| Error::IoError(format!("Failed to iterate torrents: {}", e)))?;
+            let mut session = decode_session(&data)?;
+            merge_progress(&progress_tree, &mut session)?;
+            writer.json(Kind::Torrent, &session)?;
+            summary.torrents += 1;
+            if summary.torrents % FLUSH_EVERY == 0 {
+                writer.flush()?;
+                progress(total(summary.torrents));
+            }
+        }
+
+        for item in torrent_files.iter() {
+            if cancel.is_cancelled() {
+                return Err(cancelled());
+            }
+            let (key, data) = item.map_err(|e| Error::IoError(format!("Failed to iterate torrent files: {}", e)))?;
+            let record = TorrentFileRecord { id: String::from_utf8_lossy(&key).into_owned(), data: BASE64.encode(data) };
+            writer.json(Kind::TorrentFile, &record)?;
+            summary.torrent_files += 1;
+        }
+
+        for item in availability.iter() {
+            if cancel.is_cancelled() {
+                return Err(cancelled());
+            }
+            let (key, data) =
+                item.map_err(|e| Error::IoError(format!("Failed to iterate availability history: {}", e)))?;
+            match serde_json::from_slice::<Vec<AvailabilitySample>>(&data) {
+                Ok(series) => {
+                    let record = AvailabilityRecord { id: String::from_utf8_lossy(&key).into_owned(), series };
+                    writer.json(Kind::Availability, &record)?;
+                    summary.availability += 1;
+                }
+                Err(e) => tracing::error!("Failed to deserialize availability history: {}", e),
+            }
+        }
+
+        writer.finish()?;
+        progress(total(summary.torrents));
+        Ok(summary)
+    }
+
+    /// Import a stream backup (upsert, like `restore`). The checksum is
+    /// checked first, so a damaged file changes nothing; after that, records
+    /// that don't decode are skipped and counted.
+    pub fn import_stream<R: Read + Seek>(&self, mut input: R) -> Result<BackupSummary> {
+        let header = verify(&mut input)?;
+        input.seek(SeekFrom::Start(0)).map_err(read_error)?;
+
+        let mut reader = RecordReader::new(input)?;
+        let mut summary = BackupSummary::default();
+        let mut batch: Vec<TorrentSession> = Vec::with_capacity(IMPORT_BATCH);
+        while let Some((kind, payload)) = reader.next()? {
+            match Kind::from_u8(kind) {
+                Some(Kind::Header) | Some(Kind::End) => {}
+                Some(Kind::Settings) => {
+                    if let Some(settings) = decode::<AppSettings>(&payload, "settings", &mut summary) {
+                        self.save_settings(&settings)?;
+                    }
+                }
+                Some(Kind::Torrent) => {
+                    let Some(session) = decode::<TorrentSession>(&payload, "torrent", &mut summary) else {
+                        continue;
+                    };
+                    if let Err(e) = torrent_key(&session.id) {
+                        tracing::warn!("Skipping torrent with a bad id in backup: {}", e);
+                        summary.skipped += 1;
+                        continue;
+                    }
+                    batch.push(session);
+                    if batch.len() == IMPORT_BATCH {
+                        self.save_torrents(&batch)?;
+                        summary.torrents += batch.len();
+                        batch.clear();
+                    }
+                }
+                Some(Kind::TorrentFile) => {
+                    let Some(record) = decode::<TorrentFileRecord>(&payload, "torrent file", &mut summary) else {
+                        continue;
+                    };
+                    match (BASE64.decode(&record.data), torrent_key(&record.id)) {
+                        (Ok(data), Ok(_)) => {
+                            self.save_torrent_file(&record.id, &data)?;
+                            summary.torrent_files += 1;
+                        }
+                        _ => {
+                            tracing::warn!("Skipping unreadable torrent file for {} in backup", record.id);
+                            summary.skipped += 1;
+                        }
+                    }
+                }
+                Some(Kind::Availability) => {
+                    let Some(record) = decode::<AvailabilityRecord>(&payload, "availability history", &mut summary)
+                    else {
+                        continue;
+                    };
+                    let Ok(key) = torrent_key(&record.id) else {
+                        tracing::warn!("Skipping availability history with a bad id {} in backup", record.id);
+                        summary.skipped += 1;
+                        continue;
+                    };
+                    let data = serde_json::to_vec(&record.series)
+                        .map_err(|e| Error::IoError(format!("Failed to serialize availability history: {}", e)))?;
+                    self.availability_tree()?
+                        .insert(key.as_bytes(), data)
+                        .map_err(|e| Error::IoError(format!("Failed to save availability history: {}", e)))?;
+                    summary.availability += 1;
+                }
+                None => {
+                    tracing::warn!("Skipping backup record of unknown kind {}", kind);
+                    summary.skipped += 1;
+                }
+            }
+        }
+        if !batch.is_empty() {
+            self.save_torrents(&batch)?;
+            summary.torrents += batch.len();
+        }
+        self.flush_now()?;
+
+        if summary.torrents + summary.skipped < header.torrents {
+            tracing::warn!("Backup header announced {} torrents, found {}", header.torrents, summary.torrents);
+        }
+        Ok(summary)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::debrid::types::DownloadSource;
+    use std::io::Cursor;
+    use tempfile::TempDir;
+
+    /// Counts what reaches it without keeping any of it
+    #[derive(Default)]
+    struct CountingWriter {
+        bytes: usize,
+        largest_write: usize,
+        flushes: usize,
+    }
+
+    impl Write for CountingWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.bytes += buf.len();
+            self.largest_write = self.largest_write.max(buf.len());
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            self.flushes += 1;
+            Ok(())
+        }
+    }
+
+    fn session(n: usize) -> TorrentSession {
+        TorrentSession {
+            id: format!("{:040x}", n + 1),
+            metainfo: crate::torrent::Metainfo::from_magnet([0u8; 20], None, Vec::new()),
+            bitfield: Vec::new(),
+            num_pieces: 0,
+            downloaded: n as u64,
+            uploaded: 0,
+            state: "paused".to_string(),
+            download_dir: "/tmp".to_string(),
+            added_at: 1_700_000_000,
+            last_activity: 1_700_000_000,
+            source: DownloadSource::P2P,
+            completed_at: None,
+            volume_id: None,
+            category: None,
+            traffic: Default::default(),
+            swarm: None,
+            root_name: None,
+            data_sync: Default::default(),
+            queue_position: None,
+            saved_peers: Vec::new(),
+            added_from: None,
+            storage_mode: Default::default(),
+            auto_stop: Default::default(),
+            low_disk_paused_at: None,
+            linked_files: Vec::new(),
+            disabled_trackers: Vec::new(),
+            file_completed_at: Default::default(),
+        }
+    }
+
+    fn populated(dir: &TempDir, sessions: usize) -> Database {
+        let database = Database::open(dir.path().join("source")).unwrap();
+        let sessions: Vec<TorrentSession> = (0..sessions).map(session).collect();
+        database.save_torrents(&sessions).unwrap();
+        database.save_torrent_file(&sessions[0].id, b"d4:infod4:name3:abcee").unwrap();
+        database
+    }
+
+    fn export(database: &Database) -> Vec<u8> {
+        let mut out = Vec::new();
+        database.export_stream(&mut out, &CancellationToken::new(), |_| {}).unwrap();
+        out
+    }
+
+    #[test]
+    fn test_export_streams_one_record_at_a_time() {
+        let temp_dir = TempDir::new().unwrap();
+        let database = populated(&temp_dir, 300);
+        let settings = serde_json::to_vec(&database.load_settings().unwrap()).unwrap().len();
+        let largest_record = (0..300).map(|n| serde_json::to_vec(&session(n)).unwrap().len()).fold(settings, usize::max);
+
+        let mut out = CountingWriter::default();
+        let mut reports = Vec::new();
+        let summary = database
+            .export_stream(&mut out, &CancellationToken::new(), |progress| reports.push(progress))
+            .unwrap();
+
+        assert_eq!(summary.torrents, 300);
+        // Nothing bigger than one record ever reaches the writer, and it is
+        // flushed as the export goes rather than only at the end
+        assert!(out.largest_write <= largest_record, "{} > {}", out.largest_write, largest_record);
+        assert!(out.bytes > 100 * largest_record);
+        assert!(out.flushes >= 300 / FLUSH_EVERY);
+        assert_eq!(reports.first(), Some(&StreamProgress { done: 0, total: 300 }));
+        assert_eq!(reports.last(), Some(&StreamProgress { done: 300, total: 300 }));
+        assert!(reports.windows(2).all(|pair| pair[0].done <= pair[1].done));
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let temp_dir = TempDir::new().unwrap();
+        let source = populated(&temp_dir, 40);
+        let mut settings = source.load_settings().unwrap();
+        settings.max_concurrent_downloads = 9;
+        source.save_settings(&settings).unwrap();
+        source.update_progress(&session(3).id, vec![0b1000_0000], 777, 0).unwrap();
+        let out = export(&source);
+        assert_eq!(verify(Cursor::new(&out)).unwrap().torrents, 40);
+
+        let target = Database::open(temp_dir.path().join("target")).unwrap();
+        let summary = target.import_stream(Cursor::new(&out)).unwrap();
+        assert_eq!((summary.torrents, summary.torrent_files, summary.skipped), (40, 1, 0));
+        assert_eq!(target.load_all_torrents().unwrap().len(), 40);
+        assert_eq!(target.load_settings().unwrap().max_concurrent_downloads, 9);
+        assert_eq!(target.load_torrent(&session(3).id).unwrap().unwrap().downloaded, 777);
+        assert!(target.load_torrent_file(&session(0).id).unwrap().is_some());
+    }
+
+    /// (payload offset, length) of every record before the checksum, and
+    /// the checksum record's offset
+    fn records(stream: &[u8]) -> (Vec<(usize, usize)>, usize) {
+        let mut offset = MAGIC.len();
+        let mut records = Vec::new();
+        while stream[offset] != Kind::End as u8 {
+            let len = u32::from_le_bytes(stream[offset + 1..offset + 5].try_into().unwrap()) as usize;
+            records.push((offset + 5, len));
+            offset += 5 + len;
+        }
+        (records, offset)
+    }
+
+    /// Spoil the payload of the `index`th record, keeping its length, and
+    /// recompute the checksum so only the record is bad
+    fn corrupt_record(stream: &mut [u8], index: usize) {
+        let (records, end) = records(stream);
+        let (start, len) = records[index];
+        stream[start..start + len].fill(b'#');
+        let checksum = Sha1::digest(&stream[..end]);
+        stream[end + 5..].copy_from_slice(&checksum);
+    }
+
+    #[test]
+    fn test_bad_record_is_skipped() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut out = export(&populated(&temp_dir, 10));
+        // Header, settings, then the fourth session
+        corrupt_record(&mut out, 5);
+
+        let target = Database::open(temp_dir.path().join("target")).unwrap();
+        let summary = target.import_stream(Cursor::new(&out)).unwrap();
+        assert_eq!((summary.torrents, summary.skipped), (9, 1));
+        assert_eq!(target.load_all_torrents().unwrap().len(), 9);
+    }
+
+    #[test]
+    fn test_damage_is_detected_before_importing() {
+        let temp_dir = TempDir::new().unwrap();
+        let out = export(&populated(&temp_dir, 10));
+        let target = Database::open(temp_dir.path().join("target")).unwrap();
+
+        let mut flipped = out.clone();
+        let (start, _) = records(&out).0[5];
+        flipped[start + 2] ^= 0x20;
+        let error = target.import_stream(Cursor::new(&flipped)).unwrap_err();
+        assert!(error.to_string().contains("checksum"), "{}", error);
+
+        let truncated = &out[..out.len() - 30];
+        let error = target.import_stream(Cursor::new(truncated)).unwrap_err();
+        assert!(error.to_string().contains("truncated"), "{}", error);
+        assert!(target.load_all_torrents().unwrap().is_empty());
+
+        assert!(is_stream(&mut Cursor::new(&out)).unwrap());
+        assert!(!is_stream(&mut Cursor::new(b"{\"version\":1}")).unwrap());
+    }
+
+    #[test]
+    fn test_cancelled_export_stops() {
+        let temp_dir = TempDir::new().unwrap();
+        let database = populated(&temp_dir, 100);
+        let cancel = CancellationToken::new();
+        let mut out = CountingWriter::default();
+        let error = database
+            .export_stream(&mut out, &cancel, |progress| {
+                if progress.done >= FLUSH_EVERY {
+                    cancel.cancel();
+                }
+            })
+            .unwrap_err();
+        assert!(error.to_string().contains("cancelled"));
+        assert!(out.bytes > 0);
+    }
+}
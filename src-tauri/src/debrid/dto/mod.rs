@@ -0,0 +1,12 @@
+// Provider API responses, as the providers send them
+//
+// Each provider's JSON is deserialized into its own types here and turned
+// into the shared ones of `types` (`DebridStatus`, `DebridProgress`,
+// `DebridFile`, ...) by explicit conversions, so the providers themselves
+// only make requests. Every status a provider documents is a variant that
+// maps to a `DebridStatus`; a status it adds later deserializes into the
+// `Other` variant and becomes `DebridStatus::Unknown` with the string kept,
+// rather than failing the response or passing for an error.
+
+pub mod real_debrid;
+pub mod torbox;
@@ -0,0 +1,263 @@
+// Real-Debrid REST API responses (https://api.real-debrid.com/rest/1.0)
+
+use crate::debrid::types::{DebridFile, DebridProgress, DebridStatus, RemoteFileInfo};
+use serde::Deserialize;
+use std::collections::HashMap;
+
+/// `GET /user`
+#[derive(Debug, Deserialize)]
+pub struct User {
+    pub id: i64,
+    pub username: String,
+    pub email: String,
+    pub points: i64,
+    /// Premium seconds left, 0 for a free account
+    pub premium: i64,
+}
+
+/// One file of a cached variant
+#[derive(Debug, Deserialize)]
+pub struct FileVariant {
+    pub filename: String,
+    pub filesize: u64,
+}
+
+/// File id -> file
+pub type Variant = HashMap<String, FileVariant>;
+/// "rd" -> the file sets cached for the hash
+pub type HostAvailability = HashMap<String, Vec<Variant>>;
+/// `GET /torrents/instantAvailability/{hash}`: hash -> host availability
+pub type InstantAvailability = HashMap<String, HostAvailability>;
+
+/// `POST /torrents/addMagnet` and `PUT /torrents/addTorrent`
+#[derive(Debug, Deserialize)]
+pub struct AddTorrent {
+    pub id: String,
+    pub uri: String,
+}
+
+/// A torrent's `status`, as documented
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TorrentStatus {
+    MagnetError,
+    MagnetConversion,
+    WaitingFilesSelection,
+    Queued,
+    Downloading,
+    Downloaded,
+    Error,
+    Virus,
+    Compressing,
+    Uploading,
+    Dead,
+    /// A status the API added since; `#[serde(other)]` would lose the string
+    #[serde(untagged)]
+    Other(String),
+}
+
+impl From<TorrentStatus> for DebridStatus {
+    fn from(status: TorrentStatus) -> Self {
+        match status {
+            TorrentStatus::MagnetError => DebridStatus::Error,
+            TorrentStatus::MagnetConversion => DebridStatus::MagnetConversion,
+            TorrentStatus::WaitingFilesSelection => DebridStatus::WaitingFilesSelection,
+            TorrentStatus::Queued => DebridStatus::Queued,
+            TorrentStatus::Downloading => DebridStatus::Downloading,
+            TorrentStatus::Downloaded => DebridStatus::Downloaded,
+            TorrentStatus::Error | TorrentStatus::Virus => DebridStatus::Error,
+            TorrentStatus::Compressing => DebridStatus::Compressing,
+            TorrentStatus::Uploading => DebridStatus::Uploading,
+            TorrentStatus::Dead => DebridStatus::Dead,
+            TorrentStatus::Other(status) => DebridStatus::Unknown(status),
+        }
+    }
+}
+
+/// `GET /torrents/info/{id}`, and the entries of `GET /torrents` (which
+/// leave out `files`)
+#[derive(Debug, Deserialize)]
+pub struct TorrentInfo {
+    pub id: String,
+    pub filename: String,
+    pub hash: String,
+    /// Size of the selected files
+    pub bytes: u64,
+    pub host: String,
+    pub split: u64,
+    /// 0 to 100
+    pub progress: f64,
+    pub status: TorrentStatus,
+    pub added: String,
+    #[serde(default)]
+    pub files: Vec<File>,
+    /// Hoster links of the finished files, for `/unrestrict/link`
+    #[serde(default)]
+    pub links: Vec<String>,
+    /// Bytes per second, while downloading
+    #[serde(default)]
+    pub speed: Option<u64>,
+    /// While downloading
+    #[serde(default)]
+    pub seeders: Option<u32>,
+}
+
+/// A file of `TorrentInfo`
+#[derive(Debug, Deserialize)]
+pub struct File {
+    /// 1-based
+    pub id: u64,
+    /// Path inside the torrent, with a leading '/'
+    pub path: String,
+    pub bytes: u64,
+    /// 0 or 1
+    pub selected: u64,
+}
+
+/// `POST /unrestrict/link`
+#[derive(Debug, Deserialize)]
+pub struct Unrestrict {
+    pub id: String,
+    pub filename: String,
+    pub filesize: u64,
+    /// The hoster link that was unrestricted
+    pub link: String,
+    /// The direct download URL
+    pub download: String,
+    #[serde(default)]
+    pub mime_type: Option<String>,
+}
+
+/// Body of a refused request (4xx)
+#[derive(Debug, Deserialize)]
+pub struct ApiError {
+    /// "bad_token", "unknown_ressource", ...
+    pub error: String,
+    pub error_code: i64,
+    #[serde(default)]
+    pub error_details: Option<String>,
+}
+
+/// A torrent's state and progress
+pub fn progress(info: TorrentInfo) -> DebridProgress {
+    DebridProgress {
+        torrent_id: info.id,
+        status: info.status.into(),
+        progress: info.progress as f32,
+        speed: info.speed.unwrap_or(0),
+        downloaded: (info.bytes as f64 * (info.progress / 100.0)) as u64,
+        total_size: info.bytes,
+        seeders: info.seeders,
+        eta: None,
+        info_hash: Some(info.hash.to_lowercase()),
+    }
+}
+
+/// Real-Debrid file ids are 1-based; `select_files` takes 0-based indices
+pub fn remote_files(files: Vec<File>) -> Vec<RemoteFileInfo> {
+    files
+        .into_iter()
+        .map(|file| RemoteFileInfo {
+            index: file.id.saturating_sub(1) as usize,
+            path: file.path.trim_start_matches('/').to_string(),
+            size: file.bytes,
+            selected: file.selected == 1,
+        })
+        .collect()
+}
+
+/// The `index`th link of a torrent, unrestricted; the same URL serves
+/// downloads and streams
+pub fn debrid_file(index: usize, unrestrict: Unrestrict) -> DebridFile {
+    DebridFile {
+        id: index.to_string(),
+        name: unrestrict.filename,
+        size: unrestrict.filesize,
+        download_link: Some(unrestrict.download.clone()),
+        stream_link: Some(unrestrict.download),
+        mime_type: unrestrict.mime_type,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_torrent_info_fixture() {
+        let info: TorrentInfo = serde_json::from_str(include_str!("../testdata/real_debrid_torrent_info.json")).unwrap();
+        assert_eq!(info.status, TorrentStatus::Downloading);
+        assert_eq!(info.files.len(), 3);
+
+        let progress = progress(info);
+        assert_eq!(progress.status, DebridStatus::Downloading);
+        assert_eq!(progress.speed, 4_194_304);
+        assert_eq!(progress.seeders, Some(12));
+        assert_eq!(progress.total_size, 2_147_483_648);
+        assert_eq!(progress.downloaded, 858_993_459);
+        assert_eq!(progress.info_hash.as_deref(), Some("dd8255ecdc7ca55fb0bbf81323d87062db1f6d1c"));
+    }
+
+    #[test]
+    fn test_file_list_fixture() {
+        let info: TorrentInfo = serde_json::from_str(include_str!("../testdata/real_debrid_torrent_info.json")).unwrap();
+        let files = remote_files(info.files);
+        assert_eq!(files[0], RemoteFileInfo { index: 0, path: "Big Buck Bunny/Big Buck Bunny.mkv".to_string(), size: 2_147_000_000, selected: true });
+        assert_eq!(files.iter().filter(|file| file.selected).count(), 2);
+        assert_eq!(files[2].index, 2);
+    }
+
+    #[test]
+    fn test_torrent_list_fixture() {
+        // The list leaves out files, links and speed
+        let torrents: Vec<TorrentInfo> = serde_json::from_str(include_str!("../testdata/real_debrid_torrents.json")).unwrap();
+        let statuses: Vec<DebridStatus> = torrents.into_iter().map(|torrent| progress(torrent).status).collect();
+        assert_eq!(
+            statuses,
+            vec![DebridStatus::Downloaded, DebridStatus::WaitingFilesSelection, DebridStatus::Error, DebridStatus::Dead]
+        );
+    }
+
+    #[test]
+    fn test_error_fixture() {
+        let error: ApiError = serde_json::from_str(include_str!("../testdata/real_debrid_error.json")).unwrap();
+        assert_eq!((error.error.as_str(), error.error_code), ("unknown_ressource", 7));
+        assert_eq!(error.error_details, None);
+    }
+
+    #[test]
+    fn test_every_status_is_mapped() {
+        let cases = [
+            ("magnet_error", DebridStatus::Error),
+            ("magnet_conversion", DebridStatus::MagnetConversion),
+            ("waiting_files_selection", DebridStatus::WaitingFilesSelection),
+            ("queued", DebridStatus::Queued),
+            ("downloading", DebridStatus::Downloading),
+            ("downloaded", DebridStatus::Downloaded),
+            ("error", DebridStatus::Error),
+            ("virus", DebridStatus::Error),
+            ("compressing", DebridStatus::Compressing),
+            ("uploading", DebridStatus::Uploading),
+            ("dead", DebridStatus::Dead),
+        ];
+        for (json, expected) in cases {
+            let status: TorrentStatus = serde_json::from_value(serde_json::json!(json)).unwrap();
+            assert!(!matches!(status, TorrentStatus::Other(_)), "{} is documented", json);
+            assert_eq!(DebridStatus::from(status), expected, "{}", json);
+        }
+    }
+
+    #[test]
+    fn test_unknown_status_is_kept() {
+        let json = include_str!("../testdata/real_debrid_torrent_info.json").replace("\"downloading\"", "\"checking_integrity\"");
+        let info: TorrentInfo = serde_json::from_str(&json).unwrap();
+        assert_eq!(info.status, TorrentStatus::Other("checking_integrity".to_string()));
+
+        let status = progress(info).status;
+        assert_eq!(status, DebridStatus::Unknown("checking_integrity".to_string()));
+        assert!(!status.is_error() && !status.is_ready());
+        // And on to the frontend
+        let sent = serde_json::to_string(&status).unwrap();
+        assert_eq!(serde_json::from_str::<DebridStatus>(&sent).unwrap(), status);
+    }
+}
@@ -0,0 +1,280 @@
+// Torbox API responses (https://api.torbox.app/v1/api)
+
+use crate::debrid::types::{DebridFile, DebridProgress, DebridStatus, RemoteFileInfo};
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+
+/// The envelope around every response
+#[derive(Debug, Deserialize)]
+pub struct Response<T> {
+    #[serde(default)]
+    pub success: Option<bool>,
+    pub data: Option<T>,
+    /// Error code ("BAD_TOKEN", "DATABASE_ERROR", ...)
+    pub error: Option<String>,
+    /// What happened, in words; set on success too
+    pub detail: Option<String>,
+}
+
+impl<T> Response<T> {
+    /// The data, or the error the response carries. `data` is null for an
+    /// account without torrents, which isn't an error.
+    pub fn into_data(self) -> Result<Option<T>> {
+        match self.error {
+            Some(error) if !error.is_empty() => Err(anyhow!(
+                "Torbox error {}: {}",
+                error,
+                self.detail.unwrap_or_default()
+            )),
+            _ => Ok(self.data),
+        }
+    }
+}
+
+/// A torrent's `download_state`, as documented
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub enum DownloadState {
+    #[serde(rename = "downloading")]
+    Downloading,
+    /// Seeding, once the files are on Torbox storage
+    #[serde(rename = "uploading")]
+    Uploading,
+    #[serde(rename = "stalled (no seeds)")]
+    Stalled,
+    #[serde(rename = "paused")]
+    Paused,
+    #[serde(rename = "completed")]
+    Completed,
+    #[serde(rename = "cached")]
+    Cached,
+    /// Fetching the metadata of a magnet
+    #[serde(rename = "metaDL")]
+    MetaDl,
+    #[serde(rename = "checkingResumeData")]
+    CheckingResumeData,
+    #[serde(rename = "error")]
+    Error,
+    /// A state the API added since; `#[serde(other)]` would lose the string
+    #[serde(untagged)]
+    Other(String),
+}
+
+impl From<DownloadState> for DebridStatus {
+    fn from(state: DownloadState) -> Self {
+        match state {
+            // Without seeds it may still pick up again
+            DownloadState::Downloading | DownloadState::Stalled => DebridStatus::Downloading,
+            DownloadState::Uploading => DebridStatus::Uploading,
+            DownloadState::Paused | DownloadState::CheckingResumeData => DebridStatus::Queued,
+            DownloadState::Completed | DownloadState::Cached => DebridStatus::Downloaded,
+            DownloadState::MetaDl => DebridStatus::MagnetConversion,
+            DownloadState::Error => DebridStatus::Error,
+            DownloadState::Other(state) => DebridStatus::Unknown(state),
+        }
+    }
+}
+
+/// An entry of `GET /torrents/mylist`
+#[derive(Debug, Deserialize)]
+pub struct Download {
+    pub id: i64,
+    pub name: String,
+    pub hash: Option<String>,
+    #[serde(default)]
+    pub cached: bool,
+    #[serde(default)]
+    pub download_state: Option<DownloadState>,
+    /// 0 to 1
+    #[serde(default)]
+    pub progress: f64,
+    /// Bytes per second
+    #[serde(default)]
+    pub download_speed: u64,
+    /// Seconds
+    #[serde(default)]
+    pub eta: Option<u64>,
+    #[serde(default)]
+    pub seeds: Option<u32>,
+    #[serde(default)]
+    pub size: u64,
+    #[serde(default)]
+    pub download_finished: bool,
+    /// The files can be downloaded from Torbox
+    #[serde(default)]
+    pub download_present: bool,
+    #[serde(default)]
+    pub files: Vec<File>,
+}
+
+impl Download {
+    pub fn status(&self) -> DebridStatus {
+        if self.cached || (self.download_finished && self.download_present) {
+            return DebridStatus::Downloaded;
+        }
+        match &self.download_state {
+            Some(state) => state.clone().into(),
+            None => DebridStatus::Downloading,
+        }
+    }
+
+    /// The size, or the files' when the API leaves it out
+    pub fn total_size(&self) -> u64 {
+        match self.size {
+            0 => self.files.iter().map(|file| file.size).sum(),
+            size => size,
+        }
+    }
+}
+
+/// A file of `Download`
+#[derive(Debug, Deserialize)]
+pub struct File {
+    pub id: i64,
+    #[serde(default)]
+    pub short_name: String,
+    /// Path inside the torrent
+    #[serde(default)]
+    pub name: String,
+    pub size: u64,
+    #[serde(default)]
+    pub mimetype: String,
+}
+
+/// A download's state and progress
+pub fn progress(download: Download) -> DebridProgress {
+    let status = download.status();
+    let total_size = download.total_size();
+    let (progress, downloaded) = match status {
+        DebridStatus::Downloaded => (100.0, total_size),
+        _ => {
+            let fraction = download.progress.clamp(0.0, 1.0);
+            ((fraction * 100.0) as f32, (total_size as f64 * fraction) as u64)
+        }
+    };
+    DebridProgress {
+        torrent_id: download.id.to_string(),
+        status,
+        progress,
+        speed: download.download_speed,
+        downloaded,
+        total_size,
+        seeders: download.seeds,
+        eta: download.eta.filter(|&eta| eta > 0),
+        info_hash: download.hash.map(|hash| hash.to_lowercase()),
+    }
+}
+
+/// A file of a download, fetched from `url`; the same URL serves downloads
+/// and streams
+pub fn debrid_file(file: File, url: String) -> DebridFile {
+    DebridFile {
+        id: file.id.to_string(),
+        name: if !file.short_name.is_empty() { file.short_name } else { file.name },
+        size: file.size,
+        download_link: Some(url.clone()),
+        stream_link: Some(url),
+        mime_type: Some(file.mimetype).filter(|mimetype| !mimetype.is_empty()),
+    }
+}
+
+/// Torbox has no file selection: every file is always downloadable
+pub fn remote_files(files: Vec<File>) -> Vec<RemoteFileInfo> {
+    files
+        .into_iter()
+        .enumerate()
+        .map(|(index, file)| RemoteFileInfo {
+            index,
+            path: if !file.name.is_empty() { file.name } else { file.short_name },
+            size: file.size,
+            selected: true,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mylist() -> Vec<Download> {
+        let response: Response<Vec<Download>> = serde_json::from_str(include_str!("../testdata/torbox_mylist.json")).unwrap();
+        response.into_data().unwrap().unwrap()
+    }
+
+    #[test]
+    fn test_mylist_fixture() {
+        let progress: Vec<DebridProgress> = mylist().into_iter().map(progress).collect();
+        let statuses: Vec<&DebridStatus> = progress.iter().map(|p| &p.status).collect();
+        assert_eq!(statuses, vec![&DebridStatus::Downloaded, &DebridStatus::Downloading, &DebridStatus::MagnetConversion]);
+
+        let downloading = &progress[1];
+        assert_eq!(downloading.torrent_id, "5522631");
+        assert_eq!(downloading.progress, 62.5);
+        assert_eq!(downloading.downloaded, 3_864_559_616);
+        assert_eq!(downloading.total_size, 6_183_295_386);
+        assert_eq!((downloading.speed, downloading.seeders, downloading.eta), (9_437_184, Some(31), Some(251)));
+        assert_eq!(downloading.info_hash.as_deref(), Some("209c8226b299b308beaf2b9cd3fb49212dbd13ec"));
+
+        assert_eq!((progress[0].progress, progress[0].downloaded), (100.0, 276_445_467));
+        // No eta rather than zero seconds
+        assert_eq!(progress[2].eta, None);
+    }
+
+    #[test]
+    fn test_file_list_fixture() {
+        let download = mylist().remove(0);
+        assert_eq!(
+            remote_files(download.files),
+            vec![
+                RemoteFileInfo { index: 0, path: "Sintel/Sintel.mp4".to_string(), size: 275_918_971, selected: true },
+                RemoteFileInfo { index: 1, path: "Sintel.en.srt".to_string(), size: 526_496, selected: true },
+            ]
+        );
+
+        let mut files = mylist().remove(0).files.into_iter();
+        let file = debrid_file(files.next().unwrap(), "https://example.com/dl".to_string());
+        assert_eq!((file.id.as_str(), file.name.as_str(), file.mime_type.as_deref()), ("0", "Sintel.mp4", Some("video/mp4")));
+        assert_eq!(debrid_file(files.next().unwrap(), String::new()).mime_type.as_deref(), Some("application/x-subrip"));
+    }
+
+    #[test]
+    fn test_error_fixture() {
+        let response: Response<Vec<Download>> = serde_json::from_str(include_str!("../testdata/torbox_error.json")).unwrap();
+        assert_eq!(response.success, Some(false));
+        let error = response.into_data().unwrap_err();
+        assert_eq!(error.to_string(), "Torbox error BAD_TOKEN: Invalid API token. Please make sure your API token is valid.");
+
+        // An account without torrents isn't an error
+        let empty: Response<Vec<Download>> =
+            serde_json::from_str(r#"{"success": true, "error": null, "detail": "Found no torrents.", "data": null}"#).unwrap();
+        assert!(empty.into_data().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_every_state_is_mapped() {
+        let cases = [
+            ("downloading", DebridStatus::Downloading),
+            ("uploading", DebridStatus::Uploading),
+            ("stalled (no seeds)", DebridStatus::Downloading),
+            ("paused", DebridStatus::Queued),
+            ("completed", DebridStatus::Downloaded),
+            ("cached", DebridStatus::Downloaded),
+            ("metaDL", DebridStatus::MagnetConversion),
+            ("checkingResumeData", DebridStatus::Queued),
+            ("error", DebridStatus::Error),
+        ];
+        for (json, expected) in cases {
+            let state: DownloadState = serde_json::from_value(serde_json::json!(json)).unwrap();
+            assert!(!matches!(state, DownloadState::Other(_)), "{} is documented", json);
+            assert_eq!(DebridStatus::from(state), expected, "{}", json);
+        }
+    }
+
+    #[test]
+    fn test_unknown_state_is_kept() {
+        let json = include_str!("../testdata/torbox_mylist.json").replace("\"metaDL\"", "\"moving\"");
+        let response: Response<Vec<Download>> = serde_json::from_str(&json).unwrap();
+        let download = response.into_data().unwrap().unwrap().remove(2);
+        assert_eq!(download.download_state, Some(DownloadState::Other("moving".to_string())));
+        assert_eq!(progress(download).status, DebridStatus::Unknown("moving".to_string()));
+    }
+}
@@ -0,0 +1,128 @@
+// Structured provider errors
+//
+// Providers return `anyhow::Error`; the failures worth telling apart are a
+// `ProviderError` inside it, found again with `downcast_ref`.
+
+use super::types::DebridProviderType;
+use reqwest::StatusCode;
+
+/// A provider API call that got an answer other than success
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum ProviderError {
+    /// Server error or rate limit; the clients retry these themselves
+    #[error("Transient error {status}: {body}")]
+    Transient {
+        status: StatusCode,
+        body: String,
+        /// Seconds the server asked us to wait (Retry-After)
+        retry_after: Option<u64>,
+    },
+    /// Refused by the provider (bad key, slot limit, unknown torrent, ...);
+    /// asking again won't help
+    #[error("{} API error {status}: {body}", .provider.display_name())]
+    Rejected { provider: DebridProviderType, status: StatusCode, body: String },
+    /// No API key set up for the provider
+    #[error("Provider {} not configured", .0.display_name())]
+    NotConfigured(DebridProviderType),
+    /// The provider is marked down (see `health`); no request was made
+    #[error("{}", unavailable_message(*.provider, *.until, *.maintenance))]
+    Unavailable {
+        provider: DebridProviderType,
+        /// When it is checked again (unix timestamp)
+        until: i64,
+        /// It said it is down for maintenance
+        maintenance: bool,
+    },
+}
+
+fn unavailable_message(provider: DebridProviderType, until: i64, maintenance: bool) -> String {
+    let until = chrono::DateTime::from_timestamp(until, 0)
+        .map(|time| time.format("%H:%M UTC").to_string())
+        .unwrap_or_default();
+    let why = if maintenance { "down for maintenance" } else { "not responding" };
+    format!("{} is {}; checking again at {}", provider.display_name(), why, until)
+}
+
+impl ProviderError {
+    /// The error for a non-success `status`
+    pub fn from_status(provider: DebridProviderType, status: StatusCode, body: String) -> Self {
+        if status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS {
+            Self::Transient { status, body, retry_after: None }
+        } else {
+            Self::Rejected { provider, status, body }
+        }
+    }
+
+    /// The error for a non-success response, with its Retry-After
+    pub async fn from_response(provider: DebridProviderType, response: reqwest::Response) -> Self {
+        let status = response.status();
+        let wait = retry_after(response.headers(), chrono::Utc::now().timestamp());
+        let body = response.text().await.unwrap_or_default();
+        match Self::from_status(provider, status, body) {
+            Self::Transient { status, body, .. } => Self::Transient { status, body, retry_after: wait },
+            rejected => rejected,
+        }
+    }
+}
+
+/// Seconds to wait from a Retry-After header: a number of seconds or an
+/// HTTP date (relative to `now`, a unix timestamp)
+pub fn retry_after(headers: &reqwest::header::HeaderMap, now: i64) -> Option<u64> {
+    let value = headers.get(reqwest::header::RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(secs);
+    }
+    let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    Some(date.timestamp().saturating_sub(now).max(0) as u64)
+}
+
+/// Whether `error` from a provider call will keep happening: the provider
+/// refused the request or isn't set up. Network trouble, server errors and
+/// anything unrecognised count as transient.
+pub fn is_permanent(error: &anyhow::Error) -> bool {
+    matches!(
+        error.downcast_ref::<ProviderError>(),
+        Some(ProviderError::Rejected { .. } | ProviderError::NotConfigured(_))
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+
+    #[test]
+    fn test_classification() {
+        let rejected = ProviderError::from_status(DebridProviderType::RealDebrid, StatusCode::FORBIDDEN, "slot limit".to_string());
+        assert_eq!(rejected.to_string(), "Real-Debrid API error 403 Forbidden: slot limit");
+        assert!(is_permanent(&anyhow!(rejected)));
+        assert!(is_permanent(&anyhow!(ProviderError::NotConfigured(DebridProviderType::Torbox))));
+
+        let busy = ProviderError::from_status(DebridProviderType::Torbox, StatusCode::TOO_MANY_REQUESTS, String::new());
+        assert!(matches!(busy, ProviderError::Transient { .. }));
+        assert!(busy.to_string().starts_with("Transient error 429"));
+        assert!(!is_permanent(&anyhow!(busy)));
+        assert!(!is_permanent(&anyhow!("connection closed")));
+
+        let down = ProviderError::Unavailable { provider: DebridProviderType::Torbox, until: 3600, maintenance: true };
+        assert_eq!(down.to_string(), "Torbox is down for maintenance; checking again at 01:00 UTC");
+        assert!(!is_permanent(&anyhow!(down)));
+
+        // Still found under added context
+        let wrapped = anyhow!(ProviderError::NotConfigured(DebridProviderType::Torbox)).context("adding torrent");
+        assert!(is_permanent(&wrapped));
+    }
+
+    #[test]
+    fn test_retry_after() {
+        use reqwest::header::{HeaderMap, HeaderValue, RETRY_AFTER};
+        let now = 1_445_412_480; // Wed, 21 Oct 2015 07:28:00 GMT
+        let with = |value: &'static str| HeaderMap::from_iter([(RETRY_AFTER, HeaderValue::from_static(value))]);
+        assert_eq!(retry_after(&with("120"), now), Some(120));
+        assert_eq!(retry_after(&with("Wed, 21 Oct 2015 08:28:00 GMT"), now), Some(3600));
+        // A date already past means now
+        assert_eq!(retry_after(&with("Wed, 21 Oct 2015 07:00:00 GMT"), now), Some(0));
+        assert_eq!(retry_after(&with("soon"), now), None);
+        assert_eq!(retry_after(&HeaderMap::new(), now), None);
+    }
+}
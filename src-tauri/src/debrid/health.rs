@@ -0,0 +1,405 @@
+// Provider health
+//
+// A provider in maintenance answers every call with a 503 for an hour or
+// so. Rather than let each operation fail on its own, `HealthTracker` sees
+// the outcome of every call: a 503 or a maintenance page marks the provider
+// Down at once, repeated transient failures mark it Degraded and then Down.
+// Calls to a Down provider fail fast with `ProviderError::Unavailable`
+// without touching the network, until a probe (`start_health_task`) on the
+// retry schedule gets an answer. Every change is broadcast, for the
+// `debrid-provider-health` event.
+
+use super::error::ProviderError;
+use super::types::DebridProviderType;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
+
+/// Consecutive transient failures before a provider counts as degraded
+pub const DEGRADED_AFTER: u32 = 2;
+/// ... and as down
+pub const DOWN_AFTER: u32 = 4;
+/// First wait before probing a down provider that gave no Retry-After;
+/// doubles with every failed probe up to `MAX_PROBE_WAIT`
+pub const FIRST_PROBE_WAIT: Duration = Duration::from_secs(60);
+pub const MAX_PROBE_WAIT: Duration = Duration::from_secs(30 * 60);
+/// Retry-After values beyond this are capped, so a bogus header can't take
+/// a provider away for days
+pub const MAX_RETRY_AFTER: Duration = Duration::from_secs(6 * 60 * 60);
+/// How often the health task looks for due probes
+const PROBE_TICK: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HealthState {
+    Healthy,
+    /// Failing now and then; still used
+    Degraded,
+    /// Not used until a probe succeeds
+    Down,
+}
+
+/// A provider's health, as sent with `debrid-provider-health`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ProviderHealth {
+    pub provider: DebridProviderType,
+    pub state: HealthState,
+    pub consecutive_failures: u32,
+    /// It said it is down for maintenance
+    pub maintenance: bool,
+    /// When a down provider is probed next (unix timestamp)
+    pub retry_at: Option<i64>,
+    pub last_error: Option<String>,
+}
+
+struct Entry {
+    state: HealthState,
+    failures: u32,
+    maintenance: bool,
+    /// Failed probes since it went down
+    probes: u32,
+    retry_at: Option<Instant>,
+    last_error: Option<String>,
+}
+
+impl Default for Entry {
+    fn default() -> Self {
+        Self { state: HealthState::Healthy, failures: 0, maintenance: false, probes: 0, retry_at: None, last_error: None }
+    }
+}
+
+/// What a failed call says about the provider
+enum Failure {
+    /// Maintenance or unavailable: down at once
+    Outage { retry_after: Option<Duration> },
+    /// Might be a blip
+    Transient,
+}
+
+/// How a failed call counts; None when it says nothing about the provider's
+/// health (it answered, or the problem is ours)
+fn classify(error: &anyhow::Error) -> Option<Failure> {
+    if let Some(crate::error::Error::ClockSkew(_)) = error.downcast_ref::<crate::error::Error>() {
+        return None;
+    }
+    match error.downcast_ref::<ProviderError>() {
+        Some(ProviderError::Transient { status, body, retry_after }) => {
+            let maintenance = body.to_ascii_lowercase().contains("maintenance");
+            if *status == reqwest::StatusCode::SERVICE_UNAVAILABLE || maintenance {
+                Some(Failure::Outage { retry_after: retry_after.map(Duration::from_secs) })
+            } else {
+                Some(Failure::Transient)
+            }
+        }
+        Some(_) => None,
+        // Network errors: timeouts, refused connections, broken bodies
+        None => Some(Failure::Transient),
+    }
+}
+
+fn probe_wait(probes: u32) -> Duration {
+    (FIRST_PROBE_WAIT * 2u32.pow(probes.min(16))).min(MAX_PROBE_WAIT)
+}
+
+/// Health of every provider (see the module docs)
+pub struct HealthTracker {
+    entries: Mutex<HashMap<DebridProviderType, Entry>>,
+    changes: broadcast::Sender<ProviderHealth>,
+}
+
+impl Default for HealthTracker {
+    fn default() -> Self {
+        Self { entries: Mutex::default(), changes: broadcast::channel(32).0 }
+    }
+}
+
+impl HealthTracker {
+    /// Changes of state or retry time, as they happen
+    pub fn subscribe(&self) -> broadcast::Receiver<ProviderHealth> {
+        self.changes.subscribe()
+    }
+
+    /// Every provider that has been used
+    pub fn snapshot(&self) -> Vec<ProviderHealth> {
+        let now = Instant::now();
+        let entries = self.entries.lock().unwrap();
+        let mut health: Vec<_> = entries.iter().map(|(provider, entry)| describe(*provider, entry, now)).collect();
+        health.sort_by_key(|health| health.provider.as_str());
+        health
+    }
+
+    pub fn state(&self, provider: DebridProviderType) -> HealthState {
+        self.entries.lock().unwrap().get(&provider).map_or(HealthState::Healthy, |entry| entry.state)
+    }
+
+    /// Fail fast if `provider` is down
+    pub fn check(&self, provider: DebridProviderType) -> Result<(), ProviderError> {
+        let entries = self.entries.lock().unwrap();
+        match entries.get(&provider) {
+            Some(entry) if entry.state == HealthState::Down => Err(ProviderError::Unavailable {
+                provider,
+                until: unix_time(entry.retry_at.unwrap_or_else(Instant::now), Instant::now()),
+                maintenance: entry.maintenance,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Count the outcome of a call to `provider` and pass it on
+    pub fn record<T>(&self, provider: DebridProviderType, result: anyhow::Result<T>) -> anyhow::Result<T> {
+        match &result {
+            Ok(_) => self.succeeded(provider, Instant::now()),
+            Err(e) => self.failed(provider, e, Instant::now()),
+        }
+        result
+    }
+
+    /// Down providers whose probe is due at `now`
+    pub fn probes_due(&self, now: Instant) -> Vec<DebridProviderType> {
+        let entries = self.entries.lock().unwrap();
+        let mut due: Vec<_> = entries
+            .iter()
+            .filter(|(_, entry)| entry.state == HealthState::Down && entry.retry_at.map_or(true, |at| at <= now))
+            .map(|(provider, _)| *provider)
+            .collect();
+        due.sort_by_key(DebridProviderType::as_str);
+        due
+    }
+
+    /// Count the outcome of a probe
+    pub fn probed<T>(&self, provider: DebridProviderType, result: &anyhow::Result<T>, now: Instant) {
+        match result {
+            Ok(_) => self.succeeded(provider, now),
+            Err(e) => self.probe_failed(provider, e, now),
+        }
+    }
+
+    /// Forget a provider's history, e.g. when its client is replaced
+    pub fn reset(&self, provider: DebridProviderType) {
+        if let Some(entry) = self.entries.lock().unwrap().remove(&provider) {
+            if entry.state != HealthState::Healthy {
+                self.publish(describe(provider, &Entry::default(), Instant::now()));
+            }
+        }
+    }
+
+    fn succeeded(&self, provider: DebridProviderType, now: Instant) {
+        let mut entries = self.entries.lock().unwrap();
+        let Some(entry) = entries.get_mut(&provider) else { return };
+        let was = entry.state;
+        *entry = Entry::default();
+        if was != HealthState::Healthy {
+            tracing::info!("{} is answering again", provider.display_name());
+            self.publish(describe(provider, entry, now));
+        }
+    }
+
+    fn failed(&self, provider: DebridProviderType, error: &anyhow::Error, now: Instant) {
+        let Some(failure) = classify(error) else { return };
+        let mut entries = self.entries.lock().unwrap();
+        let entry = entries.entry(provider).or_default();
+        let before = (entry.state, entry.retry_at);
+        entry.failures += 1;
+        entry.last_error = Some(error.to_string());
+
+        match failure {
+            Failure::Outage { retry_after } => {
+                entry.maintenance = true;
+                let wait = retry_after.map_or_else(|| probe_wait(entry.probes), |wait| wait.min(MAX_RETRY_AFTER));
+                // Calls already in flight when it went down don't push the probe back
+                if entry.state != HealthState::Down || retry_after.is_some() {
+                    entry.retry_at = Some(now + wait);
+                }
+                entry.state = HealthState::Down;
+            }
+            Failure::Transient if entry.state == HealthState::Down => {}
+            Failure::Transient if entry.failures >= DOWN_AFTER => {
+                entry.state = HealthState::Down;
+                entry.retry_at = Some(now + probe_wait(entry.probes));
+            }
+            Failure::Transient if entry.failures >= DEGRADED_AFTER => entry.state = HealthState::Degraded,
+            Failure::Transient => {}
+        }
+
+        if (entry.state, entry.retry_at) != before {
+            tracing::warn!("{} is now {:?}: {}", provider.display_name(), entry.state, error);
+            self.publish(describe(provider, entry, now));
+        }
+    }
+
+    fn probe_failed(&self, provider: DebridProviderType, error: &anyhow::Error, now: Instant) {
+        let mut entries = self.entries.lock().unwrap();
+        let entry = entries.entry(provider).or_default();
+        entry.probes += 1;
+        entry.last_error = Some(error.to_string());
+        entry.state = HealthState::Down;
+        let retry_after = match classify(error) {
+            Some(Failure::Outage { retry_after }) => {
+                entry.maintenance = true;
+                retry_after.map(|wait| wait.min(MAX_RETRY_AFTER))
+            }
+            _ => None,
+        };
+        entry.retry_at = Some(now + retry_after.unwrap_or_else(|| probe_wait(entry.probes)));
+        tracing::info!("{} is still down: {}", provider.display_name(), error);
+        self.publish(describe(provider, entry, now));
+    }
+
+    fn publish(&self, health: ProviderHealth) {
+        // Nobody listening is fine
+        let _ = self.changes.send(health);
+    }
+}
+
+fn describe(provider: DebridProviderType, entry: &Entry, now: Instant) -> ProviderHealth {
+    ProviderHealth {
+        provider,
+        state: entry.state,
+        consecutive_failures: entry.failures,
+        maintenance: entry.maintenance,
+        retry_at: entry.retry_at.map(|at| unix_time(at, now)),
+        last_error: entry.last_error.clone(),
+    }
+}
+
+fn unix_time(at: Instant, now: Instant) -> i64 {
+    let offset = at.saturating_duration_since(now).as_secs() as i64;
+    chrono::Utc::now().timestamp() + offset
+}
+
+/// Tell the UI about health changes and probe down providers when due
+#[cfg(feature = "app")]
+pub async fn start_health_task(app_handle: tauri::AppHandle) {
+    use crate::events::{emit_event, Event};
+    use tauri::Manager;
+
+    let state = app_handle.state::<crate::state::AppState>();
+    let debrid_manager = state.debrid_manager.clone();
+    let health = debrid_manager.read().await.health();
+    let mut changes = health.subscribe();
+    let mut tick = tokio::time::interval(PROBE_TICK);
+    tick.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    loop {
+        tokio::select! {
+            change = changes.recv() => match change {
+                Ok(change) => emit_event(&app_handle, Event::DebridProviderHealth(change)),
+                Err(broadcast::error::RecvError::Lagged(_)) => {
+                    for current in health.snapshot() {
+                        emit_event(&app_handle, Event::DebridProviderHealth(current));
+                    }
+                }
+                Err(broadcast::error::RecvError::Closed) => return,
+            },
+            _ = tick.tick() => {
+                for provider in health.probes_due(Instant::now()) {
+                    let client = debrid_manager.read().await.get_provider(provider).cloned();
+                    let Some(client) = client else {
+                        health.reset(provider);
+                        continue;
+                    };
+                    tracing::info!("Checking whether {} is back", provider.display_name());
+                    let result = client.get_user_info().await;
+                    health.probed(provider, &result, Instant::now());
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+    use reqwest::StatusCode;
+
+    const TORBOX: DebridProviderType = DebridProviderType::Torbox;
+
+    fn transient(status: StatusCode, body: &str, retry_after: Option<u64>) -> anyhow::Error {
+        anyhow!(ProviderError::Transient { status, body: body.to_string(), retry_after })
+    }
+
+    fn drain(changes: &mut broadcast::Receiver<ProviderHealth>) -> Vec<(HealthState, Option<i64>)> {
+        std::iter::from_fn(|| changes.try_recv().ok()).map(|change| (change.state, change.retry_at)).collect()
+    }
+
+    #[test]
+    fn test_failures_degrade_then_take_down() {
+        let health = HealthTracker::default();
+        let mut changes = health.subscribe();
+        let now = Instant::now();
+
+        health.failed(TORBOX, &transient(StatusCode::BAD_GATEWAY, "", None), now);
+        assert_eq!(health.state(TORBOX), HealthState::Healthy);
+        health.failed(TORBOX, &anyhow!("operation timed out"), now);
+        assert_eq!(health.state(TORBOX), HealthState::Degraded);
+        assert!(health.check(TORBOX).is_ok());
+
+        // Answers that refuse a request say nothing about availability
+        let refused = ProviderError::from_status(TORBOX, StatusCode::FORBIDDEN, "slot limit".to_string());
+        health.failed(TORBOX, &anyhow!(refused), now);
+        assert_eq!(health.state(TORBOX), HealthState::Degraded);
+
+        health.failed(TORBOX, &anyhow!("connection closed"), now);
+        health.failed(TORBOX, &anyhow!("connection closed"), now);
+        assert_eq!(health.state(TORBOX), HealthState::Down);
+        let Err(ProviderError::Unavailable { maintenance: false, until, .. }) = health.check(TORBOX) else {
+            panic!("expected fail-fast")
+        };
+        assert!(until >= chrono::Utc::now().timestamp() + FIRST_PROBE_WAIT.as_secs() as i64 - 1);
+
+        let states: Vec<_> = drain(&mut changes).into_iter().map(|(state, _)| state).collect();
+        assert_eq!(states, [HealthState::Degraded, HealthState::Down]);
+    }
+
+    #[test]
+    fn test_maintenance_is_down_at_once_until_retry_after() {
+        let health = HealthTracker::default();
+        let mut changes = health.subscribe();
+        let now = Instant::now();
+
+        health.failed(TORBOX, &transient(StatusCode::SERVICE_UNAVAILABLE, "<h1>Maintenance</h1>", Some(600)), now);
+        let Err(down @ ProviderError::Unavailable { maintenance: true, .. }) = health.check(TORBOX) else {
+            panic!("expected fail-fast")
+        };
+        assert!(down.to_string().starts_with("Torbox is down for maintenance"));
+        assert_eq!(drain(&mut changes).len(), 1);
+
+        // Calls that were already in flight don't move the probe
+        health.failed(TORBOX, &transient(StatusCode::SERVICE_UNAVAILABLE, "", None), now);
+        assert!(drain(&mut changes).is_empty());
+
+        assert!(health.probes_due(now + Duration::from_secs(599)).is_empty());
+        assert_eq!(health.probes_due(now + Duration::from_secs(600)), [TORBOX]);
+
+        // Still down: the next probe waits for the backoff
+        let later = now + Duration::from_secs(600);
+        health.probed::<()>(TORBOX, &Err(anyhow!("connection refused")), later);
+        assert_eq!(health.state(TORBOX), HealthState::Down);
+        assert!(health.probes_due(later + probe_wait(1) - Duration::from_secs(1)).is_empty());
+        assert_eq!(health.probes_due(later + probe_wait(1)), [TORBOX]);
+        assert_eq!(probe_wait(1), FIRST_PROBE_WAIT * 2);
+        assert_eq!(probe_wait(10), MAX_PROBE_WAIT);
+        assert_eq!(drain(&mut changes).len(), 1);
+
+        // Back
+        health.probed(TORBOX, &Ok(()), later + probe_wait(1));
+        assert_eq!(health.state(TORBOX), HealthState::Healthy);
+        assert!(health.check(TORBOX).is_ok());
+        assert_eq!(drain(&mut changes), [(HealthState::Healthy, None)]);
+
+        // A success while healthy is no news
+        health.record(TORBOX, Ok(())).unwrap();
+        assert!(drain(&mut changes).is_empty());
+    }
+
+    #[test]
+    fn test_retry_after_is_capped() {
+        let health = HealthTracker::default();
+        let now = Instant::now();
+        health.failed(TORBOX, &transient(StatusCode::SERVICE_UNAVAILABLE, "", Some(u64::MAX / 4)), now);
+        assert_eq!(health.probes_due(now + MAX_RETRY_AFTER), [TORBOX]);
+    }
+}
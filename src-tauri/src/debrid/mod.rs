@@ -1,5 +1,8 @@
 // Debrid services integration module
 
+pub mod dto;
+pub mod error;
+pub mod health;
 pub mod provider;
 pub mod types;
 pub mod request_queue;
@@ -11,6 +14,8 @@ use std::sync::Arc;
 use std::path::PathBuf;
 use anyhow::{anyhow, Result};
 
+pub use error::ProviderError;
+pub use health::{HealthState, HealthTracker, ProviderHealth};
 pub use provider::DebridProvider;
 pub use types::*;
 pub use request_queue::RequestQueue;
@@ -29,6 +34,15 @@ pub struct DebridManager {
     real_debrid: Option<Arc<dyn DebridProvider>>,
     /// Provider preference order
     preference_order: Vec<DebridProviderType>,
+    /// API base URL overrides from settings
+    base_urls: HashMap<DebridProviderType, String>,
+    /// Keys of providers set up via `initialize_provider`, to rebuild them
+    /// when a base URL changes
+    api_keys: HashMap<DebridProviderType, String>,
+    /// Whether debrid features are turned on (`AppSettings::enable_debrid`)
+    enabled: bool,
+    /// Availability of each provider, from the outcome of every call
+    health: Arc<HealthTracker>,
 }
 
 impl DebridManager {
@@ -38,9 +52,62 @@ impl DebridManager {
             torbox: None,
             real_debrid: None,
             preference_order: vec![DebridProviderType::Torbox, DebridProviderType::RealDebrid],
+            base_urls: HashMap::new(),
+            api_keys: HashMap::new(),
+            enabled: true,
+            health: Arc::default(),
         }
     }
 
+    /// Whether debrid features are turned on
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Turn debrid features on or off. Turning them off only refuses new
+    /// providers; `clear_providers` tears down the ones already set up.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    /// Drop every configured provider along with its API key
+    pub fn clear_providers(&mut self) {
+        self.torbox = None;
+        self.real_debrid = None;
+        self.api_keys.clear();
+        self.health.reset(DebridProviderType::Torbox);
+        self.health.reset(DebridProviderType::RealDebrid);
+    }
+
+    /// Provider health, shared with the health task
+    pub fn health(&self) -> Arc<HealthTracker> {
+        self.health.clone()
+    }
+
+    /// Providers that are set up, in preference order
+    pub fn configured_providers(&self) -> Vec<DebridProviderType> {
+        [DebridProviderType::Torbox, DebridProviderType::RealDebrid]
+            .into_iter()
+            .filter(|provider_type| self.is_configured(*provider_type))
+            .collect()
+    }
+
+    /// Providers a download added to `first` may move to if `first` fails:
+    /// `first`, then the other configured ones that aren't down, in
+    /// preference order
+    pub fn failover_candidates(&self, first: DebridProviderType) -> Vec<DebridProviderType> {
+        let mut candidates = vec![first];
+        for provider_type in &self.preference_order {
+            if !candidates.contains(provider_type)
+                && self.is_configured(*provider_type)
+                && self.health.state(*provider_type) != HealthState::Down
+            {
+                candidates.push(*provider_type);
+            }
+        }
+        candidates
+    }
+
     /// Set Torbox provider
     pub fn set_torbox(&mut self, provider: Arc<dyn DebridProvider>) {
         self.torbox = Some(provider);
@@ -56,28 +123,58 @@ impl DebridManager {
         self.preference_order = order;
     }
 
-    /// Initialize a provider with API key
-    pub async fn initialize_provider(&mut self, provider_type: DebridProviderType, api_key: String) -> Result<()> {
+    /// Set API base URL overrides (already validated, see `validate_base_url`);
+    /// providers set up with `initialize_provider` are rebuilt to use them
+    pub fn set_base_urls(&mut self, base_urls: HashMap<DebridProviderType, String>) {
+        if base_urls == self.base_urls {
+            return;
+        }
+        self.base_urls = base_urls;
+
+        let api_keys: Vec<_> = self.api_keys.iter().map(|(t, k)| (*t, k.clone())).collect();
+        for (provider_type, api_key) in api_keys {
+            self.install_provider(provider_type, self.build_provider(provider_type, api_key));
+        }
+    }
+
+    /// Build a provider client, honouring any base URL override
+    fn build_provider(&self, provider_type: DebridProviderType, api_key: String) -> Arc<dyn DebridProvider> {
+        let base_url = self.base_urls.get(&provider_type).cloned();
         match provider_type {
-            DebridProviderType::Torbox => {
-                let provider = Arc::new(torbox::TorboxProvider::new(api_key));
-                self.torbox = Some(provider);
-            }
-            DebridProviderType::RealDebrid => {
-                let provider = Arc::new(real_debrid::RealDebridProvider::new(api_key));
-                self.real_debrid = Some(provider);
-            }
+            DebridProviderType::Torbox => Arc::new(torbox::TorboxProvider::with_base_url(
+                api_key,
+                base_url.unwrap_or_else(|| torbox::DEFAULT_BASE_URL.to_string()),
+            )),
+            DebridProviderType::RealDebrid => Arc::new(real_debrid::RealDebridProvider::with_base_url(
+                api_key,
+                base_url.unwrap_or_else(|| real_debrid::DEFAULT_BASE_URL.to_string()),
+            )),
+        }
+    }
+
+    fn install_provider(&mut self, provider_type: DebridProviderType, provider: Arc<dyn DebridProvider>) {
+        // A new client (key or base URL) starts with a clean record
+        self.health.reset(provider_type);
+        match provider_type {
+            DebridProviderType::Torbox => self.torbox = Some(provider),
+            DebridProviderType::RealDebrid => self.real_debrid = Some(provider),
         }
+    }
+
+    /// Initialize a provider with API key; refused while debrid is disabled
+    pub async fn initialize_provider(&mut self, provider_type: DebridProviderType, api_key: String) -> Result<()> {
+        if !self.enabled {
+            return Err(anyhow!(crate::error::Error::FeatureDisabled("debrid".to_string())));
+        }
+        let provider = self.build_provider(provider_type, api_key.clone());
+        self.install_provider(provider_type, provider);
+        self.api_keys.insert(provider_type, api_key);
         Ok(())
     }
 
     /// Validate a provider's credentials
     pub async fn validate_provider(&self, provider_type: DebridProviderType, api_key: &str) -> Result<bool> {
-        let provider: Arc<dyn DebridProvider> = match provider_type {
-            DebridProviderType::Torbox => Arc::new(torbox::TorboxProvider::new(api_key.to_string())),
-            DebridProviderType::RealDebrid => Arc::new(real_debrid::RealDebridProvider::new(api_key.to_string())),
-        };
-        
+        let provider = self.build_provider(provider_type, api_key.to_string());
         provider.validate_credentials().await
     }
 
@@ -89,37 +186,42 @@ impl DebridManager {
         }
     }
 
+    /// A configured provider that isn't down; fails fast otherwise
+    fn usable(&self, provider_type: DebridProviderType) -> Result<&Arc<dyn DebridProvider>> {
+        let provider = self
+            .get_provider(provider_type)
+            .ok_or(ProviderError::NotConfigured(provider_type))?;
+        self.health.check(provider_type)?;
+        Ok(provider)
+    }
+
     /// Check if a provider is configured
     pub fn is_configured(&self, provider_type: DebridProviderType) -> bool {
         self.get_provider(provider_type).is_some()
     }
 
-    /// Check cache on all configured providers
+    /// Check cache on all configured providers that aren't down
     pub async fn check_cache_all(&self, info_hash: &str) -> Result<CacheCheckResult> {
         let mut results = HashMap::new();
 
-        // Check Torbox
-        if let Some(provider) = &self.torbox {
-            match provider.check_instant_availability(info_hash).await {
-                Ok(status) => {
-                    results.insert(DebridProviderType::Torbox, status);
-                }
+        for provider_type in [DebridProviderType::Torbox, DebridProviderType::RealDebrid] {
+            let provider = match self.usable(provider_type) {
+                Ok(provider) => provider,
                 Err(e) => {
-                    tracing::warn!("Torbox cache check failed: {}", e);
-                    results.insert(DebridProviderType::Torbox, CacheStatus::not_cached());
+                    if self.is_configured(provider_type) {
+                        tracing::debug!("Skipping cache check: {}", e);
+                    }
+                    continue;
                 }
-            }
-        }
-
-        // Check Real-Debrid
-        if let Some(provider) = &self.real_debrid {
-            match provider.check_instant_availability(info_hash).await {
+            };
+            let status = self.health.record(provider_type, provider.check_instant_availability(info_hash).await);
+            match status {
                 Ok(status) => {
-                    results.insert(DebridProviderType::RealDebrid, status);
+                    results.insert(provider_type, status);
                 }
                 Err(e) => {
-                    tracing::warn!("Real-Debrid cache check failed: {}", e);
-                    results.insert(DebridProviderType::RealDebrid, CacheStatus::not_cached());
+                    tracing::warn!("{} cache check failed: {}", provider_type.display_name(), e);
+                    results.insert(provider_type, CacheStatus::not_cached());
                 }
             }
         }
@@ -147,17 +249,16 @@ impl DebridManager {
         provider_type: DebridProviderType,
         request: AddTorrentRequest,
     ) -> Result<TorrentId> {
-        let provider = self
-            .get_provider(provider_type)
-            .ok_or_else(|| anyhow!("Provider {} not configured", provider_type.display_name()))?;
+        let provider = self.usable(provider_type)?;
 
-        match request {
+        let added = match request {
             AddTorrentRequest::Magnet(magnet) => provider.add_magnet(&magnet).await,
             AddTorrentRequest::File(path) => {
                 let data = std::fs::read(&path)?;
                 provider.add_torrent_file(&data).await
             }
-        }
+        };
+        self.health.record(provider_type, added)
     }
 
     /// Get download links from a provider
@@ -166,11 +267,9 @@ impl DebridManager {
         provider_type: DebridProviderType,
         torrent_id: &str,
     ) -> Result<Vec<DebridFile>> {
-        let provider = self
-            .get_provider(provider_type)
-            .ok_or_else(|| anyhow!("Provider {} not configured", provider_type.display_name()))?;
+        let provider = self.usable(provider_type)?;
 
-        provider.get_download_links(torrent_id).await
+        self.health.record(provider_type, provider.get_download_links(torrent_id).await)
     }
 
     /// Select files for a torrent
@@ -180,11 +279,20 @@ impl DebridManager {
         torrent_id: &str,
         file_ids: &[usize],
     ) -> Result<()> {
-        let provider = self
-            .get_provider(provider_type)
-            .ok_or_else(|| anyhow!("Provider {} not configured", provider_type.display_name()))?;
+        let provider = self.usable(provider_type)?;
+
+        self.health.record(provider_type, provider.select_files(torrent_id, file_ids.to_vec()).await)
+    }
 
-        provider.select_files(torrent_id, file_ids.to_vec()).await
+    /// List a torrent's files on a provider
+    pub async fn get_torrent_files(
+        &self,
+        provider_type: DebridProviderType,
+        torrent_id: &str,
+    ) -> Result<Vec<RemoteFileInfo>> {
+        let provider = self.usable(provider_type)?;
+
+        self.health.record(provider_type, provider.get_torrent_files(torrent_id).await)
     }
 
     /// Get torrent progress
@@ -193,11 +301,9 @@ impl DebridManager {
         provider_type: DebridProviderType,
         torrent_id: &str,
     ) -> Result<DebridProgress> {
-        let provider = self
-            .get_provider(provider_type)
-            .ok_or_else(|| anyhow!("Provider {} not configured", provider_type.display_name()))?;
+        let provider = self.usable(provider_type)?;
 
-        provider.get_torrent_info(torrent_id).await
+        self.health.record(provider_type, provider.get_torrent_info(torrent_id).await)
     }
 
     /// Delete a torrent from a provider
@@ -206,11 +312,9 @@ impl DebridManager {
         provider_type: DebridProviderType,
         torrent_id: &str,
     ) -> Result<()> {
-        let provider = self
-            .get_provider(provider_type)
-            .ok_or_else(|| anyhow!("Provider {} not configured", provider_type.display_name()))?;
+        let provider = self.usable(provider_type)?;
 
-        provider.delete_torrent(torrent_id).await
+        self.health.record(provider_type, provider.delete_torrent(torrent_id).await)
     }
 
     /// List all torrents from a provider
@@ -218,11 +322,9 @@ impl DebridManager {
         &self,
         provider_type: DebridProviderType,
     ) -> Result<Vec<DebridProgress>> {
-        let provider = self
-            .get_provider(provider_type)
-            .ok_or_else(|| anyhow!("Provider {} not configured", provider_type.display_name()))?;
+        let provider = self.usable(provider_type)?;
 
-        provider.list_torrents().await
+        self.health.record(provider_type, provider.list_torrents().await)
     }
 
     /// Validate all configured providers
@@ -243,8 +345,218 @@ impl DebridManager {
     }
 }
 
+/// Check a base URL override from settings; returns it without a trailing slash
+pub fn validate_base_url(url: &str) -> crate::error::Result<String> {
+    let trimmed = url.trim().trim_end_matches('/');
+    let parsed = reqwest::Url::parse(trimmed)
+        .map_err(|e| crate::error::Error::ValidationError(format!("invalid base URL {:?}: {}", url, e)))?;
+    if !matches!(parsed.scheme(), "http" | "https") || parsed.host().is_none() {
+        return Err(crate::error::Error::ValidationError(format!(
+            "base URL must be an http(s) URL: {:?}",
+            url
+        )));
+    }
+    if parsed.query().is_some() || parsed.fragment().is_some() {
+        return Err(crate::error::Error::ValidationError(format!(
+            "base URL can't have a query or fragment: {:?}",
+            url
+        )));
+    }
+    Ok(trimmed.to_string())
+}
+
 impl Default for DebridManager {
     fn default() -> Self {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_trait::async_trait;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::Mutex;
+
+    /// Provider whose progress calls return what the test set, counting
+    /// the calls that reach it
+    struct FakeProvider {
+        kind: DebridProviderType,
+        reply: Mutex<Option<ProviderError>>,
+        calls: AtomicU32,
+    }
+
+    impl FakeProvider {
+        fn new(kind: DebridProviderType) -> Arc<Self> {
+            Arc::new(Self { kind, reply: Mutex::new(None), calls: AtomicU32::new(0) })
+        }
+
+        fn fail_with(&self, error: Option<ProviderError>) {
+            *self.reply.lock().unwrap() = error;
+        }
+
+        fn answer(&self) -> Result<()> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            match self.reply.lock().unwrap().clone() {
+                Some(error) => Err(error.into()),
+                None => Ok(()),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl DebridProvider for FakeProvider {
+        fn provider_type(&self) -> DebridProviderType {
+            self.kind
+        }
+        async fn validate_credentials(&self) -> Result<bool> {
+            Ok(true)
+        }
+        async fn get_user_info(&self) -> Result<UserInfo> {
+            Err(anyhow!("unsupported"))
+        }
+        async fn check_instant_availability(&self, _info_hash: &str) -> Result<CacheStatus> {
+            self.answer().map(|()| CacheStatus::not_cached())
+        }
+        async fn add_magnet(&self, _magnet_uri: &str) -> Result<TorrentId> {
+            self.answer().map(|()| TorrentId { id: "1".to_string(), uri: None })
+        }
+        async fn add_torrent_file(&self, _torrent_data: &[u8]) -> Result<TorrentId> {
+            Err(anyhow!("unsupported"))
+        }
+        async fn select_files(&self, _torrent_id: &str, _file_ids: Vec<usize>) -> Result<()> {
+            self.answer()
+        }
+        async fn get_torrent_files(&self, _torrent_id: &str) -> Result<Vec<RemoteFileInfo>> {
+            self.answer().map(|()| Vec::new())
+        }
+        async fn get_torrent_info(&self, torrent_id: &str) -> Result<DebridProgress> {
+            self.answer().map(|()| DebridProgress {
+                torrent_id: torrent_id.to_string(),
+                status: DebridStatus::Downloading,
+                progress: 50.0,
+                speed: 0,
+                downloaded: 0,
+                total_size: 0,
+                seeders: None,
+                eta: None,
+                info_hash: None,
+            })
+        }
+        async fn get_download_links(&self, _torrent_id: &str) -> Result<Vec<DebridFile>> {
+            self.answer().map(|()| Vec::new())
+        }
+        async fn unrestrict_link(&self, _link: &str) -> Result<String> {
+            Err(anyhow!("unsupported"))
+        }
+        async fn delete_torrent(&self, _torrent_id: &str) -> Result<()> {
+            self.answer()
+        }
+        async fn list_torrents(&self) -> Result<Vec<DebridProgress>> {
+            self.answer().map(|()| Vec::new())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_down_provider_fails_fast_and_recovers() {
+        let torbox = FakeProvider::new(DebridProviderType::Torbox);
+        let real_debrid = FakeProvider::new(DebridProviderType::RealDebrid);
+        let mut manager = DebridManager::new();
+        manager.set_torbox(torbox.clone());
+        manager.set_real_debrid(real_debrid.clone());
+        let health = manager.health();
+        let mut changes = health.subscribe();
+
+        // Flaky: degraded, still used and still a failover target
+        torbox.fail_with(Some(ProviderError::from_status(
+            DebridProviderType::Torbox,
+            reqwest::StatusCode::BAD_GATEWAY,
+            String::new(),
+        )));
+        for _ in 0..health::DEGRADED_AFTER {
+            assert!(manager.get_progress(DebridProviderType::Torbox, "1").await.is_err());
+        }
+        assert_eq!(changes.try_recv().unwrap().state, HealthState::Degraded);
+        assert_eq!(
+            manager.failover_candidates(DebridProviderType::RealDebrid),
+            [DebridProviderType::RealDebrid, DebridProviderType::Torbox]
+        );
+
+        // Maintenance: down at once
+        let maintenance = ProviderError::Transient {
+            status: reqwest::StatusCode::SERVICE_UNAVAILABLE,
+            body: "Scheduled maintenance".to_string(),
+            retry_after: Some(3600),
+        };
+        torbox.fail_with(Some(maintenance));
+        assert!(manager.list_torrents(DebridProviderType::Torbox).await.is_err());
+        let change = changes.try_recv().unwrap();
+        assert_eq!((change.state, change.maintenance), (HealthState::Down, true));
+        assert!(change.retry_at.unwrap() >= chrono::Utc::now().timestamp() + 3599);
+
+        // Every operation now fails without a request
+        let calls = torbox.calls.load(Ordering::SeqCst);
+        let err = manager.get_progress(DebridProviderType::Torbox, "1").await.unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(ProviderError::Unavailable { maintenance: true, .. })));
+        assert!(manager.add_to_cloud(DebridProviderType::Torbox, AddTorrentRequest::Magnet("magnet:".to_string())).await.is_err());
+        assert!(manager.delete_torrent(DebridProviderType::Torbox, "1").await.is_err());
+        assert_eq!(torbox.calls.load(Ordering::SeqCst), calls);
+
+        // Cache checks and failover leave it out
+        let cache = manager.check_cache_all("ab").await.unwrap();
+        assert_eq!(cache.keys().collect::<Vec<_>>(), [&DebridProviderType::RealDebrid]);
+        assert_eq!(torbox.calls.load(Ordering::SeqCst), calls);
+        assert_eq!(manager.failover_candidates(DebridProviderType::RealDebrid), [DebridProviderType::RealDebrid]);
+
+        // The probe is due at Retry-After; once it gets through, back to normal
+        let now = std::time::Instant::now();
+        assert!(health.probes_due(now).is_empty());
+        let due = now + std::time::Duration::from_secs(3600);
+        assert_eq!(health.probes_due(due), [DebridProviderType::Torbox]);
+        torbox.fail_with(None);
+        health.probed(DebridProviderType::Torbox, &torbox.answer(), due);
+        assert_eq!(changes.try_recv().unwrap().state, HealthState::Healthy);
+        assert!(changes.try_recv().is_err());
+        assert!(manager.get_progress(DebridProviderType::Torbox, "1").await.is_ok());
+        assert_eq!(torbox.calls.load(Ordering::SeqCst), calls + 2);
+    }
+
+    #[test]
+    fn test_validate_base_url() {
+        assert_eq!(validate_base_url(" https://eu.torbox.app/v1/api/ ").unwrap(), "https://eu.torbox.app/v1/api");
+        assert_eq!(validate_base_url("http://127.0.0.1:8080").unwrap(), "http://127.0.0.1:8080");
+
+        assert!(validate_base_url("ftp://example.com").is_err());
+        assert!(validate_base_url("not a url").is_err());
+        assert!(validate_base_url("https://example.com/api?token=x").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_base_url_change_rebuilds_initialized_providers() {
+        let mut manager = DebridManager::new();
+        manager.initialize_provider(DebridProviderType::Torbox, "key".to_string()).await.unwrap();
+        let before = manager.get_provider(DebridProviderType::Torbox).unwrap().clone();
+
+        manager.set_base_urls(HashMap::from([(DebridProviderType::Torbox, "http://127.0.0.1:1".to_string())]));
+        let after = manager.get_provider(DebridProviderType::Torbox).unwrap();
+        assert!(!Arc::ptr_eq(&before, after));
+        assert!(!manager.is_configured(DebridProviderType::RealDebrid));
+    }
+
+    #[tokio::test]
+    async fn test_disabled_manager_refuses_providers() {
+        let mut manager = DebridManager::new();
+        manager.initialize_provider(DebridProviderType::Torbox, "key".to_string()).await.unwrap();
+        assert_eq!(manager.configured_providers(), vec![DebridProviderType::Torbox]);
+
+        manager.set_enabled(false);
+        let err = manager.initialize_provider(DebridProviderType::RealDebrid, "key".to_string()).await.unwrap_err();
+        assert_eq!(err.to_string(), "Feature disabled: debrid");
+        manager.clear_providers();
+        assert!(manager.configured_providers().is_empty());
+
+        // Base URL changes don't bring torn down providers back
+        manager.set_base_urls(HashMap::from([(DebridProviderType::Torbox, "http://127.0.0.1:1".to_string())]));
+        assert!(!manager.is_configured(DebridProviderType::Torbox));
+    }
+}
@@ -47,6 +47,12 @@ pub trait DebridProvider: Send + Sync {
     /// * `file_ids` - List of file IDs to download (or "all" for all files)
     async fn select_files(&self, torrent_id: &str, file_ids: Vec<usize>) -> Result<()>;
 
+    /// List the files inside a torrent, with the indices `select_files` takes
+    /// 
+    /// # Arguments
+    /// * `torrent_id` - The torrent ID from the service
+    async fn get_torrent_files(&self, torrent_id: &str) -> Result<Vec<RemoteFileInfo>>;
+
     /// Get information about a torrent
     /// 
     /// # Arguments
@@ -1,24 +1,31 @@
-use super::{provider::DebridProvider, types::*, request_queue::RequestQueue};
+use super::{dto::real_debrid as dto, error::ProviderError, provider::DebridProvider, types::*, request_queue::RequestQueue};
 use async_trait::async_trait;
 use reqwest::Client;
-use serde::Deserialize;
-use anyhow::{anyhow, Result};
+use anyhow::Result;
 use std::collections::HashMap;
 
-const BASE_URL: &str = "https://api.real-debrid.com/rest/1.0";
+/// Default API base URL (can be overridden per provider in settings)
+pub const DEFAULT_BASE_URL: &str = "https://api.real-debrid.com/rest/1.0";
 const MIN_REQUEST_INTERVAL_MS: u64 = 240; // 250 requests/minute = ~240ms between requests
 
 /// Real-Debrid API provider implementation
 pub struct RealDebridProvider {
     api_key: String,
+    base_url: String,
     client: Client,
     queue: RequestQueue,
 }
 
 impl RealDebridProvider {
     pub fn new(api_key: String) -> Self {
+        Self::with_base_url(api_key, DEFAULT_BASE_URL.to_string())
+    }
+
+    /// Talk to a different API endpoint (regional mirror, proxy, test mock)
+    pub fn with_base_url(api_key: String, base_url: String) -> Self {
         Self {
             api_key,
+            base_url,
             client: Client::builder()
                 .timeout(std::time::Duration::from_secs(30))
                 .build()
@@ -32,7 +39,7 @@ impl RealDebridProvider {
     where
         T: serde::de::DeserializeOwned,
     {
-        let url_base = format!("{}{}", BASE_URL, endpoint);
+        let url_base = format!("{}{}", self.base_url, endpoint);
         let max_retries = 3;
         let mut retry_count = 0;
 
@@ -50,15 +57,8 @@ impl RealDebridProvider {
                         .await?;
 
                     if !response.status().is_success() {
-                        let status = response.status();
-                        let error_text = response.text().await.unwrap_or_default();
-                        
-                        // Retry on server errors (5xx) or too many requests (429) if somehow leaked
-                        if status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS {
-                            return Err(anyhow!("Transient error {}: {}", status, error_text));
-                        }
-                        
-                        return Err(anyhow!("Real-Debrid API error {}: {}", status, error_text));
+                        // Server errors (5xx) and too many requests (429) come back transient and are retried
+                        return Err(ProviderError::from_response(DebridProviderType::RealDebrid, response).await.into());
                     }
 
                     Ok(response.json().await?)
@@ -95,7 +95,7 @@ impl RealDebridProvider {
     where
         T: serde::de::DeserializeOwned,
     {
-        let url_base = format!("{}{}", BASE_URL, endpoint);
+        let url_base = format!("{}{}", self.base_url, endpoint);
         let max_retries = 3;
         let mut retry_count = 0;
 
@@ -118,14 +118,7 @@ impl RealDebridProvider {
                     let response = request.send().await?;
 
                     if !response.status().is_success() {
-                        let status = response.status();
-                        let error_text = response.text().await.unwrap_or_default();
-                        
-                        if status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS {
-                            return Err(anyhow!("Transient error {}: {}", status, error_text));
-                        }
-
-                        return Err(anyhow!("Real-Debrid API error {}: {}", status, error_text));
+                        return Err(ProviderError::from_response(DebridProviderType::RealDebrid, response).await.into());
                     }
 
                     Ok(response.json().await?)
@@ -156,7 +149,7 @@ impl RealDebridProvider {
 
     /// Helper method to execute DELETE requests with rate limiting and retries
     async fn delete(&self, endpoint: &str) -> Result<()> {
-        let url_base = format!("{}{}", BASE_URL, endpoint);
+        let url_base = format!("{}{}", self.base_url, endpoint);
         let max_retries = 3;
         let mut retry_count = 0;
 
@@ -174,14 +167,7 @@ impl RealDebridProvider {
                         .await?;
 
                     if !response.status().is_success() {
-                        let status = response.status();
-                        let error_text = response.text().await.unwrap_or_default();
-                        
-                        if status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS {
-                            return Err(anyhow!("Transient error {}: {}", status, error_text));
-                        }
-
-                        return Err(anyhow!("Real-Debrid API error {}: {}", status, error_text));
+                        return Err(ProviderError::from_response(DebridProviderType::RealDebrid, response).await.into());
                     }
 
                     Ok(())
@@ -211,67 +197,6 @@ impl RealDebridProvider {
     }
 }
 
-// Real-Debrid API response types
-#[derive(Debug, Deserialize)]
-struct RDUser {
-    id: i64,
-    username: String,
-    email: String,
-    points: i64,
-    premium: i64, // Unix timestamp
-}
-
-/// Real-Debrid instant availability response
-/// Format: { "hash": { "rd": [{ "1": { "filename": "...", "filesize": 123 }, "2": {...} }] } }
-#[derive(Debug, Deserialize)]
-struct RDFileVariant {
-    filename: String,
-    filesize: u64,
-}
-
-type RDVariant = HashMap<String, RDFileVariant>; // file_id -> file info
-type RDHostVariants = Vec<RDVariant>; // Array of variants
-type RDHostAvailability = HashMap<String, RDHostVariants>; // "rd" -> variants
-type RDInstantAvailability = HashMap<String, RDHostAvailability>; // hash -> host availability
-
-#[derive(Debug, Deserialize)]
-struct RDAddMagnetResponse {
-    id: String,
-    uri: String,
-}
-
-#[derive(Debug, Deserialize)]
-struct RDTorrentInfo {
-    id: String,
-    filename: String,
-    hash: String,
-    bytes: u64,
-    host: String,
-    split: u64,
-    progress: f64,
-    status: String,
-    added: String,
-    files: Vec<RDFile>,
-    links: Vec<String>,
-}
-
-#[derive(Debug, Deserialize)]
-struct RDFile {
-    id: u64,
-    path: String,
-    bytes: u64,
-    selected: u64, // 0 or 1
-}
-
-#[derive(Debug, Deserialize)]
-struct RDUnrestrictResponse {
-    id: String,
-    filename: String,
-    filesize: u64,
-    link: String,
-    download: String,
-}
-
 #[async_trait]
 impl DebridProvider for RealDebridProvider {
     fn provider_type(&self) -> DebridProviderType {
@@ -279,7 +204,7 @@ impl DebridProvider for RealDebridProvider {
     }
 
     async fn validate_credentials(&self) -> Result<bool> {
-        match self.get::<RDUser>("/user").await {
+        match self.get::<dto::User>("/user").await {
             Ok(_) => Ok(true),
             Err(_) => Ok(false),
         }
@@ -289,7 +214,7 @@ impl DebridProvider for RealDebridProvider {
         // Real-Debrid instant availability endpoint
         let endpoint = format!("/torrents/instantAvailability/{}", info_hash.to_lowercase());
         
-        let response: RDInstantAvailability = self.get(&endpoint).await?;
+        let response: dto::InstantAvailability = self.get(&endpoint).await?;
         
         // Check if our hash exists in the response
         let hash_lower = info_hash.to_lowercase();
@@ -330,7 +255,7 @@ impl DebridProvider for RealDebridProvider {
         let mut form = HashMap::new();
         form.insert("magnet".to_string(), magnet.to_string());
 
-        let response: RDAddMagnetResponse = self.post("/torrents/addMagnet", Some(form)).await?;
+        let response: dto::AddTorrent = self.post("/torrents/addMagnet", Some(form)).await?;
         Ok(TorrentId {
             id: response.id,
             uri: Some(response.uri),
@@ -338,7 +263,7 @@ impl DebridProvider for RealDebridProvider {
     }
 
     async fn add_torrent_file(&self, torrent_data: &[u8]) -> Result<TorrentId> {
-        let url = format!("{}/torrents/addTorrent", BASE_URL);
+        let url = format!("{}/torrents/addTorrent", self.base_url);
         let api_key = self.api_key.clone();
         let client = self.client.clone();
         let data = torrent_data.to_vec();
@@ -361,12 +286,10 @@ impl DebridProvider for RealDebridProvider {
                     .await?;
 
                 if !response.status().is_success() {
-                    let status = response.status();
-                    let error_text = response.text().await.unwrap_or_default();
-                    return Err(anyhow!("Real-Debrid API error {}: {}", status, error_text));
+                    return Err(ProviderError::from_response(DebridProviderType::RealDebrid, response).await.into());
                 }
 
-                let result: RDAddMagnetResponse = response.json().await?;
+                let result: dto::AddTorrent = response.json().await?;
                 Ok(TorrentId {
                     id: result.id,
                     uri: Some(result.uri),
@@ -394,40 +317,21 @@ impl DebridProvider for RealDebridProvider {
         Ok(())
     }
 
-    async fn get_torrent_info(&self, torrent_id: &str) -> Result<DebridProgress> {
+    async fn get_torrent_files(&self, torrent_id: &str) -> Result<Vec<RemoteFileInfo>> {
         let endpoint = format!("/torrents/info/{}", torrent_id);
-        let info: RDTorrentInfo = self.get(&endpoint).await?;
-
-        // Map Real-Debrid status to our DebridStatus enum
-        let status = match info.status.as_str() {
-            "waiting_files_selection" => DebridStatus::WaitingFilesSelection,
-            "queued" => DebridStatus::Queued,
-            "downloading" => DebridStatus::Downloading,
-            "downloaded" => DebridStatus::Downloaded,
-            "error" => DebridStatus::Error,
-            "virus" => DebridStatus::Error, // Treat virus as error
-            "dead" => DebridStatus::Dead,
-            "magnet_conversion" => DebridStatus::MagnetConversion,
-            "compressing" => DebridStatus::Compressing,
-            "uploading" => DebridStatus::Uploading,
-            _ => DebridStatus::Error, // Unknown states treated as errors
-        };
+        let info: dto::TorrentInfo = self.get(&endpoint).await?;
+        Ok(dto::remote_files(info.files))
+    }
 
-        Ok(DebridProgress {
-            torrent_id: info.id,
-            status,
-            progress: info.progress as f32,
-            speed: 0, // Real-Debrid doesn't provide this in the response
-            downloaded: (info.bytes as f64 * (info.progress / 100.0)) as u64,
-            total_size: info.bytes,
-            seeders: None,
-            eta: None,
-        })
+    async fn get_torrent_info(&self, torrent_id: &str) -> Result<DebridProgress> {
+        let endpoint = format!("/torrents/info/{}", torrent_id);
+        let info: dto::TorrentInfo = self.get(&endpoint).await?;
+        Ok(dto::progress(info))
     }
 
     async fn get_download_links(&self, torrent_id: &str) -> Result<Vec<DebridFile>> {
         let endpoint = format!("/torrents/info/{}", torrent_id);
-        let info: RDTorrentInfo = self.get(&endpoint).await?;
+        let info: dto::TorrentInfo = self.get(&endpoint).await?;
 
         let mut debrid_files = Vec::new();
 
@@ -436,17 +340,8 @@ impl DebridProvider for RealDebridProvider {
             let mut form = HashMap::new();
             form.insert("link".to_string(), link.to_string());
 
-            match self.post::<RDUnrestrictResponse>("/unrestrict/link", Some(form)).await {
-                Ok(unrestrict) => {
-                    debrid_files.push(DebridFile {
-                        id: idx.to_string(),
-                        name: unrestrict.filename,
-                        size: unrestrict.filesize,
-                        download_link: Some(unrestrict.download.clone()),
-                        stream_link: Some(unrestrict.download), // Real-Debrid uses same link for both
-                        mime_type: None, // Real-Debrid doesn't provide MIME type directly
-                    });
-                }
+            match self.post::<dto::Unrestrict>("/unrestrict/link", Some(form)).await {
+                Ok(unrestrict) => debrid_files.push(dto::debrid_file(idx, unrestrict)),
                 Err(e) => {
                     eprintln!("Failed to unrestrict link {}: {}", link, e);
                 }
@@ -460,7 +355,7 @@ impl DebridProvider for RealDebridProvider {
         let mut form = HashMap::new();
         form.insert("link".to_string(), link.to_string());
 
-        let response: RDUnrestrictResponse = self.post("/unrestrict/link", Some(form)).await?;
+        let response: dto::Unrestrict = self.post("/unrestrict/link", Some(form)).await?;
         Ok(response.download)
     }
 
@@ -471,42 +366,12 @@ impl DebridProvider for RealDebridProvider {
 
     async fn list_torrents(&self) -> Result<Vec<DebridProgress>> {
         // Get list of all torrents (limited to 100 per request by default)
-        let torrents: Vec<RDTorrentInfo> = self.get("/torrents").await?;
-
-        let mut progress_list = Vec::new();
-        for torrent in torrents {
-            // Map Real-Debrid status to our DebridStatus enum
-            let status = match torrent.status.as_str() {
-                "waiting_files_selection" => DebridStatus::WaitingFilesSelection,
-                "queued" => DebridStatus::Queued,
-                "downloading" => DebridStatus::Downloading,
-                "downloaded" => DebridStatus::Downloaded,
-                "error" => DebridStatus::Error,
-                "virus" => DebridStatus::Error,
-                "dead" => DebridStatus::Dead,
-                "magnet_conversion" => DebridStatus::MagnetConversion,
-                "compressing" => DebridStatus::Compressing,
-                "uploading" => DebridStatus::Uploading,
-                _ => DebridStatus::Error,
-            };
-
-            progress_list.push(DebridProgress {
-                torrent_id: torrent.id,
-                status,
-                progress: torrent.progress as f32,
-                speed: 0, // Not provided in list view
-                downloaded: (torrent.bytes as f64 * (torrent.progress / 100.0)) as u64,
-                total_size: torrent.bytes,
-                seeders: None,
-                eta: None,
-            });
-        }
-
-        Ok(progress_list)
+        let torrents: Vec<dto::TorrentInfo> = self.get("/torrents").await?;
+        Ok(torrents.into_iter().map(dto::progress).collect())
     }
 
     async fn get_user_info(&self) -> Result<UserInfo> {
-        let user: RDUser = self.get("/user").await?;
+        let user: dto::User = self.get("/user").await?;
 
         Ok(UserInfo {
             username: user.username,
@@ -546,7 +411,7 @@ mod tests {
             }
         }"#;
 
-        let parsed: RDInstantAvailability = serde_json::from_str(json).unwrap();
+        let parsed: dto::InstantAvailability = serde_json::from_str(json).unwrap();
         assert!(parsed.contains_key("abcdef1234567890"));
         
         let host_avail = &parsed["abcdef1234567890"];
@@ -607,14 +472,31 @@ mod tests {
             ]
         }"#;
 
-        let parsed: RDTorrentInfo = serde_json::from_str(json).unwrap();
+        let parsed: dto::TorrentInfo = serde_json::from_str(json).unwrap();
         assert_eq!(parsed.id, "ABC123");
         assert_eq!(parsed.progress, 75.0);
-        assert_eq!(parsed.status, "downloading");
+        assert_eq!(parsed.status, dto::TorrentStatus::Downloading);
         assert_eq!(parsed.bytes, 1073741824);
         assert_eq!(parsed.links.len(), 1);
     }
 
+    #[test]
+    fn test_remote_files_mapping() {
+        let json = r#"[
+            {"id": 1, "path": "/Show/S01E01.mkv", "bytes": 1000, "selected": 1},
+            {"id": 2, "path": "/Show/sample.mkv", "bytes": 10, "selected": 0}
+        ]"#;
+        let files: Vec<dto::File> = serde_json::from_str(json).unwrap();
+
+        assert_eq!(
+            dto::remote_files(files),
+            vec![
+                RemoteFileInfo { index: 0, path: "Show/S01E01.mkv".to_string(), size: 1000, selected: true },
+                RemoteFileInfo { index: 1, path: "Show/sample.mkv".to_string(), size: 10, selected: false },
+            ]
+        );
+    }
+
     #[test]
     fn test_user_info_parsing() {
         let json = r#"{
@@ -625,7 +507,7 @@ mod tests {
             "premium": 1735689600
         }"#;
 
-        let parsed: RDUser = serde_json::from_str(json).unwrap();
+        let parsed: dto::User = serde_json::from_str(json).unwrap();
         assert_eq!(parsed.username, "testuser");
         assert_eq!(parsed.email, "test@example.com");
         assert_eq!(parsed.points, 10000);
@@ -639,7 +521,7 @@ mod tests {
             "uri": "https://real-debrid.com/torrents/info/TORRENT123"
         }"#;
 
-        let parsed: RDAddMagnetResponse = serde_json::from_str(json).unwrap();
+        let parsed: dto::AddTorrent = serde_json::from_str(json).unwrap();
         assert_eq!(parsed.id, "TORRENT123");
         assert_eq!(parsed.uri, "https://real-debrid.com/torrents/info/TORRENT123");
     }
@@ -654,9 +536,40 @@ mod tests {
             "download": "https://real-debrid.com/download/xyz"
         }"#;
 
-        let parsed: RDUnrestrictResponse = serde_json::from_str(json).unwrap();
+        let parsed: dto::Unrestrict = serde_json::from_str(json).unwrap();
         assert_eq!(parsed.filename, "movie.mkv");
         assert_eq!(parsed.filesize, 1073741824);
         assert_eq!(parsed.download, "https://real-debrid.com/download/xyz");
     }
+
+    #[tokio::test]
+    async fn test_base_url_override() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0u8; 1024];
+            while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+                let n = socket.read(&mut buf).await.unwrap();
+                request.extend_from_slice(&buf[..n]);
+            }
+            let body = br#"{"id":1,"username":"u","email":"u@example.com","points":0,"premium":0}"#;
+            let head = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                body.len()
+            );
+            socket.write_all(head.as_bytes()).await.unwrap();
+            socket.write_all(body).await.unwrap();
+            String::from_utf8(request).unwrap()
+        });
+
+        let provider = RealDebridProvider::with_base_url("key".to_string(), format!("http://{}/mock", addr));
+        assert!(provider.validate_credentials().await.unwrap());
+
+        let request = server.await.unwrap();
+        assert!(request.starts_with("GET /mock/user "), "{}", request);
+    }
 }
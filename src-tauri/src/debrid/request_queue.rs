@@ -72,7 +72,16 @@ impl RequestQueue {
         
         // Execute the request
         debug!("[{}] Executing queued request", self.provider_name);
-        request_fn.await
+        request_fn.await.map_err(|e| {
+            let Some(request_error) = e.downcast_ref::<reqwest::Error>() else {
+                return e;
+            };
+            crate::clock::on_request_error(&crate::clock::error_text(request_error), request_error.url());
+            match crate::clock::network_error(format!("{:#}", e)) {
+                skew @ crate::error::Error::ClockSkew(_) => anyhow::Error::new(skew),
+                _ => e,
+            }
+        })
     }
 
     /// Execute multiple requests with proper rate limiting
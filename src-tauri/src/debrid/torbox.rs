@@ -1,23 +1,30 @@
-use super::{provider::DebridProvider, types::*, request_queue::RequestQueue};
+use super::{dto::torbox as dto, error::ProviderError, provider::DebridProvider, types::*, request_queue::RequestQueue};
 use async_trait::async_trait;
 use reqwest::Client;
-use serde::Deserialize;
 use anyhow::{anyhow, Result};
 
-const BASE_URL: &str = "https://api.torbox.app/v1/api";
+/// Default API base URL (can be overridden per provider in settings)
+pub const DEFAULT_BASE_URL: &str = "https://api.torbox.app/v1/api";
 const MIN_REQUEST_INTERVAL_MS: u64 = 200; // Conservative rate limit
 
 /// Torbox API provider implementation
 pub struct TorboxProvider {
     api_key: String,
+    base_url: String,
     client: Client,
     queue: RequestQueue,
 }
 
 impl TorboxProvider {
     pub fn new(api_key: String) -> Self {
+        Self::with_base_url(api_key, DEFAULT_BASE_URL.to_string())
+    }
+
+    /// Talk to a different API endpoint (regional mirror, proxy, test mock)
+    pub fn with_base_url(api_key: String, base_url: String) -> Self {
         Self {
             api_key,
+            base_url,
             client: Client::builder()
                 .timeout(std::time::Duration::from_secs(30))
                 .build()
@@ -31,7 +38,7 @@ impl TorboxProvider {
     where
         T: serde::de::DeserializeOwned,
     {
-        let url_base = format!("{}{}", BASE_URL, endpoint);
+        let url_base = format!("{}{}", self.base_url, endpoint);
         // Convert params to owned Vec for cloning
         let query_params_base = params.map(|p| p.to_vec());
         
@@ -57,14 +64,7 @@ impl TorboxProvider {
                     let response = request.send().await?;
 
                     if !response.status().is_success() {
-                        let status = response.status();
-                        let error_text = response.text().await.unwrap_or_default();
-                        
-                        if status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS {
-                            return Err(anyhow!("Transient error {}: {}", status, error_text));
-                        }
-
-                        return Err(anyhow!("Torbox API error {}: {}", status, error_text));
+                        return Err(ProviderError::from_response(DebridProviderType::Torbox, response).await.into());
                     }
 
                     Ok(response.json().await?)
@@ -98,7 +98,7 @@ impl TorboxProvider {
     where
         T: serde::de::DeserializeOwned,
     {
-        let url_base = format!("{}{}", BASE_URL, endpoint);
+        let url_base = format!("{}{}", self.base_url, endpoint);
         let max_retries = 3;
         let mut retry_count = 0;
 
@@ -121,14 +121,7 @@ impl TorboxProvider {
                     let response = request.send().await?;
 
                     if !response.status().is_success() {
-                        let status = response.status();
-                        let error_text = response.text().await.unwrap_or_default();
-                        
-                        if status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS {
-                            return Err(anyhow!("Transient error {}: {}", status, error_text));
-                        }
-
-                        return Err(anyhow!("Torbox API error {}: {}", status, error_text));
+                        return Err(ProviderError::from_response(DebridProviderType::Torbox, response).await.into());
                     }
 
                     Ok(response.json().await?)
@@ -159,7 +152,7 @@ impl TorboxProvider {
 
     /// Helper method to execute DELETE requests with rate limiting and retries
     async fn delete(&self, endpoint: &str) -> Result<()> {
-        let url_base = format!("{}{}", BASE_URL, endpoint);
+        let url_base = format!("{}{}", self.base_url, endpoint);
         let max_retries = 3;
         let mut retry_count = 0;
 
@@ -177,14 +170,7 @@ impl TorboxProvider {
                         .await?;
 
                     if !response.status().is_success() {
-                        let status = response.status();
-                        let error_text = response.text().await.unwrap_or_default();
-                        
-                        if status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS {
-                            return Err(anyhow!("Transient error {}: {}", status, error_text));
-                        }
-
-                        return Err(anyhow!("Torbox API error {}: {}", status, error_text));
+                        return Err(ProviderError::from_response(DebridProviderType::Torbox, response).await.into());
                     }
 
                     Ok(())
@@ -214,37 +200,6 @@ impl TorboxProvider {
     }
 }
 
-// Torbox API response types
-#[derive(Debug, Deserialize)]
-struct TorboxResponse<T> {
-    data: Option<T>,
-    error: Option<String>,
-    detail: Option<String>,
-}
-
-#[derive(Debug, Deserialize)]
-struct TorboxDownload {
-    id: i64,
-    name: String,
-    hash: Option<String>,
-    #[serde(default)]
-    cached: bool,
-    #[serde(default)]
-    files: Vec<TorboxFile>,
-}
-
-#[derive(Debug, Deserialize)]
-struct TorboxFile {
-    id: i64,
-    #[serde(default)]
-    short_name: String,
-    #[serde(default)]
-    name: String,
-    size: u64,
-    #[serde(default)]
-    mimetype: String,
-}
-
 #[async_trait]
 impl DebridProvider for TorboxProvider {
     fn provider_type(&self) -> DebridProviderType {
@@ -265,7 +220,7 @@ impl DebridProvider for TorboxProvider {
                 tracing::warn!("Torbox /user/me failed ({}), trying /torrents/mylist", e);
                 
                 // Fallback to torrents list
-                match self.get::<TorboxResponse<Vec<TorboxDownload>>>(
+                match self.get::<dto::Response<Vec<dto::Download>>>(
                     "/torrents/mylist",
                     Some(&[("limit", "1"), ("offset", "0")]),
                 ).await {
@@ -287,12 +242,12 @@ impl DebridProvider for TorboxProvider {
     async fn check_instant_availability(&self, info_hash: &str) -> Result<CacheStatus> {
         // Torbox doesn't have a direct instant availability check API
         // We check if the hash exists in the user's downloads
-        let response: TorboxResponse<Vec<TorboxDownload>> = self.get(
+        let response: dto::Response<Vec<dto::Download>> = self.get(
             "/torrents/mylist",
             Some(&[("limit", "1000"), ("offset", "0"), ("bypass_cache", "true")]),
         ).await?;
 
-        if let Some(downloads) = response.data {
+        if let Some(downloads) = response.into_data()? {
             for download in downloads {
                 if let Some(hash) = &download.hash {
                     if hash.eq_ignore_ascii_case(info_hash) {
@@ -336,40 +291,39 @@ impl DebridProvider for TorboxProvider {
         Ok(())
     }
 
+    async fn get_torrent_files(&self, torrent_id: &str) -> Result<Vec<RemoteFileInfo>> {
+        let response: dto::Response<Vec<dto::Download>> = self.get(
+            "/torrents/mylist",
+            Some(&[("limit", "1000"), ("offset", "0"), ("bypass_cache", "true")]),
+        ).await?;
+
+        let id: i64 = torrent_id.parse()
+            .map_err(|_| anyhow!("Invalid torrent ID format"))?;
+
+        response
+            .into_data()?
+            .unwrap_or_default()
+            .into_iter()
+            .find(|download| download.id == id)
+            .map(|download| dto::remote_files(download.files))
+            .ok_or_else(|| anyhow!("Torrent not found"))
+    }
+
     async fn get_torrent_info(&self, torrent_id: &str) -> Result<DebridProgress> {
         // Get torrent info from the list
-        let response: TorboxResponse<Vec<TorboxDownload>> = self.get(
+        let response: dto::Response<Vec<dto::Download>> = self.get(
             "/torrents/mylist",
             Some(&[("limit", "1000"), ("offset", "0"), ("bypass_cache", "true")]),
         ).await?;
 
-        if let Some(downloads) = response.data {
+        if let Some(downloads) = response.into_data()? {
             // Parse torrent_id as i64
             let id: i64 = torrent_id.parse()
                 .map_err(|_| anyhow!("Invalid torrent ID format"))?;
 
             for download in downloads {
                 if download.id == id {
-                    // Torbox doesn't provide detailed progress info
-                    // If cached, assume it's downloaded
-                    let status = if download.cached {
-                        DebridStatus::Downloaded
-                    } else {
-                        DebridStatus::Downloading
-                    };
-
-                    let total_size: u64 = download.files.iter().map(|f| f.size).sum();
-
-                    return Ok(DebridProgress {
-                        torrent_id: download.id.to_string(),
-                        status,
-                        progress: if download.cached { 100.0 } else { 0.0 },
-                        speed: 0,
-                        downloaded: if download.cached { total_size } else { 0 },
-                        total_size,
-                        seeders: None,
-                        eta: None,
-                    });
+                    return Ok(dto::progress(download));
                 }
             }
         }
@@ -379,44 +333,28 @@ impl DebridProvider for TorboxProvider {
 
     async fn get_download_links(&self, torrent_id: &str) -> Result<Vec<DebridFile>> {
         // Get download from list
-        let response: TorboxResponse<Vec<TorboxDownload>> = self.get(
+        let response: dto::Response<Vec<dto::Download>> = self.get(
             "/torrents/mylist",
             Some(&[("limit", "1000"), ("offset", "0")]),
         ).await?;
 
-        if let Some(downloads) = response.data {
+        if let Some(downloads) = response.into_data()? {
             let id: i64 = torrent_id.parse()
                 .map_err(|_| anyhow!("Invalid torrent ID format"))?;
 
             for download in downloads {
                 if download.id == id {
-                    let mut files = Vec::new();
-
-                    for file in download.files {
-                        // Construct download URL
-                        let download_url = format!(
-                            "{}/torrents/requestdl?token={}&torrent_id={}&file_id={}&redirect=true",
-                            BASE_URL, self.api_key, download.id, file.id
-                        );
-
-                        files.push(DebridFile {
-                            id: file.id.to_string(),
-                            name: if !file.short_name.is_empty() {
-                                file.short_name
-                            } else {
-                                file.name
-                            },
-                            size: file.size,
-                            download_link: Some(download_url.clone()),
-                            stream_link: Some(download_url), // Same URL for streaming
-                            mime_type: if !file.mimetype.is_empty() {
-                                Some(file.mimetype)
-                            } else {
-                                None
-                            },
-                        });
-                    }
-
+                    let files = download
+                        .files
+                        .into_iter()
+                        .map(|file| {
+                            let download_url = format!(
+                                "{}/torrents/requestdl?token={}&torrent_id={}&file_id={}&redirect=true",
+                                self.base_url, self.api_key, download.id, file.id
+                            );
+                            dto::debrid_file(file, download_url)
+                        })
+                        .collect();
                     return Ok(files);
                 }
             }
@@ -436,37 +374,13 @@ impl DebridProvider for TorboxProvider {
     }
 
     async fn list_torrents(&self) -> Result<Vec<DebridProgress>> {
-        let response: TorboxResponse<Vec<TorboxDownload>> = self.get(
+        let response: dto::Response<Vec<dto::Download>> = self.get(
             "/torrents/mylist",
             Some(&[("limit", "1000"), ("offset", "0"), ("bypass_cache", "true")]),
         ).await?;
 
-        let mut progress_list = Vec::new();
-
-        if let Some(downloads) = response.data {
-            for download in downloads {
-                let status = if download.cached {
-                    DebridStatus::Downloaded
-                } else {
-                    DebridStatus::Downloading
-                };
-
-                let total_size: u64 = download.files.iter().map(|f| f.size).sum();
-
-                progress_list.push(DebridProgress {
-                    torrent_id: download.id.to_string(),
-                    status,
-                    progress: if download.cached { 100.0 } else { 0.0 },
-                    speed: 0,
-                    downloaded: if download.cached { total_size } else { 0 },
-                    total_size,
-                    seeders: None,
-                    eta: None,
-                });
-            }
-        }
-
-        Ok(progress_list)
+        let downloads = response.into_data()?.unwrap_or_default();
+        Ok(downloads.into_iter().map(dto::progress).collect())
     }
 
     async fn get_user_info(&self) -> Result<UserInfo> {
@@ -510,7 +424,7 @@ mod tests {
             }]
         }"#;
 
-        let parsed: TorboxResponse<Vec<TorboxDownload>> = serde_json::from_str(json).unwrap();
+        let parsed: dto::Response<Vec<dto::Download>> = serde_json::from_str(json).unwrap();
         assert!(parsed.data.is_some());
         
         let downloads = parsed.data.unwrap();
@@ -521,4 +435,21 @@ mod tests {
         assert_eq!(downloads[0].files.len(), 1);
         assert_eq!(downloads[0].files[0].short_name, "video.mkv");
     }
+
+    #[test]
+    fn test_remote_files_mapping() {
+        let json = r#"[
+            {"id": 456, "short_name": "video.mkv", "name": "folder/video.mkv", "size": 1000},
+            {"id": 789, "short_name": "notes.txt", "size": 10}
+        ]"#;
+        let files: Vec<dto::File> = serde_json::from_str(json).unwrap();
+
+        assert_eq!(
+            dto::remote_files(files),
+            vec![
+                RemoteFileInfo { index: 0, path: "folder/video.mkv".to_string(), size: 1000, selected: true },
+                RemoteFileInfo { index: 1, path: "notes.txt".to_string(), size: 10, selected: true },
+            ]
+        );
+    }
 }
@@ -1,5 +1,6 @@
 // Debrid service types and shared structures
 
+use crate::ids::DebridTorrentId;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
@@ -76,6 +77,19 @@ pub struct DebridFile {
     pub mime_type: Option<String>,
 }
 
+/// A file inside a torrent on the provider, for the file selection dialog
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RemoteFileInfo {
+    /// 0-based index as accepted by `select_files`
+    pub index: usize,
+    /// Path inside the torrent
+    pub path: String,
+    pub size: u64,
+    /// Whether the provider currently has it selected
+    pub selected: bool,
+}
+
 /// Debrid download progress
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -88,6 +102,9 @@ pub struct DebridProgress {
     pub total_size: u64,
     pub seeders: Option<u32>,
     pub eta: Option<u64>, // seconds
+    /// Info hash as the provider reports it (lowercase hex), if it does
+    #[serde(default)]
+    pub info_hash: Option<String>,
 }
 
 /// Debrid torrent status
@@ -112,6 +129,8 @@ pub enum DebridStatus {
     Dead,
     /// Magnet conversion in progress
     MagnetConversion,
+    /// A status the provider reports that none of these covers, as sent
+    Unknown(String),
 }
 
 impl DebridStatus {
@@ -170,12 +189,12 @@ pub enum DownloadSource {
     /// Pure cloud download
     Debrid {
         provider: DebridProviderType,
-        torrent_id: String,
+        torrent_id: DebridTorrentId,
     },
     /// Hybrid: some files from cloud, some from P2P
     Hybrid {
         debrid_provider: DebridProviderType,
-        debrid_torrent_id: String,
+        debrid_torrent_id: DebridTorrentId,
         debrid_file_ids: Vec<usize>,
         p2p_file_ids: Vec<usize>,
     },
@@ -194,6 +213,17 @@ impl DownloadSource {
         matches!(self, DownloadSource::Hybrid { .. })
     }
 
+    /// Kind of download, for messages ("P2P", "Real-Debrid cloud", ...)
+    pub fn describe(&self) -> String {
+        match self {
+            DownloadSource::P2P => "P2P".to_string(),
+            DownloadSource::Debrid { provider, .. } => format!("{} cloud", provider.display_name()),
+            DownloadSource::Hybrid { debrid_provider, .. } => {
+                format!("hybrid P2P and {} cloud", debrid_provider.display_name())
+            }
+        }
+    }
+
     pub fn get_provider(&self) -> Option<DebridProviderType> {
         match self {
             DownloadSource::Debrid { provider, .. } => Some(*provider),
@@ -203,6 +233,16 @@ impl DownloadSource {
             DownloadSource::P2P => None,
         }
     }
+
+    pub fn get_debrid_torrent_id(&self) -> Option<&DebridTorrentId> {
+        match self {
+            DownloadSource::Debrid { torrent_id, .. } => Some(torrent_id),
+            DownloadSource::Hybrid {
+                debrid_torrent_id, ..
+            } => Some(debrid_torrent_id),
+            DownloadSource::P2P => None,
+        }
+    }
 }
 
 /// Cache check result for all providers
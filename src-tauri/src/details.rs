@@ -0,0 +1,261 @@
+//! Live updates for open torrent details views
+//!
+//! Instead of the UI polling peers, trackers and pieces for the torrent whose
+//! details pane is open, it subscribes to that torrent and receives one
+//! consolidated `torrent-details-update` event per second until it
+//! unsubscribes (or the torrent is removed).
+
+#[cfg(feature = "app")]
+use crate::events::{emit_event, Event};
+use crate::peer::disconnect::DisconnectReason;
+use crate::peer::{PeerInfo, TrafficStats};
+use crate::piece::PiecesInfo;
+use crate::state::{AppState, TorrentInfo};
+use crate::tracker::TrackerInfo;
+use serde::Serialize;
+use std::collections::{BTreeMap, VecDeque};
+use std::future::Future;
+use std::sync::Mutex;
+#[cfg(feature = "app")]
+use tauri::Manager;
+use tokio::time::{self, Duration};
+
+/// Details views that can be live at once; the oldest is dropped beyond this
+pub const MAX_DETAIL_SUBSCRIPTIONS: usize = 4;
+
+/// How often subscribed torrents are published
+const PUBLISH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Longest one torrent may take to collect before it skips a round
+const COLLECT_TIMEOUT: Duration = Duration::from_millis(800);
+
+/// Disconnections counted in `TorrentDetailsUpdate::disconnects`, in seconds
+const DISCONNECT_WINDOW_SECS: i64 = 3600;
+
+/// Payload of the `torrent-details-update` event
+#[derive(Debug, Clone, Serialize)]
+pub struct TorrentDetailsUpdate {
+    pub torrent_id: String,
+    /// Live stats (same as `get_torrent_details`)
+    pub stats: TorrentInfo,
+    /// Empty while the engine isn't running
+    pub peers: Vec<PeerInfo>,
+    pub trackers: Vec<TrackerInfo>,
+    /// None while the engine isn't running or the metadata is pending
+    pub pieces: Option<PiecesInfo>,
+    /// Wire traffic and wasted payload, including earlier runs; None without an engine
+    pub traffic: Option<TrafficStats>,
+    /// Peer disconnections in the last hour, per reason
+    pub disconnects: BTreeMap<DisconnectReason, usize>,
+}
+
+/// Torrents with an open details view, oldest subscription first
+#[derive(Debug, Default)]
+pub struct DetailSubscriptions {
+    active: Mutex<VecDeque<String>>,
+}
+
+impl DetailSubscriptions {
+    /// Subscribe to a torrent (again: refreshes its age). Returns the
+    /// subscription evicted to stay within the cap, if any.
+    pub fn subscribe(&self, torrent_id: &str) -> Option<String> {
+        let mut active = self.active.lock().unwrap();
+        active.retain(|id| id != torrent_id);
+        active.push_back(torrent_id.to_string());
+        if active.len() > MAX_DETAIL_SUBSCRIPTIONS {
+            active.pop_front()
+        } else {
+            None
+        }
+    }
+
+    /// Returns false if the torrent wasn't subscribed
+    pub fn unsubscribe(&self, torrent_id: &str) -> bool {
+        let mut active = self.active.lock().unwrap();
+        let before = active.len();
+        active.retain(|id| id != torrent_id);
+        active.len() != before
+    }
+
+    pub fn is_subscribed(&self, torrent_id: &str) -> bool {
+        self.active.lock().unwrap().iter().any(|id| id == torrent_id)
+    }
+
+    /// Current subscriptions, oldest first
+    pub fn snapshot(&self) -> Vec<String> {
+        self.active.lock().unwrap().iter().cloned().collect()
+    }
+}
+
+/// One publishing round: collect every subscribed torrent and emit it.
+/// Torrents that `collect` no longer finds are unsubscribed.
+pub async fn publish_round<C, F>(
+    subscriptions: &DetailSubscriptions,
+    mut collect: C,
+    mut emit: impl FnMut(TorrentDetailsUpdate),
+) where
+    C: FnMut(String) -> F,
+    F: Future<Output = Option<TorrentDetailsUpdate>>,
+{
+    for torrent_id in subscriptions.snapshot() {
+        let update = match time::timeout(COLLECT_TIMEOUT, collect(torrent_id.clone())).await {
+            Ok(update) => update,
+            Err(_) => {
+                tracing::debug!("Details for {} took too long, skipping this round", torrent_id);
+                continue;
+            }
+        };
+        match update {
+            // Unsubscribed while we were collecting
+            Some(update) if subscriptions.is_subscribed(&torrent_id) => emit(update),
+            Some(_) => {}
+            None => {
+                subscriptions.unsubscribe(&torrent_id);
+            }
+        }
+    }
+}
+
+/// Gather a torrent's details with the same getters the polling commands use
+async fn collect(state: &AppState, torrent_id: String) -> Option<TorrentDetailsUpdate> {
+    let stats = state.torrents.read().await.get(&torrent_id).cloned()?;
+
+    let engine = state.engines.read().await.get(&torrent_id).cloned();
+    let (peers, trackers, pieces, traffic) = match engine {
+        Some(engine) => {
+            let engine = engine.read().await;
+            let pieces = engine.pieces_info(crate::piece::buckets::DEFAULT_MAX_BUCKETS).await.ready();
+            let traffic = engine.get_stats().await.traffic;
+            (engine.get_peer_list().await, engine.get_tracker_list().await, pieces, Some(traffic))
+        }
+        None => (Vec::new(), Vec::new(), None, None),
+    };
+
+    let since = chrono::Utc::now().timestamp() - DISCONNECT_WINDOW_SECS;
+    let disconnects = state.disconnect_histories.get(&torrent_id).counts_since(since);
+
+    Some(TorrentDetailsUpdate { torrent_id, stats, peers, trackers, pieces, traffic, disconnects })
+}
+
+/// Publish subscribed torrents until the app exits
+#[cfg(feature = "app")]
+pub async fn start_details_task(app_handle: tauri::AppHandle) {
+    let mut interval = time::interval(PUBLISH_INTERVAL);
+    interval.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
+
+    loop {
+        interval.tick().await;
+        let state = app_handle.state::<AppState>();
+        publish_round(
+            &state.detail_subscriptions,
+            |torrent_id| collect(&state, torrent_id),
+            |update| {
+                emit_event(&app_handle, Event::TorrentDetailsUpdate(Box::new(update)));
+            },
+        )
+        .await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn update(torrent_id: String) -> TorrentDetailsUpdate {
+        let stats = TorrentInfo {
+            id: torrent_id.clone(),
+            name: "test".to_string(),
+            size: 0,
+            downloaded: 0,
+            uploaded: 0,
+            state: crate::state::TorrentState::Downloading,
+            download_speed: 0,
+            upload_speed: 0,
+            peers: 0,
+            seeds: 0,
+            swarm_seeds: None,
+            swarm_leechers: None,
+            swarm_updated_at: None,
+            source: crate::debrid::types::DownloadSource::P2P,
+            remote_deleted: false,
+            queue_position: None,
+            metadata_pending: false,
+            name_encoding: None,
+            tags: Vec::new(),
+            error: None,
+        };
+        TorrentDetailsUpdate {
+            torrent_id,
+            stats,
+            peers: Vec::new(),
+            trackers: Vec::new(),
+            pieces: None,
+            traffic: None,
+            disconnects: BTreeMap::new(),
+        }
+    }
+
+    async fn round(subscriptions: &DetailSubscriptions, known: &[&str]) -> Vec<String> {
+        let mut emitted = Vec::new();
+        publish_round(
+            subscriptions,
+            |id| async move { known.contains(&id.as_str()).then(|| update(id)) },
+            |update| emitted.push(update.torrent_id),
+        )
+        .await;
+        emitted
+    }
+
+    #[tokio::test]
+    async fn test_subscription_lifecycle() {
+        let subscriptions = DetailSubscriptions::default();
+        assert!(round(&subscriptions, &["a", "b"]).await.is_empty());
+
+        assert_eq!(subscriptions.subscribe("a"), None);
+        assert_eq!(subscriptions.subscribe("b"), None);
+        assert_eq!(round(&subscriptions, &["a", "b"]).await, vec!["a", "b"]);
+        assert_eq!(round(&subscriptions, &["a", "b"]).await, vec!["a", "b"]);
+
+        // Nothing more for an unsubscribed view
+        assert!(subscriptions.unsubscribe("a"));
+        assert!(!subscriptions.unsubscribe("a"));
+        assert_eq!(round(&subscriptions, &["a", "b"]).await, vec!["b"]);
+
+        // A removed torrent drops its subscription
+        assert!(round(&subscriptions, &["a"]).await.is_empty());
+        assert!(subscriptions.snapshot().is_empty());
+        assert!(round(&subscriptions, &["a", "b"]).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_unsubscribe_during_collection_emits_nothing() {
+        let subscriptions = DetailSubscriptions::default();
+        subscriptions.subscribe("a");
+        let mut emitted = Vec::new();
+        publish_round(
+            &subscriptions,
+            |id| {
+                subscriptions.unsubscribe(&id);
+                async move { Some(update(id)) }
+            },
+            |update| emitted.push(update.torrent_id),
+        )
+        .await;
+        assert!(emitted.is_empty());
+    }
+
+    #[test]
+    fn test_cap_evicts_oldest() {
+        let subscriptions = DetailSubscriptions::default();
+        for id in ["a", "b", "c", "d"] {
+            assert_eq!(subscriptions.subscribe(id), None);
+        }
+
+        // Re-subscribing makes "a" the newest, so "b" goes first
+        assert_eq!(subscriptions.subscribe("a"), None);
+        assert_eq!(subscriptions.subscribe("e").as_deref(), Some("b"));
+        assert_eq!(subscriptions.snapshot(), vec!["c", "d", "a", "e"]);
+        assert_eq!(subscriptions.subscribe("f").as_deref(), Some("c"));
+        assert_eq!(subscriptions.snapshot().len(), MAX_DETAIL_SUBSCRIPTIONS);
+    }
+}
@@ -0,0 +1,283 @@
+//! Creating a torrent's files before the download starts
+//!
+//! Allocation goes file by file and stops at the first one that fails (a
+//! directory without write permission, a disk that filled up halfway). The
+//! report says what happened to every file it got to, so the failing path
+//! can be shown, and records what the attempt created, so removing the
+//! torrent can take exactly that back without touching anything that was
+//! there before. A retry skips files already at their full length instead of
+//! setting every length again.
+
+use super::{DiskError, DiskManager, FileInfo};
+use std::path::{Path, PathBuf};
+use tokio::fs::OpenOptions;
+
+/// What allocation did with one file
+#[derive(Debug)]
+pub enum FileOutcome {
+    /// The file didn't exist and was created at its length
+    Created,
+    /// Already there at the right length, left alone
+    Existed,
+    /// Already there at another length, which was set
+    Resized,
+    Failed(DiskError),
+}
+
+#[derive(Debug)]
+pub struct FileAllocation {
+    pub path: PathBuf,
+    pub outcome: FileOutcome,
+}
+
+/// Files and directories an allocation created, for a rollback
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CreatedPaths {
+    pub files: Vec<PathBuf>,
+    /// Outer directories before the ones inside them
+    pub dirs: Vec<PathBuf>,
+}
+
+impl CreatedPaths {
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty() && self.dirs.is_empty()
+    }
+
+    /// Add what a later attempt created
+    pub fn extend(&mut self, other: CreatedPaths) {
+        self.files.extend(other.files);
+        self.dirs.extend(other.dirs);
+    }
+
+    /// Delete the files, then the directories, innermost first. Directories
+    /// something else was put into since are kept. Returns how many entries
+    /// were removed.
+    pub async fn roll_back(&self) -> usize {
+        let mut removed = 0;
+        for path in &self.files {
+            match tokio::fs::remove_file(path).await {
+                Ok(()) => removed += 1,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => tracing::warn!("Could not remove {:?} after a failed allocation: {}", path, e),
+            }
+        }
+        for dir in self.dirs.iter().rev() {
+            if tokio::fs::remove_dir(dir).await.is_ok() {
+                removed += 1;
+            }
+        }
+        removed
+    }
+}
+
+/// Outcome of `DiskManager::allocate`
+#[derive(Debug, Default)]
+pub struct AllocationReport {
+    /// Every file allocation got to, in order; a failure is the last one
+    pub files: Vec<FileAllocation>,
+    /// Data files the torrent has
+    pub total: usize,
+    pub created: CreatedPaths,
+}
+
+impl AllocationReport {
+    /// Index and path of the file that stopped allocation, and why
+    pub fn failure(&self) -> Option<(usize, &Path, &DiskError)> {
+        let index = self.files.len().checked_sub(1)?;
+        let file = &self.files[index];
+        match &file.outcome {
+            FileOutcome::Failed(e) => Some((index, &file.path, e)),
+            _ => None,
+        }
+    }
+
+    /// The first failure as an error
+    pub fn into_result(mut self) -> Result<(), DiskError> {
+        match self.files.pop().map(|file| file.outcome) {
+            Some(FileOutcome::Failed(e)) => Err(e),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl DiskManager {
+    /// Create every data file at its full length, stopping at the first
+    /// one that fails
+    pub async fn allocate(&self) -> AllocationReport {
+        let mut report = AllocationReport { total: self.data_files().len(), ..Default::default() };
+        for file_info in self.data_files() {
+            let outcome = allocate_file(file_info, &mut report.created)
+                .await
+                .unwrap_or_else(FileOutcome::Failed);
+            match &outcome {
+                FileOutcome::Failed(e) => tracing::error!("Failed to allocate {:?}: {}", file_info.path, e),
+                FileOutcome::Existed => tracing::debug!("Already allocated: {:?}", file_info.path),
+                _ => tracing::info!("Allocated file: {:?} ({} bytes)", file_info.path, file_info.length),
+            }
+            let failed = matches!(outcome, FileOutcome::Failed(_));
+            report.files.push(FileAllocation { path: file_info.path.clone(), outcome });
+            if failed {
+                break;
+            }
+        }
+        report
+    }
+
+    /// Pre-allocate all files for the torrent (see `allocate`)
+    pub async fn allocate_files(&self) -> Result<(), DiskError> {
+        self.allocate().await.into_result()
+    }
+}
+
+/// Create one file and its missing parent directories, noting in `created`
+/// what didn't exist before
+async fn allocate_file(file_info: &FileInfo, created: &mut CreatedPaths) -> Result<FileOutcome, DiskError> {
+    let path = &file_info.path;
+    if let Ok(metadata) = tokio::fs::metadata(path).await {
+        if metadata.is_file() && metadata.len() == file_info.length {
+            return Ok(FileOutcome::Existed);
+        }
+    }
+
+    if let Some(parent) = path.parent() {
+        let missing: Vec<PathBuf> = parent
+            .ancestors()
+            .take_while(|dir| !dir.as_os_str().is_empty() && !dir.exists())
+            .map(Path::to_path_buf)
+            .collect();
+        let result = tokio::fs::create_dir_all(parent).await;
+        // Even a failure may have created the outer ones
+        created.dirs.extend(missing.into_iter().rev().filter(|dir| dir.is_dir()));
+        result.map_err(|e| DiskError::from_io(parent, e))?;
+    }
+
+    let existed = path.exists();
+    let file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)
+        .await
+        .map_err(|e| DiskError::from_io(path, e))?;
+    if !existed {
+        created.files.push(path.clone());
+    }
+    file.set_len(file_info.length)
+        .await
+        .map_err(|e| DiskError::from_io(path, e))?;
+    Ok(if existed { FileOutcome::Resized } else { FileOutcome::Created })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::torrent::{FileInfo as TorrentFileInfo, Metainfo, TorrentInfo};
+
+    fn metainfo(paths: &[&str]) -> Metainfo {
+        let files: Vec<TorrentFileInfo> = paths
+            .iter()
+            .map(|path| TorrentFileInfo { path: path.split('/').map(str::to_string).collect(), length: 1000 })
+            .collect();
+        Metainfo {
+            announce: "http://tracker.example.com".to_string(),
+            announce_list: vec![],
+            info: TorrentInfo {
+                piece_length: 16384,
+                pieces: vec![0u8; 20],
+                piece_count: 1,
+                total_size: files.iter().map(|file| file.length).sum(),
+                files,
+                name: "Album".to_string(),
+                is_single_file: false,
+                private: false,
+                legacy_names: None,
+            },
+            info_hash: [0u8; 20],
+            creation_date: None,
+            comment: None,
+            created_by: None,
+        }
+    }
+
+    fn outcomes(report: &AllocationReport) -> Vec<&'static str> {
+        report
+            .files
+            .iter()
+            .map(|file| match file.outcome {
+                FileOutcome::Created => "created",
+                FileOutcome::Existed => "existed",
+                FileOutcome::Resized => "resized",
+                FileOutcome::Failed(_) => "failed",
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_interrupted_allocation_resumes_and_rolls_back() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let root = temp_dir.path().join("Album");
+        let dm = DiskManager::new(&metainfo(&["a/1.bin", "b/2.bin", "c/d/3.bin", "e/4.bin"]), temp_dir.path().to_path_buf());
+
+        // The user's own files, and the first data file already in place
+        std::fs::create_dir_all(root.join("a")).unwrap();
+        std::fs::write(root.join("a/notes.txt"), b"mine").unwrap();
+        std::fs::write(root.join("a/1.bin"), vec![7u8; 1000]).unwrap();
+        std::fs::create_dir(root.join("c")).unwrap();
+
+        // "c" refuses new entries: read-only, or (as root, who ignores
+        // permissions) already holding a file named like the directory
+        #[cfg(unix)]
+        let read_only = unsafe { libc::geteuid() } != 0;
+        #[cfg(not(unix))]
+        let read_only = false;
+        let block = |blocked: bool| {
+            if read_only {
+                #[cfg(unix)]
+                {
+                    use std::os::unix::fs::PermissionsExt;
+                    let mode = if blocked { 0o555 } else { 0o755 };
+                    std::fs::set_permissions(root.join("c"), std::fs::Permissions::from_mode(mode)).unwrap();
+                }
+            } else if blocked {
+                std::fs::write(root.join("c/d"), b"").unwrap();
+            } else {
+                std::fs::remove_file(root.join("c/d")).unwrap();
+            }
+        };
+
+        block(true);
+        let report = dm.allocate().await;
+        assert_eq!(outcomes(&report), vec!["existed", "created", "failed"]);
+        assert_eq!(report.total, 4);
+        let (index, path, error) = report.failure().unwrap();
+        assert_eq!((index, path), (2, root.join("c/d/3.bin").as_path()));
+        assert!(error.path().unwrap().starts_with(root.join("c")), "{:?}", error);
+        assert_eq!(report.created, CreatedPaths { files: vec![root.join("b/2.bin")], dirs: vec![root.join("b")] });
+        assert!(!root.join("e").exists(), "nothing past the failure is touched");
+
+        // Rolling back takes only what this attempt created
+        assert_eq!(report.created.roll_back().await, 2);
+        assert!(!root.join("b").exists());
+        assert_eq!(std::fs::read(root.join("a/notes.txt")).unwrap(), b"mine");
+        assert_eq!(std::fs::read(root.join("a/1.bin")).unwrap(), vec![7u8; 1000]);
+        assert!(root.join("c").is_dir());
+
+        // Once unblocked a retry finishes, and the next one has nothing to do
+        block(false);
+        let report = dm.allocate().await;
+        assert_eq!(outcomes(&report), vec!["existed", "created", "created", "created"]);
+        assert!(report.failure().is_none());
+        assert_eq!(report.created.dirs, vec![root.join("b"), root.join("c/d"), root.join("e")]);
+        let report = dm.allocate().await;
+        assert_eq!(outcomes(&report), vec!["existed"; 4]);
+        assert!(report.created.is_empty());
+        assert_eq!(std::fs::metadata(root.join("e/4.bin")).unwrap().len(), 1000);
+
+        // A file at the wrong length is resized, and isn't ours to delete
+        std::fs::write(root.join("b/2.bin"), b"short").unwrap();
+        let report = dm.allocate().await;
+        assert_eq!(outcomes(&report), vec!["existed", "resized", "existed", "existed"]);
+        assert!(report.created.is_empty());
+        assert!(report.into_result().is_ok());
+    }
+}
@@ -1,12 +1,25 @@
 /// Disk I/O manager for reading and writing torrent pieces
 /// Handles both single-file and multi-file torrents
+use crate::piece::Bitfield;
 use crate::torrent::Metainfo;
-use std::collections::VecDeque;
+use memmap2::Mmap;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+use std::fmt;
 use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use tokio::sync::watch;
 use tokio::fs::{File, OpenOptions};
 use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
 use std::io::SeekFrom;
 
+mod allocation;
+mod parts;
+
+pub use allocation::{AllocationReport, CreatedPaths, FileAllocation, FileOutcome};
+pub use parts::{parts_path, StorageMode, PARTS_EXTENSION, SUGGEST_CONSOLIDATED_FILES};
+
 /// A write request for the disk manager
 #[derive(Debug)]
 pub struct WriteRequest {
@@ -16,6 +29,22 @@ pub struct WriteRequest {
 
 
 
+/// Where a saved bitfield stands relative to the last data sync
+///
+/// `write_piece` only hands data to the OS; it is durable once `sync` has
+/// run. Progress is saved on a timer, so a crash can leave a saved bitfield
+/// claiming pieces whose data never reached the disk. The invariant kept by
+/// everything that persists a bitfield: each piece in it was synced at or
+/// before `generation`, or is listed in `unsynced_pieces`. Restore re-verifies
+/// the listed pieces against the data on disk instead of trusting them.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SyncPoint {
+    /// `DiskManager` sync generation when the bitfield was captured
+    pub generation: u64,
+    /// Pieces in the bitfield written after that sync
+    pub unsynced_pieces: Vec<u32>,
+}
+
 /// Information about a file in the torrent
 #[derive(Debug, Clone)]
 pub struct FileInfo {
@@ -24,6 +53,201 @@ pub struct FileInfo {
     pub offset: u64, // Byte offset from start of torrent
 }
 
+/// Kind of filesystem entry, for layout collisions
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EntryKind {
+    File,
+    Directory,
+}
+
+impl fmt::Display for EntryKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::File => write!(f, "file"),
+            Self::Directory => write!(f, "directory"),
+        }
+    }
+}
+
+/// Something already on disk is in the way of the torrent's layout, e.g. a
+/// directory named like a single-file torrent, or a file where a multi-file
+/// torrent wants its folder
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PathCollision {
+    pub path: PathBuf,
+    pub existing_kind: EntryKind,
+    pub wanted_kind: EntryKind,
+}
+
+impl fmt::Display for PathCollision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} already exists as a {}, but the torrent needs a {} there. \
+             Move it away or add the torrent under a different name.",
+            self.path.display(),
+            self.existing_kind,
+            self.wanted_kind
+        )
+    }
+}
+
+impl std::error::Error for PathCollision {}
+
+/// Why a disk operation failed, classified so callers can tell a full disk
+/// from a missing file or a permissions problem
+#[derive(Debug)]
+pub enum DiskError {
+    NotFound { path: PathBuf },
+    PermissionDenied { path: PathBuf },
+    /// The filesystem (or the user's quota) is full
+    NoSpace { path: PathBuf },
+    /// A directory sits where a data file should be
+    IsDirectory { path: PathBuf },
+    /// Any other I/O failure
+    Io { path: PathBuf, kind: std::io::ErrorKind, source: std::io::Error },
+    /// A block request reaching past the end of its piece
+    InvalidBlock { piece: usize, begin: usize, length: usize, piece_size: usize },
+    /// `queue_write` was called with the queue at capacity
+    QueueFull,
+}
+
+impl DiskError {
+    /// Classify an I/O error on `path`; descriptor exhaustion is also reported
+    /// to `resources`
+    pub fn from_io(path: &Path, source: std::io::Error) -> Self {
+        crate::resources::note_io_error(&source);
+        let path = path.to_path_buf();
+        match source.kind() {
+            std::io::ErrorKind::NotFound => return Self::NotFound { path },
+            // Windows reports a directory opened for writing as access denied
+            _ if path.is_dir() => return Self::IsDirectory { path },
+            std::io::ErrorKind::PermissionDenied => return Self::PermissionDenied { path },
+            _ => {}
+        }
+        if source.raw_os_error().is_some_and(platform::is_no_space_code) {
+            return Self::NoSpace { path };
+        }
+        Self::Io { path, kind: source.kind(), source }
+    }
+
+    /// File the error is about, if any
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            Self::NotFound { path }
+            | Self::PermissionDenied { path }
+            | Self::NoSpace { path }
+            | Self::IsDirectory { path }
+            | Self::Io { path, .. } => Some(path),
+            Self::InvalidBlock { .. } | Self::QueueFull => None,
+        }
+    }
+
+    /// Whether the same operation may work if tried again later: a path
+    /// that vanished with its network share, a connection or timeout
+    /// failure, descriptors running out. Permissions, a full disk and a
+    /// directory in the way need the user.
+    pub fn is_transient(&self) -> bool {
+        use std::io::ErrorKind;
+        match self {
+            Self::NotFound { .. } | Self::QueueFull => true,
+            Self::Io { kind, source, .. } => {
+                crate::resources::is_fd_exhaustion(source)
+                    || matches!(
+                        kind,
+                        ErrorKind::TimedOut
+                            | ErrorKind::Interrupted
+                            | ErrorKind::WouldBlock
+                            | ErrorKind::NotConnected
+                            | ErrorKind::ConnectionReset
+                            | ErrorKind::ConnectionAborted
+                            | ErrorKind::BrokenPipe
+                    )
+            }
+            Self::PermissionDenied { .. } | Self::NoSpace { .. } | Self::IsDirectory { .. } | Self::InvalidBlock { .. } => false,
+        }
+    }
+}
+
+impl fmt::Display for DiskError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotFound { path } => write!(f, "File not found: {}", path.display()),
+            Self::PermissionDenied { path } => write!(f, "Permission denied: {}", path.display()),
+            Self::NoSpace { path } => write!(f, "No space left on device writing {}", path.display()),
+            Self::IsDirectory { path } => write!(f, "{} is a directory, expected a file", path.display()),
+            Self::Io { path, source, .. } => write!(f, "I/O error on {}: {}", path.display(), source),
+            Self::InvalidBlock { piece, begin, length, piece_size } => write!(
+                f,
+                "Invalid block request for piece {}: offset {} + length {} > piece size {}",
+                piece, begin, length, piece_size
+            ),
+            Self::QueueFull => write!(f, "Write queue is full"),
+        }
+    }
+}
+
+impl std::error::Error for DiskError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+/// Error codes for a full disk or exceeded quota
+#[cfg(unix)]
+mod platform {
+    use std::path::Path;
+
+    pub fn is_no_space_code(code: i32) -> bool {
+        code == libc::ENOSPC || code == libc::EDQUOT
+    }
+
+    /// Whether `len` bytes at `offset` are a hole, from where the next
+    /// allocated data starts
+    #[cfg(target_os = "linux")]
+    pub fn is_hole(path: &Path, offset: u64, len: u64) -> Option<bool> {
+        use std::os::unix::io::AsRawFd;
+
+        let file = std::fs::File::open(path).ok()?;
+        let data = unsafe { libc::lseek(file.as_raw_fd(), offset as libc::off_t, libc::SEEK_DATA) };
+        if data < 0 {
+            // ENXIO: no data at all past `offset`
+            let code = std::io::Error::last_os_error().raw_os_error();
+            return (code == Some(libc::ENXIO)).then_some(true);
+        }
+        Some(data as u64 >= offset + len)
+    }
+
+    /// Whether `len` bytes at `offset` are a hole. Without SEEK_DATA only a
+    /// file with no blocks allocated at all is known to be one.
+    #[cfg(not(target_os = "linux"))]
+    pub fn is_hole(path: &Path, _offset: u64, _len: u64) -> Option<bool> {
+        use std::os::unix::fs::MetadataExt;
+
+        let metadata = std::fs::metadata(path).ok()?;
+        (metadata.len() > 0 && metadata.blocks() == 0).then_some(true)
+    }
+}
+
+#[cfg(not(unix))]
+mod platform {
+    /// ERROR_HANDLE_DISK_FULL and ERROR_DISK_FULL
+    const NO_SPACE_CODES: [i32; 2] = [39, 112];
+
+    pub fn is_no_space_code(code: i32) -> bool {
+        NO_SPACE_CODES.contains(&code)
+    }
+
+    /// Allocation isn't looked at here: every region needs reading
+    pub fn is_hole(_path: &std::path::Path, _offset: u64, _len: u64) -> Option<bool> {
+        None
+    }
+}
+
 /// Manages disk I/O operations for torrents
 pub struct DiskManager {
     /// Root directory for downloads
@@ -38,13 +262,46 @@ pub struct DiskManager {
     write_queue: VecDeque<WriteRequest>,
     /// Maximum number of queued writes before applying backpressure
     max_queue_size: usize,
+    /// Number of completed `sync` calls that had something to sync
+    sync_generation: u64,
+    /// Pieces written since the last sync
+    unsynced_pieces: BTreeSet<usize>,
+    /// Files written since the last sync
+    unsynced_files: BTreeSet<PathBuf>,
+    /// Whether reads may go through memory maps (see `read_mapped`)
+    mmap_reads: watch::Receiver<bool>,
+    /// Files whose every piece is verified, so nothing writes them anymore
+    complete_files: HashSet<PathBuf>,
+    /// Maps of complete files, created on first read
+    mappings: Mutex<HashMap<PathBuf, Arc<Mmap>>>,
+    /// Folder of a multi-file torrent
+    root_dir: Option<PathBuf>,
+    /// The torrent's creation date, given as mtime to files it completes
+    /// while `completion_mtimes` is on
+    creation_date: Option<SystemTime>,
+    completion_mtimes: watch::Receiver<bool>,
+    /// The single data file in `StorageMode::Consolidated`
+    parts: Option<FileInfo>,
+    /// Files hard-linked to another torrent's copy, not written since
+    linked_files: HashSet<PathBuf>,
 }
 
 impl DiskManager {
     /// Create a new disk manager from torrent metainfo
     pub fn new(metainfo: &Metainfo, download_dir: PathBuf) -> Self {
-        let files = Self::build_file_list(metainfo, &download_dir);
+        Self::with_root_name(metainfo, download_dir, &metainfo.info.name)
+    }
+
+    /// Like `new`, but store the torrent under `root_name` (the file name of a
+    /// single-file torrent, the folder of a multi-file one) instead of its own name
+    pub fn with_root_name(metainfo: &Metainfo, download_dir: PathBuf, root_name: &str) -> Self {
+        let files = Self::build_file_list(metainfo, &download_dir, root_name);
         let total_size = metainfo.info.total_size;
+        let root_dir = (!metainfo.info.is_single_file).then(|| download_dir.join(root_name));
+        let creation_date = metainfo
+            .creation_date
+            .and_then(|secs| u64::try_from(secs).ok())
+            .map(|secs| SystemTime::UNIX_EPOCH + Duration::from_secs(secs));
 
         Self {
             download_dir,
@@ -53,29 +310,136 @@ impl DiskManager {
             total_size,
             write_queue: VecDeque::new(),
             max_queue_size: 100,
+            sync_generation: 0,
+            unsynced_pieces: BTreeSet::new(),
+            unsynced_files: BTreeSet::new(),
+            mmap_reads: watch::channel(false).1,
+            complete_files: HashSet::new(),
+            mappings: Mutex::new(HashMap::new()),
+            root_dir,
+            creation_date,
+            completion_mtimes: watch::channel(false).1,
+            parts: None,
+            linked_files: HashSet::new(),
+        }
+    }
+
+    /// Follow the app-wide memory-mapped reads setting
+    /// (see `AppState::mmap_reads`)
+    pub fn set_mmap_reads(&mut self, mmap_reads: watch::Receiver<bool>) {
+        self.mmap_reads = mmap_reads;
+    }
+
+    /// Follow the app-wide "file mtime from creation date" setting
+    /// (see `AppState::completion_mtimes`)
+    pub fn set_completion_mtimes(&mut self, completion_mtimes: watch::Receiver<bool>) {
+        self.completion_mtimes = completion_mtimes;
+    }
+
+    /// Files hard-linked to another torrent's copy (see `duplicates`). The
+    /// first write into one breaks the link by copying the file, so the
+    /// other torrent's data is left alone.
+    pub fn set_linked_files(&mut self, file_indices: &[usize]) {
+        self.linked_files = file_indices
+            .iter()
+            .filter_map(|&index| self.files.get(index))
+            .map(|file| file.path.clone())
+            .collect();
+    }
+
+    /// Call once a verified piece is written. Files it completed become
+    /// mappable and, with the setting on, get the torrent's creation date as
+    /// their mtime; so does the folder once the whole torrent is done. Files
+    /// that were complete before (e.g. existing data that was imported) are
+    /// left alone. Returns the paths whose mtime was set.
+    pub fn piece_completed(&mut self, piece_index: usize, bitfield: &Bitfield) -> Vec<PathBuf> {
+        // Consolidated files come into being (complete) when extracted
+        if self.parts.is_some() {
+            return Vec::new();
         }
+        let piece_length = self.piece_length as u64;
+        let piece_start = piece_index as u64 * piece_length;
+        let completed: Vec<PathBuf> = self
+            .files
+            .iter()
+            .filter(|f| f.length > 0 && f.offset < piece_start + piece_length && f.offset + f.length > piece_start)
+            .filter(|f| {
+                (f.offset / piece_length..=(f.offset + f.length - 1) / piece_length)
+                    .all(|piece| bitfield.has_piece(piece as usize))
+            })
+            .map(|f| f.path.clone())
+            .filter(|path| !self.complete_files.contains(path))
+            .collect();
+        self.complete_files.extend(completed.iter().cloned());
+        self.stamp_mtimes(&completed, bitfield.is_complete())
+    }
+
+    /// Give `paths` (and the folder, with `with_root`) the creation date as
+    /// mtime if the setting is on; returns the ones that got it
+    fn stamp_mtimes(&self, paths: &[PathBuf], with_root: bool) -> Vec<PathBuf> {
+        let Some(mtime) = self.creation_date.filter(|_| *self.completion_mtimes.borrow()) else {
+            return Vec::new();
+        };
+        let mut stamped = Vec::new();
+        let root = self.root_dir.as_ref().filter(|_| with_root);
+        for path in paths.iter().chain(root) {
+            // A file moved or renamed by the user since is skipped
+            match set_mtime(path, mtime) {
+                Ok(()) => stamped.push(path.clone()),
+                Err(e) => tracing::debug!("Not setting the mtime of {:?}: {}", path, e),
+            }
+        }
+        stamped
+    }
+
+    /// Recompute which files are complete from the verified pieces. Only
+    /// those are ever memory-mapped.
+    pub fn mark_complete_files(&mut self, bitfield: &Bitfield) {
+        let piece_length = self.piece_length as u64;
+        for file_info in &self.files {
+            let complete = file_info.length > 0
+                && (file_info.offset / piece_length..=(file_info.offset + file_info.length - 1) / piece_length)
+                    .all(|piece| bitfield.has_piece(piece as usize));
+            if complete {
+                self.complete_files.insert(file_info.path.clone());
+            } else if self.complete_files.remove(&file_info.path) {
+                self.unmap(&file_info.path);
+            }
+        }
+    }
+
+    /// Drop every memory map; call before deleting, moving or renaming files
+    pub fn unmap_all(&self) {
+        self.mappings.lock().unwrap().clear();
+    }
+
+    fn unmap(&self, path: &Path) {
+        self.mappings.lock().unwrap().remove(path);
+    }
+
+    /// Number of files currently memory-mapped
+    pub fn mapped_files(&self) -> usize {
+        self.mappings.lock().unwrap().len()
     }
 
     /// Build list of files with their absolute paths and byte offsets
-    fn build_file_list(metainfo: &Metainfo, download_dir: &Path) -> Vec<FileInfo> {
+    fn build_file_list(metainfo: &Metainfo, download_dir: &Path, root_name: &str) -> Vec<FileInfo> {
+        let root = download_dir.join(root_name);
         let mut files = Vec::new();
         let mut offset = 0u64;
 
         if metainfo.info.is_single_file {
             // Single file torrent
-            let path = download_dir.join(&metainfo.info.name);
             files.push(FileInfo {
-                path,
+                path: root,
                 length: metainfo.info.total_size,
                 offset,
             });
         } else {
             // Multi-file torrent
-            let torrent_dir = download_dir.join(&metainfo.info.name);
-            
             for file_info in &metainfo.info.files {
                 let file_path = file_info.path.iter().fold(
-                    torrent_dir.clone(),
+                    root.clone(),
                     |acc, component| acc.join(component)
                 );
                 
@@ -92,74 +456,98 @@ impl DiskManager {
         files
     }
 
-    /// Pre-allocate all files for the torrent
-    pub async fn allocate_files(&self) -> Result<(), String> {
-        for file_info in &self.files {
-            // Create parent directories
-            if let Some(parent) = file_info.path.parent() {
-                tokio::fs::create_dir_all(parent)
-                    .await
-                    .map_err(|e| format!("Failed to create directory: {}", e))?;
+    /// Find the first existing entry that would stop `allocate_files`: a
+    /// directory where a file goes, or a file where a directory goes
+    pub fn check_layout(&self) -> Result<(), PathCollision> {
+        for file_info in self.data_files() {
+            if file_info.path.is_dir() {
+                return Err(PathCollision {
+                    path: file_info.path.clone(),
+                    existing_kind: EntryKind::Directory,
+                    wanted_kind: EntryKind::File,
+                });
             }
 
-            // Create/open file
-            let file = OpenOptions::new()
-                .write(true)
-                .create(true)
-                .open(&file_info.path)
-                .await
-                .map_err(|e| format!("Failed to create file {:?}: {}", file_info.path, e))?;
-
-            // Set file length (pre-allocate space)
-            file.set_len(file_info.length)
-                .await
-                .map_err(|e| format!("Failed to allocate file space: {}", e))?;
-
-            tracing::info!(
-                "Allocated file: {:?} ({} bytes)",
-                file_info.path,
-                file_info.length
-            );
+            let parents = file_info
+                .path
+                .ancestors()
+                .skip(1)
+                .take_while(|dir| *dir != self.download_dir);
+            for dir in parents {
+                if dir.is_file() {
+                    return Err(PathCollision {
+                        path: dir.to_path_buf(),
+                        existing_kind: EntryKind::File,
+                        wanted_kind: EntryKind::Directory,
+                    });
+                }
+            }
         }
 
         Ok(())
     }
 
     /// Write a piece to disk
-    pub async fn write_piece(&mut self, piece_index: usize, data: Vec<u8>) -> Result<(), String> {
+    pub async fn write_piece(&mut self, piece_index: usize, data: Vec<u8>) -> Result<(), DiskError> {
         let piece_offset = (piece_index * self.piece_length) as u64;
         let piece_size = data.len() as u64;
 
+        if !self.linked_files.is_empty() {
+            let linked: Vec<PathBuf> = self
+                .get_files_for_range(piece_offset, piece_size)
+                .into_iter()
+                .map(|(file_info, _, _)| file_info.path.clone())
+                .filter(|path| self.linked_files.contains(path))
+                .collect();
+            for path in linked {
+                self.unmap(&path);
+                break_link(&path).await.map_err(|e| DiskError::from_io(&path, e))?;
+                self.linked_files.remove(&path);
+                tracing::info!("Gave {:?} its own copy before writing to it", path);
+            }
+        }
+
         // Find which file(s) this piece spans
         let files_to_write = self.get_files_for_range(piece_offset, piece_size);
 
         let mut data_offset = 0usize;
+        let mut written_files = Vec::new();
         
         for (file_info, file_offset, write_size) in files_to_write {
+            // Not expected for a complete file, but never write under a live map
+            if self.complete_files.contains(&file_info.path) {
+                self.unmap(&file_info.path);
+            }
             let mut file = OpenOptions::new()
                 .write(true)
                 .open(&file_info.path)
                 .await
-                .map_err(|e| format!("Failed to open file {:?}: {}", file_info.path, e))?;
+                .map_err(|e| DiskError::from_io(&file_info.path, e))?;
 
             // Seek to the correct position
             file.seek(SeekFrom::Start(file_offset))
                 .await
-                .map_err(|e| format!("Failed to seek in file: {}", e))?;
+                .map_err(|e| DiskError::from_io(&file_info.path, e))?;
 
             // Write the data chunk
             let chunk = &data[data_offset..data_offset + write_size];
             file.write_all(chunk)
                 .await
-                .map_err(|e| format!("Failed to write to file: {}", e))?;
+                .map_err(|e| DiskError::from_io(&file_info.path, e))?;
 
-            // Ensure data is flushed to disk
+            // Hand the data to the OS; `sync` makes it durable
             file.flush()
                 .await
-                .map_err(|e| format!("Failed to flush file: {}", e))?;
+                .map_err(|e| DiskError::from_io(&file_info.path, e))?;
 
+            written_files.push(file_info.path.clone());
             data_offset += write_size;
         }
+        for path in &written_files {
+            self.complete_files.remove(path);
+        }
+        self.unsynced_files.extend(written_files);
+        self.unsynced_pieces.insert(piece_index);
 
         tracing::debug!(
             "Wrote piece {} ({} bytes) to disk",
@@ -171,36 +559,56 @@ impl DiskManager {
     }
 
     /// Read a piece from disk
-    pub async fn read_piece(&self, piece_index: usize) -> Result<Vec<u8>, String> {
+    pub async fn read_piece(&self, piece_index: usize) -> Result<Vec<u8>, DiskError> {
+        let (piece_offset, piece_size) = self.piece_span(piece_index);
+        self.read_range(piece_offset, piece_size).await
+    }
+
+    /// Read one block of a piece (what an upload request asks for)
+    pub async fn read_block(&self, piece_index: usize, begin: usize, length: usize) -> Result<Vec<u8>, DiskError> {
+        let (piece_offset, piece_size) = self.piece_span(piece_index);
+        if begin + length > piece_size {
+            return Err(DiskError::InvalidBlock { piece: piece_index, begin, length, piece_size });
+        }
+        self.read_range(piece_offset + begin as u64, length).await
+    }
+
+    /// Byte offset and size of a piece (the last one may be smaller)
+    fn piece_span(&self, piece_index: usize) -> (u64, usize) {
         let piece_offset = (piece_index * self.piece_length) as u64;
-        
-        // Calculate piece size (last piece may be smaller)
         let piece_size = if piece_offset + self.piece_length as u64 > self.total_size {
-            (self.total_size - piece_offset) as usize
+            self.total_size.saturating_sub(piece_offset) as usize
         } else {
             self.piece_length
         };
+        (piece_offset, piece_size)
+    }
+
+    async fn read_range(&self, offset: u64, size: usize) -> Result<Vec<u8>, DiskError> {
+        if let Some(data) = self.read_mapped(offset, size) {
+            return Ok(data);
+        }
 
-        let mut piece_data = vec![0u8; piece_size];
-        let files_to_read = self.get_files_for_range(piece_offset, piece_size as u64);
+        let mut piece_data = vec![0u8; size];
+        let files_to_read = self.get_files_for_range(offset, size as u64);
 
         let mut data_offset = 0usize;
 
         for (file_info, file_offset, read_size) in files_to_read {
             let mut file = File::open(&file_info.path)
                 .await
-                .map_err(|e| format!("Failed to open file {:?}: {}", file_info.path, e))?;
+                .map_err(|e| DiskError::from_io(&file_info.path, e))?;
 
             // Seek to the correct position
             file.seek(SeekFrom::Start(file_offset))
                 .await
-                .map_err(|e| format!("Failed to seek in file: {}", e))?;
+                .map_err(|e| DiskError::from_io(&file_info.path, e))?;
 
             // Read the data chunk
             let chunk = &mut piece_data[data_offset..data_offset + read_size];
             file.read_exact(chunk)
                 .await
-                .map_err(|e| format!("Failed to read from file: {}", e))?;
+                .map_err(|e| DiskError::from_io(&file_info.path, e))?;
 
             data_offset += read_size;
         }
@@ -208,10 +616,64 @@ impl DiskManager {
         Ok(piece_data)
     }
 
+    /// Copy a range straight out of memory maps, when the setting is on and
+    /// every file it touches is complete and synced. None means "use the
+    /// normal path": mmap is off, a file is still being written, or mapping
+    /// failed (some filesystems don't support it).
+    ///
+    /// Off by default: a file truncated behind our back while mapped makes
+    /// the read fault (SIGBUS) instead of returning an error.
+    fn read_mapped(&self, offset: u64, size: usize) -> Option<Vec<u8>> {
+        if !*self.mmap_reads.borrow() {
+            if self.mapped_files() > 0 {
+                self.unmap_all();
+            }
+            return None;
+        }
+
+        let spans = self.get_files_for_range(offset, size as u64);
+        let mappable = |path: &PathBuf| self.complete_files.contains(path) && !self.unsynced_files.contains(path);
+        if spans.is_empty() || !spans.iter().all(|(file_info, _, _)| mappable(&file_info.path)) {
+            return None;
+        }
+
+        let mut data = Vec::with_capacity(size);
+        for (file_info, file_offset, read_size) in spans {
+            let map = self.mapping(file_info)?;
+            let start = file_offset as usize;
+            data.extend_from_slice(map.get(start..start + read_size)?);
+        }
+        Some(data)
+    }
+
+    fn mapping(&self, file_info: &FileInfo) -> Option<Arc<Mmap>> {
+        let mut mappings = self.mappings.lock().unwrap();
+        if let Some(map) = mappings.get(&file_info.path) {
+            return Some(map.clone());
+        }
+
+        let file = std::fs::File::open(&file_info.path).ok()?;
+        if file.metadata().ok()?.len() != file_info.length {
+            return None;
+        }
+        // SAFETY: the file is complete and we never write it while mapped
+        // (`write_piece` unmaps first). Other processes truncating it is the
+        // risk the setting's default guards against.
+        let map = match unsafe { Mmap::map(&file) } {
+            Ok(map) => Arc::new(map),
+            Err(e) => {
+                tracing::debug!("Cannot map {:?}, reading normally: {}", file_info.path, e);
+                return None;
+            }
+        };
+        mappings.insert(file_info.path.clone(), map.clone());
+        Some(map)
+    }
+
     /// Queue a write operation (for batching)
-    pub fn queue_write(&mut self, piece_index: usize, data: Vec<u8>) -> Result<(), String> {
+    pub fn queue_write(&mut self, piece_index: usize, data: Vec<u8>) -> Result<(), DiskError> {
         if self.write_queue.len() >= self.max_queue_size {
-            return Err("Write queue is full".to_string());
+            return Err(DiskError::QueueFull);
         }
 
         self.write_queue.push_back(WriteRequest { piece_index, data });
@@ -219,20 +681,56 @@ impl DiskManager {
     }
 
     /// Flush all queued writes to disk
-    pub async fn flush_writes(&mut self) -> Result<(), String> {
+    pub async fn flush_writes(&mut self) -> Result<(), DiskError> {
         while let Some(write_req) = self.write_queue.pop_front() {
             self.write_piece(write_req.piece_index, write_req.data).await?;
         }
         Ok(())
     }
 
+    /// Make every piece written so far durable. Returns the new sync generation.
+    pub async fn sync(&mut self) -> Result<u64, DiskError> {
+        if self.unsynced_files.is_empty() {
+            return Ok(self.sync_generation);
+        }
+
+        for path in &self.unsynced_files {
+            let file = OpenOptions::new()
+                .write(true)
+                .open(path)
+                .await
+                .map_err(|e| DiskError::from_io(path, e))?;
+            file.sync_data()
+                .await
+                .map_err(|e| DiskError::from_io(path, e))?;
+        }
+
+        self.unsynced_files.clear();
+        self.unsynced_pieces.clear();
+        self.sync_generation += 1;
+        Ok(self.sync_generation)
+    }
+
+    /// Current sync generation and the pieces of `bitfield` not yet synced
+    pub fn sync_point(&self, bitfield: &Bitfield) -> SyncPoint {
+        SyncPoint {
+            generation: self.sync_generation,
+            unsynced_pieces: self
+                .unsynced_pieces
+                .iter()
+                .filter(|&&i| bitfield.has_piece(i))
+                .map(|&i| i as u32)
+                .collect(),
+        }
+    }
+
     /// Get which files a byte range spans
     /// Returns: Vec<(FileInfo, offset_in_file, bytes_to_read)>
     fn get_files_for_range(&self, offset: u64, size: u64) -> Vec<(&FileInfo, u64, usize)> {
         let mut result = Vec::new();
         let end_offset = offset + size;
 
-        for file_info in &self.files {
+        for file_info in self.data_files() {
             let file_start = file_info.offset;
             let file_end = file_info.offset + file_info.length;
 
@@ -269,9 +767,18 @@ impl DiskManager {
         &self.files
     }
 
+    /// Files the data is actually in: the torrent's files, or the
+    /// consolidated file until it is extracted
+    pub fn data_files(&self) -> &[FileInfo] {
+        match &self.parts {
+            Some(parts) => std::slice::from_ref(parts),
+            None => &self.files,
+        }
+    }
+
     /// Check if all files exist
     pub async fn files_exist(&self) -> bool {
-        for file_info in &self.files {
+        for file_info in self.data_files() {
             if !tokio::fs::try_exists(&file_info.path).await.unwrap_or(false) {
                 return false;
             }
@@ -279,12 +786,54 @@ impl DiskManager {
         true
     }
 
+    /// Pieces of `bitfield` that run past the end of a file shorter on disk
+    /// than the torrent says. Missing files are left to the missing-files
+    /// check; a truncated one just can't hold what the bitfield claims.
+    pub fn truncated_pieces(&self, bitfield: &Bitfield) -> Vec<usize> {
+        let mut pieces = BTreeSet::new();
+        for file_info in self.data_files() {
+            let on_disk = match std::fs::metadata(&file_info.path) {
+                Ok(metadata) => metadata.len(),
+                Err(_) => continue,
+            };
+            if on_disk >= file_info.length {
+                continue;
+            }
+            let missing = file_info.offset + on_disk..file_info.offset + file_info.length;
+            pieces.extend(
+                crate::piece::ranges::pieces_covering(&missing, self.piece_length as u64)
+                    .filter(|&i| bitfield.has_piece(i)),
+            );
+        }
+        pieces.into_iter().collect()
+    }
+
+    /// Whether nothing was ever written to a piece, going by the file
+    /// system's allocation: such a piece reads back as zeros. None where the
+    /// platform doesn't say.
+    pub fn piece_is_sparse(&self, piece_index: usize) -> Option<bool> {
+        let (piece_offset, piece_size) = self.piece_span(piece_index);
+        for (file_info, offset, len) in self.get_files_for_range(piece_offset, piece_size as u64) {
+            if !platform::is_hole(&file_info.path, offset, len as u64)? {
+                return Some(false);
+            }
+        }
+        Some(true)
+    }
+
     /// Delete all files associated with this torrent
-    pub async fn delete_files(&self) -> Result<(), String> {
+    pub async fn delete_files(&self) -> Result<(), DiskError> {
+        self.unmap_all();
+        // The consolidated file sits right in the download dir
+        if let Some(parts) = &self.parts {
+            return tokio::fs::remove_file(&parts.path)
+                .await
+                .map_err(|e| DiskError::from_io(&parts.path, e));
+        }
         for file_info in &self.files {
             tokio::fs::remove_file(&file_info.path)
                 .await
-                .map_err(|e| format!("Failed to delete file {:?}: {}", file_info.path, e))?;
+                .map_err(|e| DiskError::from_io(&file_info.path, e))?;
         }
 
         // Try to remove empty directories
@@ -298,6 +847,92 @@ impl DiskManager {
     }
 }
 
+/// Give a hard-linked file a copy of its own, so writing to it leaves the
+/// other names alone
+async fn break_link(path: &Path) -> std::io::Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        if tokio::fs::metadata(path).await?.nlink() <= 1 {
+            return Ok(());
+        }
+    }
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let copy = path.with_file_name(format!(".{}.unlink", name));
+    tokio::fs::copy(path, &copy).await?;
+    tokio::fs::rename(&copy, path).await
+}
+
+/// Set a file's or folder's modification time
+fn set_mtime(path: &Path, mtime: SystemTime) -> std::io::Result<()> {
+    #[cfg(windows)]
+    let file = {
+        use std::os::windows::fs::OpenOptionsExt;
+        // FILE_FLAG_BACKUP_SEMANTICS, without which folders can't be opened
+        std::fs::OpenOptions::new().write(true).custom_flags(0x0200_0000).open(path)?
+    };
+    #[cfg(not(windows))]
+    let file = std::fs::File::open(path)?;
+    file.set_modified(mtime)
+}
+
+/// First "name (n)" root name (n >= 2) that doesn't exist in `download_dir`
+///
+/// Single-file names keep their extension ("Movie (2).mkv"); folder names are
+/// suffixed as a whole, since dots in them rarely mean an extension.
+pub fn available_root_name(download_dir: &Path, name: &str, is_single_file: bool) -> String {
+    let (stem, extension) = match (is_single_file, name.rsplit_once('.')) {
+        (true, Some((stem, ext))) if !stem.is_empty() => (stem, Some(ext)),
+        _ => (name, None),
+    };
+
+    (2..)
+        .map(|n| match extension {
+            Some(ext) => format!("{} ({}).{}", stem, n, ext),
+            None => format!("{} ({})", stem, n),
+        })
+        .find(|candidate| !download_dir.join(candidate).exists())
+        .expect("ran out of candidate names")
+}
+
+/// Identifier of the volume (filesystem) holding `path`, if the platform exposes one
+pub fn volume_id(path: &Path) -> Option<u64> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        std::fs::metadata(path).ok().map(|m| m.dev())
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = path;
+        None
+    }
+}
+
+/// Verify a download directory is usable before starting a torrent.
+///
+/// Never creates anything: an unmounted drive must not be papered over by a
+/// fresh directory tree on the mount point's parent filesystem.
+pub fn verify_download_dir(path: &Path, expected_volume: Option<u64>) -> Result<(), String> {
+    let metadata = std::fs::metadata(path)
+        .map_err(|_| format!("Download directory {} is missing", path.display()))?;
+
+    if !metadata.is_dir() {
+        return Err(format!("Download path {} is not a directory", path.display()));
+    }
+
+    if let (Some(expected), Some(actual)) = (expected_volume, volume_id(path)) {
+        if expected != actual {
+            return Err(format!(
+                "Download directory {} is on a different volume than when the torrent was added",
+                path.display()
+            ));
+        }
+    }
+
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -318,6 +953,8 @@ mod tests {
                 name: "test_file.txt".to_string(),
                 total_size: 20000,
                 is_single_file: true,
+                private: false,
+                legacy_names: None,
             },
             info_hash: [0u8; 20],
             creation_date: None,
@@ -347,6 +984,8 @@ mod tests {
                 name: "test_torrent".to_string(),
                 total_size: 20000,
                 is_single_file: false,
+                private: false,
+                legacy_names: None,
             },
             info_hash: [0u8; 20],
             creation_date: None,
@@ -359,7 +998,7 @@ mod tests {
     fn test_build_file_list_single() {
         let metainfo = create_test_metainfo_single();
         let download_dir = PathBuf::from("/tmp/downloads");
-        let files = DiskManager::build_file_list(&metainfo, &download_dir);
+        let files = DiskManager::build_file_list(&metainfo, &download_dir, &metainfo.info.name);
 
         assert_eq!(files.len(), 1);
         assert_eq!(files[0].path, PathBuf::from("/tmp/downloads/test_file.txt"));
@@ -371,7 +1010,7 @@ mod tests {
     fn test_build_file_list_multi() {
         let metainfo = create_test_metainfo_multi();
         let download_dir = PathBuf::from("/tmp/downloads");
-        let files = DiskManager::build_file_list(&metainfo, &download_dir);
+        let files = DiskManager::build_file_list(&metainfo, &download_dir, &metainfo.info.name);
 
         assert_eq!(files.len(), 2);
         assert_eq!(files[0].path, PathBuf::from("/tmp/downloads/test_torrent/file1.txt"));
@@ -426,6 +1065,96 @@ mod tests {
         assert_eq!(dm.num_pieces(), 2);
     }
 
+    #[test]
+    fn test_verify_download_dir_missing() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let missing = temp_dir.path().join("unmounted");
+
+        assert!(verify_download_dir(&missing, None).is_err());
+        assert!(!missing.exists(), "check must not create the directory");
+    }
+
+    #[test]
+    fn test_verify_download_dir_not_a_directory() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let file = temp_dir.path().join("file.bin");
+        std::fs::write(&file, b"x").unwrap();
+
+        assert!(verify_download_dir(&file, None).is_err());
+    }
+
+    #[test]
+    fn test_verify_download_dir_volume() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let volume = volume_id(temp_dir.path());
+
+        assert!(verify_download_dir(temp_dir.path(), volume).is_ok());
+        if let Some(id) = volume {
+            assert!(verify_download_dir(temp_dir.path(), Some(id.wrapping_add(1))).is_err());
+        }
+    }
+
+    #[test]
+    fn test_check_layout_collisions() {
+        let single = create_test_metainfo_single();
+        let multi = create_test_metainfo_multi();
+
+        // Existing directory where a single file goes
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        std::fs::create_dir(temp_dir.path().join("test_file.txt")).unwrap();
+        let dm = DiskManager::new(&single, temp_dir.path().to_path_buf());
+        assert_eq!(
+            dm.check_layout(),
+            Err(PathCollision {
+                path: temp_dir.path().join("test_file.txt"),
+                existing_kind: EntryKind::Directory,
+                wanted_kind: EntryKind::File,
+            })
+        );
+
+        // Existing file where the torrent folder goes
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        std::fs::write(temp_dir.path().join("test_torrent"), b"x").unwrap();
+        let dm = DiskManager::new(&multi, temp_dir.path().to_path_buf());
+        assert_eq!(
+            dm.check_layout(),
+            Err(PathCollision {
+                path: temp_dir.path().join("test_torrent"),
+                existing_kind: EntryKind::File,
+                wanted_kind: EntryKind::Directory,
+            })
+        );
+
+        // Existing file where a file goes (resuming) is fine
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        std::fs::write(temp_dir.path().join("test_file.txt"), b"x").unwrap();
+        let dm = DiskManager::new(&single, temp_dir.path().to_path_buf());
+        assert!(dm.check_layout().is_ok());
+
+        // Existing directory where a directory goes is fine too
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        std::fs::create_dir_all(temp_dir.path().join("test_torrent/subdir")).unwrap();
+        let dm = DiskManager::new(&multi, temp_dir.path().to_path_buf());
+        assert!(dm.check_layout().is_ok());
+    }
+
+    #[test]
+    fn test_available_root_name() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        std::fs::create_dir(temp_dir.path().join("Movie.mkv")).unwrap();
+        assert_eq!(available_root_name(temp_dir.path(), "Movie.mkv", true), "Movie (2).mkv");
+
+        std::fs::write(temp_dir.path().join("Show.S01"), b"x").unwrap();
+        std::fs::write(temp_dir.path().join("Show.S01 (2)"), b"x").unwrap();
+        assert_eq!(available_root_name(temp_dir.path(), "Show.S01", false), "Show.S01 (3)");
+
+        // The renamed root is where the files end up
+        let metainfo = create_test_metainfo_multi();
+        let dm = DiskManager::with_root_name(&metainfo, temp_dir.path().to_path_buf(), "Show.S01 (3)");
+        assert!(dm.check_layout().is_ok());
+        assert_eq!(dm.files()[1].path, temp_dir.path().join("Show.S01 (3)/subdir/file2.txt"));
+    }
+
     #[tokio::test]
     async fn test_write_and_read_piece() {
         let metainfo = create_test_metainfo_single();
@@ -448,6 +1177,266 @@ mod tests {
         let _ = tokio::fs::remove_dir_all(download_dir).await;
     }
 
+    #[tokio::test]
+    async fn test_disk_error_classification() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let metainfo = create_test_metainfo_single();
+        let file_path = temp_dir.path().join("test_file.txt");
+
+        // Nothing allocated yet
+        let mut dm = DiskManager::new(&metainfo, temp_dir.path().to_path_buf());
+        match dm.read_piece(0).await {
+            Err(DiskError::NotFound { path }) => assert_eq!(path, file_path),
+            other => panic!("expected NotFound, got {:?}", other),
+        }
+
+        // A directory where the data file should be
+        std::fs::create_dir(&file_path).unwrap();
+        let err = dm.write_piece(0, vec![1u8; 16384]).await.unwrap_err();
+        assert!(matches!(&err, DiskError::IsDirectory { path } if *path == file_path), "{:?}", err);
+        assert!(err.to_string().contains("test_file.txt"));
+        std::fs::remove_dir(&file_path).unwrap();
+
+        let err = dm.read_block(1, 0, 16384).await.unwrap_err();
+        assert!(matches!(err, DiskError::InvalidBlock { piece: 1, piece_size: 3616, .. }));
+
+        // Root ignores directory permissions, so only check this as a normal user
+        #[cfg(unix)]
+        if unsafe { libc::geteuid() } != 0 {
+            use std::os::unix::fs::PermissionsExt;
+            let read_only = temp_dir.path().join("read_only");
+            std::fs::create_dir(&read_only).unwrap();
+            std::fs::set_permissions(&read_only, std::fs::Permissions::from_mode(0o555)).unwrap();
+            dm = DiskManager::new(&metainfo, read_only.clone());
+            let err = dm.allocate_files().await.unwrap_err();
+            std::fs::set_permissions(&read_only, std::fs::Permissions::from_mode(0o755)).unwrap();
+            assert!(matches!(&err, DiskError::PermissionDenied { path } if path.starts_with(&read_only)), "{:?}", err);
+        }
+    }
+
+    #[test]
+    fn test_disk_error_conversion() {
+        let path = Path::new("/nonexistent/seedcore/file.bin");
+        #[cfg(unix)]
+        {
+            let full = DiskError::from_io(path, std::io::Error::from_raw_os_error(libc::ENOSPC));
+            assert!(matches!(full, DiskError::NoSpace { .. }));
+            assert!(matches!(crate::error::Error::from(full), crate::error::Error::DiskFull(msg) if msg.contains("file.bin")));
+
+            let exhausted = DiskError::from_io(path, std::io::Error::from_raw_os_error(libc::EMFILE));
+            assert!(matches!(crate::error::Error::from(exhausted), crate::error::Error::TooManyOpenFiles(_)));
+        }
+        let denied = DiskError::from_io(path, std::io::Error::from(std::io::ErrorKind::PermissionDenied));
+        assert!(matches!(crate::error::Error::from(denied), crate::error::Error::PermissionDenied(_)));
+
+        let other = DiskError::from_io(path, std::io::Error::new(std::io::ErrorKind::Other, "bad sector"));
+        assert!(matches!(&other, DiskError::Io { kind: std::io::ErrorKind::Other, .. }));
+        assert_eq!(other.path(), Some(path));
+        assert!(std::error::Error::source(&other).is_some());
+        assert_eq!(other.to_string(), "I/O error on /nonexistent/seedcore/file.bin: bad sector");
+    }
+
+    #[test]
+    fn test_transient_disk_errors() {
+        let path = Path::new("/nonexistent/seedcore/file.bin");
+        let error = |kind: std::io::ErrorKind| DiskError::from_io(path, std::io::Error::from(kind));
+        assert!(error(std::io::ErrorKind::NotFound).is_transient());
+        assert!(error(std::io::ErrorKind::TimedOut).is_transient());
+        assert!(error(std::io::ErrorKind::NotConnected).is_transient());
+        assert!(!error(std::io::ErrorKind::PermissionDenied).is_transient());
+        assert!(!error(std::io::ErrorKind::Other).is_transient());
+        #[cfg(unix)]
+        {
+            assert!(DiskError::from_io(path, std::io::Error::from_raw_os_error(libc::EMFILE)).is_transient());
+            assert!(!DiskError::from_io(path, std::io::Error::from_raw_os_error(libc::ENOSPC)).is_transient());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sync_generation() {
+        let metainfo = create_test_metainfo_single();
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let mut dm = DiskManager::new(&metainfo, temp_dir.path().to_path_buf());
+        dm.allocate_files().await.unwrap();
+
+        let mut bitfield = Bitfield::new(2);
+        bitfield.set_piece(0);
+        bitfield.set_piece(1);
+
+        // Nothing written yet: syncing is a no-op
+        assert_eq!(dm.sync().await.unwrap(), 0);
+
+        dm.write_piece(0, vec![1u8; 16384]).await.unwrap();
+        assert_eq!(dm.sync_point(&bitfield), SyncPoint { generation: 0, unsynced_pieces: vec![0] });
+
+        assert_eq!(dm.sync().await.unwrap(), 1);
+        assert_eq!(dm.sync_point(&bitfield), SyncPoint { generation: 1, unsynced_pieces: vec![] });
+
+        // Only pieces the bitfield claims are reported
+        dm.write_piece(1, vec![2u8; 3616]).await.unwrap();
+        bitfield.clear_piece(1);
+        assert!(dm.sync_point(&bitfield).unsynced_pieces.is_empty());
+        assert_eq!(dm.sync().await.unwrap(), 2);
+    }
+
+    /// Multi-file fixture with every piece written and synced
+    async fn written_multi(dir: &Path) -> (DiskManager, Vec<u8>) {
+        let metainfo = create_test_metainfo_multi();
+        let mut dm = DiskManager::new(&metainfo, dir.to_path_buf());
+        dm.allocate_files().await.unwrap();
+        let content: Vec<u8> = (0..20000u32).map(|i| (i % 253) as u8).collect();
+        dm.write_piece(0, content[..16384].to_vec()).await.unwrap();
+        dm.write_piece(1, content[16384..].to_vec()).await.unwrap();
+        dm.sync().await.unwrap();
+        (dm, content)
+    }
+
+    #[tokio::test]
+    async fn test_mmap_reads_match_normal_reads() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let (mut dm, content) = written_multi(temp_dir.path()).await;
+        let (mmap_tx, mmap_rx) = watch::channel(false);
+        dm.set_mmap_reads(mmap_rx);
+        dm.mark_complete_files(&Bitfield::complete(2));
+
+        let mut normal = Vec::new();
+        for piece in 0..2 {
+            normal.push(dm.read_piece(piece).await.unwrap());
+        }
+        assert_eq!(dm.mapped_files(), 0);
+
+        mmap_tx.send_replace(true);
+        // Piece 0 spans both files
+        for (piece, expected) in normal.iter().enumerate() {
+            assert_eq!(&dm.read_piece(piece).await.unwrap(), expected);
+        }
+        assert_eq!(dm.mapped_files(), 2);
+        assert_eq!(normal.concat(), content);
+
+        // Blocks, including one across the file boundary at byte 10000
+        assert_eq!(dm.read_block(0, 9000, 2000).await.unwrap(), content[9000..11000]);
+        assert_eq!(dm.read_block(1, 0, 3616).await.unwrap(), content[16384..]);
+        assert!(dm.read_block(1, 3000, 1000).await.is_err());
+
+        // Turning the setting off drops the maps on the next read
+        mmap_tx.send_replace(false);
+        assert_eq!(dm.read_piece(1).await.unwrap(), normal[1]);
+        assert_eq!(dm.mapped_files(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_mmap_skips_files_being_written() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let (mut dm, content) = written_multi(temp_dir.path()).await;
+        let (_mmap_tx, mmap_rx) = watch::channel(true);
+        dm.set_mmap_reads(mmap_rx);
+
+        // Incomplete: file2 still misses piece 1
+        let mut bitfield = Bitfield::new(2);
+        bitfield.set_piece(0);
+        dm.mark_complete_files(&bitfield);
+        assert_eq!(dm.read_block(0, 0, 100).await.unwrap(), content[..100]);
+        assert_eq!(dm.mapped_files(), 1, "file1 is complete");
+        assert_eq!(dm.read_piece(1).await.unwrap(), content[16384..]);
+        assert_eq!(dm.mapped_files(), 1);
+
+        // A write in flight: file1 is unmapped and read normally until synced
+        dm.mark_complete_files(&Bitfield::complete(2));
+        dm.write_piece(0, content[..16384].to_vec()).await.unwrap();
+        assert_eq!(dm.mapped_files(), 0);
+        assert_eq!(dm.read_piece(0).await.unwrap(), content[..16384]);
+        assert_eq!(dm.mapped_files(), 0);
+
+        dm.sync().await.unwrap();
+        dm.mark_complete_files(&Bitfield::complete(2));
+        assert_eq!(dm.read_piece(0).await.unwrap(), content[..16384]);
+        assert_eq!(dm.mapped_files(), 2);
+
+        dm.delete_files().await.unwrap();
+        assert_eq!(dm.mapped_files(), 0);
+    }
+
+    /// Allocated multi-file torrent created at `creation_date`, with
+    /// completion mtimes switched `on` or off
+    async fn allocated_multi(dir: &Path, creation_date: Option<i64>, on: bool) -> DiskManager {
+        let mut metainfo = create_test_metainfo_multi();
+        metainfo.creation_date = creation_date;
+        let mut dm = DiskManager::new(&metainfo, dir.to_path_buf());
+        dm.allocate_files().await.unwrap();
+        dm.set_completion_mtimes(watch::channel(on).1);
+        dm
+    }
+
+    fn mtime_secs(path: &Path) -> u64 {
+        std::fs::metadata(path)
+            .unwrap()
+            .modified()
+            .unwrap()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    }
+
+    #[tokio::test]
+    async fn test_completion_sets_creation_date_mtimes() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let mut dm = allocated_multi(temp_dir.path(), Some(1_200_000_000), true).await;
+        let root = temp_dir.path().join("test_torrent");
+        let (file1, file2) = (root.join("file1.txt"), root.join("subdir").join("file2.txt"));
+
+        // Piece 0 completes file1 only; file2 also needs piece 1
+        let mut bitfield = Bitfield::new(2);
+        bitfield.set_piece(0);
+        assert_eq!(dm.piece_completed(0, &bitfield), vec![file1.clone()]);
+        assert_eq!(mtime_secs(&file1), 1_200_000_000);
+        assert_ne!(mtime_secs(&file2), 1_200_000_000);
+        assert_ne!(mtime_secs(&root), 1_200_000_000);
+
+        // The last piece completes file2 and the torrent, so the folder too
+        bitfield.set_piece(1);
+        assert_eq!(dm.piece_completed(1, &bitfield), vec![file2.clone(), root.clone()]);
+        assert_eq!(mtime_secs(&file2), 1_200_000_000);
+        assert_eq!(mtime_secs(&root), 1_200_000_000);
+
+        // A piece completing again (re-download after a failed recheck) leaves files alone
+        assert!(dm.piece_completed(1, &bitfield).iter().all(|path| path == &root));
+    }
+
+    #[tokio::test]
+    async fn test_completion_mtimes_off_or_undated() {
+        for (creation_date, on) in [(Some(1_200_000_000), false), (None, true)] {
+            let temp_dir = tempfile::TempDir::new().unwrap();
+            let mut dm = allocated_multi(temp_dir.path(), creation_date, on).await;
+            let file1 = temp_dir.path().join("test_torrent").join("file1.txt");
+            let before = mtime_secs(&file1);
+
+            assert!(dm.piece_completed(0, &Bitfield::complete(2)).is_empty());
+            assert!(dm.piece_completed(1, &Bitfield::complete(2)).is_empty());
+            assert_eq!(mtime_secs(&file1), before);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_completion_mtimes_skip_existing_and_missing_files() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let mut dm = allocated_multi(temp_dir.path(), Some(1_200_000_000), true).await;
+        let root = temp_dir.path().join("test_torrent");
+        let (file1, file2) = (root.join("file1.txt"), root.join("subdir").join("file2.txt"));
+        let before = mtime_secs(&file1);
+
+        // file1 was already complete on disk when the torrent was loaded
+        let mut bitfield = Bitfield::new(2);
+        bitfield.set_piece(0);
+        dm.mark_complete_files(&bitfield);
+        // file2 was renamed away in the meantime
+        std::fs::rename(&file2, root.join("subdir").join("renamed.txt")).unwrap();
+
+        bitfield.set_piece(1);
+        assert_eq!(dm.piece_completed(1, &bitfield), vec![root.clone()]);
+        assert_eq!(mtime_secs(&file1), before);
+        assert_ne!(mtime_secs(&root.join("subdir").join("renamed.txt")), 1_200_000_000);
+    }
+
     #[tokio::test]
     async fn test_queue_and_flush_writes() {
         let metainfo = create_test_metainfo_single();
@@ -477,4 +1466,58 @@ mod tests {
         dm.delete_files().await.unwrap();
         let _ = tokio::fs::remove_dir_all(download_dir).await;
     }
+
+    #[tokio::test]
+    async fn test_truncated_and_sparse_pieces() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let mut dm = DiskManager::new(&create_test_metainfo_multi(), temp_dir.path().to_path_buf());
+        dm.allocate_files().await.unwrap();
+
+        // Preallocated only, then written
+        #[cfg(target_os = "linux")]
+        assert_eq!(dm.piece_is_sparse(1), Some(true));
+        dm.write_piece(1, vec![7u8; 20000 - 16384]).await.unwrap();
+        assert_eq!(dm.piece_is_sparse(1), Some(false));
+
+        let claimed = Bitfield::complete(2);
+        assert!(dm.truncated_pieces(&claimed).is_empty());
+
+        // file1 (piece 0) cut short
+        let file1 = dm.files()[0].path.clone();
+        let file2 = dm.files()[1].path.clone();
+        std::fs::File::options().write(true).open(&file1).unwrap().set_len(5000).unwrap();
+        assert_eq!(dm.truncated_pieces(&claimed), vec![0]);
+
+        // file2's missing tail is in piece 1 alone; unclaimed pieces don't count
+        std::fs::File::options().write(true).open(&file2).unwrap().set_len(8000).unwrap();
+        assert_eq!(dm.truncated_pieces(&claimed), vec![0, 1]);
+        let mut only_first = Bitfield::new(2);
+        only_first.set_piece(0);
+        assert_eq!(dm.truncated_pieces(&only_first), vec![0]);
+
+        // A missing file is not a short one
+        std::fs::remove_file(&file1).unwrap();
+        assert_eq!(dm.truncated_pieces(&claimed), vec![1]);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_write_breaks_recorded_links() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let mut dm = DiskManager::new(&create_test_metainfo_multi(), temp_dir.path().to_path_buf());
+        dm.allocate_files().await.unwrap();
+
+        // Another torrent's copy of file1, linked in its place
+        let file1 = dm.files()[0].path.clone();
+        let other = temp_dir.path().join("other.txt");
+        std::fs::write(&other, vec![3u8; 10000]).unwrap();
+        std::fs::remove_file(&file1).unwrap();
+        std::fs::hard_link(&other, &file1).unwrap();
+        dm.set_linked_files(&[0]);
+
+        dm.write_piece(0, vec![9u8; 16384]).await.unwrap();
+        assert_eq!(std::fs::read(&other).unwrap(), vec![3u8; 10000]);
+        assert_eq!(std::fs::read(&file1).unwrap(), vec![9u8; 10000]);
+        assert_eq!(crate::duplicates::same_file(&file1, &other), Some(false));
+    }
 }
@@ -0,0 +1,263 @@
+//! Consolidated storage for torrents made of many tiny files
+//!
+//! Creating tens of thousands of files up front, and opening several of
+//! them for every piece written, is slow on most filesystems. A torrent in
+//! `StorageMode::Consolidated` keeps all of its data in one preallocated
+//! `<root name>.scdata` file, laid out in the torrent's linear byte space,
+//! so pieces map onto it with the same offset math as onto the real files.
+//! `DiskManager::extract` splits it into the real files once the torrent is
+//! complete.
+
+use super::{DiskError, DiskManager, FileInfo};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use tokio::fs::{File, OpenOptions};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use std::io::SeekFrom;
+
+/// Extension of the consolidated data file
+pub const PARTS_EXTENSION: &str = "scdata";
+
+/// File count from which consolidated storage is suggested when adding
+pub const SUGGEST_CONSOLIDATED_FILES: usize = 5_000;
+
+/// How a torrent's data is stored until it completes
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StorageMode {
+    /// Straight into the torrent's own files
+    #[default]
+    Files,
+    /// In a single `.scdata` file, extracted on completion
+    Consolidated,
+}
+
+impl StorageMode {
+    /// The mode to suggest when adding a torrent
+    pub fn suggested(info: &crate::torrent::TorrentInfo) -> Self {
+        if !info.is_single_file && info.files.len() >= SUGGEST_CONSOLIDATED_FILES {
+            Self::Consolidated
+        } else {
+            Self::Files
+        }
+    }
+}
+
+/// Consolidated data file of the torrent stored as `root_name` in `download_dir`
+pub fn parts_path(download_dir: &Path, root_name: &str) -> PathBuf {
+    download_dir.join(format!("{}.{}", root_name, PARTS_EXTENSION))
+}
+
+impl DiskManager {
+    /// Store the data consolidated or in the real files. Single-file
+    /// torrents gain nothing from it and always use their file.
+    pub fn set_storage_mode(&mut self, mode: StorageMode) {
+        let root_name = self.root_dir.as_ref().and_then(|root_dir| root_dir.file_name());
+        self.parts = match (mode, root_name) {
+            (StorageMode::Consolidated, Some(root_name)) => Some(FileInfo {
+                path: parts_path(&self.download_dir, &root_name.to_string_lossy()),
+                length: self.total_size,
+                offset: 0,
+            }),
+            _ => None,
+        };
+    }
+
+    /// How the data is stored right now
+    pub fn storage_mode(&self) -> StorageMode {
+        if self.parts.is_some() {
+            StorageMode::Consolidated
+        } else {
+            StorageMode::Files
+        }
+    }
+
+    /// Split the consolidated file into the torrent's files and delete it;
+    /// the manager uses the real files from then on. Only call once every
+    /// piece is verified. Does nothing in `StorageMode::Files`.
+    pub async fn extract(&mut self) -> Result<(), DiskError> {
+        let Some(parts) = self.parts.clone() else {
+            return Ok(());
+        };
+        self.sync().await?;
+
+        let mut source = File::open(&parts.path)
+            .await
+            .map_err(|e| DiskError::from_io(&parts.path, e))?;
+        for file_info in &self.files {
+            if let Some(parent) = file_info.path.parent() {
+                tokio::fs::create_dir_all(parent)
+                    .await
+                    .map_err(|e| DiskError::from_io(parent, e))?;
+            }
+            let mut file = OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(&file_info.path)
+                .await
+                .map_err(|e| DiskError::from_io(&file_info.path, e))?;
+
+            source
+                .seek(SeekFrom::Start(file_info.offset))
+                .await
+                .map_err(|e| DiskError::from_io(&parts.path, e))?;
+            let copied = tokio::io::copy(&mut (&mut source).take(file_info.length), &mut file)
+                .await
+                .map_err(|e| DiskError::from_io(&file_info.path, e))?;
+            if copied != file_info.length {
+                let short = std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "consolidated file is too short");
+                return Err(DiskError::from_io(&parts.path, short));
+            }
+            file.sync_data()
+                .await
+                .map_err(|e| DiskError::from_io(&file_info.path, e))?;
+        }
+        drop(source);
+
+        tokio::fs::remove_file(&parts.path)
+            .await
+            .map_err(|e| DiskError::from_io(&parts.path, e))?;
+        self.parts = None;
+        let extracted: Vec<PathBuf> = self.files.iter().map(|f| f.path.clone()).collect();
+        self.complete_files.extend(extracted.iter().cloned());
+        self.stamp_mtimes(&extracted, true);
+
+        tracing::info!("Extracted {} files from {:?}", self.files.len(), parts.path);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::piece::Bitfield;
+    use crate::torrent::{FileInfo as TorrentFileInfo, Metainfo, TorrentInfo};
+
+    /// Five files, the middle one empty, spread over 3 pieces of 16 KiB
+    fn metainfo() -> Metainfo {
+        let lengths = [7000u64, 20000, 0, 5000, 9000];
+        Metainfo {
+            announce: "http://tracker.example.com".to_string(),
+            announce_list: vec![],
+            info: TorrentInfo {
+                piece_length: 16384,
+                pieces: vec![0u8; 60],
+                piece_count: 3,
+                files: lengths
+                    .iter()
+                    .enumerate()
+                    .map(|(i, &length)| TorrentFileInfo {
+                        path: vec![format!("dir{}", i % 2), format!("img{}.png", i)],
+                        length,
+                    })
+                    .collect(),
+                name: "Images".to_string(),
+                total_size: lengths.iter().sum(),
+                is_single_file: false,
+                private: false,
+                legacy_names: None,
+            },
+            info_hash: [0u8; 20],
+            creation_date: None,
+            comment: None,
+            created_by: None,
+        }
+    }
+
+    /// Download every piece (in a scrambled order) into `dir`
+    async fn download(dir: &Path, mode: StorageMode, content: &[u8]) -> DiskManager {
+        let mut dm = DiskManager::new(&metainfo(), dir.to_path_buf());
+        dm.set_storage_mode(mode);
+        dm.check_layout().unwrap();
+        dm.allocate_files().await.unwrap();
+        for piece in [2, 0, 1] {
+            let (offset, size) = dm.piece_span(piece);
+            let data = content[offset as usize..offset as usize + size].to_vec();
+            dm.write_piece(piece, data).await.unwrap();
+        }
+        dm.sync().await.unwrap();
+        dm
+    }
+
+    fn tree(dir: &Path) -> Vec<(PathBuf, Vec<u8>)> {
+        let mut entries: Vec<(PathBuf, Vec<u8>)> = walk(dir)
+            .into_iter()
+            .map(|path| (path.strip_prefix(dir).unwrap().to_path_buf(), std::fs::read(&path).unwrap()))
+            .collect();
+        entries.sort();
+        entries
+    }
+
+    fn walk(dir: &Path) -> Vec<PathBuf> {
+        let mut files = Vec::new();
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                files.extend(walk(&path));
+            } else {
+                files.push(path);
+            }
+        }
+        files
+    }
+
+    #[test]
+    fn test_suggested_mode() {
+        let mut info = metainfo().info;
+        assert_eq!(StorageMode::suggested(&info), StorageMode::Files);
+
+        let file = info.files[0].clone();
+        info.files = vec![file; SUGGEST_CONSOLIDATED_FILES];
+        assert_eq!(StorageMode::suggested(&info), StorageMode::Consolidated);
+        info.is_single_file = true;
+        assert_eq!(StorageMode::suggested(&info), StorageMode::Files);
+    }
+
+    #[tokio::test]
+    async fn test_consolidated_download_extracts_to_the_normal_layout() {
+        let content: Vec<u8> = (0..41000u32).map(|i| (i % 251) as u8).collect();
+        let normal_dir = tempfile::TempDir::new().unwrap();
+        let parts_dir = tempfile::TempDir::new().unwrap();
+        download(normal_dir.path(), StorageMode::Files, &content).await;
+        let mut dm = download(parts_dir.path(), StorageMode::Consolidated, &content).await;
+
+        // Only the one data file exists while downloading; uploads read from it
+        assert_eq!(walk(parts_dir.path()), vec![parts_dir.path().join("Images.scdata")]);
+        assert_eq!(dm.read_block(0, 6000, 2000).await.unwrap(), content[6000..8000]);
+        assert_eq!(dm.read_piece(2).await.unwrap(), content[32768..]);
+
+        dm.extract().await.unwrap();
+        assert_eq!(dm.storage_mode(), StorageMode::Files);
+        assert_eq!(tree(parts_dir.path()), tree(normal_dir.path()));
+        assert!(!parts_dir.path().join("Images.scdata").exists());
+
+        // Reads now come from the real files
+        assert_eq!(dm.read_block(0, 6000, 2000).await.unwrap(), content[6000..8000]);
+        dm.mark_complete_files(&Bitfield::complete(3));
+        assert!(dm.files_exist().await);
+    }
+
+    #[tokio::test]
+    async fn test_single_file_torrents_are_not_consolidated() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let mut metainfo = metainfo();
+        metainfo.info.is_single_file = true;
+        let mut dm = DiskManager::new(&metainfo, temp_dir.path().to_path_buf());
+
+        dm.set_storage_mode(StorageMode::Consolidated);
+        assert_eq!(dm.storage_mode(), StorageMode::Files);
+        dm.extract().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_delete_consolidated_keeps_download_dir() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        std::fs::write(temp_dir.path().join("unrelated.txt"), b"keep").unwrap();
+        let content = vec![7u8; 41000];
+        let dm = download(temp_dir.path(), StorageMode::Consolidated, &content).await;
+
+        dm.delete_files().await.unwrap();
+        assert_eq!(walk(temp_dir.path()), vec![temp_dir.path().join("unrelated.txt")]);
+    }
+}
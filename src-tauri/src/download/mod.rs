@@ -5,6 +5,32 @@
 // - Debrid services (via DebridManager)
 // - Hybrid (both P2P and Debrid simultaneously)
 // - HTTP/HTTPS direct downloads
+//
+// Every source keys its torrent list entry by info hash, so adding a torrent
+// that is already downloading from the other kind of source goes through
+// `DownloadOrchestrator::admit`: it is refused, or on request both run as one
+// hybrid entry. In a hybrid the P2P side owns the entry (state, name, size)
+// and the cloud side only reports its file progress.
+
+use crate::debrid::types::{DebridProviderType, DownloadSource};
+use crate::error::{Error, Result};
+use crate::ids::DebridTorrentId;
+
+/// Kind of download an add starts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Adding {
+    P2P,
+    Cloud,
+}
+
+/// What an add does about a download already under the same info hash
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Admission {
+    /// Add as usual; a download of the same kind is replaced, as it always was
+    Proceed,
+    /// Join the existing download as one hybrid entry
+    Join,
+}
 
 /// Download orchestrator that manages downloads from various sources
 pub struct DownloadOrchestrator {
@@ -15,6 +41,40 @@ impl DownloadOrchestrator {
     pub fn new() -> Self {
         Self {}
     }
+
+    /// Decide an add of `torrent_id` from `adding` while `existing` is its
+    /// current source, if any. A download from the other kind of source is
+    /// only joined with `hybrid`; a hybrid already has both.
+    pub fn admit(torrent_id: &str, existing: Option<&DownloadSource>, adding: Adding, hybrid: bool) -> Result<Admission> {
+        let Some(existing) = existing else {
+            return Ok(Admission::Proceed);
+        };
+        let same_kind = matches!(
+            (existing, adding),
+            (DownloadSource::P2P, Adding::P2P) | (DownloadSource::Debrid { .. }, Adding::Cloud)
+        );
+        if same_kind {
+            Ok(Admission::Proceed)
+        } else if hybrid && !existing.is_hybrid() {
+            Ok(Admission::Join)
+        } else {
+            Err(Error::AlreadyExistsWithOtherSource {
+                torrent_id: torrent_id.to_string(),
+                existing: existing.clone(),
+            })
+        }
+    }
+
+    /// Source of a P2P download joined by its copy on a debrid provider.
+    /// Both sides start out fetching every file (neither list is set).
+    pub fn hybrid_source(provider: DebridProviderType, debrid_torrent_id: DebridTorrentId) -> DownloadSource {
+        DownloadSource::Hybrid {
+            debrid_provider: provider,
+            debrid_torrent_id,
+            debrid_file_ids: Vec::new(),
+            p2p_file_ids: Vec::new(),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -25,4 +85,34 @@ mod tests {
     fn test_orchestrator_creation() {
         let _orchestrator = DownloadOrchestrator::new();
     }
+
+    #[test]
+    fn test_admission() {
+        let id = "0123456789abcdef0123456789abcdef01234567";
+        let cloud = DownloadSource::Debrid {
+            provider: DebridProviderType::RealDebrid,
+            torrent_id: DebridTorrentId::parse("RD1").unwrap(),
+        };
+        let hybrid = DownloadOrchestrator::hybrid_source(DebridProviderType::RealDebrid, DebridTorrentId::parse("RD1").unwrap());
+
+        for adding in [Adding::P2P, Adding::Cloud] {
+            assert_eq!(DownloadOrchestrator::admit(id, None, adding, false).unwrap(), Admission::Proceed);
+        }
+        // Same kind: replaced as before
+        assert_eq!(DownloadOrchestrator::admit(id, Some(&DownloadSource::P2P), Adding::P2P, false).unwrap(), Admission::Proceed);
+        assert_eq!(DownloadOrchestrator::admit(id, Some(&cloud), Adding::Cloud, true).unwrap(), Admission::Proceed);
+
+        // Other kind: refused with what is there, or joined on request
+        let err = DownloadOrchestrator::admit(id, Some(&DownloadSource::P2P), Adding::Cloud, false).unwrap_err();
+        assert!(matches!(&err, Error::AlreadyExistsWithOtherSource { existing: DownloadSource::P2P, .. }));
+        assert_eq!(err.to_string(), format!("Already added from another source: {} is a P2P download", id));
+        assert!(DownloadOrchestrator::admit(id, Some(&cloud), Adding::P2P, false).is_err());
+        assert_eq!(DownloadOrchestrator::admit(id, Some(&DownloadSource::P2P), Adding::Cloud, true).unwrap(), Admission::Join);
+        assert_eq!(DownloadOrchestrator::admit(id, Some(&cloud), Adding::P2P, true).unwrap(), Admission::Join);
+
+        // A hybrid already has both
+        for adding in [Adding::P2P, Adding::Cloud] {
+            assert!(DownloadOrchestrator::admit(id, Some(&hybrid), adding, true).is_err());
+        }
+    }
 }
@@ -0,0 +1,662 @@
+//! Torrents holding the same content
+//!
+//! Two torrents share content when their piece hashes match (which only
+//! happens with the same piece length and the data at the same offsets in
+//! the piece stream) or when they have files of the same size whose sampled
+//! hashes match: the first and last `SAMPLE_BYTES` of complete files on disk.
+//! Detection only reads those samples, so it stays cheap on large libraries;
+//! a sample match is a strong hint, not proof.
+//!
+//! Relinking goes further: after comparing every byte, one torrent's copy of
+//! a shared file is replaced by a hard link to the other's, so the data is
+//! stored once. Both sessions record the link (`LinkedFile`). Removing a
+//! name never touches the other one, so deleting either torrent's files is
+//! safe; what is not is writing through a shared inode, which would change
+//! both torrents' data. The disk manager therefore breaks a recorded link
+//! by copying before it writes into the file (see `DiskManager::set_linked_files`).
+
+use crate::database::TorrentSession;
+use crate::disk::{DiskManager, StorageMode};
+use crate::piece::{ranges, Bitfield};
+use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+/// Bytes sampled at each end of a file
+pub const SAMPLE_BYTES: u64 = 1024 * 1024;
+
+/// Smaller files aren't compared by samples: same-size coincidences are too
+/// likely and there is little space to win back
+pub const MIN_FILE_SIZE: u64 = SAMPLE_BYTES;
+
+/// What detection knows about one torrent
+#[derive(Debug, Clone)]
+pub struct TorrentContent {
+    pub torrent_id: String,
+    pub name: String,
+    pub piece_length: u64,
+    /// Hash and size of every piece
+    pub pieces: Vec<([u8; 20], u64)>,
+    pub files: Vec<ContentFile>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ContentFile {
+    pub path: PathBuf,
+    pub size: u64,
+    /// Sampled hash; None unless the file is complete, on disk and at least
+    /// `MIN_FILE_SIZE`
+    pub sample: Option<[u8; 20]>,
+}
+
+impl TorrentContent {
+    /// Gather a session's pieces and sample its complete files. Blocking.
+    pub fn from_session(session: &TorrentSession) -> Self {
+        let info = &session.metainfo.info;
+        let piece_length = info.piece_length;
+        let pieces = info
+            .pieces
+            .chunks_exact(20)
+            .enumerate()
+            .map(|(index, hash)| {
+                let mut bytes = [0u8; 20];
+                bytes.copy_from_slice(hash);
+                let span = ranges::piece_span(index, piece_length, info.total_size);
+                (bytes, span.end - span.start)
+            })
+            .collect();
+
+        let bitfield = Bitfield::from_bytes(session.bitfield.clone(), info.piece_count);
+        let spans = ranges::file_spans(&info.files);
+        let files = if info.piece_count == 0 {
+            Vec::new()
+        } else {
+            let disk = DiskManager::with_root_name(&session.metainfo, PathBuf::from(&session.download_dir), session.root_name());
+            disk.files()
+                .iter()
+                .zip(spans)
+                .map(|(file, span)| {
+                    // Consolidated data isn't in the torrent's files until extracted
+                    let complete = session.storage_mode == StorageMode::Files
+                        && ranges::pieces_covering(&span, piece_length).all(|piece| bitfield.has_piece(piece));
+                    let sample = (complete && file.length >= MIN_FILE_SIZE)
+                        .then(|| sample_file(&file.path, file.length).ok())
+                        .flatten();
+                    ContentFile { path: file.path.clone(), size: file.length, sample }
+                })
+                .collect()
+        };
+
+        Self {
+            torrent_id: session.id.clone(),
+            name: info.name.clone(),
+            piece_length,
+            pieces,
+            files,
+        }
+    }
+}
+
+/// Hash of a file's size, first and last `SAMPLE_BYTES`. Fails if the file
+/// on disk isn't `size` bytes long.
+pub fn sample_file(path: &Path, size: u64) -> std::io::Result<[u8; 20]> {
+    let mut file = File::open(path)?;
+    if file.metadata()?.len() != size {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "size differs from the torrent's"));
+    }
+    let mut hasher = Sha1::new();
+    hasher.update(size.to_le_bytes());
+    let mut buf = vec![0u8; SAMPLE_BYTES.min(size) as usize];
+    file.read_exact(&mut buf)?;
+    hasher.update(&buf);
+    file.seek(SeekFrom::Start(size - buf.len() as u64))?;
+    file.read_exact(&mut buf)?;
+    hasher.update(&buf);
+
+    let mut sample = [0u8; 20];
+    sample.copy_from_slice(&hasher.finalize());
+    Ok(sample)
+}
+
+/// Which evidence the overlap's byte count comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MatchKind {
+    Pieces,
+    Files,
+}
+
+/// A file of one torrent matching a file of another by size and samples
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FileMatch {
+    pub file_index: usize,
+    pub other_file_index: usize,
+    pub size: u64,
+}
+
+/// Content two torrents appear to share
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Overlap {
+    pub torrent_id: String,
+    pub other_torrent_id: String,
+    /// The larger of `piece_bytes` and `file_bytes`
+    pub bytes: u64,
+    pub kind: MatchKind,
+    /// Bytes in pieces with the same hash
+    pub piece_bytes: u64,
+    /// Bytes in files with the same size and samples
+    pub file_bytes: u64,
+    pub files: Vec<FileMatch>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ClusterMember {
+    pub torrent_id: String,
+    pub name: String,
+}
+
+/// Torrents connected by overlaps, with every overlap between them
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DuplicateCluster {
+    pub torrents: Vec<ClusterMember>,
+    /// Largest overlap first
+    pub overlaps: Vec<Overlap>,
+}
+
+#[derive(Default)]
+struct PairTally {
+    piece_bytes: u64,
+    files: Vec<FileMatch>,
+}
+
+/// Per torrent holding a piece hash: how many of its pieces have it, and their size
+type PieceHolders = BTreeMap<usize, (u64, u64)>;
+
+/// Overlaps between every pair of torrents, keyed by their positions
+fn pair_overlaps(contents: &[&TorrentContent]) -> BTreeMap<(usize, usize), Overlap> {
+    let mut tallies: BTreeMap<(usize, usize), PairTally> = BTreeMap::new();
+
+    // Piece hashes, counted per torrent: a hash repeated inside one torrent
+    // (padding, silence) only matches as often as the other has it too
+    let mut by_hash: HashMap<(u64, [u8; 20]), PieceHolders> = HashMap::new();
+    for (t, content) in contents.iter().enumerate() {
+        for &(hash, size) in &content.pieces {
+            let entry = by_hash.entry((content.piece_length, hash)).or_default().entry(t).or_insert((0, size));
+            entry.0 += 1;
+        }
+    }
+    for holders in by_hash.values().filter(|holders| holders.len() > 1) {
+        let holders: Vec<_> = holders.iter().collect();
+        for (i, &(&a, &(count_a, size))) in holders.iter().enumerate() {
+            for &(&b, &(count_b, _)) in &holders[i + 1..] {
+                tallies.entry((a, b)).or_default().piece_bytes += count_a.min(count_b) * size;
+            }
+        }
+    }
+
+    let mut by_sample: HashMap<(u64, [u8; 20]), BTreeMap<usize, Vec<usize>>> = HashMap::new();
+    for (t, content) in contents.iter().enumerate() {
+        for (index, file) in content.files.iter().enumerate() {
+            if let Some(sample) = file.sample {
+                by_sample.entry((file.size, sample)).or_default().entry(t).or_default().push(index);
+            }
+        }
+    }
+    for (&(size, _), holders) in by_sample.iter().filter(|(_, holders)| holders.len() > 1) {
+        let holders: Vec<_> = holders.iter().collect();
+        for (i, &(&a, files_a)) in holders.iter().enumerate() {
+            for &(&b, files_b) in &holders[i + 1..] {
+                let tally = tallies.entry((a, b)).or_default();
+                tally.files.extend(files_a.iter().zip(files_b).map(|(&file_index, &other_file_index)| FileMatch {
+                    file_index,
+                    other_file_index,
+                    size,
+                }));
+            }
+        }
+    }
+
+    tallies
+        .into_iter()
+        .map(|((a, b), mut tally)| {
+            tally.files.sort_by_key(|m| (m.file_index, m.other_file_index));
+            let file_bytes = tally.files.iter().map(|m| m.size).sum();
+            let kind = if tally.piece_bytes >= file_bytes { MatchKind::Pieces } else { MatchKind::Files };
+            let overlap = Overlap {
+                torrent_id: contents[a].torrent_id.clone(),
+                other_torrent_id: contents[b].torrent_id.clone(),
+                bytes: tally.piece_bytes.max(file_bytes),
+                kind,
+                piece_bytes: tally.piece_bytes,
+                file_bytes,
+                files: tally.files,
+            };
+            ((a, b), overlap)
+        })
+        .collect()
+}
+
+/// Content `a` and `b` appear to share; None if nothing matches
+pub fn overlap(a: &TorrentContent, b: &TorrentContent) -> Option<Overlap> {
+    pair_overlaps(&[a, b]).remove(&(0, 1))
+}
+
+/// Group torrents that share content, largest overlap first
+pub fn find_clusters(contents: &[TorrentContent]) -> Vec<DuplicateCluster> {
+    let refs: Vec<&TorrentContent> = contents.iter().collect();
+    let overlaps = pair_overlaps(&refs);
+
+    let mut parent: Vec<usize> = (0..contents.len()).collect();
+    fn root(parent: &mut [usize], mut t: usize) -> usize {
+        while parent[t] != t {
+            parent[t] = parent[parent[t]];
+            t = parent[t];
+        }
+        t
+    }
+    for &(a, b) in overlaps.keys() {
+        let (ra, rb) = (root(&mut parent, a), root(&mut parent, b));
+        parent[ra.max(rb)] = ra.min(rb);
+    }
+
+    let mut clusters: BTreeMap<usize, DuplicateCluster> = BTreeMap::new();
+    for ((a, _), overlap) in overlaps {
+        let cluster = clusters.entry(root(&mut parent, a)).or_insert_with(|| DuplicateCluster {
+            torrents: Vec::new(),
+            overlaps: Vec::new(),
+        });
+        cluster.overlaps.push(overlap);
+    }
+    for (t, content) in contents.iter().enumerate() {
+        if let Some(cluster) = clusters.get_mut(&root(&mut parent, t)) {
+            cluster.torrents.push(ClusterMember { torrent_id: content.torrent_id.clone(), name: content.name.clone() });
+        }
+    }
+
+    let mut clusters: Vec<DuplicateCluster> = clusters.into_values().collect();
+    for cluster in &mut clusters {
+        cluster.overlaps.sort_by(|a, b| b.bytes.cmp(&a.bytes));
+    }
+    clusters.sort_by(|a, b| b.overlaps[0].bytes.cmp(&a.overlaps[0].bytes));
+    clusters
+}
+
+/// A file of this torrent hard-linked to a file of another
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LinkedFile {
+    pub file_index: usize,
+    pub torrent_id: String,
+    pub other_file_index: usize,
+}
+
+/// A matched file that was left as it was
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SkippedFile {
+    pub path: String,
+    pub reason: String,
+}
+
+/// Result of `relink_duplicates`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct RelinkReport {
+    /// Files now linked, including ones that already were
+    pub linked: Vec<FileMatch>,
+    /// Space freed by the new links
+    pub bytes_saved: u64,
+    pub skipped: Vec<SkippedFile>,
+}
+
+/// Replace `member`'s copy of each matched file with a hard link to
+/// `canonical`'s, once every byte is compared. Blocking.
+pub fn relink(member: &TorrentContent, canonical: &TorrentContent, matches: &[FileMatch]) -> RelinkReport {
+    let mut report = RelinkReport::default();
+    for file_match in matches {
+        let (Some(file), Some(original)) =
+            (member.files.get(file_match.file_index), canonical.files.get(file_match.other_file_index))
+        else {
+            continue;
+        };
+        match link_file(&file.path, &original.path) {
+            Ok(saved) => {
+                report.bytes_saved += saved;
+                report.linked.push(file_match.clone());
+            }
+            Err(reason) => {
+                tracing::warn!("Not relinking {:?}: {}", file.path, reason);
+                report.skipped.push(SkippedFile { path: file.path.to_string_lossy().into_owned(), reason });
+            }
+        }
+    }
+    report
+}
+
+/// Make `path` a hard link to `original`; returns the bytes freed
+fn link_file(path: &Path, original: &Path) -> Result<u64, String> {
+    if same_file(path, original) == Some(true) {
+        return Ok(0);
+    }
+    let dir = path.parent().ok_or("no parent directory")?;
+    let original_dir = original.parent().ok_or("no parent directory")?;
+    if let (Some(a), Some(b)) = (crate::disk::volume_id(dir), crate::disk::volume_id(original_dir)) {
+        if a != b {
+            return Err("the files are on different filesystems".to_string());
+        }
+    }
+    if !files_equal(path, original).map_err(|e| format!("failed to compare: {}", e))? {
+        return Err("contents differ".to_string());
+    }
+
+    // Link next to the file, then swap it in: the file is never missing
+    let size = std::fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+    let name = path.file_name().ok_or("no file name")?.to_string_lossy();
+    let staged = dir.join(format!(".{}.relink", name));
+    let _ = std::fs::remove_file(&staged);
+    std::fs::hard_link(original, &staged).map_err(|e| format!("failed to link: {}", e))?;
+    if let Err(e) = std::fs::rename(&staged, path) {
+        let _ = std::fs::remove_file(&staged);
+        return Err(format!("failed to replace the file: {}", e));
+    }
+    tracing::info!("Linked {:?} to {:?}", path, original);
+    Ok(size)
+}
+
+/// Whether two files have the same bytes
+fn files_equal(a: &Path, b: &Path) -> std::io::Result<bool> {
+    let (mut a, mut b) = (File::open(a)?, File::open(b)?);
+    if a.metadata()?.len() != b.metadata()?.len() {
+        return Ok(false);
+    }
+    let mut buf_a = vec![0u8; SAMPLE_BYTES as usize];
+    let mut buf_b = vec![0u8; SAMPLE_BYTES as usize];
+    loop {
+        let read = a.read(&mut buf_a)?;
+        if read == 0 {
+            return Ok(true);
+        }
+        b.read_exact(&mut buf_b[..read])?;
+        if buf_a[..read] != buf_b[..read] {
+            return Ok(false);
+        }
+    }
+}
+
+/// Whether two paths are the same file (hard links to one inode). None
+/// where the platform doesn't say.
+pub fn same_file(a: &Path, b: &Path) -> Option<bool> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        let (a, b) = (std::fs::metadata(a).ok()?, std::fs::metadata(b).ok()?);
+        Some(a.dev() == b.dev() && a.ino() == b.ino())
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = (a, b);
+        None
+    }
+}
+
+/// Record links both ways, replacing older records of the same files
+pub fn record_links(member: &mut TorrentSession, canonical: &mut TorrentSession, linked: &[FileMatch]) {
+    for file_match in linked {
+        member.linked_files.retain(|link| link.file_index != file_match.file_index);
+        member.linked_files.push(LinkedFile {
+            file_index: file_match.file_index,
+            torrent_id: canonical.id.clone(),
+            other_file_index: file_match.other_file_index,
+        });
+        canonical
+            .linked_files
+            .retain(|link| !(link.torrent_id == member.id && link.other_file_index == file_match.file_index));
+        canonical.linked_files.push(LinkedFile {
+            file_index: file_match.other_file_index,
+            torrent_id: member.id.clone(),
+            other_file_index: file_match.file_index,
+        });
+    }
+}
+
+/// Drop a session's records of links to `torrent_id`, whose files are gone;
+/// true if there were any
+pub fn forget_links_to(session: &mut TorrentSession, torrent_id: &str) -> bool {
+    let before = session.linked_files.len();
+    session.linked_files.retain(|link| link.torrent_id != torrent_id);
+    session.linked_files.len() != before
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::torrent::{FileInfo, Metainfo, TorrentInfo};
+
+    /// A single-piece torrent whose piece is the whole of its one file
+    fn session(id: &str, download_dir: &Path, name: &str, size: u64) -> TorrentSession {
+        let metainfo = Metainfo {
+            announce: String::new(),
+            announce_list: vec![],
+            info: TorrentInfo {
+                piece_length: size,
+                pieces: vec![id.as_bytes()[0]; 20],
+                piece_count: 1,
+                files: vec![FileInfo { path: vec![name.to_string()], length: size }],
+                name: name.to_string(),
+                total_size: size,
+                is_single_file: true,
+                private: false,
+                legacy_names: None,
+            },
+            info_hash: [0u8; 20],
+            creation_date: None,
+            comment: None,
+            created_by: None,
+        };
+        TorrentSession {
+            id: id.to_string(),
+            metainfo,
+            bitfield: vec![0x80],
+            num_pieces: 1,
+            downloaded: 0,
+            uploaded: 0,
+            state: "seeding".to_string(),
+            download_dir: download_dir.to_string_lossy().to_string(),
+            added_at: 0,
+            last_activity: 0,
+            source: crate::debrid::types::DownloadSource::P2P,
+            completed_at: None,
+            volume_id: None,
+            category: None,
+            traffic: Default::default(),
+            swarm: None,
+            root_name: None,
+            data_sync: Default::default(),
+            queue_position: None,
+            saved_peers: Vec::new(),
+            added_from: None,
+            storage_mode: Default::default(),
+            auto_stop: Default::default(),
+            low_disk_paused_at: None,
+            linked_files: Vec::new(),
+            disabled_trackers: Vec::new(),
+            file_completed_at: Default::default(),
+        }
+    }
+
+    /// Content with the given piece hashes (one byte each, 16 KiB pieces)
+    /// and (size, sample) files
+    fn content(id: &str, pieces: &[u8], files: &[(u64, Option<u8>)]) -> TorrentContent {
+        TorrentContent {
+            torrent_id: id.to_string(),
+            name: format!("{} name", id),
+            piece_length: 16384,
+            pieces: pieces.iter().map(|&hash| ([hash; 20], 16384)).collect(),
+            files: files
+                .iter()
+                .enumerate()
+                .map(|(i, &(size, sample))| ContentFile {
+                    path: PathBuf::from(format!("{}/{}", id, i)),
+                    size,
+                    sample: sample.map(|byte| [byte; 20]),
+                })
+                .collect(),
+        }
+    }
+
+    fn write(path: &Path, bytes: &[u8]) {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, bytes).unwrap();
+    }
+
+    #[test]
+    fn test_sample_file() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let size = (SAMPLE_BYTES * 3) as usize;
+        let a = temp_dir.path().join("a");
+        let b = temp_dir.path().join("b");
+        let mut bytes = vec![5u8; size];
+        write(&a, &bytes);
+        // Past the first sample and before the last: not looked at
+        bytes[size / 2] = 6;
+        write(&b, &bytes);
+        assert_eq!(sample_file(&a, size as u64).unwrap(), sample_file(&b, size as u64).unwrap());
+
+        bytes[size - 1] = 6;
+        write(&b, &bytes);
+        assert_ne!(sample_file(&a, size as u64).unwrap(), sample_file(&b, size as u64).unwrap());
+
+        // Small files are read whole
+        write(&a, b"tiny");
+        assert!(sample_file(&a, 4).is_ok());
+        assert!(sample_file(&a, 5).is_err());
+    }
+
+    #[test]
+    fn test_piece_overlap_counts_repeats_once_per_match() {
+        // Hash 1 is in a three times and b once
+        let a = content("a", &[1, 1, 1, 2, 3], &[]);
+        let b = content("b", &[1, 2, 9], &[]);
+        let overlap = overlap(&a, &b).unwrap();
+        assert_eq!(overlap.kind, MatchKind::Pieces);
+        assert_eq!(overlap.piece_bytes, 2 * 16384);
+        assert_eq!(overlap.bytes, 2 * 16384);
+        assert!(overlap.files.is_empty());
+
+        // Same hashes under another piece length aren't the same data
+        let mut c = content("c", &[1, 2, 3], &[]);
+        c.piece_length = 32768;
+        assert_eq!(super::overlap(&a, &c), None);
+    }
+
+    #[test]
+    fn test_file_overlap() {
+        let a = content("a", &[1], &[(5_000_000, Some(7)), (2_000_000, Some(8)), (3_000_000, None)]);
+        let b = content("b", &[2], &[(2_000_000, Some(8)), (4_000_000, Some(7)), (3_000_000, None)]);
+        let overlap = overlap(&a, &b).unwrap();
+        assert_eq!(overlap.kind, MatchKind::Files);
+        assert_eq!(overlap.file_bytes, 2_000_000);
+        assert_eq!(overlap.files, vec![FileMatch { file_index: 1, other_file_index: 0, size: 2_000_000 }]);
+    }
+
+    #[test]
+    fn test_find_clusters() {
+        let contents = vec![
+            content("a", &[1, 2], &[]),
+            content("lonely", &[40], &[(2_000_000, Some(3))]),
+            content("b", &[2, 3], &[(5_000_000, Some(9))]),
+            content("c", &[4], &[(5_000_000, Some(9))]),
+            content("d", &[20, 21], &[]),
+            content("e", &[21, 20], &[]),
+        ];
+        let clusters = find_clusters(&contents);
+        assert_eq!(clusters.len(), 2);
+
+        // a-b by a piece, b-c by a file: one cluster, the file overlap first
+        let ids: Vec<&str> = clusters[0].torrents.iter().map(|member| member.torrent_id.as_str()).collect();
+        assert_eq!(ids, vec!["a", "b", "c"]);
+        assert_eq!(clusters[0].torrents[0].name, "a name");
+        let pairs: Vec<(&str, &str, u64)> = clusters[0]
+            .overlaps
+            .iter()
+            .map(|o| (o.torrent_id.as_str(), o.other_torrent_id.as_str(), o.bytes))
+            .collect();
+        assert_eq!(pairs, vec![("b", "c", 5_000_000), ("a", "b", 16384)]);
+
+        let ids: Vec<&str> = clusters[1].torrents.iter().map(|member| member.torrent_id.as_str()).collect();
+        assert_eq!(ids, vec!["d", "e"]);
+        assert_eq!(clusters[1].overlaps[0].piece_bytes, 2 * 16384);
+    }
+
+    #[test]
+    fn test_from_session_samples_complete_files() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let mut session = session("a", temp_dir.path(), "movie.mkv", MIN_FILE_SIZE);
+        write(&temp_dir.path().join("movie.mkv"), &vec![1u8; MIN_FILE_SIZE as usize]);
+
+        let content = TorrentContent::from_session(&session);
+        assert_eq!(content.pieces, vec![([b'a'; 20], MIN_FILE_SIZE)]);
+        assert_eq!(content.files[0].path, temp_dir.path().join("movie.mkv"));
+        assert!(content.files[0].sample.is_some());
+
+        // Not downloaded, or not in its own file yet
+        session.bitfield = vec![0];
+        assert_eq!(TorrentContent::from_session(&session).files[0].sample, None);
+        session.bitfield = vec![0x80];
+        session.storage_mode = StorageMode::Consolidated;
+        assert_eq!(TorrentContent::from_session(&session).files[0].sample, None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_relink() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let root = temp_dir.path();
+        // Large enough that the middle isn't sampled
+        let size = SAMPLE_BYTES * 3;
+        let (dir_a, dir_b) = (root.join("a"), root.join("b"));
+        std::fs::create_dir_all(&dir_a).unwrap();
+        std::fs::create_dir_all(&dir_b).unwrap();
+        let mut a = session("a", &dir_a, "movie.mkv", size);
+        let mut b = session("b", &dir_b, "movie.mkv", size);
+        write(&dir_a.join("movie.mkv"), &vec![1u8; size as usize]);
+        write(&dir_b.join("movie.mkv"), &vec![1u8; size as usize]);
+
+        let (content_a, content_b) = (TorrentContent::from_session(&a), TorrentContent::from_session(&b));
+        let matches = overlap(&content_a, &content_b).unwrap().files;
+        assert_eq!(matches.len(), 1);
+
+        let report = relink(&content_a, &content_b, &matches);
+        assert_eq!(report.linked, matches);
+        assert_eq!(report.bytes_saved, size);
+        assert!(report.skipped.is_empty());
+        assert_eq!(same_file(&dir_a.join("movie.mkv"), &dir_b.join("movie.mkv")), Some(true));
+        assert_eq!(std::fs::read(dir_a.join("movie.mkv")).unwrap(), vec![1u8; size as usize]);
+        assert!(!dir_a.join(".movie.mkv.relink").exists());
+
+        // Already linked: nothing more to save
+        let report = relink(&content_a, &content_b, &matches);
+        assert_eq!((report.linked.len(), report.bytes_saved), (1, 0));
+
+        record_links(&mut a, &mut b, &report.linked);
+        record_links(&mut a, &mut b, &report.linked);
+        assert_eq!(a.linked_files, vec![LinkedFile { file_index: 0, torrent_id: "b".to_string(), other_file_index: 0 }]);
+        assert_eq!(b.linked_files, vec![LinkedFile { file_index: 0, torrent_id: "a".to_string(), other_file_index: 0 }]);
+        assert!(forget_links_to(&mut b, "a"));
+        assert!(!forget_links_to(&mut b, "a"));
+        assert!(b.linked_files.is_empty());
+
+        // Samples match but a byte in the middle doesn't
+        let mut bytes = vec![1u8; size as usize];
+        bytes[size as usize / 2] = 2;
+        let c_dir = root.join("c");
+        write(&c_dir.join("movie.mkv"), &bytes);
+        let c = session("c", &c_dir, "movie.mkv", size);
+        let content_c = TorrentContent::from_session(&c);
+        let matches = overlap(&content_c, &content_b).unwrap().files;
+        let report = relink(&content_c, &content_b, &matches);
+        assert!(report.linked.is_empty());
+        assert_eq!(report.skipped[0].reason, "contents differ");
+        assert_eq!(same_file(&c_dir.join("movie.mkv"), &dir_b.join("movie.mkv")), Some(false));
+    }
+}
@@ -0,0 +1,223 @@
+/// Bounded command queue between the UI/commands layer and a TorrentEngine
+///
+/// Ordinary commands go through a bounded queue and callers give up after
+/// `COMMAND_TIMEOUT` instead of waiting forever on a stalled engine. Stop has
+/// its own single-slot queue so it can still be delivered when the ordinary
+/// queue is full. If the engine loop is wedged and never polls either queue,
+/// the engine's `CancellationToken` is the hard stop — callers that need a
+/// guaranteed shutdown must cancel the token as well as sending Stop.
+use super::{EngineCommand, EngineMetrics, EngineStats};
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
+
+/// Capacity of the ordinary engine command queue
+pub const COMMAND_CHANNEL_CAPACITY: usize = 64;
+
+/// How long a caller waits for queue space or a reply before giving up
+pub const COMMAND_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Why a command could not be delivered to (or answered by) an engine
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommandError {
+    /// Queue stayed full or no reply arrived within the timeout
+    Unresponsive,
+    /// Engine loop has exited and dropped its receivers
+    Closed,
+}
+
+impl CommandError {
+    /// Whether a watchdog should treat the engine as stuck (as opposed to stopped)
+    pub fn is_unresponsive(&self) -> bool {
+        matches!(self, CommandError::Unresponsive)
+    }
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CommandError::Unresponsive => write!(f, "engine unresponsive"),
+            CommandError::Closed => write!(f, "engine stopped"),
+        }
+    }
+}
+
+impl std::error::Error for CommandError {}
+
+/// Cloneable sending side of an engine's command queues
+#[derive(Clone)]
+pub struct EngineHandle {
+    commands: mpsc::Sender<EngineCommand>,
+    stop: mpsc::Sender<()>,
+    dropped: Arc<AtomicU64>,
+    timeout: Duration,
+    metrics: Arc<EngineMetrics>,
+}
+
+/// Receiving side of an engine's command queues (owned by the engine loop)
+pub(crate) struct CommandQueues {
+    pub commands: mpsc::Receiver<EngineCommand>,
+    pub stop: mpsc::Receiver<()>,
+}
+
+/// Create a connected handle/queue pair
+pub(crate) fn command_channel(capacity: usize, timeout: Duration) -> (EngineHandle, CommandQueues) {
+    let (commands_tx, commands_rx) = mpsc::channel(capacity);
+    // A single pending Stop is all the engine ever needs
+    let (stop_tx, stop_rx) = mpsc::channel(1);
+
+    let handle = EngineHandle {
+        commands: commands_tx,
+        stop: stop_tx,
+        dropped: Arc::new(AtomicU64::new(0)),
+        timeout,
+        metrics: Arc::new(EngineMetrics::default()),
+    };
+    let queues = CommandQueues {
+        commands: commands_rx,
+        stop: stop_rx,
+    };
+    (handle, queues)
+}
+
+impl EngineHandle {
+    /// Queue a command, waiting at most the handle's timeout for space.
+    /// `Stop` is routed through the priority path and never waits.
+    pub async fn send(&self, cmd: EngineCommand) -> Result<(), CommandError> {
+        if matches!(cmd, EngineCommand::Stop) {
+            return self.stop();
+        }
+
+        match self.commands.send_timeout(cmd, self.timeout).await {
+            Ok(()) => Ok(()),
+            Err(mpsc::error::SendTimeoutError::Timeout(cmd)) => {
+                self.record_drop(&cmd);
+                Err(CommandError::Unresponsive)
+            }
+            Err(mpsc::error::SendTimeoutError::Closed(_)) => Err(CommandError::Closed),
+        }
+    }
+
+    /// Ask the engine to stop via the priority path, bypassing a full command queue
+    pub fn stop(&self) -> Result<(), CommandError> {
+        match self.stop.try_send(()) {
+            // A Stop already pending is as good as ours
+            Ok(()) | Err(mpsc::error::TrySendError::Full(())) => Ok(()),
+            Err(mpsc::error::TrySendError::Closed(())) => Err(CommandError::Closed),
+        }
+    }
+
+    /// Request a stats snapshot, bounding both the enqueue and the reply wait
+    pub async fn request_stats(&self) -> Result<EngineStats, CommandError> {
+        let started = std::time::Instant::now();
+        let (tx, rx) = oneshot::channel();
+        self.send(EngineCommand::GetStats(tx)).await?;
+
+        match tokio::time::timeout(self.timeout, rx).await {
+            Ok(Ok(stats)) => {
+                self.metrics.record_stats_response(started.elapsed());
+                Ok(stats)
+            }
+            Ok(Err(_)) => Err(CommandError::Closed),
+            Err(_) => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+                self.metrics.record_stats_response(started.elapsed());
+                tracing::warn!("Engine did not answer GetStats within {:?}", self.timeout);
+                Err(CommandError::Unresponsive)
+            }
+        }
+    }
+
+    /// Number of commands given up on because the engine did not keep up
+    pub fn dropped_commands(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Commands currently waiting in the ordinary queue
+    pub fn queued_commands(&self) -> usize {
+        self.commands.max_capacity() - self.commands.capacity()
+    }
+
+    /// The engine's concurrency metrics
+    pub fn metrics(&self) -> &Arc<EngineMetrics> {
+        &self.metrics
+    }
+
+    /// Snapshot of the metrics, including this queue's depth
+    pub fn metrics_snapshot(&self) -> super::EngineMetricsSnapshot {
+        super::EngineMetricsSnapshot { engine_queue: self.queued_commands(), ..self.metrics.snapshot() }
+    }
+
+    fn record_drop(&self, cmd: &EngineCommand) {
+        let total = self.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+        tracing::warn!(
+            "Engine command queue full for {:?}, dropped {:?} ({} dropped so far)",
+            self.timeout,
+            cmd,
+            total
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    const TEST_TIMEOUT: Duration = Duration::from_millis(50);
+
+    #[tokio::test]
+    async fn test_full_queue_returns_unresponsive() {
+        // Nobody drains the queues: a stalled engine
+        let (handle, _queues) = command_channel(2, TEST_TIMEOUT);
+
+        handle.send(EngineCommand::Start).await.unwrap();
+        handle.send(EngineCommand::Pause).await.unwrap();
+        assert_eq!(handle.queued_commands(), 2);
+
+        let started = Instant::now();
+        let result = tokio::time::timeout(Duration::from_secs(1), handle.send(EngineCommand::Start))
+            .await
+            .expect("send must not hang on a full queue");
+        assert_eq!(result, Err(CommandError::Unresponsive));
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert_eq!(handle.dropped_commands(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_stop_delivered_when_queue_full() {
+        let (handle, mut queues) = command_channel(1, TEST_TIMEOUT);
+        handle.send(EngineCommand::Start).await.unwrap();
+
+        // Both the explicit and the routed form skip the full queue
+        handle.stop().unwrap();
+        handle.send(EngineCommand::Stop).await.unwrap();
+
+        assert!(queues.stop.try_recv().is_ok());
+        assert_eq!(handle.dropped_commands(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_stats_reply_times_out() {
+        // Queue has room but the engine never answers
+        let (handle, _queues) = command_channel(4, TEST_TIMEOUT);
+
+        let result = tokio::time::timeout(Duration::from_secs(1), handle.request_stats())
+            .await
+            .expect("request_stats must not hang without a reply");
+        assert!(matches!(result, Err(CommandError::Unresponsive)));
+        assert_eq!(handle.dropped_commands(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_closed_engine() {
+        let (handle, queues) = command_channel(4, TEST_TIMEOUT);
+        drop(queues);
+
+        assert_eq!(handle.send(EngineCommand::Pause).await, Err(CommandError::Closed));
+        assert_eq!(handle.stop(), Err(CommandError::Closed));
+        assert_eq!(handle.dropped_commands(), 0);
+    }
+}
@@ -0,0 +1,130 @@
+//! End-to-end engine tests over loopback: announce, connect, download,
+//! verify and seed against the fixtures in `crate::testing`
+
+use super::*;
+use crate::testing::{Leecher, MiniTracker, Seeder, TestTorrent};
+use tokio::task::JoinHandle;
+
+/// 50 pieces of two blocks each, the last one shorter
+fn fixture_torrent(tracker: &MiniTracker, seed: u64) -> TestTorrent {
+    TestTorrent::generate(tracker.url(), 32768, 50, 5000, seed)
+}
+
+/// Run `engine` and start it; the task hands the engine back once stopped
+async fn start(engine: TorrentEngine) -> (EngineHandle, JoinHandle<TorrentEngine>) {
+    let handle = engine.command_handle();
+    let task = tokio::spawn(async move {
+        let mut engine = engine;
+        engine.run().await;
+        engine
+    });
+    handle.send(EngineCommand::Start).await.unwrap();
+    (handle, task)
+}
+
+/// Poll the engine's stats until `done` holds, for at most 60 seconds
+async fn wait_for(handle: &EngineHandle, what: &str, done: impl Fn(&EngineStats) -> bool) -> EngineStats {
+    let deadline = time::Instant::now() + Duration::from_secs(60);
+    loop {
+        let stats = handle.request_stats().await.unwrap();
+        if done(&stats) {
+            return stats;
+        }
+        assert!(time::Instant::now() < deadline, "timed out waiting for {}: {:?}", what, stats);
+        time::sleep(Duration::from_millis(100)).await;
+    }
+}
+
+async fn stop(handle: EngineHandle, task: JoinHandle<TorrentEngine>) -> TorrentEngine {
+    handle.stop().unwrap();
+    time::timeout(Duration::from_secs(10), task).await.expect("engine should stop").unwrap()
+}
+
+#[tokio::test]
+async fn test_downloads_and_verifies_from_seeder() {
+    let tracker = MiniTracker::start(vec![]).await;
+    let torrent = fixture_torrent(&tracker, 1);
+    let seeder = Seeder::start(&torrent, 0..50).await;
+    tracker.set_peers(vec![seeder.addr()]);
+    let temp_dir = tempfile::TempDir::new().unwrap();
+
+    let engine = TorrentEngine::new(torrent.metainfo.clone(), temp_dir.path().to_path_buf(), None);
+    let (handle, task) = start(engine).await;
+    wait_for(&handle, "seeding", |s| s.state == EngineState::Seeding).await;
+    let engine = stop(handle, task).await;
+
+    let announce = &tracker.announces()[0];
+    assert_eq!(announce.info_hash, torrent.metainfo.info_hash.to_vec());
+    assert_eq!(announce.left, torrent.metainfo.info.total_size);
+    assert_eq!(announce.port, DEFAULT_LISTEN_PORT);
+
+    assert_eq!(seeder.requested_pieces().len(), 50);
+    let pm = engine.piece_manager.read().await;
+    assert!((0..50).all(|i| pm.has_piece(i)));
+    assert_eq!(std::fs::read(temp_dir.path().join("fixture.bin")).unwrap(), *torrent.content);
+}
+
+#[tokio::test]
+async fn test_resume_downloads_only_missing_pieces() {
+    let tracker = MiniTracker::start(vec![]).await;
+    let torrent = fixture_torrent(&tracker, 2);
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let database = Arc::new(Database::open(temp_dir.path().join("db")).unwrap());
+    let download_dir = temp_dir.path().join("downloads");
+
+    // The first peer only has the first half, so the run stops there
+    let half = Seeder::start(&torrent, 0..25).await;
+    tracker.set_peers(vec![half.addr()]);
+    let mut engine = TorrentEngine::new(torrent.metainfo.clone(), download_dir.clone(), None);
+    engine.set_database(database.clone());
+    let piece_manager = engine.piece_manager();
+    let (handle, task) = start(engine).await;
+    let deadline = time::Instant::now() + Duration::from_secs(60);
+    while piece_manager.read().await.our_bitfield().count_pieces() < 25 {
+        assert!(time::Instant::now() < deadline, "timed out downloading the first half");
+        time::sleep(Duration::from_millis(100)).await;
+    }
+    stop(handle, task).await;
+
+    let session = database.load_torrent(&torrent.metainfo.info_hash_hex()).unwrap().expect("stop saves the session");
+    let mut engine = TorrentEngine::new(torrent.metainfo.clone(), download_dir.clone(), None);
+    engine.set_database(database.clone());
+    engine.restore_progress(&session.bitfield, &session.data_sync).await;
+    assert_eq!(engine.piece_manager.read().await.our_bitfield().count_pieces(), 25);
+
+    let full = Seeder::start(&torrent, 0..50).await;
+    tracker.set_peers(vec![full.addr()]);
+    let (handle, task) = start(engine).await;
+    wait_for(&handle, "seeding", |s| s.state == EngineState::Seeding).await;
+    stop(handle, task).await;
+
+    // The restored half was neither asked for again nor announced as left
+    assert_eq!(full.requested_pieces(), (25..50).collect());
+    let resumed = tracker.announces().last().cloned().unwrap();
+    assert!(resumed.left < torrent.metainfo.info.total_size / 2 + 32768, "{:?}", resumed);
+    assert_eq!(std::fs::read(download_dir.join("fixture.bin")).unwrap(), *torrent.content);
+}
+
+#[tokio::test]
+async fn test_seeds_to_a_second_leecher() {
+    let tracker = MiniTracker::start(vec![]).await;
+    let torrent = fixture_torrent(&tracker, 3);
+    let seeder = Seeder::start(&torrent, 0..50).await;
+    tracker.set_peers(vec![seeder.addr()]);
+    let temp_dir = tempfile::TempDir::new().unwrap();
+
+    let engine = TorrentEngine::new(torrent.metainfo.clone(), temp_dir.path().to_path_buf(), None);
+    let (handle, task) = start(engine).await;
+    wait_for(&handle, "seeding", |s| s.state == EngineState::Seeding).await;
+
+    let leecher = Leecher::start(&torrent).await;
+    let (tx, rx) = oneshot::channel();
+    handle.send(EngineCommand::AddManualPeer(leecher.addr(), tx)).await.unwrap();
+    assert_eq!(rx.await.unwrap(), ManualDial::Dialing);
+
+    let content = time::timeout(Duration::from_secs(60), leecher.content()).await.expect("leecher should finish");
+    assert!(content == *torrent.content, "leecher got different content");
+    let total = torrent.metainfo.info.total_size;
+    wait_for(&handle, "upload stats", |s| s.traffic.payload_uploaded >= total).await;
+    stop(handle, task).await;
+}
@@ -0,0 +1,211 @@
+//! Live concurrency metrics of one engine, for tuning
+//!
+//! Gauges and counters are plain relaxed atomics bumped where the work
+//! happens; nothing is computed until someone asks for a snapshot
+//! (`get_engine_metrics`, diagnostics). The metrics hang off the engine's
+//! `EngineHandle`, so they can be read while the engine loop holds the
+//! engine's lock.
+
+use crate::peer::PeerManagerCommand;
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+
+/// Engine loop iterations taking longer than this are counted as slow
+pub const SLOW_ITERATION: Duration = Duration::from_millis(100);
+
+/// GetStats answers taking longer than this are counted as slow
+pub const SLOW_STATS_RESPONSE: Duration = Duration::from_millis(250);
+
+/// What an `InFlight` guard counts
+#[derive(Debug, Clone, Copy)]
+pub enum Gauge {
+    /// Running per-peer handler tasks
+    PeerTasks,
+    /// Verified pieces being written to disk
+    DiskWrites,
+    /// Completed pieces waiting for the disk and piece locks or being hashed
+    Verifications,
+}
+
+#[derive(Debug, Default)]
+pub struct EngineMetrics {
+    peer_tasks: AtomicUsize,
+    disk_writes: AtomicUsize,
+    verifications: AtomicUsize,
+    blocks_in_flight: AtomicUsize,
+    iterations: AtomicU64,
+    slow_iterations: AtomicU64,
+    slow_stats_responses: AtomicU64,
+    /// The running peer manager's queue, to read its depth
+    peer_commands: Mutex<Option<mpsc::WeakSender<PeerManagerCommand>>>,
+}
+
+/// Snapshot returned by `get_engine_metrics`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct EngineMetricsSnapshot {
+    pub peer_tasks: usize,
+    /// Commands waiting in the peer manager's queue
+    pub peer_manager_queue: usize,
+    /// Commands waiting in the engine's own queue
+    pub engine_queue: usize,
+    pub disk_writes: usize,
+    pub pending_verifications: usize,
+    /// Block requests sent and not yet answered, over all peers
+    pub blocks_in_flight: usize,
+    pub loop_iterations: u64,
+    /// Loop iterations over `SLOW_ITERATION`
+    pub slow_iterations: u64,
+    /// GetStats answers over `SLOW_STATS_RESPONSE`
+    pub slow_stats_responses: u64,
+}
+
+impl EngineMetrics {
+    fn gauge(&self, gauge: Gauge) -> &AtomicUsize {
+        match gauge {
+            Gauge::PeerTasks => &self.peer_tasks,
+            Gauge::DiskWrites => &self.disk_writes,
+            Gauge::Verifications => &self.verifications,
+        }
+    }
+
+    /// Count one unit of `gauge` until the guard is dropped
+    pub fn enter(self: &Arc<Self>, gauge: Gauge) -> InFlight {
+        self.gauge(gauge).fetch_add(1, Ordering::Relaxed);
+        InFlight { metrics: self.clone(), gauge }
+    }
+
+    pub fn set_blocks_in_flight(&self, blocks: usize) {
+        self.blocks_in_flight.store(blocks, Ordering::Relaxed);
+    }
+
+    /// Follow the depth of a (new) peer manager's command queue
+    pub fn watch_peer_commands(&self, sender: &mpsc::Sender<PeerManagerCommand>) {
+        *self.peer_commands.lock().unwrap() = Some(sender.downgrade());
+    }
+
+    /// Time one engine loop iteration until the guard is dropped
+    pub fn time_iteration(&self) -> IterationTimer<'_> {
+        IterationTimer { metrics: self, started: Instant::now() }
+    }
+
+    pub fn record_iteration(&self, elapsed: Duration) {
+        self.iterations.fetch_add(1, Ordering::Relaxed);
+        if elapsed > SLOW_ITERATION {
+            self.slow_iterations.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn record_stats_response(&self, elapsed: Duration) {
+        if elapsed > SLOW_STATS_RESPONSE {
+            self.slow_stats_responses.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Current values; `engine_queue` is the caller's to fill in
+    pub fn snapshot(&self) -> EngineMetricsSnapshot {
+        let peer_manager_queue = self
+            .peer_commands
+            .lock()
+            .unwrap()
+            .as_ref()
+            .and_then(mpsc::WeakSender::upgrade)
+            .map_or(0, |sender| sender.max_capacity() - sender.capacity());
+        EngineMetricsSnapshot {
+            peer_tasks: self.peer_tasks.load(Ordering::Relaxed),
+            peer_manager_queue,
+            engine_queue: 0,
+            disk_writes: self.disk_writes.load(Ordering::Relaxed),
+            pending_verifications: self.verifications.load(Ordering::Relaxed),
+            blocks_in_flight: self.blocks_in_flight.load(Ordering::Relaxed),
+            loop_iterations: self.iterations.load(Ordering::Relaxed),
+            slow_iterations: self.slow_iterations.load(Ordering::Relaxed),
+            slow_stats_responses: self.slow_stats_responses.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Holds one unit of a gauge (see `EngineMetrics::enter`)
+#[derive(Debug)]
+pub struct InFlight {
+    metrics: Arc<EngineMetrics>,
+    gauge: Gauge,
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.metrics.gauge(self.gauge).fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Records an engine loop iteration when dropped
+pub struct IterationTimer<'a> {
+    metrics: &'a EngineMetrics,
+    started: Instant,
+}
+
+impl Drop for IterationTimer<'_> {
+    fn drop(&mut self) {
+        self.metrics.record_iteration(self.started.elapsed());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gauges_follow_guards() {
+        let metrics = Arc::new(EngineMetrics::default());
+        let first = metrics.enter(Gauge::PeerTasks);
+        let second = metrics.enter(Gauge::PeerTasks);
+        let write = metrics.enter(Gauge::DiskWrites);
+        assert_eq!(metrics.snapshot().peer_tasks, 2);
+        assert_eq!(metrics.snapshot().disk_writes, 1);
+
+        drop(first);
+        drop(write);
+        let snapshot = metrics.snapshot();
+        assert_eq!((snapshot.peer_tasks, snapshot.disk_writes, snapshot.pending_verifications), (1, 0, 0));
+        drop(second);
+        assert_eq!(metrics.snapshot().peer_tasks, 0);
+    }
+
+    #[test]
+    fn test_slow_iterations_detected() {
+        let metrics = EngineMetrics::default();
+        drop(metrics.time_iteration());
+        {
+            let _iteration = metrics.time_iteration();
+            std::thread::sleep(SLOW_ITERATION + Duration::from_millis(20));
+        }
+        let snapshot = metrics.snapshot();
+        assert_eq!((snapshot.loop_iterations, snapshot.slow_iterations), (2, 1));
+
+        metrics.record_stats_response(Duration::from_millis(10));
+        metrics.record_stats_response(Duration::from_millis(300));
+        assert_eq!(metrics.snapshot().slow_stats_responses, 1);
+    }
+
+    #[tokio::test]
+    async fn test_peer_manager_queue_depth() {
+        let metrics = EngineMetrics::default();
+        assert_eq!(metrics.snapshot().peer_manager_queue, 0);
+
+        let (tx, mut rx) = mpsc::channel(8);
+        metrics.watch_peer_commands(&tx);
+        for _ in 0..3 {
+            tx.send(PeerManagerCommand::Pause).await.unwrap();
+        }
+        assert_eq!(metrics.snapshot().peer_manager_queue, 3);
+        rx.recv().await.unwrap();
+        assert_eq!(metrics.snapshot().peer_manager_queue, 2);
+
+        // Gone with the peer manager
+        drop(rx);
+        drop(tx);
+        assert_eq!(metrics.snapshot().peer_manager_queue, 0);
+    }
+}
@@ -1,31 +1,70 @@
 /// Torrent download/upload engine
 /// Coordinates peers, pieces, disk I/O, and trackers
-use crate::database::{Database, TorrentSession};
-use crate::disk::DiskManager;
-use crate::peer::{PeerManager, PeerManagerCommand};
-use crate::piece::{PieceManager, SelectionStrategy};
-use crate::torrent::Metainfo;
-use crate::tracker::http::HttpTracker;
-use crate::tracker::{AnnounceRequest, AnnounceEvent};
+mod command;
+#[cfg(test)]
+mod e2e_tests;
+pub mod metrics;
+pub mod start_retry;
+
+pub use command::{CommandError, EngineHandle, COMMAND_CHANNEL_CAPACITY, COMMAND_TIMEOUT};
+pub use metrics::{EngineMetrics, EngineMetricsSnapshot};
+
+use crate::availability::AvailabilitySample;
+use crate::database::{Database, SessionProgress, TorrentSession};
+use crate::disk::{CreatedPaths, DiskManager, StorageMode, SyncPoint};
+use crate::events::{Event, EventSink};
+use crate::peer::manual::{ManualDial, MANUAL_PEER_SOURCE};
+use crate::peer::{DialPacer, PeerManager, PeerManagerCommand, SavedPeer, TrafficStats};
+use crate::piece::{FileCompletions, FileProgressTracker, PieceManager, PieceWindow, PiecesInfo, SelectionStrategy};
+use crate::torrent::{FileInfoUI, Metainfo, MetadataResult};
+use crate::tracker::external_ip::ExternalIp;
+use crate::tracker::http::{HttpTracker, TrackerHttpConfig};
+use crate::tracker::udp::UdpTracker;
+use crate::tracker::{AnnounceRequest, AnnounceEvent, PermanentFailure, SwarmStats, TrackerOutage, TrackerToggle};
+use crate::tracker::redact::{redact_tracker_url, redacted_host_path};
+use crate::peer::disconnect::DisconnectHistory;
+use crate::transfer_log::{TransferEvent, TransferLog};
 use crate::utils;
-use std::collections::HashSet;
+use start_retry::{StartRetry, MAX_START_ATTEMPTS};
+use std::collections::{hash_map::Entry, BTreeMap, HashMap, HashSet};
 use std::net::SocketAddr;
+use std::ops::Range;
 use std::path::PathBuf;
 use std::sync::Arc;
 use std::time::Duration;
-use tokio::sync::{mpsc, oneshot, RwLock};
+use tokio::sync::{mpsc, oneshot, watch, RwLock};
 use tokio::time;
 use tokio_util::sync::CancellationToken;
 
 /// Maximum number of concurrent peer connections
 const MAX_PEERS: usize = 50;
 
+/// Peers saved with the session for the next start
+const MAX_SAVED_PEERS: usize = 50;
+
+/// Saved peers dialled on start while the first announce is still out
+const SAVED_PEER_DIALS: usize = 8;
+
+/// Score bonus for a peer that has sent us data
+const PRODUCTIVE_PEER_SCORE: u32 = 10;
+
+/// Score taken from a peer dropped for breaking the wire protocol; more
+/// than the productive bonus, so it sinks below peers we know nothing about
+const PROTOCOL_VIOLATION_PENALTY: u32 = 2 * PRODUCTIVE_PEER_SCORE;
+
+/// Score of a peer added by hand: above anything trackers and traffic earn,
+/// so it is among the best peers dialed even when few are
+const MANUAL_PEER_SCORE: u32 = 1000;
+
 /// Interval for tracker announces (30 minutes)
 const TRACKER_ANNOUNCE_INTERVAL: Duration = Duration::from_secs(1800);
 
 /// Interval for saving progress to database (30 seconds)
 const PROGRESS_SAVE_INTERVAL: Duration = Duration::from_secs(30);
 
+/// Port announced when no settings-backed port has been provided
+pub const DEFAULT_LISTEN_PORT: u16 = 6881;
+
 /// Engine state
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum EngineState {
@@ -34,6 +73,12 @@ pub enum EngineState {
     Downloading,
     Seeding,
     Paused,
+    /// Paused by the download queue until a slot frees up
+    Queued,
+    /// Paused because every tracker refused the torrent for good
+    Unregistered,
+    /// Complete, still announcing, with the peer manager torn down
+    PausedSeeding,
     Error,
 }
 
@@ -46,10 +91,52 @@ pub struct EngineStats {
     pub download_speed: f64,  // bytes per second
     pub upload_speed: f64,    // bytes per second
     pub connected_peers: usize,
+    /// Connected peers that have every piece
+    pub connected_seeds: usize,
     pub total_peers: usize,
+    /// Swarm size last reported by the trackers (may be from an earlier run)
+    pub swarm: Option<SwarmStats>,
     pub progress: f64,        // 0.0 to 1.0
     pub eta_seconds: Option<u64>,
     pub completed_at: Option<i64>,
+    /// Commands callers gave up on because the engine did not keep up
+    pub dropped_commands: u64,
+    /// Wire traffic split into payload and overhead, including earlier runs
+    pub traffic: TrafficStats,
+    /// Why the engine is in `EngineState::Error`, or why its start is being
+    /// retried (see `start_retry`)
+    pub error: Option<String>,
+}
+
+/// Transfer totals of a peer manager
+#[derive(Debug, Clone, Copy, Default)]
+struct PeerTotals {
+    downloaded: u64,
+    uploaded: u64,
+    traffic: TrafficStats,
+}
+
+impl PeerTotals {
+    fn plus(&self, other: &PeerTotals) -> PeerTotals {
+        PeerTotals {
+            downloaded: self.downloaded + other.downloaded,
+            uploaded: self.uploaded + other.uploaded,
+            traffic: self.traffic.combined(&other.traffic),
+        }
+    }
+}
+
+/// A peer address we know about
+#[derive(Debug, Clone)]
+struct KnownPeer {
+    /// Tracker that reported it first
+    source: String,
+    /// Whether it has sent us at least one block
+    productive: bool,
+    /// See `SavedPeer::score`
+    score: u32,
+    /// See `SavedPeer::last_seen`
+    last_seen: i64,
 }
 
 /// Command to control the engine
@@ -57,9 +144,45 @@ pub struct EngineStats {
 pub enum EngineCommand {
     Start,
     Pause,
+    /// Pause because the download queue gave the slot to another torrent
+    Queue,
+    /// Keep announcing but drop every peer connection (complete torrents
+    /// only); `Start` goes back to seeding
+    PauseSeeding,
     Stop,
     SetStrategy(SelectionStrategy),
     GetStats(oneshot::Sender<EngineStats>),
+    /// The info dictionary of a magnet torrent (metadata exchange or debrid)
+    MetadataReceived(Box<Metainfo>),
+    /// Split a complete consolidated download into its files
+    ExtractParts(oneshot::Sender<Result<(), String>>),
+    /// A peer typed in by the user (see `TorrentEngine::add_manual_peer`)
+    AddManualPeer(SocketAddr, oneshot::Sender<ManualDial>),
+    /// See `TorrentEngine::set_file_priority`
+    SetFilePriority(usize, crate::piece::PiecePriority, oneshot::Sender<Result<(), String>>),
+    /// See `TorrentEngine::download_file_prefix`
+    DownloadFilePrefix(usize, u64, oneshot::Sender<Result<u64, String>>),
+    /// See `TorrentEngine::set_tracker_enabled`
+    SetTrackerEnabled(String, bool, oneshot::Sender<Result<TrackerToggle, String>>),
+}
+
+/// A file downloaded only up to `bytes` (see
+/// `TorrentEngine::download_file_prefix`); setting the file's priority ends it
+#[derive(Debug, Clone)]
+struct FilePrefix {
+    bytes: u64,
+    /// The pieces holding those bytes
+    pieces: Range<usize>,
+    /// `prefix-ready` was emitted
+    ready: bool,
+}
+
+/// What it takes to steer an engine without locking it: `run` holds the
+/// engine's write lock for as long as the engine runs
+#[derive(Clone)]
+pub struct EngineControl {
+    pub handle: EngineHandle,
+    pub cancel: CancellationToken,
 }
 
 /// Main torrent engine
@@ -72,71 +195,119 @@ pub struct TorrentEngine {
     disk_manager: Arc<RwLock<DiskManager>>,
     /// Peer manager
     peer_manager_tx: Option<mpsc::Sender<PeerManagerCommand>>,
-    /// Available peer addresses
-    peer_addresses: Arc<RwLock<HashSet<SocketAddr>>>,
+    /// Tears the peer manager down without stopping the engine
+    peer_cancel: Option<CancellationToken>,
+    /// The current peer manager's totals, and those of earlier ones this
+    /// run (paused seeding replaces the peer manager)
+    peer_totals: PeerTotals,
+    retired_peer_totals: PeerTotals,
+    /// Available peer addresses and where they came from
+    peer_addresses: Arc<RwLock<HashMap<SocketAddr, KnownPeer>>>,
+    /// Peer manager reports each peer's first block here
+    productive_tx: mpsc::UnboundedSender<SocketAddr>,
+    productive_rx: mpsc::UnboundedReceiver<SocketAddr>,
+    /// Peer manager reports peers dropped for protocol violations here
+    violation_tx: mpsc::UnboundedSender<SocketAddr>,
+    violation_rx: mpsc::UnboundedReceiver<SocketAddr>,
     /// Tracker client
     tracker: Arc<HttpTracker>,
+    /// UDP tracker client, which keeps the trackers' connection IDs
+    udp_tracker: Arc<UdpTracker>,
     /// Tracker information for UI
     tracker_info: Arc<RwLock<Vec<crate::tracker::TrackerInfo>>>,
     /// Engine state
     state: Arc<RwLock<EngineState>>,
     /// Statistics
     stats: Arc<RwLock<EngineStats>>,
-    /// Our peer ID
-    peer_id: [u8; 20],
-    /// Command channel receiver
-    command_rx: mpsc::UnboundedReceiver<EngineCommand>,
-    /// Command channel sender (for cloning)
-    command_tx: mpsc::UnboundedSender<EngineCommand>,
+    /// Our peer IDs (normal and anonymous)
+    identity: utils::PeerIdentity,
+    /// Command queues (ordinary + priority Stop)
+    command_queues: command::CommandQueues,
+    /// Sending side of the command queues (for cloning)
+    command_handle: EngineHandle,
     /// Database for persistence
     database: Option<Arc<Database>>,
     /// Download directory
     download_dir: PathBuf,
     /// Cancellation token for cooperative shutdown
-    /// Cancellation token for cooperative shutdown
     cancel_token: CancellationToken,
-    /// Tauri App Handle for events
-    app_handle: Option<tauri::AppHandle>,
+    /// Where events go (the frontend, in the app)
+    events: Option<EventSink>,
+    /// Id the app tracks the torrent under, checked against the metadata
+    /// before starting (see `set_tracked_id`)
+    tracked_id: Option<String>,
     /// Time when download completed
     completed_at: Option<i64>,
+    /// Traffic persisted by earlier runs (this run's is added on top)
+    traffic_base: TrafficStats,
+    /// Effective listen port from settings; changes trigger a re-announce
+    listen_port: watch::Receiver<u16>,
+    /// Anonymous mode from settings; read at each announce and handshake
+    anonymous_mode: watch::Receiver<bool>,
+    /// Tracker user agent and headers from settings; the client is rebuilt on change
+    tracker_http: watch::Receiver<TrackerHttpConfig>,
+    /// Last swarm size reported by the trackers
+    swarm: Option<SwarmStats>,
+    /// Whether the last announce round failed on every tracker
+    tracker_outage: TrackerOutage,
+    /// When availability was last sampled (read from the history on first use)
+    availability_sampled_at: Option<i64>,
+    /// Outbound connection limiter shared by all engines
+    dial_pacer: Arc<DialPacer>,
+    /// Our public address as trackers report it (shared by all engines)
+    external_ip: Arc<ExternalIp>,
+    /// Debug transfer log (shared with the peer manager)
+    transfer_log: Arc<TransferLog>,
+    /// Why peer connections ended (shared with the peer manager)
+    disconnect_history: Arc<DisconnectHistory>,
+    /// App-wide memory-mapped reads setting, handed to the disk manager on start
+    mmap_reads: watch::Receiver<bool>,
+    /// App-wide completion mtime setting, handed to the disk manager on start
+    completion_mtimes: watch::Receiver<bool>,
+    /// Kept across disk manager rebuilds (root rename, magnet metadata)
+    storage_mode: StorageMode,
+    /// The session was saved in paused seeding: the first start goes back to it
+    start_paused_seeding: bool,
+    /// Leave paused seeding when an announce reports leechers
+    reactivate_paused_seeding: watch::Receiver<bool>,
+    /// Send `tracker-warning` events (`notify_on_tracker_warning`)
+    notify_tracker_warnings: watch::Receiver<bool>,
+    /// Files downloaded only up to a size, by file index
+    prefixes: BTreeMap<usize, FilePrefix>,
+    /// Trackers the user disabled (see `set_tracker_enabled`)
+    disabled_trackers: HashSet<String>,
+    /// An announce ahead of the regular schedule: a re-enabled tracker's,
+    /// once its minimum interval allows, or one a short interval asked for
+    reannounce_at: Option<time::Instant>,
+    /// Where the files sit in the pieces
+    file_progress: FileProgressTracker,
+    /// When each complete file finished
+    file_completed_at: FileCompletions,
+    /// Whether this run looked at the files yet; the first look only
+    /// catches up on files complete before it
+    file_completions_checked: bool,
+    /// What allocations that failed since the last complete one created
+    allocation_created: CreatedPaths,
+    /// Retries of a start that failed for a reason that may pass
+    start_retry: StartRetry,
+    /// Failures `allocate_files` reports instead of allocating
+    #[cfg(test)]
+    allocation_faults: std::collections::VecDeque<crate::disk::DiskError>,
 }
 
 impl TorrentEngine {
     /// Create a new torrent engine
-    pub fn new(metainfo: Metainfo, download_dir: PathBuf, app_handle: Option<tauri::AppHandle>) -> Self {
-        let peer_id = utils::generate_peer_id();
-        let num_pieces = metainfo.info.piece_count;
-        let piece_length = metainfo.info.piece_length as usize;
-        
-        // Calculate last piece length
-        let total_size = metainfo.info.total_size;
-        let last_piece_length = if total_size % piece_length as u64 == 0 {
-            piece_length
-        } else {
-            (total_size % piece_length as u64) as usize
-        };
-
-        // Extract piece hashes
-        let piece_hashes: Vec<Vec<u8>> = (0..num_pieces)
-            .map(|i| {
-                let start = i * 20;
-                let end = start + 20;
-                metainfo.info.pieces[start..end].to_vec()
-            })
-            .collect();
-
-        let piece_manager = PieceManager::new(
-            num_pieces,
-            piece_length,
-            last_piece_length,
-            piece_hashes,
-            SelectionStrategy::RarestFirst,
-        );
-
+    pub fn new(metainfo: Metainfo, download_dir: PathBuf, events: Option<EventSink>) -> Self {
+        let identity = utils::PeerIdentity::generate();
+        let piece_manager = Self::build_piece_manager(&metainfo);
         let disk_manager = DiskManager::new(&metainfo, download_dir.clone());
+        let file_progress = FileProgressTracker::new(&metainfo.info.files, metainfo.info.piece_length);
         let tracker = HttpTracker::new();
 
-        let (command_tx, command_rx) = mpsc::unbounded_channel();
+        let (command_handle, command_queues) =
+            command::command_channel(COMMAND_CHANNEL_CAPACITY, COMMAND_TIMEOUT);
+        let (productive_tx, productive_rx) = mpsc::unbounded_channel();
+        let (violation_tx, violation_rx) = mpsc::unbounded_channel();
 
         let stats = EngineStats {
             state: EngineState::Stopped,
@@ -145,10 +316,15 @@ impl TorrentEngine {
             download_speed: 0.0,
             upload_speed: 0.0,
             connected_peers: 0,
+            connected_seeds: 0,
             total_peers: 0,
+            swarm: None,
             progress: 0.0,
             eta_seconds: None,
             completed_at: None,
+            dropped_commands: 0,
+            traffic: TrafficStats::default(),
+            error: None,
         };
 
         Self {
@@ -156,20 +332,66 @@ impl TorrentEngine {
             piece_manager: Arc::new(RwLock::new(piece_manager)),
             disk_manager: Arc::new(RwLock::new(disk_manager)),
             peer_manager_tx: None,
-            peer_addresses: Arc::new(RwLock::new(HashSet::new())),
+            peer_cancel: None,
+            peer_totals: PeerTotals::default(),
+            retired_peer_totals: PeerTotals::default(),
+            peer_addresses: Arc::new(RwLock::new(HashMap::new())),
+            productive_tx,
+            productive_rx,
+            violation_tx,
+            violation_rx,
             tracker: Arc::new(tracker),
+            udp_tracker: Arc::new(UdpTracker::new()),
             tracker_info: Arc::new(RwLock::new(Vec::new())),
             state: Arc::new(RwLock::new(EngineState::Stopped)),
             stats: Arc::new(RwLock::new(stats)),
-            peer_id,
-            command_rx,
-            command_tx,
+            identity,
+            command_queues,
+            command_handle,
             database: None,
             download_dir,
             cancel_token: CancellationToken::new(),
-            app_handle,
+            events,
+            tracked_id: None,
             completed_at: None,
+            traffic_base: TrafficStats::default(),
+            listen_port: watch::channel(DEFAULT_LISTEN_PORT).1,
+            anonymous_mode: watch::channel(false).1,
+            tracker_http: watch::channel(TrackerHttpConfig::default()).1,
+            swarm: None,
+            tracker_outage: TrackerOutage::default(),
+            availability_sampled_at: None,
+            dial_pacer: Arc::new(DialPacer::default()),
+            external_ip: Arc::new(ExternalIp::default()),
+            transfer_log: Arc::new(TransferLog::default()),
+            disconnect_history: Arc::new(DisconnectHistory::default()),
+            mmap_reads: watch::channel(false).1,
+            completion_mtimes: watch::channel(false).1,
+            storage_mode: StorageMode::Files,
+            start_paused_seeding: false,
+            reactivate_paused_seeding: watch::channel(false).1,
+            notify_tracker_warnings: watch::channel(crate::tracker::default_notify_on_tracker_warning()).1,
+            prefixes: BTreeMap::new(),
+            disabled_trackers: HashSet::new(),
+            reannounce_at: None,
+            file_progress,
+            file_completed_at: FileCompletions::new(),
+            file_completions_checked: false,
+            allocation_created: CreatedPaths::default(),
+            start_retry: StartRetry::default(),
+            #[cfg(test)]
+            allocation_faults: Default::default(),
+        }
+    }
+
+    /// The id the app keeps the torrent under (its session id). Unless the
+    /// metadata hashes to it, the engine refuses to start: it would announce
+    /// under a hash the app never hears about.
+    pub fn set_tracked_id(&mut self, torrent_id: &str) {
+        if let Err(e) = crate::ids::check_torrent_id(torrent_id, &self.metainfo.info_hash) {
+            tracing::error!("{}", e);
         }
+        self.tracked_id = Some(torrent_id.to_string());
     }
 
     /// Set completed_at timestamp (used when restoring state)
@@ -177,14 +399,154 @@ impl TorrentEngine {
         self.completed_at = timestamp;
     }
 
+    /// Restore when each file completed
+    pub fn set_file_completed_at(&mut self, completed_at: FileCompletions) {
+        self.file_completed_at = completed_at;
+    }
+
+    /// Set traffic totals from earlier runs (used when restoring state)
+    pub fn set_traffic_base(&mut self, traffic: TrafficStats) {
+        self.traffic_base = traffic;
+        // Shown (with its waste totals) until the first stats update
+        if let Ok(mut stats) = self.stats.try_write() {
+            stats.traffic = traffic;
+        }
+    }
+
+    /// Set the last known swarm size (used when restoring state)
+    pub fn set_swarm_stats(&mut self, swarm: Option<SwarmStats>) {
+        self.swarm = swarm;
+    }
+
+    /// Set the trackers the user disabled (used when restoring state); they
+    /// are listed as disabled from the start
+    pub async fn set_disabled_trackers(&mut self, urls: &[String]) {
+        let trackers = crate::tracker::tracker_urls(&self.metainfo);
+        self.disabled_trackers = urls.iter().filter(|url| trackers.contains(url)).cloned().collect();
+        let mut tracker_list = self.tracker_info.write().await;
+        for url in &self.disabled_trackers {
+            let mut tracker = crate::tracker::TrackerInfo::new(url);
+            tracker.disable();
+            tracker_list.push(tracker);
+        }
+    }
+
+    /// Piece manager for `metainfo` (empty for a magnet stub)
+    fn build_piece_manager(metainfo: &Metainfo) -> PieceManager {
+        let num_pieces = metainfo.info.piece_count;
+        let piece_length = metainfo.info.piece_length as usize;
+
+        // Calculate last piece length
+        let total_size = metainfo.info.total_size;
+        let last_piece_length = if piece_length == 0 || total_size % piece_length as u64 == 0 {
+            piece_length
+        } else {
+            (total_size % piece_length as u64) as usize
+        };
+
+        // Extract piece hashes
+        let piece_hashes: Vec<Vec<u8>> = metainfo.info.pieces
+            .chunks_exact(20)
+            .take(num_pieces)
+            .map(|hash| hash.to_vec())
+            .collect();
+
+        PieceManager::new(
+            num_pieces,
+            piece_length,
+            last_piece_length,
+            piece_hashes,
+            SelectionStrategy::RarestFirst,
+        )
+    }
+
+    /// Store the torrent under a different root file/folder name (see
+    /// `TorrentSession::root_name`); only valid before the engine is started
+    pub fn set_root_name(&mut self, root_name: &str) {
+        let mut disk_manager = DiskManager::with_root_name(&self.metainfo, self.download_dir.clone(), root_name);
+        disk_manager.set_storage_mode(self.storage_mode);
+        self.disk_manager = Arc::new(RwLock::new(disk_manager));
+    }
+
+    /// Store the data consolidated until complete (see `disk::parts`); only
+    /// valid before the engine is started
+    pub async fn set_storage_mode(&mut self, mode: StorageMode) {
+        self.storage_mode = mode;
+        self.disk_manager.write().await.set_storage_mode(mode);
+    }
+
+    /// Follow the app-wide listen port (see `AppState::listen_port`)
+    pub fn set_listen_port(&mut self, listen_port: watch::Receiver<u16>) {
+        self.listen_port = listen_port;
+    }
+
+    /// Follow the app-wide anonymous mode (see `AppState::anonymous_mode`)
+    pub fn set_anonymous_mode(&mut self, anonymous_mode: watch::Receiver<bool>) {
+        self.anonymous_mode = anonymous_mode;
+    }
+
+    /// Follow the app-wide tracker HTTP settings (see `AppState::tracker_http`)
+    pub fn set_tracker_http(&mut self, mut tracker_http: watch::Receiver<TrackerHttpConfig>) {
+        self.tracker = Arc::new(HttpTracker::with_config(&tracker_http.borrow_and_update()));
+        self.tracker_http = tracker_http;
+    }
+
+    /// Share the app-wide dial pacer (see `AppState::dial_pacer`)
+    pub fn set_dial_pacer(&mut self, dial_pacer: Arc<DialPacer>) {
+        self.dial_pacer = dial_pacer;
+    }
+
+    /// Report to and filter by the app-wide external address (see `AppState::external_ip`)
+    pub fn set_external_ip(&mut self, external_ip: Arc<ExternalIp>) {
+        self.external_ip = external_ip;
+    }
+
+    /// Record into the torrent's debug log (see `AppState::transfer_logs`)
+    pub fn set_transfer_log(&mut self, transfer_log: Arc<TransferLog>) {
+        self.transfer_log = transfer_log;
+    }
+
+    /// Record ended peer connections (see `AppState::disconnect_histories`)
+    pub fn set_disconnect_history(&mut self, disconnect_history: Arc<DisconnectHistory>) {
+        self.disconnect_history = disconnect_history;
+    }
+
+    /// Follow the app-wide memory-mapped reads setting (see `AppState::mmap_reads`)
+    pub fn set_mmap_reads(&mut self, mmap_reads: watch::Receiver<bool>) {
+        self.mmap_reads = mmap_reads;
+    }
+
+    /// Follow the app-wide completion mtime setting (see `AppState::completion_mtimes`)
+    pub fn set_completion_mtimes(&mut self, completion_mtimes: watch::Receiver<bool>) {
+        self.completion_mtimes = completion_mtimes;
+    }
+
     /// Set database for persistence
+    /// Start in paused seeding, as the session was saved
+    pub fn set_start_paused_seeding(&mut self, paused_seeding: bool) {
+        self.start_paused_seeding = paused_seeding;
+    }
+
+    pub fn set_reactivate_paused_seeding(&mut self, reactivate: watch::Receiver<bool>) {
+        self.reactivate_paused_seeding = reactivate;
+    }
+
+    pub fn set_notify_tracker_warnings(&mut self, notify: watch::Receiver<bool>) {
+        self.notify_tracker_warnings = notify;
+    }
+
     pub fn set_database(&mut self, database: Arc<Database>) {
         self.database = Some(database);
     }
 
-    /// Get a command sender for controlling the engine
-    pub fn command_sender(&self) -> mpsc::UnboundedSender<EngineCommand> {
-        self.command_tx.clone()
+    /// Get a handle for sending commands to the engine
+    pub fn command_handle(&self) -> EngineHandle {
+        self.command_handle.clone()
+    }
+
+    /// Command handle and cancellation token, for `AppState::engine_controls`
+    pub fn control(&self) -> EngineControl {
+        EngineControl { handle: self.command_handle.clone(), cancel: self.cancel_token.clone() }
     }
 
     /// Get the cancellation token for this engine
@@ -194,40 +556,148 @@ impl TorrentEngine {
 
     /// Set priority for a specific file
     pub async fn set_file_priority(&mut self, file_index: usize, priority: crate::piece::PiecePriority) -> Result<(), String> {
-        // Calculate file range in bytes
-        let files = &self.metainfo.info.files;
-        if file_index >= files.len() {
-            return Err(format!("Invalid file index: {}", file_index));
-        }
-
-        let mut offset = 0;
-        for i in 0..file_index {
-            offset += files[i].length;
+        // The stub's placeholder file has no pieces behind it
+        if !self.has_metadata() {
+            return Err("Metadata not received yet".to_string());
         }
-        let length = files[file_index].length;
-        let end = offset + length;
 
-        // Calculate piece range
-        let piece_length = self.metainfo.info.piece_length as u64;
-        let start_piece = (offset / piece_length) as usize;
-        let end_piece = ((end + piece_length - 1) / piece_length) as usize;
+        let span = crate::piece::ranges::file_span(&self.metainfo.info.files, file_index)
+            .ok_or_else(|| format!("Invalid file index: {}", file_index))?;
+        let pieces = crate::piece::ranges::pieces_covering(&span, self.metainfo.info.piece_length as u64);
 
         // Update piece priorities
         let mut piece_manager = self.piece_manager.write().await;
-        for piece_idx in start_piece..end_piece {
+        for piece_idx in pieces {
             if piece_idx < piece_manager.stats().total_pieces {
                 piece_manager.set_piece_priority(piece_idx, priority);
             }
         }
-        
+        if self.prefixes.remove(&file_index).is_some() {
+            tracing::info!("File {} is no longer downloaded as a prefix only", file_index);
+        }
+
         Ok(())
     }
 
+    /// Download only the first `bytes` of a file, to check it before
+    /// committing to the rest: the pieces holding them go to High and the
+    /// rest of the file to Skip. `prefix-ready` is emitted once they are
+    /// all here. Returns the bytes selected (at most the file length).
+    pub async fn download_file_prefix(&mut self, file_index: usize, bytes: u64) -> Result<u64, String> {
+        if !self.has_metadata() {
+            return Err("Metadata not received yet".to_string());
+        }
+        // Consolidated data only lands in the file on completion
+        if self.storage_mode == StorageMode::Consolidated {
+            return Err("Previewing a file needs the torrent stored as separate files".to_string());
+        }
+        let files = &self.metainfo.info.files;
+        if file_index >= files.len() {
+            return Err(format!("Invalid file index: {}", file_index));
+        }
+        let plan = crate::piece::ranges::prefix_plan(files, file_index, bytes, self.metainfo.info.piece_length as u64)
+            .ok_or_else(|| format!("File {} is empty or no bytes were asked for", file_index))?;
+
+        let mut piece_manager = self.piece_manager.write().await;
+        for piece_idx in plan.prefix.clone() {
+            piece_manager.set_piece_priority(piece_idx, crate::piece::PiecePriority::High);
+        }
+        for piece_idx in plan.rest.clone() {
+            piece_manager.set_piece_priority(piece_idx, crate::piece::PiecePriority::Skip);
+        }
+        drop(piece_manager);
+
+        tracing::info!(
+            "Downloading the first {} bytes of file {} (pieces {:?}, skipping {:?})",
+            plan.bytes,
+            file_index,
+            plan.prefix,
+            plan.rest
+        );
+        self.prefixes.insert(file_index, FilePrefix { bytes: plan.bytes, pieces: plan.prefix, ready: false });
+        Ok(plan.bytes)
+    }
+
+    /// Emit `prefix-ready` for prefixes that just completed
+    async fn check_prefixes(&mut self) {
+        let piece_manager = self.piece_manager.read().await;
+        let completed: Vec<usize> = self
+            .prefixes
+            .iter()
+            .filter(|(_, prefix)| !prefix.ready && prefix.pieces.clone().all(|piece_idx| piece_manager.has_piece(piece_idx)))
+            .map(|(&file_index, _)| file_index)
+            .collect();
+        drop(piece_manager);
+        if completed.is_empty() {
+            return;
+        }
+
+        let disk_manager = self.disk_manager.read().await;
+        for file_index in completed {
+            let Some(prefix) = self.prefixes.get_mut(&file_index) else { continue };
+            prefix.ready = true;
+            let Some(file) = disk_manager.files().get(file_index) else { continue };
+            tracing::info!("First {} bytes of {} are ready", prefix.bytes, file.path.display());
+
+            if let Some(events) = &self.events {
+                let event = crate::events::PrefixReadyEvent {
+                    torrent_id: self.metainfo.info_hash_hex(),
+                    file_index,
+                    bytes: prefix.bytes,
+                    path: file.path.to_string_lossy().into_owned(),
+                };
+                events.send(Event::PrefixReady(event));
+            }
+        }
+    }
+
+    /// Stamp the files verified pieces completed and send `file-completed`
+    /// for each; files a failed check left incomplete lose their stamp
+    async fn check_file_completions(&mut self) {
+        if !self.has_metadata() {
+            return;
+        }
+        let now = chrono::Utc::now().timestamp();
+        let completed = {
+            let piece_manager = self.piece_manager.read().await;
+            self.file_progress.reconcile(&mut self.file_completed_at, piece_manager.our_bitfield(), now)
+        };
+        // Complete before this run (imported data, or saved before files
+        // were stamped): not news, and no later than the torrent itself
+        if !std::mem::replace(&mut self.file_completions_checked, true) {
+            let at = self.completed_at.unwrap_or(now);
+            for file_index in completed {
+                self.file_completed_at.insert(file_index, at);
+            }
+            return;
+        }
+        if completed.is_empty() {
+            return;
+        }
+
+        let disk_manager = self.disk_manager.read().await;
+        for file_index in completed {
+            let Some(file) = disk_manager.files().get(file_index) else { continue };
+            tracing::info!("{} is complete", file.path.display());
+            if let Some(events) = &self.events {
+                let event = crate::events::FileCompletedEvent {
+                    torrent_id: self.metainfo.info_hash_hex(),
+                    file_index,
+                    path: file.path.to_string_lossy().into_owned(),
+                    completed_at: now,
+                };
+                events.send(Event::FileCompleted(event));
+            }
+        }
+    }
+
     /// Run the engine (main event loop)
     pub async fn run(&mut self) {
         let mut tracker_timer = time::interval(TRACKER_ANNOUNCE_INTERVAL);
         let mut stats_timer = time::interval(Duration::from_secs(1));
         let mut save_timer = time::interval(PROGRESS_SAVE_INTERVAL);
+        let mut wakes = crate::wake::subscribe();
+        let metrics = self.command_handle.metrics().clone();
 
         loop {
             tokio::select! {
@@ -240,11 +710,24 @@ impl TorrentEngine {
                     break;
                 }
 
+                // Stop has its own queue so it is never stuck behind a full one
+                Some(()) = self.command_queues.stop.recv() => {
+                    self.handle_stop().await;
+                    break;
+                }
+
                 // Handle commands
-                Some(cmd) = self.command_rx.recv() => {
+                Some(cmd) = self.command_queues.commands.recv() => {
+                    let _iteration = metrics.time_iteration();
                     match cmd {
-                        EngineCommand::Start => self.handle_start().await,
-                        EngineCommand::Pause => self.handle_pause().await,
+                        EngineCommand::Start => {
+                            // Starting by hand gets a fresh set of attempts
+                            self.start_retry.reset();
+                            self.handle_start().await
+                        }
+                        EngineCommand::Pause => self.handle_pause(EngineState::Paused).await,
+                        EngineCommand::Queue => self.handle_pause(EngineState::Queued).await,
+                        EngineCommand::PauseSeeding => self.handle_pause_seeding().await,
                         EngineCommand::Stop => {
                             self.handle_stop().await;
                             break;
@@ -256,32 +739,123 @@ impl TorrentEngine {
                             let stats = self.get_stats().await;
                             let _ = tx.send(stats);
                         }
+                        EngineCommand::MetadataReceived(metainfo) => {
+                            if let Err(e) = self.apply_metadata(*metainfo).await {
+                                tracing::warn!("Ignoring received metadata: {}", e);
+                            }
+                        }
+                        EngineCommand::ExtractParts(tx) => {
+                            let _ = tx.send(self.extract_parts().await);
+                        }
+                        EngineCommand::AddManualPeer(addr, tx) => {
+                            let _ = tx.send(self.add_manual_peer(addr).await);
+                        }
+                        EngineCommand::SetFilePriority(file_index, priority, tx) => {
+                            let _ = tx.send(self.set_file_priority(file_index, priority).await);
+                        }
+                        EngineCommand::DownloadFilePrefix(file_index, bytes, tx) => {
+                            let _ = tx.send(self.download_file_prefix(file_index, bytes).await);
+                        }
+                        EngineCommand::SetTrackerEnabled(url, enabled, tx) => {
+                            let _ = tx.send(self.set_tracker_enabled(&url, enabled).await);
+                        }
+                    }
+                }
+
+                // A peer sent its first block: credit the tracker that found it
+                Some(addr) = self.productive_rx.recv() => {
+                    let _iteration = metrics.time_iteration();
+                    self.record_productive_peer(addr).await;
+                }
+
+                // A peer broke the wire protocol: dial it last from now on
+                Some(addr) = self.violation_rx.recv() => {
+                    let _iteration = metrics.time_iteration();
+                    self.penalize_peer(addr).await;
+                }
+
+                // Listen port changed in settings: tell trackers promptly
+                Ok(()) = self.listen_port.changed() => {
+                    let _iteration = metrics.time_iteration();
+                    let current_state = *self.state.read().await;
+                    if matches!(current_state, EngineState::Downloading | EngineState::Seeding | EngineState::PausedSeeding) {
+                        tracing::info!("Listen port changed, re-announcing");
+                        self.announce_to_tracker(false).await;
+                    }
+                }
+
+                // Trackers may have dropped us while the machine slept:
+                // announce again, in a slot shared with every other torrent
+                Ok(()) = wakes.changed() => {
+                    wakes.borrow_and_update();
+                    tracker_timer.reset_at(crate::wake::announce_slot());
+                    stats_timer.reset();
+                }
+
+                // A tracker was enabled again and its minimum interval is
+                // over, or a tracker asked to hear back soon
+                _ = time::sleep_until(self.reannounce_at.unwrap_or_else(time::Instant::now)), if self.reannounce_at.is_some() => {
+                    let _iteration = metrics.time_iteration();
+                    self.reannounce_at = None;
+                    let current_state = *self.state.read().await;
+                    if matches!(current_state, EngineState::Downloading | EngineState::Seeding | EngineState::PausedSeeding) {
+                        self.announce_to_tracker(true).await;
                     }
                 }
 
+                // A start that failed for a reason that may have passed
+                _ = time::sleep_until(self.start_retry.due().unwrap_or_else(time::Instant::now)), if self.start_retry.due().is_some() => {
+                    let _iteration = metrics.time_iteration();
+                    self.retry_start().await;
+                }
+
                 // Periodic tracker announces
                 _ = tracker_timer.tick() => {
+                    let _iteration = metrics.time_iteration();
+                    // Overdue from a sleep: wait for the staggered slot instead
+                    crate::wake::check();
+                    if wakes.has_changed().unwrap_or(false) {
+                        continue;
+                    }
                     let current_state = *self.state.read().await;
-                    if current_state == EngineState::Downloading
-                        || current_state == EngineState::Seeding
-                    {
-                        self.announce_to_tracker().await;
+                    match current_state {
+                        EngineState::Downloading | EngineState::Seeding => {
+                            self.announce_to_tracker(false).await;
+                        }
+                        EngineState::PausedSeeding => {
+                            let leechers = self.announce_to_tracker(false).await;
+                            self.reactivate_for_leechers(leechers).await;
+                        }
+                        _ => {}
                     }
                 }
 
                 // Update statistics
                 _ = stats_timer.tick() => {
+                    let _iteration = metrics.time_iteration();
+                    let was_complete = self.completed_at.is_some();
                     self.update_stats().await;
+                    self.emit_piece_failures().await;
+                    self.check_prefixes().await;
+                    self.check_file_completions().await;
+                    self.sample_availability(!was_complete && self.completed_at.is_some()).await;
+                    if !was_complete && self.completed_at.is_some() && self.storage_mode == StorageMode::Consolidated {
+                        if let Err(e) = self.extract_parts().await {
+                            tracing::error!("Failed to extract {}: {}", self.metainfo.info_hash_hex(), e);
+                        }
+                    }
                     
                     // Emit update event
-                    if let Some(app) = &self.app_handle {
-                        use tauri::Emitter;
+                    if let Some(events) = &self.events {
                         // Construct TorrentInfo for UI
                         let stats = self.stats.read().await;
                         let state = match stats.state {
                             EngineState::Downloading => crate::state::TorrentState::Downloading,
                             EngineState::Seeding => crate::state::TorrentState::Seeding,
                             EngineState::Paused => crate::state::TorrentState::Paused,
+                            EngineState::Queued => crate::state::TorrentState::Queued,
+                            EngineState::Unregistered => crate::state::TorrentState::Unregistered,
+                            EngineState::PausedSeeding => crate::state::TorrentState::PausedSeeding,
                             EngineState::Stopped => crate::state::TorrentState::Paused,
                             EngineState::Starting => crate::state::TorrentState::Checking,
                             EngineState::Error => crate::state::TorrentState::Error,
@@ -297,18 +871,26 @@ impl TorrentEngine {
                             download_speed: stats.download_speed as u64,
                             upload_speed: stats.upload_speed as u64,
                             peers: stats.connected_peers as u32,
-                            seeds: 0, // TODO: Get from tracker stats
+                            seeds: stats.connected_seeds as u32,
                             source: crate::debrid::types::DownloadSource::P2P,
+                            remote_deleted: false,
+                            swarm_seeds: stats.swarm.map(|s| s.seeds),
+                            swarm_leechers: stats.swarm.map(|s| s.leechers),
+                            swarm_updated_at: stats.swarm.map(|s| s.updated_at),
+                            queue_position: None,
+                            metadata_pending: !self.has_metadata(),
+                            name_encoding: self.metainfo.info.name_encoding(),
+                            tags: Vec::new(),
+                            error: stats.error.clone(),
                         };
                         
-                        if let Err(e) = app.emit("torrent-update", info) {
-                            tracing::error!("Failed to emit torrent-update event: {}", e);
-                        }
+                        events.send(Event::TorrentUpdate(info));
                     }
                 }
 
                 // Save progress to database
                 _ = save_timer.tick() => {
+                    let _iteration = metrics.time_iteration();
                     if *self.state.read().await != EngineState::Stopped {
                         self.save_progress().await;
                     }
@@ -321,9 +903,21 @@ impl TorrentEngine {
 
     /// Handle start command
     async fn handle_start(&mut self) {
+        if *self.state.read().await == EngineState::PausedSeeding {
+            self.resume_seeding().await;
+            return;
+        }
+
         // Check if we are resuming from pause (PeerManager already exists)
         if let Some(ref tx) = self.peer_manager_tx {
             tracing::info!("Resuming torrent engine");
+
+            // Starting by hand gives trackers that refused the torrent another try
+            for tracker in self.tracker_info.write().await.iter_mut() {
+                if tracker.status == crate::tracker::TrackerStatus::NotRegistered {
+                    tracker.status = crate::tracker::TrackerStatus::Error;
+                }
+            }
             
             // Determine state based on completion
             let pm = self.piece_manager.read().await;
@@ -336,49 +930,86 @@ impl TorrentEngine {
 
             *self.state.write().await = new_state;
             
-            // Resume peer manager
+            // Resume peer manager; it reconnects the peers we had before the
+            // pause ahead of anything queued after this
             let _ = tx.send(PeerManagerCommand::Resume).await;
+
+            // Most connections died while we were quiet and the next scheduled
+            // announce may be far off, so ask for fresh peers now
+            self.announce_to_tracker(true).await;
+            if *self.state.read().await != EngineState::Unregistered {
+                self.connect_to_peers().await;
+            }
             return;
         }
 
         tracing::info!("Starting torrent engine");
         *self.state.write().await = EngineState::Starting;
+        self.stats.write().await.error = None;
+
+        if let Some(torrent_id) = &self.tracked_id {
+            if let Err(e) = crate::ids::check_torrent_id(torrent_id, &self.metainfo.info_hash) {
+                tracing::error!("Refusing to start: {}", e);
+                self.fail_start(e.to_string(), false).await;
+                return;
+            }
+        }
 
         // Check if we have metadata (for magnet links)
-        if self.metainfo.info.total_size == 0 || self.metainfo.info.piece_count == 0 {
+        if !self.has_metadata() {
             tracing::warn!("Cannot start download: metadata not yet fetched (magnet link)");
             tracing::warn!("Metadata exchange (BEP 9) not yet implemented");
-            *self.state.write().await = EngineState::Error;
+            self.fail_start("The torrent's metadata hasn't been fetched yet".to_string(), false).await;
             return;
         }
 
-        // Allocate files on disk
-        if let Err(e) = self.disk_manager.read().await.allocate_files().await {
-            tracing::error!("Failed to allocate files: {}", e);
-            *self.state.write().await = EngineState::Error;
+        // Something in the way of the layout would only fail with a raw OS error below
+        let layout = self.disk_manager.read().await.check_layout();
+        if let Err(collision) = layout {
+            tracing::error!("Cannot allocate files: {}", collision);
+            self.fail_start(collision.to_string(), false).await;
             return;
         }
 
-        // Start peer manager with a child cancellation token
-        let peer_cancel = self.cancel_token.child_token();
-        let peer_manager = PeerManager::new(
-            self.metainfo.info_hash,
-            self.peer_id,
-            self.piece_manager.clone(),
-            self.disk_manager.clone(),
-            peer_cancel,
-        );
-        
-        let peer_manager_tx = peer_manager.command_sender();
-        self.peer_manager_tx = Some(peer_manager_tx.clone());
+        // Allocate files on disk; a retry skips what an earlier attempt did
+        if !self.allocate_files().await {
+            return;
+        }
 
-        // Spawn peer manager task
-        tokio::spawn(async move {
-            peer_manager.run().await;
-        });
+        {
+            let mut dm = self.disk_manager.write().await;
+            dm.set_mmap_reads(self.mmap_reads.clone());
+            dm.set_completion_mtimes(self.completion_mtimes.clone());
+            dm.mark_complete_files(self.piece_manager.read().await.our_bitfield());
+        }
+
+        // Completed but not extracted: the app quit while extracting
+        if self.storage_mode == StorageMode::Consolidated && self.piece_manager.read().await.is_complete() {
+            if let Err(e) = self.extract_parts().await {
+                tracing::error!("Failed to extract {}: {}", self.metainfo.info_hash_hex(), e);
+            }
+        }
+
+        // Back to where the session was saved: announcing, without peers
+        if std::mem::take(&mut self.start_paused_seeding) && self.piece_manager.read().await.is_complete() {
+            *self.state.write().await = EngineState::PausedSeeding;
+            self.start_retry.reset();
+            tracing::info!("Torrent engine started in paused seeding");
+            self.announce_to_tracker(false).await;
+            return;
+        }
+
+        self.spawn_peer_manager();
+
+        // Peers saved by the last run don't have to wait for the tracker
+        self.connect_to_best_peers(SAVED_PEER_DIALS).await;
 
         // Announce to tracker and get peers
-        self.announce_to_tracker().await;
+        self.announce_to_tracker(false).await;
+        if *self.state.read().await == EngineState::Unregistered {
+            return;
+        }
+        self.check_start_announce().await;
 
         // Connect to peers
         self.connect_to_peers().await;
@@ -387,118 +1018,366 @@ impl TorrentEngine {
         tracing::info!("Torrent engine started");
     }
 
-    /// Handle pause command
-    async fn handle_pause(&mut self) {
-        tracing::info!("Pausing torrent engine");
-        *self.state.write().await = EngineState::Paused;
-
-        // Pause peer manager
-        if let Some(ref tx) = self.peer_manager_tx {
-            let _ = tx.send(PeerManagerCommand::Pause).await;
+    /// Put the engine in `EngineState::Error`, saying why. A `transient`
+    /// failure is tried again by itself (see `start_retry`) until the
+    /// attempts run out; any other waits for the user.
+    async fn fail_start(&mut self, error: String, transient: bool) {
+        let now = time::Instant::now();
+        if !transient {
+            self.start_retry.reset();
+        } else if self.start_retry.schedule(error.clone(), now).is_none() {
+            tracing::warn!("Giving up on starting {} after {} attempts", self.metainfo.info_hash_hex(), MAX_START_ATTEMPTS);
         }
+        let error = self.start_retry.status(now).unwrap_or(error);
+        self.stats.write().await.error = Some(error);
+        *self.state.write().await = EngineState::Error;
     }
 
-    /// Handle stop command
-    async fn handle_stop(&mut self) {
-        tracing::info!("Stopping torrent engine");
-        *self.state.write().await = EngineState::Stopped;
-
-        // Cancel all child tasks (peer manager, etc.)
-        self.cancel_token.cancel();
-
-        // Flush pending writes
-        if let Err(e) = self.disk_manager.write().await.flush_writes().await {
-            tracing::error!("Failed to flush writes: {}", e);
+    /// After a start's announce: with every tracker failing and no peer to
+    /// try, the download would sit idle until the next regular announce, so
+    /// the announce is retried like a failed start
+    async fn check_start_announce(&mut self) {
+        let stranded = self.tracker_outage.is_failing() && self.peer_addresses.read().await.is_empty();
+        if !stranded {
+            self.start_retry.reset();
+            self.stats.write().await.error = None;
+            return;
+        }
+        let cause = "Every tracker failed and no peers are known".to_string();
+        if self.start_retry.schedule(cause, time::Instant::now()).is_none() {
+            // Left to the regular announces, as before
+            tracing::warn!("Trackers of {} still failing after {} attempts", self.metainfo.info_hash_hex(), MAX_START_ATTEMPTS);
+            self.stats.write().await.error = None;
         }
+    }
 
-        // Save final progress
-        self.save_progress().await;
+    /// A retry from `start_retry` is due: the whole start again after a
+    /// failed one, the announce again after one that found no peers
+    async fn retry_start(&mut self) {
+        let attempt = self.start_retry.fire();
+        let state = *self.state.read().await;
+        tracing::info!("Retrying start of {} ({:?}, attempt {}/{})", self.metainfo.info_hash_hex(), state, attempt, MAX_START_ATTEMPTS);
+        match state {
+            EngineState::Error => self.handle_start().await,
+            EngineState::Downloading | EngineState::Seeding => {
+                self.announce_to_tracker(false).await;
+                if *self.state.read().await != EngineState::Unregistered {
+                    self.check_start_announce().await;
+                    self.connect_to_peers().await;
+                }
+            }
+            // Paused or stopped meanwhile
+            _ => {
+                self.start_retry.reset();
+            }
+        }
+    }
 
-        // Peer manager will exit via its cancellation token
-        self.peer_manager_tx = None;
+    /// Create the torrent's files. On failure, the engine goes to
+    /// `EngineState::Error` naming the file, and whatever the attempt created
+    /// is remembered for `take_allocation_rollback`.
+    async fn allocate_files(&mut self) -> bool {
+        #[cfg(test)]
+        let report = match self.allocation_faults.pop_front() {
+            Some(e) => crate::disk::AllocationReport {
+                files: vec![crate::disk::FileAllocation { path: self.download_dir.clone(), outcome: crate::disk::FileOutcome::Failed(e) }],
+                total: 1,
+                created: CreatedPaths::default(),
+            },
+            None => self.disk_manager.read().await.allocate().await,
+        };
+        #[cfg(not(test))]
+        let report = self.disk_manager.read().await.allocate().await;
+        let Some((index, path, e)) = report.failure() else {
+            self.allocation_created = CreatedPaths::default();
+            return true;
+        };
+        let error = format!("Failed to allocate file {} of {}: {}", index + 1, report.total, e);
+        tracing::error!("{}", error);
+        if let Some(events) = &self.events {
+            let event = crate::events::AllocationFailedEvent {
+                torrent_id: self.metainfo.info_hash_hex(),
+                path: path.display().to_string(),
+                error: e.to_string(),
+                allocated: index,
+                total: report.total,
+            };
+            events.send(Event::AllocationFailed(event));
+        }
+        let transient = e.is_transient();
+        self.allocation_created.extend(report.created);
+        self.fail_start(error, transient).await;
+        false
+    }
 
-        // Final tracker announce (stopped)
-        // TODO: Implement stopped event
+    /// What failed allocations created since the last one that went through,
+    /// for removing the torrent without leaving a half-made tree behind
+    pub fn take_allocation_rollback(&mut self) -> CreatedPaths {
+        std::mem::take(&mut self.allocation_created)
     }
 
-    /// Announce to tracker and update peer list
-    async fn announce_to_tracker(&mut self) {
-        let pm = self.piece_manager.read().await;
-        let downloaded = (pm.completion() * self.metainfo.info.total_size as f64) as u64;
-        let left = self.metainfo.info.total_size - downloaded;
+    /// Start a peer manager with a child cancellation token
+    fn spawn_peer_manager(&mut self) {
+        let peer_cancel = self.cancel_token.child_token();
+        let mut peer_manager = PeerManager::new(
+            self.metainfo.info_hash,
+            self.identity,
+            self.piece_manager.clone(),
+            self.disk_manager.clone(),
+            peer_cancel.clone(),
+        );
+        peer_manager.set_anonymous_mode(self.anonymous_mode.clone());
+        peer_manager.set_productive_peer_sink(self.productive_tx.clone());
+        peer_manager.set_protocol_violation_sink(self.violation_tx.clone());
+        peer_manager.set_dial_pacer(self.dial_pacer.clone());
+        peer_manager.set_transfer_log(self.transfer_log.clone());
+        peer_manager.set_disconnect_history(self.disconnect_history.clone());
+        peer_manager.set_metrics(self.command_handle.metrics().clone());
+        peer_manager.set_max_peers(MAX_PEERS);
+
+        let peer_manager_tx = peer_manager.command_sender();
+        self.command_handle.metrics().watch_peer_commands(&peer_manager_tx);
+        self.peer_manager_tx = Some(peer_manager_tx);
+        self.peer_cancel = Some(peer_cancel);
+
+        tokio::spawn(async move {
+            peer_manager.run().await;
+        });
+    }
+
+    /// Handle pause seeding: drop the peer manager (and with it every
+    /// connection) but keep announcing, so private trackers still list us
+    async fn handle_pause_seeding(&mut self) {
+        let state = *self.state.read().await;
+        let complete = self.has_metadata() && self.piece_manager.read().await.is_complete();
+        if !complete || !matches!(state, EngineState::Seeding | EngineState::Paused) {
+            tracing::warn!("Not entering paused seeding from {:?} (complete: {})", state, complete);
+            return;
+        }
+        tracing::info!("Entering paused seeding");
+
+        // Bank the outgoing peer manager's totals before it goes
+        self.update_stats().await;
+        self.retired_peer_totals = self.retired_peer_totals.plus(&std::mem::take(&mut self.peer_totals));
+        if let Some(peer_cancel) = self.peer_cancel.take() {
+            peer_cancel.cancel();
+        }
+        self.peer_manager_tx = None;
+        *self.state.write().await = EngineState::PausedSeeding;
+
+        let mut stats = self.stats.write().await;
+        stats.state = EngineState::PausedSeeding;
+        stats.connected_peers = 0;
+        stats.connected_seeds = 0;
+        stats.download_speed = 0.0;
+        stats.upload_speed = 0.0;
+    }
+
+    /// Leave paused seeding: a new peer manager, then fresh peers
+    async fn resume_seeding(&mut self) {
+        tracing::info!("Leaving paused seeding");
+        self.spawn_peer_manager();
+        *self.state.write().await = EngineState::Seeding;
+        self.connect_to_best_peers(SAVED_PEER_DIALS).await;
+        self.announce_to_tracker(true).await;
+        if *self.state.read().await != EngineState::Unregistered {
+            self.connect_to_peers().await;
+        }
+    }
+
+    /// After an announce in paused seeding: back to seeding if the tracker
+    /// reported leechers and the setting allows it. Returns whether it did.
+    async fn reactivate_for_leechers(&mut self, leechers: Option<u32>) -> bool {
+        let Some(leechers) = leechers.filter(|&leechers| leechers > 0) else {
+            return false;
+        };
+        if *self.state.read().await != EngineState::PausedSeeding || !*self.reactivate_paused_seeding.borrow() {
+            return false;
+        }
+
+        tracing::info!("{} leechers reported, leaving paused seeding", leechers);
+        self.resume_seeding().await;
+        if let Some(events) = &self.events {
+            let event = crate::events::SeedingReactivatedEvent { torrent_id: self.metainfo.info_hash_hex(), leechers };
+            events.send(Event::SeedingReactivated(event));
+        }
+        true
+    }
+
+    /// Handle pause command (`Paused` by the user or `Queued` by the queue)
+    async fn handle_pause(&mut self, state: EngineState) {
+        tracing::info!("Pausing torrent engine ({:?})", state);
+        *self.state.write().await = state;
+        self.start_paused_seeding = false;
+        if self.start_retry.reset() {
+            tracing::info!("Pending start retry dropped");
+            self.stats.write().await.error = None;
+        }
+
+        // Pause peer manager
+        if let Some(ref tx) = self.peer_manager_tx {
+            let _ = tx.send(PeerManagerCommand::Pause).await;
+        }
+    }
+
+    /// Handle stop command
+    async fn handle_stop(&mut self) {
+        tracing::info!("Stopping torrent engine");
+        *self.state.write().await = EngineState::Stopped;
+
+        // Cancel all child tasks (peer manager, etc.)
+        self.cancel_token.cancel();
+
+        // Flush pending writes
+        if let Err(e) = self.disk_manager.write().await.flush_writes().await {
+            tracing::error!("Failed to flush writes: {}", e);
+        }
+
+        // Save final progress
+        self.save_progress().await;
+
+        // Peer manager will exit via its cancellation token
+        self.peer_manager_tx = None;
+
+        // Final tracker announce (stopped)
+        // TODO: Implement stopped event
+    }
+
+    /// Build the announce request for the current progress and listen port
+    async fn announce_request(&self) -> AnnounceRequest {
+        let pm = self.piece_manager.read().await;
+        let verified = (pm.completion() * self.metainfo.info.total_size as f64) as u64;
+        let left = self.metainfo.info.total_size - verified;
 
         drop(pm); // Release lock
 
-        let request = AnnounceRequest {
+        // This run's payload less what failed verification, like most
+        // clients report it; the UI keeps showing the raw wire counters
+        let stats = self.stats.read().await;
+        let payload = stats.traffic.payload_downloaded.saturating_sub(self.traffic_base.payload_downloaded);
+        let failed = stats.traffic.failed_downloaded.saturating_sub(self.traffic_base.failed_downloaded);
+        let downloaded = payload.saturating_sub(failed);
+        let uploaded = stats.uploaded_bytes;
+        drop(stats);
+
+        let port = *self.listen_port.borrow();
+        let anonymous = *self.anonymous_mode.borrow();
+        AnnounceRequest {
             info_hash: self.metainfo.info_hash,
-            peer_id: self.peer_id,
-            port: 6881,
-            uploaded: self.stats.read().await.uploaded_bytes,
+            peer_id: self.identity.peer_id(anonymous),
+            port,
+            uploaded,
             downloaded,
             left,
             compact: true,
             numwant: Some(50),
             event: AnnounceEvent::None,
-        };
+            anonymous,
+        }
+    }
 
-        // Collect all trackers to try (primary + announce-list)
-        let mut trackers_to_try = vec![self.metainfo.announce.clone()];
-        
-        // Add announce-list trackers (flatten the tiers)
-        for tier in &self.metainfo.announce_list {
-            for tracker_url in tier {
-                if !trackers_to_try.contains(tracker_url) {
-                    trackers_to_try.push(tracker_url.clone());
-                }
-            }
+    /// Announce to tracker and update peer list; returns the leechers
+    /// reported by the tracker that answered, if one did
+    ///
+    /// With `respect_floor`, trackers announced to within their minimum
+    /// interval are left alone (for unscheduled announces such as a resume).
+    async fn announce_to_tracker(&mut self, respect_floor: bool) -> Option<u32> {
+        let request = self.announce_request().await;
+
+        if self.tracker_http.has_changed().unwrap_or(false) {
+            let config = self.tracker_http.borrow_and_update().clone();
+            self.tracker = Arc::new(HttpTracker::with_config(&config));
         }
-        
-        // Filter to only HTTP/HTTPS trackers (UDP not yet supported)
-        trackers_to_try.retain(|url| url.starts_with("http://") || url.starts_with("https://"));
-        
-        tracing::debug!("Trying {} HTTP/HTTPS trackers", trackers_to_try.len());
+
+        // Primary + announce-list, HTTP(S) and UDP, without the trackers
+        // the user disabled
+        let trackers_to_try = crate::tracker::announce_order(&self.metainfo, &self.disabled_trackers);
+        if trackers_to_try.is_empty() {
+            return None;
+        }
+
+        tracing::debug!("Trying {} trackers", trackers_to_try.len());
         
         // Try each tracker until one succeeds
         let mut announce_succeeded = false;
+        let mut leechers = None;
+        let mut throttled = false;
         for tracker_url in &trackers_to_try {
             // Update tracker status to "Updating"
             let mut tracker_list = self.tracker_info.write().await;
             let tracker_idx = tracker_list.iter().position(|t| &t.url == tracker_url);
+            if tracker_idx.is_some_and(|idx| tracker_list[idx].status == crate::tracker::TrackerStatus::NotRegistered) {
+                continue;
+            }
+            if respect_floor {
+                let now = chrono::Utc::now().timestamp();
+                if tracker_idx.is_some_and(|idx| !tracker_list[idx].announce_allowed(now)) {
+                    tracing::debug!("Skipping announce to {}: within its minimum interval", redact_tracker_url(tracker_url));
+                    throttled = true;
+                    continue;
+                }
+            }
             if tracker_idx.is_none() {
-                tracker_list.push(crate::tracker::TrackerInfo {
-                    url: tracker_url.clone(),
-                    status: crate::tracker::TrackerStatus::Updating,
-                    message: "Announcing...".to_string(),
-                    peers: 0,
-                    seeds: 0,
-                    leechers: 0,
-                    downloaded: 0,
-                    last_announce: None,
-                    next_announce: None,
-                });
+                let mut tracker = crate::tracker::TrackerInfo::new(tracker_url);
+                tracker.message = "Announcing...".to_string();
+                tracker_list.push(tracker);
             } else if let Some(idx) = tracker_idx {
                 tracker_list[idx].status = crate::tracker::TrackerStatus::Updating;
                 tracker_list[idx].message = "Announcing...".to_string();
             }
             drop(tracker_list);
 
-            match self
-                .tracker
-                .announce(tracker_url, &request)
-                .await
-            {
+            let result = if crate::tracker::is_udp(tracker_url) {
+                self.udp_tracker.announce(tracker_url, &request).await
+            } else {
+                self.tracker.announce(tracker_url, &request).await
+            };
+            self.transfer_log.record(|| TransferEvent::Announce {
+                tracker: redacted_host_path(tracker_url),
+                result: match &result {
+                    Ok(response) => format!("{} peers, interval {}s", response.peers.len(), response.interval),
+                    Err(e) => e.to_string(),
+                },
+                warning: result.as_ref().ok().and_then(|response| response.warning_message.clone()),
+            });
+            match result {
                 Ok(response) => {
                     tracing::info!(
                         "Tracker announce successful ({}): {} peers, interval {}s",
-                        tracker_url,
+                        redact_tracker_url(tracker_url),
                         response.peers.len(),
                         response.interval
                     );
 
-                    // Add new peer addresses
+                    if let Some(ip) = response.external_ip {
+                        self.external_ip.report(tracker_url, ip);
+                    }
+
+                    // Add new peer addresses, remembering who reported them first
+                    let now = chrono::Utc::now().timestamp();
                     let mut addresses = self.peer_addresses.write().await;
+                    let mut unique = 0;
                     for peer in &response.peers {
-                        addresses.insert(peer.addr);
+                        // Trackers hand us back to ourselves
+                        if self.external_ip.is_self(peer.addr, request.port) {
+                            continue;
+                        }
+                        match addresses.entry(peer.addr) {
+                            Entry::Vacant(entry) => {
+                                entry.insert(KnownPeer {
+                                    source: tracker_url.clone(),
+                                    productive: false,
+                                    score: 1,
+                                    last_seen: now,
+                                });
+                                unique += 1;
+                            }
+                            Entry::Occupied(mut entry) => {
+                                let known = entry.get_mut();
+                                known.score = known.score.saturating_add(1);
+                                known.last_seen = now;
+                            }
+                        }
                     }
 
                     // Update stats
@@ -507,27 +1386,57 @@ impl TorrentEngine {
 
                     // Update tracker info with success
                     let mut tracker_list = self.tracker_info.write().await;
+                    let mut warning = None;
                     if let Some(tracker) = tracker_list.iter_mut().find(|t| &t.url == tracker_url) {
-                        tracker.status = crate::tracker::TrackerStatus::Working;
-                        tracker.message = "Announce OK".to_string();
-                        tracker.peers = response.peers.len() as u32;
-                        tracker.seeds = response.complete;
-                        tracker.leechers = response.incomplete;
-                        tracker.last_announce = Some(chrono::Utc::now().timestamp());
-                        tracker.next_announce = Some(chrono::Utc::now().timestamp() + response.interval as i64);
+                        warning = tracker
+                            .record_announce(&response, chrono::Utc::now().timestamp())
+                            .map(|message| (tracker.display_url.clone(), message));
+                        tracker.unique_peers += unique;
+                    }
+                    if let Some(swarm) = SwarmStats::from_trackers(&tracker_list) {
+                        self.swarm = Some(swarm);
+                    }
+                    drop(tracker_list);
+                    if let Some((tracker, message)) = warning {
+                        self.report_tracker_warning(tracker, message);
+                    }
+
+                    // A short interval means "come back soon", not a 30 minute wait
+                    if let Some(secs) = crate::tracker::early_reannounce(&response) {
+                        let at = time::Instant::now() + Duration::from_secs(secs as u64);
+                        self.reannounce_at = Some(self.reannounce_at.map_or(at, |scheduled| scheduled.min(at)));
+                        tracing::debug!("{} asked to announce again in {}s", redact_tracker_url(tracker_url), secs);
                     }
                     
+                    leechers = Some(response.incomplete);
                     announce_succeeded = true;
                     break; // Success! No need to try other trackers
                 }
                 Err(e) => {
-                    tracing::warn!("Tracker announce failed ({}): {}", tracker_url, e);
-                    
                     // Update tracker info with error
                     let mut tracker_list = self.tracker_info.write().await;
                     if let Some(tracker) = tracker_list.iter_mut().find(|t| &t.url == tracker_url) {
-                        tracker.status = crate::tracker::TrackerStatus::Error;
-                        tracker.message = format!("Error: {}", e);
+                        tracker.log_failure(&e.to_string());
+                        let permanent = match &e {
+                            crate::error::Error::TrackerFailure(reason) => {
+                                PermanentFailure::classify(reason).map(|kind| (kind, reason))
+                            }
+                            _ => None,
+                        };
+                        if let Some((kind, reason)) = permanent {
+                            tracing::warn!(
+                                "{} refused this torrent ({:?}), no longer announcing to it",
+                                tracker.display_url,
+                                kind
+                            );
+                            tracker.record_permanent_failure(reason);
+                        } else if let crate::error::Error::TlsError(text) = &e {
+                            tracker.record_tls_failure(text);
+                        } else {
+                            tracker.status = crate::tracker::TrackerStatus::Error;
+                            tracker.tls_failure = None;
+                            tracker.message = format!("Error: {}", e);
+                        }
                     }
                     
                     // Continue to next tracker
@@ -535,28 +1444,326 @@ impl TorrentEngine {
             }
         }
         
-        if !announce_succeeded {
-            tracing::error!("All trackers failed to announce");
+        if !throttled {
+            self.tracker_outage.record(&self.metainfo.info_hash_hex(), !announce_succeeded);
+        }
+        if !announce_succeeded && !throttled {
+            self.check_unregistered(&trackers_to_try).await;
         }
+        leechers
+    }
+
+    /// Log a tracker's new warning message, and tell the frontend unless
+    /// `notify_on_tracker_warning` is off
+    fn report_tracker_warning(&self, tracker: String, message: String) {
+        tracing::warn!("Tracker {} warns about {}: {}", tracker, self.metainfo.info_hash_hex(), message);
+        if !*self.notify_tracker_warnings.borrow() {
+            return;
+        }
+        if let Some(events) = &self.events {
+            let event = crate::events::TrackerWarningEvent {
+                torrent_id: self.metainfo.info_hash_hex(),
+                tracker,
+                message,
+            };
+            events.send(Event::TrackerWarning(event));
+        }
+    }
+
+    /// Pause for the user's attention once every tracker has refused the
+    /// torrent for good, instead of announcing into the void
+    async fn check_unregistered(&mut self, trackers: &[String]) {
+        let reasons: Vec<String> = {
+            let tracker_list = self.tracker_info.read().await;
+            let refused: Vec<&crate::tracker::TrackerInfo> = tracker_list
+                .iter()
+                .filter(|t| trackers.contains(&t.url) && t.status == crate::tracker::TrackerStatus::NotRegistered)
+                .collect();
+            if trackers.is_empty() || refused.len() < trackers.len() {
+                return;
+            }
+            refused.iter().map(|t| format!("{}: {}", t.display_url, t.message)).collect()
+        };
+        if *self.state.read().await == EngineState::Unregistered {
+            return;
+        }
+
+        tracing::error!("Every tracker refused {}: {}", self.metainfo.info_hash_hex(), reasons.join("; "));
+        self.handle_pause(EngineState::Unregistered).await;
+
+        if let Some(events) = &self.events {
+            let event = crate::events::TorrentUnregisteredEvent {
+                torrent_id: self.metainfo.info_hash_hex(),
+                reasons,
+            };
+            events.send(Event::TorrentUnregistered(event));
+        }
+    }
+
+    /// Append to the availability history once `SAMPLE_INTERVAL_SECS` has
+    /// passed since the last sample, or right away with `force`. Only
+    /// downloading and seeding torrents are sampled.
+    async fn sample_availability(&mut self, force: bool) {
+        use crate::availability::{distributed_copies, SAMPLE_INTERVAL_SECS};
+
+        let Some(database) = self.database.clone() else {
+            return;
+        };
+        if !matches!(*self.state.read().await, EngineState::Downloading | EngineState::Seeding) {
+            return;
+        }
+
+        let id = self.metainfo.info_hash_hex();
+        let now = chrono::Utc::now().timestamp();
+        // Without a history the first sample waits an interval, by which
+        // time the swarm has had a chance to connect
+        let last = *self.availability_sampled_at.get_or_insert_with(|| {
+            database
+                .load_availability_history(&id)
+                .ok()
+                .and_then(|series| series.last().map(|s| s.at))
+                .unwrap_or(now)
+        });
+        if !force && now - last < SAMPLE_INTERVAL_SECS {
+            return;
+        }
+
+        let piece_manager = self.piece_manager.read().await;
+        let availability = piece_manager.piece_availability();
+        let pieces_have = piece_manager.our_bitfield().count_pieces() as u32;
+        drop(piece_manager);
+        let stats = self.stats.read().await;
+        let sample = AvailabilitySample {
+            at: now,
+            swarm_seeds: stats.swarm.map(|s| s.seeds),
+            swarm_leechers: stats.swarm.map(|s| s.leechers),
+            distributed_copies: distributed_copies(&availability),
+            connected_peers: stats.connected_peers as u32,
+            pieces_have: Some(pieces_have),
+        };
+        drop(stats);
+
+        if let Err(e) = database.append_availability_sample(&id, sample) {
+            tracing::warn!("Failed to record availability of {}: {}", id, e);
+        }
+        self.availability_sampled_at = Some(now);
+    }
+
+    /// Pass hash failures recorded by the piece manager on to the UI
+    async fn emit_piece_failures(&self) {
+        let failures = self.piece_manager.write().await.take_failure_events();
+        let Some(events) = &self.events else { return };
+        let torrent_id = self.metainfo.info_hash_hex();
+        for event in failures {
+            let event = match event {
+                crate::piece::FailureEvent::Failed(failure) => {
+                    Event::PieceFailed(crate::events::PieceFailedEvent { torrent_id: torrent_id.clone(), failure })
+                }
+                crate::piece::FailureEvent::PossibleCorruption(failure) => {
+                    let message = format!(
+                        "Piece {} failed verification {} times with data from different peers. \
+                         The downloaded data may be corrupted on disk; a recheck is recommended.",
+                        failure.piece, failure.failures
+                    );
+                    Event::DiskCorruption(crate::events::DiskCorruptionEvent {
+                        torrent_id: torrent_id.clone(),
+                        failure,
+                        message,
+                    })
+                }
+            };
+            events.send(event);
+        }
+    }
+
+    /// Credit a peer's first block to the tracker that reported it (once per address)
+    async fn record_productive_peer(&self, addr: SocketAddr) {
+        let source = match self.peer_addresses.write().await.get_mut(&addr) {
+            Some(peer) if !peer.productive => {
+                peer.productive = true;
+                peer.score = peer.score.saturating_add(PRODUCTIVE_PEER_SCORE);
+                peer.last_seen = chrono::Utc::now().timestamp();
+                peer.source.clone()
+            }
+            _ => return,
+        };
+        let mut tracker_list = self.tracker_info.write().await;
+        if let Some(tracker) = tracker_list.iter_mut().find(|t| t.url == source) {
+            tracker.productive_peers += 1;
+        }
+    }
+
+    /// Lower the score of a peer that sent malformed data
+    async fn penalize_peer(&self, addr: SocketAddr) {
+        if let Some(peer) = self.peer_addresses.write().await.get_mut(&addr) {
+            peer.score = peer.score.saturating_sub(PROTOCOL_VIOLATION_PENALTY);
+            tracing::info!("Peer {} broke the wire protocol, score now {}", addr, peer.score);
+        }
+    }
+
+    /// Stop or resume announcing to one of the torrent's trackers. `url` is
+    /// the tracker's URL as added or as displayed (passkeys masked).
+    ///
+    /// A disabled tracker is neither announced to nor scraped and keeps its
+    /// last stats. One enabled again is announced to once its minimum
+    /// interval since the last announce is over.
+    pub async fn set_tracker_enabled(&mut self, url: &str, enabled: bool) -> Result<TrackerToggle, String> {
+        let url = crate::tracker::tracker_urls(&self.metainfo)
+            .into_iter()
+            .find(|tracker| tracker == url || redact_tracker_url(tracker) == url)
+            .ok_or_else(|| format!("Not a tracker of this torrent: {}", redact_tracker_url(url)))?;
+
+        let mut tracker_list = self.tracker_info.write().await;
+        let idx = match tracker_list.iter().position(|t| t.url == url) {
+            Some(idx) => idx,
+            None => {
+                tracker_list.push(crate::tracker::TrackerInfo::new(&url));
+                tracker_list.len() - 1
+            }
+        };
+        let mut warning = None;
+        if enabled {
+            if self.disabled_trackers.remove(&url) {
+                let tracker = &mut tracker_list[idx];
+                tracker.status = crate::tracker::TrackerStatus::Updating;
+                tracker.message = "Waiting to announce".to_string();
+                let delay = tracker
+                    .announce_allowed_at()
+                    .map_or(0, |at| (at - chrono::Utc::now().timestamp()).max(0) as u64);
+                self.reannounce_at = Some(time::Instant::now() + Duration::from_secs(delay));
+            }
+        } else {
+            let was_working = tracker_list[idx].status == crate::tracker::TrackerStatus::Working;
+            let others_working = tracker_list
+                .iter()
+                .any(|t| t.url != url && t.status == crate::tracker::TrackerStatus::Working);
+            if was_working && !others_working {
+                warning = Some(format!(
+                    "{} was the only working tracker; no announces reach the swarm until another one works",
+                    tracker_list[idx].display_url
+                ));
+            }
+            self.disabled_trackers.insert(url.clone());
+            tracker_list[idx].disable();
+        }
+        let tracker = &tracker_list[idx];
+        tracing::info!(
+            "Tracker {} {} for {}",
+            tracker.display_url,
+            if enabled { "enabled" } else { "disabled" },
+            self.metainfo.info_hash_hex()
+        );
+
+        let mut disabled: Vec<String> = self.disabled_trackers.iter().cloned().collect();
+        disabled.sort();
+        Ok(TrackerToggle { display_url: tracker.display_url.clone(), enabled, warning, disabled })
+    }
+
+    /// Add a peer typed in by the user ahead of every other known peer. It
+    /// is dialed right away if the torrent is active, otherwise on start.
+    pub async fn add_manual_peer(&self, addr: SocketAddr) -> ManualDial {
+        let now = chrono::Utc::now().timestamp();
+        let total_peers = {
+            let mut addresses = self.peer_addresses.write().await;
+            let peer = addresses.entry(addr).or_insert_with(|| KnownPeer {
+                source: MANUAL_PEER_SOURCE.to_string(),
+                productive: false,
+                score: 0,
+                last_seen: now,
+            });
+            peer.source = MANUAL_PEER_SOURCE.to_string();
+            peer.score = peer.score.max(MANUAL_PEER_SCORE);
+            peer.last_seen = now;
+            addresses.len()
+        };
+        self.stats.write().await.total_peers = total_peers;
+
+        let active = matches!(*self.state.read().await, EngineState::Downloading | EngineState::Seeding);
+        if let (true, Some(tx)) = (active, &self.peer_manager_tx) {
+            if tx.send(PeerManagerCommand::DialNow(addr)).await.is_ok() {
+                tracing::info!("Dialing manually added peer {}", addr);
+                return ManualDial::Dialing;
+            }
+        }
+        tracing::info!("Queued manually added peer {} until the torrent starts", addr);
+        ManualDial::Queued
     }
 
     /// Connect to available peers
     async fn connect_to_peers(&self) {
+        self.connect_to_best_peers(MAX_PEERS).await;
+    }
+
+    /// Connect to the `limit` best-scored known peers
+    async fn connect_to_best_peers(&self, limit: usize) {
         if let Some(ref peer_manager_tx) = self.peer_manager_tx {
-            let addresses = self.peer_addresses.read().await;
-            
-            // Connect to up to MAX_PEERS
-            for (i, addr) in addresses.iter().enumerate() {
-                if i >= MAX_PEERS {
-                    break;
-                }
-                
+            let best: Vec<SocketAddr> = self.best_peers(limit).await
+                .into_iter()
+                .map(|peer| peer.addr)
+                .collect();
+
+            for addr in best {
                 tracing::info!("Requesting connection to peer: {}", addr);
-                let _ = peer_manager_tx.send(PeerManagerCommand::AddPeer(*addr)).await;
+                let _ = peer_manager_tx.send(PeerManagerCommand::AddPeer(addr)).await;
             }
         }
     }
 
+    /// Known peers, best first (score, then most recently seen)
+    async fn best_peers(&self, limit: usize) -> Vec<SavedPeer> {
+        let addresses = self.peer_addresses.read().await;
+        let mut peers: Vec<SavedPeer> = addresses
+            .iter()
+            .map(|(addr, known)| SavedPeer {
+                addr: *addr,
+                source: known.source.clone(),
+                score: known.score,
+                last_seen: known.last_seen,
+            })
+            .collect();
+        peers.sort_by(|a, b| b.score.cmp(&a.score).then(b.last_seen.cmp(&a.last_seen)));
+        peers.truncate(limit);
+        peers
+    }
+
+    /// Whether `url` is one of this torrent's own trackers
+    fn is_own_tracker(&self, url: &str) -> bool {
+        self.metainfo.announce == url
+            || self.metainfo.announce_list.iter().flatten().any(|tracker| tracker == url)
+    }
+
+    /// Seed the peer store with peers saved by an earlier run (used when
+    /// restoring state). Entries not seen for `max_age` are dropped, and a
+    /// private torrent only takes peers its own trackers handed out.
+    /// Returns how many were kept.
+    pub async fn restore_peers(&self, saved: &[SavedPeer], max_age: Duration) -> usize {
+        let cutoff = chrono::Utc::now().timestamp() - max_age.as_secs() as i64;
+        let mut addresses = self.peer_addresses.write().await;
+        let before = addresses.len();
+        for peer in saved {
+            // Peers added by hand stay until the user removes the torrent
+            if !peer.is_manual() {
+                if peer.last_seen < cutoff {
+                    continue;
+                }
+                if self.metainfo.info.private && !self.is_own_tracker(&peer.source) {
+                    continue;
+                }
+            }
+            addresses.entry(peer.addr).or_insert_with(|| KnownPeer {
+                source: peer.source.clone(),
+                productive: false,
+                score: peer.score,
+                last_seen: peer.last_seen,
+            });
+        }
+        let kept = addresses.len() - before;
+        drop(addresses);
+
+        self.stats.write().await.total_peers = self.peer_addresses.read().await.len();
+        kept
+    }
+
     /// Get current engine statistics
     pub async fn get_stats(&self) -> EngineStats {
         self.stats.read().await.clone()
@@ -577,15 +1784,48 @@ impl TorrentEngine {
         self.tracker_info.read().await.clone()
     }
 
-    /// Get list of peers from peer manager
+    /// Get list of peers from peer manager, flagging the ones added by hand
     pub async fn get_peer_list(&self) -> Vec<crate::peer::PeerInfo> {
+        let mut peers = Vec::new();
         if let Some(ref tx) = self.peer_manager_tx {
             let (resp_tx, resp_rx) = oneshot::channel();
             if tx.send(PeerManagerCommand::GetPeerList(resp_tx)).await.is_ok() {
-                return resp_rx.await.unwrap_or_default();
+                peers = match time::timeout(COMMAND_TIMEOUT, resp_rx).await {
+                    Ok(peers) => peers.unwrap_or_default(),
+                    Err(_) => {
+                        tracing::warn!("Peer manager did not answer GetPeerList in time");
+                        Vec::new()
+                    }
+                };
+            }
+        }
+
+        let addresses = self.peer_addresses.read().await;
+        for peer in &mut peers {
+            let manual = peer.ip.parse().ok().is_some_and(|ip| {
+                addresses
+                    .get(&SocketAddr::new(ip, peer.port))
+                    .is_some_and(|known| known.source == MANUAL_PEER_SOURCE)
+            });
+            if manual {
+                peer.flags.push('M');
+            }
+        }
+        peers
+    }
+
+    /// Recent choking decisions about a connected peer
+    pub async fn get_peer_choke_history(&self, addr: SocketAddr) -> Option<Vec<crate::peer::choking::ChokeDecision>> {
+        let tx = self.peer_manager_tx.as_ref()?;
+        let (resp_tx, resp_rx) = oneshot::channel();
+        tx.send(PeerManagerCommand::GetChokeHistory(addr, resp_tx)).await.ok()?;
+        match time::timeout(COMMAND_TIMEOUT, resp_rx).await {
+            Ok(history) => history.ok().flatten(),
+            Err(_) => {
+                tracing::warn!("Peer manager did not answer GetChokeHistory in time");
+                None
             }
         }
-        Vec::new()
     }
 
     /// Get the peer manager command sender if available
@@ -593,45 +1833,268 @@ impl TorrentEngine {
         self.peer_manager_tx.clone()
     }
 
+    /// Restore a saved bitfield. Pieces in files now shorter than they need
+    /// to be are dropped outright; pieces that may not have reached the disk
+    /// before the last shutdown (see `SyncPoint`) are re-verified from disk
+    /// and dropped if they don't match. Returns the pieces that were re-verified.
+    pub async fn restore_progress(&self, bitfield: &[u8], data_sync: &SyncPoint) -> Vec<usize> {
+        let mut pm = self.piece_manager.write().await;
+        pm.restore_bitfield(bitfield);
+
+        let truncated = self.disk_manager.read().await.truncated_pieces(pm.our_bitfield());
+        if !truncated.is_empty() {
+            tracing::warn!(
+                "{} pieces lie past the end of a truncated file, downloading them again",
+                truncated.len()
+            );
+            for &piece in &truncated {
+                pm.unmark_piece(piece);
+            }
+        }
+
+        let suspect: Vec<usize> = data_sync
+            .unsynced_pieces
+            .iter()
+            .map(|&i| i as usize)
+            .filter(|&i| pm.has_piece(i))
+            .collect();
+        if suspect.is_empty() {
+            return suspect;
+        }
+
+        let dm = self.disk_manager.read().await;
+        for &piece in &suspect {
+            let intact = if dm.piece_is_sparse(piece) == Some(true) {
+                // Never written, only preallocated: it would read back as zeros
+                pm.piece_data_matches(piece, &vec![0u8; pm.piece_len(piece)])
+            } else {
+                match dm.read_piece(piece).await {
+                    Ok(data) => pm.piece_data_matches(piece, &data),
+                    Err(_) => false,
+                }
+            };
+            if !intact {
+                tracing::warn!("Piece {} was not fully written before shutdown, downloading it again", piece);
+                pm.unmark_piece(piece);
+            }
+        }
+        tracing::info!("Re-verified {} pieces saved before their data was synced", suspect.len());
+        suspect
+    }
+
+    /// False until a magnet torrent's metadata has arrived
+    pub fn has_metadata(&self) -> bool {
+        self.metainfo.has_metadata()
+    }
+
+    /// Piece map for the UI, in at most `max_buckets` buckets
+    pub async fn pieces_info(&self, max_buckets: usize) -> MetadataResult<PiecesInfo> {
+        if !self.has_metadata() {
+            return MetadataResult::Pending;
+        }
+        MetadataResult::Ready(self.piece_manager.read().await.get_pieces_info(max_buckets))
+    }
+
+    /// Exact state of up to `crate::piece::buckets::MAX_PIECE_WINDOW` pieces
+    /// from `start`
+    pub async fn piece_window(&self, start: usize, count: usize) -> MetadataResult<PieceWindow> {
+        if !self.has_metadata() {
+            return MetadataResult::Pending;
+        }
+        let count = count.min(crate::piece::buckets::MAX_PIECE_WINDOW);
+        MetadataResult::Ready(self.piece_manager.read().await.piece_window(start, count))
+    }
+
+    /// Files with their downloaded bytes for the UI
+    pub async fn file_list(&self) -> MetadataResult<Vec<FileInfoUI>> {
+        if !self.has_metadata() {
+            return MetadataResult::Pending;
+        }
+        let progress = self.piece_manager.read().await.calculate_file_progress(&self.metainfo.info.files);
+        let mut files = crate::torrent::get_file_list(&self.metainfo, Some(&progress));
+        for (&file_index, prefix) in &self.prefixes {
+            if let Some(file) = files.get_mut(file_index) {
+                file.prefix_only = Some(prefix.bytes);
+            }
+        }
+        for (&file_index, &completed_at) in &self.file_completed_at {
+            if let Some(file) = files.get_mut(file_index) {
+                file.completed_at = Some(completed_at);
+            }
+        }
+        for (file, on_disk) in files.iter_mut().zip(self.disk_manager.read().await.files()) {
+            if let Ok(metadata) = std::fs::metadata(&on_disk.path) {
+                file.on_disk_size = Some(metadata.len());
+                file.modified = metadata
+                    .modified()
+                    .ok()
+                    .and_then(|time| time.duration_since(std::time::UNIX_EPOCH).ok())
+                    .map(|since| since.as_secs() as i64);
+            }
+        }
+        MetadataResult::Ready(files)
+    }
+
+    /// Swap a magnet stub for the real metadata, then tell the UI with
+    /// `metadata-ready`. The piece and disk managers are rebuilt in place so
+    /// anything holding them sees the new layout; magnets have no saved root
+    /// name, so the files go under the metadata's own name.
+    async fn apply_metadata(&mut self, mut metainfo: Metainfo) -> Result<(), String> {
+        if self.has_metadata() {
+            return Err("torrent already has its metadata".to_string());
+        }
+        if metainfo.info_hash != self.metainfo.info_hash {
+            return Err(format!("metadata is for {}", metainfo.info_hash_hex()));
+        }
+        if !metainfo.has_metadata() {
+            return Err("metadata has no pieces".to_string());
+        }
+        // A bare info dictionary comes without trackers: keep the magnet's
+        if metainfo.announce.is_empty() && metainfo.announce_list.is_empty() {
+            metainfo.announce = self.metainfo.announce.clone();
+            metainfo.announce_list = self.metainfo.announce_list.clone();
+        }
+
+        *self.piece_manager.write().await = Self::build_piece_manager(&metainfo);
+        self.file_progress = FileProgressTracker::new(&metainfo.info.files, metainfo.info.piece_length);
+        self.file_completed_at.clear();
+        let mut disk_manager = DiskManager::new(&metainfo, self.download_dir.clone());
+        disk_manager.set_storage_mode(self.storage_mode);
+        *self.disk_manager.write().await = disk_manager;
+        self.metainfo = Arc::new(metainfo);
+        let torrent_id = self.metainfo.info_hash_hex();
+        tracing::info!(
+            "Metadata received for {}: {} ({} pieces)",
+            torrent_id, self.metainfo.info.name, self.metainfo.info.piece_count
+        );
+
+        if let Some(ref database) = self.database {
+            let saved = database.load_torrent(&torrent_id).and_then(|session| match session {
+                Some(mut session) => {
+                    session.metainfo = (*self.metainfo).clone();
+                    session.num_pieces = self.metainfo.info.piece_count;
+                    session.bitfield = Vec::new();
+                    database.save_torrent(&session)
+                }
+                None => Ok(()),
+            });
+            if let Err(e) = saved {
+                tracing::error!("Failed to save metadata for {}: {}", torrent_id, e);
+            }
+            crate::search::index_metainfo(database, &torrent_id, &self.metainfo);
+        }
+
+        if let Some(events) = &self.events {
+            let event = crate::events::MetadataReadyEvent {
+                torrent_id,
+                name: self.metainfo.info.name.clone(),
+                size: self.metainfo.info.total_size,
+                file_count: self.metainfo.info.files.len(),
+            };
+            events.send(Event::MetadataReady(event));
+        }
+        Ok(())
+    }
+
     /// Get the piece manager
     pub fn piece_manager(&self) -> Arc<RwLock<PieceManager>> {
         self.piece_manager.clone()
     }
 
+    /// Get the disk manager
+    pub fn disk_manager(&self) -> Arc<RwLock<DiskManager>> {
+        self.disk_manager.clone()
+    }
+
+    /// Split the consolidated file of a complete download into the real
+    /// files and record the switch. Uploads wait on the disk lock meanwhile.
+    pub async fn extract_parts(&mut self) -> Result<(), String> {
+        if self.storage_mode != StorageMode::Consolidated {
+            return Err("Torrent is not stored consolidated".to_string());
+        }
+        if !self.has_metadata() || !self.piece_manager.read().await.is_complete() {
+            return Err("Torrent is not complete yet".to_string());
+        }
+
+        self.disk_manager
+            .write()
+            .await
+            .extract()
+            .await
+            .map_err(|e| format!("Failed to extract files: {}", e))?;
+        self.storage_mode = StorageMode::Files;
+
+        if let Some(ref database) = self.database {
+            let id = self.metainfo.info_hash_hex();
+            let saved = database.load_torrent(&id).and_then(|session| match session {
+                Some(mut session) => {
+                    session.storage_mode = StorageMode::Files;
+                    database.save_torrent(&session)
+                }
+                None => Ok(()),
+            });
+            if let Err(e) = saved {
+                tracing::error!("Failed to save storage mode of {}: {}", id, e);
+            }
+        }
+        Ok(())
+    }
+
     /// Update engine statistics
     async fn update_stats(&mut self) {
         let mut stats = self.stats.write().await;
         let pm = self.piece_manager.read().await;
 
         stats.state = *self.state.read().await;
-        stats.progress = pm.completion();
+        // Count down to the next attempt
+        if let Some(status) = self.start_retry.status(time::Instant::now()) {
+            stats.error = Some(status);
+        }
+        // An empty bitfield counts as complete, which a magnet stub is not.
+        // Skipped pieces don't count, so a file prefix reads as done when it is.
+        let (wanted, have_wanted) = pm.wanted_bytes();
+        stats.progress = match (self.has_metadata(), wanted) {
+            (false, _) => 0.0,
+            (true, 0) => pm.completion(),
+            (true, wanted) => have_wanted as f64 / wanted as f64,
+        };
 
         // Get peer stats from peer manager if available
         if let Some(ref peer_manager_tx) = self.peer_manager_tx {
             let (tx, rx) = oneshot::channel();
             if peer_manager_tx.send(PeerManagerCommand::GetStats(tx)).await.is_ok() {
-                if let Ok(peer_stats) = rx.await {
+                if let Ok(Ok(peer_stats)) = time::timeout(COMMAND_TIMEOUT, rx).await {
+                    self.peer_totals = PeerTotals {
+                        downloaded: peer_stats.total_downloaded,
+                        uploaded: peer_stats.total_uploaded,
+                        traffic: peer_stats.traffic,
+                    };
+                    let totals = self.retired_peer_totals.plus(&self.peer_totals);
                     stats.connected_peers = peer_stats.connected_peers;
-                    stats.downloaded_bytes = peer_stats.total_downloaded;
-                    stats.uploaded_bytes = peer_stats.total_uploaded;
+                    stats.connected_seeds = peer_stats.connected_seeds;
+                    stats.downloaded_bytes = totals.downloaded;
+                    stats.uploaded_bytes = totals.uploaded;
                     stats.download_speed = peer_stats.download_speed;
                     stats.upload_speed = peer_stats.upload_speed;
+                    stats.traffic = self.traffic_base.combined(&totals.traffic);
                 }
             }
         }
 
         // Calculate ETA
-        if stats.download_speed > 0.0 {
-            let remaining = self.metainfo.info.total_size - stats.downloaded_bytes;
+        if stats.download_speed > 0.0 && self.has_metadata() {
+            let remaining = wanted - have_wanted;
             stats.eta_seconds = Some((remaining as f64 / stats.download_speed) as u64);
         } else {
             stats.eta_seconds = None;
         }
 
         stats.completed_at = self.completed_at;
+        stats.swarm = self.swarm;
+        stats.dropped_commands = self.command_handle.dropped_commands();
 
         // Check if we're complete
-        if pm.is_complete() {
+        if pm.is_complete() && self.has_metadata() {
              if stats.state == EngineState::Downloading {
                 drop(stats); // Release lock before modifying state
                 drop(pm);
@@ -652,38 +2115,75 @@ impl TorrentEngine {
     /// Save progress to database
     async fn save_progress(&self) {
         if let Some(ref database) = self.database {
+            // Sync the data before saving the bitfield that vouches for it.
+            // Piece writes take the disk lock before marking the piece, so
+            // holding it here means every piece in the bitfield is on disk.
+            let mut dm = self.disk_manager.write().await;
+            if let Err(e) = dm.sync().await {
+                tracing::warn!("Failed to sync torrent data: {}", e);
+            }
             let pm = self.piece_manager.read().await;
+            let data_sync = dm.sync_point(pm.our_bitfield());
+            drop(dm);
+            let saved_peers = self.best_peers(MAX_SAVED_PEERS).await;
             let stats = self.stats.read().await;
             let state = *self.state.read().await;
             let id = hex::encode(self.metainfo.info_hash);
 
-            // Preserve original added_at from existing DB entry
-            let added_at = database
-                .load_torrent(&id)
-                .ok()
-                .flatten()
-                .map(|s| s.added_at)
-                .unwrap_or_else(|| chrono::Utc::now().timestamp());
-
-            let session = TorrentSession {
-                id: id.clone(),
-                metainfo: (*self.metainfo).clone(),
+            let progress = SessionProgress {
                 bitfield: pm.our_bitfield().as_bytes().to_vec(),
-                num_pieces: pm.our_bitfield().num_pieces(),
                 downloaded: stats.downloaded_bytes,
                 uploaded: stats.uploaded_bytes,
                 state: format!("{:?}", state).to_lowercase(),
-                download_dir: self.download_dir.to_string_lossy().to_string(),
-                added_at,
                 last_activity: chrono::Utc::now().timestamp(),
-                source: crate::debrid::types::DownloadSource::P2P, // Default to P2P
                 completed_at: self.completed_at,
+                swarm: self.swarm,
+                traffic: stats.traffic,
+                data_sync,
+                saved_peers,
+                file_completed_at: self.file_completed_at.clone(),
+            };
+
+            // Usually only the progress record is rewritten; the full
+            // session (with metainfo) is written the first time only
+            let saved = match database.update_session_progress(&id, progress.clone()) {
+                Ok(true) => Ok(()),
+                Ok(false) => database.save_torrent(&TorrentSession {
+                    id: id.clone(),
+                    metainfo: (*self.metainfo).clone(),
+                    bitfield: progress.bitfield,
+                    num_pieces: pm.our_bitfield().num_pieces(),
+                    downloaded: progress.downloaded,
+                    uploaded: progress.uploaded,
+                    state: progress.state,
+                    download_dir: self.download_dir.to_string_lossy().to_string(),
+                    added_at: progress.last_activity,
+                    last_activity: progress.last_activity,
+                    source: crate::debrid::types::DownloadSource::P2P,
+                    completed_at: progress.completed_at,
+                    volume_id: None,
+                    category: None,
+                    traffic: progress.traffic,
+                    swarm: progress.swarm,
+                    root_name: None,
+                    data_sync: progress.data_sync,
+                    queue_position: None,
+                    saved_peers: progress.saved_peers,
+                    added_from: None,
+                    storage_mode: Default::default(),
+                    auto_stop: Default::default(),
+                    low_disk_paused_at: None,
+                    linked_files: Vec::new(),
+                    disabled_trackers: Vec::new(),
+                    file_completed_at: progress.file_completed_at,
+                }),
+                Err(e) => Err(e),
             };
 
-            if let Err(e) = database.save_torrent(&session) {
+            if let Err(e) = saved {
                 tracing::error!("Failed to save progress to database: {}", e);
             } else {
-                tracing::debug!("Progress saved to database for torrent {}", session.id);
+                tracing::debug!("Progress saved to database for torrent {}", id);
             }
         }
     }
@@ -692,6 +2192,7 @@ impl TorrentEngine {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::piece::buckets::DEFAULT_MAX_BUCKETS;
     use crate::torrent::{TorrentInfo, FileInfo};
 
     fn create_test_metainfo() -> Metainfo {
@@ -709,6 +2210,8 @@ mod tests {
                 name: "test.txt".to_string(),
                 total_size: 20000,
                 is_single_file: true,
+                private: false,
+                legacy_names: None,
             },
             info_hash: [0u8; 20],
             creation_date: None,
@@ -721,7 +2224,7 @@ mod tests {
     async fn test_engine_creation() {
         let metainfo = create_test_metainfo();
         let download_dir = PathBuf::from("/tmp/test_engine");
-        let engine = TorrentEngine::new(metainfo, download_dir);
+        let engine = TorrentEngine::new(metainfo, download_dir, None);
 
         assert_eq!(engine.get_state().await, EngineState::Stopped);
         
@@ -734,16 +2237,1142 @@ mod tests {
     async fn test_engine_command_sender() {
         let metainfo = create_test_metainfo();
         let download_dir = PathBuf::from("/tmp/test_engine2");
-        let engine = TorrentEngine::new(metainfo, download_dir);
+        let engine = TorrentEngine::new(metainfo, download_dir, None);
 
-        let tx = engine.command_sender();
+        let handle = engine.command_handle();
         
         // Send a command
         let (stats_tx, _stats_rx) = oneshot::channel();
-        tx.send(EngineCommand::GetStats(stats_tx)).unwrap();
+        handle.send(EngineCommand::GetStats(stats_tx)).await.unwrap();
 
-        // The command was sent successfully
+        // The command was queued
         // (we can't test receiving without running the engine)
+        assert_eq!(handle.queued_commands(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_engine_stops_with_full_queue() {
+        let metainfo = create_test_metainfo();
+        let download_dir = PathBuf::from("/tmp/test_engine3");
+        let mut engine = TorrentEngine::new(metainfo, download_dir, None);
+
+        let handle = engine.command_handle();
+        for _ in 0..COMMAND_CHANNEL_CAPACITY {
+            handle.send(EngineCommand::SetStrategy(SelectionStrategy::Sequential)).await.unwrap();
+        }
+        handle.send(EngineCommand::Stop).await.unwrap();
+
+        // Biased select picks the priority Stop before the queued backlog
+        time::timeout(Duration::from_secs(5), engine.run())
+            .await
+            .expect("engine should stop despite the full queue");
+        assert_eq!(engine.get_state().await, EngineState::Stopped);
+    }
+
+    #[tokio::test]
+    async fn test_metrics_follow_engine_loop() {
+        let mut engine = TorrentEngine::new(create_test_metainfo(), PathBuf::from("/tmp/test_engine_metrics"), None);
+        let handle = engine.command_handle();
+        for _ in 0..3 {
+            handle.send(EngineCommand::SetStrategy(SelectionStrategy::Sequential)).await.unwrap();
+        }
+        assert_eq!(handle.metrics_snapshot().engine_queue, 3);
+
+        let task = tokio::spawn(async move { engine.run().await });
+        handle.request_stats().await.unwrap();
+        let snapshot = handle.metrics_snapshot();
+        assert_eq!(snapshot.engine_queue, 0);
+        // The three queued commands and GetStats, each one iteration
+        assert!(snapshot.loop_iterations >= 4, "{:?}", snapshot);
+        assert_eq!(snapshot.slow_stats_responses, 0);
+
+        handle.stop().unwrap();
+        time::timeout(Duration::from_secs(5), task).await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_announce_uses_configured_port() {
+        let mut engine = TorrentEngine::new(create_test_metainfo(), PathBuf::from("/tmp/test_engine4"), None);
+        assert_eq!(engine.announce_request().await.port, DEFAULT_LISTEN_PORT);
+
+        let (port_tx, port_rx) = watch::channel(51413);
+        engine.set_listen_port(port_rx);
+        assert_eq!(engine.announce_request().await.port, 51413);
+
+        // Runtime changes are picked up by the next announce
+        port_tx.send(40000).unwrap();
+        assert_eq!(engine.announce_request().await.port, 40000);
+    }
+
+    #[tokio::test]
+    async fn test_announce_leaves_out_failed_bytes() {
+        let mut engine = TorrentEngine::new(create_test_metainfo(), PathBuf::from("/tmp/test_engine_waste"), None);
+        // Earlier runs don't count towards this run's announces
+        engine.set_traffic_base(TrafficStats { payload_downloaded: 5000, failed_downloaded: 1000, ..Default::default() });
+        assert_eq!(engine.announce_request().await.downloaded, 0);
+
+        engine.stats.write().await.traffic = TrafficStats {
+            payload_downloaded: 5000 + 40000,
+            failed_downloaded: 1000 + 16384,
+            redundant_downloaded: 2000,
+            discarded_downloaded: 3000,
+            ..Default::default()
+        };
+        assert_eq!(engine.announce_request().await.downloaded, 40000 - 16384);
+        // The raw counter stays as received
+        assert_eq!(engine.get_stats().await.traffic.payload_downloaded, 45000);
+    }
+
+    #[tokio::test]
+    async fn test_anonymous_mode_switches_announce_identity() {
+        let mut engine = TorrentEngine::new(create_test_metainfo(), PathBuf::from("/tmp/test_engine5"), None);
+        let (anon_tx, anon_rx) = watch::channel(false);
+        engine.set_anonymous_mode(anon_rx);
+
+        let normal = engine.announce_request().await;
+        assert!(!normal.anonymous);
+        assert_eq!(&normal.peer_id[0..8], b"-SC0100-");
+
+        // Toggled at runtime, no restart
+        anon_tx.send(true).unwrap();
+        let anonymous = engine.announce_request().await;
+        assert!(anonymous.anonymous);
+        assert_ne!(anonymous.peer_id, normal.peer_id);
+        assert_ne!(&anonymous.peer_id[0..8], b"-SC0100-");
+    }
+
+    /// Tracker that hands out compact peers and counts announces; with
+    /// `fail_first` the first announce gets an HTTP error
+    async fn fake_tracker(peers: Vec<SocketAddr>, fail_first: bool) -> (String, Arc<std::sync::atomic::AtomicUsize>) {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/announce", listener.local_addr().unwrap());
+        let announces = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let counter = announces.clone();
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let mut request = Vec::new();
+                let mut buf = [0u8; 1024];
+                while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+                    match socket.read(&mut buf).await {
+                        Ok(0) | Err(_) => break,
+                        Ok(n) => request.extend_from_slice(&buf[..n]),
+                    }
+                }
+                let n = counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                if fail_first && n == 0 {
+                    let _ = socket.write_all(b"HTTP/1.1 500 Internal Server Error\r\nContent-Length: 0\r\nConnection: close\r\n\r\n").await;
+                    continue;
+                }
+
+                let mut body = format!("d8:intervali1800e5:peers{}:", peers.len() * 6).into_bytes();
+                for peer in &peers {
+                    let SocketAddr::V4(v4) = peer else { unreachable!() };
+                    body.extend_from_slice(&v4.ip().octets());
+                    body.extend_from_slice(&v4.port().to_be_bytes());
+                }
+                body.push(b'e');
+                let head = format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", body.len());
+                let _ = socket.write_all(head.as_bytes()).await;
+                let _ = socket.write_all(&body).await;
+            }
+        });
+        (url, announces)
+    }
+
+    /// Tracker that answers every announce with `reason`, counting announces
+    async fn refusing_tracker(reason: &'static str) -> (String, Arc<std::sync::atomic::AtomicUsize>) {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/announce", listener.local_addr().unwrap());
+        let announces = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let counter = announces.clone();
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let mut buf = [0u8; 1024];
+                let _ = socket.read(&mut buf).await;
+                counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                let body = format!("d14:failure reason{}:{}e", reason.len(), reason);
+                let head = format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", body.len());
+                let _ = socket.write_all(head.as_bytes()).await;
+                let _ = socket.write_all(body.as_bytes()).await;
+            }
+        });
+        (url, announces)
+    }
+
+    #[tokio::test]
+    async fn test_trackers_refusing_the_torrent_stop_announces() {
+        use crate::tracker::TrackerStatus;
+        use std::sync::atomic::Ordering;
+
+        let (gone, gone_announces) = refusing_tracker("Unregistered torrent").await;
+        let (busy, busy_announces) = refusing_tracker("Tracker is down for maintenance").await;
+        let (banned, _) = refusing_tracker("Your client is banned.").await;
+        let mut metainfo = create_test_metainfo();
+        metainfo.announce = gone.clone();
+        metainfo.announce_list = vec![vec![gone.clone()], vec![busy.clone()]];
+        let mut engine = TorrentEngine::new(metainfo.clone(), PathBuf::from("/tmp/test_engine9"), None);
+        let (pm_tx, mut pm_rx) = mpsc::channel(16);
+        engine.peer_manager_tx = Some(pm_tx);
+        *engine.state.write().await = EngineState::Downloading;
+
+        engine.announce_to_tracker(false).await;
+        engine.announce_to_tracker(false).await;
+        assert_eq!(gone_announces.load(Ordering::SeqCst), 1);
+        assert_eq!(busy_announces.load(Ordering::SeqCst), 2);
+        let trackers = engine.get_tracker_list().await;
+        let status = |url: &str| trackers.iter().find(|t| t.url == url).map(|t| (t.status, t.message.clone()));
+        assert_eq!(status(&gone), Some((TrackerStatus::NotRegistered, "Unregistered torrent".to_string())));
+        assert_eq!(status(&busy).map(|s| s.0), Some(TrackerStatus::Error));
+
+        // One tracker still might come back: keep going
+        assert_eq!(engine.get_state().await, EngineState::Downloading);
+        assert!(pm_rx.try_recv().is_err());
+
+        // All of them refusing for good needs the user
+        metainfo.announce_list = vec![vec![gone.clone()], vec![banned.clone()]];
+        let mut engine = TorrentEngine::new(metainfo, PathBuf::from("/tmp/test_engine10"), None);
+        let (pm_tx, mut pm_rx) = mpsc::channel(16);
+        engine.peer_manager_tx = Some(pm_tx);
+        *engine.state.write().await = EngineState::Downloading;
+
+        engine.announce_to_tracker(false).await;
+        assert_eq!(engine.get_state().await, EngineState::Unregistered);
+        assert!(matches!(pm_rx.try_recv(), Ok(PeerManagerCommand::Pause)));
+
+        // Starting by hand tries them again
+        engine.handle_start().await;
+        assert_eq!(gone_announces.load(Ordering::SeqCst), 3);
+        assert_eq!(engine.get_state().await, EngineState::Unregistered);
+    }
+
+    /// Tracker whose announces carry `warning` (none when empty) and a two
+    /// minute interval
+    async fn warning_tracker(warning: Arc<std::sync::Mutex<String>>) -> String {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/announce", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let mut buf = [0u8; 1024];
+                let _ = socket.read(&mut buf).await;
+                let warning = warning.lock().unwrap().clone();
+                let warning = match warning.len() {
+                    0 => String::new(),
+                    len => format!("15:warning message{}:{}", len, warning),
+                };
+                let body = format!("d8:intervali120e5:peers0:{}e", warning);
+                let head = format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", body.len());
+                let _ = socket.write_all(head.as_bytes()).await;
+                let _ = socket.write_all(body.as_bytes()).await;
+            }
+        });
+        url
+    }
+
+    /// UDP tracker (BEP 15) that answers announces with `peers`, or with
+    /// `reason` as an error
+    async fn udp_tracker(peers: Vec<SocketAddr>, reason: Option<&'static str>) -> String {
+        let socket = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("udp://{}/announce", socket.local_addr().unwrap());
+        tokio::spawn(async move {
+            let mut buf = [0u8; 1024];
+            while let Ok((len, from)) = socket.recv_from(&mut buf).await {
+                let action = u32::from_be_bytes([buf[8], buf[9], buf[10], buf[11]]);
+                let mut answer = Vec::new();
+                match (action, reason) {
+                    (0, _) => {
+                        answer.extend_from_slice(&0u32.to_be_bytes());
+                        answer.extend_from_slice(&buf[12..16]);
+                        answer.extend_from_slice(&42u64.to_be_bytes());
+                    }
+                    (_, Some(reason)) => {
+                        answer.extend_from_slice(&3u32.to_be_bytes());
+                        answer.extend_from_slice(&buf[12..16]);
+                        answer.extend_from_slice(reason.as_bytes());
+                    }
+                    (_, None) if len == 98 => {
+                        answer.extend_from_slice(&1u32.to_be_bytes());
+                        answer.extend_from_slice(&buf[12..16]);
+                        for value in [1800u32, 0, 1] {
+                            answer.extend_from_slice(&value.to_be_bytes());
+                        }
+                        for peer in &peers {
+                            let SocketAddr::V4(v4) = peer else { unreachable!() };
+                            answer.extend_from_slice(&v4.ip().octets());
+                            answer.extend_from_slice(&v4.port().to_be_bytes());
+                        }
+                    }
+                    _ => continue,
+                }
+                let _ = socket.send_to(&answer, from).await;
+            }
+        });
+        url
+    }
+
+    #[tokio::test]
+    async fn test_udp_trackers_are_announced_to() {
+        use crate::tracker::TrackerStatus;
+
+        let swarm_peer: SocketAddr = "10.1.2.3:6881".parse().unwrap();
+        let busy = udp_tracker(Vec::new(), Some("Tracker is down for maintenance")).await;
+        let working = udp_tracker(vec![swarm_peer], None).await;
+        // Bound, so nothing else answers there, but never read
+        let silent_socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let silent = format!("udp://{}/announce", silent_socket.local_addr().unwrap());
+
+        let mut metainfo = create_test_metainfo();
+        metainfo.announce = silent.clone();
+        metainfo.announce_list = vec![vec![silent.clone(), busy.clone()], vec![working.clone()]];
+        let mut engine = TorrentEngine::new(metainfo, PathBuf::from("/tmp/test_engine_udp"), None);
+        engine.udp_tracker = Arc::new(UdpTracker::with_timeouts(Duration::from_millis(50), 1));
+
+        assert_eq!(engine.announce_to_tracker(false).await, Some(0));
+        assert!(engine.peer_addresses.read().await.contains_key(&swarm_peer));
+
+        let trackers = engine.get_tracker_list().await;
+        let status = |url: &str| trackers.iter().find(|t| t.url == url).map(|t| (t.status, t.message.clone()));
+        let (silent_status, silent_message) = status(&silent).unwrap();
+        assert_eq!(silent_status, TrackerStatus::Error);
+        assert!(silent_message.contains("No answer"), "{}", silent_message);
+        assert_eq!(
+            status(&busy),
+            Some((TrackerStatus::Error, "Error: Tracker error: Tracker is down for maintenance".to_string()))
+        );
+        assert_eq!(status(&working).map(|s| s.0), Some(TrackerStatus::Working));
+    }
+
+    #[tokio::test]
+    async fn test_tracker_warnings_and_short_intervals() {
+        let warning = Arc::new(std::sync::Mutex::new("Slow down".to_string()));
+        let url = warning_tracker(warning.clone()).await;
+        let mut metainfo = create_test_metainfo();
+        metainfo.announce = url;
+        let (sink, mut events) = mpsc::unbounded_channel::<Event>();
+        let mut engine = TorrentEngine::new(metainfo, PathBuf::from("/tmp/test_engine_warn"), Some(Arc::new(sink) as EventSink));
+        let (notify, notify_rx) = watch::channel(true);
+        engine.set_notify_tracker_warnings(notify_rx);
+        let mut warnings = move || {
+            let mut messages = Vec::new();
+            while let Ok(event) = events.try_recv() {
+                if let Event::TrackerWarning(warning) = event {
+                    messages.push(warning.message);
+                }
+            }
+            messages
+        };
+
+        // The same warning twice is reported once
+        engine.announce_to_tracker(false).await;
+        engine.announce_to_tracker(false).await;
+        assert_eq!(warnings(), vec!["Slow down".to_string()]);
+        assert_eq!(engine.get_tracker_list().await[0].warning.as_deref(), Some("Slow down"));
+
+        // The two minute interval brings the next announce forward
+        let next = engine.reannounce_at.expect("a short interval schedules an announce");
+        let wait = next.saturating_duration_since(time::Instant::now());
+        assert!(wait > Duration::from_secs(100) && wait <= Duration::from_secs(120), "{:?}", wait);
+
+        // A new message is reported, unless the setting is off
+        *warning.lock().unwrap() = "Ratio too low".to_string();
+        engine.announce_to_tracker(false).await;
+        notify.send_replace(false);
+        *warning.lock().unwrap() = "Account disabled".to_string();
+        engine.announce_to_tracker(false).await;
+        assert_eq!(warnings(), vec!["Ratio too low".to_string()]);
+        assert_eq!(engine.get_tracker_list().await[0].warning.as_deref(), Some("Account disabled"));
+
+        // The warning goes once the tracker stops sending it
+        warning.lock().unwrap().clear();
+        engine.announce_to_tracker(false).await;
+        assert_eq!(engine.get_tracker_list().await[0].warning, None);
+    }
+
+    #[tokio::test]
+    async fn test_resume_reannounces_then_reconnects() {
+        use std::sync::atomic::Ordering;
+
+        let swarm_peer: SocketAddr = "10.1.2.3:6881".parse().unwrap();
+        let (url, announces) = fake_tracker(vec![swarm_peer], false).await;
+        let mut metainfo = create_test_metainfo();
+        metainfo.announce = url;
+        let mut engine = TorrentEngine::new(metainfo, PathBuf::from("/tmp/test_engine6"), None);
+
+        // Stand-in for a running peer manager
+        let (pm_tx, mut pm_rx) = mpsc::channel(16);
+        engine.peer_manager_tx = Some(pm_tx);
+        *engine.state.write().await = EngineState::Paused;
+
+        engine.handle_start().await;
+        assert_eq!(engine.get_state().await, EngineState::Downloading);
+        assert_eq!(announces.load(Ordering::SeqCst), 1);
+
+        // Resume goes first so the peer manager reconnects old peers before new ones
+        assert!(matches!(pm_rx.try_recv(), Ok(PeerManagerCommand::Resume)));
+        assert!(matches!(pm_rx.try_recv(), Ok(PeerManagerCommand::AddPeer(a)) if a == swarm_peer));
+        assert!(pm_rx.try_recv().is_err());
+
+        // A quick pause/resume doesn't hammer the tracker inside its minimum interval
+        engine.handle_pause(EngineState::Paused).await;
+        engine.handle_start().await;
+        assert_eq!(announces.load(Ordering::SeqCst), 1);
+        assert!(matches!(pm_rx.try_recv(), Ok(PeerManagerCommand::Pause)));
+        assert!(matches!(pm_rx.try_recv(), Ok(PeerManagerCommand::Resume)));
+        assert!(matches!(pm_rx.try_recv(), Ok(PeerManagerCommand::AddPeer(a)) if a == swarm_peer));
+    }
+
+    /// Tracker reporting one seed and `leechers` leechers and no peers,
+    /// counting announces
+    async fn swarm_tracker(leechers: Arc<std::sync::atomic::AtomicU32>) -> (String, Arc<std::sync::atomic::AtomicUsize>) {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/announce", listener.local_addr().unwrap());
+        let announces = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let counter = announces.clone();
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let mut buf = [0u8; 1024];
+                let _ = socket.read(&mut buf).await;
+                counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                let incomplete = leechers.load(std::sync::atomic::Ordering::SeqCst);
+                let body = format!("d8:completei1e10:incompletei{}e8:intervali1800e5:peers0:e", incomplete);
+                let head = format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", body.len());
+                let _ = socket.write_all(head.as_bytes()).await;
+                let _ = socket.write_all(body.as_bytes()).await;
+            }
+        });
+        (url, announces)
+    }
+
+    /// A complete engine announcing to `url`, seeding through a stand-in
+    /// peer manager that reports 1000 bytes uploaded and counts dials
+    async fn seeding_engine(url: String, dir: &str) -> (TorrentEngine, Arc<std::sync::atomic::AtomicUsize>, CancellationToken) {
+        let mut metainfo = create_test_metainfo();
+        metainfo.announce = url;
+        let mut engine = TorrentEngine::new(metainfo, PathBuf::from(dir), None);
+        engine.piece_manager.write().await.restore_bitfield(&[0b1100_0000]);
+        let (pm_tx, mut pm_rx) = mpsc::channel(16);
+        let peer_cancel = engine.cancel_token.child_token();
+        engine.peer_manager_tx = Some(pm_tx);
+        engine.peer_cancel = Some(peer_cancel.clone());
+        *engine.state.write().await = EngineState::Seeding;
+
+        let dials = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let counter = dials.clone();
+        tokio::spawn(async move {
+            while let Some(command) = pm_rx.recv().await {
+                match command {
+                    PeerManagerCommand::GetStats(tx) => {
+                        let _ = tx.send(crate::peer::PeerManagerStats {
+                            connected_peers: 2,
+                            connected_seeds: 0,
+                            total_downloaded: 0,
+                            total_uploaded: 1000,
+                            download_speed: 0.0,
+                            upload_speed: 50.0,
+                            traffic: TrafficStats::default(),
+                        });
+                    }
+                    PeerManagerCommand::AddPeer(_) => {
+                        counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                    }
+                    _ => {}
+                }
+            }
+        });
+        (engine, dials, peer_cancel)
+    }
+
+    #[tokio::test]
+    async fn test_paused_seeding_transitions() {
+        use std::sync::atomic::{AtomicU32, Ordering};
+
+        let (url, announces) = swarm_tracker(Arc::new(AtomicU32::new(0))).await;
+        let (mut engine, _, peer_cancel) = seeding_engine(url.clone(), "/tmp/test_engine_ps1").await;
+
+        // Only complete torrents qualify
+        let mut metainfo = create_test_metainfo();
+        metainfo.announce = url;
+        let mut downloading = TorrentEngine::new(metainfo, PathBuf::from("/tmp/test_engine_ps2"), None);
+        let (pm_tx, _rx) = mpsc::channel(16);
+        downloading.peer_manager_tx = Some(pm_tx);
+        *downloading.state.write().await = EngineState::Downloading;
+        downloading.handle_pause_seeding().await;
+        assert_eq!(downloading.get_state().await, EngineState::Downloading);
+        assert!(downloading.peer_manager_tx.is_some());
+
+        // Seeding -> paused seeding drops the peer manager, not the engine
+        engine.handle_pause_seeding().await;
+        assert_eq!(engine.get_state().await, EngineState::PausedSeeding);
+        assert!(engine.peer_manager_tx.is_none());
+        assert!(peer_cancel.is_cancelled());
+        assert!(!engine.cancel_token.is_cancelled());
+        let stats = engine.get_stats().await;
+        assert_eq!((stats.state, stats.connected_peers), (EngineState::PausedSeeding, 0));
+        // What the old peer manager uploaded still counts
+        assert_eq!(stats.uploaded_bytes, 1000);
+
+        // Start goes straight back to seeding with a new peer manager
+        engine.handle_start().await;
+        assert_eq!(engine.get_state().await, EngineState::Seeding);
+        assert!(engine.peer_manager_tx.is_some());
+        assert_eq!(announces.load(Ordering::SeqCst), 1);
+
+        // And a pause from there clears it for good
+        engine.handle_pause_seeding().await;
+        engine.handle_pause(EngineState::Paused).await;
+        assert_eq!(engine.get_state().await, EngineState::Paused);
+        engine.cancel_token.cancel();
+    }
+
+    #[tokio::test]
+    async fn test_paused_seeding_keeps_announcing_without_peers() {
+        use std::sync::atomic::Ordering;
+
+        let swarm_peer: SocketAddr = "10.1.2.3:6881".parse().unwrap();
+        let (url, announces) = fake_tracker(vec![swarm_peer], false).await;
+        let (mut engine, dials, _) = seeding_engine(url, "/tmp/test_engine_ps3").await;
+        engine.handle_pause_seeding().await;
+
+        // Timer announces go on, as a seed, but nobody is dialled
+        engine.announce_to_tracker(false).await;
+        engine.announce_to_tracker(false).await;
+        assert_eq!(announces.load(Ordering::SeqCst), 2);
+        assert_eq!(engine.announce_request().await.left, 0);
+        assert_eq!(engine.get_stats().await.total_peers, 1);
+        engine.connect_to_peers().await;
+        assert_eq!(dials.load(Ordering::SeqCst), 0);
+        assert_eq!(engine.get_state().await, EngineState::PausedSeeding);
+    }
+
+    #[tokio::test]
+    async fn test_leechers_reactivate_paused_seeding() {
+        use std::sync::atomic::{AtomicU32, Ordering};
+
+        let leechers = Arc::new(AtomicU32::new(0));
+        let (url, _) = swarm_tracker(leechers.clone()).await;
+        let (mut engine, _, _) = seeding_engine(url, "/tmp/test_engine_ps4").await;
+        let (reactivate, reactivate_rx) = watch::channel(false);
+        engine.set_reactivate_paused_seeding(reactivate_rx);
+        engine.handle_pause_seeding().await;
+
+        // An empty swarm keeps it paused
+        let reported = engine.announce_to_tracker(false).await;
+        assert_eq!(reported, Some(0));
+        assert!(!engine.reactivate_for_leechers(reported).await);
+
+        // Leechers show up, but the setting is off
+        leechers.store(3, Ordering::SeqCst);
+        let reported = engine.announce_to_tracker(false).await;
+        assert_eq!(reported, Some(3));
+        assert!(!engine.reactivate_for_leechers(reported).await);
+        assert_eq!(engine.get_state().await, EngineState::PausedSeeding);
+
+        reactivate.send_replace(true);
+        assert!(engine.reactivate_for_leechers(reported).await);
+        assert_eq!(engine.get_state().await, EngineState::Seeding);
+        assert!(engine.peer_manager_tx.is_some());
+        engine.cancel_token.cancel();
+    }
+
+    #[tokio::test]
+    async fn test_saved_paused_seeding_starts_without_peers() {
+        use std::sync::atomic::{AtomicU32, Ordering};
+
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let (url, announces) = swarm_tracker(Arc::new(AtomicU32::new(0))).await;
+        let mut metainfo = create_test_metainfo();
+        metainfo.announce = url;
+        let mut engine = TorrentEngine::new(metainfo, temp_dir.path().to_path_buf(), None);
+        engine.piece_manager.write().await.restore_bitfield(&[0b1100_0000]);
+        engine.set_start_paused_seeding(true);
+
+        engine.handle_start().await;
+        assert_eq!(engine.get_state().await, EngineState::PausedSeeding);
+        assert!(engine.peer_manager_tx.is_none());
+        assert_eq!(announces.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_peers_attributed_to_first_reporting_tracker() {
+        let peer = |n: u8| -> SocketAddr { SocketAddr::from(([10, 0, 0, n], 6881)) };
+        // A is down for the first announce, so B reports first
+        let (url_a, _) = fake_tracker(vec![peer(2), peer(3)], true).await;
+        let (url_b, _) = fake_tracker(vec![peer(1), peer(2)], false).await;
+        let mut metainfo = create_test_metainfo();
+        metainfo.announce = url_a.clone();
+        metainfo.announce_list = vec![vec![url_a.clone()], vec![url_b.clone()]];
+        let mut engine = TorrentEngine::new(metainfo, PathBuf::from("/tmp/test_engine7"), None);
+
+        engine.announce_to_tracker(false).await;
+        engine.announce_to_tracker(false).await;
+
+        // Peers 1 and 2 sent blocks (2 twice, e.g. after a reconnect); 3 never did
+        for n in [1, 2, 2] {
+            engine.productive_tx.send(peer(n)).unwrap();
+            let addr = engine.productive_rx.recv().await.unwrap();
+            engine.record_productive_peer(addr).await;
+        }
+
+        let trackers = engine.get_tracker_list().await;
+        let a = trackers.iter().find(|t| t.url == url_a).unwrap();
+        let b = trackers.iter().find(|t| t.url == url_b).unwrap();
+        assert_eq!((b.peers_returned, b.unique_peers, b.productive_peers), (2, 2, 2));
+        assert_eq!((a.peers_returned, a.unique_peers, a.productive_peers), (2, 1, 0));
+    }
+
+    #[tokio::test]
+    async fn test_disabled_trackers_are_skipped() {
+        use crate::tracker::TrackerStatus;
+        use std::sync::atomic::Ordering;
+
+        // A and B share the first tier, C is the fallback
+        let (url_a, a) = fake_tracker(vec![], false).await;
+        let (url_b, b) = fake_tracker(vec![], false).await;
+        let (url_c, c) = fake_tracker(vec![], false).await;
+        let mut metainfo = create_test_metainfo();
+        metainfo.announce = url_a.clone();
+        metainfo.announce_list = vec![vec![url_a.clone(), url_b.clone()], vec![url_c.clone()]];
+        let mut engine = TorrentEngine::new(metainfo.clone(), PathBuf::from("/tmp/test_engine_disabled"), None);
+        let announces = || [&a, &b, &c].map(|n| n.load(Ordering::SeqCst));
+
+        // Disabling A leaves B of the same tier, ahead of C
+        let toggle = engine.set_tracker_enabled(&url_a, false).await.unwrap();
+        assert_eq!(toggle.warning, None);
+        engine.announce_to_tracker(false).await;
+        assert_eq!(announces(), [0, 1, 0]);
+        let status = |trackers: &[crate::tracker::TrackerInfo], url: &str| trackers.iter().find(|t| t.url == url).map(|t| t.status);
+        let trackers = engine.get_tracker_list().await;
+        assert_eq!(status(&trackers, &url_a), Some(TrackerStatus::Disabled));
+        assert_eq!(status(&trackers, &url_b), Some(TrackerStatus::Working));
+
+        // B was the only working one: allowed, with a warning
+        let toggle = engine.set_tracker_enabled(&url_b, false).await.unwrap();
+        assert!(toggle.warning.is_some());
+        let mut expected = vec![url_a.clone(), url_b.clone()];
+        expected.sort();
+        assert_eq!(toggle.disabled, expected);
+        engine.announce_to_tracker(false).await;
+        assert_eq!(announces(), [0, 1, 1]);
+        // Disabled trackers keep their last stats
+        let trackers = engine.get_tracker_list().await;
+        let b_info = trackers.iter().find(|t| t.url == url_b).unwrap();
+        assert_eq!((b_info.status, b_info.last_announce.is_some()), (TrackerStatus::Disabled, true));
+
+        // Enabled again: announced to once B's minimum interval is over
+        assert!(engine.reannounce_at.is_none());
+        engine.set_tracker_enabled(&url_b, true).await.unwrap();
+        let due = engine.reannounce_at.expect("re-enabling schedules an announce");
+        assert!(due > time::Instant::now() + Duration::from_secs(50));
+        engine.set_tracker_enabled(&url_a, true).await.unwrap();
+        engine.announce_to_tracker(false).await;
+        assert_eq!(announces(), [1, 1, 1]);
+
+        assert!(engine.set_tracker_enabled("http://elsewhere/announce", false).await.is_err());
+
+        // Restored from the session: unknown URLs are dropped, the rest listed as disabled
+        let mut engine = TorrentEngine::new(metainfo, PathBuf::from("/tmp/test_engine_disabled2"), None);
+        engine.set_disabled_trackers(&[url_b.clone(), "http://elsewhere/announce".to_string()]).await;
+        let trackers = engine.get_tracker_list().await;
+        assert_eq!(trackers.len(), 1);
+        assert_eq!(status(&trackers, &url_b), Some(TrackerStatus::Disabled));
+        engine.announce_to_tracker(false).await;
+        assert_eq!(announces(), [2, 1, 1]);
+    }
+
+    #[tokio::test]
+    async fn test_tracker_peers_equal_to_us_are_dropped() {
+        let us = SocketAddr::from(([10, 0, 0, 1], DEFAULT_LISTEN_PORT));
+        let other = SocketAddr::from(([10, 0, 0, 2], DEFAULT_LISTEN_PORT));
+        // Our address on another port is another client behind the same NAT
+        let neighbour = SocketAddr::from(([10, 0, 0, 1], DEFAULT_LISTEN_PORT + 1));
+        let (url, _) = fake_tracker(vec![us, other, neighbour], false).await;
+        let mut metainfo = create_test_metainfo();
+        metainfo.announce = url;
+        let mut engine = TorrentEngine::new(metainfo, PathBuf::from("/tmp/test_engine_self"), None);
+        let external_ip = Arc::new(ExternalIp::default());
+        external_ip.report("http://other.example/announce", us.ip());
+        engine.set_external_ip(external_ip);
+
+        engine.announce_to_tracker(false).await;
+        let addresses = engine.peer_addresses.read().await;
+        assert!(!addresses.contains_key(&us));
+        assert!(addresses.contains_key(&other) && addresses.contains_key(&neighbour));
+    }
+
+    #[tokio::test]
+    async fn test_protocol_violation_lowers_score() {
+        let peer = |n: u8| -> SocketAddr { SocketAddr::from(([10, 0, 0, n], 6881)) };
+        let mut engine = TorrentEngine::new(create_test_metainfo(), PathBuf::from("/tmp/test_engine_violation"), None);
+        for (n, score) in [(1, 1), (2, 11)] {
+            engine.peer_addresses.write().await.insert(peer(n), KnownPeer {
+                source: "http://tracker.example.com/announce".to_string(),
+                productive: n == 2,
+                score,
+                last_seen: 0,
+            });
+        }
+
+        engine.violation_tx.send(peer(2)).unwrap();
+        let addr = engine.violation_rx.recv().await.unwrap();
+        engine.penalize_peer(addr).await;
+        // Unknown peers are ignored
+        engine.penalize_peer(peer(3)).await;
+
+        let best: Vec<SocketAddr> = engine.best_peers(2).await.into_iter().map(|p| p.addr).collect();
+        assert_eq!(best, [peer(1), peer(2)]);
+        assert_eq!(engine.peer_addresses.read().await[&peer(2)].score, 0);
+    }
+
+    #[tokio::test]
+    async fn test_manual_peer_queued_until_active() {
+        let peer = |n: u8| -> SocketAddr { SocketAddr::from(([10, 0, 0, n], 6881)) };
+        let mut engine = TorrentEngine::new(create_test_metainfo(), PathBuf::from("/tmp/test_engine_manual"), None);
+        engine.peer_addresses.write().await.insert(peer(1), KnownPeer {
+            source: "http://tracker.example.com/announce".to_string(),
+            productive: true,
+            score: 50,
+            last_seen: 0,
+        });
+
+        // Paused: kept, first in line, but not dialed
+        assert_eq!(engine.add_manual_peer(peer(2)).await, ManualDial::Queued);
+        let best = engine.best_peers(1).await;
+        assert_eq!(best[0].addr, peer(2));
+        assert!(best[0].is_manual());
+
+        // Active: straight to the front of the dial queue
+        let (tx, mut rx) = mpsc::channel(4);
+        engine.peer_manager_tx = Some(tx);
+        *engine.state.write().await = EngineState::Downloading;
+        assert_eq!(engine.add_manual_peer(peer(1)).await, ManualDial::Dialing);
+        assert!(matches!(rx.recv().await, Some(PeerManagerCommand::DialNow(addr)) if addr == peer(1)));
+        assert!(engine.peer_addresses.read().await[&peer(1)].score >= MANUAL_PEER_SCORE);
+
+        // Shown with the M flag
+        tokio::spawn(async move {
+            if let Some(PeerManagerCommand::GetPeerList(reply)) = rx.recv().await {
+                let info = |addr: SocketAddr| crate::peer::PeerInfo {
+                    ip: addr.ip().to_string(),
+                    port: addr.port(),
+                    client: String::new(),
+                    flags: "D".to_string(),
+                    progress: 0.0,
+                    download_speed: 0,
+                    upload_speed: 0,
+                    downloaded: 0,
+                    uploaded: 0,
+                    overhead_downloaded: 0,
+                    overhead_uploaded: 0,
+                    choke_reason: None,
+                };
+                let _ = reply.send(vec![info(peer(1)), info(peer(3))]);
+            }
+        });
+        let flags: Vec<String> = engine.get_peer_list().await.into_iter().map(|p| p.flags).collect();
+        assert_eq!(flags, ["DM", "D"]);
+    }
+
+    #[tokio::test]
+    async fn test_manual_peers_are_not_evicted_on_restore() {
+        let mut metainfo = create_test_metainfo();
+        metainfo.info.private = true;
+        let engine = TorrentEngine::new(metainfo, PathBuf::from("/tmp/test_engine_manual_restore"), None);
+        let saved = |n: u8, source: &str| SavedPeer {
+            addr: SocketAddr::from(([10, 0, 0, n], 6881)),
+            source: source.to_string(),
+            score: MANUAL_PEER_SCORE,
+            last_seen: 0,
+        };
+
+        let kept = engine
+            .restore_peers(&[saved(1, MANUAL_PEER_SOURCE), saved(2, "http://other.example.com/announce")], Duration::from_secs(60))
+            .await;
+        assert_eq!(kept, 1);
+        assert!(engine.peer_addresses.read().await.contains_key(&SocketAddr::from(([10, 0, 0, 1], 6881))));
+    }
+
+    #[tokio::test]
+    async fn test_saved_peers_survive_restart() {
+        let peer = |n: u8| -> SocketAddr { SocketAddr::from(([10, 0, 0, n], 6881)) };
+        let own = "http://tracker.example.com/announce".to_string();
+        let now = chrono::Utc::now().timestamp();
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let database = Arc::new(Database::open(temp_dir.path().join("db")).unwrap());
+
+        let mut metainfo = create_test_metainfo();
+        metainfo.info.private = true;
+        let mut before = TorrentEngine::new(metainfo.clone(), temp_dir.path().to_path_buf(), None);
+        before.set_database(database.clone());
+        {
+            let mut addresses = before.peer_addresses.write().await;
+            let mut known = |n, source: &str, score, age| {
+                addresses.insert(peer(n), KnownPeer {
+                    source: source.to_string(),
+                    productive: false,
+                    score,
+                    last_seen: now - age,
+                });
+            };
+            known(1, &own, 2, 60);
+            known(2, &own, 11, 600);
+            known(3, &own, 50, 3 * 60 * 60); // stale
+            known(4, "http://other.example.com/announce", 5, 60); // not this torrent's tracker
+        }
+        for n in 10..70 {
+            before.peer_addresses.write().await.insert(peer(n), KnownPeer {
+                source: own.clone(),
+                productive: false,
+                score: 0,
+                last_seen: now,
+            });
+        }
+        before.save_progress().await;
+
+        // Only the best MAX_SAVED_PEERS are kept, best first
+        let session = database.load_torrent(&before.metainfo.info_hash_hex()).unwrap().unwrap();
+        assert_eq!(session.saved_peers.len(), MAX_SAVED_PEERS);
+        let top: Vec<SocketAddr> = session.saved_peers.iter().take(4).map(|p| p.addr).collect();
+        assert_eq!(top, vec![peer(3), peer(2), peer(4), peer(1)]);
+
+        let after = TorrentEngine::new(metainfo, temp_dir.path().to_path_buf(), None);
+        let kept = after.restore_peers(&session.saved_peers, Duration::from_secs(2 * 60 * 60)).await;
+        assert_eq!(kept, MAX_SAVED_PEERS - 2);
+        let seeded: Vec<SocketAddr> = after.best_peers(2).await.into_iter().map(|p| p.addr).collect();
+        assert_eq!(seeded, vec![peer(2), peer(1)]);
+        let addresses = after.peer_addresses.read().await;
+        assert!(!addresses.contains_key(&peer(3)));
+        assert!(!addresses.contains_key(&peer(4)));
+    }
+
+    #[tokio::test]
+    async fn test_restore_reverifies_only_unsynced_pieces() {
+        use crate::piece::Bitfield;
+        use sha1::{Digest, Sha1};
+
+        let data: Vec<Vec<u8>> = (1..=4u8).map(|b| vec![b; 16384]).collect();
+        let mut metainfo = create_test_metainfo();
+        metainfo.info.piece_count = 4;
+        metainfo.info.total_size = 4 * 16384;
+        metainfo.info.files[0].length = 4 * 16384;
+        metainfo.info.pieces = data.iter().flat_map(|d| Sha1::digest(d).to_vec()).collect();
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let dir = temp_dir.path().to_path_buf();
+
+        // Pieces 0-1 written and synced, then 2-3 written but not synced yet
+        let before = TorrentEngine::new(metainfo.clone(), dir.clone(), None);
+        let mut dm = before.disk_manager.write().await;
+        dm.allocate_files().await.unwrap();
+        for (i, piece) in data.iter().enumerate().take(2) {
+            dm.write_piece(i, piece.clone()).await.unwrap();
+        }
+        assert_eq!(dm.sync().await.unwrap(), 1);
+        for (i, piece) in data.iter().enumerate().skip(2) {
+            dm.write_piece(i, piece.clone()).await.unwrap();
+        }
+        let mut all = Bitfield::new(4);
+        (0..4).for_each(|i| all.set_piece(i));
+
+        // Progress saved after the writes returned but before the data was
+        // synced; the crash then loses piece 3 while piece 2 made it
+        let saved = dm.sync_point(&all);
+        assert_eq!(saved, SyncPoint { generation: 1, unsynced_pieces: vec![2, 3] });
+        dm.write_piece(3, vec![0u8; 16384]).await.unwrap();
+        drop(dm);
+
+        let after = TorrentEngine::new(metainfo.clone(), dir.clone(), None);
+        assert_eq!(after.restore_progress(all.as_bytes(), &saved).await, vec![2, 3]);
+        let pm = after.piece_manager.read().await;
+        assert_eq!((0..4).map(|i| pm.has_piece(i)).collect::<Vec<_>>(), vec![true, true, true, false]);
+        drop(pm);
+
+        // The other way round: data synced, crash before the bitfield was
+        // saved. The older record doesn't claim the pieces and is trusted as is.
+        let mut older = Bitfield::new(4);
+        older.set_piece(0);
+        older.set_piece(1);
+        let older_sync = SyncPoint { generation: 1, unsynced_pieces: vec![] };
+        let after = TorrentEngine::new(metainfo, dir, None);
+        assert!(after.restore_progress(older.as_bytes(), &older_sync).await.is_empty());
+        let pm = after.piece_manager.read().await;
+        assert_eq!((0..4).map(|i| pm.has_piece(i)).collect::<Vec<_>>(), vec![true, true, false, false]);
+    }
+
+    #[tokio::test]
+    async fn test_restore_drops_pieces_the_disk_cannot_hold() {
+        use crate::piece::Bitfield;
+        use sha1::{Digest, Sha1};
+
+        // Piece 2 really is all zeros
+        let data = vec![vec![1u8; 16384], vec![2u8; 16384], vec![0u8; 16384], vec![4u8; 16384]];
+        let mut metainfo = create_test_metainfo();
+        metainfo.info.piece_count = 4;
+        metainfo.info.total_size = 4 * 16384;
+        metainfo.info.files[0].length = 4 * 16384;
+        metainfo.info.pieces = data.iter().flat_map(|d| Sha1::digest(d).to_vec()).collect();
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let dir = temp_dir.path().to_path_buf();
+
+        // Only pieces 0-1 were written into the preallocated file, yet the
+        // saved record claims all four with 2-3 unsynced
+        let before = TorrentEngine::new(metainfo.clone(), dir.clone(), None);
+        let mut dm = before.disk_manager.write().await;
+        dm.allocate_files().await.unwrap();
+        for (i, piece) in data.iter().enumerate().take(2) {
+            dm.write_piece(i, piece.clone()).await.unwrap();
+        }
+        dm.sync().await.unwrap();
+        let path = dm.files()[0].path.clone();
+        drop(dm);
+        let mut all = Bitfield::new(4);
+        (0..4).for_each(|i| all.set_piece(i));
+        let unsynced = SyncPoint { generation: 1, unsynced_pieces: vec![2, 3] };
+
+        // The holes read as zeros: piece 2 matches them, piece 3 doesn't
+        let after = TorrentEngine::new(metainfo.clone(), dir.clone(), None);
+        assert_eq!(after.restore_progress(all.as_bytes(), &unsynced).await, vec![2, 3]);
+        let pm = after.piece_manager.read().await;
+        assert_eq!((0..4).map(|i| pm.has_piece(i)).collect::<Vec<_>>(), vec![true, true, true, false]);
+        drop(pm);
+
+        // The file was cut down to a piece and a half: everything past that
+        // goes, synced or not
+        std::fs::File::options().write(true).open(&path).unwrap().set_len(16384 + 8192).unwrap();
+        let synced = SyncPoint { generation: 1, unsynced_pieces: vec![] };
+        let after = TorrentEngine::new(metainfo, dir, None);
+        assert!(after.restore_progress(all.as_bytes(), &synced).await.is_empty());
+        let pm = after.piece_manager.read().await;
+        assert_eq!((0..4).map(|i| pm.has_piece(i)).collect::<Vec<_>>(), vec![true, false, false, false]);
+        assert!(!pm.can_serve(1));
+    }
+
+    #[tokio::test]
+    async fn test_file_prefix_selection() {
+        use crate::piece::{Bitfield, PiecePriority};
+
+        // 80000 bytes in 5 pieces of 16384: file 2 runs from the middle of
+        // piece 1 into piece 4, which it shares with file 3
+        let mut metainfo = create_test_metainfo();
+        metainfo.info.files = [20000, 0, 50000, 10000]
+            .iter()
+            .enumerate()
+            .map(|(i, &length)| FileInfo { path: vec![format!("file{}", i)], length })
+            .collect();
+        metainfo.info.piece_count = 5;
+        metainfo.info.pieces = vec![0u8; 100];
+        metainfo.info.total_size = 80000;
+        metainfo.info.is_single_file = false;
+        let mut engine = TorrentEngine::new(metainfo, PathBuf::from("/tmp/test_engine_prefix"), None);
+
+        // The first 20000 bytes of file 2 are 20000..40000: pieces 1-2
+        assert_eq!(engine.download_file_prefix(2, 20000).await, Ok(20000));
+        let priorities = |pm: &PieceManager| (0..5).map(|i| pm.piece_priority(i)).collect::<Vec<_>>();
+        let pm = engine.piece_manager.read().await;
+        assert_eq!(
+            priorities(&pm),
+            vec![PiecePriority::Normal, PiecePriority::High, PiecePriority::High, PiecePriority::Skip, PiecePriority::Normal]
+        );
+        // Only piece 3 is left out of the wanted bytes
+        assert_eq!(pm.wanted_bytes(), (80000 - 16384, 0));
+        drop(pm);
+
+        let mut have = Bitfield::new(5);
+        have.set_piece(1);
+        have.set_piece(2);
+        engine.piece_manager.write().await.restore_bitfield(have.as_bytes());
+        assert_eq!(engine.piece_manager.read().await.wanted_bytes(), (63616, 32768));
+        engine.update_stats().await;
+        assert_eq!(engine.get_stats().await.progress, 32768.0 / 63616.0);
+
+        engine.check_prefixes().await;
+        assert!(engine.prefixes[&2].ready);
+        let files = engine.file_list().await.ready().unwrap();
+        assert_eq!(files.iter().map(|f| f.prefix_only).collect::<Vec<_>>(), vec![None, None, Some(20000), None]);
+
+        // Un-skipping is setting the file's priority
+        engine.set_file_priority(2, PiecePriority::Normal).await.unwrap();
+        assert!(engine.prefixes.is_empty());
+        let pm = engine.piece_manager.read().await;
+        assert_eq!(priorities(&pm), vec![PiecePriority::Normal; 5]);
+        assert_eq!(pm.wanted_bytes(), (80000, 32768));
+        drop(pm);
+
+        assert!(engine.download_file_prefix(1, 100).await.is_err());
+        assert!(engine.download_file_prefix(4, 100).await.is_err());
+        engine.set_storage_mode(StorageMode::Consolidated).await;
+        assert!(engine.download_file_prefix(2, 100).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_failed_allocation_names_the_file() {
+        // The download directory is a file, so nothing can be created in it
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let blocked = temp_dir.path().join("downloads");
+        std::fs::write(&blocked, b"").unwrap();
+        let mut engine = TorrentEngine::new(create_test_metainfo(), blocked.clone(), None);
+
+        engine.handle_start().await;
+        assert_eq!(engine.get_state().await, EngineState::Error);
+        let error = engine.stats.read().await.error.clone().unwrap();
+        assert!(error.starts_with("Failed to allocate file 1 of 1:"), "{}", error);
+        assert!(error.contains("downloads"), "{}", error);
+        assert!(engine.take_allocation_rollback().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_transient_start_failures_are_retried() {
+        use crate::disk::DiskError;
+
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let (url, announces) = fake_tracker(vec![], false).await;
+        let mut metainfo = create_test_metainfo();
+        metainfo.announce = url;
+        let mut engine = TorrentEngine::new(metainfo, temp_dir.path().to_path_buf(), None);
+        let offline = || DiskError::NotFound { path: PathBuf::from("/mnt/nas/downloads") };
+        engine.allocation_faults.extend([offline(), offline()]);
+
+        // The share is offline for the first two attempts
+        engine.handle_start().await;
+        assert_eq!(engine.get_state().await, EngineState::Error);
+        let due = engine.start_retry.due().expect("a retry is scheduled");
+        assert!(due - time::Instant::now() <= start_retry::RETRY_DELAYS[0]);
+        engine.update_stats().await;
+        let error = engine.stats.read().await.error.clone().unwrap();
+        assert!(error.ends_with("; retrying in 30 s, attempt 2/5"), "{}", error);
+        assert!(error.contains("/mnt/nas/downloads"), "{}", error);
+
+        engine.retry_start().await;
+        assert_eq!(engine.get_state().await, EngineState::Error);
+        engine.update_stats().await;
+        let error = engine.stats.read().await.error.clone().unwrap();
+        assert!(error.ends_with("; retrying in 2 m, attempt 3/5"), "{}", error);
+        assert_eq!(announces.load(std::sync::atomic::Ordering::SeqCst), 0);
+
+        // The third goes through, and nothing is left scheduled
+        engine.retry_start().await;
+        assert_eq!(engine.get_state().await, EngineState::Downloading);
+        assert_eq!(engine.start_retry.due(), None);
+        assert_eq!(engine.stats.read().await.error, None);
+        assert_eq!(announces.load(std::sync::atomic::Ordering::SeqCst), 1);
+        engine.cancel_token.cancel();
+    }
+
+    #[tokio::test]
+    async fn test_start_retries_stop_at_pause_or_permanent_failure() {
+        use crate::disk::DiskError;
+
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let mut engine = TorrentEngine::new(create_test_metainfo(), temp_dir.path().to_path_buf(), None);
+        let path = PathBuf::from("/mnt/nas/downloads");
+        engine.allocation_faults.push_back(DiskError::NotFound { path: path.clone() });
+        engine.handle_start().await;
+        assert!(engine.start_retry.due().is_some());
+
+        // Pausing drops the retry
+        engine.handle_pause(EngineState::Paused).await;
+        assert_eq!(engine.start_retry.due(), None);
+        assert_eq!(engine.stats.read().await.error, None);
+
+        // A permanent failure waits for the user, as it did before
+        engine.allocation_faults.push_back(DiskError::NotFound { path: path.clone() });
+        engine.allocation_faults.push_back(DiskError::PermissionDenied { path });
+        engine.handle_start().await;
+        engine.retry_start().await;
+        assert_eq!(engine.get_state().await, EngineState::Error);
+        assert_eq!(engine.start_retry.due(), None);
+        engine.update_stats().await;
+        let error = engine.stats.read().await.error.clone().unwrap();
+        assert!(error.contains("Permission denied") && !error.contains("retrying"), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn test_start_without_trackers_or_peers_is_retried() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let (url, announces) = fake_tracker(vec![], true).await;
+        let mut metainfo = create_test_metainfo();
+        metainfo.announce = url;
+        let mut engine = TorrentEngine::new(metainfo, temp_dir.path().to_path_buf(), None);
+
+        // The only tracker fails the first announce; nothing else to go on
+        engine.handle_start().await;
+        assert_eq!(engine.get_state().await, EngineState::Downloading);
+        assert!(engine.start_retry.due().is_some());
+        engine.update_stats().await;
+        let error = engine.stats.read().await.error.clone().unwrap();
+        assert!(error.ends_with("retrying in 30 s, attempt 2/5"), "{}", error);
+
+        engine.retry_start().await;
+        assert_eq!(announces.load(std::sync::atomic::Ordering::SeqCst), 2);
+        assert_eq!(engine.start_retry.due(), None);
+        assert_eq!(engine.stats.read().await.error, None);
+        engine.cancel_token.cancel();
+    }
+
+    #[tokio::test]
+    async fn test_refuses_to_start_under_another_id() {
+        // The download directory is a file: a start that gets past the check
+        // stops at allocation, before reaching the tracker
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let blocked = temp_dir.path().join("downloads");
+        std::fs::write(&blocked, b"").unwrap();
+        let mut engine = TorrentEngine::new(create_test_metainfo(), blocked, None);
+        engine.set_tracked_id(&"ab".repeat(20));
+
+        engine.handle_start().await;
+        assert_eq!(engine.get_state().await, EngineState::Error);
+        let error = engine.stats.read().await.error.clone().unwrap();
+        assert!(error.starts_with("Internal info hash mismatch:"), "{}", error);
+        assert!(error.contains(&"0".repeat(40)), "{}", error);
+
+        engine.set_tracked_id(&"0".repeat(40));
+        engine.handle_start().await;
+        let error = engine.stats.read().await.error.clone().unwrap();
+        assert!(error.starts_with("Failed to allocate file"), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn test_file_completion_times() {
+        use crate::piece::Bitfield;
+
+        // Files of 20000 and 12768 bytes: piece 1 holds the end of the first
+        // and all of the second
+        let mut metainfo = create_test_metainfo();
+        metainfo.info.files = [20000, 12768]
+            .iter()
+            .enumerate()
+            .map(|(i, &length)| FileInfo { path: vec![format!("file{}", i)], length })
+            .collect();
+        metainfo.info.total_size = 32768;
+        metainfo.info.is_single_file = false;
+        let mut engine = TorrentEngine::new(metainfo, PathBuf::from("/tmp/test_engine_file_times"), None);
+        // Saved by the last run: file 0 completed at 100; file 1 was
+        // complete but from before files were stamped
+        engine.set_completed_at(Some(500));
+        engine.set_file_completed_at(FileCompletions::from([(0, 100)]));
+        engine.piece_manager.write().await.restore_bitfield(Bitfield::complete(2).as_bytes());
+
+        engine.check_file_completions().await;
+        assert_eq!(engine.file_completed_at, FileCompletions::from([(0, 100), (1, 500)]));
+        let files = engine.file_list().await.ready().unwrap();
+        assert_eq!(files.iter().map(|f| f.completed_at).collect::<Vec<_>>(), vec![Some(100), Some(500)]);
+
+        // A check that fails the shared piece clears both
+        engine.piece_manager.write().await.unmark_piece(1);
+        engine.check_file_completions().await;
+        assert!(engine.file_completed_at.is_empty());
+
+        // Downloaded again: stamped anew
+        let before = chrono::Utc::now().timestamp();
+        engine.piece_manager.write().await.restore_bitfield(Bitfield::complete(2).as_bytes());
+        engine.check_file_completions().await;
+        assert_eq!(engine.file_completed_at.keys().copied().collect::<Vec<_>>(), vec![0, 1]);
+        assert!(engine.file_completed_at.values().all(|&at| at >= before));
     }
 
     #[test]
@@ -755,14 +3384,70 @@ mod tests {
             download_speed: 1024.0,
             upload_speed: 512.0,
             connected_peers: 5,
+            connected_seeds: 2,
             total_peers: 10,
+            swarm: None,
             progress: 0.5,
             eta_seconds: Some(120),
             completed_at: None,
+            dropped_commands: 0,
+            traffic: TrafficStats::default(),
+            error: None,
         };
 
         assert_eq!(stats.state, EngineState::Downloading);
         assert_eq!(stats.progress, 0.5);
         assert_eq!(stats.connected_peers, 5);
     }
+
+    fn magnet_engine(download_dir: &str) -> TorrentEngine {
+        let metainfo = Metainfo::from_magnet([0u8; 20], Some("clip.mkv".to_string()), vec![]);
+        TorrentEngine::new(metainfo, PathBuf::from(download_dir), None)
+    }
+
+    #[tokio::test]
+    async fn test_magnet_stub_reports_metadata_pending() {
+        let mut engine = magnet_engine("/tmp/test_engine_magnet");
+        assert!(!engine.has_metadata());
+
+        assert!(matches!(engine.pieces_info(DEFAULT_MAX_BUCKETS).await, MetadataResult::Pending));
+        assert!(matches!(engine.piece_window(0, 10).await, MetadataResult::Pending));
+        assert!(matches!(engine.file_list().await, MetadataResult::Pending));
+        assert!(engine.set_file_priority(0, crate::piece::PiecePriority::High).await.is_err());
+        assert!(engine.set_file_priority(5, crate::piece::PiecePriority::Skip).await.is_err());
+
+        // An empty bitfield is "complete"; the stub must not look finished
+        *engine.state.write().await = EngineState::Downloading;
+        engine.stats.write().await.download_speed = 1000.0;
+        engine.update_stats().await;
+        let stats = engine.get_stats().await;
+        assert_eq!(stats.progress, 0.0);
+        assert_eq!(stats.eta_seconds, None);
+        assert_eq!(stats.completed_at, None);
+        assert_eq!(engine.get_state().await, EngineState::Downloading);
+    }
+
+    #[tokio::test]
+    async fn test_received_metadata_replaces_stub() {
+        let mut engine = magnet_engine("/tmp/test_engine_magnet2");
+
+        let mut other = create_test_metainfo();
+        other.info_hash = [1u8; 20];
+        assert!(engine.apply_metadata(other).await.is_err());
+        assert!(!engine.has_metadata());
+
+        engine.apply_metadata(create_test_metainfo()).await.unwrap();
+        assert!(engine.has_metadata());
+        match engine.pieces_info(DEFAULT_MAX_BUCKETS).await {
+            MetadataResult::Ready(pieces) => assert_eq!(pieces.total_pieces, 2),
+            MetadataResult::Pending => panic!("metadata should be ready"),
+        }
+        let files = engine.file_list().await.ready().unwrap();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].size, 20000);
+        engine.set_file_priority(0, crate::piece::PiecePriority::High).await.unwrap();
+
+        // Only the first metadata counts
+        assert!(engine.apply_metadata(create_test_metainfo()).await.is_err());
+    }
 }
@@ -0,0 +1,149 @@
+//! Starting again after a start failed for a reason that may pass
+//!
+//! A download directory on a NAS that's briefly offline, or every tracker
+//! down for a moment, shouldn't need the user to notice and press start.
+//! Such transient failures are retried after `RETRY_DELAYS`, for at most
+//! `MAX_START_ATTEMPTS` attempts counting the first; after that, and after
+//! any permanent failure, the torrent waits for the user as before. An
+//! explicit pause or start drops the pending retry. The engine drives this
+//! from its loop, passing in the time so the schedule can be tested without
+//! waiting.
+
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Attempts at starting, the first one included
+pub const MAX_START_ATTEMPTS: u32 = 5;
+
+/// Wait after the first, second and third failed attempt; the last one
+/// holds for the rest
+pub const RETRY_DELAYS: [Duration; 3] = [
+    Duration::from_secs(30),
+    Duration::from_secs(2 * 60),
+    Duration::from_secs(10 * 60),
+];
+
+/// The retry schedule of one engine
+#[derive(Debug, Default)]
+pub struct StartRetry {
+    /// Attempts that failed since the last success or explicit start
+    failed: u32,
+    /// When the next attempt is due, if one is
+    due: Option<Instant>,
+    /// Why the last attempt failed
+    cause: Option<String>,
+}
+
+impl StartRetry {
+    /// Record a transient failure at `now`. Returns when the next attempt
+    /// is due, or None when the attempts are used up.
+    pub fn schedule(&mut self, cause: String, now: Instant) -> Option<Instant> {
+        self.failed += 1;
+        self.cause = Some(cause);
+        self.due = None;
+        if self.failed >= MAX_START_ATTEMPTS {
+            return None;
+        }
+        let index = (self.failed as usize - 1).min(RETRY_DELAYS.len() - 1);
+        self.due = Some(now + RETRY_DELAYS[index]);
+        self.due
+    }
+
+    /// When the next attempt is due
+    pub fn due(&self) -> Option<Instant> {
+        self.due
+    }
+
+    /// The due attempt is being made; returns its number (2 for the first
+    /// retry)
+    pub fn fire(&mut self) -> u32 {
+        self.due = None;
+        self.failed + 1
+    }
+
+    /// An attempt went through, or the user took over: start counting
+    /// afresh. Returns whether a retry was pending.
+    pub fn reset(&mut self) -> bool {
+        let pending = self.due.is_some();
+        *self = Self::default();
+        pending
+    }
+
+    /// "<cause>; retrying in 2 m, attempt 3/5" while a retry is pending
+    pub fn status(&self, now: Instant) -> Option<String> {
+        let due = self.due?;
+        Some(format!(
+            "{}; retrying in {}, attempt {}/{}",
+            self.cause.as_deref().unwrap_or("Start failed"),
+            describe_wait(due.saturating_duration_since(now)),
+            self.failed + 1,
+            MAX_START_ATTEMPTS
+        ))
+    }
+}
+
+/// "30 s", "2 m" (whole minutes, rounded up, from a minute on)
+fn describe_wait(wait: Duration) -> String {
+    let secs = wait.as_secs() + u64::from(wait.subsec_nanos() > 0);
+    if secs < 60 {
+        format!("{} s", secs)
+    } else {
+        format!("{} m", (secs + 59) / 60)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff_schedule() {
+        let start = Instant::now();
+        let mut retry = StartRetry::default();
+        assert_eq!(retry.status(start), None);
+
+        // 30 s, 2 m, then 10 m for the rest, five attempts in all
+        let mut now = start;
+        let mut waits = Vec::new();
+        while let Some(due) = retry.schedule("NAS offline".to_string(), now) {
+            assert_eq!(retry.due(), Some(due));
+            waits.push(due - now);
+            now = due;
+            retry.fire();
+        }
+        assert_eq!(
+            waits,
+            vec![RETRY_DELAYS[0], RETRY_DELAYS[1], RETRY_DELAYS[2], RETRY_DELAYS[2]]
+        );
+        assert_eq!(waits.len() as u32, MAX_START_ATTEMPTS - 1);
+
+        // Out of attempts: nothing pending, nothing said about retrying
+        assert_eq!(retry.due(), None);
+        assert_eq!(retry.status(now), None);
+        assert!(!retry.reset());
+    }
+
+    #[test]
+    fn test_status_counts_down() {
+        let now = Instant::now();
+        let mut retry = StartRetry::default();
+        retry.schedule("Disk offline".to_string(), now);
+        assert_eq!(retry.status(now).unwrap(), "Disk offline; retrying in 30 s, attempt 2/5");
+        assert_eq!(retry.fire(), 2);
+
+        let due = retry.schedule("Disk offline".to_string(), now).unwrap();
+        assert_eq!(retry.status(now).unwrap(), "Disk offline; retrying in 2 m, attempt 3/5");
+        assert_eq!(
+            retry.status(due - Duration::from_millis(61_500)).unwrap(),
+            "Disk offline; retrying in 2 m, attempt 3/5"
+        );
+        assert_eq!(retry.status(due - Duration::from_secs(45)).unwrap(), "Disk offline; retrying in 45 s, attempt 3/5");
+        assert_eq!(retry.status(due + Duration::from_secs(5)).unwrap(), "Disk offline; retrying in 0 s, attempt 3/5");
+
+        // A pause or explicit start drops the retry and the count
+        assert!(retry.reset());
+        assert_eq!(retry.status(now), None);
+        retry.schedule("Disk offline".to_string(), now);
+        assert_eq!(retry.status(now).unwrap(), "Disk offline; retrying in 30 s, attempt 2/5");
+    }
+}
@@ -24,6 +24,20 @@ pub enum Error {
     /// Invalid data
     InvalidData(String),
 
+    /// A peer broke the wire protocol (bad handshake, malformed or oversized message)
+    ProtocolViolation(String),
+
+    /// A peer's handshake was for another torrent
+    InfoHashMismatch(String),
+
+    /// Our own bookkeeping disagrees on a torrent's identity: it is tracked
+    /// as `torrent_id` but its metadata hashes to `info_hash` (see
+    /// `ids::check_torrent_id`)
+    InternalHashMismatch {
+        torrent_id: String,
+        info_hash: String,
+    },
+
     /// Torrent not found
     TorrentNotFound(String),
 
@@ -39,6 +53,40 @@ pub enum Error {
     /// Debrid service error
     DebridError(String),
 
+    /// Malformed user input (ids, hashes, ...)
+    ValidationError(String),
+
+    /// Network failure caused by a wrong system clock
+    ClockSkew(String),
+
+    /// Tracker answered with a "failure reason"
+    TrackerFailure(String),
+
+    /// A signed download link is no longer accepted
+    LinkExpired(String),
+
+    /// A server's certificate was rejected (full error text, see `tracker::tls`)
+    TlsError(String),
+
+    /// The process or system ran out of file descriptors (see `resources`)
+    TooManyOpenFiles(String),
+
+    /// The disk holding a download is full
+    DiskFull(String),
+
+    /// A download file or directory isn't accessible
+    PermissionDenied(String),
+
+    /// A feature turned off in settings was used (the feature's name, e.g. "debrid")
+    FeatureDisabled(String),
+
+    /// A torrent was added while it downloads from the other kind of source
+    /// (P2P vs cloud); `existing` is what it downloads from
+    AlreadyExistsWithOtherSource {
+        torrent_id: String,
+        existing: crate::debrid::types::DownloadSource,
+    },
+
     /// Generic error
     Other(String),
 }
@@ -51,11 +99,28 @@ impl fmt::Display for Error {
             Self::NetworkError(msg) => write!(f, "Network error: {msg}"),
             Self::IoError(msg) => write!(f, "I/O error: {msg}"),
             Self::InvalidData(msg) => write!(f, "Invalid data: {msg}"),
+            Self::ProtocolViolation(msg) => write!(f, "Protocol violation: {msg}"),
+            Self::InfoHashMismatch(msg) => write!(f, "Info hash mismatch: {msg}"),
+            Self::InternalHashMismatch { torrent_id, info_hash } => {
+                write!(f, "Internal info hash mismatch: {torrent_id} is tracked, but its metadata is for {info_hash}")
+            }
             Self::TorrentNotFound(msg) => write!(f, "Torrent not found: {msg}"),
             Self::Timeout(msg) => write!(f, "Timeout: {msg}"),
             Self::CryptoError(msg) => write!(f, "Crypto error: {msg}"),
             Self::DatabaseError(msg) => write!(f, "Database error: {msg}"),
             Self::DebridError(msg) => write!(f, "Debrid error: {msg}"),
+            Self::ValidationError(msg) => write!(f, "Validation error: {msg}"),
+            Self::ClockSkew(msg) => write!(f, "Clock skew: {msg}"),
+            Self::TrackerFailure(msg) => write!(f, "Tracker error: {msg}"),
+            Self::LinkExpired(msg) => write!(f, "Download link expired: {msg}"),
+            Self::TlsError(msg) => write!(f, "TLS error: {msg}"),
+            Self::TooManyOpenFiles(msg) => write!(f, "Too many open files: {msg}"),
+            Self::DiskFull(msg) => write!(f, "Disk full: {msg}"),
+            Self::PermissionDenied(msg) => write!(f, "Permission denied: {msg}"),
+            Self::FeatureDisabled(feature) => write!(f, "Feature disabled: {feature}"),
+            Self::AlreadyExistsWithOtherSource { torrent_id, existing } => {
+                write!(f, "Already added from another source: {torrent_id} is a {} download", existing.describe())
+            }
             Self::Other(msg) => write!(f, "{msg}"),
         }
     }
@@ -65,7 +130,26 @@ impl std::error::Error for Error {}
 
 impl From<std::io::Error> for Error {
     fn from(err: std::io::Error) -> Self {
-        Self::IoError(err.to_string())
+        if crate::resources::note_io_error(&err) {
+            Self::TooManyOpenFiles(err.to_string())
+        } else {
+            Self::IoError(err.to_string())
+        }
+    }
+}
+
+impl From<crate::disk::DiskError> for Error {
+    fn from(err: crate::disk::DiskError) -> Self {
+        use crate::disk::DiskError;
+        match &err {
+            DiskError::NoSpace { .. } => Self::DiskFull(err.to_string()),
+            DiskError::PermissionDenied { .. } => Self::PermissionDenied(err.to_string()),
+            DiskError::Io { source, .. } if crate::resources::is_fd_exhaustion(source) => {
+                Self::TooManyOpenFiles(err.to_string())
+            }
+            DiskError::InvalidBlock { .. } => Self::InvalidData(err.to_string()),
+            _ => Self::IoError(err.to_string()),
+        }
     }
 }
 
@@ -86,7 +170,7 @@ impl From<reqwest::Error> for Error {
         if err.is_timeout() {
             Self::Timeout(err.to_string())
         } else {
-            Self::NetworkError(err.to_string())
+            crate::clock::network_error(err.to_string())
         }
     }
 }
@@ -0,0 +1,658 @@
+//! Events sent to the frontend
+//!
+//! Every event the backend emits is a variant of [`Event`], registered
+//! below with its name and payload type, and goes out through
+//! [`emit_event`]. The engine and cloud downloads send theirs to an
+//! [`EngineEventSink`] instead, which in the app is the `AppHandle` and in
+//! a headless build whatever the embedder passes. Payloads that exist only to be emitted live here; the
+//! ones that double as command results (`TorrentInfo`, the details update,
+//! audit and export progress) stay next to the code that builds them.
+//!
+//! `get_event_catalog` lists every event with an example payload, so the
+//! frontend can check its types against the real serialized shape.
+
+use crate::audit::AuditProgress;
+use crate::cloud::{FileSelectionRequest, ProviderSwitch};
+use crate::database::{DatabaseRecovery, StreamProgress};
+use crate::debrid::ProviderHealth;
+use crate::details::TorrentDetailsUpdate;
+use crate::state::TorrentInfo;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+/// Payload of the `torrent-missing-files` event
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MissingFilesEvent {
+    /// Torrent ID (info hash)
+    pub torrent_id: String,
+
+    /// Download directory that failed the check
+    pub download_dir: String,
+
+    /// Human-readable reason
+    pub reason: String,
+}
+
+/// Payload of the `torrent-ready` event: a saved torrent's engine was
+/// built after startup and it can now be started, paused or inspected
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TorrentReadyEvent {
+    /// Torrent ID (info hash)
+    pub torrent_id: String,
+}
+
+/// Payload of the `torrent-seeding-reactivated` event: a torrent in paused
+/// seeding went back to seeding because an announce reported leechers
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SeedingReactivatedEvent {
+    pub torrent_id: String,
+    pub leechers: u32,
+}
+
+/// Payload of the `tracker-warning` event: a tracker accepted an announce
+/// but sent a warning message, or a different one than before
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrackerWarningEvent {
+    pub torrent_id: String,
+    /// The tracker, passkeys masked
+    pub tracker: String,
+    pub message: String,
+}
+
+/// Payload of the `torrent-unregistered` event
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TorrentUnregisteredEvent {
+    /// Torrent ID (info hash)
+    pub torrent_id: String,
+
+    /// The trackers' failure reasons, one per tracker
+    pub reasons: Vec<String>,
+}
+
+/// Payload of the `metadata-ready` event: a magnet's info dictionary arrived
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MetadataReadyEvent {
+    /// Torrent ID (info hash)
+    pub torrent_id: String,
+
+    /// Name from the metadata (replaces the magnet's display name)
+    pub name: String,
+
+    /// Total size in bytes
+    pub size: u64,
+
+    /// Number of files
+    pub file_count: usize,
+}
+
+/// Payload of the `file-completed` event: every piece of a file is verified
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileCompletedEvent {
+    /// Torrent ID (info hash)
+    pub torrent_id: String,
+
+    /// Index of the file in the torrent
+    pub file_index: usize,
+
+    /// Where the file is on disk
+    pub path: String,
+
+    /// When it completed (unix timestamp)
+    pub completed_at: i64,
+}
+
+/// Payload of the `torrent-allocation-failed` event: creating the torrent's
+/// files stopped at one of them, and the torrent went to the error state
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AllocationFailedEvent {
+    /// Torrent ID (info hash)
+    pub torrent_id: String,
+
+    /// The file that couldn't be created
+    pub path: String,
+
+    pub error: String,
+
+    /// Files allocated before it
+    pub allocated: usize,
+
+    /// Files in the torrent
+    pub total: usize,
+}
+
+/// Payload of the `prefix-ready` event: the start of a file asked for with
+/// `download_file_prefix` is on disk
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PrefixReadyEvent {
+    /// Torrent ID (info hash)
+    pub torrent_id: String,
+
+    /// Index of the file in the torrent
+    pub file_index: usize,
+
+    /// Bytes at the start of the file that are complete
+    pub bytes: u64,
+
+    /// Where the file is on disk
+    pub path: String,
+}
+
+/// Payload of the `piece-failed` event: a piece didn't match its hash
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PieceFailedEvent {
+    /// Torrent ID (info hash)
+    pub torrent_id: String,
+
+    #[serde(flatten)]
+    pub failure: crate::piece::PieceFailure,
+}
+
+/// Payload of the `possible-disk-corruption` event: a piece keeps failing
+/// with data from unrelated peers, so the copy on disk is suspect
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DiskCorruptionEvent {
+    /// Torrent ID (info hash)
+    pub torrent_id: String,
+
+    #[serde(flatten)]
+    pub failure: crate::piece::PieceFailure,
+
+    /// What to tell the user
+    pub message: String,
+}
+
+/// Payload of `low-disk-warning`
+#[derive(Debug, Clone, Serialize)]
+pub struct LowDiskWarningEvent {
+    /// A download folder on the filesystem
+    pub path: String,
+    pub free: u64,
+    /// Bytes its running downloads still need
+    pub remaining: u64,
+    pub reserve: u64,
+    /// How far finishing them would eat into the reserve
+    pub shortfall: u64,
+    pub torrent_ids: Vec<String>,
+}
+
+/// Payload of `low-disk-paused`
+#[derive(Debug, Clone, Serialize)]
+pub struct LowDiskPausedEvent {
+    pub path: String,
+    pub free: u64,
+    pub reserve: u64,
+    pub torrent_ids: Vec<String>,
+}
+
+/// Payload of `low-disk-space-freed`
+#[derive(Debug, Clone, Serialize)]
+pub struct SpaceFreedEvent {
+    pub path: String,
+    pub free: u64,
+    /// Paused downloads that fit again
+    pub torrent_ids: Vec<String>,
+    /// Whether they were started (`low_disk_auto_resume`)
+    pub resumed: bool,
+}
+
+/// Payload of the `backup-created` event
+#[derive(Debug, Clone, Serialize)]
+pub struct BackupCreatedEvent {
+    pub path: String,
+    pub size: u64,
+}
+
+/// Payload of the `backup-failed` event
+#[derive(Debug, Clone, Serialize)]
+pub struct BackupFailedEvent {
+    /// Backup directory
+    pub path: String,
+    pub error: String,
+}
+
+/// Payload of the `clock-skew-detected` event
+#[derive(Debug, Clone, Serialize)]
+pub struct ClockSkewEvent {
+    /// Local clock minus server clock (seconds; positive = ahead)
+    pub skew_secs: i64,
+    /// Human-readable description
+    pub message: String,
+}
+
+/// Payload of the `fd-exhausted` event (sent once per run)
+#[derive(Debug, Clone, Serialize)]
+pub struct FdExhaustedEvent {
+    pub fd_limit: Option<u64>,
+    pub open_fds: Option<usize>,
+    pub message: String,
+}
+
+/// Payload of `torrent-stalled-dead`
+#[derive(Debug, Clone, Serialize)]
+pub struct StalledDeadEvent {
+    pub torrent_id: String,
+    pub name: String,
+    pub days: u32,
+}
+
+/// Payload of `torrent-revivable`
+#[derive(Debug, Clone, Serialize)]
+pub struct RevivableEvent {
+    pub torrent_id: String,
+    pub name: String,
+    /// Most seeds any tracker reported
+    pub seeds: u32,
+    /// Whether it was started again (`auto_resume_revived`)
+    pub resumed: bool,
+}
+
+/// Declare the events: the enum, each variant's event name and the list of
+/// names, kept in step by construction
+macro_rules! event_catalog {
+    ($($(#[$doc:meta])* $variant:ident($payload:ty) = $name:literal,)*) => {
+        /// An event and its payload; serializes as the bare payload
+        #[derive(Debug, Clone, Serialize)]
+        #[serde(untagged)]
+        pub enum Event {
+            $($(#[$doc])* $variant($payload),)*
+        }
+
+        impl Event {
+            /// Name the frontend listens for
+            pub fn name(&self) -> &'static str {
+                match self {
+                    $(Event::$variant(_) => $name,)*
+                }
+            }
+        }
+
+        /// Names of every event, in declaration order
+        pub const EVENT_NAMES: &[&str] = &[$($name),*];
+    };
+}
+
+event_catalog! {
+    /// A running torrent's stats, every second
+    TorrentUpdate(TorrentInfo) = "torrent-update",
+    /// Live details of a torrent with an open details view
+    TorrentDetailsUpdate(Box<TorrentDetailsUpdate>) = "torrent-details-update",
+    TorrentReady(TorrentReadyEvent) = "torrent-ready",
+    MissingFiles(MissingFilesEvent) = "torrent-missing-files",
+    SeedingReactivated(SeedingReactivatedEvent) = "torrent-seeding-reactivated",
+    TorrentUnregistered(TorrentUnregisteredEvent) = "torrent-unregistered",
+    TrackerWarning(TrackerWarningEvent) = "tracker-warning",
+    StalledDead(StalledDeadEvent) = "torrent-stalled-dead",
+    Revivable(RevivableEvent) = "torrent-revivable",
+    MetadataReady(MetadataReadyEvent) = "metadata-ready",
+    PrefixReady(PrefixReadyEvent) = "prefix-ready",
+    FileCompleted(FileCompletedEvent) = "file-completed",
+    AllocationFailed(AllocationFailedEvent) = "torrent-allocation-failed",
+    PieceFailed(PieceFailedEvent) = "piece-failed",
+    DiskCorruption(DiskCorruptionEvent) = "possible-disk-corruption",
+    LowDiskWarning(LowDiskWarningEvent) = "low-disk-warning",
+    LowDiskPaused(LowDiskPausedEvent) = "low-disk-paused",
+    SpaceFreed(SpaceFreedEvent) = "low-disk-space-freed",
+    FdExhausted(FdExhaustedEvent) = "fd-exhausted",
+    ClockSkew(ClockSkewEvent) = "clock-skew-detected",
+    DatabaseRecovered(DatabaseRecovery) = "database-recovered",
+    BackupCreated(BackupCreatedEvent) = "backup-created",
+    BackupFailed(BackupFailedEvent) = "backup-failed",
+    BackupExportProgress(StreamProgress) = "backup-export-progress",
+    StorageAuditProgress(AuditProgress) = "storage-audit-progress",
+    /// What the cleanup task removed, as a sentence
+    CleanupTriggered(String) = "cleanup-triggered",
+    /// Something about a cloud download the user should know, as a sentence
+    DebridWarning(String) = "debrid-warning",
+    DebridFileSelection(FileSelectionRequest) = "debrid-file-selection",
+    CloudProviderSwitched(ProviderSwitch) = "cloud-provider-switched",
+    /// A debrid provider went down, degraded or came back
+    DebridProviderHealth(ProviderHealth) = "debrid-provider-health",
+}
+
+/// Where the engine and cloud downloads send their events
+pub trait EngineEventSink: Send + Sync {
+    fn send(&self, event: Event);
+}
+
+/// A sink as the engine holds it
+pub type EventSink = Arc<dyn EngineEventSink>;
+
+/// The app's sink: the frontend
+#[cfg(feature = "app")]
+impl EngineEventSink for tauri::AppHandle {
+    fn send(&self, event: Event) {
+        emit_event(self, event);
+    }
+}
+
+/// Events go into a channel, or nowhere once the receiver is dropped
+impl EngineEventSink for tokio::sync::mpsc::UnboundedSender<Event> {
+    fn send(&self, event: Event) {
+        let _ = tokio::sync::mpsc::UnboundedSender::send(self, event);
+    }
+}
+
+/// Send an event to the frontend. A failure is logged: nothing a caller
+/// could do about it.
+#[cfg(feature = "app")]
+pub fn emit_event(app: &tauri::AppHandle, event: Event) {
+    use tauri::Emitter;
+
+    if let Err(e) = app.emit(event.name(), &event) {
+        tracing::error!("Failed to emit {} event: {}", event.name(), e);
+    }
+}
+
+/// One event in the catalog
+#[derive(Debug, Clone, Serialize)]
+pub struct EventSchema {
+    pub name: &'static str,
+    /// A payload as it is sent, with made-up values
+    pub example: serde_json::Value,
+}
+
+/// Every event with an example payload
+pub fn catalog() -> Vec<EventSchema> {
+    samples()
+        .into_iter()
+        .map(|event| EventSchema {
+            name: event.name(),
+            example: serde_json::to_value(&event).unwrap_or(serde_json::Value::Null),
+        })
+        .collect()
+}
+
+const SAMPLE_ID: &str = "0123456789abcdef0123456789abcdef01234567";
+
+fn sample_torrent() -> TorrentInfo {
+    TorrentInfo {
+        id: SAMPLE_ID.to_string(),
+        name: "ubuntu-24.04-desktop-amd64.iso".to_string(),
+        size: 6_114_656_256,
+        downloaded: 3_057_328_128,
+        uploaded: 1_048_576,
+        state: crate::state::TorrentState::Downloading,
+        download_speed: 5_242_880,
+        upload_speed: 65_536,
+        peers: 12,
+        seeds: 40,
+        swarm_seeds: Some(1200),
+        swarm_leechers: Some(85),
+        swarm_updated_at: Some(1_700_000_000),
+        source: crate::debrid::types::DownloadSource::P2P,
+        remote_deleted: false,
+        queue_position: None,
+        metadata_pending: false,
+        name_encoding: None,
+        tags: vec!["linux".to_string()],
+        error: None,
+    }
+}
+
+fn sample_failure() -> crate::piece::PieceFailure {
+    crate::piece::PieceFailure { piece: 42, failed_at: 1_700_000_000_000, failures: 3, peers: vec![7] }
+}
+
+/// One example of every event, in `EVENT_NAMES` order
+pub fn samples() -> Vec<Event> {
+    use crate::debrid::types::{DebridProviderType, RemoteFileInfo};
+
+    let torrent_id = || SAMPLE_ID.to_string();
+    let path = || "/home/user/Downloads".to_string();
+    let debrid_torrent_id = crate::ids::DebridTorrentId::parse("ABCDEF123456").expect("valid debrid id");
+    vec![
+        Event::TorrentUpdate(sample_torrent()),
+        Event::TorrentDetailsUpdate(Box::new(TorrentDetailsUpdate {
+            torrent_id: torrent_id(),
+            stats: sample_torrent(),
+            peers: Vec::new(),
+            trackers: Vec::new(),
+            pieces: None,
+            traffic: None,
+            disconnects: std::collections::BTreeMap::new(),
+        })),
+        Event::TorrentReady(TorrentReadyEvent { torrent_id: torrent_id() }),
+        Event::MissingFiles(MissingFilesEvent {
+            torrent_id: torrent_id(),
+            download_dir: path(),
+            reason: "Download directory /home/user/Downloads is missing".to_string(),
+        }),
+        Event::SeedingReactivated(SeedingReactivatedEvent { torrent_id: torrent_id(), leechers: 3 }),
+        Event::TorrentUnregistered(TorrentUnregisteredEvent {
+            torrent_id: torrent_id(),
+            reasons: vec!["Unregistered torrent".to_string()],
+        }),
+        Event::TrackerWarning(TrackerWarningEvent {
+            torrent_id: torrent_id(),
+            tracker: "https://tracker.example.org/REDACTED/announce".to_string(),
+            message: "Too many connections from your IP".to_string(),
+        }),
+        Event::StalledDead(StalledDeadEvent { torrent_id: torrent_id(), name: "Old torrent".to_string(), days: 30 }),
+        Event::Revivable(RevivableEvent {
+            torrent_id: torrent_id(),
+            name: "Old torrent".to_string(),
+            seeds: 2,
+            resumed: false,
+        }),
+        Event::MetadataReady(MetadataReadyEvent {
+            torrent_id: torrent_id(),
+            name: "ubuntu-24.04-desktop-amd64.iso".to_string(),
+            size: 6_114_656_256,
+            file_count: 1,
+        }),
+        Event::PrefixReady(PrefixReadyEvent {
+            torrent_id: torrent_id(),
+            file_index: 0,
+            bytes: 10_485_760,
+            path: "/home/user/Downloads/movie.mkv".to_string(),
+        }),
+        Event::FileCompleted(FileCompletedEvent {
+            torrent_id: torrent_id(),
+            file_index: 2,
+            path: "/home/user/Downloads/Season 1/episode-03.mkv".to_string(),
+            completed_at: 1_700_000_000,
+        }),
+        Event::AllocationFailed(AllocationFailedEvent {
+            torrent_id: torrent_id(),
+            path: "/home/user/Downloads/Album/CD2/01.flac".to_string(),
+            error: "Permission denied: /home/user/Downloads/Album/CD2".to_string(),
+            allocated: 36,
+            total: 80,
+        }),
+        Event::PieceFailed(PieceFailedEvent { torrent_id: torrent_id(), failure: sample_failure() }),
+        Event::DiskCorruption(DiskCorruptionEvent {
+            torrent_id: torrent_id(),
+            failure: sample_failure(),
+            message: "Piece 42 failed verification 3 times with data from different peers.".to_string(),
+        }),
+        Event::LowDiskWarning(LowDiskWarningEvent {
+            path: path(),
+            free: 1_073_741_824,
+            remaining: 2_147_483_648,
+            reserve: 536_870_912,
+            shortfall: 1_610_612_736,
+            torrent_ids: vec![torrent_id()],
+        }),
+        Event::LowDiskPaused(LowDiskPausedEvent {
+            path: path(),
+            free: 268_435_456,
+            reserve: 536_870_912,
+            torrent_ids: vec![torrent_id()],
+        }),
+        Event::SpaceFreed(SpaceFreedEvent {
+            path: path(),
+            free: 10_737_418_240,
+            torrent_ids: vec![torrent_id()],
+            resumed: true,
+        }),
+        Event::FdExhausted(FdExhaustedEvent {
+            fd_limit: Some(1024),
+            open_fds: Some(1019),
+            message: "SeedCore ran out of file descriptors.".to_string(),
+        }),
+        Event::ClockSkew(ClockSkewEvent { skew_secs: 600, message: "Clock skew detected (off by 10 minutes)".to_string() }),
+        Event::DatabaseRecovered(DatabaseRecovery {
+            error: "corrupted page".to_string(),
+            moved_to: "/home/user/.local/share/seedcore/db.damaged".to_string(),
+            latest_backup: Some("/home/user/.local/share/seedcore/backups/seedcore-1700000000.json".to_string()),
+        }),
+        Event::BackupCreated(BackupCreatedEvent {
+            path: "/home/user/.local/share/seedcore/backups/seedcore-1700000000.json".to_string(),
+            size: 48_213,
+        }),
+        Event::BackupFailed(BackupFailedEvent {
+            path: "/home/user/.local/share/seedcore/backups".to_string(),
+            error: "Permission denied".to_string(),
+        }),
+        Event::BackupExportProgress(StreamProgress { done: 10, total: 25 }),
+        Event::StorageAuditProgress(AuditProgress::Scanning { entries: 1500, dir: path() }),
+        Event::CleanupTriggered("Cleaned up Old torrent: ratio 2.0 reached".to_string()),
+        Event::DebridWarning("Downloaded 0123abcd but could not remove it from Torbox".to_string()),
+        Event::DebridFileSelection(FileSelectionRequest {
+            torrent_id: torrent_id(),
+            provider: DebridProviderType::RealDebrid,
+            debrid_torrent_id: debrid_torrent_id.to_string(),
+            files: vec![RemoteFileInfo { index: 0, path: "movie.mkv".to_string(), size: 1_000_000_000, selected: false }],
+            timeout_secs: 300,
+        }),
+        Event::CloudProviderSwitched(ProviderSwitch {
+            torrent_id: torrent_id(),
+            from: DebridProviderType::Torbox,
+            to: DebridProviderType::RealDebrid,
+            debrid_torrent_id,
+            reason: "Torbox is unreachable".to_string(),
+            resumed: true,
+        }),
+        Event::DebridProviderHealth(ProviderHealth {
+            provider: DebridProviderType::Torbox,
+            state: crate::debrid::HealthState::Down,
+            consecutive_failures: 1,
+            maintenance: true,
+            retry_at: Some(1_700_003_600),
+            last_error: Some("Transient error 503 Service Unavailable: down for maintenance".to_string()),
+        }),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+
+    /// Field names and value kinds of a payload, nested; arrays by their
+    /// first element
+    fn shape(value: &Value) -> String {
+        match value {
+            Value::Null => "null".to_string(),
+            Value::Bool(_) => "bool".to_string(),
+            Value::Number(_) => "number".to_string(),
+            Value::String(_) => "string".to_string(),
+            Value::Array(items) => match items.first() {
+                Some(item) => format!("[{}]", shape(item)),
+                None => "[]".to_string(),
+            },
+            Value::Object(fields) => {
+                let fields: Vec<String> = fields.iter().map(|(k, v)| format!("{}: {}", k, shape(v))).collect();
+                format!("{{{}}}", fields.join(", "))
+            }
+        }
+    }
+
+    const TORRENT: &str = "{download_speed: number, downloaded: number, error: null, id: string, metadata_pending: bool, name: string, name_encoding: null, peers: number, queue_position: null, remote_deleted: bool, seeds: number, size: number, source: {type: string}, state: string, swarm_leechers: number, swarm_seeds: number, swarm_updated_at: number, tags: [string], upload_speed: number, uploaded: number}";
+
+    /// The serialized shape of every payload. A change here is a change to
+    /// the frontend contract: update src/types along with it.
+    fn expected_shapes() -> Vec<(&'static str, String)> {
+        let s = |shape: &str| shape.to_string();
+        vec![
+            ("torrent-update", s(TORRENT)),
+            (
+                "torrent-details-update",
+                format!(
+                    "{{disconnects: {{}}, peers: [], pieces: null, stats: {}, torrent_id: string, trackers: [], traffic: null}}",
+                    TORRENT
+                ),
+            ),
+            ("torrent-ready", s("{torrent_id: string}")),
+            ("torrent-missing-files", s("{download_dir: string, reason: string, torrent_id: string}")),
+            ("torrent-seeding-reactivated", s("{leechers: number, torrent_id: string}")),
+            ("torrent-unregistered", s("{reasons: [string], torrent_id: string}")),
+            ("tracker-warning", s("{message: string, torrent_id: string, tracker: string}")),
+            ("torrent-stalled-dead", s("{days: number, name: string, torrent_id: string}")),
+            ("torrent-revivable", s("{name: string, resumed: bool, seeds: number, torrent_id: string}")),
+            ("metadata-ready", s("{file_count: number, name: string, size: number, torrent_id: string}")),
+            ("prefix-ready", s("{bytes: number, file_index: number, path: string, torrent_id: string}")),
+            ("file-completed", s("{completed_at: number, file_index: number, path: string, torrent_id: string}")),
+            (
+                "torrent-allocation-failed",
+                s("{allocated: number, error: string, path: string, torrent_id: string, total: number}"),
+            ),
+            (
+                "piece-failed",
+                s("{failed_at: number, failures: number, peers: [number], piece: number, torrent_id: string}"),
+            ),
+            (
+                "possible-disk-corruption",
+                s("{failed_at: number, failures: number, message: string, peers: [number], piece: number, torrent_id: string}"),
+            ),
+            (
+                "low-disk-warning",
+                s("{free: number, path: string, remaining: number, reserve: number, shortfall: number, torrent_ids: [string]}"),
+            ),
+            ("low-disk-paused", s("{free: number, path: string, reserve: number, torrent_ids: [string]}")),
+            ("low-disk-space-freed", s("{free: number, path: string, resumed: bool, torrent_ids: [string]}")),
+            ("fd-exhausted", s("{fd_limit: number, message: string, open_fds: number}")),
+            ("clock-skew-detected", s("{message: string, skew_secs: number}")),
+            ("database-recovered", s("{error: string, latest_backup: string, moved_to: string}")),
+            ("backup-created", s("{path: string, size: number}")),
+            ("backup-failed", s("{error: string, path: string}")),
+            ("backup-export-progress", s("{done: number, total: number}")),
+            ("storage-audit-progress", s("{dir: string, entries: number, phase: string}")),
+            ("cleanup-triggered", s("string")),
+            ("debrid-warning", s("string")),
+            (
+                "debrid-file-selection",
+                s("{debridTorrentId: string, files: [{index: number, path: string, selected: bool, size: number}], \
+                   provider: string, timeoutSecs: number, torrentId: string}"),
+            ),
+            (
+                "cloud-provider-switched",
+                s("{debridTorrentId: string, from: string, reason: string, resumed: bool, to: string, torrentId: string}"),
+            ),
+            (
+                "debrid-provider-health",
+                s("{consecutive_failures: number, last_error: string, maintenance: bool, provider: string, \
+                   retry_at: number, state: string}"),
+            ),
+        ]
+    }
+
+    #[test]
+    fn test_every_event_has_one_sample() {
+        let names: Vec<&str> = samples().iter().map(Event::name).collect();
+        assert_eq!(names, EVENT_NAMES);
+
+        let mut unique = EVENT_NAMES.to_vec();
+        unique.sort_unstable();
+        unique.dedup();
+        assert_eq!(unique.len(), EVENT_NAMES.len(), "event names must be unique");
+    }
+
+    #[test]
+    fn test_payload_shapes() {
+        let catalog = catalog();
+        let expected = expected_shapes();
+        assert_eq!(catalog.len(), expected.len());
+        for (schema, (name, expected_shape)) in catalog.iter().zip(expected) {
+            assert_eq!(schema.name, name);
+            assert_eq!(shape(&schema.example), expected_shape, "payload of {} changed shape", name);
+        }
+    }
+
+    #[test]
+    fn test_event_serializes_as_bare_payload() {
+        let event = Event::TorrentReady(TorrentReadyEvent { torrent_id: "abc".to_string() });
+        assert_eq!(event.name(), "torrent-ready");
+        assert_eq!(serde_json::to_value(&event).unwrap(), serde_json::json!({ "torrent_id": "abc" }));
+    }
+}
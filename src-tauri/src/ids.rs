@@ -0,0 +1,243 @@
+//! Typed torrent identifiers
+//!
+//! `InfoHash` is the canonical identity of a torrent everywhere in SeedCore:
+//! state maps, engine lookups and database keys all use its lowercase hex
+//! form. `DebridTorrentId` is the id a debrid provider assigned to a torrent,
+//! which has nothing to do with the info hash.
+
+use crate::error::{Error, Result};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::str::FromStr;
+
+/// 20-byte SHA-1 info hash of a torrent
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct InfoHash([u8; 20]);
+
+impl InfoHash {
+    /// Wrap raw info hash bytes
+    pub fn new(bytes: [u8; 20]) -> Self {
+        Self(bytes)
+    }
+
+    /// Parse a 40-char hex (any case) or 32-char base32 info hash
+    pub fn parse(s: &str) -> Result<Self> {
+        let s = s.trim();
+        match s.len() {
+            40 => Self::from_hex(s),
+            32 => Self::from_base32(s),
+            len => Err(Error::ValidationError(format!(
+                "Invalid info hash length: expected 40 (hex) or 32 (base32), got {}",
+                len
+            ))),
+        }
+    }
+
+    /// Decode a 40-char hex info hash (case-insensitive)
+    pub fn from_hex(s: &str) -> Result<Self> {
+        let mut bytes = [0u8; 20];
+        hex::decode_to_slice(s, &mut bytes)
+            .map_err(|e| Error::ValidationError(format!("Invalid hex info hash '{}': {}", s, e)))?;
+        Ok(Self(bytes))
+    }
+
+    /// Decode a 32-char base32 info hash (RFC 4648 alphabet, no padding)
+    pub fn from_base32(s: &str) -> Result<Self> {
+        const BASE32_ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+        if s.len() != 32 {
+            return Err(Error::ValidationError(format!(
+                "Invalid base32 info hash: expected 32 characters, got {}",
+                s.len()
+            )));
+        }
+
+        let mut bytes = [0u8; 20];
+        let mut buffer: u64 = 0;
+        let mut bits = 0;
+        let mut out = 0;
+
+        for c in s.bytes() {
+            let value = BASE32_ALPHABET
+                .iter()
+                .position(|&b| b == c.to_ascii_uppercase())
+                .ok_or_else(|| {
+                    Error::ValidationError(format!("Invalid base32 character: {}", c as char))
+                })?;
+
+            // Each base32 char carries 5 bits
+            buffer = (buffer << 5) | value as u64;
+            bits += 5;
+            if bits >= 8 {
+                bits -= 8;
+                bytes[out] = (buffer >> bits) as u8;
+                out += 1;
+            }
+        }
+
+        Ok(Self(bytes))
+    }
+
+    /// Raw info hash bytes
+    pub fn as_bytes(&self) -> &[u8; 20] {
+        &self.0
+    }
+
+    /// Canonical lowercase hex form (used for map and database keys)
+    pub fn to_hex(&self) -> String {
+        hex::encode(self.0)
+    }
+}
+
+impl fmt::Display for InfoHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.to_hex())
+    }
+}
+
+impl FromStr for InfoHash {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Self::parse(s)
+    }
+}
+
+impl From<[u8; 20]> for InfoHash {
+    fn from(bytes: [u8; 20]) -> Self {
+        Self(bytes)
+    }
+}
+
+impl Serialize for InfoHash {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_hex())
+    }
+}
+
+impl<'de> Deserialize<'de> for InfoHash {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        Self::parse(&s).map_err(serde::de::Error::custom)
+    }
+}
+
+/// Check that a torrent tracked as `torrent_id` announces and handshakes
+/// under that id: `info_hash` (from its metadata) must be the same hash in
+/// canonical form. A mismatch means one of the conversions between magnet,
+/// .torrent file, state map and database went wrong, and the torrent would
+/// sit at 0 peers without an error.
+pub fn check_torrent_id(torrent_id: &str, info_hash: &[u8; 20]) -> Result<()> {
+    let info_hash = InfoHash::new(*info_hash).to_hex();
+    if torrent_id == info_hash {
+        Ok(())
+    } else {
+        Err(Error::InternalHashMismatch { torrent_id: torrent_id.to_string(), info_hash })
+    }
+}
+
+/// Torrent id assigned by a debrid provider (e.g. Real-Debrid "ABCD1234", Torbox "123456")
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct DebridTorrentId(String);
+
+impl DebridTorrentId {
+    /// Validate and wrap a provider torrent id
+    pub fn parse(s: &str) -> Result<Self> {
+        let s = s.trim();
+        if s.is_empty() {
+            return Err(Error::ValidationError("Debrid torrent id is empty".to_string()));
+        }
+        if !s.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+            return Err(Error::ValidationError(format!("Invalid debrid torrent id: {}", s)));
+        }
+        Ok(Self(s.to_string()))
+    }
+
+    /// Id as sent to the provider API
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for DebridTorrentId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl FromStr for DebridTorrentId {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Self::parse(s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEX: &str = "0123456789abcdef0123456789abcdef01234567";
+
+    #[test]
+    fn test_parse_hex_normalizes_case() {
+        let lower = InfoHash::parse(HEX).unwrap();
+        let upper = InfoHash::parse(&HEX.to_uppercase()).unwrap();
+        assert_eq!(lower, upper);
+        assert_eq!(upper.to_string(), HEX);
+    }
+
+    #[test]
+    fn test_parse_base32() {
+        let hash = InfoHash::parse("AERUKZ4JVPG66AJDIVTYTK6N54ASGRLH").unwrap();
+        assert_eq!(hash.to_string(), HEX);
+
+        let lower = InfoHash::parse("aerukz4jvpg66ajdivtytk6n54asgrlh").unwrap();
+        assert_eq!(lower, hash);
+    }
+
+    #[test]
+    fn test_parse_rejects_malformed() {
+        assert!(matches!(InfoHash::parse("abc"), Err(Error::ValidationError(_))));
+        assert!(InfoHash::parse(&"z".repeat(40)).is_err());
+        assert!(InfoHash::parse(&"1".repeat(32)).is_err());
+    }
+
+    #[test]
+    fn test_check_torrent_id() {
+        let bytes = *InfoHash::parse(HEX).unwrap().as_bytes();
+        assert!(check_torrent_id(HEX, &bytes).is_ok());
+
+        // Not canonical (the state map and database keys are lowercase hex)
+        assert!(check_torrent_id(&HEX.to_uppercase(), &bytes).is_err());
+        // The Debug form of the bytes, hashed or used as an id by mistake
+        let debug = format!("{:?}", bytes);
+        match check_torrent_id(&debug, &bytes) {
+            Err(Error::InternalHashMismatch { torrent_id, info_hash }) => {
+                assert_eq!((torrent_id, info_hash), (debug, HEX.to_string()));
+            }
+            other => panic!("expected a mismatch, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_info_hash_serde_roundtrip() {
+        let hash = InfoHash::parse(HEX).unwrap();
+        let json = serde_json::to_string(&hash).unwrap();
+        assert_eq!(json, format!("\"{}\"", HEX));
+        let back: InfoHash = serde_json::from_str(&json).unwrap();
+        assert_eq!(back, hash);
+    }
+
+    #[test]
+    fn test_debrid_torrent_id() {
+        assert_eq!(DebridTorrentId::parse(" ABCD1234 ").unwrap().as_str(), "ABCD1234");
+        assert!(DebridTorrentId::parse("").is_err());
+        assert!(DebridTorrentId::parse("../etc").is_err());
+
+        // Serialized transparently so stored DownloadSource values stay compatible
+        let id = DebridTorrentId::parse("123456").unwrap();
+        assert_eq!(serde_json::to_string(&id).unwrap(), "\"123456\"");
+    }
+}
@@ -1,73 +1,116 @@
 // SeedCore - Modern BitTorrent Client
 // Core library and Tauri integration
+//
+// The default `app` feature builds the Tauri app: commands, background
+// tasks and `run`. Without it (`--no-default-features --features core`)
+// the engine, peer, disk, tracker, piece and database modules build with
+// no Tauri dependency, sending their events to an `EngineEventSink`.
 
 #![warn(clippy::all, clippy::pedantic)]
 #![allow(clippy::module_name_repetitions)]
+// Much of the background tasks' plumbing is only reached from the app
+#![cfg_attr(not(feature = "app"), allow(dead_code, unused_imports))]
 
 // Module declarations
+pub mod api_tokens;
+pub mod audit;
+pub mod availability;
+pub mod backup;
 pub mod bencode;
+pub mod benchmark;
+pub mod clock;
 pub mod cloud;
+#[cfg(feature = "app")]
 pub mod commands;
 pub mod crypto;
+pub mod dashboard;
+pub mod data_dir;
 pub mod database;
 pub mod debrid;
+pub mod details;
 pub mod disk;
 pub mod download;
+pub mod duplicates;
 pub mod engine;
 pub mod error;
+pub mod events;
+pub mod ids;
+pub mod logs;
+pub mod low_disk;
 pub mod magnet;
 pub mod peer;
 pub mod piece;
+pub mod power;
+pub mod preview;
+pub mod provenance;
+pub mod queue;
+pub mod resources;
 pub mod scheduler;
+pub mod search;
+pub mod stall;
 pub mod state;
+pub mod tasks;
+#[cfg(any(test, feature = "core"))]
+pub mod testing;
 pub mod torrent;
 pub mod tracker;
+pub mod transfer_log;
 pub mod utils;
+pub mod wake;
 pub mod cleanup;
 
 // Re-exports
 pub use error::{Error, Result};
-
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+pub use ids::{DebridTorrentId, InfoHash};
 
 /// Shared references for graceful shutdown (populated in setup, used in on_window_event)
+#[cfg(feature = "app")]
 struct ShutdownState {
-    engines: std::sync::Arc<tokio::sync::RwLock<std::collections::HashMap<String, std::sync::Arc<tokio::sync::RwLock<engine::TorrentEngine>>>>>,
+    engine_controls: std::sync::Arc<tokio::sync::RwLock<std::collections::HashMap<String, engine::EngineControl>>>,
     engine_tasks: std::sync::Arc<tokio::sync::RwLock<std::collections::HashMap<String, tokio::task::JoinHandle<()>>>>,
-    cloud_download_tasks: std::sync::Arc<tokio::sync::RwLock<std::collections::HashMap<String, tokio::task::JoinHandle<()>>>>,
+    cloud_downloads: std::sync::Arc<cloud::CloudDownloadManager>,
     master_password: std::sync::Arc<tokio::sync::RwLock<Option<String>>>,
     database: std::sync::Arc<database::Database>,
     _tracing_guard: std::sync::Arc<std::sync::Mutex<Option<tracing_appender::non_blocking::WorkerGuard>>>,
 }
 
 /// Initialize the application
+#[cfg(feature = "app")]
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
     // Initialize logging
-    let log_dir = dirs::config_dir()
-        .map(|d| d.join("seedcore").join("logs"))
-        .unwrap_or_else(|| std::path::PathBuf::from("logs"));
+    let log_dir = data_dir::data_dir().join(data_dir::LOGS_DIR);
     
     if let Err(e) = std::fs::create_dir_all(&log_dir) {
         eprintln!("Warning: Failed to create log directory: {}", e);
     }
 
-    let file_appender = tracing_appender::rolling::daily(&log_dir, "seedcore.log");
-    let (non_blocking, guard) = tracing_appender::non_blocking(file_appender);
+    let guard = logs::init(&log_dir);
     // Store guard in Arc<Mutex> so it can be properly dropped on shutdown
     let guard_arc = std::sync::Arc::new(std::sync::Mutex::new(Some(guard)));
 
-    tracing_subscriber::registry()
-        .with(
-            tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| "seedcore=debug,info".into()),
-        )
-        .with(tracing_subscriber::fmt::layer())
-        .with(tracing_subscriber::fmt::layer().with_writer(non_blocking))
-        .init();
-
     tracing::info!("Starting SeedCore v{}", env!("CARGO_PKG_VERSION"));
 
+    // `--repair` exports what's readable from the database and exits
+    if std::env::args().any(|arg| arg == "--repair") {
+        match database::repair(&state::config_dir()) {
+            Ok(report) => {
+                println!(
+                    "Exported {} entries ({} torrents) to {}",
+                    report.entries, report.torrents, report.path
+                );
+                if !report.damaged_trees.is_empty() {
+                    println!("Unreadable parts: {}", report.damaged_trees.join(", "));
+                }
+                std::process::exit(0);
+            }
+            Err(e) => {
+                eprintln!("Repair failed: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+
     // Initialize application state
     let app_state = match state::AppState::new() {
         Ok(state) => state,
@@ -80,34 +123,107 @@ pub fn run() {
         }
     };
 
+    match app_state.database.load_settings() {
+        Ok(settings) => logs::set_file_level(settings.log_file_level),
+        Err(e) => tracing::error!("Failed to load the log file level: {}", e),
+    }
+
     // Clone Arc refs before moving app_state into manage()
     let shutdown_state = std::sync::Arc::new(ShutdownState {
-        engines: app_state.engines.clone(),
+        engine_controls: app_state.engine_controls.clone(),
         engine_tasks: app_state.engine_tasks.clone(),
-        cloud_download_tasks: app_state.cloud_download_tasks.clone(),
+        cloud_downloads: app_state.cloud_downloads.clone(),
         master_password: app_state.master_password.clone(),
         database: app_state.database.clone(),
         _tracing_guard: guard_arc,
     });
+    let (resource_pacer, resource_budget) = (app_state.dial_pacer.clone(), app_state.resources);
 
     // Build and run Tauri application
     tauri::Builder::default()
         .plugin(tauri_plugin_opener::init())
         .plugin(tauri_plugin_dialog::init())
         .manage(app_state)
-        .setup(|app| {
-            // Start auto-cleanup task
-            let cleanup_app = app.handle().clone();
+        .setup(move |app| {
+            // Supervised periodic tasks: auto-cleanup, the bandwidth scheduler and
+            // the database flusher
+            cleanup::register_cleanup_task(app.handle().clone())?;
+            scheduler::register_scheduler_task(app.handle().clone())?;
+            {
+                use tauri::Manager;
+                let state = app.state::<state::AppState>();
+                database::register_flush_task(&state.background_tasks, state.database.clone(), state.flush_interval.subscribe())?;
+            }
+
+            // Report a wrong system clock to the UI
+            let clock_app = app.handle().clone();
+            tauri::async_runtime::spawn(async move {
+                clock::start_clock_task(clock_app).await;
+            });
+
+            // Notice system sleep so timers don't all fire at once on wake
+            tauri::async_runtime::spawn(wake::start_wake_task());
+
+            // Back off when the process runs out of file descriptors
+            let resource_app = app.handle().clone();
+            tauri::async_runtime::spawn(async move {
+                resources::start_resource_task(resource_app, resource_pacer, resource_budget).await;
+            });
+
+            // Publish live details for open torrent details views
+            let details_app = app.handle().clone();
+            tauri::async_runtime::spawn(async move {
+                details::start_details_task(details_app).await;
+            });
+
+            // Write scheduled database backups when enabled
+            let backup_app = app.handle().clone();
             tauri::async_runtime::spawn(async move {
-                cleanup::start_cleanup_task(cleanup_app).await;
+                backup::start_backup_task(backup_app).await;
             });
 
-            // Start bandwidth scheduler task
-            let scheduler_app = app.handle().clone();
+            // Delete old log files at startup and daily
+            let logs_app = app.handle().clone();
             tauri::async_runtime::spawn(async move {
-                scheduler::start_scheduler_task(scheduler_app).await;
+                logs::start_log_task(logs_app).await;
             });
 
+            // Pause downloads that can't finish and watch them for seeds
+            let stall_app = app.handle().clone();
+            tauri::async_runtime::spawn(async move {
+                stall::start_stall_task(stall_app).await;
+            });
+
+            // Pause downloads before their disk fills up
+            let low_disk_app = app.handle().clone();
+            tauri::async_runtime::spawn(async move {
+                low_disk::start_low_disk_task(low_disk_app).await;
+            });
+
+            // Keep the machine awake while torrents are active, if enabled
+            let power_app = app.handle().clone();
+            tauri::async_runtime::spawn(async move {
+                power::start_power_task(power_app).await;
+            });
+
+            // Report debrid outages and probe providers that are down
+            let debrid_health_app = app.handle().clone();
+            tauri::async_runtime::spawn(async move {
+                debrid::health::start_health_task(debrid_health_app).await;
+            });
+
+            // Start download queue coordinator
+            let queue_app = app.handle().clone();
+            tauri::async_runtime::spawn(async move {
+                queue::start_queue_task(queue_app).await;
+            });
+
+            // Say so if a damaged database was replaced at startup
+            use tauri::Manager;
+            if let Some(recovery) = app.state::<state::AppState>().database_recovery.clone() {
+                events::emit_event(app.handle(), events::Event::DatabaseRecovered(recovery));
+            }
+
             Ok(())
         })
         .on_window_event(move |_win, event| {
@@ -120,25 +236,16 @@ pub fn run() {
                 tauri::async_runtime::spawn(async move {
                     // 1. Cancel all engine tokens and send Stop commands
                     {
-                        let engines_map = ss.engines.read().await;
-                        for (id, engine_arc) in engines_map.iter() {
+                        let controls = ss.engine_controls.read().await;
+                        for (id, control) in controls.iter() {
                             tracing::info!("Stopping engine: {}", id);
-                            let engine = engine_arc.read().await;
-                            engine.cancel_token().cancel();
-                            let _ = engine.command_sender().send(
-                                engine::EngineCommand::Stop,
-                            );
+                            control.cancel.cancel();
+                            let _ = control.handle.stop();
                         }
                     }
 
                     // 2. Abort all cloud download tasks
-                    {
-                        let mut cloud_tasks = ss.cloud_download_tasks.write().await;
-                        for (id, task) in cloud_tasks.drain() {
-                            tracing::info!("Aborting cloud download: {}", id);
-                            task.abort();
-                        }
-                    }
+                    ss.cloud_downloads.abort_all().await;
 
                     // 3. Wait briefly for engine tasks to finish
                     {
@@ -160,7 +267,7 @@ pub fn run() {
                     }
 
                     // 5. Flush database
-                    if let Err(e) = ss.database.flush() {
+                    if let Err(e) = ss.database.flush_now() {
                         tracing::error!("Failed to flush database on shutdown: {}", e);
                     } else {
                         tracing::info!("Database flushed successfully");
@@ -185,31 +292,82 @@ pub fn run() {
             // General commands
             commands::greet,
             commands::get_version,
+            commands::get_client_info,
+            commands::check_clock,
+            commands::get_diagnostics,
+            commands::get_background_tasks,
+            commands::run_background_task_now,
+            commands::run_benchmark,
+            commands::cancel_benchmark,
+            commands::get_benchmark_history,
             commands::get_settings,
             commands::update_settings,
             commands::backup_data,
             commands::restore_data,
             commands::export_backup,
+            commands::cancel_backup_export,
             commands::import_backup,
+            commands::migrate_data_dir,
+            commands::restore_from_backup_file,
+            commands::get_database_recovery,
+            commands::salvage_damaged_database,
+            commands::get_log_usage,
             // Torrent commands
             commands::get_torrents,
+            commands::query_torrents,
+            commands::rebuild_search_index,
             commands::parse_torrent_file,
             commands::parse_magnet_link,
             commands::add_torrent_file,
             commands::add_magnet_link,
+            commands::add_torrents_batch,
             commands::add_cloud_torrent,
+            commands::add_cloud_torrent_file,
             commands::remove_torrent,
             commands::start_torrent,
             commands::pause_torrent,
+            commands::set_torrent_auto_stop,
+            commands::pause_seeding,
             commands::get_torrent_details,
+            commands::get_magnet_link,
+            commands::get_torrent_provenance,
+            commands::export_torrent_file,
             commands::load_saved_torrents,
+            commands::recover_torrent,
+            commands::relocate_torrent,
+            commands::extract_torrent_data,
+            commands::add_peer_manually,
+            // Queue commands
+            commands::set_queue_position,
+            commands::queue_move_up,
+            commands::queue_move_down,
+            commands::queue_move_top,
+            commands::queue_move_bottom,
             // Torrent info commands
             commands::get_peer_list,
+            commands::get_peer_choke_history,
             commands::get_tracker_list,
             commands::get_pieces_info,
+            commands::get_piece_details,
+            commands::get_availability_history,
+            commands::subscribe_torrent_details,
+            commands::unsubscribe_torrent_details,
+            commands::set_torrent_debug_logging,
+            commands::get_torrent_debug_log,
+            commands::get_peer_disconnect_history,
+            commands::get_engine_metrics,
+            commands::audit_storage,
+            commands::detect_duplicate_content,
             commands::get_file_list,
+            commands::get_file_preview,
             commands::set_file_priority,
+            commands::set_tracker_enabled,
+            commands::download_file_prefix,
+            commands::relink_duplicates,
             commands::get_available_disk_space,
+            commands::get_traffic_stats,
+            commands::get_event_catalog,
+            commands::get_dashboard_summary,
             // Master password commands
             commands::check_master_password_set,
             commands::set_master_password,
@@ -221,13 +379,19 @@ pub fn run() {
             commands::get_debrid_credentials_status,
             commands::delete_debrid_credentials,
             commands::validate_debrid_provider,
+            // API token commands
+            commands::create_api_token,
+            commands::list_api_tokens,
+            commands::revoke_api_token,
             // Cache check commands
             commands::check_torrent_cache,
             commands::get_preferred_cached_provider,
+            commands::get_debrid_health,
             // Torrent management commands
             commands::add_magnet_to_debrid,
             commands::add_torrent_file_to_debrid,
             commands::select_debrid_files,
+            commands::get_debrid_torrent_files,
             commands::get_debrid_download_links,
             commands::list_debrid_torrents,
             commands::delete_debrid_torrent,
@@ -0,0 +1,289 @@
+//! Log files: setup, verbosity and retention
+//!
+//! Logs go to the console (filtered by `RUST_LOG`) and to a new
+//! `seedcore.log.<UTC date>` file in the logs directory every day, filtered
+//! by the `log_file_level` setting. The appender never deletes anything, so
+//! a task prunes the directory at startup and daily after that: oldest files
+//! first, down to `log_keep_files` files and `log_max_total_mb` in total.
+//! Today's file, which the appender is writing to, is never deleted.
+
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+#[cfg(feature = "app")]
+use tauri::Manager;
+use tokio::time::{self, Duration};
+use tracing_subscriber::{layer::SubscriberExt, reload, util::SubscriberInitExt, EnvFilter, Layer};
+
+/// Log files kept unless configured
+pub const DEFAULT_KEEP_FILES: usize = 14;
+
+/// How often old log files are pruned
+const PRUNE_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+const FILE_PREFIX: &str = "seedcore.log";
+
+pub fn default_keep_files() -> usize {
+    DEFAULT_KEEP_FILES
+}
+
+/// Most verbose level written to the log files
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    /// Debug for SeedCore itself, info for its dependencies
+    #[default]
+    Debug,
+    Trace,
+}
+
+impl LogLevel {
+    fn directives(self) -> &'static str {
+        match self {
+            Self::Error => "error",
+            Self::Warn => "warn",
+            Self::Info => "info",
+            Self::Debug => "seedcore=debug,info",
+            Self::Trace => "seedcore=trace,info",
+        }
+    }
+}
+
+/// A file in the logs directory, for the settings UI
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LogFile {
+    pub name: String,
+    pub size: u64,
+    /// Day the file covers (YYYY-MM-DD, UTC)
+    pub date: String,
+    /// Being written to right now
+    pub in_use: bool,
+}
+
+/// Payload of `get_log_usage`
+#[derive(Debug, Clone, Serialize)]
+pub struct LogUsage {
+    pub dir: String,
+    /// Oldest first
+    pub files: Vec<LogFile>,
+    pub total_size: u64,
+}
+
+/// Swaps the file layer's filter; set once by `init`
+static FILE_FILTER: OnceLock<reload::Handle<EnvFilter, tracing_subscriber::Registry>> = OnceLock::new();
+
+/// Install the console and file loggers. The returned guard flushes the
+/// file writer when dropped.
+pub fn init(log_dir: &Path) -> tracing_appender::non_blocking::WorkerGuard {
+    let file_appender = tracing_appender::rolling::daily(log_dir, FILE_PREFIX);
+    let (non_blocking, guard) = tracing_appender::non_blocking(file_appender);
+    let (file_filter, handle) = reload::Layer::new(EnvFilter::new(LogLevel::default().directives()));
+    let _ = FILE_FILTER.set(handle);
+
+    // The file layer goes first so the handle's type names the bare registry
+    tracing_subscriber::registry()
+        .with(tracing_subscriber::fmt::layer().with_writer(non_blocking).with_filter(file_filter))
+        .with(
+            tracing_subscriber::fmt::layer().with_filter(
+                EnvFilter::try_from_default_env().unwrap_or_else(|_| "seedcore=debug,info".into()),
+            ),
+        )
+        .init();
+    guard
+}
+
+/// Change what goes to the log files from now on
+pub fn set_file_level(level: LogLevel) {
+    let Some(handle) = FILE_FILTER.get() else { return };
+    if let Err(e) = handle.reload(EnvFilter::new(level.directives())) {
+        tracing::warn!("Failed to change the log file level: {}", e);
+    }
+}
+
+/// The logs directory in use
+pub fn log_dir() -> PathBuf {
+    crate::data_dir::data_dir().join(crate::data_dir::LOGS_DIR)
+}
+
+/// Date of a daily log file from its name
+fn file_date(name: &str) -> Option<chrono::NaiveDate> {
+    let date = name.strip_prefix(FILE_PREFIX)?.strip_prefix('.')?;
+    chrono::NaiveDate::parse_from_str(date, "%Y-%m-%d").ok()
+}
+
+/// The daily log files in `dir`, oldest first; the one for `today` is in use
+pub fn list_logs(dir: &Path, today: chrono::NaiveDate) -> Vec<LogFile> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut files: Vec<(chrono::NaiveDate, LogFile)> = entries
+        .filter_map(|entry| {
+            let entry = entry.ok()?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let date = file_date(&name)?;
+            let metadata = entry.metadata().ok().filter(|metadata| metadata.is_file())?;
+            Some((date, LogFile { name, size: metadata.len(), date: date.to_string(), in_use: date == today }))
+        })
+        .collect();
+    files.sort_by_key(|(date, _)| *date);
+    files.into_iter().map(|(_, file)| file).collect()
+}
+
+/// Which of `files` (oldest first) to delete: the oldest ones beyond
+/// `keep`, then more of the oldest until the rest fit in `max_bytes`
+/// (0 = no size limit). Files in use are never picked.
+pub fn select_for_deletion(files: &[LogFile], keep: usize, max_bytes: u64) -> Vec<&LogFile> {
+    let mut remaining = files.len();
+    let mut total: u64 = files.iter().map(|file| file.size).sum();
+    let mut selected = Vec::new();
+    for file in files.iter().filter(|file| !file.in_use) {
+        if remaining <= keep.max(1) && (max_bytes == 0 || total <= max_bytes) {
+            break;
+        }
+        remaining -= 1;
+        total -= file.size;
+        selected.push(file);
+    }
+    selected
+}
+
+/// Delete old log files from `dir` per `select_for_deletion`. A file that
+/// can't be deleted is logged and skipped. Returns the deleted paths.
+pub fn prune(dir: &Path, keep: usize, max_bytes: u64, today: chrono::NaiveDate) -> Vec<PathBuf> {
+    let files = list_logs(dir, today);
+    let mut removed = Vec::new();
+    for file in select_for_deletion(&files, keep, max_bytes) {
+        let path = dir.join(&file.name);
+        match std::fs::remove_file(&path) {
+            Ok(()) => removed.push(path),
+            Err(e) => tracing::warn!("Failed to delete old log file {:?}: {}", path, e),
+        }
+    }
+    if !removed.is_empty() {
+        tracing::info!("Deleted {} old log files", removed.len());
+    }
+    removed
+}
+
+/// Current contents of the logs directory
+pub fn usage(dir: &Path) -> LogUsage {
+    let files = list_logs(dir, chrono::Utc::now().date_naive());
+    LogUsage {
+        dir: dir.to_string_lossy().into_owned(),
+        total_size: files.iter().map(|file| file.size).sum(),
+        files,
+    }
+}
+
+/// Prune the logs directory with the retention settings
+pub fn prune_with_settings(settings: &crate::database::AppSettings) -> Vec<PathBuf> {
+    prune(
+        &log_dir(),
+        settings.log_keep_files,
+        settings.log_max_total_mb.saturating_mul(1024 * 1024),
+        chrono::Utc::now().date_naive(),
+    )
+}
+
+/// Prune old log files at startup and daily after that
+#[cfg(feature = "app")]
+pub async fn start_log_task(app_handle: tauri::AppHandle) {
+    let database = app_handle.state::<crate::state::AppState>().database.clone();
+    let mut interval = time::interval(PRUNE_INTERVAL);
+    loop {
+        interval.tick().await;
+        let settings = match database.load_settings() {
+            Ok(settings) => settings,
+            Err(e) => {
+                tracing::error!("Log cleanup failed to load settings: {}", e);
+                continue;
+            }
+        };
+        if let Err(e) = tokio::task::spawn_blocking(move || prune_with_settings(&settings)).await {
+            tracing::error!("Log cleanup failed: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn day(n: u32) -> chrono::NaiveDate {
+        chrono::NaiveDate::from_ymd_opt(2024, 1, 1).unwrap() + chrono::Days::new(u64::from(n))
+    }
+
+    fn names(files: &[&LogFile]) -> Vec<String> {
+        files.iter().map(|file| file.date.clone()).collect()
+    }
+
+    /// Daily files for days 0..count, `size(n)` bytes each; the last in use
+    fn synthetic(count: u32, size: impl Fn(u32) -> u64) -> Vec<LogFile> {
+        (0..count)
+            .map(|n| LogFile {
+                name: format!("{}.{}", FILE_PREFIX, day(n)),
+                size: size(n),
+                date: day(n).to_string(),
+                in_use: n + 1 == count,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_file_dates() {
+        assert_eq!(file_date("seedcore.log.2024-02-29"), chrono::NaiveDate::from_ymd_opt(2024, 2, 29));
+        assert_eq!(file_date("seedcore.log"), None);
+        assert_eq!(file_date("seedcore.log.2024-02-30"), None);
+        assert_eq!(file_date("other.log.2024-02-01"), None);
+    }
+
+    #[test]
+    fn test_retention_by_count() {
+        let files = synthetic(20, |_| 100);
+        assert_eq!(names(&select_for_deletion(&files, 14, 0)), (0..6).map(|n| day(n).to_string()).collect::<Vec<_>>());
+        assert!(select_for_deletion(&files, 20, 0).is_empty());
+
+        // Keeping nothing still keeps the file in use
+        let selected = select_for_deletion(&files, 0, 0);
+        assert_eq!(selected.len(), 19);
+        assert!(selected.iter().all(|file| !file.in_use));
+    }
+
+    #[test]
+    fn test_size_pruning_removes_oldest_first() {
+        // Day n is (n + 1) KiB: 1 + 2 + ... + 10 = 55 KiB in total
+        let files = synthetic(10, |n| u64::from(n + 1) * 1024);
+        let selected = select_for_deletion(&files, 14, 40 * 1024);
+        // Dropping days 0..=3 (10 KiB) leaves 45 KiB, day 4 brings it to 40 KiB
+        assert_eq!(names(&selected), (0..5).map(|n| day(n).to_string()).collect::<Vec<_>>());
+
+        // The count limit applies first, then the size limit on what's left
+        let selected = select_for_deletion(&files, 3, 1024);
+        assert_eq!(selected.len(), 9);
+
+        // A file in use that is over the limit by itself stays
+        let files = synthetic(3, |_| 1 << 20);
+        assert_eq!(select_for_deletion(&files, 14, 1024).len(), 2);
+    }
+
+    #[test]
+    fn test_prune_directory() {
+        let temp_dir = TempDir::new().unwrap();
+        for n in 0..5 {
+            std::fs::write(temp_dir.path().join(format!("seedcore.log.{}", day(n))), vec![0; 10]).unwrap();
+        }
+        std::fs::write(temp_dir.path().join("notes.txt"), "not a log").unwrap();
+        std::fs::create_dir(temp_dir.path().join(format!("seedcore.log.{}", day(9)))).unwrap();
+
+        let removed = prune(temp_dir.path(), 2, 0, day(4));
+        assert_eq!(removed.len(), 3);
+        let kept: Vec<LogFile> = list_logs(temp_dir.path(), day(4));
+        assert_eq!(kept.iter().map(|file| file.date.clone()).collect::<Vec<_>>(), [day(3).to_string(), day(4).to_string()]);
+        assert!(kept[1].in_use);
+        assert!(temp_dir.path().join("notes.txt").exists());
+    }
+}
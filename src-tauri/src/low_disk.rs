@@ -0,0 +1,452 @@
+//! Pausing downloads before their disk fills up
+//!
+//! Every `CHECK_INTERVAL` the free space of each filesystem that running
+//! downloads write to (one check per volume, however many downloads share
+//! it) is compared with what those downloads still need plus a reserve
+//! (`low_disk_reserve_mb`). Files are created sparse, so the bytes left to
+//! download are what the disk still has to find room for.
+//!
+//! When the downloads won't fit, `low-disk-warning` goes out once. When
+//! free space actually drops below the reserve, the downloads with the most
+//! left are paused and tagged `paused-low-disk` until the rest fit in what
+//! is free. Once space is freed, the paused downloads that fit again are
+//! started (`low_disk_auto_resume`) or offered with `low-disk-space-freed`.
+
+use crate::database::AppSettings;
+#[cfg(feature = "app")]
+use crate::events::{emit_event, Event, LowDiskPausedEvent, LowDiskWarningEvent, SpaceFreedEvent};
+use crate::state::{AppState, TorrentState};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+#[cfg(feature = "app")]
+use tauri::Manager;
+use tokio::time::{self, Duration};
+
+/// Tag on torrents paused for low disk space
+pub const LOW_DISK_TAG: &str = "paused-low-disk";
+
+/// Free space kept on download filesystems unless configured, in MiB
+pub const DEFAULT_RESERVE_MB: u64 = 2048;
+
+/// How often free space is checked
+const CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+pub fn default_reserve_mb() -> u64 {
+    DEFAULT_RESERVE_MB
+}
+
+/// A download writing to a filesystem
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Download {
+    pub torrent_id: String,
+    /// Bytes it still has to write
+    pub remaining: u64,
+}
+
+/// How a filesystem's free space compares with what its downloads need
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outlook {
+    /// Every download fits with the reserve to spare
+    Fits,
+    /// Finishing every download would eat `shortfall` bytes into the reserve
+    Projected { shortfall: u64 },
+    /// Free space is already below the reserve
+    BelowReserve,
+}
+
+/// Where a filesystem with `free` bytes stands once downloads needing
+/// `remaining` bytes finish, keeping `reserve` bytes free (0 = no reserve)
+pub fn project(free: u64, remaining: u64, reserve: u64) -> Outlook {
+    if free < reserve {
+        return Outlook::BelowReserve;
+    }
+    match (remaining + reserve).checked_sub(free) {
+        Some(shortfall) if shortfall > 0 => Outlook::Projected { shortfall },
+        _ => Outlook::Fits,
+    }
+}
+
+/// Downloads to pause on a filesystem below its reserve: the most remaining
+/// first, until what the others still need fits in `free`. Nearly finished
+/// downloads get to complete.
+pub fn select_to_pause(downloads: &[Download], free: u64) -> Vec<&Download> {
+    let mut by_remaining: Vec<&Download> = downloads.iter().collect();
+    by_remaining.sort_by(|a, b| b.remaining.cmp(&a.remaining).then_with(|| a.torrent_id.cmp(&b.torrent_id)));
+    let mut running: u64 = downloads.iter().map(|download| download.remaining).sum();
+    by_remaining
+        .into_iter()
+        .take_while(|download| {
+            let over = running > free;
+            running -= download.remaining;
+            over
+        })
+        .collect()
+}
+
+/// Paused downloads that can run again: the least remaining first, while
+/// they fit next to the `running` bytes other downloads need, keeping
+/// `reserve` free
+pub fn select_to_resume(paused: &[Download], running: u64, free: u64, reserve: u64) -> Vec<&Download> {
+    if free < reserve {
+        return Vec::new();
+    }
+    let mut room = (free - reserve).saturating_sub(running);
+    let mut by_remaining: Vec<&Download> = paused.iter().collect();
+    by_remaining.sort_by(|a, b| a.remaining.cmp(&b.remaining).then_with(|| a.torrent_id.cmp(&b.torrent_id)));
+    by_remaining
+        .into_iter()
+        .take_while(|download| match room.checked_sub(download.remaining) {
+            Some(left) => {
+                room = left;
+                true
+            }
+            None => false,
+        })
+        .collect()
+}
+
+/// Record whether the monitor paused a torrent, and update its UI tags
+pub async fn set_paused(state: &AppState, torrent_id: &str, paused_at: Option<i64>) -> Result<(), String> {
+    let mut session = state.database
+        .load_torrent(torrent_id)
+        .map_err(|e| format!("Failed to load torrent: {}", e))?
+        .ok_or_else(|| format!("Torrent not found: {}", torrent_id))?;
+    if session.low_disk_paused_at != paused_at {
+        session.low_disk_paused_at = paused_at;
+        state.database
+            .save_torrent(&session)
+            .map_err(|e| format!("Failed to save torrent to database: {}", e))?;
+    }
+    if let Some(info) = state.torrents.write().await.get_mut(torrent_id) {
+        info.tags = session.tags();
+    }
+    Ok(())
+}
+
+/// Forget that the monitor paused a torrent (it is being started)
+pub async fn clear_pause(state: &AppState, torrent_id: &str) -> Result<(), String> {
+    let tagged = state.torrents.read().await
+        .get(torrent_id)
+        .is_some_and(|info| info.tags.iter().any(|tag| tag == LOW_DISK_TAG));
+    if !tagged {
+        return Ok(());
+    }
+    set_paused(state, torrent_id, None).await
+}
+
+/// Where a torrent's files go, and the filesystem that is on
+#[derive(Debug, Clone)]
+struct Location {
+    dir: PathBuf,
+    volume: Volume,
+}
+
+/// A filesystem; without a device id, each folder counts as its own
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Volume {
+    Device(u64),
+    Folder(PathBuf),
+}
+
+impl Location {
+    fn of(dir: &Path) -> Self {
+        let volume = crate::disk::volume_id(dir).map_or_else(|| Volume::Folder(dir.to_path_buf()), Volume::Device);
+        Self { dir: dir.to_path_buf(), volume }
+    }
+}
+
+/// Downloads on one filesystem
+#[derive(Debug, Default)]
+struct VolumeDownloads {
+    dir: PathBuf,
+    running: Vec<Download>,
+    paused: Vec<Download>,
+}
+
+/// State kept between checks
+#[derive(Default)]
+struct Monitor {
+    /// Download folders, looked up once per torrent
+    locations: HashMap<String, Location>,
+    /// Filesystems `low-disk-warning` went out for, until they fit again
+    warned: HashSet<Volume>,
+    /// Torrents `low-disk-space-freed` went out for
+    offered: HashSet<String>,
+}
+
+impl Monitor {
+    async fn location(&mut self, state: &AppState, torrent_id: &str) -> Option<Location> {
+        if let Some(location) = self.locations.get(torrent_id) {
+            return Some(location.clone());
+        }
+        let session = match state.database.load_torrent(torrent_id) {
+            Ok(session) => session?,
+            Err(e) => {
+                tracing::warn!("Free space check failed to load {}: {}", torrent_id, e);
+                return None;
+            }
+        };
+        let location = Location::of(Path::new(&session.download_dir));
+        self.locations.insert(torrent_id.to_string(), location.clone());
+        Some(location)
+    }
+
+    /// Running and low-disk-paused downloads by filesystem
+    async fn volumes(&mut self, state: &AppState) -> HashMap<Volume, VolumeDownloads> {
+        let torrents: Vec<(String, bool, Download)> = state.torrents.read().await
+            .iter()
+            .filter_map(|(id, info)| {
+                let paused = info.state == TorrentState::Paused && info.tags.iter().any(|tag| tag == LOW_DISK_TAG);
+                (info.state == TorrentState::Downloading || paused).then(|| {
+                    let download = Download {
+                        torrent_id: id.clone(),
+                        remaining: info.size.saturating_sub(info.downloaded),
+                    };
+                    (id.clone(), paused, download)
+                })
+            })
+            .collect();
+        // Folders may change while a torrent is stopped; look them up again then
+        self.locations.retain(|id, _| torrents.iter().any(|(watched, _, _)| watched == id));
+        self.offered.retain(|id| torrents.iter().any(|(watched, paused, _)| watched == id && *paused));
+
+        let mut volumes: HashMap<Volume, VolumeDownloads> = HashMap::new();
+        for (id, paused, download) in torrents {
+            let Some(location) = self.location(state, &id).await else {
+                continue;
+            };
+            let volume = volumes.entry(location.volume).or_default();
+            volume.dir = location.dir;
+            if paused {
+                volume.paused.push(download);
+            } else {
+                volume.running.push(download);
+            }
+        }
+        volumes
+    }
+
+    #[cfg(feature = "app")]
+    async fn check(&mut self, app: &tauri::AppHandle, state: &AppState, settings: &AppSettings) {
+        let reserve = settings.low_disk_reserve_mb.saturating_mul(1024 * 1024);
+        let volumes = self.volumes(state).await;
+        self.warned.retain(|volume| volumes.contains_key(volume));
+
+        for (volume, downloads) in volumes {
+            let dir = downloads.dir.clone();
+            let free = match tokio::task::spawn_blocking(move || crate::commands::available_disk_space(&dir)).await {
+                Ok(Ok(free)) => free,
+                Ok(Err(e)) => {
+                    tracing::warn!("Free space check of {:?} failed: {}", downloads.dir, e);
+                    continue;
+                }
+                Err(e) => {
+                    tracing::error!("Free space check failed: {}", e);
+                    continue;
+                }
+            };
+            let path = downloads.dir.to_string_lossy().into_owned();
+            let mut running: u64 = downloads.running.iter().map(|download| download.remaining).sum();
+
+            match project(free, running, reserve) {
+                Outlook::Fits => {
+                    self.warned.remove(&volume);
+                }
+                Outlook::Projected { shortfall } => {
+                    if self.warned.insert(volume.clone()) {
+                        warn(app, &path, free, running, reserve, shortfall, &downloads.running);
+                    }
+                }
+                Outlook::BelowReserve => {
+                    if self.warned.insert(volume.clone()) {
+                        let shortfall = (running + reserve).saturating_sub(free);
+                        warn(app, &path, free, running, reserve, shortfall, &downloads.running);
+                    }
+                    let selected = select_to_pause(&downloads.running, free);
+                    running -= selected.iter().map(|download| download.remaining).sum::<u64>();
+                    pause(app, state, &path, free, reserve, &selected).await;
+                }
+            }
+
+            let fitting = select_to_resume(&downloads.paused, running, free, reserve);
+            if !fitting.is_empty() {
+                self.space_freed(app, state, settings, &path, free, &fitting).await;
+            }
+        }
+    }
+
+    #[cfg(feature = "app")]
+    async fn space_freed(
+        &mut self,
+        app: &tauri::AppHandle,
+        state: &AppState,
+        settings: &AppSettings,
+        path: &str,
+        free: u64,
+        fitting: &[&Download],
+    ) {
+        let mut torrent_ids = Vec::new();
+        for download in fitting {
+            let resumed = settings.low_disk_auto_resume && resume(state, &download.torrent_id).await;
+            if resumed || self.offered.insert(download.torrent_id.clone()) {
+                torrent_ids.push(download.torrent_id.clone());
+            }
+        }
+        if torrent_ids.is_empty() {
+            return;
+        }
+        tracing::info!("{} has room again for {} paused downloads", path, torrent_ids.len());
+        let event = SpaceFreedEvent {
+            path: path.to_string(),
+            free,
+            torrent_ids,
+            resumed: settings.low_disk_auto_resume,
+        };
+        emit_event(app, Event::SpaceFreed(event));
+    }
+}
+
+#[cfg(feature = "app")]
+fn warn(app: &tauri::AppHandle, path: &str, free: u64, remaining: u64, reserve: u64, shortfall: u64, running: &[Download]) {
+    tracing::warn!(
+        "Downloads in {} need {} bytes with {} free; {} bytes short of the {} byte reserve",
+        path, remaining, free, shortfall, reserve
+    );
+    let event = LowDiskWarningEvent {
+        path: path.to_string(),
+        free,
+        remaining,
+        reserve,
+        shortfall,
+        torrent_ids: running.iter().map(|download| download.torrent_id.clone()).collect(),
+    };
+    emit_event(app, Event::LowDiskWarning(event));
+}
+
+/// Pause downloads on a filesystem below its reserve and tag them
+#[cfg(feature = "app")]
+async fn pause(app: &tauri::AppHandle, state: &AppState, path: &str, free: u64, reserve: u64, selected: &[&Download]) {
+    if selected.is_empty() {
+        return;
+    }
+    let now = chrono::Utc::now().timestamp();
+    let mut torrent_ids = Vec::new();
+    for download in selected {
+        let id = &download.torrent_id;
+        tracing::info!("Pausing {}: {} has {} bytes free, below the reserve", id, path, free);
+        if let Err(e) = crate::commands::pause_torrent_internal(state, id, TorrentState::Paused).await {
+            tracing::warn!("Failed to pause {} for low disk space: {}", id, e);
+            continue;
+        }
+        if let Err(e) = set_paused(state, id, Some(now)).await {
+            tracing::warn!("Failed to tag {} as {}: {}", id, LOW_DISK_TAG, e);
+        }
+        torrent_ids.push(id.clone());
+    }
+    state.queue.request_reconcile();
+
+    let event = LowDiskPausedEvent { path: path.to_string(), free, reserve, torrent_ids };
+    emit_event(app, Event::LowDiskPaused(event));
+}
+
+/// Start a download paused for low disk space; false if it couldn't be
+#[cfg(feature = "app")]
+async fn resume(state: &AppState, torrent_id: &str) -> bool {
+    let result = async {
+        crate::commands::start_torrent_internal(state, torrent_id.to_string(), false).await?;
+        clear_pause(state, torrent_id).await
+    }
+    .await;
+    match result {
+        Ok(()) => {
+            state.queue.request_reconcile();
+            true
+        }
+        Err(e) => {
+            tracing::warn!("Failed to resume {} after low disk space: {}", torrent_id, e);
+            false
+        }
+    }
+}
+
+/// Check free space under running downloads for the lifetime of the app
+#[cfg(feature = "app")]
+pub async fn start_low_disk_task(app_handle: tauri::AppHandle) {
+    let mut interval = time::interval(CHECK_INTERVAL);
+    interval.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
+    let mut monitor = Monitor::default();
+
+    loop {
+        interval.tick().await;
+        let state = app_handle.state::<AppState>();
+        let settings = match state.database.load_settings() {
+            Ok(settings) => settings,
+            Err(e) => {
+                tracing::error!("Free space check failed to load settings: {}", e);
+                continue;
+            }
+        };
+        monitor.check(&app_handle, &state, &settings).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GB: u64 = 1024 * 1024 * 1024;
+
+    fn downloads(remaining: &[(&str, u64)]) -> Vec<Download> {
+        remaining
+            .iter()
+            .map(|&(id, remaining)| Download { torrent_id: id.to_string(), remaining })
+            .collect()
+    }
+
+    fn ids(selected: &[&Download]) -> Vec<String> {
+        selected.iter().map(|download| download.torrent_id.clone()).collect()
+    }
+
+    #[test]
+    fn test_projection() {
+        assert_eq!(project(100 * GB, 50 * GB, 2 * GB), Outlook::Fits);
+        // Exactly the reserve left afterwards still fits
+        assert_eq!(project(52 * GB, 50 * GB, 2 * GB), Outlook::Fits);
+        assert_eq!(project(51 * GB, 50 * GB, 2 * GB), Outlook::Projected { shortfall: GB });
+        assert_eq!(project(10 * GB, 50 * GB, 0), Outlook::Projected { shortfall: 40 * GB });
+        assert_eq!(project(GB, 0, 2 * GB), Outlook::BelowReserve);
+
+        // Without a reserve nothing is ever below it
+        assert_eq!(project(0, 0, 0), Outlook::Fits);
+    }
+
+    #[test]
+    fn test_largest_remaining_paused_first() {
+        let running = downloads(&[("a", 10 * GB), ("b", GB / 2), ("c", 30 * GB), ("d", GB / 4)]);
+        // The two big ones go; the small ones fit in what's free and finish
+        assert_eq!(ids(&select_to_pause(&running, GB)), ["c", "a"]);
+        assert_eq!(ids(&select_to_pause(&running, GB / 2)), ["c", "a", "b"]);
+        // Nothing free at all: everything stops
+        assert_eq!(select_to_pause(&running, 0).len(), 4);
+        // Everything fits: nothing to pause
+        assert!(select_to_pause(&running, 50 * GB).is_empty());
+
+        // Completed downloads don't need pausing
+        let done = downloads(&[("a", 0)]);
+        assert!(select_to_pause(&done, 0).is_empty());
+    }
+
+    #[test]
+    fn test_resume_once_space_is_freed() {
+        let paused = downloads(&[("a", 10 * GB), ("b", GB), ("c", 3 * GB)]);
+        // Still below the reserve
+        assert!(select_to_resume(&paused, 0, GB, 2 * GB).is_empty());
+        // Room for the smaller ones next to a running download
+        assert_eq!(ids(&select_to_resume(&paused, GB, 8 * GB, 2 * GB)), ["b", "c"]);
+        // Room for all
+        assert_eq!(select_to_resume(&paused, 0, 100 * GB, 2 * GB).len(), 3);
+        // Running downloads already take all the room
+        assert!(select_to_resume(&paused, 20 * GB, 10 * GB, 2 * GB).is_empty());
+    }
+}
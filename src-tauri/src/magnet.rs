@@ -115,77 +115,67 @@ impl MagnetLink {
 
         let hash_str = &xt[9..]; // Remove "urn:btih:" prefix
 
-        // Try to decode as hex (40 characters) or base32 (32 characters)
-        if hash_str.len() == 40 {
-            // Hex encoding
-            Self::decode_hex(hash_str)
-        } else if hash_str.len() == 32 {
-            // Base32 encoding
-            Self::decode_base32(hash_str)
-        } else {
-            Err(format!(
-                "Invalid info hash length: expected 40 (hex) or 32 (base32), got {}",
-                hash_str.len()
-            ))
-        }
+        // Hex (40 characters) or base32 (32 characters)
+        crate::ids::InfoHash::parse(hash_str)
+            .map(|hash| *hash.as_bytes())
+            .map_err(|e| e.to_string())
     }
 
-    /// Decode hex string to 20 bytes
-    fn decode_hex(s: &str) -> Result<[u8; 20], String> {
-        let mut bytes = [0u8; 20];
+    /// Get info hash as hex string
+    pub fn info_hash_hex(&self) -> String {
+        hex::encode(self.info_hash)
+    }
 
-        for i in 0..20 {
-            let hex_byte = &s[i * 2..i * 2 + 2];
-            bytes[i] = u8::from_str_radix(hex_byte, 16)
-                .map_err(|e| format!("Invalid hex character: {}", e))?;
+    /// Build a magnet link for a torrent we already have metadata for.
+    ///
+    /// Trackers keep announce-list tier order (primary announce first) with
+    /// duplicates and blanks dropped.
+    pub fn from_metainfo(metainfo: &crate::torrent::Metainfo) -> Self {
+        let mut trackers: Vec<String> = Vec::new();
+        let tiers = std::iter::once(&metainfo.announce)
+            .chain(metainfo.announce_list.iter().flatten());
+        for tracker in tiers {
+            if !tracker.is_empty() && !trackers.contains(tracker) {
+                trackers.push(tracker.clone());
+            }
         }
 
-        Ok(bytes)
+        MagnetLink {
+            info_hash: metainfo.info_hash,
+            display_name: Some(metainfo.info.name.clone()).filter(|name| !name.is_empty()),
+            trackers,
+            // Web seeds (BEP 19) are not parsed from .torrent files yet
+            web_seeds: Vec::new(),
+        }
     }
 
-    /// Decode base32 string to 20 bytes
-    fn decode_base32(s: &str) -> Result<[u8; 20], String> {
-        // BitTorrent uses base32 without padding
-        const BASE32_ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
-
-        let s_upper = s.to_uppercase();
-        let mut bits = Vec::new();
+    /// The same link with passkeys masked in its tracker URLs, for sharing
+    /// or logging (see `crate::tracker::redact`)
+    pub fn without_passkeys(&self) -> Self {
+        MagnetLink {
+            trackers: self.trackers.iter().map(|tracker| crate::tracker::redact::redact_tracker_url(tracker)).collect(),
+            ..self.clone()
+        }
+    }
 
-        for c in s_upper.chars() {
-            let value = BASE32_ALPHABET
-                .iter()
-                .position(|&b| b as char == c)
-                .ok_or_else(|| format!("Invalid base32 character: {}", c))?;
+    /// Serialize back to a magnet URI (inverse of `parse`)
+    pub fn to_uri(&self) -> String {
+        let mut uri = format!("magnet:?xt=urn:btih:{}", self.info_hash_hex());
 
-            // Each base32 char = 5 bits
-            for i in (0..5).rev() {
-                bits.push(((value >> i) & 1) as u8);
-            }
+        if let Some(ref name) = self.display_name {
+            uri.push_str("&dn=");
+            uri.push_str(&urlencoding::encode(name));
         }
-
-        // Convert bits to bytes (should be 160 bits = 20 bytes)
-        if bits.len() < 160 {
-            return Err(format!(
-                "Invalid base32 hash: too short ({} bits)",
-                bits.len()
-            ));
+        for tracker in &self.trackers {
+            uri.push_str("&tr=");
+            uri.push_str(&urlencoding::encode(tracker));
         }
-
-        let mut bytes = [0u8; 20];
-        for i in 0..20 {
-            let mut byte = 0u8;
-            for j in 0..8 {
-                byte = (byte << 1) | bits[i * 8 + j];
-            }
-            bytes[i] = byte;
+        for web_seed in &self.web_seeds {
+            uri.push_str("&ws=");
+            uri.push_str(&urlencoding::encode(web_seed));
         }
 
-        Ok(bytes)
-    }
-
-    /// Get info hash as hex string
-    pub fn info_hash_hex(&self) -> String {
-        hex::encode(self.info_hash)
+        uri
     }
 }
 
@@ -223,6 +213,78 @@ mod tests {
         assert_eq!(magnet.trackers[0], "http://tracker.example.com/announce");
     }
 
+    fn metainfo_named(name: &str) -> crate::torrent::Metainfo {
+        let mut metainfo = crate::torrent::Metainfo::from_magnet(
+            [0xab; 20],
+            Some(name.to_string()),
+            vec![
+                "http://tracker.example.com/announce?key=a&b=c".to_string(),
+                "udp://tracker.other.org:1337".to_string(),
+            ],
+        );
+        // Duplicate across tiers must only be emitted once
+        metainfo.announce_list.push(vec!["udp://tracker.other.org:1337".to_string()]);
+        metainfo
+    }
+
+    #[test]
+    fn test_generate_round_trip() {
+        for name in ["Plain", "With spaces & ampersands", "Ünïcødé 映画 = 100%"] {
+            let metainfo = metainfo_named(name);
+            let uri = MagnetLink::from_metainfo(&metainfo).to_uri();
+            let parsed = MagnetLink::parse(&uri).unwrap();
+
+            assert_eq!(parsed.info_hash, metainfo.info_hash);
+            assert_eq!(parsed.display_name.as_deref(), Some(name));
+
+            let mut trackers = parsed.trackers.clone();
+            trackers.sort();
+            assert_eq!(
+                trackers,
+                vec![
+                    "http://tracker.example.com/announce?key=a&b=c".to_string(),
+                    "udp://tracker.other.org:1337".to_string(),
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn test_generate_preserves_tier_order() {
+        let magnet = MagnetLink::from_metainfo(&metainfo_named("x"));
+        assert_eq!(
+            magnet.trackers,
+            vec![
+                "http://tracker.example.com/announce?key=a&b=c".to_string(),
+                "udp://tracker.other.org:1337".to_string(),
+            ]
+        );
+        assert!(magnet
+            .to_uri()
+            .starts_with("magnet:?xt=urn:btih:abababababababababababababababababababab&dn=x&tr="));
+    }
+
+    #[test]
+    fn test_without_passkeys() {
+        let mut metainfo = metainfo_named("x");
+        metainfo.announce = "https://tracker.example.org/0123456789abcdef0123/announce".to_string();
+        metainfo.announce_list.push(vec!["https://other.example.org/announce?passkey=abc".to_string()]);
+        let magnet = MagnetLink::from_metainfo(&metainfo);
+
+        let stripped = magnet.without_passkeys();
+        assert_eq!(stripped.info_hash, magnet.info_hash);
+        assert_eq!(
+            stripped.trackers,
+            vec![
+                "https://tracker.example.org/REDACTED/announce".to_string(),
+                "udp://tracker.other.org:1337".to_string(),
+                "https://other.example.org/announce?passkey=REDACTED".to_string(),
+            ]
+        );
+        assert!(!stripped.to_uri().contains("0123456789abcdef0123"));
+        assert!(magnet.to_uri().contains("0123456789abcdef0123"));
+    }
+
     #[test]
     fn test_invalid_magnet() {
         let uri = "http://example.com";
@@ -0,0 +1,184 @@
+//! Upload choking decisions and their history
+//!
+//! Every choking pass ranks the interested peers by the rate they gave us
+//! (or took from us, once we seed) since the previous pass and unchokes the
+//! best few. Each peer's outcome is kept with the reason for it, so a peer
+//! that never gets upload slots can be explained from the UI.
+
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+
+/// Decisions kept per peer session
+pub const CHOKE_HISTORY_LEN: usize = 20;
+
+/// Why the choking algorithm choked or unchoked a peer
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ChokeReason {
+    /// The peer wants nothing from us
+    NotInterested,
+    /// Among the best rates; `cutoff` is the slowest rate that got a slot
+    TopRate { rate: u64, cutoff: u64 },
+    /// Slower than every unchoked peer
+    BelowTopRate { rate: u64, cutoff: u64 },
+    /// Holds the optimistic unchoke slot
+    Optimistic,
+    /// Stopped sending us the blocks we asked for
+    Snubbed,
+}
+
+/// One choking pass's outcome for a peer
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ChokeDecision {
+    /// Unix seconds
+    pub at: i64,
+    pub choked: bool,
+    pub reason: ChokeReason,
+}
+
+/// A peer's recent decisions, oldest first. A pass that repeats the previous
+/// outcome only refreshes it, so the history shows changes rather than
+/// twenty copies of the same verdict.
+#[derive(Debug, Clone, Default)]
+pub struct ChokeHistory(VecDeque<ChokeDecision>);
+
+impl ChokeHistory {
+    pub fn record(&mut self, decision: ChokeDecision) {
+        if let Some(last) = self.0.back_mut() {
+            let same_kind = std::mem::discriminant(&last.reason) == std::mem::discriminant(&decision.reason);
+            if last.choked == decision.choked && same_kind {
+                *last = decision;
+                return;
+            }
+        }
+        if self.0.len() == CHOKE_HISTORY_LEN {
+            self.0.pop_front();
+        }
+        self.0.push_back(decision);
+    }
+
+    pub fn latest(&self) -> Option<&ChokeDecision> {
+        self.0.back()
+    }
+
+    pub fn to_vec(&self) -> Vec<ChokeDecision> {
+        self.0.iter().copied().collect()
+    }
+}
+
+/// What a choking pass knows about one peer
+#[derive(Debug, Clone, Copy)]
+pub struct ChokeCandidate {
+    pub addr: SocketAddr,
+    pub interested: bool,
+    /// Bytes/sec over the last choking interval
+    pub rate: u64,
+    pub snubbed: bool,
+    /// Holds the optimistic slot
+    pub optimistic: bool,
+}
+
+/// Decide who gets the `slots` regular upload slots: the fastest interested
+/// peers that aren't snubbing us. The optimistic peer keeps its slot on top
+/// of those. Returns whether each candidate should be choked, and why.
+pub fn decide(candidates: &[ChokeCandidate], slots: usize) -> Vec<(SocketAddr, bool, ChokeReason)> {
+    let mut ranked: Vec<&ChokeCandidate> = candidates.iter().filter(|c| c.interested && !c.snubbed).collect();
+    // Ties go to the address, so passes over the same rates agree
+    ranked.sort_by(|a, b| b.rate.cmp(&a.rate).then(a.addr.cmp(&b.addr)));
+    ranked.truncate(slots);
+    let cutoff = if ranked.len() == slots { ranked.last().map_or(0, |c| c.rate) } else { 0 };
+
+    candidates
+        .iter()
+        .map(|c| {
+            let (choked, reason) = if !c.interested {
+                (true, ChokeReason::NotInterested)
+            } else if ranked.iter().any(|r| r.addr == c.addr) {
+                (false, ChokeReason::TopRate { rate: c.rate, cutoff })
+            } else if c.optimistic {
+                (false, ChokeReason::Optimistic)
+            } else if c.snubbed {
+                (true, ChokeReason::Snubbed)
+            } else {
+                (true, ChokeReason::BelowTopRate { rate: c.rate, cutoff })
+            };
+            (c.addr, choked, reason)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidate(port: u16, rate: u64) -> ChokeCandidate {
+        ChokeCandidate {
+            addr: SocketAddr::from(([10, 0, 0, 1], port)),
+            interested: true,
+            rate,
+            snubbed: false,
+            optimistic: false,
+        }
+    }
+
+    #[test]
+    fn test_history_keeps_changes() {
+        let mut history = ChokeHistory::default();
+        let decision = |at, choked, rate| ChokeDecision {
+            at,
+            choked,
+            reason: ChokeReason::BelowTopRate { rate, cutoff: 100 },
+        };
+        history.record(decision(1, true, 10));
+        history.record(decision(2, true, 20));
+        assert_eq!(history.to_vec(), vec![decision(2, true, 20)]);
+
+        for at in 0..CHOKE_HISTORY_LEN as i64 * 2 {
+            let reason = if at % 2 == 0 { ChokeReason::Optimistic } else { ChokeReason::Snubbed };
+            history.record(ChokeDecision { at, choked: at % 2 == 1, reason });
+        }
+        assert_eq!(history.to_vec().len(), CHOKE_HISTORY_LEN);
+        assert_eq!(history.latest().unwrap().at, CHOKE_HISTORY_LEN as i64 * 2 - 1);
+    }
+
+    #[test]
+    fn test_decide_classifies_each_peer() {
+        let mut uninterested = candidate(1, 900);
+        uninterested.interested = false;
+        let mut snubbed = candidate(2, 800);
+        snubbed.snubbed = true;
+        let mut optimistic = candidate(3, 5);
+        optimistic.optimistic = true;
+        let candidates = [
+            uninterested,
+            snubbed,
+            optimistic,
+            candidate(4, 500),
+            candidate(5, 400),
+            candidate(6, 300),
+            candidate(7, 200),
+            candidate(8, 100),
+        ];
+
+        let decisions = decide(&candidates, 4);
+        let expected = [
+            (true, ChokeReason::NotInterested),
+            (true, ChokeReason::Snubbed),
+            (false, ChokeReason::Optimistic),
+            (false, ChokeReason::TopRate { rate: 500, cutoff: 200 }),
+            (false, ChokeReason::TopRate { rate: 400, cutoff: 200 }),
+            (false, ChokeReason::TopRate { rate: 300, cutoff: 200 }),
+            (false, ChokeReason::TopRate { rate: 200, cutoff: 200 }),
+            (true, ChokeReason::BelowTopRate { rate: 100, cutoff: 200 }),
+        ];
+        for ((addr, choked, reason), (candidate, expected)) in decisions.iter().zip(candidates.iter().zip(expected)) {
+            assert_eq!(*addr, candidate.addr);
+            assert_eq!((*choked, *reason), expected, "{}", addr);
+        }
+
+        // With slots to spare everyone interested gets one and there's no cutoff
+        let decisions = decide(&candidates[3..5], 4);
+        assert_eq!(decisions[1].2, ChokeReason::TopRate { rate: 400, cutoff: 0 });
+    }
+}
@@ -0,0 +1,237 @@
+//! Why peer connections ended
+//!
+//! The peers list only shows live connections. Each torrent also keeps its
+//! last `DISCONNECT_HISTORY_CAPACITY` disconnections, shared by the engine
+//! and every peer manager it starts, for `get_peer_disconnect_history` and
+//! the per-reason counts in the details view.
+
+use crate::error::Error;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+/// Disconnections kept per torrent; older ones are dropped
+pub const DISCONNECT_HISTORY_CAPACITY: usize = 200;
+
+/// Why a connection ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DisconnectReason {
+    /// No handshake within the handshake timeout
+    HandshakeTimeout,
+    /// The peer answered for another torrent
+    InfoHashMismatch,
+    /// Bad handshake, malformed or oversized message, fast messages
+    /// without the fast extension
+    ProtocolError,
+    /// The peer closed or reset the connection
+    RemoteClosed,
+    /// We choked the peer and dropped the connection
+    ChokedAndDropped,
+    /// The torrent was paused or stopped, or couldn't store what the peer sent
+    Cancelled,
+    /// Dropped on purpose: removed, or sent a piece that failed verification
+    Banned,
+    /// Nothing received within the message timeout
+    IdleTimeout,
+    /// Dropped while seeding to make room: a seed too, or idle for long
+    ReapedIdle,
+}
+
+/// Who opened the connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    Outbound,
+    Inbound,
+}
+
+/// One ended connection
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Disconnection {
+    pub addr: SocketAddr,
+    /// Client name from the peer id ("Unknown" before the handshake)
+    pub client: String,
+    pub direction: Direction,
+    /// From the TCP connect to the end, in milliseconds
+    pub connected_ms: u64,
+    pub downloaded: u64,
+    pub uploaded: u64,
+    pub reason: DisconnectReason,
+    /// The error behind `reason`
+    pub detail: String,
+    /// Unix timestamp
+    pub at: i64,
+}
+
+/// Why a session is being torn down, before the session's numbers are added
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Disconnect {
+    pub reason: DisconnectReason,
+    pub detail: String,
+}
+
+impl Disconnect {
+    pub fn new(reason: DisconnectReason, detail: impl Into<String>) -> Self {
+        Self { reason, detail: detail.into() }
+    }
+
+    /// A failed handshake
+    pub fn handshake(error: &Error) -> Self {
+        let reason = match error {
+            Error::Timeout(_) => DisconnectReason::HandshakeTimeout,
+            Error::InfoHashMismatch(_) => DisconnectReason::InfoHashMismatch,
+            Error::ProtocolViolation(_) => DisconnectReason::ProtocolError,
+            _ => DisconnectReason::RemoteClosed,
+        };
+        Self::new(reason, error.to_string())
+    }
+
+    /// A failed read on an established session
+    pub fn wire(error: &Error) -> Self {
+        let reason = match error {
+            Error::Timeout(_) => DisconnectReason::IdleTimeout,
+            Error::ProtocolViolation(_) => DisconnectReason::ProtocolError,
+            _ => DisconnectReason::RemoteClosed,
+        };
+        Self::new(reason, error.to_string())
+    }
+}
+
+/// The session helpers fail with a message when a send fails: the peer is gone
+impl From<String> for Disconnect {
+    fn from(detail: String) -> Self {
+        Self::new(DisconnectReason::RemoteClosed, detail)
+    }
+}
+
+impl fmt::Display for Disconnect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}: {}", self.reason, self.detail)
+    }
+}
+
+/// A torrent's recent disconnections, oldest first
+#[derive(Debug, Default)]
+pub struct DisconnectHistory {
+    entries: Mutex<VecDeque<Disconnection>>,
+}
+
+impl DisconnectHistory {
+    pub fn record(&self, disconnection: Disconnection) {
+        tracing::debug!(
+            "Peer {} disconnected after {}ms: {}",
+            disconnection.addr,
+            disconnection.connected_ms,
+            disconnection.detail
+        );
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() == DISCONNECT_HISTORY_CAPACITY {
+            entries.pop_front();
+        }
+        entries.push_back(disconnection);
+    }
+
+    /// Every kept disconnection, newest first
+    pub fn recent(&self) -> Vec<Disconnection> {
+        self.entries.lock().unwrap().iter().rev().cloned().collect()
+    }
+
+    /// Disconnections per reason at or after `since` (unix timestamp)
+    pub fn counts_since(&self, since: i64) -> BTreeMap<DisconnectReason, usize> {
+        let mut counts = BTreeMap::new();
+        for entry in self.entries.lock().unwrap().iter().filter(|entry| entry.at >= since) {
+            *counts.entry(entry.reason).or_insert(0) += 1;
+        }
+        counts
+    }
+}
+
+/// Every torrent's disconnect history, by info hash (see
+/// `AppState::disconnect_histories`)
+#[derive(Debug, Default)]
+pub struct DisconnectHistories {
+    histories: Mutex<HashMap<String, Arc<DisconnectHistory>>>,
+}
+
+impl DisconnectHistories {
+    /// The torrent's history, created empty on first use
+    pub fn get(&self, torrent_id: &str) -> Arc<DisconnectHistory> {
+        self.histories.lock().unwrap().entry(torrent_id.to_string()).or_default().clone()
+    }
+
+    pub fn remove(&self, torrent_id: &str) {
+        self.histories.lock().unwrap().remove(torrent_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn disconnection(n: usize, reason: DisconnectReason, at: i64) -> Disconnection {
+        Disconnection {
+            addr: SocketAddr::from(([10, 0, (n / 256) as u8, (n % 256) as u8], 6881)),
+            client: "Unknown".to_string(),
+            direction: Direction::Outbound,
+            connected_ms: 0,
+            downloaded: 0,
+            uploaded: 0,
+            reason,
+            detail: String::new(),
+            at,
+        }
+    }
+
+    #[test]
+    fn test_classification() {
+        let handshake = |e: Error| Disconnect::handshake(&e).reason;
+        assert_eq!(handshake(Error::Timeout("handshake".into())), DisconnectReason::HandshakeTimeout);
+        assert_eq!(handshake(Error::InfoHashMismatch("other".into())), DisconnectReason::InfoHashMismatch);
+        assert_eq!(handshake(Error::ProtocolViolation("pstr".into())), DisconnectReason::ProtocolError);
+        assert_eq!(handshake(Error::NetworkError("eof".into())), DisconnectReason::RemoteClosed);
+
+        let wire = |e: Error| Disconnect::wire(&e).reason;
+        assert_eq!(wire(Error::Timeout("receive".into())), DisconnectReason::IdleTimeout);
+        assert_eq!(wire(Error::ProtocolViolation("length".into())), DisconnectReason::ProtocolError);
+        assert_eq!(wire(Error::NetworkError("reset".into())), DisconnectReason::RemoteClosed);
+
+        let failed_send: Disconnect = "Failed to send interested: broken pipe".to_string().into();
+        assert_eq!(failed_send.reason, DisconnectReason::RemoteClosed);
+        assert_eq!(failed_send.detail, "Failed to send interested: broken pipe");
+    }
+
+    #[test]
+    fn test_history_is_capped_and_counted() {
+        let history = DisconnectHistory::default();
+        for n in 0..DISCONNECT_HISTORY_CAPACITY + 50 {
+            let reason = if n % 2 == 0 { DisconnectReason::HandshakeTimeout } else { DisconnectReason::RemoteClosed };
+            history.record(disconnection(n, reason, n as i64));
+        }
+
+        let recent = history.recent();
+        assert_eq!(recent.len(), DISCONNECT_HISTORY_CAPACITY);
+        // Newest first, the first 50 dropped
+        assert_eq!(recent[0].at, (DISCONNECT_HISTORY_CAPACITY + 49) as i64);
+        assert_eq!(recent.last().unwrap().at, 50);
+
+        let all = history.counts_since(0);
+        assert_eq!(all[&DisconnectReason::HandshakeTimeout], 100);
+        assert_eq!(all[&DisconnectReason::RemoteClosed], 100);
+        let last_ten = history.counts_since((DISCONNECT_HISTORY_CAPACITY + 40) as i64);
+        assert_eq!(last_ten.values().sum::<usize>(), 10);
+        assert!(!last_ten.contains_key(&DisconnectReason::Cancelled));
+    }
+
+    #[test]
+    fn test_histories_by_torrent() {
+        let histories = DisconnectHistories::default();
+        histories.get("a").record(disconnection(1, DisconnectReason::Banned, 0));
+        assert_eq!(histories.get("a").recent().len(), 1);
+        assert!(histories.get("b").recent().is_empty());
+        histories.remove("a");
+        assert!(histories.get("a").recent().is_empty());
+    }
+}
@@ -0,0 +1,74 @@
+//! Fast Extension (BEP 6) allowed fast sets
+//!
+//! Each side of a fast connection can let the other download a few pieces
+//! while choked. Which pieces is derived from the peer's IP and the info hash,
+//! so a peer reconnecting (or trying from other ports) gets the same set.
+
+use sha1::{Digest, Sha1};
+use std::net::IpAddr;
+
+/// Pieces we let each fast peer request while choked
+pub const ALLOWED_FAST_COUNT: usize = 10;
+
+/// The `k` pieces a peer at `ip` may request while choked, in the BEP's
+/// canonical order. Only defined for IPv4 peers; empty for IPv6.
+pub fn allowed_fast_set(ip: IpAddr, info_hash: &[u8; 20], num_pieces: usize, k: usize) -> Vec<u32> {
+    let IpAddr::V4(ip) = ip else {
+        return Vec::new();
+    };
+    let k = k.min(num_pieces);
+    let mut set: Vec<u32> = Vec::with_capacity(k);
+
+    // The /24 network stands for the peer, so it can't pick its own set
+    let mut x = Vec::with_capacity(24);
+    x.extend_from_slice(&(u32::from(ip) & 0xFFFF_FF00).to_be_bytes());
+    x.extend_from_slice(info_hash);
+    while set.len() < k {
+        x = Sha1::digest(&x).to_vec();
+        for chunk in x.chunks_exact(4) {
+            if set.len() == k {
+                break;
+            }
+            let y = u32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+            let index = (y as u64 % num_pieces as u64) as u32;
+            if !set.contains(&index) {
+                set.push(index);
+            }
+        }
+    }
+    set
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_allowed_fast_set_matches_bep() {
+        // The example from BEP 6
+        let ip: IpAddr = "80.4.4.200".parse().unwrap();
+        let info_hash = [0xAAu8; 20];
+        assert_eq!(
+            allowed_fast_set(ip, &info_hash, 1313, 7),
+            vec![1059, 431, 808, 1217, 287, 376, 1188]
+        );
+        assert_eq!(
+            allowed_fast_set(ip, &info_hash, 1313, 9),
+            vec![1059, 431, 808, 1217, 287, 376, 1188, 353, 508]
+        );
+
+        // Same /24, same set
+        let neighbour: IpAddr = "80.4.4.13".parse().unwrap();
+        assert_eq!(allowed_fast_set(neighbour, &info_hash, 1313, 9), allowed_fast_set(ip, &info_hash, 1313, 9));
+    }
+
+    #[test]
+    fn test_small_torrents_and_ipv6() {
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+        let mut set = allowed_fast_set(ip, &[1u8; 20], 3, ALLOWED_FAST_COUNT);
+        set.sort();
+        assert_eq!(set, vec![0, 1, 2]);
+        assert!(allowed_fast_set(ip, &[1u8; 20], 0, ALLOWED_FAST_COUNT).is_empty());
+        assert!(allowed_fast_set("::1".parse().unwrap(), &[1u8; 20], 100, ALLOWED_FAST_COUNT).is_empty());
+    }
+}
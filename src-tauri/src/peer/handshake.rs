@@ -12,6 +12,9 @@ use crate::error::{Error, Result};
 const PROTOCOL_NAME: &[u8] = b"BitTorrent protocol";
 const HANDSHAKE_LENGTH: usize = 68;
 
+/// Fast Extension (BEP 6): `reserved[7] & 0x04`, in `supports_extension` numbering
+pub const FAST_EXTENSION_BIT: u8 = 58;
+
 /// BitTorrent handshake message
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Handshake {
@@ -29,20 +32,22 @@ pub struct Handshake {
 }
 
 impl Handshake {
-    /// Create a new handshake
+    /// Create a new handshake, advertising the extensions we support
     pub fn new(info_hash: [u8; 20], peer_id: [u8; 20]) -> Self {
-        Self {
+        let mut handshake = Self {
             protocol: PROTOCOL_NAME.to_vec(),
             reserved: [0u8; 8],
             info_hash,
             peer_id,
-        }
+        };
+        handshake.enable_extension(FAST_EXTENSION_BIT);
+        handshake
     }
 
     /// Parse handshake from bytes
     pub fn from_bytes(data: &[u8]) -> Result<Self> {
         if data.len() != HANDSHAKE_LENGTH {
-            return Err(Error::InvalidData(format!(
+            return Err(Error::ProtocolViolation(format!(
                 "handshake must be {} bytes, got {}",
                 HANDSHAKE_LENGTH,
                 data.len()
@@ -52,21 +57,23 @@ impl Handshake {
         // Read protocol name length
         let pstr_len = data[0] as usize;
 
-        if pstr_len != 19 {
-            return Err(Error::InvalidData(format!(
-                "protocol name length must be 19, got {}",
+        if pstr_len != PROTOCOL_NAME.len() {
+            return Err(Error::ProtocolViolation(format!(
+                "protocol name length must be {}, got {}",
+                PROTOCOL_NAME.len(),
                 pstr_len
             )));
         }
 
-        // Read protocol name
-        let protocol = data[1..20].to_vec();
-
-        if protocol != PROTOCOL_NAME {
-            return Err(Error::InvalidData(
-                "protocol name must be 'BitTorrent protocol'".to_string(),
-            ));
+        // Read protocol name; anything else (e.g. an encrypted or HTTP
+        // stream) isn't a peer we can talk to
+        if &data[1..20] != PROTOCOL_NAME {
+            return Err(Error::ProtocolViolation(format!(
+                "protocol name must be 'BitTorrent protocol', got {:?}",
+                String::from_utf8_lossy(&data[1..20])
+            )));
         }
+        let protocol = PROTOCOL_NAME.to_vec();
 
         // Read reserved bytes
         let mut reserved = [0u8; 8];
@@ -168,8 +175,8 @@ mod tests {
         // Check protocol name
         assert_eq!(&bytes[1..20], b"BitTorrent protocol");
 
-        // Check reserved bytes are zero
-        assert_eq!(&bytes[20..28], &[0u8; 8]);
+        // Only the fast extension bit is reserved
+        assert_eq!(&bytes[20..28], &[0, 0, 0, 0, 0, 0, 0, 0x04]);
 
         // Check info hash
         assert_eq!(&bytes[28..48], &[0xABu8; 20]);
@@ -182,9 +189,10 @@ mod tests {
     fn test_extension_bits() {
         let mut handshake = Handshake::new([0u8; 20], [0u8; 20]);
 
-        // Initially no extensions
+        // Initially just the fast extension
         assert!(!handshake.supports_extension(0));
         assert!(!handshake.supports_extension(20));
+        assert!(handshake.supports_extension(FAST_EXTENSION_BIT));
 
         // Enable extension bit 20 (DHT)
         handshake.enable_extension(20);
@@ -195,4 +203,58 @@ mod tests {
         let parsed = Handshake::from_bytes(&bytes).unwrap();
         assert!(parsed.supports_extension(20));
     }
+
+    #[test]
+    fn test_anonymous_handshake_hides_client() {
+        let identity = crate::utils::PeerIdentity::generate();
+        let info_hash = [7u8; 20];
+
+        let normal = Handshake::new(info_hash, identity.peer_id(false)).to_bytes();
+        let anonymous = Handshake::new(info_hash, identity.peer_id(true)).to_bytes();
+
+        // Same protocol header, reserved bytes and info hash; only the peer ID differs
+        assert_eq!(normal[..48], anonymous[..48]);
+        assert_eq!(&normal[48..56], b"-SC0100-");
+        assert_ne!(&anonymous[48..56], b"-SC0100-");
+    }
+
+    #[test]
+    fn test_malformed_handshakes_rejected() {
+        use rand::{Rng, SeedableRng};
+        let valid = Handshake::new([3u8; 20], [4u8; 20]).to_bytes();
+        let is_violation = |data: &[u8]| matches!(Handshake::from_bytes(data), Err(Error::ProtocolViolation(_)));
+
+        // Truncated or padded
+        for len in 0..valid.len() {
+            assert!(is_violation(&valid[..len]), "cut to {}", len);
+        }
+        let mut longer = valid.clone();
+        longer.push(0);
+        assert!(is_violation(&longer));
+
+        // The right length with the wrong protocol: another length byte, a
+        // different name, or not BitTorrent at all
+        let mut wrong_length = valid.clone();
+        wrong_length[0] = 18;
+        assert!(is_violation(&wrong_length));
+        let mut wrong_name = valid.clone();
+        wrong_name[1..20].copy_from_slice(b"BitTorrent Protocol");
+        assert!(is_violation(&wrong_name));
+        let mut http = b"GET /announce HTTP/1.1\r\n".to_vec();
+        http.resize(HANDSHAKE_LENGTH, b' ');
+        assert!(is_violation(&http));
+
+        // Random bytes never panic and only parse with the real protocol header
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0x68);
+        for _ in 0..10_000 {
+            let mut data: Vec<u8> = (0..rng.gen_range(0..=80)).map(|_| rng.gen()).collect();
+            if data.len() == HANDSHAKE_LENGTH && rng.gen_bool(0.5) {
+                data[..20].copy_from_slice(&valid[..20]);
+            }
+            match Handshake::from_bytes(&data) {
+                Ok(handshake) => assert_eq!(handshake.to_bytes(), data),
+                Err(e) => assert!(matches!(e, Error::ProtocolViolation(_)), "{:?}", e),
+            }
+        }
+    }
 }
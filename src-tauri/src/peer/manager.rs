@@ -1,12 +1,22 @@
 /// Peer manager - handles multiple peer connections and download coordination
-use super::{PeerConnection, Message};
-use crate::piece::{Bitfield, BlockInfo, PieceManager};
+use super::choking::{self, ChokeCandidate, ChokeDecision, ChokeHistory, ChokeReason};
+use super::disconnect::{Direction, Disconnect, DisconnectHistory, DisconnectReason, Disconnection};
+use super::fast::{allowed_fast_set, ALLOWED_FAST_COUNT};
+use super::outbox::FLUSH_DELAY;
+use super::pacer::{DialPacer, DialPermit, DialQueue};
+use super::reaper::{self, IdleSnapshot, IdleWatch, REAP_INTERVAL};
+use super::{message, PeerConnection, Message, TrafficMeter, TrafficStats, Waste};
+use crate::piece::{Bitfield, BlockInfo, PeerKey, PieceManager};
 use crate::disk::DiskManager;
-use std::collections::HashMap;
+use crate::engine::metrics::{EngineMetrics, Gauge};
+use crate::transfer_log::{TransferEvent, TransferLog};
+use crate::utils::PeerIdentity;
+use std::collections::{HashMap, HashSet};
 use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
-use tokio::sync::{mpsc, oneshot, RwLock};
+use tokio::sync::{mpsc, oneshot, watch, RwLock};
 use tokio::time;
 use tokio_util::sync::CancellationToken;
 
@@ -28,8 +38,35 @@ const OPTIMISTIC_UNCHOKE_INTERVAL: Duration = Duration::from_secs(30);
 /// Number of peers to unchoke
 const NUM_UNCHOKED: usize = 4;
 
+/// A peer that sends no block for this long while we wait on requests is
+/// snubbing us and loses its upload slot
+const SNUB_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Disconnected peers remembered for reconnecting after a resume
+const MAX_RECENT_PEERS: usize = 50;
+
+/// Longest block we serve by default. Most clients ask for 16 KiB; some
+/// older ones for 32 KiB, which the spec lets us refuse but we needn't.
+pub const DEFAULT_MAX_REQUEST_LENGTH: usize = 128 * 1024;
+
+/// A peer whose session ended, kept so a resume can try it before new ones
+struct RecentPeer {
+    /// Last bitfield we saw from them (lets us send Interested right away)
+    bitfield: Option<Bitfield>,
+    /// When the session ended
+    disconnected_at: Instant,
+}
+
+type RecentPeers = Arc<RwLock<HashMap<SocketAddr, RecentPeer>>>;
+
+/// Source of `PeerSession::key`; never reused, so a reconnect from the same
+/// address can't be confused with the session before it
+static NEXT_PEER_KEY: AtomicU64 = AtomicU64::new(1);
+
 /// Peer session state
 struct PeerSession {
+    /// Identifies this session to the piece manager
+    key: PeerKey,
     /// Peer connection
     connection: PeerConnection,
     /// Last activity timestamp
@@ -38,6 +75,10 @@ struct PeerSession {
     pending_requests: HashMap<BlockInfo, Instant>,
     /// Peer's bitfield
     peer_bitfield: Option<Bitfield>,
+    /// Pieces the peer lets us request while it chokes us (fast extension)
+    allowed_fast_in: HashSet<u32>,
+    /// Pieces we serve to the peer even while choking it (fast extension)
+    allowed_fast_out: HashSet<u32>,
     /// Download statistics
     downloaded_bytes: u64,
     uploaded_bytes: u64,
@@ -49,27 +90,118 @@ struct PeerSession {
     last_downloaded_bytes: u64,
     /// Bytes uploaded at last stats update
     last_uploaded_bytes: u64,
+    /// Number of pieces we had when interest in this peer was last evaluated
+    interest_checked_pieces: usize,
+    /// We choked them and dropped interest for a pause
+    quiet_for_pause: bool,
+    /// When the peer last sent a block or unchoked us
+    last_block_at: Instant,
+    /// Bytes downloaded and uploaded at the last choking pass
+    choke_pass_downloaded: u64,
+    choke_pass_uploaded: u64,
+    /// Holds the optimistic unchoke slot
+    optimistic: bool,
+    /// Recent choking decisions about this peer
+    choke_history: ChokeHistory,
+    /// The torrent's debug transfer log
+    transfer_log: Arc<TransferLog>,
+    /// When the TCP connection was made
+    connected_at: Instant,
+    /// Longest block the peer may request from us
+    max_request_length: usize,
+    /// When the peer last requested a block
+    last_request_at: Option<Instant>,
+    /// When a block last went either way
+    last_transfer_at: Option<Instant>,
+    /// Cancelled when the reaper drops the session
+    reaped: CancellationToken,
 }
 
 impl PeerSession {
     fn new(connection: PeerConnection) -> Self {
         Self {
+            key: NEXT_PEER_KEY.fetch_add(1, Ordering::Relaxed),
             connection,
             last_activity: Instant::now(),
             pending_requests: HashMap::new(),
             peer_bitfield: None,
+            allowed_fast_in: HashSet::new(),
+            allowed_fast_out: HashSet::new(),
             downloaded_bytes: 0,
             uploaded_bytes: 0,
             download_speed: 0.0,
             upload_speed: 0.0,
             last_downloaded_bytes: 0,
             last_uploaded_bytes: 0,
+            interest_checked_pieces: 0,
+            quiet_for_pause: false,
+            last_block_at: Instant::now(),
+            choke_pass_downloaded: 0,
+            choke_pass_uploaded: 0,
+            optimistic: false,
+            choke_history: ChokeHistory::default(),
+            transfer_log: Arc::new(TransferLog::default()),
+            connected_at: Instant::now(),
+            max_request_length: DEFAULT_MAX_REQUEST_LENGTH,
+            last_request_at: None,
+            last_transfer_at: None,
+            reaped: CancellationToken::new(),
+        }
+    }
+
+    /// What the idle reaper goes by
+    fn idle_snapshot(&self, addr: SocketAddr) -> IdleSnapshot {
+        IdleSnapshot {
+            interested: self.connection.peer_interested,
+            remote_seed: self.peer_bitfield.as_ref().is_some_and(|bf| bf.is_complete()),
+            exchanged: self.downloaded_bytes + self.uploaded_bytes,
+            last_request_at: self.last_request_at,
+            last_block_at: self.last_transfer_at,
+            pending_requests: self.pending_requests.len(),
+            ..IdleSnapshot::new(addr, self.connected_at)
+        }
+    }
+
+    /// The disconnect history entry for this session ending with `disconnect`
+    fn disconnection(&self, addr: SocketAddr, disconnect: Disconnect) -> Disconnection {
+        Disconnection {
+            addr,
+            client: parse_peer_id(self.connection.peer_id),
+            direction: Direction::Outbound,
+            connected_ms: self.connected_at.elapsed().as_millis() as u64,
+            downloaded: self.downloaded_bytes,
+            uploaded: self.uploaded_bytes,
+            reason: disconnect.reason,
+            detail: disconnect.detail,
+            at: chrono::Utc::now().timestamp(),
         }
     }
 
     /// Check if we can send more requests to this peer
     fn can_request(&self) -> bool {
-        !self.connection.peer_choking && self.pending_requests.len() < MAX_PENDING_REQUESTS
+        let may_request = !self.connection.peer_choking || !self.allowed_fast_in.is_empty();
+        may_request && self.pending_requests.len() < MAX_PENDING_REQUESTS
+    }
+
+    /// What we may request from the peer: all it has, or only its allowed
+    /// fast pieces while it chokes us
+    fn requestable_pieces(&self) -> Option<Bitfield> {
+        let peer_bitfield = self.peer_bitfield.as_ref()?;
+        if !self.connection.peer_choking {
+            return Some(peer_bitfield.clone());
+        }
+        let mut allowed = Bitfield::new(peer_bitfield.num_pieces());
+        for &piece in &self.allowed_fast_in {
+            if peer_bitfield.has_piece(piece as usize) {
+                allowed.set_piece(piece as usize);
+            }
+        }
+        Some(allowed)
+    }
+
+    /// Whether the peer sits on our requests without sending anything
+    fn is_snubbing(&self) -> bool {
+        !self.pending_requests.is_empty() && self.last_block_at.elapsed() > SNUB_TIMEOUT
     }
 
     /// Mark a request as pending
@@ -82,6 +214,22 @@ impl PeerSession {
         self.pending_requests.remove(block).is_some()
     }
 
+    /// Remove the request a block of `length` bytes at `begin` answers: the
+    /// one at that offset, whatever length it asked for, along with later
+    /// requests of the piece the block covers whole. Returns the first.
+    fn take_answered_request(&mut self, piece_index: usize, begin: usize, length: usize) -> Option<BlockInfo> {
+        let answered = *self
+            .pending_requests
+            .keys()
+            .find(|block| block.piece_index == piece_index && block.offset == begin)?;
+        self.pending_requests.remove(&answered);
+        let end = begin + length;
+        self.pending_requests.retain(|block, _| {
+            !(block.piece_index == piece_index && block.offset > begin && block.offset + block.length <= end)
+        });
+        Some(answered)
+    }
+
     /// Get timed-out requests
     fn get_timed_out_requests(&self) -> Vec<BlockInfo> {
         let now = Instant::now();
@@ -96,18 +244,42 @@ impl PeerSession {
     fn needs_keep_alive(&self) -> bool {
         Instant::now().duration_since(self.last_activity) > KEEP_ALIVE_INTERVAL
     }
+
+    /// Tell the peer we're pausing instead of just going silent
+    async fn go_quiet(&mut self) -> crate::error::Result<()> {
+        self.quiet_for_pause = true;
+        if !self.connection.am_choking {
+            self.connection.send_choke().await?;
+        }
+        if self.connection.am_interested {
+            self.connection.send_not_interested().await?;
+        }
+        Ok(())
+    }
+
+    /// Drop what went stale during a pause. Returns the requests that were
+    /// outstanding so their blocks can be handed out again.
+    fn reset_after_pause(&mut self) -> Vec<BlockInfo> {
+        self.quiet_for_pause = false;
+        self.last_activity = Instant::now();
+        self.pending_requests.drain().map(|(block, _)| block).collect()
+    }
 }
 
 /// Command to the peer manager
 pub enum PeerManagerCommand {
     /// Add a peer address to connect to
     AddPeer(SocketAddr),
+    /// Dial a peer before every queued one (peers added by hand)
+    DialNow(SocketAddr),
     /// Remove a peer
     RemovePeer(SocketAddr),
     /// Get peer statistics
     GetStats(oneshot::Sender<PeerManagerStats>),
     /// Get peer list for UI
     GetPeerList(oneshot::Sender<Vec<crate::peer::PeerInfo>>),
+    /// Get a peer's recent choking decisions (None if not connected)
+    GetChokeHistory(SocketAddr, oneshot::Sender<Option<Vec<ChokeDecision>>>),
     /// Broadcast that we have a piece
     BroadcastHave(usize),
     /// Pause peer manager (stop requesting blocks)
@@ -120,18 +292,24 @@ pub enum PeerManagerCommand {
 #[derive(Debug, Clone)]
 pub struct PeerManagerStats {
     pub connected_peers: usize,
+    /// Connected peers whose bitfield is complete
+    pub connected_seeds: usize,
     pub total_downloaded: u64,
     pub total_uploaded: u64,
     pub download_speed: f64,
     pub upload_speed: f64,
+    /// Wire traffic for this torrent since the manager started
+    pub traffic: TrafficStats,
 }
 
 /// Manages all peer connections for a torrent
 pub struct PeerManager {
     /// Torrent info hash
     info_hash: [u8; 20],
-    /// Our peer ID
-    peer_id: [u8; 20],
+    /// Our peer IDs
+    identity: PeerIdentity,
+    /// Whether new handshakes use the anonymous peer ID
+    anonymous_mode: watch::Receiver<bool>,
     /// Active peer sessions
     sessions: Arc<RwLock<HashMap<SocketAddr, PeerSession>>>,
     /// Piece manager (shared with engine)
@@ -146,15 +324,41 @@ pub struct PeerManager {
     stats: Arc<RwLock<PeerManagerStats>>,
     /// Cancellation token for cooperative shutdown
     cancel_token: CancellationToken,
-    /// Paused state
-    paused: bool,
+    /// Paused state (shared with the per-peer tasks)
+    paused: Arc<AtomicBool>,
+    /// Peers with a live session
+    connected: Arc<RwLock<HashSet<SocketAddr>>>,
+    /// Peers whose session ended, reconnected first on resume
+    recent_peers: RecentPeers,
+    /// Told about each peer's first received block
+    productive_tx: Option<mpsc::UnboundedSender<SocketAddr>>,
+    /// Told about each peer dropped for breaking the wire protocol
+    violation_tx: Option<mpsc::UnboundedSender<SocketAddr>>,
+    /// Longest message accepted from peers, before the bitfield allowance
+    max_message_length: u32,
+    /// Longest block served to peers
+    max_request_length: usize,
+    /// Torrent-wide wire traffic (outlives individual peer sessions)
+    traffic: Arc<TrafficMeter>,
+    /// Peers waiting for the global dial pacer
+    pending_dials: DialQueue,
+    /// The torrent's debug transfer log
+    transfer_log: Arc<TransferLog>,
+    /// Why the torrent's connections ended (shared with the engine)
+    disconnects: Arc<DisconnectHistory>,
+    /// The engine's concurrency metrics
+    metrics: Arc<EngineMetrics>,
+    /// Sessions as the idle reaper sees them
+    idle: Arc<IdleWatch>,
+    /// Connection cap the reaper makes room under; zero = none
+    max_peers: usize,
 }
 
 impl PeerManager {
     /// Create a new peer manager
     pub fn new(
         info_hash: [u8; 20],
-        peer_id: [u8; 20],
+        identity: PeerIdentity,
         piece_manager: Arc<RwLock<PieceManager>>,
         disk_manager: Arc<RwLock<DiskManager>>,
         cancel_token: CancellationToken,
@@ -162,15 +366,18 @@ impl PeerManager {
         let (command_tx, command_rx) = mpsc::channel(100);
         let stats = PeerManagerStats {
             connected_peers: 0,
+            connected_seeds: 0,
             total_downloaded: 0,
             total_uploaded: 0,
             download_speed: 0.0,
             upload_speed: 0.0,
+            traffic: TrafficStats::default(),
         };
 
         Self {
             info_hash,
-            peer_id,
+            identity,
+            anonymous_mode: watch::channel(false).1,
             sessions: Arc::new(RwLock::new(HashMap::new())),
             piece_manager,
             disk_manager,
@@ -178,10 +385,74 @@ impl PeerManager {
             command_tx,
             stats: Arc::new(RwLock::new(stats)),
             cancel_token,
-            paused: false,
+            paused: Arc::new(AtomicBool::new(false)),
+            connected: Arc::new(RwLock::new(HashSet::new())),
+            recent_peers: Arc::new(RwLock::new(HashMap::new())),
+            productive_tx: None,
+            violation_tx: None,
+            max_message_length: message::DEFAULT_MAX_MESSAGE_LENGTH,
+            max_request_length: DEFAULT_MAX_REQUEST_LENGTH,
+            traffic: Arc::new(TrafficMeter::new()),
+            pending_dials: DialQueue::new(Arc::new(DialPacer::default())),
+            transfer_log: Arc::new(TransferLog::default()),
+            disconnects: Arc::new(DisconnectHistory::default()),
+            metrics: Arc::new(EngineMetrics::default()),
+            idle: Arc::new(IdleWatch::default()),
+            max_peers: 0,
         }
     }
 
+    /// Pace outbound connections with the app-wide pacer (see `AppState::dial_pacer`)
+    pub fn set_dial_pacer(&mut self, pacer: Arc<DialPacer>) {
+        self.pending_dials = DialQueue::new(pacer);
+    }
+
+    /// Record into the torrent's debug transfer log
+    pub fn set_transfer_log(&mut self, transfer_log: Arc<TransferLog>) {
+        self.transfer_log = transfer_log;
+    }
+
+    /// Record ended connections into the torrent's history (see `AppState::disconnect_histories`)
+    pub fn set_disconnect_history(&mut self, disconnects: Arc<DisconnectHistory>) {
+        self.disconnects = disconnects;
+    }
+
+    /// Follow the app-wide anonymous mode (see `AppState::anonymous_mode`)
+    pub fn set_anonymous_mode(&mut self, anonymous_mode: watch::Receiver<bool>) {
+        self.anonymous_mode = anonymous_mode;
+    }
+
+    /// Report into the engine's metrics (see `EngineHandle::metrics`)
+    pub fn set_metrics(&mut self, metrics: Arc<EngineMetrics>) {
+        self.metrics = metrics;
+    }
+
+    /// Report the address of every peer that sends us its first block
+    pub fn set_productive_peer_sink(&mut self, tx: mpsc::UnboundedSender<SocketAddr>) {
+        self.productive_tx = Some(tx);
+    }
+
+    /// Report the address of every peer disconnected for a protocol violation
+    pub fn set_protocol_violation_sink(&mut self, tx: mpsc::UnboundedSender<SocketAddr>) {
+        self.violation_tx = Some(tx);
+    }
+
+    /// Accept peer messages up to `length` bytes (always enough for a bitfield)
+    pub fn set_max_message_length(&mut self, length: u32) {
+        self.max_message_length = length;
+    }
+
+    /// Serve blocks of up to `length` bytes; longer requests are refused
+    pub fn set_max_request_length(&mut self, length: usize) {
+        self.max_request_length = length;
+    }
+
+    /// Once seeding with `max_peers` connections, drop idle ones to make
+    /// room (see `reaper`)
+    pub fn set_max_peers(&mut self, max_peers: usize) {
+        self.max_peers = max_peers;
+    }
+
     /// Get command sender
     pub fn command_sender(&self) -> mpsc::Sender<PeerManagerCommand> {
         self.command_tx.clone()
@@ -193,8 +464,14 @@ impl PeerManager {
         let mut keep_alive_interval = time::interval(Duration::from_secs(30));
         let mut choking_interval = time::interval(CHOKING_INTERVAL);
         let mut optimistic_interval = time::interval(OPTIMISTIC_UNCHOKE_INTERVAL);
+        let mut reap_interval = time::interval(REAP_INTERVAL);
+        // When queued Haves are due to be written
+        let mut flush_at: Option<time::Instant> = None;
+        let mut wakes = crate::wake::subscribe();
 
         loop {
+            let pacer = self.pending_dials.pacer().clone();
+
             tokio::select! {
                 biased;
 
@@ -208,12 +485,20 @@ impl PeerManager {
                 Some(cmd) = self.command_rx.recv() => {
                     match cmd {
                         PeerManagerCommand::AddPeer(addr) => {
-                            if !self.paused {
-                                self.connect_to_peer(addr).await;
+                            if !self.is_paused() {
+                                self.pending_dials.push(addr, None);
+                            }
+                        }
+                        PeerManagerCommand::DialNow(addr) => {
+                            if !self.is_paused() {
+                                self.pending_dials.push_front(addr);
                             }
                         }
                         PeerManagerCommand::RemovePeer(addr) => {
-                            self.sessions.write().await.remove(&addr);
+                            if let Some(session) = self.sessions.write().await.remove(&addr) {
+                                let removed = Disconnect::new(DisconnectReason::Banned, "Removed");
+                                self.disconnects.record(session.disconnection(addr, removed));
+                            }
                         }
                         PeerManagerCommand::GetStats(tx) => {
                             let stats = self.stats.read().await.clone();
@@ -223,26 +508,74 @@ impl PeerManager {
                             let peer_list = self.get_peer_list().await;
                             let _ = tx.send(peer_list);
                         }
+                        PeerManagerCommand::GetChokeHistory(addr, tx) => {
+                            let history = self.sessions.read().await.get(&addr).map(|s| s.choke_history.to_vec());
+                            let _ = tx.send(history);
+                        }
                         PeerManagerCommand::BroadcastHave(piece_index) => {
                             // Can still broadcast haves while paused? Probably yes, to keep state in sync
-                            self.broadcast_have(piece_index).await;
+                            if self.broadcast_have(piece_index).await && flush_at.is_none() {
+                                flush_at = Some(time::Instant::now() + FLUSH_DELAY);
+                            }
                         }
                         PeerManagerCommand::Pause => {
                             tracing::info!("PeerManager paused");
-                            self.paused = true;
-                            // Optionally disconnect peers or just stop requesting?
-                            // For now, let's just stop requesting and processing new connections
+                            self.paused.store(true, Ordering::Relaxed);
+                            if !self.pending_dials.is_empty() {
+                                tracing::debug!("Dropping {} queued dials", self.pending_dials.len());
+                                self.pending_dials.clear();
+                            }
+                            // Peers busy in their receive loop catch up on their next message
+                            let addrs: Vec<SocketAddr> = self.sessions.read().await.keys().copied().collect();
+                            for addr in addrs {
+                                if let Err(e) = Self::sync_pause_state(addr, &self.sessions, &self.piece_manager, true).await {
+                                    tracing::debug!("Could not quiet {} for pause: {}", addr, e);
+                                }
+                            }
                         }
                         PeerManagerCommand::Resume => {
                             tracing::info!("PeerManager resumed");
-                            self.paused = false;
+                            self.paused.store(false, Ordering::Relaxed);
+                            let addrs: Vec<SocketAddr> = self.sessions.read().await.keys().copied().collect();
+                            for addr in addrs {
+                                if let Err(e) = Self::sync_pause_state(addr, &self.sessions, &self.piece_manager, false).await {
+                                    tracing::debug!("Could not resume {}: {}", addr, e);
+                                }
+                            }
+                            self.reconnect_recent_peers().await;
                         }
                     }
                 }
 
+                // Dial the next queued peer once the pacer allows
+                permit = pacer.acquire(), if !self.pending_dials.is_empty() && !self.is_paused() => {
+                    if let Some((addr, known_bitfield)) = self.pending_dials.pop() {
+                        self.connect_to_peer(addr, known_bitfield, permit).await;
+                    }
+                }
+
+                // Write the Haves batched up since the first one was queued
+                _ = time::sleep_until(flush_at.unwrap_or_else(time::Instant::now)), if flush_at.is_some() => {
+                    flush_at = None;
+                    self.flush_outboxes().await;
+                }
+
+                // The machine slept: don't count the sleep against peers
+                Ok(()) = wakes.changed() => {
+                    let wake = *wakes.borrow_and_update();
+                    if let Some(wake) = wake {
+                        self.reconcile_after_wake(wake).await;
+                    }
+                }
+
                 // Periodic tasks
                 _ = tick_interval.tick() => {
-                    if !self.paused {
+                    // After a sleep, reconcile before anything times out
+                    crate::wake::check();
+                    if wakes.has_changed().unwrap_or(false) {
+                        continue;
+                    }
+                    if !self.is_paused() {
                         self.handle_pending_requests().await;
                     }
                     self.update_stats().await; // Always update stats
@@ -255,64 +588,164 @@ impl PeerManager {
 
                 // Choking algorithm
                 _ = choking_interval.tick() => {
-                    if !self.paused {
+                    if !self.is_paused() {
                         self.update_choking().await;
                     }
                 }
 
                 // Optimistic unchoke
                 _ = optimistic_interval.tick() => {
-                    if !self.paused {
+                    if !self.is_paused() {
                         self.optimistic_unchoke().await;
                     }
                 }
+
+                // Make room for leechers while seeding
+                _ = reap_interval.tick() => {
+                    if !self.is_paused() {
+                        self.reap_idle().await;
+                    }
+                }
             }
         }
 
         // Clean up: disconnect all peers
         let mut sessions = self.sessions.write().await;
         tracing::info!("PeerManager shutting down, disconnecting {} peers", sessions.len());
-        sessions.clear();
+        let mut pm = self.piece_manager.write().await;
+        for (addr, session) in sessions.drain() {
+            pm.remove_peer(session.key);
+            let stopped = Disconnect::new(DisconnectReason::Cancelled, "Peer manager shut down");
+            self.disconnects.record(session.disconnection(addr, stopped));
+        }
+    }
+
+    fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+
+    /// Reconnect peers from before the pause, most recently seen first
+    async fn reconnect_recent_peers(&mut self) {
+        let mut recent: Vec<(SocketAddr, RecentPeer)> = self.recent_peers.write().await.drain().collect();
+        recent.sort_by(|a, b| b.1.disconnected_at.cmp(&a.1.disconnected_at));
+        if !recent.is_empty() {
+            tracing::info!("Reconnecting {} peers from before the pause", recent.len());
+        }
+        for (addr, peer) in recent {
+            self.pending_dials.push(addr, peer.bitfield);
+        }
+    }
+
+    /// Pass a peer on to the violation sink if `error` is its fault
+    fn report_violation(
+        violation_tx: &Option<mpsc::UnboundedSender<SocketAddr>>,
+        addr: SocketAddr,
+        error: &crate::error::Error,
+    ) {
+        if let (crate::error::Error::ProtocolViolation(_), Some(tx)) = (error, violation_tx) {
+            let _ = tx.send(addr);
+        }
+    }
+
+    /// Remember a peer whose session just ended
+    async fn remember_peer(recent_peers: &RecentPeers, addr: SocketAddr, bitfield: Option<Bitfield>) {
+        let mut recent = recent_peers.write().await;
+        let entry = recent.entry(addr).or_insert(RecentPeer {
+            bitfield: None,
+            disconnected_at: Instant::now(),
+        });
+        entry.disconnected_at = Instant::now();
+        if bitfield.is_some() {
+            entry.bitfield = bitfield;
+        }
+        if recent.len() > MAX_RECENT_PEERS {
+            if let Some(oldest) = recent.iter().min_by_key(|(_, p)| p.disconnected_at).map(|(a, _)| *a) {
+                recent.remove(&oldest);
+            }
+        }
     }
 
     /// Connect to a peer and start download loop
-    async fn connect_to_peer(&self, addr: SocketAddr) {
+    ///
+    /// `known_bitfield` is what the peer had when we last saw it; it lets us
+    /// declare interest before their fresh bitfield arrives. `permit` is
+    /// released once the connection is no longer half-open, and becomes a
+    /// connection slot if the handshake succeeds.
+    async fn connect_to_peer(&self, addr: SocketAddr, known_bitfield: Option<Bitfield>, permit: DialPermit) {
+        if self.connected.read().await.contains(&addr) {
+            tracing::debug!("Already connected to {}", addr);
+            return;
+        }
         tracing::info!("Connecting to peer: {}", addr);
+        let log = self.transfer_log.clone();
+        log.record(|| TransferEvent::ConnectAttempt { peer: addr });
 
         // Connect
         let connection = match PeerConnection::connect(addr).await {
             Ok(conn) => conn,
             Err(e) => {
                 tracing::warn!("Failed to connect to {}: {}", addr, e);
+                log.record(|| TransferEvent::ConnectFailed { peer: addr, error: e.to_string() });
                 return;
             }
         };
 
         let mut session = PeerSession::new(connection);
+        let key = session.key;
+        session.connection.set_traffic_meter(self.traffic.clone());
+        let num_pieces = self.piece_manager.read().await.our_bitfield().num_pieces();
+        session
+            .connection
+            .set_max_message_length(message::max_message_length(self.max_message_length, num_pieces));
+        session.transfer_log = log.clone();
+        session.max_request_length = self.max_request_length;
+        session.reaped = self.idle.track(key, session.idle_snapshot(addr));
 
         // Perform handshake
+        let peer_id = self.identity.peer_id(*self.anonymous_mode.borrow());
         if let Err(e) = session
             .connection
-            .handshake(self.info_hash, self.peer_id)
+            .handshake(self.info_hash, peer_id)
             .await
         {
             tracing::warn!("Handshake failed with {}: {}", addr, e);
+            log.record(|| TransferEvent::HandshakeFailed { peer: addr, error: e.to_string() });
+            Self::report_violation(&self.violation_tx, addr, &e);
+            self.disconnects.record(session.disconnection(addr, Disconnect::handshake(&e)));
             return;
         }
+        let slot = permit.establish();
+        log.record(|| TransferEvent::Connected { peer: addr });
 
         tracing::info!("Handshake successful with {}", addr);
 
         // CRITICAL FIX: Send our bitfield immediately after handshake
         // This tells the peer what pieces we have
-        let our_bitfield = {
+        let (have_message, num_pieces) = {
             let pm = self.piece_manager.read().await;
-            pm.our_bitfield().as_bytes().to_vec()
+            let ours = pm.our_bitfield();
+            let message = match (session.connection.fast_extension, ours.count_pieces()) {
+                (true, count) if count == ours.num_pieces() => Message::HaveAll,
+                (true, 0) => Message::HaveNone,
+                _ => Message::Bitfield { bitfield: ours.as_bytes().to_vec() },
+            };
+            (message, ours.num_pieces())
         };
-        
-        if let Err(e) = session.connection.send_message(&Message::Bitfield {
-            bitfield: our_bitfield
-        }).await {
+
+        // Fast peers also learn which pieces they may fetch while choked
+        session.connection.queue_message(have_message);
+        if session.connection.fast_extension {
+            let allowed = allowed_fast_set(addr.ip(), &self.info_hash, num_pieces, ALLOWED_FAST_COUNT);
+            for &piece_index in &allowed {
+                session.connection.queue_message(Message::AllowedFast { piece_index });
+            }
+            session.allowed_fast_out = allowed.into_iter().collect();
+        }
+
+        if let Err(e) = session.connection.flush_outbox().await {
             tracing::warn!("Failed to send bitfield to {}: {}", addr, e);
+            let failed = Disconnect::new(DisconnectReason::RemoteClosed, format!("Failed to send bitfield: {}", e));
+            self.disconnects.record(session.disconnection(addr, failed));
             return;
         }
         
@@ -320,37 +753,89 @@ impl PeerManager {
 
         // Store session
         let sessions = self.sessions.clone();
+        let has_known_bitfield = known_bitfield.is_some();
+        session.peer_bitfield = known_bitfield;
         sessions.write().await.insert(addr, session);
+        self.connected.write().await.insert(addr);
+        self.recent_peers.write().await.remove(&addr);
+
+        if has_known_bitfield {
+            if let Err(e) = Self::update_interest(addr, sessions.clone(), self.piece_manager.clone(), true).await {
+                tracing::debug!("Could not declare interest to {}: {}", addr, e);
+            }
+        }
 
         // Spawn peer handler
-        let sessions_clone = sessions.clone();
         let piece_manager = self.piece_manager.clone();
         let disk_manager = self.disk_manager.clone();
-        let peer_id_str = format!("{:?}", addr); // Use for tracking
+        let paused = self.paused.clone();
+        let connected = self.connected.clone();
+        let recent_peers = self.recent_peers.clone();
+        let productive_tx = self.productive_tx.clone();
+        let violation_tx = self.violation_tx.clone();
+        let cancel = self.cancel_token.clone();
+        let disconnects = self.disconnects.clone();
+        let metrics = self.metrics.clone();
+        let idle = self.idle.clone();
+        let task = metrics.enter(Gauge::PeerTasks);
 
         tokio::spawn(async move {
-            if let Err(e) = Self::handle_peer(
+            let _slot = slot;
+            let _task = task;
+            let result = Self::handle_peer(
                 addr,
-                sessions_clone,
-                piece_manager,
+                sessions.clone(),
+                piece_manager.clone(),
                 disk_manager,
-                peer_id_str,
+                key,
+                paused,
+                cancel,
+                productive_tx,
+                violation_tx,
+                log.clone(),
+                metrics,
+                idle.clone(),
             )
-            .await
-            {
-                tracing::error!("Peer handler error for {}: {}", addr, e);
+            .await;
+            let disconnect = result
+                .err()
+                .unwrap_or_else(|| Disconnect::new(DisconnectReason::RemoteClosed, "closed"));
+            if !matches!(disconnect.reason, DisconnectReason::Cancelled | DisconnectReason::ReapedIdle) {
+                tracing::error!("Peer handler error for {}: {}", addr, disconnect);
             }
+            log.record(|| TransferEvent::Disconnected { peer: addr, reason: disconnect.to_string() });
+
+            // However the session ended, take back its share of availability.
+            // A session already gone was ended (and recorded) by the manager.
+            let session = sessions.write().await.remove(&addr);
+            piece_manager.write().await.remove_peer(key);
+            connected.write().await.remove(&addr);
+            idle.forget(addr, key);
+            if let Some(session) = &session {
+                disconnects.record(session.disconnection(addr, disconnect));
+            }
+            Self::remember_peer(&recent_peers, addr, session.and_then(|s| s.peer_bitfield)).await;
         });
     }
 
-    /// Handle communication with a single peer
+    /// Handle communication with a single peer until the session ends, and
+    /// say why it did. Unless the session vanished from `sessions`, it is left
+    /// there for the cleanup in `connect_to_peer`.
+    #[allow(clippy::too_many_arguments)]
     async fn handle_peer(
         addr: SocketAddr,
         sessions: Arc<RwLock<HashMap<SocketAddr, PeerSession>>>,
         piece_manager: Arc<RwLock<PieceManager>>,
         disk_manager: Arc<RwLock<DiskManager>>,
-        peer_id: String,
-    ) -> Result<(), String> {
+        key: PeerKey,
+        paused: Arc<AtomicBool>,
+        cancel: CancellationToken,
+        productive_tx: Option<mpsc::UnboundedSender<SocketAddr>>,
+        violation_tx: Option<mpsc::UnboundedSender<SocketAddr>>,
+        log: Arc<TransferLog>,
+        metrics: Arc<EngineMetrics>,
+        idle: Arc<IdleWatch>,
+    ) -> Result<(), Disconnect> {
         loop {
             // CRITICAL FIX: Extract connection from sessions to avoid holding lock during I/O
             // We temporarily remove the session, do I/O, then re-insert it
@@ -359,31 +844,71 @@ impl PeerManager {
             let mut session = {
                 let mut sessions_guard = sessions.write().await;
                 match sessions_guard.remove(&addr) {
-                    None => return Err("Session not found".to_string()),
+                    None => return Err("Session not found".to_string().into()),
                     Some(s) => s,
                 }
             };
             // Lock is now released - other peers can proceed
-            
+            idle.update(key, session.idle_snapshot(addr));
+
             // Step 2: Do network I/O without holding any lock
-            let message = match session.connection.recv_message().await {
+            let received = tokio::select! {
+                biased;
+                _ = cancel.cancelled() => {
+                    Err(Disconnect::new(DisconnectReason::Cancelled, "Torrent paused or stopped"))
+                }
+                _ = session.reaped.cancelled() => {
+                    Err(Disconnect::new(DisconnectReason::ReapedIdle, "Idle while seeding"))
+                }
+                received = session.connection.recv_message() => received.map_err(|e| {
+                    Self::report_violation(&violation_tx, addr, &e);
+                    Disconnect::wire(&e)
+                }),
+            };
+            let message = match received {
                 Ok(msg) => {
                     session.last_activity = Instant::now();
                     msg
                 },
-                Err(e) => {
-                    // Don't re-insert session on error - just exit
-                    return Err(format!("Failed to receive message: {}", e));
+                Err(disconnect) => {
+                    // Put it back for the cleanup in connect_to_peer
+                    sessions.write().await.insert(addr, session);
+                    return Err(disconnect);
                 }
             };
             
+            // Haves queued for this peer while it was out of the map
+            if session.connection.outbox_due(Instant::now()) {
+                if let Err(e) = session.connection.flush_outbox().await {
+                    sessions.write().await.insert(addr, session);
+                    return Err(format!("Failed to send queued messages: {}", e).into());
+                }
+            }
+
             // Step 3: Re-insert session before processing message
+            let fast_extension = session.connection.fast_extension;
             {
                 let mut sessions_guard = sessions.write().await;
                 sessions_guard.insert(addr, session);
             }
             // Lock released again
 
+            // Catch up with a pause or resume that happened while we waited
+            let is_paused = paused.load(Ordering::Relaxed);
+            Self::sync_pause_state(addr, &sessions, &piece_manager, is_paused).await?;
+
+            // Pieces verified through other peers may have made this one uninteresting
+            if !is_paused {
+                Self::update_interest(addr, sessions.clone(), piece_manager.clone(), false).await?;
+            }
+
+            if message.is_fast_extension() && !fast_extension {
+                return Err(Disconnect::new(
+                    DisconnectReason::ProtocolError,
+                    format!("Peer sent {:?} without the fast extension", message),
+                ));
+            }
+
             // Step 4: Handle message (may need to update session state)
             match message {
                 Message::KeepAlive => {
@@ -392,6 +917,7 @@ impl PeerManager {
 
                 Message::Choke => {
                     tracing::debug!("Choked by {}", addr);
+                    log.record(|| TransferEvent::Choked { peer: addr });
                     let mut sessions_guard = sessions.write().await;
                     if let Some(session) = sessions_guard.get_mut(&addr) {
                         session.connection.peer_choking = true;
@@ -400,23 +926,41 @@ impl PeerManager {
 
                 Message::Unchoke => {
                     tracing::info!("Unchoked by {}", addr);
+                    log.record(|| TransferEvent::Unchoked { peer: addr });
                     {
                         let mut sessions_guard = sessions.write().await;
                         if let Some(session) = sessions_guard.get_mut(&addr) {
                             session.connection.peer_choking = false;
+                            session.last_block_at = Instant::now();
                         }
                     }
 
                     // Start requesting pieces
-                    Self::request_pieces(addr, sessions.clone(), piece_manager.clone(), &peer_id)
-                        .await?;
+                    if !is_paused {
+                        Self::request_pieces(addr, sessions.clone(), piece_manager.clone())
+                            .await?;
+                    }
                     continue;
                 }
 
                 Message::Interested => {
                     let mut sessions_guard = sessions.write().await;
+                    let unchoked = sessions_guard.values().filter(|s| !s.connection.am_choking).count();
                     if let Some(session) = sessions_guard.get_mut(&addr) {
                         session.connection.peer_interested = true;
+                        // The choking pass only sees sessions between messages, and
+                        // a peer waiting for its unchoke sends none: a free slot is
+                        // handed out now
+                        if session.connection.am_choking && unchoked < NUM_UNCHOKED && !is_paused {
+                            session.choke_history.record(ChokeDecision {
+                                at: chrono::Utc::now().timestamp(),
+                                choked: false,
+                                reason: ChokeReason::TopRate { rate: 0, cutoff: 0 },
+                            });
+                            if let Err(e) = session.connection.send_unchoke().await {
+                                return Err(format!("Failed to send unchoke: {}", e).into());
+                            }
+                        }
                     }
                 }
 
@@ -429,52 +973,51 @@ impl PeerManager {
 
                 Message::Have { piece_index } => {
                     tracing::debug!("Peer {} has piece {}", addr, piece_index);
-                    piece_manager.write().await.peer_has_piece(piece_index as usize);
+                    piece_manager.write().await.peer_has_piece(key, piece_index as usize);
 
-                    let mut sessions_guard = sessions.write().await;
-                    if let Some(session) = sessions_guard.get_mut(&addr) {
-                        if let Some(ref mut bitfield) = session.peer_bitfield {
-                            bitfield.set_piece(piece_index as usize);
+                    {
+                        let mut sessions_guard = sessions.write().await;
+                        if let Some(session) = sessions_guard.get_mut(&addr) {
+                            if let Some(ref mut bitfield) = session.peer_bitfield {
+                                bitfield.set_piece(piece_index as usize);
+                            }
+                            session.connection.retract_have(piece_index);
                         }
                     }
+
+                    // A previously uninteresting peer may now have something we need
+                    let wanted = piece_manager.read().await.wants_piece(piece_index as usize);
+                    if wanted && !is_paused {
+                        Self::update_interest(addr, sessions.clone(), piece_manager.clone(), true)
+                            .await?;
+                    }
                 }
 
-                Message::Bitfield { bitfield } => {
-                    tracing::debug!("Received bitfield from {} ({} bytes)", addr, bitfield.len());
-                    
+                message @ (Message::Bitfield { .. } | Message::HaveAll | Message::HaveNone) => {
                     let num_pieces = piece_manager.read().await.our_bitfield().num_pieces();
-                    let peer_bf = Bitfield::from_bytes(bitfield, num_pieces);
+                    let peer_bf = match message {
+                        Message::Bitfield { bitfield } => {
+                            tracing::debug!("Received bitfield from {} ({} bytes)", addr, bitfield.len());
+                            Bitfield::from_bytes(bitfield, num_pieces)
+                        }
+                        Message::HaveAll => Bitfield::complete(num_pieces),
+                        _ => Bitfield::new(num_pieces),
+                    };
                     
                     // Add peer to piece manager
-                    piece_manager.write().await.add_peer(peer_id.clone(), &peer_bf);
-                    
-                    // Send interested if they have pieces we need
-                    let our_bf = piece_manager.read().await.our_bitfield().clone();
-                    let pieces_we_need = our_bf.pieces_to_request(&peer_bf);
+                    piece_manager.write().await.add_peer(key, &peer_bf);
                     
-                    // Update session and send interested message
-                    let send_interested = !pieces_we_need.is_empty();
                     {
                         let mut sessions_guard = sessions.write().await;
                         if let Some(session) = sessions_guard.get_mut(&addr) {
-                            session.peer_bitfield = Some(peer_bf.clone());
+                            session.peer_bitfield = Some(peer_bf);
                         }
                     }
-                    
-                    if send_interested {
-                        tracing::info!("Peer {} has {} pieces we need, sending interested", addr, pieces_we_need.len());
-                        
-                        // Extract connection again to send message
-                        let mut session = sessions.write().await.remove(&addr)
-                            .ok_or_else(|| "Session not found".to_string())?;
-                        
-                        if let Err(e) = session.connection.send_interested().await {
-                            return Err(format!("Failed to send interested: {}", e));
-                        }
-                        
-                        sessions.write().await.insert(addr, session);
-                    } else {
-                        tracing::debug!("Peer {} has no pieces we need", addr);
+
+                    // Send interested if they have pieces we need
+                    if !is_paused {
+                        Self::update_interest(addr, sessions.clone(), piece_manager.clone(), true)
+                            .await?;
                     }
                 }
 
@@ -484,16 +1027,33 @@ impl PeerManager {
                         addr, index, begin, length
                     );
 
-                    // Check if we're choking this peer
-                    let am_choking = {
-                        let sessions_guard = sessions.read().await;
-                        sessions_guard.get(&addr)
-                            .map(|s| s.connection.am_choking)
-                            .unwrap_or(true)
+                    // Check if we're choking this peer; allowed fast pieces
+                    // are served anyway, except while paused
+                    let (allowed, max_length) = {
+                        let mut sessions_guard = sessions.write().await;
+                        match sessions_guard.get_mut(&addr) {
+                            Some(s) => {
+                                s.last_request_at = Some(Instant::now());
+                                (!s.connection.am_choking || s.allowed_fast_out.contains(&index), s.max_request_length)
+                            }
+                            None => (false, 0),
+                        }
                     };
+
+                    // Any length up to the limit, as long as it stays in the piece
+                    let fits = piece_manager.read().await.geometry().fits(index as usize, begin as usize, length as usize);
+                    if length == 0 || length as usize > max_length || !fits {
+                        tracing::debug!(
+                            "Refusing request from {} for piece {} offset {} length {} (limit {} bytes)",
+                            addr, index, begin, length, max_length
+                        );
+                        Self::reject_request(addr, &sessions, index, begin, length).await?;
+                        continue;
+                    }
                     
-                    if am_choking {
+                    if !allowed || is_paused {
                         tracing::debug!("Ignoring request from {} (we are choking them)", addr);
+                        Self::reject_request(addr, &sessions, index, begin, length).await?;
                         continue;
                     }
 
@@ -508,6 +1068,7 @@ impl PeerManager {
                             "Peer {} requested piece {} that we don't have",
                             addr, index
                         );
+                        Self::reject_request(addr, &sessions, index, begin, length).await?;
                         continue;
                     }
 
@@ -515,6 +1076,7 @@ impl PeerManager {
                     if let Err(e) = Self::handle_upload_request(
                         addr,
                         sessions.clone(),
+                        piece_manager.clone(),
                         disk_manager.clone(),
                         index as usize,
                         begin as usize,
@@ -523,6 +1085,7 @@ impl PeerManager {
                     .await
                     {
                         tracing::error!("Failed to handle upload request: {}", e);
+                        Self::reject_request(addr, &sessions, index, begin, length).await?;
                     }
                     continue;
                 }
@@ -535,23 +1098,33 @@ impl PeerManager {
                     let block = BlockInfo::new(index as usize, begin as usize, data.len());
                     
                     // Mark request as complete and update stats
-                    let (was_pending, can_request) = {
+                    let (request, can_request) = {
                         let mut sessions_guard = sessions.write().await;
                         if let Some(session) = sessions_guard.get_mut(&addr) {
-                            let was_pending = session.remove_pending_request(&block);
-                            if was_pending {
+                            let request = session.take_answered_request(block.piece_index, block.offset, block.length);
+                            if request.is_some() {
+                                session.last_block_at = Instant::now();
+                                session.last_transfer_at = Some(session.last_block_at);
+                                if session.downloaded_bytes == 0 {
+                                    if let Some(tx) = &productive_tx {
+                                        let _ = tx.send(addr);
+                                    }
+                                }
                                 session.downloaded_bytes += data.len() as u64;
+                            } else {
+                                // Its request was cancelled (or never made)
+                                session.connection.record_waste(Waste::Discarded, data.len() as u64);
                             }
-                            (was_pending, session.can_request())
+                            (request, session.can_request())
                         } else {
-                            (false, false)
+                            (None, false)
                         }
                     };
                     
-                    if !was_pending {
+                    let Some(request) = request else {
                         tracing::warn!("Received unrequested block from {}", addr);
                         continue;
-                    }
+                    };
 
                     tracing::debug!(
                         "Received piece {} offset {} ({} bytes) from {}",
@@ -563,30 +1136,78 @@ impl PeerManager {
 
                     // Write block to piece manager
                     let mut pm = piece_manager.write().await;
-                    match pm.write_block(block, &data) {
+                    let mut waste = pm.block_waste(&block);
+                    let written = match waste {
+                        Some(_) => Ok(false),
+                        None => pm.write_block(request, &data),
+                    };
+                    match written {
                         Ok(is_complete) => {
                             if is_complete {
                                 // Piece is complete - verify and write to disk
                                 drop(pm);
-                                Self::handle_piece_complete(
+                                let verified = Self::handle_piece_complete(
                                     index as usize,
                                     piece_manager.clone(),
                                     disk_manager.clone(),
+                                    &metrics,
                                 )
-                                .await?;
+                                .await;
+                                let piece = index as usize;
+                                log.record(|| match &verified {
+                                    Ok(()) => TransferEvent::PieceVerified { peer: addr, piece },
+                                    Err(e) => TransferEvent::PieceFailed { peer: addr, piece, error: e.to_string() },
+                                });
+                                if matches!(verified, Err(crate::error::Error::InvalidData(_))) {
+                                    let piece_size = piece_manager.read().await.piece_len(piece) as u64;
+                                    Self::record_waste(addr, &sessions, Waste::Failed, piece_size).await;
+                                }
+                                match verified {
+                                    Ok(()) => {}
+                                    Err(e @ crate::error::Error::InvalidData(_)) => {
+                                        return Err(Disconnect::new(DisconnectReason::Banned, e.to_string()));
+                                    }
+                                    Err(e) => return Err(Disconnect::new(DisconnectReason::Cancelled, e.to_string())),
+                                }
+                                if is_paused {
+                                    continue;
+                                }
+                                if piece_manager.read().await.is_complete() {
+                                    // Download finished: drop interest everywhere, keep seeding
+                                    disk_manager
+                                        .write()
+                                        .await
+                                        .mark_complete_files(piece_manager.read().await.our_bitfield());
+                                    Self::update_interest_all(sessions.clone(), piece_manager.clone())
+                                        .await;
+                                } else {
+                                    Self::update_interest(
+                                        addr,
+                                        sessions.clone(),
+                                        piece_manager.clone(),
+                                        false,
+                                    )
+                                    .await?;
+                                }
                                 continue;
                             }
                         }
                         Err(e) => {
-                            tracing::error!("Failed to write block: {}", e);
+                            // Doesn't fit the piece: nothing of it is kept
+                            tracing::warn!("Dropping block from {}: {}", addr, e);
+                            waste = Some(Waste::Discarded);
                         }
                     }
 
                     drop(pm);
+                    if let Some(waste) = waste {
+                        tracing::debug!("{:?} block from {}: piece {} offset {}", waste, addr, index, begin);
+                        Self::record_waste(addr, &sessions, waste, data.len() as u64).await;
+                    }
 
                     // Request more pieces if we can
-                    if can_request {
-                        Self::request_pieces(addr, sessions.clone(), piece_manager.clone(), &peer_id)
+                    if can_request && !is_paused {
+                        Self::request_pieces(addr, sessions.clone(), piece_manager.clone())
                             .await?;
                         continue;
                     }
@@ -595,6 +1216,197 @@ impl PeerManager {
                 Message::Cancel { .. } => {
                     tracing::debug!("Received cancel from {}", addr);
                 }
+
+                Message::RejectRequest { index, begin, length } => {
+                    let block = BlockInfo::new(index as usize, begin as usize, length as usize);
+                    let was_pending = sessions
+                        .write()
+                        .await
+                        .get_mut(&addr)
+                        .is_some_and(|session| session.remove_pending_request(&block));
+                    if !was_pending {
+                        tracing::debug!("Peer {} rejected a request we didn't make", addr);
+                        continue;
+                    }
+
+                    // Hand the block to other peers now rather than after the timeout
+                    tracing::debug!("Peer {} rejected piece {} offset {}", addr, index, begin);
+                    if let Err(e) = piece_manager.write().await.mark_block_failed(block) {
+                        tracing::debug!("Could not mark block as failed (piece may be complete): {}", e);
+                    }
+                    if !is_paused {
+                        Self::request_pieces_elsewhere(addr, sessions.clone(), piece_manager.clone()).await;
+                    }
+                }
+
+                Message::AllowedFast { piece_index } => {
+                    let num_pieces = piece_manager.read().await.our_bitfield().num_pieces();
+                    if piece_index as usize >= num_pieces {
+                        continue;
+                    }
+                    let choked = {
+                        let mut sessions_guard = sessions.write().await;
+                        let Some(session) = sessions_guard.get_mut(&addr) else {
+                            continue;
+                        };
+                        session.allowed_fast_in.insert(piece_index);
+                        session.connection.peer_choking
+                    };
+
+                    // Gives us something to download before the first unchoke
+                    if choked && !is_paused {
+                        Self::request_pieces(addr, sessions.clone(), piece_manager.clone()).await?;
+                    }
+                }
+
+                Message::SuggestPiece { piece_index } => {
+                    tracing::debug!("Peer {} suggests piece {}", addr, piece_index);
+                }
+            }
+        }
+    }
+
+    /// Tell a fast peer that its request won't be served. Other peers get no
+    /// answer, as before the fast extension.
+    async fn reject_request(
+        addr: SocketAddr,
+        sessions: &Arc<RwLock<HashMap<SocketAddr, PeerSession>>>,
+        index: u32,
+        begin: u32,
+        length: u32,
+    ) -> Result<(), String> {
+        let mut sessions_guard = sessions.write().await;
+        let Some(session) = sessions_guard.get_mut(&addr) else {
+            return Ok(());
+        };
+        if !session.connection.fast_extension {
+            return Ok(());
+        }
+        session
+            .connection
+            .send_message(&Message::RejectRequest { index, begin, length })
+            .await
+            .map_err(|e| format!("Failed to send reject: {}", e))
+    }
+
+    /// Bring a session in line with the manager's pause state: quiet it for a
+    /// pause, or clear stale requests and re-declare interest after a resume
+    async fn sync_pause_state(
+        addr: SocketAddr,
+        sessions: &Arc<RwLock<HashMap<SocketAddr, PeerSession>>>,
+        piece_manager: &Arc<RwLock<PieceManager>>,
+        paused: bool,
+    ) -> Result<(), String> {
+        let stale = {
+            let mut sessions_guard = sessions.write().await;
+            let Some(session) = sessions_guard.get_mut(&addr) else {
+                return Ok(());
+            };
+            if paused {
+                if !session.quiet_for_pause {
+                    tracing::debug!("Choking {} and dropping interest for pause", addr);
+                    session
+                        .go_quiet()
+                        .await
+                        .map_err(|e| format!("Failed to quiet peer: {}", e))?;
+                }
+                return Ok(());
+            }
+            if !session.quiet_for_pause {
+                return Ok(());
+            }
+            session.reset_after_pause()
+        };
+
+        // Requests sent before the pause would all time out at once; hand them out again
+        if !stale.is_empty() {
+            let mut pm = piece_manager.write().await;
+            for block in stale {
+                let _ = pm.mark_block_failed(block);
+            }
+        }
+
+        Self::update_interest(addr, sessions.clone(), piece_manager.clone(), true).await?;
+
+        // A peer that kept us unchoked won't send anything to restart requests
+        Self::request_pieces(addr, sessions.clone(), piece_manager.clone()).await
+    }
+
+    /// Re-evaluate whether we are interested in a peer and send
+    /// Interested/NotInterested if that changed.
+    ///
+    /// Unless `force` is set, the check is skipped when we haven't verified any
+    /// new piece since the last evaluation for this peer.
+    async fn update_interest(
+        addr: SocketAddr,
+        sessions: Arc<RwLock<HashMap<SocketAddr, PeerSession>>>,
+        piece_manager: Arc<RwLock<PieceManager>>,
+        force: bool,
+    ) -> Result<(), String> {
+        let mut sessions_lock = sessions.write().await;
+        let session = sessions_lock
+            .get_mut(&addr)
+            .ok_or_else(|| "Session not found".to_string())?;
+
+        let pm = piece_manager.read().await;
+        let our_pieces = pm.our_bitfield().count_pieces();
+        if !force && session.interest_checked_pieces == our_pieces {
+            return Ok(());
+        }
+        session.interest_checked_pieces = our_pieces;
+
+        let interested = match &session.peer_bitfield {
+            Some(peer_bf) => pm.is_interested_in(peer_bf),
+            None => false,
+        };
+        drop(pm);
+
+        if interested && !session.connection.am_interested {
+            tracing::info!("Peer {} has pieces we need, sending interested", addr);
+            session
+                .connection
+                .send_interested()
+                .await
+                .map_err(|e| format!("Failed to send interested: {}", e))?;
+        } else if !interested && session.connection.am_interested {
+            tracing::debug!("Peer {} has no more pieces we need, sending not interested", addr);
+            session
+                .connection
+                .send_not_interested()
+                .await
+                .map_err(|e| format!("Failed to send not interested: {}", e))?;
+        }
+
+        Ok(())
+    }
+
+    /// Re-evaluate interest for every peer whose session is currently available.
+    /// Peers busy in their receive loop pick up the change on their next message.
+    async fn update_interest_all(
+        sessions: Arc<RwLock<HashMap<SocketAddr, PeerSession>>>,
+        piece_manager: Arc<RwLock<PieceManager>>,
+    ) {
+        let addrs: Vec<SocketAddr> = sessions.read().await.keys().copied().collect();
+        for addr in addrs {
+            if let Err(e) =
+                Self::update_interest(addr, sessions.clone(), piece_manager.clone(), false).await
+            {
+                tracing::debug!("Could not update interest for {}: {}", addr, e);
+            }
+        }
+    }
+
+    /// Top up requests to every peer but `addr` whose session is currently
+    /// available. Peers busy in their receive loop do so on their next message.
+    async fn request_pieces_elsewhere(
+        addr: SocketAddr,
+        sessions: Arc<RwLock<HashMap<SocketAddr, PeerSession>>>,
+        piece_manager: Arc<RwLock<PieceManager>>,
+    ) {
+        let others: Vec<SocketAddr> = sessions.read().await.keys().copied().filter(|other| *other != addr).collect();
+        for other in others {
+            if let Err(e) = Self::request_pieces(other, sessions.clone(), piece_manager.clone()).await {
+                tracing::debug!("Could not request pieces from {}: {}", other, e);
             }
         }
     }
@@ -604,26 +1416,26 @@ impl PeerManager {
         addr: SocketAddr,
         sessions: Arc<RwLock<HashMap<SocketAddr, PeerSession>>>,
         piece_manager: Arc<RwLock<PieceManager>>,
-        peer_id: &str,
     ) -> Result<(), String> {
         let mut sessions_lock = sessions.write().await;
         let session = sessions_lock
             .get_mut(&addr)
             .ok_or_else(|| "Session not found".to_string())?;
 
-        if !session.can_request() {
+        // Only request while interested; once complete we just seed
+        if !session.can_request() || !session.connection.am_interested {
             return Ok(());
         }
 
-        let peer_bitfield = match &session.peer_bitfield {
-            Some(bf) => bf.clone(),
+        let peer_bitfield = match session.requestable_pieces() {
+            Some(bf) => bf,
             None => return Ok(()), // Don't have bitfield yet
         };
 
         let mut pm = piece_manager.write().await;
 
         // Try to select a new piece
-        if let Some((piece_idx, blocks)) = pm.select_next_piece(peer_id, &peer_bitfield) {
+        if let Some((piece_idx, blocks)) = pm.select_next_piece(session.key, &peer_bitfield) {
             tracing::debug!("Selected piece {} for download from {}", piece_idx, addr);
 
             // Request blocks
@@ -631,6 +1443,11 @@ impl PeerManager {
                 .into_iter()
                 .take(MAX_PENDING_REQUESTS - session.pending_requests.len())
                 .collect();
+            session.transfer_log.record(|| TransferEvent::PieceRequested {
+                peer: addr,
+                piece: piece_idx,
+                blocks: blocks_to_request.len(),
+            });
 
             for block in blocks_to_request {
                 let request_msg = Message::Request {
@@ -655,30 +1472,36 @@ impl PeerManager {
             // Try to get missing blocks from in-progress pieces
             for piece_idx in pm.in_progress_pieces() {
                 if let Some(missing_blocks) = pm.get_missing_blocks(piece_idx) {
-                    if let Some(peer_bf) = &session.peer_bitfield {
-                        if peer_bf.has_piece(piece_idx) {
-                            let blocks_to_request: Vec<_> = missing_blocks
-                                .into_iter()
-                                .take(MAX_PENDING_REQUESTS - session.pending_requests.len())
-                                .collect();
-
-                            for block in blocks_to_request {
-                                let request_msg = Message::Request {
-                                    index: block.piece_index as u32,
-                                    begin: block.offset as u32,
-                                    length: block.length as u32,
-                                };
-
-                                if let Err(e) = session.connection.send_message(&request_msg).await {
-                                    return Err(format!("Failed to send request: {}", e));
-                                }
+                    if peer_bitfield.has_piece(piece_idx) {
+                        let blocks_to_request: Vec<_> = missing_blocks
+                            .into_iter()
+                            .take(MAX_PENDING_REQUESTS - session.pending_requests.len())
+                            .collect();
+                        if !blocks_to_request.is_empty() {
+                            pm.track_request(session.key, piece_idx);
+                            session.transfer_log.record(|| TransferEvent::PieceRequested {
+                                peer: addr,
+                                piece: piece_idx,
+                                blocks: blocks_to_request.len(),
+                            });
+                        }
 
-                                session.add_pending_request(block);
-                            }
+                        for block in blocks_to_request {
+                            let request_msg = Message::Request {
+                                index: block.piece_index as u32,
+                                begin: block.offset as u32,
+                                length: block.length as u32,
+                            };
 
-                            if session.pending_requests.len() >= MAX_PENDING_REQUESTS {
-                                break;
+                            if let Err(e) = session.connection.send_message(&request_msg).await {
+                                return Err(format!("Failed to send request: {}", e));
                             }
+
+                            session.add_pending_request(block);
+                        }
+
+                        if session.pending_requests.len() >= MAX_PENDING_REQUESTS {
+                            break;
                         }
                     }
                 }
@@ -688,14 +1511,32 @@ impl PeerManager {
         Ok(())
     }
 
+    /// Count received payload as wasted on the peer's connection (which feeds
+    /// the torrent and global meters)
+    async fn record_waste(
+        addr: SocketAddr,
+        sessions: &RwLock<HashMap<SocketAddr, PeerSession>>,
+        waste: Waste,
+        bytes: u64,
+    ) {
+        if let Some(session) = sessions.write().await.get_mut(&addr) {
+            session.connection.record_waste(waste, bytes);
+        }
+    }
+
     /// Handle a completed piece
     async fn handle_piece_complete(
         piece_index: usize,
         piece_manager: Arc<RwLock<PieceManager>>,
         disk_manager: Arc<RwLock<DiskManager>>,
-    ) -> Result<(), String> {
+        metrics: &Arc<EngineMetrics>,
+    ) -> crate::error::Result<()> {
         tracing::info!("Piece {} completed, verifying...", piece_index);
+        let verifying = metrics.enter(Gauge::Verifications);
 
+        // Held from marking the piece until its data is written, so a progress
+        // save (which syncs under this lock) never sees one without the other
+        let mut dm = disk_manager.write().await;
         let mut pm = piece_manager.write().await;
         let piece_data = match pm.verify_piece(piece_index) {
             Ok(data) => {
@@ -704,20 +1545,22 @@ impl PeerManager {
             }
             Err(e) => {
                 tracing::error!("Piece {} verification failed: {}", piece_index, e);
-                return Err(e);
+                return Err(crate::error::Error::InvalidData(e));
             }
         };
 
         drop(pm);
+        drop(verifying);
 
         // Write to disk
-        let mut dm = disk_manager.write().await;
+        let _writing = metrics.enter(Gauge::DiskWrites);
         if let Err(e) = dm.write_piece(piece_index, piece_data).await {
             tracing::error!("Failed to write piece {} to disk: {}", piece_index, e);
-            return Err(e);
+            return Err(e.into());
         }
 
         tracing::info!("Piece {} written to disk successfully", piece_index);
+        dm.piece_completed(piece_index, piece_manager.read().await.our_bitfield());
         
         // Note: Broadcasting HAVE messages is handled per-peer in their loops
         // Each peer will be notified when they send/receive messages
@@ -726,36 +1569,33 @@ impl PeerManager {
     }
 
     /// Handle an upload request from a peer
+    ///
+    /// Only pieces that passed a hash check are served: a bit set without one
+    /// may stand for preallocated zeros, which the peer would then reject.
     async fn handle_upload_request(
         addr: SocketAddr,
         sessions: Arc<RwLock<HashMap<SocketAddr, PeerSession>>>,
+        piece_manager: Arc<RwLock<PieceManager>>,
         disk_manager: Arc<RwLock<DiskManager>>,
         piece_index: usize,
         offset: usize,
         length: usize,
-    ) -> Result<(), String> {
-        // Read piece from disk
-        let dm = disk_manager.read().await;
-        let piece_data = dm.read_piece(piece_index).await?;
-        drop(dm);
-
-        // Extract requested block
-        if offset + length > piece_data.len() {
-            return Err(format!(
-                "Invalid block request: offset {} + length {} > piece size {}",
-                offset,
-                length,
-                piece_data.len()
-            ));
+    ) -> crate::error::Result<()> {
+        if !piece_manager.read().await.can_serve(piece_index) {
+            return Err(crate::error::Error::Other(format!(
+                "Piece {} is not verified, not serving it",
+                piece_index
+            )));
         }
 
-        let block_data = piece_data[offset..offset + length].to_vec();
+        // Read just the requested block (straight from the file's map when enabled)
+        let block_data = disk_manager.read().await.read_block(piece_index, offset, length).await?;
 
         // Send piece message
         let mut sessions_lock = sessions.write().await;
         let session = sessions_lock
             .get_mut(&addr)
-            .ok_or_else(|| "Session not found".to_string())?;
+            .ok_or_else(|| crate::error::Error::Other(format!("Session not found: {}", addr)))?;
 
         let piece_msg = Message::Piece {
             index: piece_index as u32,
@@ -764,10 +1604,11 @@ impl PeerManager {
         };
 
         if let Err(e) = session.connection.send_message(&piece_msg).await {
-            return Err(format!("Failed to send piece: {}", e));
+            return Err(crate::error::Error::NetworkError(format!("Failed to send piece: {}", e)));
         }
 
         session.uploaded_bytes += length as u64;
+        session.last_transfer_at = Some(Instant::now());
 
         tracing::debug!(
             "Uploaded piece {} offset {} ({} bytes) to {}",
@@ -809,6 +1650,42 @@ impl PeerManager {
         }
     }
 
+    /// Push request deadlines back by the time the machine spent asleep, so
+    /// requests get the rest of their timeout once connections are live
+    /// again, and restart the speed samples so the sleep doesn't show up as
+    /// a spike or a drop
+    async fn reconcile_after_wake(&mut self, wake: crate::wake::Wake) {
+        let mut sessions = self.sessions.write().await;
+        for session in sessions.values_mut() {
+            for requested_at in session.pending_requests.values_mut() {
+                *requested_at += wake.monotonic;
+            }
+            session.last_block_at += wake.monotonic;
+            session.last_downloaded_bytes = session.downloaded_bytes;
+            session.last_uploaded_bytes = session.uploaded_bytes;
+            session.download_speed = 0.0;
+            session.upload_speed = 0.0;
+        }
+        drop(sessions);
+
+        let mut stats = self.stats.write().await;
+        stats.download_speed = 0.0;
+        stats.upload_speed = 0.0;
+    }
+
+    /// Drop the least useful idle sessions if seeding at the connection cap,
+    /// see `reaper::pick`
+    async fn reap_idle(&self) {
+        if !self.piece_manager.read().await.is_complete() {
+            return;
+        }
+        let connected = self.connected.read().await.len();
+        for addr in reaper::pick(&self.idle.snapshots(), connected, self.max_peers, Instant::now()) {
+            tracing::debug!("Dropping idle peer {} to make room ({} connected)", addr, connected);
+            self.idle.reap(addr);
+        }
+    }
+
     /// Send keep-alive to all peers
     async fn send_keep_alives(&self) {
         let mut sessions = self.sessions.write().await;
@@ -830,14 +1707,20 @@ impl PeerManager {
         // We need write lock on sessions to update per-session stats
         let mut sessions = self.sessions.write().await;
         let mut connected_peers = 0;
+        let mut connected_seeds = 0;
         let mut total_downloaded = 0;
         let mut total_uploaded = 0;
         let mut download_speed = 0.0;
         let mut upload_speed = 0.0;
+        let mut blocks_in_flight = 0;
         
         // Update per-peer stats
         for session in sessions.values_mut() {
             connected_peers += 1;
+            blocks_in_flight += session.pending_requests.len();
+            if session.peer_bitfield.as_ref().is_some_and(|bf| bf.is_complete()) {
+                connected_seeds += 1;
+            }
             total_downloaded += session.downloaded_bytes;
             total_uploaded += session.uploaded_bytes;
             
@@ -857,49 +1740,56 @@ impl PeerManager {
         
         let mut stats = self.stats.write().await;
         stats.connected_peers = connected_peers;
+        stats.connected_seeds = connected_seeds;
         stats.total_downloaded = total_downloaded;
         stats.total_uploaded = total_uploaded;
         stats.download_speed = download_speed;
         stats.upload_speed = upload_speed;
+        stats.traffic = self.traffic.snapshot();
+        self.metrics.set_blocks_in_flight(blocks_in_flight);
     }
 
     /// Update choking algorithm
-    /// Unchokes the best uploaders and chokes the rest
+    /// Unchokes the peers with the best recent rates and chokes the rest,
+    /// see `choking::decide`
     async fn update_choking(&self) {
+        // Once we seed, peers are ranked by how fast they take from us
+        let seeding = self.piece_manager.read().await.is_complete();
         let mut sessions = self.sessions.write().await;
-
-        // Get peers sorted by download rate (how much they've sent to us)
-        let mut peer_stats: Vec<(SocketAddr, u64)> = sessions
-            .iter()
-            .filter(|(_, s)| s.connection.peer_interested)
-            .map(|(addr, s)| (*addr, s.downloaded_bytes))
+        let interval = CHOKING_INTERVAL.as_secs().max(1);
+
+        let candidates: Vec<ChokeCandidate> = sessions
+            .iter_mut()
+            .map(|(addr, s)| {
+                let downloaded = s.downloaded_bytes.saturating_sub(s.choke_pass_downloaded);
+                let uploaded = s.uploaded_bytes.saturating_sub(s.choke_pass_uploaded);
+                s.choke_pass_downloaded = s.downloaded_bytes;
+                s.choke_pass_uploaded = s.uploaded_bytes;
+                ChokeCandidate {
+                    addr: *addr,
+                    interested: s.connection.peer_interested,
+                    rate: if seeding { uploaded } else { downloaded } / interval,
+                    snubbed: !seeding && s.is_snubbing(),
+                    optimistic: s.optimistic,
+                }
+            })
             .collect();
 
-        // Sort by download amount (best uploaders first)
-        peer_stats.sort_by(|a, b| b.1.cmp(&a.1));
-
-        // Unchoke top N peers
-        let mut unchoked = 0;
-        for (addr, _) in &peer_stats {
-            if unchoked < NUM_UNCHOKED {
-                if let Some(session) = sessions.get_mut(addr) {
-                    if session.connection.am_choking {
-                        tracing::debug!("Unchoking peer {} (good uploader)", addr);
-                        if session.connection.send_unchoke().await.is_err() {
-                            tracing::warn!("Failed to send unchoke to {}", addr);
-                        }
-                    }
+        let now = chrono::Utc::now().timestamp();
+        for (addr, choked, reason) in choking::decide(&candidates, NUM_UNCHOKED) {
+            let Some(session) = sessions.get_mut(&addr) else {
+                continue;
+            };
+            session.choke_history.record(ChokeDecision { at: now, choked, reason });
+            if choked && !session.connection.am_choking {
+                tracing::debug!("Choking peer {} ({:?})", addr, reason);
+                if session.connection.send_choke().await.is_err() {
+                    tracing::warn!("Failed to send choke to {}", addr);
                 }
-                unchoked += 1;
-            } else {
-                // Choke the rest
-                if let Some(session) = sessions.get_mut(addr) {
-                    if !session.connection.am_choking {
-                        tracing::debug!("Choking peer {}", addr);
-                        if session.connection.send_choke().await.is_err() {
-                            tracing::warn!("Failed to send choke to {}", addr);
-                        }
-                    }
+            } else if !choked && session.connection.am_choking {
+                tracing::debug!("Unchoking peer {} ({:?})", addr, reason);
+                if session.connection.send_unchoke().await.is_err() {
+                    tracing::warn!("Failed to send unchoke to {}", addr);
                 }
             }
         }
@@ -930,8 +1820,17 @@ impl PeerManager {
         };
 
         if let Some(addr) = chosen_addr {
+            for session in sessions.values_mut() {
+                session.optimistic = false;
+            }
             if let Some(session) = sessions.get_mut(&addr) {
                 tracing::info!("Optimistically unchoking peer {}", addr);
+                session.optimistic = true;
+                session.choke_history.record(ChokeDecision {
+                    at: chrono::Utc::now().timestamp(),
+                    choked: false,
+                    reason: ChokeReason::Optimistic,
+                });
                 if let Err(e) = session.connection.send_unchoke().await {
                     tracing::warn!("Failed to optimistically unchoke {}: {}", addr, e);
                 }
@@ -939,19 +1838,35 @@ impl PeerManager {
         }
     }
 
-    /// Broadcast HAVE message to all connected peers
-    async fn broadcast_have(&self, piece_index: usize) {
+    /// Queue a HAVE for every connected peer that doesn't have the piece.
+    /// Returns whether any were left queued for the next flush.
+    async fn broadcast_have(&self, piece_index: usize) -> bool {
         let mut sessions = self.sessions.write().await;
+        let mut queued = false;
 
-        let have_msg = Message::Have {
-            piece_index: piece_index as u32,
-        };
+        for (addr, session) in sessions.iter_mut() {
+            if session.peer_bitfield.as_ref().is_some_and(|bf| bf.has_piece(piece_index)) {
+                continue;
+            }
+            session.connection.queue_message(Message::Have {
+                piece_index: piece_index as u32,
+            });
+            if !session.connection.outbox_full() {
+                queued = true;
+            } else if let Err(e) = session.connection.flush_outbox().await {
+                tracing::warn!("Failed to send HAVEs to {}: {}", addr, e);
+            }
+        }
 
+        queued
+    }
+
+    /// Write whatever is queued for peers in the session map
+    async fn flush_outboxes(&self) {
+        let mut sessions = self.sessions.write().await;
         for (addr, session) in sessions.iter_mut() {
-            if let Err(e) = session.connection.send_message(&have_msg).await {
-                tracing::warn!("Failed to send HAVE to {}: {}", addr, e);
-            } else {
-                tracing::debug!("Sent HAVE {} to {}", piece_index, addr);
+            if let Err(e) = session.connection.flush_outbox().await {
+                tracing::warn!("Failed to send queued messages to {}: {}", addr, e);
             }
         }
     }
@@ -964,6 +1879,7 @@ impl PeerManager {
             let client = parse_peer_id(session.connection.peer_id);
             let flags = calculate_flags(session);
             let progress = calculate_progress(session);
+            let traffic = session.connection.traffic();
             
             super::PeerInfo {
                 ip: addr.ip().to_string(),
@@ -975,6 +1891,9 @@ impl PeerManager {
                 upload_speed: session.upload_speed as u64,
                 downloaded: session.downloaded_bytes,
                 uploaded: session.uploaded_bytes,
+                overhead_downloaded: traffic.overhead_downloaded,
+                overhead_uploaded: traffic.overhead_uploaded,
+                choke_reason: session.choke_history.latest().map(|d| d.reason),
             }
         }).collect()
     }
@@ -1077,14 +1996,17 @@ fn calculate_flags(session: &PeerSession) -> String {
     }
     
     // O = Optimistic unchoke
-    // This would need to be tracked separately in PeerSession
-    // For now, we'll skip this flag
+    if session.optimistic {
+        flags.push('O');
+    }
     
     // E = Encrypted connection
     // Not yet implemented
     
     // S = Snubbed (peer hasn't sent data in a while)
-    // Not yet implemented
+    if session.is_snubbing() {
+        flags.push('S');
+    }
     
     flags
 }
@@ -1096,3 +2018,818 @@ fn calculate_progress(session: &PeerSession) -> f64 {
         None => 0.0,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::{TcpListener, TcpStream};
+
+    /// Connect two PeerConnections over loopback: (ours, remote)
+    async fn loopback_pair() -> (PeerConnection, PeerConnection) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (ours, accepted) = tokio::join!(TcpStream::connect(addr), listener.accept());
+        let (remote_stream, remote_addr) = accepted.unwrap();
+        (
+            PeerConnection::new(ours.unwrap(), addr),
+            PeerConnection::new(remote_stream, remote_addr),
+        )
+    }
+
+    fn create_piece_manager(num_pieces: usize) -> Arc<RwLock<PieceManager>> {
+        Arc::new(RwLock::new(PieceManager::new(
+            num_pieces,
+            16384,
+            16384,
+            vec![vec![0u8; 20]; num_pieces],
+            crate::piece::SelectionStrategy::RarestFirst,
+        )))
+    }
+
+    #[tokio::test]
+    async fn test_interest_transitions() {
+        let (ours, mut remote) = loopback_pair().await;
+        let addr = ours.addr;
+        let piece_manager = create_piece_manager(4);
+
+        let mut session = PeerSession::new(ours);
+        let mut peer_bf = Bitfield::new(4);
+        peer_bf.set_piece(0);
+        session.peer_bitfield = Some(peer_bf);
+
+        let sessions = Arc::new(RwLock::new(HashMap::new()));
+        sessions.write().await.insert(addr, session);
+
+        // Peer has piece 0 which we need -> Interested
+        PeerManager::update_interest(addr, sessions.clone(), piece_manager.clone(), true)
+            .await
+            .unwrap();
+        assert!(matches!(remote.recv_message().await.unwrap(), Message::Interested));
+        assert!(sessions.read().await[&addr].connection.am_interested);
+
+        // We complete piece 0 -> nothing left to want from this peer
+        piece_manager.write().await.restore_bitfield(&[0b1000_0000]);
+        PeerManager::update_interest(addr, sessions.clone(), piece_manager.clone(), false)
+            .await
+            .unwrap();
+        assert!(matches!(remote.recv_message().await.unwrap(), Message::NotInterested));
+        assert!(!sessions.read().await[&addr].connection.am_interested);
+
+        // Peer announces piece 2 -> Interested again
+        if let Some(bf) = sessions.write().await.get_mut(&addr).unwrap().peer_bitfield.as_mut() {
+            bf.set_piece(2);
+        }
+        PeerManager::update_interest(addr, sessions.clone(), piece_manager.clone(), true)
+            .await
+            .unwrap();
+        assert!(matches!(remote.recv_message().await.unwrap(), Message::Interested));
+    }
+
+    #[tokio::test]
+    async fn test_not_interested_in_skipped_pieces() {
+        let (ours, mut remote) = loopback_pair().await;
+        let addr = ours.addr;
+        let piece_manager = create_piece_manager(4);
+        piece_manager
+            .write()
+            .await
+            .set_piece_priority(1, crate::piece::PiecePriority::Skip);
+
+        let mut session = PeerSession::new(ours);
+        let mut peer_bf = Bitfield::new(4);
+        peer_bf.set_piece(1);
+        session.peer_bitfield = Some(peer_bf);
+
+        let sessions = Arc::new(RwLock::new(HashMap::new()));
+        sessions.write().await.insert(addr, session);
+
+        PeerManager::update_interest(addr, sessions.clone(), piece_manager.clone(), true)
+            .await
+            .unwrap();
+        assert!(!sessions.read().await[&addr].connection.am_interested);
+
+        // No state change means nothing is sent; a keep-alive is the next message
+        sessions
+            .write()
+            .await
+            .get_mut(&addr)
+            .unwrap()
+            .connection
+            .send_keep_alive()
+            .await
+            .unwrap();
+        assert!(matches!(remote.recv_message().await.unwrap(), Message::KeepAlive));
+    }
+
+    #[tokio::test]
+    async fn test_no_requests_once_complete() {
+        let (ours, mut remote) = loopback_pair().await;
+        let addr = ours.addr;
+        let piece_manager = create_piece_manager(2);
+        piece_manager.write().await.restore_bitfield(&[0b1100_0000]);
+
+        // Previously interested and unchoked, as mid-download
+        let mut session = PeerSession::new(ours);
+        session.connection.peer_choking = false;
+        session.connection.am_interested = true;
+        session.peer_bitfield = Some(Bitfield::complete(2));
+
+        let sessions = Arc::new(RwLock::new(HashMap::new()));
+        sessions.write().await.insert(addr, session);
+
+        PeerManager::update_interest_all(sessions.clone(), piece_manager.clone()).await;
+        assert!(matches!(remote.recv_message().await.unwrap(), Message::NotInterested));
+
+        PeerManager::request_pieces(addr, sessions.clone(), piece_manager.clone())
+            .await
+            .unwrap();
+
+        let sessions_guard = sessions.read().await;
+        assert!(!sessions_guard[&addr].connection.am_interested);
+        assert!(sessions_guard[&addr].pending_requests.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_pause_quiets_peer_and_resume_restores_interest() {
+        let (ours, mut remote) = loopback_pair().await;
+        let addr = ours.addr;
+        let piece_manager = create_piece_manager(4);
+
+        // Mid-download: unchoked both ways, one request outstanding
+        let mut session = PeerSession::new(ours);
+        session.connection.am_choking = false;
+        session.connection.am_interested = true;
+        session.peer_bitfield = Some(Bitfield::complete(4));
+        session.add_pending_request(BlockInfo::new(0, 0, 16384));
+
+        let sessions = Arc::new(RwLock::new(HashMap::new()));
+        sessions.write().await.insert(addr, session);
+
+        PeerManager::sync_pause_state(addr, &sessions, &piece_manager, true).await.unwrap();
+        assert!(matches!(remote.recv_message().await.unwrap(), Message::Choke));
+        assert!(matches!(remote.recv_message().await.unwrap(), Message::NotInterested));
+
+        // Seeing the pause again sends nothing more
+        PeerManager::sync_pause_state(addr, &sessions, &piece_manager, true).await.unwrap();
+
+        PeerManager::sync_pause_state(addr, &sessions, &piece_manager, false).await.unwrap();
+        assert!(matches!(remote.recv_message().await.unwrap(), Message::Interested));
+        let sessions_guard = sessions.read().await;
+        assert!(sessions_guard[&addr].pending_requests.is_empty());
+        assert!(!sessions_guard[&addr].quiet_for_pause);
+    }
+
+    /// Next message that isn't one of the AllowedFast we send after connecting
+    async fn recv_skipping_allowed_fast(conn: &mut PeerConnection) -> crate::error::Result<Message> {
+        loop {
+            match conn.recv_message().await? {
+                Message::AllowedFast { .. } => continue,
+                message => return Ok(message),
+            }
+        }
+    }
+
+    /// Peer that accepts one connection, handshakes and reports what it saw
+    async fn fake_peer(
+        info_hash: [u8; 20],
+        order: mpsc::UnboundedSender<(SocketAddr, bool)>,
+    ) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (stream, remote_addr) = listener.accept().await.unwrap();
+            let mut conn = PeerConnection::new(stream, remote_addr);
+            conn.handshake(info_hash, [7u8; 20]).await.unwrap();
+            order.send((addr, false)).unwrap();
+            // Both sides speak the fast extension, and we have nothing yet
+            assert_eq!(conn.recv_message().await.unwrap(), Message::HaveNone);
+            if let Ok(Ok(Message::Interested)) =
+                time::timeout(Duration::from_millis(500), recv_skipping_allowed_fast(&mut conn)).await
+            {
+                order.send((addr, true)).unwrap();
+            }
+            // Hold the connection until the test ends
+            let _ = conn.recv_message().await;
+        });
+        addr
+    }
+
+    #[tokio::test]
+    async fn test_resume_reconnects_recent_peers_first() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let metainfo = crate::torrent::Metainfo::from_bytes(
+            b"d8:announce14:http://tracker4:infod6:lengthi32768e4:name1:a12:piece_lengthi16384e6:pieces40:1234567890123456789012345678901234567890ee",
+        )
+        .unwrap();
+        let disk_manager = Arc::new(RwLock::new(DiskManager::new(&metainfo, temp_dir.path().to_path_buf())));
+        let manager = PeerManager::new(
+            metainfo.info_hash,
+            PeerIdentity::generate(),
+            create_piece_manager(2),
+            disk_manager,
+            CancellationToken::new(),
+        );
+
+        let (order_tx, mut order_rx) = mpsc::unbounded_channel();
+        let older = fake_peer(metainfo.info_hash, order_tx.clone()).await;
+        let newer = fake_peer(metainfo.info_hash, order_tx.clone()).await;
+        let fresh = fake_peer(metainfo.info_hash, order_tx).await;
+
+        // Both were connected before the pause; only the newer one had pieces
+        let now = Instant::now();
+        PeerManager::remember_peer(&manager.recent_peers, older, None).await;
+        manager.recent_peers.write().await.get_mut(&older).unwrap().disconnected_at = now - Duration::from_secs(60);
+        PeerManager::remember_peer(&manager.recent_peers, newer, Some(Bitfield::complete(2))).await;
+
+        let tx = manager.command_sender();
+        tx.send(PeerManagerCommand::Pause).await.unwrap();
+        tx.send(PeerManagerCommand::Resume).await.unwrap();
+        tx.send(PeerManagerCommand::AddPeer(fresh)).await.unwrap();
+        let cancel = manager.cancel_token.clone();
+        let task = tokio::spawn(manager.run());
+
+        let mut connects = Vec::new();
+        let mut interested = Vec::new();
+        while connects.len() < 3 || interested.is_empty() {
+            let (addr, is_interest) = time::timeout(Duration::from_secs(5), order_rx.recv())
+                .await
+                .expect("fake peers should hear from us")
+                .unwrap();
+            if is_interest { interested.push(addr) } else { connects.push(addr) }
+        }
+        assert_eq!(connects, vec![newer, older, fresh]);
+        // The remembered bitfield let us declare interest without waiting for theirs
+        assert_eq!(interested, vec![newer]);
+
+        cancel.cancel();
+        task.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_pause_drains_queued_dials() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let metainfo = crate::torrent::Metainfo::from_bytes(
+            b"d8:announce14:http://tracker4:infod6:lengthi32768e4:name1:a12:piece_lengthi16384e6:pieces40:1234567890123456789012345678901234567890ee",
+        )
+        .unwrap();
+        let disk_manager = Arc::new(RwLock::new(DiskManager::new(&metainfo, temp_dir.path().to_path_buf())));
+        let mut manager = PeerManager::new(
+            metainfo.info_hash,
+            PeerIdentity::generate(),
+            create_piece_manager(2),
+            disk_manager,
+            CancellationToken::new(),
+        );
+        let pacer = Arc::new(DialPacer::new(1, 4));
+        manager.set_dial_pacer(pacer.clone());
+
+        // Closed local ports: each dial fails at once, the pacer is the only wait
+        let mut addrs = Vec::new();
+        for _ in 0..20 {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            addrs.push(listener.local_addr().unwrap());
+        }
+
+        let tx = manager.command_sender();
+        let cancel = manager.cancel_token.clone();
+        let task = tokio::spawn(manager.run());
+        for addr in addrs {
+            tx.send(PeerManagerCommand::AddPeer(addr)).await.unwrap();
+        }
+        time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(pacer.queue_depth(), 19);
+
+        tx.send(PeerManagerCommand::Pause).await.unwrap();
+        time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(pacer.queue_depth(), 0);
+        assert_eq!(pacer.half_open(), 0);
+
+        // Stopping drops whatever was queued after the pause too
+        tx.send(PeerManagerCommand::Resume).await.unwrap();
+        tx.send(PeerManagerCommand::AddPeer("127.0.0.1:9".parse().unwrap())).await.unwrap();
+        tx.send(PeerManagerCommand::AddPeer("127.0.0.1:7".parse().unwrap())).await.unwrap();
+        time::sleep(Duration::from_millis(100)).await;
+        cancel.cancel();
+        task.await.unwrap();
+        assert_eq!(pacer.queue_depth(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_reconnects_leave_no_availability_behind() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let metainfo = crate::torrent::Metainfo::from_bytes(
+            b"d8:announce14:http://tracker4:infod6:lengthi32768e4:name1:a12:piece_lengthi16384e6:pieces40:1234567890123456789012345678901234567890ee",
+        )
+        .unwrap();
+        let info_hash = metainfo.info_hash;
+        let disk_manager = Arc::new(RwLock::new(DiskManager::new(&metainfo, temp_dir.path().to_path_buf())));
+        let piece_manager = create_piece_manager(2);
+        let mut manager = PeerManager::new(
+            info_hash,
+            PeerIdentity::generate(),
+            piece_manager.clone(),
+            disk_manager,
+            CancellationToken::new(),
+        );
+        manager.set_dial_pacer(Arc::new(DialPacer::new(0, 0)));
+
+        // A seed that announces everything (plus a redundant Have) and hangs up
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let peer = tokio::spawn(async move {
+            loop {
+                let (stream, remote_addr) = listener.accept().await.unwrap();
+                let mut conn = PeerConnection::new(stream, remote_addr);
+                conn.handshake(info_hash, [7u8; 20]).await.unwrap();
+                assert_eq!(conn.recv_message().await.unwrap(), Message::HaveNone);
+                conn.send_message(&Message::Bitfield { bitfield: Bitfield::complete(2).as_bytes().to_vec() })
+                    .await
+                    .unwrap();
+                // Interested means our bitfield was counted
+                assert!(matches!(recv_skipping_allowed_fast(&mut conn).await.unwrap(), Message::Interested));
+                for _ in 0..2 {
+                    conn.send_message(&Message::Have { piece_index: 1 }).await.unwrap();
+                }
+            }
+        });
+
+        let recent_peers = manager.recent_peers.clone();
+        let connected = manager.connected.clone();
+        let tx = manager.command_sender();
+        let cancel = manager.cancel_token.clone();
+        let task = tokio::spawn(manager.run());
+
+        for _ in 0..100 {
+            recent_peers.write().await.remove(&addr);
+            tx.send(PeerManagerCommand::AddPeer(addr)).await.unwrap();
+            // The session is remembered once its cleanup is done
+            time::timeout(Duration::from_secs(5), async {
+                while !recent_peers.read().await.contains_key(&addr) {
+                    time::sleep(Duration::from_millis(2)).await;
+                }
+            })
+            .await
+            .expect("session should connect and end");
+            assert!(connected.read().await.is_empty());
+        }
+
+        let pm = piece_manager.read().await;
+        assert_eq!(pm.total_availability(), 0);
+        assert_eq!(pm.tracked_peers(), 0);
+        drop(pm);
+
+        cancel.cancel();
+        task.await.unwrap();
+        peer.abort();
+    }
+
+    #[tokio::test]
+    async fn test_disconnect_reasons_recorded() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let metainfo = crate::torrent::Metainfo::from_bytes(
+            b"d8:announce14:http://tracker4:infod6:lengthi32768e4:name1:a12:piece_lengthi16384e6:pieces40:1234567890123456789012345678901234567890ee",
+        )
+        .unwrap();
+        let info_hash = metainfo.info_hash;
+        let disk_manager = Arc::new(RwLock::new(DiskManager::new(&metainfo, temp_dir.path().to_path_buf())));
+        let mut manager = PeerManager::new(
+            info_hash,
+            PeerIdentity::generate(),
+            create_piece_manager(2),
+            disk_manager,
+            CancellationToken::new(),
+        );
+        manager.set_dial_pacer(Arc::new(DialPacer::new(0, 0)));
+        let history = Arc::new(DisconnectHistory::default());
+        manager.set_disconnect_history(history.clone());
+
+        // One peer answers for another torrent, one hangs up after the
+        // handshake, one stays until the torrent stops and one is reaped
+        let mut peers = Vec::new();
+        for answered_hash in [[9u8; 20], info_hash, info_hash, info_hash] {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            let hangs_up = answered_hash == info_hash && peers.len() == 1;
+            tokio::spawn(async move {
+                let (stream, remote_addr) = listener.accept().await.unwrap();
+                let mut conn = PeerConnection::new(stream, remote_addr);
+                if conn.handshake(answered_hash, [7u8; 20]).await.is_err() || hangs_up {
+                    return;
+                }
+                while conn.recv_message().await.is_ok() {}
+            });
+            peers.push(addr);
+        }
+
+        let recorded = |count: usize| {
+            let history = history.clone();
+            async move {
+                time::timeout(Duration::from_secs(5), async {
+                    while history.recent().len() < count {
+                        time::sleep(Duration::from_millis(5)).await;
+                    }
+                })
+                .await
+                .expect("disconnections should be recorded");
+                history.recent()
+            }
+        };
+
+        let tx = manager.command_sender();
+        let cancel = manager.cancel_token.clone();
+        let idle = manager.idle.clone();
+        let task = tokio::spawn(manager.run());
+        for addr in &peers {
+            tx.send(PeerManagerCommand::AddPeer(*addr)).await.unwrap();
+        }
+        let reason_of = |recent: &[Disconnection], addr| recent.iter().find(|d| d.addr == addr).map(|d| d.reason);
+
+        let recent = recorded(2).await;
+        assert_eq!(reason_of(&recent, peers[0]), Some(DisconnectReason::InfoHashMismatch));
+        assert_eq!(reason_of(&recent, peers[1]), Some(DisconnectReason::RemoteClosed));
+        assert_eq!(recent.iter().find(|d| d.addr == peers[1]).unwrap().client, "Unknown");
+
+        time::timeout(Duration::from_secs(5), async {
+            while !idle.reap(peers[3]) {
+                time::sleep(Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .expect("the last peer should connect");
+        let recent = recorded(3).await;
+        assert_eq!(reason_of(&recent, peers[3]), Some(DisconnectReason::ReapedIdle));
+
+        cancel.cancel();
+        let recent = recorded(4).await;
+        assert_eq!(reason_of(&recent, peers[2]), Some(DisconnectReason::Cancelled));
+        task.await.unwrap();
+        time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(history.recent().len(), 4, "each session is recorded once");
+    }
+
+    #[tokio::test]
+    async fn test_connected_seeds_counted_from_bitfields() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let metainfo = crate::torrent::Metainfo::from_bytes(
+            b"d8:announce14:http://tracker4:infod6:lengthi32768e4:name1:a12:piece_lengthi16384e6:pieces40:1234567890123456789012345678901234567890ee",
+        )
+        .unwrap();
+        let disk_manager = Arc::new(RwLock::new(DiskManager::new(&metainfo, temp_dir.path().to_path_buf())));
+        let mut manager = PeerManager::new(
+            metainfo.info_hash,
+            PeerIdentity::generate(),
+            create_piece_manager(2),
+            disk_manager,
+            CancellationToken::new(),
+        );
+
+        let (seed, _seed_remote) = loopback_pair().await;
+        let (leech, _leech_remote) = loopback_pair().await;
+        let mut seed_session = PeerSession::new(seed);
+        seed_session.peer_bitfield = Some(Bitfield::complete(2));
+        let mut leech_bf = Bitfield::new(2);
+        leech_bf.set_piece(0);
+        let mut leech_session = PeerSession::new(leech);
+        leech_session.peer_bitfield = Some(leech_bf);
+
+        {
+            let mut sessions = manager.sessions.write().await;
+            sessions.insert("127.0.0.1:1".parse().unwrap(), seed_session);
+            sessions.insert("127.0.0.1:2".parse().unwrap(), leech_session);
+        }
+        manager.update_stats().await;
+
+        let stats = manager.stats.read().await.clone();
+        assert_eq!(stats.connected_peers, 2);
+        assert_eq!(stats.connected_seeds, 1);
+    }
+
+    #[tokio::test]
+    async fn test_rejected_request_goes_to_another_peer() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let metainfo = crate::torrent::Metainfo::from_bytes(
+            b"d8:announce14:http://tracker4:infod6:lengthi32768e4:name1:a12:piece_lengthi16384e6:pieces40:1234567890123456789012345678901234567890ee",
+        )
+        .unwrap();
+        let disk_manager = Arc::new(RwLock::new(DiskManager::new(&metainfo, temp_dir.path().to_path_buf())));
+        let piece_manager = create_piece_manager(1);
+        let sessions = Arc::new(RwLock::new(HashMap::new()));
+
+        // Two seeds we are downloading from; the first speaks the fast extension
+        let (fast, mut fast_remote) = loopback_pair().await;
+        let (other, mut other_remote) = loopback_pair().await;
+        let (fast_addr, other_addr) = (fast.addr, other.addr);
+        for (mut connection, fast_extension) in [(fast, true), (other, false)] {
+            connection.fast_extension = fast_extension;
+            connection.peer_choking = false;
+            connection.am_interested = true;
+            let mut session = PeerSession::new(connection);
+            session.peer_bitfield = Some(Bitfield::complete(1));
+            let addr = session.connection.addr;
+            sessions.write().await.insert(addr, session);
+        }
+
+        let block = BlockInfo::new(0, 0, 16384);
+        let request = Message::Request { index: 0, begin: 0, length: 16384 };
+        PeerManager::request_pieces(fast_addr, sessions.clone(), piece_manager.clone()).await.unwrap();
+        assert_eq!(fast_remote.recv_message().await.unwrap(), request);
+
+        let key = sessions.read().await[&fast_addr].key;
+        let handler = tokio::spawn(PeerManager::handle_peer(
+            fast_addr,
+            sessions.clone(),
+            piece_manager.clone(),
+            disk_manager,
+            key,
+            Arc::new(AtomicBool::new(false)),
+            CancellationToken::new(),
+            None,
+            None,
+            Arc::new(TransferLog::default()),
+            Arc::new(EngineMetrics::default()),
+            Arc::new(IdleWatch::default()),
+        ));
+
+        // The seed won't serve it: the other peer is asked at once, no 30 s wait
+        fast_remote
+            .send_message(&Message::RejectRequest { index: 0, begin: 0, length: 16384 })
+            .await
+            .unwrap();
+        let requeued = time::timeout(Duration::from_secs(5), other_remote.recv_message())
+            .await
+            .expect("the block should be requested from the other peer")
+            .unwrap();
+        assert_eq!(requeued, request);
+        assert!(sessions.read().await[&other_addr].pending_requests.contains_key(&block));
+
+        // We're choking the seed and don't have the piece: it hears so too
+        fast_remote.send_message(&request).await.unwrap();
+        assert_eq!(
+            fast_remote.recv_message().await.unwrap(),
+            Message::RejectRequest { index: 0, begin: 0, length: 16384 }
+        );
+
+        handler.abort();
+    }
+
+    #[tokio::test]
+    async fn test_wasted_blocks_are_counted() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let metainfo = crate::torrent::Metainfo::from_bytes(
+            b"d8:announce14:http://tracker4:infod6:lengthi32768e4:name1:a12:piece lengthi32768e6:pieces20:12345678901234567890ee",
+        )
+        .unwrap();
+        let disk_manager = Arc::new(RwLock::new(DiskManager::new(&metainfo, temp_dir.path().to_path_buf())));
+        // One piece of two blocks, whose hash nothing will match
+        let piece_manager = Arc::new(RwLock::new(PieceManager::new(
+            1,
+            32768,
+            32768,
+            vec![vec![0u8; 20]],
+            crate::piece::SelectionStrategy::RarestFirst,
+        )));
+
+        let (mut ours, mut remote) = loopback_pair().await;
+        let addr = ours.addr;
+        let meter = Arc::new(TrafficMeter::new());
+        ours.set_traffic_meter(meter.clone());
+        ours.peer_choking = false;
+        ours.am_interested = true;
+        let mut session = PeerSession::new(ours);
+        session.peer_bitfield = Some(Bitfield::complete(1));
+        let key = session.key;
+        let sessions = Arc::new(RwLock::new(HashMap::from([(addr, session)])));
+
+        PeerManager::request_pieces(addr, sessions.clone(), piece_manager.clone()).await.unwrap();
+        for begin in [0, 16384] {
+            assert_eq!(remote.recv_message().await.unwrap(), Message::Request { index: 0, begin, length: 16384 });
+        }
+        // Endgame: another peer already delivered the first block
+        piece_manager.write().await.write_block(BlockInfo::new(0, 0, 16384), &[1; 16384]).unwrap();
+
+        let metrics = Arc::new(EngineMetrics::default());
+        let handler = tokio::spawn(PeerManager::handle_peer(
+            addr,
+            sessions.clone(),
+            piece_manager.clone(),
+            disk_manager.clone(),
+            key,
+            Arc::new(AtomicBool::new(false)),
+            CancellationToken::new(),
+            None,
+            None,
+            Arc::new(TransferLog::default()),
+            metrics.clone(),
+            Arc::new(IdleWatch::default()),
+        ));
+        // Hold the disk so the completed piece waits in verification
+        let disk = disk_manager.write().await;
+        let blocks = [
+            // Never requested
+            Message::Piece { index: 0, begin: 4096, data: vec![2; 100] },
+            Message::Piece { index: 0, begin: 0, data: vec![3; 16384] },
+            // Completes the piece, which then fails verification
+            Message::Piece { index: 0, begin: 16384, data: vec![4; 16384] },
+        ];
+        for block in &blocks {
+            remote.send_message(block).await.unwrap();
+        }
+        time::timeout(Duration::from_secs(5), async {
+            while metrics.snapshot().pending_verifications == 0 {
+                time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("the piece should wait for verification");
+        drop(disk);
+        let result = time::timeout(Duration::from_secs(5), handler).await.unwrap().unwrap();
+        assert_eq!(metrics.snapshot().pending_verifications, 0);
+        assert!(result.is_err(), "a hash failure drops the peer");
+
+        let traffic = meter.snapshot();
+        assert_eq!(traffic.discarded_downloaded, 100);
+        assert_eq!(traffic.redundant_downloaded, 16384);
+        assert_eq!(traffic.failed_downloaded, 32768);
+        assert_eq!(traffic.payload_downloaded, 100 + 16384 * 2);
+    }
+
+    #[tokio::test]
+    async fn test_allowed_fast_pieces_requested_while_choked() {
+        let (ours, mut remote) = loopback_pair().await;
+        let addr = ours.addr;
+        let piece_manager = create_piece_manager(4);
+
+        let mut session = PeerSession::new(ours);
+        session.connection.fast_extension = true;
+        session.connection.am_interested = true;
+        session.peer_bitfield = Some(Bitfield::complete(4));
+        let sessions = Arc::new(RwLock::new(HashMap::new()));
+        sessions.write().await.insert(addr, session);
+
+        // Choked without allowed pieces: nothing to ask for
+        PeerManager::request_pieces(addr, sessions.clone(), piece_manager.clone()).await.unwrap();
+        assert!(sessions.read().await[&addr].pending_requests.is_empty());
+
+        sessions.write().await.get_mut(&addr).unwrap().allowed_fast_in.insert(2);
+        PeerManager::request_pieces(addr, sessions.clone(), piece_manager.clone()).await.unwrap();
+        assert_eq!(remote.recv_message().await.unwrap(), Message::Request { index: 2, begin: 0, length: 16384 });
+        let pending: Vec<usize> = sessions.read().await[&addr].pending_requests.keys().map(|b| b.piece_index).collect();
+        assert_eq!(pending, vec![2]);
+    }
+
+    #[tokio::test]
+    async fn test_oversized_message_disconnects_and_reports_peer() {
+        use tokio::io::AsyncWriteExt;
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let metainfo = crate::torrent::Metainfo::from_bytes(
+            b"d8:announce14:http://tracker4:infod6:lengthi16384e4:name1:a12:piece lengthi16384e6:pieces20:12345678901234567890ee",
+        )
+        .unwrap();
+        let disk_manager = Arc::new(RwLock::new(DiskManager::new(&metainfo, temp_dir.path().to_path_buf())));
+        let piece_manager = create_piece_manager(1);
+
+        let (ours, mut remote) = loopback_pair().await;
+        let addr = ours.addr;
+        let session = PeerSession::new(ours);
+        let key = session.key;
+        let sessions = Arc::new(RwLock::new(HashMap::from([(addr, session)])));
+        let (violation_tx, mut violation_rx) = mpsc::unbounded_channel();
+        let handler = tokio::spawn(PeerManager::handle_peer(
+            addr,
+            sessions.clone(),
+            piece_manager,
+            disk_manager,
+            key,
+            Arc::new(AtomicBool::new(false)),
+            CancellationToken::new(),
+            None,
+            Some(violation_tx),
+            Arc::new(TransferLog::default()),
+            Arc::new(EngineMetrics::default()),
+            Arc::new(IdleWatch::default()),
+        ));
+
+        // A 2 GB message is refused from its length prefix alone
+        remote.stream.write_all(&(2u32 << 30).to_be_bytes()).await.unwrap();
+        let result = time::timeout(Duration::from_secs(5), handler).await.unwrap().unwrap();
+        let disconnect = result.unwrap_err();
+        assert_eq!(disconnect.reason, DisconnectReason::ProtocolError);
+        assert!(disconnect.detail.contains("Protocol violation"));
+        assert_eq!(violation_rx.try_recv().unwrap(), addr);
+        assert!(sessions.read().await.contains_key(&addr), "left for the cleanup");
+    }
+
+    #[tokio::test]
+    async fn test_upload_refuses_unverified_pieces() {
+        let (ours, mut remote) = loopback_pair().await;
+        let addr = ours.addr;
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let metainfo = crate::torrent::Metainfo::from_bytes(
+            b"d8:announce14:http://tracker4:infod6:lengthi32768e4:name1:a12:piece_lengthi16384e6:pieces40:1234567890123456789012345678901234567890ee",
+        )
+        .unwrap();
+        let disk_manager = Arc::new(RwLock::new(DiskManager::new(&metainfo, temp_dir.path().to_path_buf())));
+        disk_manager.read().await.allocate_files().await.unwrap();
+        let piece_manager = create_piece_manager(2);
+        let sessions = Arc::new(RwLock::new(HashMap::new()));
+        sessions.write().await.insert(addr, PeerSession::new(ours));
+
+        // The bit says we have piece 0, but nothing ever checked it
+        piece_manager.write().await.set_unverified(0);
+        assert!(piece_manager.read().await.has_piece(0));
+        let refused = PeerManager::handle_upload_request(
+            addr, sessions.clone(), piece_manager.clone(), disk_manager.clone(), 0, 0, 16384,
+        )
+        .await;
+        assert!(refused.is_err());
+        assert_eq!(sessions.read().await[&addr].uploaded_bytes, 0);
+
+        // Restored from a session that had verified it: served
+        piece_manager.write().await.restore_bitfield(Bitfield::complete(2).as_bytes());
+        PeerManager::handle_upload_request(
+            addr, sessions.clone(), piece_manager.clone(), disk_manager, 0, 0, 16384,
+        )
+        .await
+        .unwrap();
+        assert!(matches!(
+            remote.recv_message().await.unwrap(),
+            Message::Piece { index: 0, begin: 0, .. }
+        ));
+        assert_eq!(sessions.read().await[&addr].uploaded_bytes, 16384);
+    }
+
+    #[tokio::test]
+    async fn test_short_and_long_blocks_answer_requests() {
+        let (ours, _remote) = loopback_pair().await;
+        let mut session = PeerSession::new(ours);
+        for offset in [0, 16384, 32768, 49152] {
+            session.add_pending_request(BlockInfo::new(0, offset, 16384));
+        }
+
+        // Half a block answers the request at its offset; the rest is asked again later
+        assert_eq!(session.take_answered_request(0, 0, 8192), Some(BlockInfo::new(0, 0, 16384)));
+        // 32 KiB covers the next request too
+        assert_eq!(session.take_answered_request(0, 16384, 32768), Some(BlockInfo::new(0, 16384, 16384)));
+        let pending: Vec<usize> = session.pending_requests.keys().map(|b| b.offset).collect();
+        assert_eq!(pending, vec![49152]);
+        assert_eq!(session.take_answered_request(0, 100, 16384), None);
+        assert_eq!(session.take_answered_request(1, 49152, 16384), None);
+    }
+
+    #[tokio::test]
+    async fn test_oversized_requests_are_rejected() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let metainfo = crate::torrent::Metainfo::from_bytes(
+            b"d8:announce14:http://tracker4:infod6:lengthi16384e4:name1:a12:piece lengthi16384e6:pieces20:12345678901234567890ee",
+        )
+        .unwrap();
+        let disk_manager = Arc::new(RwLock::new(DiskManager::new(&metainfo, temp_dir.path().to_path_buf())));
+        disk_manager.read().await.allocate_files().await.unwrap();
+        let piece_manager = create_piece_manager(1);
+        piece_manager.write().await.restore_bitfield(Bitfield::complete(1).as_bytes());
+
+        let (ours, mut remote) = loopback_pair().await;
+        let addr = ours.addr;
+        let mut session = PeerSession::new(ours);
+        session.connection.fast_extension = true;
+        session.connection.am_choking = false;
+        session.max_request_length = 8192;
+        let key = session.key;
+        let sessions = Arc::new(RwLock::new(HashMap::from([(addr, session)])));
+        let handler = tokio::spawn(PeerManager::handle_peer(
+            addr,
+            sessions.clone(),
+            piece_manager,
+            disk_manager,
+            key,
+            Arc::new(AtomicBool::new(false)),
+            CancellationToken::new(),
+            None,
+            None,
+            Arc::new(TransferLog::default()),
+            Arc::new(EngineMetrics::default()),
+            Arc::new(IdleWatch::default()),
+        ));
+
+        let refused = [
+            // Over the limit
+            Message::Request { index: 0, begin: 0, length: 16384 },
+            // Past the end of the piece
+            Message::Request { index: 0, begin: 12288, length: 8192 },
+            Message::Request { index: 0, begin: 0, length: 0 },
+            Message::Request { index: 1, begin: 0, length: 4096 },
+        ];
+        for request in &refused {
+            remote.send_message(request).await.unwrap();
+            let Message::Request { index, begin, length } = *request else { unreachable!() };
+            assert_eq!(remote.recv_message().await.unwrap(), Message::RejectRequest { index, begin, length });
+        }
+        // Up to the limit, anything inside the piece is served
+        remote.send_message(&Message::Request { index: 0, begin: 10000, length: 6384 }).await.unwrap();
+        match remote.recv_message().await.unwrap() {
+            Message::Piece { index: 0, begin: 10000, data } => assert_eq!(data.len(), 6384),
+            other => panic!("expected the block, got {:?}", other),
+        }
+        handler.abort();
+    }
+}
@@ -0,0 +1,105 @@
+//! Peers added by hand from the peers tab
+//!
+//! The user types an "ip:port"; it is checked here, then the engine keeps it
+//! in its peer store under `MANUAL_PEER_SOURCE` (see
+//! `TorrentEngine::add_manual_peer`).
+
+use serde::Serialize;
+use std::net::{IpAddr, SocketAddr};
+
+/// `SavedPeer::source` of a peer added by hand
+pub const MANUAL_PEER_SOURCE: &str = "manual";
+
+/// What became of a peer added by hand
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ManualDial {
+    /// The torrent is active: the connection attempt was started
+    Dialing,
+    /// The torrent isn't active: the peer is dialed once it starts
+    Queued,
+}
+
+/// Parse an "ip:port" ("[ip]:port" for IPv6) typed by the user. Host names
+/// are not resolved.
+pub fn parse_peer_addr(input: &str) -> Result<SocketAddr, String> {
+    let input = input.trim();
+    if input.is_empty() {
+        return Err("Enter a peer address as ip:port".to_string());
+    }
+    if let Ok(addr) = input.parse::<SocketAddr>() {
+        if addr.port() == 0 {
+            return Err(format!("Invalid port in {}: 0", input));
+        }
+        return Ok(addr);
+    }
+
+    let bare = input.strip_prefix('[').and_then(|rest| rest.strip_suffix(']')).unwrap_or(input);
+    if let Ok(ip) = bare.parse::<IpAddr>() {
+        return Err(match ip {
+            IpAddr::V4(_) => format!("Missing port in {}: use ip:port", input),
+            IpAddr::V6(_) => format!("Missing port in {}: use [ip]:port for IPv6", input),
+        });
+    }
+    match input.rsplit_once(':') {
+        Some((host, port)) if host.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>().is_ok() => {
+            Err(format!("Invalid port in {}: {}", input, port))
+        }
+        Some((host, _)) => Err(format!("Not an IP address: {}", host)),
+        None => Err(format!("Not an IP address: {}", input)),
+    }
+}
+
+/// Refuse addresses no peer can have, and our own listen address (dialing
+/// it would connect the client to itself)
+pub fn check_peer_addr(addr: SocketAddr, listen_port: u16) -> Result<(), String> {
+    let ip = addr.ip();
+    let unusable = ip.is_unspecified()
+        || ip.is_multicast()
+        || matches!(ip, IpAddr::V4(v4) if v4.is_broadcast());
+    if unusable {
+        return Err(format!("Filtered address: {} can't be a peer", ip));
+    }
+    if ip.is_loopback() && addr.port() == listen_port {
+        return Err(format!("{} is this client's own listen address", addr));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_peer_addr() {
+        assert_eq!(parse_peer_addr(" 10.0.0.5:6881 ").unwrap(), "10.0.0.5:6881".parse().unwrap());
+        assert_eq!(parse_peer_addr("[2001:db8::1]:51413").unwrap(), "[2001:db8::1]:51413".parse().unwrap());
+
+        let missing = |input: &str| parse_peer_addr(input).unwrap_err().starts_with("Missing port");
+        assert!(missing("10.0.0.5"));
+        assert!(missing("2001:db8::1"));
+        assert!(missing("[2001:db8::1]"));
+        // Without brackets the last group reads as part of the address
+        assert!(missing("::1:6881"));
+
+        assert_eq!(parse_peer_addr("10.0.0.5:0").unwrap_err(), "Invalid port in 10.0.0.5:0: 0");
+        assert_eq!(parse_peer_addr("10.0.0.5:70000").unwrap_err(), "Invalid port in 10.0.0.5:70000: 70000");
+        assert_eq!(parse_peer_addr("seedbox.example:6881").unwrap_err(), "Not an IP address: seedbox.example");
+        assert_eq!(parse_peer_addr("seedbox").unwrap_err(), "Not an IP address: seedbox");
+        assert!(parse_peer_addr("").is_err());
+    }
+
+    #[test]
+    fn test_check_peer_addr() {
+        assert!(check_peer_addr("10.0.0.5:6881".parse().unwrap(), 6881).is_ok());
+        // Another client on this machine is fine, this one isn't
+        assert!(check_peer_addr("127.0.0.1:6882".parse().unwrap(), 6881).is_ok());
+        assert!(check_peer_addr("127.0.0.1:6881".parse().unwrap(), 6881).unwrap_err().contains("own listen address"));
+        assert!(check_peer_addr("[::1]:6881".parse().unwrap(), 6881).is_err());
+
+        for filtered in ["0.0.0.0:6881", "224.0.0.1:6881", "255.255.255.255:6881", "[ff02::1]:6881", "[::]:6881"] {
+            let err = check_peer_addr(filtered.parse().unwrap(), 6881).unwrap_err();
+            assert!(err.starts_with("Filtered address"), "{}: {}", filtered, err);
+        }
+    }
+}
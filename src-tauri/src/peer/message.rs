@@ -7,6 +7,35 @@
 
 use crate::error::{Error, Result};
 
+/// Length of a Piece message carrying a full block: id, index, begin, data
+const FULL_PIECE_MESSAGE: u32 = 1 + 8 + crate::piece::BLOCK_SIZE as u32;
+
+/// Extra room over a full block, for clients that pad or send a bit more
+const LENGTH_SLACK: u32 = 1024;
+
+/// Longest message accepted from a peer unless configured otherwise. The
+/// length prefix is checked against it before anything is allocated, so a
+/// peer claiming a huge message can't make us reserve the memory.
+pub const DEFAULT_MAX_MESSAGE_LENGTH: u32 = FULL_PIECE_MESSAGE + LENGTH_SLACK;
+
+/// The message length limit for a torrent of `num_pieces` pieces: `max`,
+/// raised if needed so the torrent's full bitfield still fits
+pub fn max_message_length(max: u32, num_pieces: usize) -> u32 {
+    let bitfield = 1 + num_pieces.div_ceil(8);
+    max.max(u32::try_from(bitfield).unwrap_or(u32::MAX))
+}
+
+/// Check a length prefix read off the wire against `max`
+pub fn check_length(length: u32, max: u32) -> Result<()> {
+    if length > max {
+        return Err(Error::ProtocolViolation(format!(
+            "message too large: {} bytes (max: {} bytes)",
+            length, max
+        )));
+    }
+    Ok(())
+}
+
 /// Message ID constants
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u8)]
@@ -20,6 +49,12 @@ pub enum MessageId {
     Request = 6,
     Piece = 7,
     Cancel = 8,
+    // Fast Extension (BEP 6), only with peers that set the handshake bit
+    SuggestPiece = 0x0D,
+    HaveAll = 0x0E,
+    HaveNone = 0x0F,
+    RejectRequest = 0x10,
+    AllowedFast = 0x11,
 }
 
 impl MessageId {
@@ -35,7 +70,12 @@ impl MessageId {
             6 => Ok(Self::Request),
             7 => Ok(Self::Piece),
             8 => Ok(Self::Cancel),
-            _ => Err(Error::InvalidData(format!("unknown message ID: {}", value))),
+            0x0D => Ok(Self::SuggestPiece),
+            0x0E => Ok(Self::HaveAll),
+            0x0F => Ok(Self::HaveNone),
+            0x10 => Ok(Self::RejectRequest),
+            0x11 => Ok(Self::AllowedFast),
+            _ => Err(Error::ProtocolViolation(format!("unknown message ID: {}", value))),
         }
     }
 }
@@ -76,6 +116,21 @@ pub enum Message {
 
     /// Cancel a request
     Cancel { index: u32, begin: u32, length: u32 },
+
+    /// Hint that a piece would be a good one to download (fast extension)
+    SuggestPiece { piece_index: u32 },
+
+    /// Instead of a bitfield: the sender has every piece (fast extension)
+    HaveAll,
+
+    /// Instead of a bitfield: the sender has no pieces (fast extension)
+    HaveNone,
+
+    /// A request won't be served (fast extension)
+    RejectRequest { index: u32, begin: u32, length: u32 },
+
+    /// The piece may be requested even while choked (fast extension)
+    AllowedFast { piece_index: u32 },
 }
 
 impl Message {
@@ -91,14 +146,14 @@ impl Message {
         match id {
             MessageId::Choke => {
                 if !payload.is_empty() {
-                    return Err(Error::InvalidData("choke must have no payload".to_string()));
+                    return Err(Error::ProtocolViolation("choke must have no payload".to_string()));
                 }
                 Ok(Self::Choke)
             }
 
             MessageId::Unchoke => {
                 if !payload.is_empty() {
-                    return Err(Error::InvalidData(
+                    return Err(Error::ProtocolViolation(
                         "unchoke must have no payload".to_string(),
                     ));
                 }
@@ -107,7 +162,7 @@ impl Message {
 
             MessageId::Interested => {
                 if !payload.is_empty() {
-                    return Err(Error::InvalidData(
+                    return Err(Error::ProtocolViolation(
                         "interested must have no payload".to_string(),
                     ));
                 }
@@ -116,21 +171,14 @@ impl Message {
 
             MessageId::NotInterested => {
                 if !payload.is_empty() {
-                    return Err(Error::InvalidData(
+                    return Err(Error::ProtocolViolation(
                         "not interested must have no payload".to_string(),
                     ));
                 }
                 Ok(Self::NotInterested)
             }
 
-            MessageId::Have => {
-                if payload.len() != 4 {
-                    return Err(Error::InvalidData("have must be 4 bytes".to_string()));
-                }
-                let piece_index =
-                    u32::from_be_bytes([payload[0], payload[1], payload[2], payload[3]]);
-                Ok(Self::Have { piece_index })
-            }
+            MessageId::Have => Ok(Self::Have { piece_index: piece_index(payload, "have")? }),
 
             MessageId::Bitfield => Ok(Self::Bitfield {
                 bitfield: payload.to_vec(),
@@ -138,7 +186,7 @@ impl Message {
 
             MessageId::Request => {
                 if payload.len() != 12 {
-                    return Err(Error::InvalidData("request must be 12 bytes".to_string()));
+                    return Err(Error::ProtocolViolation("request must be 12 bytes".to_string()));
                 }
 
                 let index = u32::from_be_bytes([payload[0], payload[1], payload[2], payload[3]]);
@@ -154,7 +202,7 @@ impl Message {
 
             MessageId::Piece => {
                 if payload.len() < 8 {
-                    return Err(Error::InvalidData(
+                    return Err(Error::ProtocolViolation(
                         "piece must be at least 8 bytes".to_string(),
                     ));
                 }
@@ -168,7 +216,7 @@ impl Message {
 
             MessageId::Cancel => {
                 if payload.len() != 12 {
-                    return Err(Error::InvalidData("cancel must be 12 bytes".to_string()));
+                    return Err(Error::ProtocolViolation("cancel must be 12 bytes".to_string()));
                 }
 
                 let index = u32::from_be_bytes([payload[0], payload[1], payload[2], payload[3]]);
@@ -181,6 +229,44 @@ impl Message {
                     length,
                 })
             }
+
+            MessageId::SuggestPiece => Ok(Self::SuggestPiece {
+                piece_index: piece_index(payload, "suggest piece")?,
+            }),
+
+            MessageId::HaveAll => {
+                if !payload.is_empty() {
+                    return Err(Error::ProtocolViolation("have all must have no payload".to_string()));
+                }
+                Ok(Self::HaveAll)
+            }
+
+            MessageId::HaveNone => {
+                if !payload.is_empty() {
+                    return Err(Error::ProtocolViolation("have none must have no payload".to_string()));
+                }
+                Ok(Self::HaveNone)
+            }
+
+            MessageId::RejectRequest => {
+                if payload.len() != 12 {
+                    return Err(Error::ProtocolViolation("reject request must be 12 bytes".to_string()));
+                }
+
+                let index = u32::from_be_bytes([payload[0], payload[1], payload[2], payload[3]]);
+                let begin = u32::from_be_bytes([payload[4], payload[5], payload[6], payload[7]]);
+                let length = u32::from_be_bytes([payload[8], payload[9], payload[10], payload[11]]);
+
+                Ok(Self::RejectRequest {
+                    index,
+                    begin,
+                    length,
+                })
+            }
+
+            MessageId::AllowedFast => Ok(Self::AllowedFast {
+                piece_index: piece_index(payload, "allowed fast")?,
+            }),
         }
     }
 
@@ -259,24 +345,84 @@ impl Message {
                 bytes.extend_from_slice(&begin.to_be_bytes());
                 bytes.extend_from_slice(&length.to_be_bytes());
             }
+
+            Self::SuggestPiece { piece_index } => {
+                bytes.extend_from_slice(&5u32.to_be_bytes()); // Length: 1 + 4
+                bytes.push(MessageId::SuggestPiece as u8);
+                bytes.extend_from_slice(&piece_index.to_be_bytes());
+            }
+
+            Self::HaveAll => {
+                bytes.extend_from_slice(&1u32.to_be_bytes());
+                bytes.push(MessageId::HaveAll as u8);
+            }
+
+            Self::HaveNone => {
+                bytes.extend_from_slice(&1u32.to_be_bytes());
+                bytes.push(MessageId::HaveNone as u8);
+            }
+
+            Self::RejectRequest {
+                index,
+                begin,
+                length,
+            } => {
+                bytes.extend_from_slice(&13u32.to_be_bytes()); // Length: 1 + 12
+                bytes.push(MessageId::RejectRequest as u8);
+                bytes.extend_from_slice(&index.to_be_bytes());
+                bytes.extend_from_slice(&begin.to_be_bytes());
+                bytes.extend_from_slice(&length.to_be_bytes());
+            }
+
+            Self::AllowedFast { piece_index } => {
+                bytes.extend_from_slice(&5u32.to_be_bytes()); // Length: 1 + 4
+                bytes.push(MessageId::AllowedFast as u8);
+                bytes.extend_from_slice(&piece_index.to_be_bytes());
+            }
         }
 
         bytes
     }
 
+    /// Whether this is a Fast Extension message, which peers may only send
+    /// on connections where both handshakes set the bit
+    pub fn is_fast_extension(&self) -> bool {
+        matches!(
+            self,
+            Self::SuggestPiece { .. }
+                | Self::HaveAll
+                | Self::HaveNone
+                | Self::RejectRequest { .. }
+                | Self::AllowedFast { .. }
+        )
+    }
+
     /// Get the message length (for the length prefix)
     pub fn length(&self) -> u32 {
         match self {
             Self::KeepAlive => 0,
-            Self::Choke | Self::Unchoke | Self::Interested | Self::NotInterested => 1,
-            Self::Have { .. } => 5,
+            Self::Choke
+            | Self::Unchoke
+            | Self::Interested
+            | Self::NotInterested
+            | Self::HaveAll
+            | Self::HaveNone => 1,
+            Self::Have { .. } | Self::SuggestPiece { .. } | Self::AllowedFast { .. } => 5,
             Self::Bitfield { bitfield } => 1 + bitfield.len() as u32,
-            Self::Request { .. } | Self::Cancel { .. } => 13,
+            Self::Request { .. } | Self::Cancel { .. } | Self::RejectRequest { .. } => 13,
             Self::Piece { data, .. } => 1 + 8 + data.len() as u32,
         }
     }
 }
 
+/// The piece index payload of Have, SuggestPiece and AllowedFast
+fn piece_index(payload: &[u8], name: &str) -> Result<u32> {
+    let bytes: [u8; 4] = payload
+        .try_into()
+        .map_err(|_| Error::ProtocolViolation(format!("{} must be 4 bytes", name)))?;
+    Ok(u32::from_be_bytes(bytes))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -362,4 +508,105 @@ mod tests {
 
         assert_eq!(parsed, msg);
     }
+
+    #[test]
+    fn test_fast_extension_messages() {
+        let messages = [
+            Message::SuggestPiece { piece_index: 7 },
+            Message::HaveAll,
+            Message::HaveNone,
+            Message::RejectRequest { index: 3, begin: 16384, length: 16384 },
+            Message::AllowedFast { piece_index: 1059 },
+        ];
+        for msg in messages {
+            let bytes = msg.to_bytes();
+            assert_eq!(bytes.len(), 4 + msg.length() as usize);
+            assert_eq!(&bytes[..4], &msg.length().to_be_bytes());
+            assert_eq!(Message::from_bytes(&bytes[4..]).unwrap(), msg);
+        }
+
+        assert_eq!(Message::HaveAll.to_bytes(), vec![0, 0, 0, 1, 0x0E]);
+        assert_eq!(Message::AllowedFast { piece_index: 2 }.to_bytes(), vec![0, 0, 0, 5, 0x11, 0, 0, 0, 2]);
+
+        // Wrong payload sizes are rejected
+        assert!(Message::from_bytes(&[0x0E, 0]).is_err());
+        assert!(Message::from_bytes(&[0x11, 0, 0, 2]).is_err());
+        assert!(Message::from_bytes(&[0x10, 0, 0, 0, 1]).is_err());
+    }
+
+    /// Random bytes, seeded so a failure reproduces
+    fn random_buffers(seed: u64, count: usize, max_len: usize) -> Vec<Vec<u8>> {
+        use rand::{Rng, SeedableRng};
+        let mut rng = rand::rngs::StdRng::seed_from_u64(seed);
+        (0..count)
+            .map(|_| {
+                let len = rng.gen_range(0..=max_len);
+                let mut buffer: Vec<u8> = (0..len).map(|_| rng.gen()).collect();
+                // Bias towards known IDs so the payload checks get exercised
+                if let Some(id) = buffer.first_mut() {
+                    *id %= 0x12;
+                }
+                buffer
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_random_buffers_never_panic() {
+        for buffer in random_buffers(0x5eed, 20_000, 40) {
+            match Message::from_bytes(&buffer) {
+                // Whatever parsed must have had exactly the bytes it encodes
+                Ok(msg) => assert_eq!(&msg.to_bytes()[4..], &buffer[..]),
+                Err(e) => assert!(matches!(e, Error::ProtocolViolation(_)), "{:?}", e),
+            }
+        }
+    }
+
+    #[test]
+    fn test_truncated_messages_rejected() {
+        let messages = [
+            Message::Have { piece_index: 9 },
+            Message::Request { index: 1, begin: 16384, length: 16384 },
+            Message::Cancel { index: 1, begin: 16384, length: 16384 },
+            Message::Piece { index: 2, begin: 0, data: vec![7; 32] },
+            Message::SuggestPiece { piece_index: 3 },
+            Message::RejectRequest { index: 1, begin: 0, length: 16384 },
+            Message::AllowedFast { piece_index: 4 },
+        ];
+        for msg in messages {
+            let bytes = msg.to_bytes();
+            let body = &bytes[4..];
+            // Piece only needs its 8 byte header; shorter data is still a piece
+            let minimum = if matches!(msg, Message::Piece { .. }) { 1 + 8 } else { body.len() };
+            for cut in 1..minimum {
+                let result = Message::from_bytes(&body[..cut]);
+                assert!(matches!(result, Err(Error::ProtocolViolation(_))), "{:?} cut to {}", msg, cut);
+            }
+            // One byte too many is as wrong as one too few, except for data
+            let mut longer = body.to_vec();
+            longer.push(0);
+            assert_eq!(Message::from_bytes(&longer).is_ok(), matches!(msg, Message::Piece { .. }));
+        }
+        assert!(matches!(Message::from_bytes(&[0x42]), Err(Error::ProtocolViolation(_))));
+    }
+
+    #[test]
+    fn test_length_limit() {
+        // A full block fits, with room to spare, but not much more
+        let full_block = Message::Piece { index: 0, begin: 0, data: vec![0; crate::piece::BLOCK_SIZE] };
+        assert!(check_length(full_block.length(), DEFAULT_MAX_MESSAGE_LENGTH).is_ok());
+        assert!(DEFAULT_MAX_MESSAGE_LENGTH < 32 * 1024);
+        assert!(matches!(
+            check_length(u32::MAX, DEFAULT_MAX_MESSAGE_LENGTH),
+            Err(Error::ProtocolViolation(_))
+        ));
+
+        // Large torrents still get their whole bitfield through
+        assert_eq!(max_message_length(DEFAULT_MAX_MESSAGE_LENGTH, 1000), DEFAULT_MAX_MESSAGE_LENGTH);
+        let bitfield = Message::Bitfield { bitfield: vec![0xFF; 1_000_000 / 8] };
+        let limit = max_message_length(DEFAULT_MAX_MESSAGE_LENGTH, 1_000_000);
+        assert_eq!(limit, bitfield.length());
+        assert!(check_length(bitfield.length(), limit).is_ok());
+        assert!(check_length(limit + 1, limit).is_err());
+    }
 }
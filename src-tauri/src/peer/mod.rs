@@ -2,13 +2,23 @@
 //! 
 //! Implements the BitTorrent wire protocol for communicating with peers.
 
+pub mod choking;
+pub mod disconnect;
+pub mod fast;
 pub mod handshake;
 pub mod manager;
+pub mod manual;
 pub mod message;
+pub mod outbox;
+pub mod pacer;
+pub mod reaper;
+pub mod traffic;
 
 pub use handshake::Handshake;
 pub use manager::{PeerManager, PeerManagerCommand, PeerManagerStats};
 pub use message::{Message, MessageId};
+pub use pacer::DialPacer;
+pub use traffic::{TrafficMeter, TrafficStats, Waste};
 
 use serde::{Deserialize, Serialize};
 
@@ -21,7 +31,7 @@ pub struct PeerInfo {
     pub port: u16,
     /// Client name (parsed from peer_id)
     pub client: String,
-    /// Connection flags (D=downloading, U=uploading, etc.)
+    /// Connection flags (D=downloading, U=uploading, M=added by hand, etc.)
     pub flags: String,
     /// Peer's download progress (0.0-100.0)
     pub progress: f64,
@@ -33,6 +43,32 @@ pub struct PeerInfo {
     pub downloaded: u64,
     /// Total uploaded to this peer (bytes)
     pub uploaded: u64,
+    /// Protocol overhead received from this peer (bytes)
+    pub overhead_downloaded: u64,
+    /// Protocol overhead sent to this peer (bytes)
+    pub overhead_uploaded: u64,
+    /// Why the choking algorithm last choked or unchoked this peer
+    #[serde(default)]
+    pub choke_reason: Option<choking::ChokeReason>,
+}
+
+/// A peer address kept with the session so a restart can dial it right away
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SavedPeer {
+    pub addr: std::net::SocketAddr,
+    /// Tracker that reported it first
+    pub source: String,
+    /// Higher is better (tracker reports, plus a bonus for sending data)
+    pub score: u32,
+    /// Last time a tracker reported it or it sent us data (unix timestamp)
+    pub last_seen: i64,
+}
+
+impl SavedPeer {
+    /// Added by hand by the user (see `manual`)
+    pub fn is_manual(&self) -> bool {
+        self.source == manual::MANUAL_PEER_SOURCE
+    }
 }
 
 use crate::error::Result;
@@ -65,6 +101,18 @@ pub struct PeerConnection {
     
     /// Bitfield of pieces the peer has
     pub bitfield: Option<Vec<u8>>,
+
+    /// Both handshakes set the fast extension bit (BEP 6)
+    pub fast_extension: bool,
+
+    /// Bytes sent/received on this socket
+    traffic: traffic::ConnectionTraffic,
+
+    /// Messages queued with `queue_message`, not yet written
+    outbox: outbox::Outbox,
+
+    /// Longest message accepted from the peer (see `message::max_message_length`)
+    max_message_length: u32,
 }
 
 impl PeerConnection {
@@ -79,8 +127,32 @@ impl PeerConnection {
             am_choking: true,
             am_interested: false,
             bitfield: None,
+            fast_extension: false,
+            traffic: traffic::ConnectionTraffic::default(),
+            outbox: outbox::Outbox::default(),
+            max_message_length: message::DEFAULT_MAX_MESSAGE_LENGTH,
         }
     }
+
+    /// Accept messages up to `length` bytes from the peer
+    pub fn set_max_message_length(&mut self, length: u32) {
+        self.max_message_length = length;
+    }
+
+    /// Also count this connection's traffic towards a torrent-wide meter
+    pub fn set_traffic_meter(&mut self, meter: std::sync::Arc<TrafficMeter>) {
+        self.traffic.torrent = Some(meter);
+    }
+
+    /// Payload/overhead totals for this connection
+    pub fn traffic(&self) -> TrafficStats {
+        self.traffic.stats
+    }
+
+    /// Count received payload that was of no use (see `Waste`)
+    pub fn record_waste(&mut self, waste: Waste, bytes: u64) {
+        self.traffic.record_waste(waste, bytes);
+    }
     
     /// Connect to a peer
     pub async fn connect(addr: SocketAddr) -> Result<Self> {
@@ -91,7 +163,13 @@ impl PeerConnection {
         )
         .await
         .map_err(|_| crate::error::Error::NetworkError(format!("Connection to {} timed out", addr)))?
-        .map_err(|e| crate::error::Error::NetworkError(format!("Failed to connect: {}", e)))?;
+        .map_err(|e| {
+            if crate::resources::note_io_error(&e) {
+                crate::error::Error::TooManyOpenFiles(format!("Failed to connect to {}: {}", addr, e))
+            } else {
+                crate::error::Error::NetworkError(format!("Failed to connect: {}", e))
+            }
+        })?;
         
         Ok(Self::new(stream, addr))
     }
@@ -113,6 +191,7 @@ impl PeerConnection {
                 self.stream.write_all(&handshake_bytes)
                     .await
                     .map_err(|e| crate::error::Error::NetworkError(format!("Failed to send handshake: {}", e)))?;
+                self.traffic.record_sent(0, traffic::HANDSHAKE_WIRE_SIZE);
                 
                 tracing::debug!("Sent handshake to {}", self.addr);
                 
@@ -121,17 +200,22 @@ impl PeerConnection {
                 self.stream.read_exact(&mut buf)
                     .await
                     .map_err(|e| crate::error::Error::NetworkError(format!("Failed to read handshake: {}", e)))?;
+                self.traffic.record_received(0, traffic::HANDSHAKE_WIRE_SIZE);
                 
                 let peer_handshake = Handshake::from_bytes(&buf)?;
                 
                 // Verify info hash matches
                 if peer_handshake.info_hash != info_hash {
-                    return Err(crate::error::Error::NetworkError(
-                        "Info hash mismatch".to_string()
-                    ));
+                    return Err(crate::error::Error::InfoHashMismatch(format!(
+                        "{} answered for {}",
+                        self.addr,
+                        hex::encode(peer_handshake.info_hash)
+                    )));
                 }
                 
                 self.peer_id = Some(peer_handshake.peer_id);
+                self.fast_extension = our_handshake.supports_extension(handshake::FAST_EXTENSION_BIT)
+                    && peer_handshake.supports_extension(handshake::FAST_EXTENSION_BIT);
                 
                 tracing::debug!("Received handshake from {}", self.addr);
                 
@@ -139,17 +223,52 @@ impl PeerConnection {
             }
         )
         .await
-        .map_err(|_| crate::error::Error::NetworkError(format!("Handshake with {} timed out", self.addr)))?
+        .map_err(|_| crate::error::Error::Timeout(format!("Handshake with {} timed out", self.addr)))?
     }
     
-    /// Send a message to the peer
+    /// Send a message to the peer, along with anything queued before it
     pub async fn send_message(&mut self, message: &Message) -> Result<()> {
-        let bytes = message.to_bytes();
-        
-        self.stream.write_all(&bytes)
+        self.outbox.push(message.clone());
+        self.flush_outbox().await
+    }
+
+    /// Queue a message to go out with the next flush (see `outbox`)
+    pub fn queue_message(&mut self, message: Message) {
+        self.outbox.push(message);
+    }
+
+    /// Drop a queued Have the peer no longer needs
+    pub fn retract_have(&mut self, piece_index: u32) {
+        self.outbox.retract_have(piece_index);
+    }
+
+    /// Whether queued messages should be written now
+    pub fn outbox_due(&self, now: std::time::Instant) -> bool {
+        self.outbox.is_due(now)
+    }
+
+    /// Whether the queue is big enough to write without waiting
+    pub fn outbox_full(&self) -> bool {
+        self.outbox.is_full()
+    }
+
+    /// Write every queued message in one batch
+    pub async fn flush_outbox(&mut self) -> Result<()> {
+        if self.outbox.is_empty() {
+            return Ok(());
+        }
+        let messages = self.outbox.take();
+        let buffers: Vec<Vec<u8>> = messages.iter().map(Message::to_bytes).collect();
+
+        outbox::write_batch(&mut self.stream, &buffers)
             .await
             .map_err(|e| crate::error::Error::NetworkError(format!("Failed to send message: {}", e)))?;
-        
+
+        for message in &messages {
+            let (payload, overhead) = traffic::wire_split(message);
+            self.traffic.record_sent(payload, overhead);
+        }
+
         Ok(())
     }
     
@@ -167,16 +286,12 @@ impl PeerConnection {
                 
                 let length = u32::from_be_bytes(len_buf);
                 
-                // Protect against malicious peers sending huge messages
-                const MAX_MESSAGE_SIZE: u32 = 128 * 1024 * 1024; // 128MB (large piece)
-                if length > MAX_MESSAGE_SIZE {
-                    return Err(crate::error::Error::InvalidData(
-                        format!("Message too large: {} bytes (max: {} bytes)", length, MAX_MESSAGE_SIZE)
-                    ));
-                }
+                // Refuse before allocating: the stream can't be trusted past this
+                message::check_length(length, self.max_message_length)?;
                 
                 // Handle keep-alive (length = 0)
                 if length == 0 {
+                    self.traffic.record_received(0, 4);
                     return Ok(Message::KeepAlive);
                 }
                 
@@ -186,11 +301,18 @@ impl PeerConnection {
                     .await
                     .map_err(|e| crate::error::Error::NetworkError(format!("Failed to read message payload: {}", e)))?;
                 
-                Message::from_bytes(&payload)
+                let message = Message::from_bytes(&payload);
+                // Unparseable messages still crossed the wire; count them as overhead
+                let (payload_bytes, overhead_bytes) = match message {
+                    Ok(ref msg) => traffic::wire_split(msg),
+                    Err(_) => (0, 4 + length as u64),
+                };
+                self.traffic.record_received(payload_bytes, overhead_bytes);
+                message
             }
         )
         .await
-        .map_err(|_| crate::error::Error::NetworkError(format!("Message receive from {} timed out", self.addr)))?
+        .map_err(|_| crate::error::Error::Timeout(format!("Message receive from {} timed out", self.addr)))?
     }
     
     /// Send keep-alive message
@@ -0,0 +1,304 @@
+//! Per-peer outgoing message batching
+//!
+//! Messages that don't need to go out at once (Haves after a burst of
+//! completed pieces, mostly) wait in the connection's `Outbox` for a few
+//! milliseconds and then leave in a single vectored write. While queued they
+//! are coalesced, which only ever drops messages and never moves one, so the
+//! peer sees everything that is sent in the order it was queued, Choke and
+//! Unchoke included.
+
+use super::Message;
+use std::io::{self, IoSlice};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+/// Queued messages that trigger a flush right away
+pub const MAX_BATCH_MESSAGES: usize = 64;
+
+/// Queued bytes (wire size) that trigger a flush right away
+pub const MAX_BATCH_BYTES: usize = 16 * 1024;
+
+/// Longest a message waits in the queue
+pub const FLUSH_DELAY: Duration = Duration::from_millis(5);
+
+/// A connection's queue of messages not yet written
+#[derive(Debug, Default)]
+pub struct Outbox {
+    queued: Vec<Message>,
+    /// Wire size of `queued`
+    bytes: usize,
+    /// When the oldest queued message was queued
+    since: Option<Instant>,
+}
+
+impl Outbox {
+    /// Queue a message, coalescing it with what is already queued:
+    /// - a Have already queued for the same piece is not queued twice
+    /// - a Cancel for a queued Request drops both (the Request never left)
+    pub fn push(&mut self, message: Message) {
+        match &message {
+            Message::Have { piece_index } => {
+                if self.queued.iter().any(|m| matches!(m, Message::Have { piece_index: p } if p == piece_index)) {
+                    return;
+                }
+            }
+            Message::Cancel { index, begin, length } => {
+                let request = Message::Request { index: *index, begin: *begin, length: *length };
+                if let Some(pos) = self.queued.iter().position(|m| *m == request) {
+                    self.remove(pos);
+                    return;
+                }
+            }
+            _ => {}
+        }
+        self.bytes += wire_size(&message);
+        self.since.get_or_insert_with(Instant::now);
+        self.queued.push(message);
+    }
+
+    /// The peer announced `piece_index` itself: a queued Have for it is moot
+    pub fn retract_have(&mut self, piece_index: u32) {
+        if let Some(pos) = self
+            .queued
+            .iter()
+            .position(|m| matches!(m, Message::Have { piece_index: p } if *p == piece_index))
+        {
+            self.remove(pos);
+        }
+    }
+
+    fn remove(&mut self, pos: usize) {
+        let message = self.queued.remove(pos);
+        self.bytes -= wire_size(&message);
+        if self.queued.is_empty() {
+            self.since = None;
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queued.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.queued.len()
+    }
+
+    /// Big enough to send without waiting
+    pub fn is_full(&self) -> bool {
+        self.queued.len() >= MAX_BATCH_MESSAGES || self.bytes >= MAX_BATCH_BYTES
+    }
+
+    /// Whether the queue should be flushed at `now`
+    pub fn is_due(&self, now: Instant) -> bool {
+        self.is_full() || self.since.is_some_and(|since| now.duration_since(since) >= FLUSH_DELAY)
+    }
+
+    /// Empty the queue, oldest message first
+    pub fn take(&mut self) -> Vec<Message> {
+        self.bytes = 0;
+        self.since = None;
+        std::mem::take(&mut self.queued)
+    }
+}
+
+fn wire_size(message: &Message) -> usize {
+    4 + message.length() as usize
+}
+
+/// Write all of `buffers` in order, with as few (vectored) writes as the
+/// writer allows
+pub async fn write_batch<W: AsyncWrite + Unpin>(writer: &mut W, buffers: &[Vec<u8>]) -> io::Result<()> {
+    let mut index = 0;
+    let mut offset = 0;
+    while index < buffers.len() {
+        let slices: Vec<IoSlice<'_>> = std::iter::once(&buffers[index][offset..])
+            .chain(buffers[index + 1..].iter().map(|b| &b[..]))
+            .map(IoSlice::new)
+            .collect();
+        let mut written = writer.write_vectored(&slices).await?;
+        if written == 0 {
+            return Err(io::ErrorKind::WriteZero.into());
+        }
+        // Advance past what was written
+        while index < buffers.len() && written >= buffers[index].len() - offset {
+            written -= buffers[index].len() - offset;
+            index += 1;
+            offset = 0;
+        }
+        offset += written;
+    }
+    writer.flush().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+    use tokio::io::AsyncReadExt;
+    use tokio::net::{TcpListener, TcpStream};
+
+    fn have(piece_index: u32) -> Message {
+        Message::Have { piece_index }
+    }
+
+    fn request(index: u32) -> Message {
+        Message::Request { index, begin: 0, length: 16384 }
+    }
+
+    fn cancel(index: u32) -> Message {
+        Message::Cancel { index, begin: 0, length: 16384 }
+    }
+
+    #[test]
+    fn test_coalescing_rules() {
+        let mut outbox = Outbox::default();
+        for message in [have(1), have(2), have(1), request(7), request(8), cancel(7), cancel(9)] {
+            outbox.push(message);
+        }
+        // The peer got piece 2 elsewhere meanwhile
+        outbox.retract_have(2);
+        outbox.retract_have(3);
+
+        // An unmatched Cancel still goes out: its Request left earlier
+        assert_eq!(outbox.take(), vec![have(1), request(8), cancel(9)]);
+        assert!(outbox.is_empty());
+        assert!(!outbox.is_due(Instant::now() + FLUSH_DELAY));
+    }
+
+    #[test]
+    fn test_order_kept_across_choke_boundaries() {
+        let mut outbox = Outbox::default();
+        let sequence = [
+            have(1),
+            Message::Choke,
+            have(2),
+            Message::Unchoke,
+            Message::Interested,
+            have(1),
+            Message::Choke,
+            Message::Unchoke,
+            have(3),
+        ];
+        for message in sequence.clone() {
+            outbox.push(message);
+        }
+
+        // Only the repeated Have is gone; nothing moved across a Choke/Unchoke
+        let mut expected = sequence.to_vec();
+        expected.remove(5);
+        assert_eq!(outbox.take(), expected);
+    }
+
+    #[test]
+    fn test_flush_triggers() {
+        let start = Instant::now();
+        let mut outbox = Outbox::default();
+        outbox.push(have(0));
+        assert!(!outbox.is_due(start));
+        assert!(outbox.is_due(start + FLUSH_DELAY * 2));
+
+        for piece in 1..MAX_BATCH_MESSAGES as u32 {
+            outbox.push(have(piece));
+        }
+        assert!(outbox.is_full());
+
+        let mut outbox = Outbox::default();
+        outbox.push(Message::Piece { index: 0, begin: 0, data: vec![0; MAX_BATCH_BYTES] });
+        assert!(outbox.is_full());
+    }
+
+    /// Counts the write calls that reach the underlying writer
+    struct CountingWriter<W> {
+        inner: W,
+        writes: usize,
+    }
+
+    impl<W: AsyncWrite + Unpin> AsyncWrite for CountingWriter<W> {
+        fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+            let poll = Pin::new(&mut self.inner).poll_write(cx, buf);
+            if poll.is_ready() {
+                self.writes += 1;
+            }
+            poll
+        }
+
+        fn poll_write_vectored(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            bufs: &[IoSlice<'_>],
+        ) -> Poll<io::Result<usize>> {
+            let poll = Pin::new(&mut self.inner).poll_write_vectored(cx, bufs);
+            if poll.is_ready() {
+                self.writes += 1;
+            }
+            poll
+        }
+
+        fn is_write_vectored(&self) -> bool {
+            self.inner.is_write_vectored()
+        }
+
+        fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Pin::new(&mut self.inner).poll_flush(cx)
+        }
+
+        fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Pin::new(&mut self.inner).poll_shutdown(cx)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_batch_uses_fewer_writes() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let (ours, accepted) = tokio::join!(TcpStream::connect(listener.local_addr().unwrap()), listener.accept());
+        let mut writer = CountingWriter { inner: ours.unwrap(), writes: 0 };
+        let mut remote = accepted.unwrap().0;
+
+        let haves: Vec<Vec<u8>> = (0..200).map(|piece| have(piece).to_bytes()).collect();
+        let expected: Vec<u8> = haves.concat();
+
+        // One write per message
+        for bytes in &haves {
+            writer.write_all(bytes).await.unwrap();
+        }
+        let unbatched = writer.writes;
+
+        writer.writes = 0;
+        write_batch(&mut writer, &haves).await.unwrap();
+        let batched = writer.writes;
+
+        let mut received = vec![0u8; expected.len() * 2];
+        remote.read_exact(&mut received).await.unwrap();
+        assert_eq!(&received[..expected.len()], &expected[..]);
+        assert_eq!(&received[expected.len()..], &expected[..]);
+
+        assert_eq!(unbatched, 200);
+        // Bounded by the platform's iovec limit, not the message count
+        assert!(batched <= 2, "{} writes", batched);
+    }
+
+    #[tokio::test]
+    async fn test_partial_writes_resume_mid_buffer() {
+        // Accepts at most 3 bytes per call
+        struct Trickle(Vec<u8>);
+        impl AsyncWrite for Trickle {
+            fn poll_write(mut self: Pin<&mut Self>, _: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+                let n = buf.len().min(3);
+                self.0.extend_from_slice(&buf[..n]);
+                Poll::Ready(Ok(n))
+            }
+            fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+                Poll::Ready(Ok(()))
+            }
+            fn poll_shutdown(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+                Poll::Ready(Ok(()))
+            }
+        }
+
+        let buffers: Vec<Vec<u8>> = vec![have(1).to_bytes(), Message::Choke.to_bytes(), Vec::new(), have(2).to_bytes()];
+        let mut writer = Trickle(Vec::new());
+        write_batch(&mut writer, &buffers).await.unwrap();
+        assert_eq!(writer.0, buffers.concat());
+    }
+}
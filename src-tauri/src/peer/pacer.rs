@@ -0,0 +1,460 @@
+//! Global pacing of outbound peer connections
+//!
+//! A successful announce hands every torrent 50 or so peers at once; dialing
+//! them all immediately (times the number of torrents) can overwhelm home
+//! routers' NAT tables. All peer managers draw from one `DialPacer`, which
+//! spaces dials evenly and caps how many are half-open (connecting or
+//! handshaking) at any moment. It also caps connections overall, so peers
+//! can't use up the file descriptors (see `resources`).
+
+use crate::piece::Bitfield;
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::Notify;
+use tokio::time::{self, Instant};
+
+/// New outbound connections per second, across all torrents
+pub const DEFAULT_DIALS_PER_SEC: u32 = 10;
+
+/// Connections that may be connecting/handshaking at the same time
+pub const DEFAULT_MAX_HALF_OPEN: u32 = 20;
+
+/// Evenly spaced dial slots (a token bucket holding a single token)
+#[derive(Debug)]
+struct DialRate {
+    /// Time between dials; zero = unlimited
+    interval: Duration,
+    /// Earliest time the next dial may go out
+    next: Option<Instant>,
+}
+
+impl DialRate {
+    fn new(dials_per_sec: u32) -> Self {
+        let interval = if dials_per_sec == 0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs(1) / dials_per_sec
+        };
+        Self { interval, next: None }
+    }
+
+    /// Take the slot at `now`, or say how long until the next one
+    fn try_take(&mut self, now: Instant) -> Result<(), Duration> {
+        match self.next {
+            Some(next) if next > now => Err(next - now),
+            _ => {
+                self.next = Some(now + self.interval);
+                Ok(())
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct PacerState {
+    rate: DialRate,
+    half_open: usize,
+    /// Zero = unlimited
+    max_half_open: usize,
+    /// Handshaked connections
+    established: usize,
+    /// Cap on half-open plus established; zero = unlimited
+    max_connections: usize,
+    /// Lower limits in force until the deadline (after running out of descriptors)
+    throttle: Option<Throttle>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Throttle {
+    max_half_open: usize,
+    max_connections: usize,
+    until: Instant,
+}
+
+/// Floor for the throttled connection cap
+const MIN_THROTTLED_CONNECTIONS: usize = 8;
+
+impl PacerState {
+    /// Limits in force at `now`: (half-open, connections), zero = unlimited
+    fn limits(&mut self, now: Instant) -> (usize, usize) {
+        match self.throttle {
+            Some(throttle) if throttle.until > now => {
+                (throttle.max_half_open, throttle.max_connections)
+            }
+            Some(_) => {
+                self.throttle = None;
+                (self.max_half_open, self.max_connections)
+            }
+            None => (self.max_half_open, self.max_connections),
+        }
+    }
+
+    /// Whether another dial may start at `now`
+    fn has_room(&mut self, now: Instant) -> bool {
+        let (max_half_open, max_connections) = self.limits(now);
+        (max_half_open == 0 || self.half_open < max_half_open)
+            && (max_connections == 0 || self.half_open + self.established < max_connections)
+    }
+}
+
+/// Shared limiter for outbound peer connections (see `AppState::dial_pacer`)
+#[derive(Debug)]
+pub struct DialPacer {
+    state: Mutex<PacerState>,
+    /// Signalled when a half-open slot frees up
+    released: Notify,
+    /// Dials queued by all peer managers
+    queued: AtomicUsize,
+}
+
+impl Default for DialPacer {
+    fn default() -> Self {
+        Self::new(DEFAULT_DIALS_PER_SEC, DEFAULT_MAX_HALF_OPEN)
+    }
+}
+
+impl DialPacer {
+    /// `0` for either limit means unlimited
+    pub fn new(dials_per_sec: u32, max_half_open: u32) -> Self {
+        Self {
+            state: Mutex::new(PacerState {
+                rate: DialRate::new(dials_per_sec),
+                half_open: 0,
+                max_half_open: max_half_open as usize,
+                established: 0,
+                max_connections: 0,
+                throttle: None,
+            }),
+            released: Notify::new(),
+            queued: AtomicUsize::new(0),
+        }
+    }
+
+    /// Change the limits; dials already waiting pick them up
+    pub fn configure(&self, dials_per_sec: u32, max_half_open: u32) {
+        let mut state = self.state.lock().unwrap();
+        state.rate = DialRate::new(dials_per_sec);
+        state.max_half_open = max_half_open as usize;
+        drop(state);
+        self.released.notify_waiters();
+    }
+
+    /// Cap connections (half-open and established) across all torrents; zero
+    /// = unlimited. Connections over a lowered cap are left alone.
+    pub fn set_max_connections(&self, max_connections: usize) {
+        self.state.lock().unwrap().max_connections = max_connections;
+        self.released.notify_waiters();
+    }
+
+    /// Lower the limits for `duration` to fewer connections than are open
+    /// now, as a stopgap when the process runs out of file descriptors
+    pub fn throttle(&self, duration: Duration) {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        let open = state.half_open + state.established;
+        let (max_half_open, max_connections) = state.limits(now);
+        let mut throttled = (open * 3 / 4).max(MIN_THROTTLED_CONNECTIONS);
+        if max_connections != 0 {
+            throttled = throttled.min(max_connections);
+        }
+        let half_open = (throttled / 4).max(1);
+        state.throttle = Some(Throttle {
+            max_half_open: if max_half_open == 0 { half_open } else { half_open.min(max_half_open) },
+            max_connections: throttled,
+            until: now + duration,
+        });
+    }
+
+    /// Whether the limits are currently lowered by `throttle`
+    pub fn is_throttled(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        state.limits(now);
+        state.throttle.is_some()
+    }
+
+    /// Connections currently handshaked
+    pub fn established(&self) -> usize {
+        self.state.lock().unwrap().established
+    }
+
+    /// Connection cap in force (throttle included); zero = unlimited
+    pub fn connection_cap(&self) -> usize {
+        self.state.lock().unwrap().limits(Instant::now()).1
+    }
+
+    /// Dials waiting to go out, across all torrents
+    pub fn queue_depth(&self) -> usize {
+        self.queued.load(Ordering::Relaxed)
+    }
+
+    /// Connections currently being set up
+    pub fn half_open(&self) -> usize {
+        self.state.lock().unwrap().half_open
+    }
+
+    /// Wait for a half-open slot and the next dial slot. Cancel-safe: a
+    /// dropped wait gives back whatever it had taken.
+    pub async fn acquire(self: &Arc<Self>) -> DialPermit {
+        loop {
+            let released = self.released.notified();
+            tokio::pin!(released);
+            released.as_mut().enable();
+            {
+                let mut state = self.state.lock().unwrap();
+                if state.has_room(Instant::now()) {
+                    state.half_open += 1;
+                    break;
+                }
+            }
+            // A throttle lapsing frees room without anyone being released
+            let throttled_until = self.state.lock().unwrap().throttle.map(|throttle| throttle.until);
+            match throttled_until {
+                Some(until) => {
+                    let _ = time::timeout_at(until, released).await;
+                }
+                None => released.await,
+            }
+        }
+        let permit = DialPermit { pacer: self.clone() };
+
+        loop {
+            let wait = self.state.lock().unwrap().rate.try_take(Instant::now());
+            match wait {
+                Ok(()) => return permit,
+                Err(wait) => time::sleep(wait).await,
+            }
+        }
+    }
+}
+
+/// Held while a connection is half-open
+#[derive(Debug)]
+pub struct DialPermit {
+    pacer: Arc<DialPacer>,
+}
+
+impl DialPermit {
+    /// The handshake succeeded: trade the half-open slot for a connection
+    /// slot, held for as long as the connection stays up
+    pub fn establish(self) -> ConnectionSlot {
+        self.pacer.state.lock().unwrap().established += 1;
+        ConnectionSlot { pacer: self.pacer.clone() }
+    }
+}
+
+impl Drop for DialPermit {
+    fn drop(&mut self) {
+        self.pacer.state.lock().unwrap().half_open -= 1;
+        self.pacer.released.notify_one();
+    }
+}
+
+/// Held while a connection is established
+#[derive(Debug)]
+pub struct ConnectionSlot {
+    pacer: Arc<DialPacer>,
+}
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        self.pacer.state.lock().unwrap().established -= 1;
+        self.pacer.released.notify_one();
+    }
+}
+
+/// A peer manager's dials waiting for the pacer (counted in its queue depth)
+#[derive(Debug)]
+pub struct DialQueue {
+    pacer: Arc<DialPacer>,
+    peers: VecDeque<(SocketAddr, Option<Bitfield>)>,
+}
+
+impl DialQueue {
+    pub fn new(pacer: Arc<DialPacer>) -> Self {
+        Self { pacer, peers: VecDeque::new() }
+    }
+
+    pub fn pacer(&self) -> &Arc<DialPacer> {
+        &self.pacer
+    }
+
+    /// Queue a dial (ignored if that address is already queued)
+    pub fn push(&mut self, addr: SocketAddr, known_bitfield: Option<Bitfield>) {
+        if self.peers.iter().any(|(queued, _)| *queued == addr) {
+            return;
+        }
+        self.peers.push_back((addr, known_bitfield));
+        self.pacer.queued.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Queue a dial ahead of all others (moved up if already queued)
+    pub fn push_front(&mut self, addr: SocketAddr) {
+        match self.peers.iter().position(|(queued, _)| *queued == addr) {
+            Some(index) => {
+                let queued = self.peers.remove(index).expect("index in range");
+                self.peers.push_front(queued);
+            }
+            None => {
+                self.peers.push_front((addr, None));
+                self.pacer.queued.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    pub fn pop(&mut self) -> Option<(SocketAddr, Option<Bitfield>)> {
+        let next = self.peers.pop_front();
+        if next.is_some() {
+            self.pacer.queued.fetch_sub(1, Ordering::Relaxed);
+        }
+        next
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.peers.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.peers.len()
+    }
+
+    /// Drop every queued dial (pause/stop)
+    pub fn clear(&mut self) {
+        self.pacer.queued.fetch_sub(self.peers.len(), Ordering::Relaxed);
+        self.peers.clear();
+    }
+}
+
+impl Drop for DialQueue {
+    fn drop(&mut self) {
+        self.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dials_are_spaced_at_the_configured_rate() {
+        let mut rate = DialRate::new(10);
+        let start = Instant::now();
+        let mut now = start;
+
+        // 100 dials queued at once; each waits exactly as long as it's told
+        let mut dialed_at = Vec::new();
+        while dialed_at.len() < 100 {
+            match rate.try_take(now) {
+                Ok(()) => dialed_at.push(now - start),
+                Err(wait) => now += wait,
+            }
+        }
+        let expected: Vec<Duration> = (0..100).map(|i| Duration::from_millis(100) * i).collect();
+        assert_eq!(dialed_at, expected);
+
+        // A quiet spell doesn't bank slots for a burst later
+        now += Duration::from_secs(5);
+        assert!(rate.try_take(now).is_ok());
+        assert_eq!(rate.try_take(now), Err(Duration::from_millis(100)));
+
+        let mut unlimited = DialRate::new(0);
+        assert!((0..100).all(|_| unlimited.try_take(start).is_ok()));
+    }
+
+    #[tokio::test]
+    async fn test_half_open_cap() {
+        let pacer = Arc::new(DialPacer::new(0, 2));
+        let first = pacer.acquire().await;
+        let _second = pacer.acquire().await;
+        assert_eq!(pacer.half_open(), 2);
+
+        // A third waits until a connection finishes its handshake
+        let waiting = tokio::spawn({
+            let pacer = pacer.clone();
+            async move { pacer.acquire().await }
+        });
+        time::sleep(Duration::from_millis(50)).await;
+        assert!(!waiting.is_finished());
+        drop(first);
+        let _third = time::timeout(Duration::from_secs(1), waiting).await.unwrap().unwrap();
+        assert_eq!(pacer.half_open(), 2);
+
+        // Giving up while waiting holds nothing
+        assert!(time::timeout(Duration::from_millis(20), pacer.acquire()).await.is_err());
+        assert_eq!(pacer.half_open(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_connection_cap_and_throttle() {
+        let pacer = Arc::new(DialPacer::new(0, 0));
+        pacer.set_max_connections(2);
+        let first = pacer.acquire().await.establish();
+        let second = pacer.acquire().await;
+        assert_eq!((pacer.established(), pacer.half_open()), (1, 1));
+
+        // Established connections count against the cap too
+        assert!(time::timeout(Duration::from_millis(20), pacer.acquire()).await.is_err());
+        let second = second.establish();
+        assert!(time::timeout(Duration::from_millis(20), pacer.acquire()).await.is_err());
+        drop(first);
+        let third = time::timeout(Duration::from_secs(1), pacer.acquire()).await.unwrap();
+        drop((second, third));
+        assert_eq!((pacer.established(), pacer.half_open()), (0, 0));
+
+        // A throttle lowers the cap below what's open, then lapses by itself
+        pacer.set_max_connections(0);
+        let slots: Vec<ConnectionSlot> = futures::future::join_all((0..12).map(|_| pacer.acquire()))
+            .await
+            .into_iter()
+            .map(DialPermit::establish)
+            .collect();
+        pacer.throttle(Duration::from_millis(100));
+        assert!(pacer.is_throttled());
+        assert_eq!(pacer.connection_cap(), 9);
+        assert!(time::timeout(Duration::from_millis(20), pacer.acquire()).await.is_err());
+        let _late = time::timeout(Duration::from_secs(1), pacer.acquire()).await.unwrap();
+        assert!(!pacer.is_throttled());
+        assert_eq!(pacer.connection_cap(), 0);
+        drop(slots);
+    }
+
+    #[test]
+    fn test_queue_depth_follows_queues() {
+        let pacer = Arc::new(DialPacer::default());
+        let peer = |n: u8| SocketAddr::from(([10, 0, 0, n], 6881));
+
+        let mut a = DialQueue::new(pacer.clone());
+        let mut b = DialQueue::new(pacer.clone());
+        a.push(peer(1), None);
+        a.push(peer(1), None);
+        a.push(peer(2), None);
+        b.push(peer(3), None);
+        assert_eq!(pacer.queue_depth(), 3);
+
+        assert_eq!(a.pop().map(|(addr, _)| addr), Some(peer(1)));
+        assert_eq!(pacer.queue_depth(), 2);
+        drop(b);
+        assert_eq!(pacer.queue_depth(), 1);
+        a.clear();
+        assert_eq!(pacer.queue_depth(), 0);
+    }
+
+    #[test]
+    fn test_push_front_jumps_the_queue() {
+        let pacer = Arc::new(DialPacer::default());
+        let peer = |n: u8| SocketAddr::from(([10, 0, 0, n], 6881));
+
+        let mut queue = DialQueue::new(pacer.clone());
+        queue.push(peer(1), None);
+        queue.push(peer(2), None);
+        queue.push_front(peer(3));
+        queue.push_front(peer(2));
+        assert_eq!(pacer.queue_depth(), 3);
+
+        let order: Vec<SocketAddr> = std::iter::from_fn(|| queue.pop().map(|(addr, _)| addr)).collect();
+        assert_eq!(order, vec![peer(2), peer(3), peer(1)]);
+    }
+}
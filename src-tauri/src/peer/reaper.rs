@@ -0,0 +1,271 @@
+//! Dropping idle connections while seeding
+//!
+//! Once a torrent is complete, some connections can't lead to an upload but
+//! still hold one of its connection slots: other seeds want nothing from us,
+//! and some leechers connect and never ask for anything. Every
+//! `REAP_INTERVAL`, if the torrent is at its connection cap, the least useful
+//! of these are disconnected to make room for peers that might download:
+//! seeds first, then peers that have been uninterested and silent for
+//! `IDLE_REQUEST_WINDOW`. A peer that moved a block recently is never
+//! dropped, and neither is an interested one (it's waiting for an unchoke).
+//! While downloading the reaper stays off, as any peer may still get pieces
+//! we want.
+
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio_util::sync::CancellationToken;
+
+use crate::piece::PeerKey;
+
+/// Time between reaper passes
+pub const REAP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// A peer that asked for nothing in this long is idle
+pub const IDLE_REQUEST_WINDOW: Duration = Duration::from_secs(5 * 60);
+
+/// A peer that sent or received a block this recently is transferring
+pub const ACTIVE_WINDOW: Duration = Duration::from_secs(60);
+
+/// New connections are left alone this long (bitfields, first requests)
+pub const GRACE_PERIOD: Duration = Duration::from_secs(60);
+
+/// Slots a pass frees below the cap, so a few new peers fit
+pub const REAP_HEADROOM: usize = 5;
+
+/// What the reaper knows about one session
+#[derive(Debug, Clone, Copy)]
+pub struct IdleSnapshot {
+    pub addr: SocketAddr,
+    pub connected_at: Instant,
+    /// The peer wants pieces from us
+    pub interested: bool,
+    /// The peer has every piece
+    pub remote_seed: bool,
+    /// Payload sent and received over the connection
+    pub exchanged: u64,
+    /// When the peer last requested a block
+    pub last_request_at: Option<Instant>,
+    /// When a block last went either way
+    pub last_block_at: Option<Instant>,
+    /// Our requests the peer hasn't answered
+    pub pending_requests: usize,
+}
+
+impl IdleSnapshot {
+    pub fn new(addr: SocketAddr, connected_at: Instant) -> Self {
+        Self {
+            addr,
+            connected_at,
+            interested: false,
+            remote_seed: false,
+            exchanged: 0,
+            last_request_at: None,
+            last_block_at: None,
+            pending_requests: 0,
+        }
+    }
+
+    /// Whether blocks are moving over the connection
+    fn is_transferring(&self, now: Instant) -> bool {
+        self.pending_requests > 0
+            || self
+                .last_block_at
+                .is_some_and(|at| now.saturating_duration_since(at) < ACTIVE_WINDOW)
+    }
+
+    /// How long since the peer last did anything useful
+    fn idle_for(&self, now: Instant) -> Duration {
+        let last = [self.last_request_at, self.last_block_at]
+            .into_iter()
+            .flatten()
+            .fold(self.connected_at, Instant::max);
+        now.saturating_duration_since(last)
+    }
+}
+
+/// Why a session may be reaped, least valuable first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum IdleKind {
+    /// The remote is a seed too: neither side wants anything
+    SeedToSeed,
+    /// Uninterested and asked for nothing in `IDLE_REQUEST_WINDOW`
+    NotInterested,
+}
+
+/// Whether a session of a seeding torrent may be reaped, and why
+pub fn classify(snapshot: &IdleSnapshot, now: Instant) -> Option<IdleKind> {
+    if now.saturating_duration_since(snapshot.connected_at) < GRACE_PERIOD || snapshot.is_transferring(now) {
+        return None;
+    }
+    if snapshot.remote_seed {
+        return Some(IdleKind::SeedToSeed);
+    }
+    let last_request = snapshot.last_request_at.unwrap_or(snapshot.connected_at);
+    if !snapshot.interested && now.saturating_duration_since(last_request) >= IDLE_REQUEST_WINDOW {
+        return Some(IdleKind::NotInterested);
+    }
+    None
+}
+
+/// Sessions to disconnect when `connected` sessions are up against
+/// `max_peers` (zero = no cap): seeds before idle leechers, and within each,
+/// those that exchanged the least and then those idle the longest
+pub fn pick(snapshots: &[IdleSnapshot], connected: usize, max_peers: usize, now: Instant) -> Vec<SocketAddr> {
+    if max_peers == 0 || connected < max_peers {
+        return Vec::new();
+    }
+    let mut reapable: Vec<(IdleKind, &IdleSnapshot)> = snapshots
+        .iter()
+        .filter_map(|snapshot| classify(snapshot, now).map(|kind| (kind, snapshot)))
+        .collect();
+    reapable.sort_by_key(|(kind, snapshot)| (*kind, snapshot.exchanged, Reverse(snapshot.idle_for(now))));
+    reapable
+        .into_iter()
+        .take(connected + REAP_HEADROOM - max_peers)
+        .map(|(_, snapshot)| snapshot.addr)
+        .collect()
+}
+
+/// The latest snapshot of every live session of a torrent. Peer tasks
+/// refresh their session's snapshot between messages (a session waiting for
+/// its next message is out of the session map), and the reaper ends a
+/// session through its token.
+#[derive(Debug, Default)]
+pub struct IdleWatch {
+    sessions: Mutex<HashMap<SocketAddr, (PeerKey, IdleSnapshot, CancellationToken)>>,
+}
+
+impl IdleWatch {
+    /// Start watching a new session; the token is cancelled if it's reaped
+    pub fn track(&self, key: PeerKey, snapshot: IdleSnapshot) -> CancellationToken {
+        let token = CancellationToken::new();
+        self.sessions.lock().unwrap().insert(snapshot.addr, (key, snapshot, token.clone()));
+        token
+    }
+
+    pub fn update(&self, key: PeerKey, snapshot: IdleSnapshot) {
+        if let Some(entry) = self.sessions.lock().unwrap().get_mut(&snapshot.addr) {
+            if entry.0 == key {
+                entry.1 = snapshot;
+            }
+        }
+    }
+
+    /// Stop watching an ended session (unless a newer one took its address)
+    pub fn forget(&self, addr: SocketAddr, key: PeerKey) {
+        let mut sessions = self.sessions.lock().unwrap();
+        if sessions.get(&addr).is_some_and(|entry| entry.0 == key) {
+            sessions.remove(&addr);
+        }
+    }
+
+    pub fn snapshots(&self) -> Vec<IdleSnapshot> {
+        self.sessions.lock().unwrap().values().map(|entry| entry.1).collect()
+    }
+
+    /// End a session; its task records the disconnect
+    pub fn reap(&self, addr: SocketAddr) -> bool {
+        match self.sessions.lock().unwrap().get(&addr) {
+            Some(entry) => {
+                entry.2.cancel();
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(n: u8) -> SocketAddr {
+        SocketAddr::from(([10, 0, 0, n], 6881))
+    }
+
+    /// A session connected an hour before `now`, quiet since
+    fn quiet(n: u8, now: Instant) -> IdleSnapshot {
+        IdleSnapshot::new(addr(n), now - Duration::from_secs(3600))
+    }
+
+    #[test]
+    fn test_reap_ordering() {
+        // Far enough from the clock's origin to subtract an hour
+        let now = Instant::now() + Duration::from_secs(3600);
+        let ago = |secs: u64| Some(now - Duration::from_secs(secs));
+        let snapshots = vec![
+            // Idle leecher that took a lot from us once
+            IdleSnapshot { exchanged: 1 << 30, last_request_at: ago(1200), last_block_at: ago(1200), ..quiet(1, now) },
+            // Seeds, one of which we exchanged something with
+            IdleSnapshot { remote_seed: true, exchanged: 4096, ..quiet(2, now) },
+            IdleSnapshot { remote_seed: true, ..quiet(3, now) },
+            // Never asked for anything
+            quiet(4, now),
+            // Idle leecher that asked for something 10 minutes ago
+            IdleSnapshot { last_request_at: ago(600), ..quiet(5, now) },
+            // Kept: interested, asked recently, or too new to judge
+            IdleSnapshot { interested: true, ..quiet(6, now) },
+            IdleSnapshot { last_request_at: ago(120), ..quiet(7, now) },
+            IdleSnapshot::new(addr(8), now - Duration::from_secs(30)),
+        ];
+        assert_eq!(classify(&snapshots[2], now), Some(IdleKind::SeedToSeed));
+        assert_eq!(classify(&snapshots[3], now), Some(IdleKind::NotInterested));
+        assert!(snapshots[5..].iter().all(|snapshot| classify(snapshot, now).is_none()));
+
+        // Below the cap nothing goes; at it, enough for the headroom
+        assert!(pick(&snapshots, 49, 50, now).is_empty());
+        assert!(pick(&snapshots, 60, 0, now).is_empty());
+        let all = pick(&snapshots, 50, 50, now);
+        assert_eq!(all, vec![addr(3), addr(2), addr(4), addr(5), addr(1)]);
+        assert_eq!(pick(&snapshots[..4], 48, 46, now), vec![addr(3), addr(2), addr(4), addr(1)]);
+
+        // No more than the headroom plus what's over the cap
+        let mut crowded = snapshots.clone();
+        crowded.extend((10..20).map(|n| IdleSnapshot { remote_seed: true, ..quiet(n, now) }));
+        assert_eq!(pick(&crowded, 50, 50, now), vec![addr(3), addr(10), addr(11), addr(12), addr(13)]);
+        assert_eq!(pick(&crowded, 52, 50, now).len(), 7);
+    }
+
+    #[test]
+    fn test_transferring_peers_are_never_reaped() {
+        let now = Instant::now() + Duration::from_secs(3600);
+        let ago = |secs: u64| Some(now - Duration::from_secs(secs));
+        let snapshots = vec![
+            // A seed we're still downloading from
+            IdleSnapshot { remote_seed: true, pending_requests: 3, ..quiet(1, now) },
+            // A seed and a leecher that moved a block within the window
+            IdleSnapshot { remote_seed: true, last_block_at: ago(10), ..quiet(2, now) },
+            IdleSnapshot { last_request_at: ago(59), last_block_at: ago(59), ..quiet(3, now) },
+        ];
+        assert!(pick(&snapshots, 500, 1, now).is_empty());
+
+        // Once the block is older than the window the seed may go; the
+        // leecher asked for it too recently to be idle
+        let later = now + ACTIVE_WINDOW;
+        assert_eq!(pick(&snapshots[1..], 500, 1, later), vec![addr(2)]);
+    }
+
+    #[test]
+    fn test_watch() {
+        let watch = IdleWatch::default();
+        let now = Instant::now();
+        let token = watch.track(1, IdleSnapshot::new(addr(1), now));
+        watch.update(1, IdleSnapshot { interested: true, ..IdleSnapshot::new(addr(1), now) });
+        assert!(watch.snapshots()[0].interested);
+
+        // A newer session from the same address isn't touched by the old one
+        let newer = watch.track(2, IdleSnapshot::new(addr(1), now));
+        watch.update(1, IdleSnapshot::new(addr(1), now));
+        watch.forget(addr(1), 1);
+        assert_eq!(watch.snapshots().len(), 1);
+        assert!(!watch.snapshots()[0].interested);
+
+        assert!(watch.reap(addr(1)));
+        assert!(newer.is_cancelled() && !token.is_cancelled());
+        watch.forget(addr(1), 2);
+        assert!(!watch.reap(addr(1)));
+    }
+}
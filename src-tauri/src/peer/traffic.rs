@@ -0,0 +1,323 @@
+/// Wire traffic accounting split into payload and protocol overhead
+///
+/// Payload is the block data carried by Piece messages; everything else on the
+/// socket (handshakes, length prefixes, bitfields, requests, haves,
+/// keep-alives, Piece headers) is overhead. Payload that turned out to be
+/// useless is also counted by `Waste` category; it stays in the payload.
+use super::Message;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Size of the BitTorrent handshake on the wire (each direction)
+pub const HANDSHAKE_WIRE_SIZE: u64 = 68;
+
+/// Snapshot of traffic counters
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TrafficStats {
+    pub payload_downloaded: u64,
+    pub overhead_downloaded: u64,
+    pub payload_uploaded: u64,
+    pub overhead_uploaded: u64,
+    /// Blocks received again after we already had them (endgame duplicates)
+    #[serde(default)]
+    pub redundant_downloaded: u64,
+    /// Blocks of pieces that failed verification
+    #[serde(default)]
+    pub failed_downloaded: u64,
+    /// Blocks that arrived after their request was cancelled or their piece completed
+    #[serde(default)]
+    pub discarded_downloaded: u64,
+}
+
+/// Why received payload was of no use
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Waste {
+    /// See `TrafficStats::redundant_downloaded`
+    Redundant,
+    /// See `TrafficStats::failed_downloaded`
+    Failed,
+    /// See `TrafficStats::discarded_downloaded`
+    Discarded,
+}
+
+impl TrafficStats {
+    /// Everything received on the socket
+    pub fn total_downloaded(&self) -> u64 {
+        self.payload_downloaded + self.overhead_downloaded
+    }
+
+    /// Everything sent on the socket
+    pub fn total_uploaded(&self) -> u64 {
+        self.payload_uploaded + self.overhead_uploaded
+    }
+
+    /// Received payload that was of no use
+    pub fn wasted_downloaded(&self) -> u64 {
+        self.redundant_downloaded + self.failed_downloaded + self.discarded_downloaded
+    }
+
+    /// Received bytes a rate limiter should charge
+    pub fn limited_downloaded(&self, count_overhead: bool) -> u64 {
+        if count_overhead {
+            self.total_downloaded()
+        } else {
+            self.payload_downloaded
+        }
+    }
+
+    /// Sent bytes a rate limiter should charge
+    pub fn limited_uploaded(&self, count_overhead: bool) -> u64 {
+        if count_overhead {
+            self.total_uploaded()
+        } else {
+            self.payload_uploaded
+        }
+    }
+
+    /// Counter-wise sum (e.g. persisted totals + this run)
+    pub fn combined(&self, other: &TrafficStats) -> TrafficStats {
+        TrafficStats {
+            payload_downloaded: self.payload_downloaded + other.payload_downloaded,
+            overhead_downloaded: self.overhead_downloaded + other.overhead_downloaded,
+            payload_uploaded: self.payload_uploaded + other.payload_uploaded,
+            overhead_uploaded: self.overhead_uploaded + other.overhead_uploaded,
+            redundant_downloaded: self.redundant_downloaded + other.redundant_downloaded,
+            failed_downloaded: self.failed_downloaded + other.failed_downloaded,
+            discarded_downloaded: self.discarded_downloaded + other.discarded_downloaded,
+        }
+    }
+
+    fn record_received(&mut self, payload: u64, overhead: u64) {
+        self.payload_downloaded += payload;
+        self.overhead_downloaded += overhead;
+    }
+
+    fn record_waste(&mut self, waste: Waste, bytes: u64) {
+        match waste {
+            Waste::Redundant => self.redundant_downloaded += bytes,
+            Waste::Failed => self.failed_downloaded += bytes,
+            Waste::Discarded => self.discarded_downloaded += bytes,
+        }
+    }
+
+    fn record_sent(&mut self, payload: u64, overhead: u64) {
+        self.payload_uploaded += payload;
+        self.overhead_uploaded += overhead;
+    }
+}
+
+/// Shared, lock-free traffic totals (one per torrent, plus the global one)
+#[derive(Debug, Default)]
+pub struct TrafficMeter {
+    payload_downloaded: AtomicU64,
+    overhead_downloaded: AtomicU64,
+    payload_uploaded: AtomicU64,
+    overhead_uploaded: AtomicU64,
+    redundant_downloaded: AtomicU64,
+    failed_downloaded: AtomicU64,
+    discarded_downloaded: AtomicU64,
+}
+
+static GLOBAL_TRAFFIC: TrafficMeter = TrafficMeter::new();
+
+/// Process-wide totals across all torrents
+pub fn global_traffic() -> &'static TrafficMeter {
+    &GLOBAL_TRAFFIC
+}
+
+impl TrafficMeter {
+    pub const fn new() -> Self {
+        Self {
+            payload_downloaded: AtomicU64::new(0),
+            overhead_downloaded: AtomicU64::new(0),
+            payload_uploaded: AtomicU64::new(0),
+            overhead_uploaded: AtomicU64::new(0),
+            redundant_downloaded: AtomicU64::new(0),
+            failed_downloaded: AtomicU64::new(0),
+            discarded_downloaded: AtomicU64::new(0),
+        }
+    }
+
+    pub fn snapshot(&self) -> TrafficStats {
+        TrafficStats {
+            payload_downloaded: self.payload_downloaded.load(Ordering::Relaxed),
+            overhead_downloaded: self.overhead_downloaded.load(Ordering::Relaxed),
+            payload_uploaded: self.payload_uploaded.load(Ordering::Relaxed),
+            overhead_uploaded: self.overhead_uploaded.load(Ordering::Relaxed),
+            redundant_downloaded: self.redundant_downloaded.load(Ordering::Relaxed),
+            failed_downloaded: self.failed_downloaded.load(Ordering::Relaxed),
+            discarded_downloaded: self.discarded_downloaded.load(Ordering::Relaxed),
+        }
+    }
+
+    fn record_received(&self, payload: u64, overhead: u64) {
+        self.payload_downloaded.fetch_add(payload, Ordering::Relaxed);
+        self.overhead_downloaded.fetch_add(overhead, Ordering::Relaxed);
+    }
+
+    fn record_sent(&self, payload: u64, overhead: u64) {
+        self.payload_uploaded.fetch_add(payload, Ordering::Relaxed);
+        self.overhead_uploaded.fetch_add(overhead, Ordering::Relaxed);
+    }
+
+    fn record_waste(&self, waste: Waste, bytes: u64) {
+        let counter = match waste {
+            Waste::Redundant => &self.redundant_downloaded,
+            Waste::Failed => &self.failed_downloaded,
+            Waste::Discarded => &self.discarded_downloaded,
+        };
+        counter.fetch_add(bytes, Ordering::Relaxed);
+    }
+}
+
+/// Split a message's wire size (length prefix included) into (payload, overhead)
+pub fn wire_split(message: &Message) -> (u64, u64) {
+    let wire_size = 4 + message.length() as u64;
+    let payload = match message {
+        Message::Piece { data, .. } => data.len() as u64,
+        _ => 0,
+    };
+    (payload, wire_size - payload)
+}
+
+/// Per-connection counters that also feed the torrent and global meters
+#[derive(Debug, Default)]
+pub struct ConnectionTraffic {
+    /// This connection's own totals
+    pub stats: TrafficStats,
+    /// Torrent-wide meter, when the connection belongs to a torrent
+    pub torrent: Option<std::sync::Arc<TrafficMeter>>,
+}
+
+impl ConnectionTraffic {
+    pub fn record_received(&mut self, payload: u64, overhead: u64) {
+        self.stats.record_received(payload, overhead);
+        if let Some(ref meter) = self.torrent {
+            meter.record_received(payload, overhead);
+        }
+        GLOBAL_TRAFFIC.record_received(payload, overhead);
+    }
+
+    pub fn record_sent(&mut self, payload: u64, overhead: u64) {
+        self.stats.record_sent(payload, overhead);
+        if let Some(ref meter) = self.torrent {
+            meter.record_sent(payload, overhead);
+        }
+        GLOBAL_TRAFFIC.record_sent(payload, overhead);
+    }
+
+    /// Count `bytes` of received payload as wasted
+    pub fn record_waste(&mut self, waste: Waste, bytes: u64) {
+        self.stats.record_waste(waste, bytes);
+        if let Some(ref meter) = self.torrent {
+            meter.record_waste(waste, bytes);
+        }
+        GLOBAL_TRAFFIC.record_waste(waste, bytes);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::peer::PeerConnection;
+    use std::sync::Arc;
+    use tokio::net::{TcpListener, TcpStream};
+
+    async fn loopback_pair() -> (PeerConnection, PeerConnection) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (ours, accepted) = tokio::join!(TcpStream::connect(addr), listener.accept());
+        let (remote_stream, remote_addr) = accepted.unwrap();
+        (
+            PeerConnection::new(ours.unwrap(), addr),
+            PeerConnection::new(remote_stream, remote_addr),
+        )
+    }
+
+    #[test]
+    fn test_wire_split() {
+        assert_eq!(wire_split(&Message::KeepAlive), (0, 4));
+        assert_eq!(wire_split(&Message::Interested), (0, 5));
+        assert_eq!(wire_split(&Message::Have { piece_index: 1 }), (0, 9));
+        assert_eq!(wire_split(&Message::Request { index: 0, begin: 0, length: 16384 }), (0, 17));
+        assert_eq!(
+            wire_split(&Message::Piece { index: 0, begin: 0, data: vec![0; 100] }),
+            (100, 13)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_loopback_payload_overhead_split() {
+        let (mut ours, mut remote) = loopback_pair().await;
+        let meter = Arc::new(TrafficMeter::new());
+        ours.set_traffic_meter(meter.clone());
+
+        let sent = [
+            Message::Bitfield { bitfield: vec![0xFF, 0x00] }, // 4 + 1 + 2 = 7
+            Message::Interested,                              // 5
+            Message::Request { index: 0, begin: 0, length: 64 }, // 17
+        ];
+        for message in &sent {
+            ours.send_message(message).await.unwrap();
+            remote.recv_message().await.unwrap();
+        }
+
+        let replies = [
+            Message::Unchoke,                                          // 5
+            Message::Piece { index: 0, begin: 0, data: vec![7; 64] }, // 13 + 64
+            Message::KeepAlive,                                        // 4
+        ];
+        for message in &replies {
+            remote.send_message(message).await.unwrap();
+            ours.recv_message().await.unwrap();
+        }
+
+        let expected = TrafficStats {
+            payload_downloaded: 64,
+            overhead_downloaded: 5 + 13 + 4,
+            payload_uploaded: 0,
+            overhead_uploaded: 7 + 5 + 17,
+            ..Default::default()
+        };
+        assert_eq!(ours.traffic(), expected);
+        assert_eq!(meter.snapshot(), expected);
+
+        // The other side sees the mirror image
+        let remote_stats = remote.traffic();
+        assert_eq!(remote_stats.payload_uploaded, expected.payload_downloaded);
+        assert_eq!(remote_stats.overhead_uploaded, expected.overhead_downloaded);
+        assert_eq!(remote_stats.overhead_downloaded, expected.overhead_uploaded);
+
+        assert_eq!(expected.limited_downloaded(false), 64);
+        assert_eq!(expected.limited_downloaded(true), 64 + 22);
+    }
+
+    #[test]
+    fn test_waste_feeds_every_meter() {
+        let meter = Arc::new(TrafficMeter::new());
+        let mut connection = ConnectionTraffic { torrent: Some(meter.clone()), ..Default::default() };
+        let global_before = global_traffic().snapshot();
+
+        connection.record_received(16384 * 3, 39);
+        connection.record_waste(Waste::Redundant, 16384);
+        connection.record_waste(Waste::Discarded, 100);
+        connection.record_waste(Waste::Failed, 32768);
+
+        let torrent = meter.snapshot();
+        assert_eq!(torrent, connection.stats);
+        assert_eq!(
+            (torrent.redundant_downloaded, torrent.discarded_downloaded, torrent.failed_downloaded),
+            (16384, 100, 32768)
+        );
+        assert_eq!(torrent.wasted_downloaded(), 16384 + 100 + 32768);
+        // Waste is a breakdown of the payload, not extra traffic
+        assert_eq!(torrent.total_downloaded(), 16384 * 3 + 39);
+
+        // Other tests feed the global meter concurrently, so only check it grew
+        let global = global_traffic().snapshot();
+        assert!(global.failed_downloaded >= global_before.failed_downloaded + 32768);
+
+        // Persisted totals add up across runs
+        assert_eq!(torrent.combined(&torrent).failed_downloaded, 2 * 32768);
+    }
+}
@@ -120,9 +120,33 @@ impl Bitfield {
 
     /// Check which pieces a peer has that we don't
     pub fn pieces_to_request(&self, peer_bitfield: &Bitfield) -> Vec<usize> {
-        (0..self.num_pieces)
-            .filter(|&i| !self.has_piece(i) && peer_bitfield.has_piece(i))
-            .collect()
+        self.iter_missing_from(peer_bitfield).collect()
+    }
+
+    /// Check whether a peer has at least one piece we don't (no allocation)
+    pub fn has_missing_from(&self, peer_bitfield: &Bitfield) -> bool {
+        self.iter_missing_from(peer_bitfield).next().is_some()
+    }
+
+    /// Lazily iterate over pieces the peer has that we don't.
+    /// Works a byte at a time so fully-overlapping regions are skipped cheaply.
+    pub fn iter_missing_from<'a>(
+        &'a self,
+        peer_bitfield: &'a Bitfield,
+    ) -> impl Iterator<Item = usize> + 'a {
+        let num_pieces = self.num_pieces;
+        self.bytes
+            .iter()
+            .zip(&peer_bitfield.bytes)
+            .enumerate()
+            .filter(|(_, (ours, theirs))| !**ours & **theirs != 0)
+            .flat_map(move |(byte_index, (ours, theirs))| {
+                let wanted = !*ours & *theirs;
+                (0..8)
+                    .filter(move |bit| wanted & (0x80 >> bit) != 0)
+                    .map(move |bit| byte_index * 8 + bit)
+            })
+            .filter(move |&i| i < num_pieces)
     }
 
     /// Bitwise OR with another bitfield (union of available pieces)
@@ -271,6 +295,29 @@ mod tests {
         assert_eq!(to_request, vec![2, 5]);
     }
 
+    #[test]
+    fn test_has_missing_from() {
+        let mut our_bf = Bitfield::new(10);
+        let mut peer_bf = Bitfield::new(10);
+        assert!(!our_bf.has_missing_from(&peer_bf));
+
+        peer_bf.set_piece(9);
+        assert!(our_bf.has_missing_from(&peer_bf));
+
+        our_bf.set_piece(9);
+        assert!(!our_bf.has_missing_from(&peer_bf));
+    }
+
+    #[test]
+    fn test_iter_missing_from_ignores_spare_bits() {
+        let our_bf = Bitfield::new(10);
+        // Peer sets spare bits past piece 9 in the trailing byte
+        let peer_bf = Bitfield::from_bytes(vec![0x00, 0xFF], 10);
+
+        let missing: Vec<usize> = our_bf.iter_missing_from(&peer_bf).collect();
+        assert_eq!(missing, vec![8, 9]);
+    }
+
     #[test]
     fn test_union() {
         let mut bf1 = Bitfield::new(10);
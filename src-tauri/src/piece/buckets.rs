@@ -0,0 +1,153 @@
+//! The piece map at a resolution the UI can take
+//!
+//! A 100 GB torrent of 16 KiB pieces has over six million of them, far too
+//! many to send to the webview every poll. Above a number of buckets (by
+//! default `DEFAULT_MAX_BUCKETS`) the map is summarised: runs of
+//! `bucket_size` consecutive pieces become one `PieceBucket`, so bucket `i`
+//! covers pieces `i * bucket_size..(i + 1) * bucket_size` (the last may be
+//! shorter). The exact state of a stretch of pieces is available as a
+//! `PieceWindow` of at most `MAX_PIECE_WINDOW` pieces.
+
+use serde::{Deserialize, Serialize};
+
+/// Buckets the piece map is summarised into unless the UI asks otherwise
+pub const DEFAULT_MAX_BUCKETS: usize = 1024;
+
+/// Most pieces a `PieceWindow` covers
+pub const MAX_PIECE_WINDOW: usize = 4096;
+
+/// Piece states in `PiecesInfo::bitfield` and `PieceWindow::bitfield`
+pub const PIECE_MISSING: u8 = 0;
+pub const PIECE_HAVE: u8 = 1;
+pub const PIECE_DOWNLOADING: u8 = 2;
+
+/// A run of consecutive pieces, summarised
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PieceBucket {
+    /// Share of the bucket's pieces we have, 0 to 1
+    pub have_fraction: f32,
+    /// Pieces of the bucket being downloaded
+    pub downloading_count: usize,
+    /// Peers having the bucket's rarest piece
+    pub min_availability: usize,
+    /// Peers per piece, averaged over the bucket
+    pub avg_availability: f32,
+}
+
+/// The exact state of pieces `start..start + bitfield.len()`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PieceWindow {
+    pub start: usize,
+    /// `PIECE_MISSING`, `PIECE_HAVE` or `PIECE_DOWNLOADING` per piece
+    pub bitfield: Vec<u8>,
+    /// Peers having each piece
+    pub availability: Vec<usize>,
+}
+
+/// Pieces per bucket for `total` pieces in at most `max_buckets` buckets;
+/// 1 (every piece its own) when they fit
+pub fn bucket_size(total: usize, max_buckets: usize) -> usize {
+    let max_buckets = max_buckets.max(1);
+    ((total + max_buckets - 1) / max_buckets).max(1)
+}
+
+/// Summarise `total` pieces into buckets of `bucket_size(total,
+/// max_buckets)`, in one pass. `piece` gives a piece's state and
+/// availability.
+pub fn bucket_pieces(
+    total: usize,
+    max_buckets: usize,
+    mut piece: impl FnMut(usize) -> (u8, usize),
+) -> Vec<PieceBucket> {
+    let size = bucket_size(total, max_buckets);
+    let mut buckets = Vec::with_capacity((total + size - 1) / size);
+    for start in (0..total).step_by(size) {
+        let end = (start + size).min(total);
+        let (mut have, mut downloading_count, mut min_availability, mut sum) = (0usize, 0, usize::MAX, 0u64);
+        for index in start..end {
+            let (state, availability) = piece(index);
+            match state {
+                PIECE_HAVE => have += 1,
+                PIECE_DOWNLOADING => downloading_count += 1,
+                _ => {}
+            }
+            min_availability = min_availability.min(availability);
+            sum += availability as u64;
+        }
+        let len = (end - start) as f32;
+        buckets.push(PieceBucket {
+            have_fraction: have as f32 / len,
+            downloading_count,
+            min_availability,
+            avg_availability: sum as f32 / len,
+        });
+    }
+    buckets
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{Rng, SeedableRng};
+
+    /// Full-resolution states and availability, chunked by hand
+    fn reference(states: &[u8], availability: &[usize], size: usize) -> Vec<PieceBucket> {
+        states
+            .chunks(size)
+            .zip(availability.chunks(size))
+            .map(|(states, availability)| {
+                let len = states.len() as f32;
+                PieceBucket {
+                    have_fraction: states.iter().filter(|&&s| s == PIECE_HAVE).count() as f32 / len,
+                    downloading_count: states.iter().filter(|&&s| s == PIECE_DOWNLOADING).count(),
+                    min_availability: *availability.iter().min().unwrap(),
+                    avg_availability: availability.iter().sum::<usize>() as f32 / len,
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_buckets_match_reference() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0x5eed);
+        for _ in 0..200 {
+            let total = rng.gen_range(0..5000);
+            let max_buckets = rng.gen_range(0..1500);
+            let states: Vec<u8> = (0..total).map(|_| rng.gen_range(0..3)).collect();
+            let availability: Vec<usize> = (0..total).map(|_| rng.gen_range(0..50)).collect();
+
+            let size = bucket_size(total, max_buckets);
+            let buckets = bucket_pieces(total, max_buckets, |i| (states[i], availability[i]));
+            assert!(buckets.len() <= max_buckets.max(1), "{} pieces in {} buckets", total, buckets.len());
+            assert_eq!(buckets, reference(&states, &availability, size));
+            if total <= max_buckets {
+                assert_eq!(size, 1);
+            }
+        }
+    }
+
+    #[test]
+    fn test_bucket_edges() {
+        assert_eq!(bucket_size(0, 1024), 1);
+        assert!(bucket_pieces(0, 1024, |_| unreachable!()).is_empty());
+        assert_eq!(bucket_size(1025, 1024), 2);
+        assert_eq!(bucket_size(10, 0), 10);
+
+        // The short last bucket is averaged over its own pieces
+        let buckets = bucket_pieces(5, 2, |i| (if i == 4 { PIECE_HAVE } else { PIECE_MISSING }, i));
+        assert_eq!(buckets.len(), 2);
+        assert_eq!(buckets[1], PieceBucket { have_fraction: 0.5, downloading_count: 0, min_availability: 3, avg_availability: 3.5 });
+    }
+
+    #[test]
+    fn test_huge_torrent_payload_is_bounded() {
+        // 100 GB of 16 KiB pieces
+        let total = 6_103_516;
+        let buckets = bucket_pieces(total, DEFAULT_MAX_BUCKETS, |i| ((i % 3) as u8, i % 40));
+        assert_eq!(buckets.len(), DEFAULT_MAX_BUCKETS);
+        assert_eq!(bucket_size(total, DEFAULT_MAX_BUCKETS), 5961);
+
+        let json = serde_json::to_vec(&buckets).unwrap();
+        assert!(json.len() < 128 * 1024, "{} bytes", json.len());
+    }
+}
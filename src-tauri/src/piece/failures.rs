@@ -0,0 +1,215 @@
+//! Recently failed piece verifications
+//!
+//! One hash failure is usually a bad peer. The same piece failing again and
+//! again, with data from peers that have nothing in common, points at our own
+//! disk instead: the tracker notices that and asks for a recheck.
+
+use super::PeerKey;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+
+/// How long a failure stays listed after the piece last failed
+pub const FAILURE_TTL_MS: i64 = 5 * 60 * 1000;
+
+/// Failures of one piece before it's logged as a warning and checked for
+/// disk corruption
+pub const REPEAT_THRESHOLD: u32 = 3;
+
+/// Failures listed in `PiecesInfo` (most recent first)
+pub const MAX_LISTED: usize = 32;
+
+/// Events waiting for the engine to emit them; older ones are dropped
+const MAX_PENDING_EVENTS: usize = 64;
+
+/// A piece that failed verification recently
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PieceFailure {
+    pub piece: usize,
+    /// When it last failed (Unix ms)
+    pub failed_at: i64,
+    /// Failures since the entry was created
+    pub failures: u32,
+    /// Peers the piece was requested from, across all failures
+    pub peers: Vec<PeerKey>,
+}
+
+/// What the engine should tell the UI about
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FailureEvent {
+    /// "piece-failed"
+    Failed(PieceFailure),
+    /// "possible-disk-corruption": the piece keeps failing whoever sends it
+    PossibleCorruption(PieceFailure),
+}
+
+#[derive(Debug, Default)]
+struct Entry {
+    failed_at: i64,
+    failures: u32,
+    /// Peers behind each failure, oldest first
+    sources: Vec<BTreeSet<PeerKey>>,
+    corruption_reported: bool,
+}
+
+impl Entry {
+    fn snapshot(&self, piece: usize) -> PieceFailure {
+        let peers: BTreeSet<PeerKey> = self.sources.iter().flatten().copied().collect();
+        PieceFailure {
+            piece,
+            failed_at: self.failed_at,
+            failures: self.failures,
+            peers: peers.into_iter().collect(),
+        }
+    }
+
+    /// No single peer took part in every failure (and we know who did)
+    fn blames_no_peer(&self) -> bool {
+        let Some((first, rest)) = self.sources.split_first() else { return false };
+        if self.sources.iter().any(BTreeSet::is_empty) {
+            return false;
+        }
+        !first.iter().any(|peer| rest.iter().all(|source| source.contains(peer)))
+    }
+}
+
+/// Per-torrent record of recent verification failures
+#[derive(Debug, Default)]
+pub struct FailureTracker {
+    entries: HashMap<usize, Entry>,
+    pending: Vec<FailureEvent>,
+}
+
+impl FailureTracker {
+    /// Record that `piece` failed verification at `now` (Unix ms) with data
+    /// requested from `peers`
+    pub fn record(&mut self, piece: usize, peers: impl IntoIterator<Item = PeerKey>, now: i64) {
+        self.expire(now);
+
+        let entry = self.entries.entry(piece).or_default();
+        entry.failed_at = now;
+        entry.failures += 1;
+        entry.sources.push(peers.into_iter().collect());
+        let failure = entry.snapshot(piece);
+
+        let mut events = vec![FailureEvent::Failed(failure.clone())];
+        if entry.failures >= REPEAT_THRESHOLD {
+            tracing::warn!(
+                "Piece {} failed verification {} times (peers {:?})",
+                piece,
+                entry.failures,
+                failure.peers
+            );
+            if !entry.corruption_reported && entry.blames_no_peer() {
+                entry.corruption_reported = true;
+                tracing::warn!("Piece {} fails with data from unrelated peers, local data may be corrupt", piece);
+                events.push(FailureEvent::PossibleCorruption(failure));
+            }
+        }
+
+        self.pending.extend(events);
+        if self.pending.len() > MAX_PENDING_EVENTS {
+            let excess = self.pending.len() - MAX_PENDING_EVENTS;
+            self.pending.drain(..excess);
+        }
+    }
+
+    /// Forget failures whose piece hasn't failed for `FAILURE_TTL_MS`
+    pub fn expire(&mut self, now: i64) {
+        self.entries.retain(|_, entry| now - entry.failed_at < FAILURE_TTL_MS);
+    }
+
+    /// Unexpired failures at `now`, most recent first, at most `MAX_LISTED`
+    pub fn recent(&self, now: i64) -> Vec<PieceFailure> {
+        let mut recent: Vec<PieceFailure> = self
+            .entries
+            .iter()
+            .filter(|(_, entry)| now - entry.failed_at < FAILURE_TTL_MS)
+            .map(|(&piece, entry)| entry.snapshot(piece))
+            .collect();
+        recent.sort_by(|a, b| b.failed_at.cmp(&a.failed_at).then(a.piece.cmp(&b.piece)));
+        recent.truncate(MAX_LISTED);
+        recent
+    }
+
+    /// Events recorded since the last call
+    pub fn take_events(&mut self) -> Vec<FailureEvent> {
+        std::mem::take(&mut self.pending)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_failures_are_listed_and_expire() {
+        let mut tracker = FailureTracker::default();
+        tracker.record(4, [1], 1_000);
+        tracker.record(7, [2], 2_000);
+        tracker.record(4, [1], 3_000);
+
+        let recent = tracker.recent(3_000);
+        assert_eq!(recent.len(), 2);
+        assert_eq!(recent[0], PieceFailure { piece: 4, failed_at: 3_000, failures: 2, peers: vec![1] });
+        assert_eq!(recent[1].piece, 7);
+
+        // Piece 7 ages out first
+        let recent = tracker.recent(2_000 + FAILURE_TTL_MS);
+        assert_eq!(recent.iter().map(|f| f.piece).collect::<Vec<_>>(), vec![4]);
+        tracker.expire(3_000 + FAILURE_TTL_MS);
+        assert!(tracker.recent(3_000 + FAILURE_TTL_MS).is_empty());
+
+        // A failure after expiry starts counting again
+        tracker.record(4, [1], 4_000 + FAILURE_TTL_MS);
+        assert_eq!(tracker.recent(4_000 + FAILURE_TTL_MS)[0].failures, 1);
+    }
+
+    #[test]
+    fn test_one_bad_peer_is_not_corruption() {
+        let mut tracker = FailureTracker::default();
+        tracker.record(0, [1], 0);
+        tracker.record(0, [1, 2], 1);
+        tracker.record(0, [1, 3], 2);
+        tracker.record(0, [1], 3);
+
+        let events = tracker.take_events();
+        assert_eq!(events.len(), 4);
+        assert!(events.iter().all(|e| matches!(e, FailureEvent::Failed(_))));
+        assert!(tracker.take_events().is_empty());
+    }
+
+    #[test]
+    fn test_unrelated_peers_escalate_once() {
+        let mut tracker = FailureTracker::default();
+        tracker.record(9, [1], 0);
+        tracker.record(9, [2], 1);
+        assert_eq!(tracker.take_events().len(), 2);
+
+        // Below the threshold nothing escalates; the third failure does
+        tracker.record(9, [3], 2);
+        let events = tracker.take_events();
+        assert_eq!(events.len(), 2);
+        match &events[1] {
+            FailureEvent::PossibleCorruption(failure) => {
+                assert_eq!(failure.failures, 3);
+                assert_eq!(failure.peers, vec![1, 2, 3]);
+            }
+            other => panic!("expected an escalation, got {:?}", other),
+        }
+
+        tracker.record(9, [4], 3);
+        assert_eq!(tracker.take_events().len(), 1);
+    }
+
+    #[test]
+    fn test_unknown_sources_do_not_escalate() {
+        let mut tracker = FailureTracker::default();
+        for now in 0..5 {
+            tracker.record(1, [], now);
+        }
+        assert!(tracker
+            .take_events()
+            .iter()
+            .all(|e| matches!(e, FailureEvent::Failed(_))));
+    }
+}
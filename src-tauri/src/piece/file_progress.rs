@@ -0,0 +1,183 @@
+//! Per-file progress and completion
+//!
+//! A file is complete once every piece holding any of its bytes is verified.
+//! A piece at a file boundary counts for the files on both sides, so one
+//! piece may complete two files, and a file may complete before the ones in
+//! front of it. Empty files hold no piece and are never counted complete.
+
+use super::ranges;
+use super::Bitfield;
+use crate::torrent::FileInfo;
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+/// When each file finished (unix seconds), by file index
+pub type FileCompletions = BTreeMap<usize, i64>;
+
+/// The files of a torrent laid over its pieces
+#[derive(Debug, Clone, Default)]
+pub struct FileProgressTracker {
+    /// Byte range of every file in the torrent's byte stream
+    spans: Vec<Range<u64>>,
+    piece_length: u64,
+    total_size: u64,
+}
+
+impl FileProgressTracker {
+    pub fn new(files: &[FileInfo], piece_length: u64) -> Self {
+        let spans = ranges::file_spans(files);
+        let total_size = spans.last().map_or(0, |span| span.end);
+        Self { spans, piece_length, total_size }
+    }
+
+    pub fn num_files(&self) -> usize {
+        self.spans.len()
+    }
+
+    /// Pieces holding any byte of a file; empty for an empty or unknown file
+    pub fn pieces(&self, file_index: usize) -> Range<usize> {
+        match self.spans.get(file_index) {
+            Some(span) if self.piece_length > 0 => ranges::pieces_covering(span, self.piece_length),
+            _ => 0..0,
+        }
+    }
+
+    /// Files with bytes in `piece_index`, in order
+    pub fn files_in_piece(&self, piece_index: usize) -> Vec<usize> {
+        let piece = ranges::piece_span(piece_index, self.piece_length, self.total_size);
+        // Spans are sorted and touching: skip those ending before the piece
+        let first = self.spans.partition_point(|span| span.end <= piece.start);
+        (first..self.spans.len())
+            .take_while(|&file_index| self.spans[file_index].start < piece.end)
+            .filter(|&file_index| !self.spans[file_index].is_empty())
+            .collect()
+    }
+
+    /// Whether every piece of a non-empty file is in `bitfield`
+    pub fn is_complete(&self, file_index: usize, bitfield: &Bitfield) -> bool {
+        let mut pieces = self.pieces(file_index);
+        !pieces.is_empty() && pieces.all(|piece| bitfield.has_piece(piece))
+    }
+
+    /// Files `piece_index` completed, given `bitfield` now has it
+    pub fn completed_by(&self, piece_index: usize, bitfield: &Bitfield) -> Vec<usize> {
+        self.files_in_piece(piece_index)
+            .into_iter()
+            .filter(|&file_index| self.is_complete(file_index, bitfield))
+            .collect()
+    }
+
+    /// Verified bytes of every file
+    pub fn downloaded(&self, bitfield: &Bitfield) -> Vec<u64> {
+        (0..self.spans.len())
+            .map(|file_index| {
+                let span = &self.spans[file_index];
+                self.pieces(file_index)
+                    .filter(|&piece| bitfield.has_piece(piece))
+                    .map(|piece| ranges::overlap(span, &ranges::piece_span(piece, self.piece_length, self.total_size)))
+                    .sum()
+            })
+            .collect()
+    }
+
+    /// Bring `completed_at` in line with `bitfield`. Files that became
+    /// complete get `now` and are returned; files no longer complete (a
+    /// piece failed a recheck) lose their timestamp until they complete
+    /// again. Files still complete keep the time they first completed.
+    pub fn reconcile(&self, completed_at: &mut FileCompletions, bitfield: &Bitfield, now: i64) -> Vec<usize> {
+        completed_at.retain(|&file_index, _| self.is_complete(file_index, bitfield));
+        let completed: Vec<usize> = (0..self.spans.len())
+            .filter(|file_index| !completed_at.contains_key(file_index))
+            .filter(|&file_index| self.is_complete(file_index, bitfield))
+            .collect();
+        for &file_index in &completed {
+            completed_at.insert(file_index, now);
+        }
+        completed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn files(lengths: &[u64]) -> Vec<FileInfo> {
+        lengths
+            .iter()
+            .enumerate()
+            .map(|(i, &length)| FileInfo { path: vec![format!("file{}", i)], length })
+            .collect()
+    }
+
+    /// 400 bytes in 64-byte pieces (7, the last one 16 bytes): piece 1 is
+    /// shared by files 0 and 2, piece 5 by files 2 and 3; file 1 is empty
+    fn tracker() -> FileProgressTracker {
+        FileProgressTracker::new(&files(&[100, 0, 250, 50]), 64)
+    }
+
+    fn bitfield(pieces: &[usize]) -> Bitfield {
+        let mut bitfield = Bitfield::new(7);
+        pieces.iter().for_each(|&piece| bitfield.set_piece(piece));
+        bitfield
+    }
+
+    #[test]
+    fn test_boundary_pieces() {
+        let tracker = tracker();
+        assert_eq!(tracker.pieces(0), 0..2);
+        assert_eq!(tracker.pieces(1), 0..0);
+        assert_eq!(tracker.pieces(2), 1..6);
+        assert_eq!(tracker.pieces(3), 5..7);
+        assert_eq!(tracker.pieces(9), 0..0);
+
+        assert_eq!(tracker.files_in_piece(0), vec![0]);
+        assert_eq!(tracker.files_in_piece(1), vec![0, 2]);
+        assert_eq!(tracker.files_in_piece(3), vec![2]);
+        assert_eq!(tracker.files_in_piece(5), vec![2, 3]);
+        assert_eq!(tracker.files_in_piece(6), vec![3]);
+
+        // The shared piece finishes both files at once
+        let have = bitfield(&[0, 2, 3, 4, 5, 6, 1]);
+        assert_eq!(tracker.completed_by(1, &have), vec![0, 2]);
+        assert!(!tracker.is_complete(1, &have), "empty files never complete");
+        assert_eq!(tracker.downloaded(&have), vec![100, 0, 250, 50]);
+
+        // Without it only the bytes of the other pieces count
+        let have = bitfield(&[0, 2, 3, 4, 5, 6]);
+        assert!(!tracker.is_complete(0, &have) && !tracker.is_complete(2, &have));
+        assert_eq!(tracker.downloaded(&have), vec![64, 0, 250 - 28, 50]);
+    }
+
+    #[test]
+    fn test_out_of_order_completion() {
+        let tracker = tracker();
+        let mut completed_at = FileCompletions::new();
+
+        // The last file first: its pieces are 5 and 6
+        let mut have = bitfield(&[5, 6]);
+        assert_eq!(tracker.completed_by(6, &have), vec![3]);
+        assert_eq!(tracker.reconcile(&mut completed_at, &have, 100), vec![3]);
+
+        // Then the first; the middle one still misses pieces 2-4
+        have.set_piece(0);
+        have.set_piece(1);
+        assert_eq!(tracker.reconcile(&mut completed_at, &have, 200), vec![0]);
+        for piece in 2..5 {
+            have.set_piece(piece);
+        }
+        assert_eq!(tracker.reconcile(&mut completed_at, &have, 300), vec![2]);
+        assert_eq!(completed_at, FileCompletions::from([(0, 200), (2, 300), (3, 100)]));
+
+        // A recheck that confirms everything changes nothing
+        assert!(tracker.reconcile(&mut completed_at, &have, 400).is_empty());
+        assert_eq!(completed_at[&3], 100);
+
+        // One that fails the shared piece 5 clears both of its files
+        have.clear_piece(5);
+        assert!(tracker.reconcile(&mut completed_at, &have, 500).is_empty());
+        assert_eq!(completed_at, FileCompletions::from([(0, 200)]));
+        have.set_piece(5);
+        assert_eq!(tracker.reconcile(&mut completed_at, &have, 600), vec![2, 3]);
+        assert_eq!(completed_at, FileCompletions::from([(0, 200), (2, 600), (3, 600)]));
+    }
+}
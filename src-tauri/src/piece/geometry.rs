@@ -0,0 +1,112 @@
+//! Piece and block boundaries
+//!
+//! Every piece but the last is `piece_length` long; the last holds what is
+//! left. Pieces are requested in `BLOCK_SIZE` blocks, the last block of a
+//! piece being short when the piece length isn't a multiple of it. All the
+//! length math for requests and incoming blocks goes through here.
+
+use super::{BlockInfo, BLOCK_SIZE};
+
+/// Lengths of a torrent's pieces
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PieceGeometry {
+    num_pieces: usize,
+    piece_length: usize,
+    last_piece_length: usize,
+}
+
+impl PieceGeometry {
+    pub fn new(num_pieces: usize, piece_length: usize, last_piece_length: usize) -> Self {
+        Self {
+            num_pieces,
+            piece_length,
+            last_piece_length,
+        }
+    }
+
+    pub fn num_pieces(&self) -> usize {
+        self.num_pieces
+    }
+
+    pub fn piece_length(&self) -> usize {
+        self.piece_length
+    }
+
+    /// Length of one piece; the last may be shorter
+    pub fn piece_len(&self, piece_index: usize) -> usize {
+        if piece_index + 1 == self.num_pieces {
+            self.last_piece_length
+        } else {
+            self.piece_length
+        }
+    }
+
+    /// Sum of the piece lengths
+    pub fn total_size(&self) -> u64 {
+        match self.num_pieces {
+            0 => 0,
+            n => (n - 1) as u64 * self.piece_length as u64 + self.last_piece_length as u64,
+        }
+    }
+
+    /// Number of `BLOCK_SIZE` blocks a piece is requested in
+    pub fn num_blocks(&self, piece_index: usize) -> usize {
+        self.piece_len(piece_index).div_ceil(BLOCK_SIZE)
+    }
+
+    /// The block of a piece starting at `offset`: up to `BLOCK_SIZE` bytes,
+    /// fewer at the end of the piece
+    pub fn block_at(&self, piece_index: usize, offset: usize) -> BlockInfo {
+        let length = self.piece_len(piece_index).saturating_sub(offset).min(BLOCK_SIZE);
+        BlockInfo::new(piece_index, offset, length)
+    }
+
+    /// Every block of a piece, in order
+    pub fn blocks(&self, piece_index: usize) -> Vec<BlockInfo> {
+        (0..self.num_blocks(piece_index))
+            .map(|i| self.block_at(piece_index, i * BLOCK_SIZE))
+            .collect()
+    }
+
+    /// Whether `length` bytes at `begin` lie within an existing piece
+    pub fn fits(&self, piece_index: usize, begin: usize, length: usize) -> bool {
+        piece_index < self.num_pieces
+            && begin
+                .checked_add(length)
+                .is_some_and(|end| end <= self.piece_len(piece_index))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_last_piece_and_block() {
+        // 3 pieces of 40000 bytes, the last one 1000
+        let geometry = PieceGeometry::new(3, 40000, 1000);
+        assert_eq!(geometry.total_size(), 81000);
+        assert_eq!((geometry.piece_len(1), geometry.piece_len(2)), (40000, 1000));
+        assert_eq!(geometry.num_blocks(0), 3);
+        assert_eq!(
+            geometry.blocks(0),
+            vec![
+                BlockInfo::new(0, 0, BLOCK_SIZE),
+                BlockInfo::new(0, BLOCK_SIZE, BLOCK_SIZE),
+                BlockInfo::new(0, 2 * BLOCK_SIZE, 40000 - 2 * BLOCK_SIZE),
+            ]
+        );
+        assert_eq!(geometry.blocks(2), vec![BlockInfo::new(2, 0, 1000)]);
+        assert_eq!(geometry.block_at(2, 1000).length, 0);
+
+        assert!(geometry.fits(0, 0, 40000));
+        assert!(geometry.fits(2, 999, 1));
+        assert!(!geometry.fits(2, 999, 2));
+        assert!(!geometry.fits(3, 0, 1));
+        assert!(!geometry.fits(0, usize::MAX, 2));
+
+        let empty = PieceGeometry::new(0, 16384, 0);
+        assert_eq!(empty.total_size(), 0);
+        assert!(!empty.fits(0, 0, 0));
+    }
+}
@@ -1,13 +1,22 @@
 /// Piece manager for coordinating piece downloads and verification
 pub mod bitfield;
+pub mod buckets;
+pub mod failures;
+pub mod file_progress;
+pub mod geometry;
+pub mod ranges;
 pub mod strategy;
 
 pub use bitfield::Bitfield;
+pub use buckets::{PieceBucket, PieceWindow};
+pub use failures::{FailureEvent, PieceFailure};
+pub use file_progress::{FileCompletions, FileProgressTracker};
+pub use geometry::PieceGeometry;
 pub use strategy::{PieceSelector, SelectionStrategy, PiecePriority};
 
 use serde::{Deserialize, Serialize};
 use sha1::{Digest, Sha1};
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
 
 /// Pieces information for UI display
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -18,10 +27,32 @@ pub struct PiecesInfo {
     pub pieces_have: usize,
     /// Number of pieces currently downloading
     pub pieces_downloading: usize,
-    /// Bitfield state (0=missing, 1=have, 2=downloading)
+    /// Pieces per entry of `buckets`; 1 when the pieces are listed one by
+    /// one in `bitfield` and `availability` instead (see `buckets` module)
+    #[serde(default)]
+    pub bucket_size: usize,
+    /// Bitfield state (0=missing, 1=have, 2=downloading); empty when bucketed
     pub bitfield: Vec<u8>,
-    /// Piece availability (number of peers that have each piece)
+    /// Piece availability (number of peers that have each piece); empty when bucketed
     pub availability: Vec<usize>,
+    /// The piece map summarised, when it has more pieces than buckets were asked for
+    #[serde(default)]
+    pub buckets: Vec<PieceBucket>,
+    /// Pieces that failed verification in the last few minutes, most recent first
+    #[serde(default)]
+    pub recent_failures: Vec<PieceFailure>,
+}
+
+/// Session-unique key of a connected peer (never reused, unlike addresses)
+pub type PeerKey = u64;
+
+/// What the piece manager tracks per connected peer
+#[derive(Debug)]
+struct TrackedPeer {
+    /// Pieces this peer has added to the selector's availability counts
+    counted: Bitfield,
+    /// Pieces we're downloading from this peer
+    requests: HashSet<usize>,
 }
 
 /// Standard block size for piece requests (16KB)
@@ -46,43 +77,135 @@ impl BlockInfo {
 }
 
 /// State of a piece being downloaded
+///
+/// Blocks are hashed as they arrive, as far as the data is contiguous from
+/// the start of the piece; blocks past a gap wait until it is filled. By
+/// the time the last block lands little is left to hash, instead of the
+/// whole piece at once.
+///
+/// What arrived is kept as byte ranges rather than whole blocks, so a peer
+/// answering a request with more or fewer bytes than asked loses nothing.
 #[derive(Debug, Clone)]
 struct PieceState {
     /// Data buffer for this piece
     data: Vec<u8>,
-    /// Which blocks have been downloaded
-    downloaded_blocks: HashSet<usize>, // block offset
-    /// Total blocks in this piece
-    total_blocks: usize,
+    /// Byte ranges received, start to end; disjoint and not touching
+    received: BTreeMap<usize, usize>,
+    /// SHA1 of `data[..hashed_to]`
+    hasher: Sha1,
+    /// End of the contiguous prefix already fed to `hasher`
+    hashed_to: usize,
 }
 
 impl PieceState {
     fn new(piece_length: usize) -> Self {
-        let total_blocks = (piece_length + BLOCK_SIZE - 1) / BLOCK_SIZE;
         Self {
             data: vec![0; piece_length],
-            downloaded_blocks: HashSet::new(),
-            total_blocks,
+            received: BTreeMap::new(),
+            hasher: Sha1::new(),
+            hashed_to: 0,
         }
     }
 
     fn write_block(&mut self, offset: usize, data: &[u8]) {
         let end = offset + data.len();
-        if end <= self.data.len() {
+        if end <= self.data.len() && !data.is_empty() {
+            // A block rewritten after a failure, with different bytes, under
+            // what was hashed already: start the hash over
+            if offset < self.hashed_to && self.data[offset..end] != *data {
+                self.hasher = Sha1::new();
+                self.hashed_to = 0;
+            }
             self.data[offset..end].copy_from_slice(data);
-            self.downloaded_blocks.insert(offset);
+            self.mark_received(offset, end);
+            self.hash_contiguous();
+        }
+    }
+
+    /// Add `start..end` to the received ranges, merging with its neighbours
+    fn mark_received(&mut self, mut start: usize, mut end: usize) {
+        let touching: Vec<(usize, usize)> = self
+            .received
+            .range(..=end)
+            .filter(|&(_, &range_end)| range_end >= start)
+            .map(|(&range_start, &range_end)| (range_start, range_end))
+            .collect();
+        for (range_start, range_end) in touching {
+            self.received.remove(&range_start);
+            start = start.min(range_start);
+            end = end.max(range_end);
+        }
+        self.received.insert(start, end);
+    }
+
+    /// Take `start..end` out of the received ranges
+    fn forget(&mut self, start: usize, end: usize) {
+        let overlapping: Vec<(usize, usize)> = self
+            .received
+            .range(..end)
+            .filter(|&(_, &range_end)| range_end > start)
+            .map(|(&range_start, &range_end)| (range_start, range_end))
+            .collect();
+        for (range_start, range_end) in overlapping {
+            self.received.remove(&range_start);
+            if range_start < start {
+                self.received.insert(range_start, start);
+            }
+            if range_end > end {
+                self.received.insert(end, range_end);
+            }
+        }
+    }
+
+    /// Whether every byte of `start..end` arrived
+    fn has_range(&self, start: usize, end: usize) -> bool {
+        self.received
+            .range(..=start)
+            .next_back()
+            .is_some_and(|(_, &range_end)| range_end >= end)
+    }
+
+    /// Feed the hasher the received bytes that follow `hashed_to`
+    fn hash_contiguous(&mut self) {
+        let Some(&prefix_end) = self.received.get(&0) else { return };
+        if prefix_end > self.hashed_to {
+            self.hasher.update(&self.data[self.hashed_to..prefix_end]);
+            self.hashed_to = prefix_end;
         }
     }
 
+    /// SHA1 of the whole piece: the running hash plus whatever is left
+    fn finish_hash(&mut self) -> Vec<u8> {
+        let mut hasher = std::mem::replace(&mut self.hasher, Sha1::new());
+        hasher.update(&self.data[self.hashed_to..]);
+        self.hashed_to = 0;
+        hasher.finalize().to_vec()
+    }
+
     fn is_complete(&self) -> bool {
-        self.downloaded_blocks.len() == self.total_blocks
+        self.has_range(0, self.data.len())
     }
 
-    fn missing_blocks(&self) -> Vec<usize> {
-        (0..self.total_blocks)
-            .map(|i| i * BLOCK_SIZE)
-            .filter(|offset| !self.downloaded_blocks.contains(offset))
-            .collect()
+    /// The gaps, cut at `BLOCK_SIZE` boundaries: (offset, length) of each
+    /// block still to request
+    fn missing_blocks(&self) -> Vec<(usize, usize)> {
+        let mut blocks = Vec::new();
+        let mut gap_start = 0;
+        let gap_ends = self
+            .received
+            .iter()
+            .map(|(&start, &end)| (start, end))
+            .chain(std::iter::once((self.data.len(), self.data.len())));
+        for (gap_end, next_start) in gap_ends {
+            let mut offset = gap_start;
+            while offset < gap_end {
+                let block_end = ((offset / BLOCK_SIZE + 1) * BLOCK_SIZE).min(gap_end);
+                blocks.push((offset, block_end - offset));
+                offset = block_end;
+            }
+            gap_start = next_start;
+        }
+        blocks
     }
 }
 
@@ -94,19 +217,19 @@ pub struct PieceManager {
     selector: PieceSelector,
     /// SHA1 hashes for each piece (from metainfo)
     piece_hashes: Vec<Vec<u8>>,
-    /// Length of each piece in bytes
-    piece_length: usize,
-    /// Length of the last piece (may be shorter)
-    last_piece_length: usize,
+    /// Piece and block lengths
+    geometry: PieceGeometry,
     /// Total number of pieces
     num_pieces: usize,
     /// Pieces currently being downloaded
     in_progress: HashMap<usize, PieceState>,
     /// Pieces that have been verified and are complete
     verified_pieces: HashSet<usize>,
-    /// Track which pieces we've requested from which peers
-    /// peer_id -> set of piece indices
-    peer_requests: HashMap<String, HashSet<usize>>,
+    /// Connected peers: their share of availability and the pieces we
+    /// requested from them
+    peers: HashMap<PeerKey, TrackedPeer>,
+    /// Recent hash failures, for the piece map and corruption warnings
+    failures: failures::FailureTracker,
 }
 
 impl PieceManager {
@@ -127,12 +250,12 @@ impl PieceManager {
             our_bitfield: Bitfield::new(num_pieces),
             selector: PieceSelector::new(strategy),
             piece_hashes,
-            piece_length,
-            last_piece_length,
+            geometry: PieceGeometry::new(num_pieces, piece_length, last_piece_length),
             num_pieces,
             in_progress: HashMap::new(),
             verified_pieces: HashSet::new(),
-            peer_requests: HashMap::new(),
+            peers: HashMap::new(),
+            failures: Default::default(),
         }
     }
 
@@ -141,21 +264,79 @@ impl PieceManager {
         self.selector.set_piece_priority(piece_idx, priority);
     }
 
-    /// Add a peer's bitfield to tracking
-    pub fn add_peer(&mut self, peer_id: String, peer_bitfield: &Bitfield) {
+    /// Priority of a piece
+    pub fn piece_priority(&self, piece_idx: usize) -> PiecePriority {
+        self.selector.priority(piece_idx)
+    }
+
+    /// Add a peer's bitfield to tracking (replacing what it reported before)
+    pub fn add_peer(&mut self, peer: PeerKey, peer_bitfield: &Bitfield) {
+        let requests = match self.peers.remove(&peer) {
+            Some(old) => {
+                self.selector.remove_peer(&old.counted);
+                old.requests
+            }
+            None => HashSet::new(),
+        };
         self.selector.add_peer(peer_bitfield);
-        self.peer_requests.insert(peer_id, HashSet::new());
+        self.peers.insert(peer, TrackedPeer { counted: peer_bitfield.clone(), requests });
+        debug_assert!(self.availability_is_consistent());
+    }
+
+    /// Remove a peer from tracking, taking back exactly what it added
+    pub fn remove_peer(&mut self, peer: PeerKey) {
+        if let Some(old) = self.peers.remove(&peer) {
+            self.selector.remove_peer(&old.counted);
+        }
+        debug_assert!(self.availability_is_consistent());
+    }
+
+    /// Update when peer announces they have a new piece (repeats are ignored)
+    pub fn peer_has_piece(&mut self, peer: PeerKey, piece_index: usize) {
+        if piece_index >= self.num_pieces {
+            return;
+        }
+        // Peers with nothing may skip the bitfield and start with Have
+        let num_pieces = self.num_pieces;
+        let tracked = self.peers.entry(peer).or_insert_with(|| TrackedPeer {
+            counted: Bitfield::new(num_pieces),
+            requests: HashSet::new(),
+        });
+        if !tracked.counted.has_piece(piece_index) {
+            tracked.counted.set_piece(piece_index);
+            self.selector.mark_piece_available(piece_index);
+        }
+    }
+
+    /// Number of peers being tracked
+    pub fn tracked_peers(&self) -> usize {
+        self.peers.len()
+    }
+
+    /// Sum of all pieces' availability counts
+    pub fn total_availability(&self) -> usize {
+        self.selector.total_availability()
+    }
+
+    /// Availability must be exactly what the tracked peers contributed, or it
+    /// drifts with every reconnect
+    pub fn availability_is_consistent(&self) -> bool {
+        let contributed: usize = self.peers.values().map(|p| p.counted.count_pieces()).sum();
+        self.selector.total_availability() == contributed
     }
 
-    /// Remove a peer from tracking
-    pub fn remove_peer(&mut self, peer_id: &str, peer_bitfield: &Bitfield) {
-        self.selector.remove_peer(peer_bitfield);
-        self.peer_requests.remove(peer_id);
+    /// Check if we want a specific piece (missing and not skipped)
+    pub fn wants_piece(&self, piece_index: usize) -> bool {
+        piece_index < self.num_pieces
+            && !self.our_bitfield.has_piece(piece_index)
+            && !self.selector.is_skipped(piece_index)
     }
 
-    /// Update when peer announces they have a new piece
-    pub fn peer_has_piece(&mut self, piece_index: usize) {
-        self.selector.mark_piece_available(piece_index);
+    /// Check if a peer has any piece we still want to download
+    pub fn is_interested_in(&self, peer_bitfield: &Bitfield) -> bool {
+        self.our_bitfield
+            .iter_missing_from(peer_bitfield)
+            .any(|i| !self.selector.is_skipped(i))
     }
 
     /// Get our current bitfield
@@ -180,11 +361,37 @@ impl PieceManager {
         );
     }
 
+    /// Whether `data` hashes to the expected value for a piece
+    pub fn piece_data_matches(&self, piece_index: usize, data: &[u8]) -> bool {
+        self.piece_hashes
+            .get(piece_index)
+            .is_some_and(|expected| Sha1::digest(data).as_slice() == expected.as_slice())
+    }
+
+    /// Drop a piece we can no longer vouch for so it gets downloaded again
+    pub fn unmark_piece(&mut self, piece_index: usize) {
+        self.our_bitfield.clear_piece(piece_index);
+        self.verified_pieces.remove(&piece_index);
+    }
+
     /// Check if we have a specific piece
     pub fn has_piece(&self, piece_index: usize) -> bool {
         self.our_bitfield.has_piece(piece_index)
     }
 
+    /// Whether a piece may be uploaded: its bit is set and it passed a hash
+    /// check (or was restored from a session that had). A bit set any other
+    /// way isn't proof the data on disk is right.
+    pub fn can_serve(&self, piece_index: usize) -> bool {
+        self.our_bitfield.has_piece(piece_index) && self.verified_pieces.contains(&piece_index)
+    }
+
+    /// Set a piece's bit without verifying it, for building inconsistent states
+    #[cfg(test)]
+    pub(crate) fn set_unverified(&mut self, piece_index: usize) {
+        self.our_bitfield.set_piece(piece_index);
+    }
+
     /// Get download completion percentage
     pub fn completion(&self) -> f64 {
         self.our_bitfield.completion()
@@ -195,34 +402,26 @@ impl PieceManager {
         self.our_bitfield.is_complete()
     }
 
+    /// Piece and block lengths of the torrent
+    pub fn geometry(&self) -> &PieceGeometry {
+        &self.geometry
+    }
+
     /// Get the length of a specific piece
     pub fn piece_len(&self, piece_index: usize) -> usize {
-        if piece_index == self.num_pieces - 1 {
-            self.last_piece_length
-        } else {
-            self.piece_length
-        }
+        self.geometry.piece_len(piece_index)
     }
 
     /// Get list of blocks to request for a piece
     pub fn get_blocks_for_piece(&self, piece_index: usize) -> Vec<BlockInfo> {
-        let piece_len = self.piece_len(piece_index);
-        let num_blocks = (piece_len + BLOCK_SIZE - 1) / BLOCK_SIZE;
-
-        (0..num_blocks)
-            .map(|i| {
-                let offset = i * BLOCK_SIZE;
-                let length = std::cmp::min(BLOCK_SIZE, piece_len - offset);
-                BlockInfo::new(piece_index, offset, length)
-            })
-            .collect()
+        self.geometry.blocks(piece_index)
     }
 
     /// Select next piece to download from a peer
     /// Returns piece index and list of blocks to request
     pub fn select_next_piece(
         &mut self,
-        peer_id: &str,
+        peer: PeerKey,
         peer_bitfield: &Bitfield,
     ) -> Option<(usize, Vec<BlockInfo>)> {
         let pending: Vec<usize> = self.in_progress.keys().copied().collect();
@@ -238,40 +437,52 @@ impl PieceManager {
                 .insert(piece_index, PieceState::new(piece_len));
         }
 
-        // Track that this peer is working on this piece
-        if let Some(peer_pieces) = self.peer_requests.get_mut(peer_id) {
-            peer_pieces.insert(piece_index);
-        }
+        self.track_request(peer, piece_index);
 
         let blocks = self.get_blocks_for_piece(piece_index);
         Some((piece_index, blocks))
     }
 
+    /// Note that blocks of `piece_index` were requested from `peer`
+    pub fn track_request(&mut self, peer: PeerKey, piece_index: usize) {
+        if let Some(tracked) = self.peers.get_mut(&peer) {
+            tracked.requests.insert(piece_index);
+        }
+    }
+
     /// Get missing blocks for a piece that's in progress
     pub fn get_missing_blocks(&self, piece_index: usize) -> Option<Vec<BlockInfo>> {
         let state = self.in_progress.get(&piece_index)?;
-        let missing_offsets = state.missing_blocks();
-
-        let blocks: Vec<BlockInfo> = missing_offsets
+        let blocks = state
+            .missing_blocks()
             .into_iter()
-            .map(|offset| {
-                let piece_len = self.piece_len(piece_index);
-                let length = std::cmp::min(BLOCK_SIZE, piece_len - offset);
-                BlockInfo::new(piece_index, offset, length)
-            })
+            .map(|(offset, length)| BlockInfo::new(piece_index, offset, length))
             .collect();
-
         Some(blocks)
     }
 
-    /// Write received block data to piece buffer
+    /// Write received block data to piece buffer. `block` is the request
+    /// answered; data of another length is taken as long as it starts at the
+    /// block and stays inside the piece (what it leaves out is requested
+    /// again, what it adds counts as received).
     pub fn write_block(&mut self, block: BlockInfo, data: &[u8]) -> Result<bool, String> {
-        if block.length != data.len() {
+        if !self.geometry.fits(block.piece_index, block.offset, data.len()) {
             return Err(format!(
-                "Block data length mismatch: expected {}, got {}",
+                "Block of {} bytes at offset {} runs past the end of piece {} ({} bytes)",
+                data.len(),
+                block.offset,
+                block.piece_index,
+                self.piece_len(block.piece_index)
+            ));
+        }
+        if block.length != data.len() {
+            tracing::debug!(
+                "Piece {} offset {}: asked for {} bytes, got {}",
+                block.piece_index,
+                block.offset,
                 block.length,
                 data.len()
-            ));
+            );
         }
 
         let state = self
@@ -285,16 +496,32 @@ impl PieceManager {
         Ok(state.is_complete())
     }
 
+    /// Why a requested block that just arrived is of no use, if it isn't:
+    /// its piece is done or was given up on (`Discarded`), or another peer
+    /// already sent the block (`Redundant`)
+    pub fn block_waste(&self, block: &BlockInfo) -> Option<crate::peer::Waste> {
+        if self.our_bitfield.has_piece(block.piece_index) {
+            return Some(crate::peer::Waste::Discarded);
+        }
+        match self.in_progress.get(&block.piece_index) {
+            None => Some(crate::peer::Waste::Discarded),
+            Some(state) if state.has_range(block.offset, block.offset + block.length) => {
+                Some(crate::peer::Waste::Redundant)
+            }
+            Some(_) => None,
+        }
+    }
+
     /// Mark a block as failed (e.g., due to timeout)
-    /// This removes it from downloaded_blocks so it can be re-requested
+    /// This forgets its bytes so it can be re-requested
     pub fn mark_block_failed(&mut self, block: BlockInfo) -> Result<(), String> {
         let state = self
             .in_progress
             .get_mut(&block.piece_index)
             .ok_or_else(|| format!("Piece {} not in progress", block.piece_index))?;
 
-        // Remove block from downloaded blocks so it will appear in missing_blocks()
-        state.downloaded_blocks.remove(&block.offset);
+        // Forget its bytes so it will appear in missing_blocks()
+        state.forget(block.offset, block.offset + block.length);
 
         tracing::debug!(
             "Marked block failed: piece {} offset {} - will be re-requested",
@@ -308,7 +535,7 @@ impl PieceManager {
     /// Verify and finalize a completed piece
     /// Returns the piece data if verification succeeds
     pub fn verify_piece(&mut self, piece_index: usize) -> Result<Vec<u8>, String> {
-        let state = self
+        let mut state = self
             .in_progress
             .remove(&piece_index)
             .ok_or_else(|| format!("Piece {} not in progress", piece_index))?;
@@ -319,17 +546,23 @@ impl PieceManager {
             return Err("Piece not complete".to_string());
         }
 
-        // Calculate SHA1 hash
-        let mut hasher = Sha1::new();
-        hasher.update(&state.data);
-        let hash = hasher.finalize().to_vec();
+        // Mostly hashed already as the blocks came in
+        let hash = state.finish_hash();
 
         // Compare with expected hash
         let expected_hash = &self.piece_hashes[piece_index];
         if hash != *expected_hash {
-            // Hash mismatch - put piece back for re-download
+            // Hash mismatch - put piece back for re-download, and blame
+            // whoever it was requested from (the next attempt starts afresh)
             self.in_progress
                 .insert(piece_index, PieceState::new(state.data.len()));
+            let sources: Vec<PeerKey> = self
+                .peers
+                .iter_mut()
+                .filter_map(|(&peer, tracked)| tracked.requests.remove(&piece_index).then_some(peer))
+                .collect();
+            self.failures
+                .record(piece_index, sources, chrono::Utc::now().timestamp_millis());
             return Err(format!(
                 "Piece {} hash verification failed: expected {:?}, got {:?}",
                 piece_index, expected_hash, hash
@@ -341,8 +574,8 @@ impl PieceManager {
         self.verified_pieces.insert(piece_index);
 
         // Remove from peer request tracking
-        for peer_pieces in self.peer_requests.values_mut() {
-            peer_pieces.remove(&piece_index);
+        for tracked in self.peers.values_mut() {
+            tracked.requests.remove(&piece_index);
         }
 
         Ok(state.data)
@@ -374,77 +607,92 @@ impl PieceManager {
         self.selector.set_strategy(strategy);
     }
 
+    /// Verification failures recorded since the last call, for the engine to emit
+    pub fn take_failure_events(&mut self) -> Vec<FailureEvent> {
+        self.failures.expire(chrono::Utc::now().timestamp_millis());
+        self.failures.take_events()
+    }
+
     /// Get pieces that are currently being downloaded
     pub fn in_progress_pieces(&self) -> Vec<usize> {
         self.in_progress.keys().copied().collect()
     }
 
-    /// Get pieces information for UI display
-    pub fn get_pieces_info(&self) -> PiecesInfo {
-        let total = self.num_pieces;
-        let have = self.our_bitfield.count_pieces();
-        let downloading = self.in_progress.len();
-
-        // Build bitfield state array (0=missing, 1=have, 2=downloading)
-        let mut bitfield_state = vec![0u8; total];
-        for i in 0..total {
-            if self.our_bitfield.has_piece(i) {
-                bitfield_state[i] = 1; // Have
-            } else if self.in_progress.contains_key(&i) {
-                bitfield_state[i] = 2; // Downloading
-            }
-            // else 0 = missing (default)
+    /// A piece's state for the UI (see `buckets::PIECE_HAVE` and co)
+    fn piece_display_state(&self, index: usize) -> u8 {
+        if self.our_bitfield.has_piece(index) {
+            buckets::PIECE_HAVE
+        } else if self.in_progress.contains_key(&index) {
+            buckets::PIECE_DOWNLOADING
+        } else {
+            buckets::PIECE_MISSING
         }
+    }
 
-        // Calculate piece availability from selector
-        // This shows how many peers have each piece
-        let availability = self.selector.get_piece_availability(total);
+    /// Get pieces information for UI display, the map summarised into at
+    /// most `max_buckets` buckets if there are more pieces than that
+    pub fn get_pieces_info(&self, max_buckets: usize) -> PiecesInfo {
+        let total = self.num_pieces;
+        let bucket_size = buckets::bucket_size(total, max_buckets);
+
+        let (bitfield, availability, buckets) = if bucket_size == 1 {
+            let window = self.piece_window(0, total);
+            (window.bitfield, window.availability, Vec::new())
+        } else {
+            let buckets = buckets::bucket_pieces(total, max_buckets, |index| {
+                (self.piece_display_state(index), self.selector.get_availability(index))
+            });
+            (Vec::new(), Vec::new(), buckets)
+        };
 
         PiecesInfo {
             total_pieces: total,
-            pieces_have: have,
-            pieces_downloading: downloading,
-            bitfield: bitfield_state,
+            pieces_have: self.our_bitfield.count_pieces(),
+            pieces_downloading: self.in_progress.len(),
+            bucket_size,
+            bitfield,
             availability,
+            buckets,
+            recent_failures: self.failures.recent(chrono::Utc::now().timestamp_millis()),
         }
     }
 
+    /// State and availability of up to `count` pieces from `start`
+    pub fn piece_window(&self, start: usize, count: usize) -> PieceWindow {
+        let start = start.min(self.num_pieces);
+        let end = start + count.min(self.num_pieces - start);
+        PieceWindow {
+            start,
+            bitfield: (start..end).map(|index| self.piece_display_state(index)).collect(),
+            availability: (start..end).map(|index| self.selector.get_availability(index)).collect(),
+        }
+    }
+
+    /// How many peers have each piece
+    pub fn piece_availability(&self) -> Vec<usize> {
+        self.selector.get_piece_availability(self.num_pieces)
+    }
+
     /// Calculate downloaded bytes for a list of files based on current pieces
     pub fn calculate_file_progress(&self, files: &[crate::torrent::FileInfo]) -> Vec<u64> {
-        let mut file_downloaded = Vec::with_capacity(files.len());
-        let mut current_offset: u64 = 0;
-        let piece_len = self.piece_length as u64;
-
-        for file in files {
-            let mut downloaded: u64 = 0;
-            let file_start = current_offset;
-            let file_end = current_offset + file.length;
-
-            if file.length > 0 {
-                let first_piece = (file_start / piece_len) as usize;
-                let last_piece = ((file_end.saturating_sub(1)) / piece_len) as usize;
-
-                for piece_idx in first_piece..=last_piece {
-                    if self.our_bitfield.has_piece(piece_idx) {
-                        let piece_start = piece_idx as u64 * piece_len;
-                        let piece_end = piece_start + self.piece_len(piece_idx) as u64;
-
-                        // Intersection of [file_start, file_end) and [piece_start, piece_end)
-                        let overlap_start = std::cmp::max(file_start, piece_start);
-                        let overlap_end = std::cmp::min(file_end, piece_end);
-
-                        if overlap_end > overlap_start {
-                            downloaded += overlap_end - overlap_start;
-                        }
-                    }
-                }
-            }
+        FileProgressTracker::new(files, self.geometry.piece_length() as u64).downloaded(&self.our_bitfield)
+    }
 
-            file_downloaded.push(downloaded);
-            current_offset += file.length;
+    /// Bytes of the pieces not marked Skip, and how many of those we have;
+    /// what progress and ETA are measured against once files are skipped
+    pub fn wanted_bytes(&self) -> (u64, u64) {
+        let (mut wanted, mut have) = (0u64, 0u64);
+        for piece_idx in 0..self.num_pieces {
+            if self.selector.is_skipped(piece_idx) {
+                continue;
+            }
+            let len = self.piece_len(piece_idx) as u64;
+            wanted += len;
+            if self.our_bitfield.has_piece(piece_idx) {
+                have += len;
+            }
         }
-
-        file_downloaded
+        (wanted, have)
     }
 }
 
@@ -477,8 +725,7 @@ mod tests {
         let pm = PieceManager::new(10, 16384, 8192, hashes, SelectionStrategy::RarestFirst);
 
         assert_eq!(pm.num_pieces, 10);
-        assert_eq!(pm.piece_length, 16384);
-        assert_eq!(pm.last_piece_length, 8192);
+        assert_eq!(pm.geometry, PieceGeometry::new(10, 16384, 8192));
         assert!(!pm.is_complete());
         assert_eq!(pm.completion(), 0.0);
     }
@@ -522,9 +769,9 @@ mod tests {
         peer_bf.set_piece(2);
         peer_bf.set_piece(4);
 
-        pm.add_peer("peer1".to_string(), &peer_bf);
+        pm.add_peer(1, &peer_bf);
 
-        let result = pm.select_next_piece("peer1", &peer_bf);
+        let result = pm.select_next_piece(1, &peer_bf);
         assert!(result.is_some());
 
         let (piece_idx, blocks) = result.unwrap();
@@ -551,9 +798,9 @@ mod tests {
         // Start downloading piece 0
         let mut peer_bf = Bitfield::new(1);
         peer_bf.set_piece(0);
-        pm.add_peer("peer1".to_string(), &peer_bf);
+        pm.add_peer(1, &peer_bf);
 
-        let (piece_idx, blocks) = pm.select_next_piece("peer1", &peer_bf).unwrap();
+        let (piece_idx, blocks) = pm.select_next_piece(1, &peer_bf).unwrap();
         assert_eq!(piece_idx, 0);
 
         // Write the data
@@ -572,6 +819,110 @@ mod tests {
         assert_eq!(pm.completion(), 1.0);
     }
 
+    /// A piece of `len` bytes that differ from block to block
+    fn piece_bytes(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 7 + i / BLOCK_SIZE) as u8).collect()
+    }
+
+    /// Write `data` into a fresh piece state, blocks in `order`
+    fn written(data: &[u8], order: &[usize]) -> PieceState {
+        let mut state = PieceState::new(data.len());
+        for &block in order {
+            let offset = block * BLOCK_SIZE;
+            let end = (offset + BLOCK_SIZE).min(data.len());
+            state.write_block(offset, &data[offset..end]);
+        }
+        state
+    }
+
+    #[test]
+    fn test_incremental_hash_matches_one_shot() {
+        // Short last block
+        let data = piece_bytes(5 * BLOCK_SIZE + 1000);
+        let expected = Sha1::digest(&data).to_vec();
+
+        let mut in_order = written(&data, &[0, 1, 2, 3, 4, 5]);
+        assert_eq!(in_order.hashed_to, data.len());
+        assert_eq!(in_order.finish_hash(), expected);
+
+        // Out of order: only the contiguous prefix is hashed until the gaps fill
+        let mut state = written(&data, &[0, 2, 3]);
+        assert_eq!(state.hashed_to, BLOCK_SIZE);
+        state.write_block(BLOCK_SIZE, &data[BLOCK_SIZE..2 * BLOCK_SIZE]);
+        assert_eq!(state.hashed_to, 4 * BLOCK_SIZE);
+        assert_eq!(written(&data, &[5, 4, 3, 2, 1, 0]).finish_hash(), expected);
+        assert_eq!(written(&data, &[3, 0, 5, 1, 4, 2]).finish_hash(), expected);
+
+        // The same block twice (endgame) changes nothing
+        assert_eq!(written(&data, &[0, 1, 1, 2, 0, 3, 4, 5]).finish_hash(), expected);
+    }
+
+    #[test]
+    fn test_rewritten_block_rehashes() {
+        let data = piece_bytes(4 * BLOCK_SIZE);
+        let mut state = written(&data, &[0, 1, 2]);
+
+        // Block 1 failed and came back with other bytes, then the right ones
+        state.write_block(BLOCK_SIZE, &[0xff; BLOCK_SIZE]);
+        assert_eq!(state.hashed_to, 3 * BLOCK_SIZE);
+        let mut corrupt = data.clone();
+        corrupt[BLOCK_SIZE..2 * BLOCK_SIZE].fill(0xff);
+        state.write_block(3 * BLOCK_SIZE, &data[3 * BLOCK_SIZE..]);
+        assert_eq!(state.clone().finish_hash(), Sha1::digest(&corrupt).to_vec());
+
+        state.write_block(BLOCK_SIZE, &data[BLOCK_SIZE..2 * BLOCK_SIZE]);
+        assert_eq!(state.finish_hash(), Sha1::digest(&data).to_vec());
+    }
+
+    #[test]
+    fn test_verify_after_in_order_arrival_is_cheap() {
+        let data = piece_bytes(8 * 1024 * 1024);
+        let blocks: Vec<usize> = (0..data.len() / BLOCK_SIZE).collect();
+
+        let started = std::time::Instant::now();
+        let one_shot = Sha1::digest(&data).to_vec();
+        let full = started.elapsed();
+
+        let mut state = written(&data, &blocks);
+        let started = std::time::Instant::now();
+        let hash = state.finish_hash();
+        let at_completion = started.elapsed();
+
+        assert_eq!(hash, one_shot);
+        assert!(
+            at_completion * 20 < full,
+            "finishing took {:?}, hashing the whole piece {:?}",
+            at_completion,
+            full
+        );
+    }
+
+    #[test]
+    fn test_block_waste() {
+        use crate::peer::Waste;
+        let piece_data = vec![5u8; 32768];
+        let hash = Sha1::digest(&piece_data).to_vec();
+        let mut pm = PieceManager::new(2, 32768, 32768, vec![hash.clone(), hash], SelectionStrategy::Sequential);
+        let mut peer_bf = Bitfield::new(2);
+        peer_bf.set_piece(0);
+        peer_bf.set_piece(1);
+        pm.add_peer(1, &peer_bf);
+
+        let (piece_idx, blocks) = pm.select_next_piece(1, &peer_bf).unwrap();
+        assert_eq!(piece_idx, 0);
+        assert_eq!(pm.block_waste(&blocks[0]), None);
+        pm.write_block(blocks[0], &piece_data[..16384]).unwrap();
+        // Endgame: the same block from a second peer
+        assert_eq!(pm.block_waste(&blocks[0]), Some(Waste::Redundant));
+        assert_eq!(pm.block_waste(&blocks[1]), None);
+        // Piece 1 was never started (or was given up on)
+        assert_eq!(pm.block_waste(&BlockInfo::new(1, 0, 16384)), Some(Waste::Discarded));
+
+        pm.write_block(blocks[1], &piece_data[16384..]).unwrap();
+        pm.verify_piece(0).unwrap();
+        assert_eq!(pm.block_waste(&blocks[1]), Some(Waste::Discarded));
+    }
+
     #[test]
     fn test_verify_piece_hash_mismatch() {
         let correct_data = b"correct data";
@@ -584,9 +935,9 @@ mod tests {
 
         let mut peer_bf = Bitfield::new(1);
         peer_bf.set_piece(0);
-        pm.add_peer("peer1".to_string(), &peer_bf);
+        pm.add_peer(1, &peer_bf);
 
-        pm.select_next_piece("peer1", &peer_bf);
+        pm.select_next_piece(1, &peer_bf);
 
         // Write wrong data
         let wrong_data = b"wrong data!!";
@@ -599,6 +950,37 @@ mod tests {
         assert!(!pm.has_piece(0));
     }
 
+    #[test]
+    fn test_repeated_failures_from_different_peers() {
+        let hashes = create_test_hashes(1);
+        let mut pm = PieceManager::new(1, 32, 32, hashes, SelectionStrategy::RarestFirst);
+
+        let mut peer_bf = Bitfield::new(1);
+        peer_bf.set_piece(0);
+        for peer in 1..=3 {
+            pm.add_peer(peer, &peer_bf);
+        }
+
+        // Each attempt is fetched from another peer and fails the hash check
+        pm.select_next_piece(1, &peer_bf).unwrap();
+        for peer in 1..=3 {
+            pm.track_request(peer, 0);
+            pm.write_block(BlockInfo::new(0, 0, 32), &[peer as u8; 32]).unwrap();
+            assert!(pm.verify_piece(0).is_err());
+        }
+
+        let failures = pm.get_pieces_info(buckets::DEFAULT_MAX_BUCKETS).recent_failures;
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].piece, 0);
+        assert_eq!(failures[0].failures, 3);
+        assert_eq!(failures[0].peers, vec![1, 2, 3]);
+
+        let events = pm.take_failure_events();
+        assert_eq!(events.iter().filter(|e| matches!(e, FailureEvent::Failed(_))).count(), 3);
+        assert!(matches!(events.last(), Some(FailureEvent::PossibleCorruption(f)) if f.failures == 3));
+        assert!(pm.take_failure_events().is_empty());
+    }
+
     #[test]
     fn test_piece_stats() {
         let hashes = create_test_hashes(10);
@@ -620,4 +1002,119 @@ mod tests {
         assert_eq!(stats.verified_pieces, 2);
         assert_eq!(stats.completion_percent, 20.0);
     }
+
+    #[test]
+    fn test_peer_churn_leaves_no_availability() {
+        let hashes = create_test_hashes(8);
+        let mut pm = PieceManager::new(8, 16384, 16384, hashes, SelectionStrategy::RarestFirst);
+        let complete = Bitfield::complete(8);
+        let mut partial = Bitfield::new(8);
+        partial.set_piece(3);
+
+        pm.add_peer(1, &partial);
+        for key in 2..102 {
+            pm.add_peer(key, &complete);
+            pm.select_next_piece(key, &complete);
+            // Repeated and redundant Haves count once
+            pm.peer_has_piece(key, 5);
+            pm.peer_has_piece(key, 5);
+            // A fresh bitfield replaces the old one instead of adding to it
+            pm.add_peer(key, &complete);
+            assert!(pm.availability_is_consistent());
+            pm.remove_peer(key);
+            pm.remove_peer(key);
+        }
+        assert_eq!(pm.total_availability(), 1);
+        assert_eq!(pm.tracked_peers(), 1);
+
+        // A peer known only from Have messages
+        pm.peer_has_piece(200, 6);
+        pm.peer_has_piece(200, 99);
+        assert_eq!(pm.total_availability(), 2);
+
+        pm.remove_peer(1);
+        pm.remove_peer(200);
+        assert_eq!(pm.total_availability(), 0);
+        assert_eq!(pm.tracked_peers(), 0);
+    }
+
+    /// Answer requests for random pieces with random lengths: short,
+    /// long, overrunning, overlapping and repeated blocks. Seeded so a
+    /// failure reproduces.
+    #[test]
+    fn test_random_block_sizes_reassemble() {
+        use rand::{Rng, SeedableRng};
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0xb10c);
+
+        for _ in 0..200 {
+            let piece_length = rng.gen_range(1..3 * BLOCK_SIZE + 100);
+            let last_length = rng.gen_range(1..=piece_length);
+            let num_pieces = rng.gen_range(1..4);
+            let geometry = PieceGeometry::new(num_pieces, piece_length, last_length);
+            let pieces: Vec<Vec<u8>> = (0..num_pieces)
+                .map(|i| (0..geometry.piece_len(i)).map(|_| rng.gen()).collect())
+                .collect();
+            let hashes = pieces.iter().map(|piece| Sha1::digest(piece).to_vec()).collect();
+            let mut pm = PieceManager::new(num_pieces, piece_length, last_length, hashes, SelectionStrategy::Sequential);
+            let complete = Bitfield::complete(num_pieces);
+            pm.add_peer(1, &complete);
+
+            for (index, piece) in pieces.iter().enumerate() {
+                let (selected, _) = pm.select_next_piece(1, &complete).unwrap();
+                assert_eq!(selected, index);
+                let mut writes = 0;
+                loop {
+                    let missing = pm.get_missing_blocks(index).unwrap();
+                    if missing.is_empty() {
+                        break;
+                    }
+                    assert!(missing.iter().all(|b| b.length > 0 && geometry.fits(index, b.offset, b.length)));
+                    let block = missing[rng.gen_range(0..missing.len())];
+                    // Sometimes a block already received, at an odd offset
+                    let offset = match rng.gen_range(0..4) {
+                        0 => rng.gen_range(0..piece.len()),
+                        _ => block.offset,
+                    };
+                    let length = match rng.gen_range(0..5) {
+                        0 => rng.gen_range(1..=block.length),
+                        1 => rng.gen_range(block.length..=2 * BLOCK_SIZE),
+                        _ => block.length,
+                    };
+                    let request = BlockInfo::new(index, offset, block.length);
+                    let end = offset + length;
+                    if end > piece.len() {
+                        let overrun = vec![0xee; length];
+                        assert!(pm.write_block(request, &overrun).is_err());
+                    } else {
+                        let is_complete = pm.write_block(request, &piece[offset..end]).unwrap();
+                        assert_eq!(is_complete, pm.get_missing_blocks(index).unwrap().is_empty());
+                    }
+                    writes += 1;
+                    assert!(writes < 10_000, "piece {} never completed", index);
+                }
+                assert_eq!(pm.verify_piece(index).unwrap(), *piece);
+            }
+            assert!(pm.is_complete());
+        }
+    }
+
+    #[test]
+    fn test_received_ranges() {
+        let mut state = PieceState::new(3 * BLOCK_SIZE + 10);
+        state.write_block(100, &[1; 50]);
+        state.write_block(150, &[1; 50]);
+        assert_eq!(state.received, BTreeMap::from([(100, 200)]));
+        assert!(state.has_range(120, 200));
+        assert!(!state.has_range(99, 150));
+        assert_eq!(
+            state.missing_blocks(),
+            vec![(0, 100), (200, BLOCK_SIZE - 200), (BLOCK_SIZE, BLOCK_SIZE), (2 * BLOCK_SIZE, BLOCK_SIZE), (3 * BLOCK_SIZE, 10)]
+        );
+
+        state.forget(120, 130);
+        assert_eq!(state.received, BTreeMap::from([(100, 120), (130, 200)]));
+        state.write_block(0, &[1; 3 * BLOCK_SIZE + 10]);
+        assert!(state.is_complete());
+        assert!(state.missing_blocks().is_empty());
+    }
 }
@@ -0,0 +1,145 @@
+//! Where files sit in the piece space
+//!
+//! Files are laid end to end in the torrent's byte stream, so a file starts
+//! at the sum of the lengths before it and a piece may hold the end of one
+//! file and the start of the next. File priorities, prefix selections and
+//! per-file progress all go through these helpers.
+
+use crate::torrent::FileInfo;
+use std::ops::Range;
+
+/// Byte range of every file in the torrent's byte stream
+pub fn file_spans(files: &[FileInfo]) -> Vec<Range<u64>> {
+    let mut offset = 0u64;
+    files
+        .iter()
+        .map(|file| {
+            let span = offset..offset + file.length;
+            offset = span.end;
+            span
+        })
+        .collect()
+}
+
+/// Byte range of one file, None if there is no such file
+pub fn file_span(files: &[FileInfo], file_index: usize) -> Option<Range<u64>> {
+    let file = files.get(file_index)?;
+    let start: u64 = files[..file_index].iter().map(|file| file.length).sum();
+    Some(start..start + file.length)
+}
+
+/// Pieces holding any of `bytes`; empty for an empty range
+pub fn pieces_covering(bytes: &Range<u64>, piece_length: u64) -> Range<usize> {
+    if bytes.start >= bytes.end {
+        return 0..0;
+    }
+    let first = bytes.start / piece_length;
+    let end = (bytes.end + piece_length - 1) / piece_length;
+    first as usize..end as usize
+}
+
+/// Byte range of a piece; the last one may be short
+pub fn piece_span(piece_index: usize, piece_length: u64, total_size: u64) -> Range<u64> {
+    let start = piece_index as u64 * piece_length;
+    start..(start + piece_length).min(total_size)
+}
+
+/// Bytes two ranges share
+pub fn overlap(a: &Range<u64>, b: &Range<u64>) -> u64 {
+    a.end.min(b.end).saturating_sub(a.start.max(b.start))
+}
+
+/// The piece priorities that download only the start of a file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PrefixPlan {
+    /// Bytes of the file selected, `bytes` clamped to the file length
+    pub bytes: u64,
+    /// Pieces holding the selected bytes, set to High
+    pub prefix: Range<usize>,
+    /// The rest of the file's pieces, set to Skip. A piece shared with a
+    /// neighbouring file is left alone: that file may still want it.
+    pub rest: Range<usize>,
+}
+
+/// Plan downloading the first `bytes` of a file; None if there is no such
+/// file or it's empty
+pub fn prefix_plan(files: &[FileInfo], file_index: usize, bytes: u64, piece_length: u64) -> Option<PrefixPlan> {
+    let span = file_span(files, file_index)?;
+    if span.is_empty() || bytes == 0 {
+        return None;
+    }
+    let bytes = bytes.min(span.end - span.start);
+    let prefix = pieces_covering(&(span.start..span.start + bytes), piece_length);
+
+    let file_pieces = pieces_covering(&span, piece_length);
+    let mut rest_end = file_pieces.end;
+    // Only the file's last piece can also hold the next file's bytes
+    if rest_end > prefix.end && span.end < rest_end as u64 * piece_length {
+        let next_file_bytes = files[file_index + 1..].iter().any(|file| file.length > 0);
+        if next_file_bytes {
+            rest_end -= 1;
+        }
+    }
+    let rest = prefix.end..rest_end.max(prefix.end);
+    Some(PrefixPlan { bytes, prefix, rest })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn files(lengths: &[u64]) -> Vec<FileInfo> {
+        lengths
+            .iter()
+            .enumerate()
+            .map(|(i, &length)| FileInfo { path: vec![format!("file{}", i)], length })
+            .collect()
+    }
+
+    /// 400 bytes in 64-byte pieces: piece 1 holds the end of file 0 and the
+    /// start of file 2, piece 5 the end of file 2 and the start of file 3
+    fn fixture() -> Vec<FileInfo> {
+        files(&[100, 0, 250, 50])
+    }
+
+    #[test]
+    fn test_spans_and_pieces() {
+        let files = fixture();
+        assert_eq!(file_spans(&files), vec![0..100, 100..100, 100..350, 350..400]);
+        assert_eq!(file_span(&files, 2), Some(100..350));
+        assert_eq!(file_span(&files, 4), None);
+
+        assert_eq!(pieces_covering(&(100..350), 64), 1..6);
+        assert_eq!(pieces_covering(&(128..192), 64), 2..3);
+        assert_eq!(pieces_covering(&(100..100), 64), 0..0);
+        assert_eq!(piece_span(6, 64, 400), 384..400);
+        assert_eq!(overlap(&(100..350), &(320..384)), 30);
+        assert_eq!(overlap(&(0..100), &(128..192)), 0);
+    }
+
+    #[test]
+    fn test_prefix_plan() {
+        let files = fixture();
+
+        // 100..200 is in pieces 1-3; piece 4 is file 2's alone, piece 5
+        // is shared with file 3
+        let plan = prefix_plan(&files, 2, 100, 64).unwrap();
+        assert_eq!(plan, PrefixPlan { bytes: 100, prefix: 1..4, rest: 4..5 });
+
+        // More than the file: all of it, nothing skipped
+        let plan = prefix_plan(&files, 2, 1000, 64).unwrap();
+        assert_eq!(plan, PrefixPlan { bytes: 250, prefix: 1..6, rest: 6..6 });
+
+        // The last file's last piece is its own
+        let plan = prefix_plan(&files, 3, 10, 64).unwrap();
+        assert_eq!(plan, PrefixPlan { bytes: 10, prefix: 5..6, rest: 6..7 });
+
+        // A prefix ending mid-piece inside the first file
+        let plan = prefix_plan(&files, 0, 1, 64).unwrap();
+        assert_eq!(plan, PrefixPlan { bytes: 1, prefix: 0..1, rest: 1..1 });
+
+        assert_eq!(prefix_plan(&files, 1, 10, 64), None);
+        assert_eq!(prefix_plan(&files, 2, 0, 64), None);
+        assert_eq!(prefix_plan(&files, 9, 10, 64), None);
+    }
+}
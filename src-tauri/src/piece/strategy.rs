@@ -61,14 +61,25 @@ impl PieceSelector {
         }
     }
 
+    /// Priority of a piece (Normal unless set)
+    pub fn priority(&self, piece_idx: usize) -> PiecePriority {
+        self.priorities.get(&piece_idx).copied().unwrap_or_default()
+    }
+
+    /// Check if a piece has been marked as Skip
+    pub fn is_skipped(&self, piece_idx: usize) -> bool {
+        self.priorities.get(&piece_idx) == Some(&PiecePriority::Skip)
+    }
+
     /// Update piece availability based on a peer's bitfield
     pub fn update_peer_availability(&mut self, peer_bitfield: &Bitfield, increment: bool) {
         for piece_idx in peer_bitfield.available_pieces() {
             let count = self.piece_availability.entry(piece_idx).or_insert(0);
             if increment {
                 *count += 1;
-            } else if *count > 0 {
-                *count -= 1;
+            } else {
+                debug_assert!(*count > 0, "availability of piece {} would go negative", piece_idx);
+                *count = count.saturating_sub(1);
             }
         }
     }
@@ -194,6 +205,11 @@ impl PieceSelector {
             .unwrap_or(0)
     }
 
+    /// Sum of all pieces' availability counts
+    pub fn total_availability(&self) -> usize {
+        self.piece_availability.values().sum()
+    }
+
     /// Get average piece availability across all pieces
     pub fn average_availability(&self) -> f64 {
         if self.piece_availability.is_empty() {
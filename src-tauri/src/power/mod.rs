@@ -0,0 +1,290 @@
+//! Keeping the machine awake while torrents are active
+//!
+//! With `prevent_sleep` on, the OS is asked not to sleep while at least one
+//! torrent is downloading (or seeding too, with `prevent_sleep_while_seeding`).
+//! Activity is counted from the torrent list the UI sums its global stats
+//! from. The OS request is held as one reference for all active torrents:
+//! it is taken when the first one becomes active and given back only after
+//! none has been for `RELEASE_DELAY`, so torrents flapping between states
+//! don't turn into a stream of OS calls.
+//!
+//! The OS calls themselves live in `platform`. Failing to take the request
+//! doesn't stop anything; it is logged once and shown in the diagnostics.
+
+mod platform;
+
+use crate::state::{AppState, TorrentState};
+use serde::Serialize;
+use std::sync::Mutex;
+#[cfg(feature = "app")]
+use tauri::Manager;
+use tokio::time::{self, Duration, Instant};
+
+pub use platform::system_inhibitor;
+
+/// How long the request is kept after the last torrent stopped being active
+pub const RELEASE_DELAY: Duration = Duration::from_secs(60);
+
+/// How often torrent activity is looked at
+const CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// An OS facility that keeps the machine from sleeping while held
+pub trait SleepInhibitor: Send {
+    /// Ask the OS not to sleep until `release`
+    fn acquire(&mut self) -> Result<(), String>;
+    /// Give the request back; only called after a successful `acquire`
+    fn release(&mut self);
+}
+
+/// What the sleep inhibitor is doing, for the diagnostics
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SleepStatus {
+    /// Whether `prevent_sleep` is on
+    pub enabled: bool,
+    /// Whether the OS request is held now
+    pub engaged: bool,
+    /// Torrents keeping the machine awake
+    pub active_torrents: usize,
+    /// Why the request couldn't be taken; not tried again until the setting
+    /// is turned off and on
+    pub error: Option<String>,
+}
+
+static STATUS: Mutex<SleepStatus> =
+    Mutex::new(SleepStatus { enabled: false, engaged: false, active_torrents: 0, error: None });
+
+/// The sleep inhibitor's state as of its last check
+pub fn status() -> SleepStatus {
+    STATUS.lock().unwrap().clone()
+}
+
+/// Torrents that keep the machine awake
+pub fn active_torrents<'a>(states: impl IntoIterator<Item = &'a TorrentState>, while_seeding: bool) -> usize {
+    states
+        .into_iter()
+        .filter(|state| match state {
+            TorrentState::Downloading => true,
+            TorrentState::Seeding => while_seeding,
+            _ => false,
+        })
+        .count()
+}
+
+/// Takes and gives back the OS request as torrents become active and idle
+pub struct SleepGuard<I: SleepInhibitor> {
+    inhibitor: I,
+    enabled: bool,
+    engaged: bool,
+    active: usize,
+    /// When the count last dropped to zero while engaged
+    idle_since: Option<Instant>,
+    error: Option<String>,
+}
+
+impl<I: SleepInhibitor> SleepGuard<I> {
+    pub fn new(inhibitor: I) -> Self {
+        Self { inhibitor, enabled: false, engaged: false, active: 0, idle_since: None, error: None }
+    }
+
+    /// Follow the number of active torrents
+    pub fn update(&mut self, active: usize, now: Instant) {
+        self.enabled = true;
+        self.active = active;
+        if active > 0 {
+            self.idle_since = None;
+            if !self.engaged && self.error.is_none() {
+                match self.inhibitor.acquire() {
+                    Ok(()) => {
+                        tracing::info!("Preventing system sleep: {} torrent(s) active", active);
+                        self.engaged = true;
+                    }
+                    Err(e) => {
+                        tracing::warn!("Failed to prevent system sleep: {}", e);
+                        self.error = Some(e);
+                    }
+                }
+            }
+        } else if self.engaged {
+            let idle_since = *self.idle_since.get_or_insert(now);
+            if now.duration_since(idle_since) >= RELEASE_DELAY {
+                self.release();
+            }
+        }
+    }
+
+    /// The setting was turned off: give the request back at once, and try
+    /// again after an earlier failure once it is turned on
+    pub fn disable(&mut self) {
+        if self.engaged {
+            self.release();
+        }
+        self.enabled = false;
+        self.active = 0;
+        self.error = None;
+    }
+
+    fn release(&mut self) {
+        self.inhibitor.release();
+        self.engaged = false;
+        self.idle_since = None;
+        tracing::info!("System sleep allowed again");
+    }
+
+    pub fn status(&self) -> SleepStatus {
+        SleepStatus {
+            enabled: self.enabled,
+            engaged: self.engaged,
+            active_torrents: self.active,
+            error: self.error.clone(),
+        }
+    }
+}
+
+impl<I: SleepInhibitor> Drop for SleepGuard<I> {
+    fn drop(&mut self) {
+        if self.engaged {
+            self.inhibitor.release();
+        }
+    }
+}
+
+/// Keep the machine awake per the settings until the app exits
+#[cfg(feature = "app")]
+pub async fn start_power_task(app_handle: tauri::AppHandle) {
+    let mut interval = time::interval(CHECK_INTERVAL);
+    interval.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
+    let mut guard = SleepGuard::new(system_inhibitor());
+
+    loop {
+        interval.tick().await;
+        let state = app_handle.state::<AppState>();
+        let (enabled, while_seeding) = {
+            let settings = state.settings.read().await;
+            (settings.prevent_sleep, settings.prevent_sleep_while_seeding)
+        };
+        if enabled {
+            let torrents = state.torrents.read().await;
+            let active = active_torrents(torrents.values().map(|info| &info.state), while_seeding);
+            drop(torrents);
+            guard.update(active, Instant::now());
+        } else {
+            guard.disable();
+        }
+        *STATUS.lock().unwrap() = guard.status();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    /// Counts OS calls; fails every acquire while `fail` is set
+    #[derive(Default)]
+    struct MockInhibitor {
+        calls: Arc<Mutex<Vec<&'static str>>>,
+        fail: bool,
+    }
+
+    impl SleepInhibitor for MockInhibitor {
+        fn acquire(&mut self) -> Result<(), String> {
+            self.calls.lock().unwrap().push("acquire");
+            if self.fail {
+                return Err("Inhibitor unavailable".to_string());
+            }
+            Ok(())
+        }
+
+        fn release(&mut self) {
+            self.calls.lock().unwrap().push("release");
+        }
+    }
+
+    fn guard(fail: bool) -> (SleepGuard<MockInhibitor>, Arc<Mutex<Vec<&'static str>>>) {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        (SleepGuard::new(MockInhibitor { calls: calls.clone(), fail }), calls)
+    }
+
+    #[test]
+    fn test_active_torrents() {
+        let states = [
+            TorrentState::Downloading,
+            TorrentState::Seeding,
+            TorrentState::Paused,
+            TorrentState::Queued,
+            TorrentState::Downloading,
+        ];
+        assert_eq!(active_torrents(&states, false), 2);
+        assert_eq!(active_torrents(&states, true), 3);
+        assert_eq!(active_torrents(&[], true), 0);
+    }
+
+    #[test]
+    fn test_engaged_while_active_and_released_after_delay() {
+        let (mut guard, calls) = guard(false);
+        let start = Instant::now();
+
+        guard.update(0, start);
+        assert!(calls.lock().unwrap().is_empty());
+        guard.update(1, start);
+        guard.update(3, start + Duration::from_secs(5));
+        assert!(guard.status().engaged);
+        assert_eq!(guard.status().active_torrents, 3);
+
+        // Idle, but not for long enough
+        guard.update(0, start + Duration::from_secs(10));
+        guard.update(0, start + Duration::from_secs(10) + RELEASE_DELAY - Duration::from_secs(1));
+        assert!(guard.status().engaged);
+
+        guard.update(0, start + Duration::from_secs(10) + RELEASE_DELAY);
+        assert!(!guard.status().engaged);
+        assert_eq!(*calls.lock().unwrap(), vec!["acquire", "release"]);
+    }
+
+    #[test]
+    fn test_flapping_keeps_one_request() {
+        let (mut guard, calls) = guard(false);
+        let start = Instant::now();
+        for tick in 0..100u64 {
+            guard.update((tick % 2) as usize, start + Duration::from_secs(tick));
+        }
+        assert!(guard.status().engaged);
+        assert_eq!(*calls.lock().unwrap(), vec!["acquire"]);
+
+        drop(guard);
+        assert_eq!(*calls.lock().unwrap(), vec!["acquire", "release"]);
+    }
+
+    #[test]
+    fn test_failure_is_reported_once() {
+        let (mut guard, calls) = guard(true);
+        let start = Instant::now();
+        for tick in 0..10u64 {
+            guard.update(1, start + Duration::from_secs(tick));
+        }
+        let status = guard.status();
+        assert!(status.enabled && !status.engaged);
+        assert_eq!(status.error.as_deref(), Some("Inhibitor unavailable"));
+        assert_eq!(*calls.lock().unwrap(), vec!["acquire"]);
+
+        // Turning the setting off and on tries again
+        guard.disable();
+        assert_eq!(guard.status().error, None);
+        guard.update(1, start + Duration::from_secs(20));
+        assert_eq!(calls.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_disable_releases_at_once() {
+        let (mut guard, calls) = guard(false);
+        guard.update(2, Instant::now());
+        guard.disable();
+        let status = guard.status();
+        assert!(!status.enabled && !status.engaged);
+        assert_eq!(status.active_torrents, 0);
+        assert_eq!(*calls.lock().unwrap(), vec!["acquire", "release"]);
+        // Nothing left to give back on drop
+        drop(guard);
+        assert_eq!(calls.lock().unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,248 @@
+//! OS sleep inhibitors
+//!
+//! - Linux: a `systemd-inhibit` child holding a logind sleep lock. The child
+//!   runs `cat` on a pipe from us, so it also ends if we exit without
+//!   releasing.
+//! - Windows: `SetThreadExecutionState` on a thread kept for the purpose, as
+//!   the request belongs to the calling thread.
+//! - macOS: an IOKit `PreventUserIdleSystemSleep` power assertion.
+
+use super::SleepInhibitor;
+
+/// The inhibitor for the platform we run on
+pub fn system_inhibitor() -> SystemInhibitor {
+    SystemInhibitor::default()
+}
+
+#[cfg(target_os = "linux")]
+pub use linux::SystemInhibitor;
+#[cfg(target_os = "macos")]
+pub use macos::SystemInhibitor;
+#[cfg(windows)]
+pub use windows::SystemInhibitor;
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+pub use unsupported::SystemInhibitor;
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use super::SleepInhibitor;
+    use std::io::Read;
+    use std::process::{Child, Command, Stdio};
+    use std::time::Duration;
+
+    /// How long a just-started `systemd-inhibit` gets to fail (no logind,
+    /// lock refused) before the lock counts as held
+    const STARTUP_GRACE: Duration = Duration::from_millis(200);
+
+    #[derive(Default)]
+    pub struct SystemInhibitor {
+        child: Option<Child>,
+    }
+
+    impl SleepInhibitor for SystemInhibitor {
+        fn acquire(&mut self) -> Result<(), String> {
+            let mut child = Command::new("systemd-inhibit")
+                .args([
+                    "--what=sleep:idle",
+                    "--who=SeedCore",
+                    "--why=Torrents are active",
+                    "--mode=block",
+                    "cat",
+                ])
+                .stdin(Stdio::piped())
+                .stdout(Stdio::null())
+                .stderr(Stdio::piped())
+                .spawn()
+                .map_err(|e| format!("Failed to run systemd-inhibit: {}", e))?;
+
+            std::thread::sleep(STARTUP_GRACE);
+            match child.try_wait() {
+                Ok(None) => {
+                    self.child = Some(child);
+                    Ok(())
+                }
+                Ok(Some(status)) => {
+                    let mut stderr = String::new();
+                    if let Some(mut pipe) = child.stderr.take() {
+                        let _ = pipe.read_to_string(&mut stderr);
+                    }
+                    Err(format!("systemd-inhibit exited ({}): {}", status, stderr.trim()))
+                }
+                Err(e) => Err(format!("Failed to check on systemd-inhibit: {}", e)),
+            }
+        }
+
+        fn release(&mut self) {
+            if let Some(mut child) = self.child.take() {
+                // Closing its input ends it; kill in case it doesn't notice
+                drop(child.stdin.take());
+                let _ = child.kill();
+                let _ = child.wait();
+            }
+        }
+    }
+}
+
+#[cfg(windows)]
+mod windows {
+    use super::SleepInhibitor;
+    use std::sync::mpsc;
+
+    const ES_CONTINUOUS: u32 = 0x8000_0000;
+    const ES_SYSTEM_REQUIRED: u32 = 0x0000_0001;
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn SetThreadExecutionState(flags: u32) -> u32;
+    }
+
+    /// Dropping `stop` lets the holding thread clear its request and exit
+    #[derive(Default)]
+    pub struct SystemInhibitor {
+        stop: Option<mpsc::Sender<()>>,
+    }
+
+    impl SleepInhibitor for SystemInhibitor {
+        fn acquire(&mut self) -> Result<(), String> {
+            let (stop_tx, stop_rx) = mpsc::channel::<()>();
+            let (held_tx, held_rx) = mpsc::channel();
+            std::thread::Builder::new()
+                .name("sleep-inhibitor".to_string())
+                .spawn(move || {
+                    // SAFETY: plain Win32 call without pointers
+                    let previous = unsafe { SetThreadExecutionState(ES_CONTINUOUS | ES_SYSTEM_REQUIRED) };
+                    let _ = held_tx.send(previous != 0);
+                    if previous != 0 {
+                        let _ = stop_rx.recv();
+                        // SAFETY: as above
+                        unsafe { SetThreadExecutionState(ES_CONTINUOUS) };
+                    }
+                })
+                .map_err(|e| format!("Failed to start the sleep inhibitor thread: {}", e))?;
+
+            match held_rx.recv() {
+                Ok(true) => {
+                    self.stop = Some(stop_tx);
+                    Ok(())
+                }
+                _ => Err("SetThreadExecutionState failed".to_string()),
+            }
+        }
+
+        fn release(&mut self) {
+            self.stop = None;
+        }
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod macos {
+    use super::SleepInhibitor;
+    use std::ffi::{c_void, CStr};
+
+    type CFStringRef = *const c_void;
+
+    const K_CF_STRING_ENCODING_UTF8: u32 = 0x0800_0100;
+    const K_IOPM_ASSERTION_LEVEL_ON: u32 = 255;
+    const K_IO_RETURN_SUCCESS: i32 = 0;
+
+    #[link(name = "CoreFoundation", kind = "framework")]
+    extern "C" {
+        fn CFStringCreateWithCString(alloc: *const c_void, c_str: *const std::ffi::c_char, encoding: u32) -> CFStringRef;
+        fn CFRelease(cf: *const c_void);
+    }
+
+    #[link(name = "IOKit", kind = "framework")]
+    extern "C" {
+        fn IOPMAssertionCreateWithName(
+            assertion_type: CFStringRef,
+            level: u32,
+            name: CFStringRef,
+            assertion_id: *mut u32,
+        ) -> i32;
+        fn IOPMAssertionRelease(assertion_id: u32) -> i32;
+    }
+
+    fn cf_string(value: &'static [u8]) -> Result<CFStringRef, String> {
+        let value = CStr::from_bytes_with_nul(value).map_err(|e| e.to_string())?;
+        // SAFETY: `value` is NUL-terminated; a null result is handled below
+        let string = unsafe { CFStringCreateWithCString(std::ptr::null(), value.as_ptr(), K_CF_STRING_ENCODING_UTF8) };
+        if string.is_null() {
+            return Err(format!("Failed to create a CFString for {:?}", value));
+        }
+        Ok(string)
+    }
+
+    #[derive(Default)]
+    pub struct SystemInhibitor {
+        assertion: Option<u32>,
+    }
+
+    impl SleepInhibitor for SystemInhibitor {
+        fn acquire(&mut self) -> Result<(), String> {
+            let assertion_type = cf_string(b"PreventUserIdleSystemSleep\0")?;
+            let name = match cf_string(b"SeedCore: torrents are active\0") {
+                Ok(name) => name,
+                Err(e) => {
+                    // SAFETY: created above and not used again
+                    unsafe { CFRelease(assertion_type) };
+                    return Err(e);
+                }
+            };
+            let mut assertion = 0u32;
+            // SAFETY: both strings are valid CFStrings, `assertion` outlives the call
+            let result = unsafe {
+                IOPMAssertionCreateWithName(assertion_type, K_IOPM_ASSERTION_LEVEL_ON, name, &mut assertion)
+            };
+            // SAFETY: the assertion keeps its own references
+            unsafe {
+                CFRelease(name);
+                CFRelease(assertion_type);
+            }
+            if result != K_IO_RETURN_SUCCESS {
+                return Err(format!("IOPMAssertionCreateWithName failed: {:#x}", result));
+            }
+            self.assertion = Some(assertion);
+            Ok(())
+        }
+
+        fn release(&mut self) {
+            if let Some(assertion) = self.assertion.take() {
+                // SAFETY: an id returned by IOPMAssertionCreateWithName, released once
+                unsafe { IOPMAssertionRelease(assertion) };
+            }
+        }
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+mod unsupported {
+    use super::SleepInhibitor;
+
+    #[derive(Default)]
+    pub struct SystemInhibitor;
+
+    impl SleepInhibitor for SystemInhibitor {
+        fn acquire(&mut self) -> Result<(), String> {
+            Err("Preventing sleep isn't supported on this platform".to_string())
+        }
+
+        fn release(&mut self) {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Takes the real OS request for a while; check it with
+    /// `systemd-inhibit --list`, `pmset -g assertions` or `powercfg /requests`
+    #[test]
+    #[ignore = "changes the machine's power state"]
+    fn test_system_inhibitor() {
+        let mut inhibitor = system_inhibitor();
+        inhibitor.acquire().unwrap();
+        std::thread::sleep(std::time::Duration::from_secs(10));
+        inhibitor.release();
+    }
+}
@@ -0,0 +1,380 @@
+//! Thumbnails of (partially) downloaded video files
+//!
+//! ffmpeg isn't bundled; previews only work once the user points
+//! `ffmpeg_path` at their own binary. There's no local streaming endpoint,
+//! so ffmpeg reads the file on disk directly, and it's only run once the
+//! byte ranges it needs have been downloaded: the head (container header and
+//! first keyframes) and the tail, where MP4 often keeps its moov atom and
+//! MKV its cues. Anything else ends in a typed `Unavailable` result the UI
+//! can show as "download more to preview".
+
+use crate::database::TorrentSession;
+use crate::error::{Error, Result};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Bytes at the start of the file that must be present
+pub const HEAD_BYTES: u64 = 4 * 1024 * 1024;
+
+/// Bytes at the end of the file that must be present
+pub const TAIL_BYTES: u64 = 2 * 1024 * 1024;
+
+/// ffmpeg is killed if it hasn't produced a frame by then
+pub const EXTRACT_TIMEOUT: Duration = Duration::from_secs(30);
+
+const VIDEO_EXTENSIONS: &[&str] = &[
+    "mp4", "m4v", "mov", "mkv", "webm", "avi", "wmv", "flv", "mpg", "mpeg", "ts",
+];
+
+/// Result of `get_file_preview`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "camelCase")]
+pub enum FilePreview {
+    /// Thumbnail (JPEG) on disk
+    Ready { path: PathBuf },
+    /// No thumbnail, and why
+    Unavailable { reason: PreviewUnavailable },
+}
+
+/// Why a preview can't be shown (yet)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum PreviewUnavailable {
+    /// Magnet whose metadata hasn't arrived: the file isn't known yet
+    MetadataPending,
+    /// Not a video file (by extension)
+    NotVideo,
+    /// No ffmpeg binary configured in settings
+    FfmpegNotConfigured,
+    /// This many pieces of the needed ranges are still missing
+    NeedMoreData { pieces: usize },
+    /// ffmpeg ran but failed or timed out
+    ExtractionFailed { message: String },
+}
+
+impl From<PreviewUnavailable> for FilePreview {
+    fn from(reason: PreviewUnavailable) -> Self {
+        Self::Unavailable { reason }
+    }
+}
+
+/// Pulls a single frame out of a video file
+#[async_trait]
+pub trait ThumbnailExtractor: Send + Sync {
+    /// Write a JPEG thumbnail of `input` to `output`
+    async fn extract(&self, input: &Path, output: &Path) -> Result<()>;
+}
+
+/// `ThumbnailExtractor` backed by a user-configured ffmpeg binary
+///
+/// The child is spawned with `kill_on_drop`, so it dies both on timeout and
+/// when the calling future is dropped (e.g. the command is cancelled).
+pub struct Ffmpeg {
+    binary: PathBuf,
+    timeout: Duration,
+}
+
+impl Ffmpeg {
+    pub fn new(binary: PathBuf) -> Self {
+        Self {
+            binary,
+            timeout: EXTRACT_TIMEOUT,
+        }
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+}
+
+#[async_trait]
+impl ThumbnailExtractor for Ffmpeg {
+    async fn extract(&self, input: &Path, output: &Path) -> Result<()> {
+        let mut child = tokio::process::Command::new(&self.binary)
+            .args(["-v", "error", "-y", "-ss", "5", "-i"])
+            .arg(input)
+            .args(["-frames:v", "1", "-vf", "scale=320:-2"])
+            .arg(output)
+            .stdin(std::process::Stdio::null())
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| Error::IoError(format!("Failed to run {}: {}", self.binary.display(), e)))?;
+
+        match tokio::time::timeout(self.timeout, child.wait()).await {
+            Ok(Ok(status)) if status.success() => Ok(()),
+            Ok(Ok(status)) => Err(Error::Other(format!("ffmpeg exited with {}", status))),
+            Ok(Err(e)) => Err(Error::IoError(format!("Failed to wait for ffmpeg: {}", e))),
+            Err(_) => {
+                let _ = child.kill().await;
+                Err(Error::Timeout(format!("ffmpeg took longer than {:?}", self.timeout)))
+            }
+        }
+    }
+}
+
+/// A file of a torrent, located on disk and in the torrent's byte space
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PreviewFile {
+    pub info_hash: String,
+    pub index: usize,
+    pub path: PathBuf,
+    /// Byte offset from the start of the torrent
+    pub offset: u64,
+    pub size: u64,
+    pub piece_length: u64,
+}
+
+impl PreviewFile {
+    /// File `index` of a saved session, or None if out of range
+    pub fn from_session(session: &TorrentSession, index: usize) -> Option<Self> {
+        let info = &session.metainfo.info;
+        let root = Path::new(&session.download_dir).join(session.root_name());
+
+        let (path, offset, size) = if info.is_single_file {
+            if index != 0 {
+                return None;
+            }
+            (root, 0, info.total_size)
+        } else {
+            let file = info.files.get(index)?;
+            let offset = info.files[..index].iter().map(|f| f.length).sum();
+            let path = file.path.iter().fold(root, |acc, component| acc.join(component));
+            (path, offset, file.length)
+        };
+
+        Some(Self {
+            info_hash: session.id.clone(),
+            index,
+            path,
+            offset,
+            size,
+            piece_length: info.piece_length,
+        })
+    }
+}
+
+/// Whether the file name looks like a video
+pub fn is_video(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| VIDEO_EXTENSIONS.contains(&ext.to_ascii_lowercase().as_str()))
+}
+
+/// Byte ranges of a file (relative to its start) that ffmpeg needs
+pub fn required_ranges(file_size: u64) -> Vec<Range<u64>> {
+    if file_size <= HEAD_BYTES + TAIL_BYTES {
+        vec![0..file_size]
+    } else {
+        vec![0..HEAD_BYTES, file_size - TAIL_BYTES..file_size]
+    }
+}
+
+/// Pieces covering the required ranges of `file` that we don't have yet
+pub fn missing_pieces(file: &PreviewFile, has_piece: impl Fn(usize) -> bool) -> Vec<usize> {
+    let mut missing = Vec::new();
+    for range in required_ranges(file.size).into_iter().filter(|r| !r.is_empty()) {
+        let first = ((file.offset + range.start) / file.piece_length) as usize;
+        let last = ((file.offset + range.end - 1) / file.piece_length) as usize;
+        for piece in first..=last {
+            if !has_piece(piece) && !missing.contains(&piece) {
+                missing.push(piece);
+            }
+        }
+    }
+    missing
+}
+
+/// Cache file name; the size is part of it so a different file under the same
+/// torrent/index never gets a stale thumbnail
+pub fn cache_key(info_hash: &str, file_index: usize, file_size: u64) -> String {
+    format!("{}-{}-{}.jpg", info_hash.to_lowercase(), file_index, file_size)
+}
+
+/// Where thumbnails are cached
+pub fn cache_dir() -> PathBuf {
+    crate::state::config_dir().join("previews")
+}
+
+/// Thumbnail for `file`, from the cache or freshly extracted
+pub async fn file_preview(
+    extractor: Option<&dyn ThumbnailExtractor>,
+    cache_dir: &Path,
+    file: &PreviewFile,
+    has_piece: impl Fn(usize) -> bool,
+) -> FilePreview {
+    if !is_video(&file.path) {
+        return PreviewUnavailable::NotVideo.into();
+    }
+
+    let cached = cache_dir.join(cache_key(&file.info_hash, file.index, file.size));
+    if cached.is_file() {
+        return FilePreview::Ready { path: cached };
+    }
+
+    let Some(extractor) = extractor else {
+        return PreviewUnavailable::FfmpegNotConfigured.into();
+    };
+
+    let missing = missing_pieces(file, has_piece);
+    if !missing.is_empty() {
+        return PreviewUnavailable::NeedMoreData { pieces: missing.len() }.into();
+    }
+
+    // Extract next to the cache entry and rename, so a killed run never
+    // leaves a half-written thumbnail behind as a cache hit
+    let partial = cached.with_extension("part.jpg");
+    let result = async {
+        tokio::fs::create_dir_all(cache_dir).await?;
+        extractor.extract(&file.path, &partial).await?;
+        tokio::fs::rename(&partial, &cached).await?;
+        Ok::<_, Error>(())
+    }
+    .await;
+
+    match result {
+        Ok(()) => FilePreview::Ready { path: cached },
+        Err(e) => {
+            let _ = tokio::fs::remove_file(&partial).await;
+            tracing::warn!("Preview of {} failed: {}", file.path.display(), e);
+            PreviewUnavailable::ExtractionFailed { message: e.to_string() }.into()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    const MIB: u64 = 1024 * 1024;
+
+    /// Writes a fake JPEG and counts calls
+    struct MockExtractor {
+        calls: AtomicUsize,
+        fail: bool,
+    }
+
+    impl MockExtractor {
+        fn new(fail: bool) -> Self {
+            Self { calls: AtomicUsize::new(0), fail }
+        }
+    }
+
+    #[async_trait]
+    impl ThumbnailExtractor for MockExtractor {
+        async fn extract(&self, _input: &Path, output: &Path) -> Result<()> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            if self.fail {
+                return Err(Error::Other("no video stream".to_string()));
+            }
+            tokio::fs::write(output, b"\xFF\xD8jpeg").await?;
+            Ok(())
+        }
+    }
+
+    fn movie(offset: u64, size: u64) -> PreviewFile {
+        PreviewFile {
+            info_hash: "ABCDEF0123456789ABCDEF0123456789ABCDEF01".to_string(),
+            index: 1,
+            path: PathBuf::from("/downloads/Show/episode.mkv"),
+            offset,
+            size,
+            piece_length: MIB,
+        }
+    }
+
+    #[test]
+    fn test_missing_pieces_covers_head_and_tail() {
+        // 100 MiB file starting half-way into piece 10
+        let file = movie(10 * MIB + MIB / 2, 100 * MIB);
+
+        let all: Vec<usize> = missing_pieces(&file, |_| false);
+        // Head: pieces 10..=14, tail: last 2 MiB end at 110.5 MiB -> 108..=110
+        assert_eq!(all, vec![10, 11, 12, 13, 14, 108, 109, 110]);
+
+        // The middle of the file doesn't matter
+        assert!(missing_pieces(&file, |p| (10..=14).contains(&p) || p >= 108).is_empty());
+        assert_eq!(missing_pieces(&file, |p| p != 109), vec![109]);
+
+        // Small files need everything
+        assert_eq!(missing_pieces(&movie(0, 3 * MIB), |_| false), vec![0, 1, 2]);
+        assert!(missing_pieces(&movie(0, 0), |_| false).is_empty());
+    }
+
+    #[test]
+    fn test_cache_key() {
+        let key = cache_key("ABCDEF0123456789ABCDEF0123456789ABCDEF01", 3, 1234);
+        assert_eq!(key, "abcdef0123456789abcdef0123456789abcdef01-3-1234.jpg");
+        assert_ne!(key, cache_key("abcdef0123456789abcdef0123456789abcdef01", 3, 1235));
+        assert_ne!(key, cache_key("abcdef0123456789abcdef0123456789abcdef01", 4, 1234));
+    }
+
+    #[tokio::test]
+    async fn test_file_preview_availability() {
+        let cache = tempfile::TempDir::new().unwrap();
+        let extractor = MockExtractor::new(false);
+        let file = movie(0, 100 * MIB);
+
+        let mut subtitles = file.clone();
+        subtitles.path.set_extension("srt");
+        assert_eq!(
+            file_preview(Some(&extractor), cache.path(), &subtitles, |_| true).await,
+            PreviewUnavailable::NotVideo.into()
+        );
+        assert_eq!(
+            file_preview(None, cache.path(), &file, |_| true).await,
+            PreviewUnavailable::FfmpegNotConfigured.into()
+        );
+        assert_eq!(
+            file_preview(Some(&extractor), cache.path(), &file, |p| p < 4).await,
+            PreviewUnavailable::NeedMoreData { pieces: 2 }.into()
+        );
+        assert_eq!(extractor.calls.load(Ordering::SeqCst), 0);
+
+        let expected = cache.path().join(cache_key(&file.info_hash, 1, 100 * MIB));
+        let ready = file_preview(Some(&extractor), cache.path(), &file, |_| true).await;
+        assert_eq!(ready, FilePreview::Ready { path: expected.clone() });
+        assert!(expected.is_file());
+
+        // Served from the cache afterwards, even without ffmpeg
+        assert_eq!(file_preview(None, cache.path(), &file, |_| false).await, ready);
+        assert_eq!(extractor.calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_failed_extraction_is_not_cached() {
+        let cache = tempfile::TempDir::new().unwrap();
+        let file = movie(0, 10 * MIB);
+
+        let preview = file_preview(Some(&MockExtractor::new(true)), cache.path(), &file, |_| true).await;
+        assert!(matches!(
+            preview,
+            FilePreview::Unavailable { reason: PreviewUnavailable::ExtractionFailed { .. } }
+        ));
+        assert_eq!(std::fs::read_dir(cache.path()).unwrap().count(), 0);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_ffmpeg_is_killed_on_timeout() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let binary = dir.path().join("ffmpeg");
+        std::fs::write(&binary, "#!/bin/sh\nsleep 30\n").unwrap();
+        std::fs::set_permissions(&binary, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let ffmpeg = Ffmpeg::new(binary).with_timeout(Duration::from_millis(200));
+        let started = std::time::Instant::now();
+        let result = ffmpeg.extract(Path::new("in.mkv"), &dir.path().join("out.jpg")).await;
+
+        assert!(matches!(result, Err(Error::Timeout(_))));
+        assert!(started.elapsed() < Duration::from_secs(10));
+    }
+}
@@ -0,0 +1,103 @@
+//! Where a torrent was added from
+//!
+//! Recorded once at add time and kept with the session (and in backups). For
+//! .torrent files and URLs the raw file is stashed as well (see
+//! `Database::save_torrent_file`), so the exact source can be exported again.
+
+use serde::{Deserialize, Serialize};
+
+/// Largest .torrent file kept alongside its session
+pub const MAX_STASHED_TORRENT_SIZE: usize = 10 * 1024 * 1024;
+
+/// How a torrent came to be added
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum AddedFrom {
+    /// A .torrent file; no path when its contents were handed over directly
+    /// (drag and drop)
+    TorrentFile { original_path: Option<String> },
+    /// A magnet link, possibly scrubbed (see `AddedFrom::magnet`)
+    Magnet { uri: String },
+    /// A .torrent downloaded from a URL
+    Url { url: String },
+    /// An item of an RSS feed
+    Rss { feed_id: String, item_title: String },
+    /// Imported from another client's session
+    Imported { client: String },
+}
+
+impl AddedFrom {
+    /// Provenance for a magnet link; in anonymous mode the display name and
+    /// trackers are dropped from the recorded URI
+    pub fn magnet(uri: &str, anonymous: bool) -> Self {
+        let uri = if anonymous { scrub_magnet(uri) } else { uri.to_string() };
+        AddedFrom::Magnet { uri }
+    }
+}
+
+/// `uri` without its `dn` and `tr` (including `tr.1`, ...) parameters
+pub fn scrub_magnet(uri: &str) -> String {
+    let Some((scheme, query)) = uri.split_once('?') else {
+        return uri.to_string();
+    };
+    let kept: Vec<&str> = query
+        .split('&')
+        .filter(|param| {
+            let key = param.split('=').next().unwrap_or_default();
+            let base = key.split('.').next().unwrap_or_default();
+            !param.is_empty() && !base.eq_ignore_ascii_case("dn") && !base.eq_ignore_ascii_case("tr")
+        })
+        .collect();
+    format!("{}?{}", scheme, kept.join("&"))
+}
+
+/// Raw .torrent contents worth stashing: None if over the size limit
+pub fn stashable(data: Vec<u8>) -> Option<Vec<u8>> {
+    if data.len() > MAX_STASHED_TORRENT_SIZE {
+        tracing::debug!("Not keeping a {} byte .torrent file", data.len());
+        return None;
+    }
+    Some(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HASH: &str = "0123456789abcdef0123456789abcdef01234567";
+
+    #[test]
+    fn test_scrub_magnet() {
+        let uri = format!(
+            "magnet:?xt=urn:btih:{}&dn=Some+Name&tr=udp%3A%2F%2Ft1&tr.1=http%3A%2F%2Ft2&xl=1234",
+            HASH
+        );
+        assert_eq!(scrub_magnet(&uri), format!("magnet:?xt=urn:btih:{}&xl=1234", HASH));
+        assert_eq!(
+            AddedFrom::magnet(&uri, false),
+            AddedFrom::Magnet { uri: uri.clone() }
+        );
+        assert_eq!(
+            AddedFrom::magnet(&uri, true),
+            AddedFrom::Magnet { uri: format!("magnet:?xt=urn:btih:{}&xl=1234", HASH) }
+        );
+        assert_eq!(scrub_magnet("not a magnet"), "not a magnet");
+    }
+
+    #[test]
+    fn test_serialization() {
+        let from = AddedFrom::TorrentFile { original_path: Some("/tmp/a.torrent".to_string()) };
+        let json = serde_json::to_string(&from).unwrap();
+        assert_eq!(json, r#"{"kind":"torrent_file","original_path":"/tmp/a.torrent"}"#);
+        assert_eq!(serde_json::from_str::<AddedFrom>(&json).unwrap(), from);
+
+        let rss = AddedFrom::Rss { feed_id: "feed-1".to_string(), item_title: "Episode 2".to_string() };
+        assert_eq!(serde_json::from_str::<AddedFrom>(&serde_json::to_string(&rss).unwrap()).unwrap(), rss);
+    }
+
+    #[test]
+    fn test_stash_limit() {
+        assert!(stashable(vec![0; 100]).is_some());
+        assert!(stashable(vec![0; MAX_STASHED_TORRENT_SIZE + 1]).is_none());
+    }
+}
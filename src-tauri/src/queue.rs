@@ -0,0 +1,402 @@
+//! Download queue
+//!
+//! P2P torrents that are still downloading are kept in an explicit order
+//! (`TorrentSession::queue_position`). At most `max_active_downloads` of the
+//! ones the user wants running are started, strictly in that order; the rest
+//! wait as `Queued`. Seeding and cloud torrents are not queued.
+//!
+//! Torrents that were running when the app quit are handed over once their
+//! engines are built (`QueueHandle::defer_starts`); the coordinator resumes
+//! them `AUTO_STARTS_PER_PASS` at a time rather than all at once.
+
+use crate::database::TorrentSession;
+use crate::debrid::types::DownloadSource;
+use crate::state::{AppState, TorrentState};
+use serde::Deserialize;
+use std::collections::{HashSet, VecDeque};
+use std::sync::{Arc, Mutex};
+#[cfg(feature = "app")]
+use tauri::Manager;
+use tokio::sync::Notify;
+use tokio::time::{self, Duration};
+
+/// Quiet time after the last queue change before engines are started/paused,
+/// so dragging a torrent through several positions doesn't thrash engines
+pub const RECONCILE_DEBOUNCE: Duration = Duration::from_millis(750);
+
+/// How often the queue is re-checked anyway (finished downloads free slots)
+const RECONCILE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Unqueued torrents resumed per coordinator pass at startup; each pass
+/// allocates files and announces, so a few hundred seeds trickle in
+pub const AUTO_STARTS_PER_PASS: usize = 4;
+
+/// Relative queue moves
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QueueMove {
+    Up,
+    Down,
+    Top,
+    Bottom,
+}
+
+/// Whether a session takes part in the queue
+pub fn is_queued_kind(session: &TorrentSession) -> bool {
+    matches!(session.source, DownloadSource::P2P) && session.completed_at.is_none()
+}
+
+/// Queue order of the given sessions: by saved position, then (for torrents
+/// without one, i.e. added since the last reorder) by time added
+pub fn queue_order<'a>(sessions: impl IntoIterator<Item = &'a TorrentSession>) -> Vec<String> {
+    let mut members: Vec<&TorrentSession> = sessions.into_iter().filter(|s| is_queued_kind(s)).collect();
+    members.sort_by(|a, b| {
+        (a.queue_position.is_none(), a.queue_position, a.added_at, &a.id)
+            .cmp(&(b.queue_position.is_none(), b.queue_position, b.added_at, &b.id))
+    });
+    members.into_iter().map(|s| s.id.clone()).collect()
+}
+
+/// Move `id` to `position` (clamped to the end). Returns false if it isn't queued.
+pub fn move_to(order: &mut Vec<String>, id: &str, position: usize) -> bool {
+    let Some(current) = order.iter().position(|o| o == id) else {
+        return false;
+    };
+    let entry = order.remove(current);
+    order.insert(position.min(order.len()), entry);
+    true
+}
+
+/// Apply a relative move. Returns false if `id` isn't queued.
+pub fn shift(order: &mut Vec<String>, id: &str, movement: QueueMove) -> bool {
+    let Some(current) = order.iter().position(|o| o == id) else {
+        return false;
+    };
+    let target = match movement {
+        QueueMove::Up => current.saturating_sub(1),
+        QueueMove::Down => current + 1,
+        QueueMove::Top => 0,
+        QueueMove::Bottom => order.len(),
+    };
+    move_to(order, id, target)
+}
+
+/// What the coordinator should do to match the queue
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct QueuePlan {
+    /// Torrents to start (in queue order)
+    pub start: Vec<String>,
+    /// Running torrents that fell below the cutoff
+    pub queue: Vec<String>,
+}
+
+/// Fill `slots` with the first torrents in `order` that want to run
+pub fn plan(order: &[String], wants_to_run: &HashSet<String>, running: &HashSet<String>, slots: usize) -> QueuePlan {
+    let mut plan = QueuePlan::default();
+    for (rank, id) in order.iter().filter(|id| wants_to_run.contains(*id)).enumerate() {
+        match (rank < slots, running.contains(id)) {
+            (true, false) => plan.start.push(id.clone()),
+            (false, true) => plan.queue.push(id.clone()),
+            _ => {}
+        }
+    }
+    plan
+}
+
+/// Wakes the queue coordinator; requests made in quick succession coalesce
+#[derive(Clone, Default)]
+pub struct QueueHandle {
+    notify: Arc<Notify>,
+    /// Unqueued torrents waiting to be resumed, oldest first
+    auto_starts: Arc<Mutex<VecDeque<String>>>,
+}
+
+impl QueueHandle {
+    /// Ask for the queue to be re-applied (debounced)
+    pub fn request_reconcile(&self) {
+        self.notify.notify_one();
+    }
+
+    /// Resume these torrents over the next passes, `AUTO_STARTS_PER_PASS` each
+    pub fn defer_starts(&self, ids: impl IntoIterator<Item = String>) {
+        self.auto_starts.lock().unwrap().extend(ids);
+        self.request_reconcile();
+    }
+
+    /// The next torrents to resume; asks for another pass if more are waiting
+    pub fn next_auto_starts(&self) -> Vec<String> {
+        let mut waiting = self.auto_starts.lock().unwrap();
+        let count = waiting.len().min(AUTO_STARTS_PER_PASS);
+        let batch: Vec<String> = waiting.drain(..count).collect();
+        if !waiting.is_empty() {
+            self.request_reconcile();
+        }
+        batch
+    }
+
+    /// Wait for a request, then until none has arrived for `debounce`
+    pub async fn settled(&self, debounce: Duration) {
+        self.notify.notified().await;
+        while time::timeout(debounce, self.notify.notified()).await.is_ok() {}
+    }
+}
+
+/// Persist a new queue order; positions are renumbered 0..n
+pub fn save_order(state: &AppState, sessions: Vec<TorrentSession>, order: &[String]) -> Result<(), String> {
+    let changed: Vec<TorrentSession> = sessions
+        .into_iter()
+        .filter_map(|mut session| {
+            let position = order.iter().position(|id| *id == session.id).map(|p| p as u32);
+            if session.queue_position == position {
+                return None;
+            }
+            session.queue_position = position;
+            Some(session)
+        })
+        .collect();
+
+    if !changed.is_empty() {
+        state
+            .database
+            .save_torrents(&changed)
+            .map_err(|e| format!("Failed to save queue order: {}", e))?;
+    }
+    Ok(())
+}
+
+/// Copy queue positions into the UI entries (None for torrents not queued)
+pub async fn publish_positions(state: &AppState, order: &[String]) {
+    let mut torrents = state.torrents.write().await;
+    for (id, info) in torrents.iter_mut() {
+        info.queue_position = order.iter().position(|o| o == id).map(|p| p as u32);
+    }
+}
+
+/// Start/queue engines so the first `max_active_downloads` wanted torrents run
+#[cfg(feature = "app")]
+async fn reconcile(state: &AppState) {
+    let sessions = match state.database.load_all_torrents() {
+        Ok(sessions) => sessions,
+        Err(e) => {
+            tracing::error!("Queue: failed to load torrents: {}", e);
+            return;
+        }
+    };
+    let order = queue_order(&sessions);
+    let slots = state.settings.read().await.max_active_downloads as usize;
+
+    let (wants_to_run, running) = {
+        let mut torrents = state.torrents.write().await;
+        let engines = state.engines.read().await;
+        let tasks = state.engine_tasks.read().await;
+        let mut wants_to_run = HashSet::new();
+        let mut running = HashSet::new();
+        for session in &sessions {
+            // Engines of saved torrents may still be being built at startup
+            if !engines.contains_key(&session.id) {
+                continue;
+            }
+            let Some(info) = torrents.get_mut(&session.id) else { continue };
+            // Finished since the last look: the engine only reports it in its own saves
+            if session.completed_at.is_some() && info.state == TorrentState::Downloading {
+                info.state = TorrentState::Seeding;
+            }
+            if matches!(info.state, TorrentState::Downloading | TorrentState::Queued) {
+                wants_to_run.insert(session.id.clone());
+            }
+            if info.state == TorrentState::Downloading && tasks.contains_key(&session.id) {
+                running.insert(session.id.clone());
+            }
+        }
+        (wants_to_run, running)
+    };
+
+    let plan = plan(&order, &wants_to_run, &running, slots);
+    for id in &plan.queue {
+        tracing::info!("Queue: {} is below the cutoff, queueing", id);
+        if let Err(e) = crate::commands::pause_torrent_internal(state, id, TorrentState::Queued).await {
+            tracing::warn!("Queue: failed to queue {}: {}", id, e);
+        }
+    }
+    for id in &plan.start {
+        tracing::info!("Queue: starting {}", id);
+        if let Err(e) = crate::commands::start_torrent_internal(state, id.clone(), false).await {
+            tracing::warn!("Queue: failed to start {}: {}", id, e);
+        }
+    }
+    for id in state.queue.next_auto_starts() {
+        // Paused (or removed) by the user before its turn came
+        let still_wanted = state.torrents.read().await
+            .get(&id)
+            .is_some_and(|t| matches!(t.state, TorrentState::Downloading | TorrentState::Seeding | TorrentState::PausedSeeding));
+        if !still_wanted {
+            continue;
+        }
+        tracing::info!("Queue: resuming {}", id);
+        if let Err(e) = crate::commands::start_torrent_internal(state, id.clone(), false).await {
+            tracing::warn!("Queue: failed to resume {}: {}", id, e);
+        }
+    }
+    publish_positions(state, &order).await;
+}
+
+/// Run the queue coordinator until the app exits
+#[cfg(feature = "app")]
+pub async fn start_queue_task(app_handle: tauri::AppHandle) {
+    let handle = app_handle.state::<AppState>().queue.clone();
+    let mut interval = time::interval(RECONCILE_INTERVAL);
+
+    loop {
+        tokio::select! {
+            _ = handle.settled(RECONCILE_DEBOUNCE) => {}
+            _ = interval.tick() => {}
+        }
+        reconcile(&app_handle.state::<AppState>()).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ids(names: &[&str]) -> Vec<String> {
+        names.iter().map(|n| n.to_string()).collect()
+    }
+
+    fn session(id: &str, position: Option<u32>, added_at: i64) -> TorrentSession {
+        let metainfo = crate::torrent::Metainfo::from_magnet([0u8; 20], None, Vec::new());
+        TorrentSession {
+            id: id.to_string(),
+            metainfo,
+            bitfield: Vec::new(),
+            num_pieces: 0,
+            downloaded: 0,
+            uploaded: 0,
+            state: "paused".to_string(),
+            download_dir: "/tmp".to_string(),
+            added_at,
+            last_activity: added_at,
+            source: DownloadSource::P2P,
+            completed_at: None,
+            volume_id: None,
+            category: None,
+            traffic: Default::default(),
+            swarm: None,
+            root_name: None,
+            data_sync: Default::default(),
+            queue_position: position,
+            saved_peers: Vec::new(),
+            added_from: None,
+            storage_mode: Default::default(),
+            auto_stop: Default::default(),
+            low_disk_paused_at: None,
+            linked_files: Vec::new(),
+            disabled_trackers: Vec::new(),
+            file_completed_at: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_queue_order_and_moves() {
+        let mut done = session("done", Some(0), 1);
+        done.completed_at = Some(5);
+        let sessions = vec![
+            session("new", None, 50),
+            session("c", Some(7), 3),
+            done,
+            session("a", Some(2), 9),
+            session("newer", None, 60),
+            session("b", Some(4), 1),
+        ];
+
+        // Gaps left by removals close up; unpositioned torrents go to the bottom by age
+        let mut order = queue_order(&sessions);
+        assert_eq!(order, ids(&["a", "b", "c", "new", "newer"]));
+
+        assert!(shift(&mut order, "c", QueueMove::Up));
+        assert_eq!(order, ids(&["a", "c", "b", "new", "newer"]));
+        assert!(shift(&mut order, "a", QueueMove::Up));
+        assert_eq!(order, ids(&["a", "c", "b", "new", "newer"]));
+        assert!(shift(&mut order, "newer", QueueMove::Down));
+        assert_eq!(order, ids(&["a", "c", "b", "new", "newer"]));
+        assert!(shift(&mut order, "new", QueueMove::Top));
+        assert_eq!(order, ids(&["new", "a", "c", "b", "newer"]));
+        assert!(shift(&mut order, "a", QueueMove::Bottom));
+        assert_eq!(order, ids(&["new", "c", "b", "newer", "a"]));
+        assert!(move_to(&mut order, "a", 1));
+        assert_eq!(order, ids(&["new", "a", "c", "b", "newer"]));
+        assert!(move_to(&mut order, "new", 99));
+        assert_eq!(order, ids(&["a", "c", "b", "newer", "new"]));
+
+        assert!(!shift(&mut order, "done", QueueMove::Top));
+    }
+
+    #[test]
+    fn test_plan_fills_slots_in_order() {
+        let order = ids(&["a", "b", "c", "d"]);
+        let wants: HashSet<String> = ids(&["a", "c", "d"]).into_iter().collect();
+
+        // b is paused by the user and doesn't take a slot
+        let running: HashSet<String> = ids(&["d"]).into_iter().collect();
+        assert_eq!(
+            plan(&order, &wants, &running, 2),
+            QueuePlan { start: ids(&["a", "c"]), queue: ids(&["d"]) }
+        );
+
+        let running: HashSet<String> = ids(&["a", "c"]).into_iter().collect();
+        assert_eq!(plan(&order, &wants, &running, 2), QueuePlan::default());
+    }
+
+    #[test]
+    fn test_auto_starts_are_paced() {
+        let handle = QueueHandle::default();
+        let waiting: Vec<String> = (0..10).map(|i| format!("t{}", i)).collect();
+        handle.defer_starts(waiting.clone());
+
+        let mut passes = Vec::new();
+        loop {
+            let batch = handle.next_auto_starts();
+            if batch.is_empty() {
+                break;
+            }
+            passes.push(batch);
+        }
+        assert_eq!(passes.iter().map(Vec::len).collect::<Vec<_>>(), vec![4, 4, 2]);
+        assert_eq!(passes.concat(), waiting);
+    }
+
+    #[tokio::test]
+    async fn test_reconcile_requests_are_debounced() {
+        let debounce = Duration::from_millis(100);
+        let handle = QueueHandle::default();
+        let runs = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+
+        let coordinator = {
+            let handle = handle.clone();
+            let runs = runs.clone();
+            tokio::spawn(async move {
+                loop {
+                    handle.settled(debounce).await;
+                    runs.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                }
+            })
+        };
+
+        // Dragging through several positions
+        for _ in 0..5 {
+            handle.request_reconcile();
+            time::sleep(Duration::from_millis(20)).await;
+        }
+        assert_eq!(runs.load(std::sync::atomic::Ordering::SeqCst), 0);
+
+        time::sleep(debounce * 3).await;
+        assert_eq!(runs.load(std::sync::atomic::Ordering::SeqCst), 1);
+
+        // A later change is applied on its own
+        handle.request_reconcile();
+        time::sleep(debounce * 3).await;
+        assert_eq!(runs.load(std::sync::atomic::Ordering::SeqCst), 2);
+
+        coordinator.abort();
+    }
+}
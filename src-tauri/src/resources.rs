@@ -0,0 +1,257 @@
+//! File descriptor budget
+//!
+//! Peer sockets, open download files, sled and the HTTP clients all draw
+//! from the process's descriptor limit, which is low by default on macOS
+//! (256) and often 1024 on Linux. Running out fails whatever happens to be
+//! opening something next with EMFILE. We size the global connection cap
+//! from the limit at startup, and when EMFILE does turn up anyway, warn the
+//! user once and hold the dial pacer back until usage comes down.
+
+use crate::peer::DialPacer;
+#[cfg(feature = "app")]
+use crate::events::{emit_event, Event, FdExhaustedEvent};
+use serde::Serialize;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Notify;
+
+/// Descriptors kept out of the budget for sled, logs, HTTP clients and the UI
+pub const RESERVED_FDS: u64 = 128;
+
+/// Connection cap when the limit is unknown (Windows) or very high
+pub const MAX_CONNECTIONS: usize = 500;
+
+/// File handle cache size when the limit is unknown or very high
+pub const MAX_FILE_HANDLES: usize = 256;
+
+const MIN_CONNECTIONS: usize = 16;
+const MIN_FILE_HANDLES: usize = 4;
+
+/// How long the pacer stays throttled after running out of descriptors
+pub const THROTTLE_DURATION: Duration = Duration::from_secs(5 * 60);
+
+/// Usage (share of the limit) below which a throttle is allowed to lapse
+const RECOVERED_USAGE: f64 = 0.8;
+
+/// What the descriptor limit leaves for connections and open files
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct ResourceBudget {
+    /// Soft RLIMIT_NOFILE, None if unknown or unlimited
+    pub fd_limit: Option<u64>,
+    /// Default global cap on peer connections (half-open included)
+    pub connections: usize,
+    /// Default size of the open file handle cache
+    pub file_handles: usize,
+}
+
+impl ResourceBudget {
+    /// Connection cap for the `max_connections` setting (0 = automatic)
+    pub fn connection_cap(&self, setting: u32) -> usize {
+        if setting == 0 {
+            self.connections
+        } else {
+            setting as usize
+        }
+    }
+
+    /// Budget for the current process
+    pub fn detect() -> Self {
+        Self::from_limit(platform::fd_limit())
+    }
+
+    /// Split `fd_limit` after the reserve, keeping a quarter of the rest as
+    /// headroom: a fifth for file handles, the remainder for connections
+    pub fn from_limit(fd_limit: Option<u64>) -> Self {
+        let Some(limit) = fd_limit else {
+            return Self { fd_limit, connections: MAX_CONNECTIONS, file_handles: MAX_FILE_HANDLES };
+        };
+        let reserved = RESERVED_FDS.min(limit / 2);
+        let usable = (limit - reserved) * 3 / 4;
+        let usable = usize::try_from(usable).unwrap_or(usize::MAX);
+        let file_handles = (usable / 5).clamp(MIN_FILE_HANDLES, MAX_FILE_HANDLES);
+        let connections = usable.saturating_sub(file_handles).clamp(MIN_CONNECTIONS, MAX_CONNECTIONS);
+        Self { fd_limit, connections, file_handles }
+    }
+}
+
+/// Descriptors this process has open now, if the platform can tell
+pub fn open_fds() -> Option<usize> {
+    platform::open_fds()
+}
+
+/// Whether an I/O error means the process (EMFILE) or the whole system
+/// (ENFILE) has run out of file descriptors
+pub fn is_fd_exhaustion(err: &std::io::Error) -> bool {
+    err.raw_os_error().is_some_and(platform::is_fd_exhaustion_code) || is_fd_exhaustion_text(&err.to_string())
+}
+
+/// Same for an error that has already been turned into text
+pub fn is_fd_exhaustion_text(text: &str) -> bool {
+    text.to_ascii_lowercase().contains("too many open files")
+}
+
+struct FdPressure {
+    /// Set once the user has been warned
+    warned: AtomicBool,
+    /// Descriptor exhaustion errors seen so far
+    hits: AtomicU64,
+    /// Signalled on every hit
+    hit: Notify,
+}
+
+static FD_PRESSURE: FdPressure = FdPressure {
+    warned: AtomicBool::new(false),
+    hits: AtomicU64::new(0),
+    hit: Notify::const_new(),
+};
+
+/// Check a failed I/O operation for descriptor exhaustion and report it
+/// (see `start_resource_task`). Returns whether it was.
+pub fn note_io_error(err: &std::io::Error) -> bool {
+    if !is_fd_exhaustion(err) {
+        return false;
+    }
+    FD_PRESSURE.hits.fetch_add(1, Ordering::Relaxed);
+    FD_PRESSURE.hit.notify_one();
+    true
+}
+
+/// Descriptor exhaustion errors seen since startup
+pub fn fd_exhaustion_count() -> u64 {
+    FD_PRESSURE.hits.load(Ordering::Relaxed)
+}
+
+/// Whether usage is high enough that a throttle should stay on
+fn still_under_pressure(fd_limit: Option<u64>, open_fds: Option<usize>) -> bool {
+    match (fd_limit, open_fds) {
+        (Some(limit), Some(open)) => open as f64 >= limit as f64 * RECOVERED_USAGE,
+        // Can't tell: let the throttle lapse on its own
+        _ => false,
+    }
+}
+
+/// React to descriptor exhaustion: throttle the pacer, warn the user the
+/// first time, and keep the throttle on while usage stays high
+#[cfg(feature = "app")]
+pub async fn start_resource_task(app_handle: tauri::AppHandle, pacer: Arc<DialPacer>, budget: ResourceBudget) {
+    loop {
+        FD_PRESSURE.hit.notified().await;
+        pacer.throttle(THROTTLE_DURATION);
+        tracing::warn!(
+            "Ran out of file descriptors (limit {:?}, {:?} open); limiting new peer connections",
+            budget.fd_limit,
+            open_fds()
+        );
+
+        if !FD_PRESSURE.warned.swap(true, Ordering::Relaxed) {
+            let event = FdExhaustedEvent {
+                fd_limit: budget.fd_limit,
+                open_fds: open_fds(),
+                message: "SeedCore ran out of file descriptors, so some connections or files could not be opened. \
+                          New peer connections are limited for now. Lower the connection limit or raise the \
+                          open files limit (ulimit -n) to avoid this."
+                    .to_string(),
+            };
+            emit_event(&app_handle, Event::FdExhausted(event));
+        }
+
+        // Hold the throttle until usage has dropped, then wait for the next hit
+        loop {
+            tokio::time::sleep(THROTTLE_DURATION).await;
+            if !still_under_pressure(budget.fd_limit, open_fds()) {
+                break;
+            }
+            pacer.throttle(THROTTLE_DURATION);
+        }
+    }
+}
+
+/// rlimit and descriptor counting; no-ops where unsupported
+#[cfg(unix)]
+mod platform {
+    pub fn fd_limit() -> Option<u64> {
+        let mut limit = libc::rlimit { rlim_cur: 0, rlim_max: 0 };
+        // SAFETY: getrlimit only writes to the struct we pass
+        if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) } != 0 {
+            return None;
+        }
+        (limit.rlim_cur != libc::RLIM_INFINITY).then_some(limit.rlim_cur as u64)
+    }
+
+    pub fn open_fds() -> Option<usize> {
+        let dir = if cfg!(target_os = "linux") { "/proc/self/fd" } else { "/dev/fd" };
+        // Less the descriptor read_dir itself holds open
+        std::fs::read_dir(dir).ok().map(|entries| entries.count().saturating_sub(1))
+    }
+
+    pub fn is_fd_exhaustion_code(code: i32) -> bool {
+        code == libc::EMFILE || code == libc::ENFILE
+    }
+}
+
+#[cfg(not(unix))]
+mod platform {
+    /// ERROR_TOO_MANY_OPEN_FILES and WSAEMFILE
+    const FD_EXHAUSTION_CODES: [i32; 2] = [4, 10024];
+
+    pub fn fd_limit() -> Option<u64> {
+        None
+    }
+
+    pub fn open_fds() -> Option<usize> {
+        None
+    }
+
+    pub fn is_fd_exhaustion_code(code: i32) -> bool {
+        FD_EXHAUSTION_CODES.contains(&code)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_budget_from_limit() {
+        // macOS default
+        let budget = ResourceBudget::from_limit(Some(256));
+        assert_eq!((budget.connections, budget.file_handles), (77, 19));
+
+        // Common Linux default: connections hit the cap
+        let budget = ResourceBudget::from_limit(Some(1024));
+        assert_eq!((budget.connections, budget.file_handles), (MAX_CONNECTIONS, 134));
+
+        let budget = ResourceBudget::from_limit(Some(1 << 20));
+        assert_eq!((budget.connections, budget.file_handles), (MAX_CONNECTIONS, MAX_FILE_HANDLES));
+
+        // Tiny limits still leave something to work with
+        let budget = ResourceBudget::from_limit(Some(32));
+        assert_eq!((budget.connections, budget.file_handles), (MIN_CONNECTIONS, MIN_FILE_HANDLES));
+
+        let unknown = ResourceBudget::from_limit(None);
+        assert_eq!((unknown.connections, unknown.file_handles), (MAX_CONNECTIONS, MAX_FILE_HANDLES));
+    }
+
+    #[test]
+    fn test_fd_exhaustion_classification() {
+        #[cfg(unix)]
+        {
+            assert!(is_fd_exhaustion(&std::io::Error::from_raw_os_error(libc::EMFILE)));
+            assert!(is_fd_exhaustion(&std::io::Error::from_raw_os_error(libc::ENFILE)));
+            assert!(!is_fd_exhaustion(&std::io::Error::from_raw_os_error(libc::ENOENT)));
+        }
+        assert!(is_fd_exhaustion_text("Failed to open file \"a\": Too many open files (os error 24)"));
+        assert!(is_fd_exhaustion_text("Too many open files in system"));
+        assert!(!is_fd_exhaustion_text("Connection refused (os error 111)"));
+        assert!(!is_fd_exhaustion(&std::io::Error::new(std::io::ErrorKind::Other, "disk full")));
+    }
+
+    #[test]
+    fn test_pressure_lifts_when_usage_drops() {
+        assert!(still_under_pressure(Some(1000), Some(900)));
+        assert!(!still_under_pressure(Some(1000), Some(500)));
+        assert!(!still_under_pressure(None, Some(900)));
+        assert!(!still_under_pressure(Some(1000), None));
+    }
+}
@@ -1,71 +1,107 @@
+//! Bandwidth scheduler: applies the limits of the schedule rule in effect
+//!
+//! Runs under the background task supervisor; the schedule comes from the
+//! settings through `AppState::bandwidth_schedule`.
+
+use crate::database::{AppSettings, BandwidthRule};
 use crate::state::AppState;
+#[cfg(feature = "app")]
 use tauri::Manager;
-use tokio::time::{self, Duration};
+use tokio::time::Duration;
 use chrono::{Timelike, Datelike, Local};
 
-pub async fn start_scheduler_task(app_handle: tauri::AppHandle) {
-    let mut interval = time::interval(Duration::from_secs(30)); // Check every 30 seconds
+/// How often the schedule is checked
+pub const SCHEDULER_INTERVAL: Duration = Duration::from_secs(30);
+
+/// The bandwidth scheduler settings
+#[derive(Debug, Clone, PartialEq)]
+pub struct BandwidthSchedule {
+    pub enabled: bool,
+    pub rules: Vec<BandwidthRule>,
+    /// Limits outside the scheduled rules (bytes/sec, 0 = unlimited)
+    pub download_limit: u64,
+    pub upload_limit: u64,
+}
 
-    loop {
-        interval.tick().await;
+impl From<&AppSettings> for BandwidthSchedule {
+    fn from(settings: &AppSettings) -> Self {
+        Self {
+            enabled: settings.bandwidth_scheduler_enabled,
+            rules: settings.bandwidth_schedule.clone(),
+            download_limit: settings.max_download_speed,
+            upload_limit: settings.max_upload_speed,
+        }
+    }
+}
 
-        let state_guard = app_handle.state::<AppState>();
-        
-        // Load settings from database
-        let settings = match state_guard.database.load_settings() {
-            Ok(s) => s,
-            Err(e) => {
-                tracing::error!("Scheduler task failed to load settings: {}", e);
-                continue;
+/// Register the bandwidth scheduler with the supervisor
+#[cfg(feature = "app")]
+pub fn register_scheduler_task(app_handle: tauri::AppHandle) -> Result<(), String> {
+    let state = app_handle.state::<AppState>();
+    let schedule = state.bandwidth_schedule.subscribe();
+    let app = app_handle.clone();
+    state.background_tasks.register("bandwidth_scheduler", SCHEDULER_INTERVAL, schedule, move |schedule: BandwidthSchedule| {
+        let app = app.clone();
+        crate::tasks::job(move || {
+            let (app, schedule) = (app.clone(), schedule.clone());
+            async move {
+                apply_schedule(&app, &schedule).await;
+                Ok(())
             }
-        };
+        })
+    })
+}
 
-        if !settings.bandwidth_scheduler_enabled {
-            // If scheduler is disabled, ensure we are using global limits from settings
-            let mut app_settings = state_guard.settings.write().await;
-            app_settings.download_limit = settings.max_download_speed;
-            app_settings.upload_limit = settings.max_upload_speed;
-            continue;
-        }
+/// Set the global limits from the rule in effect now
+#[cfg(feature = "app")]
+async fn apply_schedule(app_handle: &tauri::AppHandle, settings: &BandwidthSchedule) {
+    let state_guard = app_handle.state::<AppState>();
 
-        let now = Local::now();
-        let current_time_str = format!("{:02}:{:02}", now.hour(), now.minute());
-        let current_day = now.weekday().num_days_from_sunday() as u8; // 0=Sunday, 6=Saturday
+    if !settings.enabled {
+        // If scheduler is disabled, ensure we are using global limits from settings
+        let mut app_settings = state_guard.settings.write().await;
+        app_settings.download_limit = settings.download_limit;
+        app_settings.upload_limit = settings.upload_limit;
+        return;
+    }
 
-        let mut active_rule = None;
+    let now = Local::now();
+    let current_time_str = format!("{:02}:{:02}", now.hour(), now.minute());
+    let current_day = now.weekday().num_days_from_sunday() as u8; // 0=Sunday, 6=Saturday
 
-        for rule in &settings.bandwidth_schedule {
-            if !rule.enabled {
-                continue;
-            }
+    let mut active_rule = None;
 
-            // Check if today is in the rule's days
-            if !rule.days.contains(&current_day) {
-                continue;
-            }
+    for rule in &settings.rules {
+        if !rule.enabled {
+            continue;
+        }
 
-            // Check if current time is within [start_time, end_time]
-            // Simple string comparison works for HH:MM format
-            if current_time_str >= rule.start_time && current_time_str <= rule.end_time {
-                active_rule = Some(rule);
-                break; // Use the first matching rule
-            }
+        // Check if today is in the rule's days
+        if !rule.days.contains(&current_day) {
+            continue;
         }
 
-        let mut app_settings = state_guard.settings.write().await;
-        if let Some(rule) = active_rule {
-            if app_settings.download_limit != rule.download_limit || app_settings.upload_limit != rule.upload_limit {
-                tracing::info!("Applying scheduled limits: DL={} UL={}", rule.download_limit, rule.upload_limit);
-                app_settings.download_limit = rule.download_limit;
-                app_settings.upload_limit = rule.upload_limit;
-            }
-        } else {
-            // No active rule, fallback to default limits
-            if app_settings.download_limit != settings.max_download_speed || app_settings.upload_limit != settings.max_upload_speed {
-                tracing::info!("Resuming default limits: DL={} UL={}", settings.max_download_speed, settings.max_upload_speed);
-                app_settings.download_limit = settings.max_download_speed;
-                app_settings.upload_limit = settings.max_upload_speed;
-            }
+        // Check if current time is within [start_time, end_time]
+        // Simple string comparison works for HH:MM format
+        if current_time_str >= rule.start_time && current_time_str <= rule.end_time {
+            active_rule = Some(rule);
+            break; // Use the first matching rule
+        }
+    }
+
+    let mut app_settings = state_guard.settings.write().await;
+    if let Some(rule) = active_rule {
+        if app_settings.download_limit != rule.download_limit || app_settings.upload_limit != rule.upload_limit {
+            tracing::info!("Applying scheduled limits: DL={} UL={}", rule.download_limit, rule.upload_limit);
+            app_settings.download_limit = rule.download_limit;
+            app_settings.upload_limit = rule.upload_limit;
+        }
+    } else {
+        // No active rule, fallback to default limits
+        if app_settings.download_limit != settings.download_limit || app_settings.upload_limit != settings.upload_limit {
+            tracing::info!("Resuming default limits: DL={} UL={}", settings.download_limit, settings.upload_limit);
+            app_settings.download_limit = settings.download_limit;
+            app_settings.upload_limit = settings.upload_limit;
         }
     }
 }
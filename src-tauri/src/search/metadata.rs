@@ -0,0 +1,254 @@
+//! Metadata search: comment, creator, trackers, file types, creation year
+//!
+//! Each torrent's metainfo is boiled down to a `MetadataSummary` when it is
+//! added (or its magnet metadata arrives) and kept in the database's own
+//! tree, so a `MetadataQuery` streams those small records instead of
+//! decoding sessions. `rebuild` fills the tree for collections added before
+//! it existed.
+
+use crate::database::Database;
+use crate::torrent::Metainfo;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+
+/// Comment tokens kept per torrent; the rest of a long comment isn't searchable
+pub const MAX_COMMENT_TOKENS: usize = 64;
+
+/// Searchable digest of a torrent's metainfo
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MetadataSummary {
+    /// Distinct lowercased words of the comment, in order of appearance
+    pub comment_tokens: Vec<String>,
+    /// Lowercased "created by"
+    pub created_by: Option<String>,
+    /// Lowercased tracker hosts, sorted
+    pub tracker_hosts: Vec<String>,
+    /// Number of files by lowercased extension (without the dot)
+    pub extensions: BTreeMap<String, u32>,
+    pub creation_year: Option<i32>,
+}
+
+impl MetadataSummary {
+    pub fn from_metainfo(metainfo: &Metainfo) -> Self {
+        let mut comment_tokens = Vec::new();
+        for token in tokens(metainfo.comment.as_deref().unwrap_or_default()) {
+            if comment_tokens.len() == MAX_COMMENT_TOKENS {
+                break;
+            }
+            if !comment_tokens.contains(&token) {
+                comment_tokens.push(token);
+            }
+        }
+
+        let mut tracker_hosts = super::tracker_hosts(metainfo);
+        tracker_hosts.dedup();
+
+        let mut extensions = BTreeMap::new();
+        for file in &metainfo.info.files {
+            let extension = file
+                .path
+                .last()
+                .and_then(|name| std::path::Path::new(name).extension())
+                .map(|extension| extension.to_string_lossy().to_lowercase());
+            if let Some(extension) = extension {
+                *extensions.entry(extension).or_insert(0) += 1;
+            }
+        }
+
+        let creation_year = metainfo
+            .creation_date
+            .and_then(|secs| chrono::DateTime::from_timestamp(secs, 0))
+            .map(|date| chrono::Datelike::year(&date));
+
+        Self {
+            comment_tokens,
+            created_by: metainfo.created_by.as_deref().map(str::to_lowercase),
+            tracker_hosts,
+            extensions,
+            creation_year,
+        }
+    }
+}
+
+/// Lowercased alphanumeric words
+fn tokens(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+}
+
+/// A predicate over `MetadataSummary`, combined with `all`/`any`/`not`.
+/// In JSON: `{"all": [{"extension": "flac"}, {"tracker": "redacted"}]}`.
+/// Text comparisons ignore case.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MetadataQuery {
+    All(Vec<MetadataQuery>),
+    Any(Vec<MetadataQuery>),
+    Not(Box<MetadataQuery>),
+    /// Every word starts one of the comment's words
+    Comment(String),
+    /// Substring of "created by"
+    CreatedBy(String),
+    /// Substring of any tracker host
+    Tracker(String),
+    /// Contains a file with this extension (a leading dot is ignored)
+    Extension(String),
+    /// Contains at least `at_least` files with this extension
+    ExtensionCount { extension: String, at_least: u32 },
+    /// Created within these years, inclusive
+    CreatedIn { from: Option<i32>, to: Option<i32> },
+}
+
+impl MetadataQuery {
+    pub fn matches(&self, summary: &MetadataSummary) -> bool {
+        let extension_count = |extension: &str| {
+            let extension = extension.trim_start_matches('.').to_lowercase();
+            summary.extensions.get(&extension).copied().unwrap_or(0)
+        };
+        match self {
+            Self::All(queries) => queries.iter().all(|query| query.matches(summary)),
+            Self::Any(queries) => queries.iter().any(|query| query.matches(summary)),
+            Self::Not(query) => !query.matches(summary),
+            Self::Comment(text) => tokens(text)
+                .all(|word| summary.comment_tokens.iter().any(|token| token.starts_with(&word))),
+            Self::CreatedBy(text) => summary
+                .created_by
+                .as_ref()
+                .is_some_and(|created_by| created_by.contains(&text.to_lowercase())),
+            Self::Tracker(text) => {
+                let text = text.to_ascii_lowercase();
+                summary.tracker_hosts.iter().any(|host| host.contains(&text))
+            }
+            Self::Extension(extension) => extension_count(extension) > 0,
+            Self::ExtensionCount { extension, at_least } => extension_count(extension) >= *at_least,
+            Self::CreatedIn { from, to } => summary.creation_year.is_some_and(|year| {
+                from.map_or(true, |from| year >= from) && to.map_or(true, |to| year <= to)
+            }),
+        }
+    }
+}
+
+/// Ids of the torrents whose stored summary matches `query`
+pub fn matching_ids(database: &Database, query: &MetadataQuery) -> crate::Result<HashSet<String>> {
+    let mut ids = HashSet::new();
+    database.for_each_search_metadata(|id, summary| {
+        if query.matches(&summary) {
+            ids.insert(id.to_string());
+        }
+    })?;
+    Ok(ids)
+}
+
+/// Store the summary of a torrent's metainfo, logging failures: metadata
+/// search is a convenience and `rebuild` can always catch up
+pub fn index_metainfo(database: &Database, id: &str, metainfo: &Metainfo) {
+    if let Err(e) = database.save_search_metadata(id, &MetadataSummary::from_metainfo(metainfo)) {
+        tracing::warn!("Failed to index metadata of {}: {}", id, e);
+    }
+}
+
+/// Re-derive every summary from the stored sessions, one session at a time.
+/// Returns how many torrents were indexed.
+pub fn rebuild(database: &Database) -> crate::Result<usize> {
+    database.clear_search_metadata()?;
+    let mut failed = None;
+    let count = database.for_each_torrent(|session| {
+        if failed.is_none() {
+            let summary = MetadataSummary::from_metainfo(&session.metainfo);
+            failed = database.save_search_metadata(&session.id, &summary).err();
+        }
+    })?;
+    if let Some(e) = failed {
+        return Err(e);
+    }
+    database.flush_now()?;
+    tracing::info!("Rebuilt the metadata search index for {} torrents", count);
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::torrent::{FileInfo, TorrentInfo};
+
+    fn metainfo(files: &[&str]) -> Metainfo {
+        Metainfo {
+            announce: "https://flacsfor.me/abc/announce".to_string(),
+            announce_list: vec![
+                vec!["https://FLACSFOR.me/abc/announce".to_string()],
+                vec!["udp://tracker.opentrackr.org:1337/announce".to_string()],
+            ],
+            info: TorrentInfo {
+                piece_length: 16384,
+                pieces: vec![0; 20],
+                piece_count: 1,
+                files: files
+                    .iter()
+                    .map(|name| FileInfo { path: vec!["Album".to_string(), name.to_string()], length: 1 })
+                    .collect(),
+                name: "Album".to_string(),
+                total_size: files.len() as u64,
+                is_single_file: false,
+                private: true,
+                legacy_names: None,
+            },
+            info_hash: [0; 20],
+            // 2019-06-15
+            creation_date: Some(1_560_556_800),
+            comment: Some("Ripped with EAC, log + cue. Ripped by someone".to_string()),
+            created_by: Some("mktorrent 1.1".to_string()),
+        }
+    }
+
+    fn album() -> MetadataSummary {
+        MetadataSummary::from_metainfo(&metainfo(&["01.FLAC", "02.flac", "03.flac", "cover.jpg", "rip.log", "README"]))
+    }
+
+    #[test]
+    fn test_summary_from_metainfo() {
+        let summary = album();
+        assert_eq!(summary.comment_tokens, ["ripped", "with", "eac", "log", "cue", "by", "someone"]);
+        assert_eq!(summary.created_by.as_deref(), Some("mktorrent 1.1"));
+        assert_eq!(summary.tracker_hosts, ["flacsfor.me", "tracker.opentrackr.org"]);
+        assert_eq!(
+            summary.extensions,
+            BTreeMap::from([("flac".to_string(), 3), ("jpg".to_string(), 1), ("log".to_string(), 1)])
+        );
+        assert_eq!(summary.creation_year, Some(2019));
+
+        let mut long = metainfo(&[]);
+        long.comment = Some((0..200).map(|n| format!("word{} ", n)).collect());
+        long.creation_date = None;
+        let summary = MetadataSummary::from_metainfo(&long);
+        assert_eq!(summary.comment_tokens.len(), MAX_COMMENT_TOKENS);
+        assert_eq!(summary.creation_year, None);
+    }
+
+    #[test]
+    fn test_combined_queries() {
+        let summary = album();
+        let query: MetadataQuery =
+            serde_json::from_str(r#"{"all": [{"extension": ".FLAC"}, {"tracker": "flacsfor"}]}"#).unwrap();
+        assert!(query.matches(&summary));
+
+        let matches = |query: MetadataQuery| query.matches(&summary);
+        assert!(matches(MetadataQuery::Comment("rip EAC".to_string())));
+        assert!(!matches(MetadataQuery::Comment("rip XLD".to_string())));
+        assert!(matches(MetadataQuery::CreatedBy("MKTORRENT".to_string())));
+        assert!(matches(MetadataQuery::ExtensionCount { extension: "flac".to_string(), at_least: 3 }));
+        assert!(!matches(MetadataQuery::ExtensionCount { extension: "flac".to_string(), at_least: 4 }));
+        assert!(matches(MetadataQuery::CreatedIn { from: Some(2019), to: None }));
+        assert!(!matches(MetadataQuery::CreatedIn { from: None, to: Some(2018) }));
+        assert!(matches(MetadataQuery::Any(vec![
+            MetadataQuery::Extension("mkv".to_string()),
+            MetadataQuery::Extension("jpg".to_string()),
+        ])));
+        assert!(!matches(MetadataQuery::All(vec![
+            MetadataQuery::Extension("flac".to_string()),
+            MetadataQuery::Not(Box::new(MetadataQuery::Tracker("opentrackr".to_string()))),
+        ])));
+        assert!(matches(MetadataQuery::All(Vec::new())));
+        assert!(!matches(MetadataQuery::Any(Vec::new())));
+    }
+}
@@ -0,0 +1,615 @@
+//! Server-side torrent search (`query_torrents`)
+//!
+//! Filters and sorts the in-memory torrent list joined with what the
+//! sessions know (category, trackers, dates), one page at a time. The
+//! session side and the lowercased names are kept in `SearchIndex` so a
+//! query over thousands of torrents doesn't decode sessions or re-derive
+//! tracker hosts (completion dates are re-read only for torrents not yet
+//! known to be complete, and only when the query filters or sorts by them). Names are re-indexed lazily when the torrent list shows a
+//! different one (magnet metadata, cloud downloads naming themselves).
+//! Predicates on the metainfo itself (`MetadataQuery`) are answered from
+//! the database, see `metadata`.
+
+mod metadata;
+pub use metadata::{index_metainfo, rebuild, MetadataQuery, MetadataSummary, MAX_COMMENT_TOKENS};
+
+use crate::database::{Database, TorrentSession};
+use crate::state::{TorrentInfo, TorrentState};
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, RwLock};
+
+/// Page size when the query doesn't give one
+pub const DEFAULT_PAGE_SIZE: usize = 100;
+
+/// Largest page a query may ask for
+pub const MAX_PAGE_SIZE: usize = 1000;
+
+/// Column to sort by
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SortKey {
+    #[default]
+    Name,
+    Size,
+    Progress,
+    State,
+    AddedAt,
+    CompletedAt,
+    DownloadSpeed,
+    UploadSpeed,
+    QueuePosition,
+}
+
+/// Filters, sort and page of a `query_torrents` call; every filter is optional
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TorrentQuery {
+    /// Case-insensitive substring of the name
+    pub text: Option<String>,
+    /// Start of the info hash (hex)
+    pub info_hash_prefix: Option<String>,
+    /// Any of these states; empty for all
+    pub states: Vec<TorrentState>,
+    /// Exact category
+    pub category: Option<String>,
+    /// Case-insensitive substring of any tracker's host
+    pub tracker: Option<String>,
+    /// Unix seconds, inclusive
+    pub added_after: Option<i64>,
+    pub added_before: Option<i64>,
+    pub completed_after: Option<i64>,
+    pub completed_before: Option<i64>,
+    /// Predicate on the metainfo (comment, creator, file types, ...)
+    pub metadata: Option<MetadataQuery>,
+    pub sort: SortKey,
+    pub descending: bool,
+    pub offset: usize,
+    /// Defaults to `DEFAULT_PAGE_SIZE`, capped at `MAX_PAGE_SIZE`
+    pub limit: Option<usize>,
+}
+
+impl TorrentQuery {
+    fn uses_completion(&self) -> bool {
+        self.completed_after.is_some() || self.completed_before.is_some() || self.sort == SortKey::CompletedAt
+    }
+}
+
+/// One page of results
+#[derive(Debug, Clone, Serialize)]
+pub struct TorrentPage {
+    pub torrents: Vec<TorrentInfo>,
+    /// Matches across all pages
+    pub total: usize,
+    pub offset: usize,
+}
+
+/// What a query needs beyond `TorrentInfo`
+#[derive(Debug, Clone, Default)]
+struct IndexEntry {
+    /// Name the lowercase form was derived from
+    name: String,
+    name_lower: String,
+    tracker_hosts: Vec<String>,
+    category: Option<String>,
+    added_at: Option<i64>,
+    completed_at: Option<i64>,
+}
+
+impl IndexEntry {
+    fn from_session(session: &TorrentSession) -> Self {
+        let metainfo = &session.metainfo;
+        let mut tracker_hosts = tracker_hosts(metainfo);
+        tracker_hosts.dedup();
+
+        let mut entry = Self {
+            tracker_hosts,
+            category: session.category.clone(),
+            added_at: Some(session.added_at),
+            completed_at: session.completed_at,
+            ..Self::default()
+        };
+        entry.rename(&metainfo.info.name);
+        entry
+    }
+
+    fn rename(&mut self, name: &str) {
+        self.name = name.to_string();
+        self.name_lower = name.to_lowercase();
+    }
+}
+
+/// Lowercased hosts of all the trackers, sorted (with duplicates)
+fn tracker_hosts(metainfo: &crate::torrent::Metainfo) -> Vec<String> {
+    let mut hosts: Vec<String> = std::iter::once(&metainfo.announce)
+        .chain(metainfo.announce_list.iter().flatten())
+        .filter_map(|url| reqwest::Url::parse(url).ok())
+        .filter_map(|url| url.host_str().map(str::to_ascii_lowercase))
+        .collect();
+    hosts.sort();
+    hosts
+}
+
+/// Search data by torrent id
+#[derive(Clone, Default)]
+pub struct SearchIndex {
+    entries: Arc<RwLock<HashMap<String, IndexEntry>>>,
+}
+
+impl SearchIndex {
+    /// Index (or re-index) a torrent from its session
+    pub fn insert(&self, session: &TorrentSession) {
+        self.entries.write().unwrap().insert(session.id.clone(), IndexEntry::from_session(session));
+    }
+
+    pub fn remove(&self, torrent_id: &str) {
+        self.entries.write().unwrap().remove(torrent_id);
+    }
+
+    /// Category by torrent id, for the torrents that have one
+    pub fn categories(&self) -> HashMap<String, String> {
+        self.entries
+            .read()
+            .unwrap()
+            .iter()
+            .filter_map(|(id, entry)| entry.category.clone().map(|category| (id.clone(), category)))
+            .collect()
+    }
+
+    /// Pick up completions since indexing. Engines record them in the
+    /// database only, and a torrent never goes back to incomplete, so only
+    /// entries without a completion time need looking at.
+    pub fn refresh_completion(&self, database: &Database) {
+        let pending: Vec<String> = self
+            .entries
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|(_, entry)| entry.completed_at.is_none())
+            .map(|(id, _)| id.clone())
+            .collect();
+
+        for id in pending {
+            let completed_at = match database.load_torrent(&id) {
+                Ok(Some(session)) => session.completed_at,
+                _ => None,
+            };
+            if let Some(at) = completed_at {
+                if let Some(entry) = self.entries.write().unwrap().get_mut(&id) {
+                    entry.completed_at = Some(at);
+                }
+            }
+        }
+    }
+
+    /// Run `query` over `torrents`; `query.metadata` needs the database and
+    /// is left to `run_query`
+    pub fn query(&self, torrents: &HashMap<String, TorrentInfo>, query: &TorrentQuery) -> TorrentPage {
+        self.query_among(torrents, query, None)
+    }
+
+    /// Run `query` over those of `torrents` whose id is in `only` (all when None)
+    fn query_among(
+        &self,
+        torrents: &HashMap<String, TorrentInfo>,
+        query: &TorrentQuery,
+        only: Option<&HashSet<String>>,
+    ) -> TorrentPage {
+        let mut entries = self.entries.write().unwrap();
+
+        // Cloud torrents have no session: index them by name alone
+        for (id, info) in torrents {
+            let entry = entries.entry(id.clone()).or_default();
+            if entry.name != info.name {
+                entry.rename(&info.name);
+            }
+        }
+
+        let text = query.text.as_deref().map(str::to_lowercase).filter(|t| !t.is_empty());
+        let hash_prefix = query.info_hash_prefix.as_deref().map(str::to_ascii_lowercase);
+        let tracker = query.tracker.as_deref().map(str::to_ascii_lowercase).filter(|t| !t.is_empty());
+        let in_range = |value: Option<i64>, after: Option<i64>, before: Option<i64>| {
+            if after.is_none() && before.is_none() {
+                return true;
+            }
+            value.is_some_and(|v| after.map_or(true, |a| v >= a) && before.map_or(true, |b| v <= b))
+        };
+
+        let mut matches: Vec<(&TorrentInfo, &IndexEntry)> = torrents
+            .values()
+            .filter(|info| only.map_or(true, |ids| ids.contains(&info.id)))
+            .filter_map(|info| entries.get(&info.id).map(|entry| (info, entry)))
+            .filter(|(info, entry)| {
+                text.as_ref().map_or(true, |t| entry.name_lower.contains(t.as_str()))
+                    && hash_prefix.as_ref().map_or(true, |p| info.id.starts_with(p.as_str()))
+                    && (query.states.is_empty() || query.states.contains(&info.state))
+                    && query.category.as_ref().map_or(true, |c| entry.category.as_ref() == Some(c))
+                    && tracker.as_ref().map_or(true, |t| entry.tracker_hosts.iter().any(|h| h.contains(t.as_str())))
+                    && in_range(entry.added_at, query.added_after, query.added_before)
+                    && in_range(entry.completed_at, query.completed_after, query.completed_before)
+            })
+            .collect();
+
+        matches.sort_by(|a, b| {
+            let ordering = compare(query.sort, a, b);
+            let ordering = if query.descending { ordering.reverse() } else { ordering };
+            // Ties keep a stable order so pages don't overlap
+            ordering.then_with(|| a.0.id.cmp(&b.0.id))
+        });
+
+        let total = matches.len();
+        let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE).min(MAX_PAGE_SIZE);
+        let torrents = matches
+            .into_iter()
+            .skip(query.offset)
+            .take(limit)
+            .map(|(info, _)| info.clone())
+            .collect();
+        TorrentPage { torrents, total, offset: query.offset }
+    }
+
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.entries.read().unwrap().len()
+    }
+}
+
+fn progress(info: &TorrentInfo) -> f64 {
+    if info.size == 0 {
+        0.0
+    } else {
+        info.downloaded as f64 / info.size as f64
+    }
+}
+
+/// Ascending order by `key`; missing values sort last
+fn compare(key: SortKey, (a, a_entry): &(&TorrentInfo, &IndexEntry), (b, b_entry): &(&TorrentInfo, &IndexEntry)) -> Ordering {
+    fn some_first<T: Ord>(a: Option<T>, b: Option<T>) -> Ordering {
+        match (a, b) {
+            (Some(a), Some(b)) => a.cmp(&b),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => Ordering::Equal,
+        }
+    }
+
+    match key {
+        SortKey::Name => a_entry.name_lower.cmp(&b_entry.name_lower),
+        SortKey::Size => a.size.cmp(&b.size),
+        SortKey::Progress => progress(a).total_cmp(&progress(b)),
+        SortKey::State => format!("{:?}", a.state).cmp(&format!("{:?}", b.state)),
+        SortKey::AddedAt => some_first(a_entry.added_at, b_entry.added_at),
+        SortKey::CompletedAt => some_first(a_entry.completed_at, b_entry.completed_at),
+        SortKey::DownloadSpeed => a.download_speed.cmp(&b.download_speed),
+        SortKey::UploadSpeed => a.upload_speed.cmp(&b.upload_speed),
+        SortKey::QueuePosition => some_first(a.queue_position, b.queue_position),
+    }
+}
+
+/// Search with completion times brought up to date first when the query
+/// depends on them
+pub fn run_query(
+    index: &SearchIndex,
+    database: &Database,
+    torrents: &HashMap<String, TorrentInfo>,
+    query: &TorrentQuery,
+) -> TorrentPage {
+    if query.uses_completion() {
+        index.refresh_completion(database);
+    }
+    let only = query.metadata.as_ref().map(|metadata| {
+        metadata::matching_ids(database, metadata).unwrap_or_else(|e| {
+            tracing::error!("Metadata search failed: {}", e);
+            HashSet::new()
+        })
+    });
+    index.query_among(torrents, query, only.as_ref())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::torrent::{Metainfo, TorrentInfo as InfoDict};
+
+    const COUNT: usize = 1000;
+
+    fn id(n: usize) -> String {
+        format!("{:040x}", n)
+    }
+
+    /// Torrent `n`: "Ubuntu ..." or "Debian ..." alternating, a tracker per
+    /// parity, category every third, added at `n * 10`, even ones completed
+    fn synthetic(n: usize) -> (TorrentInfo, TorrentSession) {
+        let distro = if n % 2 == 0 { "Ubuntu" } else { "Debian" };
+        let name = format!("{} Release {:04}", distro, n);
+        let state = match n % 4 {
+            0 => TorrentState::Seeding,
+            1 => TorrentState::Downloading,
+            2 => TorrentState::Paused,
+            _ => TorrentState::Queued,
+        };
+        let info = TorrentInfo {
+            id: id(n),
+            name: name.clone(),
+            size: 1000 + n as u64,
+            downloaded: (n as u64 * 7) % 1000,
+            uploaded: 0,
+            state,
+            download_speed: (COUNT - n) as u64,
+            upload_speed: 0,
+            peers: 0,
+            seeds: 0,
+            source: crate::debrid::types::DownloadSource::P2P,
+            remote_deleted: false,
+            swarm_seeds: None,
+            swarm_leechers: None,
+            swarm_updated_at: None,
+            queue_position: None,
+            metadata_pending: false,
+            name_encoding: None,
+            tags: Vec::new(),
+            error: None,
+        };
+        let tracker = if n % 2 == 0 { "udp://tracker.opentrackr.org:1337/announce" } else { "http://bttracker.debian.org:6969/announce" };
+        let metainfo = Metainfo {
+            announce: tracker.to_string(),
+            announce_list: vec![vec!["https://Backup.Example.com/announce".to_string()]],
+            info: InfoDict {
+                piece_length: 16384,
+                pieces: vec![0; 20],
+                piece_count: 1,
+                files: Vec::new(),
+                name,
+                total_size: info.size,
+                is_single_file: true,
+                private: false,
+                legacy_names: None,
+            },
+            info_hash: [0; 20],
+            creation_date: None,
+            comment: None,
+            created_by: None,
+        };
+        let session = TorrentSession {
+            id: id(n),
+            metainfo,
+            bitfield: Vec::new(),
+            num_pieces: 1,
+            downloaded: 0,
+            uploaded: 0,
+            state: "paused".to_string(),
+            download_dir: "/downloads".to_string(),
+            added_at: n as i64 * 10,
+            last_activity: 0,
+            source: crate::debrid::types::DownloadSource::P2P,
+            completed_at: (n % 2 == 0).then_some(n as i64 * 10 + 5),
+            volume_id: None,
+            category: (n % 3 == 0).then(|| "linux".to_string()),
+            traffic: Default::default(),
+            swarm: None,
+            root_name: None,
+            data_sync: Default::default(),
+            queue_position: None,
+            saved_peers: Vec::new(),
+            added_from: None,
+            storage_mode: Default::default(),
+            auto_stop: Default::default(),
+            low_disk_paused_at: None,
+            linked_files: Vec::new(),
+            disabled_trackers: Vec::new(),
+            file_completed_at: Default::default(),
+        };
+        (info, session)
+    }
+
+    fn setup() -> (SearchIndex, HashMap<String, TorrentInfo>) {
+        let index = SearchIndex::default();
+        let mut torrents = HashMap::new();
+        for n in 0..COUNT {
+            let (info, session) = synthetic(n);
+            index.insert(&session);
+            torrents.insert(info.id.clone(), info);
+        }
+        (index, torrents)
+    }
+
+    fn query(index: &SearchIndex, torrents: &HashMap<String, TorrentInfo>, query: TorrentQuery) -> TorrentPage {
+        index.query(torrents, &TorrentQuery { limit: Some(MAX_PAGE_SIZE), ..query })
+    }
+
+    #[test]
+    fn test_filters() {
+        let (index, torrents) = setup();
+
+        let page = query(&index, &torrents, TorrentQuery { text: Some("uBUNTU".to_string()), ..Default::default() });
+        assert_eq!(page.total, 500);
+        assert!(page.torrents.iter().all(|t| t.name.starts_with("Ubuntu")));
+
+        let page = query(&index, &torrents, TorrentQuery { text: Some("release 012".to_string()), ..Default::default() });
+        assert_eq!(page.total, 10); // 0120..=0129
+
+        let page = query(&index, &torrents, TorrentQuery { info_hash_prefix: Some(id(0x3e7)[..39].to_uppercase()), ..Default::default() });
+        // 0x3e0..=0x3e7 (below COUNT = 0x3e8)
+        assert_eq!(page.total, 8);
+
+        let page = query(
+            &index,
+            &torrents,
+            TorrentQuery { states: vec![TorrentState::Seeding, TorrentState::Paused], ..Default::default() },
+        );
+        assert_eq!(page.total, 500);
+        assert!(page.torrents.iter().all(|t| matches!(t.state, TorrentState::Seeding | TorrentState::Paused)));
+
+        let page = query(&index, &torrents, TorrentQuery { category: Some("linux".to_string()), ..Default::default() });
+        assert_eq!(page.total, 334);
+
+        let page = query(&index, &torrents, TorrentQuery { tracker: Some("DEBIAN.org".to_string()), ..Default::default() });
+        assert_eq!(page.total, 500);
+        let page = query(&index, &torrents, TorrentQuery { tracker: Some("backup.example".to_string()), ..Default::default() });
+        assert_eq!(page.total, COUNT);
+
+        let page = query(
+            &index,
+            &torrents,
+            TorrentQuery { added_after: Some(100), added_before: Some(190), ..Default::default() },
+        );
+        assert_eq!(page.total, 10); // n = 10..=19
+
+        // Only even torrents are complete
+        let page = query(&index, &torrents, TorrentQuery { completed_after: Some(0), ..Default::default() });
+        assert_eq!(page.total, 500);
+        let page = query(&index, &torrents, TorrentQuery { completed_before: Some(105), ..Default::default() });
+        assert_eq!(page.total, 6); // n = 0, 2, .., 10
+
+        // Dimensions combine
+        let page = query(
+            &index,
+            &torrents,
+            TorrentQuery {
+                text: Some("debian".to_string()),
+                category: Some("linux".to_string()),
+                states: vec![TorrentState::Downloading],
+                ..Default::default()
+            },
+        );
+        // Odd, multiple of 3 and n % 4 == 1: n % 12 == 9
+        assert_eq!(page.total, (0..COUNT).filter(|n| n % 12 == 9).count());
+    }
+
+    #[test]
+    fn test_sort_and_pagination() {
+        let (index, torrents) = setup();
+
+        let mut seen = Vec::new();
+        let mut offset = 0;
+        loop {
+            let page = index.query(&torrents, &TorrentQuery { sort: SortKey::Size, offset, limit: Some(300), ..Default::default() });
+            assert_eq!(page.total, COUNT);
+            assert_eq!(page.offset, offset);
+            if page.torrents.is_empty() {
+                break;
+            }
+            assert_eq!(page.torrents.len(), 300.min(COUNT - offset));
+            seen.extend(page.torrents.into_iter().map(|t| t.size));
+            offset += 300;
+        }
+        assert_eq!(seen, (0..COUNT as u64).map(|n| 1000 + n).collect::<Vec<_>>());
+
+        // Default and capped page sizes
+        let page = index.query(&torrents, &TorrentQuery::default());
+        assert_eq!(page.torrents.len(), DEFAULT_PAGE_SIZE);
+        let page = index.query(&torrents, &TorrentQuery { limit: Some(5000), ..Default::default() });
+        assert_eq!(page.torrents.len(), MAX_PAGE_SIZE.min(COUNT));
+        let page = index.query(&torrents, &TorrentQuery { offset: 5000, ..Default::default() });
+        assert!(page.torrents.is_empty());
+        assert_eq!(page.total, COUNT);
+
+        let page = index.query(
+            &torrents,
+            &TorrentQuery { sort: SortKey::DownloadSpeed, descending: true, limit: Some(3), ..Default::default() },
+        );
+        let speeds: Vec<u64> = page.torrents.iter().map(|t| t.download_speed).collect();
+        assert_eq!(speeds, vec![1000, 999, 998]);
+
+        // Incomplete torrents sort after completed ones
+        let page = index.query(&torrents, &TorrentQuery { sort: SortKey::CompletedAt, offset: 499, limit: Some(2), ..Default::default() });
+        assert!(page.torrents[0].id == id(998) && page.torrents[1].id == id(1));
+
+        let page = index.query(&torrents, &TorrentQuery { sort: SortKey::Name, descending: true, limit: Some(1), ..Default::default() });
+        assert_eq!(page.torrents[0].name, "Ubuntu Release 0998");
+    }
+
+    #[test]
+    fn test_index_follows_torrent_list() {
+        let (index, mut torrents) = setup();
+
+        // Magnet metadata or a cloud download renaming the torrent
+        torrents.get_mut(&id(1)).unwrap().name = "Renamed Thing".to_string();
+        let page = index.query(&torrents, &TorrentQuery { text: Some("renamed".to_string()), ..Default::default() });
+        assert_eq!(page.torrents.len(), 1);
+
+        // Cloud torrents are found by name without a session
+        let (mut cloud, _) = synthetic(COUNT);
+        cloud.name = "Cloud Only".to_string();
+        torrents.insert(cloud.id.clone(), cloud);
+        let page = index.query(&torrents, &TorrentQuery { text: Some("cloud only".to_string()), ..Default::default() });
+        assert_eq!(page.total, 1);
+
+        torrents.remove(&id(2));
+        index.remove(&id(2));
+        assert_eq!(index.len(), COUNT);
+        let page = index.query(&torrents, &TorrentQuery { limit: Some(MAX_PAGE_SIZE), ..Default::default() });
+        assert_eq!(page.total, COUNT);
+    }
+
+    #[test]
+    fn test_completion_refreshed_from_database() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let database = Database::open(temp_dir.path().join("db")).unwrap();
+        let index = SearchIndex::default();
+        let (info, mut session) = synthetic(1);
+        database.save_torrent(&session).unwrap();
+        index.insert(&session);
+        let torrents = HashMap::from([(info.id.clone(), info)]);
+
+        let completed = TorrentQuery { completed_after: Some(0), ..Default::default() };
+        assert_eq!(run_query(&index, &database, &torrents, &completed).total, 0);
+
+        // The engine records the completion in the database only
+        session.completed_at = Some(50);
+        database.save_torrent(&session).unwrap();
+        assert_eq!(run_query(&index, &database, &torrents, &completed).total, 1);
+    }
+
+    #[test]
+    fn test_metadata_index_build_and_maintenance() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let database = Database::open(temp_dir.path().join("db")).unwrap();
+        let index = SearchIndex::default();
+        let mut torrents = HashMap::new();
+        let sessions: Vec<TorrentSession> = (0..4)
+            .map(|n| {
+                let (info, mut session) = synthetic(n);
+                session.metainfo.comment = Some(format!("Batch {}", n % 2));
+                session.metainfo.created_by = (n == 3).then(|| "qBittorrent v4.6".to_string());
+                database.save_torrent(&session).unwrap();
+                index.insert(&session);
+                torrents.insert(info.id.clone(), info);
+                session
+            })
+            .collect();
+        let ids = |torrents: &HashMap<String, TorrentInfo>, metadata: MetadataQuery| -> Vec<String> {
+            let query = TorrentQuery { metadata: Some(metadata), sort: SortKey::Name, ..Default::default() };
+            run_query(&index, &database, torrents, &query).torrents.into_iter().map(|t| t.id).collect()
+        };
+        let batch_one = MetadataQuery::Comment("batch 1".to_string());
+
+        // Saved before the index existed: nothing matches until a rebuild
+        assert!(ids(&torrents, batch_one.clone()).is_empty());
+        assert_eq!(rebuild(&database).unwrap(), 4);
+        assert_eq!(ids(&torrents, batch_one.clone()), [id(1), id(3)]);
+        assert_eq!(
+            ids(&torrents, MetadataQuery::All(vec![batch_one.clone(), MetadataQuery::Tracker("debian".to_string())])),
+            [id(1), id(3)]
+        );
+        assert_eq!(ids(&torrents, MetadataQuery::All(vec![batch_one.clone(), MetadataQuery::CreatedBy("qbit".to_string())])), [id(3)]);
+
+        // Added and removed torrents are kept up to date
+        let (info, mut session) = synthetic(4);
+        session.metainfo.comment = Some("batch 1".to_string());
+        database.save_torrent(&session).unwrap();
+        index_metainfo(&database, &session.id, &session.metainfo);
+        index.insert(&session);
+        torrents.insert(info.id.clone(), info);
+        database.delete_torrent(&sessions[1].id).unwrap();
+        index.remove(&sessions[1].id);
+        torrents.remove(&sessions[1].id);
+        assert_eq!(ids(&torrents, batch_one.clone()), [id(3), id(4)]);
+
+        // Other filters still apply on top
+        let query = TorrentQuery { metadata: Some(batch_one), text: Some("ubuntu".to_string()), ..Default::default() };
+        let page = run_query(&index, &database, &torrents, &query);
+        assert_eq!(page.torrents.into_iter().map(|t| t.id).collect::<Vec<_>>(), [id(4)]);
+    }
+}
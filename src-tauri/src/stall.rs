@@ -0,0 +1,428 @@
+//! Auto-stop for downloads that can't finish
+//!
+//! A download whose connected swarm has had less than one complete copy
+//! (`distributed_copies` < 1.0) for `auto_stop_dead_days`, while we gained
+//! no piece, is paused and tagged `stalled-dead`. Being paused, it no longer
+//! takes a slot in the download queue. The hourly availability samples are
+//! the record: they have to cover the whole window with no gap longer than
+//! `MAX_SAMPLE_GAP`, since a torrent that wasn't running wasn't watched.
+//!
+//! Stopped torrents are scraped every `SCRAPE_INTERVAL_SECS`. Once a tracker
+//! reports a seed, `torrent-revivable` is emitted and, with
+//! `auto_resume_revived`, the torrent is started again. Single torrents opt
+//! out with `set_torrent_auto_stop`.
+
+use crate::availability::{AvailabilitySample, SAMPLE_INTERVAL_SECS};
+use crate::database::{AppSettings, TorrentSession};
+#[cfg(feature = "app")]
+use crate::events::{emit_event, Event, RevivableEvent, StalledDeadEvent};
+use crate::state::{AppState, TorrentState};
+use crate::tracker::ScrapeResponse;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+#[cfg(feature = "app")]
+use tauri::Manager;
+use tokio::time::{self, Duration};
+
+/// Tag on torrents the policy stopped
+pub const STALLED_DEAD_TAG: &str = "stalled-dead";
+
+/// Days without a complete copy before a download is stopped, unless configured
+pub const DEFAULT_DEAD_AFTER_DAYS: u32 = 14;
+
+/// Longest window; the availability history doesn't go back further
+pub const MAX_DEAD_AFTER_DAYS: u32 = (crate::availability::MAX_SAMPLES as i64 * SAMPLE_INTERVAL_SECS / DAY) as u32;
+
+/// Longest gap between two samples still counted as continuous watching
+pub const MAX_SAMPLE_GAP: i64 = 3 * SAMPLE_INTERVAL_SECS;
+
+/// Time between scrapes of a stopped torrent
+pub const SCRAPE_INTERVAL_SECS: i64 = 6 * 60 * 60;
+
+/// How often downloads are evaluated
+const CHECK_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+const DAY: i64 = 24 * 60 * 60;
+
+pub fn default_dead_after_days() -> u32 {
+    DEFAULT_DEAD_AFTER_DAYS
+}
+
+/// A torrent's standing with the policy, saved with its session
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AutoStop {
+    /// Never stopped by the policy
+    #[serde(default)]
+    pub exempt: bool,
+    /// When the policy stopped it; cleared once it is started again
+    #[serde(default)]
+    pub stopped_at: Option<i64>,
+    /// When it was last started after being stopped; history from before
+    /// doesn't count towards another stop
+    #[serde(default)]
+    pub resumed_at: Option<i64>,
+}
+
+/// The policy's settings
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Policy {
+    pub enabled: bool,
+    pub after_days: u32,
+    /// Start stopped torrents again once a seed shows up
+    pub auto_resume: bool,
+}
+
+impl Policy {
+    pub fn from_settings(settings: &AppSettings) -> Self {
+        Self {
+            enabled: settings.auto_stop_dead_enabled,
+            after_days: settings.auto_stop_dead_days.clamp(1, MAX_DEAD_AFTER_DAYS),
+            auto_resume: settings.auto_resume_revived,
+        }
+    }
+
+    fn window(&self) -> i64 {
+        i64::from(self.after_days) * DAY
+    }
+}
+
+/// The tags a session's policy state puts on its UI entry
+pub fn tags(auto_stop: &AutoStop) -> Vec<String> {
+    match auto_stop.stopped_at {
+        Some(_) => vec![STALLED_DEAD_TAG.to_string()],
+        None => Vec::new(),
+    }
+}
+
+/// Whether `series` shows the torrent watched for the whole `window` before
+/// `now` with less than one complete copy around and no piece gained
+pub fn stalled(series: &[AvailabilitySample], now: i64, window: i64) -> bool {
+    let start = now - window;
+    let watched: Vec<&AvailabilitySample> = series.iter().filter(|s| s.at >= start && s.at <= now).collect();
+    let (Some(first), Some(last)) = (watched.first(), watched.last()) else {
+        return false;
+    };
+    let continuous = first.at - start <= MAX_SAMPLE_GAP
+        && now - last.at <= MAX_SAMPLE_GAP
+        && watched.windows(2).all(|pair| pair[1].at - pair[0].at <= MAX_SAMPLE_GAP);
+    if !continuous || watched.iter().any(|s| s.distributed_copies >= 1.0) {
+        return false;
+    }
+    // Progress can only be ruled out when every sample recorded it
+    let Some(have) = first.pieces_have else {
+        return false;
+    };
+    watched.iter().all(|s| s.pieces_have == Some(have))
+}
+
+/// Whether the policy stops a downloading torrent at `now`
+pub fn should_stop(policy: &Policy, auto_stop: &AutoStop, series: &[AvailabilitySample], now: i64) -> bool {
+    if !policy.enabled || auto_stop.exempt || auto_stop.stopped_at.is_some() {
+        return false;
+    }
+    let window = policy.window();
+    if auto_stop.resumed_at.is_some_and(|at| at > now - window) {
+        return false;
+    }
+    stalled(series, now, window)
+}
+
+/// Seeds reported by a round of scrapes, if any tracker saw one
+pub fn revived(scrapes: &[ScrapeResponse]) -> Option<u32> {
+    scrapes.iter().map(|scrape| scrape.complete).max().filter(|&seeds| seeds > 0)
+}
+
+/// Change a torrent's policy state and its UI tags
+pub async fn update(state: &AppState, torrent_id: &str, modify: impl FnOnce(&mut AutoStop)) -> Result<AutoStop, String> {
+    let mut session = state.database
+        .load_torrent(torrent_id)
+        .map_err(|e| format!("Failed to load torrent: {}", e))?
+        .ok_or_else(|| format!("Torrent not found: {}", torrent_id))?;
+    let before = session.auto_stop;
+    modify(&mut session.auto_stop);
+    if session.auto_stop != before {
+        state.database
+            .save_torrent(&session)
+            .map_err(|e| format!("Failed to save torrent to database: {}", e))?;
+    }
+    if let Some(info) = state.torrents.write().await.get_mut(torrent_id) {
+        info.tags = session.tags();
+    }
+    Ok(session.auto_stop)
+}
+
+/// Forget that the policy stopped a torrent (it is being started)
+pub async fn clear_stop(state: &AppState, torrent_id: &str) -> Result<(), String> {
+    let now = chrono::Utc::now().timestamp();
+    update(state, torrent_id, |auto_stop| {
+        if auto_stop.stopped_at.take().is_some() {
+            auto_stop.resumed_at = Some(now);
+        }
+    })
+    .await
+    .map(|_| ())
+}
+
+/// Pause a dead download and tag it
+#[cfg(feature = "app")]
+async fn stop(app: &tauri::AppHandle, state: &AppState, session: &TorrentSession, policy: &Policy, now: i64) {
+    tracing::info!(
+        "Stopping {}: less than one copy in the swarm and no progress for {} days",
+        session.id,
+        policy.after_days
+    );
+    if let Err(e) = crate::commands::pause_torrent_internal(state, &session.id, TorrentState::Paused).await {
+        tracing::warn!("Failed to stop dead download {}: {}", session.id, e);
+        return;
+    }
+    if let Err(e) = update(state, &session.id, |auto_stop| auto_stop.stopped_at = Some(now)).await {
+        tracing::warn!("Failed to tag {} as {}: {}", session.id, STALLED_DEAD_TAG, e);
+    }
+    // Its queue slot goes to the next download
+    state.queue.request_reconcile();
+
+    let event = StalledDeadEvent {
+        torrent_id: session.id.clone(),
+        name: session.metainfo.info.name.clone(),
+        days: policy.after_days,
+    };
+    emit_event(app, Event::StalledDead(event));
+}
+
+/// Scrape every enabled HTTP tracker of a session; failures are left out
+async fn scrape(state: &AppState, session: &TorrentSession) -> Vec<ScrapeResponse> {
+    let tracker = crate::tracker::http::HttpTracker::with_config(&state.tracker_http.borrow());
+    let anonymous = *state.anonymous_mode.borrow();
+    let disabled = session.disabled_trackers.iter().cloned().collect();
+    // UDP trackers aren't scraped
+    let urls: Vec<String> = crate::tracker::announce_order(&session.metainfo, &disabled)
+        .into_iter()
+        .filter(|url| !crate::tracker::is_udp(url))
+        .collect();
+
+    let mut scrapes = Vec::new();
+    for url in &urls {
+        match tracker.scrape(url, &session.metainfo.info_hash, anonymous).await {
+            Ok(scrape) => scrapes.push(scrape),
+            Err(e) => tracing::debug!("Scrape of {} failed: {}", session.id, e),
+        }
+    }
+    scrapes
+}
+
+/// Scrapes of stopped torrents, between checks
+#[derive(Default)]
+struct Revival {
+    /// Last scrape per torrent
+    scraped_at: HashMap<String, i64>,
+    /// Torrents `torrent-revivable` went out for
+    notified: HashSet<String>,
+}
+
+impl Revival {
+    #[cfg(feature = "app")]
+    async fn check(&mut self, app: &tauri::AppHandle, state: &AppState, session: &TorrentSession, policy: &Policy, now: i64) {
+        let due = self.scraped_at.get(&session.id).map_or(true, |&at| now - at >= SCRAPE_INTERVAL_SECS);
+        if !due {
+            return;
+        }
+        self.scraped_at.insert(session.id.clone(), now);
+        let Some(seeds) = revived(&scrape(state, session).await) else {
+            return;
+        };
+
+        let resumed = policy.auto_resume && resume(state, &session.id).await;
+        if !resumed && !self.notified.insert(session.id.clone()) {
+            return;
+        }
+        tracing::info!("{} has {} seeds again", session.id, seeds);
+        let event = RevivableEvent {
+            torrent_id: session.id.clone(),
+            name: session.metainfo.info.name.clone(),
+            seeds,
+            resumed,
+        };
+        emit_event(app, Event::Revivable(event));
+    }
+
+    /// Drop entries of torrents no longer stopped
+    fn retain(&mut self, stopped: &HashSet<String>) {
+        self.scraped_at.retain(|id, _| stopped.contains(id));
+        self.notified.retain(|id| stopped.contains(id));
+    }
+}
+
+/// Start a revived torrent; false if it couldn't be
+#[cfg(feature = "app")]
+async fn resume(state: &AppState, torrent_id: &str) -> bool {
+    let result = async {
+        crate::commands::start_torrent_internal(state, torrent_id.to_string(), false).await?;
+        clear_stop(state, torrent_id).await
+    }
+    .await;
+    match result {
+        Ok(()) => {
+            state.queue.request_reconcile();
+            true
+        }
+        Err(e) => {
+            tracing::warn!("Failed to resume revived torrent {}: {}", torrent_id, e);
+            false
+        }
+    }
+}
+
+/// Evaluate downloads hourly and watch stopped ones for seeds
+#[cfg(feature = "app")]
+pub async fn start_stall_task(app_handle: tauri::AppHandle) {
+    let mut interval = time::interval(CHECK_INTERVAL);
+    let mut revival = Revival::default();
+
+    loop {
+        interval.tick().await;
+        let state = app_handle.state::<AppState>();
+        let settings = match state.database.load_settings() {
+            Ok(settings) => settings,
+            Err(e) => {
+                tracing::error!("Dead download check failed to load settings: {}", e);
+                continue;
+            }
+        };
+        let policy = Policy::from_settings(&settings);
+        if !policy.enabled {
+            continue;
+        }
+        let sessions = match state.database.load_all_torrents() {
+            Ok(sessions) => sessions,
+            Err(e) => {
+                tracing::error!("Dead download check failed to load torrents: {}", e);
+                continue;
+            }
+        };
+
+        let now = chrono::Utc::now().timestamp();
+        let downloading: HashSet<String> = state.torrents.read().await
+            .iter()
+            .filter(|(_, info)| info.state == TorrentState::Downloading)
+            .map(|(id, _)| id.clone())
+            .collect();
+        let mut stopped = HashSet::new();
+        for session in &sessions {
+            if session.auto_stop.stopped_at.is_some() {
+                stopped.insert(session.id.clone());
+                revival.check(&app_handle, &state, session, &policy, now).await;
+                continue;
+            }
+            if !downloading.contains(&session.id) {
+                continue;
+            }
+            let series = match state.database.load_availability_history(&session.id) {
+                Ok(series) => series,
+                Err(e) => {
+                    tracing::warn!("Failed to load availability of {}: {}", session.id, e);
+                    continue;
+                }
+            };
+            if should_stop(&policy, &session.auto_stop, &series, now) {
+                stop(&app_handle, &state, session, &policy, now).await;
+            }
+        }
+        revival.retain(&stopped);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOUR: i64 = SAMPLE_INTERVAL_SECS;
+    const NOW: i64 = 1_700_000_000;
+
+    fn policy() -> Policy {
+        Policy { enabled: true, after_days: 14, auto_resume: false }
+    }
+
+    /// Hourly samples over the last `days`; `copies` and `have` by hours ago
+    fn series(days: i64, copies: impl Fn(i64) -> f64, have: impl Fn(i64) -> Option<u32>) -> Vec<AvailabilitySample> {
+        (0..days * 24)
+            .rev()
+            .map(|ago| AvailabilitySample {
+                at: NOW - ago * HOUR,
+                swarm_seeds: Some(0),
+                swarm_leechers: Some(3),
+                distributed_copies: copies(ago),
+                connected_peers: 3,
+                pieces_have: have(ago),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_stall_over_the_whole_window_stops() {
+        let dead = series(15, |_| 0.6, |_| Some(40));
+        assert!(should_stop(&policy(), &AutoStop::default(), &dead, NOW));
+
+        // Not long enough yet
+        let young = series(10, |_| 0.6, |_| Some(40));
+        assert!(!should_stop(&policy(), &AutoStop::default(), &young, NOW));
+
+        // A piece arrived a week ago
+        let progress = series(15, |_| 0.6, |ago| Some(if ago > 7 * 24 { 39 } else { 40 }));
+        assert!(!should_stop(&policy(), &AutoStop::default(), &progress, NOW));
+
+        // Paused for two days in between: not watched continuously
+        let mut gap = dead.clone();
+        gap.retain(|s| !(NOW - 6 * 24 * HOUR..NOW - 4 * 24 * HOUR).contains(&s.at));
+        assert!(!should_stop(&policy(), &AutoStop::default(), &gap, NOW));
+
+        // Older samples don't say whether anything was gained
+        let unknown = series(15, |_| 0.6, |_| None);
+        assert!(!should_stop(&policy(), &AutoStop::default(), &unknown, NOW));
+
+        // Already stopped
+        let stopped = AutoStop { stopped_at: Some(NOW - HOUR), ..AutoStop::default() };
+        assert!(!should_stop(&policy(), &stopped, &dead, NOW));
+    }
+
+    #[test]
+    fn test_recovery() {
+        // A complete copy showed up for a while three days ago
+        let recovered = series(15, |ago| if (72..80).contains(&ago) { 1.2 } else { 0.6 }, |_| Some(40));
+        assert!(!should_stop(&policy(), &AutoStop::default(), &recovered, NOW));
+
+        // Started again by hand five days ago: the stall has to build up anew
+        let dead = series(30, |_| 0.6, |_| Some(40));
+        let resumed = AutoStop { resumed_at: Some(NOW - 5 * 24 * HOUR), ..AutoStop::default() };
+        assert!(!should_stop(&policy(), &resumed, &dead, NOW));
+        let later = NOW + 10 * 24 * HOUR;
+        let dead_since = series(30, |_| 0.6, |_| Some(40))
+            .into_iter()
+            .map(|s| AvailabilitySample { at: s.at + 10 * 24 * HOUR, ..s })
+            .collect::<Vec<_>>();
+        assert!(should_stop(&policy(), &resumed, &dead_since, later));
+
+        // Scrapes of the stopped torrent
+        let scrape = |complete| ScrapeResponse { complete, incomplete: 4, downloaded: 10 };
+        assert_eq!(revived(&[]), None);
+        assert_eq!(revived(&[scrape(0), scrape(0)]), None);
+        assert_eq!(revived(&[scrape(0), scrape(2), scrape(1)]), Some(2));
+    }
+
+    #[test]
+    fn test_opt_out() {
+        let dead = series(20, |_| 0.0, |_| Some(0));
+        let exempt = AutoStop { exempt: true, ..AutoStop::default() };
+        assert!(!should_stop(&policy(), &exempt, &dead, NOW));
+
+        let disabled = Policy { enabled: false, ..policy() };
+        assert!(!should_stop(&disabled, &AutoStop::default(), &dead, NOW));
+        assert!(should_stop(&policy(), &AutoStop::default(), &dead, NOW));
+    }
+
+    #[test]
+    fn test_tags_follow_stop() {
+        assert!(tags(&AutoStop::default()).is_empty());
+        let stopped = AutoStop { stopped_at: Some(NOW), ..AutoStop::default() };
+        assert_eq!(tags(&stopped), [STALLED_DEAD_TAG]);
+    }
+}
@@ -3,11 +3,12 @@
 use crate::database::Database;
 use crate::debrid::{types::DownloadSource, DebridManager};
 use crate::engine::TorrentEngine;
+use crate::tracker::http::TrackerHttpConfig;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use tokio::sync::RwLock;
+use tokio::sync::{watch, RwLock};
 use tokio::task::JoinHandle;
 
 /// Global application state
@@ -15,12 +16,18 @@ pub struct AppState {
     /// Active torrent engines (by info_hash hex)
     pub engines: Arc<RwLock<HashMap<String, Arc<RwLock<TorrentEngine>>>>>,
 
+    /// Command handles of the engines in `engines`, usable while they run
+    pub engine_controls: Arc<RwLock<HashMap<String, crate::engine::EngineControl>>>,
+
     /// Running engine task handles to prevent double-spawning
     pub engine_tasks: Arc<RwLock<HashMap<String, JoinHandle<()>>>>,
 
     /// Active torrents metadata for quick UI access
     pub torrents: Arc<RwLock<HashMap<String, TorrentInfo>>>,
 
+    /// Names, trackers and dates of `torrents` for `query_torrents`
+    pub search_index: crate::search::SearchIndex,
+
     /// Application settings
     pub settings: Arc<RwLock<Settings>>,
 
@@ -34,11 +41,76 @@ pub struct AppState {
     /// This is used to decrypt API keys when needed
     pub master_password: Arc<RwLock<Option<String>>>,
 
-    /// Cloud download task handles (by info_hash)
-    pub cloud_download_tasks: Arc<RwLock<HashMap<String, JoinHandle<()>>>>,
+    /// Cloud downloads, running or not (by info_hash)
+    pub cloud_downloads: Arc<crate::cloud::CloudDownloadManager>,
+
+    /// Cloud file download progress, live or summarized once finished
+    pub cloud_file_progress: crate::cloud::CloudProgress,
+
+    /// Cloud downloads waiting for the user to pick files (by debrid torrent id)
+    pub cloud_file_selections: crate::cloud::FileSelectionWaiters,
+
+    /// Effective listen port; engines subscribe and re-announce when it changes
+    pub listen_port: watch::Sender<u16>,
+
+    /// Anonymous mode; engines read it on every announce and new handshake
+    pub anonymous_mode: watch::Sender<bool>,
+
+    /// Memory-mapped piece reads; engines pick up changes on their next read
+    pub mmap_reads: watch::Sender<bool>,
+
+    /// Creation date as mtime of completed files; applies to the next file
+    /// each engine completes
+    pub completion_mtimes: watch::Sender<bool>,
+
+    /// Tracker user agent and extra headers; engines rebuild their client on change
+    pub tracker_http: watch::Sender<TrackerHttpConfig>,
+
+    /// Whether paused seeding ends when leechers appear; read after each announce
+    pub reactivate_paused_seeding: watch::Sender<bool>,
+
+    /// Whether tracker warnings reach the frontend; read on each new warning
+    pub notify_tracker_warnings: watch::Sender<bool>,
+
+    /// Wakes the download queue coordinator
+    pub queue: crate::queue::QueueHandle,
+
+    /// Periodic background tasks, observable and restartable
+    pub background_tasks: crate::tasks::Supervisor,
+
+    /// Cleanup settings; the cleanup task is rebuilt when they change
+    pub cleanup_policy: watch::Sender<crate::cleanup::CleanupPolicy>,
+
+    /// Bandwidth schedule and default limits; the scheduler is rebuilt when
+    /// they change
+    pub bandwidth_schedule: watch::Sender<crate::scheduler::BandwidthSchedule>,
+
+    /// Database flush interval in seconds, for the flusher task
+    pub flush_interval: watch::Sender<u64>,
+
+    /// Paces outbound peer connections across all torrents
+    pub dial_pacer: Arc<crate::peer::DialPacer>,
+
+    /// Our public address as reported by trackers
+    pub external_ip: Arc<crate::tracker::external_ip::ExternalIp>,
+
+    /// Recent add-time scrapes, so the add dialog's torrent isn't scraped twice
+    pub swarm_health: crate::tracker::health::HealthCache,
+
+    /// Connection and file handle defaults derived from the open files limit
+    pub resources: crate::resources::ResourceBudget,
+
+    /// Torrents whose details view is open (see `details`)
+    pub detail_subscriptions: crate::details::DetailSubscriptions,
+
+    /// Per-torrent debug transfer logs (off unless enabled for a torrent)
+    pub transfer_logs: crate::transfer_log::TransferLogs,
 
-    /// Cloud file download progress (by info_hash -> file name -> progress)
-    pub cloud_file_progress: Arc<RwLock<HashMap<String, HashMap<String, CloudFileProgress>>>>,
+    /// Per-torrent history of why peer connections ended
+    pub disconnect_histories: crate::peer::disconnect::DisconnectHistories,
+
+    /// Set when startup replaced a damaged database with an empty one
+    pub database_recovery: Option<crate::database::DatabaseRecovery>,
 }
 
 /// Cloud file download progress
@@ -60,6 +132,27 @@ pub struct CloudFileProgress {
     pub state: CloudFileState,
 }
 
+/// What is kept of a cloud file once its torrent has finished
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CloudFileSummary {
+    pub name: String,
+    pub size: u64,
+    pub state: CloudFileState,
+}
+
+impl From<&CloudFileProgress> for CloudFileSummary {
+    fn from(progress: &CloudFileProgress) -> Self {
+        Self { name: progress.name.clone(), size: progress.size, state: progress.state }
+    }
+}
+
+impl From<CloudFileSummary> for CloudFileProgress {
+    fn from(summary: CloudFileSummary) -> Self {
+        let downloaded = if summary.state == CloudFileState::Complete { summary.size } else { 0 };
+        Self { name: summary.name, size: summary.size, downloaded, speed: 0, state: summary.state }
+    }
+}
+
 /// Cloud file download state
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum CloudFileState {
@@ -80,45 +173,135 @@ impl AppState {
     /// Create a new application state
     /// Returns Result to allow graceful error handling in main
     pub fn new() -> Result<Self, String> {
-        // Get config directory
-        let config_dir = dirs::config_dir()
-            .unwrap_or_else(|| PathBuf::from("."))
-            .join("seedcore");
+        Self::open(&config_dir())
+    }
 
+    /// Open the state kept under `config_dir` (the data dir its pointer
+    /// names, or the config dir itself), for embedders of the core that
+    /// keep their own directory instead of the app's
+    pub fn open(config_dir: &Path) -> Result<Self, String> {
         // Create config directory if it doesn't exist
-        if let Err(e) = std::fs::create_dir_all(&config_dir) {
+        if let Err(e) = std::fs::create_dir_all(config_dir) {
             tracing::warn!("Failed to create config directory: {}", e);
         }
 
-        // Open database
-        let db_path = config_dir.join("data.db");
-        let database = Database::open(&db_path).map_err(|e| {
+        // Open database, in a directory picked by migrate_data_dir if there is one
+        let data_dir = crate::data_dir::resolve(config_dir);
+        if !data_dir.is_dir() {
+            tracing::error!("Data directory {:?} is missing", data_dir);
+            return Err(format!(
+                "Data directory {} is missing (is the drive connected?)",
+                data_dir.display()
+            ));
+        }
+        crate::database::lock_instance(config_dir).map_err(|e| e.to_string())?;
+        let db_path = data_dir.join(crate::data_dir::DATABASE_DIR);
+        let now = chrono::Utc::now().timestamp();
+        let (database, recovery) = crate::database::open_or_recover(&db_path, now).map_err(|e| {
             tracing::error!("Failed to open database at {:?}: {}", db_path, e);
             format!("Cannot start without database: {}", e)
         })?;
 
         tracing::info!("Database opened at: {:?}", db_path);
 
+        let mut state = Self::with_database(database);
+        state.database_recovery = recovery;
+        Ok(state)
+    }
+
+    /// Build state around an already-open database (no config dir access)
+    pub fn with_database(database: Database) -> Self {
         // Load settings from database
         let settings = database.load_settings().unwrap_or_default();
+        let (listen_port, _) = watch::channel(settings.listen_port);
+        let (anonymous_mode, _) = watch::channel(settings.anonymous_mode);
+        let (mmap_reads, _) = watch::channel(settings.mmap_piece_reads);
+        let (completion_mtimes, _) = watch::channel(settings.file_mtime_from_creation_date);
+        let (reactivate_paused_seeding, _) = watch::channel(settings.reactivate_paused_seeding);
+        let (notify_tracker_warnings, _) = watch::channel(settings.notify_on_tracker_warning);
+        let (cleanup_policy, _) = watch::channel(crate::cleanup::CleanupPolicy::from(&settings));
+        let (bandwidth_schedule, _) = watch::channel(crate::scheduler::BandwidthSchedule::from(&settings));
+        let (flush_interval, _) = watch::channel(settings.db_flush_interval_secs);
+        let tracker_config = TrackerHttpConfig::new(
+            settings.tracker_user_agent.as_deref(),
+            &settings.tracker_extra_headers,
+        )
+        .and_then(|config| config.with_tls(settings.tracker_ca_file.as_deref(), &settings.tracker_insecure_hosts))
+        .unwrap_or_else(|e| {
+            tracing::warn!("Ignoring invalid tracker HTTP settings: {}", e);
+            TrackerHttpConfig::default()
+        });
+        let (tracker_http, _) = watch::channel(tracker_config);
+        let dial_pacer = crate::peer::DialPacer::new(settings.max_dials_per_sec, settings.max_half_open_connections);
+        let resources = crate::resources::ResourceBudget::detect();
+        dial_pacer.set_max_connections(resources.connection_cap(settings.max_connections));
+        tracing::info!(
+            "Open files limit {:?}: up to {} peer connections, {} file handles",
+            resources.fd_limit,
+            resources.connections,
+            resources.file_handles
+        );
 
         // Initialize debrid manager (providers will be loaded when master password is provided)
-        let debrid_manager = DebridManager::new();
-
-        Ok(Self {
+        let mut debrid_manager = DebridManager::new();
+        debrid_manager.set_enabled(settings.enable_debrid);
+        debrid_manager.set_base_urls(settings.debrid_base_urls.clone());
+
+        let database = Arc::new(database);
+        let cloud_file_progress = crate::cloud::CloudProgress::new(database.clone());
+        let cloud_file_selections: crate::cloud::FileSelectionWaiters = Arc::new(RwLock::new(HashMap::new()));
+        let torrents = Arc::new(RwLock::new(HashMap::new()));
+        let debrid_manager = Arc::new(RwLock::new(debrid_manager));
+        let cloud_downloads = Arc::new(crate::cloud::CloudDownloadManager::new(
+            debrid_manager.clone(),
+            Arc::clone(&torrents),
+            cloud_file_progress.clone(),
+            cloud_file_selections.clone(),
+        ));
+        Self {
             engines: Arc::new(RwLock::new(HashMap::new())),
+            engine_controls: Arc::new(RwLock::new(HashMap::new())),
             engine_tasks: Arc::new(RwLock::new(HashMap::new())),
-            torrents: Arc::new(RwLock::new(HashMap::new())),
+            torrents,
+            search_index: Default::default(),
             settings: Arc::new(RwLock::new(settings.into())),
-            database: Arc::new(database),
-            debrid_manager: Arc::new(RwLock::new(debrid_manager)),
+            database,
+            debrid_manager,
             master_password: Arc::new(RwLock::new(None)),
-            cloud_download_tasks: Arc::new(RwLock::new(HashMap::new())),
-            cloud_file_progress: Arc::new(RwLock::new(HashMap::new())),
-        })
+            cloud_downloads,
+            cloud_file_progress,
+            cloud_file_selections,
+            listen_port,
+            anonymous_mode,
+            mmap_reads,
+            completion_mtimes,
+            tracker_http,
+            reactivate_paused_seeding,
+            notify_tracker_warnings,
+            queue: Default::default(),
+            background_tasks: Default::default(),
+            cleanup_policy,
+            bandwidth_schedule,
+            flush_interval,
+            dial_pacer: Arc::new(dial_pacer),
+            external_ip: Default::default(),
+            swarm_health: Default::default(),
+            resources,
+            detail_subscriptions: Default::default(),
+            transfer_logs: Default::default(),
+            disconnect_histories: Default::default(),
+            database_recovery: None,
+        }
     }
 }
 
+/// SeedCore's directory under the platform config dir
+pub fn config_dir() -> PathBuf {
+    dirs::config_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join("seedcore")
+}
+
 impl Default for AppState {
     fn default() -> Self {
         Self::new().unwrap_or_else(|e| {
@@ -157,11 +340,50 @@ pub struct TorrentInfo {
     /// Number of connected peers
     pub peers: u32,
 
-    /// Number of seeds
+    /// Number of connected peers that have the whole torrent
     pub seeds: u32,
 
+    /// Seeds in the swarm as last reported by the trackers
+    #[serde(default)]
+    pub swarm_seeds: Option<u32>,
+
+    /// Leechers in the swarm as last reported by the trackers
+    #[serde(default)]
+    pub swarm_leechers: Option<u32>,
+
+    /// When the swarm numbers were reported (unix timestamp)
+    #[serde(default)]
+    pub swarm_updated_at: Option<i64>,
+
     /// Download source type (P2P, Cloud, or Hybrid)
     pub source: DownloadSource,
+
+    /// Whether the cloud copy was deleted from the debrid provider
+    #[serde(default)]
+    pub remote_deleted: bool,
+
+    /// Position in the download queue (None for seeding and cloud torrents).
+    /// Not carried by `torrent-update` events; read it from `get_torrents`.
+    #[serde(default)]
+    pub queue_position: Option<u32>,
+
+    /// Magnet whose metadata hasn't arrived: size, files and pieces are unknown
+    #[serde(default)]
+    pub metadata_pending: bool,
+
+    /// Set when the name wasn't UTF-8 and got transcoded: the encoding it was
+    /// decoded from, see `torrent::LegacyNames`
+    #[serde(default)]
+    pub name_encoding: Option<String>,
+    /// Labels the app put on the torrent, e.g. `stall::STALLED_DEAD_TAG`.
+    /// Not carried by `torrent-update` events; read them from `get_torrents`.
+    #[serde(default)]
+    pub tags: Vec<String>,
+
+    /// Why the torrent is in `TorrentState::Error`, e.g. the file its
+    /// allocation failed on
+    #[serde(default)]
+    pub error: Option<String>,
 }
 
 /// Torrent state
@@ -184,6 +406,16 @@ pub enum TorrentState {
 
     /// Queued
     Queued,
+
+    /// Download directory missing or on the wrong volume (e.g. unmounted drive)
+    MissingFiles,
+
+    /// Every tracker refused the torrent for good (deleted, banned, bad passkey)
+    Unregistered,
+
+    /// Complete and still announced to the trackers, but with no peer
+    /// connections and no uploads
+    PausedSeeding,
 }
 
 /// Application settings
@@ -195,6 +427,10 @@ pub struct Settings {
     /// Global upload speed limit (bytes/sec, 0 = unlimited)
     pub upload_limit: u64,
 
+    /// Count protocol overhead against the limits, not just payload
+    #[serde(default)]
+    pub count_overhead_in_rate_limits: bool,
+
     /// Maximum number of active downloads
     pub max_active_downloads: u32,
 
@@ -210,14 +446,146 @@ pub struct Settings {
     /// Enable PEX (Peer Exchange)
     pub enable_pex: bool,
 
+    /// Hide client identity in announces and handshakes
+    #[serde(default)]
+    pub anonymous_mode: bool,
+
+    /// Tracker User-Agent override (None = default)
+    #[serde(default)]
+    pub tracker_user_agent: Option<String>,
+
+    /// Extra headers for tracker requests
+    #[serde(default)]
+    pub tracker_extra_headers: Vec<crate::tracker::http::HttpHeader>,
+
+    /// Extra root CA file (PEM) for HTTPS trackers
+    #[serde(default)]
+    pub tracker_ca_file: Option<String>,
+
+    /// Tracker hosts whose certificates are not checked (dangerous)
+    #[serde(default)]
+    pub tracker_insecure_hosts: Vec<String>,
+
     /// Dark mode enabled
     pub dark_mode: bool,
 
+    /// Pause or remove torrents that seeded enough
+    #[serde(default)]
+    pub cleanup_enabled: bool,
+
+    /// Seeding ratio limit (0.0 = unlimited)
+    #[serde(default = "crate::cleanup::default_ratio")]
+    pub cleanup_ratio: f32,
+
+    /// Seeding time limit in seconds (0 = unlimited)
+    #[serde(default)]
+    pub cleanup_time: u64,
+
+    /// Cleanup action: "Pause", "Remove", "Delete"
+    #[serde(default = "crate::cleanup::default_mode")]
+    pub cleanup_mode: String,
+
     /// Bandwidth scheduler enabled
     pub bandwidth_scheduler_enabled: bool,
 
     /// Bandwidth schedule rules
     pub bandwidth_schedule: Vec<crate::database::BandwidthRule>,
+
+    /// ffmpeg binary used for video previews (None = previews off)
+    #[serde(default)]
+    pub ffmpeg_path: Option<String>,
+
+    /// Saved peers older than this are dropped on load (seconds)
+    #[serde(default)]
+    pub saved_peer_max_age_secs: u64,
+
+    /// New outbound peer connections per second, all torrents (0 = unlimited)
+    #[serde(default)]
+    pub max_dials_per_sec: u32,
+
+    /// Peer connections allowed to be connecting at once (0 = unlimited)
+    #[serde(default)]
+    pub max_half_open_connections: u32,
+
+    /// Peer connections across all torrents (0 = automatic)
+    #[serde(default)]
+    pub max_connections: u32,
+
+    /// Read complete files through memory maps when seeding
+    #[serde(default)]
+    pub mmap_piece_reads: bool,
+
+    /// Set completed files' mtime to the torrent's creation date
+    #[serde(default)]
+    pub file_mtime_from_creation_date: bool,
+
+    /// Scheduled database backups
+    #[serde(default)]
+    pub auto_backup_enabled: bool,
+
+    /// Hours between scheduled backups
+    #[serde(default = "crate::backup::default_interval_hours")]
+    pub auto_backup_interval_hours: u64,
+
+    /// Scheduled backup files kept
+    #[serde(default = "crate::backup::default_keep")]
+    pub auto_backup_keep: usize,
+
+    /// Backup directory (None = next to the database)
+    #[serde(default)]
+    pub auto_backup_dir: Option<String>,
+
+    /// Daily log files kept
+    #[serde(default = "crate::logs::default_keep_files")]
+    pub log_keep_files: usize,
+
+    /// Cap on the logs directory in MiB (0 = none)
+    #[serde(default)]
+    pub log_max_total_mb: u64,
+
+    /// Most verbose level written to the log files
+    #[serde(default)]
+    pub log_file_level: crate::logs::LogLevel,
+
+    /// Pause downloads that can't finish
+    #[serde(default)]
+    pub auto_stop_dead_enabled: bool,
+
+    /// Days without a complete copy in the swarm before that
+    #[serde(default = "crate::stall::default_dead_after_days")]
+    pub auto_stop_dead_days: u32,
+
+    /// Resume them once a seed shows up
+    #[serde(default)]
+    pub auto_resume_revived: bool,
+
+    /// Leave paused seeding when a tracker reports leechers
+    #[serde(default)]
+    pub reactivate_paused_seeding: bool,
+
+    /// Tell the frontend about new tracker warning messages
+    #[serde(default = "crate::tracker::default_notify_on_tracker_warning")]
+    pub notify_on_tracker_warning: bool,
+
+    /// Free space to keep on download filesystems, in MiB
+    #[serde(default = "crate::low_disk::default_reserve_mb")]
+    pub low_disk_reserve_mb: u64,
+
+    /// Resume downloads paused for low disk space once there is room
+    #[serde(default)]
+    pub low_disk_auto_resume: bool,
+
+    /// Prevent system sleep while torrents are downloading
+    #[serde(default)]
+    pub prevent_sleep: bool,
+
+    /// ... or seeding
+    #[serde(default)]
+    pub prevent_sleep_while_seeding: bool,
+
+    /// Longest a saved change waits to be flushed to disk, in seconds
+    #[serde(default = "crate::database::default_flush_interval_secs")]
+    pub db_flush_interval_secs: u64,
 }
 
 impl Default for Settings {
@@ -225,14 +593,48 @@ impl Default for Settings {
         Self {
             download_limit: 0,
             upload_limit: 0,
+            count_overhead_in_rate_limits: false,
             max_active_downloads: 3,
             max_active_uploads: 3,
             listen_port: 6881,
             enable_dht: true,
             enable_pex: true,
+            anonymous_mode: false,
+            tracker_user_agent: None,
+            tracker_extra_headers: Vec::new(),
+            tracker_ca_file: None,
+            tracker_insecure_hosts: Vec::new(),
             dark_mode: true,
+            cleanup_enabled: false,
+            cleanup_ratio: crate::cleanup::DEFAULT_RATIO,
+            cleanup_time: 0,
+            cleanup_mode: crate::cleanup::default_mode(),
             bandwidth_scheduler_enabled: false,
             bandwidth_schedule: Vec::new(),
+            ffmpeg_path: None,
+            saved_peer_max_age_secs: 2 * 60 * 60,
+            max_dials_per_sec: crate::peer::pacer::DEFAULT_DIALS_PER_SEC,
+            max_half_open_connections: crate::peer::pacer::DEFAULT_MAX_HALF_OPEN,
+            max_connections: 0,
+            mmap_piece_reads: false,
+            file_mtime_from_creation_date: false,
+            auto_backup_enabled: false,
+            auto_backup_interval_hours: crate::backup::DEFAULT_INTERVAL_HOURS,
+            auto_backup_keep: crate::backup::DEFAULT_KEEP,
+            auto_backup_dir: None,
+            log_keep_files: crate::logs::DEFAULT_KEEP_FILES,
+            log_max_total_mb: 0,
+            log_file_level: crate::logs::LogLevel::default(),
+            auto_stop_dead_enabled: false,
+            auto_stop_dead_days: crate::stall::DEFAULT_DEAD_AFTER_DAYS,
+            auto_resume_revived: false,
+            reactivate_paused_seeding: false,
+            notify_on_tracker_warning: true,
+            low_disk_reserve_mb: crate::low_disk::DEFAULT_RESERVE_MB,
+            low_disk_auto_resume: false,
+            prevent_sleep: false,
+            prevent_sleep_while_seeding: false,
+            db_flush_interval_secs: crate::database::DEFAULT_FLUSH_INTERVAL_SECS,
         }
     }
 }
@@ -243,14 +645,48 @@ impl From<crate::database::AppSettings> for Settings {
         Self {
             download_limit: db_settings.max_download_speed,
             upload_limit: db_settings.max_upload_speed,
+            count_overhead_in_rate_limits: db_settings.count_overhead_in_rate_limits,
             max_active_downloads: db_settings.max_concurrent_downloads as u32,
             max_active_uploads: 3, // Not stored in DB, use default
             listen_port: db_settings.listen_port,
             enable_dht: db_settings.enable_dht,
             enable_pex: db_settings.enable_pex,
+            anonymous_mode: db_settings.anonymous_mode,
+            tracker_user_agent: db_settings.tracker_user_agent,
+            tracker_extra_headers: db_settings.tracker_extra_headers,
+            tracker_ca_file: db_settings.tracker_ca_file,
+            tracker_insecure_hosts: db_settings.tracker_insecure_hosts,
             dark_mode: true, // Not stored in DB, use default
+            cleanup_enabled: db_settings.cleanup_enabled,
+            cleanup_ratio: db_settings.cleanup_ratio,
+            cleanup_time: db_settings.cleanup_time,
+            cleanup_mode: db_settings.cleanup_mode,
             bandwidth_scheduler_enabled: db_settings.bandwidth_scheduler_enabled,
             bandwidth_schedule: db_settings.bandwidth_schedule,
+            ffmpeg_path: db_settings.ffmpeg_path,
+            saved_peer_max_age_secs: db_settings.saved_peer_max_age_secs,
+            max_dials_per_sec: db_settings.max_dials_per_sec,
+            max_half_open_connections: db_settings.max_half_open_connections,
+            max_connections: db_settings.max_connections,
+            mmap_piece_reads: db_settings.mmap_piece_reads,
+            file_mtime_from_creation_date: db_settings.file_mtime_from_creation_date,
+            auto_backup_enabled: db_settings.auto_backup_enabled,
+            auto_backup_interval_hours: db_settings.auto_backup_interval_hours,
+            auto_backup_keep: db_settings.auto_backup_keep,
+            auto_backup_dir: db_settings.auto_backup_dir,
+            log_keep_files: db_settings.log_keep_files,
+            log_max_total_mb: db_settings.log_max_total_mb,
+            log_file_level: db_settings.log_file_level,
+            auto_stop_dead_enabled: db_settings.auto_stop_dead_enabled,
+            auto_stop_dead_days: db_settings.auto_stop_dead_days,
+            auto_resume_revived: db_settings.auto_resume_revived,
+            reactivate_paused_seeding: db_settings.reactivate_paused_seeding,
+            notify_on_tracker_warning: db_settings.notify_on_tracker_warning,
+            low_disk_reserve_mb: db_settings.low_disk_reserve_mb,
+            low_disk_auto_resume: db_settings.low_disk_auto_resume,
+            prevent_sleep: db_settings.prevent_sleep,
+            prevent_sleep_while_seeding: db_settings.prevent_sleep_while_seeding,
+            db_flush_interval_secs: db_settings.db_flush_interval_secs,
         }
     }
 }
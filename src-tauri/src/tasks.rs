@@ -0,0 +1,376 @@
+//! Supervised background tasks
+//!
+//! Periodic tasks register with the `Supervisor` in `AppState` rather than
+//! being spawned bare. Each one shows its last run, last result and next run
+//! in `get_background_tasks`, can be run at once with
+//! `run_background_task_now`, and follows its settings: they arrive on a
+//! watch channel, and a change builds a new job from them and runs it
+//! straight away. A run that panics is recorded, the job is rebuilt and the
+//! next run waits a doubling `Backoff` until one gets through.
+
+use futures::FutureExt;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use tokio::sync::{watch, Notify};
+use tokio::time::{self, Duration, Instant};
+
+pub type JobFuture = Pin<Box<dyn Future<Output = Result<(), String>> + Send>>;
+
+/// One run of a task's work
+pub type Job = Arc<dyn Fn() -> JobFuture + Send + Sync>;
+
+/// Wrap an async closure as a `Job`
+pub fn job<F, Fut>(f: F) -> Job
+where
+    F: Fn() -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<(), String>> + Send + 'static,
+{
+    Arc::new(move || Box::pin(f()))
+}
+
+/// How a run ended
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "status", content = "message", rename_all = "snake_case")]
+pub enum TaskOutcome {
+    Ok,
+    Failed(String),
+    Panicked(String),
+}
+
+/// A registered task, for `get_background_tasks`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TaskStatus {
+    pub name: String,
+    pub interval_secs: u64,
+    /// A run is in progress
+    pub running: bool,
+    /// When the last run started (unix timestamp)
+    pub last_run: Option<i64>,
+    pub last_duration_ms: Option<u64>,
+    pub last_result: Option<TaskOutcome>,
+    /// When the next run is due (unix timestamp)
+    pub next_run: Option<i64>,
+    pub runs: u64,
+    pub panics: u64,
+    /// Jobs built for changed settings
+    pub rebuilds: u64,
+}
+
+/// Wait before running again after consecutive panics
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Backoff {
+    pub initial: Duration,
+    pub max: Duration,
+}
+
+impl Backoff {
+    /// The wait after the `panics`-th panic in a row
+    pub fn after(&self, panics: u32) -> Duration {
+        let doublings = panics.saturating_sub(1).min(16);
+        (self.initial * 2u32.pow(doublings)).min(self.max)
+    }
+}
+
+/// Backoff of the app's supervisor
+pub const PANIC_BACKOFF: Backoff = Backoff { initial: Duration::from_secs(5), max: Duration::from_secs(600) };
+
+struct Task {
+    status: Mutex<TaskStatus>,
+    trigger: Notify,
+}
+
+/// Runs the registered tasks (see the module docs)
+#[derive(Clone)]
+pub struct Supervisor {
+    tasks: Arc<Mutex<BTreeMap<String, Arc<Task>>>>,
+    backoff: Backoff,
+}
+
+impl Default for Supervisor {
+    fn default() -> Self {
+        Self::with_backoff(PANIC_BACKOFF)
+    }
+}
+
+impl Supervisor {
+    pub fn with_backoff(backoff: Backoff) -> Self {
+        Self { tasks: Arc::default(), backoff }
+    }
+
+    /// Run `build(settings)` now and every `interval`, rebuilding it
+    /// whenever `config` changes. Fails if the name is taken.
+    pub fn register<C, B>(&self, name: &str, interval: Duration, config: watch::Receiver<C>, build: B) -> Result<(), String>
+    where
+        C: Clone + Send + Sync + 'static,
+        B: Fn(C) -> Job + Send + 'static,
+    {
+        let task = Arc::new(Task {
+            status: Mutex::new(TaskStatus {
+                name: name.to_string(),
+                interval_secs: interval.as_secs(),
+                running: false,
+                last_run: None,
+                last_duration_ms: None,
+                last_result: None,
+                next_run: None,
+                runs: 0,
+                panics: 0,
+                rebuilds: 0,
+            }),
+            trigger: Notify::new(),
+        });
+        {
+            let mut tasks = self.tasks.lock().unwrap();
+            if tasks.contains_key(name) {
+                return Err(format!("Background task {} is already registered", name));
+            }
+            tasks.insert(name.to_string(), task.clone());
+        }
+
+        tracing::info!("Starting background task {} (every {:?})", name, interval);
+        #[cfg(feature = "app")]
+        tauri::async_runtime::spawn(drive(task, interval, config, build, self.backoff));
+        #[cfg(not(feature = "app"))]
+        tokio::spawn(drive(task, interval, config, build, self.backoff));
+        Ok(())
+    }
+
+    /// Every registered task, by name
+    pub fn statuses(&self) -> Vec<TaskStatus> {
+        let tasks = self.tasks.lock().unwrap();
+        tasks.values().map(|task| task.status.lock().unwrap().clone()).collect()
+    }
+
+    /// Run a task now instead of waiting for its next run (after the
+    /// current run, if one is in progress)
+    pub fn run_now(&self, name: &str) -> Result<(), String> {
+        let tasks = self.tasks.lock().unwrap();
+        let task = tasks.get(name).ok_or_else(|| format!("No background task named {}", name))?;
+        task.trigger.notify_one();
+        Ok(())
+    }
+}
+
+/// Message of a caught panic
+fn panic_message(panic: &(dyn std::any::Any + Send)) -> String {
+    panic
+        .downcast_ref::<&str>()
+        .map(|message| message.to_string())
+        .or_else(|| panic.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "panicked".to_string())
+}
+
+async fn drive<C, B>(task: Arc<Task>, interval: Duration, mut config: watch::Receiver<C>, build: B, backoff: Backoff)
+where
+    C: Clone + Send + Sync + 'static,
+    B: Fn(C) -> Job + Send + 'static,
+{
+    let name = task.status.lock().unwrap().name.clone();
+    let mut job = build(config.borrow_and_update().clone());
+    let mut config_open = true;
+    let mut panics_in_a_row = 0u32;
+    let mut next = Instant::now();
+
+    loop {
+        tokio::select! {
+            _ = time::sleep_until(next) => {}
+            _ = task.trigger.notified() => {}
+            changed = config.changed(), if config_open => {
+                if changed.is_err() {
+                    // Settings can't change any more; keep the job as it is
+                    config_open = false;
+                    continue;
+                }
+                job = build(config.borrow_and_update().clone());
+                panics_in_a_row = 0;
+                task.status.lock().unwrap().rebuilds += 1;
+                tracing::info!("Background task {} rebuilt for new settings", name);
+            }
+        }
+
+        {
+            let mut status = task.status.lock().unwrap();
+            status.running = true;
+            status.last_run = Some(chrono::Utc::now().timestamp());
+        }
+        let started = Instant::now();
+        let run = job.clone();
+        let outcome = match AssertUnwindSafe(async move { run().await }).catch_unwind().await {
+            Ok(Ok(())) => TaskOutcome::Ok,
+            Ok(Err(e)) => TaskOutcome::Failed(e),
+            Err(panic) => TaskOutcome::Panicked(panic_message(panic.as_ref())),
+        };
+
+        let wait = match &outcome {
+            TaskOutcome::Panicked(message) => {
+                panics_in_a_row += 1;
+                let wait = backoff.after(panics_in_a_row);
+                tracing::error!("Background task {} panicked: {}; restarting in {:?}", name, message, wait);
+                job = build(config.borrow().clone());
+                wait
+            }
+            TaskOutcome::Failed(e) => {
+                tracing::warn!("Background task {} failed: {}", name, e);
+                panics_in_a_row = 0;
+                interval
+            }
+            TaskOutcome::Ok => {
+                panics_in_a_row = 0;
+                interval
+            }
+        };
+        next = Instant::now() + wait;
+
+        let mut status = task.status.lock().unwrap();
+        status.running = false;
+        status.last_duration_ms = Some(started.elapsed().as_millis() as u64);
+        status.runs += 1;
+        if matches!(outcome, TaskOutcome::Panicked(_)) {
+            status.panics += 1;
+        }
+        status.last_result = Some(outcome);
+        status.next_run = Some(chrono::Utc::now().timestamp() + wait.as_secs() as i64);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOUR: Duration = Duration::from_secs(3600);
+
+    /// Poll until `done` holds, for at most 5 seconds
+    async fn wait_for(mut done: impl FnMut() -> bool) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !done() {
+            assert!(Instant::now() < deadline, "timed out");
+            time::sleep(Duration::from_millis(5)).await;
+        }
+    }
+
+    fn status(supervisor: &Supervisor, name: &str) -> TaskStatus {
+        supervisor.statuses().into_iter().find(|status| status.name == name).unwrap()
+    }
+
+    /// A job counting its runs, failing if `fail`
+    fn counting(runs: Arc<Mutex<u32>>, fail: bool) -> Job {
+        job(move || {
+            let runs = runs.clone();
+            async move {
+                *runs.lock().unwrap() += 1;
+                if fail { Err("no database".to_string()) } else { Ok(()) }
+            }
+        })
+    }
+
+    #[tokio::test]
+    async fn test_registration() {
+        let supervisor = Supervisor::default();
+        let (_config, rx) = watch::channel(());
+        supervisor.register("scheduler", HOUR, rx.clone(), |_| counting(Arc::default(), false)).unwrap();
+        supervisor.register("cleanup", HOUR, rx.clone(), |_| counting(Arc::default(), true)).unwrap();
+        assert!(supervisor.register("cleanup", HOUR, rx, |_| counting(Arc::default(), false)).is_err());
+
+        // Both run once at startup, then wait for their interval
+        wait_for(|| supervisor.statuses().iter().all(|status| status.runs == 1)).await;
+        let statuses = supervisor.statuses();
+        assert_eq!(statuses.iter().map(|s| s.name.as_str()).collect::<Vec<_>>(), vec!["cleanup", "scheduler"]);
+        assert_eq!(statuses[0].last_result, Some(TaskOutcome::Failed("no database".to_string())));
+        assert_eq!(statuses[1].last_result, Some(TaskOutcome::Ok));
+        let now = chrono::Utc::now().timestamp();
+        for status in &statuses {
+            assert!(!status.running);
+            assert_eq!(status.interval_secs, 3600);
+            assert!(status.last_run.unwrap() <= now);
+            assert!((now + 3590..=now + 3600).contains(&status.next_run.unwrap()));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_run_now() {
+        let supervisor = Supervisor::default();
+        let runs = Arc::new(Mutex::new(0));
+        let (_config, rx) = watch::channel(());
+        let counted = runs.clone();
+        supervisor.register("cleanup", HOUR, rx, move |_| counting(counted.clone(), false)).unwrap();
+        wait_for(|| *runs.lock().unwrap() == 1).await;
+
+        supervisor.run_now("cleanup").unwrap();
+        wait_for(|| status(&supervisor, "cleanup").runs == 2).await;
+        assert_eq!(*runs.lock().unwrap(), 2);
+        assert!(supervisor.run_now("rss").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_settings_change_rebuilds() {
+        let supervisor = Supervisor::default();
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let (config, rx) = watch::channel(1u32);
+        let record = seen.clone();
+        supervisor
+            .register("scheduler", HOUR, rx, move |limit| {
+                let record = record.clone();
+                job(move || {
+                    record.lock().unwrap().push(limit);
+                    async { Ok(()) }
+                })
+            })
+            .unwrap();
+        wait_for(|| seen.lock().unwrap().len() == 1).await;
+
+        // The new settings take effect at once, not an hour later
+        config.send_replace(2);
+        wait_for(|| seen.lock().unwrap().len() == 2).await;
+        assert_eq!(*seen.lock().unwrap(), vec![1, 2]);
+        assert_eq!(status(&supervisor, "scheduler").rebuilds, 1);
+
+        // Later runs keep the new settings
+        supervisor.run_now("scheduler").unwrap();
+        wait_for(|| seen.lock().unwrap().len() == 3).await;
+        assert_eq!(*seen.lock().unwrap(), vec![1, 2, 2]);
+    }
+
+    #[tokio::test]
+    async fn test_panics_restart_with_backoff() {
+        let backoff = Backoff { initial: Duration::from_millis(20), max: Duration::from_millis(80) };
+        assert_eq!(
+            (1..=5).map(|n| backoff.after(n).as_millis()).collect::<Vec<_>>(),
+            vec![20, 40, 80, 80, 80]
+        );
+
+        // Each built job panics on its first run until four have
+        let supervisor = Supervisor::with_backoff(backoff);
+        let builds = Arc::new(Mutex::new(0u32));
+        let runs_at = Arc::new(Mutex::new(Vec::new()));
+        let (_config, rx) = watch::channel(());
+        let (counted, record) = (builds.clone(), runs_at.clone());
+        supervisor
+            .register("rss", HOUR, rx, move |_| {
+                *counted.lock().unwrap() += 1;
+                let built = *counted.lock().unwrap();
+                let record = record.clone();
+                job(move || {
+                    record.lock().unwrap().push(Instant::now());
+                    assert!(built > 4, "job {} broke", built);
+                    async { Ok(()) }
+                })
+            })
+            .unwrap();
+
+        wait_for(|| status(&supervisor, "rss").runs == 5).await;
+        let status = status(&supervisor, "rss");
+        assert_eq!(status.panics, 4);
+        assert_eq!(status.last_result, Some(TaskOutcome::Ok));
+        assert_eq!(*builds.lock().unwrap(), 5);
+
+        let runs_at = runs_at.lock().unwrap();
+        let gaps: Vec<Duration> = runs_at.windows(2).map(|pair| pair[1] - pair[0]).collect();
+        for (gap, expected) in gaps.iter().zip([20, 40, 80, 80]) {
+            assert!(*gap >= Duration::from_millis(expected), "{:?} < {}ms", gap, expected);
+        }
+    }
+}
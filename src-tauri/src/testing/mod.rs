@@ -0,0 +1,82 @@
+//! Test support: a loopback swarm for engine tests
+//!
+//! `MiniTracker` answers HTTP announces with a configurable peer list and
+//! records what it was told. `Seeder` and `Leecher` speak just enough of the
+//! wire protocol (no extensions) to stand on the other end of a real
+//! `TorrentEngine`. `TestTorrent` generates the content they share.
+//!
+//! Built with the `core` feature too, for examples and benchmarks that run
+//! the engine against a swarm of their own.
+
+pub mod peer;
+pub mod tracker;
+
+pub use peer::{Leecher, Seeder};
+pub use tracker::MiniTracker;
+
+use crate::torrent::Metainfo;
+use sha1::{Digest, Sha1};
+use std::sync::Arc;
+
+/// A generated single-file torrent and its content
+#[derive(Clone)]
+pub struct TestTorrent {
+    pub metainfo: Metainfo,
+    pub content: Arc<Vec<u8>>,
+    /// The .torrent file
+    pub torrent: Vec<u8>,
+}
+
+impl TestTorrent {
+    /// `num_pieces` pieces of `piece_length` bytes, the last one `short` bytes
+    /// shorter, announced to `announce`. The content comes from `seed`, so
+    /// every piece differs from the others.
+    pub fn generate(announce: &str, piece_length: usize, num_pieces: usize, short: usize, seed: u64) -> Self {
+        let total = piece_length * num_pieces - short;
+        let mut state = seed | 1;
+        let content: Vec<u8> = (0..total)
+            .map(|_| {
+                // xorshift64: cheap, and no two pieces come out alike
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect();
+        let hashes: Vec<u8> = content.chunks(piece_length).flat_map(|piece| Sha1::digest(piece).to_vec()).collect();
+
+        let name = "fixture.bin";
+        let mut torrent = format!("d8:announce{}:{}4:info", announce.len(), announce).into_bytes();
+        torrent.extend_from_slice(
+            format!(
+                "d6:lengthi{}e4:name{}:{}12:piece lengthi{}e6:pieces{}:",
+                total,
+                name.len(),
+                name,
+                piece_length,
+                hashes.len()
+            )
+            .as_bytes(),
+        );
+        torrent.extend_from_slice(&hashes);
+        torrent.extend_from_slice(b"ee");
+
+        let metainfo = Metainfo::from_bytes(&torrent).expect("generated torrent should parse");
+        Self { metainfo, content: Arc::new(content), torrent }
+    }
+
+    pub fn piece_length(&self) -> usize {
+        self.metainfo.info.piece_length as usize
+    }
+
+    pub fn num_pieces(&self) -> usize {
+        self.metainfo.info.piece_count
+    }
+
+    /// Bytes of piece `index`
+    pub fn piece(&self, index: usize) -> &[u8] {
+        let start = index * self.piece_length();
+        let end = (start + self.piece_length()).min(self.content.len());
+        &self.content[start..end]
+    }
+}
@@ -0,0 +1,247 @@
+//! Fixture peers for loopback swarms
+//!
+//! Both wait for the engine to dial them: it has no listener of its own.
+//! They speak the plain protocol, so the engine falls back to a bitfield and
+//! never sends fast extension messages.
+
+use super::TestTorrent;
+use crate::peer::handshake::Handshake;
+use crate::peer::message::Message;
+use crate::piece::Bitfield;
+use std::collections::{BTreeSet, HashSet, VecDeque};
+use std::io;
+use std::net::SocketAddr;
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+
+/// Size of the blocks a `Leecher` asks for
+const BLOCK_SIZE: usize = 16384;
+
+/// Requests a `Leecher` keeps outstanding
+const REQUEST_WINDOW: usize = 8;
+
+/// An accepted connection past the handshake
+struct Wire {
+    stream: TcpStream,
+}
+
+impl Wire {
+    /// Take the dialing side's handshake and answer it without extension bits
+    async fn accept(mut stream: TcpStream, info_hash: [u8; 20], peer_id: [u8; 20]) -> io::Result<Self> {
+        let mut buf = [0u8; 68];
+        stream.read_exact(&mut buf).await?;
+        let theirs = Handshake::from_bytes(&buf).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+        if theirs.info_hash != info_hash {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "handshake for another torrent"));
+        }
+
+        let mut ours = Handshake::new(info_hash, peer_id);
+        ours.reserved = [0u8; 8];
+        stream.write_all(&ours.to_bytes()).await?;
+        Ok(Self { stream })
+    }
+
+    async fn send(&mut self, message: &Message) -> io::Result<()> {
+        self.stream.write_all(&message.to_bytes()).await
+    }
+
+    async fn recv(&mut self) -> io::Result<Message> {
+        let length = self.stream.read_u32().await? as usize;
+        let mut payload = vec![0u8; length];
+        self.stream.read_exact(&mut payload).await?;
+        Message::from_bytes(&payload).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))
+    }
+}
+
+/// Peer holding `pieces` of a torrent in memory. It unchokes whoever is
+/// interested and serves their requests, remembering which pieces were asked for.
+pub struct Seeder {
+    addr: SocketAddr,
+    requested: Arc<Mutex<BTreeSet<usize>>>,
+    task: JoinHandle<()>,
+}
+
+impl Seeder {
+    pub async fn start(torrent: &TestTorrent, pieces: Range<usize>) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let requested = Arc::new(Mutex::new(BTreeSet::new()));
+
+        let (torrent, log) = (torrent.clone(), requested.clone());
+        let task = tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let (torrent, pieces, log) = (torrent.clone(), pieces.clone(), log.clone());
+                tokio::spawn(async move {
+                    if let Err(e) = Self::serve(stream, &torrent, pieces, &log).await {
+                        tracing::debug!("Fixture seeder connection ended: {}", e);
+                    }
+                });
+            }
+        });
+        Self { addr, requested, task }
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Pieces any connection requested blocks of
+    pub fn requested_pieces(&self) -> BTreeSet<usize> {
+        self.requested.lock().unwrap().clone()
+    }
+
+    async fn serve(
+        stream: TcpStream,
+        torrent: &TestTorrent,
+        pieces: Range<usize>,
+        requested: &Mutex<BTreeSet<usize>>,
+    ) -> io::Result<()> {
+        let mut wire = Wire::accept(stream, torrent.metainfo.info_hash, *b"-FX0001-seeder000000").await?;
+        let mut ours = Bitfield::new(torrent.num_pieces());
+        pieces.clone().for_each(|piece| ours.set_piece(piece));
+        wire.send(&Message::Bitfield { bitfield: ours.as_bytes().to_vec() }).await?;
+
+        loop {
+            match wire.recv().await? {
+                Message::Interested => wire.send(&Message::Unchoke).await?,
+                Message::Request { index, begin, length } => {
+                    let index = index as usize;
+                    requested.lock().unwrap().insert(index);
+                    let (begin, length) = (begin as usize, length as usize);
+                    if !pieces.contains(&index) || begin + length > torrent.piece(index).len() {
+                        continue;
+                    }
+                    let data = torrent.piece(index)[begin..begin + length].to_vec();
+                    wire.send(&Message::Piece { index: index as u32, begin: begin as u32, data }).await?;
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+impl Drop for Seeder {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Peer that starts with nothing and downloads the whole torrent from the
+/// first connection that offers it
+pub struct Leecher {
+    addr: SocketAddr,
+    content: watch::Receiver<Option<Vec<u8>>>,
+    task: JoinHandle<()>,
+}
+
+impl Leecher {
+    pub async fn start(torrent: &TestTorrent) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (content_tx, content) = watch::channel(None);
+
+        let torrent = torrent.clone();
+        let task = tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                match Self::download(stream, &torrent).await {
+                    Ok(data) => {
+                        let _ = content_tx.send(Some(data));
+                        return;
+                    }
+                    Err(e) => tracing::debug!("Fixture leecher connection ended: {}", e),
+                }
+            }
+        });
+        Self { addr, content, task }
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Everything the leecher downloaded, once it has every byte
+    pub async fn content(&self) -> Vec<u8> {
+        let mut content = self.content.clone();
+        loop {
+            if let Some(data) = content.borrow_and_update().clone() {
+                return data;
+            }
+            content.changed().await.expect("leecher stopped before finishing");
+        }
+    }
+
+    async fn download(stream: TcpStream, torrent: &TestTorrent) -> io::Result<Vec<u8>> {
+        let mut wire = Wire::accept(stream, torrent.metainfo.info_hash, *b"-FX0001-leecher00000").await?;
+        let num_pieces = torrent.num_pieces();
+        wire.send(&Message::Bitfield { bitfield: Bitfield::new(num_pieces).as_bytes().to_vec() }).await?;
+
+        let mut data = vec![0u8; torrent.content.len()];
+        let mut missing: HashSet<(u32, u32)> = HashSet::new();
+        for piece in 0..num_pieces {
+            for begin in (0..torrent.piece(piece).len()).step_by(BLOCK_SIZE) {
+                missing.insert((piece as u32, begin as u32));
+            }
+        }
+        let mut theirs = Bitfield::new(num_pieces);
+        let mut queue: VecDeque<(u32, u32)> = VecDeque::new();
+        let mut outstanding: HashSet<(u32, u32)> = HashSet::new();
+        let (mut interested, mut unchoked) = (false, false);
+
+        while !missing.is_empty() {
+            match wire.recv().await? {
+                Message::Bitfield { bitfield } => theirs = Bitfield::from_bytes(bitfield, num_pieces),
+                Message::Have { piece_index } => theirs.set_piece(piece_index as usize),
+                Message::Unchoke => unchoked = true,
+                Message::Choke => {
+                    // Requests die with the choke
+                    unchoked = false;
+                    outstanding.clear();
+                }
+                Message::Piece { index, begin, data: block }
+                    if outstanding.remove(&(index, begin)) && missing.remove(&(index, begin)) =>
+                {
+                    let offset = index as usize * torrent.piece_length() + begin as usize;
+                    data[offset..offset + block.len()].copy_from_slice(&block);
+                }
+                _ => {}
+            }
+
+            if !interested && theirs.count_pieces() > 0 {
+                wire.send(&Message::Interested).await?;
+                interested = true;
+            }
+            if !unchoked {
+                continue;
+            }
+            if queue.is_empty() {
+                let mut wanted: Vec<(u32, u32)> = missing
+                    .iter()
+                    .copied()
+                    .filter(|block| theirs.has_piece(block.0 as usize) && !outstanding.contains(block))
+                    .collect();
+                wanted.sort_unstable();
+                queue.extend(wanted);
+            }
+            while outstanding.len() < REQUEST_WINDOW {
+                let Some((index, begin)) = queue.pop_front() else { break };
+                if !missing.contains(&(index, begin)) || outstanding.contains(&(index, begin)) {
+                    continue;
+                }
+                let length = (torrent.piece(index as usize).len() - begin as usize).min(BLOCK_SIZE);
+                wire.send(&Message::Request { index, begin, length: length as u32 }).await?;
+                outstanding.insert((index, begin));
+            }
+        }
+        Ok(data)
+    }
+}
+
+impl Drop for Leecher {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
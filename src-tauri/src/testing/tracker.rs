@@ -0,0 +1,166 @@
+//! In-process HTTP tracker for loopback swarms
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+
+/// One announce as the tracker received it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Announce {
+    pub info_hash: Vec<u8>,
+    pub peer_id: Vec<u8>,
+    pub port: u16,
+    pub left: u64,
+    pub event: Option<String>,
+}
+
+/// What the tracker answers with, changeable while it runs
+#[derive(Default)]
+struct Replies {
+    peers: Vec<SocketAddr>,
+    /// Seeds and leechers reported by scrapes
+    swarm: (u32, u32),
+    /// Wait before answering anything
+    delay: Duration,
+}
+
+/// Tracker answering every announce with the current peer list (compact,
+/// IPv4 only), and scrapes with the configured swarm. Stops serving when
+/// dropped.
+pub struct MiniTracker {
+    url: String,
+    replies: Arc<Mutex<Replies>>,
+    announces: Arc<Mutex<Vec<Announce>>>,
+    task: JoinHandle<()>,
+}
+
+impl MiniTracker {
+    pub async fn start(peers: Vec<SocketAddr>) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/announce", listener.local_addr().unwrap());
+        let replies = Arc::new(Mutex::new(Replies { peers, ..Default::default() }));
+        let announces = Arc::new(Mutex::new(Vec::new()));
+
+        let (answers, log) = (replies.clone(), announces.clone());
+        let task = tokio::spawn(async move {
+            while let Ok((socket, _)) = listener.accept().await {
+                tokio::spawn(serve(socket, answers.clone(), log.clone()));
+            }
+        });
+        Self { url, replies, announces, task }
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Peers handed out from the next announce on
+    pub fn set_peers(&self, peers: Vec<SocketAddr>) {
+        self.replies.lock().unwrap().peers = peers;
+    }
+
+    /// Seeds and leechers scrapes report from now on
+    pub fn set_swarm(&self, seeds: u32, leechers: u32) {
+        self.replies.lock().unwrap().swarm = (seeds, leechers);
+    }
+
+    /// Hold every answer for `delay`, like an overloaded tracker
+    pub fn set_delay(&self, delay: Duration) {
+        self.replies.lock().unwrap().delay = delay;
+    }
+
+    /// Every announce so far, oldest first
+    pub fn announces(&self) -> Vec<Announce> {
+        self.announces.lock().unwrap().clone()
+    }
+}
+
+impl Drop for MiniTracker {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Answer one HTTP request on `socket`
+async fn serve(mut socket: TcpStream, replies: Arc<Mutex<Replies>>, announces: Arc<Mutex<Vec<Announce>>>) {
+    let mut request = Vec::new();
+    let mut buf = [0u8; 1024];
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+        match socket.read(&mut buf).await {
+            Ok(0) | Err(_) => return,
+            Ok(n) => request.extend_from_slice(&buf[..n]),
+        }
+    }
+
+    let delay = replies.lock().unwrap().delay;
+    tokio::time::sleep(delay).await;
+
+    let target = request
+        .split(|&b| b == b'\r')
+        .next()
+        .and_then(|line| std::str::from_utf8(line).ok())
+        .and_then(|line| line.split(' ').nth(1))
+        .unwrap_or_default();
+    let body = if let Some(announce) = target.strip_prefix("/announce?").and_then(parse_announce) {
+        announces.lock().unwrap().push(announce);
+        let peers = replies.lock().unwrap().peers.clone();
+        let mut body = format!("d8:intervali1800e5:peers{}:", peers.len() * 6).into_bytes();
+        for peer in &peers {
+            let SocketAddr::V4(v4) = peer else { panic!("MiniTracker only hands out IPv4 peers") };
+            body.extend_from_slice(&v4.ip().octets());
+            body.extend_from_slice(&v4.port().to_be_bytes());
+        }
+        body.push(b'e');
+        body
+    } else if let Some(info_hash) = target.strip_prefix("/scrape?info_hash=") {
+        let (seeds, leechers) = replies.lock().unwrap().swarm;
+        let mut body = b"d5:filesd20:".to_vec();
+        body.extend_from_slice(&urlencoding::decode_binary(info_hash.as_bytes()));
+        body.extend_from_slice(format!("d8:completei{}e10:downloadedi0e10:incompletei{}eeee", seeds, leechers).as_bytes());
+        body
+    } else {
+        let _ = socket.write_all(b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n").await;
+        return;
+    };
+    let head = format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", body.len());
+    let _ = socket.write_all(head.as_bytes()).await;
+    let _ = socket.write_all(&body).await;
+}
+
+/// The announce in a query string, if it has the required fields
+fn parse_announce(query: &str) -> Option<Announce> {
+    let params: HashMap<&str, Vec<u8>> = query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .map(|(key, value)| (key, urlencoding::decode_binary(value.as_bytes()).into_owned()))
+        .collect();
+    let text = |key: &str| params.get(key).and_then(|v| String::from_utf8(v.clone()).ok());
+
+    Some(Announce {
+        info_hash: params.get("info_hash")?.clone(),
+        peer_id: params.get("peer_id")?.clone(),
+        port: text("port")?.parse().ok()?,
+        left: text("left")?.parse().ok()?,
+        event: text("event"),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_announce() {
+        let announce = parse_announce("info_hash=%01%02ab&peer_id=-SC0100-x&port=6881&left=42&compact=1&event=started").unwrap();
+        assert_eq!(announce.info_hash, vec![1, 2, b'a', b'b']);
+        assert_eq!(announce.peer_id, b"-SC0100-x".to_vec());
+        assert_eq!((announce.port, announce.left), (6881, 42));
+        assert_eq!(announce.event.as_deref(), Some("started"));
+
+        assert!(parse_announce("info_hash=%01&port=6881&left=0").is_none());
+    }
+}
@@ -3,6 +3,10 @@
 //! Parses .torrent files according to the BitTorrent specification.
 //! Reference: http://bittorrent.org/beps/bep_0003.html
 
+mod names;
+
+pub use names::LegacyNames;
+
 use crate::bencode::BencodeValue;
 use crate::error::{Error, Result};
 use serde::{Deserialize, Serialize};
@@ -56,6 +60,15 @@ pub struct TorrentInfo {
 
     /// Whether this is a single-file torrent
     pub is_single_file: bool,
+
+    /// BEP 27 private flag: peers may only come from the torrent's own trackers
+    #[serde(default)]
+    pub private: bool,
+
+    /// Original bytes of names that weren't UTF-8 and had to be transcoded
+    /// (see `names`); None for ordinary torrents
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub legacy_names: Option<LegacyNames>,
 }
 
 /// File information
@@ -94,6 +107,19 @@ pub struct FileInfoUI {
     pub priority: FilePriority,
     /// Whether this is a folder entry
     pub is_folder: bool,
+    /// Size of the file on disk, None if it doesn't exist (yet)
+    #[serde(default)]
+    pub on_disk_size: Option<u64>,
+    /// Modification time of the file on disk (unix timestamp)
+    #[serde(default)]
+    pub modified: Option<i64>,
+    /// Only this many bytes from the start are downloaded (see
+    /// `download_file_prefix`); setting the file's priority clears it
+    #[serde(default)]
+    pub prefix_only: Option<u64>,
+    /// When every piece of the file was verified (unix timestamp)
+    #[serde(default)]
+    pub completed_at: Option<i64>,
 }
 
 impl Metainfo {
@@ -128,16 +154,18 @@ impl Metainfo {
             })
             .unwrap_or_default();
 
-        // Get info dictionary
-        let info_value = dict
-            .get(b"info" as &[u8])
+        // Get info dictionary. It is parsed strictly from the exact bytes that
+        // get hashed, so the hash and the parsed contents can't disagree.
+        let info_bytes = crate::bencode::raw_dict_value(data, b"info")?
             .ok_or_else(|| Error::MetainfoError("missing info field".to_string()))?;
+        let info_value = BencodeValue::parse_strict(info_bytes)?;
 
         // Calculate info hash
         let info_hash = Self::calculate_info_hash(data)?;
 
-        // Parse info dictionary
-        let info = TorrentInfo::parse(info_value)?;
+        // Parse info dictionary; legacy torrents may say how their names are encoded
+        let encoding = dict.get(b"encoding" as &[u8]).and_then(|v| v.as_bytes());
+        let info = TorrentInfo::parse(&info_value, encoding)?;
 
         // Get optional fields
         let creation_date = dict
@@ -167,25 +195,12 @@ impl Metainfo {
 
     /// Calculate the info hash from raw .torrent data
     fn calculate_info_hash(data: &[u8]) -> Result<[u8; 20]> {
-        // Find the info dictionary in the bencode data
-        // This is a simplified approach - we need to hash the exact bytes
-        let root = BencodeValue::parse(data)?;
-        let dict = root
-            .as_dict()
-            .ok_or_else(|| Error::MetainfoError("root must be a dictionary".to_string()))?;
-
-        // For now, we'll return a placeholder
-        // TODO: Implement proper info dictionary extraction and hashing
-        let info_value = dict
-            .get(b"info" as &[u8])
+        // The info hash is the SHA-1 of the info dictionary's exact source bytes
+        let info_bytes = crate::bencode::raw_dict_value(data, b"info")?
             .ok_or_else(|| Error::MetainfoError("missing info field".to_string()))?;
 
-        // Hash the info dictionary
-        // Note: This is a simplified version - in production, we need to hash
-        // the exact bytes of the info dictionary from the original data
-        let info_bytes = format!("{:?}", info_value).into_bytes();
         let mut hasher = Sha1::new();
-        hasher.update(&info_bytes);
+        hasher.update(info_bytes);
         let result = hasher.finalize();
 
         let mut hash = [0u8; 20];
@@ -211,8 +226,9 @@ impl Metainfo {
 }
 
 impl TorrentInfo {
-    /// Parse the info dictionary
-    fn parse(value: &BencodeValue) -> Result<Self> {
+    /// Parse the info dictionary. `encoding` is the torrent's `encoding` key,
+    /// a hint for names that aren't UTF-8.
+    fn parse(value: &BencodeValue, encoding: Option<&[u8]>) -> Result<Self> {
         let dict = value
             .as_dict()
             .ok_or_else(|| Error::MetainfoError("info must be a dictionary".to_string()))?;
@@ -240,12 +256,12 @@ impl TorrentInfo {
 
         let piece_count = pieces.len() / 20;
 
-        // Get name
-        let name = dict
+        // Get name (raw for now, decoded once all names are known)
+        let raw_name = dict
             .get(b"name" as &[u8])
-            .and_then(|v| v.as_str())
-            .ok_or_else(|| Error::MetainfoError("missing name".to_string()))?
-            .to_string();
+            .and_then(|v| v.as_bytes())
+            .ok_or_else(|| Error::MetainfoError("missing name".to_string()))?;
+        let name_utf8 = dict.get(b"name.utf-8" as &[u8]).and_then(|v| v.as_bytes());
 
         // Check if single-file or multi-file torrent
         let (files, total_size, is_single_file) = if let Some(length) = dict.get(b"length" as &[u8])
@@ -256,12 +272,7 @@ impl TorrentInfo {
                 .ok_or_else(|| Error::MetainfoError("invalid length".to_string()))?
                 as u64;
 
-            let file = FileInfo {
-                path: vec![name.clone()],
-                length,
-            };
-
-            (vec![file], length, true)
+            (vec![(Vec::new(), None, length)], length, true)
         } else if let Some(files_value) = dict.get(b"files" as &[u8]) {
             // Multi-file torrent
             let files_list = files_value
@@ -287,17 +298,24 @@ impl TorrentInfo {
                     .and_then(|v| v.as_list())
                     .ok_or_else(|| Error::MetainfoError("file missing path".to_string()))?;
 
-                let path: Vec<String> = path_list
+                let path: Vec<&[u8]> = path_list
                     .iter()
-                    .filter_map(|v| v.as_str().map(|s| s.to_string()))
-                    .collect();
+                    .map(|v| v.as_bytes().ok_or_else(|| Error::MetainfoError("file path must be strings".to_string())))
+                    .collect::<Result<_>>()?;
 
                 if path.is_empty() {
                     return Err(Error::MetainfoError("empty file path".to_string()));
                 }
 
+                // The UTF-8 twin only helps if it lines up with the raw path
+                let path_utf8: Option<Vec<&[u8]>> = file_dict
+                    .get(b"path.utf-8" as &[u8])
+                    .and_then(|v| v.as_list())
+                    .and_then(|list| list.iter().map(|v| v.as_bytes()).collect::<Option<_>>())
+                    .filter(|utf8: &Vec<&[u8]>| utf8.len() == path.len());
+
                 total += length;
-                files.push(FileInfo { path, length });
+                files.push((path, path_utf8, length));
             }
 
             (files, total, false)
@@ -307,6 +325,39 @@ impl TorrentInfo {
             ));
         };
 
+        let private = dict.get(b"private" as &[u8]).and_then(|v| v.as_integer()) == Some(1);
+
+        let raw_components = std::iter::once(raw_name).chain(files.iter().flat_map(|(path, _, _)| path.iter().copied()));
+        let mut decoder = names::NameDecoder::new(raw_components, encoding);
+        let name = decoder.decode(raw_name, name_utf8);
+        let mut paths: Vec<Vec<String>> = files
+            .iter()
+            .map(|(path, path_utf8, _)| {
+                path.iter()
+                    .enumerate()
+                    .map(|(i, raw)| decoder.decode(raw, path_utf8.as_ref().map(|utf8| utf8[i])))
+                    .collect()
+            })
+            .collect();
+
+        let legacy_names = decoder.encoding().map(|encoding| {
+            // Distinct raw names may have decoded to the same text
+            names::uniquify(&mut paths);
+            LegacyNames {
+                encoding,
+                name: raw_name.to_vec(),
+                paths: files.iter().map(|(path, _, _)| path.iter().map(|raw| raw.to_vec()).collect()).collect(),
+            }
+        });
+        let files = files
+            .iter()
+            .zip(paths)
+            .map(|((_, _, length), path)| FileInfo {
+                path: if is_single_file { vec![name.clone()] } else { path },
+                length: *length,
+            })
+            .collect();
+
         Ok(Self {
             piece_length,
             pieces,
@@ -315,9 +366,16 @@ impl TorrentInfo {
             name,
             total_size,
             is_single_file,
+            private,
+            legacy_names,
         })
     }
 
+    /// What the names were transcoded from, None if they were UTF-8
+    pub fn name_encoding(&self) -> Option<String> {
+        self.legacy_names.as_ref().map(|names| names.encoding.clone())
+    }
+
     /// Get the SHA1 hash for a specific piece
     pub fn piece_hash(&self, index: usize) -> Option<&[u8]> {
         if index >= self.piece_count {
@@ -367,6 +425,8 @@ impl Metainfo {
             name: display_name.unwrap_or_else(|| hex::encode(&info_hash[..8])),
             total_size: 0,        // Unknown until metadata
             is_single_file: true, // Assume single file for now
+            private: false,
+            legacy_names: None,
         };
 
         Metainfo {
@@ -379,6 +439,30 @@ impl Metainfo {
             created_by: Some("SeedCore".to_string()),
         }
     }
+
+    /// False for a magnet stub whose info dictionary hasn't been fetched yet
+    pub fn has_metadata(&self) -> bool {
+        self.info.piece_count > 0
+    }
+}
+
+/// What an info command returns for a torrent that may still be a magnet
+/// stub: its piece and file layout doesn't exist until the metadata arrives
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "metadata", content = "data", rename_all = "camelCase")]
+pub enum MetadataResult<T> {
+    /// Waiting for the metadata (the `metadata-ready` event follows)
+    Pending,
+    Ready(T),
+}
+
+impl<T> MetadataResult<T> {
+    pub fn ready(self) -> Option<T> {
+        match self {
+            Self::Ready(value) => Some(value),
+            Self::Pending => None,
+        }
+    }
 }
 
 /// Get file list with UI metadata for a torrent
@@ -396,6 +480,10 @@ pub fn get_file_list(metainfo: &Metainfo, downloaded_bytes: Option<&[u64]>) -> V
             downloaded,
             priority: FilePriority::Normal, // TODO: Store and retrieve actual priority
             is_folder: false,
+            on_disk_size: None,
+            modified: None,
+            prefix_only: None,
+            completed_at: None,
         });
     }
 
@@ -432,6 +520,32 @@ mod tests {
         assert_eq!(metainfo.info.files.len(), 1);
     }
 
+    #[test]
+    fn test_info_dict_must_be_canonical() {
+        // "name" before "length": out of order inside info
+        let data = b"d8:announce14:http://tracker4:infod4:name1:a6:lengthi1e12:piece_lengthi16384e6:pieces20:12345678901234567890ee";
+        let err = Metainfo::from_bytes(data).unwrap_err().to_string();
+        assert!(err.contains("length after name"), "{}", err);
+
+        // Sloppy ordering outside info is still accepted
+        let data = b"d4:infod6:lengthi1e4:name1:a12:piece_lengthi16384e6:pieces20:12345678901234567890e8:announce14:http://trackere";
+        assert!(Metainfo::from_bytes(data).is_ok());
+    }
+
+    #[test]
+    fn test_info_hash_is_sha1_of_raw_info() {
+        let info = b"d6:lengthi1234e4:name4:test12:piece_lengthi16384e6:pieces20:12345678901234567890e";
+        let mut data = b"d8:announce14:http://tracker4:info".to_vec();
+        data.extend_from_slice(info);
+        data.push(b'e');
+
+        let expected: [u8; 20] = Sha1::digest(info).into();
+
+        // Stable across parses, so the same file always maps to the same torrent id
+        assert_eq!(Metainfo::from_bytes(&data).unwrap().info_hash, expected);
+        assert_eq!(Metainfo::from_bytes(&data).unwrap().info_hash, expected);
+    }
+
     #[test]
     fn test_piece_hash_extraction() {
         // Format with 2 pieces (40 bytes of hash data)
@@ -459,4 +573,142 @@ mod tests {
 
         assert!(metainfo.info.piece_hash(2).is_none());
     }
+    /// Bencode a byte string
+    fn bstr(out: &mut Vec<u8>, bytes: &[u8]) {
+        out.extend_from_slice(format!("{}:", bytes.len()).as_bytes());
+        out.extend_from_slice(bytes);
+    }
+
+    /// A multi-file torrent with raw `name`/`path`s and, when given, their
+    /// `.utf-8` twins; returns the torrent and its info dictionary
+    fn legacy_torrent(
+        encoding: Option<&[u8]>,
+        name: &[u8],
+        name_utf8: Option<&[u8]>,
+        files: &[(&[&[u8]], Option<&[&[u8]]>)],
+    ) -> (Vec<u8>, Vec<u8>) {
+        let mut info = b"d5:filesl".to_vec();
+        for (path, path_utf8) in files {
+            info.extend_from_slice(b"d6:lengthi100e4:pathl");
+            path.iter().for_each(|component| bstr(&mut info, component));
+            info.push(b'e');
+            if let Some(path_utf8) = path_utf8 {
+                info.extend_from_slice(b"10:path.utf-8l");
+                path_utf8.iter().for_each(|component| bstr(&mut info, component));
+                info.push(b'e');
+            }
+            info.push(b'e');
+        }
+        info.extend_from_slice(b"e4:name");
+        bstr(&mut info, name);
+        if let Some(name_utf8) = name_utf8 {
+            info.extend_from_slice(b"10:name.utf-8");
+            bstr(&mut info, name_utf8);
+        }
+        info.extend_from_slice(b"12:piece_lengthi16384e6:pieces20:12345678901234567890e");
+
+        let mut data = b"d8:announce14:http://tracker".to_vec();
+        if let Some(encoding) = encoding {
+            data.extend_from_slice(b"8:encoding");
+            bstr(&mut data, encoding);
+        }
+        data.extend_from_slice(b"4:info");
+        data.extend_from_slice(&info);
+        data.push(b'e');
+        (data, info)
+    }
+
+    fn paths(metainfo: &Metainfo) -> Vec<String> {
+        metainfo.info.files.iter().map(|file| file.path.join("/")).collect()
+    }
+
+    // "アニメ", "字幕", "第1話.mkv" and "第1話.ass" in Shift-JIS
+    const SJIS_ANIME: &[u8] = b"\x83\x41\x83\x6a\x83\x81";
+    const SJIS_SUBTITLES: &[u8] = b"\x8e\x9a\x96\x8b";
+    const SJIS_EPISODE_MKV: &[u8] = b"\x91\xe6\x31\x98\x62\x2e\x6d\x6b\x76";
+    const SJIS_EPISODE_ASS: &[u8] = b"\x91\xe6\x31\x98\x62\x2e\x61\x73\x73";
+
+    #[cfg(feature = "legacy-encodings")]
+    #[test]
+    fn test_shift_jis_names() {
+        let (data, info) = legacy_torrent(
+            None,
+            SJIS_ANIME,
+            None,
+            &[(&[SJIS_EPISODE_MKV], None), (&[SJIS_SUBTITLES, SJIS_EPISODE_ASS], None)],
+        );
+        let metainfo = Metainfo::from_bytes(&data).unwrap();
+
+        assert_eq!(metainfo.info.name, "アニメ");
+        assert_eq!(paths(&metainfo), vec!["第1話.mkv", "字幕/第1話.ass"]);
+
+        // The raw bytes are kept, and still what the info hash covers
+        let legacy = metainfo.info.legacy_names.as_ref().unwrap();
+        assert_eq!(legacy.encoding, "Shift_JIS");
+        assert_eq!(legacy.name, SJIS_ANIME);
+        assert_eq!(legacy.paths[1], vec![SJIS_SUBTITLES.to_vec(), SJIS_EPISODE_ASS.to_vec()]);
+        let expected: [u8; 20] = Sha1::digest(&info).into();
+        assert_eq!(metainfo.info_hash, expected);
+    }
+
+    #[cfg(feature = "legacy-encodings")]
+    #[test]
+    fn test_cp1251_names() {
+        // "Фильмы", "Документы", "отчёт.doc" and "Музыка.mp3" in CP1251
+        let name: &[u8] = b"\xd4\xe8\xeb\xfc\xec\xfb";
+        let documents: &[u8] = b"\xc4\xee\xea\xf3\xec\xe5\xed\xf2\xfb";
+        let report: &[u8] = b"\xee\xf2\xf7\xb8\xf2\x2e\x64\x6f\x63";
+        let music: &[u8] = b"\xcc\xf3\xe7\xfb\xea\xe0\x2e\x6d\x70\x33";
+
+        let (data, _) = legacy_torrent(None, name, None, &[(&[documents, report], None), (&[music], None)]);
+        let metainfo = Metainfo::from_bytes(&data).unwrap();
+        assert_eq!(metainfo.info.name, "Фильмы");
+        assert_eq!(paths(&metainfo), vec!["Документы/отчёт.doc", "Музыка.mp3"]);
+        assert_eq!(metainfo.info.legacy_names.unwrap().encoding, "windows-1251");
+
+        // Same answer when the torrent names its encoding
+        let (data, _) = legacy_torrent(Some(b"CP1251"), name, None, &[(&[music], None)]);
+        assert_eq!(Metainfo::from_bytes(&data).unwrap().info.files[0].path, vec!["Музыка.mp3"]);
+    }
+
+    #[test]
+    fn test_utf8_keys_win_over_raw_names() {
+        let (data, _) = legacy_torrent(
+            Some(b"GBK"),
+            SJIS_ANIME,
+            Some("アニメ".as_bytes()),
+            &[
+                (&[SJIS_EPISODE_MKV], Some(&["第1話.mkv".as_bytes()])),
+                // A twin that doesn't line up with the raw path is ignored
+                (&[SJIS_SUBTITLES, SJIS_EPISODE_ASS], Some(&["第1話.ass".as_bytes()])),
+                (&[b"readme.txt" as &[u8]], None),
+            ],
+        );
+        let metainfo = Metainfo::from_bytes(&data).unwrap();
+        assert_eq!(metainfo.info.name, "アニメ");
+        assert_eq!(paths(&metainfo)[0], "第1話.mkv");
+        assert_eq!(paths(&metainfo)[2], "readme.txt");
+        assert!(metainfo.info.legacy_names.is_some());
+    }
+
+    #[test]
+    fn test_transcoded_collisions_are_uniquified() {
+        // Different raw names whose .utf-8 twins are the same
+        let (data, _) = legacy_torrent(
+            None,
+            SJIS_ANIME,
+            Some("アニメ".as_bytes()),
+            &[
+                (&[SJIS_EPISODE_MKV], Some(&["第1話.mkv".as_bytes()])),
+                (&[b"\x91\xe6\x31\x98\x62\x2e\x6d\x6b\x76\x81" as &[u8]], Some(&["第1話.mkv".as_bytes()])),
+            ],
+        );
+        let metainfo = Metainfo::from_bytes(&data).unwrap();
+        assert_eq!(paths(&metainfo), vec!["第1話.mkv", "第1話 (2).mkv"]);
+        assert_eq!(metainfo.info.legacy_names.unwrap().encoding, names::UTF8_KEYS);
+
+        // All-UTF-8 torrents are left alone
+        let (data, _) = legacy_torrent(None, b"plain", None, &[(&[b"a.txt" as &[u8]], None)]);
+        assert!(Metainfo::from_bytes(&data).unwrap().info.legacy_names.is_none());
+    }
 }
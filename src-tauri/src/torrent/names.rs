@@ -0,0 +1,274 @@
+//! File names of torrents not made in UTF-8
+//!
+//! BEP 3 names are UTF-8, but old torrents from some regions carry them in
+//! the creator's local code page (Shift-JIS, GBK, CP1251, ...). Each name or
+//! path component is taken, in order of preference:
+//! 1. as is, when it is valid UTF-8
+//! 2. from the `name.utf-8`/`path.utf-8` twin many such torrents include
+//! 3. decoded from the torrent's legacy encoding: its `encoding` key when that
+//!    fits, else the most plausible of a few common code pages (needs the
+//!    `legacy-encodings` feature)
+//! 4. as UTF-8 with invalid bytes replaced
+//!
+//! The info hash is computed over the original bytes, which stay in the
+//! stashed .torrent file and, for transcoded names, in `LegacyNames`.
+
+#[cfg(feature = "legacy-encodings")]
+use encoding_rs::{Encoding, GBK, SHIFT_JIS, WINDOWS_1251, WINDOWS_1252};
+use serde::{Deserialize, Serialize};
+
+/// Label for names taken from the `.utf-8` keys
+pub const UTF8_KEYS: &str = "UTF-8 keys";
+
+/// Label for names whose encoding couldn't be told
+pub const UNKNOWN: &str = "unknown";
+
+/// Original name bytes of a torrent whose names weren't UTF-8
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LegacyNames {
+    /// What the names were decoded from: an encoding name ("Shift_JIS"),
+    /// `UTF8_KEYS` or `UNKNOWN`
+    pub encoding: String,
+    /// The raw `name`
+    pub name: Vec<u8>,
+    /// The raw `path` of each file (empty for single-file torrents)
+    pub paths: Vec<Vec<Vec<u8>>>,
+}
+
+/// Where a decoded name came from, best first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Source {
+    Utf8,
+    Utf8Key,
+    #[cfg(feature = "legacy-encodings")]
+    Legacy,
+    Lossy,
+}
+
+/// Turns one torrent's names into text, see the module docs
+pub(super) struct NameDecoder {
+    #[cfg(feature = "legacy-encodings")]
+    legacy: Option<&'static Encoding>,
+    /// Worst source used so far
+    worst: Source,
+}
+
+impl NameDecoder {
+    /// Pick the legacy encoding from every raw name and path component and
+    /// the torrent's `encoding` key
+    pub fn new<'a>(raw: impl Iterator<Item = &'a [u8]>, encoding_key: Option<&[u8]>) -> Self {
+        let not_utf8: Vec<&[u8]> = raw.filter(|bytes| std::str::from_utf8(bytes).is_err()).collect();
+
+        #[cfg(feature = "legacy-encodings")]
+        let legacy = (!not_utf8.is_empty()).then(|| detect(&not_utf8, encoding_key));
+        #[cfg(not(feature = "legacy-encodings"))]
+        let _ = (not_utf8, encoding_key);
+
+        Self {
+            #[cfg(feature = "legacy-encodings")]
+            legacy,
+            worst: Source::Utf8,
+        }
+    }
+
+    /// Decode one name or path component; `utf8_key` is its `.utf-8` twin
+    pub fn decode(&mut self, raw: &[u8], utf8_key: Option<&[u8]>) -> String {
+        let (text, source) = self.decode_with_source(raw, utf8_key);
+        self.worst = self.worst.max(source);
+        text
+    }
+
+    fn decode_with_source(&self, raw: &[u8], utf8_key: Option<&[u8]>) -> (String, Source) {
+        if let Ok(text) = std::str::from_utf8(raw) {
+            return (text.to_string(), Source::Utf8);
+        }
+        if let Some(text) = utf8_key.and_then(|bytes| std::str::from_utf8(bytes).ok()) {
+            return (text.to_string(), Source::Utf8Key);
+        }
+        #[cfg(feature = "legacy-encodings")]
+        if let Some(encoding) = self.legacy {
+            let (text, _) = encoding.decode_without_bom_handling(raw);
+            return (text.into_owned(), Source::Legacy);
+        }
+        (String::from_utf8_lossy(raw).into_owned(), Source::Lossy)
+    }
+
+    /// How the names were transcoded, None if they all were UTF-8
+    pub fn encoding(&self) -> Option<String> {
+        match self.worst {
+            Source::Utf8 => None,
+            Source::Utf8Key => Some(UTF8_KEYS.to_string()),
+            #[cfg(feature = "legacy-encodings")]
+            Source::Legacy => self.legacy.map(|encoding| encoding.name().to_string()),
+            _ => Some(UNKNOWN.to_string()),
+        }
+    }
+}
+
+/// Make transcoded paths unique, since distinct raw names can decode to the
+/// same text. Later duplicates get " (n)" added to their last component, as
+/// `disk::available_root_name` does for files on disk.
+pub(super) fn uniquify(paths: &mut [Vec<String>]) {
+    let mut seen = std::collections::HashSet::new();
+    for path in paths.iter_mut() {
+        if seen.insert(path.clone()) {
+            continue;
+        }
+        let Some(last) = path.last().cloned() else {
+            continue;
+        };
+        let (stem, extension) = match last.rsplit_once('.') {
+            Some((stem, ext)) if !stem.is_empty() => (stem.to_string(), Some(ext.to_string())),
+            _ => (last.clone(), None),
+        };
+        for n in 2.. {
+            let candidate = match &extension {
+                Some(ext) => format!("{} ({}).{}", stem, n, ext),
+                None => format!("{} ({})", stem, n),
+            };
+            *path.last_mut().unwrap() = candidate;
+            if seen.insert(path.clone()) {
+                break;
+            }
+        }
+    }
+}
+
+/// Code pages tried, in the order ties are settled
+#[cfg(feature = "legacy-encodings")]
+const CANDIDATES: [&Encoding; 4] = [SHIFT_JIS, WINDOWS_1251, GBK, WINDOWS_1252];
+
+/// The encoding named by the torrent if it decodes every name cleanly, else
+/// the candidate whose decoding looks least like mojibake
+#[cfg(feature = "legacy-encodings")]
+fn detect(raw: &[&[u8]], encoding_key: Option<&[u8]>) -> &'static Encoding {
+    let named = encoding_key.and_then(Encoding::for_label).filter(|&encoding| encoding != encoding_rs::UTF_8);
+    if let Some(encoding) = named {
+        let clean = raw.iter().all(|bytes| encoding.decode_without_bom_handling_and_without_replacement(bytes).is_some());
+        if clean {
+            return encoding;
+        }
+    }
+
+    CANDIDATES
+        .into_iter()
+        .min_by_key(|&encoding| {
+            raw.iter()
+                .map(|bytes| weirdness(encoding, &encoding.decode_without_bom_handling(bytes).0))
+                .sum::<i64>()
+        })
+        .unwrap_or(WINDOWS_1252)
+}
+
+/// How unlikely `text` is as a name written in `encoding`; lower is better.
+/// Characters outside what that code page is normally used for count
+/// against it; kana count for Shift-JIS, being specific to Japanese.
+#[cfg(feature = "legacy-encodings")]
+fn weirdness(encoding: &'static Encoding, text: &str) -> i64 {
+    let mut score = 0;
+    let mut previous = ' ';
+    for c in text.chars() {
+        score += match c {
+            '\u{FFFD}' => 4,
+            c if c.is_ascii() => 0,
+            // Hiragana and katakana
+            '\u{3040}'..='\u{30FF}' if encoding == SHIFT_JIS => -1,
+            // CJK punctuation and fullwidth forms
+            '\u{3000}'..='\u{303F}' | '\u{FF01}'..='\u{FF5E}' if encoding == SHIFT_JIS || encoding == GBK => 0,
+            '\u{4E00}'..='\u{9FFF}' if is_common_ideograph(encoding, c) => 0,
+            '\u{0400}'..='\u{04FF}' if encoding == WINDOWS_1251 => {
+                // Real words don't switch case mid-word or mix in Latin letters
+                let case_switch = previous.is_lowercase() && c.is_uppercase();
+                i64::from(case_switch || previous.is_ascii_alphabetic())
+            }
+            '\u{00C0}'..='\u{00FF}' if encoding == WINDOWS_1252 => 0,
+            _ => 1,
+        };
+        previous = c;
+    }
+    score
+}
+
+/// Whether an ideograph is in the frequently used level of the code page
+/// (JIS level 1, GB2312 level 1); mojibake mostly lands outside it
+#[cfg(feature = "legacy-encodings")]
+fn is_common_ideograph(encoding: &'static Encoding, c: char) -> bool {
+    let mut buf = [0u8; 4];
+    let (bytes, _, had_errors) = encoding.encode(c.encode_utf8(&mut buf));
+    if had_errors || bytes.len() != 2 {
+        return false;
+    }
+    if encoding == SHIFT_JIS {
+        (0x88..=0x98).contains(&bytes[0])
+    } else if encoding == GBK {
+        (0xB0..=0xD7).contains(&bytes[0]) && bytes[1] >= 0xA1
+    } else {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_utf8_and_utf8_keys() {
+        let mut decoder = NameDecoder::new([b"plain.txt" as &[u8]].into_iter(), None);
+        assert_eq!(decoder.decode(b"plain.txt", None), "plain.txt");
+        assert_eq!(decoder.encoding(), None);
+
+        // A .utf-8 twin is preferred to guessing
+        let raw: &[u8] = &[0x83, 0x65, 0x83, 0x58, 0x83, 0x67];
+        let mut decoder = NameDecoder::new([raw].into_iter(), None);
+        assert_eq!(decoder.decode(raw, Some("テスト".as_bytes())), "テスト");
+        assert_eq!(decoder.encoding().as_deref(), Some(UTF8_KEYS));
+    }
+
+    #[test]
+    fn test_uniquify() {
+        let mut paths = vec![
+            vec!["dir".to_string(), "a.txt".to_string()],
+            vec!["dir".to_string(), "a.txt".to_string()],
+            vec!["dir".to_string(), "a (2).txt".to_string()],
+            vec!["dir".to_string(), "a.txt".to_string()],
+            vec!["other".to_string()],
+        ];
+        uniquify(&mut paths);
+        let last: Vec<&str> = paths.iter().map(|p| p.last().unwrap().as_str()).collect();
+        assert_eq!(last, vec!["a.txt", "a (2).txt", "a (2) (2).txt", "a (3).txt", "other"]);
+    }
+
+    #[cfg(feature = "legacy-encodings")]
+    #[test]
+    fn test_detects_common_code_pages() {
+        let cases: [(&str, &Encoding, &[&str]); 4] = [
+            ("Shift_JIS", SHIFT_JIS, &["テスト動画", "日本語のファイル.txt", "ドキュメント"]),
+            ("windows-1251", WINDOWS_1251, &["Привет мир", "Документы", "отчёт за 2009.doc"]),
+            ("GBK", GBK, &["中文电影", "说明文件.txt"]),
+            ("windows-1252", WINDOWS_1252, &["Café", "Größe.txt"]),
+        ];
+        for (label, encoding, names) in cases {
+            let raw: Vec<Vec<u8>> = names.iter().map(|name| encoding.encode(name).0.into_owned()).collect();
+            let mut decoder = NameDecoder::new(raw.iter().map(Vec::as_slice), None);
+            let decoded: Vec<String> = raw.iter().map(|bytes| decoder.decode(bytes, None)).collect();
+            assert_eq!(decoded, names.to_vec(), "{}", label);
+            assert_eq!(decoder.encoding().as_deref(), Some(label));
+        }
+    }
+
+    #[cfg(feature = "legacy-encodings")]
+    #[test]
+    fn test_encoding_key_wins_when_it_fits() {
+        // EUC-KR isn't guessed; only the torrent's key makes it readable
+        let raw = encoding_rs::EUC_KR.encode("한국어 자막").0.into_owned();
+        let mut decoder = NameDecoder::new([raw.as_slice()].into_iter(), Some(b"EUC-KR"));
+        assert_eq!(decoder.decode(&raw, None), "한국어 자막");
+        assert_eq!(decoder.encoding().as_deref(), Some("EUC-KR"));
+
+        // A key the bytes aren't valid in is ignored: in GBK these would end
+        // on a dangling lead byte
+        let raw = WINDOWS_1251.encode("Фильм").0.into_owned();
+        let mut decoder = NameDecoder::new([raw.as_slice()].into_iter(), Some(b"GBK"));
+        assert_eq!(decoder.decode(&raw, None), "Фильм");
+    }
+}
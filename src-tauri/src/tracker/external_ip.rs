@@ -0,0 +1,234 @@
+//! Our public address, as trackers see it
+//!
+//! Announce responses may carry an "external ip" key (BEP 24), either as 4
+//! or 16 raw bytes or, from some trackers, as text. Each tracker's latest
+//! report counts as one vote, per address family since a dual-stack client
+//! has one address of each. The address with the most votes wins; on a tie
+//! the one reported last. A tracker contradicting the others is logged.
+
+use crate::bencode::BencodeValue;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Mutex;
+
+/// The "external ip" value of an announce response. None if it is malformed.
+pub fn parse(value: &BencodeValue) -> Option<IpAddr> {
+    let bytes = value.as_bytes()?;
+    match bytes.len() {
+        4 => Some(IpAddr::V4(Ipv4Addr::from(<[u8; 4]>::try_from(bytes).ok()?))),
+        16 => Some(IpAddr::V6(Ipv6Addr::from(<[u8; 16]>::try_from(bytes).ok()?))),
+        _ => std::str::from_utf8(bytes).ok()?.trim().parse().ok(),
+    }
+}
+
+/// One tracker's latest report
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ExternalIpReport {
+    /// Tracker host (announce URLs may hold passkeys)
+    pub tracker: String,
+    pub ip: IpAddr,
+}
+
+#[derive(Debug, Default)]
+struct Votes {
+    /// Latest report by tracker host, with its report number
+    by_tracker: HashMap<String, (IpAddr, u64)>,
+    reports: u64,
+    v4: Option<Ipv4Addr>,
+    v6: Option<Ipv6Addr>,
+}
+
+impl Votes {
+    /// The winning address of `ip`'s family
+    fn consensus_like(&self, ip: IpAddr) -> Option<IpAddr> {
+        let mut tally: HashMap<IpAddr, (usize, u64)> = HashMap::new();
+        for &(voted, at) in self.by_tracker.values().filter(|(voted, _)| voted.is_ipv4() == ip.is_ipv4()) {
+            let entry = tally.entry(voted).or_default();
+            entry.0 += 1;
+            entry.1 = entry.1.max(at);
+        }
+        tally.into_iter().max_by_key(|&(_, votes)| votes).map(|(ip, _)| ip)
+    }
+}
+
+/// Our external addresses agreed by trackers, shared by all engines (see
+/// `AppState::external_ip`)
+#[derive(Debug, Default)]
+pub struct ExternalIp {
+    votes: Mutex<Votes>,
+}
+
+impl ExternalIp {
+    /// Count a tracker's report; returns the agreed address of that family
+    pub fn report(&self, tracker_url: &str, ip: IpAddr) -> Option<IpAddr> {
+        let tracker = reqwest::Url::parse(tracker_url)
+            .ok()
+            .and_then(|url| url.host_str().map(str::to_string))
+            .unwrap_or_else(|| tracker_url.to_string());
+
+        let mut votes = self.votes.lock().unwrap();
+        votes.reports += 1;
+        let at = votes.reports;
+        let previous = votes.by_tracker.insert(tracker.clone(), (ip, at));
+        let consensus = votes.consensus_like(ip);
+
+        // Once per change of that tracker's answer, not every announce
+        if previous.map(|(ip, _)| ip) != Some(ip) {
+            if let Some(agreed) = consensus.filter(|agreed| *agreed != ip) {
+                tracing::warn!("{} reports our external IP as {}, other trackers say {}", tracker, ip, agreed);
+            }
+        }
+        let changed = match consensus {
+            Some(IpAddr::V4(v4)) => votes.v4.replace(v4) != Some(v4),
+            Some(IpAddr::V6(v6)) => votes.v6.replace(v6) != Some(v6),
+            None => false,
+        };
+        if changed {
+            tracing::info!("External IP is now {}", consensus.unwrap());
+        }
+        consensus
+    }
+
+    pub fn ipv4(&self) -> Option<Ipv4Addr> {
+        self.votes.lock().unwrap().v4
+    }
+
+    pub fn ipv6(&self) -> Option<Ipv6Addr> {
+        self.votes.lock().unwrap().v6
+    }
+
+    /// Whether `addr` is us: our agreed address of its family and our port
+    pub fn is_self(&self, addr: SocketAddr, listen_port: u16) -> bool {
+        if addr.port() != listen_port {
+            return false;
+        }
+        let votes = self.votes.lock().unwrap();
+        match addr.ip() {
+            IpAddr::V4(ip) => votes.v4 == Some(ip),
+            IpAddr::V6(ip) => votes.v6 == Some(ip),
+        }
+    }
+
+    /// Every tracker's latest report, by tracker
+    pub fn reports(&self) -> Vec<ExternalIpReport> {
+        let votes = self.votes.lock().unwrap();
+        let mut reports: Vec<ExternalIpReport> = votes
+            .by_tracker
+            .iter()
+            .map(|(tracker, &(ip, _))| ExternalIpReport { tracker: tracker.clone(), ip })
+            .collect();
+        reports.sort_by(|a, b| a.tracker.cmp(&b.tracker));
+        reports
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response_ip(response: &[u8]) -> Option<IpAddr> {
+        let value = BencodeValue::parse(response).unwrap();
+        value.as_dict().unwrap().get(b"external ip" as &[u8]).and_then(parse)
+    }
+
+    #[test]
+    fn test_parse_encodings() {
+        // Raw bytes, as BEP 24 has it
+        assert_eq!(
+            response_ip(b"d11:external ip4:\xcb\x00\x71\x07e"),
+            Some("203.0.113.7".parse().unwrap())
+        );
+        let mut v6 = b"d11:external ip16:".to_vec();
+        v6.extend_from_slice(&"2001:db8::7".parse::<Ipv6Addr>().unwrap().octets());
+        v6.push(b'e');
+        assert_eq!(response_ip(&v6), Some("2001:db8::7".parse().unwrap()));
+
+        // Text, from some trackers
+        assert_eq!(response_ip(b"d11:external ip11:203.0.113.7e"), Some("203.0.113.7".parse().unwrap()));
+        assert_eq!(response_ip(b"d11:external ip11:2001:db8::7e"), Some("2001:db8::7".parse().unwrap()));
+
+        // Malformed values are ignored
+        assert_eq!(response_ip(b"d11:external ip3:abce"), None);
+        assert_eq!(response_ip(b"d11:external ip5:1.2.3e"), None);
+        assert_eq!(response_ip(b"d11:external ipi42ee"), None);
+        assert_eq!(response_ip(b"de"), None);
+    }
+
+    /// Messages logged at WARN while running `f`
+    fn warnings(f: impl FnOnce()) -> Vec<String> {
+        use std::sync::Arc;
+        use tracing_subscriber::layer::SubscriberExt;
+
+        struct Capture(Arc<Mutex<Vec<String>>>);
+        impl<S: tracing::Subscriber> tracing_subscriber::Layer<S> for Capture {
+            fn on_event(&self, event: &tracing::Event<'_>, _: tracing_subscriber::layer::Context<'_, S>) {
+                struct Message<'a>(&'a mut String);
+                impl tracing::field::Visit for Message<'_> {
+                    fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+                        if field.name() == "message" {
+                            self.0.push_str(&format!("{:?}", value));
+                        }
+                    }
+                }
+                if *event.metadata().level() == tracing::Level::WARN {
+                    let mut message = String::new();
+                    event.record(&mut Message(&mut message));
+                    self.0.lock().unwrap().push(message);
+                }
+            }
+        }
+
+        let messages = Arc::new(Mutex::new(Vec::new()));
+        let subscriber = tracing_subscriber::registry().with(Capture(messages.clone()));
+        tracing::subscriber::with_default(subscriber, f);
+        let messages = messages.lock().unwrap().clone();
+        messages
+    }
+
+    #[test]
+    fn test_consensus_and_disagreement() {
+        let external = ExternalIp::default();
+        let ours: IpAddr = "203.0.113.7".parse().unwrap();
+        let other: IpAddr = "198.51.100.9".parse().unwrap();
+
+        let logged = warnings(|| {
+            assert_eq!(external.report("http://a.example/announce", ours), Some(ours));
+            assert_eq!(external.report("https://b.example:8443/abc123/announce", ours), Some(ours));
+            // Outvoted, and logged once however often it repeats
+            assert_eq!(external.report("http://c.example/announce", other), Some(ours));
+            assert_eq!(external.report("http://c.example/announce", other), Some(ours));
+        });
+        assert_eq!(logged, vec!["c.example reports our external IP as 198.51.100.9, other trackers say 203.0.113.7"]);
+        assert_eq!(external.ipv4(), Some("203.0.113.7".parse().unwrap()));
+
+        // Trackers changing their answer move the majority; a tie goes to
+        // the address reported last
+        assert_eq!(external.report("http://a.example/announce", other), Some(other));
+        assert_eq!(external.report("http://d.example/announce", ours), Some(ours));
+
+        // Other families don't vote against each other
+        let v6: IpAddr = "2001:db8::7".parse().unwrap();
+        assert_eq!(external.report("http://e.example/announce", v6), Some(v6));
+        assert_eq!(external.ipv4(), Some("203.0.113.7".parse().unwrap()));
+        assert_eq!(external.ipv6(), Some("2001:db8::7".parse().unwrap()));
+
+        // Passkeys in announce URLs aren't kept
+        let reports = external.reports();
+        assert_eq!(reports.len(), 5);
+        assert_eq!(reports[1], ExternalIpReport { tracker: "b.example".to_string(), ip: ours });
+    }
+
+    #[test]
+    fn test_is_self() {
+        let external = ExternalIp::default();
+        let addr: SocketAddr = "203.0.113.7:6881".parse().unwrap();
+        assert!(!external.is_self(addr, 6881));
+
+        external.report("http://a.example/announce", addr.ip());
+        assert!(external.is_self(addr, 6881));
+        assert!(!external.is_self(addr, 6882));
+        assert!(!external.is_self("203.0.113.8:6881".parse().unwrap(), 6881));
+        assert!(!external.is_self("[2001:db8::7]:6881".parse().unwrap(), 6881));
+    }
+}
@@ -0,0 +1,187 @@
+//! Swarm health of a torrent that isn't added yet
+//!
+//! The add dialog shows how many seeds and leechers a torrent has before
+//! anything starts. The counts come from one scrape of each HTTP(S) tracker,
+//! all at once and cut off after `PROBE_BUDGET`: trackers that haven't
+//! answered by then are left out, and if none has the health is unknown.
+//! Trackers report overlapping swarms, so the counts are the largest any
+//! tracker gave rather than a sum.
+
+use super::http::HttpTracker;
+use futures::stream::{FuturesUnordered, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Longest a probe may take, whatever the trackers do
+pub const PROBE_BUDGET: Duration = Duration::from_secs(2);
+
+/// How long a probe's result is reused (parsing the same torrent again, or
+/// confirming the dialog)
+const CACHE_TTL: Duration = Duration::from_secs(120);
+
+/// Where the counts of a `SwarmHealth` come from
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HealthSource {
+    /// No tracker answered in time, and nothing else to go by
+    #[default]
+    Unknown,
+    Trackers,
+    /// A debrid provider's cache check (magnets without tracker answers)
+    Debrid,
+}
+
+/// Seeds and leechers of a torrent at add time
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SwarmHealth {
+    pub source: HealthSource,
+    /// Most seeds any tracker reported
+    pub seeds: Option<u32>,
+    /// Most leechers any tracker reported
+    pub leechers: Option<u32>,
+    /// Trackers scraped
+    pub trackers_asked: usize,
+    /// Trackers that answered within the budget
+    pub trackers_answered: usize,
+    /// Whether a debrid provider has the torrent cached, when one was asked
+    pub debrid_cached: Option<bool>,
+}
+
+impl SwarmHealth {
+    /// Trackers answered and none knows a seed
+    pub fn no_seeds(&self) -> bool {
+        self.seeds == Some(0)
+    }
+}
+
+/// Scrape `urls` for `info_hash` at once, taking what answered within
+/// `budget`. Trackers without a scrape URL (and non-HTTP ones) aren't asked.
+pub async fn probe(
+    tracker: &HttpTracker,
+    urls: &[String],
+    info_hash: &[u8; 20],
+    anonymous: bool,
+    budget: Duration,
+) -> SwarmHealth {
+    let deadline = tokio::time::Instant::now() + budget;
+    let scrapable: Vec<&String> = urls
+        .iter()
+        .filter(|url| url.starts_with("http://") || url.starts_with("https://"))
+        .filter(|url| super::http::scrape_url(url).is_some())
+        .collect();
+
+    let mut health = SwarmHealth { trackers_asked: scrapable.len(), ..Default::default() };
+    let mut scrapes: FuturesUnordered<_> = scrapable
+        .iter()
+        .map(|url| async move { (*url, tracker.scrape(url, info_hash, anonymous).await) })
+        .collect();
+    while let Ok(Some((url, result))) = tokio::time::timeout_at(deadline, scrapes.next()).await {
+        match result {
+            Ok(scrape) => {
+                health.source = HealthSource::Trackers;
+                health.trackers_answered += 1;
+                health.seeds = health.seeds.max(Some(scrape.complete));
+                health.leechers = health.leechers.max(Some(scrape.incomplete));
+            }
+            Err(e) => tracing::debug!("Scrape of {} failed: {}", super::redact::redact_tracker_url(url), e),
+        }
+    }
+    if health.trackers_answered < health.trackers_asked {
+        tracing::debug!(
+            "{} of {} trackers answered the scrape in time",
+            health.trackers_answered,
+            health.trackers_asked
+        );
+    }
+    health
+}
+
+/// Recent probe results by info hash (see `AppState::swarm_health`)
+#[derive(Debug, Default)]
+pub struct HealthCache {
+    entries: Mutex<HashMap<[u8; 20], (Instant, SwarmHealth)>>,
+}
+
+impl HealthCache {
+    /// The result for `info_hash`, if probed within the last `CACHE_TTL`
+    pub fn get(&self, info_hash: &[u8; 20]) -> Option<SwarmHealth> {
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|_, (at, _)| at.elapsed() < CACHE_TTL);
+        entries.get(info_hash).map(|(_, health)| health.clone())
+    }
+
+    pub fn insert(&self, info_hash: [u8; 20], health: SwarmHealth) {
+        self.entries.lock().unwrap().insert(info_hash, (Instant::now(), health));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::MiniTracker;
+
+    const INFO_HASH: [u8; 20] = [7; 20];
+
+    #[tokio::test]
+    async fn test_counts_are_the_largest_reported() {
+        let (first, second) = (MiniTracker::start(vec![]).await, MiniTracker::start(vec![]).await);
+        first.set_swarm(12, 400);
+        second.set_swarm(15, 30);
+        let urls = vec![first.url().to_string(), second.url().to_string(), "udp://tracker.example.org:1337".to_string()];
+
+        let health = probe(&HttpTracker::new(), &urls, &INFO_HASH, true, PROBE_BUDGET).await;
+        assert_eq!(
+            health,
+            SwarmHealth {
+                source: HealthSource::Trackers,
+                seeds: Some(15),
+                leechers: Some(400),
+                trackers_asked: 2,
+                trackers_answered: 2,
+                debrid_cached: None,
+            }
+        );
+        assert!(!health.no_seeds());
+    }
+
+    #[tokio::test]
+    async fn test_slow_trackers_are_cut_off() {
+        let (fast, slow) = (MiniTracker::start(vec![]).await, MiniTracker::start(vec![]).await);
+        fast.set_swarm(0, 3);
+        slow.set_swarm(50, 50);
+        slow.set_delay(Duration::from_secs(30));
+        let urls = vec![slow.url().to_string(), fast.url().to_string()];
+        let budget = Duration::from_millis(300);
+
+        // Partial: what the fast tracker said
+        let started = Instant::now();
+        let health = probe(&HttpTracker::new(), &urls, &INFO_HASH, true, budget).await;
+        assert!(started.elapsed() < budget + Duration::from_millis(500), "took {:?}", started.elapsed());
+        assert_eq!((health.seeds, health.leechers), (Some(0), Some(3)));
+        assert_eq!((health.trackers_asked, health.trackers_answered), (2, 1));
+        assert!(health.no_seeds());
+
+        // Nothing in time: unknown, not zero
+        let started = Instant::now();
+        let health = probe(&HttpTracker::new(), &urls[..1], &INFO_HASH, true, budget).await;
+        assert!(started.elapsed() < budget + Duration::from_millis(500), "took {:?}", started.elapsed());
+        assert_eq!(health.source, HealthSource::Unknown);
+        assert_eq!((health.seeds, health.trackers_answered), (None, 0));
+        assert!(!health.no_seeds());
+    }
+
+    #[test]
+    fn test_cache() {
+        let cache = HealthCache::default();
+        assert_eq!(cache.get(&INFO_HASH), None);
+        let health = SwarmHealth { seeds: Some(1), ..Default::default() };
+        cache.insert(INFO_HASH, health.clone());
+        assert_eq!(cache.get(&INFO_HASH), Some(health));
+
+        let Some(expired) = Instant::now().checked_sub(CACHE_TTL) else { return };
+        cache.entries.lock().unwrap().get_mut(&INFO_HASH).unwrap().0 = expired;
+        assert_eq!(cache.get(&INFO_HASH), None);
+    }
+}
@@ -4,26 +4,173 @@
 
 use crate::bencode::BencodeValue;
 use crate::error::{Error, Result};
-use crate::tracker::{AnnounceRequest, AnnounceResponse, Peer};
+use crate::tracker::{tls, AnnounceRequest, AnnounceResponse, Peer, ScrapeResponse};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use serde::{Deserialize, Serialize};
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::time::Duration;
 
+/// User-Agent sent to trackers unless configured otherwise
+pub const DEFAULT_USER_AGENT: &str = concat!("SeedCore/", env!("CARGO_PKG_VERSION"));
+
+/// Headers that extra headers may not override
+const RESERVED_HEADERS: &[&str] = &["host", "content-length", "transfer-encoding", "connection", "user-agent"];
+
+/// An extra header for tracker requests, as stored in settings
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HttpHeader {
+    pub name: String,
+    pub value: String,
+}
+
+/// Validated tracker HTTP settings
+///
+/// Only ever applied to tracker requests; debrid clients are built
+/// separately so these headers (often credentials for a tracker proxy) and
+/// the certificate overrides can't leak to a debrid provider.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrackerHttpConfig {
+    user_agent: HeaderValue,
+    extra_headers: HeaderMap,
+    /// Extra trusted root certificates (PEM)
+    extra_root_ca: Option<Vec<u8>>,
+    /// Hosts whose certificates aren't checked at all (exact names, sorted)
+    insecure_hosts: Vec<String>,
+}
+
+impl Default for TrackerHttpConfig {
+    fn default() -> Self {
+        Self {
+            user_agent: HeaderValue::from_static(DEFAULT_USER_AGENT),
+            extra_headers: HeaderMap::new(),
+            extra_root_ca: None,
+            insecure_hosts: Vec::new(),
+        }
+    }
+}
+
+impl TrackerHttpConfig {
+    /// Validate settings; `user_agent` None or blank means the default
+    pub fn new(user_agent: Option<&str>, extra_headers: &[HttpHeader]) -> Result<Self> {
+        if let Some(ua) = user_agent.filter(|ua| has_line_break(ua)) {
+            return Err(Error::ValidationError(format!("User-Agent {:?} contains a line break", ua)));
+        }
+        let user_agent = match user_agent.map(str::trim).filter(|ua| !ua.is_empty()) {
+            Some(ua) => header_value("User-Agent", ua)?,
+            None => HeaderValue::from_static(DEFAULT_USER_AGENT),
+        };
+
+        let mut headers = HeaderMap::new();
+        for header in extra_headers {
+            if has_line_break(&header.name) {
+                return Err(Error::ValidationError(format!("header name {:?} contains a line break", header.name)));
+            }
+            let name = header.name.trim();
+            let name = HeaderName::from_bytes(name.as_bytes())
+                .map_err(|_| Error::ValidationError(format!("invalid header name {:?}", header.name)))?;
+            if RESERVED_HEADERS.contains(&name.as_str()) {
+                return Err(Error::ValidationError(format!("header {} can't be overridden", name)));
+            }
+            let value = header_value(name.as_str(), &header.value)?;
+            headers.append(name, value);
+        }
+
+        Ok(Self { user_agent, extra_headers: headers, ..Self::default() })
+    }
+
+    /// Add certificate settings: a PEM file of extra root CAs (None or blank
+    /// for none) and hosts to skip certificate checks for
+    pub fn with_tls(mut self, root_ca_file: Option<&str>, insecure_hosts: &[String]) -> Result<Self> {
+        self.extra_root_ca = match root_ca_file.map(str::trim).filter(|path| !path.is_empty()) {
+            Some(path) => Some(tls::load_root_ca(std::path::Path::new(path))?),
+            None => None,
+        };
+        let mut hosts = insecure_hosts.iter().map(|host| tls::insecure_host(host)).collect::<Result<Vec<_>>>()?;
+        hosts.sort();
+        hosts.dedup();
+        self.insecure_hosts = hosts;
+        Ok(self)
+    }
+
+    /// User-Agent sent outside anonymous mode
+    pub fn user_agent(&self) -> &str {
+        // Built from a &str, so always valid UTF-8
+        self.user_agent.to_str().unwrap_or(DEFAULT_USER_AGENT)
+    }
+}
+
+fn has_line_break(s: &str) -> bool {
+    s.contains(['\r', '\n', '\0'])
+}
+
+fn header_value(name: &str, value: &str) -> Result<HeaderValue> {
+    if has_line_break(value) {
+        return Err(Error::ValidationError(format!("value of header {} contains a line break", name)));
+    }
+    HeaderValue::from_str(value)
+        .map_err(|_| Error::ValidationError(format!("invalid value for header {}", name)))
+}
+
 /// HTTP tracker client
 pub struct HttpTracker {
     /// HTTP client
     client: reqwest::Client,
+    /// Client that accepts any certificate, used only for `insecure_hosts`
+    insecure_client: Option<reqwest::Client>,
+    insecure_hosts: Vec<String>,
+    /// User-Agent for non-anonymous announces
+    user_agent: HeaderValue,
 }
 
 impl HttpTracker {
-    /// Create a new HTTP tracker client
+    /// Create a new HTTP tracker client with default settings
     pub fn new() -> Self {
-        let client = reqwest::Client::builder()
-            .timeout(Duration::from_secs(30))
-            .user_agent("SeedCore/0.1.0")
-            .build()
-            .expect("Failed to create HTTP client");
-        
-        Self { client }
+        Self::with_config(&TrackerHttpConfig::default())
+    }
+
+    /// Create a client for the given settings
+    ///
+    /// Extra headers go on every request. The User-Agent is added per request
+    /// unless the announce is anonymous, so toggling the mode doesn't need a
+    /// new client.
+    pub fn with_config(config: &TrackerHttpConfig) -> Self {
+        let builder = || {
+            let mut builder = reqwest::Client::builder()
+                .timeout(Duration::from_secs(30))
+                .default_headers(config.extra_headers.clone());
+            // Validated when the config was built
+            let roots = config.extra_root_ca.as_deref().map(reqwest::Certificate::from_pem_bundle);
+            for cert in roots.into_iter().flatten().flatten() {
+                builder = builder.add_root_certificate(cert);
+            }
+            builder
+        };
+        let client = builder().build().expect("Failed to create HTTP client");
+        let insecure_client = (!config.insecure_hosts.is_empty()).then(|| {
+            tracing::warn!("Certificate checks disabled for trackers on: {}", config.insecure_hosts.join(", "));
+            builder()
+                .danger_accept_invalid_certs(true)
+                .build()
+                .expect("Failed to create HTTP client")
+        });
+
+        Self {
+            client,
+            insecure_client,
+            insecure_hosts: config.insecure_hosts.clone(),
+            user_agent: config.user_agent.clone(),
+        }
+    }
+
+    /// The client for `url`: the unchecked one only for an exact host match
+    fn client_for(&self, url: &str) -> &reqwest::Client {
+        let host = reqwest::Url::parse(url)
+            .ok()
+            .and_then(|url| url.host_str().map(|host| host.trim_end_matches('.').to_ascii_lowercase()));
+        match (&self.insecure_client, host) {
+            (Some(insecure), Some(host)) if self.insecure_hosts.binary_search(&host).is_ok() => insecure,
+            _ => &self.client,
+        }
     }
     
     /// Send announce request to tracker
@@ -35,14 +182,26 @@ impl HttpTracker {
         // Build URL with query parameters
         let url = self.build_announce_url(tracker_url, request)?;
         
-        tracing::debug!("Announcing to tracker: {}", url);
+        tracing::debug!("Announcing to tracker: {}", super::redact::redact_tracker_url(&url));
         
         // Send HTTP GET request
-        let response = self.client
-            .get(&url)
+        let response = self.announce_get(&url, request)
             .send()
             .await
-            .map_err(|e| Error::NetworkError(format!("HTTP request failed: {}", e)))?;
+            .map_err(|e| {
+                // The URL (with passkey and info hash) would only pollute the match
+                let url = e.url().cloned();
+                let e = e.without_url();
+                let text = crate::clock::error_text(&e);
+                crate::clock::on_request_error(&text, url.as_ref());
+                // A wrong clock explains an expired certificate better
+                if crate::clock::detected_skew().is_none() && tls::TlsFailure::classify(&text).is_some() {
+                    Error::TlsError(text)
+                } else {
+                    crate::clock::network_error(format!("HTTP request failed: {}", e))
+                }
+            })?;
+        crate::clock::observe_date_header(response.headers());
         
         // Check status code
         if !response.status().is_success() {
@@ -62,6 +221,66 @@ impl HttpTracker {
         self.parse_announce_response(&bytes)
     }
     
+    /// Ask the tracker about one torrent without announcing (BEP 48). Fails
+    /// for trackers whose announce URL has no scrape counterpart.
+    pub async fn scrape(&self, tracker_url: &str, info_hash: &[u8; 20], anonymous: bool) -> Result<ScrapeResponse> {
+        let scrape_url = scrape_url(tracker_url)
+            .ok_or_else(|| Error::NetworkError(format!("Tracker doesn't support scrape: {}", super::redact::redact_tracker_url(tracker_url))))?;
+        let separator = if scrape_url.contains('?') { '&' } else { '?' };
+        let url = format!("{}{}info_hash={}", scrape_url, separator, Self::url_encode_bytes(info_hash));
+
+        let mut builder = self.client_for(&url).get(&url);
+        if !anonymous {
+            builder = builder.header(reqwest::header::USER_AGENT, self.user_agent.clone());
+        }
+        let response = builder
+            .send()
+            .await
+            .map_err(|e| crate::clock::network_error(format!("HTTP request failed: {}", e.without_url())))?;
+        if !response.status().is_success() {
+            return Err(Error::NetworkError(format!("Tracker returned error: {}", response.status())));
+        }
+        let bytes = response
+            .bytes()
+            .await
+            .map_err(|e| Error::NetworkError(format!("Failed to read response: {}", e)))?;
+        Self::parse_scrape_response(&bytes, info_hash)
+    }
+
+    /// Pick one torrent's counts out of a scrape response
+    fn parse_scrape_response(data: &[u8], info_hash: &[u8; 20]) -> Result<ScrapeResponse> {
+        let value = BencodeValue::parse(data)?;
+        let dict = value.as_dict()
+            .ok_or_else(|| Error::MetainfoError("response must be a dictionary".to_string()))?;
+        if let Some(reason) = dict.get(b"failure reason" as &[u8]).and_then(|v| v.as_str()) {
+            return Err(Error::TrackerFailure(reason.to_string()));
+        }
+
+        let files = dict.get(b"files" as &[u8])
+            .and_then(|v| v.as_dict())
+            .ok_or_else(|| Error::MetainfoError("missing files".to_string()))?;
+        // A tracker that doesn't know the torrent leaves it out
+        let Some(entry) = files.get(info_hash as &[u8]).and_then(|v| v.as_dict()) else {
+            return Ok(ScrapeResponse { complete: 0, incomplete: 0, downloaded: 0 });
+        };
+        let count = |key: &[u8]| entry.get(key).and_then(|v| v.as_integer()).unwrap_or(0).clamp(0, u32::MAX as i64) as u32;
+        Ok(ScrapeResponse {
+            complete: count(b"complete"),
+            incomplete: count(b"incomplete"),
+            downloaded: count(b"downloaded"),
+        })
+    }
+    
+    /// GET request for an announce URL, with a User-Agent unless anonymous
+    fn announce_get(&self, url: &str, request: &AnnounceRequest) -> reqwest::RequestBuilder {
+        let builder = self.client_for(url).get(url);
+        if request.anonymous {
+            builder
+        } else {
+            builder.header(reqwest::header::USER_AGENT, self.user_agent.clone())
+        }
+    }
+    
     /// Build announce URL with parameters
     fn build_announce_url(
         &self,
@@ -100,6 +319,10 @@ impl HttpTracker {
         if let Some(event) = request.event.as_str() {
             params.push(format!("event={}", event));
         }
+
+        // `ip` and `key` are deliberately never sent, in any mode: the
+        // tracker sees our address anyway, and a stable key would link
+        // announces together.
         
         // Set query string directly
         url.set_query(Some(&params.join("&")));
@@ -124,7 +347,7 @@ impl HttpTracker {
         // Check for failure reason
         if let Some(failure) = dict.get(b"failure reason" as &[u8]) {
             if let Some(reason) = failure.as_str() {
-                return Err(Error::NetworkError(format!("Tracker error: {}", reason)));
+                return Err(Error::TrackerFailure(reason.to_string()));
             }
         }
         
@@ -157,6 +380,15 @@ impl HttpTracker {
         
         // Parse peers
         let peers = self.parse_peers(dict)?;
+
+        // Advisory: a value we can't read doesn't fail the announce
+        let external_ip = dict.get(b"external ip" as &[u8]).and_then(|value| {
+            let ip = super::external_ip::parse(value);
+            if ip.is_none() {
+                tracing::debug!("Ignoring malformed external ip in announce response");
+            }
+            ip
+        });
         
         Ok(AnnounceResponse {
             warning_message,
@@ -166,13 +398,14 @@ impl HttpTracker {
             complete,
             incomplete,
             peers,
+            external_ip,
         })
     }
     
     /// Parse peers from response (supports both compact and dictionary format)
     fn parse_peers(
         &self,
-        dict: &std::collections::HashMap<Vec<u8>, BencodeValue>,
+        dict: &crate::bencode::Dictionary,
     ) -> Result<Vec<Peer>> {
         let peers_value = dict.get(b"peers" as &[u8])
             .ok_or_else(|| Error::MetainfoError("missing peers".to_string()))?;
@@ -252,6 +485,23 @@ impl HttpTracker {
     }
 }
 
+/// The scrape URL of an HTTP tracker: "announce" in the last path segment
+/// replaced by "scrape". None when the segment doesn't start with "announce".
+pub fn scrape_url(announce_url: &str) -> Option<String> {
+    let (path, query) = match announce_url.split_once('?') {
+        Some((path, query)) => (path, Some(query)),
+        None => (announce_url, None),
+    };
+    let slash = path.rfind('/')?;
+    let rest = path[slash + 1..].strip_prefix("announce")?;
+    let mut url = format!("{}/scrape{}", &path[..slash], rest);
+    if let Some(query) = query {
+        url.push('?');
+        url.push_str(query);
+    }
+    Some(url)
+}
+
 impl Default for HttpTracker {
     fn default() -> Self {
         Self::new()
@@ -269,6 +519,57 @@ mod tests {
         assert_eq!(encoded, "%68%65%6c%6c%6f");
     }
     
+    #[test]
+    fn test_scrape_url() {
+        let cases = [
+            ("http://example.com/announce", Some("http://example.com/scrape")),
+            ("http://example.com/x/announce.php?passkey=abc", Some("http://example.com/x/scrape.php?passkey=abc")),
+            ("http://example.com/announce?a=b/c", Some("http://example.com/scrape?a=b/c")),
+            ("http://example.com/a", None),
+            ("http://example.com/x/announce/", None),
+        ];
+        for (announce, scrape) in cases {
+            assert_eq!(scrape_url(announce).as_deref(), scrape, "{}", announce);
+        }
+    }
+
+    #[test]
+    fn test_parse_scrape_response() {
+        let hash = [7u8; 20];
+        let mut data = b"d5:filesd20:".to_vec();
+        data.extend_from_slice(&hash);
+        data.extend_from_slice(b"d8:completei3e10:downloadedi40e10:incompletei5eeee");
+        let stats = HttpTracker::parse_scrape_response(&data, &hash).unwrap();
+        assert_eq!(stats, ScrapeResponse { complete: 3, incomplete: 5, downloaded: 40 });
+
+        // Unknown to the tracker
+        let empty = HttpTracker::parse_scrape_response(b"d5:filesdee", &hash).unwrap();
+        assert_eq!(empty.complete, 0);
+        assert!(HttpTracker::parse_scrape_response(b"d14:failure reason6:refusee", &hash).is_err());
+    }
+
+    #[test]
+    fn test_announce_response_external_ip() {
+        let tracker = HttpTracker::new();
+        let announce = |external_ip: &[u8]| {
+            let mut data = b"d".to_vec();
+            data.extend_from_slice(external_ip);
+            data.extend_from_slice(b"8:intervali1800e5:peers6:\x0a\x00\x00\x01\x1a\xe1e");
+            tracker.parse_announce_response(&data).unwrap()
+        };
+
+        let binary = announce(b"11:external ip4:\xcb\x00\x71\x07");
+        assert_eq!(binary.external_ip, Some("203.0.113.7".parse().unwrap()));
+        let text = announce(b"11:external ip11:203.0.113.7");
+        assert_eq!(text.external_ip, Some("203.0.113.7".parse().unwrap()));
+
+        // A value we can't read leaves the rest of the announce alone
+        let malformed = announce(b"11:external ip2:\x01\x02");
+        assert_eq!(malformed.external_ip, None);
+        assert_eq!(malformed.peers.len(), 1);
+        assert_eq!(announce(b"").external_ip, None);
+    }
+
     #[test]
     fn test_parse_compact_peers() {
         let tracker = HttpTracker::new();
@@ -309,4 +610,170 @@ mod tests {
         assert!(url.contains("downloaded=0"));
         assert!(url.contains("left=0"));
     }
+    
+    #[test]
+    fn test_anonymous_announce_hides_client() {
+        let tracker = HttpTracker::new();
+        let identity = crate::utils::PeerIdentity::generate();
+        let tracker_url = "http://tracker.example.com/announce";
+        
+        let mut request = AnnounceRequest::default();
+        request.peer_id = identity.peer_id(false);
+        let normal_url = tracker.build_announce_url(tracker_url, &request).unwrap();
+        let normal = tracker.announce_get(&normal_url, &request).build().unwrap();
+        
+        request.peer_id = identity.peer_id(true);
+        request.anonymous = true;
+        let anonymous_url = tracker.build_announce_url(tracker_url, &request).unwrap();
+        let anonymous = tracker.announce_get(&anonymous_url, &request).build().unwrap();
+        
+        // "-SC0100-" encoded
+        let client_prefix = "peer_id=%2d%53%43%30%31%30%30%2d";
+        assert!(normal_url.contains(client_prefix));
+        assert!(!anonymous_url.contains(client_prefix));
+        assert_ne!(normal_url, anonymous_url);
+        
+        assert_eq!(normal.headers()[reqwest::header::USER_AGENT], DEFAULT_USER_AGENT);
+        assert!(anonymous.headers().get(reqwest::header::USER_AGENT).is_none());
+        
+        // Never sent, in either mode
+        for url in [&normal_url, &anonymous_url] {
+            assert!(!url.contains("&ip=") && !url.contains("&key="));
+        }
+    }
+    
+    #[test]
+    fn test_tracker_http_config_validation() {
+        let header = |name: &str, value: &str| HttpHeader { name: name.to_string(), value: value.to_string() };
+        
+        let config = TrackerHttpConfig::new(Some("  "), &[]).unwrap();
+        assert_eq!(config.user_agent(), DEFAULT_USER_AGENT);
+        assert!(TrackerHttpConfig::new(Some("qBittorrent/4.6.2"), &[header("X-Api-Key", "abc")]).is_ok());
+        
+        // No header injection through values, names or the user agent
+        assert!(TrackerHttpConfig::new(None, &[header("X-Api-Key", "abc\r\nX-Evil: 1")]).is_err());
+        assert!(TrackerHttpConfig::new(None, &[header("X-Api-Key", "abc\n")]).is_err());
+        assert!(TrackerHttpConfig::new(None, &[header("X-Evil\n", "1")]).is_err());
+        assert!(TrackerHttpConfig::new(Some("SeedCore\r\nX-Evil: 1"), &[]).is_err());
+        
+        assert!(TrackerHttpConfig::new(None, &[header("Bad Name", "1")]).is_err());
+        assert!(TrackerHttpConfig::new(None, &[header("Host", "example.com")]).is_err());
+    }
+    
+    #[tokio::test]
+    async fn test_announce_sends_configured_headers() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0u8; 1024];
+            while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+                let n = socket.read(&mut buf).await.unwrap();
+                assert!(n > 0, "connection closed mid-request");
+                request.extend_from_slice(&buf[..n]);
+            }
+            let body = b"d8:intervali1800e5:peers0:e";
+            let head = format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", body.len());
+            socket.write_all(head.as_bytes()).await.unwrap();
+            socket.write_all(body).await.unwrap();
+            String::from_utf8(request).unwrap().to_lowercase()
+        });
+        
+        let config = TrackerHttpConfig::new(
+            Some("qBittorrent/4.6.2"),
+            &[HttpHeader { name: "X-Proxy-Token".to_string(), value: "s3cret".to_string() }],
+        )
+        .unwrap();
+        let tracker = HttpTracker::with_config(&config);
+        let response = tracker
+            .announce(&format!("http://{}/announce", addr), &AnnounceRequest::default())
+            .await
+            .unwrap();
+        assert_eq!(response.interval, 1800);
+        
+        let request = server.await.unwrap();
+        assert!(request.contains("\r\nuser-agent: qbittorrent/4.6.2\r\n"), "{}", request);
+        assert!(request.contains("\r\nx-proxy-token: s3cret\r\n"), "{}", request);
+    }
+
+    /// HTTPS tracker on 127.0.0.1 presenting `cert_pem` (issued for
+    /// "localhost" by testdata/ca.pem); every announce gets no peers
+    async fn tls_tracker(cert_pem: &str) -> std::net::SocketAddr {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        
+        let identity = native_tls::Identity::from_pkcs8(cert_pem.as_bytes(), include_bytes!("testdata/server.key")).unwrap();
+        let acceptor = tokio_native_tls::TlsAcceptor::from(native_tls::TlsAcceptor::new(identity).unwrap());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((socket, _)) = listener.accept().await {
+                let acceptor = acceptor.clone();
+                tokio::spawn(async move {
+                    // Clients that reject the certificate end the handshake here
+                    let Ok(mut stream) = acceptor.accept(socket).await else { return };
+                    let mut request = Vec::new();
+                    let mut buf = [0u8; 1024];
+                    while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+                        match stream.read(&mut buf).await {
+                            Ok(n) if n > 0 => request.extend_from_slice(&buf[..n]),
+                            _ => return,
+                        }
+                    }
+                    let body = b"d8:intervali1800e5:peers0:e";
+                    let head = format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", body.len());
+                    let _ = stream.write_all(head.as_bytes()).await;
+                    let _ = stream.write_all(body).await;
+                    let _ = stream.shutdown().await;
+                });
+            }
+        });
+        addr
+    }
+    
+    async fn tls_failure(tracker: &HttpTracker, url: &str) -> Option<tls::TlsFailure> {
+        match tracker.announce(url, &AnnounceRequest::default()).await {
+            Err(Error::TlsError(text)) => tls::TlsFailure::classify(&text),
+            Err(e) => panic!("expected a TLS error from {}, got {}", url, e),
+            Ok(_) => None,
+        }
+    }
+    
+    #[tokio::test]
+    async fn test_https_tracker_certificate_errors() {
+        let valid = tls_tracker(include_str!("testdata/server.pem")).await.port();
+        let expired = tls_tracker(include_str!("testdata/expired.pem")).await.port();
+        let dir = tempfile::TempDir::new().unwrap();
+        let ca_file = dir.path().join("ca.pem");
+        std::fs::write(&ca_file, include_str!("testdata/ca.pem")).unwrap();
+        
+        let url = |host: &str, port: u16| format!("https://{}:{}/announce", host, port);
+        
+        let default = HttpTracker::new();
+        assert_eq!(tls_failure(&default, &url("localhost", valid)).await, Some(tls::TlsFailure::UnknownIssuer));
+        
+        let config = TrackerHttpConfig::default().with_tls(ca_file.to_str(), &[]).unwrap();
+        let trusted = HttpTracker::with_config(&config);
+        assert_eq!(tls_failure(&trusted, &url("localhost", valid)).await, None);
+        assert_eq!(tls_failure(&trusted, &url("127.0.0.1", valid)).await, Some(tls::TlsFailure::HostnameMismatch));
+        assert_eq!(tls_failure(&trusted, &url("localhost", expired)).await, Some(tls::TlsFailure::Expired));
+    }
+    
+    #[tokio::test]
+    async fn test_insecure_override_is_scoped_to_exact_host() {
+        let port = tls_tracker(include_str!("testdata/server.pem")).await.port();
+        let config = TrackerHttpConfig::default().with_tls(None, &["LOCALHOST".to_string()]).unwrap();
+        let tracker = HttpTracker::with_config(&config);
+        
+        assert_eq!(tls_failure(&tracker, &format!("https://localhost:{}/announce", port)).await, None);
+        // The same server under another name is still checked
+        assert_eq!(
+            tls_failure(&tracker, &format!("https://127.0.0.1:{}/announce", port)).await,
+            Some(tls::TlsFailure::UnknownIssuer)
+        );
+        
+        assert!(TrackerHttpConfig::default().with_tls(None, &["*.localhost".to_string()]).is_err());
+    }
 }
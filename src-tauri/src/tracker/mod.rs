@@ -2,10 +2,28 @@
 //! 
 //! Implements HTTP and UDP tracker protocols for peer discovery.
 
+pub mod external_ip;
+pub mod health;
 pub mod http;
+pub mod redact;
+pub mod tls;
+pub mod udp;
 
 use serde::{Deserialize, Serialize};
-use std::net::SocketAddr;
+use std::collections::HashSet;
+use std::net::{IpAddr, SocketAddr};
+
+/// Announce floor for trackers that don't send a "min interval" (seconds)
+pub const DEFAULT_MIN_ANNOUNCE_INTERVAL: u32 = 60;
+
+/// An announce interval below this (seconds) is a tracker asking to hear
+/// back soon, and is followed instead of waiting for the regular schedule
+pub const SHORT_INTERVAL_HINT: u32 = 300;
+
+/// `notify_on_tracker_warning` is on unless turned off
+pub fn default_notify_on_tracker_warning() -> bool {
+    true
+}
 
 /// Tracker announce response
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -30,6 +48,20 @@ pub struct AnnounceResponse {
     
     /// List of peers
     pub peers: Vec<Peer>,
+
+    /// Our address as the tracker saw it ("external ip"), if it said
+    pub external_ip: Option<IpAddr>,
+}
+
+/// One torrent's entry in a tracker scrape response
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScrapeResponse {
+    /// Number of seeders
+    pub complete: u32,
+    /// Number of leechers
+    pub incomplete: u32,
+    /// Completed downloads the tracker has seen
+    pub downloaded: u32,
 }
 
 /// Peer information from tracker
@@ -99,6 +131,10 @@ pub struct AnnounceRequest {
     
     /// Event type
     pub event: AnnounceEvent,
+
+    /// Anonymous mode: send no User-Agent (`peer_id` is expected to be the
+    /// anonymous one already)
+    pub anonymous: bool,
 }
 
 impl Default for AnnounceRequest {
@@ -113,6 +149,7 @@ impl Default for AnnounceRequest {
             compact: true,
             numwant: Some(50),
             event: AnnounceEvent::None,
+            anonymous: false,
         }
     }
 }
@@ -128,13 +165,93 @@ pub enum TrackerStatus {
     Error,
     /// Tracker disabled by user
     Disabled,
+    /// Tracker refused this torrent for good (see `PermanentFailure`); no
+    /// longer announced to
+    NotRegistered,
+    /// The tracker's certificate was rejected (see `TrackerInfo::tls_failure`)
+    CertificateError,
+}
+
+/// "failure reason"s that won't go away by retrying
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PermanentFailure {
+    /// Torrent deleted from (or never uploaded to) the tracker
+    Unregistered,
+    /// Torrent banned by the tracker
+    TorrentBanned,
+    /// Our client isn't allowed on the tracker
+    ClientBanned,
+    /// The passkey in the announce URL is wrong or revoked
+    PasskeyInvalid,
+}
+
+/// Lower-case fragments of real-world failure reasons, per kind. Anything not
+/// listed (rate limits, maintenance, database errors) is retried as usual.
+const PERMANENT_FAILURES: &[(PermanentFailure, &[&str])] = &[
+    (
+        PermanentFailure::Unregistered,
+        &[
+            "unregistered torrent",
+            "torrent not registered",
+            "torrent is not registered",
+            "torrent not found",
+            "torrent does not exist",
+            "torrent has been deleted",
+            "torrent was deleted",
+            "info_hash not found",
+            "infohash not found",
+            "unknown torrent",
+        ],
+    ),
+    (
+        PermanentFailure::TorrentBanned,
+        &["torrent banned", "torrent is banned", "torrent has been banned"],
+    ),
+    (
+        PermanentFailure::ClientBanned,
+        &[
+            "client banned",
+            "client is banned",
+            "client is not allowed",
+            "client not allowed",
+            "client is not whitelisted",
+            "client not whitelisted",
+            "not on the client whitelist",
+        ],
+    ),
+    (
+        PermanentFailure::PasskeyInvalid,
+        &[
+            "invalid passkey",
+            "passkey is invalid",
+            "passkey invalid",
+            "passkey not found",
+            "unknown passkey",
+            "invalid authkey",
+        ],
+    ),
+];
+
+impl PermanentFailure {
+    /// Classify a tracker's "failure reason"; None for transient failures
+    pub fn classify(reason: &str) -> Option<Self> {
+        let reason = reason.to_ascii_lowercase();
+        PERMANENT_FAILURES
+            .iter()
+            .find(|(_, fragments)| fragments.iter().any(|f| reason.contains(f)))
+            .map(|(kind, _)| *kind)
+    }
 }
 
 /// Detailed tracker information for UI display
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TrackerInfo {
-    /// Tracker URL
+    /// Tracker URL, passkey included; only used to announce and never sent to the UI
+    #[serde(skip_serializing, default)]
     pub url: String,
+    /// `url` with its passkeys masked, for display and logs
+    #[serde(default)]
+    pub display_url: String,
     /// Current status
     pub status: TrackerStatus,
     /// Status message or error
@@ -151,4 +268,436 @@ pub struct TrackerInfo {
     pub last_announce: Option<i64>,
     /// Next scheduled announce time (unix timestamp)
     pub next_announce: Option<i64>,
+    /// Tracker's "min interval" from the last announce (seconds)
+    #[serde(default)]
+    pub min_interval: Option<u32>,
+    /// Peers returned across all announces (repeats included)
+    #[serde(default)]
+    pub peers_returned: u64,
+    /// Returned peers no other source had reported before
+    #[serde(default)]
+    pub unique_peers: u64,
+    /// Of the unique peers, those that sent us at least one block
+    #[serde(default)]
+    pub productive_peers: u64,
+    /// Why the certificate was rejected, with `TrackerStatus::CertificateError`
+    #[serde(default)]
+    pub tls_failure: Option<tls::TlsFailure>,
+    /// The "warning message" of the last successful announce ("too many
+    /// connections from your IP"); the announce still worked, unlike `message`
+    #[serde(default)]
+    pub warning: Option<String>,
+    /// Last failure logged at warn (see `log_failure`)
+    #[serde(skip)]
+    pub failure_log: crate::utils::RepeatedError,
+}
+
+impl TrackerInfo {
+    /// A tracker not announced to yet
+    pub fn new(url: &str) -> Self {
+        Self {
+            url: url.to_string(),
+            display_url: redact::redact_tracker_url(url),
+            status: TrackerStatus::Updating,
+            message: String::new(),
+            peers: 0,
+            seeds: 0,
+            leechers: 0,
+            downloaded: 0,
+            last_announce: None,
+            next_announce: None,
+            min_interval: None,
+            peers_returned: 0,
+            unique_peers: 0,
+            productive_peers: 0,
+            tls_failure: None,
+            warning: None,
+            failure_log: Default::default(),
+        }
+    }
+
+    /// Record a successful announce. Returns its warning message if it
+    /// differs from the previous announce's, so the same warning sent with
+    /// every announce is only reported once.
+    pub fn record_announce(&mut self, response: &AnnounceResponse, now: i64) -> Option<String> {
+        self.failure_log.clear();
+        self.status = TrackerStatus::Working;
+        self.tls_failure = None;
+        self.message = "Announce OK".to_string();
+        self.peers = response.peers.len() as u32;
+        self.seeds = response.complete;
+        self.leechers = response.incomplete;
+        self.last_announce = Some(now);
+        self.next_announce = Some(now + response.interval as i64);
+        self.min_interval = response.min_interval;
+        self.peers_returned += response.peers.len() as u64;
+
+        let warning = response.warning_message.as_deref().map(str::trim).filter(|w| !w.is_empty());
+        if warning == self.warning.as_deref() {
+            return None;
+        }
+        self.warning = warning.map(str::to_string);
+        self.warning.clone()
+    }
+
+    /// Log a failed announce: at warn when it's the first failure since the
+    /// tracker last worked or the error changed, at debug while it repeats
+    pub fn log_failure(&mut self, error: &str) {
+        if self.failure_log.is_new(error) {
+            tracing::warn!("Tracker announce failed ({}): {}", self.display_url, error);
+        } else {
+            tracing::debug!("Tracker announce failed again ({}): {}", self.display_url, error);
+        }
+    }
+
+    /// Record a rejected certificate; `error` is the full error text
+    pub fn record_tls_failure(&mut self, error: &str) {
+        let failure = tls::TlsFailure::classify(error).unwrap_or(tls::TlsFailure::Other);
+        self.status = TrackerStatus::CertificateError;
+        self.tls_failure = Some(failure);
+        self.message = format!("{} ({})", failure.describe(), error);
+    }
+
+    /// Record a permanent refusal; the tracker is skipped from now on
+    pub fn record_permanent_failure(&mut self, reason: &str) {
+        self.status = TrackerStatus::NotRegistered;
+        self.message = reason.to_string();
+        self.next_announce = None;
+    }
+
+    /// Whether an unscheduled announce (resume, port change) may be sent now
+    /// without undercutting the tracker's minimum interval
+    pub fn announce_allowed(&self, now: i64) -> bool {
+        self.announce_allowed_at().map_or(true, |at| now >= at)
+    }
+
+    /// When the tracker's minimum interval since the last announce ends
+    pub fn announce_allowed_at(&self) -> Option<i64> {
+        let floor = self.min_interval.unwrap_or(DEFAULT_MIN_ANNOUNCE_INTERVAL) as i64;
+        self.last_announce.map(|last| last + floor)
+    }
+
+    /// Disabled by the user: not announced to or scraped, last stats kept
+    pub fn disable(&mut self) {
+        self.status = TrackerStatus::Disabled;
+        self.message = "Disabled".to_string();
+        self.next_announce = None;
+    }
+}
+
+/// Seconds until the next announce a short interval in `response` asks for
+/// (see `SHORT_INTERVAL_HINT`), no sooner than the tracker's minimum
+/// interval; None leaves it to the regular schedule
+pub fn early_reannounce(response: &AnnounceResponse) -> Option<u32> {
+    (response.interval < SHORT_INTERVAL_HINT)
+        .then(|| response.interval.max(response.min_interval.unwrap_or(DEFAULT_MIN_ANNOUNCE_INTERVAL)))
+}
+
+/// Outcome of enabling or disabling a tracker of one torrent
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TrackerToggle {
+    /// The tracker, passkeys masked
+    pub display_url: String,
+    pub enabled: bool,
+    /// Set when the torrent is left without a working tracker
+    pub warning: Option<String>,
+    /// Every disabled tracker of the torrent, to persist on the session
+    #[serde(skip)]
+    pub disabled: Vec<String>,
+}
+
+/// A torrent's trackers in announce order: the primary announce, then the
+/// announce-list tiers, duplicates and blanks dropped
+pub fn tracker_urls(metainfo: &crate::torrent::Metainfo) -> Vec<String> {
+    let mut urls: Vec<String> = Vec::new();
+    for url in std::iter::once(&metainfo.announce).chain(metainfo.announce_list.iter().flatten()) {
+        if !url.is_empty() && !urls.contains(url) {
+            urls.push(url.clone());
+        }
+    }
+    urls
+}
+
+/// Whether `url` is a UDP tracker (BEP 15) rather than an HTTP(S) one
+pub fn is_udp(url: &str) -> bool {
+    url.starts_with("udp://")
+}
+
+/// The trackers of `metainfo` an announce round tries, in order: HTTP(S)
+/// and UDP ones not in `disabled`. A disabled tracker counts as absent from its
+/// tier, so the rest of the tier is tried before falling back to the next.
+pub fn announce_order(metainfo: &crate::torrent::Metainfo, disabled: &HashSet<String>) -> Vec<String> {
+    tracker_urls(metainfo)
+        .into_iter()
+        .filter(|url| url.starts_with("http://") || url.starts_with("https://") || is_udp(url))
+        .filter(|url| !disabled.contains(url))
+        .collect()
+}
+
+/// Whether every tracker of a torrent is failing, so an outage is logged
+/// when it starts and ends rather than on every announce round
+#[derive(Debug, Default)]
+pub struct TrackerOutage {
+    failing: bool,
+}
+
+impl TrackerOutage {
+    /// Note the outcome of an announce round for `torrent`
+    pub fn record(&mut self, torrent: &str, all_failed: bool) {
+        match (self.failing, all_failed) {
+            (false, true) => tracing::error!("All trackers failed to announce {}", torrent),
+            (true, true) => tracing::debug!("All trackers still failing for {}", torrent),
+            (true, false) => tracing::info!("Trackers reachable again for {}", torrent),
+            (false, false) => {}
+        }
+        self.failing = all_failed;
+    }
+
+    /// Whether the last announce round failed on every tracker
+    pub fn is_failing(&self) -> bool {
+        self.failing
+    }
+}
+
+/// Swarm size as last reported by the trackers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SwarmStats {
+    /// Peers with the complete torrent
+    pub seeds: u32,
+    /// Peers still downloading
+    pub leechers: u32,
+    /// When the trackers reported this (unix timestamp)
+    pub updated_at: i64,
+}
+
+impl SwarmStats {
+    /// Largest counts among the working trackers, if any
+    pub fn from_trackers(trackers: &[TrackerInfo]) -> Option<Self> {
+        trackers
+            .iter()
+            .filter(|t| t.status == TrackerStatus::Working)
+            .map(|t| SwarmStats {
+                seeds: t.seeds,
+                leechers: t.leechers,
+                updated_at: t.last_announce.unwrap_or_default(),
+            })
+            .reduce(|a, b| SwarmStats {
+                seeds: a.seeds.max(b.seeds),
+                leechers: a.leechers.max(b.leechers),
+                updated_at: a.updated_at.max(b.updated_at),
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(complete: u32, incomplete: u32) -> AnnounceResponse {
+        AnnounceResponse {
+            warning_message: None,
+            interval: 1800,
+            min_interval: None,
+            tracker_id: None,
+            complete,
+            incomplete,
+            peers: Vec::new(),
+            external_ip: None,
+        }
+    }
+
+    /// Level of each event logged while running `f`
+    fn logged_levels(f: impl FnOnce()) -> Vec<tracing::Level> {
+        use std::sync::{Arc, Mutex};
+        use tracing_subscriber::layer::SubscriberExt;
+
+        struct Capture(Arc<Mutex<Vec<tracing::Level>>>);
+        impl<S: tracing::Subscriber> tracing_subscriber::Layer<S> for Capture {
+            fn on_event(&self, event: &tracing::Event<'_>, _: tracing_subscriber::layer::Context<'_, S>) {
+                self.0.lock().unwrap().push(*event.metadata().level());
+            }
+        }
+
+        let levels = Arc::new(Mutex::new(Vec::new()));
+        let subscriber = tracing_subscriber::registry().with(Capture(levels.clone()));
+        tracing::subscriber::with_default(subscriber, f);
+        let levels = levels.lock().unwrap().clone();
+        levels
+    }
+
+    #[test]
+    fn test_repeated_failures_are_logged_quietly() {
+        use tracing::Level;
+
+        let mut dead = TrackerInfo::new("http://dead/announce");
+        let levels = logged_levels(|| {
+            dead.log_failure("connection refused");
+            dead.log_failure("connection refused");
+            dead.log_failure("connection refused");
+            // A different error is worth a warning
+            dead.log_failure("timed out");
+            dead.log_failure("timed out");
+            // So is failing again after recovering
+            dead.record_announce(&response(1, 1), 100);
+            dead.log_failure("timed out");
+        });
+        assert_eq!(
+            levels,
+            vec![Level::WARN, Level::DEBUG, Level::DEBUG, Level::WARN, Level::DEBUG, Level::WARN]
+        );
+
+        let mut outage = TrackerOutage::default();
+        let levels = logged_levels(|| {
+            outage.record("t", false);
+            outage.record("t", true);
+            outage.record("t", true);
+            outage.record("t", true);
+            outage.record("t", false);
+            outage.record("t", false);
+            outage.record("t", true);
+        });
+        assert_eq!(
+            levels,
+            vec![Level::ERROR, Level::DEBUG, Level::DEBUG, Level::INFO, Level::ERROR]
+        );
+    }
+
+    #[test]
+    fn test_swarm_stats_take_max_of_working_trackers() {
+        let mut a = TrackerInfo::new("http://a/announce");
+        let mut b = TrackerInfo::new("http://b/announce");
+        let mut broken = TrackerInfo::new("http://c/announce");
+        a.record_announce(&response(12, 3), 100);
+        b.record_announce(&response(7, 9), 200);
+        broken.record_announce(&response(500, 500), 300);
+        broken.status = TrackerStatus::Error;
+
+        assert_eq!(a.next_announce, Some(1900));
+        assert_eq!(
+            SwarmStats::from_trackers(&[a, b, broken.clone()]),
+            Some(SwarmStats { seeds: 12, leechers: 9, updated_at: 200 })
+        );
+        assert_eq!(SwarmStats::from_trackers(&[broken]), None);
+    }
+
+    #[test]
+    fn test_announce_floor() {
+        let mut t = TrackerInfo::new("http://a/announce");
+        assert!(t.announce_allowed(0));
+
+        t.record_announce(&response(1, 1), 1000);
+        assert!(!t.announce_allowed(1000 + DEFAULT_MIN_ANNOUNCE_INTERVAL as i64 - 1));
+        assert!(t.announce_allowed(1000 + DEFAULT_MIN_ANNOUNCE_INTERVAL as i64));
+
+        // The tracker's own floor wins when it sends one
+        let mut strict = response(1, 1);
+        strict.min_interval = Some(900);
+        t.record_announce(&strict, 2000);
+        assert!(!t.announce_allowed(2600));
+        assert!(t.announce_allowed(2900));
+    }
+
+    #[test]
+    fn test_warnings_are_reported_once() {
+        let mut t = TrackerInfo::new("http://a/announce");
+        let warned = |warning: Option<&str>| AnnounceResponse {
+            warning_message: warning.map(str::to_string),
+            ..response(1, 1)
+        };
+
+        assert_eq!(t.record_announce(&warned(None), 100), None);
+        let first = t.record_announce(&warned(Some("Too many connections from your IP")), 200);
+        assert_eq!(first.as_deref(), Some("Too many connections from your IP"));
+        assert_eq!(t.message, "Announce OK");
+        assert_eq!(t.status, TrackerStatus::Working);
+
+        // Sent with every announce: kept, not reported again
+        assert_eq!(t.record_announce(&warned(Some(" Too many connections from your IP ")), 300), None);
+        assert_eq!(t.warning.as_deref(), Some("Too many connections from your IP"));
+
+        let changed = t.record_announce(&warned(Some("Please update your client")), 400);
+        assert_eq!(changed.as_deref(), Some("Please update your client"));
+
+        // Gone, then back: new again
+        assert_eq!(t.record_announce(&warned(Some("")), 500), None);
+        assert_eq!(t.warning, None);
+        assert!(t.record_announce(&warned(Some("Please update your client")), 600).is_some());
+    }
+
+    #[test]
+    fn test_short_interval_is_a_reannounce_hint() {
+        let with = |interval: u32, min_interval: Option<u32>| AnnounceResponse {
+            interval,
+            min_interval,
+            ..response(1, 1)
+        };
+        assert_eq!(early_reannounce(&with(1800, None)), None);
+        assert_eq!(early_reannounce(&with(SHORT_INTERVAL_HINT, None)), None);
+        assert_eq!(early_reannounce(&with(120, None)), Some(120));
+        // Never under the tracker's floor, or ours without one
+        assert_eq!(early_reannounce(&with(120, Some(180))), Some(180));
+        assert_eq!(early_reannounce(&with(0, None)), Some(DEFAULT_MIN_ANNOUNCE_INTERVAL));
+    }
+
+    #[test]
+    fn test_classify_failure_reasons() {
+        use PermanentFailure::*;
+        let permanent = [
+            ("Unregistered torrent", Unregistered),
+            ("unregistered torrent", Unregistered),
+            ("Torrent not registered with this tracker.", Unregistered),
+            ("torrent not found", Unregistered),
+            ("Torrent has been deleted.", Unregistered),
+            ("Torrent does not exist", Unregistered),
+            ("info_hash not found in the database", Unregistered),
+            ("Torrent is banned", TorrentBanned),
+            ("Your client is banned.", ClientBanned),
+            ("Your client is not allowed on this tracker", ClientBanned),
+            ("Client not whitelisted", ClientBanned),
+            ("Invalid passkey (32 - 0123456789abcdef0123456789abcdef)", PasskeyInvalid),
+            ("Passkey not found", PasskeyInvalid),
+            ("Unknown passkey. Please redownload the torrent file.", PasskeyInvalid),
+        ];
+        for (reason, kind) in permanent {
+            assert_eq!(PermanentFailure::classify(reason), Some(kind), "{}", reason);
+        }
+
+        let transient = [
+            "Tracker is down for maintenance",
+            "You are announcing too fast, slow down",
+            "Too many requests",
+            "Rate limited",
+            "Database error, please try again later",
+            "Unregistered IP address",
+            "You are already downloading this torrent from another location",
+            "Internal server error",
+            "",
+        ];
+        for reason in transient {
+            assert_eq!(PermanentFailure::classify(reason), None, "{}", reason);
+        }
+    }
+
+    #[test]
+    fn test_disabled_tracker_is_absent_from_its_tier() {
+        let mut metainfo = crate::torrent::Metainfo::from_magnet([1; 20], None, vec![]);
+        metainfo.announce = "http://a/announce".to_string();
+        metainfo.announce_list = vec![
+            vec!["http://a/announce".to_string(), "http://b/announce".to_string()],
+            vec!["udp://c:1337/announce".to_string(), "http://d/announce".to_string()],
+            vec!["wss://e/announce".to_string()],
+        ];
+        assert_eq!(tracker_urls(&metainfo).len(), 5);
+        assert_eq!(
+            announce_order(&metainfo, &HashSet::new()),
+            vec!["http://a/announce", "http://b/announce", "udp://c:1337/announce", "http://d/announce"]
+        );
+
+        // The rest of the tier goes first, ahead of the fallback tier
+        let disabled = HashSet::from(["http://a/announce".to_string()]);
+        assert_eq!(announce_order(&metainfo, &disabled), vec!["http://b/announce", "udp://c:1337/announce", "http://d/announce"]);
+
+        let disabled = HashSet::from(["http://a/announce".to_string(), "http://b/announce".to_string()]);
+        assert_eq!(announce_order(&metainfo, &disabled), vec!["udp://c:1337/announce", "http://d/announce"]);
+    }
 }
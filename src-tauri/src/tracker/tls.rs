@@ -0,0 +1,157 @@
+//! Certificate problems with HTTPS trackers
+//!
+//! reqwest reports a rejected certificate as a generic request error with the
+//! real cause a few sources down. `TlsFailure::classify` reads that chain (see
+//! `clock::error_text`) so the tracker list can say what is wrong instead.
+//! Matching is on message text because the TLS backend, and with it the
+//! wording, depends on the platform.
+
+use crate::error::{Error, Result};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// Why a tracker's certificate was rejected
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TlsFailure {
+    /// Expired or not yet valid (or the system clock is wrong)
+    Expired,
+    /// Self-signed, or signed by a CA we don't trust
+    UnknownIssuer,
+    /// Issued for a different host name
+    HostnameMismatch,
+    /// Any other handshake or certificate error
+    Other,
+}
+
+/// Lower-case fragments of backend messages (OpenSSL, SChannel, Security.framework, rustls)
+const UNKNOWN_ISSUER: &[&str] = &[
+    "unable to get local issuer certificate",
+    "self-signed certificate",
+    "self signed certificate",
+    "unable to verify the first certificate",
+    "unknownissuer",
+    "unknown ca",
+    "not trusted",
+    "untrusted root",
+];
+const HOSTNAME_MISMATCH: &[&str] = &[
+    "hostname mismatch",
+    "notvalidforname",
+    "certificate is not valid for",
+    "target principal name is incorrect",
+    "ip address mismatch",
+];
+/// Anything else mentioning these is counted as `Other`
+const TLS_CONTEXT: &[&str] = &["certificate", "tls", "ssl", "handshake"];
+
+impl TlsFailure {
+    /// Classify a request error's full text; None if it isn't a TLS error
+    pub fn classify(text: &str) -> Option<Self> {
+        let lower = text.to_ascii_lowercase();
+        let failure = if crate::clock::is_certificate_validity_error(text) {
+            Self::Expired
+        } else if HOSTNAME_MISMATCH.iter().any(|needle| lower.contains(needle)) {
+            Self::HostnameMismatch
+        } else if UNKNOWN_ISSUER.iter().any(|needle| lower.contains(needle)) {
+            Self::UnknownIssuer
+        } else if TLS_CONTEXT.iter().any(|ctx| lower.contains(ctx)) {
+            Self::Other
+        } else {
+            return None;
+        };
+        Some(failure)
+    }
+
+    /// What the user sees in the tracker list
+    pub fn describe(self) -> &'static str {
+        match self {
+            Self::Expired => "The tracker's certificate has expired or is not valid yet",
+            Self::UnknownIssuer => {
+                "The tracker's certificate is not signed by a trusted authority; add your organization's CA in the advanced settings"
+            }
+            Self::HostnameMismatch => "The tracker's certificate was issued for a different host name",
+            Self::Other => "The secure connection to the tracker failed",
+        }
+    }
+}
+
+/// Read a PEM file of extra root certificates, rejecting one with none
+pub fn load_root_ca(path: &Path) -> Result<Vec<u8>> {
+    let pem = std::fs::read(path)
+        .map_err(|e| Error::ValidationError(format!("can't read CA file {}: {}", path.display(), e)))?;
+    match reqwest::Certificate::from_pem_bundle(&pem) {
+        Ok(certs) if !certs.is_empty() => Ok(pem),
+        Ok(_) => Err(Error::ValidationError(format!("{} contains no certificates", path.display()))),
+        Err(e) => Err(Error::ValidationError(format!("invalid CA file {}: {}", path.display(), e))),
+    }
+}
+
+/// Normalize a host for the "accept invalid certificates" list. Only exact
+/// host names are allowed: no wildcards, schemes, ports or paths, so the
+/// override can't quietly cover more than the one tracker it was meant for.
+pub fn insecure_host(host: &str) -> Result<String> {
+    let host = host.trim().trim_end_matches('.').to_ascii_lowercase();
+    let valid = !host.is_empty()
+        && host
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.');
+    if !valid {
+        return Err(Error::ValidationError(format!(
+            "{:?} is not a plain host name (no wildcards, ports or URLs)",
+            host
+        )));
+    }
+    Ok(host)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classify_backend_messages() {
+        let cases = [
+            (
+                "error sending request: client error (Connect): error:0A000086:SSL routines:tls_post_process_server_certificate:certificate verify failed:(certificate has expired)",
+                Some(TlsFailure::Expired),
+            ),
+            (
+                "error sending request: certificate verify failed:(unable to get local issuer certificate)",
+                Some(TlsFailure::UnknownIssuer),
+            ),
+            ("invalid peer certificate: UnknownIssuer", Some(TlsFailure::UnknownIssuer)),
+            ("SSL routines::certificate verify failed:(Hostname mismatch)", Some(TlsFailure::HostnameMismatch)),
+            (
+                "The target principal name is incorrect. (os error -2146762481)",
+                Some(TlsFailure::HostnameMismatch),
+            ),
+            ("TLS handshake eof", Some(TlsFailure::Other)),
+            ("error sending request: connection refused", None),
+            ("operation timed out", None),
+        ];
+        for (text, expected) in cases {
+            assert_eq!(TlsFailure::classify(text), expected, "{}", text);
+        }
+    }
+
+    #[test]
+    fn test_insecure_hosts_are_exact_names() {
+        assert_eq!(insecure_host(" Tracker.Example.org. ").unwrap(), "tracker.example.org");
+        for bad in ["", "*.example.org", "https://example.org", "example.org:443", "example.org/announce"] {
+            assert!(insecure_host(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_load_root_ca() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let good = dir.path().join("ca.pem");
+        std::fs::write(&good, include_str!("testdata/ca.pem")).unwrap();
+        assert!(load_root_ca(&good).is_ok());
+
+        let empty = dir.path().join("empty.pem");
+        std::fs::write(&empty, "not a certificate\n").unwrap();
+        assert!(load_root_ca(&empty).is_err());
+        assert!(load_root_ca(&dir.path().join("missing.pem")).is_err());
+    }
+}
@@ -0,0 +1,509 @@
+//! UDP tracker protocol implementation
+//!
+//! Reference: http://bittorrent.org/beps/bep_0015.html
+//!
+//! An announce is two exchanges: a connect, answered with a connection ID
+//! the tracker accepts for a minute, then the announce itself under that
+//! ID. The ID is kept for later announces to the same tracker. A request
+//! without an answer is sent again after 15 * 2^n seconds as the spec says,
+//! but only `DEFAULT_RETRANSMITS` times rather than its eight: the announce
+//! round waits on each tracker in turn, and an hour spent on one that's
+//! gone would hold up all the others.
+
+use crate::error::{Error, Result};
+use crate::tracker::{AnnounceEvent, AnnounceRequest, AnnounceResponse, Peer};
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::time::Instant;
+
+/// Magic constant opening every connect request
+pub const PROTOCOL_ID: u64 = 0x41727101980;
+
+const ACTION_CONNECT: u32 = 0;
+const ACTION_ANNOUNCE: u32 = 1;
+const ACTION_ERROR: u32 = 3;
+
+/// How long a connection ID may be used after it was received
+pub const CONNECTION_ID_LIFETIME: Duration = Duration::from_secs(60);
+
+/// Wait for the answer to the first transmission; doubles with each
+/// retransmission
+pub const BASE_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Retransmissions before giving up: 45 s in all, about what an HTTP
+/// tracker gets
+pub const DEFAULT_RETRANSMITS: u32 = 1;
+
+/// Largest datagram read, room for over a thousand IPv4 peers
+const MAX_PACKET: usize = 8192;
+
+/// UDP tracker client
+pub struct UdpTracker {
+    base_timeout: Duration,
+    retransmits: u32,
+    /// Connection ID and when it was received, per tracker address
+    connections: Mutex<HashMap<SocketAddr, (u64, Instant)>>,
+}
+
+impl UdpTracker {
+    /// Create a UDP tracker client with the spec's timeouts
+    pub fn new() -> Self {
+        Self::with_timeouts(BASE_TIMEOUT, DEFAULT_RETRANSMITS)
+    }
+
+    /// Create a client that waits `base_timeout * 2^n` for the answer to
+    /// transmission n, sending each request at most `retransmits` more times
+    pub fn with_timeouts(base_timeout: Duration, retransmits: u32) -> Self {
+        Self {
+            base_timeout,
+            retransmits,
+            connections: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Send announce request to tracker
+    ///
+    /// The attempt count carries over from connect to announce, so a
+    /// tracker that answers neither gets the same number of tries as one
+    /// that never answers the announce.
+    pub async fn announce(&self, tracker_url: &str, request: &AnnounceRequest) -> Result<AnnounceResponse> {
+        let addr = resolve(tracker_url).await?;
+        tracing::debug!("Announcing to tracker: {}", super::redact::redact_tracker_url(tracker_url));
+
+        let local: SocketAddr = match addr {
+            SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+            SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+        };
+        let socket = UdpSocket::bind(local)
+            .await
+            .map_err(|e| Error::NetworkError(format!("Failed to open UDP socket: {}", e)))?;
+        socket
+            .connect(addr)
+            .await
+            .map_err(|e| Error::NetworkError(format!("UDP connect failed: {}", e)))?;
+
+        for attempt in 0..=self.retransmits {
+            let timeout = self.base_timeout * 2u32.saturating_pow(attempt.min(8));
+            let connection_id = match self.connection_id(addr) {
+                Some(id) => id,
+                None => {
+                    let packet = connect_packet(rand::random());
+                    match self.exchange(addr, &socket, &packet, ACTION_CONNECT, timeout).await? {
+                        Some(body) => {
+                            let id = parse_connect(&body)?;
+                            self.connections.lock().unwrap().insert(addr, (id, Instant::now()));
+                            id
+                        }
+                        None => continue,
+                    }
+                }
+            };
+
+            let packet = announce_packet(connection_id, rand::random(), request);
+            if let Some(body) = self.exchange(addr, &socket, &packet, ACTION_ANNOUNCE, timeout).await? {
+                return parse_announce(&body, addr.is_ipv6());
+            }
+        }
+
+        Err(Error::Timeout(format!(
+            "No answer from {} after {} attempts",
+            super::redact::redact_tracker_url(tracker_url),
+            self.retransmits + 1
+        )))
+    }
+
+    /// The cached connection ID for `addr`, while it's still valid
+    fn connection_id(&self, addr: SocketAddr) -> Option<u64> {
+        let mut connections = self.connections.lock().unwrap();
+        match connections.get(&addr) {
+            Some(&(id, received)) if received.elapsed() < CONNECTION_ID_LIFETIME => Some(id),
+            Some(_) => {
+                connections.remove(&addr);
+                None
+            }
+            None => None,
+        }
+    }
+
+    /// Send `packet` and wait up to `timeout` for the answer with its
+    /// transaction ID; None when none came. Datagrams for other
+    /// transactions (late answers to an earlier transmission) are skipped.
+    async fn exchange(
+        &self,
+        addr: SocketAddr,
+        socket: &UdpSocket,
+        packet: &[u8],
+        action: u32,
+        timeout: Duration,
+    ) -> Result<Option<Vec<u8>>> {
+        let transaction_id = &packet[12..16];
+        socket
+            .send(packet)
+            .await
+            .map_err(|e| Error::NetworkError(format!("UDP send failed: {}", e)))?;
+
+        let deadline = Instant::now() + timeout;
+        let mut buf = vec![0u8; MAX_PACKET];
+        loop {
+            let len = match tokio::time::timeout_at(deadline, socket.recv(&mut buf)).await {
+                Err(_) => return Ok(None),
+                Ok(Err(e)) => return Err(Error::NetworkError(format!("UDP receive failed: {}", e))),
+                Ok(Ok(len)) => len,
+            };
+            let answer = &buf[..len];
+            if len < 8 || &answer[4..8] != transaction_id {
+                tracing::debug!("Ignoring {} byte datagram for another transaction", len);
+                continue;
+            }
+
+            return match read_u32(answer, 0) {
+                ACTION_ERROR => {
+                    // The connection ID may be what it objects to
+                    self.connections.lock().unwrap().remove(&addr);
+                    let message = String::from_utf8_lossy(&answer[8..]);
+                    Err(Error::TrackerFailure(message.trim_end_matches('\0').to_string()))
+                }
+                answered if answered == action => Ok(Some(answer[8..].to_vec())),
+                answered => Err(Error::ProtocolViolation(format!(
+                    "UDP tracker answered action {} with action {}",
+                    action, answered
+                ))),
+            };
+        }
+    }
+}
+
+impl Default for UdpTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The address of a `udp://host:port/...` tracker
+async fn resolve(tracker_url: &str) -> Result<SocketAddr> {
+    let url = reqwest::Url::parse(tracker_url)
+        .map_err(|e| Error::NetworkError(format!("Invalid tracker URL: {}", e)))?;
+    let port = url
+        .port()
+        .ok_or_else(|| Error::NetworkError("UDP tracker URL has no port".to_string()))?;
+    let host = url
+        .host_str()
+        .ok_or_else(|| Error::NetworkError("UDP tracker URL has no host".to_string()))?;
+    // IPv6 literals keep their brackets in the URL
+    if let Ok(ip) = host.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>() {
+        return Ok(SocketAddr::new(ip, port));
+    }
+    let mut addrs = tokio::net::lookup_host((host, port))
+        .await
+        .map_err(|e| Error::NetworkError(format!("Failed to resolve {}: {}", host, e)))?;
+    addrs.next().ok_or_else(|| Error::NetworkError(format!("No address for {}", host)))
+}
+
+/// Connect request: protocol ID, action, transaction ID
+fn connect_packet(transaction_id: u32) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(16);
+    packet.extend_from_slice(&PROTOCOL_ID.to_be_bytes());
+    packet.extend_from_slice(&ACTION_CONNECT.to_be_bytes());
+    packet.extend_from_slice(&transaction_id.to_be_bytes());
+    packet
+}
+
+/// Announce request (98 bytes)
+fn announce_packet(connection_id: u64, transaction_id: u32, request: &AnnounceRequest) -> Vec<u8> {
+    let event: u32 = match request.event {
+        AnnounceEvent::None => 0,
+        AnnounceEvent::Completed => 1,
+        AnnounceEvent::Started => 2,
+        AnnounceEvent::Stopped => 3,
+    };
+    // -1 asks for the tracker's default
+    let numwant = request.numwant.map_or(-1, |n| n.min(i32::MAX as u32) as i32);
+
+    let mut packet = Vec::with_capacity(98);
+    packet.extend_from_slice(&connection_id.to_be_bytes());
+    packet.extend_from_slice(&ACTION_ANNOUNCE.to_be_bytes());
+    packet.extend_from_slice(&transaction_id.to_be_bytes());
+    packet.extend_from_slice(&request.info_hash);
+    packet.extend_from_slice(&request.peer_id);
+    packet.extend_from_slice(&request.downloaded.to_be_bytes());
+    packet.extend_from_slice(&request.left.to_be_bytes());
+    packet.extend_from_slice(&request.uploaded.to_be_bytes());
+    packet.extend_from_slice(&event.to_be_bytes());
+    // IP and key are left 0, as over HTTP: the tracker sees our address
+    // anyway, and a stable key would link announces together
+    packet.extend_from_slice(&0u32.to_be_bytes());
+    packet.extend_from_slice(&0u32.to_be_bytes());
+    packet.extend_from_slice(&numwant.to_be_bytes());
+    packet.extend_from_slice(&request.port.to_be_bytes());
+    packet
+}
+
+/// The connection ID of a connect response, after action and transaction
+fn parse_connect(body: &[u8]) -> Result<u64> {
+    let bytes: [u8; 8] = body
+        .get(..8)
+        .and_then(|id| id.try_into().ok())
+        .ok_or_else(|| Error::ProtocolViolation("connect response too short".to_string()))?;
+    Ok(u64::from_be_bytes(bytes))
+}
+
+/// An announce response after action and transaction: interval, leechers,
+/// seeders, then 6 bytes per IPv4 peer or 18 per IPv6 one (trackers answer
+/// in the family they were asked over)
+fn parse_announce(body: &[u8], ipv6: bool) -> Result<AnnounceResponse> {
+    if body.len() < 12 {
+        return Err(Error::ProtocolViolation("announce response too short".to_string()));
+    }
+    let peers = &body[12..];
+    let peer_len = if ipv6 { 18 } else { 6 };
+    if peers.len() % peer_len != 0 {
+        return Err(Error::ProtocolViolation(format!(
+            "peers length must be multiple of {}",
+            peer_len
+        )));
+    }
+
+    let peers = peers
+        .chunks_exact(peer_len)
+        .map(|chunk| {
+            let (ip, port) = chunk.split_at(peer_len - 2);
+            let ip = match <[u8; 16]>::try_from(ip) {
+                Ok(octets) => IpAddr::V6(Ipv6Addr::from(octets)),
+                Err(_) => IpAddr::V4(Ipv4Addr::new(ip[0], ip[1], ip[2], ip[3])),
+            };
+            Peer {
+                peer_id: None,
+                addr: SocketAddr::new(ip, u16::from_be_bytes([port[0], port[1]])),
+            }
+        })
+        .collect();
+
+    Ok(AnnounceResponse {
+        warning_message: None,
+        interval: read_u32(body, 0),
+        min_interval: None,
+        tracker_id: None,
+        complete: read_u32(body, 8),
+        incomplete: read_u32(body, 4),
+        peers,
+        external_ip: None,
+    })
+}
+
+/// Big-endian u32 at `offset`; the caller checked the length
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A socket standing in for the tracker, and its URL
+    async fn mock_tracker() -> (UdpSocket, String) {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("udp://{}/announce", socket.local_addr().unwrap());
+        (socket, url)
+    }
+
+    async fn receive(mock: &UdpSocket) -> (Vec<u8>, SocketAddr) {
+        let mut buf = vec![0u8; MAX_PACKET];
+        let (len, from) = mock.recv_from(&mut buf).await.unwrap();
+        buf.truncate(len);
+        (buf, from)
+    }
+
+    /// Action and transaction ID, then `body`
+    fn answer(action: u32, transaction_id: &[u8], body: &[u8]) -> Vec<u8> {
+        let mut packet = action.to_be_bytes().to_vec();
+        packet.extend_from_slice(transaction_id);
+        packet.extend_from_slice(body);
+        packet
+    }
+
+    fn announce_body(peers: &[SocketAddr]) -> Vec<u8> {
+        let mut body = Vec::new();
+        for value in [1800u32, 7, 3] {
+            body.extend_from_slice(&value.to_be_bytes());
+        }
+        for peer in peers {
+            let SocketAddr::V4(v4) = peer else { unreachable!() };
+            body.extend_from_slice(&v4.ip().octets());
+            body.extend_from_slice(&v4.port().to_be_bytes());
+        }
+        body
+    }
+
+    /// Answer a connect with `connection_id`, after a datagram for another
+    /// transaction that must be ignored
+    async fn answer_connect(mock: &UdpSocket, connection_id: u64) {
+        let (connect, from) = receive(mock).await;
+        assert_eq!(connect.len(), 16);
+        assert_eq!(connect[..8], PROTOCOL_ID.to_be_bytes());
+        assert_eq!(read_u32(&connect, 8), ACTION_CONNECT);
+
+        let stray: Vec<u8> = connect[12..16].iter().map(|b| !b).collect();
+        mock.send_to(&answer(ACTION_CONNECT, &stray, &7u64.to_be_bytes()), from).await.unwrap();
+        mock.send_to(&answer(ACTION_CONNECT, &connect[12..16], &connection_id.to_be_bytes()), from)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_connect_and_announce_packets() {
+        let (mock, url) = mock_tracker().await;
+        let tracker = UdpTracker::with_timeouts(Duration::from_secs(5), 0);
+        let request = AnnounceRequest {
+            info_hash: [1; 20],
+            peer_id: [2; 20],
+            port: 51413,
+            uploaded: 3,
+            downloaded: 4,
+            left: 5,
+            numwant: Some(30),
+            event: AnnounceEvent::Started,
+            ..Default::default()
+        };
+        let peers: Vec<SocketAddr> = vec!["10.0.0.1:6881".parse().unwrap(), "10.0.0.2:51413".parse().unwrap()];
+
+        let script = async {
+            answer_connect(&mock, 0xC0FFEE).await;
+
+            let (announce, from) = receive(&mock).await;
+            assert_eq!(announce.len(), 98);
+            assert_eq!(announce[..8], 0xC0FFEEu64.to_be_bytes());
+            assert_eq!(read_u32(&announce, 8), ACTION_ANNOUNCE);
+            assert_eq!(announce[16..36], [1; 20]);
+            assert_eq!(announce[36..56], [2; 20]);
+            assert_eq!(announce[56..64], 4u64.to_be_bytes());
+            assert_eq!(announce[64..72], 5u64.to_be_bytes());
+            assert_eq!(announce[72..80], 3u64.to_be_bytes());
+            assert_eq!(read_u32(&announce, 80), 2, "started");
+            assert_eq!(announce[84..92], [0; 8], "no IP or key");
+            assert_eq!(read_u32(&announce, 92), 30);
+            assert_eq!(announce[96..], 51413u16.to_be_bytes());
+            mock.send_to(&answer(ACTION_ANNOUNCE, &announce[12..16], &announce_body(&peers)), from)
+                .await
+                .unwrap();
+        };
+        let (response, ()) = tokio::join!(tracker.announce(&url, &request), script);
+        let response = response.unwrap();
+        assert_eq!((response.interval, response.incomplete, response.complete), (1800, 7, 3));
+        assert_eq!(response.peers.iter().map(|peer| peer.addr).collect::<Vec<_>>(), peers);
+
+        // The connection ID is reused within its minute, with -1 for the
+        // tracker's default number of peers
+        let request = AnnounceRequest { numwant: None, event: AnnounceEvent::None, ..request };
+        let script = async {
+            let (announce, from) = receive(&mock).await;
+            assert_eq!(announce[..8], 0xC0FFEEu64.to_be_bytes());
+            assert_eq!(read_u32(&announce, 80), 0);
+            assert_eq!(announce[92..96], (-1i32).to_be_bytes());
+            mock.send_to(&answer(ACTION_ANNOUNCE, &announce[12..16], &announce_body(&[])), from)
+                .await
+                .unwrap();
+        };
+        let (response, ()) = tokio::join!(tracker.announce(&url, &request), script);
+        assert!(response.unwrap().peers.is_empty());
+
+        // And asked for again once it has expired
+        for (_, received) in tracker.connections.lock().unwrap().values_mut() {
+            *received -= CONNECTION_ID_LIFETIME;
+        }
+        let script = async {
+            answer_connect(&mock, 0xBEEF).await;
+            let (announce, from) = receive(&mock).await;
+            assert_eq!(announce[..8], 0xBEEFu64.to_be_bytes());
+            mock.send_to(&answer(ACTION_ANNOUNCE, &announce[12..16], &announce_body(&[])), from)
+                .await
+                .unwrap();
+        };
+        let (response, ()) = tokio::join!(tracker.announce(&url, &request), script);
+        response.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_tracker_that_never_answers() {
+        let (mock, url) = mock_tracker().await;
+        let base = Duration::from_millis(40);
+        let tracker = UdpTracker::with_timeouts(base, 2);
+
+        let started = Instant::now();
+        let result = tracker.announce(&url, &AnnounceRequest::default()).await;
+        assert!(matches!(result, Err(Error::Timeout(_))), "{:?}", result);
+        // 40 + 80 + 160 ms
+        assert!(started.elapsed() >= base * 7, "{:?}", started.elapsed());
+
+        // Three connect requests, each its own transaction
+        let mut transactions = Vec::new();
+        for _ in 0..3 {
+            let (connect, _) = receive(&mock).await;
+            assert_eq!(connect[..8], PROTOCOL_ID.to_be_bytes());
+            assert_eq!(read_u32(&connect, 8), ACTION_CONNECT);
+            transactions.push(read_u32(&connect, 12));
+        }
+        transactions.dedup();
+        assert_eq!(transactions.len(), 3);
+        assert!(tracker.connections.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_error_responses() {
+        let (mock, url) = mock_tracker().await;
+        let tracker = UdpTracker::with_timeouts(Duration::from_secs(5), 0);
+        let request = AnnounceRequest::default();
+
+        // An error action carries the reason, NUL padding dropped
+        let script = async {
+            answer_connect(&mock, 0xC0FFEE).await;
+            let (announce, from) = receive(&mock).await;
+            mock.send_to(&answer(ACTION_ERROR, &announce[12..16], b"Unregistered torrent\0"), from)
+                .await
+                .unwrap();
+        };
+        let (result, ()) = tokio::join!(tracker.announce(&url, &request), script);
+        match result {
+            Err(Error::TrackerFailure(reason)) => assert_eq!(reason, "Unregistered torrent"),
+            other => panic!("expected a tracker failure, got {:?}", other),
+        }
+        // The connection ID is dropped with it
+        assert!(tracker.connections.lock().unwrap().is_empty());
+
+        // An answer with the wrong action is a protocol violation
+        let script = async {
+            let (connect, from) = receive(&mock).await;
+            mock.send_to(&answer(ACTION_ANNOUNCE, &connect[12..16], &[0; 12]), from).await.unwrap();
+        };
+        let (result, ()) = tokio::join!(tracker.announce(&url, &request), script);
+        assert!(matches!(result, Err(Error::ProtocolViolation(_))));
+
+        // As is a truncated peer list
+        let script = async {
+            answer_connect(&mock, 0xC0FFEE).await;
+            let (announce, from) = receive(&mock).await;
+            let mut body = announce_body(&["10.0.0.1:6881".parse().unwrap()]);
+            body.pop();
+            mock.send_to(&answer(ACTION_ANNOUNCE, &announce[12..16], &body), from).await.unwrap();
+        };
+        let (result, ()) = tokio::join!(tracker.announce(&url, &request), script);
+        assert!(matches!(result, Err(Error::ProtocolViolation(_))));
+
+        assert!(matches!(
+            tracker.announce("udp://127.0.0.1/announce", &request).await,
+            Err(Error::NetworkError(_))
+        ));
+    }
+
+    #[test]
+    fn test_parse_ipv6_peers() {
+        let mut body = announce_body(&[]);
+        body.extend_from_slice(&Ipv6Addr::LOCALHOST.octets());
+        body.extend_from_slice(&6881u16.to_be_bytes());
+        let response = parse_announce(&body, true).unwrap();
+        assert_eq!(response.peers.len(), 1);
+        assert_eq!(response.peers[0].addr, "[::1]:6881".parse().unwrap());
+    }
+}
@@ -0,0 +1,292 @@
+//! Opt-in per-torrent transfer log
+//!
+//! For debugging one misbehaving swarm without drowning the global log: while
+//! enabled, the engine and its peer manager record connections, chokes and
+//! piece traffic into a bounded ring (and optionally a JSON-lines file in the
+//! logs dir). Disabled, recording is a single relaxed atomic load.
+
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Records kept per torrent; older ones are dropped
+pub const TRANSFER_LOG_CAPACITY: usize = 10_000;
+
+/// Most records returned by one `page` call
+pub const MAX_PAGE_SIZE: usize = 1_000;
+
+/// What happened
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum TransferEvent {
+    /// `warning` is the tracker's "warning message", if it sent one
+    Announce { tracker: String, result: String, warning: Option<String> },
+    ConnectAttempt { peer: SocketAddr },
+    ConnectFailed { peer: SocketAddr, error: String },
+    HandshakeFailed { peer: SocketAddr, error: String },
+    Connected { peer: SocketAddr },
+    Disconnected { peer: SocketAddr, reason: String },
+    Choked { peer: SocketAddr },
+    Unchoked { peer: SocketAddr },
+    PieceRequested { peer: SocketAddr, piece: usize, blocks: usize },
+    PieceVerified { peer: SocketAddr, piece: usize },
+    PieceFailed { peer: SocketAddr, piece: usize, error: String },
+}
+
+/// One log entry
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TransferRecord {
+    /// Increases by one per record, from 1; never reused while the app runs
+    pub seq: u64,
+    /// Unix timestamp in milliseconds
+    pub at_ms: i64,
+    #[serde(flatten)]
+    pub event: TransferEvent,
+}
+
+/// A page of records, for `get_torrent_debug_log`
+#[derive(Debug, Clone, Serialize)]
+pub struct TransferLogPage {
+    pub records: Vec<TransferRecord>,
+    /// Pass as `since_seq` to get the records after this page
+    pub next_seq: u64,
+    /// Records after `since_seq` that had already been dropped from the ring
+    pub missed: u64,
+    pub enabled: bool,
+}
+
+#[derive(Debug, Default)]
+struct Ring {
+    records: VecDeque<TransferRecord>,
+    last_seq: u64,
+    file: Option<File>,
+}
+
+/// A torrent's transfer log, shared by its engine and peer manager
+#[derive(Debug)]
+pub struct TransferLog {
+    enabled: AtomicBool,
+    capacity: usize,
+    ring: Mutex<Ring>,
+}
+
+impl Default for TransferLog {
+    fn default() -> Self {
+        Self::with_capacity(TRANSFER_LOG_CAPACITY)
+    }
+}
+
+impl TransferLog {
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            enabled: AtomicBool::new(false),
+            capacity,
+            ring: Mutex::new(Ring::default()),
+        }
+    }
+
+    #[inline]
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    /// Start recording, also appending to `file` if given
+    pub fn enable(&self, file: Option<PathBuf>) -> std::io::Result<()> {
+        let file = match file {
+            Some(path) => {
+                if let Some(dir) = path.parent() {
+                    std::fs::create_dir_all(dir)?;
+                }
+                Some(OpenOptions::new().create(true).append(true).open(path)?)
+            }
+            None => None,
+        };
+        self.ring.lock().unwrap().file = file;
+        self.enabled.store(true, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Stop recording and close the file; the ring stays readable
+    pub fn disable(&self) {
+        self.enabled.store(false, Ordering::Relaxed);
+        self.ring.lock().unwrap().file = None;
+    }
+
+    /// Record an event. `event` is only built while logging is enabled, so
+    /// hot paths pay nothing for formatting otherwise.
+    #[inline]
+    pub fn record(&self, event: impl FnOnce() -> TransferEvent) {
+        if self.is_enabled() {
+            self.push(event());
+        }
+    }
+
+    fn push(&self, event: TransferEvent) {
+        let mut ring = self.ring.lock().unwrap();
+        // Disabled while we waited for the lock
+        if !self.is_enabled() {
+            return;
+        }
+        ring.last_seq += 1;
+        let record = TransferRecord {
+            seq: ring.last_seq,
+            at_ms: chrono::Utc::now().timestamp_millis(),
+            event,
+        };
+
+        if let Some(file) = ring.file.as_mut() {
+            let written = serde_json::to_string(&record)
+                .map_err(std::io::Error::from)
+                .and_then(|line| writeln!(file, "{}", line));
+            if let Err(e) = written {
+                tracing::warn!("Transfer log file write failed, continuing without it: {}", e);
+                ring.file = None;
+            }
+        }
+
+        if ring.records.len() >= self.capacity {
+            ring.records.pop_front();
+        }
+        ring.records.push_back(record);
+    }
+
+    /// Records with `seq > since_seq`, oldest first, at most `limit`
+    /// (capped at `MAX_PAGE_SIZE`)
+    pub fn page(&self, since_seq: u64, limit: usize) -> TransferLogPage {
+        let ring = self.ring.lock().unwrap();
+        let first_kept = ring.records.front().map_or(ring.last_seq + 1, |r| r.seq);
+        let records: Vec<TransferRecord> = ring
+            .records
+            .iter()
+            .skip_while(|r| r.seq <= since_seq)
+            .take(limit.min(MAX_PAGE_SIZE))
+            .cloned()
+            .collect();
+        TransferLogPage {
+            next_seq: records.last().map_or(since_seq.max(first_kept - 1), |r| r.seq),
+            missed: first_kept.saturating_sub(since_seq + 1),
+            records,
+            enabled: self.is_enabled(),
+        }
+    }
+}
+
+/// File a torrent's log is mirrored to (named by info hash)
+pub fn log_file_path(info_hash: &str) -> PathBuf {
+    crate::data_dir::data_dir()
+        .join(crate::data_dir::LOGS_DIR)
+        .join(format!("transfer-{}.jsonl", info_hash.to_lowercase()))
+}
+
+/// Every torrent's transfer log, by info hash (see `AppState::transfer_logs`)
+#[derive(Debug, Default)]
+pub struct TransferLogs {
+    logs: Mutex<HashMap<String, Arc<TransferLog>>>,
+}
+
+impl TransferLogs {
+    /// The torrent's log, created (disabled) on first use
+    pub fn get(&self, torrent_id: &str) -> Arc<TransferLog> {
+        self.logs.lock().unwrap().entry(torrent_id.to_string()).or_default().clone()
+    }
+
+    pub fn remove(&self, torrent_id: &str) {
+        if let Some(log) = self.logs.lock().unwrap().remove(torrent_id) {
+            log.disable();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn peer() -> SocketAddr {
+        "10.0.0.1:6881".parse().unwrap()
+    }
+
+    fn seqs(page: &TransferLogPage) -> Vec<u64> {
+        page.records.iter().map(|r| r.seq).collect()
+    }
+
+    #[test]
+    fn test_paging() {
+        let log = TransferLog::with_capacity(5);
+        log.enable(None).unwrap();
+        let empty = log.page(0, 10);
+        assert!(empty.records.is_empty());
+        assert_eq!((empty.next_seq, empty.missed), (0, 0));
+
+        for _ in 0..3 {
+            log.record(|| TransferEvent::Connected { peer: peer() });
+        }
+        let page = log.page(0, 2);
+        assert_eq!(seqs(&page), vec![1, 2]);
+        let page = log.page(page.next_seq, 2);
+        assert_eq!(seqs(&page), vec![3]);
+        let page = log.page(page.next_seq, 2);
+        assert!(page.records.is_empty());
+        assert_eq!(page.next_seq, 3);
+
+        // The ring drops the oldest; a reader that fell behind is told how many it missed
+        for _ in 0..5 {
+            log.record(|| TransferEvent::Choked { peer: peer() });
+        }
+        let page = log.page(1, 10);
+        assert_eq!(seqs(&page), vec![4, 5, 6, 7, 8]);
+        assert_eq!(page.missed, 2);
+        let page = log.page(0, 0);
+        assert!(page.records.is_empty());
+        assert_eq!((page.next_seq, page.missed), (3, 3));
+    }
+
+    #[test]
+    fn test_disabled_log_builds_nothing() {
+        let log = TransferLog::default();
+        log.record(|| unreachable!("event built while disabled"));
+        assert!(log.page(0, 10).records.is_empty());
+
+        log.enable(None).unwrap();
+        log.record(|| TransferEvent::Unchoked { peer: peer() });
+        log.disable();
+        log.record(|| unreachable!("event built after disabling"));
+
+        // What was recorded stays readable; numbering continues after a re-enable
+        let page = log.page(0, 10);
+        assert_eq!(seqs(&page), vec![1]);
+        assert!(!page.enabled);
+        log.enable(None).unwrap();
+        log.record(|| TransferEvent::Unchoked { peer: peer() });
+        assert_eq!(seqs(&log.page(1, 10)), vec![2]);
+    }
+
+    #[test]
+    fn test_disabling_stops_file_growth() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let path = temp_dir.path().join("logs").join("transfer-abc.jsonl");
+        let log = TransferLog::default();
+
+        log.enable(Some(path.clone())).unwrap();
+        log.record(|| TransferEvent::PieceVerified { peer: peer(), piece: 3 });
+        log.record(|| TransferEvent::ConnectFailed { peer: peer(), error: "refused".to_string() });
+        let contents = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = contents.lines().collect();
+        assert_eq!(lines.len(), 2);
+        let first: serde_json::Value = serde_json::from_str(lines[0]).unwrap();
+        assert_eq!(first["seq"], 1);
+        assert_eq!(first["kind"], "piece_verified");
+        assert_eq!(first["piece"], 3);
+
+        log.disable();
+        let size = std::fs::metadata(&path).unwrap().len();
+        for _ in 0..10 {
+            log.record(|| TransferEvent::Connected { peer: peer() });
+        }
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), size);
+    }
+}
@@ -29,6 +29,47 @@ pub fn generate_peer_id() -> [u8; 20] {
     peer_id
 }
 
+/// Generate a peer ID that carries no client signature
+///
+/// All 20 bytes are random printable ASCII, so neither the client nor its
+/// version can be read from it.
+pub fn generate_anonymous_peer_id() -> [u8; 20] {
+    let mut peer_id = [0u8; 20];
+    let mut rng = rand::thread_rng();
+    for byte in &mut peer_id {
+        *byte = rng.gen_range(33..=126);
+    }
+    peer_id
+}
+
+/// The peer IDs a torrent can present: the usual `-SC0100-` one and an
+/// anonymous one, both generated per torrent so they can't be linked across
+/// torrents. Which one goes out is decided at each announce/handshake.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PeerIdentity {
+    branded: [u8; 20],
+    anonymous: [u8; 20],
+}
+
+impl PeerIdentity {
+    /// Generate a fresh pair of IDs
+    pub fn generate() -> Self {
+        Self {
+            branded: generate_peer_id(),
+            anonymous: generate_anonymous_peer_id(),
+        }
+    }
+
+    /// Peer ID to send right now
+    pub fn peer_id(&self, anonymous: bool) -> [u8; 20] {
+        if anonymous {
+            self.anonymous
+        } else {
+            self.branded
+        }
+    }
+}
+
 /// Format bytes as human-readable size
 pub fn format_bytes(bytes: u64) -> String {
     const UNITS: &[&str] = &["B", "KiB", "MiB", "GiB", "TiB", "PiB"];
@@ -85,6 +126,29 @@ pub fn calculate_eta(remaining_bytes: u64, download_speed: u64) -> Option<u64> {
     Some(remaining_bytes / download_speed)
 }
 
+/// Remembers the last error of something that fails over and over (a dead
+/// tracker, a provider poll), so only changes are logged loudly
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RepeatedError {
+    last: Option<String>,
+}
+
+impl RepeatedError {
+    /// Whether `error` differs from the one before it; it becomes the new last error
+    pub fn is_new(&mut self, error: &str) -> bool {
+        if self.last.as_deref() == Some(error) {
+            return false;
+        }
+        self.last = Some(error.to_string());
+        true
+    }
+
+    /// Forget the last error (after a success)
+    pub fn clear(&mut self) {
+        self.last = None;
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -113,6 +177,19 @@ mod tests {
         assert_ne!(peer_id, peer_id2);
     }
 
+    #[test]
+    fn test_peer_identity() {
+        let identity = PeerIdentity::generate();
+        assert_eq!(&identity.peer_id(false)[0..8], b"-SC0100-");
+
+        let anonymous = identity.peer_id(true);
+        assert_ne!(&anonymous[0..8], b"-SC0100-");
+        assert!(anonymous.iter().all(|b| (33..=126).contains(b)));
+
+        // Each torrent gets its own anonymous ID
+        assert_ne!(anonymous, PeerIdentity::generate().peer_id(true));
+    }
+
     #[test]
     fn test_format_bytes() {
         assert_eq!(format_bytes(0), "0 B");
@@ -0,0 +1,197 @@
+//! Recovering from system sleep
+//!
+//! After a suspend, timers that were due while the machine slept all fire at
+//! once: every torrent announces together and every outstanding block request
+//! times out. A watchdog notices the sleep from how far the clocks moved
+//! between two of its ticks (the monotonic clock stops during sleep on Linux
+//! and macOS, the wall clock doesn't; on Windows both keep running but the
+//! tick is late) and publishes a `Wake`. Engines then re-announce in
+//! staggered slots and peer managers push their request deadlines back.
+//!
+//! A large forward step of the wall clock (e.g. a manual correction) looks
+//! the same as a sleep; the only cost is one round of staggered announces.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::sync::watch;
+
+/// How often the watchdog looks at the clocks
+pub const WATCHDOG_PERIOD: Duration = Duration::from_secs(5);
+
+/// Extra gap between two watchdog ticks that counts as a sleep
+pub const WAKE_THRESHOLD: Duration = Duration::from_secs(30);
+
+/// Spacing of the announces due after a wake, across all torrents
+pub const ANNOUNCE_STAGGER: Duration = Duration::from_millis(500);
+
+/// A detected sleep
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Wake {
+    /// Roughly how long the machine was asleep
+    pub slept: Duration,
+    /// How much of that the monotonic clock saw; deadlines kept as
+    /// `Instant`s are pushed back by this much
+    pub monotonic: Duration,
+}
+
+/// Finds sleeps in successive (monotonic, wall clock) readings that are
+/// normally at most `period` apart
+#[derive(Debug)]
+pub struct DriftDetector {
+    period: Duration,
+    threshold: Duration,
+    last: Option<(Instant, SystemTime)>,
+}
+
+impl DriftDetector {
+    pub const fn new(period: Duration, threshold: Duration) -> Self {
+        Self { period, threshold, last: None }
+    }
+
+    /// Record a reading; returns the sleep that ended since the last one
+    pub fn observe(&mut self, monotonic: Instant, wall: SystemTime) -> Option<Wake> {
+        let (last_monotonic, last_wall) = self.last.replace((monotonic, wall))?;
+        let monotonic_gap = monotonic.saturating_duration_since(last_monotonic);
+        // A wall clock stepping back is someone fixing it, not a sleep
+        let wall_gap = wall.duration_since(last_wall).unwrap_or_default();
+        let gap = monotonic_gap.max(wall_gap);
+        (gap > self.period + self.threshold).then(|| Wake {
+            slept: gap,
+            monotonic: monotonic_gap.saturating_sub(self.period),
+        })
+    }
+}
+
+/// Hands out evenly spaced slots from a shared schedule
+#[derive(Debug)]
+pub struct Stagger {
+    spacing: Duration,
+    next: Option<tokio::time::Instant>,
+}
+
+impl Stagger {
+    pub const fn new(spacing: Duration) -> Self {
+        Self { spacing, next: None }
+    }
+
+    /// The earliest free slot at or after `now`
+    pub fn slot(&mut self, now: tokio::time::Instant) -> tokio::time::Instant {
+        let slot = self.next.map_or(now, |next| next.max(now));
+        self.next = Some(slot + self.spacing);
+        slot
+    }
+}
+
+static DETECTOR: Mutex<DriftDetector> = Mutex::new(DriftDetector::new(WATCHDOG_PERIOD, WAKE_THRESHOLD));
+
+/// Set once the watchdog runs; without its regular readings a long gap
+/// between two checks means nothing
+static WATCHING: AtomicBool = AtomicBool::new(false);
+
+static ANNOUNCES: Mutex<Stagger> = Mutex::new(Stagger::new(ANNOUNCE_STAGGER));
+
+fn wakes() -> &'static watch::Sender<Option<Wake>> {
+    static WAKES: OnceLock<watch::Sender<Option<Wake>>> = OnceLock::new();
+    WAKES.get_or_init(|| watch::channel(None).0)
+}
+
+/// Wakes detected from now on
+pub fn subscribe() -> watch::Receiver<Option<Wake>> {
+    wakes().subscribe()
+}
+
+/// Look at the clocks now and publish a wake if the machine slept. Besides
+/// the watchdog, timers that may be overdue from a sleep call this before
+/// acting, so they see the wake even when they fire before the watchdog.
+pub fn check() -> Option<Wake> {
+    if !WATCHING.load(Ordering::Acquire) {
+        return None;
+    }
+    let wake = DETECTOR.lock().unwrap().observe(Instant::now(), SystemTime::now())?;
+    tracing::info!(
+        slept_secs = wake.slept.as_secs(),
+        "Resumed from sleep (slept ~{})",
+        crate::clock::describe_duration(wake.slept.as_secs())
+    );
+    wakes().send_replace(Some(wake));
+    Some(wake)
+}
+
+/// When the next announce overdue from a sleep may go out
+pub fn announce_slot() -> tokio::time::Instant {
+    ANNOUNCES.lock().unwrap().slot(tokio::time::Instant::now())
+}
+
+/// Watch for sleeps for the lifetime of the app
+pub async fn start_wake_task() {
+    let mut watchdog = tokio::time::interval(WATCHDOG_PERIOD);
+    watchdog.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    WATCHING.store(true, Ordering::Release);
+    loop {
+        watchdog.tick().await;
+        check();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PERIOD: Duration = Duration::from_secs(5);
+
+    fn detector() -> (DriftDetector, Instant, SystemTime) {
+        let mut detector = DriftDetector::new(PERIOD, Duration::from_secs(30));
+        let (monotonic, wall) = (Instant::now(), SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000));
+        assert_eq!(detector.observe(monotonic, wall), None);
+        (detector, monotonic, wall)
+    }
+
+    #[test]
+    fn test_regular_ticks_are_not_sleeps() {
+        let (mut detector, mut monotonic, mut wall) = detector();
+        for _ in 0..10 {
+            monotonic += PERIOD;
+            wall += PERIOD;
+            assert_eq!(detector.observe(monotonic, wall), None);
+        }
+        // A late tick on a busy machine, and the wall clock being set back
+        assert_eq!(detector.observe(monotonic + Duration::from_secs(20), wall + Duration::from_secs(20)), None);
+        assert_eq!(detector.observe(monotonic + Duration::from_secs(25), wall - Duration::from_secs(3600)), None);
+    }
+
+    #[test]
+    fn test_sleep_with_stopped_monotonic_clock() {
+        // Linux and macOS: only the wall clock moves while asleep
+        let (mut detector, monotonic, wall) = detector();
+        let wake = detector.observe(monotonic + PERIOD, wall + Duration::from_secs(3 * 3600)).unwrap();
+        assert_eq!(wake, Wake { slept: Duration::from_secs(3 * 3600), monotonic: Duration::ZERO });
+
+        // Back to normal afterwards
+        let wall = wall + Duration::from_secs(3 * 3600) + PERIOD;
+        assert_eq!(detector.observe(monotonic + 2 * PERIOD, wall), None);
+    }
+
+    #[test]
+    fn test_sleep_with_running_monotonic_clock() {
+        // Windows: both clocks move, the tick just comes late
+        let (mut detector, monotonic, wall) = detector();
+        let slept = Duration::from_secs(600);
+        let wake = detector.observe(monotonic + slept, wall + slept).unwrap();
+        assert_eq!(wake, Wake { slept, monotonic: slept - PERIOD });
+    }
+
+    #[test]
+    fn test_stagger_spaces_due_announces() {
+        let mut stagger = Stagger::new(Duration::from_millis(500));
+        let now = tokio::time::Instant::now();
+        let slots: Vec<Duration> = (0..4).map(|_| stagger.slot(now) - now).collect();
+        assert_eq!(slots, [0, 500, 1000, 1500].map(Duration::from_millis));
+
+        // Later requests continue the schedule, or start afresh once it has passed
+        assert_eq!(stagger.slot(now + Duration::from_secs(1)) - now, Duration::from_millis(2000));
+        let later = now + Duration::from_secs(60);
+        assert_eq!(stagger.slot(later), later);
+        assert_eq!(stagger.slot(later) - later, Duration::from_millis(500));
+    }
+}
@@ -0,0 +1,194 @@
+//! Command layer tests: drive the `*_internal` commands against a real
+//! `AppState` over a temporary database, without a Tauri app.
+
+#![cfg(feature = "app")]
+
+use seedcore_lib::commands::{
+    add_magnet_link_internal, add_peer_manually_internal, add_torrent_file_internal, load_saved_torrents_internal,
+    pause_torrent_internal, remove_torrent_internal, start_torrent_internal, update_settings_internal,
+};
+use seedcore_lib::peer::manual::ManualDial;
+use seedcore_lib::database::Database;
+use seedcore_lib::state::{AppState, TorrentState};
+use std::future::Future;
+use std::path::Path;
+use std::time::Duration;
+use tempfile::TempDir;
+
+const MAGNET_HASH: &str = "0123456789abcdef0123456789abcdef01234567";
+
+/// Commands must not wait on a running engine; fail instead of hanging
+async fn within<T>(future: impl Future<Output = T>) -> T {
+    tokio::time::timeout(Duration::from_secs(5), future)
+        .await
+        .expect("command timed out")
+}
+
+/// State over the database in `dir`, downloading into `dir/downloads`
+fn open_state(dir: &Path) -> AppState {
+    let database = Database::open(dir.join("db")).unwrap();
+    let mut settings = database.load_settings().unwrap();
+    settings.download_dir = dir.join("downloads").to_string_lossy().to_string();
+    database.save_settings(&settings).unwrap();
+    std::fs::create_dir_all(&settings.download_dir).unwrap();
+    AppState::with_database(database)
+}
+
+fn magnet() -> String {
+    format!("magnet:?xt=urn:btih:{}&dn=Test%20Magnet", MAGNET_HASH)
+}
+
+/// Write a single-file .torrent into `dir`
+fn write_torrent(dir: &Path, name: &str) -> String {
+    let mut data = Vec::new();
+    data.extend_from_slice(b"d8:announce14:http://tracker4:infod6:lengthi1234e");
+    data.extend_from_slice(format!("4:name{}:{}", name.len(), name).as_bytes());
+    data.extend_from_slice(b"12:piece_lengthi16384e6:pieces20:12345678901234567890ee");
+    let path = dir.join(format!("{}.torrent", name));
+    std::fs::write(&path, data).unwrap();
+    path.to_string_lossy().to_string()
+}
+
+async fn state_of(state: &AppState, id: &str) -> Option<TorrentState> {
+    state.torrents.read().await.get(id).map(|t| t.state.clone())
+}
+
+#[tokio::test]
+async fn test_add_start_pause_remove() {
+    let dir = TempDir::new().unwrap();
+    let state = open_state(dir.path());
+
+    let magnet_id = add_magnet_link_internal(None, &state, magnet(), false).await.unwrap();
+    assert_eq!(magnet_id, MAGNET_HASH);
+    let file_id = add_torrent_file_internal(None, &state, write_torrent(dir.path(), "file.iso"), false, false)
+        .await
+        .unwrap();
+    assert_eq!(state.engines.read().await.len(), 2);
+    assert_eq!(state.engine_controls.read().await.len(), 2);
+    assert_eq!(state_of(&state, &magnet_id).await, Some(TorrentState::Paused));
+
+    within(start_torrent_internal(&state, magnet_id.clone(), false)).await.unwrap();
+    assert_eq!(state_of(&state, &magnet_id).await, Some(TorrentState::Downloading));
+    assert!(state.engine_tasks.read().await.contains_key(&magnet_id));
+
+    // Pausing and resuming go to the running engine
+    within(pause_torrent_internal(&state, &magnet_id, TorrentState::Paused)).await.unwrap();
+    assert_eq!(state_of(&state, &magnet_id).await, Some(TorrentState::Paused));
+    within(start_torrent_internal(&state, magnet_id.clone(), false)).await.unwrap();
+    assert_eq!(state_of(&state, &magnet_id).await, Some(TorrentState::Downloading));
+
+    within(remove_torrent_internal(&state, magnet_id.clone(), false, false, false)).await.unwrap();
+    assert!(!state.engines.read().await.contains_key(&magnet_id));
+    assert!(!state.engine_controls.read().await.contains_key(&magnet_id));
+    assert!(!state.engine_tasks.read().await.contains_key(&magnet_id));
+    assert!(state.database.load_torrent(&magnet_id).unwrap().is_none());
+
+    // The paused one is untouched
+    assert_eq!(state_of(&state, &file_id).await, Some(TorrentState::Paused));
+    assert!(state.database.load_torrent(&file_id).unwrap().is_some());
+
+    assert!(within(pause_torrent_internal(&state, &magnet_id, TorrentState::Paused)).await.is_err());
+}
+
+#[tokio::test]
+async fn test_add_peer_manually() {
+    let dir = TempDir::new().unwrap();
+    let state = open_state(dir.path());
+    let id = add_magnet_link_internal(None, &state, magnet(), false).await.unwrap();
+    let add = |addr: &str| within(add_peer_manually_internal(&state, id.clone(), addr.to_string()));
+
+    // Never started, then started and paused: either way the dial waits
+    assert_eq!(add("10.0.0.5:6881").await, Ok(ManualDial::Queued));
+    within(start_torrent_internal(&state, id.clone(), false)).await.unwrap();
+    within(pause_torrent_internal(&state, &id, TorrentState::Paused)).await.unwrap();
+    assert_eq!(add("[2001:db8::1]:51413").await, Ok(ManualDial::Queued));
+
+    assert!(add("10.0.0.5").await.unwrap_err().starts_with("Missing port"));
+    assert!(add("224.0.0.1:6881").await.unwrap_err().starts_with("Filtered address"));
+    let own = format!("127.0.0.1:{}", *state.listen_port.borrow());
+    assert!(add(&own).await.unwrap_err().contains("own listen address"));
+    assert!(within(add_peer_manually_internal(&state, "f".repeat(40), "10.0.0.5:6881".to_string()))
+        .await
+        .is_err());
+
+    within(remove_torrent_internal(&state, id, false, false, false)).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_saved_torrents_survive_restart() {
+    let dir = TempDir::new().unwrap();
+    let file_id = {
+        let state = open_state(dir.path());
+        add_magnet_link_internal(None, &state, magnet(), false).await.unwrap();
+        add_torrent_file_internal(None, &state, write_torrent(dir.path(), "kept.iso"), false, false)
+            .await
+            .unwrap()
+    };
+
+    let state = open_state(dir.path());
+    assert!(state.torrents.read().await.is_empty());
+    let loaded = load_saved_torrents_internal(None, &state).await.unwrap();
+
+    let mut ids: Vec<_> = loaded.iter().map(|t| t.id.clone()).collect();
+    ids.sort();
+    let mut expected = vec![MAGNET_HASH.to_string(), file_id.clone()];
+    expected.sort();
+    assert_eq!(ids, expected);
+    assert_eq!(state.engine_controls.read().await.len(), 2);
+    let kept = loaded.iter().find(|t| t.id == file_id).unwrap();
+    assert_eq!(kept.name, "kept.iso");
+    assert_eq!(kept.size, 1234);
+
+    // Loading again doesn't duplicate anything
+    load_saved_torrents_internal(None, &state).await.unwrap();
+    assert_eq!(state.engines.read().await.len(), 2);
+
+    within(start_torrent_internal(&state, file_id.clone(), false)).await.unwrap();
+    within(pause_torrent_internal(&state, &file_id, TorrentState::Paused)).await.unwrap();
+    within(remove_torrent_internal(&state, file_id, true, false, false)).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_settings_round_trip() {
+    let dir = TempDir::new().unwrap();
+    {
+        let state = open_state(dir.path());
+        let mut settings = state.settings.read().await.clone();
+        settings.download_limit = 512 * 1024;
+        settings.upload_limit = 64 * 1024;
+        settings.max_active_downloads = 7;
+        settings.anonymous_mode = true;
+        update_settings_internal(&state, settings).await.unwrap();
+        assert!(*state.anonymous_mode.borrow());
+    }
+
+    let state = open_state(dir.path());
+    let settings = state.settings.read().await.clone();
+    assert_eq!(settings.download_limit, 512 * 1024);
+    assert_eq!(settings.upload_limit, 64 * 1024);
+    assert_eq!(settings.max_active_downloads, 7);
+    assert!(settings.anonymous_mode);
+    assert!(*state.anonymous_mode.borrow());
+
+    // A bad port is rejected and nothing is saved
+    let mut bad = settings.clone();
+    bad.listen_port = 0;
+    bad.download_limit = 1;
+    assert!(update_settings_internal(&state, bad).await.is_err());
+    assert_eq!(state.database.load_settings().unwrap().max_download_speed, 512 * 1024);
+}
+
+#[tokio::test]
+async fn test_remove_forgets_cloud_progress() {
+    let dir = TempDir::new().unwrap();
+    let state = open_state(dir.path());
+    let id = "89abcdef0123456789abcdef0123456789abcdef".to_string();
+
+    state.cloud_file_progress.start(&id, [("movie.mkv".to_string(), 100)]).await;
+    state.cloud_file_progress.finish(&id).await;
+    assert_eq!(state.cloud_file_progress.files(&id).await.len(), 1);
+
+    within(remove_torrent_internal(&state, id.clone(), false, false, false)).await.unwrap();
+    assert!(state.cloud_file_progress.files(&id).await.is_empty());
+    assert!(state.database.load_cloud_files(&id).unwrap().is_none());
+}
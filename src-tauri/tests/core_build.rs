@@ -0,0 +1,27 @@
+//! The core build: the library without the `app` feature
+//!
+//! Run with `cargo test --no-default-features --features core`. The test
+//! binary and `examples/headless_download.rs` link a `seedcore_lib` built
+//! without Tauri, and `cargo tree` confirms no Tauri crate made it into that
+//! build's dependency graph.
+
+#![cfg(all(feature = "core", not(feature = "app")))]
+
+use std::process::Command;
+
+#[test]
+fn test_no_tauri_in_dependency_graph() {
+    let output = Command::new(env!("CARGO"))
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .args(["tree", "--no-default-features", "--features", "core"])
+        .args(["--edges", "normal,build", "--prefix", "none", "--format", "{p}"])
+        .output()
+        .expect("cargo tree should run");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let tree = String::from_utf8_lossy(&output.stdout);
+    assert!(tree.lines().any(|line| line.starts_with("tokio ")), "unexpected output: {}", tree);
+    let tauri: Vec<&str> = tree.lines().filter(|line| line.starts_with("tauri")).collect();
+    assert!(tauri.is_empty(), "Tauri crates in the core build: {:?}", tauri);
+}
+
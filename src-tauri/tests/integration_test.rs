@@ -40,10 +40,10 @@ async fn test_ubuntu_torrent_download() {
     std::fs::create_dir_all(&download_dir).expect("Failed to create download dir");
     
     // 4. Create and start engine
-    let mut engine = TorrentEngine::new(metainfo, download_dir.clone());
+    let mut engine = TorrentEngine::new(metainfo, download_dir.clone(), None);
     
     // Create a channel to control the engine from a separate task
-    let command_tx = engine.command_sender();
+    let command_tx = engine.command_handle();
     
     // Spawn engine in background
     let engine_handle = tokio::spawn(async move {
@@ -51,7 +51,7 @@ async fn test_ubuntu_torrent_download() {
     });
     
     // Start the engine
-    command_tx.send(EngineCommand::Start).expect("Failed to send start command");
+    command_tx.send(EngineCommand::Start).await.expect("Failed to send start command");
     
     // 5. Monitor progress
     let mut success = false;
@@ -59,12 +59,7 @@ async fn test_ubuntu_torrent_download() {
     while attempts < 120 { // Wait up to 120 seconds
         sleep(Duration::from_secs(1)).await;
         
-        let (stats_tx, stats_rx) = tokio::sync::oneshot::channel();
-        if command_tx.send(EngineCommand::GetStats(stats_tx)).is_err() {
-            break;
-        }
-        
-        match stats_rx.await {
+        match command_tx.request_stats().await {
             Ok(stats) => {
                 println!(
                     "Time: {}s, State: {:?}, Peers: {}, Downloaded: {} bytes, Speed: {:.2} KB/s", 
@@ -84,7 +79,7 @@ async fn test_ubuntu_torrent_download() {
     }
     
     // Cleanup
-    let _ = command_tx.send(EngineCommand::Stop);
+    let _ = command_tx.stop();
     let _ = engine_handle.await;
     
     // Clean up files
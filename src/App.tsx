@@ -77,6 +77,7 @@ function App() {
       openAddTorrentModal({
         metadata: {
           ...metadata,
+          createdBy: metadata.created_by ?? undefined,
           // Map API metadata to UI metadata types if needed, or matched
           files: metadata.files.map((f, i) => ({
             path: f.path,
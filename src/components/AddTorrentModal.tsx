@@ -1,5 +1,6 @@
 import { useState, useEffect, useMemo } from "react";
 import { formatBytes } from "../lib/utils";
+import type { SwarmHealth } from "../types";
 
 // ============================================================================
 // TYPES
@@ -18,6 +19,8 @@ export interface TorrentMetadata {
   creationDate?: number;
   files: TorrentFile[];
   comment?: string;
+  createdBy?: string;
+  health?: SwarmHealth;
 }
 
 export type DownloadMode = "smart" | "cloud" | "p2p" | "hybrid";
@@ -390,6 +393,11 @@ export function AddTorrentModal({
 
             {/* Torrent Info */}
             <Section title="Torrent Info" icon={<InfoIcon />}>
+              {metadata.health?.seeds === 0 && (
+                <p className="mb-2 text-xs text-warning">
+                  No tracker knows of a seed: this torrent may never finish.
+                </p>
+              )}
               <InfoCard
                 items={[
                   {
@@ -409,6 +417,14 @@ export function AddTorrentModal({
                         ).toLocaleDateString()
                       : "Unknown",
                   },
+                  {
+                    label: "Created By",
+                    value: metadata.createdBy || "Unknown",
+                  },
+                  {
+                    label: "Swarm",
+                    value: formatSwarmHealth(metadata.health),
+                  },
                   {
                     label: "Free Space",
                     value: availableDiskSpace
@@ -783,6 +799,14 @@ interface InfoCardProps {
   items: Array<{ label: string; value: string }>;
 }
 
+function formatSwarmHealth(health?: SwarmHealth): string {
+  if (!health || health.source === "unknown") return "Unknown";
+  if (health.source === "debrid") {
+    return health.debrid_cached ? "Cached on debrid" : "Not cached on debrid";
+  }
+  return `${health.seeds ?? 0} seeds / ${health.leechers ?? 0} leechers`;
+}
+
 function InfoCard({ items }: InfoCardProps) {
   return (
     <div className="bg-dark-surface-elevated rounded-lg border border-dark-border p-3 space-y-2">
@@ -56,6 +56,11 @@ export function TorrentItem({ torrent }: TorrentItemProps) {
                             <Badge variant={getStateBadgeVariant(torrent.state)} className="text-[10px] px-1.5 py-0 h-5">
                                 {torrent.state}
                             </Badge>
+                            {torrent.name_encoding && (
+                                <Badge variant="outline" className="text-[10px] px-1.5 py-0 h-5" title={`Name transcoded from ${torrent.name_encoding}`}>
+                                    {torrent.name_encoding}
+                                </Badge>
+                            )}
                         </div>
                         <div className="text-xs text-text-secondary flex items-center gap-2">
                             <span>{formatBytes(torrent.size)}</span>
@@ -1,5 +1,6 @@
 import { useState, useEffect } from "react";
-import { TorrentInfo } from "../../types";
+import { listen } from "@tauri-apps/api/event";
+import { MetadataReadyEvent, TorrentInfo } from "../../types";
 import { formatBytes, cn } from "../../lib/utils";
 import { api } from "../../lib/api";
 import {
@@ -85,6 +86,7 @@ export function FilesTab({ torrent }: FilesTabProps) {
   const [availableSpace, setAvailableSpace] = useState<number | null>(null);
   const [files, setFiles] = useState<FileItem[]>([]);
   const [loading, setLoading] = useState(true);
+  const [metadataPending, setMetadataPending] = useState(false);
   const [expandedFolders, setExpandedFolders] = useState<Set<string>>(new Set());
 
   // ... (Data fetching logic similar to before, keeping it functional)
@@ -106,8 +108,8 @@ export function FilesTab({ torrent }: FilesTabProps) {
       try {
         if (!silent) setLoading(true);
         const fileList = await api.getFileList(torrent.id);
-        const tree = buildFileTree(fileList);
-        setFiles(tree);
+        setMetadataPending(fileList.metadata === "pending");
+        setFiles(fileList.metadata === "ready" ? buildFileTree(fileList.data) : []);
       } catch (error) {
         if (!silent) setFiles([]);
       } finally {
@@ -118,7 +120,13 @@ export function FilesTab({ torrent }: FilesTabProps) {
     const interval = setInterval(() => {
       if (torrent.state === "Downloading" || torrent.state === "Seeding") fetchFileList(true);
     }, 2000);
-    return () => clearInterval(interval);
+    const unlistenMetadata = listen<MetadataReadyEvent>("metadata-ready", (event) => {
+      if (event.payload.torrent_id === torrent.id) fetchFileList();
+    });
+    return () => {
+      clearInterval(interval);
+      unlistenMetadata.then((unlisten) => unlisten());
+    };
   }, [torrent.id, torrent.state]);
 
   const toggleFolder = (path: string) => {
@@ -251,7 +259,7 @@ export function FilesTab({ torrent }: FilesTabProps) {
         {files.length === 0 && !loading ? (
           <div className="flex flex-col items-center justify-center h-full text-text-tertiary opacity-60">
             <FolderOpen className="h-12 w-12 mb-3 opacity-50" />
-            <p>No files found</p>
+            <p>{metadataPending ? "Waiting for metadata from peers" : "No files found"}</p>
           </div>
         ) : (
           renderFileTree(files)
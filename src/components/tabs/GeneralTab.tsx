@@ -72,6 +72,9 @@ export function GeneralTab({ torrent }: GeneralTabProps) {
         {/* General Info Section */}
         <Section title="General" icon={<FileText className="h-4 w-4" />}>
           <InfoRow label="Name" value={torrent.name} className="truncate" />
+          {torrent.name_encoding && (
+            <InfoRow label="Name Encoding" value={`Transcoded from ${torrent.name_encoding}`} />
+          )}
           <InfoRow label="Status" value={torrent.state} />
           <InfoRow label="Info Hash" value={torrent.id.substring(0, 8)} mono />
           <InfoRow label="Save Path" value="/downloads" />
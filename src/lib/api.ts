@@ -9,6 +9,39 @@ import {
   CacheStatus,
   DebridFile,
   DebridProgress,
+  TransferLogPage,
+  PeerDisconnection,
+  DashboardSummary,
+  BackgroundTask,
+  EngineMetrics,
+  RestoreSummary,
+  BackupSummary,
+  DatabaseRecovery,
+  SalvageReport,
+  StorageAudit,
+  DuplicateCluster,
+  RelinkReport,
+  TokenScope,
+  ApiTokenInfo,
+  CreatedApiToken,
+  MetadataResult,
+  TlsFailure,
+  PiecesInfo,
+  PieceWindow,
+  TorrentProvenance,
+  BenchmarkOptions,
+  BenchmarkReport,
+  TorrentQuery,
+  LogUsage,
+  TorrentPage,
+  AvailabilitySample,
+  ChokeReason,
+  ChokeDecision,
+  TrafficStats,
+  ManualDial,
+  EventSchema,
+  ProviderHealth,
+  TrackerToggle,
 } from "../types";
 
 export const api = {
@@ -17,6 +50,15 @@ export const api = {
     return invoke("get_torrents");
   },
 
+  async queryTorrents(query: TorrentQuery): Promise<TorrentPage> {
+    return invoke("query_torrents", { query });
+  },
+
+  // Index torrents added before metadata search existed; returns the count
+  async rebuildSearchIndex(): Promise<number> {
+    return invoke("rebuild_search_index");
+  },
+
   async parseTorrentFile(filePath: string): Promise<TorrentMetadata> {
     return invoke("parse_torrent_file", { filePath });
   },
@@ -25,38 +67,110 @@ export const api = {
     return invoke("parse_magnet_link", { magnetUri });
   },
 
-  async addTorrentFile(filePath: string): Promise<string> {
-    return invoke("add_torrent_file", { filePath });
+  // hybrid joins a cloud download of the same torrent instead of failing
+  // with ALREADY_ADDED_ERROR
+  async addTorrentFile(
+    filePath: string,
+    renameOnCollision?: boolean,
+    hybrid?: boolean,
+  ): Promise<string> {
+    return invoke("add_torrent_file", { filePath, renameOnCollision, hybrid });
   },
 
-  async addMagnetLink(magnetUri: string): Promise<string> {
-    return invoke("add_magnet_link", { magnetUri });
+  async addMagnetLink(magnetUri: string, hybrid?: boolean): Promise<string> {
+    return invoke("add_magnet_link", { magnetUri, hybrid });
   },
 
+  // noFailover keeps the download on this provider even if it fails;
+  // hybrid joins a P2P download of the same torrent
   async addCloudTorrent(
     magnetOrHash: string,
     provider: string,
     savePath: string,
+    noFailover?: boolean,
+    hybrid?: boolean,
+  ): Promise<string> {
+    return invoke("add_cloud_torrent", { magnetOrHash, provider, savePath, noFailover, hybrid });
+  },
+
+  async addCloudTorrentFile(
+    filePath: string,
+    provider: string,
+    savePath: string,
+    noFailover?: boolean,
+    hybrid?: boolean,
   ): Promise<string> {
-    return invoke("add_cloud_torrent", { magnetOrHash, provider, savePath });
+    return invoke("add_cloud_torrent_file", { filePath, provider, savePath, noFailover, hybrid });
   },
 
-  async removeTorrent(torrentId: string, deleteFiles: boolean): Promise<void> {
-    return invoke("remove_torrent", { torrentId, deleteFiles });
+  // deleteFromProvider also deletes a cloud torrent from its debrid provider
+  async removeTorrent(
+    torrentId: string,
+    deleteFiles: boolean,
+    deleteFromProvider?: boolean,
+    // Only what a failed file allocation created (with deleteFiles off)
+    rollbackAllocation?: boolean,
+  ): Promise<void> {
+    return invoke("remove_torrent", { torrentId, deleteFiles, deleteFromProvider, rollbackAllocation });
   },
 
-  async startTorrent(torrentId: string): Promise<void> {
-    return invoke("start_torrent", { torrentId });
+  async startTorrent(
+    torrentId: string,
+    renameOnCollision?: boolean,
+  ): Promise<void> {
+    return invoke("start_torrent", { torrentId, renameOnCollision });
   },
 
   async pauseTorrent(torrentId: string): Promise<void> {
     return invoke("pause_torrent", { torrentId });
   },
 
+  // Opt a torrent out of (or back into) the dead download auto-stop
+  async setTorrentAutoStop(torrentId: string, enabled: boolean): Promise<void> {
+    return invoke("set_torrent_auto_stop", { torrentId, enabled });
+  },
+
+  // Stay announced to the trackers without peers; startTorrent resumes seeding
+  async pauseSeeding(torrentId: string): Promise<void> {
+    return invoke("pause_seeding", { torrentId });
+  },
+
+  async setQueuePosition(torrentId: string, position: number): Promise<void> {
+    return invoke("set_queue_position", { torrentId, position });
+  },
+
+  async queueMove(
+    torrentId: string,
+    direction: "up" | "down" | "top" | "bottom",
+  ): Promise<void> {
+    return invoke(`queue_move_${direction}`, { torrentId });
+  },
+
   async getTorrentDetails(torrentId: string): Promise<TorrentInfo> {
     return invoke("get_torrent_details", { torrentId });
   },
 
+  async getTorrentProvenance(torrentId: string): Promise<TorrentProvenance> {
+    return invoke("get_torrent_provenance", { torrentId });
+  },
+
+  // Writes the .torrent file the torrent was added from (if one was kept);
+  // includePasskeys: false masks the passkeys in its tracker URLs
+  async exportTorrentFile(torrentId: string, path: string, includePasskeys?: boolean): Promise<void> {
+    return invoke("export_torrent_file", { torrentId, path, includePasskeys });
+  },
+
+  // Passkeys in tracker URLs are masked unless includePasskeys is set
+  async getMagnetLink(torrentId: string, includePasskeys?: boolean): Promise<string> {
+    return invoke("get_magnet_link", { torrentId, includePasskeys });
+  },
+
+  // Split a completed consolidated download into its files (normally automatic)
+  async extractTorrentData(torrentId: string): Promise<void> {
+    return invoke("extract_torrent_data", { torrentId });
+  },
+
+  // Engines are built afterwards; each torrent then emits "torrent-ready"
   async loadSavedTorrents(): Promise<TorrentInfo[]> {
     return invoke("load_saved_torrents");
   },
@@ -75,6 +189,67 @@ export const api = {
     return invoke("get_version");
   },
 
+  async getClientInfo(): Promise<{
+    version: string;
+    tracker_user_agent: string | null;
+    external_ipv4: string | null; // As trackers report it
+    external_ipv6: string | null;
+  }> {
+    return invoke("get_client_info");
+  },
+
+  async getDiagnostics(): Promise<{
+    queued_dials: number;
+    half_open_dials: number;
+    established_connections: number;
+    connection_cap: number;
+    connections_throttled: boolean;
+    fd_limit: number | null;
+    open_fds: number | null;
+    file_handle_budget: number;
+    fd_exhaustion_errors: number;
+    engines: Record<string, EngineMetrics>;
+    sleep_inhibitor: {
+      enabled: boolean;
+      engaged: boolean;
+      active_torrents: number;
+      error: string | null; // Not retried until the setting is toggled
+    };
+    external_ip_reports: { tracker: string; ip: string }[];
+    // Saved torrents that would start under another hash than they're listed as
+    hash_mismatches: { key: string; id: string; info_hash: string }[];
+  }> {
+    return invoke("get_diagnostics");
+  },
+
+  async getBackgroundTasks(): Promise<BackgroundTask[]> {
+    return invoke("get_background_tasks");
+  },
+
+  // Queued behind the current run if the task is running
+  async runBackgroundTaskNow(name: string): Promise<void> {
+    return invoke("run_background_task_now", { name });
+  },
+
+  // Rejects while torrents or cloud downloads are busy unless force is set
+  async runBenchmark(options: BenchmarkOptions): Promise<BenchmarkReport> {
+    return invoke("run_benchmark", { options });
+  },
+
+  async cancelBenchmark(): Promise<boolean> {
+    return invoke("cancel_benchmark");
+  },
+
+  async getBenchmarkHistory(): Promise<BenchmarkReport[]> {
+    return invoke("get_benchmark_history");
+  },
+
+  async checkClock(
+    probeUrl?: string,
+  ): Promise<{ skew_secs: number; skewed: boolean; message: string }> {
+    return invoke("check_clock", { probeUrl });
+  },
+
   async greet(name: string): Promise<string> {
     return invoke("greet", { name });
   },
@@ -91,11 +266,21 @@ export const api = {
       upload_speed: number;
       downloaded: number;
       uploaded: number;
+      choke_reason?: ChokeReason | null;
     }[]
   > {
     return invoke("get_peer_list", { torrentId });
   },
 
+  // addr is "ip:port", or "[ip]:port" for IPv6
+  async addPeerManually(torrentId: string, addr: string): Promise<ManualDial> {
+    return invoke("add_peer_manually", { torrentId, addr });
+  },
+
+  async getPeerChokeHistory(torrentId: string, peerAddr: string): Promise<ChokeDecision[]> {
+    return invoke("get_peer_choke_history", { torrentId, peerAddr });
+  },
+
   async getTrackerList(torrentId: string): Promise<
     {
       url: string;
@@ -107,33 +292,122 @@ export const api = {
       downloaded: number;
       last_announce: number | null;
       next_announce: number | null;
+      peers_returned: number;
+      unique_peers: number;
+      productive_peers: number;
+      tls_failure: TlsFailure | null;
     }[]
   > {
     return invoke("get_tracker_list", { torrentId });
   },
 
-  async getPiecesInfo(torrentId: string): Promise<{
-    total_pieces: number;
-    pieces_have: number;
-    pieces_downloading: number;
-    bitfield: number[];
-    availability: number[];
-  }> {
-    return invoke("get_pieces_info", { torrentId });
+  // Bucketed above maxBuckets pieces (default 1024)
+  async getPiecesInfo(torrentId: string, maxBuckets?: number): Promise<MetadataResult<PiecesInfo>> {
+    return invoke("get_pieces_info", { torrentId, maxBuckets });
+  },
+
+  // At most 4096 pieces
+  async getPieceDetails(torrentId: string, start: number, count: number): Promise<MetadataResult<PieceWindow>> {
+    return invoke("get_piece_details", { torrentId, start, count });
+  },
+
+  // from/to are unix seconds (inclusive); omit either for an open range
+  async getAvailabilityHistory(
+    torrentId: string,
+    from?: number,
+    to?: number,
+  ): Promise<AvailabilitySample[]> {
+    return invoke("get_availability_history", { torrentId, from, to });
+  },
+
+  // Pushes "torrent-details-update" (TorrentDetailsUpdate) every second;
+  // resolves to the torrent whose subscription was evicted, if any
+  async subscribeTorrentDetails(torrentId: string): Promise<string | null> {
+    return invoke("subscribe_torrent_details", { torrentId });
+  },
+
+  async unsubscribeTorrentDetails(torrentId: string): Promise<void> {
+    return invoke("unsubscribe_torrent_details", { torrentId });
+  },
+
+  // Resolves to the log file path when writeFile is set
+  async setTorrentDebugLogging(
+    torrentId: string,
+    enabled: boolean,
+    writeFile?: boolean,
+  ): Promise<string | null> {
+    return invoke("set_torrent_debug_logging", {
+      torrentId,
+      enabled,
+      writeFile,
+    });
+  },
+
+  async getTorrentDebugLog(
+    torrentId: string,
+    sinceSeq: number,
+    limit?: number,
+  ): Promise<TransferLogPage> {
+    return invoke("get_torrent_debug_log", { torrentId, sinceSeq, limit });
+  },
+
+  async getPeerDisconnectHistory(
+    torrentId: string,
+  ): Promise<PeerDisconnection[]> {
+    return invoke("get_peer_disconnect_history", { torrentId });
+  },
+
+  async getEngineMetrics(torrentId: string): Promise<EngineMetrics> {
+    return invoke("get_engine_metrics", { torrentId });
+  },
+
+  async auditStorage(): Promise<StorageAudit> {
+    return invoke("audit_storage");
+  },
+
+  async detectDuplicateContent(): Promise<DuplicateCluster[]> {
+    return invoke("detect_duplicate_content");
+  },
+
+  async relinkDuplicates(
+    torrentId: string,
+    canonicalId: string,
+  ): Promise<RelinkReport> {
+    return invoke("relink_duplicates", { torrentId, canonicalId });
   },
 
   async getFileList(torrentId: string): Promise<
-    {
-      path: string;
-      size: number;
-      downloaded: number;
-      priority: "Skip" | "Low" | "Normal" | "High";
-      is_folder: boolean;
-    }[]
+    MetadataResult<
+      {
+        path: string;
+        size: number;
+        downloaded: number;
+        priority: "Skip" | "Low" | "Normal" | "High";
+        is_folder: boolean;
+      }[]
+    >
   > {
     return invoke("get_file_list", { torrentId });
   },
 
+  async getFilePreview(
+    torrentId: string,
+    fileIndex: number,
+  ): Promise<
+    | { status: "ready"; path: string }
+    | {
+        status: "unavailable";
+        reason:
+          | { kind: "metadataPending" }
+          | { kind: "notVideo" }
+          | { kind: "ffmpegNotConfigured" }
+          | { kind: "needMoreData"; pieces: number }
+          | { kind: "extractionFailed"; message: string };
+      }
+  > {
+    return invoke("get_file_preview", { torrentId, fileIndex });
+  },
+
   async setFilePriority(
     torrentId: string,
     fileIndex: number,
@@ -142,6 +416,26 @@ export const api = {
     return invoke("set_file_priority", { torrentId, fileIndex, priority });
   },
 
+  // url may be a TrackerInfo's display_url. Disabling the last working
+  // tracker is allowed but comes back with a warning.
+  async setTrackerEnabled(
+    torrentId: string,
+    url: string,
+    enabled: boolean,
+  ): Promise<TrackerToggle> {
+    return invoke("set_tracker_enabled", { torrentId, url, enabled });
+  },
+
+  // Only the first `bytes` of the file; the rest is skipped until its
+  // priority is set again. Resolves to the bytes selected.
+  async downloadFilePrefix(
+    torrentId: string,
+    fileIndex: number,
+    bytes: number,
+  ): Promise<number> {
+    return invoke("download_file_prefix", { torrentId, fileIndex, bytes });
+  },
+
   // Debrid - Credential Management
   async saveDebridCredentials(provider: string, apiKey: string): Promise<void> {
     return invoke("save_debrid_credentials", { provider, apiKey });
@@ -159,6 +453,23 @@ export const api = {
     return invoke("validate_debrid_provider", { provider });
   },
 
+  // API tokens
+  async createApiToken(
+    label: string,
+    scopes: TokenScope[],
+    expiresDays?: number,
+  ): Promise<CreatedApiToken> {
+    return invoke("create_api_token", { label, scopes, expiresDays });
+  },
+
+  async listApiTokens(): Promise<ApiTokenInfo[]> {
+    return invoke("list_api_tokens");
+  },
+
+  async revokeApiToken(id: string): Promise<void> {
+    return invoke("revoke_api_token", { id });
+  },
+
   // Debrid - Cache Check
   async checkTorrentCache(
     infoHash: string,
@@ -170,6 +481,10 @@ export const api = {
     return invoke("get_preferred_cached_provider", { infoHash });
   },
 
+  async getDebridHealth(): Promise<ProviderHealth[]> {
+    return invoke("get_debrid_health");
+  },
+
   // Debrid - Torrent Management
   async addMagnetToDebrid(magnet: string, provider: string): Promise<string> {
     return invoke("add_magnet_to_debrid", { magnet, provider });
@@ -234,6 +549,20 @@ export const api = {
     return invoke("get_available_disk_space", { path });
   },
 
+  // Global wire traffic and wasted payload for this session
+  async getTrafficStats(): Promise<TrafficStats> {
+    return invoke("get_traffic_stats");
+  },
+
+  // Event names and example payloads, for checking the frontend's types
+  async getEventCatalog(): Promise<EventSchema[]> {
+    return invoke("get_event_catalog");
+  },
+
+  async getDashboardSummary(): Promise<DashboardSummary> {
+    return invoke("get_dashboard_summary");
+  },
+
   async backupData(): Promise<string> {
     return invoke("backup_data");
   },
@@ -242,11 +571,39 @@ export const api = {
     return invoke("restore_data", { json });
   },
 
-  async exportBackup(path: string): Promise<void> {
+  // Streams the backup to the file; progress comes as "backup-export-progress"
+  async exportBackup(path: string): Promise<BackupSummary> {
     return invoke("export_backup", { path });
   },
 
-  async importBackup(path: string): Promise<void> {
+  async cancelBackupExport(): Promise<boolean> {
+    return invoke("cancel_backup_export");
+  },
+
+  async importBackup(path: string): Promise<BackupSummary> {
     return invoke("import_backup", { path });
   },
+
+  // Replaces the whole database with the backup file, checked beforehand
+  async restoreFromBackupFile(path: string): Promise<RestoreSummary> {
+    return invoke("restore_from_backup_file", { path });
+  },
+
+  async getDatabaseRecovery(): Promise<DatabaseRecovery | null> {
+    return invoke("get_database_recovery");
+  },
+
+  async salvageDamagedDatabase(): Promise<SalvageReport> {
+    return invoke("salvage_damaged_database");
+  },
+
+  // Log files on disk, oldest first
+  async getLogUsage(): Promise<LogUsage> {
+    return invoke("get_log_usage");
+  },
+
+  // Moves the database and logs; resolves to the new data directory
+  async migrateDataDir(newPath: string): Promise<string> {
+    return invoke("migrate_data_dir", { newPath });
+  },
 };
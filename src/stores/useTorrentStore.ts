@@ -330,14 +330,14 @@ export const useTorrentStore = create<TorrentStoreState>((set, get) => ({
           if (config.debridProvider) provider = config.debridProvider;
         }
 
-        const magnetOrHash =
-          source.type === "file" ? metadata.infoHash : source.uri;
-
-        await api.addCloudTorrent(
-          magnetOrHash,
-          provider,
-          config.savePath || "/downloads",
-        );
+        // Upload .torrent files as is: a bare hash only works if the
+        // provider already knows the torrent
+        const savePath = config.savePath || "/downloads";
+        if (source.type === "file") {
+          await api.addCloudTorrentFile(source.path, provider, savePath);
+        } else {
+          await api.addCloudTorrent(source.uri, provider, savePath);
+        }
 
         useUIStore
           .getState()
@@ -7,6 +7,8 @@ export enum TorrentState {
   Checking = "Checking",
   Error = "Error",
   Queued = "Queued",
+  Unregistered = "Unregistered",
+  PausedSeeding = "PausedSeeding", // Announcing, but no peers and no uploads
 }
 
 export enum DownloadSource {
@@ -27,6 +29,114 @@ export interface TorrentInfo {
   peers: number;
   seeds: number;
   source: DownloadSource;
+  queue_position?: number | null;
+  // Magnet still waiting for its metadata (size and files unknown)
+  metadata_pending?: boolean;
+  // Encoding a non-UTF-8 name was transcoded from (e.g. "Shift_JIS")
+  name_encoding?: string | null;
+  // Set by the app, e.g. "stalled-dead"; only in get_torrents
+  tags?: string[];
+  // Why the torrent is in the Error state, e.g. the file allocation failed on
+  error?: string | null;
+}
+
+// Server-side search (query_torrents); omitted filters match everything
+export type TorrentSortKey =
+  | "name"
+  | "size"
+  | "progress"
+  | "state"
+  | "added_at"
+  | "completed_at"
+  | "download_speed"
+  | "upload_speed"
+  | "queue_position";
+
+export interface TorrentQuery {
+  text?: string | null; // Case-insensitive name substring
+  info_hash_prefix?: string | null;
+  states?: TorrentState[];
+  category?: string | null;
+  tracker?: string | null; // Tracker host substring
+  added_after?: number | null; // Unix seconds, inclusive
+  added_before?: number | null;
+  completed_after?: number | null;
+  completed_before?: number | null;
+  metadata?: MetadataQuery | null;
+  sort?: TorrentSortKey; // Default "name"
+  descending?: boolean;
+  offset?: number;
+  limit?: number | null; // Default 100, at most 1000
+}
+
+// Predicate over comment, creator, trackers, file types and creation year,
+// e.g. { all: [{ extension: "flac" }, { tracker: "redacted" }] }
+export type MetadataQuery =
+  | { all: MetadataQuery[] }
+  | { any: MetadataQuery[] }
+  | { not: MetadataQuery }
+  | { comment: string } // Every word starts a comment word
+  | { created_by: string }
+  | { tracker: string }
+  | { extension: string }
+  | { extension_count: { extension: string; at_least: number } }
+  | { created_in: { from: number | null; to: number | null } };
+
+export interface TorrentPage {
+  torrents: TorrentInfo[];
+  total: number; // Matches across all pages
+  offset: number;
+}
+
+// Where a torrent was added from (magnet URIs lose dn/tr in anonymous mode)
+export type AddedFrom =
+  | { kind: "torrent_file"; original_path: string | null }
+  | { kind: "magnet"; uri: string }
+  | { kind: "url"; url: string }
+  | { kind: "rss"; feed_id: string; item_title: string }
+  | { kind: "imported"; client: string };
+
+export interface TorrentProvenance {
+  added_from: AddedFrom | null; // null for torrents added before it was recorded
+  torrent_file_size: number | null; // Size of the kept .torrent file
+}
+
+// Info commands answer "pending" for a magnet without metadata yet;
+// "metadata-ready" (MetadataReadyEvent) is emitted once it arrives
+export type MetadataResult<T> =
+  | { metadata: "pending" }
+  | { metadata: "ready"; data: T };
+
+export interface MetadataReadyEvent {
+  torrent_id: string;
+  name: string;
+  size: number;
+  file_count: number;
+}
+
+// "prefix-ready": the start of a file asked for with downloadFilePrefix is on disk
+export interface PrefixReadyEvent {
+  torrent_id: string;
+  file_index: number;
+  bytes: number;
+  path: string;
+}
+
+// "file-completed": every piece of a file has been verified
+export interface FileCompletedEvent {
+  torrent_id: string;
+  file_index: number;
+  path: string;
+  completed_at: number; // unix seconds
+}
+
+// "torrent-allocation-failed": creating the torrent's files stopped at `path`
+export interface AllocationFailedEvent {
+  torrent_id: string;
+  path: string;
+  error: string;
+  allocated: number; // files allocated before it
+  total: number;
 }
 
 export interface Settings {
@@ -47,6 +157,43 @@ export interface Settings {
   // Bandwidth scheduler settings
   bandwidth_scheduler_enabled: boolean;
   bandwidth_schedule: BandwidthRule[];
+  // Scheduled database backups
+  auto_backup_enabled?: boolean;
+  auto_backup_interval_hours?: number;
+  auto_backup_keep?: number; // Newest backup files kept
+  auto_backup_dir?: string | null; // Defaults to "backups" next to the database
+  // Log files (one per day)
+  log_keep_files?: number; // Default 14
+  log_max_total_mb?: number; // 0 = no limit
+  log_file_level?: LogLevel; // Default "debug"
+  // Pause downloads with less than one copy in the swarm and no progress
+  auto_stop_dead_enabled?: boolean;
+  auto_stop_dead_days?: number; // Default 14, at most 30
+  auto_resume_revived?: boolean; // Resume once a tracker reports a seed
+  reactivate_paused_seeding?: boolean; // Leave paused seeding when leechers appear
+  notify_on_tracker_warning?: boolean; // Default true: emit "tracker-warning" events
+  // Pause downloads before their disk fills up
+  low_disk_reserve_mb?: number; // Default 2048, 0 = never pause
+  low_disk_auto_resume?: boolean; // Resume them once there is room again
+  // Keep the machine from sleeping while torrents download
+  prevent_sleep?: boolean;
+  prevent_sleep_while_seeding?: boolean; // Seeding torrents count too
+  db_flush_interval_secs?: number; // Default 5; longest a change waits to reach the disk
+}
+
+export type LogLevel = "error" | "warn" | "info" | "debug" | "trace";
+
+export interface LogFile {
+  name: string;
+  size: number;
+  date: string; // YYYY-MM-DD (UTC)
+  in_use: boolean; // Today's file, never deleted
+}
+
+export interface LogUsage {
+  dir: string;
+  files: LogFile[]; // Oldest first
+  total_size: number;
 }
 
 export interface BandwidthRule {
@@ -72,19 +219,66 @@ export interface PeerInfo {
   ip: string;
   port: number;
   client: string;
+  // D downloading, U uploading, I interested, C choking, O optimistic,
+  // S snubbed, M added by hand
   flags: string;
   progress: number;
   download_speed: number;
   upload_speed: number;
   downloaded: number;
   uploaded: number;
+  // Why the choking algorithm last choked or unchoked the peer
+  choke_reason?: ChokeReason | null;
+}
+
+// Whether a peer added by hand is being dialed or waits for the torrent to start
+export type ManualDial = "dialing" | "queued";
+
+export type ChokeReason =
+  | { kind: "not_interested" }
+  | { kind: "top_rate"; rate: number; cutoff: number }
+  | { kind: "below_top_rate"; rate: number; cutoff: number }
+  | { kind: "optimistic" }
+  | { kind: "snubbed" };
+
+export interface ChokeDecision {
+  at: number; // unix seconds
+  choked: boolean;
+  reason: ChokeReason;
 }
 
 // Tracker monitoring types
-export type TrackerStatus = "Working" | "Updating" | "Error" | "Disabled";
+export type TrackerStatus =
+  | "Working"
+  | "Updating"
+  | "Error"
+  | "Disabled"
+  | "NotRegistered"
+  | "CertificateError";
+
+// Why an HTTPS tracker's certificate was rejected (with "CertificateError")
+export type TlsFailure = "Expired" | "UnknownIssuer" | "HostnameMismatch" | "Other";
+
+// Payload of the "torrent-ready" event: a saved torrent's engine is built
+export interface TorrentReadyEvent {
+  torrent_id: string;
+}
+
+// Payload of the "torrent-unregistered" event
+export interface TorrentUnregisteredEvent {
+  torrent_id: string;
+  reasons: string[];
+}
+
+// Payload of the "tracker-warning" event: a tracker's new warning message
+export interface TrackerWarningEvent {
+  torrent_id: string;
+  tracker: string; // display URL
+  message: string;
+}
 
 export interface TrackerInfo {
-  url: string;
+  display_url: string; // passkeys masked; the real URL stays in the backend
   status: TrackerStatus;
   message: string;
   peers: number;
@@ -93,6 +287,17 @@ export interface TrackerInfo {
   downloaded: number;
   last_announce: number | null;
   next_announce: number | null;
+  peers_returned: number;
+  unique_peers: number;
+  productive_peers: number;
+  tls_failure: TlsFailure | null;
+  warning: string | null; // The last announce's warning message
+}
+
+export interface TrackerToggle {
+  display_url: string;
+  enabled: boolean;
+  warning: string | null;
 }
 
 // Pieces monitoring types
@@ -100,8 +305,395 @@ export interface PiecesInfo {
   total_pieces: number;
   pieces_have: number;
   pieces_downloading: number;
-  bitfield: number[]; // 0=missing, 1=have, 2=downloading
-  availability: number[]; // How many peers have each piece
+  // Pieces per bucket; 1 when bitfield/availability list every piece
+  bucket_size: number;
+  bitfield: number[]; // 0=missing, 1=have, 2=downloading; empty when bucketed
+  availability: number[]; // How many peers have each piece; empty when bucketed
+  // Bucket i covers pieces [i * bucket_size, (i + 1) * bucket_size)
+  buckets: PieceBucket[];
+  recent_failures: PieceFailure[]; // Most recent first, expire after a few minutes
+}
+
+export interface PieceBucket {
+  have_fraction: number; // 0 to 1
+  downloading_count: number;
+  min_availability: number;
+  avg_availability: number;
+}
+
+// Exact state of pieces [start, start + bitfield.length)
+export interface PieceWindow {
+  start: number;
+  bitfield: number[];
+  availability: number[];
+}
+
+// A piece that failed hash verification; also the "piece-failed" payload
+// (with torrent_id)
+export interface PieceFailure {
+  piece: number;
+  failed_at: number; // Unix ms
+  failures: number;
+  peers: number[]; // Session keys of the peers it was requested from
+}
+
+export interface PieceFailedEvent extends PieceFailure {
+  torrent_id: string;
+}
+
+// Payload of the "possible-disk-corruption" event
+export interface DiskCorruptionEvent extends PieceFailure {
+  torrent_id: string;
+  message: string;
+}
+
+// Hourly swarm health sample (get_availability_history), kept for 30 days
+export interface AvailabilitySample {
+  at: number; // Unix seconds
+  swarm_seeds: number | null; // As reported by the trackers
+  swarm_leechers: number | null;
+  distributed_copies: number; // Complete copies among connected peers
+  connected_peers: number;
+  pieces_have?: number | null;
+}
+
+// Payload of the "torrent-stalled-dead" event: paused by the dead download policy
+export interface StalledDeadEvent {
+  torrent_id: string;
+  name: string;
+  days: number;
+}
+
+// Payload of the "torrent-revivable" event: a tracker reports a seed again
+export interface RevivableEvent {
+  torrent_id: string;
+  name: string;
+  seeds: number;
+  resumed: boolean; // Started again (auto_resume_revived)
+}
+
+// Payload of the "low-disk-warning" event: running downloads won't fit
+// on their filesystem with the reserve left free
+export interface LowDiskWarningEvent {
+  path: string; // A download folder on the filesystem
+  free: number;
+  remaining: number; // Bytes the running downloads still need
+  reserve: number;
+  shortfall: number;
+  torrent_ids: string[];
+}
+
+// Payload of the "low-disk-paused" event: downloads paused and tagged
+// "paused-low-disk" because free space fell below the reserve
+export interface LowDiskPausedEvent {
+  path: string;
+  free: number;
+  reserve: number;
+  torrent_ids: string[];
+}
+
+// Payload of the "low-disk-space-freed" event: paused downloads fit again
+export interface LowDiskSpaceFreedEvent {
+  path: string;
+  free: number;
+  torrent_ids: string[];
+  resumed: boolean; // Started again (low_disk_auto_resume)
+}
+
+// Payload of the "cloud-provider-switched" event: a cloud download moved
+// to the next debrid provider after its provider failed
+export interface CloudProviderSwitchedEvent {
+  torrentId: string;
+  from: string;
+  to: string;
+  debridTorrentId: string; // Id of the torrent on the new provider
+  reason: string;
+  resumed: boolean; // Partly downloaded files continue where they stopped
+}
+
+// Payload of the "debrid-provider-health" event and get_debrid_health
+export interface ProviderHealth {
+  provider: string;
+  state: "healthy" | "degraded" | "down"; // Down: calls fail at once until it answers again
+  consecutive_failures: number;
+  maintenance: boolean; // It said it is down for maintenance
+  retry_at: number | null; // Unix seconds of the next check while down
+  last_error: string | null;
+}
+
+// Payload of the "backup-created" event
+export interface BackupCreatedEvent {
+  path: string;
+  size: number; // Bytes
+}
+
+// Payload of the "torrent-seeding-reactivated" event: paused seeding ended
+// because an announce reported leechers
+export interface SeedingReactivatedEvent {
+  torrent_id: string;
+  leechers: number;
+}
+
+// Payload of the "backup-failed" event
+export interface BackupFailedEvent {
+  path: string; // Backup directory
+  error: string;
+}
+
+// Result of export_backup and import_backup
+export interface BackupSummary {
+  torrents: number;
+  torrent_files: number;
+  availability: number;
+  skipped: number; // Unreadable records left out of an import
+}
+
+// Payload of the "backup-export-progress" event: sessions written so far
+export interface BackupExportProgress {
+  done: number;
+  total: number;
+}
+
+// Result of restore_from_backup_file
+export interface RestoreSummary {
+  torrents: number;
+  torrent_files: number;
+}
+
+// Payload of the "database-recovered" event and get_database_recovery:
+// a damaged database was moved aside and replaced with an empty one
+export interface DatabaseRecovery {
+  error: string;
+  moved_to: string;
+  latest_backup: string | null; // Offer restoring this
+}
+
+// Result of salvage_damaged_database; `path` restores like a backup
+export interface SalvageReport {
+  path: string;
+  torrents: number;
+  entries: number;
+  damaged_trees: string[];
+}
+
+// Payload of the "torrent-details-update" event
+export interface TorrentDetailsUpdate {
+  torrent_id: string;
+  stats: TorrentInfo;
+  peers: PeerInfo[];
+  trackers: TrackerInfo[];
+  pieces: PiecesInfo | null;
+  traffic: TrafficStats | null;
+  disconnects: Partial<Record<DisconnectReason, number>>; // last hour
+}
+
+// Why a peer connection ended (get_peer_disconnect_history)
+export type DisconnectReason =
+  | "handshake_timeout"
+  | "info_hash_mismatch"
+  | "protocol_error"
+  | "remote_closed"
+  | "choked_and_dropped"
+  | "cancelled"
+  | "banned"
+  | "idle_timeout"
+  | "reaped_idle";
+
+export interface PeerDisconnection {
+  addr: string;
+  client: string;
+  direction: "outbound" | "inbound";
+  connected_ms: number;
+  downloaded: number;
+  uploaded: number;
+  reason: DisconnectReason;
+  detail: string;
+  at: number; // unix timestamp
+}
+
+// Wire traffic; the *_downloaded waste counters are part of payload_downloaded
+export interface TrafficStats {
+  payload_downloaded: number;
+  overhead_downloaded: number;
+  payload_uploaded: number;
+  overhead_uploaded: number;
+  redundant_downloaded: number; // duplicate blocks (endgame)
+  failed_downloaded: number; // pieces that failed verification
+  discarded_downloaded: number; // blocks for cancelled requests or finished pieces
+}
+
+// get_event_catalog: every backend event with an example payload
+export interface EventSchema {
+  name: string;
+  example: unknown;
+}
+
+// get_background_tasks; timestamps are unix seconds
+export type TaskOutcome =
+  | { status: "ok" }
+  | { status: "failed"; message: string }
+  | { status: "panicked"; message: string };
+
+export interface BackgroundTask {
+  name: string;
+  interval_secs: number;
+  running: boolean;
+  last_run: number | null;
+  last_duration_ms: number | null;
+  last_result: TaskOutcome | null;
+  next_run: number | null;
+  runs: number;
+  panics: number;
+  rebuilds: number; // Rebuilt for changed settings
+}
+
+// get_dashboard_summary
+export interface DashboardSummary {
+  torrents: number;
+  by_state: {
+    state: TorrentState;
+    count: number;
+    download_speed: number;
+    upload_speed: number;
+  }[]; // States with torrents only
+  by_category: {
+    category: string | null; // null (last) for torrents without one
+    count: number;
+    size: number;
+    downloaded: number;
+    uploaded: number;
+    active: number; // Downloading or seeding
+  }[];
+  size: number;
+  downloaded: number;
+  uploaded: number;
+  download_speed: number;
+  upload_speed: number;
+  ratio: number;
+  top: { id: string; name: string; download_speed: number; upload_speed: number }[]; // Fastest 5
+}
+
+// Debug transfer log (get_torrent_debug_log)
+export type TransferEvent =
+  | { kind: "announce"; tracker: string; result: string; warning: string | null }
+  | { kind: "connect_attempt"; peer: string }
+  | { kind: "connect_failed"; peer: string; error: string }
+  | { kind: "handshake_failed"; peer: string; error: string }
+  | { kind: "connected"; peer: string }
+  | { kind: "disconnected"; peer: string; reason: string }
+  | { kind: "choked"; peer: string }
+  | { kind: "unchoked"; peer: string }
+  | { kind: "piece_requested"; peer: string; piece: number; blocks: number }
+  | { kind: "piece_verified"; peer: string; piece: number }
+  | { kind: "piece_failed"; peer: string; piece: number; error: string };
+
+export type TransferRecord = { seq: number; at_ms: number } & TransferEvent;
+
+export interface TransferLogPage {
+  records: TransferRecord[];
+  next_seq: number;
+  missed: number;
+  enabled: boolean;
+}
+
+// Live concurrency metrics of one engine (get_engine_metrics)
+export interface EngineMetrics {
+  peer_tasks: number;
+  peer_manager_queue: number;
+  engine_queue: number;
+  disk_writes: number;
+  pending_verifications: number;
+  blocks_in_flight: number; // Requests sent and not yet answered
+  loop_iterations: number;
+  slow_iterations: number; // Over 100 ms
+  slow_stats_responses: number; // Over 250 ms
+}
+
+// Disk and network benchmark (run_benchmark); omitted fields use the defaults
+export interface BenchmarkOptions {
+  disk?: boolean;
+  disk_size_mb?: number; // Default 256
+  network?: boolean;
+  network_url?: string | null; // Defaults to a CDN test file
+  network_max_mb?: number; // Default 100
+  force?: boolean; // Run even while transfers are active
+}
+
+export interface BenchmarkReport {
+  started_at: number; // Unix seconds
+  disk: {
+    path: string;
+    bytes: number;
+    write_mbps: number;
+    sync_ms: number;
+    read_mbps: number; // Partly served from the OS cache
+  } | null;
+  network: { url: string; bytes: number; secs: number; mbps: number } | null;
+  warnings: string[];
+}
+
+// Storage audit (read-only; nothing is deleted)
+export interface SizeMismatch {
+  path: string;
+  expected: number;
+  actual: number;
+}
+
+export interface TorrentStorage {
+  torrent_id: string;
+  name: string;
+  root: string;
+  expected_bytes: number;
+  on_disk_bytes: number;
+  missing: string[];
+  size_mismatched: SizeMismatch[];
+  metadata_pending: boolean;
+}
+
+export interface StorageAudit {
+  torrents: TorrentStorage[];
+  orphans: { path: string; size: number }[];
+  unreadable: { path: string; error: string }[];
+  depth_limited: string[];
+  scanned_dirs: string[];
+  expected_bytes: number;
+  on_disk_bytes: number;
+  orphan_bytes: number;
+}
+
+// Payload of the "storage-audit-progress" event
+export type AuditProgress =
+  | { phase: "torrents"; done: number; total: number }
+  | { phase: "scanning"; entries: number; dir: string }
+  | { phase: "finished" };
+
+// detect_duplicate_content
+export interface FileMatch {
+  file_index: number;
+  other_file_index: number;
+  size: number;
+}
+
+export interface Overlap {
+  torrent_id: string;
+  other_torrent_id: string;
+  bytes: number;
+  kind: "pieces" | "files";
+  piece_bytes: number;
+  file_bytes: number;
+  files: FileMatch[];
+}
+
+export interface DuplicateCluster {
+  torrents: { torrent_id: string; name: string }[];
+  overlaps: Overlap[];
+}
+
+// relink_duplicates
+export interface RelinkReport {
+  linked: FileMatch[];
+  bytes_saved: number;
+  skipped: { path: string; reason: string }[];
 }
 
 // File monitoring types
@@ -113,6 +705,11 @@ export interface FileInfo {
   downloaded: number;
   priority: "Skip" | "Low" | "Normal" | "High"; // API uses Capitalized?
   is_folder: boolean;
+  on_disk_size?: number | null;
+  modified?: number | null; // unix seconds
+  // Only this many bytes from the start are downloaded (downloadFilePrefix)
+  prefix_only?: number | null;
+  completed_at?: number | null; // unix seconds, when every piece of the file was verified
 }
 
 // Note: FileInfo priority from API is capitalized "Skip" etc.
@@ -121,6 +718,9 @@ export interface FileInfo {
 // For now, keeping FileInfo as per previous file content which had "Skip" etc.
 // But FilePriority type export I changed to lowercase to match AddTorrentModal usage in TorrentConfig.
 
+// "consolidated" keeps all data in one .scdata file until the download completes
+export type StorageMode = "files" | "consolidated";
+
 // Torrent metadata (before adding to client)
 export interface TorrentMetadata {
   name: string;
@@ -131,6 +731,18 @@ export interface TorrentMetadata {
   creation_date: number | null;
   comment: string | null;
   created_by: string | null;
+  suggested_storage_mode: StorageMode;
+  health: SwarmHealth;
+}
+
+// Seeds and leechers from a quick scrape when the torrent is parsed
+export interface SwarmHealth {
+  source: "unknown" | "trackers" | "debrid";
+  seeds: number | null;
+  leechers: number | null;
+  trackers_asked: number;
+  trackers_answered: number;
+  debrid_cached: boolean | null;
 }
 
 // Debrid types
@@ -138,6 +750,40 @@ export interface DebridSettings {
   enable_debrid: boolean;
   debrid_preference: string[]; // ["torbox", "real-debrid"]
   smart_mode_enabled: boolean;
+  debrid_base_urls?: Record<string, string>; // provider -> API base URL override
+  debrid_disable_policy?: DebridDisablePolicy;
+  status?: DebridStatus; // filled in by getDebridSettings, ignored on update
+}
+
+// What running cloud downloads do when debrid is switched off
+export type DebridDisablePolicy = "finish" | "abort";
+
+// Effective debrid state: show debrid UI only when `enabled`
+export interface DebridStatus {
+  enabled: boolean;
+  unlocked: boolean;
+  providers_configured: string[];
+}
+
+// Error returned by debrid and cloud commands while debrid is disabled
+export const DEBRID_DISABLED_ERROR = "Feature disabled: debrid";
+
+// Error returned when adding a torrent that already downloads from the other
+// kind of source (P2P or cloud); the rest of the message names that source
+export const ALREADY_ADDED_ERROR = "Already added from another source";
+
+// Whether a saved API key decrypts with the unlocked master password
+export type CredentialHealth = "ok" | "salt_mismatch" | "decrypt_failed";
+
+export interface CredentialCheck {
+  provider: string;
+  health: CredentialHealth;
+}
+
+// Result of unlock_with_master_password: ask again for keys not "ok"
+export interface UnlockResult {
+  unlocked: boolean;
+  credentials: CredentialCheck[];
 }
 
 export interface CredentialStatus {
@@ -147,6 +793,23 @@ export interface CredentialStatus {
   last_validated: number | null;
 }
 
+// Scoped API tokens (every scope also allows read-only access)
+export type TokenScope = "read-only" | "torrent-control" | "settings" | "debrid";
+
+export interface ApiTokenInfo {
+  id: string;
+  label: string;
+  scopes: TokenScope[];
+  created_at: number;
+  expires_at: number | null;
+}
+
+// Returned once by createApiToken; the token can't be retrieved later
+export interface CreatedApiToken {
+  token: string;
+  info: ApiTokenInfo;
+}
+
 export interface CachedFile {
   id: number;
   name: string;
@@ -178,7 +841,8 @@ export type DebridStatus =
   | "Uploading"
   | "Error"
   | "Dead"
-  | "MagnetConversion";
+  | "MagnetConversion"
+  | { unknown: string }; // A provider status without a mapping, as sent
 
 export interface DebridProgress {
   torrent_id: string;